@@ -0,0 +1,75 @@
+//! Gossip benchmarks: order signing and verification, snapshot serialization and
+//! order message envelopes
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use darkswap_bench::signed_orders;
+use darkswap_sdk::clock::{Clock, SystemClock};
+use darkswap_sdk::orderbook::{OrderMessage, OrderbookSnapshot, SignedOrder};
+use darkswap_support::envelope;
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+
+/// Snapshot sizes, in orders
+const SNAPSHOT_SIZES: &[usize] = &[100, 1_000, 10_000];
+
+fn bench_signed_order(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gossip_order");
+
+    let keypair = Keypair::generate_ed25519();
+    let maker = PeerId::from(keypair.public()).to_string();
+    let order = darkswap_bench::order(0, &maker);
+    let signed = SignedOrder::sign(order.clone(), &keypair).expect("sign");
+    let json = serde_json::to_vec(&signed).expect("serialize");
+
+    group.bench_function("sign", |b| b.iter(|| black_box(SignedOrder::sign(order.clone(), &keypair))));
+    group.bench_function("verify", |b| b.iter(|| black_box(signed.verify())));
+    group.bench_function("serialize", |b| b.iter(|| black_box(serde_json::to_vec(&signed))));
+    group.bench_function("deserialize", |b| {
+        b.iter(|| black_box(serde_json::from_slice::<SignedOrder>(&json)))
+    });
+
+    group.finish();
+}
+
+fn bench_snapshot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gossip_snapshot");
+    group.sample_size(10);
+
+    for &size in SNAPSHOT_SIZES {
+        let snapshot = OrderbookSnapshot::new(signed_orders(size), SystemClock.now());
+        let json = snapshot.to_json().expect("serialize");
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("to_json", size), &size, |b, _| {
+            b.iter(|| black_box(snapshot.to_json()))
+        });
+        group.bench_with_input(BenchmarkId::new("from_json", size), &size, |b, _| {
+            b.iter(|| black_box(OrderbookSnapshot::from_json(&json)))
+        });
+        group.bench_with_input(BenchmarkId::new("validate", size), &size, |b, _| {
+            b.iter(|| black_box(snapshot.validate(u64::MAX, SystemClock.now())))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_envelope(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gossip_envelope");
+
+    // Orders are gossiped as versioned envelopes, the way the orderbook publishes and reads them
+    let keypair = Keypair::generate_ed25519();
+    let maker = PeerId::from(keypair.public()).to_string();
+    let signed = SignedOrder::sign(darkswap_bench::order(0, &maker), &keypair).expect("sign");
+    let message = OrderMessage::SignedOrder(signed);
+    let data = envelope::encode(&message).expect("encode");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+
+    group.bench_function("encode", |b| b.iter(|| black_box(envelope::encode(&message))));
+    group.bench_function("decode", |b| b.iter(|| black_box(envelope::decode::<OrderMessage>(&data))));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_signed_order, bench_snapshot, bench_envelope);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+//! Orderbook benchmarks: insert, cancel and match in books of 10k to 1M orders
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use darkswap_bench::{book, book_sizes, order, pair};
+use darkswap_sdk::orderbook::{matching::match_order, view::BookView, OrderSide, OrderStatus};
+use rust_decimal::Decimal;
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("orderbook_insert");
+    group.sample_size(10);
+
+    for size in book_sizes() {
+        let view = book(size);
+        // A new order at the most crowded end of the book
+        let new_order = order(size, "maker");
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| black_box(view.with_order(black_box(&new_order))))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_cancel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("orderbook_cancel");
+    group.sample_size(10);
+
+    for size in book_sizes() {
+        let view = book(size);
+        let mut canceled = order(size / 2, "maker");
+        canceled.status = OrderStatus::Canceled;
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| black_box(view.with_order(black_box(&canceled))))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_match(c: &mut Criterion) {
+    let mut group = c.benchmark_group("orderbook_match");
+    let (base, quote) = pair();
+
+    for size in book_sizes() {
+        let view = book(size);
+        let book = view.pair(&base, &quote).expect("benchmark book");
+
+        // Sweeps about a hundred resting orders
+        group.bench_with_input(BenchmarkId::new("sweep", size), &size, |b, _| {
+            b.iter(|| black_box(match_order(book, OrderSide::Buy, Decimal::new(50, 0), None)))
+        });
+
+        // Stops at the limit price after the first levels
+        let limit = book.best_ask().map(|ask| ask + Decimal::new(2, 0));
+        group.bench_with_input(BenchmarkId::new("limit", size), &size, |b, _| {
+            b.iter(|| black_box(match_order(book, OrderSide::Buy, Decimal::new(1_000_000, 0), limit)))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_bulk_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("orderbook_bulk_load");
+    group.sample_size(10);
+
+    for size in book_sizes() {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter_batched(
+                || (0..size).map(|n| order(n, "maker")).collect::<Vec<_>>(),
+                |orders| black_box(BookView::from_orders(&orders)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_cancel, bench_match, bench_bulk_load);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! PSBT benchmarks: construction of transfer PSBTs with coin selection
+
+use bitcoin::consensus::encode::serialize;
+use bitcoin::Network;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use darkswap_bench::wallet;
+use darkswap_sdk::bitcoin_utils::{generate_test_address, BitcoinWallet, PsbtUtils};
+
+/// Wallet sizes, in spendable outputs
+const WALLET_SIZES: &[usize] = &[10, 100, 1_000];
+
+fn bench_transfer_psbt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("psbt_transfer");
+    let to_address = generate_test_address(Network::Regtest, 2).expect("address");
+
+    for &size in WALLET_SIZES {
+        let wallet = wallet(size);
+        let from_address = wallet.get_address(0).expect("address");
+
+        // More than the wallet's 1 BTC output, so about half of the small outputs
+        // have to be selected as well
+        let amount = 100_000_000 + 5_000 * size as u64;
+
+        group.bench_with_input(BenchmarkId::new("create", size), &size, |b, _| {
+            b.iter(|| {
+                black_box(PsbtUtils::create_bitcoin_transfer_psbt(
+                    &wallet,
+                    &from_address,
+                    &to_address,
+                    amount,
+                    2.0,
+                ))
+            })
+        });
+
+        let psbt = PsbtUtils::create_bitcoin_transfer_psbt(&wallet, &from_address, &to_address, amount, 2.0)
+            .expect("psbt");
+        group.bench_with_input(BenchmarkId::new("serialize", size), &size, |b, _| {
+            b.iter(|| black_box(serialize(&psbt)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_transfer_psbt);
+criterion_main!(benches);
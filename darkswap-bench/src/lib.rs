@@ -0,0 +1,109 @@
+//! Benchmark fixtures for DarkSwap
+//!
+//! The benchmarks in `benches/` measure the orderbook and P2P hot paths: order
+//! insertion, cancellation and matching in large books, gossip serialization and
+//! signature checks, and PSBT construction. This crate only holds the shared
+//! fixtures; it is not a default workspace member and is built by
+//! `cargo bench -p darkswap-bench`.
+
+use std::str::FromStr;
+
+use bitcoin::{Network, OutPoint, TxOut, Txid};
+use darkswap_sdk::bitcoin_utils::{BitcoinWallet, SimpleWallet};
+use darkswap_sdk::orderbook::{view::BookView, Order, OrderId, OrderSide, OrderStatus, SignedOrder};
+use darkswap_sdk::types::Asset;
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use rust_decimal::Decimal;
+
+/// Book sizes benchmarked by default
+pub const BOOK_SIZES: &[usize] = &[10_000, 100_000, 1_000_000];
+
+/// Environment variable capping the benchmarked book size
+pub const MAX_ORDERS_VAR: &str = "DARKSWAP_BENCH_MAX_ORDERS";
+
+/// Number of distinct price levels per side
+const PRICE_LEVELS: usize = 1_000;
+
+/// Mid price of the benchmark books
+const MID_PRICE: i64 = 50_000;
+
+/// Get the book sizes to benchmark
+///
+/// Sizes above `DARKSWAP_BENCH_MAX_ORDERS` are skipped, so a quick run on a small
+/// machine can leave out the million-order book.
+pub fn book_sizes() -> Vec<usize> {
+    let max = std::env::var(MAX_ORDERS_VAR)
+        .ok()
+        .and_then(|max| max.parse().ok())
+        .unwrap_or(usize::MAX);
+
+    BOOK_SIZES.iter().copied().filter(|size| *size <= max).collect()
+}
+
+/// Get the benchmark trading pair
+pub fn pair() -> (Asset, Asset) {
+    (Asset::Bitcoin, Asset::Rune(1))
+}
+
+/// Create the `n`th order of a benchmark book
+///
+/// Even orders are bids below the mid price and odd orders asks above it, spread
+/// over a fixed number of price levels so every level holds many orders.
+pub fn order(n: usize, maker: &str) -> Order {
+    let level = ((n / 2) % PRICE_LEVELS) as i64;
+    let (side, price) = if n % 2 == 0 {
+        (OrderSide::Buy, MID_PRICE - 1 - level)
+    } else {
+        (OrderSide::Sell, MID_PRICE + 1 + level)
+    };
+    let (base_asset, quote_asset) = pair();
+
+    Order {
+        id: OrderId(format!("order-{}", n)),
+        maker: maker.to_string(),
+        base_asset,
+        quote_asset,
+        side,
+        amount: Decimal::new(1 + (n % 100) as i64, 2),
+        price: Decimal::new(price, 0),
+        status: OrderStatus::Open,
+        timestamp: n as u64,
+        expiry: u64::MAX,
+    }
+}
+
+/// Create a book view holding `size` open orders
+pub fn book(size: usize) -> BookView {
+    let orders: Vec<Order> = (0..size).map(|n| order(n, "maker")).collect();
+    BookView::from_orders(&orders)
+}
+
+/// Create `count` orders signed by one maker
+pub fn signed_orders(count: usize) -> Vec<SignedOrder> {
+    let keypair = Keypair::generate_ed25519();
+    let maker = PeerId::from(keypair.public()).to_string();
+
+    (0..count)
+        .map(|n| SignedOrder::sign(order(n, &maker), &keypair).expect("maker signs its own orders"))
+        .collect()
+}
+
+/// Create a regtest wallet holding `count` extra plain bitcoin outputs
+pub fn wallet(count: usize) -> SimpleWallet {
+    let mut wallet = SimpleWallet::new(Network::Regtest).expect("wallet");
+    let script_pubkey = wallet.get_addresses().expect("addresses")[0].script_pubkey();
+
+    for n in 0..count {
+        let txid = Txid::from_str(&format!("{:064x}", n + 2)).expect("txid");
+        wallet.add_utxo(
+            OutPoint { txid, vout: 0 },
+            TxOut {
+                value: 10_000 + n as u64,
+                script_pubkey: script_pubkey.clone(),
+            },
+        );
+    }
+
+    wallet
+}
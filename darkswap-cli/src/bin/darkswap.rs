@@ -0,0 +1,247 @@
+//! DarkSwap launcher
+//!
+//! One `darkswap` executable for operators: `darkswap daemon` and `darkswap relay`
+//! start the `darkswap-daemon` and `darkswap-relay` executables installed next to
+//! it (or on the `PATH`). Both find their configuration files in one directory,
+//! `~/.darkswap` unless `--config-dir` or `DARKSWAP_CONFIG_DIR` says otherwise,
+//! and share the `--log-level` flag. Arguments after the subcommand are passed
+//! through unchanged, and a flag given there wins over a discovered file.
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Configuration files of the daemon, with the flag each is passed with
+const DAEMON_FILES: &[(&str, &str)] = &[
+    ("config.json", "--config"),
+    ("rate-limits.json", "--rate-limits"),
+    ("browser-mode.json", "--browser-mode"),
+    ("notify.toml", "--notify"),
+    ("market-rules.json", "--market-rules"),
+    ("asset-meta.json", "--asset-meta"),
+];
+
+/// Configuration files of the relay, with the flag each is passed with
+const RELAY_FILES: &[(&str, &str)] = &[("relay.toml", "--config")];
+
+/// DarkSwap launcher
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    /// Directory with the configuration files
+    #[clap(long, global = true, env = "DARKSWAP_CONFIG_DIR")]
+    config_dir: Option<PathBuf>,
+
+    /// Log level or filter, e.g. `debug` or `info,darkswap_sdk=trace`; `RUST_LOG` is used if unset
+    #[clap(long, global = true)]
+    log_level: Option<String>,
+
+    /// Subcommand
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+/// Launcher commands
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Start the daemon (`darkswap-daemon`)
+    Daemon {
+        /// Arguments passed to the daemon
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Start the relay server (`darkswap-relay`)
+    Relay {
+        /// Arguments passed to the relay
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<OsString>,
+    },
+    /// Show the configuration files each component would be started with
+    Paths,
+}
+
+/// Get the configuration directory
+fn config_dir(cli: &Cli) -> Result<PathBuf> {
+    match &cli.config_dir {
+        Some(dir) => Ok(dir.clone()),
+        None => {
+            let home_dir = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
+            Ok(home_dir.join(".darkswap"))
+        }
+    }
+}
+
+/// Check if `args` already set `flag`, as `--flag value` or `--flag=value`
+///
+/// `-c` counts as `--config`, which both components accept in its short form.
+fn has_flag(args: &[OsString], flag: &str) -> bool {
+    let prefix = format!("{}=", flag);
+    args.iter()
+        .filter_map(|arg| arg.to_str())
+        .any(|arg| arg == flag || arg.starts_with(&prefix) || (flag == "--config" && arg.starts_with("-c")))
+}
+
+/// Get the flags for the configuration files found in `dir` and not already set in `args`
+fn discovered_args(dir: &Path, files: &[(&str, &str)], args: &[OsString]) -> Vec<OsString> {
+    let mut discovered = Vec::new();
+    for (file, flag) in files {
+        let path = dir.join(file);
+        if path.is_file() && !has_flag(args, flag) {
+            discovered.push(OsString::from(flag));
+            discovered.push(path.into_os_string());
+        }
+    }
+
+    discovered
+}
+
+/// Find an executable next to this one, falling back to the `PATH`
+fn executable(name: &str) -> PathBuf {
+    let file_name = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name(&file_name))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(file_name))
+}
+
+/// Run a component with the discovered configuration files, returning its exit code
+fn launch(cli: &Cli, name: &str, files: &[(&str, &str)], args: &[OsString]) -> Result<i32> {
+    let dir = config_dir(cli)?;
+
+    let mut command = Command::new(executable(name));
+    command.args(discovered_args(&dir, files, args)).args(args);
+    if let Some(log_level) = &cli.log_level {
+        // The daemon (env_logger) and the relay (tracing) both read their filter from RUST_LOG
+        command.env("RUST_LOG", log_level);
+    }
+
+    run(command, name)
+}
+
+/// Run a component in place of the launcher, so it gets signals from the service manager directly
+#[cfg(unix)]
+fn run(mut command: Command, name: &str) -> Result<i32> {
+    use std::os::unix::process::CommandExt;
+
+    // `exec` only returns if the component could not be started
+    let e = command.exec();
+    Err(e).with_context(|| format!("Failed to start {}", name))
+}
+
+/// Run a component and wait for it to exit
+#[cfg(not(unix))]
+fn run(mut command: Command, name: &str) -> Result<i32> {
+    let status = command.status().with_context(|| format!("Failed to start {}", name))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/// Print the configuration files found for each component
+fn print_paths(cli: &Cli) -> Result<()> {
+    let dir = config_dir(cli)?;
+    println!("Configuration directory: {}", dir.display());
+
+    for (component, files) in [("daemon", DAEMON_FILES), ("relay", RELAY_FILES)] {
+        println!("{}:", component);
+        for (file, flag) in files {
+            let path = dir.join(file);
+            let state = if path.is_file() { "found" } else { "not found" };
+            println!("  {:<20} {:<16} {}", file, flag, state);
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let code = match &cli.command {
+        Commands::Daemon { args } => launch(&cli, "darkswap-daemon", DAEMON_FILES, args)?,
+        Commands::Relay { args } => launch(&cli, "darkswap-relay", RELAY_FILES, args)?,
+        Commands::Paths => {
+            print_paths(&cli)?;
+            0
+        }
+    };
+
+    std::process::exit(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        let cli = Cli::try_parse_from(["darkswap", "--log-level", "debug", "daemon", "--addr", "0.0.0.0:3000"]).unwrap();
+        assert_eq!(cli.log_level.as_deref(), Some("debug"));
+        assert!(matches!(&cli.command, Commands::Daemon { args: passed } if *passed == args(&["--addr", "0.0.0.0:3000"])));
+
+        // Once the component's arguments start, everything is passed through
+        let cli = Cli::try_parse_from(["darkswap", "relay", "run", "--log-level", "trace"]).unwrap();
+        assert!(cli.log_level.is_none());
+        assert!(matches!(&cli.command, Commands::Relay { args: passed } if *passed == args(&["run", "--log-level", "trace"])));
+        let cli = Cli::try_parse_from(["darkswap", "--config-dir", "/etc/darkswap", "relay", "run"]).unwrap();
+        assert_eq!(cli.config_dir, Some(PathBuf::from("/etc/darkswap")));
+        assert!(matches!(&cli.command, Commands::Relay { args: passed } if *passed == args(&["run"])));
+
+        assert!(matches!(Cli::try_parse_from(["darkswap", "paths"]).unwrap().command, Commands::Paths));
+
+        // There is no bridge executable to launch
+        assert!(Cli::try_parse_from(["darkswap", "bridge"]).is_err());
+        assert!(Cli::try_parse_from(["darkswap"]).is_err());
+    }
+
+    #[test]
+    fn test_config_dir_resolution() {
+        // The only test touching the variable, so it does not race the others
+        std::env::set_var("DARKSWAP_CONFIG_DIR", "/from/env");
+        let cli = Cli::try_parse_from(["darkswap", "paths"]).unwrap();
+        assert_eq!(config_dir(&cli).unwrap(), PathBuf::from("/from/env"));
+
+        // The flag wins over the environment
+        let cli = Cli::try_parse_from(["darkswap", "--config-dir", "/from/flag", "paths"]).unwrap();
+        assert_eq!(config_dir(&cli).unwrap(), PathBuf::from("/from/flag"));
+
+        // Without either, the home directory
+        std::env::remove_var("DARKSWAP_CONFIG_DIR");
+        let cli = Cli::try_parse_from(["darkswap", "paths"]).unwrap();
+        assert_eq!(config_dir(&cli).unwrap(), dirs::home_dir().unwrap().join(".darkswap"));
+    }
+
+    #[test]
+    fn test_discovered_files_yield_to_passed_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["config.json", "notify.toml", "market-rules.json"] {
+            std::fs::write(dir.path().join(file), "{}").unwrap();
+        }
+        let path = |file: &str| dir.path().join(file).into_os_string();
+
+        // Found files are passed in the order of the table
+        assert_eq!(
+            discovered_args(dir.path(), DAEMON_FILES, &[]),
+            vec![
+                OsString::from("--config"), path("config.json"),
+                OsString::from("--notify"), path("notify.toml"),
+                OsString::from("--market-rules"), path("market-rules.json"),
+            ]
+        );
+
+        // A flag given after the subcommand, in any form, replaces the discovered file
+        let passed = args(&["--notify=/tmp/notify.toml", "-c", "/tmp/config.json"]);
+        assert_eq!(
+            discovered_args(dir.path(), DAEMON_FILES, &passed),
+            vec![OsString::from("--market-rules"), path("market-rules.json")]
+        );
+
+        // Files of the other component are not picked up
+        assert!(discovered_args(dir.path(), RELAY_FILES, &[]).is_empty());
+    }
+}
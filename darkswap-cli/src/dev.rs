@@ -0,0 +1,152 @@
+//! Developer commands for the DarkSwap CLI
+//!
+//! `dev seed` gets a fresh signet or regtest node ready to trade: it requests
+//! coins from a faucet, etches a throwaway rune with a Bitcoin Core wallet and
+//! posts a ladder of sample orders for it through a running daemon. Built with
+//! the `devtools` feature.
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use colored::*;
+use darkswap_sdk::{
+    config::Config,
+    devtools::{self, CoreRpc, FaucetConfig, RpcConfig},
+    orderbook::OrderSide,
+    types::Asset,
+};
+use rust_decimal::Decimal;
+
+/// Developer commands
+#[derive(Subcommand, Debug)]
+pub enum DevCommands {
+    /// Fund a daemon's wallet from a faucet, etch a test rune and post sample orders for it
+    Seed {
+        /// Daemon the sample orders are posted to
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Signet faucet URL; no coins are requested if unset
+        #[clap(long)]
+        faucet: Option<String>,
+        /// Amount to request from the faucet (satoshis)
+        #[clap(long, default_value = "1000000")]
+        faucet_amount: u64,
+        /// Post orders for an existing rune instead of etching one
+        #[clap(long)]
+        rune: Option<u128>,
+        /// Symbol of the etched rune
+        #[clap(long, default_value = "DEVRUNE")]
+        symbol: String,
+        /// Bitcoin Core RPC URL of the wallet etching the rune, with `/wallet/<name>` to pick one
+        #[clap(long, default_value = "http://127.0.0.1:18443")]
+        rpc_url: String,
+        /// Bitcoin Core RPC user
+        #[clap(long, default_value = "")]
+        rpc_user: String,
+        /// Bitcoin Core RPC password
+        #[clap(long, default_value = "")]
+        rpc_password: String,
+        /// Mid price of the sample orders (BTC per rune)
+        #[clap(long, default_value = "0.00001")]
+        mid_price: String,
+        /// Amount of each sample order (runes)
+        #[clap(long, default_value = "100")]
+        amount: String,
+        /// Sample orders on each side
+        #[clap(long, default_value = "5")]
+        levels: u32,
+        /// Distance between sample order prices (basis points)
+        #[clap(long, default_value = "50")]
+        step_bps: u32,
+    },
+}
+
+/// Run a developer command
+pub async fn run(config: Config, command: DevCommands) -> Result<()> {
+    match command {
+        DevCommands::Seed {
+            daemon,
+            faucet,
+            faucet_amount,
+            rune,
+            symbol,
+            rpc_url,
+            rpc_user,
+            rpc_password,
+            mid_price,
+            amount,
+            levels,
+            step_bps,
+        } => {
+            let mid_price = Decimal::from_str(&mid_price).context("Invalid mid price")?;
+            let amount = Decimal::from_str(&amount).context("Invalid amount")?;
+            let faucet = faucet.map(|url| FaucetConfig { url, amount: faucet_amount, ..FaucetConfig::default() });
+            let rpc = RpcConfig { url: rpc_url, user: rpc_user, password: rpc_password, ..RpcConfig::default() };
+            seed(config, &daemon, faucet, rune, &symbol, rpc, mid_price, amount, levels, step_bps).await
+        }
+    }
+}
+
+/// Fund the daemon's wallet, etch a test rune and post sample orders through the daemon
+#[allow(clippy::too_many_arguments)]
+async fn seed(
+    config: Config,
+    daemon: &str,
+    faucet: Option<FaucetConfig>,
+    rune: Option<u128>,
+    symbol: &str,
+    rpc: RpcConfig,
+    mid_price: Decimal,
+    amount: Decimal,
+    levels: u32,
+    step_bps: u32,
+) -> Result<()> {
+    let network: bitcoin::Network = config.bitcoin.network.into();
+    devtools::check_network(network)?;
+
+    if let Some(faucet) = &faucet {
+        let address = daemon_address(daemon).await?;
+        let txid = devtools::request_faucet_coins(faucet, &config.dns, &address, network).await?;
+        println!("{} {} sats to {} in {}", "Funded".green().bold(), faucet.amount, address, txid);
+    }
+
+    let rune_id = match rune {
+        Some(rune_id) => rune_id,
+        None => {
+            let rpc = CoreRpc::new(rpc, &config.dns)?;
+            let rune = devtools::etch_test_rune(&rpc, network, symbol, config.bitcoin.fee_rate).await?;
+            println!("{} {} as rune {} in {}", "Etched".green().bold(), rune.symbol, rune.id, rune.txid);
+            rune.id
+        }
+    };
+
+    let orders = devtools::sample_orders(mid_price, amount, levels, step_bps);
+    let created = devtools::seed_daemon(daemon, &config.dns, &Asset::Rune(rune_id), &Asset::Bitcoin, &orders).await?;
+    for order in &created {
+        let side = match order.side {
+            OrderSide::Buy => "BUY ".green(),
+            OrderSide::Sell => "SELL".red(),
+        };
+        println!("  {} {} @ {}  {}", side, order.amount, order.price, order.id.to_string().dimmed());
+    }
+    println!("{} {} sample orders for RUNE:{}/BTC", "Posted".green().bold(), created.len(), rune_id);
+
+    Ok(())
+}
+
+/// Get a receive address of the daemon's wallet
+async fn daemon_address(daemon: &str) -> Result<bitcoin::Address> {
+    let response = reqwest::Client::new()
+        .post(format!("{}/addresses", daemon.trim_end_matches('/')))
+        .json(&serde_json::json!({ "label": "faucet" }))
+        .send()
+        .await
+        .context("Failed to reach daemon")?
+        .error_for_status()
+        .context("Daemon refused to issue an address")?;
+    let info: serde_json::Value = response.json().await.context("Failed to parse daemon address")?;
+
+    let address = info["address"].as_str().ok_or_else(|| anyhow::anyhow!("Daemon answered no address"))?;
+    bitcoin::Address::from_str(address).context("Invalid daemon address")
+}
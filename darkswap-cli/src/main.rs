@@ -10,6 +10,16 @@ use darkswap_sdk::{
     config::{BitcoinNetwork, Config},
     types::{Asset, AlkaneId},
     orderbook::{Order, OrderId, OrderSide, OrderStatus},
+    p2p::relay_store::RelayHealth,
+    trade::{
+        approval::{ApprovalRequest, TradeProposal},
+        memo::{Memo, TradeHistoryEntry},
+        query::{TradePage, DEFAULT_PAGE_SIZE},
+        ticker::Ticker,
+        timeline::TradeTimeline,
+        TradeState,
+    },
+    wallet::{address_book::AddressInfo, consolidation::ConsolidationPlan, simple_wallet::SimpleWallet, WalletInterface},
     DarkSwap, types::Event,
 };
 use rust_decimal::Decimal;
@@ -18,6 +28,10 @@ use std::str::FromStr;
 use tokio::signal;
 use tokio::sync::mpsc;
 
+#[cfg(feature = "devtools")]
+mod dev;
+mod watch;
+
 /// DarkSwap CLI
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -43,6 +57,9 @@ enum Commands {
         /// Listen address for the REST API
         #[clap(short, long, default_value = "127.0.0.1:8080")]
         listen: String,
+        /// Do not prompt for approval of inbound trades; apply the auto-accept policy
+        #[clap(long)]
+        no_prompt: bool,
     },
     /// Create an order
     CreateOrder {
@@ -79,6 +96,9 @@ enum Commands {
         /// Amount
         #[clap(short, long)]
         amount: String,
+        /// Address the proceeds are paid to instead of the wallet's default
+        #[clap(long)]
+        payout_address: Option<String>,
     },
     /// List orders
     ListOrders {
@@ -104,6 +124,32 @@ enum Commands {
         #[clap(short, long)]
         quote_asset: String,
     },
+    /// Show the name, symbol, decimals and icon of an asset
+    Asset {
+        /// Asset (BTC, RUNE:<id>, ALKANE:<id>)
+        asset: String,
+    },
+    /// Show the 24h ticker of a pair from a running daemon
+    Ticker {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Base asset (BTC, RUNE:<id>, ALKANE:<id>)
+        #[clap(short, long)]
+        base_asset: String,
+        /// Quote asset (BTC, RUNE:<id>, ALKANE:<id>)
+        #[clap(short, long)]
+        quote_asset: String,
+    },
+    /// Fetch the market rules a daemon publishes and verify their signature
+    MarketRules {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Operator key trusted to sign the rules (hex); may be repeated
+        #[clap(short, long, required = true)]
+        trusted_key: Vec<String>,
+    },
     /// Connect wallet
     ConnectWallet {
         /// Wallet type (simple, bdk, external)
@@ -119,6 +165,288 @@ enum Commands {
         #[clap(short, long)]
         derivation_path: Option<String>,
     },
+    /// Prepare an unsigned order for offline signing
+    PrepareOrder {
+        /// Base asset (BTC, RUNE:<id>, ALKANE:<id>)
+        #[clap(short, long)]
+        base_asset: String,
+        /// Quote asset (BTC, RUNE:<id>, ALKANE:<id>)
+        #[clap(short, long)]
+        quote_asset: String,
+        /// Order side (buy, sell)
+        #[clap(short, long)]
+        side: String,
+        /// Amount
+        #[clap(short, long)]
+        amount: String,
+        /// Price
+        #[clap(short, long)]
+        price: String,
+        /// Expiry in seconds
+        #[clap(short, long)]
+        expiry: Option<u64>,
+        /// Maker peer ID (defaults to the configured identity)
+        #[clap(short, long)]
+        maker: Option<String>,
+        /// File to write the unsigned order to
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+    /// Sign a prepared order with the configured identity key
+    SignOrder {
+        /// Unsigned order file
+        #[clap(short, long)]
+        file: PathBuf,
+        /// File to write the signed order to (defaults to <file>.signed.json)
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Sign market rules with the configured identity key for a daemon to publish
+    SignRules {
+        /// Market rules file (JSON)
+        #[clap(short, long)]
+        file: PathBuf,
+        /// File to write the signed rules to (defaults to <file>.signed.json)
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Submit an order that was signed offline
+    SubmitOrder {
+        /// Signed order file
+        #[clap(short, long)]
+        file: PathBuf,
+    },
+    /// Watch the order book and trades of a pair live
+    Watch {
+        /// Pair to watch (BASE/QUOTE, e.g. BTC/RUNE:840000:3)
+        #[clap(short, long)]
+        pair: String,
+        /// Attach to a running daemon instead of starting a node (e.g. http://127.0.0.1:3000)
+        #[clap(short, long)]
+        daemon: Option<String>,
+        /// Number of price levels shown per side
+        #[clap(long, default_value = "10")]
+        depth: usize,
+    },
+    /// Manage wallet receive addresses of a running daemon
+    Address {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Address subcommand
+        #[clap(subcommand)]
+        command: AddressCommands,
+    },
+    /// List the trades of a running daemon with their memos
+    Trades {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Base asset (BTC, RUNE:<id>, ALKANE:<id>)
+        #[clap(short, long)]
+        base_asset: Option<String>,
+        /// Quote asset (BTC, RUNE:<id>, ALKANE:<id>)
+        #[clap(short, long)]
+        quote_asset: Option<String>,
+        /// Trade state (created, completed, failed, canceled, expired, ...)
+        #[clap(short, long)]
+        state: Option<String>,
+        /// Only trades with this memo label
+        #[clap(short, long)]
+        label: Option<String>,
+        /// Text to search for in memos, trade IDs and transaction IDs
+        #[clap(long)]
+        query: Option<String>,
+        /// Only trades with this maker or taker peer ID
+        #[clap(long)]
+        counterparty: Option<String>,
+        /// Only trades created at or after this time (unix seconds)
+        #[clap(long)]
+        since: Option<u64>,
+        /// Only trades created at or before this time (unix seconds)
+        #[clap(long)]
+        until: Option<u64>,
+        /// Sort order (newest, oldest)
+        #[clap(long)]
+        sort: Option<String>,
+        /// Number of trades per page
+        #[clap(long)]
+        limit: Option<usize>,
+        /// Cursor of the page to show, as printed below the previous page
+        #[clap(long)]
+        cursor: Option<String>,
+    },
+    /// Ask full peers to forward a running daemon only the orders of some pairs and makers
+    Interest {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Full peer to send the filter to (repeat for several)
+        #[clap(long = "peer", required = true)]
+        peers: Vec<String>,
+        /// Pair whose orders are wanted, as BASE/QUOTE (repeat for several)
+        #[clap(long = "pair")]
+        pairs: Vec<String>,
+        /// Maker whose orders are wanted (repeat for several)
+        #[clap(long = "maker")]
+        makers: Vec<String>,
+    },
+    /// Ask peers for the recent orders of a pair they cached, after joining or reconnecting
+    Backfill {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Peer to ask (repeat for several)
+        #[clap(long = "peer", required = true)]
+        peers: Vec<String>,
+        /// Pair whose orders are wanted, as BASE/QUOTE
+        #[clap(long)]
+        pair: String,
+        /// How far back to ask for orders (minutes)
+        #[clap(long, default_value = "10")]
+        minutes: u64,
+    },
+    /// List the relays of a running daemon with their health
+    Relays {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+    },
+    /// Inspect a trade of a running daemon
+    Trade {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Trade subcommand
+        #[clap(subcommand)]
+        command: TradeCommands,
+    },
+    /// Attach a local memo to a trade or transaction; memos are never broadcast
+    Memo {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Trade ID
+        #[clap(short, long, conflicts_with = "txid", required_unless_present = "txid")]
+        trade: Option<String>,
+        /// Transaction ID
+        #[clap(long)]
+        txid: Option<String>,
+        /// Label (repeat for several); no labels and no note removes the memo
+        #[clap(short, long = "label")]
+        labels: Vec<String>,
+        /// Note
+        #[clap(short, long)]
+        note: Option<String>,
+    },
+    /// Maintain the wallet of a running daemon
+    Wallet {
+        /// Daemon to use
+        #[clap(short, long, default_value = "http://127.0.0.1:3000")]
+        daemon: String,
+        /// Wallet subcommand
+        #[clap(subcommand)]
+        command: WalletCommands,
+    },
+    /// Rotate the node identity key, keeping reputation
+    RotateIdentity {
+        /// File to write the rotation statement to
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Do not announce the rotation on the network
+        #[clap(long)]
+        no_publish: bool,
+    },
+    /// Change the passphrase encrypting the node's storage
+    RotateStorageKey {
+        /// Also re-encrypt every record under a new data key
+        #[clap(long)]
+        data_key: bool,
+    },
+    /// Manage secrets in the OS keyring, referenced from the configuration as `keyring:<name>`
+    Secrets {
+        /// Secrets subcommand
+        #[clap(subcommand)]
+        command: SecretsCommands,
+    },
+    /// Developer tools for signet
+    #[cfg(feature = "devtools")]
+    Dev {
+        /// Developer subcommand
+        #[clap(subcommand)]
+        command: dev::DevCommands,
+    },
+}
+
+/// Address commands
+#[derive(Subcommand, Debug)]
+enum AddressCommands {
+    /// Get a new receive address
+    New {
+        /// Label for the address
+        #[clap(short, long)]
+        label: Option<String>,
+    },
+    /// List issued receive addresses
+    List,
+}
+
+/// Trade commands
+#[derive(Subcommand, Debug)]
+enum TradeCommands {
+    /// Show when the trade reached each stage and how long each took
+    Timeline {
+        /// Trade ID
+        trade_id: String,
+    },
+}
+
+/// Wallet commands
+#[derive(Subcommand, Debug)]
+enum WalletCommands {
+    /// Sweep dust outputs into a single output
+    Consolidate {
+        /// Consolidate even if the fee rate is above the configured ceiling
+        #[clap(long)]
+        force: bool,
+        /// Show the plan without broadcasting
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Print the output descriptor of the configured wallet, for Sparrow or Bitcoin Core
+    ExportDescriptor {
+        /// Include the private key, so the other wallet can spend
+        #[clap(long)]
+        private: bool,
+    },
+    /// Use a private `wpkh(xprv/.../*)` descriptor, with its checksum, as the wallet
+    ImportDescriptor {
+        /// Descriptor, e.g. from `listdescriptors true` in Bitcoin Core
+        descriptor: String,
+    },
+}
+
+/// Secrets commands
+#[derive(Subcommand, Debug)]
+enum SecretsCommands {
+    /// Store a secret
+    Set {
+        /// Secret name
+        name: String,
+        /// Secret value; asked for if not given, which keeps it out of the shell history
+        #[clap(long)]
+        value: Option<String>,
+    },
+    /// Print a secret
+    Get {
+        /// Secret name
+        name: String,
+    },
+    /// Remove a secret
+    Delete {
+        /// Secret name
+        name: String,
+    },
 }
 
 /// Parse asset from string
@@ -127,7 +455,15 @@ fn parse_asset(asset_str: &str) -> Result<Asset> {
         Ok(Asset::Bitcoin)
     } else if asset_str.starts_with("RUNE:") {
         let id = asset_str.strip_prefix("RUNE:").unwrap();
-        let id_num = id.parse::<u128>().map_err(|_| anyhow::anyhow!("Invalid rune ID: {}", id))?;
+        let id_num = match id.split_once(':') {
+            // Block and transaction index, packed as block << 16 | tx
+            Some((block, tx)) => {
+                let block = block.parse::<u64>().map_err(|_| anyhow::anyhow!("Invalid rune ID: {}", id))?;
+                let tx = tx.parse::<u16>().map_err(|_| anyhow::anyhow!("Invalid rune ID: {}", id))?;
+                (u128::from(block) << 16) | u128::from(tx)
+            }
+            None => id.parse::<u128>().map_err(|_| anyhow::anyhow!("Invalid rune ID: {}", id))?,
+        };
         Ok(Asset::Rune(id_num))
     } else if asset_str.starts_with("ALKANE:") {
         let id = asset_str.strip_prefix("ALKANE:").unwrap();
@@ -193,10 +529,109 @@ fn load_or_create_config(config_path: Option<PathBuf>, network: &str) -> Result<
     Ok(config)
 }
 
+/// Print an inbound trade proposal and ask whether to accept it
+///
+/// Returns `None` if there was no answer before the timeout.
+async fn prompt_trade_approval(
+    proposal: &TradeProposal,
+    timeout: std::time::Duration,
+    input: &mut mpsc::Receiver<String>,
+) -> Option<bool> {
+    use colored::*;
+
+    let trade = &proposal.trade;
+
+    println!("\n{}", "Inbound trade proposal".yellow().bold());
+    println!("  Trade:   {}", trade.id.to_string().cyan());
+    println!("  Order:   {}", trade.order_id.to_string().cyan());
+    println!("  Taker:   {}", trade.taker_peer_id);
+    println!("  Amount:  {} {} @ {} {}", trade.amount, trade.base_asset, trade.price, trade.quote_asset);
+
+    match &proposal.psbt_summary {
+        Some(summary) => {
+            println!("  PSBT:    {} inputs, {} outputs", summary.inputs, summary.outputs.len());
+            for output in &summary.outputs {
+                println!(
+                    "           {} sat -> {}{}",
+                    output.value,
+                    output.address.as_deref().unwrap_or(&output.script_pubkey),
+                    if output.operator_fee { " (operator fee)" } else { "" }
+                );
+            }
+            match summary.fee {
+                Some(fee) => println!("  Fee:     {} sat", fee),
+                None => println!("  Fee:     {}", "unknown (inputs without UTXO data)".yellow()),
+            }
+            if let Some(fees) = &trade.fees {
+                println!(
+                    "  Operator fee: {} sat (maker {} bps, taker {} bps)",
+                    summary.operator_fee, fees.maker_fee_bps, fees.taker_fee_bps
+                );
+            }
+        }
+        None => println!("  PSBT:    {}", "could not be decoded".red()),
+    }
+
+    print!("Accept this trade? [y/n] ({}s) ", timeout.as_secs());
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+
+    match tokio::time::timeout(timeout, input.recv()).await {
+        Ok(Some(line)) => match line.trim().to_lowercase().as_str() {
+            "y" | "yes" => Some(true),
+            "n" | "no" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Read stdin lines on a dedicated thread, so a pending read never holds up shutdown
+fn spawn_stdin_reader() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel(1);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            match line {
+                Ok(line) if sender.blocking_send(line).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+    receiver
+}
+
+/// Answer approval requests at the terminal, one at a time
+async fn run_trade_approval(mut approvals: mpsc::Receiver<ApprovalRequest>, auto_accept: bool) {
+    use colored::*;
+
+    let mut input = spawn_stdin_reader();
+    while let Some(mut request) = approvals.recv().await {
+        // The answer timeout starts once the proposal is on screen
+        request.displayed();
+        match prompt_trade_approval(&request.proposal, request.timeout, &mut input).await {
+            Some(true) => {
+                println!("{}", "Trade accepted".green());
+                request.accept();
+            }
+            Some(false) => {
+                println!("{}", "Trade rejected".red());
+                request.reject();
+            }
+            None => {
+                println!(
+                    "\n{}",
+                    format!("No answer, {}", if auto_accept { "auto-accepting" } else { "auto-rejecting" }).yellow()
+                );
+                drop(request);
+            }
+        }
+    }
+}
+
 /// Start the daemon
-async fn start_daemon(config: Config, listen_addr: &str) -> Result<()> {
+async fn start_daemon(config: Config, listen_addr: &str, no_prompt: bool) -> Result<()> {
     use colored::*;
     use indicatif::{ProgressBar, ProgressStyle};
+    use std::io::IsTerminal;
     use std::time::Duration;
 
     println!("{}", "Starting DarkSwap daemon...".green().bold());
@@ -214,6 +649,8 @@ async fn start_daemon(config: Config, listen_addr: &str) -> Result<()> {
     spinner.set_message("Initializing DarkSwap...");
     spinner.enable_steady_tick(Duration::from_millis(100));
 
+    let auto_accept = config.trade.auto_accept;
+
     // Create DarkSwap instance
     let mut darkswap = DarkSwap::new(config)?;
 
@@ -222,6 +659,15 @@ async fn start_daemon(config: Config, listen_addr: &str) -> Result<()> {
     // Start DarkSwap
     darkswap.start().await?;
 
+    // Prompt for inbound trades when attended, without holding up events or Ctrl+C;
+    // unattended, the auto-accept policy decides
+    let prompt = !no_prompt && std::io::stdin().is_terminal();
+    let prompter = if prompt {
+        Some(tokio::spawn(run_trade_approval(darkswap.enable_trade_approval().await?, auto_accept)))
+    } else {
+        None
+    };
+
     // Stop the spinner and show success message
     spinner.finish_with_message("DarkSwap daemon started successfully!".green().to_string());
 
@@ -233,6 +679,13 @@ async fn start_daemon(config: Config, listen_addr: &str) -> Result<()> {
         let network = n.read().now_or_never().unwrap();
         network.local_peer_id().to_string()
     }).cyan());
+    println!("  Trade Approval: {}", if prompt {
+        "Interactive".green()
+    } else if auto_accept {
+        "Auto-accept".yellow()
+    } else {
+        "Auto-reject".yellow()
+    });
     println!("  Press Ctrl+C to stop the daemon");
 
     // Create a channel for shutdown signal
@@ -386,6 +839,13 @@ async fn start_daemon(config: Config, listen_addr: &str) -> Result<()> {
                                 }
                             }
                         }
+                        Event::LaggedEvents(count) => {
+                            table.add_row(row![
+                                now,
+                                "Events Dropped".red(),
+                                format!("{} events lost, consumer is lagging", count)
+                            ]);
+                        }
                         _ => {
                             table.add_row(row![
                                 now,
@@ -411,6 +871,9 @@ async fn start_daemon(config: Config, listen_addr: &str) -> Result<()> {
                 spinner.set_message("Shutting down DarkSwap daemon...");
                 spinner.enable_steady_tick(Duration::from_millis(100));
                 
+                if let Some(prompter) = &prompter {
+                    prompter.abort();
+                }
                 darkswap.stop().await?;
                 
                 spinner.finish_with_message("DarkSwap daemon stopped successfully!".green().to_string());
@@ -547,7 +1010,7 @@ async fn cancel_order(config: Config, order_id_str: &str) -> Result<()> {
 }
 
 /// Take an order
-async fn take_order(config: Config, order_id_str: &str, amount_str: &str) -> Result<()> {
+async fn take_order(config: Config, order_id_str: &str, amount_str: &str, payout_address: Option<String>) -> Result<()> {
     use colored::*;
     use indicatif::{ProgressBar, ProgressStyle};
 
@@ -558,6 +1021,9 @@ async fn take_order(config: Config, order_id_str: &str, amount_str: &str) -> Res
     println!("{}", "Taking order...".green().bold());
     println!("  Order ID: {}", order_id.to_string().cyan());
     println!("  Amount:   {}", amount.to_string().cyan());
+    if let Some(address) = &payout_address {
+        println!("  Payout:   {}", address.cyan());
+    }
 
     // Show a spinner while connecting
     let spinner = ProgressBar::new_spinner();
@@ -579,7 +1045,7 @@ async fn take_order(config: Config, order_id_str: &str, amount_str: &str) -> Res
     spinner.set_message("Taking order...");
 
     // Take order
-    let trade = darkswap.take_order(&order_id, amount).await?;
+    let trade = darkswap.take_order_with_payout(&order_id, amount, payout_address).await?;
 
     // Stop the spinner
     spinner.finish_with_message("Order taken successfully!".green().to_string());
@@ -694,12 +1160,16 @@ async fn list_orders(
             .format("%Y-%m-%d %H:%M:%S")
             .to_string();
         
+        // Show assets by their symbols, with the configured overrides
+        let base_asset = darkswap.get_asset_info(&order.base_asset).await?;
+        let quote_asset = darkswap.get_asset_info(&order.quote_asset).await?;
+        
         table.add_row(row![
             order.id.to_string(),
             side_str,
             order.amount.to_string(),
-            order.base_asset.to_string(),
-            format!("{} {}", order.price, order.quote_asset),
+            base_asset.label(),
+            format!("{} {}", order.price, quote_asset.label()),
             status_str,
             timestamp
         ]);
@@ -719,6 +1189,38 @@ async fn list_orders(
 }
 
 /// Get market data
+/// Show the display metadata of an asset
+async fn show_asset(config: Config, asset_str: &str) -> Result<()> {
+    use colored::*;
+    use prettytable::{format, Table, row, cell};
+
+    // Parse parameters
+    let asset = parse_asset(asset_str)?;
+
+    // Create DarkSwap instance
+    let darkswap = DarkSwap::new(config)?;
+
+    // Get asset info
+    let info = darkswap.get_asset_info(&asset).await?;
+
+    // Create a table for the asset
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    
+    table.add_row(row!["ID".bold(), info.id.cyan()]);
+    table.add_row(row!["Name".bold(), info.name.as_deref().unwrap_or("-")]);
+    table.add_row(row!["Symbol".bold(), info.symbol.as_deref().unwrap_or("-")]);
+    table.add_row(row!["Decimals".bold(), info.decimals]);
+    table.add_row(row!["Icon".bold(), info.icon_url.as_deref().unwrap_or("-")]);
+    if info.overridden {
+        table.add_row(row!["Source".bold(), "config override".yellow()]);
+    }
+    
+    table.printstd();
+
+    Ok(())
+}
+
 async fn get_market_data(config: Config, base_asset_str: &str, quote_asset_str: &str) -> Result<()> {
     use colored::*;
     use prettytable::{format, Table, row, cell};
@@ -933,34 +1435,959 @@ async fn connect_wallet(
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Parse command line arguments
-    let cli = Cli::parse();
+/// Prepare an unsigned order
+fn prepare_order(
+    config: &Config,
+    base_asset_str: &str,
+    quote_asset_str: &str,
+    side_str: &str,
+    amount_str: &str,
+    price_str: &str,
+    expiry: Option<u64>,
+    maker: Option<&str>,
+    output: &PathBuf,
+) -> Result<()> {
+    use colored::*;
+    use darkswap_sdk::reputation::identity_peer_id;
 
-    // Load or create configuration
-    let config = load_or_create_config(cli.config, &cli.network)?;
+    // Parse parameters
+    let base_asset = parse_asset(base_asset_str)?;
+    let quote_asset = parse_asset(quote_asset_str)?;
+    let side = parse_order_side(side_str)?;
+    let amount = Decimal::from_str(amount_str).context("Invalid amount")?;
+    let price = Decimal::from_str(price_str).context("Invalid price")?;
 
-    // Execute command
-    match cli.command {
-        Commands::Daemon { listen } => {
-            start_daemon(config, &listen).await?;
+    if amount <= Decimal::ZERO || price <= Decimal::ZERO {
+        anyhow::bail!("Amount and price must be positive");
+    }
+
+    // Determine the maker peer ID
+    let maker = match maker {
+        Some(maker) => maker.to_string(),
+        None => {
+            let key = config.p2p.identity_key.as_deref()
+                .ok_or_else(|| anyhow::anyhow!("No identity key configured; pass --maker with the offline peer ID"))?;
+            identity_peer_id(key)?
         }
-        Commands::CreateOrder {
-            base_asset,
-            quote_asset,
-            side,
-            amount,
-            price,
-            expiry,
-        } => {
-            create_order(config, &base_asset, &quote_asset, &side, &amount, &price, expiry).await?;
+    };
+
+    // Create the unsigned order
+    let order = Order::new(maker, base_asset, quote_asset, side, amount, price, expiry);
+
+    std::fs::write(output, serde_json::to_string_pretty(&order)?)
+        .context("Failed to write unsigned order")?;
+
+    println!("{}", "Unsigned order prepared!".green().bold());
+    println!("  ID:     {}", order.id.to_string().green());
+    println!("  Maker:  {}", order.maker.cyan());
+    println!("  Expiry: {}", order.expiry);
+    println!("Sign it offline with {} and submit the result with {}",
+        format!("sign-order --file {}", output.display()).blue(),
+        "submit-order".blue()
+    );
+
+    Ok(())
+}
+
+/// Sign a prepared order
+fn sign_order(config: &Config, file: &PathBuf, output: Option<&PathBuf>) -> Result<()> {
+    use colored::*;
+    use darkswap_sdk::orderbook::SignedOrder;
+    use darkswap_sdk::reputation::decode_identity_key;
+
+    // Load the unsigned order
+    let contents = std::fs::read_to_string(file).context("Failed to read unsigned order")?;
+    let order: Order = serde_json::from_str(&contents).context("Invalid unsigned order")?;
+
+    // Sign with the configured identity key
+    let key = config.p2p.identity_key.as_deref()
+        .ok_or_else(|| anyhow::anyhow!("No identity key configured"))?;
+    let keypair = decode_identity_key(key)?;
+    let signed_order = SignedOrder::sign(order, &keypair)?;
+
+    // Write the signed order
+    let output = output.cloned().unwrap_or_else(|| file.with_extension("signed.json"));
+    std::fs::write(&output, serde_json::to_string_pretty(&signed_order)?)
+        .context("Failed to write signed order")?;
+
+    println!("{}", "Order signed successfully!".green().bold());
+    println!("  ID:     {}", signed_order.order.id.to_string().green());
+    println!("Signed order saved to {}", output.display().to_string().blue());
+
+    Ok(())
+}
+
+/// Sign market rules
+fn sign_rules(config: &Config, file: &PathBuf, output: Option<&PathBuf>) -> Result<()> {
+    use colored::*;
+    use darkswap_sdk::reputation::decode_identity_key;
+    use darkswap_sdk::rules::{MarketRules, SignedMarketRules};
+
+    // Load the rules
+    let contents = std::fs::read_to_string(file).context("Failed to read market rules")?;
+    let rules: MarketRules = serde_json::from_str(&contents).context("Invalid market rules")?;
+
+    // Sign with the configured identity key
+    let key = config.p2p.identity_key.as_deref()
+        .ok_or_else(|| anyhow::anyhow!("No identity key configured"))?;
+    let keypair = decode_identity_key(key)?;
+    let signed_rules = SignedMarketRules::sign(rules, &keypair)?;
+
+    // Write the signed rules
+    let output = output.cloned().unwrap_or_else(|| file.with_extension("signed.json"));
+    std::fs::write(&output, serde_json::to_string_pretty(&signed_rules)?)
+        .context("Failed to write signed market rules")?;
+
+    println!("{}", "Market rules signed successfully!".green().bold());
+    println!("  Sequence: {}", signed_rules.rules.sequence.to_string().green());
+    println!("  Key:      {}", signed_rules.public_key.cyan());
+    println!("Signed rules saved to {}; publish them with {}",
+        output.display().to_string().blue(),
+        format!("darkswap-daemon --market-rules {}", output.display()).blue()
+    );
+
+    Ok(())
+}
+
+/// Read a signed order, checking its signature
+fn read_signed_order(file: &PathBuf) -> Result<darkswap_sdk::orderbook::SignedOrder> {
+    let contents = std::fs::read_to_string(file).context("Failed to read signed order")?;
+    let signed_order: darkswap_sdk::orderbook::SignedOrder = serde_json::from_str(&contents).context("Invalid signed order")?;
+    signed_order.verify()?;
+
+    Ok(signed_order)
+}
+
+/// Submit an order that was signed offline
+async fn submit_order(config: Config, file: &PathBuf) -> Result<()> {
+    use colored::*;
+
+    // Load the signed order, checking the signature before connecting
+    let signed_order = read_signed_order(file)?;
+
+    println!("{}", "Submitting signed order...".green().bold());
+
+    // Create DarkSwap instance
+    let mut darkswap = DarkSwap::new(config)?;
+
+    // Start DarkSwap
+    darkswap.start().await?;
+
+    // Submit order
+    let order = darkswap.submit_signed_order(signed_order).await?;
+
+    println!("{}", "Order submitted successfully!".green().bold());
+    println!("  ID:     {}", order.id.to_string().green());
+    println!("  Maker:  {}", order.maker.cyan());
+
+    // Stop DarkSwap
+    darkswap.stop().await?;
+
+    Ok(())
+}
+
+/// Rotate the node identity key
+async fn rotate_identity(mut config: Config, output: Option<&PathBuf>, no_publish: bool) -> Result<()> {
+    use colored::*;
+    use darkswap_sdk::reputation::{decode_identity_key, generate_identity_key, RotationStatement};
+
+    println!("{}", "Rotating node identity...".green().bold());
+
+    // The configuration must be saved with the new key
+    let config_path = config.config_path.clone()
+        .ok_or_else(|| anyhow::anyhow!("A configuration file is required to rotate the identity"))?;
+
+    // Load the current identity
+    let old_key = config.p2p.identity_key.as_deref()
+        .ok_or_else(|| anyhow::anyhow!("No persistent identity key configured; there is nothing to rotate"))?;
+    let old_keypair = decode_identity_key(old_key)?;
+
+    // Generate the new identity and link it to the old one
+    let new_key = generate_identity_key()?;
+    let new_keypair = decode_identity_key(&new_key)?;
+    let statement = RotationStatement::new(&old_keypair, &new_keypair)?;
+
+    println!("  Old peer ID: {}", statement.old_peer_id.yellow());
+    println!("  New peer ID: {}", statement.new_peer_id.green());
+
+    // Write the rotation statement
+    let statement_json = serde_json::to_string_pretty(&statement)?;
+    if let Some(path) = output {
+        std::fs::write(path, &statement_json).context("Failed to write rotation statement")?;
+        println!("Rotation statement saved to {}", path.display().to_string().blue());
+    } else {
+        println!("\n{}", "Rotation Statement:".bold());
+        println!("{}", statement_json);
+    }
+
+    // Save the new identity key
+    config.p2p.identity_key = Some(new_key);
+    config.to_file(&config_path).context("Failed to save configuration")?;
+    println!("New identity saved to {}", config_path.display().to_string().blue());
+
+    if no_publish {
+        return Ok(());
+    }
+
+    // Announce the rotation using the new identity
+    let mut darkswap = DarkSwap::new(config)?;
+    darkswap.start().await?;
+    darkswap.publish_rotation(&statement).await?;
+    darkswap.stop().await?;
+
+    println!("{}", "Identity rotation announced successfully!".green().bold());
+
+    Ok(())
+}
+
+/// Manage secrets in the OS keyring
+fn manage_secrets(command: SecretsCommands) -> Result<()> {
+    use colored::*;
+    use darkswap_sdk::secrets::{self, KEYRING_PREFIX};
+    use dialoguer::Password;
+
+    let store = secrets::default_store()?;
+
+    match command {
+        SecretsCommands::Set { name, value } => {
+            let value = match value {
+                Some(value) => value,
+                None => Password::new()
+                    .with_prompt(format!("Value of {}", name))
+                    .with_confirmation("Confirm value", "Values do not match")
+                    .interact()?,
+            };
+            store.set(&name, &value)?;
+            println!("{} Reference it in the configuration as {}", "Secret stored.".green().bold(), format!("\"{}{}\"", KEYRING_PREFIX, name).cyan());
         }
-        Commands::CancelOrder { order_id } => {
-            cancel_order(config, &order_id).await?;
+        SecretsCommands::Get { name } => {
+            println!("{}", store.get(&name)?);
         }
-        Commands::TakeOrder { order_id, amount } => {
-            take_order(config, &order_id, &amount).await?;
+        SecretsCommands::Delete { name } => {
+            if store.delete(&name)? {
+                println!("{}", "Secret removed.".green().bold());
+            } else {
+                println!("{}", format!("No secret named {}.", name).yellow());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Change the passphrase encrypting the node's storage
+///
+/// The daemon must be stopped while the key is rotated. With a keyring entry,
+/// the new passphrase is kept in a pending entry until the storage and its
+/// files are all under it, and running the command again completes a rotation
+/// that was interrupted.
+fn rotate_storage_key(config: &Config, data_key: bool) -> Result<()> {
+    use colored::*;
+    use darkswap_sdk::secrets;
+    use darkswap_sdk::storage::{self, MasterKey};
+    use dialoguer::Password;
+
+    let config = config.for_network(config.bitcoin.network);
+    let encryption = config.storage.encryption.as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Storage encryption is not enabled in the configuration"))?;
+    let keyring = match &encryption.keyring_entry {
+        Some(entry) => Some((secrets::default_store()?, entry.clone(), storage::pending_entry(entry))),
+        None => None,
+    };
+
+    // The current passphrase comes from the keyring or the environment, as for the daemon
+    let current = match &keyring {
+        Some((store, entry, _)) => store.get(entry).ok(),
+        None => std::env::var(&encryption.passphrase_env).ok().filter(|passphrase| !passphrase.is_empty()),
+    };
+    let current_key = MasterKey::Passphrase(match current {
+        Some(passphrase) => passphrase,
+        None => Password::new().with_prompt("Current storage passphrase").interact()?,
+    });
+
+    // An interrupted rotation is completed with the passphrase it started with
+    let pending = keyring.as_ref().and_then(|(store, _, pending_entry)| store.get(pending_entry).ok());
+    let new_passphrase = match pending {
+        Some(passphrase) => {
+            println!("{}", "Completing an interrupted rotation.".yellow());
+            passphrase
+        }
+        None => Password::new()
+            .with_prompt("New storage passphrase")
+            .with_confirmation("Confirm passphrase", "Passphrases do not match")
+            .interact()?,
+    };
+    if let Some((store, _, pending_entry)) = &keyring {
+        store.set(pending_entry, &new_passphrase)?;
+    }
+    let new_key = MasterKey::Passphrase(new_passphrase.clone());
+
+    if data_key {
+        println!("Re-encrypting records under a new data key...");
+    }
+    let files: Vec<std::path::PathBuf> = config.store_paths().into_iter().map(Into::into).collect();
+    storage::rotate_master_key(storage::open_backend(&config.storage)?, &current_key, &new_key, &files, data_key)?;
+
+    // Everything is under the new passphrase: make it the current one
+    match &keyring {
+        Some((store, entry, pending_entry)) => {
+            store.set(entry, &new_passphrase)?;
+            store.delete(pending_entry)?;
+            println!("{}", "Storage passphrase changed.".green().bold());
+        }
+        None => {
+            println!("{}", "Storage passphrase changed.".green().bold());
+            println!("Set {} to the new passphrase before starting the daemon.", encryption.passphrase_env.yellow());
+        }
+    }
+
+    Ok(())
+}
+
+/// Read an error message from a failed daemon response
+async fn daemon_error(response: reqwest::Response) -> anyhow::Error {
+    let status = response.status();
+    let message = response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| status.to_string());
+
+    anyhow::anyhow!(message)
+}
+
+/// Get a new receive address from a daemon
+async fn new_address(daemon_url: &str, label: Option<String>) -> Result<()> {
+    use colored::*;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/addresses", daemon_url.trim_end_matches('/')))
+        .json(&serde_json::json!({ "label": label }))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let info: AddressInfo = response.json().await.context("Failed to parse daemon address")?;
+
+    println!("{}", info.address.green().bold());
+    if let Some(label) = &info.label {
+        println!("Label: {}", label.cyan());
+    }
+    println!("Index: {}", info.index);
+
+    Ok(())
+}
+
+/// List the receive addresses issued by a daemon
+async fn list_addresses(daemon_url: &str) -> Result<()> {
+    use colored::*;
+    use prettytable::{format, Table, row, cell};
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/addresses", daemon_url.trim_end_matches('/')))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let addresses: Vec<AddressInfo> = response.json().await.context("Failed to parse daemon addresses")?;
+
+    if addresses.is_empty() {
+        println!("{}", "No addresses issued yet.".yellow());
+        return Ok(());
+    }
+
+    // Create a table for addresses
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row![
+        "Index".bold(),
+        "Address".bold(),
+        "Label".bold(),
+        "Status".bold(),
+        "Created".bold()
+    ]);
+
+    for info in addresses {
+        let status = if info.used { "USED".blue() } else { "UNUSED".green() };
+        let created = chrono::NaiveDateTime::from_timestamp_opt(info.created_at as i64, 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+
+        table.add_row(row![
+            info.index.to_string(),
+            info.address,
+            info.label.unwrap_or_default(),
+            status,
+            created
+        ]);
+    }
+
+    table.printstd();
+
+    Ok(())
+}
+
+/// Fetch and verify the market rules of a daemon
+async fn show_market_rules(daemon_url: &str, trusted_keys: &[String]) -> Result<()> {
+    use colored::*;
+    use darkswap_sdk::rules::SignedMarketRules;
+    use prettytable::{format, Table, row, cell};
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/market/rules", daemon_url.trim_end_matches('/')))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let signed_rules: SignedMarketRules = response.json().await.context("Failed to parse market rules")?;
+    signed_rules.verify(trusted_keys)?;
+    let rules = &signed_rules.rules;
+
+    println!("{}", "Market rules verified".green().bold());
+    println!("  Sequence:  {}", rules.sequence);
+    println!("  Issued:    {}", chrono::NaiveDateTime::from_timestamp_opt(rules.issued_at as i64, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S"));
+    if let Some(expires_at) = rules.expires_at {
+        let expires = chrono::NaiveDateTime::from_timestamp_opt(expires_at as i64, 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        if rules.is_expired(chrono::Utc::now().timestamp().max(0) as u64) {
+            println!("  Expires:   {} {}", expires, "(expired)".red());
+        } else {
+            println!("  Expires:   {}", expires);
+        }
+    }
+    if let Some(min_amount) = rules.min_amount {
+        println!("  Minimum:   {}", min_amount);
+    }
+    println!("  Fees:      maker {} bps, taker {} bps", rules.maker_fee_bps, rules.taker_fee_bps);
+    if let Some(notes) = &rules.notes {
+        println!("  Notes:     {}", notes);
+    }
+
+    if rules.pairs.is_empty() {
+        println!("{}", "All pairs are allowed.".yellow());
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row![
+        "Pair".bold(),
+        "Min".bold(),
+        "Max".bold(),
+        "Maker (bps)".bold(),
+        "Taker (bps)".bold()
+    ]);
+
+    for pair in &rules.pairs {
+        let (maker_fee_bps, taker_fee_bps) = rules.fees_bps(&pair.base_asset, &pair.quote_asset);
+        table.add_row(row![
+            format!("{}/{}", pair.base_asset, pair.quote_asset),
+            pair.min_amount.or(rules.min_amount).map(|amount| amount.to_string()).unwrap_or_default(),
+            pair.max_amount.map(|amount| amount.to_string()).unwrap_or_default(),
+            maker_fee_bps.to_string(),
+            taker_fee_bps.to_string()
+        ]);
+    }
+
+    table.printstd();
+
+    Ok(())
+}
+
+/// Show the 24h ticker of a pair from a daemon
+async fn show_ticker(daemon_url: &str, base_asset: &str, quote_asset: &str) -> Result<()> {
+    use colored::*;
+    use prettytable::{format, Table, row, cell};
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/market/ticker", daemon_url.trim_end_matches('/')))
+        .query(&[("base_asset", base_asset), ("quote_asset", quote_asset)])
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let ticker: Ticker = response.json().await.context("Failed to parse daemon ticker")?;
+
+    let price = |price: Option<Decimal>| price.map_or("N/A".yellow().to_string(), |price| price.to_string());
+    let change = match (ticker.price_change(), ticker.open_price) {
+        (Some(change), Some(open)) if !open.is_zero() => {
+            let text = format!("{} ({:.2}%)", change, change / open * Decimal::from(100));
+            if change.is_sign_negative() { text.red() } else { text.green() }
+        }
+        _ => "N/A".yellow(),
+    };
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row![
+        format!("24h Ticker for {}/{}", ticker.base_asset.to_string().cyan(), ticker.quote_asset.to_string().cyan()).bold()
+    ]);
+    table.add_row(row!["Last Price", price(ticker.last_price)]);
+    table.add_row(row!["24h Change", change]);
+    table.add_row(row!["24h High", price(ticker.high)]);
+    table.add_row(row!["24h Low", price(ticker.low)]);
+    table.add_row(row!["24h Volume", format!("{} {}", ticker.volume, ticker.base_asset)]);
+    table.add_row(row!["24h Quote Volume", format!("{} {}", ticker.quote_volume, ticker.quote_asset)]);
+    table.add_row(row!["24h Trades", ticker.trade_count]);
+    table.printstd();
+
+    Ok(())
+}
+
+/// Print the latency timeline of a trade of a daemon
+async fn trade_timeline(daemon_url: &str, trade_id: &str) -> Result<()> {
+    use colored::*;
+    use prettytable::{format, Table, row, cell};
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/trades/{}/timeline", daemon_url.trim_end_matches('/'), trade_id))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let timeline: TradeTimeline = response.json().await.context("Failed to parse daemon trade timeline")?;
+
+    println!("\n{}", "Trade Timeline:".bold());
+    println!("  Trade ID:  {}", timeline.trade_id.to_string().green());
+    println!("  State:     {:?}", timeline.state);
+
+    if timeline.steps.is_empty() {
+        println!("{}", "No stages recorded for this trade.".yellow());
+        return Ok(());
+    }
+
+    // Create a table for the stages
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row![
+        "Stage".bold(),
+        "Time".bold(),
+        "Step".bold(),
+        "Elapsed".bold()
+    ]);
+
+    for step in &timeline.steps {
+        let at = chrono::NaiveDateTime::from_timestamp_millis(step.at_ms as i64)
+            .map(|at| at.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+            .unwrap_or_default();
+        let stage = if timeline.slowest == Some(step.stage) {
+            step.stage.to_string().red()
+        } else {
+            step.stage.to_string().normal()
+        };
+
+        table.add_row(row![
+            stage,
+            at,
+            format!("+{} ms", step.since_previous_ms),
+            format!("{} ms", step.since_start_ms)
+        ]);
+    }
+
+    table.printstd();
+    println!();
+    println!("Total: {} ms", timeline.total_ms.to_string().cyan());
+
+    Ok(())
+}
+
+/// Ask full peers to forward a daemon only the orders of some pairs and makers
+async fn set_order_interest(daemon_url: &str, peers: Vec<String>, pairs: &[String], makers: Vec<String>) -> Result<()> {
+    use colored::*;
+
+    let pairs = pairs
+        .iter()
+        .map(|pair| {
+            let (base_asset, quote_asset) = pair
+                .split_once('/')
+                .ok_or_else(|| anyhow::anyhow!("Invalid pair: {} (expected BASE/QUOTE)", pair))?;
+            Ok(serde_json::json!({ "base_asset": base_asset, "quote_asset": quote_asset }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if pairs.is_empty() && makers.is_empty() {
+        anyhow::bail!("At least one pair or maker is required");
+    }
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/orders/interest", daemon_url.trim_end_matches('/')))
+        .json(&serde_json::json!({ "peers": peers, "pairs": pairs, "makers": makers }))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let body: serde_json::Value = response.json().await.context("Failed to parse daemon response")?;
+
+    let reached = body["peers"].as_u64().unwrap_or(0);
+    if reached == 0 {
+        println!("{}", "No peer could be reached.".yellow());
+    } else {
+        println!("{}", format!("Interest filter sent to {} of {} peers.", reached, peers.len()).green());
+    }
+
+    Ok(())
+}
+
+/// Ask peers for the recent orders of a pair on behalf of a daemon
+async fn backfill_orders(daemon_url: &str, peers: Vec<String>, pair: &str, minutes: u64) -> Result<()> {
+    use colored::*;
+
+    let (base_asset, quote_asset) = pair
+        .split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("Invalid pair: {} (expected BASE/QUOTE)", pair))?;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/orders/backfill", daemon_url.trim_end_matches('/')))
+        .json(&serde_json::json!({
+            "peers": peers,
+            "base_asset": base_asset,
+            "quote_asset": quote_asset,
+            "minutes": minutes,
+        }))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let body: serde_json::Value = response.json().await.context("Failed to parse daemon response")?;
+
+    let reached = body["peers"].as_u64().unwrap_or(0);
+    if reached == 0 {
+        println!("{}", "No peer could be reached.".yellow());
+    } else {
+        println!("{}", format!("Backfill requested from {} of {} peers.", reached, peers.len()).green());
+    }
+
+    Ok(())
+}
+
+/// List the relays of a daemon with their health, in the order they are tried
+async fn list_relays(daemon_url: &str) -> Result<()> {
+    use colored::*;
+    use prettytable::{format, Table, row, cell};
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/relays", daemon_url.trim_end_matches('/')))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let relays: Vec<RelayHealth> = response.json().await.context("Failed to parse daemon relays")?;
+
+    if relays.is_empty() {
+        println!("{}", "No relays in use.".yellow());
+        return Ok(());
+    }
+
+    // Create a table for the relays
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row![
+        "Relay".bold(),
+        "Health".bold(),
+        "Uptime".bold(),
+        "Latency".bold(),
+        "Checks".bold(),
+        "Last Success".bold(),
+        "Last Error".bold()
+    ]);
+
+    for relay in &relays {
+        let health = match (relay.healthy, relay.checks) {
+            (true, _) => "HEALTHY".green(),
+            (false, 0) => "UNCHECKED".yellow(),
+            (false, _) => "FAILING".red(),
+        };
+        let address = if relay.configured {
+            relay.address.normal()
+        } else {
+            format!("{} (remembered)", relay.address).dimmed()
+        };
+        let last_success = relay.last_success
+            .and_then(|at| chrono::NaiveDateTime::from_timestamp_opt(at as i64, 0))
+            .map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "-".to_string());
+
+        table.add_row(row![
+            address,
+            health,
+            relay.uptime.map_or("-".to_string(), |uptime| format!("{:.0}%", uptime * 100.0)),
+            relay.latency_ms.map_or("-".to_string(), |latency| format!("{} ms", latency)),
+            format!("{}/{}", relay.successes, relay.checks),
+            last_success,
+            relay.last_error.as_deref().unwrap_or("-")
+        ]);
+    }
+
+    table.printstd();
+
+    Ok(())
+}
+
+/// List the trades of a daemon with their memos
+async fn list_trades(daemon_url: &str, filter: &[(&str, Option<String>)]) -> Result<()> {
+    use colored::*;
+    use prettytable::{format, Table, row, cell};
+
+    let mut query: Vec<(&str, &str)> = filter.iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (*key, value)))
+        .collect();
+
+    // Ask for a page, so the daemon answers with the cursor of the next one
+    let default_limit = DEFAULT_PAGE_SIZE.to_string();
+    if !query.iter().any(|(key, _)| *key == "limit") {
+        query.push(("limit", &default_limit));
+    }
+    let response = reqwest::Client::new()
+        .get(format!("{}/trades", daemon_url.trim_end_matches('/')))
+        .query(&query)
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let page: TradePage<TradeHistoryEntry> = response.json().await.context("Failed to parse daemon trades")?;
+    let history = page.trades;
+
+    if history.is_empty() {
+        println!("{}", "No trades found matching the criteria.".yellow());
+        return Ok(());
+    }
+
+    // Create a table for trades
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.add_row(row![
+        "ID".bold(),
+        "Pair".bold(),
+        "Amount".bold(),
+        "Price".bold(),
+        "State".bold(),
+        "Labels".bold(),
+        "Note".bold()
+    ]);
+
+    for entry in history {
+        let trade = entry.trade;
+        let state = match trade.state {
+            TradeState::Completed => "COMPLETED".green(),
+            TradeState::Failed | TradeState::Expired => format!("{:?}", trade.state).to_uppercase().red(),
+            TradeState::Canceled => "CANCELED".yellow(),
+            _ => format!("{:?}", trade.state).to_uppercase().blue(),
+        };
+
+        // Trade and transaction memos are shown together
+        let memos: Vec<&Memo> = entry.memo.iter().chain(entry.transaction_memo.iter()).collect();
+        let labels = memos.iter().flat_map(|memo| memo.labels.iter().cloned()).collect::<Vec<_>>().join(", ");
+        let note = memos.iter().filter_map(|memo| memo.note.clone()).collect::<Vec<_>>().join(" / ");
+
+        table.add_row(row![
+            trade.id.0,
+            format!("{}/{}", trade.base_asset, trade.quote_asset),
+            trade.amount.to_string(),
+            trade.price.to_string(),
+            state,
+            labels.cyan(),
+            note
+        ]);
+    }
+
+    table.printstd();
+
+    if let Some(cursor) = page.next_cursor {
+        println!();
+        println!("More trades: pass {} for the next page", format!("--cursor {}", cursor).cyan());
+    }
+
+    Ok(())
+}
+
+/// Set the memo of a trade or transaction on a daemon
+async fn set_memo(
+    daemon_url: &str,
+    trade_id: Option<&str>,
+    txid: Option<&str>,
+    labels: Vec<String>,
+    note: Option<String>,
+) -> Result<()> {
+    use colored::*;
+
+    let path = match (trade_id, txid) {
+        (Some(trade_id), _) => format!("trades/{}/memo", trade_id),
+        (None, Some(txid)) => format!("transactions/{}/memo", txid),
+        (None, None) => anyhow::bail!("Either a trade ID or a transaction ID is required"),
+    };
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/{}", daemon_url.trim_end_matches('/'), path))
+        .json(&serde_json::json!({ "labels": labels, "note": note }))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let memo: Memo = response.json().await.context("Failed to parse daemon memo")?;
+
+    if memo.is_empty() {
+        println!("{}", "Memo removed.".yellow());
+    } else {
+        println!("{}", "Memo saved.".green());
+    }
+
+    Ok(())
+}
+
+/// Consolidate the dust of a daemon wallet
+async fn consolidate(daemon_url: &str, force: bool, dry_run: bool) -> Result<()> {
+    use colored::*;
+
+    /// Consolidation response
+    #[derive(serde::Deserialize)]
+    struct ConsolidateResponse {
+        plan: ConsolidationPlan,
+        txid: Option<String>,
+    }
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/wallet/consolidate", daemon_url.trim_end_matches('/')))
+        .json(&serde_json::json!({ "force": force, "dry_run": dry_run }))
+        .send()
+        .await
+        .context("Failed to reach daemon")?;
+    if !response.status().is_success() {
+        return Err(daemon_error(response).await);
+    }
+    let ConsolidateResponse { plan, txid } = response.json().await.context("Failed to parse daemon response")?;
+
+    println!("Inputs: {} dust outputs ({} sats)", plan.inputs.len(), plan.input_value);
+    println!("Output: {} sats", plan.output_value);
+    println!("Fee: {} sats at {} sat/vB", plan.fee, plan.fee_rate);
+    if plan.skipped_asset_outputs > 0 {
+        println!("Kept {} dust outputs holding runes or alkanes", plan.skipped_asset_outputs);
+    }
+    if plan.skipped_uneconomic_outputs > 0 {
+        println!("Kept {} dust outputs worth less than the fee to spend them", plan.skipped_uneconomic_outputs);
+    }
+
+    match txid {
+        Some(txid) => println!("{} {}", "Consolidation broadcast:".green().bold(), txid),
+        None => println!("{}", "Dry run, nothing was broadcast.".yellow()),
+    }
+
+    Ok(())
+}
+
+/// Build the simple wallet the configuration describes
+fn configured_wallet(config: &Config) -> Result<SimpleWallet> {
+    use darkswap_sdk::secrets::SecretResolver;
+
+    let config = config.resolve_secrets(&mut SecretResolver::new())?;
+    if config.wallet.wallet_type != "simple" {
+        anyhow::bail!("Descriptors are only supported for simple wallets, not {}", config.wallet.wallet_type);
+    }
+
+    match (&config.wallet.descriptor, &config.wallet.private_key) {
+        (Some(descriptor), _) => SimpleWallet::from_descriptor(descriptor, config.bitcoin.network),
+        (None, Some(private_key)) => SimpleWallet::new(Some(private_key), config.bitcoin.network),
+        (None, None) => anyhow::bail!("No wallet key is configured"),
+    }
+}
+
+/// Print the output descriptor of the configured wallet
+async fn export_descriptor(config: &Config, private: bool) -> Result<()> {
+    use colored::*;
+
+    let wallet = configured_wallet(config)?;
+    let descriptor = wallet.export_descriptor(private).await?;
+
+    if private {
+        eprintln!("{}", "The descriptor holds the wallet's private key; anyone with it can spend the funds.".yellow());
+    }
+    println!("{}", descriptor);
+
+    Ok(())
+}
+
+/// Replace the wallet key in the configuration by a descriptor
+async fn import_descriptor(mut config: Config, descriptor: &str) -> Result<()> {
+    use colored::*;
+
+    // Refuse a descriptor the daemon could not load
+    let wallet = SimpleWallet::from_descriptor(descriptor, config.bitcoin.network)?;
+    let first_address = wallet.get_new_address(None).await?.address;
+
+    config.wallet.wallet_type = "simple".to_string();
+    config.wallet.descriptor = Some(descriptor.trim().to_string());
+    config.wallet.private_key = None;
+
+    let config_path = match config.config_path.clone() {
+        Some(path) => path,
+        None => {
+            let home_dir = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not determine home directory"))?;
+            let config_dir = home_dir.join(".darkswap");
+            std::fs::create_dir_all(&config_dir)?;
+            config_dir.join("config.json")
+        }
+    };
+    config.to_file(&config_path)?;
+
+    println!("{}", "Descriptor imported.".green().bold());
+    println!("First receive address: {}", first_address);
+    println!("Wallet configuration saved to {}", config_path.display().to_string().blue());
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Parse command line arguments
+    let cli = Cli::parse();
+
+    // Load or create configuration
+    let config = load_or_create_config(cli.config, &cli.network)?;
+
+    // Execute command
+    match cli.command {
+        Commands::Daemon { listen, no_prompt } => {
+            start_daemon(config, &listen, no_prompt).await?;
+        }
+        Commands::CreateOrder {
+            base_asset,
+            quote_asset,
+            side,
+            amount,
+            price,
+            expiry,
+        } => {
+            create_order(config, &base_asset, &quote_asset, &side, &amount, &price, expiry).await?;
+        }
+        Commands::CancelOrder { order_id } => {
+            cancel_order(config, &order_id).await?;
+        }
+        Commands::TakeOrder { order_id, amount, payout_address } => {
+            take_order(config, &order_id, &amount, payout_address).await?;
         }
         Commands::ListOrders {
             base_asset,
@@ -976,6 +2403,19 @@ async fn main() -> Result<()> {
         } => {
             get_market_data(config, &base_asset, &quote_asset).await?;
         }
+        Commands::Asset { asset } => {
+            show_asset(config, &asset).await?;
+        }
+        Commands::Ticker {
+            daemon,
+            base_asset,
+            quote_asset,
+        } => {
+            show_ticker(&daemon, &base_asset, &quote_asset).await?;
+        }
+        Commands::MarketRules { daemon, trusted_key } => {
+            show_market_rules(&daemon, &trusted_key).await?;
+        }
         Commands::ConnectWallet {
             wallet_type,
             private_key,
@@ -984,7 +2424,172 @@ async fn main() -> Result<()> {
         } => {
             connect_wallet(config, &wallet_type, private_key.as_deref(), mnemonic.as_deref(), derivation_path.as_deref()).await?;
         }
+        Commands::PrepareOrder {
+            base_asset,
+            quote_asset,
+            side,
+            amount,
+            price,
+            expiry,
+            maker,
+            output,
+        } => {
+            prepare_order(&config, &base_asset, &quote_asset, &side, &amount, &price, expiry, maker.as_deref(), &output)?;
+        }
+        Commands::SignOrder { file, output } => {
+            sign_order(&config, &file, output.as_ref())?;
+        }
+        Commands::SignRules { file, output } => {
+            sign_rules(&config, &file, output.as_ref())?;
+        }
+        Commands::SubmitOrder { file } => {
+            submit_order(config, &file).await?;
+        }
+        Commands::Watch { pair, daemon, depth } => {
+            watch::watch(config, &pair, daemon.as_deref(), depth).await?;
+        }
+        Commands::Address { daemon, command } => match command {
+            AddressCommands::New { label } => {
+                new_address(&daemon, label).await?;
+            }
+            AddressCommands::List => {
+                list_addresses(&daemon).await?;
+            }
+        },
+        Commands::Trades {
+            daemon,
+            base_asset,
+            quote_asset,
+            state,
+            label,
+            query,
+            counterparty,
+            since,
+            until,
+            sort,
+            limit,
+            cursor,
+        } => {
+            let filter = [
+                ("base_asset", base_asset),
+                ("quote_asset", quote_asset),
+                ("state", state),
+                ("label", label),
+                ("query", query),
+                ("counterparty", counterparty),
+                ("since", since.map(|since| since.to_string())),
+                ("until", until.map(|until| until.to_string())),
+                ("sort", sort),
+                ("limit", limit.map(|limit| limit.to_string())),
+                ("cursor", cursor),
+            ];
+            list_trades(&daemon, &filter).await?;
+        }
+        Commands::Interest { daemon, peers, pairs, makers } => {
+            set_order_interest(&daemon, peers, &pairs, makers).await?;
+        }
+        Commands::Backfill { daemon, peers, pair, minutes } => {
+            backfill_orders(&daemon, peers, &pair, minutes).await?;
+        }
+        Commands::Relays { daemon } => {
+            list_relays(&daemon).await?;
+        }
+        Commands::Trade { daemon, command } => match command {
+            TradeCommands::Timeline { trade_id } => {
+                trade_timeline(&daemon, &trade_id).await?;
+            }
+        },
+        Commands::Memo {
+            daemon,
+            trade,
+            txid,
+            labels,
+            note,
+        } => {
+            set_memo(&daemon, trade.as_deref(), txid.as_deref(), labels, note).await?;
+        }
+        Commands::Wallet { daemon, command } => match command {
+            WalletCommands::Consolidate { force, dry_run } => {
+                consolidate(&daemon, force, dry_run).await?;
+            }
+            WalletCommands::ExportDescriptor { private } => {
+                export_descriptor(&config, private).await?;
+            }
+            WalletCommands::ImportDescriptor { descriptor } => {
+                import_descriptor(config, &descriptor).await?;
+            }
+        },
+        Commands::RotateIdentity { output, no_publish } => {
+            rotate_identity(config, output.as_ref(), no_publish).await?;
+        }
+        Commands::RotateStorageKey { data_key } => {
+            rotate_storage_key(&config, data_key)?;
+        }
+        Commands::Secrets { command } => {
+            manage_secrets(command)?;
+        }
+        #[cfg(feature = "devtools")]
+        Commands::Dev { command } => {
+            dev::run(config, command).await?;
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkswap_sdk::reputation::{generate_identity_key, identity_peer_id};
+
+    #[test]
+    fn test_offline_signing_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let unsigned = dir.path().join("order.json");
+        let signed = dir.path().join("order.signed.json");
+
+        // The online machine prepares the order for the offline key's peer ID
+        let key = generate_identity_key().unwrap();
+        let maker = identity_peer_id(&key).unwrap();
+        prepare_order(&Config::default(), "BTC", "RUNE:840000:3", "sell", "0.5", "100", Some(3600), Some(&maker), &unsigned).unwrap();
+
+        // The offline machine holds the key and signs
+        let mut offline = Config::default();
+        offline.p2p.identity_key = Some(key);
+        sign_order(&offline, &unsigned, Some(&signed)).unwrap();
+
+        let signed_order = read_signed_order(&signed).unwrap();
+        let prepared: Order = serde_json::from_str(&std::fs::read_to_string(&unsigned).unwrap()).unwrap();
+        assert_eq!(signed_order.order.id, prepared.id);
+        assert_eq!(signed_order.order.maker, maker);
+        assert_eq!(signed_order.order.base_asset, Asset::Bitcoin);
+        assert_eq!(signed_order.order.quote_asset, Asset::Rune((840_000 << 16) | 3));
+    }
+
+    #[test]
+    fn test_tampered_signed_order_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let unsigned = dir.path().join("order.json");
+        let signed = dir.path().join("order.signed.json");
+
+        let key = generate_identity_key().unwrap();
+        let maker = identity_peer_id(&key).unwrap();
+        prepare_order(&Config::default(), "BTC", "RUNE:1", "buy", "1", "100", None, Some(&maker), &unsigned).unwrap();
+        let mut offline = Config::default();
+        offline.p2p.identity_key = Some(key);
+        sign_order(&offline, &unsigned, Some(&signed)).unwrap();
+
+        // A better price after signing breaks the signature
+        let mut json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&signed).unwrap()).unwrap();
+        json["order"]["price"] = serde_json::json!("1");
+        std::fs::write(&signed, json.to_string()).unwrap();
+        assert!(read_signed_order(&signed).is_err());
+
+        // So does signing an order prepared for another maker
+        let other = dir.path().join("other.json");
+        let other_maker = identity_peer_id(&generate_identity_key().unwrap()).unwrap();
+        prepare_order(&Config::default(), "BTC", "RUNE:1", "buy", "1", "100", None, Some(&other_maker), &other).unwrap();
+        sign_order(&offline, &other, Some(&signed)).unwrap();
+        assert!(read_signed_order(&signed).is_err());
+    }
+}
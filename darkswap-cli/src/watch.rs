@@ -0,0 +1,469 @@
+//! Watch mode for the DarkSwap CLI
+//!
+//! Renders a continuously updating order book and trade tape for one pair. The
+//! view is built from the event stream, either of an embedded node or of a
+//! running daemon reached over its REST and WebSocket API.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use colored::*;
+use darkswap_sdk::{
+    config::Config,
+    orderbook::{Order, OrderId, OrderSide, OrderStatus},
+    trade::Trade,
+    types::{Asset, Event, TradeId},
+    DarkSwap,
+};
+use futures_util::StreamExt;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::parse_asset;
+
+/// Number of trades kept on the tape
+const TAPE_LENGTH: usize = 20;
+
+/// Message sent by the daemon WebSocket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+enum DaemonMessage {
+    /// Event
+    Event {
+        /// Event type
+        event_type: String,
+        /// Event data
+        data: serde_json::Value,
+    },
+    /// Any other message
+    #[serde(other)]
+    Other,
+}
+
+/// Source of book updates
+enum Source {
+    /// Node running in this process
+    Embedded(DarkSwap),
+    /// Daemon reached over WebSocket
+    Daemon(WebSocketStream<MaybeTlsStream<TcpStream>>),
+}
+
+impl Source {
+    /// Wait for the next event, or `None` when the stream ended
+    async fn next_event(&mut self) -> Result<Option<Event>> {
+        match self {
+            Source::Embedded(darkswap) => Ok(darkswap.next_event().await),
+            Source::Daemon(stream) => loop {
+                let text = match stream.next().await {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => return Ok(None),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e).context("Daemon connection failed"),
+                };
+
+                // Confirmations and errors are not events
+                if let Ok(DaemonMessage::Event { event_type, data }) = serde_json::from_str(&text) {
+                    match serde_json::from_value::<Event>(data) {
+                        Ok(event) => return Ok(Some(event)),
+                        Err(_) => log::debug!("Ignoring daemon message: {}", event_type),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Look up a trade, if the source can
+    async fn get_trade(&self, trade_id: &TradeId) -> Option<Trade> {
+        match self {
+            Source::Embedded(darkswap) => darkswap.get_trade(trade_id).await.ok(),
+            Source::Daemon(_) => None,
+        }
+    }
+
+    /// Describe the source for the header
+    fn describe(&self, daemon_url: Option<&str>) -> String {
+        match self {
+            Source::Embedded(_) => "embedded node".to_string(),
+            Source::Daemon(_) => format!("daemon {}", daemon_url.unwrap_or_default()),
+        }
+    }
+}
+
+/// Book and trade tape of one pair
+struct WatchState {
+    /// Base asset
+    base_asset: Asset,
+    /// Quote asset
+    quote_asset: Asset,
+    /// Open orders of the pair
+    orders: HashMap<OrderId, Order>,
+    /// Recent trades, newest first
+    tape: VecDeque<String>,
+    /// Number of events dropped by a lagging subscription
+    dropped_events: u64,
+}
+
+impl WatchState {
+    /// Create an empty state for a pair
+    fn new(base_asset: Asset, quote_asset: Asset) -> Self {
+        Self {
+            base_asset,
+            quote_asset,
+            orders: HashMap::new(),
+            tape: VecDeque::new(),
+            dropped_events: 0,
+        }
+    }
+
+    /// Check if an order belongs to the watched pair
+    fn is_pair(&self, order: &Order) -> bool {
+        order.base_asset == self.base_asset && order.quote_asset == self.quote_asset
+    }
+
+    /// Add or replace an order
+    fn upsert(&mut self, order: Order) {
+        if !self.is_pair(&order) {
+            return;
+        }
+
+        if order.status == OrderStatus::Open {
+            self.orders.insert(order.id.clone(), order);
+        } else {
+            self.orders.remove(&order.id);
+        }
+    }
+
+    /// Add a line to the trade tape
+    fn push_trade(&mut self, line: String) {
+        let now = chrono::Local::now().format("%H:%M:%S");
+        self.tape.push_front(format!("{}  {}", now, line));
+        self.tape.truncate(TAPE_LENGTH);
+    }
+
+    /// Apply an event, returning true if the view changed
+    fn apply(&mut self, event: Event) -> bool {
+        match event {
+            Event::OrderCreated(order) | Event::OrderUpdated(order) => {
+                let relevant = self.is_pair(&order);
+                self.upsert(order);
+                relevant
+            }
+            Event::OrderCancelled(order_id) | Event::OrderExpired(order_id) => {
+                self.orders.remove(&order_id).is_some()
+            }
+            Event::OrderFilled(order_id) => match self.orders.remove(&order_id) {
+                Some(order) => {
+                    let side = match order.side {
+                        OrderSide::Buy => "BUY ".green(),
+                        OrderSide::Sell => "SELL".red(),
+                    };
+                    self.push_trade(format!("{} {} @ {}", side, order.amount, order.price));
+                    true
+                }
+                None => false,
+            },
+            Event::LaggedEvents(count) => {
+                self.dropped_events += count;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Aggregate the orders of one side by price, best price first
+    fn levels(&self, side: OrderSide, depth: usize) -> Vec<(Decimal, Decimal, usize)> {
+        let mut levels: BTreeMap<Decimal, (Decimal, usize)> = BTreeMap::new();
+
+        for order in self.orders.values().filter(|order| order.side == side && !order.is_expired()) {
+            let level = levels.entry(order.price).or_insert((Decimal::ZERO, 0));
+            level.0 += order.amount;
+            level.1 += 1;
+        }
+
+        let levels = levels.into_iter().map(|(price, (amount, count))| (price, amount, count));
+        match side {
+            OrderSide::Buy => levels.rev().take(depth).collect(),
+            OrderSide::Sell => levels.take(depth).collect(),
+        }
+    }
+
+    /// Render the book and trade tape
+    fn render(&self, source: &str, depth: usize) -> Result<()> {
+        let asks = self.levels(OrderSide::Sell, depth);
+        let bids = self.levels(OrderSide::Buy, depth);
+
+        let mut out = String::new();
+
+        // Clear the screen and move the cursor home
+        out.push_str("\x1B[2J\x1B[H");
+
+        out.push_str(&format!(
+            "{}  {}/{}  ({})  {}\n\n",
+            "DarkSwap".bold(),
+            self.base_asset.to_string().cyan(),
+            self.quote_asset.to_string().cyan(),
+            source,
+            chrono::Local::now().format("%H:%M:%S"),
+        ));
+        out.push_str(&format!("{}\n", format!("{:>20} {:>20} {:>8}", "Price", "Amount", "Orders").bold()));
+
+        // Asks are printed worst first so the best prices meet at the spread
+        for (price, amount, count) in asks.iter().rev() {
+            out.push_str(&format!("{} {:>20} {:>8}\n", format!("{:>20}", price).red(), amount, count));
+        }
+
+        let spread = match (bids.first(), asks.first()) {
+            (Some(&(bid, _, _)), Some(&(ask, _, _))) if !bid.is_zero() => {
+                let spread = ask - bid;
+                format!("spread {} ({:.2}%)", spread, spread / bid * Decimal::from(100))
+            }
+            _ => "spread N/A".to_string(),
+        };
+        out.push_str(&format!("{:^50}\n", spread).blue().to_string());
+
+        for (price, amount, count) in &bids {
+            out.push_str(&format!("{} {:>20} {:>8}\n", format!("{:>20}", price).green(), amount, count));
+        }
+
+        // Trade tape
+        out.push_str(&format!("\n{}\n", "Trades".bold()));
+        if self.tape.is_empty() {
+            out.push_str(&format!("{}\n", "No trades yet.".yellow()));
+        }
+        for line in &self.tape {
+            out.push_str(&format!("{}\n", line));
+        }
+
+        if self.dropped_events > 0 {
+            out.push_str(&format!(
+                "\n{}\n",
+                format!("{} events dropped, the view may be stale", self.dropped_events).red()
+            ));
+        }
+        out.push_str(&format!("\n{}\n", "Press Ctrl+C to exit".dimmed()));
+
+        let mut stdout = std::io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Parse a pair such as `BTC/RUNE:840000:3`
+fn parse_pair(pair: &str) -> Result<(Asset, Asset)> {
+    let (base, quote) = pair
+        .split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("Invalid pair: {} (expected BASE/QUOTE)", pair))?;
+
+    Ok((parse_asset(base)?, parse_asset(quote)?))
+}
+
+/// Format an asset the way the daemon API parses it
+fn api_asset(asset: &Asset) -> String {
+    match asset {
+        Asset::Bitcoin => "BTC".to_string(),
+        Asset::Rune(id) => format!("RUNE:{}", id),
+        Asset::Alkane(id) => id.0.clone(),
+    }
+}
+
+/// Attach to a daemon, returning its open orders for the pair and its event stream
+async fn attach_daemon(url: &str, base_asset: &Asset, quote_asset: &Asset) -> Result<(Vec<Order>, Source)> {
+    let url = url.trim_end_matches('/');
+
+    // Fetch the current book
+    let orders: Vec<Order> = reqwest::Client::new()
+        .get(format!("{}/orders", url))
+        .query(&[
+            ("base_asset", api_asset(base_asset)),
+            ("quote_asset", api_asset(quote_asset)),
+            ("side", "all".to_string()),
+            ("status", "open".to_string()),
+        ])
+        .send()
+        .await
+        .context("Failed to reach daemon")?
+        .error_for_status()
+        .context("Daemon refused the order request")?
+        .json()
+        .await
+        .context("Failed to parse daemon orders")?;
+
+    // Follow the event stream
+    let ws_url = if let Some(rest) = url.strip_prefix("https://") {
+        format!("wss://{}/ws", rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        format!("ws://{}/ws", rest)
+    } else {
+        format!("ws://{}/ws", url)
+    };
+    let (stream, _) = connect_async(ws_url.as_str())
+        .await
+        .with_context(|| format!("Failed to connect to {}", ws_url))?;
+
+    Ok((orders, Source::Daemon(stream)))
+}
+
+/// Watch the book and trades of a pair
+pub async fn watch(config: Config, pair: &str, daemon_url: Option<&str>, depth: usize) -> Result<()> {
+    let (base_asset, quote_asset) = parse_pair(pair)?;
+    let mut state = WatchState::new(base_asset.clone(), quote_asset.clone());
+
+    // Connect to the event source and load the current book
+    let (orders, mut source) = match daemon_url {
+        Some(url) => attach_daemon(url, &base_asset, &quote_asset).await?,
+        None => {
+            let mut darkswap = DarkSwap::new(config)?;
+            darkswap.start().await?;
+            let orders = darkswap.get_orders(&base_asset, &quote_asset).await?;
+            (orders, Source::Embedded(darkswap))
+        }
+    };
+    for order in orders {
+        state.upsert(order);
+    }
+
+    let description = source.describe(daemon_url);
+    state.render(&description, depth)?;
+
+    // Redraw periodically as well, so expired orders drop off
+    let mut refresh = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            event = source.next_event() => {
+                let event = match event? {
+                    Some(event) => event,
+                    None => {
+                        println!("{}", "Event stream closed".yellow());
+                        break;
+                    }
+                };
+
+                // Completed trades are looked up to show their size and price
+                if let Event::TradeCompleted(trade_id) = &event {
+                    match source.get_trade(trade_id).await {
+                        Some(trade) if trade.base_asset == base_asset && trade.quote_asset == quote_asset => {
+                            state.push_trade(format!("{} {} @ {}", "TRADE".blue(), trade.amount, trade.price));
+                        }
+                        Some(_) => continue,
+                        None => state.push_trade(format!("{} {}", "TRADE".blue(), trade_id)),
+                    }
+                    state.render(&description, depth)?;
+                    continue;
+                }
+
+                if state.apply(event) {
+                    state.render(&description, depth)?;
+                }
+            }
+            _ = refresh.tick() => {
+                state.render(&description, depth)?;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                break;
+            }
+        }
+    }
+
+    // Stop the embedded node
+    if let Source::Embedded(mut darkswap) = source {
+        darkswap.stop().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn order(side: OrderSide, amount: Decimal, price: Decimal) -> Order {
+        Order::new("maker".to_string(), Asset::Bitcoin, Asset::Rune(1), side, amount, price, None)
+    }
+
+    #[test]
+    fn test_parse_pair() {
+        assert_eq!(parse_pair("BTC/RUNE:840000:3").unwrap(), (Asset::Bitcoin, Asset::Rune((840_000 << 16) | 3)));
+        assert_eq!(parse_pair("RUNE:1/BTC").unwrap(), (Asset::Rune(1), Asset::Bitcoin));
+
+        assert!(parse_pair("BTC").is_err());
+        assert!(parse_pair("BTC/ETH").is_err());
+        assert!(parse_pair("BTC/RUNE:840000:x").is_err());
+    }
+
+    #[test]
+    fn test_events_update_the_book() {
+        let mut state = WatchState::new(Asset::Bitcoin, Asset::Rune(1));
+        let ask = order(OrderSide::Sell, dec!(1), dec!(101));
+        let bid = order(OrderSide::Buy, dec!(2), dec!(99));
+        let other_bid = order(OrderSide::Buy, dec!(3), dec!(99));
+
+        assert!(state.apply(Event::OrderCreated(ask.clone())));
+        assert!(state.apply(Event::OrderCreated(bid.clone())));
+        assert!(state.apply(Event::OrderCreated(other_bid.clone())));
+        assert_eq!(state.levels(OrderSide::Buy, 10), vec![(dec!(99), dec!(5), 2)]);
+        assert_eq!(state.levels(OrderSide::Sell, 10), vec![(dec!(101), dec!(1), 1)]);
+
+        // Orders of other pairs are not shown
+        let mut foreign = order(OrderSide::Sell, dec!(1), dec!(100));
+        foreign.quote_asset = Asset::Rune(2);
+        assert!(!state.apply(Event::OrderCreated(foreign)));
+
+        // An update that closes an order takes it off the book
+        let mut filled = other_bid.clone();
+        filled.status = OrderStatus::Filled;
+        assert!(state.apply(Event::OrderUpdated(filled)));
+        assert_eq!(state.levels(OrderSide::Buy, 10), vec![(dec!(99), dec!(2), 1)]);
+
+        assert!(state.apply(Event::OrderCancelled(ask.id.clone())));
+        assert!(!state.apply(Event::OrderCancelled(ask.id)));
+        assert!(state.levels(OrderSide::Sell, 10).is_empty());
+        assert!(state.tape.is_empty());
+    }
+
+    #[test]
+    fn test_fills_go_on_the_tape() {
+        let mut state = WatchState::new(Asset::Bitcoin, Asset::Rune(1));
+        let bid = order(OrderSide::Buy, dec!(0.5), dec!(99));
+        state.apply(Event::OrderCreated(bid.clone()));
+
+        assert!(state.apply(Event::OrderFilled(bid.id.clone())));
+        assert!(state.orders.is_empty());
+        assert_eq!(state.tape.len(), 1);
+        assert!(state.tape[0].contains("0.5 @ 99"));
+
+        // A fill of an order not on the book changes nothing
+        assert!(!state.apply(Event::OrderFilled(bid.id)));
+
+        // The tape keeps the newest trades
+        for n in 0..TAPE_LENGTH {
+            state.push_trade(format!("trade {}", n));
+        }
+        assert_eq!(state.tape.len(), TAPE_LENGTH);
+        assert!(state.tape[0].ends_with(&format!("trade {}", TAPE_LENGTH - 1)));
+
+        assert!(state.apply(Event::LaggedEvents(3)));
+        assert_eq!(state.dropped_events, 3);
+    }
+
+    #[test]
+    fn test_levels_are_best_first() {
+        let mut state = WatchState::new(Asset::Bitcoin, Asset::Rune(1));
+        for price in [dec!(97), dec!(99), dec!(98)] {
+            state.upsert(order(OrderSide::Buy, dec!(1), price));
+            state.upsert(order(OrderSide::Sell, dec!(1), price + dec!(5)));
+        }
+
+        let bids: Vec<_> = state.levels(OrderSide::Buy, 2).into_iter().map(|(price, _, _)| price).collect();
+        let asks: Vec<_> = state.levels(OrderSide::Sell, 2).into_iter().map(|(price, _, _)| price).collect();
+        assert_eq!(bids, vec![dec!(99), dec!(98)]);
+        assert_eq!(asks, vec![dec!(102), dec!(103)]);
+    }
+}
@@ -6,14 +6,29 @@ use axum::{
     extract::ws::WebSocketUpgrade,
     extract::{Path, Query, State},
     http::StatusCode,
+    middleware,
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use darkswap_sdk::{
     config::Config,
-    types::{Asset, RuneId, AlkaneId, Event},
-    orderbook::{Order, OrderId, OrderSide, OrderStatus},
+    types::{Asset, RuneId, AlkaneId, Event, TradeId},
+    orderbook::{
+        cosign::CosignError,
+        group::GroupId,
+        dark::DarkOrder,
+        iceberg::IcebergId,
+        matching::MatchRefused,
+        migration::SignedOrderBundle,
+        routing::RoutingError,
+        scheduler::{OrderTemplate, PriceTrigger, ReferencePrice, ScheduleId, TriggerDirection},
+        Order, OrderId, OrderSide, OrderStatus, OrderbookError,
+    },
+    rules::SignedMarketRules,
+    spv::MerkleProof,
+    trade::{fees::validate_referral_code, memo::TradeHistoryFilter, query::{TradeQuery, TradeSort, MAX_PAGE_SIZE}, receipt::TradeReceipt, TradeError, TradeState},
+    wallet::{consolidation::ConsolidationError, WalletError},
     DarkSwap,
 };
 use rust_decimal::Decimal;
@@ -21,6 +36,11 @@ use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
 use tower_http::cors::{Any, CorsLayer};
+
+use crate::assets::{self, AssetMetaError, AssetMetaService};
+use crate::browser::{self, BrowserAuth};
+use crate::rate_limit::{self, RateLimiter};
+use crate::settlements::{SettlementFilter, SettlementLedger};
 use tower_http::trace::TraceLayer;
 
 /// API state
@@ -29,6 +49,16 @@ pub struct ApiState {
     pub darkswap: Arc<Mutex<DarkSwap>>,
     /// Event sender
     pub event_sender: mpsc::Sender<Event>,
+    /// API rate limiter; requests are not limited if unset
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Browser mode sessions; the API is open to any origin if unset
+    pub browser: Option<Arc<BrowserAuth>>,
+    /// Signed market rules published by the operator, enforced on the orders created here
+    pub market_rules: Option<Arc<SignedMarketRules>>,
+    /// Asset icons and descriptions for front-ends; the asset routes answer 404 if unset
+    pub asset_meta: Option<Arc<AssetMetaService>>,
+    /// Settlement receipts pushed by connected nodes
+    pub settlements: Arc<SettlementLedger>,
 }
 
 /// API error
@@ -63,6 +93,10 @@ pub struct CreateOrderRequest {
     pub price: String,
     /// Expiry in seconds
     pub expiry: Option<u64>,
+    /// Address the proceeds are paid to instead of the wallet's default
+    pub payout_address: Option<String>,
+    /// Referral code of the front-end the order came from
+    pub referral_code: Option<String>,
 }
 
 /// Cancel order request
@@ -79,6 +113,146 @@ pub struct TakeOrderRequest {
     pub order_id: String,
     /// Amount
     pub amount: String,
+    /// Address the proceeds are paid to instead of the wallet's default
+    pub payout_address: Option<String>,
+}
+
+/// Market order request
+#[derive(Debug, Deserialize)]
+pub struct MarketOrderRequest {
+    /// Base asset
+    pub base_asset: String,
+    /// Quote asset
+    pub quote_asset: String,
+    /// Side
+    pub side: String,
+    /// Amount
+    pub amount: String,
+    /// Worst price to take
+    pub limit_price: Option<String>,
+    /// Skip makers with a higher estimated latency (milliseconds)
+    pub max_latency: Option<u64>,
+    /// Address the proceeds are paid to instead of the wallet's default
+    pub payout_address: Option<String>,
+}
+
+/// Prewarm order makers request
+#[derive(Debug, Deserialize)]
+pub struct PrewarmRequest {
+    /// IDs of the orders being viewed
+    pub order_ids: Vec<String>,
+}
+
+/// Reveal dark order request
+#[derive(Debug, Deserialize)]
+pub struct RevealRequest {
+    /// Peer ID of the taker who asked for the terms
+    pub taker: String,
+}
+
+/// Join group request
+#[derive(Debug, Deserialize)]
+pub struct JoinGroupRequest {
+    /// Shared group key (hex)
+    pub key: String,
+}
+
+/// Create iceberg order request
+#[derive(Debug, Deserialize)]
+pub struct CreateIcebergOrderRequest {
+    /// Base asset
+    pub base_asset: String,
+    /// Quote asset
+    pub quote_asset: String,
+    /// Order side
+    pub side: String,
+    /// Total amount, never shown to peers
+    pub amount: String,
+    /// Amount of each visible slice
+    pub slice_size: String,
+    /// Price
+    pub price: String,
+    /// Expiry in seconds
+    pub expiry: Option<u64>,
+}
+
+/// Request quotes request
+#[derive(Debug, Deserialize)]
+pub struct RequestQuotesRequest {
+    /// Base asset
+    pub base_asset: String,
+    /// Quote asset
+    pub quote_asset: String,
+    /// Side of the taker
+    pub side: String,
+    /// Amount
+    pub amount: String,
+    /// Seconds to collect quotes for
+    #[serde(default = "default_quote_timeout")]
+    pub timeout: u64,
+}
+
+/// Default time to collect quotes for
+fn default_quote_timeout() -> u64 {
+    10
+}
+
+/// Send quote request
+#[derive(Debug, Deserialize)]
+pub struct SendQuoteRequest {
+    /// Amount, at most the requested amount
+    pub amount: String,
+    /// Price
+    pub price: String,
+    /// Seconds the quote holds for
+    #[serde(default = "default_quote_ttl")]
+    pub ttl: u64,
+}
+
+/// Default time a quote holds for
+fn default_quote_ttl() -> u64 {
+    60
+}
+
+/// Accept quote request
+#[derive(Debug, Deserialize)]
+pub struct AcceptQuoteRequest {
+    /// Address the proceeds are paid to instead of the wallet's default
+    pub payout_address: Option<String>,
+}
+
+/// Schedule order request
+#[derive(Debug, Deserialize)]
+pub struct ScheduleOrderRequest {
+    /// Base asset
+    pub base_asset: String,
+    /// Quote asset
+    pub quote_asset: String,
+    /// Order side
+    pub side: String,
+    /// Amount
+    pub amount: String,
+    /// Price
+    pub price: String,
+    /// Expiry of the posted order in seconds
+    pub expiry: Option<u64>,
+    /// Earliest time the order is posted (unix seconds)
+    pub activate_at: Option<u64>,
+    /// Price condition
+    pub trigger: Option<PriceTriggerRequest>,
+    /// End of the window in which the order can be posted (unix seconds)
+    pub expires_at: Option<u64>,
+}
+
+/// Price trigger of a schedule order request
+#[derive(Debug, Deserialize)]
+pub struct PriceTriggerRequest {
+    /// Reference price: "best_bid", "best_ask" or "mid"
+    pub reference: String,
+    /// Direction: "at_or_above" or "at_or_below"
+    pub direction: String,
+    /// Trigger price
+    pub price: String,
 }
 
 /// List orders query
@@ -106,6 +280,174 @@ fn default_status() -> String {
     "open".to_string()
 }
 
+/// New address request
+#[derive(Debug, Deserialize)]
+pub struct NewAddressRequest {
+    /// Label
+    pub label: Option<String>,
+}
+
+/// Watch maker request
+#[derive(Debug, Deserialize)]
+pub struct WatchMakerRequest {
+    /// Maker peer ID
+    pub peer_id: String,
+}
+
+/// Pair of assets
+#[derive(Debug, Deserialize)]
+pub struct PairRequest {
+    /// Base asset
+    pub base_asset: String,
+    /// Quote asset
+    pub quote_asset: String,
+}
+
+/// Order interest request
+#[derive(Debug, Deserialize)]
+pub struct OrderInterestRequest {
+    /// Full peers asked to forward the orders
+    pub peers: Vec<String>,
+    /// Pairs whose orders are wanted
+    #[serde(default)]
+    pub pairs: Vec<PairRequest>,
+    /// Makers whose orders are wanted
+    #[serde(default)]
+    pub makers: Vec<String>,
+}
+
+/// Backfill request
+#[derive(Debug, Deserialize)]
+pub struct BackfillRequest {
+    /// Peers asked for their cached orders
+    pub peers: Vec<String>,
+    /// Base asset
+    pub base_asset: String,
+    /// Quote asset
+    pub quote_asset: String,
+    /// How far back to ask for orders (minutes)
+    #[serde(default = "default_backfill_minutes")]
+    pub minutes: u64,
+}
+
+fn default_backfill_minutes() -> u64 {
+    10
+}
+
+/// Confirm trade request
+#[derive(Debug, Deserialize)]
+pub struct ConfirmTradeRequest {
+    /// Proof that the trade transaction is included in a tracked block
+    pub inclusion_proof: MerkleProof,
+}
+
+/// Watch pair request
+#[derive(Debug, Deserialize)]
+pub struct WatchPairRequest {
+    /// Base asset
+    pub base_asset: String,
+    /// Quote asset
+    pub quote_asset: String,
+    /// Prices whose crossing is reported
+    #[serde(default)]
+    pub thresholds: Vec<String>,
+}
+
+/// Consolidate request
+#[derive(Debug, Deserialize)]
+pub struct ConsolidateRequest {
+    /// Ignore the fee rate ceiling
+    #[serde(default)]
+    pub force: bool,
+    /// Only plan the consolidation
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Payout proof request
+#[derive(Debug, Deserialize)]
+pub struct PayoutProofRequest {
+    /// Payout address
+    pub address: String,
+    /// BIP-322 signature of the payout proof message by the address (base64)
+    pub proof: String,
+}
+
+/// Trade history query
+#[derive(Debug, Deserialize)]
+pub struct TradeHistoryQuery {
+    /// Base asset
+    pub base_asset: Option<String>,
+    /// Quote asset
+    pub quote_asset: Option<String>,
+    /// Trade state
+    pub state: Option<String>,
+    /// Memo label
+    pub label: Option<String>,
+    /// Text in memos, trade ID or transaction ID
+    pub query: Option<String>,
+    /// Maker or taker peer ID
+    pub counterparty: Option<String>,
+    /// Referral code
+    pub referral_code: Option<String>,
+    /// Created at or after (unix seconds)
+    pub since: Option<u64>,
+    /// Created at or before (unix seconds)
+    pub until: Option<u64>,
+    /// Sort order (`newest` or `oldest`)
+    pub sort: Option<String>,
+    /// Page size
+    pub limit: Option<usize>,
+    /// Cursor returned with the previous page
+    pub cursor: Option<String>,
+}
+
+/// Referral report query
+#[derive(Debug, Deserialize)]
+pub struct ReferralReportQuery {
+    /// Completed at or after (unix seconds)
+    pub since: Option<u64>,
+    /// Completed at or before (unix seconds)
+    pub until: Option<u64>,
+}
+
+/// Federated orders query
+#[derive(Debug, Deserialize)]
+pub struct FederatedOrdersQuery {
+    /// Base asset
+    pub base_asset: Option<String>,
+    /// Quote asset
+    pub quote_asset: Option<String>,
+}
+
+/// Set memo request
+#[derive(Debug, Deserialize)]
+pub struct SetMemoRequest {
+    /// Labels
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Note
+    pub note: Option<String>,
+}
+
+/// Record trade message request
+#[derive(Debug, Deserialize)]
+pub struct RecordTradeMessageRequest {
+    /// Peer ID of the sender
+    pub sender: String,
+    /// Text
+    pub text: String,
+}
+
+/// Register refund request
+#[derive(Debug, Deserialize)]
+pub struct RegisterRefundRequest {
+    /// Trade ID
+    pub trade_id: String,
+    /// Signed, timelocked refund transaction (hex)
+    pub refund_tx: String,
+}
+
 /// Market data query
 #[derive(Debug, Deserialize)]
 pub struct MarketDataQuery {
@@ -115,6 +457,65 @@ pub struct MarketDataQuery {
     pub quote_asset: String,
 }
 
+/// Audit export query
+#[derive(Debug, Deserialize)]
+pub struct AuditExportQuery {
+    /// Only export announcements received at or after this time (unix seconds)
+    pub since: Option<u64>,
+    /// Sequence number to start at; the next page starts after the last record exported
+    #[serde(default)]
+    pub from: u64,
+    /// Records exported at most, up to 10000
+    pub limit: Option<usize>,
+}
+
+/// Audit records exported per request at most
+const MAX_AUDIT_EXPORT: usize = 10_000;
+
+/// Settlement query
+#[derive(Debug, Deserialize)]
+pub struct SettlementQuery {
+    /// Received at or after (unix seconds)
+    pub from: Option<u64>,
+    /// Received before (unix seconds)
+    pub to: Option<u64>,
+    /// Base asset; needs `quote_asset`
+    pub base_asset: Option<String>,
+    /// Quote asset; needs `base_asset`
+    pub quote_asset: Option<String>,
+    /// Maker or taker peer ID
+    pub peer: Option<String>,
+    /// Maximum number of records (default 100)
+    pub limit: Option<usize>,
+}
+
+/// Route quote query
+#[derive(Debug, Deserialize)]
+pub struct RouteQuery {
+    /// Asset given
+    pub from: String,
+    /// Asset received
+    pub to: String,
+    /// Amount of `from` given
+    pub amount: String,
+}
+
+/// Route execution request
+#[derive(Debug, Deserialize)]
+pub struct ExecuteRouteRequest {
+    /// Asset given
+    pub from: String,
+    /// Asset received
+    pub to: String,
+    /// Amount of `from` given
+    pub amount: String,
+    /// Refuse to execute a route quoted with more slippage (basis points)
+    pub max_slippage_bps: Option<String>,
+    /// Convert the intermediate asset back if the second hop fails
+    #[serde(default)]
+    pub unwind: bool,
+}
+
 /// Parse asset from string
 fn parse_asset(asset_str: &str) -> Result<Asset, ApiError> {
     if asset_str == "BTC" {
@@ -150,6 +551,33 @@ fn parse_order_side(side_str: &str) -> Result<OrderSide, ApiError> {
     }
 }
 
+/// Parse a price trigger from a request
+fn parse_price_trigger(trigger: &PriceTriggerRequest) -> Result<PriceTrigger, ApiError> {
+    let reference = match trigger.reference.to_lowercase().as_str() {
+        "best_bid" => ReferencePrice::BestBid,
+        "best_ask" => ReferencePrice::BestAsk,
+        "mid" => ReferencePrice::Mid,
+        _ => return Err(ApiError {
+            message: format!("Invalid reference price: {}", trigger.reference),
+            code: 400,
+        }),
+    };
+    let direction = match trigger.direction.to_lowercase().as_str() {
+        "at_or_above" => TriggerDirection::AtOrAbove,
+        "at_or_below" => TriggerDirection::AtOrBelow,
+        _ => return Err(ApiError {
+            message: format!("Invalid trigger direction: {}", trigger.direction),
+            code: 400,
+        }),
+    };
+    let price = trigger.price.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid trigger price".to_string(),
+        code: 400,
+    })?;
+
+    Ok(PriceTrigger { reference, direction, price })
+}
+
 /// Parse order status from string
 fn parse_order_status(status_str: &str) -> Result<OrderStatus, ApiError> {
     match status_str.to_lowercase().as_str() {
@@ -164,28 +592,125 @@ fn parse_order_status(status_str: &str) -> Result<OrderStatus, ApiError> {
     }
 }
 
+/// Parse trade sort order from string
+fn parse_trade_sort(sort_str: &str) -> Result<TradeSort, ApiError> {
+    match sort_str.to_lowercase().as_str() {
+        "newest" | "newest_first" => Ok(TradeSort::NewestFirst),
+        "oldest" | "oldest_first" => Ok(TradeSort::OldestFirst),
+        _ => Err(ApiError {
+            message: format!("Invalid sort order: {}", sort_str),
+            code: 400,
+        }),
+    }
+}
+
+/// Parse trade state from string
+fn parse_trade_state(state_str: &str) -> Result<TradeState, ApiError> {
+    match state_str.to_lowercase().as_str() {
+        "created" => Ok(TradeState::Created),
+        "maker_psbt_sent" => Ok(TradeState::MakerPsbtSent),
+        "taker_psbt_sent" => Ok(TradeState::TakerPsbtSent),
+        "maker_signed" => Ok(TradeState::MakerSigned),
+        "taker_signed" => Ok(TradeState::TakerSigned),
+        "completed" => Ok(TradeState::Completed),
+        "failed" => Ok(TradeState::Failed),
+        "canceled" => Ok(TradeState::Canceled),
+        "expired" => Ok(TradeState::Expired),
+        _ => Err(ApiError {
+            message: format!("Invalid trade state: {}", state_str),
+            code: 400,
+        }),
+    }
+}
+
 /// Create API router
 pub fn create_router(state: Arc<ApiState>) -> Router {
     use crate::handlers::ws_handler;
     
-    // Create CORS layer
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    // Create CORS layer; browser mode only allows its own origins
+    let cors = match &state.browser {
+        Some(browser) => browser.cors_layer(),
+        None => CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any),
+    };
 
     // Create router
     Router::new()
         .route("/health", get(health_handler))
+        .route("/limits", get(rate_limit::limits_handler))
+        .route("/dashboard", get(dashboard_handler))
+        .route("/relays", get(list_relays_handler))
         .route("/orders", get(list_orders_handler).post(create_order_handler))
         .route("/orders/:id", get(get_order_handler).delete(cancel_order_handler))
         .route("/orders/:id/take", post(take_order_handler))
+        .route("/orders/prewarm", post(prewarm_orders_handler))
+        .route("/orders/interest", put(set_order_interest_handler))
+        .route("/orders/backfill", post(backfill_orders_handler))
+        .route("/orders/market", post(market_order_handler))
+        .route("/orders/stats", get(orderbook_stats_handler))
+        .route("/orders/compact", post(compact_orders_handler))
+        .route("/orders/export", get(export_orders_handler))
+        .route("/orders/import", post(import_orders_handler))
+        .route("/audit/export", get(export_audit_handler))
+        .route("/rfq/quotes", post(request_quotes_handler))
+        .route("/rfq/quotes/:id/accept", post(accept_quote_handler))
+        .route("/rfq/requests", get(list_quote_requests_handler))
+        .route("/rfq/requests/:id/quote", post(send_quote_handler))
+        .route("/dark/orders", get(list_dark_orders_handler).post(create_dark_order_handler))
+        .route("/dark/orders/:id", delete(cancel_dark_order_handler))
+        .route("/dark/orders/:id/interest", post(request_reveal_handler))
+        .route("/dark/orders/:id/reveal", post(reveal_dark_order_handler))
+        .route("/dark/orders/:id/decline", post(decline_reveal_handler))
+        .route("/dark/orders/:id/take", post(take_dark_order_handler))
+        .route("/dark/reveals", get(list_reveal_requests_handler))
+        .route("/dark/revealed", get(list_revealed_orders_handler))
+        .route("/groups", get(list_groups_handler).post(join_group_handler))
+        .route("/groups/:id", delete(leave_group_handler))
+        .route("/groups/:id/orders", get(list_group_orders_handler).post(create_group_order_handler))
+        .route("/groups/:id/orders/:order_id", delete(cancel_group_order_handler))
+        .route("/groups/:id/orders/:order_id/take", post(take_group_order_handler))
+        .route("/icebergs", get(list_icebergs_handler).post(create_iceberg_handler))
+        .route("/icebergs/:id", delete(cancel_iceberg_handler))
+        .route("/schedules", get(list_schedules_handler).post(schedule_order_handler))
+        .route("/schedules/:id", delete(cancel_schedule_handler))
         .route("/market", get(get_market_data_handler))
+        .route("/market/ticker", get(get_ticker_handler))
+        .route("/market/rules", get(get_market_rules_handler))
+        .route("/markets", get(list_markets_handler))
+        .route("/routes/quote", get(quote_route_handler))
+        .route("/routes", post(execute_route_handler))
+        .route("/watchlist", get(get_watchlist_handler))
+        .route("/watchlist/makers", post(watch_maker_handler))
+        .route("/watchlist/makers/:peer_id", delete(unwatch_maker_handler))
+        .route("/watchlist/pairs", put(watch_pair_handler).delete(unwatch_pair_handler))
+        .route("/federation/orders", get(federated_orders_handler))
+        .route("/federation/status", get(federation_status_handler))
+        .route("/trades", get(trade_history_handler))
+        .route("/trades/:id/memo", put(set_trade_memo_handler))
+        .route("/trades/:id/transcript", get(get_trade_transcript_handler).post(record_trade_message_handler))
+        .route("/trades/:id/timeline", get(get_trade_timeline_handler))
+        .route("/trades/:id/confirm", post(confirm_trade_handler))
+        .route("/settlements", get(list_settlements_handler).post(record_settlement_handler))
+        .route("/referrals", get(referral_report_handler))
+        .route("/refunds", get(list_refunds_handler).post(register_refund_handler))
+        .route("/transactions/:txid/memo", put(set_transaction_memo_handler))
         .route("/runes", get(list_runes_handler))
         .route("/runes/:id", get(get_rune_handler))
         .route("/alkanes", get(list_alkanes_handler))
         .route("/alkanes/:id", get(get_alkane_handler))
+        .route("/assets/:id", get(get_asset_info_handler))
+        .route("/assets/:id/meta", get(get_asset_meta_handler))
+        .route("/assets/:id/icon", get(get_asset_icon_handler))
+        .route("/addresses", get(list_addresses_handler).post(new_address_handler))
+        .route("/deposits", get(list_deposits_handler))
+        .route("/wallet/consolidate", post(consolidate_handler))
+        .route("/wallet/payout-proofs", post(add_payout_proof_handler))
         .route("/ws", get(ws_handler)) // WebSocket endpoint
+        .merge(browser::routes())
+        .layer(middleware::from_fn_with_state(state.clone(), browser::require_session))
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit::enforce))
         .layer(TraceLayer::new_for_http())
         .layer(cors)
         .with_state(state)
@@ -199,6 +724,32 @@ async fn health_handler() -> impl IntoResponse {
     }))
 }
 
+/// Dashboard handler
+async fn dashboard_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    let dashboard = darkswap.get_dashboard().await.map_err(|e| ApiError {
+        message: format!("Failed to get dashboard: {}", e),
+        code: 500,
+    })?;
+
+    Ok(Json(dashboard))
+}
+
+/// List relays handler
+async fn list_relays_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    let relays = darkswap.list_relays().await.map_err(|e| ApiError {
+        message: format!("Failed to list relays: {}", e),
+        code: 500,
+    })?;
+
+    Ok(Json(relays))
+}
+
 /// Create order handler
 async fn create_order_handler(
     State(state): State<Arc<ApiState>>,
@@ -217,15 +768,58 @@ async fn create_order_handler(
         code: 400,
     })?;
 
+    if let Some(code) = &request.referral_code {
+        validate_referral_code(code).map_err(|e| ApiError {
+            message: e.to_string(),
+            code: 400,
+        })?;
+    }
+
+    // Hold own orders to the published rules
+    if let Some(market_rules) = &state.market_rules {
+        market_rules.rules.check_order(&base_asset, &quote_asset, amount).map_err(|e| ApiError {
+            message: e.to_string(),
+            code: 400,
+        })?;
+    }
+
     // Create order
     let order = {
         let mut darkswap = state.darkswap.lock().await;
-        darkswap.create_order(base_asset, quote_asset, side, amount, price, request.expiry)
+
+        // A payout address that cannot be set must not leave a published order behind
+        if let Some(address) = &request.payout_address {
+            darkswap.validate_payout_address(address).map_err(|e| ApiError {
+                message: format!("Invalid payout address: {}", e),
+                code: 400,
+            })?;
+        }
+
+        let order = darkswap
+            .create_referred_order(base_asset, quote_asset, side, amount, price, request.expiry, request.referral_code)
             .await
             .map_err(|e| ApiError {
+                code: match (e.downcast_ref::<WalletError>(), e.downcast_ref::<CosignError>()) {
+                    (Some(WalletError::FeeReserve { .. }), _) => 409,
+                    (_, Some(CosignError::Rejected(_))) => 403,
+                    (_, Some(_)) => 502,
+                    _ => 500,
+                },
                 message: format!("Failed to create order: {}", e),
-                code: 500,
-            })?
+            })?;
+
+        if request.payout_address.is_some() {
+            if let Err(e) = darkswap.set_order_payout_address(&order.id, request.payout_address).await {
+                // Without its payout address the order must not be taken
+                let _ = darkswap.cancel_order(&order.id).await;
+                return Err(ApiError {
+                    message: format!("Failed to set payout address: {}", e),
+                    code: 500,
+                });
+            }
+        }
+
+        order
     };
 
     // Return order
@@ -258,114 +852,1307 @@ async fn cancel_order_handler(
     })))
 }
 
-/// Take order handler
-async fn take_order_handler(
+/// Request quotes handler
+async fn request_quotes_handler(
     State(state): State<Arc<ApiState>>,
-    Path(order_id_str): Path<String>,
-    Json(request): Json<TakeOrderRequest>,
+    Json(request): Json<RequestQuotesRequest>,
 ) -> Result<impl IntoResponse, ApiError> {
     // Parse parameters
-    let order_id = OrderId(order_id_str);
+    let base_asset = parse_asset(&request.base_asset)?;
+    let quote_asset = parse_asset(&request.quote_asset)?;
+    let side = parse_order_side(&request.side)?;
     let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
         message: "Invalid amount".to_string(),
         code: 400,
     })?;
 
-    // Take order
+    // Collect quotes without blocking other requests
+    let rfq = state.darkswap.lock().await.get_rfq_manager().ok_or_else(|| ApiError {
+        message: "RFQ manager not initialized".to_string(),
+        code: 503,
+    })?;
+    let quotes = rfq.request_quotes(base_asset, quote_asset, side, amount, std::time::Duration::from_secs(request.timeout))
+        .await
+        .map_err(|e| ApiError {
+            message: format!("Failed to request quotes: {}", e),
+            code: 400,
+        })?;
+
+    Ok(Json(quotes))
+}
+
+/// Accept quote handler
+async fn accept_quote_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(quote_id): Path<String>,
+    Json(request): Json<AcceptQuoteRequest>,
+) -> Result<impl IntoResponse, ApiError> {
     let trade = {
-        let mut darkswap = state.darkswap.lock().await;
-        darkswap.take_order(&order_id, amount)
+        let darkswap = state.darkswap.lock().await;
+        darkswap.accept_quote(&quote_id, request.payout_address)
             .await
             .map_err(|e| ApiError {
-                message: format!("Failed to take order: {}", e),
-                code: 500,
+                message: format!("Failed to accept quote: {}", e),
+                code: if e.is::<MatchRefused>() { 403 } else { 400 },
             })?
     };
 
-    // Return trade
     Ok(Json(trade))
 }
 
-/// Get order handler
-async fn get_order_handler(
+/// List quote requests handler
+async fn list_quote_requests_handler(
     State(state): State<Arc<ApiState>>,
-    Path(order_id_str): Path<String>,
 ) -> Result<impl IntoResponse, ApiError> {
-    // Parse parameters
-    let order_id = OrderId(order_id_str);
-
-    // Get order
-    let order = {
+    let requests = {
         let darkswap = state.darkswap.lock().await;
-        darkswap.get_order(&order_id)
+        darkswap.get_quote_requests()
             .await
             .map_err(|e| ApiError {
-                message: format!("Failed to get order: {}", e),
+                message: format!("Failed to get quote requests: {}", e),
                 code: 500,
             })?
     };
 
-    // Return order
-    Ok(Json(order))
+    Ok(Json(requests))
 }
 
-/// List orders handler
-async fn list_orders_handler(
+/// Send quote handler
+async fn send_quote_handler(
     State(state): State<Arc<ApiState>>,
-    Query(query): Query<ListOrdersQuery>,
+    Path(request_id): Path<String>,
+    Json(request): Json<SendQuoteRequest>,
 ) -> Result<impl IntoResponse, ApiError> {
-    // Get orders
-    let orders = {
-        let mut darkswap = state.darkswap.lock().await;
-        
-        if let (Some(base_asset_str), Some(quote_asset_str)) = (&query.base_asset, &query.quote_asset) {
-            let base_asset = parse_asset(base_asset_str)?;
-            let quote_asset = parse_asset(quote_asset_str)?;
-            darkswap.get_orders(&base_asset, &quote_asset)
-                .await
-                .map_err(|e| ApiError {
-                    message: format!("Failed to get orders: {}", e),
-                    code: 500,
-                })?
+    // Parse parameters
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+    let price = request.price.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid price".to_string(),
+        code: 400,
+    })?;
+
+    let quote = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.send_quote(&request_id, amount, price, request.ttl)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to send quote: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(quote))
+}
+
+/// List dark orders handler
+async fn list_dark_orders_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let orders: Vec<DarkOrder> = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_dark_orders()
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get dark orders: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(orders))
+}
+
+/// Create dark order handler
+async fn create_dark_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<CreateOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let base_asset = parse_asset(&request.base_asset)?;
+    let quote_asset = parse_asset(&request.quote_asset)?;
+    let side = parse_order_side(&request.side)?;
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+    let price = request.price.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid price".to_string(),
+        code: 400,
+    })?;
+
+    // Create order
+    let order = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.create_dark_order(base_asset, quote_asset, side, amount, price, request.expiry, request.payout_address)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to create dark order: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(order))
+}
+
+/// Cancel dark order handler
+async fn cancel_dark_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(order_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.cancel_dark_order(&OrderId(order_id.clone()))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to cancel dark order: {}", e),
+                code: 400,
+            })?;
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "order_id": order_id,
+    })))
+}
+
+/// Request reveal handler
+async fn request_reveal_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(order_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.request_reveal(&OrderId(order_id.clone()))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to request reveal: {}", e),
+                code: 400,
+            })?;
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "order_id": order_id,
+    })))
+}
+
+/// Reveal dark order handler
+async fn reveal_dark_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(order_id): Path<String>,
+    Json(request): Json<RevealRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let order = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.reveal_dark_order(&OrderId(order_id), &request.taker)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to reveal dark order: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(order))
+}
+
+/// Decline reveal handler
+async fn decline_reveal_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(order_id): Path<String>,
+    Json(request): Json<RevealRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.decline_reveal(&OrderId(order_id.clone()), &request.taker)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to decline reveal: {}", e),
+                code: 400,
+            })?;
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "order_id": order_id,
+    })))
+}
+
+/// Take dark order handler
+async fn take_dark_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(order_id): Path<String>,
+    Json(request): Json<TakeOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+
+    // Take order
+    let trade = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.take_dark_order(&OrderId(order_id), amount, request.payout_address)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to take dark order: {}", e),
+                code: if e.is::<MatchRefused>() { 403 } else { 500 },
+            })?
+    };
+
+    Ok(Json(trade))
+}
+
+/// List reveal requests handler
+async fn list_reveal_requests_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let requests = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_reveal_requests()
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get reveal requests: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(requests))
+}
+
+/// List revealed orders handler
+async fn list_revealed_orders_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let orders = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_revealed_orders()
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get revealed orders: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(orders))
+}
+
+/// List groups handler
+async fn list_groups_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let groups = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_groups()
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get groups: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Never return the group keys
+    let groups: Vec<serde_json::Value> = groups.iter()
+        .map(|group| serde_json::json!({
+            "id": group.id,
+            "name": group.name,
+            "encrypted": group.is_encrypted(),
+            "allowlist": group.allowlist(),
+            "takers": group.takers(),
+        }))
+        .collect();
+
+    Ok(Json(groups))
+}
+
+/// Join group handler
+async fn join_group_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<JoinGroupRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let group_id = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.join_group(&request.key)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to join group: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "group_id": group_id,
+    })))
+}
+
+/// Leave group handler
+async fn leave_group_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(group_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.leave_group(&GroupId(group_id.clone()))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to leave group: {}", e),
+                code: 400,
+            })?;
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "group_id": group_id,
+    })))
+}
+
+/// List group orders handler
+async fn list_group_orders_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(group_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let orders = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_group_orders(&GroupId(group_id))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get group orders: {}", e),
+                code: 404,
+            })?
+    };
+
+    Ok(Json(orders))
+}
+
+/// Create group order handler
+async fn create_group_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(group_id): Path<String>,
+    Json(request): Json<CreateOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let base_asset = parse_asset(&request.base_asset)?;
+    let quote_asset = parse_asset(&request.quote_asset)?;
+    let side = parse_order_side(&request.side)?;
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+    let price = request.price.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid price".to_string(),
+        code: 400,
+    })?;
+
+    // Create order
+    let order = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.create_group_order(&GroupId(group_id), base_asset, quote_asset, side, amount, price, request.expiry, request.payout_address)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to create group order: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(order))
+}
+
+/// Cancel group order handler
+async fn cancel_group_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path((group_id, order_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, ApiError> {
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.cancel_group_order(&GroupId(group_id), &OrderId(order_id.clone()))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to cancel group order: {}", e),
+                code: 400,
+            })?;
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "order_id": order_id,
+    })))
+}
+
+/// Take group order handler
+async fn take_group_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path((group_id, order_id)): Path<(String, String)>,
+    Json(request): Json<TakeOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+
+    // Take order
+    let trade = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.take_group_order(&GroupId(group_id), &OrderId(order_id), amount, request.payout_address)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to take group order: {}", e),
+                code: if e.is::<MatchRefused>() { 403 } else { 500 },
+            })?
+    };
+
+    Ok(Json(trade))
+}
+
+/// List iceberg orders handler
+async fn list_icebergs_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let icebergs = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_iceberg_orders().await
+    };
+
+    Ok(Json(icebergs))
+}
+
+/// Create iceberg order handler
+async fn create_iceberg_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<CreateIcebergOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let base_asset = parse_asset(&request.base_asset)?;
+    let quote_asset = parse_asset(&request.quote_asset)?;
+    let side = parse_order_side(&request.side)?;
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+    let slice_size = request.slice_size.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid slice size".to_string(),
+        code: 400,
+    })?;
+    let price = request.price.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid price".to_string(),
+        code: 400,
+    })?;
+
+    // Create iceberg order
+    let iceberg = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.create_iceberg_order(base_asset, quote_asset, side, amount, slice_size, price, request.expiry)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to create iceberg order: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(iceberg))
+}
+
+/// Cancel iceberg order handler
+async fn cancel_iceberg_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(iceberg_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let iceberg = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.cancel_iceberg_order(&IcebergId(iceberg_id))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to cancel iceberg order: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(iceberg))
+}
+
+/// Get watchlist handler
+async fn get_watchlist_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (makers, pairs) = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_watchlist().await
+    };
+
+    Ok(Json(serde_json::json!({
+        "makers": makers,
+        "pairs": pairs,
+    })))
+}
+
+/// Watch maker handler
+async fn watch_maker_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<WatchMakerRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.watch_maker(&request.peer_id)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to follow maker: {}", e),
+                code: 500,
+            })?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Unwatch maker handler
+async fn unwatch_maker_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(peer_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.unwatch_maker(&peer_id)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to unfollow maker: {}", e),
+                code: 404,
+            })?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Watch pair handler
+async fn watch_pair_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<WatchPairRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let base_asset = parse_asset(&request.base_asset)?;
+    let quote_asset = parse_asset(&request.quote_asset)?;
+    let thresholds = request.thresholds
+        .iter()
+        .map(|threshold| threshold.parse::<Decimal>().map_err(|_| ApiError {
+            message: format!("Invalid threshold: {}", threshold),
+            code: 400,
+        }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.watch_pair(base_asset, quote_asset, thresholds)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to follow pair: {}", e),
+                code: 500,
+            })?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Unwatch pair handler
+async fn unwatch_pair_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<MarketDataQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let base_asset = parse_asset(&query.base_asset)?;
+    let quote_asset = parse_asset(&query.quote_asset)?;
+
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.unwatch_pair(&base_asset, &quote_asset)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to unfollow pair: {}", e),
+                code: 404,
+            })?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// List scheduled orders handler
+async fn list_schedules_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let schedules = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_scheduled_orders().await
+    };
+
+    Ok(Json(schedules))
+}
+
+/// Schedule order handler
+async fn schedule_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<ScheduleOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let order = OrderTemplate {
+        base_asset: parse_asset(&request.base_asset)?,
+        quote_asset: parse_asset(&request.quote_asset)?,
+        side: parse_order_side(&request.side)?,
+        amount: request.amount.parse::<Decimal>().map_err(|_| ApiError {
+            message: "Invalid amount".to_string(),
+            code: 400,
+        })?,
+        price: request.price.parse::<Decimal>().map_err(|_| ApiError {
+            message: "Invalid price".to_string(),
+            code: 400,
+        })?,
+        expiry: request.expiry,
+    };
+    let trigger = request.trigger.as_ref().map(parse_price_trigger).transpose()?;
+
+    // Schedule order
+    let scheduled = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.schedule_order(order, request.activate_at, trigger, request.expires_at)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to schedule order: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(scheduled))
+}
+
+/// Cancel scheduled order handler
+async fn cancel_schedule_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(schedule_id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let scheduled = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.cancel_scheduled_order(&ScheduleId(schedule_id))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to cancel scheduled order: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(scheduled))
+}
+
+/// Take order handler
+async fn take_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(order_id_str): Path<String>,
+    Json(request): Json<TakeOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let order_id = OrderId(order_id_str);
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+
+    // Take order
+    let trade = {
+        let mut darkswap = state.darkswap.lock().await;
+        darkswap.take_order_with_payout(&order_id, amount, request.payout_address)
+            .await
+            .map_err(|e| ApiError {
+                code: match e.downcast_ref::<TradeError>() {
+                    Some(TradeError::LimitReached { .. }) => 429,
+                    _ if e.is::<MatchRefused>() => 403,
+                    _ => 500,
+                },
+                message: format!("Failed to take order: {}", e),
+            })?
+    };
+
+    // Return trade
+    Ok(Json(trade))
+}
+
+/// Market order handler
+async fn market_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<MarketOrderRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let base_asset = parse_asset(&request.base_asset)?;
+    let quote_asset = parse_asset(&request.quote_asset)?;
+    let side = parse_order_side(&request.side)?;
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+    let limit_price = request.limit_price
+        .map(|price| price.parse::<Decimal>())
+        .transpose()
+        .map_err(|_| ApiError {
+            message: "Invalid limit price".to_string(),
+            code: 400,
+        })?;
+
+    // Plan the fills and take them, one trade per maker
+    let darkswap = state.darkswap.lock().await;
+    let plan = darkswap
+        .plan_market_order(&base_asset, &quote_asset, side, amount, limit_price, request.max_latency)
+        .await
+        .map_err(|e| ApiError {
+            message: format!("Failed to match order: {}", e),
+            code: if e.is::<MatchRefused>() { 403 } else { 500 },
+        })?;
+    let trades = darkswap.take_fills(&plan.fills, request.payout_address)
+        .await
+        .map_err(|e| ApiError {
+            message: format!("Failed to take orders: {}", e),
+            code: 500,
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "trades": trades,
+        "remaining": plan.remaining.to_string(),
+    })))
+}
+
+/// Prewarm order makers handler
+async fn prewarm_orders_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<PrewarmRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let order_ids: Vec<OrderId> = request.order_ids.into_iter().map(OrderId).collect();
+
+    // Dial the makers
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.prewarm_order_makers(&order_ids)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to prewarm order makers: {}", e),
+                code: 500,
+            })?;
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set order interest handler
+async fn set_order_interest_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<OrderInterestRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    if request.peers.is_empty() {
+        return Err(ApiError {
+            message: "At least one peer is required".to_string(),
+            code: 400,
+        });
+    }
+    let peers = request.peers
+        .iter()
+        .map(|peer| peer.parse::<libp2p::PeerId>().map_err(|_| ApiError {
+            message: format!("Invalid peer ID: {}", peer),
+            code: 400,
+        }))
+        .collect::<Result<Vec<_>, _>>()?;
+    let pairs = request.pairs
+        .iter()
+        .map(|pair| Ok((parse_asset(&pair.base_asset)?, parse_asset(&pair.quote_asset)?)))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+
+    // Send the filter
+    let reached = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.set_order_interest(&peers, &pairs, &request.makers)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to set order interest: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(serde_json::json!({ "peers": reached })))
+}
+
+/// Backfill orders handler
+async fn backfill_orders_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<BackfillRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    if request.peers.is_empty() {
+        return Err(ApiError {
+            message: "At least one peer is required".to_string(),
+            code: 400,
+        });
+    }
+    let peers = request.peers
+        .iter()
+        .map(|peer| peer.parse::<libp2p::PeerId>().map_err(|_| ApiError {
+            message: format!("Invalid peer ID: {}", peer),
+            code: 400,
+        }))
+        .collect::<Result<Vec<_>, _>>()?;
+    let base_asset = parse_asset(&request.base_asset)?;
+    let quote_asset = parse_asset(&request.quote_asset)?;
+
+    // Send the requests
+    let reached = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.backfill_orders(&peers, &base_asset, &quote_asset, request.minutes)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to request backfill: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(serde_json::json!({ "peers": reached })))
+}
+
+/// Get order handler
+async fn get_order_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(order_id_str): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let order_id = OrderId(order_id_str);
+
+    // Get order
+    let order = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_order(&order_id)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get order: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Return order
+    Ok(Json(order))
+}
+
+/// Orderbook size handler
+async fn orderbook_stats_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    let stats = darkswap.get_orderbook_stats().await.map_err(|e| ApiError {
+        message: format!("Failed to get orderbook stats: {}", e),
+        code: 500,
+    })?;
+
+    Ok(Json(stats))
+}
+
+/// Compact orders handler
+async fn compact_orders_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    let report = darkswap.compact_orderbook().await.map_err(|e| ApiError {
+        message: format!("Failed to compact orderbook: {}", e),
+        code: 500,
+    })?;
+
+    Ok(Json(report))
+}
+
+/// Export own orders handler
+async fn export_orders_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    let bundle = darkswap.export_orders().await.map_err(|e| ApiError {
+        message: format!("Failed to export orders: {}", e),
+        code: 500,
+    })?;
+
+    Ok(Json(bundle))
+}
+
+/// Import orders exported by another node handler
+async fn import_orders_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(bundle): Json<SignedOrderBundle>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    let import = darkswap.import_orders(&bundle).await.map_err(|e| ApiError {
+        message: format!("Failed to import orders: {}", e),
+        code: match e.downcast_ref::<OrderbookError>() {
+            Some(OrderbookError::InvalidOrder(_)) => 400,
+            Some(OrderbookError::ReadOnly) => 403,
+            _ => 500,
+        },
+    })?;
+
+    Ok(Json(import))
+}
+
+/// Export audit records handler
+async fn export_audit_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<AuditExportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    if !darkswap.is_audit_node() {
+        return Err(ApiError {
+            message: "Audit mode not enabled".to_string(),
+            code: 404,
+        });
+    }
+
+    let mut body = Vec::new();
+    let limit = query.limit.unwrap_or(MAX_AUDIT_EXPORT).min(MAX_AUDIT_EXPORT);
+    darkswap.export_audit(&mut body, query.since, query.from, limit).map_err(|e| ApiError {
+        message: format!("Failed to export audit records: {}", e),
+        code: 500,
+    })?;
+
+    Ok(([(axum::http::header::CONTENT_TYPE, "application/x-ndjson")], body))
+}
+
+/// List orders handler
+async fn list_orders_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<ListOrdersQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Get orders with the estimated latency to their makers
+    let orders = {
+        let darkswap = state.darkswap.lock().await;
+        
+        let pair = match (&query.base_asset, &query.quote_asset) {
+            (Some(base_asset_str), Some(quote_asset_str)) => Some((parse_asset(base_asset_str)?, parse_asset(quote_asset_str)?)),
+            _ => None,
+        };
+        darkswap.get_order_listings(pair.as_ref().map(|(base_asset, quote_asset)| (base_asset, quote_asset)))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get orders: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Filter orders by side and status
+    let filtered_orders = if query.side == "all" && query.status == "all" {
+        orders
+    } else {
+        let side = if query.side == "all" {
+            None
         } else {
-            // Get all orders
-            darkswap.get_all_orders()
-                .await
-                .map_err(|e| ApiError {
-                    message: format!("Failed to get all orders: {}", e),
-                    code: 500,
-                })?
+            Some(parse_order_side(&query.side)?)
+        };
+
+        let status = if query.status == "all" {
+            None
+        } else {
+            Some(parse_order_status(&query.status)?)
+        };
+
+        orders
+            .into_iter()
+            .filter(|listing| {
+                let side_match = side.map_or(true, |s| listing.order.side == s);
+                let status_match = status.map_or(true, |s| listing.order.status == s);
+                side_match && status_match
+            })
+            .collect()
+    };
+
+    // Return orders
+    Ok(Json(filtered_orders))
+}
+
+/// Federated orders handler
+async fn federated_orders_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<FederatedOrdersQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let pair = match (&query.base_asset, &query.quote_asset) {
+        (Some(base_asset), Some(quote_asset)) => Some((parse_asset(base_asset)?, parse_asset(quote_asset)?)),
+        _ => None,
+    };
+
+    // Get orders
+    let orders = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_federated_orders(pair.as_ref().map(|(base, quote)| (base, quote)))
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get federated orders: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Return orders
+    Ok(Json(orders))
+}
+
+/// Federation status handler
+async fn federation_status_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    Ok(Json(darkswap.get_federation_status().await))
+}
+
+/// Trade history handler
+async fn trade_history_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<TradeHistoryQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters; the indexed filters go into the trade query and the
+    // memo filters are applied on top
+    let trade_query = TradeQuery {
+        state: query.state.as_deref().map(parse_trade_state).transpose()?,
+        base_asset: query.base_asset.as_deref().map(parse_asset).transpose()?,
+        quote_asset: query.quote_asset.as_deref().map(parse_asset).transpose()?,
+        counterparty: query.counterparty,
+        referral_code: query.referral_code,
+        since: query.since,
+        until: query.until,
+        sort: query.sort.as_deref().map(parse_trade_sort).transpose()?.unwrap_or_default(),
+        limit: query.limit,
+        cursor: query.cursor,
+    };
+    let filter = TradeHistoryFilter {
+        label: query.label,
+        query: query.query,
+        ..Default::default()
+    };
+    let paged = trade_query.limit.is_some() || trade_query.cursor.is_some();
+
+    // Get trades
+    let darkswap = state.darkswap.lock().await;
+    let history_error = |e: anyhow::Error| ApiError {
+        message: format!("Failed to get trade history: {}", e),
+        code: 500,
+    };
+
+    // A page with its cursor if one was asked for, otherwise every match as before paging existed
+    if paged {
+        let page = darkswap.query_trade_history(&trade_query, &filter).await.map_err(history_error)?;
+        return Ok(Json(page).into_response());
+    }
+
+    let mut history = Vec::new();
+    let mut trade_query = TradeQuery { limit: Some(MAX_PAGE_SIZE), ..trade_query };
+    loop {
+        let page = darkswap.query_trade_history(&trade_query, &filter).await.map_err(history_error)?;
+        history.extend(page.trades);
+        match page.next_cursor {
+            Some(cursor) => trade_query.cursor = Some(cursor),
+            None => break,
         }
+    }
+
+    // Return trades
+    Ok(Json(history).into_response())
+}
+
+/// Referral report handler
+async fn referral_report_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<ReferralReportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let report = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.referral_report(query.since, query.until)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get referral report: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(report))
+}
+
+/// Get trade transcript handler
+async fn get_trade_transcript_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(trade_id_str): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let trade_id = TradeId(trade_id_str);
+
+    // Get transcript
+    let transcript = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_trade_transcript(&trade_id)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get trade transcript: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Return transcript
+    Ok(Json(transcript))
+}
+
+/// Record trade message handler
+async fn record_trade_message_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(trade_id_str): Path<String>,
+    Json(request): Json<RecordTradeMessageRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let trade_id = TradeId(trade_id_str);
+
+    // Record message
+    let message = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.record_trade_message(&trade_id, &request.sender, &request.text)
+            .await
+            .map_err(|e| ApiError {
+                code: match e.downcast_ref::<TradeError>() {
+                    Some(TradeError::NotFound(_)) => 404,
+                    _ => 500,
+                },
+                message: format!("Failed to record trade message: {}", e),
+            })?
     };
 
-    // Filter orders by side and status
-    let filtered_orders = if query.side == "all" && query.status == "all" {
-        orders
-    } else {
-        let side = if query.side == "all" {
-            None
-        } else {
-            Some(parse_order_side(&query.side)?)
-        };
+    // Return message
+    Ok(Json(message))
+}
 
-        let status = if query.status == "all" {
-            None
-        } else {
-            Some(parse_order_status(&query.status)?)
-        };
+/// Get trade timeline handler
+async fn get_trade_timeline_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(trade_id_str): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let trade_id = TradeId(trade_id_str);
 
-        orders
-            .into_iter()
-            .filter(|order| {
-                let side_match = side.map_or(true, |s| order.side == s);
-                let status_match = status.map_or(true, |s| order.status == s);
-                side_match && status_match
-            })
-            .collect()
+    // Get timeline
+    let timeline = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_trade_timeline(&trade_id)
+            .await
+            .map_err(|e| ApiError {
+                code: match e.downcast_ref::<TradeError>() {
+                    Some(TradeError::NotFound(_)) => 404,
+                    _ => 500,
+                },
+                message: format!("Failed to get trade timeline: {}", e),
+            })?
     };
 
-    // Return orders
-    Ok(Json(filtered_orders))
+    // Return timeline
+    Ok(Json(timeline))
+}
+
+/// Confirm trade handler
+///
+/// The proof is checked against the block headers tracked for SPV verification.
+async fn confirm_trade_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(trade_id_str): Path<String>,
+    Json(request): Json<ConfirmTradeRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let trade_id = TradeId(trade_id_str);
+
+    // Confirm trade
+    {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.confirm_trade(&trade_id, &request.inclusion_proof)
+            .await
+            .map_err(|e| ApiError {
+                code: match e.downcast_ref::<TradeError>() {
+                    Some(TradeError::NotFound(_)) => 404,
+                    Some(TradeError::InvalidState(_)) => 409,
+                    _ => 400,
+                },
+                message: format!("Failed to confirm trade: {}", e),
+            })?
+    };
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Set trade memo handler
+async fn set_trade_memo_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(trade_id_str): Path<String>,
+    Json(request): Json<SetMemoRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let trade_id = TradeId(trade_id_str);
+
+    // Set memo
+    let memo = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.set_trade_memo(&trade_id, request.labels, request.note)
+            .await
+            .map_err(|e| ApiError {
+                code: match e.downcast_ref::<TradeError>() {
+                    Some(TradeError::NotFound(_)) => 404,
+                    _ => 500,
+                },
+                message: format!("Failed to set trade memo: {}", e),
+            })?
+    };
+
+    // Return memo
+    Ok(Json(memo))
+}
+
+/// Set transaction memo handler
+async fn set_transaction_memo_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(txid): Path<String>,
+    Json(request): Json<SetMemoRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Set memo
+    let memo = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.set_transaction_memo(&txid, request.labels, request.note)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to set transaction memo: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Return memo
+    Ok(Json(memo))
+}
+
+/// List refund paths handler
+async fn list_refunds_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let refunds = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_refund_paths().await
+    };
+
+    Ok(Json(refunds))
+}
+
+/// Register refund path handler
+async fn register_refund_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<RegisterRefundRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Register refund
+    let refund = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.register_refund_path(&TradeId(request.trade_id), &request.refund_tx)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to register refund: {}", e),
+                code: 400,
+            })?
+    };
+
+    Ok(Json(refund))
 }
 
 /// Get market data handler
@@ -404,6 +2191,244 @@ async fn get_market_data_handler(
     })))
 }
 
+/// List markets handler
+async fn list_markets_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Get the pairs with live orders
+    let markets = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.list_markets()
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to list markets: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(markets))
+}
+
+/// Ticker handler
+async fn get_ticker_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<MarketDataQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let base_asset = parse_asset(&query.base_asset)?;
+    let quote_asset = parse_asset(&query.quote_asset)?;
+
+    // Get 24h statistics
+    let ticker = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_ticker(&base_asset, &quote_asset)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get ticker: {}", e),
+                code: 500,
+            })?
+    };
+
+    Ok(Json(ticker))
+}
+
+/// Get the status code of a routing failure
+fn routing_error_code(e: &anyhow::Error) -> u16 {
+    match e.downcast_ref::<RoutingError>() {
+        Some(RoutingError::SameAsset(_)) => 400,
+        Some(RoutingError::NoRoute(_, _)) => 404,
+        Some(RoutingError::InsufficientLiquidity { .. }) => 409,
+        Some(RoutingError::Refused(_)) => 403,
+        None => 500,
+    }
+}
+
+/// Route quote handler
+async fn quote_route_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<RouteQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let from = parse_asset(&query.from)?;
+    let to = parse_asset(&query.to)?;
+    let amount = query.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+
+    // Quote the route
+    let darkswap = state.darkswap.lock().await;
+    let route = darkswap.quote_route(&from, &to, amount).await.map_err(|e| ApiError {
+        code: routing_error_code(&e),
+        message: format!("Failed to quote route: {}", e),
+    })?;
+
+    Ok(Json(route))
+}
+
+/// Execute route handler
+async fn execute_route_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<ExecuteRouteRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let from = parse_asset(&request.from)?;
+    let to = parse_asset(&request.to)?;
+    let amount = request.amount.parse::<Decimal>().map_err(|_| ApiError {
+        message: "Invalid amount".to_string(),
+        code: 400,
+    })?;
+    let max_slippage_bps = request.max_slippage_bps
+        .map(|bps| bps.parse::<Decimal>())
+        .transpose()
+        .map_err(|_| ApiError {
+            message: "Invalid max_slippage_bps".to_string(),
+            code: 400,
+        })?;
+
+    // Quote the route again, since the books may have moved since it was shown
+    let (route, executor) = {
+        let darkswap = state.darkswap.lock().await;
+        let route = darkswap.quote_route(&from, &to, amount).await.map_err(|e| ApiError {
+            code: routing_error_code(&e),
+            message: format!("Failed to quote route: {}", e),
+        })?;
+        let executor = darkswap.get_route_executor().map_err(|e| ApiError {
+            message: format!("Failed to execute route: {}", e),
+            code: 500,
+        })?;
+        (route, executor)
+    };
+    if let Some(max_slippage_bps) = max_slippage_bps {
+        if route.slippage_bps > max_slippage_bps {
+            return Err(ApiError {
+                message: format!("Route slippage of {} bps exceeds {} bps", route.slippage_bps.round_dp(2), max_slippage_bps),
+                code: 409,
+            });
+        }
+    }
+
+    // Execute it hop by hop, without holding the node while the trades settle
+    let execution = executor.execute(&route, request.unwind).await.map_err(|e| ApiError {
+        message: format!("Failed to execute route: {}", e),
+        code: 500,
+    })?;
+
+    Ok(Json(execution))
+}
+
+/// Market rules handler
+async fn get_market_rules_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let market_rules = state.market_rules.as_ref().ok_or_else(|| ApiError {
+        message: "This daemon publishes no market rules".to_string(),
+        code: 404,
+    })?;
+
+    Ok(Json(market_rules.as_ref().clone()))
+}
+
+/// New address handler
+async fn new_address_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<NewAddressRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Derive address
+    let address = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_new_address(request.label)
+            .await
+            .map_err(|e| ApiError {
+                code: match e.downcast_ref::<WalletError>() {
+                    Some(WalletError::GapLimitReached(_)) => 409,
+                    _ => 500,
+                },
+                message: format!("Failed to get new address: {}", e),
+            })?
+    };
+
+    // Return address
+    Ok(Json(address))
+}
+
+/// List addresses handler
+async fn list_addresses_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Get addresses
+    let addresses = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.list_addresses()
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to list addresses: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Return addresses
+    Ok(Json(addresses))
+}
+
+/// List deposits handler
+async fn list_deposits_handler(
+    State(state): State<Arc<ApiState>>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Get deposits
+    let deposits = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_deposits()
+            .map_err(|e| ApiError {
+                message: format!("Failed to list deposits: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Return deposits
+    Ok(Json(deposits))
+}
+
+/// Consolidate handler
+async fn consolidate_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<ConsolidateRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Consolidation that cannot run now is a conflict, not a failure
+    let to_api_error = |e: anyhow::Error| ApiError {
+        code: if e.downcast_ref::<ConsolidationError>().is_some() { 409 } else { 500 },
+        message: format!("Failed to consolidate: {}", e),
+    };
+
+    let darkswap = state.darkswap.lock().await;
+    if request.dry_run {
+        let plan = darkswap.plan_consolidation(request.force).await.map_err(to_api_error)?;
+        return Ok(Json(serde_json::json!({ "plan": plan, "txid": null })));
+    }
+
+    let report = darkswap.consolidate_dust(request.force).await.map_err(to_api_error)?;
+    Ok(Json(serde_json::json!({ "plan": report.plan, "txid": report.txid })))
+}
+
+/// Payout proof handler
+async fn add_payout_proof_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<PayoutProofRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let darkswap = state.darkswap.lock().await;
+    darkswap.add_payout_proof(&request.address, &request.proof)
+        .await
+        .map_err(|e| ApiError {
+            code: match e.downcast_ref::<TradeError>() {
+                Some(TradeError::InvalidPayoutAddress(_) | TradeError::PayoutProof(_)) => 400,
+                _ => 500,
+            },
+            message: format!("Failed to add payout proof: {}", e),
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// List runes handler
 async fn list_runes_handler(
     State(state): State<Arc<ApiState>>,
@@ -453,6 +2478,167 @@ async fn get_rune_handler(
     Ok(Json(rune))
 }
 
+/// Get asset display metadata handler
+async fn get_asset_info_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(asset_str): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    // Parse parameters
+    let asset = parse_asset(&asset_str)?;
+
+    // Get asset info
+    let info = {
+        let darkswap = state.darkswap.lock().await;
+        darkswap.get_asset_info(&asset)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to get asset info: {}", e),
+                code: 500,
+            })?
+    };
+
+    // Return asset info
+    Ok(Json(info))
+}
+
+/// Get the cached metadata of an asset
+async fn cached_asset(state: &ApiState, asset_str: &str) -> Result<(Arc<assets::CachedAsset>, u64), ApiError> {
+    let service = state.asset_meta.as_ref().ok_or_else(|| ApiError {
+        message: "Asset metadata not configured".to_string(),
+        code: 404,
+    })?;
+    let asset = parse_asset(asset_str)?;
+
+    let cached = service.get(&asset).await.map_err(|e| ApiError {
+        code: match e {
+            AssetMetaError::NotFound(_) | AssetMetaError::NoIcon(_) => 404,
+            AssetMetaError::Provider(_) => 502,
+        },
+        message: e.to_string(),
+    })?;
+
+    Ok((cached, service.ttl()))
+}
+
+/// Get asset metadata handler
+async fn get_asset_meta_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(asset_str): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<Response, ApiError> {
+    let (cached, ttl) = cached_asset(&state, &asset_str).await?;
+    let cache_headers = [
+        (axum::http::header::ETAG, cached.etag.clone()),
+        (axum::http::header::CACHE_CONTROL, format!("public, max-age={}", ttl)),
+    ];
+
+    if assets::not_modified(&headers, &cached.etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    Ok((cache_headers, Json(cached.meta.clone())).into_response())
+}
+
+/// Get asset icon handler
+async fn get_asset_icon_handler(
+    State(state): State<Arc<ApiState>>,
+    Path(asset_str): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> Result<Response, ApiError> {
+    let (cached, ttl) = cached_asset(&state, &asset_str).await?;
+    let icon = cached.icon.as_ref().ok_or_else(|| ApiError {
+        message: AssetMetaError::NoIcon(asset_str.clone()).to_string(),
+        code: 404,
+    })?;
+    let cache_headers = [
+        (axum::http::header::ETAG, icon.etag.clone()),
+        (axum::http::header::CACHE_CONTROL, format!("public, max-age={}", ttl)),
+    ];
+
+    if assets::not_modified(&headers, &icon.etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    Ok((
+        cache_headers,
+        [(axum::http::header::CONTENT_TYPE, icon.content_type.clone())],
+        icon.bytes.clone(),
+    ).into_response())
+}
+
+/// Record settlement handler
+///
+/// A receipt signed by one party is only accepted with the settlement key as a
+/// bearer token, and an inclusion proof must verify against the tracked headers.
+async fn record_settlement_handler(
+    State(state): State<Arc<ApiState>>,
+    headers: axum::http::HeaderMap,
+    Json(receipt): Json<TradeReceipt>,
+) -> Result<impl IntoResponse, ApiError> {
+    let key = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let trusted = state.settlements.is_trusted(key);
+
+    if let Some(proof) = &receipt.inclusion_proof {
+        let included = state.darkswap.lock().await
+            .verify_transaction_inclusion(&receipt.body.txid, proof)
+            .await
+            .map_err(|e| ApiError {
+                message: format!("Failed to verify inclusion proof: {}", e),
+                code: 400,
+            })?;
+        if !included {
+            return Err(ApiError {
+                message: format!("Transaction {} is not proven included in a block", receipt.body.txid),
+                code: 400,
+            });
+        }
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let record = state.settlements.record(receipt, now, trusted).map_err(|e| ApiError {
+        message: format!("Failed to record settlement: {}", e),
+        code: 400,
+    })?;
+
+    Ok((StatusCode::CREATED, Json(record)))
+}
+
+/// List settlements handler
+async fn list_settlements_handler(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<SettlementQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let pair = match (&query.base_asset, &query.quote_asset) {
+        (Some(base_asset), Some(quote_asset)) => Some((parse_asset(base_asset)?, parse_asset(quote_asset)?)),
+        (None, None) => None,
+        _ => {
+            return Err(ApiError {
+                message: "Both base_asset and quote_asset are needed to filter by pair".to_string(),
+                code: 400,
+            });
+        }
+    };
+    let filter = SettlementFilter {
+        from: query.from,
+        to: query.to,
+        pair,
+        peer: query.peer,
+    };
+
+    let records = state.settlements.query(&filter, query.limit.unwrap_or(100)).map_err(|e| ApiError {
+        message: format!("Failed to query settlements: {}", e),
+        code: 500,
+    })?;
+
+    Ok(Json(records))
+}
+
 /// List alkanes handler
 async fn list_alkanes_handler(
     State(state): State<Arc<ApiState>>,
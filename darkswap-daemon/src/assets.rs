@@ -0,0 +1,351 @@
+//! Asset metadata for front-ends
+//!
+//! Runes and alkanes carry no icon or description on chain. The daemon fetches
+//! them from a metadata provider configured in a JSON file, keeps them in memory
+//! for `ttl` seconds and serves them at `/assets/:id/meta` and
+//! `/assets/:id/icon`:
+//!
+//! ```json
+//! {
+//!   "provider_url": "https://meta.example.com/assets/{id}",
+//!   "ttl": 86400,
+//!   "max_icon_bytes": 262144
+//! }
+//! ```
+//!
+//! The provider answers `GET provider_url` with `{id}` replaced by the asset ID
+//! (e.g. `RUNE:2a`) with a JSON document holding any of `name`, `symbol`,
+//! `description` and `icon_url`. Icons larger than `max_icon_bytes` or not of
+//! an `image/*` type are left out. Both routes send an `ETag`, and a request
+//! whose `If-None-Match` matches it gets `304 Not Modified`.
+//!
+//! The entries of the SDK configuration's `assets` take precedence over the
+//! provider field by field, and are served even for assets the provider does
+//! not know, or without a provider at all.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use darkswap_sdk::asset_info;
+use darkswap_sdk::config::AssetOverride;
+use darkswap_sdk::types::Asset;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+/// Asset metadata configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssetMetaConfig {
+    /// Provider URL, `{id}` is replaced by the asset ID
+    pub provider_url: String,
+    /// Time metadata is served from the cache before it is fetched again (seconds)
+    pub ttl: u64,
+    /// Largest icon served (bytes)
+    pub max_icon_bytes: usize,
+    /// Provider request timeout (seconds)
+    pub timeout: u64,
+}
+
+impl Default for AssetMetaConfig {
+    fn default() -> Self {
+        Self {
+            provider_url: String::new(),
+            ttl: 86400, // 1 day
+            max_icon_bytes: 256 * 1024,
+            timeout: 10,
+        }
+    }
+}
+
+impl AssetMetaConfig {
+    /// Load the configuration from a JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read asset metadata configuration {}", path.display()))?;
+        let config: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse asset metadata configuration {}", path.display()))?;
+        if !config.provider_url.contains("{id}") {
+            anyhow::bail!("Asset metadata provider URL must contain {{id}}");
+        }
+
+        Ok(config)
+    }
+}
+
+/// Asset metadata error
+#[derive(Debug, Error)]
+pub enum AssetMetaError {
+    /// The provider has no metadata for the asset
+    #[error("No metadata for asset {0}")]
+    NotFound(String),
+    /// The provider failed or sent an invalid document
+    #[error("Metadata provider error: {0}")]
+    Provider(String),
+    /// The asset has no icon, or it could not be served
+    #[error("No icon for asset {0}")]
+    NoIcon(String),
+}
+
+/// Metadata document of the provider
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ProviderMeta {
+    /// Name
+    name: Option<String>,
+    /// Ticker symbol
+    symbol: Option<String>,
+    /// Description
+    description: Option<String>,
+    /// Icon location
+    icon_url: Option<String>,
+}
+
+/// Metadata served to front-ends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetMeta {
+    /// Asset ID
+    pub id: String,
+    /// Name
+    pub name: Option<String>,
+    /// Ticker symbol
+    pub symbol: Option<String>,
+    /// Description
+    pub description: Option<String>,
+    /// Decimals amounts are displayed with, if the operator set them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+    /// Path of the cached icon on this daemon, if the asset has one
+    pub icon: Option<String>,
+}
+
+/// Cached icon
+#[derive(Debug, Clone)]
+pub struct Icon {
+    /// Media type
+    pub content_type: String,
+    /// Image data
+    pub bytes: Vec<u8>,
+    /// Entity tag of the image
+    pub etag: String,
+}
+
+/// Cached metadata of an asset
+#[derive(Debug, Clone)]
+pub struct CachedAsset {
+    /// Metadata
+    pub meta: AssetMeta,
+    /// Entity tag of the metadata
+    pub etag: String,
+    /// Icon
+    pub icon: Option<Icon>,
+    /// Time the metadata was fetched
+    fetched_at: Instant,
+}
+
+/// Fetches, caches and serves asset metadata
+pub struct AssetMetaService {
+    /// Configuration
+    config: AssetMetaConfig,
+    /// HTTP client
+    client: reqwest::Client,
+    /// Metadata by asset ID
+    cache: RwLock<HashMap<String, Arc<CachedAsset>>>,
+    /// Operator's metadata by asset ID, merged over the provider's
+    overrides: HashMap<String, AssetOverride>,
+}
+
+impl AssetMetaService {
+    /// Create the service
+    pub fn new(config: AssetMetaConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout.max(1)))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Self {
+            config,
+            client,
+            cache: RwLock::new(HashMap::new()),
+            overrides: HashMap::new(),
+        })
+    }
+
+    /// Merge the operator's metadata over the provider's
+    pub fn with_overrides(mut self, overrides: HashMap<String, AssetOverride>) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Get the time clients may cache a response for (seconds)
+    pub fn ttl(&self) -> u64 {
+        self.config.ttl
+    }
+
+    /// Get the metadata of an asset, from the cache while it is fresh
+    ///
+    /// Stale metadata is served if the provider cannot be reached.
+    pub async fn get(&self, asset: &Asset) -> Result<Arc<CachedAsset>, AssetMetaError> {
+        let id = asset.to_string();
+        let cached = self.cache.read().await.get(&id).cloned();
+        if let Some(cached) = &cached {
+            if cached.fetched_at.elapsed() < Duration::from_secs(self.config.ttl) {
+                return Ok(cached.clone());
+            }
+        }
+
+        match self.fetch(asset, &id).await {
+            Ok(fetched) => {
+                let fetched = Arc::new(fetched);
+                self.cache.write().await.insert(id, fetched.clone());
+                Ok(fetched)
+            }
+            Err(AssetMetaError::Provider(e)) if cached.is_some() => {
+                log::warn!("Serving stale metadata of {}: {}", id, e);
+                cached.ok_or(AssetMetaError::Provider(e))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetch the metadata and icon of an asset from the provider, with the operator's metadata merged over them
+    async fn fetch(&self, asset: &Asset, id: &str) -> Result<CachedAsset, AssetMetaError> {
+        let entry = asset_info::find_override(&self.overrides, asset);
+        let provided = match self.fetch_provided(id).await {
+            Ok(provided) => provided,
+            Err(AssetMetaError::NotFound(_)) if entry.is_some() => ProviderMeta::default(),
+            Err(e) => return Err(e),
+        };
+        let provided = match entry {
+            Some(entry) => ProviderMeta {
+                name: entry.name.clone().or(provided.name),
+                symbol: entry.symbol.clone().or(provided.symbol),
+                description: provided.description,
+                icon_url: entry.icon_url.clone().or(provided.icon_url),
+            },
+            None => provided,
+        };
+
+        // A missing or oversized icon does not hold up the rest of the metadata
+        let icon = match &provided.icon_url {
+            Some(icon_url) => match self.fetch_icon(icon_url).await {
+                Ok(icon) => Some(icon),
+                Err(e) => {
+                    log::warn!("Leaving out the icon of {}: {}", id, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let meta = AssetMeta {
+            id: id.to_string(),
+            name: provided.name,
+            symbol: provided.symbol,
+            description: provided.description,
+            decimals: entry.and_then(|entry| entry.decimals),
+            icon: icon.as_ref().map(|_| format!("/assets/{}/icon", id)),
+        };
+        let etag = etag(&serde_json::to_vec(&meta).map_err(|e| AssetMetaError::Provider(e.to_string()))?);
+
+        Ok(CachedAsset { meta, etag, icon, fetched_at: Instant::now() })
+    }
+
+    /// Fetch the provider's metadata document of an asset
+    async fn fetch_provided(&self, id: &str) -> Result<ProviderMeta, AssetMetaError> {
+        if self.config.provider_url.is_empty() {
+            return Err(AssetMetaError::NotFound(id.to_string()));
+        }
+
+        let url = self.config.provider_url.replace("{id}", id);
+        let response = self.client.get(&url).send().await
+            .map_err(|e| AssetMetaError::Provider(e.to_string()))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(AssetMetaError::NotFound(id.to_string()));
+        }
+
+        response
+            .error_for_status()
+            .map_err(|e| AssetMetaError::Provider(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AssetMetaError::Provider(e.to_string()))
+    }
+
+    /// Fetch an icon, refusing anything but an image within the size limit
+    async fn fetch_icon(&self, url: &str) -> Result<Icon> {
+        let mut response = self.client.get(url).send().await?.error_for_status()?;
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        if !content_type.starts_with("image/") {
+            anyhow::bail!("Icon is not an image: {:?}", content_type);
+        }
+
+        let limit = self.config.max_icon_bytes;
+        if let Some(length) = response.content_length() {
+            if length > limit as u64 {
+                anyhow::bail!("Icon of {} bytes exceeds the {} byte limit", length, limit);
+            }
+        }
+
+        // The length header may be missing or wrong, so the limit is enforced while reading
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if bytes.len() + chunk.len() > limit {
+                anyhow::bail!("Icon exceeds the {} byte limit", limit);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(Icon { etag: etag(&bytes), content_type, bytes })
+    }
+}
+
+/// Get the entity tag of a response body
+pub fn etag(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(body)[..16]))
+}
+
+/// Check if the `If-None-Match` header of a request matches `etag`
+pub fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(axum::http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_not_modified() {
+        let tag = etag(b"icon");
+        assert_eq!(tag, etag(b"icon"));
+        assert_ne!(tag, etag(b"other icon"));
+
+        let mut headers = HeaderMap::new();
+        assert!(!not_modified(&headers, &tag));
+
+        headers.insert(axum::http::header::IF_NONE_MATCH, HeaderValue::from_str(&format!("\"abc\", W/{}", tag)).unwrap());
+        assert!(not_modified(&headers, &tag));
+        assert!(!not_modified(&headers, &etag(b"other icon")));
+
+        headers.insert(axum::http::header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert!(not_modified(&headers, &tag));
+    }
+}
@@ -0,0 +1,590 @@
+//! Browser mode: strict CORS, cookie sessions and CSRF protection
+//!
+//! A daemon the web UI talks to directly must not let other web pages drive it
+//! through the user's browser. In browser mode:
+//!
+//! - Only the configured origins pass CORS, and requests whose `Origin` is not
+//!   one of them are rejected outright, WebSocket upgrades included.
+//! - Every route but `/health` and `/auth/login` needs a session. Logging in with
+//!   the configured token sets a short-lived access JWT and a longer-lived refresh
+//!   JWT as `HttpOnly`, `SameSite=Strict` cookies.
+//! - State-changing requests must echo the session's CSRF token, which login
+//!   returns and sets as a cookie readable by the UI, in the `X-CSRF-Token` header.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use chrono::Utc;
+use darkswap_sdk::secrets::SecretResolver;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use uuid::Uuid;
+
+use crate::api::{ApiError, ApiState};
+
+/// Header carrying the CSRF token
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Access token cookie
+const ACCESS_COOKIE: &str = "darkswap_access";
+/// Refresh token cookie, only sent to the `/auth` routes
+const REFRESH_COOKIE: &str = "darkswap_refresh";
+/// CSRF token cookie, readable by the web UI
+const CSRF_COOKIE: &str = "darkswap_csrf";
+
+/// Browser mode configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrowserConfig {
+    /// Origins allowed to call the API, e.g. `https://app.example.com`
+    pub allowed_origins: Vec<String>,
+    /// Token the user logs in with
+    pub login_token: String,
+    /// Key the session JWTs are signed with; a random key is used if unset,
+    /// which ends all sessions when the daemon restarts
+    pub jwt_secret: Option<String>,
+    /// Access token lifetime (seconds)
+    pub access_ttl: u64,
+    /// Refresh token lifetime (seconds)
+    pub refresh_ttl: u64,
+    /// Mark cookies `Secure`; only disable when serving the UI over plain HTTP locally
+    pub secure_cookies: bool,
+}
+
+impl Default for BrowserConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            login_token: String::new(),
+            jwt_secret: None,
+            access_ttl: 300, // 5 minutes
+            refresh_ttl: 86400, // 24 hours
+            secure_cookies: true,
+        }
+    }
+}
+
+impl BrowserConfig {
+    /// Load the configuration from a JSON file
+    ///
+    /// The login token and JWT secret may name OS keyring entries as
+    /// `keyring:<entry>`.
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read browser mode configuration {}: {}", path.display(), e))?;
+        let mut config: Self = serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Failed to parse browser mode configuration {}: {}", path.display(), e))?;
+
+        let mut resolver = SecretResolver::new();
+        config.login_token = resolver.resolve(&config.login_token)?;
+        resolver.resolve_in_place(&mut config.jwt_secret)?;
+
+        Ok(config)
+    }
+}
+
+/// Browser authentication error
+#[derive(Debug, Error)]
+pub enum BrowserAuthError {
+    /// Wrong login token
+    #[error("Invalid login token")]
+    InvalidCredentials,
+    /// No session cookie
+    #[error("Not logged in")]
+    MissingSession,
+    /// Malformed, expired or foreign token
+    #[error("Invalid session token: {0}")]
+    InvalidToken(String),
+    /// The session was logged out or has expired
+    #[error("Session has ended")]
+    SessionEnded,
+    /// Request from an origin that is not allowed
+    #[error("Origin not allowed: {0}")]
+    OriginNotAllowed(String),
+    /// Missing or wrong CSRF token
+    #[error("Missing or invalid CSRF token")]
+    CsrfMismatch,
+}
+
+impl BrowserAuthError {
+    /// Get the HTTP status of the error
+    fn status(&self) -> StatusCode {
+        match self {
+            BrowserAuthError::OriginNotAllowed(_) | BrowserAuthError::CsrfMismatch => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+impl From<BrowserAuthError> for ApiError {
+    fn from(e: BrowserAuthError) -> Self {
+        ApiError {
+            code: e.status().as_u16(),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Kind of session token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TokenKind {
+    Access,
+    Refresh,
+}
+
+/// Session token claims
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    /// Session ID
+    sub: String,
+    /// Token kind
+    kind: TokenKind,
+    /// CSRF token of the session
+    csrf: String,
+    /// Issued at
+    iat: i64,
+    /// Expiration time
+    exp: i64,
+}
+
+/// Tokens of a session
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Access token
+    pub access_token: String,
+    /// Refresh token
+    pub refresh_token: String,
+    /// CSRF token
+    pub csrf_token: String,
+    /// Access token expiry (unix seconds)
+    pub expires_at: i64,
+    /// Session expiry (unix seconds)
+    pub refresh_expires_at: i64,
+}
+
+/// Browser session authority
+pub struct BrowserAuth {
+    /// Configuration
+    config: BrowserConfig,
+    /// JWT signing key
+    secret: Vec<u8>,
+    /// Live sessions with their expiry (unix seconds)
+    sessions: Mutex<HashMap<String, i64>>,
+}
+
+impl BrowserAuth {
+    /// Create the session authority
+    pub fn new(config: BrowserConfig) -> Result<Self> {
+        if config.allowed_origins.is_empty() {
+            anyhow::bail!("Browser mode needs at least one allowed origin");
+        }
+        for origin in &config.allowed_origins {
+            HeaderValue::from_str(origin).map_err(|_| anyhow::anyhow!("Invalid origin: {}", origin))?;
+        }
+        if config.login_token.len() < 16 {
+            anyhow::bail!("Browser mode login token must be at least 16 characters");
+        }
+
+        let secret = match &config.jwt_secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                let mut secret = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                secret
+            }
+        };
+
+        Ok(Self {
+            config,
+            secret,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Get the CORS layer allowing only the configured origins
+    pub fn cors_layer(&self) -> CorsLayer {
+        let origins: Vec<HeaderValue> = self.config.allowed_origins.iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok())
+            .collect();
+
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([
+                header::CONTENT_TYPE,
+                header::HeaderName::from_static(CSRF_HEADER),
+                header::HeaderName::from_static(crate::rate_limit::API_KEY_HEADER),
+            ])
+            .allow_credentials(true)
+    }
+
+    /// Start a session for the holder of the login token
+    pub fn login(&self, login_token: &str) -> Result<Session, BrowserAuthError> {
+        if !constant_time_eq(login_token.as_bytes(), self.config.login_token.as_bytes()) {
+            return Err(BrowserAuthError::InvalidCredentials);
+        }
+
+        let session_id = Uuid::new_v4().to_string();
+        let csrf = Uuid::new_v4().simple().to_string();
+        let refresh_expires_at = Utc::now().timestamp() + self.config.refresh_ttl as i64;
+        self.lock_sessions().insert(session_id.clone(), refresh_expires_at);
+
+        self.issue(&session_id, &csrf, refresh_expires_at)
+    }
+
+    /// Issue a new access token for the session of a refresh token
+    pub fn refresh(&self, refresh_token: &str, csrf: Option<&str>) -> Result<Session, BrowserAuthError> {
+        let claims = self.verify(refresh_token, TokenKind::Refresh)?;
+        check_csrf(&claims, csrf)?;
+
+        self.issue(&claims.sub, &claims.csrf, claims.exp)
+    }
+
+    /// End the session of a token
+    pub fn logout(&self, token: &str) {
+        let session = self.verify(token, TokenKind::Access)
+            .or_else(|_| self.verify(token, TokenKind::Refresh));
+        if let Ok(claims) = session {
+            self.lock_sessions().remove(&claims.sub);
+        }
+    }
+
+    /// Check that a request may reach the API
+    pub fn authorize(&self, method: &Method, headers: &HeaderMap) -> Result<(), BrowserAuthError> {
+        self.check_origin(headers)?;
+
+        let token = cookie(headers, ACCESS_COOKIE).ok_or(BrowserAuthError::MissingSession)?;
+        let claims = self.verify(token, TokenKind::Access)?;
+
+        if !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            check_csrf(&claims, header_str(headers, CSRF_HEADER))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reject requests sent from pages of other origins
+    fn check_origin(&self, headers: &HeaderMap) -> Result<(), BrowserAuthError> {
+        match header_str(headers, header::ORIGIN.as_str()) {
+            Some(origin) if !self.config.allowed_origins.iter().any(|allowed| allowed == origin) => {
+                Err(BrowserAuthError::OriginNotAllowed(origin.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sign the tokens of a session
+    fn issue(&self, session_id: &str, csrf: &str, refresh_expires_at: i64) -> Result<Session, BrowserAuthError> {
+        let now = Utc::now().timestamp();
+        // An access token never outlives its session
+        let expires_at = (now + self.config.access_ttl as i64).min(refresh_expires_at);
+
+        let sign = |kind, exp| {
+            let claims = Claims { sub: session_id.to_string(), kind, csrf: csrf.to_string(), iat: now, exp };
+            encode(&Header::default(), &claims, &EncodingKey::from_secret(&self.secret))
+                .map_err(|e| BrowserAuthError::InvalidToken(format!("Failed to sign token: {}", e)))
+        };
+
+        Ok(Session {
+            access_token: sign(TokenKind::Access, expires_at)?,
+            refresh_token: sign(TokenKind::Refresh, refresh_expires_at)?,
+            csrf_token: csrf.to_string(),
+            expires_at,
+            refresh_expires_at,
+        })
+    }
+
+    /// Verify a token of a live session
+    fn verify(&self, token: &str, kind: TokenKind) -> Result<Claims, BrowserAuthError> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        let claims = decode::<Claims>(token, &DecodingKey::from_secret(&self.secret), &validation)
+            .map_err(|e| BrowserAuthError::InvalidToken(e.to_string()))?
+            .claims;
+        if claims.kind != kind {
+            return Err(BrowserAuthError::InvalidToken(format!("expected a {:?} token", kind)));
+        }
+
+        let now = Utc::now().timestamp();
+        let mut sessions = self.lock_sessions();
+        sessions.retain(|_, expires_at| *expires_at > now);
+        if !sessions.contains_key(&claims.sub) {
+            return Err(BrowserAuthError::SessionEnded);
+        }
+
+        Ok(claims)
+    }
+
+    /// Get the session set
+    fn lock_sessions(&self) -> std::sync::MutexGuard<'_, HashMap<String, i64>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Build the cookies of a session
+    fn session_cookies(&self, session: &Session) -> Vec<String> {
+        let now = Utc::now().timestamp();
+        vec![
+            self.cookie(ACCESS_COOKIE, &session.access_token, "/", session.expires_at - now, true),
+            self.cookie(REFRESH_COOKIE, &session.refresh_token, "/auth", session.refresh_expires_at - now, true),
+            self.cookie(CSRF_COOKIE, &session.csrf_token, "/", session.refresh_expires_at - now, false),
+        ]
+    }
+
+    /// Build the cookies ending a session
+    fn cleared_cookies(&self) -> Vec<String> {
+        vec![
+            self.cookie(ACCESS_COOKIE, "", "/", 0, true),
+            self.cookie(REFRESH_COOKIE, "", "/auth", 0, true),
+            self.cookie(CSRF_COOKIE, "", "/", 0, false),
+        ]
+    }
+
+    /// Build a `Set-Cookie` value
+    fn cookie(&self, name: &str, value: &str, path: &str, max_age: i64, http_only: bool) -> String {
+        let mut cookie = format!("{}={}; Path={}; Max-Age={}; SameSite=Strict", name, value, path, max_age.max(0));
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        if self.config.secure_cookies {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+/// Login request
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    /// Login token
+    pub token: String,
+}
+
+/// Session response
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    /// CSRF token to send in the `X-CSRF-Token` header
+    pub csrf_token: String,
+    /// Access token expiry (unix seconds)
+    pub expires_at: i64,
+}
+
+/// Routes managing browser sessions
+pub fn routes() -> Router<Arc<ApiState>> {
+    Router::new()
+        .route("/auth/login", post(login_handler))
+        .route("/auth/refresh", post(refresh_handler))
+        .route("/auth/logout", post(logout_handler))
+}
+
+/// Middleware enforcing browser mode on the API routes
+pub async fn require_session<B>(
+    State(state): State<Arc<ApiState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let auth = match &state.browser {
+        Some(auth) => auth,
+        None => return next.run(request).await,
+    };
+
+    let path = request.uri().path();
+    let result = if path == "/health" {
+        Ok(())
+    } else if path.starts_with("/auth/") {
+        // The session routes check their own tokens
+        auth.check_origin(request.headers())
+    } else {
+        auth.authorize(request.method(), request.headers())
+    };
+
+    match result {
+        Ok(()) => next.run(request).await,
+        Err(e) => ApiError::from(e).into_response(),
+    }
+}
+
+/// Login handler
+async fn login_handler(
+    State(state): State<Arc<ApiState>>,
+    Json(request): Json<LoginRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let auth = browser_auth(&state)?;
+    let session = auth.login(&request.token)?;
+
+    Ok(session_response(auth, &session))
+}
+
+/// Refresh handler
+async fn refresh_handler(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let auth = browser_auth(&state)?;
+    let refresh_token = cookie(&headers, REFRESH_COOKIE).ok_or(BrowserAuthError::MissingSession)?;
+    let session = auth.refresh(refresh_token, header_str(&headers, CSRF_HEADER))?;
+
+    Ok(session_response(auth, &session))
+}
+
+/// Logout handler
+async fn logout_handler(
+    State(state): State<Arc<ApiState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let auth = browser_auth(&state)?;
+    for name in [ACCESS_COOKIE, REFRESH_COOKIE] {
+        if let Some(token) = cookie(&headers, name) {
+            auth.logout(token);
+        }
+    }
+
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    append_cookies(response.headers_mut(), auth.cleared_cookies());
+    Ok(response)
+}
+
+/// Get the session authority of a daemon in browser mode
+fn browser_auth(state: &ApiState) -> Result<&BrowserAuth, ApiError> {
+    state.browser.as_deref().ok_or(ApiError {
+        message: "Browser mode is not enabled".to_string(),
+        code: 404,
+    })
+}
+
+/// Build the response setting the cookies of a session
+fn session_response(auth: &BrowserAuth, session: &Session) -> Response {
+    let mut response = Json(SessionResponse {
+        csrf_token: session.csrf_token.clone(),
+        expires_at: session.expires_at,
+    })
+    .into_response();
+    append_cookies(response.headers_mut(), auth.session_cookies(session));
+    response
+}
+
+/// Add `Set-Cookie` headers
+fn append_cookies(headers: &mut HeaderMap, cookies: Vec<String>) {
+    for cookie in cookies {
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            headers.append(header::SET_COOKIE, value);
+        }
+    }
+}
+
+/// Check the CSRF token of a request against its session
+fn check_csrf(claims: &Claims, csrf: Option<&str>) -> Result<(), BrowserAuthError> {
+    match csrf {
+        Some(csrf) if constant_time_eq(csrf.as_bytes(), claims.csrf.as_bytes()) => Ok(()),
+        _ => Err(BrowserAuthError::CsrfMismatch),
+    }
+}
+
+/// Get a header as a string
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Get a cookie of a request
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Compare secrets in time independent of where they differ
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> BrowserAuth {
+        BrowserAuth::new(BrowserConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            login_token: "correct horse battery staple".to_string(),
+            ..BrowserConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn request_headers(session: &Session, origin: &str, csrf: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+        headers.insert(header::COOKIE, HeaderValue::from_str(&format!("theme=dark; {}={}", ACCESS_COOKIE, session.access_token)).unwrap());
+        if let Some(csrf) = csrf {
+            headers.insert(CSRF_HEADER, HeaderValue::from_str(csrf).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_login_needs_the_token() {
+        let auth = auth();
+
+        assert!(matches!(auth.login("guess"), Err(BrowserAuthError::InvalidCredentials)));
+        assert!(auth.login("correct horse battery staple").is_ok());
+    }
+
+    #[test]
+    fn test_state_changes_need_csrf_token() {
+        let auth = auth();
+        let session = auth.login("correct horse battery staple").unwrap();
+        let origin = "https://app.example.com";
+
+        assert!(auth.authorize(&Method::GET, &request_headers(&session, origin, None)).is_ok());
+        assert!(matches!(
+            auth.authorize(&Method::POST, &request_headers(&session, origin, None)),
+            Err(BrowserAuthError::CsrfMismatch)
+        ));
+        assert!(auth.authorize(&Method::POST, &request_headers(&session, origin, Some(&session.csrf_token))).is_ok());
+    }
+
+    #[test]
+    fn test_foreign_origin_is_rejected() {
+        let auth = auth();
+        let session = auth.login("correct horse battery staple").unwrap();
+        let headers = request_headers(&session, "https://evil.example.com", Some(&session.csrf_token));
+
+        assert!(matches!(auth.authorize(&Method::POST, &headers), Err(BrowserAuthError::OriginNotAllowed(_))));
+    }
+
+    #[test]
+    fn test_refresh_and_logout() {
+        let auth = auth();
+        let session = auth.login("correct horse battery staple").unwrap();
+
+        // Refresh tokens are not access tokens
+        assert!(auth.verify(&session.refresh_token, TokenKind::Access).is_err());
+        assert!(auth.refresh(&session.refresh_token, None).is_err());
+
+        let refreshed = auth.refresh(&session.refresh_token, Some(&session.csrf_token)).unwrap();
+        assert_eq!(refreshed.csrf_token, session.csrf_token);
+
+        auth.logout(&refreshed.access_token);
+        assert!(matches!(auth.verify(&session.access_token, TokenKind::Access), Err(BrowserAuthError::SessionEnded)));
+        assert!(auth.refresh(&session.refresh_token, Some(&session.csrf_token)).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Cold-standby failover for DarkSwap daemon
+//!
+//! A primary and a standby daemon share a state directory holding the node
+//! identity, the persisted peers and open orders, and a lease file. The daemon
+//! holding the lease refreshes its heartbeat in the lease file; a standby waits
+//! until the heartbeat is older than the failover timeout, takes the lease, and
+//! starts with the same peer identity, re-announcing the open orders.
+//!
+//! The lease is a heartbeat file, not a consensus protocol: it assumes the state
+//! directory is visible to both daemons and their clocks roughly agree. A daemon
+//! that finds its lease taken over, or cannot heartbeat for longer than the
+//! failover timeout, exits instead of announcing orders twice. Shorter I/O
+//! failures on the shared directory are retried.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Lease file name in the state directory
+const LEASE_FILE: &str = "daemon.lock";
+/// Identity key file name in the state directory
+const IDENTITY_FILE: &str = "identity.key";
+/// Peer store file name in the state directory
+pub const PEER_STORE_FILE: &str = "peers.json";
+/// Relay store file name in the state directory
+pub const RELAY_STORE_FILE: &str = "relays.json";
+/// Order store file name in the state directory
+pub const ORDER_STORE_FILE: &str = "orders.json";
+/// Schedule store file name in the state directory
+pub const SCHEDULE_STORE_FILE: &str = "schedules.json";
+/// Iceberg store file name in the state directory
+pub const ICEBERG_STORE_FILE: &str = "icebergs.json";
+/// Refund store file name in the state directory
+pub const REFUND_STORE_FILE: &str = "refunds.json";
+
+/// Failover error
+#[derive(Debug, Error)]
+pub enum FailoverError {
+    /// Another daemon holds a live lease
+    #[error("Lease is held by {owner} (pid {pid}), last heartbeat {age} seconds ago")]
+    LeaseHeld {
+        /// Owner of the lease
+        owner: String,
+        /// Process ID of the owner
+        pid: u32,
+        /// Age of the owner's heartbeat (seconds)
+        age: u64,
+    },
+    /// The lease was taken over by another daemon
+    #[error("Lease was taken over by {0}")]
+    LeaseLost(String),
+}
+
+/// Contents of the lease file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Lease {
+    /// Daemon holding the lease
+    owner: String,
+    /// Process ID of the daemon
+    pid: u32,
+    /// Last heartbeat (unix seconds)
+    heartbeat: u64,
+}
+
+/// Heartbeat lease on a shared state directory
+pub struct LeaseLock {
+    /// Lease file
+    path: PathBuf,
+    /// This daemon's owner ID
+    owner: String,
+    /// Heartbeat age after which the lease can be taken over (seconds)
+    timeout: u64,
+}
+
+impl LeaseLock {
+    /// Create a lease lock in a state directory
+    pub fn new<P: AsRef<Path>>(state_dir: P, timeout: u64) -> Self {
+        Self {
+            path: state_dir.as_ref().join(LEASE_FILE),
+            owner: uuid::Uuid::new_v4().to_string(),
+            timeout,
+        }
+    }
+
+    /// Read the current lease
+    fn read(&self) -> Result<Option<Lease>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read lease {}", self.path.display()))?;
+
+        // A torn or corrupt lease counts as no lease
+        Ok(serde_json::from_str(&contents).ok())
+    }
+
+    /// Write a heartbeat for this daemon
+    fn write(&self) -> Result<()> {
+        let lease = Lease {
+            owner: self.owner.clone(),
+            pid: std::process::id(),
+            heartbeat: now(),
+        };
+
+        let contents = serde_json::to_string(&lease).context("Failed to serialize lease")?;
+        let tmp_path = self.path.with_extension(format!("{}.tmp", self.owner));
+        fs::write(&tmp_path, contents).context("Failed to write lease")?;
+        fs::rename(&tmp_path, &self.path).context("Failed to replace lease")?;
+
+        Ok(())
+    }
+
+    /// Take the lease if it is free, stale or already ours
+    pub async fn try_acquire(&self) -> Result<()> {
+        if let Some(lease) = self.read()? {
+            let age = now().saturating_sub(lease.heartbeat);
+            if lease.owner != self.owner && age <= self.timeout {
+                return Err(FailoverError::LeaseHeld { owner: lease.owner, pid: lease.pid, age }.into());
+            }
+        }
+
+        self.write()?;
+
+        // Two standbys may have written at once; the last write wins
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        match self.read()? {
+            Some(lease) if lease.owner == self.owner => Ok(()),
+            Some(lease) => Err(FailoverError::LeaseHeld { owner: lease.owner, pid: lease.pid, age: 0 }.into()),
+            None => Err(FailoverError::LeaseLost("nobody".to_string()).into()),
+        }
+    }
+
+    /// Wait until the lease can be taken, checking every `interval`
+    pub async fn acquire(&self, interval: Duration) -> Result<()> {
+        let mut waiting = false;
+        loop {
+            match self.try_acquire().await {
+                Ok(()) => return Ok(()),
+                Err(e) => match e.downcast_ref::<FailoverError>() {
+                    Some(FailoverError::LeaseHeld { .. }) => {
+                        if !waiting {
+                            log::info!("Standing by: {}", e);
+                            waiting = true;
+                        }
+                    }
+                    _ => return Err(e),
+                },
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Refresh the heartbeat, failing if another daemon took the lease
+    pub fn renew(&self) -> Result<()> {
+        if let Some(lease) = self.read()? {
+            if lease.owner != self.owner {
+                return Err(FailoverError::LeaseLost(lease.owner).into());
+            }
+        }
+
+        self.write()
+    }
+
+    /// Heartbeat every `interval` until the lease is lost
+    ///
+    /// Failed heartbeats are retried until none has succeeded for the failover
+    /// timeout, after which a standby may hold the lease. Returns why the lease was
+    /// given up.
+    pub async fn hold(&self, interval: Duration) -> anyhow::Error {
+        let mut interval = tokio::time::interval(interval);
+        let mut last_renewed = tokio::time::Instant::now();
+        loop {
+            interval.tick().await;
+
+            match self.renew() {
+                Ok(()) => last_renewed = tokio::time::Instant::now(),
+                Err(e) => {
+                    if e.downcast_ref::<FailoverError>().is_some() {
+                        return e;
+                    }
+                    if last_renewed.elapsed() > Duration::from_secs(self.timeout) {
+                        return e.context("No heartbeat within the failover timeout");
+                    }
+                    log::warn!("Failed to heartbeat the daemon lease, retrying: {:#}", e);
+                }
+            }
+        }
+    }
+
+    /// Get this daemon's owner ID
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+}
+
+/// Load the identity key of a state directory, creating it on first use
+pub fn load_or_create_identity<P: AsRef<Path>>(state_dir: P) -> Result<String> {
+    let path = state_dir.as_ref().join(IDENTITY_FILE);
+
+    if path.exists() {
+        let key = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read identity key {}", path.display()))?;
+        return Ok(key.trim().to_string());
+    }
+
+    let key = darkswap_sdk::reputation::generate_identity_key()?;
+    fs::write(&path, &key)
+        .with_context(|| format!("Failed to write identity key {}", path.display()))?;
+
+    // The key is the node's identity, so only the daemon user may read it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .context("Failed to restrict identity key permissions")?;
+    }
+
+    Ok(key)
+}
+
+/// Get the current unix time in seconds
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("darkswap-failover-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_live_lease_is_held() {
+        let dir = state_dir();
+        let primary = LeaseLock::new(&dir, 15);
+        let standby = LeaseLock::new(&dir, 15);
+
+        primary.try_acquire().await.unwrap();
+        let err = standby.try_acquire().await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<FailoverError>(), Some(FailoverError::LeaseHeld { .. })));
+
+        // The holder can keep renewing its own lease
+        primary.renew().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_lease_is_taken_over() {
+        let dir = state_dir();
+        let primary = LeaseLock::new(&dir, 15);
+        let standby = LeaseLock::new(&dir, 15);
+
+        // The primary stopped heartbeating a minute ago
+        let stale = Lease { owner: primary.owner.clone(), pid: 1, heartbeat: now() - 60 };
+        fs::write(dir.join(LEASE_FILE), serde_json::to_string(&stale).unwrap()).unwrap();
+
+        standby.try_acquire().await.unwrap();
+        let err = primary.renew().unwrap_err();
+        assert!(matches!(err.downcast_ref::<FailoverError>(), Some(FailoverError::LeaseLost(owner)) if owner == standby.owner()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_lease_counts_as_free() {
+        let dir = state_dir();
+        fs::write(dir.join(LEASE_FILE), "{\"owner\":").unwrap();
+
+        let lease = LeaseLock::new(&dir, 15);
+        lease.try_acquire().await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_hold_gives_up_on_takeover() {
+        let dir = state_dir();
+        let primary = LeaseLock::new(&dir, 15);
+        primary.try_acquire().await.unwrap();
+
+        let other = Lease { owner: "standby".to_string(), pid: 1, heartbeat: now() };
+        fs::write(dir.join(LEASE_FILE), serde_json::to_string(&other).unwrap()).unwrap();
+
+        let err = primary.hold(Duration::from_millis(10)).await;
+        assert!(matches!(err.downcast_ref::<FailoverError>(), Some(FailoverError::LeaseLost(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
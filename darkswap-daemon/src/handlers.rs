@@ -108,11 +108,27 @@ async fn handle_socket(socket: WebSocket, state: Arc<ApiState>) {
                 darkswap_sdk::types::Event::TradeCompleted(_) => "trade_completed",
                 darkswap_sdk::types::Event::TradeFailed(_) => "trade_failed",
                 darkswap_sdk::types::Event::TradeCreated(_) => "trade_created",
+                darkswap_sdk::types::Event::TradeProposalReceived(_) => "trade_proposal_received",
                 darkswap_sdk::types::Event::TradeUpdated(_) => "trade_updated",
                 darkswap_sdk::types::Event::TradeCancelled(_) => "trade_cancelled",
                 darkswap_sdk::types::Event::TradeExpired(_) => "trade_expired",
+                darkswap_sdk::types::Event::RevealRequested(_) => "reveal_requested",
+                darkswap_sdk::types::Event::OrderRevealed(_) => "order_revealed",
+                darkswap_sdk::types::Event::RefundBroadcast(_) => "refund_broadcast",
+                darkswap_sdk::types::Event::QuoteRequestReceived(_) => "quote_request_received",
+                darkswap_sdk::types::Event::QuoteReceived(_) => "quote_received",
                 darkswap_sdk::types::Event::PeerConnected(_) => "peer_connected",
                 darkswap_sdk::types::Event::PeerDisconnected(_) => "peer_disconnected",
+                darkswap_sdk::types::Event::LowFeeReserve(_) => "low_fee_reserve",
+                darkswap_sdk::types::Event::NewOrderFromWatchedMaker(_) => "new_order_from_watched_maker",
+                darkswap_sdk::types::Event::PriceCrossedThreshold(_) => "price_crossed_threshold",
+                darkswap_sdk::types::Event::NetworkSwitched(_) => "network_switched",
+                darkswap_sdk::types::Event::LaggedEvents(_) => "lagged_events",
+                darkswap_sdk::types::Event::ChainBackend(_) => "chain_backend",
+                darkswap_sdk::types::Event::TradeMessageUndelivered(_) => "trade_message_undelivered",
+                darkswap_sdk::types::Event::BalanceChanged(_) => "balance_changed",
+                darkswap_sdk::types::Event::DepositDetected(_) => "deposit_detected",
+                darkswap_sdk::types::Event::DepositConfirmed(_) => "deposit_confirmed",
             };
 
             // Serialize event data
@@ -2,18 +2,28 @@
 
 mod types;
 mod api;
+mod assets;
+mod browser;
+mod failover;
 mod handlers;
+mod notify;
+mod rate_limit;
+mod service;
+mod settlements;
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::Router;
 use clap::Parser;
 use tokio::sync::{Mutex, mpsc};
 use tower_http::trace::TraceLayer;
 
-use darkswap_sdk::{DarkSwap, types::Event};
+use darkswap_sdk::{DarkSwap, rules::SignedMarketRules, secrets::SecretResolver, types::Event};
 use api::{ApiState, create_router};
+use failover::LeaseLock;
 
 /// DarkSwap daemon
 #[derive(Parser, Debug)]
@@ -22,6 +32,64 @@ struct Args {
     /// Listen address
     #[arg(short, long, default_value = "127.0.0.1:3000")]
     addr: String,
+
+    /// SDK configuration file (JSON); defaults are used if unset
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// File to persist known peers to
+    #[arg(long)]
+    peer_store: Option<String>,
+
+    /// Forget all known peers before starting
+    #[arg(long)]
+    clear_peer_store: bool,
+
+    /// Directory with the node identity, peers, open orders and failover lease,
+    /// shared between a primary and a standby daemon
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+
+    /// Wait as a cold standby until the daemon holding the lease stops heartbeating
+    #[arg(long, requires = "state_dir")]
+    standby: bool,
+
+    /// Lease heartbeat interval (seconds)
+    #[arg(long, default_value_t = 5)]
+    heartbeat_interval: u64,
+
+    /// Heartbeat age after which a standby takes over (seconds)
+    #[arg(long, default_value_t = 15)]
+    failover_timeout: u64,
+
+    /// API rate limit tiers and keys (JSON); requests are not limited if unset
+    #[arg(long)]
+    rate_limits: Option<PathBuf>,
+
+    /// Browser mode configuration (JSON): strict CORS origins, cookie sessions and CSRF tokens
+    #[arg(long)]
+    browser_mode: Option<PathBuf>,
+
+    /// Notification channels and rules (TOML) for trade and balance alerts
+    #[arg(long)]
+    notify: Option<PathBuf>,
+
+    /// Signed market rules (JSON) to publish at `/market/rules`, made with `darkswap-cli sign-rules`
+    #[arg(long)]
+    market_rules: Option<PathBuf>,
+
+    /// Asset metadata provider and cache limits (JSON) for the icons and descriptions served at `/assets`
+    #[arg(long)]
+    asset_meta: Option<PathBuf>,
+
+    /// Daemon to push the receipts of completed trades to, e.g. a back-office daemon at http://10.0.0.5:3000
+    #[arg(long)]
+    settlement_sink: Option<String>,
+
+    /// Key shared by the desk's nodes: sent with pushes to the sink, and lets pushes carrying it
+    /// record receipts signed by one party; accepts "keyring:" references
+    #[arg(long)]
+    settlement_key: Option<String>,
 }
 
 #[tokio::main]
@@ -32,8 +100,75 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse arguments
     let args = Args::parse();
 
+    // Take the failover lease before touching the shared state
+    let heartbeat_interval = Duration::from_secs(args.heartbeat_interval.max(1));
+    let lease = match &args.state_dir {
+        Some(state_dir) => {
+            std::fs::create_dir_all(state_dir)?;
+            let lease = LeaseLock::new(state_dir, args.failover_timeout);
+            
+            let acquired = if args.standby {
+                lease.acquire(heartbeat_interval).await
+            } else {
+                lease.try_acquire().await
+            };
+            acquired.map_err(|e| {
+                log::error!("Failed to take the daemon lease: {} (start with --standby to wait for it)", e);
+                Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+            })?;
+            log::info!("Holding the daemon lease as {}", lease.owner());
+            
+            Some(Arc::new(lease))
+        }
+        None => None,
+    };
+
     // Initialize DarkSwap
-    let config = darkswap_sdk::config::Config::default();
+    let mut config = match &args.config {
+        Some(path) => darkswap_sdk::config::Config::from_file(path).map_err(|e| {
+            log::error!("Failed to load configuration: {}", e);
+            Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+        })?,
+        None => darkswap_sdk::config::Config::default(),
+    };
+    if args.peer_store.is_some() {
+        config.p2p.peer_store_path = args.peer_store.clone();
+    }
+    if let Some(state_dir) = &args.state_dir {
+        let identity_key = failover::load_or_create_identity(state_dir).map_err(|e| {
+            log::error!("Failed to load node identity: {}", e);
+            Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+        })?;
+        config.p2p.identity_key = Some(identity_key);
+        if config.p2p.peer_store_path.is_none() {
+            config.p2p.peer_store_path = Some(state_dir.join(failover::PEER_STORE_FILE).to_string_lossy().into_owned());
+        }
+        if config.p2p.relay_store_path.is_none() {
+            config.p2p.relay_store_path = Some(state_dir.join(failover::RELAY_STORE_FILE).to_string_lossy().into_owned());
+        }
+        config.orderbook.order_store_path = Some(state_dir.join(failover::ORDER_STORE_FILE).to_string_lossy().into_owned());
+        if config.orderbook.schedule_store_path.is_none() {
+            config.orderbook.schedule_store_path = Some(state_dir.join(failover::SCHEDULE_STORE_FILE).to_string_lossy().into_owned());
+        }
+        if config.orderbook.iceberg_store_path.is_none() {
+            config.orderbook.iceberg_store_path = Some(state_dir.join(failover::ICEBERG_STORE_FILE).to_string_lossy().into_owned());
+        }
+        if config.trade.refund_store_path.is_none() {
+            config.trade.refund_store_path = Some(state_dir.join(failover::REFUND_STORE_FILE).to_string_lossy().into_owned());
+        }
+    }
+
+    // Clear the peer store before the network loads it
+    if args.clear_peer_store {
+        if let Some(path) = &config.p2p.peer_store_path {
+            darkswap_sdk::p2p::peer_store::PeerStore::remove_file(path).map_err(|e| {
+                log::error!("Failed to clear peer store: {}", e);
+                Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+            })?;
+            log::info!("Cleared peer store {}", path);
+        }
+    }
+    let asset_overrides = config.assets.clone();
     let mut darkswap = DarkSwap::new(config).map_err(|e| {
         log::error!("Failed to initialize DarkSwap: {}", e);
         Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
@@ -45,15 +180,136 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
     })?;
 
+    // Load the API rate limits
+    let rate_limiter = match &args.rate_limits {
+        Some(path) => {
+            let config = rate_limit::RateLimitConfig::from_file(path).map_err(|e| {
+                log::error!("Failed to load rate limits: {}", e);
+                Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+            })?;
+            Some(Arc::new(rate_limit::RateLimiter::new(config)))
+        }
+        None => None,
+    };
+
+    // Load the browser mode configuration
+    let browser = match &args.browser_mode {
+        Some(path) => {
+            let auth = browser::BrowserConfig::from_file(path)
+                .and_then(browser::BrowserAuth::new)
+                .map_err(|e| {
+                    log::error!("Failed to set up browser mode: {}", e);
+                    Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+                })?;
+            Some(Arc::new(auth))
+        }
+        None => None,
+    };
+
+    // Load the market rules, refusing a document that does not verify
+    let market_rules = match &args.market_rules {
+        Some(path) => {
+            let market_rules = SignedMarketRules::from_file(path).map_err(|e| {
+                log::error!("Failed to load market rules: {}", e);
+                Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+            })?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            if market_rules.rules.is_expired(now) {
+                log::warn!("Publishing expired market rules (sequence {})", market_rules.rules.sequence);
+            }
+            Some(Arc::new(market_rules))
+        }
+        None => None,
+    };
+
+    // Set up the asset metadata cache, which also serves the configured overrides without a provider
+    let asset_meta_config = match &args.asset_meta {
+        Some(path) => Some(assets::AssetMetaConfig::from_file(path)),
+        None if !asset_overrides.is_empty() => Some(Ok(assets::AssetMetaConfig::default())),
+        None => None,
+    };
+    let asset_meta = match asset_meta_config {
+        Some(config) => {
+            let service = config
+                .and_then(assets::AssetMetaService::new)
+                .map_err(|e| {
+                    log::error!("Failed to set up asset metadata: {}", e);
+                    Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+                })?
+                .with_overrides(asset_overrides);
+            Some(Arc::new(service))
+        }
+        None => None,
+    };
+
     // Create event channel
     let (event_sender, mut event_receiver) = mpsc::channel::<Event>(100);
 
+    // Keep the settlement receipts pushed by connected nodes next to the node's own records
+    let settlement_key = match &args.settlement_key {
+        Some(key) => Some(SecretResolver::new().resolve(key).map_err(|e| {
+            log::error!("Failed to resolve settlement key: {}", e);
+            Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+        })?),
+        None => None,
+    };
+    let settlements = Arc::new(
+        settlements::SettlementLedger::new(darkswap.storage()).with_api_key(settlement_key.clone()),
+    );
+
     // Create API state
     let api_state = Arc::new(ApiState {
         darkswap: Arc::new(Mutex::new(darkswap)),
         event_sender: event_sender.clone(),
+        rate_limiter,
+        browser,
+        market_rules,
+        asset_meta,
+        settlements,
     });
 
+    // Push the receipts of own trades to the back office
+    if let Some(url) = &args.settlement_sink {
+        settlements::spawn_sink(url.clone(), settlement_key, api_state.darkswap.clone());
+    }
+
+    // Deliver trade and balance alerts out-of-band
+    if let Some(path) = &args.notify {
+        let notifier = notify::NotifyConfig::from_file(path)
+            .and_then(|config| notify::Notifier::from_config(&config))
+            .map_err(|e| {
+                log::error!("Failed to set up notifications: {}", e);
+                Box::new(std::io::Error::new(std::io::ErrorKind::Other, e.to_string())) as Box<dyn std::error::Error>
+            })?;
+        Arc::new(notifier).spawn(api_state.darkswap.clone());
+    }
+
+    // Heartbeat the lease and keep the open orders on disk for a standby
+    if let Some(lease) = lease {
+        // The heartbeat never waits for the node, so a busy node does not lose its lease
+        tokio::spawn(async move {
+            let e = lease.hold(heartbeat_interval).await;
+            // Another daemon may own the identity now; announcing orders twice would be worse than exiting
+            log::error!("Lost the daemon lease: {:#}", e);
+            std::process::exit(1);
+        });
+        
+        let darkswap = api_state.darkswap.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(heartbeat_interval);
+            loop {
+                interval.tick().await;
+                
+                if let Err(e) = darkswap.lock().await.save_own_orders().await {
+                    log::warn!("Failed to persist orders: {}", e);
+                }
+            }
+        });
+    }
+
     // Create router
     let app = create_router(api_state.clone())
         .layer(TraceLayer::new_for_http());
@@ -140,8 +396,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Start server
     log::info!("Starting server on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    let server = axum::Server::bind(&addr);
+
+    // Report readiness and liveness to systemd, if it started the daemon
+    service::spawn_readiness(api_state.darkswap.clone(), addr);
+    if let Some(timeout) = darkswap_sdk::systemd::watchdog_timeout() {
+        log::info!("Pinging the systemd watchdog every {:?}", timeout / 2);
+        service::spawn_watchdog(addr, timeout);
+    }
+
+    server
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
 
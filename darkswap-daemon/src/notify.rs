@@ -0,0 +1,543 @@
+//! Out-of-band notifications for unattended maker nodes
+//!
+//! The notifier watches the daemon's events and wallet balance and delivers
+//! alerts through the channels configured in a TOML file:
+//!
+//! ```toml
+//! [rules]
+//! trade_completed = true
+//! trade_failed = true
+//! large_fill = "0.5"      # alert on trades of at least this amount
+//! low_balance = 100000    # alert when the balance drops below this many sats
+//! watchlist = true        # alert on followed makers' orders and pair price crossings
+//!
+//! [[channels]]
+//! type = "slack"
+//! webhook_url = "https://hooks.slack.com/services/..."
+//! ```
+//!
+//! Channels are plugins implementing [`NotificationChannel`]; email, Telegram and
+//! Slack are built in. A failing channel is logged and does not hold up the others.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use darkswap_sdk::{trade::Trade, types::Event, DarkSwap};
+use lettre::{
+    message::Mailbox,
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+/// Notifier configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// What to notify about
+    #[serde(default)]
+    pub rules: NotifyRules,
+    /// Where to deliver notifications
+    pub channels: Vec<ChannelConfig>,
+}
+
+impl NotifyConfig {
+    /// Load the configuration from a TOML file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read notifier configuration {}", path.display()))?;
+        toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse notifier configuration {}", path.display()))
+    }
+}
+
+/// Notification rules
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotifyRules {
+    /// Notify about completed trades
+    pub trade_completed: bool,
+    /// Notify about failed trades
+    pub trade_failed: bool,
+    /// Notify about completed trades of at least this amount
+    pub large_fill: Option<Decimal>,
+    /// Notify when the wallet balance drops below this many sats
+    pub low_balance: Option<u64>,
+    /// Notify when uncommitted bitcoin drops below the SDK's fee reserve
+    pub low_fee_reserve: bool,
+    /// Notify about new orders of followed makers and price crossings of followed pairs
+    pub watchlist: bool,
+    /// How often the balance is checked (seconds)
+    pub balance_interval: u64,
+}
+
+impl Default for NotifyRules {
+    fn default() -> Self {
+        Self {
+            trade_completed: true,
+            trade_failed: true,
+            large_fill: None,
+            low_balance: None,
+            low_fee_reserve: true,
+            watchlist: false,
+            balance_interval: 300, // 5 minutes
+        }
+    }
+}
+
+impl NotifyRules {
+    /// Get the notifications about a finished trade
+    pub fn trade_notifications(&self, trade: &Trade, completed: bool) -> Vec<Notification> {
+        let summary = format!(
+            "{} {} at {} {}/{}",
+            trade.id, trade.amount, trade.price, trade.base_asset, trade.quote_asset,
+        );
+        let mut notifications = Vec::new();
+
+        if completed {
+            if self.trade_completed {
+                notifications.push(Notification::new("Trade completed", summary.clone()));
+            }
+            if self.large_fill.map_or(false, |threshold| trade.amount >= threshold) {
+                notifications.push(Notification::new("Large fill", summary));
+            }
+        } else if self.trade_failed {
+            notifications.push(Notification::new("Trade failed", summary));
+        }
+
+        notifications
+    }
+}
+
+/// Channel configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelConfig {
+    /// Email over SMTP with STARTTLS or TLS
+    Email {
+        /// SMTP server
+        smtp_host: String,
+        /// SMTP port; the server's default TLS port if unset
+        smtp_port: Option<u16>,
+        /// SMTP user
+        username: String,
+        /// SMTP password
+        password: String,
+        /// Sender address
+        from: String,
+        /// Recipient addresses
+        to: Vec<String>,
+    },
+    /// Telegram bot message
+    Telegram {
+        /// Bot token
+        bot_token: String,
+        /// Chat to post to
+        chat_id: String,
+    },
+    /// Slack incoming webhook
+    Slack {
+        /// Webhook URL
+        webhook_url: String,
+    },
+}
+
+impl ChannelConfig {
+    /// Create the channel
+    pub fn build(&self, client: &reqwest::Client) -> Result<Box<dyn NotificationChannel>> {
+        Ok(match self {
+            ChannelConfig::Email { smtp_host, smtp_port, username, password, from, to } => {
+                Box::new(EmailChannel::new(smtp_host, *smtp_port, username, password, from, to)?)
+            }
+            ChannelConfig::Telegram { bot_token, chat_id } => Box::new(TelegramChannel {
+                client: client.clone(),
+                bot_token: bot_token.clone(),
+                chat_id: chat_id.clone(),
+            }),
+            ChannelConfig::Slack { webhook_url } => Box::new(SlackChannel {
+                client: client.clone(),
+                webhook_url: webhook_url.clone(),
+            }),
+        })
+    }
+}
+
+/// Notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Title
+    pub title: String,
+    /// Body
+    pub body: String,
+}
+
+impl Notification {
+    /// Create a notification
+    pub fn new(title: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            body: body.into(),
+        }
+    }
+}
+
+/// Notification channel plugin
+#[async_trait]
+pub trait NotificationChannel: Send + Sync {
+    /// Channel name, for logs
+    fn name(&self) -> &str;
+
+    /// Deliver a notification
+    async fn send(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Email channel
+pub struct EmailChannel {
+    /// SMTP transport
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    /// Sender
+    from: Mailbox,
+    /// Recipients
+    to: Vec<Mailbox>,
+}
+
+impl EmailChannel {
+    /// Create an email channel
+    pub fn new(smtp_host: &str, smtp_port: Option<u16>, username: &str, password: &str, from: &str, to: &[String]) -> Result<Self> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::relay(smtp_host)
+            .with_context(|| format!("Invalid SMTP server {}", smtp_host))?
+            .credentials(Credentials::new(username.to_string(), password.to_string()));
+        if let Some(port) = smtp_port {
+            transport = transport.port(port);
+        }
+
+        let to = to.iter()
+            .map(|address| address.parse().with_context(|| format!("Invalid recipient {}", address)))
+            .collect::<Result<Vec<Mailbox>>>()?;
+        if to.is_empty() {
+            anyhow::bail!("Email channel has no recipients");
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            from: from.parse().with_context(|| format!("Invalid sender {}", from))?,
+            to,
+        })
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for EmailChannel {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(format!("DarkSwap: {}", notification.title));
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+
+        self.transport.send(message.body(notification.body.clone())?).await?;
+        Ok(())
+    }
+}
+
+/// Telegram channel
+pub struct TelegramChannel {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Bot token
+    bot_token: String,
+    /// Chat to post to
+    chat_id: String,
+}
+
+#[async_trait]
+impl NotificationChannel for TelegramChannel {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        self.client
+            .post(format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token))
+            .json(&serde_json::json!({
+                "chat_id": self.chat_id,
+                "text": format!("{}\n{}", notification.title, notification.body),
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Slack channel
+pub struct SlackChannel {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Webhook URL
+    webhook_url: String,
+}
+
+#[async_trait]
+impl NotificationChannel for SlackChannel {
+    fn name(&self) -> &str {
+        "slack"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        self.client
+            .post(&self.webhook_url)
+            .json(&serde_json::json!({
+                "text": format!("*{}*\n{}", notification.title, notification.body),
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Low balance alarm, raised once per drop below the threshold
+#[derive(Debug)]
+pub struct LowBalanceAlarm {
+    /// Threshold (sats)
+    threshold: u64,
+    /// Whether the balance is below the threshold
+    raised: bool,
+}
+
+impl LowBalanceAlarm {
+    /// Create an alarm
+    pub fn new(threshold: u64) -> Self {
+        Self { threshold, raised: false }
+    }
+
+    /// Check a balance, returning a notification when it first drops below the threshold
+    pub fn check(&mut self, balance: u64) -> Option<Notification> {
+        let low = balance < self.threshold;
+        let notify = low && !self.raised;
+        self.raised = low;
+
+        notify.then(|| Notification::new(
+            "Low balance",
+            format!("Wallet balance is {} sats, below the {} sats threshold", balance, self.threshold),
+        ))
+    }
+}
+
+/// Notifier delivering to all configured channels
+pub struct Notifier {
+    /// Rules
+    rules: NotifyRules,
+    /// Channels
+    channels: Vec<Box<dyn NotificationChannel>>,
+}
+
+impl Notifier {
+    /// Create a notifier from its configuration
+    pub fn from_config(config: &NotifyConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("Failed to create HTTP client")?;
+        let channels = config.channels.iter()
+            .map(|channel| channel.build(&client))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self::new(config.rules.clone(), channels))
+    }
+
+    /// Create a notifier with the given channels
+    pub fn new(rules: NotifyRules, channels: Vec<Box<dyn NotificationChannel>>) -> Self {
+        Self { rules, channels }
+    }
+
+    /// Deliver a notification to every channel
+    pub async fn notify(&self, notification: &Notification) {
+        for channel in &self.channels {
+            if let Err(e) = channel.send(notification).await {
+                log::warn!("Failed to send {} notification: {}", channel.name(), e);
+            }
+        }
+    }
+
+    /// Spawn the tasks watching trade events and the wallet balance
+    pub fn spawn(self: Arc<Self>, darkswap: Arc<Mutex<DarkSwap>>) -> Vec<tokio::task::JoinHandle<()>> {
+        let mut tasks = Vec::new();
+
+        let notifier = self.clone();
+        let events_darkswap = darkswap.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut receiver = events_darkswap.lock().await.subscribe_to_events().await;
+
+            while let Some(event) = receiver.recv().await {
+                let (trade_id, completed) = match &event {
+                    Event::TradeCompleted(trade_id) => (trade_id, true),
+                    Event::TradeFailed(trade_id) => (trade_id, false),
+                    Event::LowFeeReserve(status) => {
+                        if notifier.rules.low_fee_reserve {
+                            notifier.notify(&Notification::new(
+                                "Low fee reserve",
+                                format!(
+                                    "{} sats left uncommitted, {} sats are reserved for the fees of in-flight trades",
+                                    status.spare(), status.required,
+                                ),
+                            )).await;
+                        }
+                        continue;
+                    }
+                    Event::NewOrderFromWatchedMaker(order) => {
+                        if notifier.rules.watchlist {
+                            notifier.notify(&Notification::new(
+                                "New order from followed maker",
+                                format!(
+                                    "{} {:?} {} {}/{} at {}",
+                                    order.maker, order.side, order.amount, order.base_asset, order.quote_asset, order.price,
+                                ),
+                            )).await;
+                        }
+                        continue;
+                    }
+                    Event::PriceCrossedThreshold(crossing) => {
+                        if notifier.rules.watchlist {
+                            notifier.notify(&Notification::new(
+                                "Price threshold crossed",
+                                format!(
+                                    "{}/{} moved {:?} through {}: {} -> {}",
+                                    crossing.base_asset, crossing.quote_asset, crossing.direction,
+                                    crossing.threshold, crossing.previous_price, crossing.price,
+                                ),
+                            )).await;
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+
+                let trade = events_darkswap.lock().await.get_trade(trade_id).await;
+                match trade {
+                    Ok(trade) => {
+                        for notification in notifier.rules.trade_notifications(&trade, completed) {
+                            notifier.notify(&notification).await;
+                        }
+                    }
+                    Err(e) => log::warn!("Failed to get trade {} to notify about: {}", trade_id, e),
+                }
+            }
+        }));
+
+        if self.rules.low_balance.is_some() || self.rules.low_fee_reserve {
+            let notifier = self.clone();
+            tasks.push(tokio::spawn(async move {
+                let mut alarm = notifier.rules.low_balance.map(LowBalanceAlarm::new);
+                let mut interval = tokio::time::interval(Duration::from_secs(notifier.rules.balance_interval.max(1)));
+
+                loop {
+                    interval.tick().await;
+
+                    if let Some(alarm) = alarm.as_mut() {
+                        let balance = darkswap.lock().await.get_balance().await;
+                        match balance {
+                            Ok(balance) => {
+                                if let Some(notification) = alarm.check(balance) {
+                                    notifier.notify(&notification).await;
+                                }
+                            }
+                            Err(e) => log::warn!("Failed to check balance: {}", e),
+                        }
+                    }
+
+                    // Raises LowFeeReserve, notified above, when the reserve is first breached
+                    if notifier.rules.low_fee_reserve {
+                        let _ = darkswap.lock().await.get_fee_reserve_status().await;
+                    }
+                }
+            }));
+        }
+
+        tasks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkswap_sdk::{orderbook::OrderId, types::Asset};
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn trade(amount: Decimal) -> Trade {
+        Trade::new(
+            OrderId("order-1".to_string()),
+            String::new(),
+            String::new(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            amount,
+            dec("100"),
+            None,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_config_from_toml() {
+        let config: NotifyConfig = toml::from_str(r#"
+            [rules]
+            trade_completed = false
+            large_fill = "0.5"
+
+            [[channels]]
+            type = "telegram"
+            bot_token = "123:abc"
+            chat_id = "42"
+
+            [[channels]]
+            type = "slack"
+            webhook_url = "https://hooks.slack.com/services/x"
+        "#).unwrap();
+
+        assert!(!config.rules.trade_completed);
+        assert!(config.rules.trade_failed);
+        assert_eq!(config.rules.large_fill, Some(dec("0.5")));
+        assert_eq!(config.channels.len(), 2);
+    }
+
+    #[test]
+    fn test_trade_notifications() {
+        let rules = NotifyRules {
+            large_fill: Some(dec("1")),
+            ..NotifyRules::default()
+        };
+
+        let titles = |trade, completed| -> Vec<String> {
+            rules.trade_notifications(&trade, completed).into_iter().map(|n| n.title).collect()
+        };
+        assert_eq!(titles(trade(dec("0.1")), true), vec!["Trade completed"]);
+        assert_eq!(titles(trade(dec("2")), true), vec!["Trade completed", "Large fill"]);
+        assert_eq!(titles(trade(dec("2")), false), vec!["Trade failed"]);
+    }
+
+    #[test]
+    fn test_low_balance_alarm_fires_once_per_drop() {
+        let mut alarm = LowBalanceAlarm::new(1_000);
+
+        assert!(alarm.check(5_000).is_none());
+        assert!(alarm.check(500).is_some());
+        assert!(alarm.check(400).is_none());
+        assert!(alarm.check(2_000).is_none());
+        assert!(alarm.check(900).is_some());
+    }
+}
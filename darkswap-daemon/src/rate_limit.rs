@@ -0,0 +1,680 @@
+//! Tiered rate limiting of the REST API
+//!
+//! Clients are limited per API key, sent in the `X-API-Key` header, or per IP
+//! address if they send none. Each key belongs to a tier, which has a quota for
+//! market data and one for trading requests. A tier with a zero trading quota
+//! gives read-only access, so a daemon can serve market data to the public while
+//! trading stays with the holders of a key.
+//!
+//! Every limited response carries `X-RateLimit-Limit`, `X-RateLimit-Remaining`
+//! and `X-RateLimit-Reset` headers; a request over the quota gets `429 Too Many
+//! Requests` with a `Retry-After` header.
+//!
+//! Each order placed is announced to the whole network, so a tier can further
+//! cap order placement with a burst quota over a short window and a sustained
+//! one over a long window. A bot stuck in a loop then floods neither the
+//! network nor the peers' books, and `GET /limits` lets a well-behaved bot pace
+//! itself before it hits either.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::api::{ApiError, ApiState};
+
+/// Header carrying the API key
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Number of tracked clients above which expired windows are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Check if a request places an order
+pub fn is_order_placement(method: &Method, path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    method == Method::POST && matches!(segments.as_slice(), ["orders"] | ["groups", _, "orders"])
+}
+
+/// Route group with its own quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteGroup {
+    /// Public market data: orders, market data, assets and their metadata, and federated orderbooks
+    MarketData,
+    /// Everything else: creating and taking orders, trades, wallet and events
+    Trading,
+}
+
+impl RouteGroup {
+    /// Get the group of a request
+    pub fn of(method: &Method, path: &str) -> Self {
+        let section = path.trim_start_matches('/').split('/').next().unwrap_or("");
+
+        match (method, section) {
+            (&Method::GET, "health" | "limits" | "orders" | "market" | "markets" | "runes" | "alkanes" | "assets" | "federation") => RouteGroup::MarketData,
+            _ => RouteGroup::Trading,
+        }
+    }
+}
+
+/// Requests allowed per window
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Quota {
+    /// Requests per window; zero closes the route group to the tier
+    pub requests: u32,
+    /// Window (seconds)
+    pub window: u64,
+}
+
+/// Order placement quotas, on top of the trading quota
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PlacementQuota {
+    /// Orders per short window
+    pub burst: Quota,
+    /// Orders per long window
+    pub sustained: Quota,
+}
+
+/// Order placement quota a request ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementLimit {
+    /// Burst quota
+    Burst,
+    /// Sustained quota
+    Sustained,
+}
+
+/// Quotas of a tier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tier {
+    /// Market data quota
+    pub market_data: Quota,
+    /// Trading quota
+    pub trading: Quota,
+    /// Order placement quotas; placement is only bound by the trading quota if unset
+    #[serde(default)]
+    pub order_placement: Option<PlacementQuota>,
+}
+
+impl Tier {
+    /// Get the quota of a route group
+    fn quota(&self, group: RouteGroup) -> Quota {
+        match group {
+            RouteGroup::MarketData => self.market_data,
+            RouteGroup::Trading => self.trading,
+        }
+    }
+}
+
+/// Rate limit configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Tiers by name
+    pub tiers: HashMap<String, Tier>,
+    /// Tier of each API key
+    pub keys: HashMap<String, String>,
+    /// Tier of requests without an API key; they are rejected if unset
+    pub anonymous: Option<String>,
+}
+
+impl RateLimitConfig {
+    /// Load the configuration from a JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rate limit configuration {}", path.display()))?;
+        let config: Self = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse rate limit configuration {}", path.display()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Check that every key and the anonymous access name a known tier
+    pub fn validate(&self) -> Result<()> {
+        for tier in self.keys.values().chain(self.anonymous.iter()) {
+            if !self.tiers.contains_key(tier) {
+                anyhow::bail!("Unknown rate limit tier: {}", tier);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Quota usage after a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    /// Requests per window
+    pub limit: u32,
+    /// Requests left in the current window
+    pub remaining: u32,
+    /// Time until the window resets (seconds)
+    pub reset: u64,
+}
+
+/// Rate limit error
+#[derive(Debug, Error)]
+pub enum RateLimitError {
+    /// The API key is not configured
+    #[error("Unknown API key")]
+    UnknownKey,
+    /// Requests without an API key are not served
+    #[error("An API key is required")]
+    KeyRequired,
+    /// The tier has no access to the route group
+    #[error("The {tier} tier has no {group:?} access")]
+    Closed {
+        /// Tier
+        tier: String,
+        /// Route group
+        group: RouteGroup,
+    },
+    /// The quota of the current window is used up
+    #[error("Rate limit exceeded, retry in {} seconds", .0.reset)]
+    Exceeded(Usage),
+    /// An order placement quota of the current window is used up
+    #[error("Order placement {limit:?} quota of {} orders exceeded, retry in {} seconds", .usage.limit, .usage.reset)]
+    PlacementExceeded {
+        /// Quota
+        limit: PlacementLimit,
+        /// Usage of the quota
+        usage: Usage,
+    },
+}
+
+/// Order placement usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PlacementUsage {
+    /// Burst quota usage
+    pub burst: Usage,
+    /// Sustained quota usage
+    pub sustained: Usage,
+}
+
+/// Quotas left to a client, as reported by `GET /limits`
+#[derive(Debug, Clone, Serialize)]
+pub struct Limits {
+    /// Tier of the client
+    pub tier: String,
+    /// Market data quota usage; absent if the tier has no market data access
+    pub market_data: Option<Usage>,
+    /// Trading quota usage; absent if the tier has no trading access
+    pub trading: Option<Usage>,
+    /// Order placement quota usage; absent if the tier has no placement quotas
+    pub order_placement: Option<PlacementUsage>,
+}
+
+/// Body of a response refused by an order placement quota
+#[derive(Debug, Clone, Serialize)]
+struct PlacementErrorBody {
+    /// Error message
+    message: String,
+    /// Error code
+    code: u16,
+    /// Quota the order ran into
+    limit: PlacementLimit,
+    /// Orders allowed per window of that quota
+    quota: u32,
+    /// Window of that quota (seconds)
+    window: u64,
+    /// Time until an order is allowed again (seconds)
+    retry_after: u64,
+}
+
+/// Requests of a client in the current window
+#[derive(Debug, Clone)]
+struct Window {
+    /// Window start
+    start: Instant,
+    /// Requests made
+    requests: u32,
+}
+
+impl Window {
+    /// Create a window starting at `now`
+    fn new(now: Instant) -> Self {
+        Self { start: now, requests: 0 }
+    }
+
+    /// Start a new window at `now` if the current one is over, returning the usage of `quota`
+    fn roll(&mut self, quota: Quota, now: Instant) -> Usage {
+        let window_length = Duration::from_secs(quota.window.max(1));
+        if now.duration_since(self.start) >= window_length {
+            self.start = now;
+            self.requests = 0;
+        }
+
+        let elapsed = now.duration_since(self.start);
+        Usage {
+            limit: quota.requests,
+            remaining: quota.requests.saturating_sub(self.requests),
+            // Round up, so clients retrying after `reset` seconds find a new window
+            reset: (window_length - elapsed).as_secs_f64().ceil() as u64,
+        }
+    }
+}
+
+/// Order placement windows of a client
+#[derive(Debug)]
+struct PlacementWindows {
+    /// Burst window
+    burst: Window,
+    /// Sustained window
+    sustained: Window,
+}
+
+/// Tiered rate limiter
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Configuration
+    config: RateLimitConfig,
+    /// Longest window of any quota
+    longest_window: Duration,
+    /// Windows by client and route group
+    windows: Mutex<HashMap<(String, RouteGroup), Window>>,
+    /// Order placement windows by client
+    placements: Mutex<HashMap<String, PlacementWindows>>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter
+    pub fn new(config: RateLimitConfig) -> Self {
+        let longest_window = config.tiers.values()
+            .flat_map(|tier| {
+                let placement = tier.order_placement.map(|placement| placement.burst.window.max(placement.sustained.window));
+                [tier.market_data.window, tier.trading.window, placement.unwrap_or(0)]
+            })
+            .max()
+            .unwrap_or(0);
+
+        Self {
+            config,
+            longest_window: Duration::from_secs(longest_window.max(1)),
+            windows: Mutex::new(HashMap::new()),
+            placements: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Identify a client by its API key or else its address, returning it with its tier
+    fn client(&self, api_key: Option<&str>, address: Option<&str>) -> Result<(String, &String, &Tier), RateLimitError> {
+        let (client, tier_name) = match api_key {
+            Some(key) => {
+                let tier = self.config.keys.get(key).ok_or(RateLimitError::UnknownKey)?;
+                (format!("key:{}", key), tier)
+            }
+            None => {
+                let tier = self.config.anonymous.as_ref().ok_or(RateLimitError::KeyRequired)?;
+                (format!("address:{}", address.unwrap_or("unknown")), tier)
+            }
+        };
+        let tier = self.config.tiers.get(tier_name).ok_or(RateLimitError::UnknownKey)?;
+
+        Ok((client, tier_name, tier))
+    }
+
+    /// Count a request of a client, identified by its API key or else its address
+    pub fn check(&self, api_key: Option<&str>, address: Option<&str>, group: RouteGroup) -> Result<Usage, RateLimitError> {
+        self.check_at(api_key, address, group, Instant::now())
+    }
+
+    /// Count a request at `now`
+    fn check_at(&self, api_key: Option<&str>, address: Option<&str>, group: RouteGroup, now: Instant) -> Result<Usage, RateLimitError> {
+        let (client, tier_name, tier) = self.client(api_key, address)?;
+        let quota = tier.quota(group);
+        if quota.requests == 0 {
+            return Err(RateLimitError::Closed { tier: tier_name.clone(), group });
+        }
+
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        if windows.len() > PRUNE_THRESHOLD {
+            let longest_window = self.longest_window;
+            windows.retain(|_, window| now.duration_since(window.start) < longest_window);
+        }
+
+        let window = windows.entry((client, group)).or_insert_with(|| Window::new(now));
+        let usage = window.roll(quota, now);
+        if usage.remaining == 0 {
+            return Err(RateLimitError::Exceeded(usage));
+        }
+        window.requests += 1;
+
+        Ok(Usage { remaining: usage.remaining - 1, ..usage })
+    }
+
+    /// Count an order placed by a client against the placement quotas of its tier
+    ///
+    /// Returns `None` if the tier has no placement quotas. An order refused by
+    /// one quota is not counted against the other.
+    pub fn check_placement(&self, api_key: Option<&str>, address: Option<&str>) -> Result<Option<PlacementUsage>, RateLimitError> {
+        self.check_placement_at(api_key, address, Instant::now())
+    }
+
+    /// Count an order placed at `now`
+    fn check_placement_at(&self, api_key: Option<&str>, address: Option<&str>, now: Instant) -> Result<Option<PlacementUsage>, RateLimitError> {
+        let (client, _, tier) = self.client(api_key, address)?;
+        let Some(quota) = tier.order_placement else { return Ok(None) };
+
+        let mut placements = self.placements.lock().unwrap_or_else(|e| e.into_inner());
+        if placements.len() > PRUNE_THRESHOLD {
+            let longest_window = self.longest_window;
+            placements.retain(|_, windows| now.duration_since(windows.sustained.start) < longest_window);
+        }
+
+        let windows = placements.entry(client).or_insert_with(|| PlacementWindows {
+            burst: Window::new(now),
+            sustained: Window::new(now),
+        });
+        let burst = windows.burst.roll(quota.burst, now);
+        let sustained = windows.sustained.roll(quota.sustained, now);
+
+        // The sustained quota is reported first, since waiting out the burst would not help
+        if sustained.remaining == 0 {
+            return Err(RateLimitError::PlacementExceeded { limit: PlacementLimit::Sustained, usage: sustained });
+        }
+        if burst.remaining == 0 {
+            return Err(RateLimitError::PlacementExceeded { limit: PlacementLimit::Burst, usage: burst });
+        }
+        windows.burst.requests += 1;
+        windows.sustained.requests += 1;
+
+        Ok(Some(PlacementUsage {
+            burst: Usage { remaining: burst.remaining - 1, ..burst },
+            sustained: Usage { remaining: sustained.remaining - 1, ..sustained },
+        }))
+    }
+
+    /// Get the quotas left to a client, without counting a request
+    pub fn limits(&self, api_key: Option<&str>, address: Option<&str>) -> Result<Limits, RateLimitError> {
+        self.limits_at(api_key, address, Instant::now())
+    }
+
+    /// Get the quotas left to a client at `now`
+    fn limits_at(&self, api_key: Option<&str>, address: Option<&str>, now: Instant) -> Result<Limits, RateLimitError> {
+        let (client, tier_name, tier) = self.client(api_key, address)?;
+
+        let group_usage = |group: RouteGroup| {
+            let quota = tier.quota(group);
+            if quota.requests == 0 {
+                return None;
+            }
+
+            let windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+            let mut window = windows.get(&(client.clone(), group)).cloned().unwrap_or_else(|| Window::new(now));
+            Some(window.roll(quota, now))
+        };
+
+        let order_placement = tier.order_placement.map(|quota| {
+            let placements = self.placements.lock().unwrap_or_else(|e| e.into_inner());
+            let windows = placements.get(&client);
+            let mut burst = windows.map_or_else(|| Window::new(now), |windows| windows.burst.clone());
+            let mut sustained = windows.map_or_else(|| Window::new(now), |windows| windows.sustained.clone());
+
+            PlacementUsage {
+                burst: burst.roll(quota.burst, now),
+                sustained: sustained.roll(quota.sustained, now),
+            }
+        });
+
+        Ok(Limits {
+            tier: tier_name.clone(),
+            market_data: group_usage(RouteGroup::MarketData),
+            trading: group_usage(RouteGroup::Trading),
+            order_placement,
+        })
+    }
+}
+
+/// Middleware enforcing the rate limits of the API state
+pub async fn enforce<B>(
+    State(state): State<Arc<ApiState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let limiter = match &state.rate_limiter {
+        Some(limiter) => limiter.clone(),
+        None => return next.run(request).await,
+    };
+
+    let group = RouteGroup::of(request.method(), request.uri().path());
+    let api_key = request.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let address = request.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+    match limiter.check(api_key.as_deref(), address.as_deref(), group) {
+        Ok(usage) => {
+            if is_order_placement(request.method(), request.uri().path()) {
+                if let Err(e) = limiter.check_placement(api_key.as_deref(), address.as_deref()) {
+                    let mut response = placement_error_response(&limiter, api_key.as_deref(), address.as_deref(), e);
+                    set_usage_headers(response.headers_mut(), &usage);
+                    return response;
+                }
+            }
+
+            let mut response = next.run(request).await;
+            set_usage_headers(response.headers_mut(), &usage);
+            response
+        }
+        Err(RateLimitError::Exceeded(usage)) => {
+            let mut response = ApiError {
+                message: RateLimitError::Exceeded(usage).to_string(),
+                code: StatusCode::TOO_MANY_REQUESTS.as_u16(),
+            }
+            .into_response();
+            set_usage_headers(response.headers_mut(), &usage);
+            response.headers_mut().insert("retry-after", HeaderValue::from(usage.reset));
+            response
+        }
+        Err(e) => {
+            let status = match e {
+                RateLimitError::Closed { .. } => StatusCode::FORBIDDEN,
+                _ => StatusCode::UNAUTHORIZED,
+            };
+            ApiError {
+                message: e.to_string(),
+                code: status.as_u16(),
+            }
+            .into_response()
+        }
+    }
+}
+
+/// Build the response refusing an order over a placement quota
+fn placement_error_response(limiter: &RateLimiter, api_key: Option<&str>, address: Option<&str>, error: RateLimitError) -> Response {
+    let RateLimitError::PlacementExceeded { limit, usage } = error else {
+        return ApiError { message: error.to_string(), code: StatusCode::UNAUTHORIZED.as_u16() }.into_response();
+    };
+
+    let quota = limiter.client(api_key, address).ok().and_then(|(_, _, tier)| tier.order_placement);
+    let window = quota.map_or(0, |quota| match limit {
+        PlacementLimit::Burst => quota.burst.window,
+        PlacementLimit::Sustained => quota.sustained.window,
+    });
+
+    let body = PlacementErrorBody {
+        message: error.to_string(),
+        code: StatusCode::TOO_MANY_REQUESTS.as_u16(),
+        limit,
+        quota: usage.limit,
+        window,
+        retry_after: usage.reset,
+    };
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, Json(body)).into_response();
+    response.headers_mut().insert("retry-after", HeaderValue::from(usage.reset));
+    response
+}
+
+/// Quotas left handler
+pub async fn limits_handler(
+    State(state): State<Arc<ApiState>>,
+    request: Request<Body>,
+) -> Result<impl IntoResponse, ApiError> {
+    let limiter = state.rate_limiter.as_ref().ok_or_else(|| ApiError {
+        message: "Rate limits are not configured".to_string(),
+        code: 404,
+    })?;
+
+    let api_key = request.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let address = request.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+    let limits = limiter.limits(api_key, address.as_deref()).map_err(|e| ApiError {
+        message: e.to_string(),
+        code: StatusCode::UNAUTHORIZED.as_u16(),
+    })?;
+
+    Ok(Json(limits))
+}
+
+/// Set the `X-RateLimit-*` headers
+fn set_usage_headers(headers: &mut HeaderMap, usage: &Usage) {
+    headers.insert("x-ratelimit-limit", HeaderValue::from(usage.limit));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from(usage.remaining));
+    headers.insert("x-ratelimit-reset", HeaderValue::from(usage.reset));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> RateLimitConfig {
+        let mut tiers = HashMap::new();
+        tiers.insert("public".to_string(), Tier {
+            market_data: Quota { requests: 2, window: 60 },
+            trading: Quota { requests: 0, window: 60 },
+            order_placement: None,
+        });
+        tiers.insert("trader".to_string(), Tier {
+            market_data: Quota { requests: 100, window: 60 },
+            trading: Quota { requests: 1, window: 10 },
+            order_placement: None,
+        });
+        tiers.insert("bot".to_string(), Tier {
+            market_data: Quota { requests: 100, window: 60 },
+            trading: Quota { requests: 100, window: 60 },
+            order_placement: Some(PlacementQuota {
+                burst: Quota { requests: 2, window: 10 },
+                sustained: Quota { requests: 3, window: 3600 },
+            }),
+        });
+        let mut keys = HashMap::new();
+        keys.insert("secret".to_string(), "trader".to_string());
+        keys.insert("bot".to_string(), "bot".to_string());
+
+        RateLimitConfig { tiers, keys, anonymous: Some("public".to_string()) }
+    }
+
+    #[test]
+    fn test_route_groups() {
+        assert_eq!(RouteGroup::of(&Method::GET, "/orders/abc"), RouteGroup::MarketData);
+        assert_eq!(RouteGroup::of(&Method::GET, "/market"), RouteGroup::MarketData);
+        assert_eq!(RouteGroup::of(&Method::GET, "/markets"), RouteGroup::MarketData);
+        assert_eq!(RouteGroup::of(&Method::POST, "/orders"), RouteGroup::Trading);
+        assert_eq!(RouteGroup::of(&Method::GET, "/addresses"), RouteGroup::Trading);
+        assert_eq!(RouteGroup::of(&Method::GET, "/ordersx"), RouteGroup::Trading);
+
+        assert!(is_order_placement(&Method::POST, "/orders"));
+        assert!(is_order_placement(&Method::POST, "/groups/abc/orders"));
+        assert!(!is_order_placement(&Method::POST, "/orders/abc/take"));
+        assert!(!is_order_placement(&Method::GET, "/orders"));
+    }
+
+    #[test]
+    fn test_order_placement_quotas() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        // Tiers without placement quotas are only bound by the trading quota
+        assert_eq!(limiter.check_placement_at(Some("secret"), None, now).unwrap(), None);
+
+        let usage = limiter.check_placement_at(Some("bot"), None, now).unwrap().unwrap();
+        assert_eq!((usage.burst.remaining, usage.sustained.remaining), (1, 2));
+        assert!(limiter.check_placement_at(Some("bot"), None, now).is_ok());
+        match limiter.check_placement_at(Some("bot"), None, now + Duration::from_secs(3)) {
+            Err(RateLimitError::PlacementExceeded { limit: PlacementLimit::Burst, usage }) => assert_eq!(usage.reset, 7),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // A new burst window, but the sustained quota runs out
+        assert!(limiter.check_placement_at(Some("bot"), None, now + Duration::from_secs(10)).is_ok());
+        assert!(matches!(
+            limiter.check_placement_at(Some("bot"), None, now + Duration::from_secs(20)),
+            Err(RateLimitError::PlacementExceeded { limit: PlacementLimit::Sustained, .. })
+        ));
+
+        // Reporting the limits counts nothing
+        let limits = limiter.limits_at(Some("bot"), None, now + Duration::from_secs(20)).unwrap();
+        assert_eq!(limits.tier, "bot");
+        let placement = limits.order_placement.unwrap();
+        assert_eq!((placement.burst.remaining, placement.sustained.remaining), (2, 0));
+        assert_eq!(limits.trading.unwrap().remaining, 100);
+        assert!(limiter.limits_at(None, Some("10.0.0.1"), now).unwrap().trading.is_none());
+    }
+
+    #[test]
+    fn test_anonymous_tier_is_read_only() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        let usage = limiter.check_at(None, Some("10.0.0.1"), RouteGroup::MarketData, now).unwrap();
+        assert_eq!(usage, Usage { limit: 2, remaining: 1, reset: 60 });
+        assert!(limiter.check_at(None, Some("10.0.0.1"), RouteGroup::MarketData, now).is_ok());
+        assert!(matches!(
+            limiter.check_at(None, Some("10.0.0.1"), RouteGroup::MarketData, now),
+            Err(RateLimitError::Exceeded(Usage { remaining: 0, .. }))
+        ));
+
+        // Other addresses have their own window
+        assert!(limiter.check_at(None, Some("10.0.0.2"), RouteGroup::MarketData, now).is_ok());
+        assert!(matches!(
+            limiter.check_at(None, Some("10.0.0.2"), RouteGroup::Trading, now),
+            Err(RateLimitError::Closed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_window_resets() {
+        let limiter = RateLimiter::new(config());
+        let now = Instant::now();
+
+        assert!(limiter.check_at(Some("secret"), None, RouteGroup::Trading, now).is_ok());
+        match limiter.check_at(Some("secret"), None, RouteGroup::Trading, now + Duration::from_secs(4)) {
+            Err(RateLimitError::Exceeded(usage)) => assert_eq!(usage.reset, 6),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(limiter.check_at(Some("secret"), None, RouteGroup::Trading, now + Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_key_is_rejected() {
+        let limiter = RateLimiter::new(config());
+
+        assert!(matches!(limiter.check(Some("guess"), None, RouteGroup::MarketData), Err(RateLimitError::UnknownKey)));
+
+        let mut config = config();
+        config.keys.insert("other".to_string(), "missing".to_string());
+        assert!(config.validate().is_err());
+    }
+}
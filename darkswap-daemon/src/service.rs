@@ -0,0 +1,95 @@
+//! Service manager integration
+//!
+//! Run under a `Type=notify` systemd unit, the daemon reports `READY=1` once
+//! the API accepts connections, the P2P network is listening and the wallet has
+//! synced with its chain backend, so dependent units never see a node that
+//! cannot trade yet. With `WatchdogSec=` set it pings the watchdog while the API
+//! accepts connections, and a daemon whose runtime or server hangs is
+//! restarted. The watchdog never takes the node lock, which long operations
+//! such as settling a trade legitimately hold.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use darkswap_sdk::{systemd, DarkSwap};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Time between readiness checks before the daemon is ready
+const READY_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Spawn a task telling the service manager the daemon is ready once the API,
+/// the P2P network and the wallet are
+pub fn spawn_readiness(darkswap: Arc<Mutex<DarkSwap>>, api_addr: SocketAddr) -> tokio::task::JoinHandle<()> {
+    let api_addr = local_address(api_addr);
+
+    tokio::spawn(async move {
+        loop {
+            match check_ready(&darkswap, api_addr).await {
+                Ok(utxos) => {
+                    log::info!("P2P network listening and wallet synced ({} outputs), daemon ready", utxos);
+                    if let Err(e) = systemd::ready() {
+                        log::warn!("Failed to notify the service manager: {}", e);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("Daemon not ready: {}", e);
+                    let _ = systemd::status(&format!("Waiting: {}", e));
+                }
+            }
+
+            tokio::time::sleep(READY_RETRY_INTERVAL).await;
+        }
+    })
+}
+
+/// Check that the API accepts connections, the P2P network listens and the wallet syncs
+async fn check_ready(darkswap: &Mutex<DarkSwap>, api_addr: SocketAddr) -> anyhow::Result<usize> {
+    TcpStream::connect(api_addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("API not accepting connections on {}: {}", api_addr, e))?;
+
+    let darkswap = darkswap.lock().await;
+    if !darkswap.is_listening().await {
+        anyhow::bail!("P2P network not listening");
+    }
+
+    darkswap.sync_wallet()
+        .await
+        .map_err(|e| anyhow::anyhow!("Wallet not synced: {}", e))
+}
+
+/// Spawn a task pinging the watchdog twice per `timeout` while the API accepts connections
+pub fn spawn_watchdog(api_addr: SocketAddr, timeout: Duration) -> tokio::task::JoinHandle<()> {
+    let api_addr = local_address(api_addr);
+    let interval = timeout / 2;
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            match tokio::time::timeout(interval, TcpStream::connect(api_addr)).await {
+                Ok(Ok(_)) => {
+                    if let Err(e) = systemd::watchdog() {
+                        log::warn!("Failed to ping the watchdog: {}", e);
+                    }
+                }
+                Ok(Err(e)) => log::warn!("API not accepting connections, skipping the watchdog ping: {}", e),
+                Err(_) => log::warn!("API did not accept a connection within {:?}, skipping the watchdog ping", interval),
+            }
+        }
+    })
+}
+
+/// Get the address to reach the API at locally, the loopback one if it is bound to all interfaces
+fn local_address(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), addr.port()),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port()),
+        _ => addr,
+    }
+}
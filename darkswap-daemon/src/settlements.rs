@@ -0,0 +1,382 @@
+//! Settlement ledger
+//!
+//! A daemon can serve as a small back-office database for the nodes of a desk.
+//! Nodes push the signed receipt of each completed trade to `POST /settlements`
+//! (a daemon started with `--settlement-sink <url>` does so for its own trades),
+//! and the ledger keeps them in the `settlements` storage namespace, one record
+//! per trade, to be queried by date, pair and peer at `GET /settlements`.
+//!
+//! Anyone can push a receipt signed by both trade parties. A receipt signed by
+//! one party only, as a node has it right after settling, is accepted from
+//! pushes carrying the desk's settlement key. Receipts the sink fails to push
+//! wait in the `settlement_outbox` namespace and are pushed again later.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use darkswap_sdk::{
+    storage::{Storage, StorageExt},
+    trade::receipt::TradeReceipt,
+    types::{Asset, Event},
+    DarkSwap,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::browser::constant_time_eq;
+
+/// Storage namespace of settlement records
+pub const SETTLEMENT_NAMESPACE: &str = "settlements";
+
+/// Storage namespace of the receipts waiting to be pushed to the sink
+pub const OUTBOX_NAMESPACE: &str = "settlement_outbox";
+
+/// Interval at which receipts that failed to push are pushed again
+const SINK_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time a push to the sink may take
+const SINK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Receipt of a settled trade, as kept by the ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettlementRecord {
+    /// Signed receipt
+    pub receipt: TradeReceipt,
+    /// Time the receipt was first received (unix seconds)
+    pub received_at: u64,
+}
+
+/// Settlements to look up
+#[derive(Debug, Clone, Default)]
+pub struct SettlementFilter {
+    /// Received at or after (unix seconds)
+    pub from: Option<u64>,
+    /// Received before (unix seconds)
+    pub to: Option<u64>,
+    /// Base and quote asset
+    pub pair: Option<(Asset, Asset)>,
+    /// Maker or taker peer ID
+    pub peer: Option<String>,
+}
+
+impl SettlementFilter {
+    /// Check if a record is selected
+    pub fn matches(&self, record: &SettlementRecord) -> bool {
+        let body = &record.receipt.body;
+
+        self.from.map_or(true, |from| record.received_at >= from)
+            && self.to.map_or(true, |to| record.received_at < to)
+            && self.pair.as_ref().map_or(true, |(base, quote)| &body.base_asset == base && &body.quote_asset == quote)
+            && self.peer.as_ref().map_or(true, |peer| &body.maker_peer_id == peer || &body.taker_peer_id == peer)
+    }
+}
+
+/// Check that a receipt carries only valid signatures of its trade parties
+///
+/// Both parties must have signed, or at least one of them if `both` is false.
+pub fn check_signatures(receipt: &TradeReceipt, both: bool) -> Result<()> {
+    let body = &receipt.body;
+    if receipt.signatures.is_empty() {
+        anyhow::bail!("Receipt of trade {} is not signed", body.trade_id);
+    }
+
+    for signature in &receipt.signatures {
+        if signature.peer_id != body.maker_peer_id && signature.peer_id != body.taker_peer_id {
+            anyhow::bail!("Receipt signed by {} who is not a trade party", signature.peer_id);
+        }
+        signature.verify(body)?;
+    }
+
+    if both {
+        for (party, peer_id) in [("maker", &body.maker_peer_id), ("taker", &body.taker_peer_id)] {
+            if !receipt.signatures.iter().any(|signature| &signature.peer_id == peer_id) {
+                anyhow::bail!("Receipt of trade {} is missing the {} signature", body.trade_id, party);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Settlements pushed by connected nodes
+pub struct SettlementLedger {
+    /// Storage the records are kept in
+    storage: Arc<dyn Storage>,
+    /// Key of the desk's nodes, allowed to push receipts signed by one party
+    api_key: Option<String>,
+}
+
+impl SettlementLedger {
+    /// Create a ledger on `storage`
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage, api_key: None }
+    }
+
+    /// Accept receipts signed by one party from pushes carrying `api_key`
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    /// Check if a push carries the settlement key
+    pub fn is_trusted(&self, key: Option<&str>) -> bool {
+        match (&self.api_key, key) {
+            (Some(api_key), Some(key)) => constant_time_eq(api_key.as_bytes(), key.as_bytes()),
+            _ => false,
+        }
+    }
+
+    /// Record a receipt received at `now` (unix seconds)
+    ///
+    /// An untrusted receipt must be signed by both parties. A receipt of a trade
+    /// already recorded adds the signatures the record is missing, e.g. when
+    /// both parties push their receipt; the terms must match. Inclusion proofs
+    /// are taken as is and must be checked by the caller.
+    pub fn record(&self, receipt: TradeReceipt, now: u64, trusted: bool) -> Result<SettlementRecord> {
+        check_signatures(&receipt, !trusted)?;
+
+        let key = &receipt.body.trade_id.0;
+        let record = match self.storage.get_json::<SettlementRecord>(SETTLEMENT_NAMESPACE, key)? {
+            Some(mut record) => {
+                if record.receipt.body != receipt.body {
+                    anyhow::bail!("Receipt of trade {} does not match the recorded terms", key);
+                }
+                for signature in receipt.signatures {
+                    if !record.receipt.signatures.iter().any(|known| known.peer_id == signature.peer_id) {
+                        record.receipt.signatures.push(signature);
+                    }
+                }
+                if record.receipt.inclusion_proof.is_none() {
+                    record.receipt.inclusion_proof = receipt.inclusion_proof;
+                }
+                record
+            }
+            None => SettlementRecord { receipt, received_at: now },
+        };
+
+        self.storage
+            .put_json(SETTLEMENT_NAMESPACE, key, &record)
+            .context("Failed to store settlement record")?;
+
+        Ok(record)
+    }
+
+    /// Get the records selected by `filter`, oldest first, at most `limit`
+    pub fn query(&self, filter: &SettlementFilter, limit: usize) -> Result<Vec<SettlementRecord>> {
+        let mut records: Vec<SettlementRecord> = self
+            .storage
+            .scan_json::<SettlementRecord>(SETTLEMENT_NAMESPACE, "")
+            .context("Failed to read settlement records")?
+            .into_iter()
+            .map(|(_, record)| record)
+            .filter(|record| filter.matches(record))
+            .collect();
+
+        records.sort_by(|a, b| {
+            a.received_at.cmp(&b.received_at).then_with(|| a.receipt.body.trade_id.0.cmp(&b.receipt.body.trade_id.0))
+        });
+        records.truncate(limit);
+
+        Ok(records)
+    }
+}
+
+/// Pushes the receipts of this node's trades to a ledger
+struct SettlementSink {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Settlements endpoint of the ledger
+    url: String,
+    /// Settlement key sent with every push
+    api_key: Option<String>,
+    /// Storage the receipts wait in until they are pushed
+    storage: Arc<dyn Storage>,
+}
+
+impl SettlementSink {
+    /// Push the waiting receipts, oldest trade ID first, until one fails to reach the ledger
+    ///
+    /// A receipt the ledger refuses is dropped, since pushing it again would be
+    /// refused as well.
+    async fn push_pending(&self) {
+        let pending = match self.storage.scan_json::<TradeReceipt>(OUTBOX_NAMESPACE, "") {
+            Ok(pending) => pending,
+            Err(e) => {
+                log::warn!("Failed to read the settlement outbox: {}", e);
+                return;
+            }
+        };
+
+        for (key, receipt) in pending {
+            let request = self.client.post(&self.url).json(&receipt);
+            let request = match &self.api_key {
+                Some(api_key) => request.bearer_auth(api_key),
+                None => request,
+            };
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) if response.status().is_client_error() => {
+                    log::warn!("Ledger {} refused the receipt of trade {}: {}", self.url, key, response.status());
+                }
+                Ok(response) => {
+                    log::warn!("Failed to push the receipt of trade {} to {}: {}", key, self.url, response.status());
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("Failed to push the receipt of trade {} to {}: {}", key, self.url, e);
+                    return;
+                }
+            }
+
+            if let Err(e) = self.storage.delete(OUTBOX_NAMESPACE, key.as_bytes()) {
+                log::warn!("Failed to remove the receipt of trade {} from the settlement outbox: {}", key, e);
+            }
+        }
+    }
+}
+
+/// Spawn a task pushing the receipt of every trade this node completes to the ledger at `url`
+///
+/// Receipts wait in storage until the ledger took them, so those that failed to
+/// push, also before a restart, are pushed again every minute.
+pub fn spawn_sink(url: String, api_key: Option<String>, darkswap: Arc<Mutex<DarkSwap>>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = match reqwest::Client::builder().timeout(SINK_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("Failed to create the settlement sink HTTP client: {}", e);
+                return;
+            }
+        };
+        let (mut receiver, storage) = {
+            let darkswap = darkswap.lock().await;
+            (darkswap.subscribe_to_events().await, darkswap.storage())
+        };
+        let sink = SettlementSink {
+            client,
+            url: format!("{}/settlements", url.trim_end_matches('/')),
+            api_key,
+            storage,
+        };
+        let mut retry = tokio::time::interval(SINK_RETRY_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = receiver.recv() => {
+                    let trade_id = match event {
+                        Some(Event::TradeCompleted(trade_id)) => trade_id,
+                        Some(_) => continue,
+                        None => break,
+                    };
+
+                    let receipt = match darkswap.lock().await.export_receipt(&trade_id, None).await {
+                        Ok(receipt) => receipt,
+                        Err(e) => {
+                            log::warn!("Failed to export the receipt of trade {}: {}", trade_id, e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = sink.storage.put_json(OUTBOX_NAMESPACE, &trade_id.0, &receipt) {
+                        log::warn!("Failed to queue the receipt of trade {}: {}", trade_id, e);
+                    }
+                }
+                _ = retry.tick() => {}
+            }
+
+            sink.push_pending().await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkswap_sdk::{
+        orderbook::OrderId,
+        storage::MemoryStorage,
+        trade::receipt::ReceiptBody,
+        types::TradeId,
+    };
+    use libp2p::{identity::Keypair, PeerId};
+    use rust_decimal::Decimal;
+
+    fn record(trade: &str, quote_asset: Asset, maker: &str, received_at: u64) -> SettlementRecord {
+        SettlementRecord {
+            receipt: TradeReceipt {
+                body: ReceiptBody {
+                    trade_id: TradeId(trade.to_string()),
+                    order_id: OrderId(format!("order-{}", trade)),
+                    maker_peer_id: maker.to_string(),
+                    taker_peer_id: "taker".to_string(),
+                    base_asset: Asset::Bitcoin,
+                    quote_asset,
+                    amount: Decimal::ONE,
+                    price: Decimal::ONE_HUNDRED,
+                    txid: "00".repeat(32),
+                },
+                order: None,
+                signatures: Vec::new(),
+                inclusion_proof: None,
+            },
+            received_at,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let a = record("a", Asset::Rune(1), "maker-1", 100);
+        let b = record("b", Asset::Rune(2), "maker-2", 200);
+
+        let filter = SettlementFilter { from: Some(150), ..SettlementFilter::default() };
+        assert!(!filter.matches(&a) && filter.matches(&b));
+
+        let filter = SettlementFilter { to: Some(150), ..SettlementFilter::default() };
+        assert!(filter.matches(&a) && !filter.matches(&b));
+
+        let filter = SettlementFilter { pair: Some((Asset::Bitcoin, Asset::Rune(2))), ..SettlementFilter::default() };
+        assert!(!filter.matches(&a) && filter.matches(&b));
+
+        let filter = SettlementFilter { peer: Some("taker".to_string()), ..SettlementFilter::default() };
+        assert!(filter.matches(&a) && filter.matches(&b));
+        let filter = SettlementFilter { peer: Some("maker-1".to_string()), ..SettlementFilter::default() };
+        assert!(filter.matches(&a) && !filter.matches(&b));
+    }
+
+    #[test]
+    fn test_unsigned_receipt_is_refused() {
+        let ledger = SettlementLedger::new(Arc::new(MemoryStorage::new()));
+        let receipt = record("a", Asset::Rune(1), "maker-1", 0).receipt;
+
+        assert!(ledger.record(receipt, 100, true).is_err());
+        assert!(ledger.query(&SettlementFilter::default(), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_one_signature_needs_the_settlement_key() {
+        let maker = Keypair::generate_ed25519();
+        let taker = Keypair::generate_ed25519();
+        let mut receipt = record("a", Asset::Rune(1), &PeerId::from(maker.public()).to_string(), 0).receipt;
+        receipt.body.taker_peer_id = PeerId::from(taker.public()).to_string();
+        receipt.signatures.push(receipt.body.sign(&maker).unwrap());
+
+        let ledger = SettlementLedger::new(Arc::new(MemoryStorage::new())).with_api_key(Some("desk-key".to_string()));
+        assert!(!ledger.is_trusted(None));
+        assert!(!ledger.is_trusted(Some("other-key")));
+        assert!(ledger.is_trusted(Some("desk-key")));
+
+        // Signed by the maker only: refused from anyone, accepted with the key
+        assert!(ledger.record(receipt.clone(), 100, false).is_err());
+        ledger.record(receipt.clone(), 100, true).unwrap();
+
+        // The taker's push completes the record
+        receipt.signatures = vec![receipt.body.sign(&taker).unwrap()];
+        assert!(ledger.record(receipt.clone(), 200, false).is_err());
+        let mut both = receipt;
+        both.signatures.push(both.body.sign(&maker).unwrap());
+        let record = ledger.record(both, 200, false).unwrap();
+        assert_eq!(record.receipt.signatures.len(), 2);
+        assert_eq!(record.received_at, 100);
+    }
+}
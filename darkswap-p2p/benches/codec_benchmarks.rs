@@ -0,0 +1,107 @@
+//! Gossip codec benchmarks, on the paths `Network::publish_message` and
+//! `Network::decode_message` take
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use darkswap_p2p::JsonCodec;
+use serde::{Deserialize, Serialize};
+
+/// Number of messages per iteration, one second of traffic at 10k msgs/sec
+const MESSAGES: usize = 10_000;
+
+/// Order message with owned fields
+#[derive(Serialize, Deserialize)]
+struct OrderMessage {
+    id: String,
+    maker: String,
+    base_asset: String,
+    quote_asset: String,
+    side: String,
+    amount: String,
+    price: String,
+    timestamp: u64,
+}
+
+/// Order message borrowing from the received buffer
+#[derive(Serialize, Deserialize)]
+struct OrderMessageRef<'a> {
+    id: &'a str,
+    maker: &'a str,
+    base_asset: &'a str,
+    quote_asset: &'a str,
+    side: &'a str,
+    amount: &'a str,
+    price: &'a str,
+    timestamp: u64,
+}
+
+fn order(n: usize) -> OrderMessage {
+    OrderMessage {
+        id: format!("order-{}", n),
+        maker: "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN".to_string(),
+        base_asset: "BTC".to_string(),
+        quote_asset: "RUNE:840000:1".to_string(),
+        side: "Sell".to_string(),
+        amount: "0.015".to_string(),
+        price: "2150000".to_string(),
+        timestamp: 1_700_000_000 + n as u64,
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let orders: Vec<OrderMessage> = (0..MESSAGES).map(order).collect();
+
+    let mut group = c.benchmark_group("codec_encode");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+
+    // A fresh vector per message, as `Network::publish` callers serialize
+    group.bench_function("to_vec", |b| {
+        b.iter(|| {
+            for order in &orders {
+                black_box(bytes::Bytes::from(serde_json::to_vec(order).unwrap()));
+            }
+        })
+    });
+
+    // The reused buffer of `Network::publish_message`
+    group.bench_function("publish_message", |b| {
+        let mut codec = JsonCodec::new();
+        b.iter(|| {
+            for order in &orders {
+                black_box(codec.encode(order).unwrap());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut codec = JsonCodec::new();
+    let encoded: Vec<_> = (0..MESSAGES).map(|n| codec.encode(&order(n)).unwrap()).collect();
+
+    let mut group = c.benchmark_group("codec_decode");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+
+    // Received payloads are not copied either way; owned fields allocate per string
+    group.bench_function("owned", |b| {
+        b.iter(|| {
+            for message in &encoded {
+                black_box(codec.decode::<OrderMessage>(message).unwrap());
+            }
+        })
+    });
+
+    // What `Network::decode_message` gives a subscriber with borrowing types
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            for message in &encoded {
+                black_box(codec.decode::<OrderMessageRef>(message).unwrap());
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode, bench_decode);
+criterion_main!(benches);
@@ -5,7 +5,7 @@
 
 use darkswap_p2p::{
     network::{Network, NetworkConfig, NetworkEvent},
-    Error,
+    Error, GossipConfig,
 };
 use darkswap_support::types::PeerId;
 use std::time::Duration;
@@ -29,6 +29,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             // Add relay peers here
         ],
         connection_timeout: Duration::from_secs(30),
+        gossip: GossipConfig::light(),
     };
 
     // Create a network
@@ -53,10 +54,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     println!("Peer disconnected: {}", peer_id);
                 }
                 NetworkEvent::MessageReceived { peer_id, topic, message } => {
-                    println!(
-                        "Message received from {} on topic {}: {:?}",
-                        peer_id, topic, message
-                    );
+                    match network.decode_message::<serde_json::Value>(&message) {
+                        Ok(message) => println!(
+                            "Message received from {} on topic {}: {}",
+                            peer_id, topic, message
+                        ),
+                        Err(e) => println!("Undecodable message from {} on topic {}: {}", peer_id, topic, e),
+                    }
                 }
                 NetworkEvent::RelayReserved { relay_peer_id, reservation_id } => {
                     println!(
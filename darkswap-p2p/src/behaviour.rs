@@ -4,6 +4,7 @@
 //! combining various libp2p protocols into a single behaviour.
 
 use crate::circuit_relay::{CircuitRelayBehaviour, CircuitRelayEvent, RelayConfig};
+use crate::gossip::GossipConfig;
 use darkswap_support::types::PeerId;
 use libp2p::{
     gossipsub::{Gossipsub, GossipsubConfig, GossipsubEvent, MessageAuthenticity, ValidationMode},
@@ -146,6 +147,7 @@ fn inject_connection_closed(
 pub fn new_behaviour(
     local_peer_id: &PeerId,
     keypair: &libp2p::identity::Keypair,
+    gossip_config: &GossipConfig,
 ) -> Result<DarkSwapBehaviour, Box<dyn std::error::Error>> {
     let peer_id = libp2p::PeerId::from_public_key(&keypair.public());
     
@@ -162,16 +164,8 @@ pub fn new_behaviour(
     let store = MemoryStore::new(peer_id);
     let kademlia = Kademlia::new(peer_id, store);
     
-    // Create GossipSub behaviour
-    let message_id_fn = |message: &libp2p::gossipsub::Message| {
-        use std::hash::{Hash, Hasher};
-        let mut s = std::collections::hash_map::DefaultHasher::new();
-        message.data.hash(&mut s);
-        libp2p::gossipsub::MessageId::from(s.finish().to_string())
-    };
-    
     // Create GossipSub config
-    let gossipsub_config = GossipsubConfig::default();
+    let gossipsub_config = gossip_config.build()?;
     
     let gossipsub = Gossipsub::new(
         MessageAuthenticity::Signed(keypair.clone()),
@@ -0,0 +1,117 @@
+//! Message codec for darkswap-p2p
+//!
+//! This module provides the JSON codec of gossip messages:
+//! [`Network::publish_message`](crate::Network::publish_message) encodes
+//! through it and subscribers decode received messages with
+//! [`Network::decode_message`](crate::Network::decode_message). Messages are
+//! encoded into a reusable buffer and handed out as `Bytes`, so they can be
+//! shared between peers and queues without copying. Decoding borrows from the
+//! input where the target type allows it (`&str`, `&[u8]` with
+//! `#[serde(borrow)]`).
+
+use crate::error::Error;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+/// Default maximum message size (1 MiB)
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// JSON message codec
+#[derive(Debug)]
+pub struct JsonCodec {
+    /// Encode buffer, reused between messages
+    buffer: BytesMut,
+    /// Maximum message size
+    max_size: usize,
+}
+
+impl JsonCodec {
+    /// Create a new codec with the default maximum message size
+    pub fn new() -> Self {
+        Self::with_max_size(MAX_MESSAGE_SIZE)
+    }
+
+    /// Create a new codec with the given maximum message size
+    pub fn with_max_size(max_size: usize) -> Self {
+        JsonCodec {
+            buffer: BytesMut::with_capacity(4096),
+            max_size,
+        }
+    }
+
+    /// Encode a message
+    pub fn encode<T: Serialize>(&mut self, message: &T) -> Result<Bytes, Error> {
+        let start = self.buffer.len();
+
+        if let Err(e) = serde_json::to_writer((&mut self.buffer).writer(), message) {
+            self.buffer.clear();
+            return Err(Error::SerializationError(e.to_string()));
+        }
+
+        if self.buffer.len() - start > self.max_size {
+            self.buffer.clear();
+            return Err(Error::SerializationError(format!(
+                "Message exceeds the maximum of {} bytes",
+                self.max_size
+            )));
+        }
+
+        Ok(self.buffer.split().freeze())
+    }
+
+    /// Decode a message, borrowing from `data` where possible
+    pub fn decode<'a, T: Deserialize<'a>>(&self, data: &'a [u8]) -> Result<T, Error> {
+        self.check_size(data.len())?;
+
+        serde_json::from_slice(data).map_err(|e| Error::SerializationError(e.to_string()))
+    }
+
+    /// Check that a message of `len` bytes is within the maximum size
+    pub fn check_size(&self, len: usize) -> Result<(), Error> {
+        if len > self.max_size {
+            return Err(Error::SerializationError(format!(
+                "Message of {} bytes exceeds the maximum of {} bytes",
+                len, self.max_size
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for JsonCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Message<'a> {
+        id: &'a str,
+        amount: u64,
+    }
+
+    #[test]
+    fn test_borrowed_roundtrip() {
+        let mut codec = JsonCodec::new();
+        let encoded = codec.encode(&Message { id: "order-1", amount: 5 }).unwrap();
+
+        let decoded: Message = codec.decode(&encoded).unwrap();
+        assert_eq!(decoded, Message { id: "order-1", amount: 5 });
+    }
+
+    #[test]
+    fn test_oversized_message_is_rejected() {
+        let mut codec = JsonCodec::with_max_size(24);
+
+        assert!(codec.encode(&Message { id: "a long order id", amount: 1 }).is_err());
+        assert!(codec.decode::<Message>(br#"{"id":"a long order id","amount":1}"#).is_err());
+
+        // The buffer is usable again after a failed encode
+        assert!(codec.encode(&Message { id: "a", amount: 1 }).is_ok());
+    }
+}
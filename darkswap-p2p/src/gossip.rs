@@ -0,0 +1,218 @@
+//! Gossip configuration for darkswap-p2p
+//!
+//! This module provides the tunable GossipSub parameters and a bounded
+//! duplicate cache. High-volume pairs can cause re-propagation storms when
+//! message IDs are forgotten too early, so history length, message TTL and
+//! duplicate cache size are exposed with presets for light and full nodes.
+
+use crate::error::Error;
+use libp2p::gossipsub::{GossipsubConfig, GossipsubConfigBuilder, Message, MessageId, ValidationMode};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+/// GossipSub configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GossipConfig {
+    /// Number of heartbeats a message is kept in the message cache
+    pub history_length: usize,
+    /// Number of past heartbeats whose messages are advertised in gossip
+    pub history_gossip: usize,
+    /// Heartbeat interval
+    pub heartbeat_interval: Duration,
+    /// Time a seen message ID is remembered; copies arriving within it are dropped
+    pub message_ttl: Duration,
+    /// Maximum number of message IDs kept in the duplicate cache
+    pub duplicate_cache_size: usize,
+    /// Publish own messages to all peers on the topic, not just the mesh
+    pub flood_publish: bool,
+}
+
+impl GossipConfig {
+    /// Preset for light nodes, such as browsers and mobile clients
+    ///
+    /// Keeps a short history and a small duplicate cache, and only publishes
+    /// to the mesh.
+    pub fn light() -> Self {
+        GossipConfig {
+            history_length: 3,
+            history_gossip: 2,
+            heartbeat_interval: Duration::from_secs(1),
+            message_ttl: Duration::from_secs(30),
+            duplicate_cache_size: 1_000,
+            flood_publish: false,
+        }
+    }
+
+    /// Preset for full nodes, such as daemons and relays
+    ///
+    /// Remembers messages for longer so re-propagated copies on busy pairs are
+    /// dropped instead of forwarded again.
+    pub fn full() -> Self {
+        GossipConfig {
+            history_length: 6,
+            history_gossip: 3,
+            heartbeat_interval: Duration::from_secs(1),
+            message_ttl: Duration::from_secs(120),
+            duplicate_cache_size: 20_000,
+            flood_publish: true,
+        }
+    }
+
+    /// Build the libp2p GossipSub configuration
+    pub fn build(&self) -> Result<GossipsubConfig, Error> {
+        if self.history_gossip > self.history_length {
+            return Err(Error::Other(
+                "Gossip history must not be longer than the message history".to_string(),
+            ));
+        }
+
+        GossipsubConfigBuilder::default()
+            .history_length(self.history_length)
+            .history_gossip(self.history_gossip)
+            .heartbeat_interval(self.heartbeat_interval)
+            .duplicate_cache_time(self.message_ttl)
+            .flood_publish(self.flood_publish)
+            .validation_mode(ValidationMode::Strict)
+            .message_id_fn(message_id)
+            .build()
+            .map_err(|e| Error::Other(format!("Invalid gossip configuration: {}", e)))
+    }
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        GossipConfig::full()
+    }
+}
+
+/// Derive a message ID from the message content
+///
+/// Identical payloads share an ID, so a message re-published by another peer is
+/// recognised as a duplicate.
+fn message_id(message: &Message) -> MessageId {
+    MessageId::from(content_hash(&message.data).to_string())
+}
+
+/// Hash message content
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Bounded cache of recently seen messages
+#[derive(Debug)]
+pub struct DuplicateCache {
+    /// Maximum number of entries
+    capacity: usize,
+    /// Time an entry is kept
+    ttl: Duration,
+    /// Time each message was first seen
+    seen: HashMap<u64, Instant>,
+    /// Messages in insertion order
+    order: VecDeque<u64>,
+}
+
+impl DuplicateCache {
+    /// Create a new duplicate cache
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        DuplicateCache {
+            capacity,
+            ttl,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Create a duplicate cache sized by the gossip configuration
+    pub fn from_config(config: &GossipConfig) -> Self {
+        DuplicateCache::new(config.duplicate_cache_size, config.message_ttl)
+    }
+
+    /// Record a message, returning `true` if it was not seen within the TTL
+    pub fn insert(&mut self, data: &[u8]) -> bool {
+        let now = Instant::now();
+        self.expire(now);
+
+        let hash = content_hash(data);
+        if self.seen.contains_key(&hash) {
+            return false;
+        }
+
+        // Evict the oldest entries when full
+        while self.order.len() >= self.capacity.max(1) {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        self.seen.insert(hash, now);
+        self.order.push_back(hash);
+
+        true
+    }
+
+    /// Get the number of cached messages
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Remove entries older than the TTL
+    fn expire(&mut self, now: Instant) {
+        while let Some(oldest) = self.order.front() {
+            match self.seen.get(oldest) {
+                Some(seen_at) if now.duration_since(*seen_at) < self.ttl => break,
+                _ => {
+                    if let Some(oldest) = self.order.pop_front() {
+                        self.seen.remove(&oldest);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_build() {
+        assert!(GossipConfig::light().build().is_ok());
+        assert!(GossipConfig::full().build().is_ok());
+
+        let mut config = GossipConfig::light();
+        config.history_gossip = config.history_length + 1;
+        assert!(config.build().is_err());
+    }
+
+    #[test]
+    fn test_duplicate_cache() {
+        let mut cache = DuplicateCache::new(2, Duration::from_secs(60));
+
+        assert!(cache.insert(b"a"));
+        assert!(!cache.insert(b"a"));
+        assert!(cache.insert(b"b"));
+        assert!(cache.insert(b"c"));
+        assert_eq!(cache.len(), 2);
+
+        // The oldest entry was evicted
+        assert!(cache.insert(b"a"));
+    }
+
+    #[test]
+    fn test_duplicate_cache_expiry() {
+        let mut cache = DuplicateCache::new(10, Duration::from_millis(0));
+
+        assert!(cache.insert(b"a"));
+        assert!(cache.insert(b"a"));
+    }
+}
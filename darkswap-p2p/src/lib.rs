@@ -8,14 +8,18 @@ pub mod transport;
 pub mod behaviour;
 pub mod network;
 pub mod circuit_relay;
+pub mod codec;
 pub mod error;
+pub mod gossip;
 pub mod signaling_client;
 pub mod webrtc_signaling_client;
 pub mod webrtc_transport;
 pub mod webrtc_connection;
 
 pub use network::Network;
+pub use codec::JsonCodec;
 pub use error::Error;
+pub use gossip::GossipConfig;
 pub use webrtc_transport::WebRtcTransport;
 pub use webrtc_signaling_client::WebRtcSignalingClient;
 pub use webrtc_connection::{WebRtcConnection, WebRtcConnectionManager, DataChannel, ConnectionState, IceConnectionState, SignalingState, DataChannelState};
\ No newline at end of file
@@ -5,9 +5,13 @@
 use crate::{
     behaviour::{new_behaviour, DarkSwapBehaviour, DarkSwapEvent},
     circuit_relay::CircuitRelayEvent,
+    codec::JsonCodec,
     error::Error,
+    gossip::{DuplicateCache, GossipConfig},
     transport::build_transport,
 };
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use void::Void;
 use darkswap_support::types::PeerId;
@@ -41,6 +45,8 @@ pub struct NetworkConfig {
     pub relay_peers: Vec<(PeerId, Multiaddr)>,
     /// Connection timeout
     pub connection_timeout: Duration,
+    /// Gossip configuration
+    pub gossip: GossipConfig,
 }
 
 impl Default for NetworkConfig {
@@ -50,6 +56,7 @@ impl Default for NetworkConfig {
             topics: Vec::new(),
             relay_peers: Vec::new(),
             connection_timeout: Duration::from_secs(30),
+            gossip: GossipConfig::default(),
         }
     }
 }
@@ -68,7 +75,7 @@ pub enum NetworkEvent {
         /// Topic
         topic: String,
         /// Message
-        message: Vec<u8>,
+        message: Bytes,
     },
     /// Relay reserved
     RelayReserved {
@@ -100,6 +107,10 @@ pub struct Network {
     event_sender: mpsc::Sender<NetworkEvent>,
     /// Event receiver
     event_receiver: mpsc::Receiver<NetworkEvent>,
+    /// Recently received messages
+    duplicates: DuplicateCache,
+    /// Codec of gossip messages
+    codec: JsonCodec,
     /// Configuration
     config: NetworkConfig,
 }
@@ -114,7 +125,7 @@ impl Network {
         // Create the transport
         let transport = build_transport();
         // Create the behaviour
-        let behaviour = match new_behaviour(&local_peer_id, &keypair) {
+        let behaviour = match new_behaviour(&local_peer_id, &keypair, &config.gossip) {
             Ok(b) => b,
             Err(e) => return Err(Error::Other(format!("Failed to create behaviour: {}", e))),
         };
@@ -135,6 +146,8 @@ impl Network {
             topics: HashMap::new(),
             event_sender,
             event_receiver,
+            duplicates: DuplicateCache::from_config(&config.gossip),
+            codec: JsonCodec::new(),
             config,
         };
         
@@ -192,7 +205,7 @@ impl Network {
     }
     
     /// Publish a message to a topic
-    pub async fn publish(&mut self, topic: &str, message: Vec<u8>) -> Result<(), Error> {
+    pub async fn publish(&mut self, topic: &str, message: impl Into<Bytes>) -> Result<(), Error> {
         // Check if we're subscribed to the topic
         if !self.topics.contains_key(topic) {
             return Err(Error::Other(format!("Not subscribed to topic: {}", topic)));
@@ -202,12 +215,24 @@ impl Network {
         let topic_obj = IdentTopic::new(topic);
         
         // Publish the message
+        let message: Bytes = message.into();
         match self.swarm.behaviour_mut().gossipsub.publish(topic_obj, message) {
             Ok(_) => Ok(()),
             Err(e) => Err(Error::Other(format!("Failed to publish message: {}", e))),
         }
     }
     
+    /// Encode a message with the gossip codec and publish it to a topic
+    pub async fn publish_message<T: Serialize>(&mut self, topic: &str, message: &T) -> Result<(), Error> {
+        let data = self.codec.encode(message)?;
+        self.publish(topic, data).await
+    }
+    
+    /// Decode a received gossip message, borrowing from `data` where possible
+    pub fn decode_message<'a, T: Deserialize<'a>>(&self, data: &'a [u8]) -> Result<T, Error> {
+        self.codec.decode(data)
+    }
+    
     /// Dial a peer with a multiaddress
     pub async fn dial_peer_with_addr(&mut self, peer_id: &PeerId, addr: &Multiaddr) -> Result<(), Error> {
         let libp2p_peer_id = parse_peer_id(&peer_id.0)?;
@@ -285,6 +310,17 @@ impl Network {
                 message_id: _,
                 message,
             })) => {
+                // Drop messages no subscriber could decode
+                if let Err(e) = self.codec.check_size(message.data.len()) {
+                    log::warn!("Dropping message from {}: {}", propagation_source, e);
+                    return Ok(());
+                }
+                
+                // Drop copies of messages we have already delivered
+                if !self.duplicates.insert(&message.data) {
+                    return Ok(());
+                }
+                
                 // Find the topic name
                 let topic_name = self.topics.iter()
                     .find_map(|(name, hash)| if hash == &message.topic { Some(name.clone()) } else { None })
@@ -297,7 +333,7 @@ impl Network {
                 self.event_sender.send(NetworkEvent::MessageReceived {
                     peer_id,
                     topic: topic_name,
-                    message: message.data,
+                    message: Bytes::from(message.data),
                 }).await
                 .map_err(|e| Error::Other(format!("Failed to send event: {}", e)))?;
             }
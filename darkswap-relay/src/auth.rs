@@ -6,15 +6,16 @@
 use crate::{
     config::Config,
     error::Error,
+    revocation::{self, RevocationList},
     Result,
 };
 use chrono::{Duration, Utc};
+use darkswap_support::secrets::SecretResolver;
 use jsonwebtoken::{
     decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
 /// Authentication claims
@@ -52,15 +53,18 @@ pub struct AuthManager {
     /// Admin token
     admin_token: String,
     /// Revoked tokens
-    revoked_tokens: Arc<RwLock<Vec<String>>>,
+    revocations: Arc<RevocationList>,
 }
 
 impl AuthManager {
     /// Create a new authentication manager
     pub fn new(config: Config) -> Result<Self> {
-        // Get authentication settings from environment variables
+        // Get authentication settings from environment variables; the secrets
+        // may name OS keyring entries as `keyring:<entry>`
+        let mut resolver = SecretResolver::new();
         let secret = std::env::var("DARKSWAP_RELAY_AUTH_SECRET")
             .unwrap_or_else(|_| "default-secret-key-change-me".to_string());
+        let secret = resolver.resolve(&secret)?;
         
         let token_expiry = std::env::var("DARKSWAP_RELAY_AUTH_TOKEN_EXPIRY")
             .ok()
@@ -69,16 +73,30 @@ impl AuthManager {
         
         let admin_token = std::env::var("DARKSWAP_RELAY_AUTH_ADMIN_TOKEN")
             .unwrap_or_else(|_| "admin-token-change-me".to_string());
+        let admin_token = resolver.resolve(&admin_token)?;
+        
+        let revocations = Arc::new(RevocationList::open(&config.revocation)?);
         
         Ok(Self {
             config,
             secret,
             token_expiry,
             admin_token,
-            revoked_tokens: Arc::new(RwLock::new(Vec::new())),
+            revocations,
         })
     }
     
+    /// Check tokens against a revocation list shared with other components
+    pub fn with_revocations(mut self, revocations: Arc<RevocationList>) -> Self {
+        self.revocations = revocations;
+        self
+    }
+    
+    /// Get the revocation list
+    pub fn revocations(&self) -> Arc<RevocationList> {
+        self.revocations.clone()
+    }
+    
     /// Generate a token for a peer
     pub async fn generate_token(&self, peer_id: &str, roles: Vec<String>) -> Result<Token> {
         let now = Utc::now();
@@ -107,11 +125,8 @@ impl AuthManager {
     /// Validate a token
     pub async fn validate_token(&self, token: &str) -> Result<Claims> {
         // Check if the token is revoked
-        {
-            let revoked_tokens = self.revoked_tokens.read().await;
-            if revoked_tokens.contains(&token.to_string()) {
-                return Err(Error::PermissionDenied("Token has been revoked".to_string()));
-            }
+        if self.revocations.is_revoked(token) {
+            return Err(Error::PermissionDenied("Token has been revoked".to_string()));
         }
         
         // Validate the token
@@ -125,9 +140,14 @@ impl AuthManager {
     }
     
     /// Revoke a token
+    ///
+    /// The entry is kept until the token expires; tokens this relay cannot
+    /// decode are kept for the longest a token of this relay lives.
     pub async fn revoke_token(&self, token: &str) -> Result<()> {
-        let mut revoked_tokens = self.revoked_tokens.write().await;
-        revoked_tokens.push(token.to_string());
+        let expires_at = revocation::token_expiry(token)
+            .unwrap_or_else(|| Utc::now().timestamp() + self.token_expiry);
+        
+        self.revocations.revoke(token, expires_at)?;
         Ok(())
     }
     
@@ -148,17 +168,7 @@ impl AuthManager {
     
     /// Clean up expired revoked tokens
     pub async fn cleanup_revoked_tokens(&self) -> Result<()> {
-        let mut revoked_tokens = self.revoked_tokens.write().await;
-        let now = Utc::now().timestamp();
-        
-        // Keep only tokens that are not expired
-        // This is a simplification - in a real implementation, we would store the expiration time
-        // with each revoked token and remove only those that are expired
-        if revoked_tokens.len() > 1000 {
-            revoked_tokens.clear();
-            debug!("Cleared revoked tokens cache (exceeded 1000 entries)");
-        }
-        
+        self.revocations.prune(Utc::now().timestamp())?;
         Ok(())
     }
 }
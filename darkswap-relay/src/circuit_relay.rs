@@ -5,6 +5,7 @@
 
 use crate::{
     config::Config,
+    drain::DrainController,
     error::Error,
     Result,
 };
@@ -181,6 +182,8 @@ pub struct CircuitRelayManager {
     webrtc_sender: mpsc::Sender<crate::webrtc::WebRtcTransportEvent>,
     /// WebRTC event receiver
     webrtc_receiver: mpsc::Receiver<crate::webrtc::WebRtcTransportEvent>,
+    /// Drain switch
+    drain: Arc<DrainController>,
 }
 
 impl CircuitRelayManager {
@@ -201,9 +204,16 @@ impl CircuitRelayManager {
             event_receiver: rx,
             webrtc_sender: tx,
             webrtc_receiver,
+            drain: Arc::new(DrainController::new()),
         })
     }
     
+    /// Share a drain switch with the rest of the server
+    pub fn with_drain(mut self, drain: Arc<DrainController>) -> Self {
+        self.drain = drain;
+        self
+    }
+    
     /// Run the circuit relay manager
     pub async fn run(&mut self) -> Result<()> {
         // Spawn a task to clean up expired reservations
@@ -309,6 +319,13 @@ impl CircuitRelayManager {
                                 continue;
                             }
                             
+                            // No new circuits while draining
+                            if self.drain.is_draining() {
+                                self.reservations.remove(&circuit_id);
+                                warn!("Dropped reservation while draining: {}", circuit_id);
+                                continue;
+                            }
+                            
                             // Check if the reservation exists
                             if let Some(reservation) = self.reservations.get(&circuit_id) {
                                 // Check if the source and destination match
@@ -491,6 +508,11 @@ impl CircuitRelayManager {
     
     /// Create a circuit
     pub async fn create_circuit(&self, src: &str, dst: &str) -> Result<String> {
+        // No new reservations while draining
+        if self.drain.is_draining() {
+            return Err(Error::Draining);
+        }
+        
         // Check if the source peer has reached the maximum number of circuits
         let src_circuits = self.peer_circuits.get(src).map(|circuits| circuits.len()).unwrap_or(0);
         if src_circuits >= self.config.max_circuits_per_peer() {
@@ -598,6 +620,16 @@ impl CircuitRelayManager {
         Ok(())
     }
     
+    /// Get the number of circuits and reservations that are still open
+    pub fn active_circuits(&self) -> usize {
+        let circuits = self.circuits
+            .iter()
+            .filter(|entry| entry.state != CircuitState::Closed)
+            .count();
+        
+        circuits + self.reservations.len()
+    }
+    
     /// Get metrics
     pub fn get_metrics(&self) -> (usize, usize, usize) {
         (
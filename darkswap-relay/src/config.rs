@@ -6,6 +6,7 @@ use crate::{
     error::Error,
     Result,
 };
+use darkswap_support::secrets::SecretResolver;
 use serde::{Deserialize, Serialize};
 use std::{
     fs::File,
@@ -51,6 +52,34 @@ pub struct WebRtcConfig {
     /// Data channel establishment timeout in seconds
     #[serde(default = "default_data_channel_establishment_timeout")]
     pub data_channel_establishment_timeout: u64,
+    /// Regional STUN/TURN servers, handed out instead of the global ones to peers in the region
+    #[serde(default)]
+    pub regions: Vec<IceRegionConfig>,
+    /// Header with the peer's ISO country code, set by a CDN in front of the relay
+    #[serde(default)]
+    pub country_header: Option<String>,
+    /// Take the peer address from `X-Forwarded-For` (only behind a trusted proxy)
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+}
+
+/// Regional ICE server configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IceRegionConfig {
+    /// Region name
+    pub name: String,
+    /// Peer networks in the region (CIDR)
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// Peer country codes in the region
+    #[serde(default)]
+    pub countries: Vec<String>,
+    /// STUN servers
+    #[serde(default)]
+    pub stun_servers: Vec<String>,
+    /// TURN servers
+    #[serde(default)]
+    pub turn_servers: Vec<TurnServer>,
 }
 
 /// Network configuration
@@ -117,6 +146,45 @@ pub struct RelayConfig {
     pub reservation_cleanup_interval: u64,
 }
 
+/// Orderbook snapshot configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotConfig {
+    /// Cache and serve orderbook snapshots
+    #[serde(default)]
+    pub enabled: bool,
+    /// Maximum snapshot age in seconds
+    #[serde(default = "default_snapshot_max_age")]
+    pub max_age: u64,
+    /// Maximum snapshot size in bytes
+    #[serde(default = "default_snapshot_max_size")]
+    pub max_size: usize,
+}
+
+/// Drain configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrainConfig {
+    /// Time to wait for open circuits to close before exiting, in seconds
+    #[serde(default = "default_drain_timeout")]
+    pub timeout: u64,
+    /// Bearer token for the admin drain endpoint, or `keyring:<entry>`; the endpoint is disabled if unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+/// Token revocation configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevocationConfig {
+    /// File to keep revoked tokens in; they are kept in memory if unset
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Base URLs of the metrics servers of peer relays to pull revocations from
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Interval between pulls from the peer relays in seconds
+    #[serde(default = "default_revocation_sync_interval")]
+    pub sync_interval: u64,
+}
+
 /// Configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -132,6 +200,15 @@ pub struct Config {
     /// Relay configuration
     #[serde(default)]
     pub relay: RelayConfig,
+    /// Orderbook snapshot configuration
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    /// Drain configuration
+    #[serde(default)]
+    pub drain: DrainConfig,
+    /// Token revocation configuration
+    #[serde(default)]
+    pub revocation: RevocationConfig,
     /// Enable metrics
     #[serde(default = "default_enable_metrics")]
     pub enable_metrics: bool,
@@ -148,6 +225,9 @@ impl Default for WebRtcConfig {
             ice_gathering_timeout: default_ice_gathering_timeout(),
             connection_establishment_timeout: default_connection_establishment_timeout(),
             data_channel_establishment_timeout: default_data_channel_establishment_timeout(),
+            regions: Vec::new(),
+            country_header: None,
+            trust_forwarded_for: false,
         }
     }
 }
@@ -190,6 +270,35 @@ impl Default for RelayConfig {
     }
 }
 
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age: default_snapshot_max_age(),
+            max_size: default_snapshot_max_size(),
+        }
+    }
+}
+
+impl Default for DrainConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_drain_timeout(),
+            admin_token: None,
+        }
+    }
+}
+
+impl Default for RevocationConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            peers: Vec::new(),
+            sync_interval: default_revocation_sync_interval(),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -197,6 +306,9 @@ impl Default for Config {
             network: NetworkConfig::default(),
             security: SecurityConfig::default(),
             relay: RelayConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            drain: DrainConfig::default(),
+            revocation: RevocationConfig::default(),
             enable_metrics: default_enable_metrics(),
         }
     }
@@ -213,7 +325,10 @@ impl Config {
         file.read_to_string(&mut contents)?;
         
         // Parse the file
-        let config: Config = toml::from_str(&contents)?;
+        let mut config: Config = toml::from_str(&contents)?;
+        
+        // Replace keyring references by the secrets they name
+        SecretResolver::new().resolve_in_place(&mut config.drain.admin_token)?;
         
         Ok(config)
     }
@@ -267,6 +382,16 @@ impl Config {
     pub fn reservation_cleanup_interval(&self) -> Duration {
         Duration::from_secs(self.relay.reservation_cleanup_interval)
     }
+    
+    /// Get the maximum orderbook snapshot age
+    pub fn snapshot_max_age(&self) -> Duration {
+        Duration::from_secs(self.snapshot.max_age)
+    }
+    
+    /// Get the drain timeout
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain.timeout)
+    }
 }
 
 // Default values
@@ -338,6 +463,22 @@ fn default_reservation_cleanup_interval() -> u64 {
     300
 }
 
+fn default_snapshot_max_age() -> u64 {
+    300
+}
+
+fn default_snapshot_max_size() -> usize {
+    1024 * 1024
+}
+
+fn default_drain_timeout() -> u64 {
+    600
+}
+
+fn default_revocation_sync_interval() -> u64 {
+    60
+}
+
 fn default_enable_metrics() -> bool {
     true
 }
\ No newline at end of file
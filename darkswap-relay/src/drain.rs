@@ -0,0 +1,114 @@
+//! Graceful draining for the DarkSwap Relay Server
+//!
+//! Before a relay is upgraded it is taken out of DNS rotation and drained: it stops
+//! accepting new reservations and circuits, waits for the existing circuits to
+//! close, and then exits. Circuits still open at the drain deadline are cut.
+//! Draining is started by SIGTERM or ctrl-c, or by `POST /admin/drain` on the
+//! metrics port when an admin token is configured.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+use tracing::info;
+
+/// Drain status reported by the admin endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrainStatus {
+    /// Whether the relay is draining
+    pub draining: bool,
+    /// Circuits and reservations still open
+    pub active_circuits: usize,
+}
+
+/// Drain switch shared by the relay components
+#[derive(Debug, Default)]
+pub struct DrainController {
+    /// Whether draining has started
+    draining: AtomicBool,
+    /// Wakes the server when draining starts
+    notify: Notify,
+}
+
+impl DrainController {
+    /// Create a new drain controller
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start draining, returning false if it had already started
+    pub fn start(&self) -> bool {
+        if self.draining.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+
+        info!("Draining relay: no longer accepting new circuits");
+        self.notify.notify_waiters();
+        true
+    }
+
+    /// Check if the relay is draining
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Wait until draining starts
+    pub async fn started(&self) {
+        loop {
+            let notified = self.notify.notified();
+            if self.is_draining() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Check an admin request's `Authorization` header against the configured token
+///
+/// Without a configured token the admin endpoint is disabled.
+pub fn is_authorized(admin_token: Option<&str>, authorization: Option<&str>) -> bool {
+    match (admin_token, authorization.and_then(|header| header.strip_prefix("Bearer "))) {
+        (Some(expected), Some(token)) => {
+            // Constant-time comparison
+            expected.len() == token.len()
+                && expected.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_drain_starts_once() {
+        let drain = Arc::new(DrainController::new());
+        assert!(!drain.is_draining());
+
+        let waiter = {
+            let drain = drain.clone();
+            tokio::spawn(async move { drain.started().await })
+        };
+
+        assert!(drain.start());
+        assert!(!drain.start());
+        assert!(drain.is_draining());
+
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+
+        // Waiting after the start returns immediately
+        tokio::time::timeout(Duration::from_secs(1), drain.started()).await.unwrap();
+    }
+
+    #[test]
+    fn test_admin_authorization() {
+        assert!(is_authorized(Some("secret"), Some("Bearer secret")));
+        assert!(!is_authorized(Some("secret"), Some("Bearer other")));
+        assert!(!is_authorized(Some("secret"), Some("secret")));
+        assert!(!is_authorized(Some("secret"), None));
+        assert!(!is_authorized(None, Some("Bearer secret")));
+    }
+}
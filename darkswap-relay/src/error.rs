@@ -51,6 +51,10 @@ pub enum Error {
     #[error("Circuit limit exceeded: {0}")]
     CircuitLimitExceeded(String),
     
+    /// Relay is draining
+    #[error("Relay is draining and not accepting new circuits")]
+    Draining,
+    
     /// Permission denied
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
@@ -67,6 +71,10 @@ pub enum Error {
     #[error("Prometheus error: {0}")]
     Prometheus(#[from] prometheus::Error),
     
+    /// Secret error
+    #[error("Secret error: {0}")]
+    Secret(#[from] darkswap_support::secrets::SecretError),
+    
     /// Other error
     #[error("Other error: {0}")]
     Other(String),
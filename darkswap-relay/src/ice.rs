@@ -0,0 +1,243 @@
+//! Region-aware ICE server distribution for the DarkSwap Relay Server
+//!
+//! Peers set up WebRTC connections faster with STUN and TURN servers close to
+//! them. The relay maps each connecting peer to a configured region, by its IP
+//! address or by a country code set by a CDN in front of the relay, and hands out
+//! that region's ICE servers. Peers that match no region get the global list.
+
+use crate::{
+    config::{Config, IceRegionConfig, TurnServer},
+    error::Error,
+    Result,
+};
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+
+/// ICE servers handed out to a peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IceServers {
+    /// Region the servers were chosen for; none for the global list
+    pub region: Option<String>,
+    /// STUN server URLs
+    pub stun_servers: Vec<String>,
+    /// TURN servers
+    pub turn_servers: Vec<TurnServer>,
+}
+
+/// IP network in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IpNetwork {
+    /// Network address
+    addr: IpAddr,
+    /// Prefix length
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Parse a network such as `203.0.113.0/24` or `2001:db8::/32`
+    fn parse(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.trim().parse()
+            .map_err(|_| Error::Other(format!("Invalid network address: {}", s)))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| Error::Other(format!("Invalid network prefix: {}", s)))?,
+            None => max,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+
+    /// Check if the network contains an address
+    fn contains(&self, ip: IpAddr) -> bool {
+        // Compare IPv4-mapped IPv6 addresses as IPv4
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Region with its networks
+#[derive(Debug, Clone)]
+struct Region {
+    /// Region configuration
+    config: IceRegionConfig,
+    /// Parsed networks
+    networks: Vec<IpNetwork>,
+}
+
+/// Maps peers to the ICE servers of their region
+#[derive(Debug, Clone)]
+pub struct IceRegistry {
+    /// Regions, matched in order
+    regions: Vec<Region>,
+    /// Global STUN servers
+    stun_servers: Vec<String>,
+    /// Global TURN servers
+    turn_servers: Vec<TurnServer>,
+    /// Header carrying the peer's country code, set by a CDN
+    country_header: Option<String>,
+    /// Whether to take the peer address from `X-Forwarded-For`
+    trust_forwarded_for: bool,
+}
+
+impl IceRegistry {
+    /// Create a registry from the WebRTC configuration
+    pub fn new(config: &Config) -> Result<Self> {
+        let regions = config.webrtc.regions
+            .iter()
+            .map(|region| {
+                let networks = region.networks
+                    .iter()
+                    .map(|network| IpNetwork::parse(network))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Region { config: region.clone(), networks })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            regions,
+            stun_servers: config.webrtc.stun_servers.clone(),
+            turn_servers: config.webrtc.turn_servers.clone(),
+            country_header: config.webrtc.country_header.clone(),
+            trust_forwarded_for: config.webrtc.trust_forwarded_for,
+        })
+    }
+
+    /// Get the ICE servers for a peer by address and country code
+    ///
+    /// Networks are matched before countries, and regions in configuration order.
+    pub fn lookup(&self, ip: Option<IpAddr>, country: Option<&str>) -> IceServers {
+        let by_network = ip.and_then(|ip| {
+            self.regions.iter().find(|region| region.networks.iter().any(|network| network.contains(ip)))
+        });
+        let by_country = || country.and_then(|country| {
+            self.regions.iter().find(|region| {
+                region.config.countries.iter().any(|c| c.eq_ignore_ascii_case(country.trim()))
+            })
+        });
+
+        match by_network.or_else(by_country) {
+            Some(region) => IceServers {
+                region: Some(region.config.name.clone()),
+                stun_servers: region.config.stun_servers.clone(),
+                turn_servers: region.config.turn_servers.clone(),
+            },
+            None => IceServers {
+                region: None,
+                stun_servers: self.stun_servers.clone(),
+                turn_servers: self.turn_servers.clone(),
+            },
+        }
+    }
+
+    /// Get the ICE servers for a request
+    pub fn lookup_request(&self, remote: SocketAddr, headers: &HeaderMap) -> IceServers {
+        let forwarded = if self.trust_forwarded_for {
+            headers.get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+        } else {
+            None
+        };
+
+        let country = self.country_header.as_ref()
+            .and_then(|header| headers.get(header.as_str()))
+            .and_then(|value| value.to_str().ok());
+
+        self.lookup(Some(forwarded.unwrap_or_else(|| remote.ip())), country)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> IceRegistry {
+        let mut config = Config::default();
+        config.webrtc.regions = vec![
+            IceRegionConfig {
+                name: "eu".to_string(),
+                networks: vec!["203.0.113.0/24".to_string(), "2001:db8::/32".to_string()],
+                countries: vec!["DE".to_string(), "FR".to_string()],
+                stun_servers: vec!["stun:eu.example.com:3478".to_string()],
+                turn_servers: Vec::new(),
+            },
+            IceRegionConfig {
+                name: "asia".to_string(),
+                networks: vec!["198.51.100.0/24".to_string()],
+                countries: vec!["JP".to_string()],
+                stun_servers: vec!["stun:asia.example.com:3478".to_string()],
+                turn_servers: Vec::new(),
+            },
+        ];
+        config.webrtc.country_header = Some("cf-ipcountry".to_string());
+
+        IceRegistry::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_network_matching() {
+        let network = IpNetwork::parse("203.0.113.0/24").unwrap();
+        assert!(network.contains("203.0.113.77".parse().unwrap()));
+        assert!(network.contains("::ffff:203.0.113.77".parse().unwrap()));
+        assert!(!network.contains("203.0.114.1".parse().unwrap()));
+
+        assert!(IpNetwork::parse("0.0.0.0/0").unwrap().contains("192.0.2.1".parse().unwrap()));
+        assert!(IpNetwork::parse("203.0.113.0/33").is_err());
+    }
+
+    #[test]
+    fn test_region_lookup() {
+        let registry = registry();
+
+        let servers = registry.lookup(Some("198.51.100.9".parse().unwrap()), Some("DE"));
+        assert_eq!(servers.region.as_deref(), Some("asia"));
+
+        let servers = registry.lookup(Some("192.0.2.1".parse().unwrap()), Some("fr"));
+        assert_eq!(servers.region.as_deref(), Some("eu"));
+
+        let servers = registry.lookup(Some("2001:db8::1".parse().unwrap()), None);
+        assert_eq!(servers.region.as_deref(), Some("eu"));
+
+        let servers = registry.lookup(Some("192.0.2.1".parse().unwrap()), Some("US"));
+        assert_eq!(servers.region, None);
+        assert_eq!(servers.stun_servers, Config::default().webrtc.stun_servers);
+    }
+
+    #[test]
+    fn test_request_lookup() {
+        let registry = registry();
+        let remote: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.9".parse().unwrap());
+        // Forwarded addresses are ignored unless trusted
+        assert_eq!(registry.lookup_request(remote, &headers).region, None);
+
+        headers.insert("cf-ipcountry", "JP".parse().unwrap());
+        assert_eq!(registry.lookup_request(remote, &headers).region.as_deref(), Some("asia"));
+    }
+}
@@ -5,12 +5,16 @@
 //! allowing peers to connect to each other even when behind NATs.
 
 pub mod config;
+pub mod drain;
 pub mod error;
+pub mod ice;
 pub mod server;
 pub mod signaling;
 pub mod circuit;
 pub mod webrtc;
 pub mod metrics;
+pub mod revocation;
+pub mod snapshot;
 pub mod utils;
 
 use error::Error;
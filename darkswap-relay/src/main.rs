@@ -6,11 +6,15 @@
 mod auth;
 mod circuit_relay;
 mod config;
+mod drain;
 mod error;
+mod ice;
 mod metrics;
 mod rate_limit;
+mod revocation;
 mod server;
 mod signaling;
+mod snapshot;
 mod utils;
 mod webrtc;
 
@@ -67,6 +71,33 @@ enum Commands {
         #[clap(short, long, value_parser)]
         output: PathBuf,
     },
+    /// Drain a running relay before an upgrade
+    Drain {
+        /// Config file of the running relay
+        #[clap(short, long, value_parser)]
+        config: Option<PathBuf>,
+        /// Metrics URL of the running relay
+        #[clap(short, long, value_parser, default_value = "http://127.0.0.1:9090")]
+        url: String,
+    },
+    /// Revoke a token on a running relay
+    RevokeToken {
+        /// Token to revoke
+        #[clap(short, long, value_parser)]
+        token: Option<String>,
+        /// ID of the token to revoke, in place of the token
+        #[clap(short, long, value_parser)]
+        id: Option<String>,
+        /// Time the token expires (unix seconds); read from the token if unset
+        #[clap(short, long, value_parser)]
+        expires_at: Option<i64>,
+        /// Config file of the running relay
+        #[clap(short, long, value_parser)]
+        config: Option<PathBuf>,
+        /// Metrics URL of the running relay
+        #[clap(short, long, value_parser, default_value = "http://127.0.0.1:9090")]
+        url: String,
+    },
     /// Generate a token
     GenerateToken {
         /// Peer ID
@@ -131,6 +162,69 @@ async fn main() -> Result<()> {
             
             info!("Generated config file: {}", output.display());
         }
+        Some(Commands::Drain { config, url }) => {
+            // Load the config
+            let config = match config.or(args.config) {
+                Some(path) => Config::from_file(path)?,
+                None => Config::default(),
+            };
+            
+            let token = config.drain.admin_token
+                .ok_or_else(|| Error::Other("No drain admin token configured".to_string()))?;
+            
+            // Ask the relay to drain
+            let response = reqwest::Client::new()
+                .post(format!("{}/admin/drain", url.trim_end_matches('/')))
+                .bearer_auth(token)
+                .send()
+                .await
+                .map_err(|e| Error::Connection(e.to_string()))?;
+            
+            if !response.status().is_success() {
+                return Err(Error::Other(format!("Drain request failed: {}", response.status())));
+            }
+            
+            let status: drain::DrainStatus = response.json()
+                .await
+                .map_err(|e| Error::Other(e.to_string()))?;
+            
+            println!("Draining: {} circuits still open", status.active_circuits);
+        }
+        Some(Commands::RevokeToken { token, id, expires_at, config, url }) => {
+            // Load the config
+            let config = match config.or(args.config) {
+                Some(path) => Config::from_file(path)?,
+                None => Config::default(),
+            };
+            
+            let admin_token = config.drain.admin_token
+                .ok_or_else(|| Error::Other("No drain admin token configured".to_string()))?;
+            
+            // Ask the relay to revoke the token
+            let response = reqwest::Client::new()
+                .post(format!("{}/admin/revocations", url.trim_end_matches('/')))
+                .bearer_auth(admin_token)
+                .json(&revocation::RevokeRequest { token, id, expires_at })
+                .send()
+                .await
+                .map_err(|e| Error::Connection(e.to_string()))?;
+            
+            if !response.status().is_success() {
+                let status = response.status();
+                let message = response.text().await.unwrap_or_default();
+                return Err(Error::Other(format!("Revoke request failed: {} {}", status, message)));
+            }
+            
+            let revoked: revocation::RevokeResponse = response.json()
+                .await
+                .map_err(|e| Error::Other(e.to_string()))?;
+            
+            if revoked.newly_revoked {
+                println!("Revoked token {}", revoked.id);
+            } else {
+                println!("Token {} was already revoked", revoked.id);
+            }
+        }
         Some(Commands::GenerateToken { peer_id, roles, config }) => {
             // Load the config
             let config = match config {
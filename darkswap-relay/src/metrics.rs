@@ -8,6 +8,8 @@ use crate::{
     error::Error,
     webrtc::WebRtcManager,
     circuit_relay::CircuitRelayManager,
+    drain::{self, DrainController, DrainStatus},
+    revocation::{RevocationList, RevokeRequest},
     Result,
 };
 use prometheus::{
@@ -29,6 +31,10 @@ pub struct MetricsServer {
     webrtc_manager: Arc<WebRtcManager>,
     /// Circuit relay manager
     circuit_manager: Arc<CircuitRelayManager>,
+    /// Drain switch
+    drain: Arc<DrainController>,
+    /// Revoked auth tokens
+    revocations: Arc<RevocationList>,
     
     // WebRTC metrics
     /// Number of WebRTC connections
@@ -129,6 +135,8 @@ impl MetricsServer {
             registry,
             webrtc_manager,
             circuit_manager,
+            drain: Arc::new(DrainController::new()),
+            revocations: Arc::new(RevocationList::new()),
             webrtc_connections,
             webrtc_peers,
             webrtc_data_channels,
@@ -148,6 +156,18 @@ impl MetricsServer {
         })
     }
     
+    /// Serve the admin drain endpoint for a shared drain switch
+    pub fn with_drain(mut self, drain: Arc<DrainController>) -> Self {
+        self.drain = drain;
+        self
+    }
+    
+    /// Serve and revoke into a shared revocation list
+    pub fn with_revocations(mut self, revocations: Arc<RevocationList>) -> Self {
+        self.revocations = revocations;
+        self
+    }
+    
     /// Run the metrics server
     pub async fn run(self) -> Result<()> {
         // Get address
@@ -163,6 +183,59 @@ impl MetricsServer {
             String::from_utf8(buffer).unwrap()
         });
         
+        // Admin drain endpoint
+        let drain_switch = self.drain.clone();
+        let drain_circuits = self.circuit_manager.clone();
+        let admin_token = self.config.drain.admin_token.clone();
+        let drain_route = warp::path!("admin" / "drain")
+            .and(warp::post())
+            .and(warp::header::optional::<String>("authorization"))
+            .map(move |authorization: Option<String>| {
+                if !drain::is_authorized(admin_token.as_deref(), authorization.as_deref()) {
+                    return warp::reply::with_status(
+                        warp::reply::json(&"Unauthorized"),
+                        warp::http::StatusCode::UNAUTHORIZED,
+                    );
+                }
+                
+                drain_switch.start();
+                let status = DrainStatus {
+                    draining: drain_switch.is_draining(),
+                    active_circuits: drain_circuits.active_circuits(),
+                };
+                warp::reply::with_status(warp::reply::json(&status), warp::http::StatusCode::ACCEPTED)
+            });
+        
+        // Revocation list, pulled by peer relays; it holds token hashes only
+        let revocation_list = self.revocations.clone();
+        let revocations_route = warp::path("revocations")
+            .and(warp::get())
+            .map(move || warp::reply::json(&revocation_list.entries()));
+        
+        // Admin revoke endpoint, behind the drain admin token
+        let revoke_list = self.revocations.clone();
+        let revoke_admin_token = self.config.drain.admin_token.clone();
+        let revoke_route = warp::path!("admin" / "revocations")
+            .and(warp::post())
+            .and(warp::header::optional::<String>("authorization"))
+            .and(warp::body::json())
+            .map(move |authorization: Option<String>, request: RevokeRequest| {
+                if !drain::is_authorized(revoke_admin_token.as_deref(), authorization.as_deref()) {
+                    return warp::reply::with_status(
+                        warp::reply::json(&"Unauthorized"),
+                        warp::http::StatusCode::UNAUTHORIZED,
+                    );
+                }
+                
+                match revoke_list.handle_request(request) {
+                    Ok(response) => warp::reply::with_status(warp::reply::json(&response), warp::http::StatusCode::OK),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&e.to_string()),
+                        warp::http::StatusCode::BAD_REQUEST,
+                    ),
+                }
+            });
+        
         // Start the server
         info!("Starting metrics server on {}", addr);
         
@@ -195,7 +268,7 @@ impl MetricsServer {
         });
         
         // Start the server
-        warp::serve(metrics_route).run(addr).await;
+        warp::serve(metrics_route.or(drain_route).or(revocations_route).or(revoke_route)).run(addr).await;
         
         Ok(())
     }
@@ -0,0 +1,319 @@
+//! Auth token revocation list for the DarkSwap Relay Server
+//!
+//! Tokens handed out by the relay are valid until they expire. Revoking one
+//! adds it to this list, which the signaling server checks on every request,
+//! so a leaked token stops working at once rather than at its expiry. Entries
+//! hold the SHA-256 of a token rather than the token itself, are kept until
+//! the token would have expired anyway, and are written to a file so a
+//! restart does not bring revoked tokens back.
+//!
+//! Relays sharing a token secret can share their lists too: each serves its
+//! list at `GET /revocations` on the metrics port and pulls the lists of the
+//! peers in its configuration, so a token revoked on one relay is refused by
+//! all of them after the next sync.
+
+use crate::{
+    config::RevocationConfig,
+    error::Error,
+    Result,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Revoked token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevokedToken {
+    /// SHA-256 of the token (hex)
+    pub id: String,
+    /// Time the token expires, after which the entry is dropped (unix seconds)
+    pub expires_at: i64,
+}
+
+/// Admin request to revoke a token, by value or by ID
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeRequest {
+    /// Token
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Token ID, in place of the token
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Time the token expires (unix seconds); read from the token if unset
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+/// Answer to a revoke request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeResponse {
+    /// Token ID
+    pub id: String,
+    /// Whether the token was not revoked before
+    pub newly_revoked: bool,
+}
+
+/// Get the ID a token is revoked under
+pub fn token_id(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Read the expiry of a token from its claims, without checking its signature
+///
+/// Good enough to know how long to keep a revocation; returns `None` for
+/// anything that is not a JWT with an `exp` claim.
+pub fn token_expiry(token: &str) -> Option<i64> {
+    let payload = token.split('.').nth(1)?;
+    let claims = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    serde_json::from_slice::<serde_json::Value>(&claims).ok()?["exp"].as_i64()
+}
+
+/// Check that an ID is a hex SHA-256
+fn is_token_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// Revoked tokens
+pub struct RevocationList {
+    /// Expiry of each revoked token, by ID
+    entries: DashMap<String, i64>,
+    /// File the list is kept in; kept in memory if unset
+    path: Option<PathBuf>,
+}
+
+impl RevocationList {
+    /// Create an empty list kept in memory
+    pub fn new() -> Self {
+        Self {
+            entries: DashMap::new(),
+            path: None,
+        }
+    }
+
+    /// Open the list kept in the configured file, if any
+    pub fn open(config: &RevocationConfig) -> Result<Self> {
+        let Some(path) = &config.path else { return Ok(Self::new()) };
+        let path = PathBuf::from(path);
+
+        let list = Self {
+            entries: DashMap::new(),
+            path: Some(path.clone()),
+        };
+        if path.exists() {
+            let revoked: Vec<RevokedToken> = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+            for token in revoked {
+                list.entries.insert(token.id, token.expires_at);
+            }
+        }
+        list.prune(Utc::now().timestamp())?;
+
+        Ok(list)
+    }
+
+    /// Write the list to its file
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+
+        // Write next to the file and rename, so a crash never leaves half a list
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_string_pretty(&self.entries())?)?;
+        std::fs::rename(&temp, path)?;
+
+        Ok(())
+    }
+
+    /// Revoke a token expiring at `expires_at`, returning whether it was newly revoked
+    pub fn revoke(&self, token: &str, expires_at: i64) -> Result<bool> {
+        self.revoke_id(&token_id(token), expires_at)
+    }
+
+    /// Revoke a token by ID, e.g. one revoked on another relay
+    pub fn revoke_id(&self, id: &str, expires_at: i64) -> Result<bool> {
+        if !is_token_id(id) {
+            return Err(Error::Other(format!("Invalid token ID: {}", id)));
+        }
+        if expires_at <= Utc::now().timestamp() {
+            return Ok(false);
+        }
+
+        let added = self.entries.insert(id.to_ascii_lowercase(), expires_at).is_none();
+        self.save()?;
+
+        Ok(added)
+    }
+
+    /// Handle an admin revoke request
+    pub fn handle_request(&self, request: RevokeRequest) -> Result<RevokeResponse> {
+        let id = match (&request.token, request.id) {
+            (Some(token), _) => token_id(token),
+            (None, Some(id)) => id.to_ascii_lowercase(),
+            (None, None) => return Err(Error::Other("Pass a token or a token ID".to_string())),
+        };
+        let expires_at = request.expires_at
+            .or_else(|| request.token.as_deref().and_then(token_expiry))
+            .ok_or_else(|| Error::Other("The token expiry is unknown, pass expires_at".to_string()))?;
+
+        let newly_revoked = self.revoke_id(&id, expires_at)?;
+        info!("Revoked token {}", id);
+
+        Ok(RevokeResponse { id, newly_revoked })
+    }
+
+    /// Check if a token is revoked
+    pub fn is_revoked(&self, token: &str) -> bool {
+        self.entries.contains_key(&token_id(token))
+    }
+
+    /// Add the entries of another list, returning how many were new
+    pub fn merge(&self, revoked: Vec<RevokedToken>, now: i64) -> Result<usize> {
+        let mut added = 0;
+        for token in revoked {
+            if !is_token_id(&token.id) {
+                warn!("Skipping invalid revoked token ID: {}", token.id);
+                continue;
+            }
+            let id = token.id.to_ascii_lowercase();
+            if token.expires_at <= now || self.entries.contains_key(&id) {
+                continue;
+            }
+            self.entries.insert(id, token.expires_at);
+            added += 1;
+        }
+        if added > 0 {
+            self.save()?;
+        }
+
+        Ok(added)
+    }
+
+    /// Drop the entries of tokens expired at `now`, returning how many
+    pub fn prune(&self, now: i64) -> Result<usize> {
+        let before = self.entries.len();
+        self.entries.retain(|_, expires_at| *expires_at > now);
+        let pruned = before - self.entries.len();
+        if pruned > 0 {
+            debug!("Pruned {} expired revoked tokens", pruned);
+            self.save()?;
+        }
+
+        Ok(pruned)
+    }
+
+    /// Get the revoked tokens, ordered by ID
+    pub fn entries(&self) -> Vec<RevokedToken> {
+        let mut entries: Vec<RevokedToken> = self.entries
+            .iter()
+            .map(|entry| RevokedToken { id: entry.key().clone(), expires_at: *entry.value() })
+            .collect();
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        entries
+    }
+
+    /// Get the number of revoked tokens
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no token is revoked
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for RevocationList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Pull the revocation lists of the peer relays every `interval`, and prune expired entries
+pub async fn sync_with_peers(list: Arc<RevocationList>, peers: Vec<String>, interval: Duration) {
+    let client = reqwest::Client::new();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        for peer in &peers {
+            let url = format!("{}/revocations", peer.trim_end_matches('/'));
+            let revoked = match client.get(&url).timeout(interval).send().await {
+                Ok(response) => response.json::<Vec<RevokedToken>>().await,
+                Err(e) => Err(e),
+            };
+
+            match revoked {
+                Ok(revoked) => match list.merge(revoked, Utc::now().timestamp()) {
+                    Ok(0) => {}
+                    Ok(added) => info!("Revoked {} tokens from {}", added, peer),
+                    Err(e) => warn!("Failed to merge the revocations of {}: {}", peer, e),
+                },
+                Err(e) => warn!("Failed to fetch the revocations of {}: {}", peer, e),
+            }
+        }
+
+        if let Err(e) = list.prune(Utc::now().timestamp()) {
+            warn!("Failed to prune revoked tokens: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoke_and_merge() {
+        let now = Utc::now().timestamp();
+        let list = RevocationList::new();
+
+        assert!(list.revoke("token-a", now + 3600).unwrap());
+        assert!(!list.revoke("token-a", now + 3600).unwrap());
+        assert!(list.is_revoked("token-a"));
+        assert!(!list.is_revoked("token-b"));
+
+        // Expired tokens need no entry
+        assert!(!list.revoke("token-c", now - 1).unwrap());
+
+        // Lists from other relays add what is new and still live
+        let other = RevocationList::new();
+        other.revoke("token-b", now + 60).unwrap();
+        let mut revoked = other.entries();
+        revoked.push(RevokedToken { id: token_id("token-d"), expires_at: now - 1 });
+        revoked.push(RevokedToken { id: "not-a-hash".to_string(), expires_at: now + 60 });
+        assert_eq!(list.merge(revoked, now).unwrap(), 1);
+        assert!(list.is_revoked("token-b"));
+
+        assert_eq!(list.prune(now + 120).unwrap(), 1);
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn test_token_expiry() {
+        let claims = URL_SAFE_NO_PAD.encode(br#"{"sub":"peer","exp":1700000000}"#);
+        assert_eq!(token_expiry(&format!("eyJhbGciOiJIUzI1NiJ9.{}.c2ln", claims)), Some(1_700_000_000));
+        assert_eq!(token_expiry("not-a-jwt"), None);
+    }
+
+    #[test]
+    fn test_list_survives_restart() {
+        let path = std::env::temp_dir().join(format!("darkswap-revocations-{}.json", uuid::Uuid::new_v4()));
+        let config = RevocationConfig {
+            path: Some(path.to_string_lossy().into_owned()),
+            ..RevocationConfig::default()
+        };
+
+        RevocationList::open(&config).unwrap().revoke("token-a", Utc::now().timestamp() + 3600).unwrap();
+        assert!(RevocationList::open(&config).unwrap().is_revoked("token-a"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -8,12 +8,16 @@ use crate::{
     error::Error,
     signaling::SignalingServer,
     circuit_relay::{CircuitRelayManager, CircuitRelayEvent},
+    drain::DrainController,
     webrtc::{WebRtcManager, WebRtcTransportEvent},
     metrics::MetricsServer,
+    revocation,
     utils,
     Result,
 };
+use darkswap_support::systemd;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
@@ -34,6 +38,9 @@ pub struct Server {
     
     /// Metrics server (optional)
     metrics_server: Option<MetricsServer>,
+    
+    /// Drain switch
+    drain: Arc<DrainController>,
 }
 
 impl Server {
@@ -51,12 +58,15 @@ impl Server {
             circuit_rx,
         )?);
         
+        // Create the drain switch
+        let drain = Arc::new(DrainController::new());
+        
         // Create circuit relay manager
         let circuit_manager = Arc::new(CircuitRelayManager::new(
             config.clone(),
             circuit_tx,
             webrtc_rx,
-        )?);
+        )?.with_drain(drain.clone()));
         
         // Create signaling server
         let signaling_server = SignalingServer::new(
@@ -73,7 +83,9 @@ impl Server {
                 config.clone(),
                 webrtc_manager.clone(),
                 circuit_manager.clone(),
-            )?)
+            )?
+            .with_drain(drain.clone())
+            .with_revocations(signaling_server.revocations()))
         } else {
             None
         };
@@ -84,6 +96,7 @@ impl Server {
             circuit_manager,
             signaling_server,
             metrics_server,
+            drain,
         })
     }
     
@@ -91,6 +104,9 @@ impl Server {
     pub async fn start(self) -> Result<()> {
         info!("Starting DarkSwap Relay Server v{}", crate::VERSION);
         
+        // Drain on SIGTERM or ctrl-c
+        tokio::spawn(drain_on_signal(self.drain.clone()));
+        
         // Start the WebRTC manager
         let webrtc_handle = {
             let mut manager = self.webrtc_manager.clone();
@@ -111,6 +127,15 @@ impl Server {
             })
         };
         
+        // Pull the revocations of the peer relays
+        if !self.config.revocation.peers.is_empty() {
+            tokio::spawn(revocation::sync_with_peers(
+                self.signaling_server.revocations(),
+                self.config.revocation.peers.clone(),
+                Duration::from_secs(self.config.revocation.sync_interval.max(1)),
+            ));
+        }
+        
         // Start the signaling server
         let signaling_handle = {
             let server = self.signaling_server;
@@ -133,6 +158,12 @@ impl Server {
             None
         };
         
+        // Report readiness and liveness to systemd, if it started the relay
+        tokio::spawn(notify_when_listening(self.config.signaling_address()));
+        if let Some(timeout) = systemd::watchdog_timeout() {
+            tokio::spawn(ping_watchdog(timeout));
+        }
+        
         // Wait for all components to finish
         let mut handles: Vec<JoinHandle<()>> = vec![
             webrtc_handle,
@@ -144,8 +175,11 @@ impl Server {
             handles.push(handle);
         }
         
-        // Wait for any component to finish (which likely means an error occurred)
+        // Wait for any component to finish (which likely means an error occurred),
+        // or for a drain to complete
+        let drained = wait_for_drain(self.drain.clone(), self.circuit_manager.clone(), self.config.drain_timeout());
         tokio::select! {
+            _ = drained => {},
             _ = handles[0].await => {},
             _ = handles[1].await => {},
             _ = handles[2].await => {},
@@ -171,6 +205,95 @@ impl Server {
     pub fn config(&self) -> &Config {
         &self.config
     }
+    
+    /// Get the drain switch
+    pub fn drain(&self) -> Arc<DrainController> {
+        self.drain.clone()
+    }
+}
+
+/// Start draining when the process is asked to stop
+async fn drain_on_signal(drain: Arc<DrainController>) {
+    #[cfg(unix)]
+    {
+        let mut terminate = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                drain.start();
+                return;
+            }
+        };
+        
+        tokio::select! {
+            _ = terminate.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+    }
+    
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+    
+    if let Err(e) = systemd::stopping() {
+        warn!("Failed to notify the service manager: {}", e);
+    }
+    drain.start();
+}
+
+/// Tell the service manager the relay is ready once the signaling server accepts connections
+async fn notify_when_listening(address: String) {
+    loop {
+        if tokio::net::TcpStream::connect(&address).await.is_ok() {
+            info!("Signaling server listening on {}", address);
+            if let Err(e) = systemd::ready() {
+                warn!("Failed to notify the service manager: {}", e);
+            }
+            return;
+        }
+        
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+/// Ping the service manager watchdog twice per `timeout` while the runtime keeps scheduling tasks
+async fn ping_watchdog(timeout: Duration) {
+    let mut interval = tokio::time::interval(timeout / 2);
+    
+    loop {
+        interval.tick().await;
+        
+        if let Err(e) = systemd::watchdog() {
+            warn!("Failed to ping the watchdog: {}", e);
+        }
+    }
+}
+
+/// Once draining starts, wait for the open circuits to close or the deadline to pass
+async fn wait_for_drain(drain: Arc<DrainController>, circuit_manager: Arc<CircuitRelayManager>, timeout: Duration) {
+    drain.started().await;
+    
+    let deadline = Instant::now() + timeout;
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    
+    loop {
+        interval.tick().await;
+        
+        let active = circuit_manager.active_circuits();
+        if active == 0 {
+            info!("Relay drained");
+            return;
+        }
+        
+        if Instant::now() >= deadline {
+            warn!("Drain deadline reached, closing {} open circuits", active);
+            return;
+        }
+        
+        debug!("Draining: {} circuits still open", active);
+    }
 }
 
 /// Create a new server with default configuration
@@ -9,16 +9,23 @@ use crate::{
     webrtc::WebRtcManager,
     circuit::CircuitRelayManager,
     auth::{AuthManager, AuthMiddleware},
+    config::TurnServer,
+    ice::{IceRegistry, IceServers},
     rate_limit::{RateLimitManager, RateLimitMiddleware},
+    revocation::RevocationList,
+    snapshot::SnapshotCache,
     Result,
 };
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo,
         State,
     },
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     routing::get,
+    Json,
     Router,
 };
 use futures::{SinkExt, StreamExt};
@@ -41,6 +48,9 @@ pub enum SignalingMessage {
     Register {
         /// Peer ID
         peer_id: String,
+        /// Auth token, required when authentication is enabled
+        #[serde(default)]
+        token: Option<String>,
     },
     /// WebRTC offer
     Offer {
@@ -112,6 +122,29 @@ pub enum SignalingMessage {
         /// Relay ID
         relay_id: String,
     },
+    /// Publish a signed orderbook snapshot to the relay cache
+    PublishSnapshot {
+        /// Publisher peer ID
+        peer_id: String,
+        /// Snapshot JSON
+        snapshot: String,
+    },
+    /// Request the cached orderbook snapshots
+    GetSnapshots,
+    /// Cached orderbook snapshots
+    Snapshots {
+        /// Snapshot JSON documents
+        snapshots: Vec<String>,
+    },
+    /// ICE servers for the peer's region, sent on connect
+    IceServers {
+        /// Region the servers were chosen for
+        region: Option<String>,
+        /// STUN server URLs
+        stun_servers: Vec<String>,
+        /// TURN servers
+        turn_servers: Vec<TurnServer>,
+    },
     /// Error message
     Error {
         /// Error message
@@ -149,8 +182,14 @@ pub struct SignalingServer {
     circuit_manager: Arc<CircuitRelayManager>,
     /// Authentication middleware
     auth_middleware: Option<AuthMiddleware>,
+    /// Revoked auth tokens
+    revocations: Arc<RevocationList>,
     /// Rate limiting middleware
     rate_limit_middleware: Option<RateLimitMiddleware>,
+    /// Orderbook snapshot cache
+    snapshot_cache: Option<Arc<SnapshotCache>>,
+    /// Regional ICE servers
+    ice_registry: IceRegistry,
     /// Authentication enabled flag
     auth_enabled: bool,
     /// Rate limiting enabled flag
@@ -179,8 +218,9 @@ impl SignalingServer {
             .unwrap_or(false);
         
         // Create authentication middleware if enabled
+        let revocations = Arc::new(RevocationList::open(&config.revocation)?);
         let auth_middleware = if auth_enabled {
-            let auth_manager = Arc::new(AuthManager::new(config.clone())?);
+            let auth_manager = Arc::new(AuthManager::new(config.clone())?.with_revocations(revocations.clone()));
             Some(AuthMiddleware::new(auth_manager))
         } else {
             None
@@ -194,6 +234,15 @@ impl SignalingServer {
             None
         };
         
+        // Create orderbook snapshot cache if enabled
+        let snapshot_cache = if config.snapshot.enabled {
+            Some(Arc::new(SnapshotCache::new(&config)))
+        } else {
+            None
+        };
+        
+        let ice_registry = IceRegistry::new(&config)?;
+        
         Ok(Self {
             config,
             peers: Arc::new(Mutex::new(HashMap::new())),
@@ -202,7 +251,10 @@ impl SignalingServer {
             webrtc_manager,
             circuit_manager,
             auth_middleware,
+            revocations,
             rate_limit_middleware,
+            snapshot_cache,
+            ice_registry,
             auth_enabled,
             rate_limit_enabled,
         })
@@ -213,6 +265,8 @@ impl SignalingServer {
         // Create router
         let app = Router::new()
             .route("/signaling", get(Self::websocket_handler))
+            .route("/snapshots", get(Self::snapshots_handler))
+            .route("/ice-servers", get(Self::ice_servers_handler))
             .with_state(Arc::new(self));
         
         // Get address
@@ -223,7 +277,7 @@ impl SignalingServer {
         
         // Start the WebSocket server
         axum::Server::bind(&addr)
-            .serve(app.into_make_service())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
             .await?;
         
         Ok(())
@@ -232,13 +286,35 @@ impl SignalingServer {
     /// Handle WebSocket connections
     async fn websocket_handler(
         ws: WebSocketUpgrade,
+        ConnectInfo(remote): ConnectInfo<SocketAddr>,
+        headers: HeaderMap,
         State(state): State<Arc<Self>>,
     ) -> impl IntoResponse {
-        ws.on_upgrade(|socket| Self::handle_socket(socket, state))
+        let ice_servers = state.ice_registry.lookup_request(remote, &headers);
+        ws.on_upgrade(|socket| Self::handle_socket(socket, state, ice_servers))
+    }
+    
+    /// Serve the ICE servers for the requester's region over HTTP
+    async fn ice_servers_handler(
+        ConnectInfo(remote): ConnectInfo<SocketAddr>,
+        headers: HeaderMap,
+        State(state): State<Arc<Self>>,
+    ) -> Json<IceServers> {
+        Json(state.ice_registry.lookup_request(remote, &headers))
+    }
+    
+    /// Serve cached orderbook snapshots over HTTP
+    async fn snapshots_handler(
+        State(state): State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        match &state.snapshot_cache {
+            Some(cache) => (StatusCode::OK, Json(cache.get_snapshots())),
+            None => (StatusCode::NOT_FOUND, Json(Vec::new())),
+        }
     }
     
     /// Handle a WebSocket connection
-    async fn handle_socket(socket: WebSocket, state: Arc<Self>) {
+    async fn handle_socket(socket: WebSocket, state: Arc<Self>, ice_servers: IceServers) {
         let (mut sender, mut receiver) = socket.split();
         
         // Create a channel for sending messages to the WebSocket
@@ -275,6 +351,14 @@ impl SignalingServer {
             }
         });
         
+        // Hand out the ICE servers for the peer's region
+        debug!("ICE servers for region {:?}", ice_servers.region);
+        let _ = tx.send(SignalingMessage::IceServers {
+            region: ice_servers.region,
+            stun_servers: ice_servers.stun_servers,
+            turn_servers: ice_servers.turn_servers,
+        }).await;
+        
         // Generate a temporary peer ID
         let temp_peer_id = Uuid::new_v4().to_string();
         
@@ -293,6 +377,7 @@ impl SignalingServer {
         
         // Process incoming messages
         let mut peer_id = temp_peer_id.clone();
+        let mut session_token: Option<String> = None;
         
         while let Some(Ok(msg)) = receiver.next().await {
             match msg {
@@ -324,43 +409,51 @@ impl SignalingServer {
                         }
                     };
                     
+                    // Check the session token on every request, so a revoked token is cut off at once
+                    if let (true, Some(auth)) = (state.auth_enabled, &state.auth_middleware) {
+                        if !matches!(msg, SignalingMessage::Register { .. } | SignalingMessage::Ping) {
+                            let Some(token) = &session_token else {
+                                let error_msg = SignalingMessage::Error {
+                                    message: "Authentication required".to_string(),
+                                };
+                                let _ = tx.send(error_msg).await;
+                                continue;
+                            };
+                            
+                            if let Err(e) = auth.authenticate(token).await {
+                                warn!("Closing connection of {}: {}", peer_id, e);
+                                let error_msg = SignalingMessage::Error {
+                                    message: format!("Authentication failed: {}", e),
+                                };
+                                let _ = tx.send(error_msg).await;
+                                break;
+                            }
+                        }
+                    }
+                    
                     // Process the message
                     match msg {
-                        SignalingMessage::Register { peer_id: new_peer_id } => {
+                        SignalingMessage::Register { peer_id: new_peer_id, token } => {
                             // Apply authentication if enabled
-                            if state.auth_enabled {
-                                if let Some(auth) = &state.auth_middleware {
-                                    // Check if the peer is authenticated
-                                    // In a real implementation, we would extract a token from the message
-                                    // and validate it. For now, we'll just allow all registrations.
-                                    // This is where you would add the authentication check.
-                                    
-                                    // Example of how to validate a token:
-                                    // if let Some(token) = extract_token_from_message(&msg) {
-                                    //     match auth.authenticate(token).await {
-                                    //         Ok(_) => {
-                                    //             // Authentication successful
-                                    //         }
-                                    //         Err(e) => {
-                                    //             // Authentication failed
-                                    //             warn!("Authentication failed: {}", e);
-                                    //             let error_msg = SignalingMessage::Error {
-                                    //                 message: format!("Authentication failed: {}", e),
-                                    //             };
-                                    //             let _ = tx.send(error_msg).await;
-                                    //             continue;
-                                    //         }
-                                    //     }
-                                    // } else {
-                                    //     // No token provided
-                                    //     warn!("No authentication token provided");
-                                    //     let error_msg = SignalingMessage::Error {
-                                    //         message: "Authentication required".to_string(),
-                                    //     };
-                                    //     let _ = tx.send(error_msg).await;
-                                    //     continue;
-                                    // }
+                            if let (true, Some(auth)) = (state.auth_enabled, &state.auth_middleware) {
+                                let Some(token) = token else {
+                                    warn!("No authentication token provided by {}", new_peer_id);
+                                    let error_msg = SignalingMessage::Error {
+                                        message: "Authentication required".to_string(),
+                                    };
+                                    let _ = tx.send(error_msg).await;
+                                    continue;
+                                };
+                                
+                                if let Err(e) = auth.authenticate(&token).await {
+                                    warn!("Authentication failed for {}: {}", new_peer_id, e);
+                                    let error_msg = SignalingMessage::Error {
+                                        message: format!("Authentication failed: {}", e),
+                                    };
+                                    let _ = tx.send(error_msg).await;
+                                    continue;
                                 }
+                                session_token = Some(token);
                             }
                             
                             // Update the peer ID
@@ -541,6 +634,42 @@ impl SignalingServer {
                                 }
                             }
                         }
+                        SignalingMessage::PublishSnapshot { peer_id: publisher, snapshot } => {
+                            // Only registered peers may publish their own snapshot
+                            if publisher != peer_id {
+                                let error_msg = SignalingMessage::Error {
+                                    message: "Snapshot publisher does not match peer ID".to_string(),
+                                };
+                                let _ = tx.send(error_msg).await;
+                                continue;
+                            }
+                            
+                            match &state.snapshot_cache {
+                                Some(cache) => {
+                                    if let Err(e) = cache.publish(&publisher, snapshot) {
+                                        warn!("Failed to cache snapshot from {}: {}", publisher, e);
+                                        let error_msg = SignalingMessage::Error {
+                                            message: format!("Failed to cache snapshot: {}", e),
+                                        };
+                                        let _ = tx.send(error_msg).await;
+                                    }
+                                }
+                                None => {
+                                    let error_msg = SignalingMessage::Error {
+                                        message: "Snapshot cache is disabled".to_string(),
+                                    };
+                                    let _ = tx.send(error_msg).await;
+                                }
+                            }
+                        }
+                        SignalingMessage::GetSnapshots => {
+                            // Send the cached snapshots
+                            let snapshots = state.snapshot_cache
+                                .as_ref()
+                                .map(|cache| cache.get_snapshots())
+                                .unwrap_or_default();
+                            let _ = tx.send(SignalingMessage::Snapshots { snapshots }).await;
+                        }
                         SignalingMessage::Ping => {
                             // Send a pong message
                             let pong_msg = SignalingMessage::Pong;
@@ -584,6 +713,11 @@ impl SignalingServer {
         info!("Peer disconnected: {}", peer_id);
     }
     
+    /// Get the revoked auth tokens, shared with the admin endpoints
+    pub fn revocations(&self) -> Arc<RevocationList> {
+        self.revocations.clone()
+    }
+    
     /// Get the number of connected peers
     pub fn get_peer_count(&self) -> usize {
         self.peers.lock().unwrap().len()
@@ -603,5 +737,10 @@ impl SignalingServer {
         peers.retain(|_, conn| {
             now.duration_since(conn.last_activity) < timeout
         });
+        
+        // Drop expired orderbook snapshots
+        if let Some(cache) = &self.snapshot_cache {
+            cache.cleanup();
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+//! Orderbook snapshot cache for the DarkSwap Relay Server
+//!
+//! This module lets the relay cache signed orderbook snapshots published by makers
+//! and serve them to new peers. The relay treats snapshots as opaque JSON; maker
+//! signatures and snapshot freshness are validated by the clients.
+
+use crate::{
+    config::Config,
+    error::Error,
+    Result,
+};
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Cached snapshot entry
+#[derive(Debug, Clone)]
+struct CachedSnapshot {
+    /// Snapshot JSON
+    snapshot: String,
+    /// Time the snapshot was received
+    received_at: Instant,
+}
+
+/// Orderbook snapshot cache
+pub struct SnapshotCache {
+    /// Snapshots by publisher peer ID
+    snapshots: DashMap<String, CachedSnapshot>,
+    /// Maximum snapshot age
+    max_age: Duration,
+    /// Maximum snapshot size in bytes
+    max_size: usize,
+}
+
+impl SnapshotCache {
+    /// Create a new snapshot cache
+    pub fn new(config: &Config) -> Self {
+        Self {
+            snapshots: DashMap::new(),
+            max_age: config.snapshot_max_age(),
+            max_size: config.snapshot.max_size,
+        }
+    }
+
+    /// Store the latest snapshot published by a peer
+    pub fn publish(&self, peer_id: &str, snapshot: String) -> Result<()> {
+        if snapshot.len() > self.max_size {
+            return Err(Error::Other(format!(
+                "Snapshot too large: {} bytes (max {})",
+                snapshot.len(),
+                self.max_size
+            )));
+        }
+
+        // Make sure the snapshot is at least valid JSON
+        serde_json::from_str::<serde_json::Value>(&snapshot)?;
+
+        self.snapshots.insert(
+            peer_id.to_string(),
+            CachedSnapshot {
+                snapshot,
+                received_at: Instant::now(),
+            },
+        );
+
+        debug!("Cached orderbook snapshot from {}", peer_id);
+
+        Ok(())
+    }
+
+    /// Get all snapshots that have not expired
+    pub fn get_snapshots(&self) -> Vec<String> {
+        self.snapshots
+            .iter()
+            .filter(|entry| entry.received_at.elapsed() <= self.max_age)
+            .map(|entry| entry.snapshot.clone())
+            .collect()
+    }
+
+    /// Remove the snapshot published by a peer
+    pub fn remove(&self, peer_id: &str) {
+        self.snapshots.remove(peer_id);
+    }
+
+    /// Remove expired snapshots
+    pub fn cleanup(&self) {
+        let max_age = self.max_age;
+        self.snapshots.retain(|_, entry| entry.received_at.elapsed() <= max_age);
+    }
+
+    /// Get the number of cached snapshots
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Check if the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
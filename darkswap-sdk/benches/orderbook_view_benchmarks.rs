@@ -0,0 +1,174 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use darkswap_sdk::{
+    orderbook::{view::OrderbookView, Order, OrderSide},
+    types::Asset,
+};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of orders in the book
+const ORDERS: usize = 1_000;
+/// Number of reads per reader thread and iteration
+const READS: usize = 1_000;
+
+fn order(n: usize) -> Order {
+    let side = if n % 2 == 0 { OrderSide::Buy } else { OrderSide::Sell };
+    let price = if n % 2 == 0 { 50_000 - (n % 500) as i64 } else { 50_001 + (n % 500) as i64 };
+
+    Order::new(
+        "maker".to_string(),
+        Asset::Bitcoin,
+        Asset::Rune((n % 4) as u128),
+        side,
+        Decimal::new(1, 2),
+        Decimal::new(price, 0),
+        None,
+    )
+}
+
+/// Best bid and ask under a read lock, as the orderbook used to serve them
+fn locked_best_bid_ask(orders: &RwLock<HashMap<String, Order>>, quote: &Asset) -> (Option<Decimal>, Option<Decimal>) {
+    let orders = orders.read().unwrap();
+    let pair = orders.values().filter(|order| &order.quote_asset == quote);
+
+    let mut best_bid = None;
+    let mut best_ask = None;
+    for order in pair {
+        match order.side {
+            OrderSide::Buy => best_bid = best_bid.max(Some(order.price)),
+            OrderSide::Sell => {
+                best_ask = Some(best_ask.map_or(order.price, |ask: Decimal| ask.min(order.price)))
+            }
+        }
+    }
+
+    (best_bid, best_ask)
+}
+
+/// Run `readers` threads doing `read` while one thread keeps writing, returning the
+/// time the readers took
+fn mixed_load<R, W>(readers: usize, read: R, write: W) -> Duration
+where
+    R: Fn(usize) + Send + Sync + 'static,
+    W: Fn(usize) + Send + 'static,
+{
+    let read = Arc::new(read);
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let writer = {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut n = 0;
+            while !stop.load(Ordering::Relaxed) {
+                write(n);
+                n += 1;
+            }
+        })
+    };
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..readers)
+        .map(|reader| {
+            let read = read.clone();
+            thread::spawn(move || {
+                for n in 0..READS {
+                    read(reader * READS + n);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+
+    elapsed
+}
+
+fn bench_mixed_load(c: &mut Criterion) {
+    let orders: Vec<Order> = (0..ORDERS).map(order).collect();
+
+    let mut group = c.benchmark_group("orderbook_mixed_load");
+
+    for readers in [1, 4, 8] {
+        group.throughput(Throughput::Elements((readers * READS) as u64));
+
+        group.bench_with_input(BenchmarkId::new("rwlock", readers), &readers, |b, &readers| {
+            let book = Arc::new(RwLock::new(
+                orders.iter().map(|order| (order.id.0.clone(), order.clone())).collect::<HashMap<_, _>>(),
+            ));
+            let updates = orders.clone();
+
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let reader_book = book.clone();
+                    let writer_book = book.clone();
+                    let updates = updates.clone();
+
+                    total += mixed_load(
+                        readers,
+                        move |n| {
+                            let quote = Asset::Rune((n % 4) as u128);
+                            black_box(locked_best_bid_ask(&reader_book, &quote));
+                        },
+                        move |n| {
+                            let mut update = updates[n % ORDERS].clone();
+                            update.amount += Decimal::new(1, 8);
+                            writer_book.write().unwrap().insert(update.id.0.clone(), update);
+                        },
+                    );
+                }
+                total
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("snapshot", readers), &readers, |b, &readers| {
+            let view = Arc::new(OrderbookView::new());
+            for order in &orders {
+                view.apply(order);
+            }
+            let updates = orders.clone();
+
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let reader_view = view.clone();
+                    let writer_view = view.clone();
+                    let updates = updates.clone();
+
+                    total += mixed_load(
+                        readers,
+                        move |n| {
+                            let quote = Asset::Rune((n % 4) as u128);
+                            let snapshot = reader_view.load();
+                            black_box(
+                                snapshot
+                                    .pair(&Asset::Bitcoin, &quote)
+                                    .map(|book| (book.best_bid(), book.best_ask())),
+                            );
+                        },
+                        move |n| {
+                            let mut update = updates[n % ORDERS].clone();
+                            update.amount += Decimal::new(1, 8);
+                            writer_view.apply(&update);
+                        },
+                    );
+                }
+                total
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_mixed_load);
+criterion_main!(benches);
@@ -0,0 +1,96 @@
+use bitcoin::consensus::Encodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+use bitcoin::util::sighash::SighashCache;
+use bitcoin::{
+    EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, Script, Sequence, Transaction, TxIn, TxOut,
+    Witness,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use darkswap_sdk::trade::psbt::{verify_signatures, VerificationPool};
+use tokio::runtime::Runtime;
+
+/// Number of PSBTs arriving at once
+const BURST: usize = 64;
+/// Signed inputs per PSBT
+const INPUTS: usize = 8;
+
+/// PSBT whose inputs all spend P2WPKH outputs of one key, signed
+fn signed_psbt(n: usize) -> Vec<u8> {
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_slice(&[(n % 250) as u8 + 1; 32]).unwrap();
+    let public_key = PublicKey::new(secret_key.public_key(&secp));
+    let spent = TxOut {
+        value: 100_000,
+        script_pubkey: Script::new_v0_p2wpkh(&public_key.wpubkey_hash().unwrap()),
+    };
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: (0..INPUTS)
+            .map(|vout| TxIn {
+                previous_output: OutPoint { txid: Default::default(), vout: vout as u32 },
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: vec![TxOut { value: 90_000 * INPUTS as u64, script_pubkey: Script::new() }],
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+    let script_code = Script::new_p2pkh(&public_key.pubkey_hash());
+    let mut cache = SighashCache::new(&tx);
+    for index in 0..INPUTS {
+        let sighash = cache
+            .segwit_signature_hash(index, &script_code, spent.value, EcdsaSighashType::All)
+            .unwrap();
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        let signature = EcdsaSig::sighash_all(secp.sign_ecdsa(&message, &secret_key));
+
+        psbt.inputs[index].witness_utxo = Some(spent.clone());
+        psbt.inputs[index].partial_sigs.insert(public_key, signature);
+    }
+
+    let mut bytes = Vec::new();
+    psbt.consensus_encode(&mut bytes).unwrap();
+    bytes
+}
+
+fn bench_burst(c: &mut Criterion) {
+    let psbts: Vec<Vec<u8>> = (0..BURST).map(signed_psbt).collect();
+    let runtime = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("psbt_verification_burst");
+    group.throughput(Throughput::Elements(BURST as u64));
+
+    // Every PSBT checked in turn on the event loop, as the trade manager used to
+    group.bench_function("inline", |b| {
+        b.iter(|| {
+            for psbt in &psbts {
+                black_box(verify_signatures(psbt).unwrap());
+            }
+        })
+    });
+
+    for concurrency in [1, 4, 8] {
+        group.bench_with_input(BenchmarkId::new("pool", concurrency), &concurrency, |b, &concurrency| {
+            let pool = VerificationPool::new(concurrency);
+
+            b.iter(|| {
+                runtime.block_on(async {
+                    let checks = psbts.iter().map(|psbt| pool.verify_psbt(psbt.clone()));
+                    for result in futures::future::join_all(checks).await {
+                        black_box(result.unwrap());
+                    }
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_burst);
+criterion_main!(benches);
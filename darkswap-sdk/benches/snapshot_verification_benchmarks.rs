@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use darkswap_sdk::orderbook::snapshot::SignedOrder;
+use darkswap_sdk::orderbook::{Order, OrderSide};
+use darkswap_sdk::types::Asset;
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use rust_decimal::Decimal;
+
+/// Orders in the snapshot
+const ORDERS: usize = 10_000;
+/// Makers the orders are spread over
+const MAKERS: usize = 100;
+
+fn snapshot_orders() -> Vec<SignedOrder> {
+    let makers: Vec<Keypair> = (0..MAKERS).map(|_| Keypair::generate_ed25519()).collect();
+
+    (0..ORDERS)
+        .map(|n| {
+            let keypair = &makers[n % MAKERS];
+            let order = Order::new(
+                PeerId::from(keypair.public()).to_string(),
+                Asset::Bitcoin,
+                Asset::Rune(1),
+                if n % 2 == 0 { OrderSide::Buy } else { OrderSide::Sell },
+                Decimal::from(1 + n % 10),
+                Decimal::from(1_000 + n),
+                None,
+            );
+            SignedOrder::sign(order, keypair).unwrap()
+        })
+        .collect()
+}
+
+fn bench_snapshot_sync(c: &mut Criterion) {
+    let orders = snapshot_orders();
+
+    let mut group = c.benchmark_group("snapshot_verification");
+    group.throughput(Throughput::Elements(ORDERS as u64));
+    group.sample_size(10);
+
+    // One signature and one key decoding after the other, as snapshot sync used to
+    group.bench_function("individual", |b| {
+        b.iter(|| {
+            for signed_order in &orders {
+                black_box(signed_order.verify().is_ok());
+            }
+        })
+    });
+
+    // Each maker key decoded once, the signatures checked one by one on a shared verifier
+    group.bench_function("verify_all", |b| {
+        b.iter(|| black_box(SignedOrder::verify_all(&orders)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_snapshot_sync);
+criterion_main!(benches);
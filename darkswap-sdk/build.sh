@@ -102,10 +102,22 @@ if [ "$BUILD_WASM" = true ]; then
     echo -e "${YELLOW}Building WebAssembly package...${NC}"
     
     if [ "$BUILD_TYPE" = "release" ]; then
+        # Reproducible build: no local paths or timestamps in the artifacts
+        export SOURCE_DATE_EPOCH="${SOURCE_DATE_EPOCH:-$(git log -1 --format=%ct)}"
+        export RUSTFLAGS="${RUSTFLAGS} --remap-path-prefix=$(pwd)=/darkswap-sdk --remap-path-prefix=${CARGO_HOME:-$HOME/.cargo}=/cargo"
         wasm-pack build --target web --release --features wasm
     else
         wasm-pack build --target web --dev --features wasm
     fi
+
+    # Worker script for JsDarkSwapWorker
+    cp js/darkswap-worker.js pkg/
+
+    # Artifact hashes to compare against the signed release manifest
+    if [ "$BUILD_TYPE" = "release" ]; then
+        (cd pkg && sha256sum darkswap_sdk_bg.wasm darkswap_sdk.js darkswap-worker.js > SHA256SUMS)
+        echo -e "${YELLOW}Artifact hashes written to pkg/SHA256SUMS.${NC}"
+    fi
     
     echo -e "${GREEN}WebAssembly build complete.${NC}"
     echo -e "${YELLOW}WebAssembly package is available in the 'pkg' directory.${NC}"
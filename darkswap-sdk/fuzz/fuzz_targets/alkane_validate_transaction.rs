@@ -0,0 +1,33 @@
+//! Fuzz `AlkaneProtocol::validate_transaction` and `process_transaction` with arbitrary transactions
+
+#![no_main]
+
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::{PackedLockTime, Script, Transaction, TxOut, WPubkeyHash};
+use darkswap_sdk::alkanes::AlkaneProtocol;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Either a consensus-encoded transaction or the bytes of an OP_RETURN script
+    let tx = match deserialize::<Transaction>(data) {
+        Ok(tx) => tx,
+        Err(_) => {
+            let mut script = vec![0x6a];
+            script.extend_from_slice(data);
+            Transaction {
+                version: 2,
+                lock_time: PackedLockTime::ZERO,
+                input: vec![],
+                output: vec![
+                    TxOut { value: 0, script_pubkey: Script::from(script) },
+                    TxOut { value: 546, script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()) },
+                ],
+            }
+        }
+    };
+
+    let mut protocol = AlkaneProtocol::new(bitcoin::Network::Regtest);
+    let _ = protocol.validate_transaction(&tx);
+    let _ = protocol.process_transaction(&tx, 1);
+});
@@ -0,0 +1,17 @@
+//! Fuzz the P2P gossip codec with arbitrary message data
+
+#![no_main]
+
+use darkswap_p2p::codec::JsonCodec;
+use darkswap_sdk::orderbook::OrderMessage;
+use darkswap_sdk::trade::TradeMessage;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let codec = JsonCodec::with_max_size(64 * 1024);
+
+    // The whole input as one gossip message
+    let _ = codec.decode::<serde_json::Value>(data);
+    let _ = codec.decode::<OrderMessage>(data);
+    let _ = codec.decode::<TradeMessage>(data);
+});
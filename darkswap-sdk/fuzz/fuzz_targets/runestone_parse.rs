@@ -0,0 +1,47 @@
+//! Fuzz `Runestone::parse` and rune balance updates with arbitrary OP_RETURN data
+
+#![no_main]
+
+use bitcoin::blockdata::opcodes::all::OP_RETURN;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::Hash;
+use bitcoin::{PackedLockTime, Script, Transaction, TxOut, WPubkeyHash};
+use darkswap_sdk::runes::RuneProtocol;
+use darkswap_sdk::runestone::Runestone;
+use libfuzzer_sys::fuzz_target;
+
+/// Build a transaction paying to `script_pubkey`, followed by a regular output
+fn transaction(script_pubkey: Script) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![],
+        output: vec![
+            TxOut { value: 0, script_pubkey },
+            TxOut { value: 546, script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()) },
+        ],
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The raw bytes as a script, which is rarely a valid OP_RETURN
+    let _ = Runestone::parse(&transaction(Script::from(data.to_vec())));
+
+    // The bytes as the payload of a rune protocol OP_RETURN
+    let mut builder = Builder::new().push_opcode(OP_RETURN);
+    let mut payload = b"RUNE".to_vec();
+    payload.extend_from_slice(data);
+    for chunk in payload.chunks(75) {
+        builder = builder.push_slice(chunk);
+    }
+    let tx = transaction(builder.into_script());
+
+    if let Some(runestone) = Runestone::parse(&tx) {
+        // Whatever parses must survive a round trip
+        let reparsed = Runestone::parse(&transaction(runestone.to_script()));
+        assert_eq!(reparsed.as_ref(), Some(&runestone));
+    }
+
+    let mut protocol = RuneProtocol::new(bitcoin::Network::Regtest);
+    let _ = protocol.process_transaction(&tx, 1);
+});
@@ -0,0 +1,27 @@
+// Worker script for the DarkSwap SDK
+//
+// Serves a DarkSwap instance to JsDarkSwapWorker from a dedicated or shared
+// worker. It is copied next to the wasm-pack output and loaded as a module:
+//
+//   const darkswap = new JsDarkSwapWorker(config, new URL('./darkswap-worker.js', import.meta.url).href, false);
+
+import init, { worker_main, shared_worker_main } from './darkswap_sdk.js';
+
+const isShared = typeof SharedWorkerGlobalScope !== 'undefined' && self instanceof SharedWorkerGlobalScope;
+
+// Tabs may connect to a shared worker while the module is still loading
+const earlyConnections = [];
+if (isShared) {
+  self.onconnect = (event) => earlyConnections.push(event);
+}
+
+await init();
+
+if (isShared) {
+  shared_worker_main();
+  for (const event of earlyConnections) {
+    self.onconnect(event);
+  }
+} else {
+  worker_main();
+}
@@ -223,8 +223,10 @@ impl Alkane {
             0
         };
 
-        let divisor = 10u128.pow(self.decimals as u32);
-        Ok(integer_part * divisor + fractional_part)
+        10u128.checked_pow(self.decimals as u32)
+            .and_then(|divisor| integer_part.checked_mul(divisor))
+            .and_then(|amount| amount.checked_add(fractional_part))
+            .ok_or_else(|| Error::InvalidAmount("Amount too large".to_string()))
     }
 }
 
@@ -0,0 +1,362 @@
+//! Trading session analytics
+//!
+//! Front-ends measure the trading experience from structured events instead of
+//! scraping logs: how long a trade waits for its counterparty to commit, how
+//! long it then takes for its settlement to confirm, why trade handshakes fail,
+//! and how often the counterparty is reached over a relay rather than directly,
+//! on either side of the trade. The events of
+//! the session are summarized in [`SessionStats`], and subscribers receive each
+//! one as it happens. The SDK records them when built with the `analytics`
+//! feature.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::p2p::path_selection::PathKind;
+use crate::types::TradeId;
+
+/// Events kept for subscribers that fall behind
+const EVENT_CAPACITY: usize = 256;
+
+/// Trades timed at once; the oldest is dropped past it
+const MAX_TRACKED_TRADES: usize = 10_000;
+
+/// Analytics event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnalyticsEvent {
+    /// The counterparty committed its PSBT to a trade
+    Matched {
+        /// Trade
+        trade_id: TradeId,
+        /// Time since the trade was proposed (milliseconds)
+        time_to_match_ms: u64,
+    },
+    /// The settlement transaction of a trade confirmed in a block
+    Confirmed {
+        /// Trade
+        trade_id: TradeId,
+        /// Time since the trade was matched (milliseconds)
+        time_to_confirm_ms: u64,
+    },
+    /// A trade could not be set up with a counterparty
+    HandshakeFailed {
+        /// Trade, if one was proposed
+        trade_id: Option<TradeId>,
+        /// Counterparty
+        peer_id: String,
+        /// Reason code, e.g. `unreachable`, `invalid_psbt` or `trade_limit_reached`
+        reason: String,
+    },
+    /// A counterparty was reached
+    Connected {
+        /// Counterparty
+        peer_id: String,
+        /// Path the connection took
+        path: PathKind,
+    },
+}
+
+/// Summary of the session's analytics events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    /// Time since the session started (seconds)
+    pub session_secs: u64,
+    /// Trades proposed, by either side
+    pub trades_started: u64,
+    /// Trades the counterparty committed to
+    pub trades_matched: u64,
+    /// Trades whose settlement confirmed
+    pub trades_confirmed: u64,
+    /// Average time from proposal to match (milliseconds)
+    pub average_time_to_match_ms: Option<u64>,
+    /// Average time from match to confirmation (milliseconds)
+    pub average_time_to_confirm_ms: Option<u64>,
+    /// Failed handshakes by reason code
+    pub handshake_failures: BTreeMap<String, u64>,
+    /// Counterparties reached directly, including through hole punching
+    pub direct_connections: u64,
+    /// Counterparties reached through a relay
+    pub relayed_connections: u64,
+}
+
+impl SessionStats {
+    /// Get the share of connections that went through a relay
+    pub fn relay_ratio(&self) -> Option<f64> {
+        let total = self.direct_connections + self.relayed_connections;
+        if total == 0 {
+            return None;
+        }
+
+        Some(self.relayed_connections as f64 / total as f64)
+    }
+}
+
+/// Running totals
+#[derive(Debug, Default)]
+struct Totals {
+    /// Start of each trade in flight
+    started: HashMap<TradeId, Instant>,
+    /// Match of each trade in flight or waiting for confirmation
+    matched: HashMap<TradeId, Instant>,
+    /// Summary, without the averages
+    stats: SessionStats,
+    /// Sum of the times to match (milliseconds)
+    total_time_to_match_ms: u64,
+    /// Sum of the times to confirm (milliseconds)
+    total_time_to_confirm_ms: u64,
+}
+
+/// Analytics of one trading session
+pub struct SessionAnalytics {
+    /// Session start
+    started: Instant,
+    /// Running totals
+    totals: Mutex<Totals>,
+    /// Event subscribers
+    events: broadcast::Sender<AnalyticsEvent>,
+}
+
+impl Default for SessionAnalytics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionAnalytics {
+    /// Start a new session
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        Self {
+            started: Instant::now(),
+            totals: Mutex::new(Totals::default()),
+            events,
+        }
+    }
+
+    /// Subscribe to the events recorded after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<AnalyticsEvent> {
+        self.events.subscribe()
+    }
+
+    /// Get the summary of the session so far
+    pub fn stats(&self) -> SessionStats {
+        let totals = self.lock();
+        let mut stats = totals.stats.clone();
+        stats.session_secs = self.started.elapsed().as_secs();
+        stats.average_time_to_match_ms = average(totals.total_time_to_match_ms, stats.trades_matched);
+        stats.average_time_to_confirm_ms = average(totals.total_time_to_confirm_ms, stats.trades_confirmed);
+
+        stats
+    }
+
+    /// Record the proposal of a trade
+    pub fn trade_started(&self, trade_id: &TradeId) {
+        let mut totals = self.lock();
+        if totals.started.contains_key(trade_id) {
+            return;
+        }
+        track(&mut totals.started, trade_id, Instant::now());
+        totals.stats.trades_started += 1;
+    }
+
+    /// Record the counterparty committing to a trade
+    pub fn trade_matched(&self, trade_id: &TradeId) {
+        let event = {
+            let mut totals = self.lock();
+            let Some(started) = totals.started.remove(trade_id) else { return };
+
+            let now = Instant::now();
+            let time_to_match_ms = millis(now - started);
+            track(&mut totals.matched, trade_id, now);
+            totals.stats.trades_matched += 1;
+            totals.total_time_to_match_ms += time_to_match_ms;
+
+            AnalyticsEvent::Matched { trade_id: trade_id.clone(), time_to_match_ms }
+        };
+
+        self.emit(event);
+    }
+
+    /// Record the settlement transaction of a trade confirming
+    pub fn trade_confirmed(&self, trade_id: &TradeId) {
+        let event = {
+            let mut totals = self.lock();
+            let Some(matched) = totals.matched.remove(trade_id) else { return };
+
+            let time_to_confirm_ms = millis(matched.elapsed());
+            totals.stats.trades_confirmed += 1;
+            totals.total_time_to_confirm_ms += time_to_confirm_ms;
+
+            AnalyticsEvent::Confirmed { trade_id: trade_id.clone(), time_to_confirm_ms }
+        };
+
+        self.emit(event);
+    }
+
+    /// Forget a trade that failed, was canceled or expired
+    pub fn trade_finished(&self, trade_id: &TradeId) {
+        let mut totals = self.lock();
+        totals.started.remove(trade_id);
+        totals.matched.remove(trade_id);
+    }
+
+    /// Record a failed handshake, forgetting the trade if there is one
+    pub fn handshake_failed(&self, trade_id: Option<&TradeId>, peer_id: &str, reason: &str) {
+        if let Some(trade_id) = trade_id {
+            self.trade_finished(trade_id);
+        }
+        *self.lock().stats.handshake_failures.entry(reason.to_string()).or_default() += 1;
+
+        self.emit(AnalyticsEvent::HandshakeFailed {
+            trade_id: trade_id.cloned(),
+            peer_id: peer_id.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Record a connection to a counterparty
+    pub fn connected(&self, peer_id: &str, path: PathKind) {
+        {
+            let mut totals = self.lock();
+            match path {
+                PathKind::Direct | PathKind::HolePunched => totals.stats.direct_connections += 1,
+                PathKind::Relayed => totals.stats.relayed_connections += 1,
+            }
+        }
+
+        self.emit(AnalyticsEvent::Connected { peer_id: peer_id.to_string(), path });
+    }
+
+    /// Lock the totals; a panic while they were held leaves them usable
+    fn lock(&self) -> std::sync::MutexGuard<'_, Totals> {
+        self.totals.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Send an event to the subscribers, if there are any
+    fn emit(&self, event: AnalyticsEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+/// Get the reason code of a cancel reason: its `code:` prefix, or `fallback`
+pub fn reason_code(reason: &str, fallback: &str) -> String {
+    match reason.split_once(':') {
+        Some((code, _)) if !code.is_empty() && code.chars().all(|c| c.is_ascii_lowercase() || c == '_') => code.to_string(),
+        _ => fallback.to_string(),
+    }
+}
+
+/// Time a trade, dropping the oldest one timed if there are too many
+fn track(times: &mut HashMap<TradeId, Instant>, trade_id: &TradeId, time: Instant) {
+    if times.len() >= MAX_TRACKED_TRADES {
+        let oldest = times.iter().min_by_key(|(_, time)| **time).map(|(trade_id, _)| trade_id.clone());
+        if let Some(oldest) = oldest {
+            times.remove(&oldest);
+        }
+    }
+
+    times.insert(trade_id.clone(), time);
+}
+
+/// Get a duration in milliseconds
+fn millis(duration: std::time::Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Get the average of `count` values summing to `total`
+fn average(total: u64, count: u64) -> Option<u64> {
+    if count == 0 {
+        return None;
+    }
+
+    Some(total / count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_stats() {
+        let analytics = SessionAnalytics::new();
+        let mut events = analytics.subscribe();
+        let trade = TradeId("trade-1".to_string());
+
+        analytics.trade_started(&trade);
+        analytics.trade_matched(&trade);
+        analytics.trade_confirmed(&trade);
+        // Repeats change nothing
+        analytics.trade_matched(&trade);
+        analytics.trade_confirmed(&trade);
+
+        let failed = TradeId("trade-2".to_string());
+        analytics.trade_started(&failed);
+        analytics.handshake_failed(Some(&failed), "maker", "invalid_psbt");
+        analytics.handshake_failed(None, "maker", "unreachable");
+        analytics.trade_matched(&failed);
+
+        analytics.connected("maker", PathKind::Direct);
+        analytics.connected("maker", PathKind::HolePunched);
+        analytics.connected("maker", PathKind::Relayed);
+        analytics.connected("other", PathKind::Relayed);
+
+        let stats = analytics.stats();
+        assert_eq!(stats.trades_started, 2);
+        assert_eq!(stats.trades_matched, 1);
+        assert_eq!(stats.trades_confirmed, 1);
+        assert!(stats.average_time_to_match_ms.is_some());
+        assert_eq!(stats.handshake_failures.get("invalid_psbt"), Some(&1));
+        assert_eq!(stats.handshake_failures.get("unreachable"), Some(&1));
+        assert_eq!(stats.relay_ratio(), Some(0.5));
+
+        assert!(matches!(events.try_recv(), Ok(AnalyticsEvent::Matched { .. })));
+        assert!(matches!(events.try_recv(), Ok(AnalyticsEvent::Confirmed { .. })));
+        assert!(matches!(events.try_recv(), Ok(AnalyticsEvent::HandshakeFailed { .. })));
+    }
+
+    #[test]
+    fn test_finished_trades_are_forgotten() {
+        let analytics = SessionAnalytics::new();
+        let canceled = TradeId("trade-1".to_string());
+        let expired = TradeId("trade-2".to_string());
+
+        analytics.trade_started(&canceled);
+        analytics.trade_started(&expired);
+        analytics.trade_matched(&expired);
+        analytics.trade_finished(&canceled);
+        analytics.trade_finished(&expired);
+
+        // Nothing is left to match or confirm
+        analytics.trade_matched(&canceled);
+        analytics.trade_confirmed(&expired);
+        let stats = analytics.stats();
+        assert_eq!(stats.trades_matched, 1);
+        assert_eq!(stats.trades_confirmed, 0);
+        assert!(analytics.lock().started.is_empty() && analytics.lock().matched.is_empty());
+
+        // The oldest trade is dropped past the limit
+        let mut times = HashMap::new();
+        let start = Instant::now();
+        for i in 0..MAX_TRACKED_TRADES {
+            times.insert(TradeId(i.to_string()), start + std::time::Duration::from_millis(i as u64 + 1));
+        }
+        times.insert(TradeId("oldest".to_string()), start);
+        times.remove(&TradeId("0".to_string()));
+        track(&mut times, &TradeId("new".to_string()), Instant::now());
+        assert_eq!(times.len(), MAX_TRACKED_TRADES);
+        assert!(!times.contains_key(&TradeId("oldest".to_string())));
+    }
+
+    #[test]
+    fn test_reason_code() {
+        assert_eq!(reason_code("trade_limit_reached: at most 1", "canceled"), "trade_limit_reached");
+        assert_eq!(reason_code("Rejected by maker", "canceled"), "canceled");
+        assert_eq!(reason_code("Batch not supported: x", "canceled"), "canceled");
+    }
+}
@@ -0,0 +1,285 @@
+//! Asset display metadata
+//!
+//! Front-ends show an asset by its name, symbol and icon, and amounts with its
+//! decimals. These come from the rune and alkane registries, with the entries
+//! of `Config.assets` merged over them field by field, so the CLI, the daemon's
+//! REST API and the wasm bindings all show an asset the same way.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AssetOverride;
+use crate::runestone::MAX_DECIMALS;
+use crate::types::{Alkane, AlkaneId, Asset, Rune};
+
+/// Display metadata of an asset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetInfo {
+    /// Asset ID
+    pub id: String,
+    /// Name
+    pub name: Option<String>,
+    /// Ticker symbol
+    pub symbol: Option<String>,
+    /// Decimals amounts are displayed with
+    pub decimals: u8,
+    /// Icon location
+    pub icon_url: Option<String>,
+    /// Whether any field was set by the operator
+    pub overridden: bool,
+}
+
+impl AssetInfo {
+    /// Get the metadata of bitcoin
+    pub fn bitcoin() -> Self {
+        Self {
+            id: Asset::Bitcoin.to_string(),
+            name: Some("Bitcoin".to_string()),
+            symbol: Some("BTC".to_string()),
+            decimals: 8,
+            icon_url: None,
+            overridden: false,
+        }
+    }
+
+    /// Get the metadata of an asset the registry does not know
+    pub fn unregistered(asset: &Asset) -> Self {
+        Self {
+            id: asset.to_string(),
+            name: None,
+            symbol: None,
+            decimals: 0,
+            icon_url: None,
+            overridden: false,
+        }
+    }
+
+    /// Get the metadata of a registered rune
+    pub fn from_rune(rune: &Rune) -> Self {
+        Self {
+            id: Asset::Rune(rune.id).to_string(),
+            name: Some(rune.name.clone()),
+            symbol: Some(rune.symbol.clone()),
+            decimals: rune.decimals,
+            icon_url: None,
+            overridden: false,
+        }
+    }
+
+    /// Get the metadata of a registered alkane
+    pub fn from_alkane(alkane: &Alkane) -> Self {
+        Self {
+            id: Asset::Alkane(alkane.id.clone()).to_string(),
+            name: Some(alkane.name.clone()),
+            symbol: Some(alkane.symbol.clone()),
+            decimals: alkane.decimals,
+            icon_url: None,
+            overridden: false,
+        }
+    }
+
+    /// Merge an operator's entry over the metadata
+    pub fn with_override(mut self, entry: Option<&AssetOverride>) -> Self {
+        let Some(entry) = entry else {
+            return self;
+        };
+
+        if let Some(name) = &entry.name {
+            self.name = Some(name.clone());
+        }
+        if let Some(symbol) = &entry.symbol {
+            self.symbol = Some(symbol.clone());
+        }
+        if let Some(decimals) = entry.decimals {
+            self.decimals = decimals;
+        }
+        if let Some(icon_url) = &entry.icon_url {
+            self.icon_url = Some(icon_url.clone());
+        }
+        self.overridden = true;
+
+        self
+    }
+
+    /// Get the label to show the asset with: its symbol, or its ID
+    pub fn label(&self) -> &str {
+        self.symbol.as_deref().unwrap_or(&self.id)
+    }
+}
+
+/// Get the operator's entry for an asset
+pub fn find_override<'a>(overrides: &'a HashMap<String, AssetOverride>, asset: &Asset) -> Option<&'a AssetOverride> {
+    match asset {
+        // Alkane IDs are parsed both with and without their prefix
+        Asset::Alkane(id) => overrides.get(&format!("ALKANE:{}", id.0.trim_start_matches("ALKANE:"))),
+        _ => overrides.get(&asset.to_string()),
+    }
+}
+
+/// Parse the asset ID an entry of `Config.assets` is keyed by
+pub fn parse_override_key(key: &str) -> Result<Asset> {
+    if let Some(id) = key.strip_prefix("RUNE:") {
+        let id = u128::from_str_radix(id, 16)
+            .map_err(|_| anyhow::anyhow!("Invalid rune ID {:?}, expected hex", id))?;
+        Ok(Asset::Rune(id))
+    } else if let Some(id) = key.strip_prefix("ALKANE:") {
+        if id.is_empty() {
+            anyhow::bail!("Missing alkane ID");
+        }
+        Ok(Asset::Alkane(AlkaneId(id.to_string())))
+    } else {
+        anyhow::bail!("Only runes and alkanes can be overridden, not {:?}", key)
+    }
+}
+
+/// Check that the entries of `Config.assets` name runes or alkanes and have displayable decimals
+pub fn validate_overrides(overrides: &HashMap<String, AssetOverride>) -> Result<()> {
+    for (key, entry) in overrides {
+        parse_override_key(key).map_err(|e| anyhow::anyhow!("{}: {}", key, e))?;
+        if entry.decimals.map_or(false, |decimals| decimals > MAX_DECIMALS) {
+            anyhow::bail!("{}: at most {} decimals can be displayed", key, MAX_DECIMALS);
+        }
+    }
+
+    Ok(())
+}
+
+/// Merge the operator's entry over a registered rune, or build the rune from the entry alone
+pub fn override_rune(id: u128, registered: Option<Rune>, overrides: &HashMap<String, AssetOverride>) -> Option<Rune> {
+    let entry = find_override(overrides, &Asset::Rune(id));
+    let mut rune = match (registered, entry) {
+        (Some(rune), _) => rune,
+        (None, Some(_)) => Rune {
+            id,
+            symbol: Asset::Rune(id).to_string(),
+            name: Asset::Rune(id).to_string(),
+            decimals: 0,
+            supply: 0,
+            limit: 0,
+        },
+        (None, None) => return None,
+    };
+
+    if let Some(entry) = entry {
+        rune.name = entry.name.clone().unwrap_or(rune.name);
+        rune.symbol = entry.symbol.clone().unwrap_or(rune.symbol);
+        rune.decimals = entry.decimals.unwrap_or(rune.decimals);
+    }
+
+    Some(rune)
+}
+
+/// Merge the operator's entry over a registered alkane, or build the alkane from the entry alone
+pub fn override_alkane(id: &AlkaneId, registered: Option<Alkane>, overrides: &HashMap<String, AssetOverride>) -> Option<Alkane> {
+    let entry = find_override(overrides, &Asset::Alkane(id.clone()));
+    let mut alkane = match (registered, entry) {
+        (Some(alkane), _) => alkane,
+        (None, Some(_)) => Alkane {
+            id: id.clone(),
+            symbol: id.to_string(),
+            name: id.to_string(),
+            decimals: 0,
+            supply: 0,
+            limit: 0,
+        },
+        (None, None) => return None,
+    };
+
+    if let Some(entry) = entry {
+        alkane.name = entry.name.clone().unwrap_or(alkane.name);
+        alkane.symbol = entry.symbol.clone().unwrap_or(alkane.symbol);
+        alkane.decimals = entry.decimals.unwrap_or(alkane.decimals);
+    }
+
+    Some(alkane)
+}
+
+/// Merge the operator's entries over registered runes, adding the runes only the entries name
+pub fn override_runes(registered: Vec<Rune>, overrides: &HashMap<String, AssetOverride>) -> Vec<Rune> {
+    let mut runes: Vec<Rune> = registered
+        .into_iter()
+        .filter_map(|rune| override_rune(rune.id, Some(rune), overrides))
+        .collect();
+
+    let mut unregistered: Vec<u128> = overrides
+        .keys()
+        .filter_map(|key| match parse_override_key(key) {
+            Ok(Asset::Rune(id)) if !runes.iter().any(|rune| rune.id == id) => Some(id),
+            _ => None,
+        })
+        .collect();
+    unregistered.sort_unstable();
+    runes.extend(unregistered.into_iter().filter_map(|id| override_rune(id, None, overrides)));
+
+    runes
+}
+
+/// Merge the operator's entries over registered alkanes, adding the alkanes only the entries name
+pub fn override_alkanes(registered: Vec<Alkane>, overrides: &HashMap<String, AssetOverride>) -> Vec<Alkane> {
+    let mut alkanes: Vec<Alkane> = registered
+        .into_iter()
+        .filter_map(|alkane| override_alkane(&alkane.id.clone(), Some(alkane), overrides))
+        .collect();
+
+    let mut unregistered: Vec<AlkaneId> = overrides
+        .keys()
+        .filter_map(|key| match parse_override_key(key) {
+            Ok(Asset::Alkane(id)) if !alkanes.iter().any(|alkane| alkane.id.0 == id.0) => Some(id),
+            _ => None,
+        })
+        .collect();
+    unregistered.sort_by(|a, b| a.0.cmp(&b.0));
+    alkanes.extend(unregistered.iter().filter_map(|id| override_alkane(id, None, overrides)));
+
+    alkanes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(entries: &[(&str, AssetOverride)]) -> HashMap<String, AssetOverride> {
+        entries.iter().map(|(key, entry)| (key.to_string(), entry.clone())).collect()
+    }
+
+    #[test]
+    fn test_overrides() {
+        let registered = Rune {
+            id: 0x2a,
+            symbol: "DOG".to_string(),
+            name: "DOG•GO•TO•THE•MOON".to_string(),
+            decimals: 5,
+            supply: 100_000,
+            limit: 0,
+        };
+        let overrides = overrides(&[
+            ("RUNE:2a", AssetOverride { symbol: Some("DOGE".to_string()), ..Default::default() }),
+            ("RUNE:ff", AssetOverride { name: Some("Test rune".to_string()), decimals: Some(2), ..Default::default() }),
+        ]);
+        validate_overrides(&overrides).unwrap();
+
+        // Fields left out keep the registry's value
+        let runes = override_runes(vec![registered.clone()], &overrides);
+        assert_eq!(runes.len(), 2);
+        assert_eq!((runes[0].symbol.as_str(), runes[0].name.as_str(), runes[0].decimals), ("DOGE", "DOG•GO•TO•THE•MOON", 5));
+
+        // An asset only the operator knows is listed with what its entry sets
+        assert_eq!((runes[1].id, runes[1].name.as_str(), runes[1].decimals), (0xff, "Test rune", 2));
+        assert!(override_rune(0x01, None, &overrides).is_none());
+
+        let info = AssetInfo::from_rune(&registered).with_override(find_override(&overrides, &Asset::Rune(0x2a)));
+        assert_eq!((info.label(), info.decimals, info.overridden), ("DOGE", 5, true));
+        assert_eq!(AssetInfo::unregistered(&Asset::Rune(0x01)).label(), "RUNE:1");
+
+        // Entries must name a rune or alkane and keep decimals displayable
+        assert!(validate_overrides(&overrides_of("BTC", AssetOverride::default())).is_err());
+        assert!(validate_overrides(&overrides_of("RUNE:zz", AssetOverride::default())).is_err());
+        assert!(validate_overrides(&overrides_of("ALKANE:2:1", AssetOverride { decimals: Some(39), ..Default::default() })).is_err());
+    }
+
+    fn overrides_of(key: &str, entry: AssetOverride) -> HashMap<String, AssetOverride> {
+        overrides(&[(key, entry)])
+    }
+}
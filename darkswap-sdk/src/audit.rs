@@ -0,0 +1,245 @@
+//! Audit mode
+//!
+//! An audit node joins the network only to watch it. It subscribes to every
+//! public topic, never creates orders or takes part in trades, and records each
+//! order and trade announcement it receives, with the signatures it carries and
+//! the time it arrived, in the `audit` storage namespace. Messages on the
+//! other topics it listens on are recorded as received. Researchers and market
+//! surveillance export the records as JSON Lines, a page at a time.
+//!
+//! The log keeps at most `max_records` records; the oldest are dropped first.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::{Storage, StorageExt};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Clock;
+use crate::orderbook::OrderMessage;
+use crate::trade::protocol::SignedTradeMessage;
+
+/// Storage namespace of audit records
+pub const AUDIT_NAMESPACE: &str = "audit";
+
+/// Announcement observed on the network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum Observation {
+    /// Order announcement, signed by the maker if it was relayed as a signed order
+    Order(OrderMessage),
+    /// Trade protocol message with its sender signature
+    Trade(SignedTradeMessage),
+    /// Message on another subscribed topic, as received
+    Gossip {
+        /// Topic the message arrived on
+        topic: String,
+        /// Encoded message (base64)
+        data: String,
+    },
+}
+
+/// Recorded observation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Position in the log, increasing with every record
+    pub sequence: u64,
+    /// Time the announcement was received (unix seconds)
+    pub received_at: u64,
+    /// Peer the announcement was received from
+    pub peer_id: String,
+    /// Announcement
+    pub observation: Observation,
+}
+
+/// Append-only log of the announcements observed by an audit node
+pub struct AuditLog {
+    /// Storage the records are kept in
+    storage: Arc<dyn Storage>,
+    /// Time source of the receive times
+    clock: Arc<dyn Clock>,
+    /// Sequence number of the oldest record kept
+    first_sequence: AtomicU64,
+    /// Sequence number of the next record
+    next_sequence: AtomicU64,
+    /// Records kept at most; 0 keeps all
+    max_records: u64,
+}
+
+impl AuditLog {
+    /// Open the log, continuing after the records already in storage
+    pub fn open(storage: Arc<dyn Storage>, clock: Arc<dyn Clock>) -> Result<Self> {
+        let records = storage
+            .scan_prefix(AUDIT_NAMESPACE, b"")
+            .context("Failed to read the audit log")?;
+        let sequence = |(key, _): &(Vec<u8>, Vec<u8>)| std::str::from_utf8(key).ok()?.parse::<u64>().ok();
+        let next_sequence = records.last().and_then(sequence).map_or(0, |sequence| sequence + 1);
+        let first_sequence = records.first().and_then(sequence).unwrap_or(next_sequence);
+
+        Ok(Self {
+            storage,
+            clock,
+            first_sequence: AtomicU64::new(first_sequence),
+            next_sequence: AtomicU64::new(next_sequence),
+            max_records: 0,
+        })
+    }
+
+    /// Keep at most `max_records` records, dropping the oldest; 0 keeps all
+    pub fn with_max_records(mut self, max_records: u64) -> Self {
+        self.max_records = max_records;
+        self
+    }
+
+    /// Record an announcement received from `peer_id`
+    pub fn record(&self, peer_id: &str, observation: Observation) -> Result<AuditRecord> {
+        let record = AuditRecord {
+            sequence: self.next_sequence.fetch_add(1, Ordering::SeqCst),
+            received_at: self.clock.now(),
+            peer_id: peer_id.to_string(),
+            observation,
+        };
+
+        self.storage
+            .put_json(AUDIT_NAMESPACE, &key(record.sequence), &record)
+            .context("Failed to write audit record")?;
+
+        // Drop the oldest records beyond the limit
+        if self.max_records > 0 {
+            loop {
+                let first = self.first_sequence.load(Ordering::SeqCst);
+                if (record.sequence + 1).saturating_sub(first) <= self.max_records {
+                    break;
+                }
+                if self.first_sequence.compare_exchange(first, first + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    self.storage
+                        .delete(AUDIT_NAMESPACE, key(first).as_bytes())
+                        .context("Failed to drop audit record")?;
+                }
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// Get up to `limit` records from sequence number `from` on, oldest first
+    ///
+    /// Only records received at or after `since` (unix seconds) are returned.
+    /// The next page starts after the sequence number of the last record.
+    pub fn records(&self, since: Option<u64>, from: u64, limit: usize) -> Result<Vec<AuditRecord>> {
+        let next = self.next_sequence.load(Ordering::SeqCst);
+        let mut records = Vec::new();
+
+        let mut sequence = from.max(self.first_sequence.load(Ordering::SeqCst));
+        while sequence < next && records.len() < limit {
+            let record = self.storage
+                .get_json::<AuditRecord>(AUDIT_NAMESPACE, &key(sequence))
+                .context("Failed to read the audit log")?;
+            if let Some(record) = record.filter(|record| since.map_or(true, |since| record.received_at >= since)) {
+                records.push(record);
+            }
+            sequence += 1;
+        }
+
+        Ok(records)
+    }
+
+    /// Get the number of records kept
+    pub fn len(&self) -> u64 {
+        self.next_sequence.load(Ordering::SeqCst) - self.first_sequence.load(Ordering::SeqCst)
+    }
+
+    /// Check if nothing was recorded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write a page of records as JSON Lines, returning how many were written
+    ///
+    /// The page is the one `records` returns for `since`, `from` and `limit`.
+    pub fn export<W: Write>(&self, mut writer: W, since: Option<u64>, from: u64, limit: usize) -> Result<usize> {
+        let records = self.records(since, from, limit)?;
+        for record in &records {
+            serde_json::to_writer(&mut writer, record).context("Failed to serialize audit record")?;
+            writer.write_all(b"\n").context("Failed to write audit export")?;
+        }
+        writer.flush().context("Failed to write audit export")?;
+
+        Ok(records.len())
+    }
+}
+
+/// Storage key of a record; zero padding keeps the records in sequence order
+fn key(sequence: u64) -> String {
+    format!("{:020}", sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::orderbook::OrderId;
+    use darkswap_support::storage::MemoryStorage;
+
+    fn cancel(order_id: &str) -> Observation {
+        Observation::Order(OrderMessage::CancelOrder {
+            order_id: OrderId(order_id.to_string()),
+            maker: "maker".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+
+        let log = AuditLog::open(storage.clone(), clock.clone()).unwrap();
+        log.record("peer-1", cancel("order-1")).unwrap();
+        clock.advance(60);
+        log.record("peer-2", cancel("order-2")).unwrap();
+
+        // A reopened log continues the sequence
+        let log = AuditLog::open(storage, clock.clone()).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.record("peer-3", cancel("order-3")).unwrap().sequence, 2);
+
+        let recent = log.records(Some(1_700_000_060), 0, 10).unwrap();
+        assert_eq!(recent.iter().map(|record| record.peer_id.as_str()).collect::<Vec<_>>(), vec!["peer-2", "peer-3"]);
+
+        // Pages continue after the last sequence number
+        let page = log.records(None, 0, 2).unwrap();
+        assert_eq!(page.len(), 2);
+        let next = log.records(None, page[1].sequence + 1, 2).unwrap();
+        assert_eq!(next.iter().map(|record| record.peer_id.as_str()).collect::<Vec<_>>(), vec!["peer-3"]);
+
+        let mut export = Vec::new();
+        assert_eq!(log.export(&mut export, None, 0, 10).unwrap(), 3);
+        let lines: Vec<AuditRecord> = String::from_utf8(export)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].received_at, 1_700_000_000);
+    }
+
+    #[test]
+    fn test_audit_log_drops_the_oldest_records() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+
+        let log = AuditLog::open(storage.clone(), clock.clone()).unwrap().with_max_records(2);
+        for index in 0..5 {
+            log.record("peer", cancel(&format!("order-{}", index))).unwrap();
+        }
+        assert_eq!(log.len(), 2);
+        let sequences: Vec<u64> = log.records(None, 0, 10).unwrap().iter().map(|record| record.sequence).collect();
+        assert_eq!(sequences, vec![3, 4]);
+
+        // A reopened log starts at the oldest record kept
+        let log = AuditLog::open(storage, clock).unwrap();
+        assert_eq!(log.len(), 2);
+    }
+}
@@ -4,7 +4,9 @@
 //! functionality and PSBT utilities.
 
 use crate::error::{Error, Result};
-use crate::types::{RuneId, AlkaneId};
+use crate::types::{Asset, RuneId, AlkaneId};
+use crate::wallet::coin_selection::{self, CoinSelection};
+use crate::wallet::utxo::Utxo;
 use bitcoin::{
     Address, Network, OutPoint, Script, Transaction, TxIn, TxOut, Txid, Witness,
     psbt::Psbt,
@@ -79,6 +81,15 @@ pub trait BitcoinWallet: Send + Sync {
     /// Get UTXOs
     fn get_utxos(&self) -> Result<Vec<(OutPoint, TxOut)>>;
 
+    /// Get UTXOs tagged with the runes and alkanes they hold
+    ///
+    /// Coin selection relies on these tags to keep asset-bearing outputs out of
+    /// bitcoin-only spends. A wallet that cannot tell which outputs hold assets
+    /// must not take part in coin selection, so the default fails.
+    fn get_tagged_utxos(&self) -> Result<Vec<Utxo>> {
+        Err(Error::WalletError("Wallet does not report the assets held by its UTXOs".to_string()))
+    }
+
     /// Sign a PSBT
     fn sign_psbt(&self, psbt: &mut Psbt) -> Result<()>;
 
@@ -96,6 +107,8 @@ pub struct SimpleWallet {
     addresses: Vec<Address>,
     /// UTXOs
     utxos: Vec<(OutPoint, TxOut)>,
+    /// Runes and alkanes held by UTXOs
+    asset_tags: HashMap<OutPoint, Vec<(Asset, u128)>>,
 }
 
 impl SimpleWallet {
@@ -118,6 +131,7 @@ impl SimpleWallet {
             keypair,
             addresses: vec![address],
             utxos: Vec::new(),
+            asset_tags: HashMap::new(),
         };
 
         // Add some test UTXOs
@@ -148,6 +162,7 @@ impl SimpleWallet {
             keypair,
             addresses: vec![address],
             utxos: Vec::new(),
+            asset_tags: HashMap::new(),
         };
 
         // Add some test UTXOs
@@ -180,6 +195,12 @@ impl SimpleWallet {
     pub fn add_utxo(&mut self, outpoint: OutPoint, txout: TxOut) {
         self.utxos.push((outpoint, txout));
     }
+
+    /// Add a UTXO holding runes or alkanes
+    pub fn add_asset_utxo(&mut self, outpoint: OutPoint, txout: TxOut, assets: Vec<(Asset, u128)>) {
+        self.utxos.push((outpoint, txout));
+        self.asset_tags.insert(outpoint, assets);
+    }
 }
 
 impl<T: BitcoinWallet + ?Sized> BitcoinWallet for Box<T> {
@@ -203,6 +224,11 @@ impl<T: BitcoinWallet + ?Sized> BitcoinWallet for Box<T> {
         (**self).get_utxos()
     }
 
+    /// Get UTXOs tagged with the runes and alkanes they hold
+    fn get_tagged_utxos(&self) -> Result<Vec<Utxo>> {
+        (**self).get_tagged_utxos()
+    }
+
     /// Sign a PSBT
     fn sign_psbt(&self, psbt: &mut Psbt) -> Result<()> {
         (**self).sign_psbt(psbt)
@@ -239,6 +265,18 @@ impl BitcoinWallet for SimpleWallet {
         Ok(self.utxos.clone())
     }
 
+    /// Get UTXOs tagged with the runes and alkanes they hold
+    fn get_tagged_utxos(&self) -> Result<Vec<Utxo>> {
+        Ok(self.utxos.iter()
+            .map(|(outpoint, txout)| Utxo {
+                outpoint: *outpoint,
+                txout: txout.clone(),
+                assets: self.asset_tags.get(outpoint).cloned().unwrap_or_default(),
+                confirmations: 0,
+            })
+            .collect())
+    }
+
     /// Sign a PSBT
     fn sign_psbt(&self, psbt: &mut Psbt) -> Result<()> {
         // Create secp256k1 context
@@ -361,6 +399,34 @@ impl PsbtUtils {
         (tx_size as f64 * fee_rate).ceil() as u64
     }
 
+    /// Estimate the fee for one P2WPKH input
+    pub fn estimate_input_fee(fee_rate: f64) -> u64 {
+        ((41 + 108) as f64 * fee_rate).ceil() as u64
+    }
+
+    /// Build unsigned inputs spending a coin selection
+    fn selection_inputs(selection: &CoinSelection) -> Vec<TxIn> {
+        selection.selected.iter()
+            .map(|utxo| TxIn {
+                previous_output: utxo.outpoint,
+                script_sig: Script::new(),
+                sequence: bitcoin::Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect()
+    }
+
+    /// Create a PSBT spending a coin selection, with the spent outputs attached
+    fn create_selection_psbt(selection: &CoinSelection, outputs: Vec<TxOut>) -> Result<Psbt> {
+        let mut psbt = Self::create_psbt(Self::selection_inputs(selection), outputs)?;
+
+        for (input, utxo) in psbt.inputs.iter_mut().zip(&selection.selected) {
+            input.witness_utxo = Some(utxo.txout.clone());
+        }
+
+        Ok(psbt)
+    }
+
     /// Create a PSBT for a rune transfer
     pub fn create_rune_transfer_psbt(
         wallet: &impl BitcoinWallet,
@@ -370,60 +436,77 @@ impl PsbtUtils {
         amount: u128,
         fee_rate: f64,
     ) -> Result<Psbt> {
-        // Get UTXOs
-        let utxos = wallet.get_utxos()?;
-        if utxos.is_empty() {
-            return Err(Error::BitcoinError("No UTXOs available".to_string()));
-        }
-
-        // Create inputs
-        let mut inputs = Vec::new();
-        let mut input_value = 0;
-        for (outpoint, txout) in &utxos {
-            inputs.push(TxIn {
-                previous_output: *outpoint,
-                script_sig: Script::new(),
-                sequence: bitcoin::Sequence::MAX,
-                witness: Witness::new(),
-            });
-            input_value += txout.value;
-        }
+        Self::create_asset_transfer_psbt(
+            wallet,
+            &Asset::Rune(*rune_id),
+            *rune_id,
+            from_address,
+            to_address,
+            amount,
+            fee_rate,
+        )
+    }
 
-        // Create outputs
-        let mut outputs = Vec::new();
+    /// Create a PSBT moving `amount` of a rune or alkane with the edict ID `edict_id`
+    ///
+    /// Only outputs holding the asset are spent alongside plain bitcoin. Everything
+    /// the inputs hold beyond the edict goes to an asset change output back to the
+    /// sender instead of following the transfer.
+    fn create_asset_transfer_psbt(
+        wallet: &impl BitcoinWallet,
+        asset: &Asset,
+        edict_id: u128,
+        from_address: &Address,
+        to_address: &Address,
+        amount: u128,
+        fee_rate: f64,
+    ) -> Result<Psbt> {
+        // Outputs: runestone, recipient, asset change, bitcoin change
+        let funded = 546 + 546;
+        let target = funded + Self::estimate_transaction_fee(0, 4, fee_rate);
+        let selection = coin_selection::select_asset(
+            &wallet.get_tagged_utxos()?,
+            asset,
+            amount,
+            target,
+            Self::estimate_input_fee(fee_rate),
+        )?;
 
-        // Create the edict for the rune transfer
+        // Create the edict for the transfer
         let edict = crate::runestone::Edict {
-            id: *rune_id,
+            id: edict_id,
             amount,
             output: 1, // The recipient output will be at index 1
         };
 
-        // Create the runestone
+        // Unallocated runes go to the asset change output
         let runestone = crate::runestone::Runestone {
             edicts: vec![edict],
             etching: None,
-            default_output: None,
+            default_output: Some(2),
             burn: false,
         };
 
-        // Add the runestone as an OP_RETURN output
-        outputs.push(TxOut {
-            value: 0,
-            script_pubkey: runestone.to_script(),
-        });
-
-        // Add recipient output
-        outputs.push(TxOut {
-            value: 546, // Dust limit
-            script_pubkey: to_address.script_pubkey(),
-        });
-
-        // Calculate fee
-        let fee = Self::estimate_transaction_fee(inputs.len(), outputs.len() + 1, fee_rate);
+        let mut outputs = vec![
+            // Runestone
+            TxOut {
+                value: 0,
+                script_pubkey: runestone.to_script(),
+            },
+            // Recipient
+            TxOut {
+                value: 546, // Dust limit
+                script_pubkey: to_address.script_pubkey(),
+            },
+            // Asset change
+            TxOut {
+                value: 546, // Dust limit
+                script_pubkey: from_address.script_pubkey(),
+            },
+        ];
 
         // Add change output
-        let change_value = input_value - 546 - fee;
+        let change_value = selection.change(target);
         if change_value > 546 {
             outputs.push(TxOut {
                 value: change_value,
@@ -431,15 +514,7 @@ impl PsbtUtils {
             });
         }
 
-        // Create PSBT
-        let mut psbt = Self::create_psbt(inputs, outputs)?;
-
-        // Add input information
-        for (i, (_, txout)) in utxos.iter().enumerate() {
-            psbt.inputs[i].witness_utxo = Some(txout.clone());
-        }
-
-        Ok(psbt)
+        Self::create_selection_psbt(&selection, outputs)
     }
 
     /// Create a PSBT for an alkane transfer
@@ -453,24 +528,12 @@ impl PsbtUtils {
     ) -> Result<Psbt> {
         // Alkanes are implemented on top of runes, so we can use the rune transfer PSBT
         // with the alkane ID as the rune ID
-        let numeric_id = if let Some(rune_id) = alkane_id.0.strip_prefix("ALKANE:") {
-            if let Ok(rune_id_num) = rune_id.parse::<u128>() {
-                rune_id_num
-            } else {
-                return Err(Error::InvalidAlkane);
-            }
-        } else {
-            // Try to parse the entire ID as a number
-            if let Ok(id_num) = alkane_id.0.parse::<u128>() {
-                id_num
-            } else {
-                return Err(Error::InvalidAlkane);
-            }
-        };
-        
-        Self::create_rune_transfer_psbt(
+        let numeric_id = Self::alkane_edict_id(alkane_id)?;
+
+        Self::create_asset_transfer_psbt(
             wallet,
-            &numeric_id,
+            &Asset::Alkane(alkane_id.clone()),
+            numeric_id,
             from_address,
             to_address,
             amount,
@@ -479,6 +542,8 @@ impl PsbtUtils {
     }
 
     /// Create a PSBT for a Bitcoin transfer
+    ///
+    /// Outputs holding runes or alkanes are never spent.
     pub fn create_bitcoin_transfer_psbt(
         wallet: &impl BitcoinWallet,
         from_address: &Address,
@@ -486,24 +551,13 @@ impl PsbtUtils {
         amount: u64,
         fee_rate: f64,
     ) -> Result<Psbt> {
-        // Get UTXOs
-        let utxos = wallet.get_utxos()?;
-        if utxos.is_empty() {
-            return Err(Error::BitcoinError("No UTXOs available".to_string()));
-        }
-
-        // Create inputs
-        let mut inputs = Vec::new();
-        let mut input_value = 0;
-        for (outpoint, txout) in &utxos {
-            inputs.push(TxIn {
-                previous_output: *outpoint,
-                script_sig: Script::new(),
-                sequence: bitcoin::Sequence::MAX,
-                witness: Witness::new(),
-            });
-            input_value += txout.value;
-        }
+        // Select plain bitcoin for the recipient and change outputs
+        let target = amount + Self::estimate_transaction_fee(0, 2, fee_rate);
+        let selection = coin_selection::select_bitcoin(
+            &wallet.get_tagged_utxos()?,
+            target,
+            Self::estimate_input_fee(fee_rate),
+        )?;
 
         // Create outputs
         let mut outputs = Vec::new();
@@ -514,11 +568,8 @@ impl PsbtUtils {
             script_pubkey: to_address.script_pubkey(),
         });
 
-        // Calculate fee
-        let fee = Self::estimate_transaction_fee(inputs.len(), outputs.len() + 1, fee_rate);
-
         // Add change output
-        let change_value = input_value - amount - fee;
+        let change_value = selection.change(target);
         if change_value > 546 {
             outputs.push(TxOut {
                 value: change_value,
@@ -526,18 +577,14 @@ impl PsbtUtils {
             });
         }
 
-        // Create PSBT
-        let mut psbt = Self::create_psbt(inputs, outputs)?;
-
-        // Add input information
-        for (i, (_, txout)) in utxos.iter().enumerate() {
-            psbt.inputs[i].witness_utxo = Some(txout.clone());
-        }
-
-        Ok(psbt)
+        Self::create_selection_psbt(&selection, outputs)
     }
 
     /// Create a PSBT for a swap
+    ///
+    /// Rune and alkane legs are moved by edicts of a single runestone. Whatever the
+    /// selected inputs hold of the sent asset beyond `send_amount` goes to an asset
+    /// change output back to the sender.
     pub fn create_swap_psbt(
         wallet: &impl BitcoinWallet,
         from_address: &Address,
@@ -548,187 +595,78 @@ impl PsbtUtils {
         receive_amount: u64,
         fee_rate: f64,
     ) -> Result<Psbt> {
-        // Get UTXOs
-        let utxos = wallet.get_utxos()?;
-        if utxos.is_empty() {
-            return Err(Error::BitcoinError("No UTXOs available".to_string()));
-        }
-
-        // Create inputs
-        let mut inputs = Vec::new();
-        let mut input_value = 0;
-        for (outpoint, txout) in &utxos {
-            inputs.push(TxIn {
-                previous_output: *outpoint,
-                script_sig: Script::new(),
-                sequence: bitcoin::Sequence::MAX,
-                witness: Witness::new(),
-            });
-            input_value += txout.value;
-        }
-
-        // Create outputs
-        let mut outputs = Vec::new();
+        // The runestone goes first so that edict output indexes are known up front
+        let mut edicts = Vec::new();
+        let mut outputs = vec![TxOut {
+            value: 0,
+            script_pubkey: Script::new(),
+        }];
 
         // Add send asset output
-        match send_asset {
-            crate::types::Asset::Bitcoin => {
-                // Add Bitcoin output
-                outputs.push(TxOut {
-                    value: send_amount,
-                    script_pubkey: to_address.script_pubkey(),
-                });
-            }
-            crate::types::Asset::Rune(rune_id) => {
-                // Add rune transfer output
-                let rune_data = format!("RUNE:{}:{}", rune_id, send_amount);
-                // Create a fixed-size array that implements AsRef<PushBytes>
-                let data_bytes = rune_data.as_bytes();
-                let mut buffer = [0u8; 75]; // Max size for a standard push
-                let len = std::cmp::min(data_bytes.len(), 75);
-                buffer[..len].copy_from_slice(&data_bytes[..len]);
-                
-                // Create OP_RETURN script
-                let mut script = Script::new();
-                // Create a script with OP_RETURN
-                let mut builder = bitcoin::blockdata::script::Builder::new();
-                builder = builder.push_opcode(bitcoin::blockdata::opcodes::all::OP_RETURN);
-                
-                // Add the data
-                if len > 0 {
-                    builder = builder.push_slice(&buffer[..len]);
-                }
-                
-                // Build the script
-                let script = builder.into_script();
-                outputs.push(TxOut {
-                    value: 0,
-                    script_pubkey: script,
-                });
-
-                // Add recipient output
-                outputs.push(TxOut {
-                    value: 546, // Dust limit
-                    script_pubkey: to_address.script_pubkey(),
-                });
-            }
-            crate::types::Asset::Alkane(alkane_id) => {
-                // Add alkane transfer output
-                let alkane_data = format!("ALKANE:{}:{}", alkane_id, send_amount);
-                // Create a fixed-size array that implements AsRef<PushBytes>
-                let data_bytes = alkane_data.as_bytes();
-                let mut buffer = [0u8; 75]; // Max size for a standard push
-                let len = std::cmp::min(data_bytes.len(), 75);
-                buffer[..len].copy_from_slice(&data_bytes[..len]);
-                
-                // Create OP_RETURN script
-                let mut script = Script::new();
-                // Create a script with OP_RETURN
-                let mut builder = bitcoin::blockdata::script::Builder::new();
-                builder = builder.push_opcode(bitcoin::blockdata::opcodes::all::OP_RETURN);
-                
-                // Add the data
-                if len > 0 {
-                    builder = builder.push_slice(&buffer[..len]);
-                }
-                
-                // Build the script
-                let script = builder.into_script();
-                outputs.push(TxOut {
-                    value: 0,
-                    script_pubkey: script,
-                });
+        Self::push_swap_leg(&mut outputs, &mut edicts, send_asset, send_amount, to_address)?;
 
-                // Add recipient output
-                outputs.push(TxOut {
-                    value: 546, // Dust limit
-                    script_pubkey: to_address.script_pubkey(),
-                });
-            }
-        }
+        // This wallet funds the send side
+        let funded: u64 = outputs.iter().map(|output| output.value).sum();
 
         // Add receive asset output
-        match receive_asset {
-            crate::types::Asset::Bitcoin => {
-                // Add Bitcoin output
-                outputs.push(TxOut {
-                    value: receive_amount,
-                    script_pubkey: from_address.script_pubkey(),
-                });
-            }
-            crate::types::Asset::Rune(rune_id) => {
-                // Add rune transfer output
-                let rune_data = format!("RUNE:{}:{}", rune_id, receive_amount);
-                // Create a fixed-size array that implements AsRef<PushBytes>
-                let data_bytes = rune_data.as_bytes();
-                let mut buffer = [0u8; 75]; // Max size for a standard push
-                let len = std::cmp::min(data_bytes.len(), 75);
-                buffer[..len].copy_from_slice(&data_bytes[..len]);
-                
-                // Create OP_RETURN script
-                let mut script = Script::new();
-                // Create a script with OP_RETURN
-                let mut builder = bitcoin::blockdata::script::Builder::new();
-                builder = builder.push_opcode(bitcoin::blockdata::opcodes::all::OP_RETURN);
-                
-                // Add the data
-                if len > 0 {
-                    builder = builder.push_slice(&buffer[..len]);
-                }
-                
-                // Build the script
-                let script = builder.into_script();
-                outputs.push(TxOut {
-                    value: 0,
-                    script_pubkey: script,
-                });
+        Self::push_swap_leg(&mut outputs, &mut edicts, receive_asset, receive_amount, from_address)?;
+
+        // Reserve the asset change output when sending a rune or alkane
+        let asset_change = if *send_asset == Asset::Bitcoin { 0 } else { 546 };
+
+        // Select inputs; only a sent rune or alkane may be spent
+        let utxos = wallet.get_tagged_utxos()?;
+        let target = funded + asset_change + Self::estimate_transaction_fee(0, outputs.len() + 2, fee_rate);
+        let input_fee = Self::estimate_input_fee(fee_rate);
+        let selection = match send_asset {
+            crate::types::Asset::Bitcoin => coin_selection::select_bitcoin(&utxos, target, input_fee)?,
+            asset => coin_selection::select_asset(&utxos, asset, send_amount as u128, target, input_fee)?,
+        };
 
-                // Add recipient output
-                outputs.push(TxOut {
-                    value: 546, // Dust limit
-                    script_pubkey: from_address.script_pubkey(),
-                });
-            }
-            crate::types::Asset::Alkane(alkane_id) => {
-                // Add alkane transfer output
-                let alkane_data = format!("ALKANE:{}:{}", alkane_id, receive_amount);
-                // Create a fixed-size array that implements AsRef<PushBytes>
-                let data_bytes = alkane_data.as_bytes();
-                let mut buffer = [0u8; 75]; // Max size for a standard push
-                let len = std::cmp::min(data_bytes.len(), 75);
-                buffer[..len].copy_from_slice(&data_bytes[..len]);
-                
-                // Create OP_RETURN script
-                let mut script = Script::new();
-                // Create a script with OP_RETURN
-                let mut builder = bitcoin::blockdata::script::Builder::new();
-                builder = builder.push_opcode(bitcoin::blockdata::opcodes::all::OP_RETURN);
-                
-                // Add the data
-                if len > 0 {
-                    builder = builder.push_slice(&buffer[..len]);
-                }
-                
-                // Build the script
-                let script = builder.into_script();
-                outputs.push(TxOut {
-                    value: 0,
-                    script_pubkey: script,
-                });
+        // The swap has no outputs for other assets, so spending them would lose them
+        if let Some((asset, _)) = selection.carried_assets().into_iter().find(|(asset, _)| asset != send_asset) {
+            return Err(Error::InvalidTransaction(format!(
+                "Swap inputs also hold {}, which the swap would not return",
+                asset
+            )));
+        }
 
-                // Add recipient output
-                outputs.push(TxOut {
-                    value: 546, // Dust limit
-                    script_pubkey: from_address.script_pubkey(),
+        // Return the rest of the sent asset to the sender
+        if asset_change > 0 {
+            let carried = selection.carried_assets()
+                .into_iter()
+                .find(|(asset, _)| asset == send_asset)
+                .map(|(_, amount)| amount)
+                .unwrap_or(0);
+            let rest = carried.checked_sub(send_amount as u128).ok_or(Error::InsufficientBalance)?;
+            if rest > 0 {
+                edicts.push(crate::runestone::Edict {
+                    id: Self::edict_id(send_asset)?,
+                    amount: rest,
+                    output: outputs.len() as u32,
                 });
             }
+            outputs.push(TxOut {
+                value: asset_change,
+                script_pubkey: from_address.script_pubkey(),
+            });
         }
 
-        // Calculate fee
-        let fee = Self::estimate_transaction_fee(inputs.len(), outputs.len() + 1, fee_rate);
+        // Fill in the runestone, or drop it when both legs are bitcoin
+        if edicts.is_empty() {
+            outputs.remove(0);
+        } else {
+            let runestone = crate::runestone::Runestone {
+                edicts,
+                etching: None,
+                default_output: None,
+                burn: false,
+            };
+            outputs[0].script_pubkey = runestone.to_script();
+        }
 
         // Add change output
-        let change_value = input_value - (if let crate::types::Asset::Bitcoin = send_asset { send_amount } else { 0 }) - fee;
+        let change_value = selection.change(target);
         if change_value > 546 {
             outputs.push(TxOut {
                 value: change_value,
@@ -736,14 +674,57 @@ impl PsbtUtils {
             });
         }
 
-        // Create PSBT
-        let mut psbt = Self::create_psbt(inputs, outputs)?;
+        Self::create_selection_psbt(&selection, outputs)
+    }
+
+    /// Add the output of one swap leg, with its edict for a rune or alkane
+    fn push_swap_leg(
+        outputs: &mut Vec<TxOut>,
+        edicts: &mut Vec<crate::runestone::Edict>,
+        asset: &Asset,
+        amount: u64,
+        address: &Address,
+    ) -> Result<()> {
+        match asset {
+            Asset::Bitcoin => {
+                outputs.push(TxOut {
+                    value: amount,
+                    script_pubkey: address.script_pubkey(),
+                });
+            }
+            asset => {
+                edicts.push(crate::runestone::Edict {
+                    id: Self::edict_id(asset)?,
+                    amount: amount as u128,
+                    output: outputs.len() as u32,
+                });
+                outputs.push(TxOut {
+                    value: 546, // Dust limit
+                    script_pubkey: address.script_pubkey(),
+                });
+            }
+        }
 
-        // Add input information
-        for (i, (_, txout)) in utxos.iter().enumerate() {
-            psbt.inputs[i].witness_utxo = Some(txout.clone());
+        Ok(())
+    }
+
+    /// Get the edict ID of a rune or alkane
+    fn edict_id(asset: &Asset) -> Result<u128> {
+        match asset {
+            Asset::Bitcoin => Err(Error::InvalidAsset("Bitcoin has no edict ID".to_string())),
+            Asset::Rune(rune_id) => Ok(*rune_id),
+            Asset::Alkane(alkane_id) => Self::alkane_edict_id(alkane_id),
         }
+    }
 
-        Ok(psbt)
+    /// Get the edict ID of an alkane
+    ///
+    /// Alkanes are implemented on top of runes, so an alkane moves by the edict
+    /// of its numeric ID.
+    fn alkane_edict_id(alkane_id: &AlkaneId) -> Result<u128> {
+        alkane_id.0.strip_prefix("ALKANE:")
+            .unwrap_or(&alkane_id.0)
+            .parse::<u128>()
+            .map_err(|_| Error::InvalidAlkane)
     }
 }
\ No newline at end of file
@@ -0,0 +1,503 @@
+//! Resilient chain backend
+//!
+//! Chain servers drop connections, restart and rate limit, and a single lost
+//! connection should not fail a wallet sync or a refund broadcast. A
+//! [`ResilientChain`] keeps a connection opened through a [`ChainConnector`]
+//! and reopens it after a failure. Failed requests are retried with exponential
+//! backoff and jitter, drawing on a retry budget shared by all requests so a
+//! dead server is not hammered. After too many consecutive failures a circuit
+//! breaker makes requests fail fast for a while, then lets a single request
+//! through to probe the server: if it fails the circuit opens again at once.
+//! Disconnects, reconnects and circuit changes are reported as events.
+//!
+//! The wrapper is generic over the connection type, so a backend with more
+//! requests than [`RefundChain`], like the Esplora client of the watch-only
+//! wallet, is made resilient through [`ResilientChain::call`].
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bitcoin::{OutPoint, Transaction, Txid};
+use log::{debug, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::config::ChainBackendConfig;
+use crate::trade::refund::RefundChain;
+use crate::types::Event;
+
+/// Opens connections to a chain server
+#[async_trait]
+pub trait ChainConnector<C: ?Sized + Send + Sync = dyn RefundChain>: Send + Sync {
+    /// Open a new connection
+    async fn connect(&self) -> Result<Arc<C>>;
+}
+
+/// Chain backend error
+#[derive(Debug, Error)]
+pub enum ChainBackendError {
+    /// Too many consecutive failures; requests fail fast until the backend is probed again
+    #[error("Chain backend unavailable, retrying in {0:?}")]
+    CircuitOpen(Duration),
+
+    /// The server answered, but refused the request; it is not retried
+    #[error("Chain backend rejected the request: {0}")]
+    Rejected(String),
+}
+
+/// Change of the chain backend connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChainBackendEvent {
+    /// The connection failed and was dropped
+    Disconnected {
+        /// Error that dropped it
+        error: String,
+    },
+    /// A new connection was opened after a disconnect
+    Reconnected,
+    /// Requests fail fast after too many consecutive failures
+    CircuitOpened {
+        /// Consecutive failures
+        failures: u32,
+        /// Time until the backend is probed again (seconds)
+        retry_after_secs: u64,
+    },
+    /// A request succeeded again after the circuit opened
+    CircuitClosed,
+}
+
+/// Connection and failure state
+struct State<C: ?Sized> {
+    /// Open connection
+    connection: Option<Arc<C>>,
+    /// A connection was dropped since the last one was opened
+    disconnected: bool,
+    /// Failures since the last successful request
+    consecutive_failures: u32,
+    /// End of the fail-fast period
+    open_until: Option<Instant>,
+    /// The circuit opened since the last successful request
+    circuit_open: bool,
+    /// A request is probing the backend after the fail-fast period
+    probing: bool,
+    /// Retries left in the budget
+    retry_tokens: u32,
+}
+
+/// Chain backend that reconnects and retries
+pub struct ResilientChain<C: ?Sized + Send + Sync + 'static = dyn RefundChain> {
+    /// Connection factory
+    connector: Arc<dyn ChainConnector<C>>,
+    /// Retry and circuit breaker settings
+    config: ChainBackendConfig,
+    /// Connection and failure state
+    state: Mutex<State<C>>,
+    /// Event sender
+    event_sender: Option<mpsc::Sender<Event>>,
+}
+
+impl<C: ?Sized + Send + Sync + 'static> ResilientChain<C> {
+    /// Create a backend connecting through `connector` on first use
+    pub fn new(connector: Arc<dyn ChainConnector<C>>, config: ChainBackendConfig) -> Self {
+        let state = State {
+            connection: None,
+            disconnected: false,
+            consecutive_failures: 0,
+            open_until: None,
+            circuit_open: false,
+            probing: false,
+            retry_tokens: config.retry_budget,
+        };
+
+        Self {
+            connector,
+            config,
+            state: Mutex::new(state),
+            event_sender: None,
+        }
+    }
+
+    /// Report connection changes as events
+    pub fn with_events(mut self, event_sender: mpsc::Sender<Event>) -> Self {
+        self.event_sender = Some(event_sender);
+        self
+    }
+
+    /// Run a request, reconnecting and retrying on failure
+    ///
+    /// The request is made again on a new connection after a failure, so it
+    /// must be safe to repeat. A [`ChainBackendError`] it returns is passed on
+    /// without a retry.
+    pub async fn call<T, F>(&self, request: F) -> Result<T>
+    where
+        F: Fn(Arc<C>) -> Pin<Box<dyn Future<Output = Result<T>> + Send>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            self.check_circuit().await?;
+
+            let error = match self.connection().await {
+                Ok(chain) => match request(chain).await {
+                    Ok(value) => {
+                        self.record_success().await;
+                        return Ok(value);
+                    }
+                    Err(e) if e.downcast_ref::<ChainBackendError>().is_some() => {
+                        // The server answered, so it is up
+                        self.record_success().await;
+                        return Err(e);
+                    }
+                    Err(e) => e,
+                },
+                Err(e) => e,
+            };
+
+            let circuit_opened = self.record_failure(&error).await;
+            if circuit_opened || attempt >= self.config.max_retries || !self.take_retry_token().await {
+                return Err(error.context(format!("Chain backend request failed after {} attempts", attempt + 1)));
+            }
+
+            let delay = backoff(
+                Duration::from_millis(self.config.retry_backoff),
+                Duration::from_millis(self.config.max_backoff),
+                attempt,
+            );
+            debug!("Retrying chain backend request in {:?}: {:#}", delay, error);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Fail fast while the circuit is open; once the period is over, let a single probe through
+    async fn check_circuit(&self) -> Result<(), ChainBackendError> {
+        let mut state = self.state.lock().await;
+
+        match state.open_until {
+            Some(until) if Instant::now() < until => Err(ChainBackendError::CircuitOpen(until - Instant::now())),
+            // Other requests wait for the outcome of the probe
+            Some(_) if state.probing => Err(ChainBackendError::CircuitOpen(Duration::ZERO)),
+            Some(_) => {
+                state.probing = true;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Get the open connection, opening one if there is none
+    ///
+    /// The connection is opened without holding the state lock, so requests
+    /// on the open circuit fail fast instead of waiting for it.
+    async fn connection(&self) -> Result<Arc<C>> {
+        if let Some(connection) = &self.state.lock().await.connection {
+            return Ok(connection.clone());
+        }
+
+        let connection = self.connector.connect().await?;
+
+        let mut state = self.state.lock().await;
+        // Another request may have connected meanwhile; keep a single connection
+        let connection = state.connection.get_or_insert(connection).clone();
+        let reconnected = std::mem::take(&mut state.disconnected);
+        drop(state);
+        if reconnected {
+            self.emit(ChainBackendEvent::Reconnected).await;
+        }
+
+        Ok(connection)
+    }
+
+    /// Close the circuit, reset the failure count and earn back a retry
+    async fn record_success(&self) {
+        let mut state = self.state.lock().await;
+        state.consecutive_failures = 0;
+        state.open_until = None;
+        state.probing = false;
+        state.retry_tokens = (state.retry_tokens + 1).min(self.config.retry_budget);
+
+        let closed = std::mem::take(&mut state.circuit_open);
+        drop(state);
+        if closed {
+            self.emit(ChainBackendEvent::CircuitClosed).await;
+        }
+    }
+
+    /// Drop the connection and count the failure, returning whether the circuit opened
+    async fn record_failure(&self, error: &anyhow::Error) -> bool {
+        let mut events = Vec::new();
+        let mut state = self.state.lock().await;
+
+        if state.connection.take().is_some() {
+            state.disconnected = true;
+            events.push(ChainBackendEvent::Disconnected { error: format!("{:#}", error) });
+        }
+
+        // A failed probe opens the circuit again at once
+        state.consecutive_failures += 1;
+        let opened = std::mem::take(&mut state.probing)
+            || state.consecutive_failures >= self.config.failure_threshold.max(1);
+        if opened {
+            let open_time = Duration::from_secs(self.config.circuit_open_time);
+            state.open_until = Some(Instant::now() + open_time);
+            state.circuit_open = true;
+            events.push(ChainBackendEvent::CircuitOpened {
+                failures: state.consecutive_failures,
+                retry_after_secs: open_time.as_secs(),
+            });
+            // The count starts over for the probe
+            state.consecutive_failures = 0;
+        }
+        drop(state);
+
+        for event in events {
+            self.emit(event).await;
+        }
+
+        opened
+    }
+
+    /// Take a retry from the budget, if one is left
+    async fn take_retry_token(&self) -> bool {
+        let mut state = self.state.lock().await;
+        if state.retry_tokens == 0 {
+            warn!("Chain backend retry budget exhausted");
+            return false;
+        }
+
+        state.retry_tokens -= 1;
+        true
+    }
+
+    /// Send an event, if events are reported
+    async fn emit(&self, event: ChainBackendEvent) {
+        debug!("Chain backend: {:?}", event);
+
+        if let Some(sender) = &self.event_sender {
+            if let Err(e) = sender.send(Event::ChainBackend(event)).await {
+                warn!("Failed to send chain backend event: {}", e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<C: ?Sized + RefundChain + 'static> RefundChain for ResilientChain<C> {
+    async fn tip_height(&self) -> Result<u32> {
+        self.call(|chain| Box::pin(async move { chain.tip_height().await })).await
+    }
+
+    async fn is_spent(&self, outpoint: &OutPoint) -> Result<bool> {
+        let outpoint = *outpoint;
+        self.call(move |chain| Box::pin(async move { chain.is_spent(&outpoint).await })).await
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
+        self.call(|chain| {
+            let tx = tx.clone();
+            Box::pin(async move { chain.broadcast(&tx).await })
+        })
+        .await
+    }
+}
+
+/// Get the delay before retry number `attempt` (from zero)
+///
+/// The delay doubles with each attempt up to `max`, and is then drawn from its
+/// upper half so clients that failed together do not retry together.
+pub fn backoff(base: Duration, max: Duration, attempt: u32) -> Duration {
+    let delay = base.checked_mul(2u32.saturating_pow(attempt)).map_or(max, |delay| delay.min(max));
+    let millis = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX);
+
+    Duration::from_millis(millis - rand::thread_rng().gen_range(0..=millis / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Chain failing its first requests
+    struct FlakyChain {
+        failures_left: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl RefundChain for FlakyChain {
+        async fn tip_height(&self) -> Result<u32> {
+            let failing = self.failures_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+                .is_ok();
+            if failing {
+                return Err(anyhow::anyhow!("Connection reset"));
+            }
+            Ok(800_000)
+        }
+
+        async fn is_spent(&self, _outpoint: &OutPoint) -> Result<bool> {
+            Ok(false)
+        }
+
+        async fn broadcast(&self, _tx: &Transaction) -> Result<Txid> {
+            Err(ChainBackendError::Rejected("txn-mempool-conflict".to_string()).into())
+        }
+    }
+
+    /// Connector counting its connections
+    struct Connector {
+        connects: AtomicU32,
+        failures_left: Arc<AtomicU32>,
+    }
+
+    impl Connector {
+        fn new(failures: u32) -> Arc<Self> {
+            Arc::new(Self {
+                connects: AtomicU32::new(0),
+                failures_left: Arc::new(AtomicU32::new(failures)),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl ChainConnector for Connector {
+        async fn connect(&self) -> Result<Arc<dyn RefundChain>> {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            Ok(Arc::new(FlakyChain { failures_left: self.failures_left.clone() }))
+        }
+    }
+
+    fn config() -> ChainBackendConfig {
+        ChainBackendConfig {
+            retry_backoff: 1,
+            max_backoff: 5,
+            ..ChainBackendConfig::default()
+        }
+    }
+
+    fn drain(events: &mut mpsc::Receiver<Event>) -> Vec<ChainBackendEvent> {
+        std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                Event::ChainBackend(event) => Some(event),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_and_retries() {
+        let connector = Connector::new(2);
+        let (sender, mut events) = mpsc::channel(16);
+        let chain = ResilientChain::new(connector.clone(), config()).with_events(sender);
+
+        assert_eq!(chain.tip_height().await.unwrap(), 800_000);
+        assert_eq!(connector.connects.load(Ordering::SeqCst), 3);
+
+        let events = drain(&mut events);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], ChainBackendEvent::Disconnected { .. }));
+        assert_eq!(events[1], ChainBackendEvent::Reconnected);
+    }
+
+    #[tokio::test]
+    async fn test_rejections_are_not_retried() {
+        let connector = Connector::new(0);
+        let chain = ResilientChain::new(connector.clone(), config());
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        let error = chain.broadcast(&tx).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ChainBackendError>(), Some(ChainBackendError::Rejected(_))));
+        assert_eq!(connector.connects.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let connector = Connector::new(10);
+        let (sender, mut events) = mpsc::channel(16);
+        let chain = ResilientChain::new(connector.clone(), ChainBackendConfig {
+            max_retries: 0,
+            failure_threshold: 2,
+            ..config()
+        })
+        .with_events(sender);
+
+        assert!(chain.tip_height().await.is_err());
+        assert!(chain.tip_height().await.is_err());
+        assert!(drain(&mut events).contains(&ChainBackendEvent::CircuitOpened { failures: 2, retry_after_secs: 30 }));
+
+        // Requests fail fast without reaching the server
+        let error = chain.tip_height().await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ChainBackendError>(), Some(ChainBackendError::CircuitOpen(_))));
+        assert_eq!(connector.connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_half_open_probe() {
+        let connector = Connector::new(3);
+        let (sender, mut events) = mpsc::channel(16);
+        let chain = ResilientChain::new(connector.clone(), ChainBackendConfig {
+            max_retries: 0,
+            failure_threshold: 2,
+            circuit_open_time: 0,
+            ..config()
+        })
+        .with_events(sender);
+
+        assert!(chain.tip_height().await.is_err());
+        assert!(chain.tip_height().await.is_err());
+        drain(&mut events);
+
+        // While a probe is in flight, other requests fail fast
+        chain.state.lock().await.probing = true;
+        let error = chain.tip_height().await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<ChainBackendError>(), Some(ChainBackendError::CircuitOpen(_))));
+        chain.state.lock().await.probing = false;
+
+        // A failed probe opens the circuit again without waiting for the threshold
+        assert!(chain.tip_height().await.is_err());
+        assert!(drain(&mut events).contains(&ChainBackendEvent::CircuitOpened { failures: 1, retry_after_secs: 0 }));
+
+        // A successful probe closes it
+        assert_eq!(chain.tip_height().await.unwrap(), 800_000);
+        assert!(drain(&mut events).contains(&ChainBackendEvent::CircuitClosed));
+        assert_eq!(chain.state.lock().await.consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget() {
+        let connector = Connector::new(10);
+        let chain = ResilientChain::new(connector.clone(), ChainBackendConfig {
+            retry_budget: 1,
+            failure_threshold: 100,
+            ..config()
+        });
+
+        // One retry is left in the budget, then the request gives up
+        assert!(chain.tip_height().await.is_err());
+        assert_eq!(connector.connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_millis(500);
+        let max = Duration::from_secs(30);
+
+        for attempt in 0..3 {
+            let delay = backoff(base, max, attempt);
+            let full = base * 2u32.pow(attempt);
+            assert!(delay <= full && delay >= full / 2);
+        }
+        assert!(backoff(base, max, 40) <= max);
+    }
+}
@@ -0,0 +1,94 @@
+//! Time source
+//!
+//! Order expiry, trade timestamps and timeouts, quote and dark order expiry, the
+//! schedulers and the refund sweeper read the time through the `Clock` of the
+//! node, handed to each module with `with_clock` or as a `now` argument, so tests
+//! can run them against a `MockClock` and move time forward instead of sleeping.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Source of the current time
+pub trait Clock: Send + Sync {
+    /// Get the current time (unix seconds)
+    fn now(&self) -> u64;
+
+    /// Get the current time (unix milliseconds)
+    fn now_ms(&self) -> u64 {
+        self.now().saturating_mul(1000)
+    }
+}
+
+/// Wall clock time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn now_ms(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// Clock that only moves when told to, for tests
+#[derive(Debug, Default)]
+pub struct MockClock {
+    /// Current time (unix seconds)
+    now: AtomicU64,
+}
+
+impl MockClock {
+    /// Create a clock stopped at `now` (unix seconds)
+    pub fn new(now: u64) -> Self {
+        Self { now: AtomicU64::new(now) }
+    }
+
+    /// Set the time (unix seconds)
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    /// Move the time forward
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// Get the wall clock
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1_700_000_000);
+        assert_eq!(clock.now(), 1_700_000_000);
+
+        clock.advance(60);
+        assert_eq!(clock.now(), 1_700_000_060);
+
+        clock.set(5);
+        assert_eq!(clock.now(), 5);
+
+        assert!(SystemClock.now() > 1_700_000_000);
+    }
+}
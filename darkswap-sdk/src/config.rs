@@ -2,6 +2,7 @@
 //!
 //! This module provides configuration options for the DarkSwap SDK.
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
@@ -10,8 +11,17 @@ use anyhow::{Context, Result};
 use libp2p::core::multiaddr::Multiaddr;
 use serde::{Deserialize, Serialize};
 
+use crate::events::OverflowPolicy;
+use crate::p2p::ice::{IceTransportPolicy, TurnServer};
+use crate::trade::fees::{OrderFees, ReferralShare};
+use crate::trade::limits::TradeLimits;
+
+pub use darkswap_support::secrets::SecretResolver;
+pub use darkswap_support::storage::{EncryptionConfig, StorageBackend, StorageConfig};
+use darkswap_support::storage::DocumentFile;
+
 /// Bitcoin network
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BitcoinNetwork {
     /// Mainnet
     Mainnet,
@@ -35,6 +45,17 @@ impl BitcoinNetwork {
     }
 }
 
+impl From<BitcoinNetwork> for bitcoin::Network {
+    fn from(network: BitcoinNetwork) -> Self {
+        match network {
+            BitcoinNetwork::Mainnet => bitcoin::Network::Bitcoin,
+            BitcoinNetwork::Testnet => bitcoin::Network::Testnet,
+            BitcoinNetwork::Regtest => bitcoin::Network::Regtest,
+            BitcoinNetwork::Signet => bitcoin::Network::Signet,
+        }
+    }
+}
+
 /// Bitcoin configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BitcoinConfig {
@@ -42,8 +63,18 @@ pub struct BitcoinConfig {
     pub network: BitcoinNetwork,
     /// Electrum server URL
     pub electrum_url: Option<String>,
+    /// Esplora server URL the node watches the chain through, for refunds
+    #[serde(default)]
+    pub esplora_url: Option<String>,
     /// Fee rate (satoshis per vbyte)
     pub fee_rate: f32,
+    /// Verify transaction inclusion against locally tracked block headers
+    pub spv_verification: bool,
+    /// Minimum confirmations required by SPV verification
+    pub spv_min_confirmations: u32,
+    /// Reconnection and retries of the chain backend
+    #[serde(default)]
+    pub backend: ChainBackendConfig,
 }
 
 impl Default for BitcoinConfig {
@@ -51,11 +82,79 @@ impl Default for BitcoinConfig {
         Self {
             network: BitcoinNetwork::Testnet,
             electrum_url: None,
+            esplora_url: None,
             fee_rate: 5.0,
+            spv_verification: false,
+            spv_min_confirmations: 1,
+            backend: ChainBackendConfig::default(),
         }
     }
 }
 
+/// Reconnection and retry configuration of the chain backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainBackendConfig {
+    /// Retries of a failed request after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one (milliseconds)
+    pub retry_backoff: u64,
+    /// Longest delay between retries (milliseconds)
+    pub max_backoff: u64,
+    /// Retries available across requests; each successful request earns one back
+    pub retry_budget: u32,
+    /// Consecutive failures after which requests fail fast
+    pub failure_threshold: u32,
+    /// Time requests fail fast before the backend is tried again (seconds)
+    pub circuit_open_time: u64,
+}
+
+impl Default for ChainBackendConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            retry_backoff: 500,
+            max_backoff: 30_000,
+            retry_budget: 20,
+            failure_threshold: 5,
+            circuit_open_time: 30,
+        }
+    }
+}
+
+/// Settings that differ between Bitcoin networks
+///
+/// A configuration may hold a profile per network. When DarkSwap runs on, or
+/// switches to, a network with a profile, the values the profile sets replace
+/// those of the configuration; the others are shared by every network.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkProfile {
+    /// Electrum server URL
+    pub electrum_url: Option<String>,
+    /// Esplora server URL
+    pub esplora_url: Option<String>,
+    /// Bootstrap peers
+    pub bootstrap_peers: Option<Vec<Multiaddr>>,
+    /// Relay servers
+    pub relay_servers: Option<Vec<Multiaddr>>,
+    /// File known peers are persisted to
+    pub peer_store_path: Option<String>,
+    /// File relay health history is persisted to
+    pub relay_store_path: Option<String>,
+    /// Wallet, whose keys and addresses belong to one network
+    pub wallet: Option<WalletConfig>,
+    /// File own open orders are persisted to
+    pub order_store_path: Option<String>,
+    /// File escrow refund paths are kept in
+    pub refund_store_path: Option<String>,
+    /// Operator fees, paid to an address of this network
+    pub fees: Option<OrderFees>,
+    /// Referrers, paid to addresses of this network
+    pub referrers: Option<Vec<ReferralShare>>,
+    /// Storage
+    pub storage: Option<StorageConfig>,
+}
+
 /// P2P configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct P2PConfig {
@@ -67,8 +166,14 @@ pub struct P2PConfig {
     pub relay_servers: Vec<Multiaddr>,
     /// Enable WebRTC
     pub enable_webrtc: bool,
-    /// WebRTC ICE servers
+    /// WebRTC STUN server URLs; with no TURN servers, connections are STUN-only
     pub ice_servers: Vec<String>,
+    /// WebRTC TURN servers
+    #[serde(default)]
+    pub turn_servers: Vec<TurnServer>,
+    /// Candidates WebRTC connections may use; `relay` sends everything through TURN
+    #[serde(default)]
+    pub ice_transport_policy: IceTransportPolicy,
     /// Signaling server URL
     pub signaling_server_url: Option<String>,
     /// Enable mDNS
@@ -77,6 +182,49 @@ pub struct P2PConfig {
     pub enable_kademlia: bool,
     /// Enable circuit relay
     pub enable_circuit_relay: bool,
+    /// Node identity key (protobuf encoded, hex); a random key is used if unset
+    pub identity_key: Option<String>,
+    /// Delay between dialing alternative paths to a peer (milliseconds)
+    #[serde(default = "default_dial_stagger_ms")]
+    pub dial_stagger_ms: u64,
+    /// Timeout of a single path dial attempt (seconds)
+    #[serde(default = "default_dial_timeout")]
+    pub dial_timeout: u64,
+    /// File known peers are persisted to; peers are kept in memory only if unset
+    pub peer_store_path: Option<String>,
+    /// Time a peer address is kept after it was last seen (seconds)
+    #[serde(default = "default_peer_address_ttl")]
+    pub peer_address_ttl: u64,
+    /// Interval between saves of the peer store (seconds); 0 saves it on stop only
+    #[serde(default = "default_peer_store_save_interval")]
+    pub peer_store_save_interval: u64,
+    /// File relay health history is persisted to; kept in memory only if unset
+    #[serde(default)]
+    pub relay_store_path: Option<String>,
+    /// Interval between relay health checks (seconds); 0 disables them
+    #[serde(default = "default_relay_check_interval")]
+    pub relay_check_interval: u64,
+    /// Keep using relays that worked in earlier sessions after they leave `relay_servers`
+    #[serde(default)]
+    pub remember_relays: bool,
+    /// Maximum number of warm connections kept to counterparties and viewed makers; 0 disables the pool
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    /// Idle time after which a warm connection is recycled (seconds)
+    #[serde(default = "default_pool_idle_ttl")]
+    pub pool_idle_ttl: u64,
+    /// Completed trades with a peer after which its connection is kept warm
+    #[serde(default = "default_pool_frequent_after")]
+    pub pool_frequent_after: u32,
+    /// Region advertised in own orders, so takers can prefer nearby makers
+    pub region: Option<String>,
+    /// Outbound message queues
+    #[serde(default)]
+    pub send_queue: SendQueueConfig,
+    /// Faults injected into outbound messages; test builds only
+    #[cfg(feature = "fault-injection")]
+    #[serde(default)]
+    pub faults: Option<FaultConfig>,
 }
 
 impl Default for P2PConfig {
@@ -90,14 +238,110 @@ impl Default for P2PConfig {
                 "stun:stun.l.google.com:19302".to_string(),
                 "stun:stun1.l.google.com:19302".to_string(),
             ],
+            turn_servers: vec![],
+            ice_transport_policy: IceTransportPolicy::All,
             signaling_server_url: Some("wss://signaling.darkswap.io".to_string()),
             enable_mdns: true,
             enable_kademlia: true,
             enable_circuit_relay: true,
+            identity_key: None,
+            dial_stagger_ms: default_dial_stagger_ms(),
+            dial_timeout: default_dial_timeout(),
+            peer_store_path: None,
+            peer_address_ttl: default_peer_address_ttl(),
+            peer_store_save_interval: default_peer_store_save_interval(),
+            relay_store_path: None,
+            relay_check_interval: default_relay_check_interval(),
+            remember_relays: false,
+            pool_size: default_pool_size(),
+            pool_idle_ttl: default_pool_idle_ttl(),
+            pool_frequent_after: default_pool_frequent_after(),
+            region: None,
+            send_queue: SendQueueConfig::default(),
+            #[cfg(feature = "fault-injection")]
+            faults: None,
         }
     }
 }
 
+/// Default delay between dialing alternative paths to a peer (milliseconds)
+fn default_dial_stagger_ms() -> u64 {
+    250
+}
+
+/// Default timeout of a single path dial attempt (seconds)
+fn default_dial_timeout() -> u64 {
+    10
+}
+
+/// Default time a peer address is kept after it was last seen (seconds)
+fn default_peer_address_ttl() -> u64 {
+    604800 // 7 days
+}
+
+/// Default interval between saves of the peer store (seconds)
+fn default_peer_store_save_interval() -> u64 {
+    300
+}
+
+/// Default interval between relay health checks (seconds)
+fn default_relay_check_interval() -> u64 {
+    60
+}
+
+/// Default maximum number of warm connections
+fn default_pool_size() -> usize {
+    8
+}
+
+/// Default idle time after which a warm connection is recycled (seconds)
+fn default_pool_idle_ttl() -> u64 {
+    600 // 10 minutes
+}
+
+/// Default number of completed trades after which a counterparty is kept warm
+fn default_pool_frequent_after() -> u32 {
+    3
+}
+
+/// Outbound message queue configuration
+///
+/// Trade messages, other requests and gossip queue separately, and a queue
+/// is only sent from when those above it are empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SendQueueConfig {
+    /// Messages each class may have waiting; the oldest gossip is dropped beyond it
+    pub capacity: usize,
+}
+
+impl Default for SendQueueConfig {
+    fn default() -> Self {
+        Self { capacity: 4096 }
+    }
+}
+
+/// Network faults to inject, as rates between 0 and 1
+#[cfg(feature = "fault-injection")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    /// Share of messages dropped
+    pub drop_rate: f64,
+    /// Share of messages delivered twice
+    pub duplicate_rate: f64,
+    /// Share of messages delivered after the next one on their stream
+    pub reorder_rate: f64,
+    /// Share of sends delayed
+    pub delay_rate: f64,
+    /// Delay of a delayed send (milliseconds)
+    pub delay_ms: u64,
+    /// Random extra delay, up to this (milliseconds)
+    pub delay_jitter_ms: u64,
+    /// Seed of the fault rolls, to replay a run; random if unset
+    pub seed: Option<u64>,
+}
+
 /// Wallet configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WalletConfig {
@@ -107,8 +351,35 @@ pub struct WalletConfig {
     pub private_key: Option<String>,
     /// Mnemonic
     pub mnemonic: Option<String>,
-    /// Derivation path
+    /// Derivation path; for a `simple` wallet, that of the parent of the receive
+    /// addresses (e.g. `m/84'/1'/0'/0`), ignored if `descriptor` is set
     pub derivation_path: Option<String>,
+    /// Private output descriptor of a `simple` wallet, in place of `private_key`
+    #[serde(default)]
+    pub descriptor: Option<String>,
+    /// Number of unused receive addresses that may be issued in a row
+    pub gap_limit: u32,
+    /// Dust consolidation
+    #[serde(default)]
+    pub consolidation: ConsolidationConfig,
+    /// Bitcoin kept back for the fees of in-flight trades
+    #[serde(default)]
+    pub fee_reserve: FeeReserveConfig,
+    /// Signing service holding the keys of a `remote` wallet
+    pub remote_signer: Option<RemoteSignerConfig>,
+    /// Custody provider holding the keys and balances of a `custody` wallet
+    #[serde(default)]
+    pub custody: Option<CustodyConfig>,
+    /// Public descriptor of the addresses a `remote` or `custody` wallet
+    /// receives on, watched through `bitcoin.esplora_url`
+    #[serde(default)]
+    pub watch_descriptor: Option<String>,
+    /// Balance change events
+    #[serde(default)]
+    pub balance_sync: BalanceSyncConfig,
+    /// Deposit detection
+    #[serde(default)]
+    pub deposits: DepositConfig,
 }
 
 impl Default for WalletConfig {
@@ -118,10 +389,199 @@ impl Default for WalletConfig {
             private_key: None,
             mnemonic: None,
             derivation_path: None,
+            descriptor: None,
+            gap_limit: 20,
+            consolidation: ConsolidationConfig::default(),
+            fee_reserve: FeeReserveConfig::default(),
+            remote_signer: None,
+            custody: None,
+            watch_descriptor: None,
+            balance_sync: BalanceSyncConfig::default(),
+            deposits: DepositConfig::default(),
+        }
+    }
+}
+
+/// Dust consolidation configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsolidationConfig {
+    /// Consolidate on a schedule
+    pub scheduled: bool,
+    /// Time between scheduled consolidations (seconds)
+    pub interval: u64,
+    /// Outputs at or below this value are dust (satoshis)
+    pub dust_threshold: u64,
+    /// Highest estimated fee rate to consolidate at (sat/vB)
+    pub max_fee_rate: f32,
+    /// Fewest dust outputs worth a transaction
+    pub min_inputs: usize,
+    /// Most outputs spent by one transaction
+    pub max_inputs: usize,
+}
+
+impl Default for ConsolidationConfig {
+    fn default() -> Self {
+        Self {
+            scheduled: false,
+            interval: 3600,
+            dust_threshold: 1_000,
+            max_fee_rate: 2.0,
+            min_inputs: 10,
+            max_inputs: 200,
+        }
+    }
+}
+
+/// Fee reserve configuration
+///
+/// Orders and inbound trades are refused if they would leave less than
+/// `minimum + per_trade * in-flight trades` of uncommitted bitcoin. Both default
+/// to zero, which disables the reserve.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeReserveConfig {
+    /// Bitcoin always kept back (satoshis)
+    pub minimum: u64,
+    /// Bitcoin kept back per in-flight trade (satoshis)
+    pub per_trade: u64,
+}
+
+impl Default for FeeReserveConfig {
+    fn default() -> Self {
+        Self {
+            minimum: 0,
+            per_trade: 0,
+        }
+    }
+}
+
+/// Balance change event configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BalanceSyncConfig {
+    /// Read the wallet balances periodically and emit an event for each change
+    pub enabled: bool,
+    /// Time between readings (seconds)
+    pub interval: u64,
+}
+
+impl Default for BalanceSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 30,
+        }
+    }
+}
+
+/// Deposit detection configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DepositConfig {
+    /// Scan the wallet for incoming payments and emit an event for each
+    pub enabled: bool,
+    /// Time between scans (seconds)
+    pub interval: u64,
+    /// Confirmations after which a deposit is confirmed
+    pub confirmations: u32,
+}
+
+impl Default for DepositConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 30,
+            confirmations: 1,
         }
     }
 }
 
+/// Remote signer configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteSignerConfig {
+    /// Signer base URL
+    pub url: String,
+    /// Bearer token sent to the signer
+    pub auth_token: Option<String>,
+    /// CA certificate (PEM file) the signer's certificate must chain to, instead of the built-in roots
+    pub ca_cert: Option<String>,
+    /// Client certificate and key (PEM file) for mutual TLS
+    pub client_identity: Option<String>,
+    /// Policy the signer is expected to apply and echo back
+    pub policy: Option<String>,
+    /// Request timeout (seconds)
+    pub timeout: u64,
+    /// Allow a plain HTTP signer URL, for local testing only
+    pub allow_insecure: bool,
+}
+
+/// External custody provider configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CustodyConfig {
+    /// Provider base URL
+    pub url: String,
+    /// Bearer token sent to the provider
+    pub auth_token: Option<String>,
+    /// Policy the provider is expected to apply and echo back
+    pub policy: Option<String>,
+    /// Request timeout (seconds)
+    pub timeout: u64,
+    /// Times a request that failed in transit or with a server error is retried
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one (milliseconds)
+    pub retry_backoff: u64,
+    /// Allow a plain HTTP provider URL, for local testing only
+    pub allow_insecure: bool,
+}
+
+impl Default for CustodyConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            auth_token: None,
+            policy: None,
+            timeout: 30,
+            max_retries: 3,
+            retry_backoff: 500,
+            allow_insecure: false,
+        }
+    }
+}
+
+/// Private trading group configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupConfig {
+    /// Group name
+    pub name: String,
+    /// Shared group key (32 bytes, hex); orders are encrypted to it if set
+    pub key: Option<String>,
+    /// Peer IDs of the makers whose orders are accepted; any key holder if empty
+    pub allowlist: Vec<String>,
+    /// Peer IDs allowed to take the group's orders; the allowlisted makers if empty
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+/// Order co-signer configuration
+///
+/// Used by makers whose funds sit in a multisig wallet: the second signer is a
+/// service that must sign every order PSBT before the order is published.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CosignerConfig {
+    /// URL the order PSBTs are POSTed to
+    pub url: String,
+    /// Bearer token sent to the co-signer
+    pub auth_token: Option<String>,
+    /// Signatures each input needs once co-signed
+    pub required_signatures: usize,
+    /// Public keys of the multisig account (hex); signatures by other keys are rejected
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// Request timeout (seconds)
+    pub timeout: u64,
+}
+
 /// Orderbook configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderbookConfig {
@@ -133,6 +593,41 @@ pub struct OrderbookConfig {
     pub min_order_amount: String,
     /// Maximum order amount
     pub max_order_amount: String,
+    /// Maximum age of a relay-served orderbook snapshot (seconds)
+    pub snapshot_max_age: u64,
+    /// File this node's open orders are persisted to and re-announced from on start
+    pub order_store_path: Option<String>,
+    /// File scheduled and conditional orders are persisted to
+    pub schedule_store_path: Option<String>,
+    /// Interval at which scheduled orders are checked (seconds)
+    #[serde(default = "default_scheduler_interval")]
+    pub scheduler_interval: u64,
+    /// File iceberg orders are persisted to
+    pub iceberg_store_path: Option<String>,
+    /// File the followed makers and pairs are persisted to
+    pub watchlist_path: Option<String>,
+    /// Private trading groups joined on start
+    #[serde(default)]
+    pub groups: Vec<GroupConfig>,
+    /// Co-signer own orders must be approved by before they are published
+    pub cosigner: Option<CosignerConfig>,
+    /// How long filled, canceled and expired orders are kept in memory
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// How order digests are published and answered
+    #[serde(default)]
+    pub digest: DigestConfig,
+    /// Recent order gossip kept for late joiners
+    #[serde(default)]
+    pub gossip_cache: GossipCacheConfig,
+    /// Key own orders are announced with, instead of the network identity key
+    #[serde(default)]
+    pub announcement: Option<AnnouncementConfig>,
+}
+
+/// Default interval at which scheduled orders are checked (seconds)
+fn default_scheduler_interval() -> u64 {
+    5
 }
 
 impl Default for OrderbookConfig {
@@ -142,10 +637,109 @@ impl Default for OrderbookConfig {
             max_order_expiry: 604800, // 7 days
             min_order_amount: "0.00000001".to_string(),
             max_order_amount: "1000.0".to_string(),
+            snapshot_max_age: 300, // 5 minutes
+            order_store_path: None,
+            schedule_store_path: None,
+            scheduler_interval: default_scheduler_interval(),
+            iceberg_store_path: None,
+            watchlist_path: None,
+            groups: Vec::new(),
+            cosigner: None,
+            retention: RetentionConfig::default(),
+            digest: DigestConfig::default(),
+            gossip_cache: GossipCacheConfig::default(),
+            announcement: None,
+        }
+    }
+}
+
+/// Retention of terminal orders
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Days a filled, canceled or expired order is kept after it closed
+    pub max_age_days: u64,
+    /// Terminal orders kept at most; the oldest beyond this are compacted first
+    pub max_records: usize,
+    /// Interval at which the book is compacted (seconds)
+    pub compaction_interval: u64,
+    /// Archive compacted orders to storage instead of dropping them
+    pub archive: bool,
+    /// Archived orders kept at most; those closed first are dropped beyond this, 0 keeps all
+    #[serde(default = "default_max_archived")]
+    pub max_archived: usize,
+}
+
+/// Default number of archived orders kept
+fn default_max_archived() -> usize {
+    100_000
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age_days: 7,
+            max_records: 10_000,
+            compaction_interval: 3600, // 1 hour
+            archive: true,
+            max_archived: default_max_archived(),
+        }
+    }
+}
+
+/// Order digest gossip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DigestConfig {
+    /// Interval at which the digest of the servable orders is published (seconds); 0 disables publishing
+    pub interval: u64,
+    /// Orders fetched from, or served to, a peer per request
+    pub max_fetch: usize,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        Self {
+            interval: 60,
+            max_fetch: 256,
+        }
+    }
+}
+
+/// Gossip cache serving late joiners
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GossipCacheConfig {
+    /// Keep recent signed orders and answer backfill requests
+    pub enabled: bool,
+    /// How long orders are kept (minutes)
+    pub retention_minutes: u64,
+    /// Orders kept per pair
+    pub max_per_pair: usize,
+    /// Orders served to, or accepted from, a peer per backfill request
+    pub max_backfill: usize,
+}
+
+impl Default for GossipCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_minutes: 10,
+            max_per_pair: 1000,
+            max_backfill: 500,
         }
     }
 }
 
+/// Announcement key configuration
+///
+/// Exactly one of `key` and `remote` must be set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnnouncementConfig {
+    /// Announcement key held by the node (protobuf encoded, hex)
+    pub key: Option<String>,
+    /// Signer holding the announcement key
+    pub remote: Option<RemoteSignerConfig>,
+}
+
 /// Trade configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TradeConfig {
@@ -155,6 +749,43 @@ pub struct TradeConfig {
     pub max_trade_expiry: u64,
     /// Trade timeout (seconds)
     pub trade_timeout: u64,
+    /// Accept inbound trades the approval handler did not answer in time, or all
+    /// inbound trades if there is no approval handler
+    #[serde(default = "default_auto_accept")]
+    pub auto_accept: bool,
+    /// Time the approval handler has to answer once it displayed a trade (seconds)
+    #[serde(default = "default_approval_timeout")]
+    pub approval_timeout: u64,
+    /// File to keep trade and transaction memos in; memos are kept in memory if unset
+    pub memo_store_path: Option<String>,
+    /// How old a trade message may be when it arrives; older ones are rejected as replays (seconds)
+    #[serde(default = "default_message_window")]
+    pub message_window: u64,
+    /// File to keep escrow refund paths in; they are kept in memory if unset
+    pub refund_store_path: Option<String>,
+    /// How often matured refunds are swept (seconds)
+    #[serde(default = "default_refund_sweep_interval")]
+    pub refund_sweep_interval: u64,
+    /// Operator fees charged on the trades of own orders; none are charged if unset
+    pub fees: Option<OrderFees>,
+    /// Referrers sharing the operator fees of the orders tagged with their code
+    #[serde(default)]
+    pub referrers: Vec<ReferralShare>,
+    /// Bounds on the trades in flight; unlimited by default
+    #[serde(default)]
+    pub limits: TradeLimits,
+    /// Only pay counterparty payout addresses that come with a BIP-322 proof of ownership
+    #[serde(default)]
+    pub require_payout_proof: bool,
+    /// PSBT and order signature checks run at once; 0 runs one per core
+    #[serde(default)]
+    pub verification_concurrency: usize,
+    /// Local negotiation transcripts; none are kept by default
+    #[serde(default)]
+    pub transcripts: TranscriptConfig,
+    /// Retries of trade messages the counterparty did not acknowledge
+    #[serde(default)]
+    pub outbox: OutboxConfig,
 }
 
 impl Default for TradeConfig {
@@ -163,6 +794,79 @@ impl Default for TradeConfig {
             default_trade_expiry: 3600, // 1 hour
             max_trade_expiry: 86400, // 24 hours
             trade_timeout: 300, // 5 minutes
+            auto_accept: default_auto_accept(),
+            approval_timeout: default_approval_timeout(),
+            memo_store_path: None,
+            message_window: default_message_window(),
+            refund_store_path: None,
+            refund_sweep_interval: default_refund_sweep_interval(),
+            fees: None,
+            referrers: Vec::new(),
+            limits: TradeLimits::default(),
+            require_payout_proof: false,
+            verification_concurrency: 0,
+            transcripts: TranscriptConfig::default(),
+            outbox: OutboxConfig::default(),
+        }
+    }
+}
+
+/// Default policy for inbound trades nobody approved
+fn default_auto_accept() -> bool {
+    true
+}
+
+/// Default time the approval handler has to answer (seconds)
+fn default_approval_timeout() -> u64 {
+    30
+}
+
+/// Default age past which trade messages are rejected as replays (seconds)
+fn default_message_window() -> u64 {
+    120
+}
+
+/// Default interval between refund sweeps (seconds)
+fn default_refund_sweep_interval() -> u64 {
+    60
+}
+
+/// Negotiation transcript configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptConfig {
+    /// Keep the negotiation transcripts of trades; requires encrypted storage
+    pub enabled: bool,
+    /// Days a transcript is kept after its last message; 0 keeps transcripts forever
+    pub retention_days: u64,
+}
+
+impl Default for TranscriptConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_days: 90,
+        }
+    }
+}
+
+/// Trade outbox configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboxConfig {
+    /// Keep sending trade messages until the counterparty acknowledges them
+    pub enabled: bool,
+    /// Time a message is sent again for; useless beyond the counterparty's message window (seconds)
+    pub ttl: u64,
+    /// Time between sends of a message not acknowledged (seconds)
+    pub retry_interval: u64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl: 120,
+            retry_interval: 10,
         }
     }
 }
@@ -209,6 +913,166 @@ impl Default for PerformanceConfig {
     }
 }
 
+/// Event configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventConfig {
+    /// Capacity of the internal event channel
+    pub channel_capacity: usize,
+    /// Capacity of each subscriber queue
+    pub subscriber_capacity: usize,
+    /// Behaviour when a subscriber queue is full
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for EventConfig {
+    fn default() -> Self {
+        Self {
+            channel_capacity: 1000,
+            subscriber_capacity: 100,
+            overflow_policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+/// Remote daemon in a federation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteDaemonConfig {
+    /// Name shown as the origin of the daemon's orders
+    pub name: String,
+    /// Base URL of the daemon's REST API
+    pub url: String,
+}
+
+/// Federation configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationConfig {
+    /// Whether to aggregate the orderbooks of the remote daemons
+    pub enabled: bool,
+    /// Trusted remote daemons
+    pub remotes: Vec<RemoteDaemonConfig>,
+    /// Refresh interval in seconds
+    pub refresh_interval: u64,
+    /// Request timeout in seconds
+    pub request_timeout: u64,
+    /// Seconds without a successful fetch after which a remote's orders are dropped
+    #[serde(default = "default_federation_stale_after")]
+    pub stale_after: u64,
+}
+
+/// Default time after which an unreachable remote's orders are dropped (seconds)
+fn default_federation_stale_after() -> u64 {
+    60
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            remotes: Vec::new(),
+            refresh_interval: 10,
+            request_timeout: 5,
+            stale_after: default_federation_stale_after(),
+        }
+    }
+}
+
+/// Audit mode configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Run as a read-only node recording the order and trade announcements it observes
+    pub enabled: bool,
+    /// Topics subscribed to besides the order and trade topics
+    pub topics: Vec<String>,
+    /// Records kept at most; the oldest are dropped beyond this, 0 keeps all
+    #[serde(default = "default_audit_max_records")]
+    pub max_records: u64,
+}
+
+/// Default number of audit records kept
+fn default_audit_max_records() -> u64 {
+    10_000_000
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_records: default_audit_max_records(),
+            topics: vec![
+                crate::p2p::rfq::RFQ_TOPIC.to_string(),
+                crate::orderbook::dark::DARK_TOPIC.to_string(),
+                crate::reputation::IDENTITY_TOPIC.to_string(),
+            ],
+        }
+    }
+}
+
+/// DNS resolver used for hostnames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsResolver {
+    /// Resolver of the operating system
+    System,
+    /// Cloudflare over HTTPS
+    Cloudflare,
+    /// Google over HTTPS
+    Google,
+    /// Quad9 over HTTPS
+    Quad9,
+}
+
+/// DNS and IPv6 configuration of outgoing connections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsConfig {
+    /// Resolver for relay and HTTP service hostnames
+    pub resolver: DnsResolver,
+    /// Connect over IPv6; if disabled, only IPv4 addresses are used
+    pub ipv6: bool,
+    /// Try IPv6 addresses before IPv4 ones
+    pub prefer_ipv6: bool,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            resolver: DnsResolver::System,
+            ipv6: true,
+            prefer_ipv6: false,
+        }
+    }
+}
+
+impl DnsConfig {
+    /// Check whether the libraries' own resolution applies unchanged
+    pub fn is_default(&self) -> bool {
+        self.resolver == DnsResolver::System && self.ipv6 && !self.prefer_ipv6
+    }
+}
+
+/// Display metadata of an asset set by the operator
+///
+/// Keys of `Config.assets` are asset IDs as assets are displayed: `RUNE:2a`,
+/// with the rune ID in hex, or `ALKANE:<id>`. Fields left out keep the
+/// registry's value, so an entry may only rename an asset or only give it an
+/// icon. An asset the registry does not know, such as an internal test asset,
+/// is listed with what its entry sets.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssetOverride {
+    /// Display name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Ticker symbol
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    /// Decimals amounts are displayed with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+    /// Icon location
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+}
+
 /// DarkSwap configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -229,6 +1093,25 @@ pub struct Config {
     pub logging: LoggingConfig,
     /// Performance configuration
     pub performance: PerformanceConfig,
+    /// Event configuration
+    pub events: EventConfig,
+    /// Federation configuration
+    pub federation: FederationConfig,
+    /// Storage configuration
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Settings of each Bitcoin network, applied when running on it
+    #[serde(default)]
+    pub networks: HashMap<BitcoinNetwork, NetworkProfile>,
+    /// Audit mode configuration
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// DNS resolver and IPv6 settings
+    #[serde(default)]
+    pub dns: DnsConfig,
+    /// Display metadata of runes and alkanes, by asset ID, merged over the registry's
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub assets: HashMap<String, AssetOverride>,
 }
 
 impl Default for Config {
@@ -242,10 +1125,29 @@ impl Default for Config {
             trade: TradeConfig::default(),
             logging: LoggingConfig::default(),
             performance: PerformanceConfig::default(),
+            events: EventConfig::default(),
+            federation: FederationConfig::default(),
+            storage: StorageConfig::default(),
+            networks: HashMap::new(),
+            audit: AuditConfig::default(),
+            dns: DnsConfig::default(),
+            assets: HashMap::new(),
         }
     }
 }
 
+/// Get the path of a file kept for one network: `orders.json` is `orders-mainnet.json` on mainnet
+fn network_path(path: &str, network: BitcoinNetwork) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, network.to_string(), extension.to_string_lossy()),
+        None => format!("{}-{}", stem, network.to_string()),
+    };
+    
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
 impl Config {
     /// Load configuration from file
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
@@ -255,6 +1157,7 @@ impl Config {
         
         let mut config: Config = serde_json::from_str(&contents).context("Failed to parse config file")?;
         config.config_path = Some(path.as_ref().to_path_buf());
+        crate::asset_info::validate_overrides(&config.assets).context("Invalid asset overrides")?;
         
         Ok(config)
     }
@@ -268,4 +1171,194 @@ impl Config {
         
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Get the configuration to run on a Bitcoin network
+    ///
+    /// The network's profile, if any, is applied on top of this configuration.
+    /// On a network other than the configured one, the files holding state of
+    /// one network, such as own orders and known peers, get the network's name
+    /// appended unless the profile names them, so networks never share them.
+    pub fn for_network(&self, network: BitcoinNetwork) -> Self {
+        let mut config = self.clone();
+        config.bitcoin.network = network;
+        
+        if network != self.bitcoin.network {
+            for path in [
+                &mut config.p2p.peer_store_path,
+                &mut config.p2p.relay_store_path,
+                &mut config.orderbook.order_store_path,
+                &mut config.orderbook.schedule_store_path,
+                &mut config.orderbook.iceberg_store_path,
+                &mut config.trade.refund_store_path,
+            ] {
+                if let Some(shared) = path.take() {
+                    *path = Some(network_path(&shared, network));
+                }
+            }
+        }
+        
+        let profile = match self.networks.get(&network) {
+            Some(profile) => profile.clone(),
+            None => return config,
+        };
+        
+        if let Some(electrum_url) = profile.electrum_url {
+            config.bitcoin.electrum_url = Some(electrum_url);
+        }
+        if let Some(esplora_url) = profile.esplora_url {
+            config.bitcoin.esplora_url = Some(esplora_url);
+        }
+        if let Some(bootstrap_peers) = profile.bootstrap_peers {
+            config.p2p.bootstrap_peers = bootstrap_peers;
+        }
+        if let Some(relay_servers) = profile.relay_servers {
+            config.p2p.relay_servers = relay_servers;
+        }
+        if let Some(peer_store_path) = profile.peer_store_path {
+            config.p2p.peer_store_path = Some(peer_store_path);
+        }
+        if let Some(relay_store_path) = profile.relay_store_path {
+            config.p2p.relay_store_path = Some(relay_store_path);
+        }
+        if let Some(wallet) = profile.wallet {
+            config.wallet = wallet;
+        }
+        if let Some(order_store_path) = profile.order_store_path {
+            config.orderbook.order_store_path = Some(order_store_path);
+        }
+        if let Some(refund_store_path) = profile.refund_store_path {
+            config.trade.refund_store_path = Some(refund_store_path);
+        }
+        if let Some(fees) = profile.fees {
+            config.trade.fees = Some(fees);
+        }
+        if let Some(referrers) = profile.referrers {
+            config.trade.referrers = referrers;
+        }
+        if let Some(storage) = profile.storage {
+            config.storage = storage;
+        }
+        
+        config
+    }
+
+    /// Get a copy of the configuration with the keyring references resolved
+    ///
+    /// Secret values of the form `keyring:<entry>` are replaced by the entry in
+    /// the OS keyring. The configuration itself is left untouched so that saving
+    /// it keeps the references.
+    pub fn resolve_secrets(&self, resolver: &mut SecretResolver) -> Result<Self> {
+        let mut config = self.clone();
+        
+        resolver.resolve_in_place(&mut config.wallet.private_key)?;
+        resolver.resolve_in_place(&mut config.wallet.mnemonic)?;
+        resolver.resolve_in_place(&mut config.wallet.descriptor)?;
+        if let Some(signer) = &mut config.wallet.remote_signer {
+            resolver.resolve_in_place(&mut signer.auth_token)?;
+        }
+        if let Some(custody) = &mut config.wallet.custody {
+            resolver.resolve_in_place(&mut custody.auth_token)?;
+        }
+        resolver.resolve_in_place(&mut config.p2p.identity_key)?;
+        for group in &mut config.orderbook.groups {
+            resolver.resolve_in_place(&mut group.key)?;
+        }
+        if let Some(cosigner) = &mut config.orderbook.cosigner {
+            resolver.resolve_in_place(&mut cosigner.auth_token)?;
+        }
+        if let Some(announcement) = &mut config.orderbook.announcement {
+            resolver.resolve_in_place(&mut announcement.key)?;
+            if let Some(signer) = &mut announcement.remote {
+                resolver.resolve_in_place(&mut signer.auth_token)?;
+            }
+        }
+        
+        Ok(config)
+    }
+
+    /// Get the file a store is persisted to at `path`
+    ///
+    /// With encrypted storage the file is sealed with the storage master key.
+    pub fn store_file(&self, path: &str) -> Result<DocumentFile> {
+        let master_key = match &self.storage.encryption {
+            Some(encryption) => Some(encryption.master_key()?),
+            None => None,
+        };
+        
+        Ok(DocumentFile::new(path, master_key))
+    }
+
+    /// Get the paths of the stores kept in files of their own
+    pub fn store_paths(&self) -> Vec<&str> {
+        [
+            &self.p2p.peer_store_path,
+            &self.p2p.relay_store_path,
+            &self.orderbook.order_store_path,
+            &self.orderbook.schedule_store_path,
+            &self.orderbook.iceberg_store_path,
+            &self.orderbook.watchlist_path,
+            &self.trade.memo_store_path,
+            &self.trade.refund_store_path,
+        ]
+        .into_iter()
+        .filter_map(|path| path.as_deref())
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkswap_support::secrets::{MemorySecretStore, SecretStore};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_resolve_secrets() {
+        let store = Arc::new(MemorySecretStore::new());
+        store.set("mnemonic", "abandon abandon about").unwrap();
+
+        let mut config = Config::default();
+        config.wallet.mnemonic = Some("keyring:mnemonic".to_string());
+        config.wallet.private_key = Some("literal".to_string());
+
+        let resolved = config.resolve_secrets(&mut SecretResolver::with_store(store)).unwrap();
+        assert_eq!(resolved.wallet.mnemonic.as_deref(), Some("abandon abandon about"));
+        assert_eq!(resolved.wallet.private_key.as_deref(), Some("literal"));
+        assert_eq!(config.wallet.mnemonic.as_deref(), Some("keyring:mnemonic"));
+
+        config.p2p.identity_key = Some("keyring:missing".to_string());
+        assert!(config.resolve_secrets(&mut SecretResolver::with_store(Arc::new(MemorySecretStore::new()))).is_err());
+    }
+
+    #[test]
+    fn test_for_network() {
+        let mut config = Config::default();
+        config.bitcoin.electrum_url = Some("ssl://testnet.example:50002".to_string());
+        config.orderbook.order_store_path = Some("orders-testnet.json".to_string());
+        config.networks.insert(BitcoinNetwork::Mainnet, NetworkProfile {
+            electrum_url: Some("ssl://mainnet.example:50002".to_string()),
+            order_store_path: Some("orders-mainnet.json".to_string()),
+            ..NetworkProfile::default()
+        });
+
+        let mainnet = config.for_network(BitcoinNetwork::Mainnet);
+        assert_eq!(mainnet.bitcoin.network, BitcoinNetwork::Mainnet);
+        assert_eq!(mainnet.bitcoin.electrum_url.as_deref(), Some("ssl://mainnet.example:50002"));
+        assert_eq!(mainnet.orderbook.order_store_path.as_deref(), Some("orders-mainnet.json"));
+        assert_eq!(mainnet.bitcoin.fee_rate, config.bitcoin.fee_rate);
+
+        let testnet = config.for_network(BitcoinNetwork::Testnet);
+        assert_eq!(testnet.orderbook.order_store_path.as_deref(), Some("orders-testnet.json"));
+        // Files a profile does not name are kept apart per network
+        config.orderbook.order_store_path = Some("state/orders.json".to_string());
+        config.p2p.peer_store_path = Some("state/peers.json".to_string());
+        let regtest = config.for_network(BitcoinNetwork::Regtest);
+        assert_eq!(regtest.orderbook.order_store_path.as_deref(), Some("state/orders-regtest.json"));
+        assert_eq!(regtest.p2p.peer_store_path.as_deref(), Some("state/peers-regtest.json"));
+        let mainnet = config.for_network(BitcoinNetwork::Mainnet);
+        assert_eq!(mainnet.orderbook.order_store_path.as_deref(), Some("orders-mainnet.json"));
+        assert_eq!(mainnet.p2p.peer_store_path.as_deref(), Some("state/peers-mainnet.json"));
+        let testnet = config.for_network(BitcoinNetwork::Testnet);
+        assert_eq!(testnet.orderbook.order_store_path.as_deref(), Some("state/orders.json"));
+    }
+}
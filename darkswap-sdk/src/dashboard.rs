@@ -0,0 +1,140 @@
+//! Operator dashboard
+//!
+//! A [`Dashboard`] gathers the figures an operator watches, from the peers and
+//! the open orders to the wallet and its fee reserve, so a status page needs a
+//! single call. Figures of a subsystem that is not running are left at zero or
+//! empty rather than failing the whole summary.
+
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::view::MarketSummary;
+use crate::trade::ticker::{self, TICKER_WINDOW};
+use crate::trade::Trade;
+use crate::types::Asset;
+use crate::wallet::reserve::FeeReserveStatus;
+
+/// Open orders and recent volume of a pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairActivity {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Number of open buy orders
+    pub bid_count: usize,
+    /// Number of open sell orders
+    pub ask_count: usize,
+    /// Traded base amount in the last 24 hours
+    pub volume: Decimal,
+    /// Traded quote amount in the last 24 hours
+    pub quote_volume: Decimal,
+    /// Number of trades in the last 24 hours
+    pub trade_count: usize,
+}
+
+/// Node status summary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dashboard {
+    /// Time since the node started (seconds)
+    pub uptime_secs: u64,
+    /// Number of connected peers
+    pub connected_peers: usize,
+    /// Pairs with open orders or trades in the last 24 hours
+    pub pairs: Vec<PairActivity>,
+    /// Number of trades not finished yet
+    pub in_flight_trades: usize,
+    /// Wallet balances by asset
+    pub balances: BTreeMap<String, u64>,
+    /// Fee reserve state, if a reserve is configured
+    pub fee_reserve: Option<FeeReserveStatus>,
+}
+
+/// Summarize the pairs with open orders or trades in the window ending at `now`
+pub fn pair_activity(markets: &[MarketSummary], trades: &[Trade], now: u64) -> Vec<PairActivity> {
+    let mut pairs: Vec<(Asset, Asset)> = markets.iter()
+        .map(|market| (market.base_asset.clone(), market.quote_asset.clone()))
+        .collect();
+    for trade in trades {
+        let pair = (trade.base_asset.clone(), trade.quote_asset.clone());
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    }
+
+    pairs.into_iter()
+        .filter_map(|(base_asset, quote_asset)| {
+            let market = markets.iter()
+                .find(|market| market.base_asset == base_asset && market.quote_asset == quote_asset);
+            let ticker = ticker::ticker(trades, &base_asset, &quote_asset, now, TICKER_WINDOW);
+            if market.is_none() && ticker.trade_count == 0 {
+                return None;
+            }
+
+            Some(PairActivity {
+                bid_count: market.map_or(0, |market| market.bid_count),
+                ask_count: market.map_or(0, |market| market.ask_count),
+                volume: ticker.volume,
+                quote_volume: ticker.quote_volume,
+                trade_count: ticker.trade_count,
+                base_asset,
+                quote_asset,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use crate::trade::TradeState;
+    use rust_decimal_macros::dec;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn completed(quote_asset: Asset, completed_at: u64) -> Trade {
+        let mut trade = Trade::new(
+            OrderId("order".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            quote_asset,
+            dec!(2),
+            dec!(100),
+            None,
+            NOW,
+        );
+        trade.update_state_at(TradeState::Completed, completed_at);
+        trade
+    }
+
+    #[test]
+    fn test_pair_activity() {
+        let market = MarketSummary {
+            base_asset: Asset::Bitcoin,
+            quote_asset: Asset::Rune(1),
+            bid_count: 3,
+            ask_count: 1,
+            bid_depth: dec!(3),
+            ask_depth: dec!(1),
+            best_bid: Some(dec!(99)),
+            best_ask: Some(dec!(101)),
+        };
+        let trades = vec![
+            completed(Asset::Rune(1), NOW - 60),
+            completed(Asset::Rune(2), NOW - 120),
+            // Outside the window, and the pair has no open orders
+            completed(Asset::Rune(3), NOW - TICKER_WINDOW - 1),
+        ];
+
+        let pairs = pair_activity(&[market], &trades, NOW);
+        assert_eq!(pairs.len(), 2);
+        assert_eq!((pairs[0].bid_count, pairs[0].ask_count, pairs[0].trade_count), (3, 1, 1));
+        assert_eq!(pairs[0].volume, dec!(2));
+        assert_eq!(pairs[1].quote_asset, Asset::Rune(2));
+        assert_eq!((pairs[1].bid_count, pairs[1].trade_count), (0, 1));
+    }
+}
@@ -0,0 +1,442 @@
+//! Developer tools
+//!
+//! Helpers to get a fresh signet or regtest node to a useful state: request
+//! coins from a faucet, etch a throwaway rune to trade with a Bitcoin Core
+//! wallet, and fill the book of a running node with sample orders around a mid
+//! price. They refuse to run on mainnet. Behind the `devtools` feature and used
+//! by `darkswap-cli dev seed`.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::{Address, LockTime, Network, Transaction, TxOut, Txid};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::config::DnsConfig;
+use crate::orderbook::{Order, OrderSide};
+use crate::runestone::{Etching, Runestone};
+use crate::types::Asset;
+use crate::DarkSwap;
+
+/// Supply of an etched test rune
+pub const TEST_RUNE_SUPPLY: u128 = 21_000_000;
+
+/// Developer tools error
+#[derive(Debug, Error)]
+pub enum DevToolsError {
+    /// Developer tools only run on test networks
+    #[error("Developer tools do not run on {0}")]
+    WrongNetwork(Network),
+    /// The faucet refused the request
+    #[error("Faucet refused the request: {0}")]
+    Faucet(String),
+    /// The etching transaction does not carry an etching
+    #[error("Etching transaction carries no rune")]
+    NoEtching,
+    /// Bitcoin Core answered a call with an error
+    #[error("Bitcoin Core {method} failed: {message}")]
+    Rpc {
+        /// Method called
+        method: String,
+        /// Error message
+        message: String,
+    },
+    /// Orders are only posted by a running node
+    #[error("DarkSwap is not running")]
+    NotRunning,
+}
+
+/// Faucet configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaucetConfig {
+    /// Faucet URL; the request is POSTed as JSON
+    pub url: String,
+    /// Amount to request (satoshis)
+    pub amount: u64,
+    /// Request timeout (seconds)
+    pub timeout: u64,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8000/faucet".to_string(),
+            amount: 1_000_000,
+            timeout: 30,
+        }
+    }
+}
+
+/// Bitcoin Core RPC configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcConfig {
+    /// RPC URL, with `/wallet/<name>` to pick a wallet
+    pub url: String,
+    /// RPC user
+    pub user: String,
+    /// RPC password
+    pub password: String,
+    /// Request timeout (seconds)
+    pub timeout: u64,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:18443".to_string(),
+            user: String::new(),
+            password: String::new(),
+            timeout: 30,
+        }
+    }
+}
+
+/// Request sent to a faucet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaucetRequest {
+    /// Address to pay
+    pub address: String,
+    /// Amount (satoshis)
+    pub amount: u64,
+}
+
+/// Response of a faucet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaucetResponse {
+    /// Transaction paying the address; absent if refused
+    pub txid: Option<String>,
+    /// Reason for a refusal
+    pub error: Option<String>,
+}
+
+/// Rune etched for testing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestRune {
+    /// Rune ID
+    pub id: u128,
+    /// Symbol
+    pub symbol: String,
+    /// Etching transaction
+    pub txid: Txid,
+}
+
+/// Sample order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampleOrder {
+    /// Side
+    pub side: OrderSide,
+    /// Amount
+    pub amount: Decimal,
+    /// Price
+    pub price: Decimal,
+}
+
+/// Check that a network is meant for testing
+pub fn check_network(network: Network) -> Result<(), DevToolsError> {
+    match network {
+        Network::Bitcoin => Err(DevToolsError::WrongNetwork(network)),
+        _ => Ok(()),
+    }
+}
+
+/// Request coins for an address from a signet faucet, returning the paying transaction
+pub async fn request_faucet_coins(config: &FaucetConfig, dns: &DnsConfig, address: &Address, network: Network) -> Result<String> {
+    if network != Network::Signet {
+        return Err(DevToolsError::WrongNetwork(network).into());
+    }
+
+    let client = crate::dns::http_client_builder(dns)?
+        .timeout(Duration::from_secs(config.timeout.max(1)))
+        .build()
+        .context("Failed to create HTTP client")?;
+    let request = FaucetRequest {
+        address: address.to_string(),
+        amount: config.amount,
+    };
+
+    let response: FaucetResponse = client
+        .post(&config.url)
+        .json(&request)
+        .send()
+        .await
+        .context("Failed to reach faucet")?
+        .error_for_status()
+        .context("Faucet failed")?
+        .json()
+        .await
+        .context("Invalid faucet response")?;
+
+    match response.txid {
+        Some(txid) => Ok(txid),
+        None => Err(DevToolsError::Faucet(response.error.unwrap_or_else(|| "no reason given".to_string())).into()),
+    }
+}
+
+/// Bitcoin Core wallet, reached over JSON-RPC
+pub struct CoreRpc {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Configuration
+    config: RpcConfig,
+}
+
+/// JSON-RPC response
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    /// Result, if the call succeeded
+    result: Option<T>,
+    /// Error, if it failed
+    error: Option<RpcError>,
+}
+
+/// JSON-RPC error
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    /// Message
+    message: String,
+}
+
+/// Transaction funded by the wallet
+#[derive(Debug, Deserialize)]
+struct FundedTransaction {
+    /// Transaction (hex)
+    hex: String,
+}
+
+/// Transaction signed by the wallet
+#[derive(Debug, Deserialize)]
+struct SignedTransaction {
+    /// Transaction (hex)
+    hex: String,
+    /// Whether every input is signed
+    complete: bool,
+}
+
+impl CoreRpc {
+    /// Create a client for the Bitcoin Core wallet at `config.url`
+    pub fn new(config: RpcConfig, dns: &DnsConfig) -> Result<Self> {
+        let client = crate::dns::http_client_builder(dns)?
+            .timeout(Duration::from_secs(config.timeout.max(1)))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Self { client, config })
+    }
+
+    /// Call a method
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> Result<T> {
+        let response: RpcResponse<T> = self.client
+            .post(&self.config.url)
+            .basic_auth(&self.config.user, Some(&self.config.password))
+            .json(&json!({ "jsonrpc": "1.0", "id": "darkswap", "method": method, "params": params }))
+            .send()
+            .await
+            .context("Failed to reach Bitcoin Core")?
+            .json()
+            .await
+            .with_context(|| format!("Invalid Bitcoin Core {} response", method))?;
+
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(DevToolsError::Rpc {
+                method: method.to_string(),
+                message: error.message,
+            }.into()),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(DevToolsError::Rpc {
+                method: method.to_string(),
+                message: "empty response".to_string(),
+            }.into()),
+        }
+    }
+}
+
+/// Etch a throwaway rune with a Bitcoin Core wallet and broadcast it
+///
+/// The wallet funds and signs the etching; the runes go to a new address of
+/// the wallet. On regtest a block is mined so the etching confirms.
+pub async fn etch_test_rune(rpc: &CoreRpc, network: Network, symbol: &str, fee_rate: f32) -> Result<TestRune> {
+    check_network(network)?;
+
+    let runestone = Runestone {
+        edicts: Vec::new(),
+        etching: Some(Etching {
+            rune: rand::random::<u128>(),
+            symbol: Some(symbol.to_string()),
+            decimals: Some(0),
+            spacers: 0,
+            amount: TEST_RUNE_SUPPLY,
+            terms: None,
+        }),
+        default_output: None,
+        burn: false,
+    };
+
+    // The runes go to the first output that is not the runestone
+    let address: String = rpc.call("getnewaddress", json!([])).await?;
+    let address = Address::from_str(&address).context("Invalid wallet address")?;
+    let tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO.into(),
+        input: Vec::new(),
+        output: vec![
+            TxOut { value: 0, script_pubkey: runestone.to_script() },
+            TxOut { value: 546, script_pubkey: address.script_pubkey() },
+        ],
+    };
+
+    let funded: FundedTransaction = rpc.call(
+        "fundrawtransaction",
+        json!([hex::encode(serialize(&tx)), { "fee_rate": fee_rate, "changePosition": 2 }]),
+    ).await?;
+    let signed: SignedTransaction = rpc.call("signrawtransactionwithwallet", json!([funded.hex])).await?;
+    if !signed.complete {
+        return Err(DevToolsError::Rpc {
+            method: "signrawtransactionwithwallet".to_string(),
+            message: "the wallet could not sign every input".to_string(),
+        }.into());
+    }
+
+    let tx: Transaction = deserialize(&hex::decode(&signed.hex).context("Invalid signed transaction")?)
+        .context("Invalid signed transaction")?;
+    let id = Runestone::parse(&tx)
+        .and_then(|runestone| runestone.etching)
+        .map(|etching| etching.rune)
+        .ok_or(DevToolsError::NoEtching)?;
+
+    let txid: String = rpc.call("sendrawtransaction", json!([signed.hex])).await?;
+    if network == Network::Regtest {
+        let _: Vec<String> = rpc.call("generatetoaddress", json!([1, address.to_string()])).await?;
+    }
+
+    Ok(TestRune {
+        id,
+        symbol: symbol.to_string(),
+        txid: Txid::from_str(&txid).context("Invalid transaction ID")?,
+    })
+}
+
+/// Get a ladder of sample orders around a mid price
+///
+/// Each side gets `levels` orders of `amount`, `step_bps` apart, the first
+/// one `step_bps` away from the mid price.
+pub fn sample_orders(mid_price: Decimal, amount: Decimal, levels: u32, step_bps: u32) -> Vec<SampleOrder> {
+    let step = mid_price * Decimal::from(step_bps) / Decimal::from(10_000);
+
+    (1..=levels)
+        .flat_map(|level| {
+            let offset = step * Decimal::from(level);
+            [
+                SampleOrder { side: OrderSide::Buy, amount, price: mid_price - offset },
+                SampleOrder { side: OrderSide::Sell, amount, price: mid_price + offset },
+            ]
+        })
+        .filter(|order| order.price > Decimal::ZERO)
+        .collect()
+}
+
+/// Post sample orders for a pair on a started node, returning the orders created
+///
+/// The orders live as long as the node does.
+pub async fn seed_orderbook(
+    darkswap: &DarkSwap,
+    base_asset: &Asset,
+    quote_asset: &Asset,
+    orders: &[SampleOrder],
+) -> Result<Vec<Order>> {
+    check_network(darkswap.bitcoin_network().into())?;
+    if !darkswap.is_running() {
+        return Err(DevToolsError::NotRunning.into());
+    }
+
+    let mut created = Vec::with_capacity(orders.len());
+    for order in orders {
+        created.push(
+            darkswap
+                .create_order(base_asset.clone(), quote_asset.clone(), order.side, order.amount, order.price, None)
+                .await?,
+        );
+    }
+
+    Ok(created)
+}
+
+/// Post sample orders for a pair through a running daemon's API
+pub async fn seed_daemon(
+    daemon_url: &str,
+    dns: &DnsConfig,
+    base_asset: &Asset,
+    quote_asset: &Asset,
+    orders: &[SampleOrder],
+) -> Result<Vec<Order>> {
+    let client = crate::dns::http_client_builder(dns)?
+        .build()
+        .context("Failed to create HTTP client")?;
+    let url = format!("{}/orders", daemon_url.trim_end_matches('/'));
+
+    let mut created = Vec::with_capacity(orders.len());
+    for order in orders {
+        let side = match order.side {
+            OrderSide::Buy => "buy",
+            OrderSide::Sell => "sell",
+        };
+        let response = client
+            .post(&url)
+            .json(&json!({
+                "base_asset": api_asset(base_asset),
+                "quote_asset": api_asset(quote_asset),
+                "side": side,
+                "amount": order.amount.to_string(),
+                "price": order.price.to_string(),
+            }))
+            .send()
+            .await
+            .context("Failed to reach daemon")?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("Daemon refused a sample order ({}): {}", status, body.trim()));
+        }
+        created.push(response.json().await.context("Invalid daemon order")?);
+    }
+
+    Ok(created)
+}
+
+/// Get an asset as the daemon API names it
+fn api_asset(asset: &Asset) -> String {
+    match asset {
+        Asset::Bitcoin => "BTC".to_string(),
+        Asset::Rune(id) => format!("RUNE:{}", id),
+        Asset::Alkane(id) => id.0.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_sample_orders() {
+        let orders = sample_orders(dec!(0.0001), dec!(100), 2, 100);
+
+        assert_eq!(orders.len(), 4);
+        assert_eq!(orders[0], SampleOrder { side: OrderSide::Buy, amount: dec!(100), price: dec!(0.000099) });
+        assert_eq!(orders[3], SampleOrder { side: OrderSide::Sell, amount: dec!(100), price: dec!(0.000102) });
+
+        // Levels that would reach a zero price are left out
+        assert_eq!(sample_orders(dec!(1), dec!(1), 3, 5000).len(), 4);
+
+        assert!(check_network(Network::Bitcoin).is_err());
+        assert!(check_network(Network::Signet).is_ok());
+
+        assert_eq!(api_asset(&Asset::Rune(840000)), "RUNE:840000");
+        assert_eq!(api_asset(&Asset::Bitcoin), "BTC");
+    }
+}
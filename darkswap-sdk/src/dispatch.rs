@@ -0,0 +1,341 @@
+//! Inbound message dispatch
+//!
+//! Routes the messages peers send, by the topic or protocol they arrived on, to
+//! the module that handles them. A message that fails to decode or is refused by
+//! its handler is logged and dropped; one bad peer does not stop the others.
+//!
+//! An audit node records the messages on the extra topics it listens on as
+//! they arrive, without handling them.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::envelope;
+use log::{debug, warn};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::audit::{AuditLog, Observation};
+use crate::orderbook::dark::{DarkPool, DARK_TOPIC, REVEAL_PROTOCOL};
+use crate::orderbook::digest::{DIGEST_TOPIC, FETCH_PROTOCOL};
+use crate::orderbook::gossip_cache::BACKFILL_PROTOCOL;
+use crate::orderbook::group::{self, GroupManager};
+use crate::orderbook::interest::INTEREST_PROTOCOL;
+use crate::orderbook::Orderbook;
+use crate::p2p::inbound::{InboundMessage, InboundSource};
+use crate::p2p::rfq::{QUOTE_PROTOCOL, RFQ_TOPIC};
+use crate::trade::outbox::TRADE_PROTOCOL;
+use crate::trade::rfq::RfqManager;
+use crate::trade::TradeModule;
+
+/// Handlers inbound messages are routed to
+#[derive(Clone, Default)]
+pub struct Dispatcher {
+    /// Orderbook
+    orderbook: Option<Arc<Orderbook>>,
+    /// Audit log and the extra topics recorded to it, in audit mode
+    audit: Option<(Arc<AuditLog>, Arc<HashSet<String>>)>,
+    /// Trade manager
+    trades: Option<Arc<TradeModule>>,
+    /// Request-for-quote manager
+    rfq: Option<Arc<RfqManager>>,
+    /// Private group orderbooks
+    groups: Option<Arc<GroupManager>>,
+    /// Dark pool
+    dark_pool: Option<Arc<DarkPool>>,
+}
+
+impl Dispatcher {
+    /// Create a dispatcher without handlers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route order gossip, digests, order fetches, backfills and interest filters to the orderbook
+    pub fn with_orderbook(mut self, orderbook: Arc<Orderbook>) -> Self {
+        self.orderbook = Some(orderbook);
+        self
+    }
+
+    /// Record the messages on `topics` to `audit` instead of handling them
+    pub fn with_audit(mut self, audit: Arc<AuditLog>, topics: Vec<String>) -> Self {
+        self.audit = Some((audit, Arc::new(topics.into_iter().collect())));
+        self
+    }
+
+    /// Route signed trade messages and their acknowledgments to the trade manager
+    pub fn with_trades(mut self, trades: Arc<TradeModule>) -> Self {
+        self.trades = Some(trades);
+        self
+    }
+
+    /// Route quote requests and quotes to the RFQ manager
+    pub fn with_rfq(mut self, rfq: Arc<RfqManager>) -> Self {
+        self.rfq = Some(rfq);
+        self
+    }
+
+    /// Route the orders of private groups to the group manager
+    pub fn with_groups(mut self, groups: Arc<GroupManager>) -> Self {
+        self.groups = Some(groups);
+        self
+    }
+
+    /// Route dark orders, reveal requests and disclosures to the dark pool
+    pub fn with_dark_pool(mut self, dark_pool: Arc<DarkPool>) -> Self {
+        self.dark_pool = Some(dark_pool);
+        self
+    }
+
+    /// Handle a message received from a peer
+    ///
+    /// Messages on a topic or protocol without a handler are ignored. A request
+    /// whose handler has a response is answered on its response channel.
+    pub async fn dispatch(&self, message: &mut InboundMessage) -> Result<()> {
+        let peer_id = message.peer_id.to_string();
+        let source = message.source.clone();
+
+        if let (Some((audit, topics)), InboundSource::Topic(topic)) = (&self.audit, &source) {
+            if topics.contains(topic) {
+                let observation = Observation::Gossip { topic: topic.clone(), data: base64::encode(&message.data) };
+                audit.record(&peer_id, observation)?;
+                return Ok(());
+            }
+        }
+
+        match &source {
+            InboundSource::Topic(topic) if self.is_order_topic(topic) => {
+                let Some(orderbook) = &self.orderbook else { return Ok(()) };
+                let order_message = envelope::decode(&message.data).context("Failed to decode order message")?;
+                orderbook.handle_order_message(order_message, &peer_id).await
+            }
+            InboundSource::Topic(topic) if topic == DIGEST_TOPIC => {
+                let Some(orderbook) = &self.orderbook else { return Ok(()) };
+                let digest_message = envelope::decode(&message.data).context("Failed to decode order digest")?;
+                orderbook.handle_digest_message(digest_message, &peer_id).await.map(|_| ())
+            }
+            InboundSource::Protocol(protocol) if protocol == FETCH_PROTOCOL => {
+                let Some(orderbook) = &self.orderbook else { return Ok(()) };
+                let digest_message = envelope::decode(&message.data).context("Failed to decode order fetch")?;
+                if let Some(response) = orderbook.handle_digest_message(digest_message, &peer_id).await? {
+                    message.respond(envelope::encode(&response).context("Failed to serialize fetched orders")?);
+                }
+                Ok(())
+            }
+            InboundSource::Protocol(protocol) if protocol == BACKFILL_PROTOCOL => {
+                let Some(orderbook) = &self.orderbook else { return Ok(()) };
+                let backfill_message = envelope::decode(&message.data).context("Failed to decode backfill message")?;
+                if let Some(response) = orderbook.handle_backfill_message(backfill_message, &peer_id).await? {
+                    message.respond(envelope::encode(&response).context("Failed to serialize backfilled orders")?);
+                }
+                Ok(())
+            }
+            InboundSource::Protocol(protocol) if protocol == INTEREST_PROTOCOL => {
+                let Some(orderbook) = &self.orderbook else { return Ok(()) };
+                let interest_message = envelope::decode(&message.data).context("Failed to decode interest message")?;
+                orderbook.handle_interest_message(interest_message, &peer_id).await
+            }
+            InboundSource::Topic(topic) if self.is_trade_topic(topic) => self.handle_trade(&peer_id, &message.data).await,
+            InboundSource::Protocol(protocol) if protocol == TRADE_PROTOCOL => self.handle_trade(&peer_id, &message.data).await,
+            InboundSource::Topic(topic) if topic == RFQ_TOPIC => {
+                let Some(rfq) = &self.rfq else { return Ok(()) };
+                let rfq_message = envelope::decode(&message.data).context("Failed to decode quote request")?;
+                rfq.handle_rfq_message(rfq_message, &peer_id).await
+            }
+            InboundSource::Protocol(protocol) if protocol == QUOTE_PROTOCOL => {
+                let Some(rfq) = &self.rfq else { return Ok(()) };
+                let rfq_message = envelope::decode(&message.data).context("Failed to decode quote")?;
+                rfq.handle_rfq_message(rfq_message, &peer_id).await
+            }
+            InboundSource::Topic(topic) if group::is_group_topic(topic) => {
+                let Some(groups) = &self.groups else { return Ok(()) };
+                let group_message = envelope::decode(&message.data).context("Failed to decode group message")?;
+                groups.handle_group_message(group_message, &peer_id).await
+            }
+            InboundSource::Topic(topic) if topic == DARK_TOPIC => {
+                let Some(dark_pool) = &self.dark_pool else { return Ok(()) };
+                let dark_message = envelope::decode(&message.data).context("Failed to decode dark order")?;
+                dark_pool.handle_dark_message(dark_message, &peer_id).await
+            }
+            InboundSource::Protocol(protocol) if protocol == REVEAL_PROTOCOL => {
+                let Some(dark_pool) = &self.dark_pool else { return Ok(()) };
+                let dark_message = envelope::decode(&message.data).context("Failed to decode reveal message")?;
+                dark_pool.handle_dark_message(dark_message, &peer_id).await
+            }
+            source => {
+                debug!("No handler for message from {} on {:?}", peer_id, source);
+                Ok(())
+            }
+        }
+    }
+
+    /// Check if a topic carries order gossip
+    fn is_order_topic(&self, topic: &str) -> bool {
+        self.orderbook.as_ref().map_or(false, |orderbook| orderbook.order_topic() == topic)
+    }
+
+    /// Check if a topic carries trade messages
+    fn is_trade_topic(&self, topic: &str) -> bool {
+        self.trades.as_ref().map_or(false, |trades| trades.trade_topic() == topic)
+    }
+
+    /// Handle a signed trade message, in the name of the peer that signed it
+    async fn handle_trade(&self, peer_id: &str, data: &[u8]) -> Result<()> {
+        let Some(trades) = &self.trades else { return Ok(()) };
+        let envelope = match envelope::decode(data) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                trades.record_invalid_message(peer_id);
+                return Err(e).context("Failed to decode trade message");
+            }
+        };
+        trades.handle_signed_message(envelope).await
+    }
+}
+
+/// Dispatch inbound messages until the network drops the channel
+///
+/// Each message is handled in a task of its own, so a handler waiting on the
+/// user, such as a trade held for approval, does not hold up the others.
+pub fn spawn_dispatcher(dispatcher: Dispatcher, mut inbound: mpsc::Receiver<InboundMessage>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(mut message) = inbound.recv().await {
+            let dispatcher = dispatcher.clone();
+            tokio::spawn(async move {
+                if let Err(e) = dispatcher.dispatch(&mut message).await {
+                    warn!("Dropped message from {} on {:?}: {}", message.peer_id, message.source, e);
+                }
+            });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use crate::config::{Config, GossipCacheConfig};
+    use crate::orderbook::digest::DigestMessage;
+    use crate::orderbook::gossip_cache::{BackfillMessage, GossipCache};
+    use crate::orderbook::{Order, OrderSide, SignedOrder};
+    use crate::p2p::rfq::{QuoteRequest, RfqMessage};
+    use crate::p2p::P2PNetwork;
+    use crate::types::Asset;
+    use crate::wallet::simple_wallet::SimpleWallet;
+    use rust_decimal_macros::dec;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn test_quote_request_reaches_the_rfq_manager() {
+        let network = Arc::new(RwLock::new(P2PNetwork::new(&Config::default(), mpsc::channel(16).0).unwrap()));
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let rfq = Arc::new(RfqManager::new(network, mpsc::channel(16).0).with_clock(clock.clone()));
+        let dispatcher = Dispatcher::new().with_rfq(rfq.clone());
+
+        let taker = libp2p::identity::Keypair::generate_ed25519().public().to_peer_id();
+        let request = QuoteRequest::new(taker.to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Buy, dec!(5), 60, clock.now()).unwrap();
+        let data = envelope::encode(&RfqMessage::Request(request.clone())).unwrap();
+
+        // A message on an unknown topic is ignored
+        dispatcher.dispatch(&mut InboundMessage::topic(taker, "darkswap/unknown", data.clone())).await.unwrap();
+        assert!(rfq.inbound_requests().await.is_empty());
+
+        dispatcher.dispatch(&mut InboundMessage::topic(taker, RFQ_TOPIC, data)).await.unwrap();
+        assert_eq!(rfq.inbound_requests().await[0].id, request.id);
+    }
+    #[tokio::test]
+    async fn test_audit_node_records_the_extra_topics() {
+        let storage: Arc<dyn darkswap_support::storage::Storage> = Arc::new(darkswap_support::storage::MemoryStorage::new());
+        let audit = Arc::new(AuditLog::open(storage, Arc::new(MockClock::new(1_700_000_000))).unwrap());
+        let network = Arc::new(RwLock::new(P2PNetwork::new(&Config::default(), mpsc::channel(16).0).unwrap()));
+        let rfq = Arc::new(RfqManager::new(network, mpsc::channel(16).0));
+        let dispatcher = Dispatcher::new()
+            .with_rfq(rfq.clone())
+            .with_audit(audit.clone(), vec![RFQ_TOPIC.to_string()]);
+
+        // The message is recorded as received and not handled
+        let peer = libp2p::identity::Keypair::generate_ed25519().public().to_peer_id();
+        dispatcher.dispatch(&mut InboundMessage::topic(peer, RFQ_TOPIC, b"quote".to_vec())).await.unwrap();
+        assert!(rfq.inbound_requests().await.is_empty());
+
+        let records = audit.records(None, 0, 10).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].peer_id, peer.to_string());
+        assert!(matches!(
+            &records[0].observation,
+            Observation::Gossip { topic, data } if topic == RFQ_TOPIC && base64::decode(data).unwrap() == b"quote"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_orders_are_fetched_from_the_digest_publisher() {
+        let node = || {
+            let (event_sender, _) = mpsc::channel(100);
+            let network = Arc::new(RwLock::new(P2PNetwork::new(&Config::default(), event_sender.clone()).unwrap()));
+            let wallet = Arc::new(SimpleWallet::new(None, Config::default().bitcoin.network).unwrap());
+            (network.clone(), Arc::new(Orderbook::new(network, wallet, event_sender)))
+        };
+        let (publisher_network, publisher) = node();
+        let (requester_network, requester) = node();
+        let publisher_id = publisher_network.read().await.local_peer_id();
+        let requester_id = requester_network.read().await.local_peer_id();
+
+        let order = Order::new(publisher_id.to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(1), dec!(100), Some(3600));
+        let signed_order = SignedOrder::sign(order.clone(), publisher_network.read().await.keypair()).unwrap();
+        publisher.submit_signed_order(signed_order).await.unwrap();
+
+        // The requester compares the published digest with its own book
+        let digest = DigestMessage::Digest(publisher.digest().await.unwrap());
+        let requester_dispatcher = Dispatcher::new().with_orderbook(requester.clone());
+        let data = envelope::encode(&digest).unwrap();
+        requester_dispatcher.dispatch(&mut InboundMessage::topic(publisher_id, DIGEST_TOPIC, data)).await.unwrap();
+
+        // The publisher answers the fetch on the response channel
+        let fetch = envelope::encode(&DigestMessage::Fetch { order_ids: vec![order.id.clone()] }).unwrap();
+        let (mut request, response) = InboundMessage::request(requester_id, FETCH_PROTOCOL, fetch);
+        Dispatcher::new().with_orderbook(publisher).dispatch(&mut request).await.unwrap();
+        let response = response.await.unwrap();
+        assert!(matches!(envelope::decode(&response).unwrap(), DigestMessage::Orders(orders) if orders.len() == 1));
+
+        // The fetched order is taken into the requester's book
+        assert!(requester.get_order(&order.id).await.is_err());
+        let mut fetched = InboundMessage {
+            peer_id: publisher_id,
+            source: InboundSource::Protocol(FETCH_PROTOCOL.to_string()),
+            data: response,
+            response: None,
+        };
+        requester_dispatcher.dispatch(&mut fetched).await.unwrap();
+        assert_eq!(requester.get_order(&order.id).await.unwrap().id, order.id);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_is_answered_from_the_gossip_cache() {
+        let (event_sender, _) = mpsc::channel(100);
+        let network = Arc::new(RwLock::new(P2PNetwork::new(&Config::default(), event_sender.clone()).unwrap()));
+        let wallet = Arc::new(SimpleWallet::new(None, Config::default().bitcoin.network).unwrap());
+        let cache_config = GossipCacheConfig { enabled: true, ..Default::default() };
+        let full_node = Arc::new(
+            Orderbook::new(network.clone(), wallet, event_sender)
+                .with_gossip_cache(cache_config.clone(), Some(GossipCache::new(cache_config))),
+        );
+        let full_node_id = network.read().await.local_peer_id();
+
+        let order = Order::new(full_node_id.to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(1), dec!(100), Some(3600));
+        let signed_order = SignedOrder::sign(order.clone(), network.read().await.keypair()).unwrap();
+        full_node.submit_signed_order(signed_order).await.unwrap();
+
+        // The cached order is sent back on the response channel
+        let joiner = libp2p::identity::Keypair::generate_ed25519().public().to_peer_id();
+        let backfill = envelope::encode(&BackfillMessage::Request { base_asset: Asset::Bitcoin, quote_asset: Asset::Rune(1), since: 0 }).unwrap();
+        let (mut request, response) = InboundMessage::request(joiner, BACKFILL_PROTOCOL, backfill);
+        Dispatcher::new().with_orderbook(full_node).dispatch(&mut request).await.unwrap();
+        let orders = match envelope::decode(&response.await.unwrap()).unwrap() {
+            BackfillMessage::Orders(orders) => orders,
+            other => panic!("Unexpected backfill answer: {:?}", other),
+        };
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].order.id, order.id);
+    }
+}
@@ -0,0 +1,223 @@
+//! DNS resolution and IP family selection
+//!
+//! By default hostnames are resolved the way each library does it, usually
+//! through the operating system, and dual-stack hosts are dialed in whatever
+//! order that yields. Locked-down environments may block the system resolver
+//! or have no working IPv6 route, so [`DnsConfig`] selects a DNS over HTTPS
+//! resolver and whether IPv6 is used, or tried first. The settings apply to the
+//! HTTP clients of the SDK and to the peers and relay servers the P2P network
+//! dials.
+//!
+//! In the browser names are always resolved by the browser, so only the IPv6
+//! filtering of addresses applies there.
+
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+use std::net::{Ipv4Addr, SocketAddr};
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::IpAddr;
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+use std::sync::Arc;
+
+#[cfg(any(feature = "http", not(target_arch = "wasm32")))]
+use anyhow::Result;
+#[cfg(not(target_arch = "wasm32"))]
+use anyhow::Context;
+use libp2p::core::multiaddr::{Multiaddr, Protocol};
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns_resolver::config::{LookupIpStrategy, ResolverConfig, ResolverOpts};
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::config::DnsConfig;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::config::DnsResolver;
+
+/// Get the lookup strategy of the IPv6 settings
+#[cfg(not(target_arch = "wasm32"))]
+fn ip_strategy(config: &DnsConfig) -> LookupIpStrategy {
+    if !config.ipv6 {
+        LookupIpStrategy::Ipv4Only
+    } else if config.prefer_ipv6 {
+        LookupIpStrategy::Ipv6thenIpv4
+    } else {
+        LookupIpStrategy::Ipv4AndIpv6
+    }
+}
+
+/// Create the configured resolver
+#[cfg(not(target_arch = "wasm32"))]
+pub fn resolver(config: &DnsConfig) -> Result<TokioAsyncResolver> {
+    let (resolver_config, mut options) = match config.resolver {
+        DnsResolver::System => trust_dns_resolver::system_conf::read_system_conf()
+            .context("Failed to read the system DNS configuration")?,
+        DnsResolver::Cloudflare => (ResolverConfig::cloudflare_https(), ResolverOpts::default()),
+        DnsResolver::Google => (ResolverConfig::google_https(), ResolverOpts::default()),
+        DnsResolver::Quad9 => (ResolverConfig::quad9_https(), ResolverOpts::default()),
+    };
+    options.ip_strategy = ip_strategy(config);
+
+    TokioAsyncResolver::tokio(resolver_config, options).context("Failed to create DNS resolver")
+}
+
+/// Resolver of the HTTP clients
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+struct HttpResolver(Arc<TokioAsyncResolver>);
+
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+impl reqwest::dns::Resolve for HttpResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let lookup = resolver.lookup_ip(name.as_str()).await?;
+            // The port is filled in by the client
+            let addrs: Vec<SocketAddr> = lookup.iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Get an HTTP client builder honoring the DNS settings
+#[cfg(all(feature = "http", not(target_arch = "wasm32")))]
+pub fn http_client_builder(config: &DnsConfig) -> Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::Client::builder();
+    if config.is_default() {
+        return Ok(builder);
+    }
+
+    builder = builder.dns_resolver(Arc::new(HttpResolver(Arc::new(resolver(config)?))));
+    if !config.ipv6 {
+        // Also keeps IPv6 literals in URLs from being dialed
+        builder = builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    }
+
+    Ok(builder)
+}
+
+/// Get an HTTP client builder; the browser resolves names itself
+#[cfg(all(feature = "http", target_arch = "wasm32"))]
+pub fn http_client_builder(_config: &DnsConfig) -> Result<reqwest::ClientBuilder> {
+    Ok(reqwest::Client::builder())
+}
+
+/// Check whether an address may be dialed with the IPv6 settings
+pub fn is_dialable(config: &DnsConfig, addr: &Multiaddr) -> bool {
+    dialable_address(config, addr).is_some()
+}
+
+/// Get the address to dial with the IPv6 settings
+///
+/// With IPv6 off, IPv6 addresses are left out and `/dns/` names are narrowed
+/// to `/dns4/`, so they cannot resolve to an IPv6 address.
+pub fn dialable_address(config: &DnsConfig, addr: &Multiaddr) -> Option<Multiaddr> {
+    if config.ipv6 {
+        return Some(addr.clone());
+    }
+
+    let mut dialable = Multiaddr::empty();
+    for proto in addr.iter() {
+        match proto {
+            Protocol::Ip6(_) | Protocol::Dns6(_) => return None,
+            Protocol::Dns(name) => dialable.push(Protocol::Dns4(name)),
+            proto => dialable.push(proto),
+        }
+    }
+
+    Some(dialable)
+}
+
+/// Resolve the names in an address through the resolver
+///
+/// `/dns4/` and `/dns6/` names resolve to that family only; `/dns/` names
+/// follow the lookup strategy of the resolver.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn resolve_multiaddr(resolver: &TokioAsyncResolver, addr: &Multiaddr) -> Result<Multiaddr> {
+    let mut resolved = Multiaddr::empty();
+    for proto in addr.iter() {
+        let (name, family) = match &proto {
+            Protocol::Dns(name) => (name.to_string(), None),
+            Protocol::Dns4(name) => (name.to_string(), Some(false)),
+            Protocol::Dns6(name) => (name.to_string(), Some(true)),
+            _ => {
+                resolved.push(proto);
+                continue;
+            }
+        };
+
+        match resolve_host(resolver, &name, family).await? {
+            IpAddr::V4(ip) => resolved.push(Protocol::Ip4(ip)),
+            IpAddr::V6(ip) => resolved.push(Protocol::Ip6(ip)),
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Resolve a hostname to the address to dial, following the IPv6 settings
+///
+/// `family` restricts the result to IPv4 (`Some(false)`) or IPv6 (`Some(true)`).
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn resolve_host(resolver: &TokioAsyncResolver, host: &str, family: Option<bool>) -> Result<IpAddr> {
+    let lookup = resolver.lookup_ip(host).await
+        .with_context(|| format!("Failed to resolve {}", host))?;
+
+    lookup.iter()
+        .find(|ip| family.map_or(true, |ipv6| ip.is_ipv6() == ipv6))
+        .ok_or_else(|| anyhow::anyhow!("No usable address for {}", host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_ip_strategy() {
+        let mut config = DnsConfig::default();
+        assert!(config.is_default());
+        assert_eq!(ip_strategy(&config), LookupIpStrategy::Ipv4AndIpv6);
+
+        config.prefer_ipv6 = true;
+        assert_eq!(ip_strategy(&config), LookupIpStrategy::Ipv6thenIpv4);
+
+        config.ipv6 = false;
+        assert!(!config.is_default());
+        assert_eq!(ip_strategy(&config), LookupIpStrategy::Ipv4Only);
+    }
+
+    #[test]
+    fn test_is_dialable() {
+        let ipv4: Multiaddr = "/ip4/127.0.0.1/tcp/9002".parse().unwrap();
+        let ipv6: Multiaddr = "/ip6/::1/tcp/9002".parse().unwrap();
+        let dns6: Multiaddr = "/dns6/relay.example/tcp/9002".parse().unwrap();
+
+        let mut config = DnsConfig::default();
+        assert!(is_dialable(&config, &ipv6));
+
+        config.ipv6 = false;
+        assert!(is_dialable(&config, &ipv4));
+        assert!(!is_dialable(&config, &ipv6));
+        assert!(!is_dialable(&config, &dns6));
+
+        // Names that may resolve to either family are narrowed to IPv4
+        let dns: Multiaddr = "/dns/relay.example/tcp/9002".parse().unwrap();
+        assert_eq!(
+            dialable_address(&config, &dns),
+            Some("/dns4/relay.example/tcp/9002".parse().unwrap())
+        );
+        assert_eq!(dialable_address(&config, &ipv4), Some(ipv4));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_resolve_multiaddr() {
+        let resolver = resolver(&DnsConfig::default()).unwrap();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/9002".parse().unwrap();
+        assert_eq!(resolve_multiaddr(&resolver, &addr).await.unwrap(), addr);
+
+        let localhost: Multiaddr = "/dns4/localhost/tcp/9002".parse().unwrap();
+        let resolved = resolve_multiaddr(&resolver, &localhost).await.unwrap();
+        assert!(resolved.iter().any(|proto| matches!(proto, Protocol::Ip4(_))));
+        assert!(!resolved.iter().any(|proto| matches!(proto, Protocol::Dns4(_))));
+    }
+}
@@ -243,6 +243,17 @@ impl From<rust_decimal::Error> for Error {
     }
 }
 
+impl From<crate::wallet::coin_selection::CoinSelectionError> for Error {
+    fn from(error: crate::wallet::coin_selection::CoinSelectionError) -> Self {
+        use crate::wallet::coin_selection::CoinSelectionError;
+
+        match error {
+            CoinSelectionError::InsufficientFunds { .. } => Error::InsufficientFunds,
+            CoinSelectionError::InsufficientAsset { .. } => Error::InsufficientBalance,
+        }
+    }
+}
+
 impl From<String> for Error {
     fn from(error: String) -> Self {
         Error::UnknownError(error)
@@ -264,4 +275,4 @@ impl From<&str> for Error {
 // }
 
 // Note: We don't need to implement From<std::io::Error> for Error
-// because it's already implemented by the thiserror derive macro
\ No newline at end of file
+// because it's already implemented by the thiserror derive macro
@@ -0,0 +1,174 @@
+//! Esplora chain backend
+//!
+//! Talks to an Esplora server (the API of blockstream.info and mempool.space)
+//! for the chain state the node watches itself: the tip height, whether an
+//! output has been spent, and the broadcast of transactions. [`EsploraConnector`]
+//! opens a connection per [`ChainConnector::connect`], so a [`ResilientChain`]
+//! around it gets a fresh client after a failure.
+//!
+//! [`ResilientChain`]: crate::chain::ResilientChain
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::{Address, OutPoint, Transaction, Txid};
+use serde::Deserialize;
+
+use crate::chain::{ChainBackendError, ChainConnector};
+use crate::config::DnsConfig;
+use crate::trade::refund::RefundChain;
+
+/// Time a request to the server may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Spending status of an output
+#[derive(Debug, Deserialize)]
+struct OutSpend {
+    /// Whether the output has been spent
+    spent: bool,
+}
+
+/// Confirmation status of a transaction
+#[derive(Debug, Clone, Deserialize)]
+pub struct TxStatus {
+    /// Whether the transaction is in a block
+    pub confirmed: bool,
+    /// Height of the block, if confirmed
+    pub block_height: Option<u32>,
+}
+
+/// Unspent output of an address
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddressUtxo {
+    /// Transaction ID
+    pub txid: Txid,
+    /// Output index
+    pub vout: u32,
+    /// Value (satoshis)
+    pub value: u64,
+    /// Confirmation status
+    pub status: TxStatus,
+}
+
+/// Connection to an Esplora server
+pub struct EsploraChain {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Server base URL, without trailing slash
+    url: String,
+}
+
+impl EsploraChain {
+    /// Create a connection to the server at `url`
+    pub fn new(url: &str, dns: &DnsConfig) -> Result<Self> {
+        let client = crate::dns::http_client_builder(dns)?
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to create Esplora HTTP client")?;
+
+        Ok(Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Send a request and get the body of a successful response
+    ///
+    /// Client errors mean the server refused the request and are reported as
+    /// [`ChainBackendError::Rejected`], which is not retried.
+    async fn text(&self, request: reqwest::RequestBuilder) -> Result<String> {
+        let response = request.send().await.context("Failed to reach Esplora server")?;
+        let status = response.status();
+        let body = response.text().await.context("Failed to read Esplora response")?;
+
+        if status.is_client_error() {
+            return Err(ChainBackendError::Rejected(format!("{}: {}", status, body.trim())).into());
+        }
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("Esplora server answered {}: {}", status, body.trim()));
+        }
+
+        Ok(body)
+    }
+
+    /// Get the unspent outputs of an address
+    pub async fn address_utxos(&self, address: &Address) -> Result<Vec<AddressUtxo>> {
+        let body = self.text(self.client.get(format!("{}/address/{}/utxo", self.url, address))).await?;
+        serde_json::from_str(&body).context("Invalid address outputs")
+    }
+
+    /// Get a transaction
+    pub async fn transaction(&self, txid: &Txid) -> Result<Transaction> {
+        let body = self.text(self.client.get(format!("{}/tx/{}/hex", self.url, txid))).await?;
+        let bytes = hex::decode(body.trim()).context("Invalid transaction encoding")?;
+        deserialize(&bytes).context("Invalid transaction")
+    }
+
+    /// Get the fee rates (sat/vB) estimated for confirmation targets (blocks)
+    pub async fn fee_estimates(&self) -> Result<HashMap<u16, f32>> {
+        let body = self.text(self.client.get(format!("{}/fee-estimates", self.url))).await?;
+        let estimates: HashMap<String, f32> = serde_json::from_str(&body).context("Invalid fee estimates")?;
+        Ok(estimates
+            .into_iter()
+            .filter_map(|(target, rate)| target.parse().ok().map(|target| (target, rate)))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl RefundChain for EsploraChain {
+    async fn tip_height(&self) -> Result<u32> {
+        let body = self.text(self.client.get(format!("{}/blocks/tip/height", self.url))).await?;
+        body.trim().parse().context("Invalid tip height")
+    }
+
+    async fn is_spent(&self, outpoint: &OutPoint) -> Result<bool> {
+        let url = format!("{}/tx/{}/outspend/{}", self.url, outpoint.txid, outpoint.vout);
+        let body = self.text(self.client.get(url)).await?;
+        let outspend: OutSpend = serde_json::from_str(&body).context("Invalid output status")?;
+        Ok(outspend.spent)
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
+        let request = self.client.post(format!("{}/tx", self.url)).body(hex::encode(serialize(tx)));
+        let body = self.text(request).await?;
+        body.trim().parse().context("Invalid broadcast transaction ID")
+    }
+}
+
+/// Opens connections to an Esplora server
+pub struct EsploraConnector {
+    /// Server base URL
+    url: String,
+    /// DNS settings of the HTTP client
+    dns: DnsConfig,
+}
+
+impl EsploraConnector {
+    /// Create a connector for the server at `url`
+    pub fn new(url: &str, dns: &DnsConfig) -> Self {
+        Self {
+            url: url.to_string(),
+            dns: dns.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl ChainConnector for EsploraConnector {
+    async fn connect(&self) -> Result<Arc<dyn RefundChain>> {
+        Ok(Arc::new(EsploraChain::new(&self.url, &self.dns)?))
+    }
+}
+
+/// Connections for the requests only Esplora serves, like address outputs
+#[async_trait]
+impl ChainConnector<EsploraChain> for EsploraConnector {
+    async fn connect(&self) -> Result<Arc<EsploraChain>> {
+        Ok(Arc::new(EsploraChain::new(&self.url, &self.dns)?))
+    }
+}
@@ -0,0 +1,311 @@
+//! Events module for DarkSwap
+//!
+//! This module provides the event bus that fans SDK events out to subscribers.
+//! Each subscriber has a bounded queue. When a subscriber lags, the configured
+//! overflow policy either blocks the producers until there is room, or drops the
+//! oldest queued events and reports the loss with an `Event::LaggedEvents`.
+//!
+//! The SDK's own tasks that react to events also emit events, through the channel
+//! the bus is fed from. Were they to block the bus, and the bus them, neither would
+//! move, so they subscribe with [`EventBus::subscribe_internal`], whose queue never
+//! holds up producers and never drops an event.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::types::Event;
+
+/// Behaviour when a subscriber queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Wait for the subscriber to make room; slow subscribers slow down the SDK
+    Block,
+    /// Drop the oldest queued events and emit `Event::LaggedEvents`
+    DropOldest,
+}
+
+/// Event bus
+pub struct EventBus {
+    /// Overflow policy
+    policy: OverflowPolicy,
+    /// Capacity of each subscriber queue
+    capacity: usize,
+    /// Subscribers of a blocking bus
+    subscribers: Mutex<Vec<mpsc::Sender<Event>>>,
+    /// Internal subscribers, with unbounded queues
+    internal: Mutex<Vec<mpsc::UnboundedSender<Event>>>,
+    /// Sender of a drop-oldest bus, and of the lossy subscribers of a blocking bus
+    broadcast: broadcast::Sender<Event>,
+    /// Number of events dropped across all subscribers
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventBus {
+    /// Create a new event bus
+    pub fn new(policy: OverflowPolicy, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (broadcast, _) = broadcast::channel(capacity);
+
+        Self {
+            policy,
+            capacity,
+            subscribers: Mutex::new(Vec::new()),
+            internal: Mutex::new(Vec::new()),
+            broadcast,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Get the overflow policy
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Subscribe to events published after this call
+    pub fn subscribe(&self) -> EventReceiver {
+        let inner = match self.policy {
+            OverflowPolicy::Block => {
+                let (sender, receiver) = mpsc::channel(self.capacity);
+                self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+                ReceiverInner::Block(receiver)
+            }
+            OverflowPolicy::DropOldest => ReceiverInner::DropOldest(self.broadcast.subscribe()),
+        };
+
+        EventReceiver {
+            inner,
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Subscribe to events published after this call without ever holding up producers
+    ///
+    /// Whatever the overflow policy, this subscription drops its oldest events when it
+    /// lags and reports the loss with an `Event::LaggedEvents`. It is meant for
+    /// subscriptions that nothing is guaranteed to drain.
+    pub fn subscribe_lossy(&self) -> EventReceiver {
+        EventReceiver {
+            inner: ReceiverInner::DropOldest(self.broadcast.subscribe()),
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Subscribe an SDK task that emits events of its own
+    ///
+    /// Whatever the overflow policy, the subscription neither holds up producers nor
+    /// loses events: its queue grows while the task is busy. Only tasks that keep
+    /// draining it may use it.
+    pub(crate) fn subscribe_internal(&self) -> EventReceiver {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.internal.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+
+        EventReceiver {
+            inner: ReceiverInner::Internal(receiver),
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Publish an event to all subscribers
+    pub async fn publish(&self, event: Event) {
+        // Internal subscribers never wait; forget the closed ones
+        self.internal
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+
+        match self.policy {
+            OverflowPolicy::Block => {
+                let subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+                // Wait for room in each queue
+                for subscriber in &subscribers {
+                    let _ = subscriber.send(event.clone()).await;
+                }
+
+                // Forget closed subscribers
+                self.subscribers
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .retain(|subscriber| !subscriber.is_closed());
+
+                // Lossy subscribers never wait
+                let _ = self.broadcast.send(event);
+            }
+            OverflowPolicy::DropOldest => {
+                // An error only means that there are no subscribers
+                let _ = self.broadcast.send(event);
+            }
+        }
+    }
+
+    /// Forward events from a channel to the subscribers until it is closed
+    pub async fn run(&self, mut receiver: mpsc::Receiver<Event>) {
+        while let Some(event) = receiver.recv().await {
+            self.publish(event).await;
+        }
+    }
+
+    /// Get the number of events dropped because subscribers lagged
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Subscriber queue
+enum ReceiverInner {
+    /// Queue of a blocking bus
+    Block(mpsc::Receiver<Event>),
+    /// Queue of a drop-oldest bus
+    DropOldest(broadcast::Receiver<Event>),
+    /// Unbounded queue of an internal subscriber
+    Internal(mpsc::UnboundedReceiver<Event>),
+}
+
+/// Event subscription
+pub struct EventReceiver {
+    /// Subscriber queue
+    inner: ReceiverInner,
+    /// Number of events dropped across all subscribers
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventReceiver {
+    /// Wait for the next event
+    ///
+    /// If events were dropped since the last call, `Event::LaggedEvents` is returned
+    /// first with the number of lost events.
+    pub async fn recv(&mut self) -> Option<Event> {
+        match &mut self.inner {
+            ReceiverInner::Block(receiver) => receiver.recv().await,
+            ReceiverInner::Internal(receiver) => receiver.recv().await,
+            ReceiverInner::DropOldest(receiver) => match receiver.recv().await {
+                Ok(event) => Some(event),
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Event subscriber lagged, {} events dropped", count);
+                    self.dropped.fetch_add(count, Ordering::Relaxed);
+                    Some(Event::LaggedEvents(count))
+                }
+                Err(broadcast::error::RecvError::Closed) => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TradeId;
+
+    fn event(n: usize) -> Event {
+        Event::TradeUpdated(TradeId(n.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_reports_lag() {
+        let bus = EventBus::new(OverflowPolicy::DropOldest, 2);
+        let mut receiver = bus.subscribe();
+
+        for n in 0..5 {
+            bus.publish(event(n)).await;
+        }
+
+        match receiver.recv().await {
+            Some(Event::LaggedEvents(3)) => {}
+            other => panic!("Expected LaggedEvents(3), got {:?}", other),
+        }
+        assert_eq!(bus.dropped_events(), 3);
+
+        match receiver.recv().await {
+            Some(Event::TradeUpdated(trade_id)) => assert_eq!(trade_id.0, "3"),
+            other => panic!("Expected TradeUpdated, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_keeps_every_event() {
+        let bus = Arc::new(EventBus::new(OverflowPolicy::Block, 1));
+        let mut receiver = bus.subscribe();
+
+        let publisher = {
+            let bus = bus.clone();
+            tokio::spawn(async move {
+                for n in 0..5 {
+                    bus.publish(event(n)).await;
+                }
+            })
+        };
+
+        for n in 0..5 {
+            match receiver.recv().await {
+                Some(Event::TradeUpdated(trade_id)) => assert_eq!(trade_id.0, n.to_string()),
+                other => panic!("Expected TradeUpdated, got {:?}", other),
+            }
+        }
+
+        publisher.await.unwrap();
+        assert_eq!(bus.dropped_events(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lossy_subscriber_does_not_block() {
+        let bus = EventBus::new(OverflowPolicy::Block, 1);
+        let mut lossy = bus.subscribe_lossy();
+
+        // Nothing drains the lossy subscription, publishing must not wait for it
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            for n in 0..5 {
+                bus.publish(event(n)).await;
+            }
+        })
+        .await
+        .expect("publishing blocked on a lossy subscriber");
+
+        match lossy.recv().await {
+            Some(Event::LaggedEvents(4)) => {}
+            other => panic!("Expected LaggedEvents(4), got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_internal_subscriber_feeding_the_bus_does_not_deadlock() {
+        let bus = Arc::new(EventBus::new(OverflowPolicy::Block, 1));
+        let (sender, receiver) = mpsc::channel(1);
+        let mut internal = bus.subscribe_internal();
+        let mut external = bus.subscribe();
+
+        // The internal task answers every event with one of its own, like the watchlist
+        let task = {
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                while let Some(event) = internal.recv().await {
+                    if let Event::TradeUpdated(trade_id) = event {
+                        let _ = sender.send(Event::TradeCompleted(trade_id)).await;
+                    }
+                }
+            })
+        };
+        let forwarder = {
+            let bus = bus.clone();
+            tokio::spawn(async move { bus.run(receiver).await })
+        };
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            for n in 0..5 {
+                sender.send(event(n)).await.unwrap();
+            }
+            // Every event and every answer reaches the external subscriber
+            for _ in 0..10 {
+                external.recv().await.unwrap();
+            }
+        })
+        .await
+        .expect("bus deadlocked on an internal subscriber");
+
+        task.abort();
+        forwarder.abort();
+    }
+}
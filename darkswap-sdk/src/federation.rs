@@ -0,0 +1,335 @@
+//! Orderbook federation
+//!
+//! Users running regional nodes can let one instance show the orders of several
+//! trusted remote daemons next to its own. Each remote's open orders are polled
+//! over its REST API and kept per remote; the combined view tags every order with
+//! the node it came from. Remote orders are for display and routing only: they are
+//! never inserted into the local orderbook or gossiped to peers. A remote that has
+//! not answered for `stale_after` seconds has its orders dropped.
+//!
+//! Polling needs an HTTP client and is only built with the `federation` feature;
+//! the order types and [`merge_orders`] are always available.
+
+use std::collections::HashSet;
+#[cfg(feature = "federation")]
+use std::collections::HashMap;
+#[cfg(feature = "federation")]
+use std::sync::Arc;
+#[cfg(feature = "federation")]
+use std::time::Duration;
+
+#[cfg(feature = "federation")]
+use anyhow::{Context, Result};
+#[cfg(feature = "federation")]
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "federation")]
+use tokio::sync::RwLock;
+
+#[cfg(feature = "federation")]
+use crate::clock::{self, Clock};
+#[cfg(feature = "federation")]
+use crate::config::{DnsConfig, FederationConfig, RemoteDaemonConfig};
+use crate::orderbook::{Order, OrderStatus};
+use crate::types::Asset;
+
+/// Node an order came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderOrigin {
+    /// This node's orderbook
+    Local,
+    /// A remote daemon, by configured name
+    Remote(String),
+}
+
+/// Order in the combined view
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedOrder {
+    /// Order
+    pub order: Order,
+    /// Node the order came from
+    pub origin: OrderOrigin,
+}
+
+/// Polling state of a remote daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteStatus {
+    /// Configured name
+    pub name: String,
+    /// Base URL
+    pub url: String,
+    /// Number of open orders last fetched
+    pub order_count: usize,
+    /// Last successful fetch (unix seconds)
+    pub last_success: Option<u64>,
+    /// Error of the last fetch, if it failed
+    pub last_error: Option<String>,
+}
+
+/// Orders and status of a remote daemon
+#[cfg(any(feature = "federation", test))]
+#[derive(Debug, Clone)]
+struct RemoteBook {
+    /// Open orders
+    orders: Vec<Order>,
+    /// Status
+    status: RemoteStatus,
+}
+
+#[cfg(any(feature = "federation", test))]
+impl RemoteBook {
+    /// Drop the orders if the last successful fetch is older than `stale_after` seconds
+    fn expire(&mut self, now: u64, stale_after: u64) {
+        let fresh = self.status.last_success
+            .map_or(false, |last_success| now.saturating_sub(last_success) <= stale_after);
+        if !fresh && !self.orders.is_empty() {
+            self.orders.clear();
+            self.status.order_count = 0;
+        }
+    }
+}
+
+/// Aggregator of remote daemon orderbooks
+#[cfg(feature = "federation")]
+pub struct Federation {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Remote daemons
+    remotes: Vec<RemoteDaemonConfig>,
+    /// Seconds without a successful fetch after which a remote's orders are dropped
+    stale_after: u64,
+    /// Books by remote name
+    books: RwLock<HashMap<String, RemoteBook>>,
+    /// Time source of fetch times
+    clock: Arc<dyn Clock>,
+}
+
+#[cfg(feature = "federation")]
+impl Federation {
+    /// Create a federation of the configured remotes
+    pub fn new(config: &FederationConfig, dns: &DnsConfig) -> Result<Self> {
+        let client = crate::dns::http_client_builder(dns)?
+            .timeout(Duration::from_secs(config.request_timeout.max(1)))
+            .build()
+            .context("Failed to create federation HTTP client")?;
+
+        let books = config.remotes.iter()
+            .map(|remote| {
+                let book = RemoteBook {
+                    orders: Vec::new(),
+                    status: RemoteStatus {
+                        name: remote.name.clone(),
+                        url: remote.url.clone(),
+                        order_count: 0,
+                        last_success: None,
+                        last_error: None,
+                    },
+                };
+                (remote.name.clone(), book)
+            })
+            .collect();
+
+        Ok(Self {
+            client,
+            remotes: config.remotes.clone(),
+            stale_after: config.stale_after,
+            books: RwLock::new(books),
+            clock: clock::system(),
+        })
+    }
+
+    /// Read the time from `clock` instead of the wall clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Fetch the open orders of a remote daemon
+    async fn fetch(&self, remote: &RemoteDaemonConfig) -> Result<Vec<Order>> {
+        let url = format!("{}/orders", remote.url.trim_end_matches('/'));
+        let response = self.client.get(&url)
+            .query(&[("status", "open")])
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("{} answered {}", url, response.status()));
+        }
+
+        response.json::<Vec<Order>>()
+            .await
+            .with_context(|| format!("Invalid orders from {}", url))
+    }
+
+    /// Refresh the orders of every remote daemon
+    ///
+    /// A remote that cannot be reached keeps its last orders for `stale_after`
+    /// seconds after its last successful fetch, and its status records the error.
+    pub async fn refresh(&self) {
+        let results = futures::future::join_all(
+            self.remotes.iter().map(|remote| async move { (remote, self.fetch(remote).await) })
+        ).await;
+
+        let now = self.clock.now();
+
+        let mut books = self.books.write().await;
+        for (remote, result) in results {
+            let book = match books.get_mut(&remote.name) {
+                Some(book) => book,
+                None => continue,
+            };
+
+            match result {
+                Ok(orders) => {
+                    debug!("Fetched {} orders from federated daemon {}", orders.len(), remote.name);
+                    book.status.order_count = orders.len();
+                    book.status.last_success = Some(now);
+                    book.status.last_error = None;
+                    book.orders = orders;
+                }
+                Err(e) => {
+                    warn!("Failed to refresh federated daemon {}: {:#}", remote.name, e);
+                    book.status.last_error = Some(format!("{:#}", e));
+                    book.expire(now, self.stale_after);
+                }
+            }
+        }
+    }
+
+    /// Refresh the remotes on an interval
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                self.refresh().await;
+            }
+        })
+    }
+
+    /// Combine local orders with the remote ones, optionally for one pair
+    pub async fn combined(&self, local: Vec<Order>, pair: Option<(&Asset, &Asset)>) -> Vec<FederatedOrder> {
+        let books = self.books.read().await;
+
+        // Keep the configured order, so earlier remotes win duplicates
+        let remote_books = self.remotes.iter()
+            .filter_map(|remote| books.get(&remote.name).map(|book| (remote.name.clone(), book.orders.clone())))
+            .collect();
+
+        merge_orders(local, remote_books, pair, self.clock.now())
+    }
+
+    /// Get the polling state of every remote daemon
+    pub async fn statuses(&self) -> Vec<RemoteStatus> {
+        let books = self.books.read().await;
+        self.remotes.iter()
+            .filter_map(|remote| books.get(&remote.name).map(|book| book.status.clone()))
+            .collect()
+    }
+}
+
+/// Merge local and remote orders into one tagged list
+///
+/// An order ID seen more than once is kept from the local book if present, or else
+/// from the first remote listing it, since gossip spreads the same order to every
+/// node. Orders that are not open or have expired at `now` are dropped.
+pub fn merge_orders(
+    local: Vec<Order>,
+    remotes: Vec<(String, Vec<Order>)>,
+    pair: Option<(&Asset, &Asset)>,
+    now: u64,
+) -> Vec<FederatedOrder> {
+    let tagged = local.into_iter()
+        .map(|order| (order, OrderOrigin::Local))
+        .chain(remotes.into_iter().flat_map(|(name, orders)| {
+            orders.into_iter().map(move |order| (order, OrderOrigin::Remote(name.clone())))
+        }));
+
+    let mut seen = HashSet::new();
+    tagged
+        .filter(|(order, _)| order.status == OrderStatus::Open && !order.is_expired_at(now))
+        .filter(|(order, _)| {
+            pair.map_or(true, |(base, quote)| order.base_asset == *base && order.quote_asset == *quote)
+        })
+        .filter(|(order, _)| seen.insert(order.id.clone()))
+        .map(|(order, origin)| FederatedOrder { order, origin })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+    use rust_decimal::Decimal;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn order(quote_asset: Asset) -> Order {
+        Order::new_at(
+            "maker".to_string(),
+            Asset::Bitcoin,
+            quote_asset,
+            OrderSide::Sell,
+            Decimal::ONE,
+            Decimal::ONE,
+            None,
+            NOW,
+        )
+    }
+
+    #[test]
+    fn test_merge_tags_and_deduplicates() {
+        let local = order(Asset::Rune(1));
+        let shared = order(Asset::Rune(1));
+        let mut filled = order(Asset::Rune(1));
+        filled.status = OrderStatus::Filled;
+        let other_pair = order(Asset::Rune(2));
+
+        let merged = merge_orders(
+            vec![local.clone(), shared.clone()],
+            vec![
+                ("eu".to_string(), vec![shared.clone(), filled, other_pair.clone()]),
+                ("us".to_string(), vec![other_pair.clone()]),
+            ],
+            None,
+            NOW,
+        );
+
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[1].order.id, shared.id);
+        assert_eq!(merged[1].origin, OrderOrigin::Local);
+        assert_eq!(merged[2].order.id, other_pair.id);
+        assert_eq!(merged[2].origin, OrderOrigin::Remote("eu".to_string()));
+
+        let pair = merge_orders(
+            vec![local],
+            vec![("us".to_string(), vec![other_pair])],
+            Some((&Asset::Bitcoin, &Asset::Rune(2))),
+            NOW,
+        );
+        assert_eq!(pair.len(), 1);
+        assert_eq!(pair[0].origin, OrderOrigin::Remote("us".to_string()));
+    }
+
+    #[test]
+    fn test_unreachable_remote_orders_expire() {
+        let mut book = RemoteBook {
+            orders: vec![order(Asset::Rune(1))],
+            status: RemoteStatus {
+                name: "eu".to_string(),
+                url: "http://eu.example".to_string(),
+                order_count: 1,
+                last_success: Some(1_000),
+                last_error: None,
+            },
+        };
+
+        book.expire(1_060, 60);
+        assert_eq!(book.orders.len(), 1);
+
+        book.expire(1_061, 60);
+        assert!(book.orders.is_empty());
+        assert_eq!(book.status.order_count, 0);
+    }
+}
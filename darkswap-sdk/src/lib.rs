@@ -3,23 +3,49 @@
 //! This is the main entry point for the DarkSwap SDK, a decentralized peer-to-peer
 //! trading platform for Bitcoin, runes, and alkanes.
 
+#[cfg(not(feature = "no-alkanes"))]
 pub mod alkanes;
+#[cfg(not(feature = "no-alkanes"))]
 pub mod alkane_trade;
+pub mod analytics;
+pub mod asset_info;
+pub mod audit;
 pub mod bitcoin_utils;
+pub mod chain;
+pub mod clock;
 pub mod config;
+pub mod dashboard;
+pub mod dns;
+#[cfg(feature = "devtools")]
+pub mod devtools;
+pub mod dispatch;
 pub mod error;
+#[cfg(feature = "http")]
+pub mod esplora;
+pub mod events;
+pub mod federation;
 pub mod orderbook;
 pub mod p2p;
 pub mod performance;
+#[cfg(not(feature = "no-alkanes"))]
 pub mod predicates;
+pub mod release;
+pub mod reputation;
+pub mod rules;
 pub mod runes;
 pub mod runestone;
+pub mod spv;
 pub mod trade;
 pub mod types;
 pub mod wallet;
 #[cfg(feature = "wasm")]
 pub mod wasm;
+#[cfg(feature = "wasm")]
+pub mod wasm_worker;
+
+pub use darkswap_support::{secrets, storage, systemd};
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use anyhow::{Context as AnyhowContext, Result};
 use async_trait::async_trait;
@@ -27,13 +53,65 @@ use log::{debug, error, info, warn};
 use tokio::sync::{mpsc, Mutex, RwLock};
 
 use performance::{PerformanceProfiler, PerformanceOptimizer};
+use reputation::{Reputation, ReputationManager, RotationStatement};
 
-use config::Config;
-use orderbook::{Order, OrderId, OrderSide, OrderStatus, Orderbook};
-use p2p::{circuit_relay::CircuitRelayManager, webrtc_transport::DarkSwapWebRtcTransport, P2PNetwork};
-use trade::{Trade, TradeModule as TradeManager};
+use analytics::SessionAnalytics;
+use asset_info::AssetInfo;
+use audit::{AuditLog, AuditRecord};
+use chain::{ChainConnector, ResilientChain};
+use clock::Clock;
+use config::{BitcoinNetwork, Config};
+use events::{EventBus, EventReceiver};
+use federation::{FederatedOrder, RemoteStatus};
+#[cfg(feature = "federation")]
+use federation::Federation;
+use orderbook::{Order, OrderId, OrderListing, OrderSide, OrderStatus, Orderbook, OrderbookSnapshot, SignedOrder};
+use orderbook::migration::{OrderImport, SignedOrderBundle};
+use orderbook::matching::{Fill, MatchHooks, MatchResult, PostMatchHook, PreMatchHook};
+use orderbook::retention::{BookStats, CompactionReport};
+use orderbook::routing::{self, Route, RouteExecution, RouteExecutor};
+use orderbook::view::MarketSummary;
+use orderbook::watchlist::{WatchedPair, Watchlist};
+use orderbook::announcement::{AnnouncementSigner, Delegation, LocalAnnouncementSigner};
+#[cfg(feature = "http")]
+use orderbook::cosign::HttpCoSigner;
+use orderbook::cosign::OrderCosigner;
+use orderbook::dark::{DarkOrder, DarkPool, RevealRequest};
+use orderbook::group::{GroupId, GroupKey, GroupManager, PrivateGroup};
+use orderbook::iceberg::{IcebergId, IcebergManager, IcebergOrder};
+use orderbook::scheduler::{OrderScheduler, OrderTemplate, PriceTrigger, ScheduleId, ScheduledOrder};
+use p2p::{circuit_relay::CircuitRelayManager, path_selection::PathMetrics, webrtc_transport::DarkSwapWebRtcTransport, P2PNetwork};
+use p2p::rfq::{QuoteRequest, SignedQuote};
+use storage::{DocumentFile, Storage};
+use trade::{
+    fees::{self as trade_fees, ReferralSummary},
+    memo::{Memo, MemoStore, TradeHistoryEntry, TradeHistoryFilter},
+    query::{TradePage, TradeQuery},
+    psbt::VerificationPool,
+    refund::{RefundChain, RefundPath, RefundSweeper},
+    rfq::RfqManager,
+    ticker::{self as trade_ticker, Ticker},
+    outbox::Outbox,
+    transcript::{TradeTranscript, TranscriptMessage, TranscriptStore},
+    wal::{TradeWal, WalRecovery},
+    Trade,
+    TradeModule as TradeManager,
+    TradeState,
+};
 use types::{Asset, Event, TradeId};
-use wallet::{bdk_wallet::BdkWallet, simple_wallet::SimpleWallet, WalletInterface};
+#[cfg(not(feature = "no-bdk"))]
+use wallet::bdk_wallet::BdkWallet;
+#[cfg(feature = "http")]
+use wallet::{custody::CustodyWallet, remote_signer::{RemoteAnnouncementSigner, RemoteSignerWallet}, watch_only::WatchOnlyWallet};
+use wallet::{
+    address_book::AddressInfo,
+    consolidation::{self, ConsolidationPlan, ConsolidationReport},
+    deposits::{Deposit, DepositWatcher},
+    reserve::{btc_commitment, FeeReserveGuard, FeeReserveStatus},
+    simple_wallet::SimpleWallet,
+    WalletInterface,
+};
+#[cfg(not(feature = "no-alkanes"))]
 use predicates::{
     EqualityPredicateAlkane,
     Predicate,
@@ -50,6 +128,8 @@ use predicates::{
 
 /// DarkSwap SDK
 pub struct DarkSwap {
+    /// Configuration as given, before the profile of the network is applied
+    base_config: Config,
     /// Configuration
     config: Config,
     /// P2P network
@@ -64,21 +144,184 @@ pub struct DarkSwap {
     orderbook: Option<Arc<Orderbook>>,
     /// Trade manager
     trade_manager: Option<Arc<TradeManager>>,
-    /// Event channel
-    event_channel: (mpsc::Sender<Event>, mpsc::Receiver<Event>),
+    /// Fee reserve new orders and inbound trades must leave untouched, if configured
+    fee_guard: Option<Arc<FeeReserveGuard>>,
+    /// Co-signer own orders must be approved by, if configured
+    cosigner: Option<Arc<OrderCosigner>>,
+    /// Request-for-quote manager
+    rfq: Option<Arc<RfqManager>>,
+    /// Private group orderbooks
+    groups: Option<Arc<GroupManager>>,
+    /// Dark pool
+    dark_pool: Option<Arc<DarkPool>>,
+    /// Event channel; the receiver is handed to the event bus on start
+    event_channel: (mpsc::Sender<Event>, Option<mpsc::Receiver<Event>>),
+    /// Event bus
+    event_bus: Arc<EventBus>,
+    /// Subscription read by `next_event`
+    events: EventReceiver,
     /// Performance profiler
     performance_profiler: Option<Arc<PerformanceProfiler>>,
     /// Performance optimizer
     performance_optimizer: Option<Arc<PerformanceOptimizer>>,
+    /// Reputation manager
+    reputation: Arc<RwLock<ReputationManager>>,
+    /// SPV verifier
+    spv: Option<Arc<RwLock<spv::SpvVerifier>>>,
+    /// Scheduled dust consolidation
+    consolidation_task: Option<tokio::task::JoinHandle<()>>,
+    /// Local trade and transaction memos
+    memos: Arc<RwLock<MemoStore>>,
+    /// Negotiation transcripts, if they are kept
+    transcripts: Option<Arc<TranscriptStore>>,
+    /// Remote daemons whose orderbooks are aggregated
+    #[cfg(feature = "federation")]
+    federation: Option<Arc<Federation>>,
+    /// Periodic refresh of the federated orderbooks
+    #[cfg(feature = "federation")]
+    federation_task: Option<tokio::task::JoinHandle<()>>,
+    /// File this node's open orders are persisted to, if any
+    order_store: Option<Arc<DocumentFile>>,
+    /// Scheduled and conditional orders
+    scheduler: Arc<RwLock<OrderScheduler>>,
+    /// Task posting scheduled orders when their conditions are met
+    scheduler_task: Option<tokio::task::JoinHandle<()>>,
+    /// Iceberg orders
+    icebergs: Arc<RwLock<IcebergManager>>,
+    /// Task posting the next slice of iceberg orders as slices fill
+    iceberg_task: Option<tokio::task::JoinHandle<()>>,
+    /// Chain backend the node watches the chain through, if one is configured
+    chain: Option<Arc<ResilientChain>>,
+    /// Refund paths of escrowed trades
+    refunds: Arc<RwLock<RefundSweeper>>,
+    /// Task broadcasting refunds as they mature
+    refund_task: Option<tokio::task::JoinHandle<()>>,
+    /// Task retrying unacknowledged trade messages
+    outbox_task: Option<tokio::task::JoinHandle<()>>,
+    /// Task expiring timed out trades
+    trade_expiry_task: Option<tokio::task::JoinHandle<()>>,
+    /// Task emitting wallet balance changes
+    balance_task: Option<tokio::task::JoinHandle<()>>,
+    /// Incoming payments to the wallet, if they are watched
+    deposits: Option<Arc<DepositWatcher>>,
+    /// Task scanning the wallet for deposits
+    deposit_task: Option<tokio::task::JoinHandle<()>>,
+    /// Connection pool maintenance task
+    pool_task: Option<tokio::task::JoinHandle<()>>,
+    /// Followed makers and pairs
+    watchlist: Arc<RwLock<Watchlist>>,
+    /// Task emitting watchlist events
+    watchlist_task: Option<tokio::task::JoinHandle<()>>,
+    /// Task forgetting the interest filters of disconnected light peers
+    interest_task: Option<tokio::task::JoinHandle<()>>,
+    /// Task compacting terminal orders out of the book
+    compaction_task: Option<tokio::task::JoinHandle<()>>,
+    /// Task publishing the order digest
+    digest_task: Option<tokio::task::JoinHandle<()>>,
+    /// Task routing the messages peers send to their handlers
+    dispatch_task: Option<tokio::task::JoinHandle<()>>,
+    /// Storage shared by the subsystems
+    storage: Arc<dyn Storage>,
+    /// Time source of the orderbook and the trade manager
+    clock: Arc<dyn Clock>,
+    /// Log of observed announcements, in audit mode
+    audit: Option<Arc<AuditLog>>,
+    /// Trading session analytics, recorded with the `analytics` feature
+    analytics: Option<Arc<SessionAnalytics>>,
+    /// Pool PSBT and order signatures are checked on, shared by the orderbook and trade manager
+    verification_pool: VerificationPool,
+    /// Time DarkSwap was started (unix seconds), while it runs
+    started_at: Option<u64>,
+    /// Hooks the orderbook runs around matching
+    match_hooks: MatchHooks,
 }
 
 impl DarkSwap {
     /// Create a new DarkSwap instance
     pub fn new(config: Config) -> Result<Self> {
-        // Create event channel
-        let (event_sender, event_receiver) = mpsc::channel(100);
+        let network_config = config.for_network(config.bitcoin.network);
+        if network_config.trade.transcripts.enabled && network_config.storage.encryption.is_none() {
+            anyhow::bail!("Negotiation transcripts are only kept on encrypted storage");
+        }
+        
+        let storage = storage::open(&network_config.storage)?;
+        Self::with_storage(config, storage)
+    }
+
+    /// Create a new DarkSwap instance on storage provided by the embedder
+    ///
+    /// The storage configuration is ignored, except for its encryption, which
+    /// still seals the stores kept in files of their own.
+    pub fn with_storage(config: Config, storage: Arc<dyn Storage>) -> Result<Self> {
+        // Apply the profile of the network and replace keyring references by the secrets they name
+        let base_config = config;
+        let config = base_config.for_network(base_config.bitcoin.network)
+            .resolve_secrets(&mut secrets::SecretResolver::new())?;
+        
+        // Create event channel and bus
+        let (event_sender, event_receiver) = mpsc::channel(config.events.channel_capacity.max(1));
+        let event_bus = Arc::new(EventBus::new(
+            config.events.overflow_policy,
+            config.events.subscriber_capacity,
+        ));
+        // Nothing has to call `next_event`, so this subscription must never hold up the bus
+        let events = event_bus.subscribe_lossy();
+        
+        // Create SPV verifier if enabled
+        let spv = if config.bitcoin.spv_verification {
+            Some(Arc::new(RwLock::new(spv::SpvVerifier::new(
+                config.bitcoin.network.into(),
+                config.bitcoin.spv_min_confirmations,
+            ))))
+        } else {
+            None
+        };
+        
+        // Open the memo store
+        let memos = match &config.trade.memo_store_path {
+            Some(path) => MemoStore::open_file(config.store_file(path)?)?,
+            None => MemoStore::new(),
+        };
+        
+        // Open the transcript store; embedders providing the storage are responsible for encrypting it
+        let clock = clock::system();
+        let transcripts = if config.trade.transcripts.enabled {
+            Some(Arc::new(TranscriptStore::open(storage.clone(), config.trade.transcripts.clone(), clock.now())?))
+        } else {
+            None
+        };
+        
+        // Open the schedule store
+        let scheduler = match &config.orderbook.schedule_store_path {
+            Some(path) => OrderScheduler::open_file(config.store_file(path)?)?,
+            None => OrderScheduler::new(),
+        };
+        
+        // Open the iceberg store
+        let icebergs = match &config.orderbook.iceberg_store_path {
+            Some(path) => IcebergManager::open_file(config.store_file(path)?)?,
+            None => IcebergManager::new(),
+        };
+        
+        // Open the refund store
+        let refunds = match &config.trade.refund_store_path {
+            Some(path) => RefundSweeper::open_file(config.store_file(path)?)?,
+            None => RefundSweeper::new(),
+        };
+        
+        // Open the watchlist
+        let watchlist = match &config.orderbook.watchlist_path {
+            Some(path) => Watchlist::open_file(config.store_file(path)?)?,
+            None => Watchlist::new(),
+        };
+        
+        let order_store = match &config.orderbook.order_store_path {
+            Some(path) => Some(Arc::new(config.store_file(path)?)),
+            None => None,
+        };
         
         Ok(Self {
+            base_config,
             config,
             network: None,
             webrtc_transport: None,
@@ -86,29 +329,154 @@ impl DarkSwap {
             wallet: None,
             orderbook: None,
             trade_manager: None,
-            event_channel: (event_sender, event_receiver),
+            fee_guard: None,
+            cosigner: None,
+            rfq: None,
+            groups: None,
+            dark_pool: None,
+            event_channel: (event_sender, Some(event_receiver)),
+            event_bus,
+            events,
             performance_profiler: None,
             performance_optimizer: None,
+            reputation: Arc::new(RwLock::new(ReputationManager::new())),
+            spv,
+            consolidation_task: None,
+            memos: Arc::new(RwLock::new(memos)),
+            transcripts,
+            #[cfg(feature = "federation")]
+            federation: None,
+            #[cfg(feature = "federation")]
+            federation_task: None,
+            order_store,
+            scheduler: Arc::new(RwLock::new(scheduler)),
+            scheduler_task: None,
+            icebergs: Arc::new(RwLock::new(icebergs)),
+            iceberg_task: None,
+            chain: None,
+            refunds: Arc::new(RwLock::new(refunds)),
+            refund_task: None,
+            outbox_task: None,
+            trade_expiry_task: None,
+            balance_task: None,
+            deposits: None,
+            deposit_task: None,
+            pool_task: None,
+            watchlist: Arc::new(RwLock::new(watchlist)),
+            watchlist_task: None,
+            interest_task: None,
+            compaction_task: None,
+            digest_task: None,
+            dispatch_task: None,
+            storage,
+            clock,
+            audit: None,
+            analytics: cfg!(feature = "analytics").then(|| Arc::new(SessionAnalytics::new())),
+            verification_pool: VerificationPool::new(config.trade.verification_concurrency),
+            started_at: None,
+            match_hooks: MatchHooks::default(),
         })
     }
 
+    /// Read the time from `clock` instead of the wall clock, e.g. a `MockClock` in tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Run `hook` before every match, direct take and route hop, e.g. to enforce jurisdictional rules
+    ///
+    /// Hooks run in the order they are added; see [`orderbook::matching`].
+    pub fn with_pre_match_hook(mut self, hook: Arc<dyn PreMatchHook>) -> Self {
+        self.match_hooks = self.match_hooks.with_pre_match(hook);
+        self
+    }
+
+    /// Run `hook` after every match, direct take and route hop that was not refused
+    pub fn with_post_match_hook(mut self, hook: Arc<dyn PostMatchHook>) -> Self {
+        self.match_hooks = self.match_hooks.with_post_match(hook);
+        self
+    }
+
+    /// Get the storage shared by the subsystems, for embedders keeping their own records next to them
+    pub fn storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
+
     /// Start DarkSwap
     pub async fn start(&mut self) -> Result<()> {
+        // Start forwarding events to subscribers
+        if let Some(event_receiver) = self.event_channel.1.take() {
+            let event_bus = self.event_bus.clone();
+            tokio::spawn(async move {
+                event_bus.run(event_receiver).await;
+            });
+        }
+        
         // Initialize wallet
         self.init_wallet().await?;
         
         // Initialize P2P network
         self.init_network().await?;
         
+        // Open the audit log and listen on every topic in audit mode
+        self.init_audit().await?;
+        
         // Initialize orderbook
         self.init_orderbook().await?;
         
         // Initialize trade manager
         self.init_trade_manager().await?;
         
+        // Route the messages peers send to their handlers
+        self.init_dispatch().await?;
+        
+        // Report wallet balance changes as they happen
+        if let (Some(wallet), true) = (&self.wallet, self.config.wallet.balance_sync.enabled) {
+            self.balance_task = Some(wallet::balance_sync::spawn_balance_sync(
+                wallet.clone(),
+                self.trade_manager.clone(),
+                self.event_channel.0.clone(),
+                self.clock.clone(),
+                std::time::Duration::from_secs(self.config.wallet.balance_sync.interval.max(1)),
+            ));
+        }
+        
+        // Report incoming payments as they arrive and confirm
+        if let (Some(wallet), Some(deposits)) = (&self.wallet, &self.deposits) {
+            self.deposit_task = Some(wallet::deposits::spawn_deposit_watcher(
+                deposits.clone(),
+                wallet.clone(),
+                self.trade_manager.clone(),
+                self.event_channel.0.clone(),
+                self.clock.clone(),
+                std::time::Duration::from_secs(self.config.wallet.deposits.interval.max(1)),
+            ));
+        }
+        
+        // Broadcast escrow refunds as they mature
+        self.init_chain();
+        
+        // Start posting iceberg order slices
+        self.init_icebergs().await?;
+        
         // Initialize performance profiler and optimizer
         self.init_performance().await?;
         
+        // Start aggregating remote orderbooks if enabled
+        #[cfg(not(feature = "federation"))]
+        if self.config.federation.enabled {
+            return Err(anyhow::anyhow!("Federation support was left out of this build (federation feature)"));
+        }
+        #[cfg(feature = "federation")]
+        if self.config.federation.enabled {
+            let federation = Arc::new(Federation::new(&self.config.federation, &self.config.dns)?.with_clock(self.clock.clone()));
+            let interval = std::time::Duration::from_secs(self.config.federation.refresh_interval.max(1));
+            self.federation_task = Some(federation.clone().spawn(interval));
+            self.federation = Some(federation);
+        }
+        
+        self.started_at = Some(self.clock.now());
         info!("DarkSwap started successfully");
         
         Ok(())
@@ -117,6 +485,7 @@ impl DarkSwap {
     /// Initialize wallet
     async fn init_wallet(&mut self) -> Result<()> {
         let wallet: Arc<dyn WalletInterface + Send + Sync> = match self.config.wallet.wallet_type.as_str() {
+            #[cfg(not(feature = "no-bdk"))]
             "bdk" => {
                 // Create BDK wallet
                 let mnemonic = self.config.wallet.mnemonic.as_deref()
@@ -134,17 +503,75 @@ impl DarkSwap {
                 
                 Arc::new(bdk_wallet)
             }
+            #[cfg(feature = "no-bdk")]
+            "bdk" => {
+                return Err(anyhow::anyhow!("BDK wallet support was left out of this build (no-bdk feature)"));
+            }
+            #[cfg(feature = "http")]
+            "remote" => {
+                let signer = self.config.wallet.remote_signer.as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Remote signer configuration required for remote wallet"))?;
+                
+                // Balances and broadcast only; the keys stay with the signer
+                let chain = self.watch_only_wallet()?;
+                
+                Arc::new(RemoteSignerWallet::new(signer, &self.config.dns, Arc::new(chain))?)
+            }
+            #[cfg(feature = "http")]
+            "custody" => {
+                let custody = self.config.wallet.custody.as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Custody configuration required for custody wallet"))?;
+                
+                // UTXOs and broadcast only; keys and balances stay with the provider
+                let chain = self.watch_only_wallet()?;
+                
+                Arc::new(CustodyWallet::from_config(custody, &self.config.dns, Arc::new(chain))?)
+            }
+            #[cfg(not(feature = "http"))]
+            "remote" | "custody" => {
+                return Err(anyhow::anyhow!("Remote signer and custody wallets were left out of this build (http feature)"));
+            }
             "simple" | _ => {
-                // Create simple wallet
-                let simple_wallet = SimpleWallet::new(
-                    self.config.wallet.private_key.as_deref(),
-                    self.config.bitcoin.network,
-                )?;
+                // Create simple wallet, from an imported descriptor if there is one
+                let mut simple_wallet = match &self.config.wallet.descriptor {
+                    Some(descriptor) => SimpleWallet::from_descriptor(descriptor, self.config.bitcoin.network)?,
+                    None => SimpleWallet::new(
+                        self.config.wallet.private_key.as_deref(),
+                        self.config.bitcoin.network,
+                    )?,
+                }
+                .with_gap_limit(self.config.wallet.gap_limit);
+                
+                // A descriptor carries its own derivation path
+                if let (None, Some(path)) = (&self.config.wallet.descriptor, &self.config.wallet.derivation_path) {
+                    simple_wallet = simple_wallet.with_derivation_path(path)?;
+                }
+                
+                // Keep the issued addresses across restarts
+                let simple_wallet = simple_wallet.with_address_store(self.storage.clone()).await?;
                 
                 Arc::new(simple_wallet)
             }
         };
         
+        // Keep track of incoming payments if enabled
+        if self.config.wallet.deposits.enabled {
+            self.deposits = Some(Arc::new(DepositWatcher::open(
+                self.storage.clone(),
+                self.config.bitcoin.network.into(),
+                &self.config.wallet.deposits,
+            )));
+        }
+        
+        // Sweep dust on a schedule if enabled
+        if self.config.wallet.consolidation.scheduled {
+            self.consolidation_task = Some(consolidation::spawn_scheduler(
+                wallet.clone(),
+                self.config.wallet.consolidation.clone(),
+                self.deposits.clone(),
+            ));
+        }
+        
         self.wallet = Some(wallet);
         
         info!("Wallet initialized successfully");
@@ -152,15 +579,44 @@ impl DarkSwap {
         Ok(())
     }
 
+    /// Create the watch-only wallet a remote signer or custody wallet reads the chain through
+    #[cfg(feature = "http")]
+    fn watch_only_wallet(&self) -> Result<WatchOnlyWallet> {
+        let descriptor = self.config.wallet.watch_descriptor.as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Watch descriptor (wallet.watch_descriptor) required for remote and custody wallets"))?;
+        let esplora_url = self.config.bitcoin.esplora_url.as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Chain backend (bitcoin.esplora_url) required for remote and custody wallets"))?;
+        
+        // Reconnects and retries like the refund sweeper's backend
+        let connector: Arc<dyn ChainConnector<esplora::EsploraChain>> =
+            Arc::new(esplora::EsploraConnector::new(esplora_url, &self.config.dns));
+        let chain = ResilientChain::new(connector, self.config.bitcoin.backend.clone())
+            .with_events(self.event_channel.0.clone());
+        
+        WatchOnlyWallet::new(
+            descriptor,
+            self.config.wallet.gap_limit,
+            self.config.bitcoin.network.into(),
+            chain,
+        )
+    }
+
     /// Initialize P2P network
     async fn init_network(&mut self) -> Result<()> {
         // Create P2P network
-        let network = P2PNetwork::new(&self.config, self.event_channel.0.clone())?;
+        let network = P2PNetwork::new(&self.config, self.event_channel.0.clone())?
+            .with_clock(self.clock.clone());
         let network = Arc::new(RwLock::new(network));
         
         // Start P2P network
         network.write().await.start().await?;
         
+        // Keep warm connections to frequent counterparties
+        if self.config.p2p.pool_size > 0 {
+            let interval = std::time::Duration::from_secs((self.config.p2p.pool_idle_ttl / 4).max(1));
+            self.pool_task = Some(p2p::connection_pool::spawn_connection_pool(network.clone(), interval));
+        }
+        
         self.network = Some(network);
         
         info!("P2P network initialized successfully");
@@ -168,6 +624,31 @@ impl DarkSwap {
         Ok(())
     }
 
+    /// Initialize audit mode
+    async fn init_audit(&mut self) -> Result<()> {
+        if !self.config.audit.enabled {
+            return Ok(());
+        }
+        
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        let audit = AuditLog::open(self.storage.clone(), self.clock.clone())?
+            .with_max_records(self.config.audit.max_records);
+        
+        // The order and trade topics are joined by the orderbook and the trade manager
+        let mut network = network.write().await;
+        for topic in &self.config.audit.topics {
+            network.subscribe(topic).await?;
+        }
+        
+        info!("Audit mode enabled, {} records so far", audit.len());
+        
+        self.audit = Some(Arc::new(audit));
+        
+        Ok(())
+    }
+
     /// Initialize orderbook
     async fn init_orderbook(&mut self) -> Result<()> {
         // Get network and wallet
@@ -178,24 +659,192 @@ impl DarkSwap {
             .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
         
         // Create orderbook
-        let orderbook = Orderbook::new(
+        let mut orderbook = Orderbook::new(
             network.clone(),
             wallet.clone(),
             self.event_channel.0.clone(),
-        );
+        )
+        .with_bitcoin_network(self.config.bitcoin.network.into())
+        .with_clock(self.clock.clone())
+        .with_verification_pool(self.verification_pool.clone())
+        .with_match_hooks(self.match_hooks.clone());
+        
+        #[cfg(not(feature = "http"))]
+        if self.config.orderbook.cosigner.is_some() {
+            return Err(anyhow::anyhow!("The order co-signer was left out of this build (http feature)"));
+        }
+        #[cfg(feature = "http")]
+        if let Some(config) = &self.config.orderbook.cosigner {
+            let client = HttpCoSigner::new(config, &self.config.dns)?;
+            let keys = orderbook::cosign::parse_keys(&config.keys)?;
+            let cosigner = OrderCosigner::new(Arc::new(client), wallet.clone(), config.required_signatures, keys)?
+                .with_storage(self.storage.clone())?;
+            let cosigner = Arc::new(cosigner);
+            orderbook = orderbook.with_cosigner(cosigner.clone());
+            self.cosigner = Some(cosigner);
+        }
+        
+        if let Some(fees) = &self.config.trade.fees {
+            fees.validate(self.config.bitcoin.network.into())?;
+            orderbook = orderbook.with_fees(fees.clone());
+        }
+        
+        for referrer in &self.config.trade.referrers {
+            referrer.validate(self.config.bitcoin.network.into())?;
+        }
+        orderbook = orderbook.with_referrers(self.config.trade.referrers.clone());
+        
+        // Announce own orders with a key apart from the network identity
+        if let Some(announcement) = &self.config.orderbook.announcement {
+            let signer: Arc<dyn AnnouncementSigner> = match (&announcement.key, &announcement.remote) {
+                (Some(key), None) => Arc::new(LocalAnnouncementSigner::new(reputation::decode_identity_key(key)?)),
+                #[cfg(feature = "http")]
+                (None, Some(remote)) => Arc::new(RemoteAnnouncementSigner::connect(remote, &self.config.dns).await?),
+                #[cfg(not(feature = "http"))]
+                (None, Some(_)) => anyhow::bail!("The remote announcement signer was left out of this build (http feature)"),
+                _ => anyhow::bail!("Set exactly one of the announcement key and the remote announcement signer"),
+            };
+            let delegation = Delegation::issue(network.read().await.keypair(), &signer.public_key())?;
+            orderbook = orderbook.with_announcement_signer(signer, delegation);
+        }
+        
+        orderbook = orderbook.with_announcement_storage(self.storage.clone())?;
+        
+        let retention = &self.config.orderbook.retention;
+        let archive = retention.archive.then(|| self.storage.clone());
+        orderbook = orderbook.with_retention(retention.clone(), archive);
+        orderbook = orderbook.with_digest(self.config.orderbook.digest.clone());
+        
+        let gossip_cache = &self.config.orderbook.gossip_cache;
+        let cache = if gossip_cache.enabled {
+            Some(orderbook::gossip_cache::GossipCache::open(self.storage.clone(), gossip_cache.clone(), self.clock.now())?)
+        } else {
+            None
+        };
+        orderbook = orderbook.with_gossip_cache(gossip_cache.clone(), cache);
+        
+        if let Some(audit) = &self.audit {
+            orderbook = orderbook.with_audit(audit.clone());
+        }
         
         let orderbook = Arc::new(orderbook);
         
         // Start orderbook
         orderbook.start().await?;
         
-        self.orderbook = Some(orderbook);
+        self.orderbook = Some(orderbook.clone());
+        
+        // Join the configured private groups
+        let groups = Arc::new(GroupManager::new(network.clone(), self.event_channel.0.clone()).with_clock(self.clock.clone()));
+        for config in &self.config.orderbook.groups {
+            let group = PrivateGroup::from_config(config)
+                .with_context(|| format!("Invalid private group {}", config.name))?;
+            groups.join(group).await?;
+        }
+        self.groups = Some(groups);
+        
+        // Trade large orders without showing their terms
+        let dark_pool = Arc::new(DarkPool::new(network.clone(), self.event_channel.0.clone()).with_clock(self.clock.clone()));
+        dark_pool.init().await?;
+        self.dark_pool = Some(dark_pool);
+        
+        // Emit events for followed makers and pairs
+        self.watchlist_task = Some(orderbook::watchlist::spawn_watchlist(
+            self.watchlist.clone(),
+            orderbook.clone(),
+            self.event_bus.subscribe_internal(),
+            self.event_channel.0.clone(),
+        ));
+        
+        // Stop forwarding orders to light peers once they are gone
+        self.interest_task = Some(orderbook::interest::spawn_interest_cleanup(
+            orderbook.clone(),
+            self.event_bus.subscribe_internal(),
+        ));
+        
+        // Keep the book from growing with filled, canceled and expired orders
+        self.compaction_task = Some(orderbook::retention::spawn_compaction(
+            orderbook.clone(),
+            std::time::Duration::from_secs(self.config.orderbook.retention.compaction_interval.max(1)),
+        ));
+        
+        // Let peers catch up on missed orders from a digest instead of re-gossiping them
+        if self.config.orderbook.digest.interval > 0 {
+            self.digest_task = Some(orderbook::digest::spawn_digest_publisher(
+                orderbook.clone(),
+                std::time::Duration::from_secs(self.config.orderbook.digest.interval),
+            ));
+        }
+        
+        // Post scheduled orders once their conditions are met
+        self.scheduler_task = Some(orderbook::scheduler::spawn_scheduler(
+            self.scheduler.clone(),
+            orderbook,
+            self.order_store.clone(),
+            std::time::Duration::from_secs(self.config.orderbook.scheduler_interval.max(1)),
+        ));
+        
+        // Re-announce the open orders this identity made before a restart or failover
+        match self.restore_own_orders().await {
+            Ok(0) => {}
+            Ok(count) => info!("Re-announced {} persisted orders", count),
+            Err(e) => warn!("Failed to restore persisted orders: {}", e),
+        }
         
         info!("Orderbook initialized successfully");
         
         Ok(())
     }
 
+    /// Re-announce the persisted open orders made by this node's identity
+    ///
+    /// Orders signed by another identity, expired orders and orders already in the
+    /// book are skipped. Returns the number of orders announced.
+    pub async fn restore_own_orders(&self) -> Result<usize> {
+        let file = match &self.order_store {
+            Some(file) => file,
+            None => return Ok(0),
+        };
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        let local_peer_id = network.read().await.local_peer_id().to_string();
+        
+        let mut restored = 0;
+        for signed_order in orderbook::own_orders::load(file)? {
+            if signed_order.order.maker != local_peer_id {
+                debug!("Skipping persisted order {} of another identity", signed_order.order.id);
+                continue;
+            }
+            
+            let order_id = signed_order.order.id.clone();
+            match orderbook.submit_signed_order(signed_order).await {
+                Ok(_) => restored += 1,
+                Err(e) => debug!("Skipping persisted order {}: {}", order_id, e),
+            }
+        }
+        
+        Ok(restored)
+    }
+
+    /// Persist this node's open orders, if an order store is configured
+    ///
+    /// Returns the number of orders saved.
+    pub async fn save_own_orders(&self) -> Result<usize> {
+        let file = match &self.order_store {
+            Some(file) => file,
+            None => return Ok(0),
+        };
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let orders = orderbook.own_signed_orders().await;
+        orderbook::own_orders::save(file, &orders)?;
+        
+        Ok(orders.len())
+    }
+
     /// Initialize trade manager
     async fn init_trade_manager(&mut self) -> Result<()> {
         // Get network and wallet
@@ -209,30 +858,166 @@ impl DarkSwap {
         // Note: This is a simplified implementation for now
         // In a real implementation, we would need to create proper implementations
         // of the Wallet, RunesExecutor, and AlkanesExecutor traits
-        let wallet_trait = Arc::new(DummyWallet {});
+        let descriptor = match wallet.export_descriptor(false).await {
+            Ok(descriptor) => Some(descriptor.parse().map_err(|e| anyhow::anyhow!("Invalid wallet descriptor: {}", e))?),
+            Err(_) => None,
+        };
+        let wallet_trait = Arc::new(DummyWallet {
+            network: self.config.bitcoin.network.into(),
+            descriptor,
+            gap_limit: self.config.wallet.gap_limit,
+        });
         let runes_executor = Arc::new(DummyRunesExecutor {});
         let alkanes_executor = Arc::new(DummyAlkanesExecutor {});
         
-        let trade_manager = TradeManager::new(
+        let mut trade_manager = TradeManager::new(
             network.clone(),
             self.event_channel.0.clone(),
             wallet_trait,
             runes_executor,
             alkanes_executor,
-        );
+        )
+        .with_bitcoin_network(self.config.bitcoin.network.into())
+        .with_message_window(self.config.trade.message_window)
+        .with_auto_accept(self.config.trade.auto_accept)
+        .with_limits(self.config.trade.limits.clone())
+        .with_payout_proof_required(self.config.trade.require_payout_proof)
+        .with_verification_pool(self.verification_pool.clone())
+        .with_wal(Arc::new(TradeWal::open(self.storage.clone(), self.clock.clone())?))
+        .with_payout_storage(self.storage.clone())?
+        .with_trade_storage(self.storage.clone())?
+        .with_trade_timeout(self.config.trade.trade_timeout)
+        .with_clock(self.clock.clone());
+        
+        // Keep bitcoin back for the fees of in-flight trades
+        let fee_reserve = &self.config.wallet.fee_reserve;
+        if fee_reserve.minimum > 0 || fee_reserve.per_trade > 0 {
+            let mut guard = FeeReserveGuard::new(fee_reserve.clone(), wallet.clone(), self.event_channel.0.clone());
+            if let Some(orderbook) = &self.orderbook {
+                guard = guard.with_orderbook(orderbook.clone());
+            }
+            let guard = Arc::new(guard);
+            trade_manager = trade_manager.with_fee_reserve(guard.clone());
+            self.fee_guard = Some(guard);
+        }
+        
+        // Only open trades on own orders that were co-signed
+        if let Some(cosigner) = &self.cosigner {
+            trade_manager = trade_manager.with_cosigner(cosigner.clone());
+        }
+        
+        if let Some(audit) = &self.audit {
+            trade_manager = trade_manager.with_audit(audit.clone());
+        }
+        
+        if let Some(analytics) = &self.analytics {
+            trade_manager = trade_manager.with_analytics(analytics.clone());
+        }
+        
+        let outbox = &self.config.trade.outbox;
+        if outbox.enabled {
+            trade_manager = trade_manager.with_outbox(Arc::new(Outbox::open(self.storage.clone(), outbox.clone())));
+        }
         
         let trade_manager = Arc::new(trade_manager);
         
         // Start trade manager
         trade_manager.init().await?;
         
+        // Finish what a crash interrupted before taking new messages
+        let recovery = trade_manager.recover().await?;
+        if recovery != WalRecovery::default() {
+            info!("Recovered trades from the trade log: {:?}", recovery);
+        }
+        
+        // Send trade messages again until the counterparty acknowledges them
+        if outbox.enabled {
+            self.outbox_task = Some(trade::outbox::spawn_outbox_retrier(
+                trade_manager.clone(),
+                self.event_bus.subscribe_internal(),
+                std::time::Duration::from_secs(outbox.retry_interval.max(1)),
+            ));
+        }
+        
+        // Expire trades not signed within the trade timeout, and time out queued proposals
+        let sweep_interval = 60.min(self.config.trade.limits.queue_timeout.max(1));
+        self.trade_expiry_task = Some(trade::spawn_trade_expiry(
+            trade_manager.clone(),
+            std::time::Duration::from_secs(sweep_interval),
+        ));
+        
         self.trade_manager = Some(trade_manager);
         
+        // Answer and collect quotes for block trades
+        let rfq = Arc::new(RfqManager::new(network.clone(), self.event_channel.0.clone()).with_clock(self.clock.clone()));
+        rfq.init().await?;
+        self.rfq = Some(rfq);
+        
         info!("Trade manager initialized successfully");
         
         Ok(())
     }
 
+    /// Route inbound messages to the orderbook, trade and RFQ handlers
+    async fn init_dispatch(&mut self) -> Result<()> {
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        let inbound = network.write().await.take_inbound()
+            .ok_or_else(|| anyhow::anyhow!("Inbound messages are already dispatched"))?;
+        
+        let mut dispatcher = dispatch::Dispatcher::new();
+        if let Some(orderbook) = &self.orderbook {
+            dispatcher = dispatcher.with_orderbook(orderbook.clone());
+        }
+        if let Some(audit) = &self.audit {
+            dispatcher = dispatcher.with_audit(audit.clone(), self.config.audit.topics.clone());
+        }
+        if let Some(trade_manager) = &self.trade_manager {
+            dispatcher = dispatcher.with_trades(trade_manager.clone());
+        }
+        if let Some(rfq) = &self.rfq {
+            dispatcher = dispatcher.with_rfq(rfq.clone());
+        }
+        if let Some(groups) = &self.groups {
+            dispatcher = dispatcher.with_groups(groups.clone());
+        }
+        if let Some(dark_pool) = &self.dark_pool {
+            dispatcher = dispatcher.with_dark_pool(dark_pool.clone());
+        }
+        
+        self.dispatch_task = Some(dispatch::spawn_dispatcher(dispatcher, inbound));
+        
+        Ok(())
+    }
+
+    /// Initialize iceberg orders
+    async fn init_icebergs(&mut self) -> Result<()> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        // Subscribe before resuming, so no fill of a re-posted slice is missed
+        self.iceberg_task = Some(orderbook::iceberg::spawn_iceberg_manager(
+            self.icebergs.clone(),
+            orderbook.clone(),
+            trade_manager.clone(),
+            self.event_bus.subscribe_internal(),
+            self.order_store.clone(),
+        ));
+        
+        // Re-post the slices that are gone since the last run
+        match orderbook::iceberg::resume(&self.icebergs, orderbook).await {
+            Ok(0) => {}
+            Ok(count) => info!("Re-posted {} iceberg order slices", count),
+            Err(e) => warn!("Failed to resume iceberg orders: {}", e),
+        }
+        
+        Ok(())
+    }
+
     /// Initialize performance profiler and optimizer
     async fn init_performance(&mut self) -> Result<()> {
         // Create performance profiler
@@ -260,8 +1045,28 @@ impl DarkSwap {
 
     /// Stop DarkSwap
     pub async fn stop(&mut self) -> Result<()> {
+        self.started_at = None;
+        
+        // Keep the open orders for the next start
+        if self.orderbook.is_some() {
+            if let Err(e) = self.save_own_orders().await {
+                warn!("Failed to persist orders: {}", e);
+            }
+        }
+        
         // Stop P2P network
         if let Some(network) = &self.network {
+            // Keep reputation scores with the persisted peers
+            let reputation = self.reputation.read().await;
+            let network_guard = network.read().await;
+            for peer in network_guard.known_peers().await {
+                if let Some(peer_reputation) = reputation.get_reputation(&peer.peer_id) {
+                    network_guard.set_peer_reputation(&peer.peer_id, peer_reputation.score()).await;
+                }
+            }
+            drop(network_guard);
+            drop(reputation);
+            
             network.write().await.stop().await?;
         }
         
@@ -278,18 +1083,196 @@ impl DarkSwap {
         // Clear performance profiler and optimizer
         self.performance_profiler = None;
         self.performance_optimizer = None;
+        if let Some(task) = self.consolidation_task.take() {
+            task.abort();
+        }
+        #[cfg(feature = "federation")]
+        if let Some(task) = self.federation_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.scheduler_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.iceberg_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.refund_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.outbox_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.trade_expiry_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.balance_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.deposit_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.pool_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.watchlist_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.interest_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.compaction_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.digest_task.take() {
+            task.abort();
+        }
+        if let Some(task) = self.dispatch_task.take() {
+            task.abort();
+        }
+        #[cfg(feature = "federation")]
+        {
+            self.federation = None;
+        }
         self.wallet = None;
         self.orderbook = None;
         self.trade_manager = None;
+        self.fee_guard = None;
+        self.cosigner = None;
+        self.rfq = None;
+        self.groups = None;
+        self.dark_pool = None;
         
         info!("DarkSwap stopped successfully");
         
         Ok(())
     }
 
+    /// Get the Bitcoin network DarkSwap runs on
+    pub fn bitcoin_network(&self) -> BitcoinNetwork {
+        self.config.bitcoin.network
+    }
+
+    /// Check if DarkSwap has been started and not stopped since
+    pub fn is_running(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// Check if the P2P network is listening for peers
+    pub async fn is_listening(&self) -> bool {
+        match &self.network {
+            Some(network) => network.read().await.is_listening(),
+            None => false,
+        }
+    }
+
+    /// Switch to another Bitcoin network
+    ///
+    /// Every component is rebuilt from the configuration of the new network (see
+    /// `Config::for_network`), and restarted if DarkSwap was running. Storage is
+    /// reopened if either network's profile sets one. Event subscriptions are
+    /// kept and receive `Event::NetworkSwitched`. Switching is refused while
+    /// trades are in flight, as they could not complete on the new network. If
+    /// the new network fails to start, the previous one is restored and
+    /// restarted.
+    pub async fn switch_network(&mut self, network: BitcoinNetwork) -> Result<()> {
+        if network == self.config.bitcoin.network {
+            return Ok(());
+        }
+        
+        if let Some(trade_manager) = &self.trade_manager {
+            let in_flight = trade_manager.in_flight_trades().await;
+            if in_flight > 0 {
+                return Err(anyhow::anyhow!("Cannot switch networks with {} trades in flight", in_flight));
+            }
+        }
+        
+        // Build the new context first, so an invalid profile leaves this one untouched
+        let profiles = &self.base_config.networks;
+        let own_storage = [self.config.bitcoin.network, network].iter()
+            .any(|bitcoin_network| profiles.get(bitcoin_network).map_or(false, |profile| profile.storage.is_some()));
+        let config = self.base_config.for_network(network);
+        let storage = if own_storage {
+            storage::open(&config.storage)?
+        } else {
+            self.storage.clone()
+        };
+        let mut context = Self::with_storage(config, storage)
+            .with_context(|| format!("Failed to configure DarkSwap for {}", network.to_string()))?;
+        context.base_config = self.base_config.clone();
+        context.clock = self.clock.clone();
+        context.match_hooks = self.match_hooks.clone();
+        
+        let running = self.wallet.is_some();
+        if running {
+            self.stop().await?;
+        }
+        
+        // Keep the event bus and its subscribers
+        context.event_channel = (self.event_channel.0.clone(), self.event_channel.1.take());
+        context.event_bus = self.event_bus.clone();
+        std::mem::swap(&mut context.events, &mut self.events);
+        
+        // The trading session goes on
+        context.analytics = self.analytics.clone();
+        
+        let previous = std::mem::replace(self, context);
+        
+        if running {
+            if let Err(e) = self.start().await {
+                // Go back to the network that was running, with the event subscriptions
+                if let Err(stop_error) = self.stop().await {
+                    warn!("Failed to stop DarkSwap on {}: {}", network.to_string(), stop_error);
+                }
+                let mut failed = std::mem::replace(self, previous);
+                self.event_channel.1 = failed.event_channel.1.take();
+                std::mem::swap(&mut self.events, &mut failed.events);
+                
+                if let Err(restart_error) = self.start().await {
+                    warn!("Failed to restart DarkSwap on {}: {}", self.config.bitcoin.network.to_string(), restart_error);
+                }
+                return Err(e.context(format!("Failed to start DarkSwap on {}", network.to_string())));
+            }
+        }
+        
+        if let Err(e) = self.event_channel.0.send(Event::NetworkSwitched(network)).await {
+            warn!("Failed to send network switch event: {}", e);
+        }
+        
+        info!("Switched to {}", network.to_string());
+        
+        Ok(())
+    }
+
     /// Wait for the next event
+    ///
+    /// This subscription drops its oldest events when it lags, whatever the overflow
+    /// policy; use `subscribe_to_events` for a subscription that follows the policy.
     pub async fn next_event(&mut self) -> Option<Event> {
-        self.event_channel.1.recv().await
+        self.events.recv().await
+    }
+
+    /// Get the number of events dropped because subscribers lagged
+    pub fn dropped_events(&self) -> u64 {
+        self.event_bus.dropped_events()
+    }
+
+    /// Get the analytics of the trading session: times to match and confirm,
+    /// handshake failures by reason and the share of relayed connections
+    #[cfg(feature = "analytics")]
+    pub fn get_session_stats(&self) -> Result<analytics::SessionStats> {
+        let analytics = self.analytics.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Session analytics not initialized"))?;
+        
+        Ok(analytics.stats())
+    }
+
+    /// Subscribe to the analytics events of the trading session
+    #[cfg(feature = "analytics")]
+    pub fn subscribe_to_analytics(&self) -> Result<tokio::sync::broadcast::Receiver<analytics::AnalyticsEvent>> {
+        let analytics = self.analytics.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Session analytics not initialized"))?;
+        
+        Ok(analytics.subscribe())
     }
 
     /// Create an order
@@ -305,72 +1288,986 @@ impl DarkSwap {
         let orderbook = self.orderbook.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
         
-        orderbook.create_order(base_asset, quote_asset, side, amount, price, expiry).await
+        self.check_fee_reserve(&base_asset, &quote_asset, side, amount, price).await?;
+        let order = orderbook.create_order(base_asset, quote_asset, side, amount, price, expiry).await?;
+        
+        if let Err(e) = self.save_own_orders().await {
+            warn!("Failed to persist orders: {}", e);
+        }
+        
+        Ok(order)
     }
 
-    /// Cancel an order
-    pub async fn cancel_order(&self, order_id: &OrderId) -> Result<()> {
+    /// Create an order tagged with the referral code of the front-end it came from
+    pub async fn create_referred_order(
+        &self,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: rust_decimal::Decimal,
+        price: rust_decimal::Decimal,
+        expiry: Option<u64>,
+        referral_code: Option<String>,
+    ) -> Result<Order> {
         let orderbook = self.orderbook.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
         
-        orderbook.cancel_order(order_id).await
+        self.check_fee_reserve(&base_asset, &quote_asset, side, amount, price).await?;
+        let order = orderbook
+            .create_referred_order(base_asset, quote_asset, side, amount, price, expiry, referral_code)
+            .await?;
+        
+        if let Err(e) = self.save_own_orders().await {
+            warn!("Failed to persist orders: {}", e);
+        }
+        
+        Ok(order)
     }
 
-    /// Get an order by ID
-    pub async fn get_order(&self, order_id: &OrderId) -> Result<Order> {
+    /// Get the co-signed PSBT of an own order
+    pub async fn get_cosigned_psbt(&self, order_id: &OrderId) -> Result<Option<String>> {
         let orderbook = self.orderbook.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
         
-        orderbook.get_order(order_id).await
+        Ok(orderbook.cosigned_psbt(order_id).await)
     }
 
-    /// Get orders for a pair
-    pub async fn get_orders(&self, base_asset: &Asset, quote_asset: &Asset) -> Result<Vec<Order>> {
+    /// Cancel an order
+    pub async fn cancel_order(&self, order_id: &OrderId) -> Result<()> {
         let orderbook = self.orderbook.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
         
-        orderbook.get_orders(base_asset, quote_asset).await
+        orderbook.cancel_order(order_id).await?;
+        
+        if let Err(e) = self.save_own_orders().await {
+            warn!("Failed to persist orders: {}", e);
+        }
+        
+        Ok(())
     }
 
-    /// Get all orders
-    pub async fn get_all_orders(&self) -> Result<Vec<Order>> {
+    /// Export this node's open orders, to move them to another node of the same maker
+    pub async fn export_orders(&self) -> Result<SignedOrderBundle> {
         let orderbook = self.orderbook.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
         
-        orderbook.get_all_orders().await
+        orderbook.export_orders().await
     }
 
-    /// Get best bid and ask for a pair
-    pub async fn get_best_bid_ask(
-        &self,
-        base_asset: &Asset,
+    /// Import orders exported by another node of the same maker
+    ///
+    /// The orders are canceled under their old IDs and announced again by this
+    /// node, so peers drop the other node's announcements.
+    pub async fn import_orders(&self, signed_bundle: &SignedOrderBundle) -> Result<OrderImport> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let import = orderbook.import_orders(signed_bundle).await?;
+        
+        if let Err(e) = self.save_own_orders().await {
+            warn!("Failed to persist orders: {}", e);
+        }
+        
+        Ok(import)
+    }
+
+    /// Schedule an order to be posted once its conditions are met
+    ///
+    /// The order is posted after `activate_at` and, with a trigger, once the
+    /// reference price of its pair reaches the trigger price. It is dropped if this
+    /// has not happened by `expires_at`. Until then nothing is broadcast.
+    pub async fn schedule_order(
+        &self,
+        order: OrderTemplate,
+        activate_at: Option<u64>,
+        trigger: Option<PriceTrigger>,
+        expires_at: Option<u64>,
+    ) -> Result<ScheduledOrder> {
+        let scheduled = ScheduledOrder::new(order, activate_at, trigger, expires_at, self.clock.now())?;
+        
+        let mut scheduler = self.scheduler.write().await;
+        scheduler.add(scheduled.clone());
+        scheduler.save()?;
+        
+        Ok(scheduled)
+    }
+
+    /// Cancel a scheduled order that has not been posted yet
+    pub async fn cancel_scheduled_order(&self, id: &ScheduleId) -> Result<ScheduledOrder> {
+        let mut scheduler = self.scheduler.write().await;
+        let scheduled = scheduler.cancel(id)?;
+        scheduler.save()?;
+        
+        Ok(scheduled)
+    }
+
+    /// Get all scheduled orders, including the posted, expired and canceled ones
+    pub async fn get_scheduled_orders(&self) -> Vec<ScheduledOrder> {
+        self.scheduler.read().await.list()
+    }
+
+    /// Create an iceberg order
+    ///
+    /// Only a slice of `slice_size` of the total amount is posted and gossiped at a
+    /// time; the next slice is posted once the visible one has filled.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_iceberg_order(
+        &self,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        total_amount: rust_decimal::Decimal,
+        slice_size: rust_decimal::Decimal,
+        price: rust_decimal::Decimal,
+        expiry: Option<u64>,
+    ) -> Result<IcebergOrder> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        self.check_fee_reserve(&base_asset, &quote_asset, side, total_amount, price).await?;
+        let iceberg = IcebergOrder::new(base_asset, quote_asset, side, total_amount, slice_size, price, expiry, self.clock.now())?;
+        let iceberg = orderbook::iceberg::post(&self.icebergs, orderbook, iceberg).await?;
+        
+        if let Err(e) = self.save_own_orders().await {
+            warn!("Failed to persist orders: {}", e);
+        }
+        
+        Ok(iceberg)
+    }
+
+    /// Cancel an iceberg order, withdrawing its visible slice
+    pub async fn cancel_iceberg_order(&self, id: &IcebergId) -> Result<IcebergOrder> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let iceberg = orderbook::iceberg::cancel(&self.icebergs, orderbook, id).await?;
+        
+        if let Err(e) = self.save_own_orders().await {
+            warn!("Failed to persist orders: {}", e);
+        }
+        
+        Ok(iceberg)
+    }
+
+    /// Get all iceberg orders, including the filled, expired and canceled ones
+    pub async fn get_iceberg_orders(&self) -> Vec<IcebergOrder> {
+        self.icebergs.read().await.list()
+    }
+
+    /// Follow a maker, emitting `Event::NewOrderFromWatchedMaker` for its new orders
+    pub async fn watch_maker(&self, peer_id: &str) -> Result<()> {
+        let mut watchlist = self.watchlist.write().await;
+        if watchlist.watch_maker(peer_id) {
+            watchlist.save()?;
+        }
+        
+        Ok(())
+    }
+
+    /// Stop following a maker
+    pub async fn unwatch_maker(&self, peer_id: &str) -> Result<()> {
+        let mut watchlist = self.watchlist.write().await;
+        if !watchlist.unwatch_maker(peer_id) {
+            return Err(anyhow::anyhow!("Maker {} is not followed", peer_id));
+        }
+        
+        watchlist.save()
+    }
+
+    /// Follow a pair, emitting `Event::PriceCrossedThreshold` when its price crosses a threshold
+    ///
+    /// Following a pair again replaces its thresholds.
+    pub async fn watch_pair(&self, base_asset: Asset, quote_asset: Asset, thresholds: Vec<rust_decimal::Decimal>) -> Result<()> {
+        let mut watchlist = self.watchlist.write().await;
+        watchlist.watch_pair(base_asset, quote_asset, thresholds);
+        
+        watchlist.save()
+    }
+
+    /// Stop following a pair
+    pub async fn unwatch_pair(&self, base_asset: &Asset, quote_asset: &Asset) -> Result<()> {
+        let mut watchlist = self.watchlist.write().await;
+        if !watchlist.unwatch_pair(base_asset, quote_asset) {
+            return Err(anyhow::anyhow!("Pair {}/{} is not followed", base_asset, quote_asset));
+        }
+        
+        watchlist.save()
+    }
+
+    /// Get the followed makers and pairs
+    pub async fn get_watchlist(&self) -> (Vec<String>, Vec<WatchedPair>) {
+        let watchlist = self.watchlist.read().await;
+        
+        (watchlist.makers(), watchlist.pairs().to_vec())
+    }
+
+    /// Ask full peers to forward only orders of the given pairs and makers
+    ///
+    /// For light clients that do not follow the order topic. Returns the number
+    /// of peers the interest filter was sent to.
+    pub async fn set_order_interest(
+        &self,
+        peers: &[libp2p::PeerId],
+        pairs: &[(Asset, Asset)],
+        makers: &[String],
+    ) -> Result<usize> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let filter = orderbook::interest::interest_filter(pairs, makers, 0.001);
+        orderbook.announce_interest(peers, &filter).await
+    }
+
+    /// Ask `peers` for the orders of a pair they received in the last `minutes` minutes
+    ///
+    /// For peers that just joined or reconnected. Peers answer from their gossip
+    /// cache on the request's response channel. Returns the number of peers the
+    /// request was sent to.
+    pub async fn backfill_orders(
+        &self,
+        peers: &[libp2p::PeerId],
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        minutes: u64,
+    ) -> Result<usize> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let since = self.clock.now().saturating_sub(minutes.saturating_mul(60));
+        let mut reached = 0;
+        for peer in peers {
+            match orderbook.request_backfill(peer, base_asset, quote_asset, since).await {
+                Ok(()) => reached += 1,
+                Err(e) => warn!("Failed to request backfill from {}: {}", peer, e),
+            }
+        }
+        
+        Ok(reached)
+    }
+
+    /// Register the presigned refund transaction of an escrowed trade
+    ///
+    /// The refund is broadcast by the refund sweeper once its lock time has passed,
+    /// unless the escrow has been spent by then.
+    pub async fn register_refund_path(&self, trade_id: &TradeId, refund_tx: &str) -> Result<RefundPath> {
+        let refund = RefundPath::new(trade_id.clone(), refund_tx)?;
+        
+        let mut refunds = self.refunds.write().await;
+        refunds.add(refund.clone())?;
+        refunds.save()?;
+        
+        Ok(refund)
+    }
+
+    /// Get the refund paths of escrowed trades, including the broadcast and spent ones
+    pub async fn get_refund_paths(&self) -> Vec<RefundPath> {
+        self.refunds.read().await.list()
+    }
+
+    /// Start broadcasting refunds as they mature, replacing a running sweeper
+    pub fn start_refund_sweeper(&mut self, chain: Arc<dyn RefundChain>) {
+        if let Some(task) = self.refund_task.take() {
+            task.abort();
+        }
+        
+        self.refund_task = Some(trade::refund::spawn_refund_sweeper(
+            self.refunds.clone(),
+            chain,
+            self.deposits.clone(),
+            self.event_channel.0.clone(),
+            self.clock.clone(),
+            std::time::Duration::from_secs(self.config.trade.refund_sweep_interval.max(1)),
+        ));
+    }
+
+    /// Connect to the configured chain backend and sweep refunds through it
+    fn init_chain(&mut self) {
+        #[cfg(feature = "http")]
+        if let Some(url) = &self.config.bitcoin.esplora_url {
+            let connector = Arc::new(esplora::EsploraConnector::new(url, &self.config.dns));
+            let chain = self.resilient_chain(connector);
+            self.start_refund_sweeper(chain.clone());
+            self.chain = Some(chain);
+            return;
+        }
+        
+        warn!("No chain backend configured (bitcoin.esplora_url); matured refunds are not broadcast");
+    }
+
+    /// Wrap a chain backend so it reconnects and retries as configured
+    ///
+    /// Disconnects, reconnects and circuit breaker changes are emitted as
+    /// `Event::ChainBackend`. The result can be passed to `start_refund_sweeper`.
+    pub fn resilient_chain(&self, connector: Arc<dyn ChainConnector>) -> Arc<ResilientChain> {
+        Arc::new(
+            ResilientChain::new(connector, self.config.bitcoin.backend.clone())
+                .with_events(self.event_channel.0.clone()),
+        )
+    }
+
+    /// Keep a BIP-322 proof that we control a payout address
+    ///
+    /// The proof is sent along whenever the address is, for counterparties that
+    /// only pay proven addresses. It must sign the message from
+    /// `trade::settlement::payout_proof_message`.
+    pub async fn add_payout_proof(&self, address: &str, proof: &str) -> Result<()> {
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        trade_manager.add_payout_proof(address, proof).await
+    }
+
+    /// Check that a payout address belongs to the node's bitcoin network
+    pub fn validate_payout_address(&self, address: &str) -> Result<()> {
+        trade::settlement::parse_payout_address(address, self.config.bitcoin.network.into())?;
+        Ok(())
+    }
+
+    /// Set or clear the payout address of an own order
+    ///
+    /// Trades on the order pay the proceeds to this address instead of the wallet's
+    /// default address. The address is stored with the node's other state.
+    pub async fn set_order_payout_address(&self, order_id: &OrderId, payout_address: Option<String>) -> Result<()> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+
+        let order = orderbook.get_order(order_id).await?;
+        if order.maker != network.read().await.local_peer_id().to_string() {
+            return Err(anyhow::anyhow!("Order {} is not our own", order_id));
+        }
+
+        trade_manager.set_payout_address(order_id, payout_address).await
+    }
+
+    /// Get an order by ID
+    pub async fn get_order(&self, order_id: &OrderId) -> Result<Order> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        orderbook.get_order(order_id).await
+    }
+
+    /// Get the size of the orderbook and its compaction totals
+    pub async fn get_orderbook_stats(&self) -> Result<BookStats> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        Ok(orderbook.stats().await)
+    }
+
+    /// Compact the terminal orders beyond the retention policy now
+    pub async fn compact_orderbook(&self) -> Result<CompactionReport> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        orderbook.compact().await
+    }
+
+    /// Check if the node runs in audit mode, observing the network without trading
+    pub fn is_audit_node(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Get up to `limit` announcements recorded in audit mode, from sequence number `from` on
+    ///
+    /// Only those received at or after `since` (unix seconds) are returned.
+    pub fn audit_records(&self, since: Option<u64>, from: u64, limit: usize) -> Result<Vec<AuditRecord>> {
+        let audit = self.audit.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Audit mode not enabled"))?;
+        
+        audit.records(since, from, limit)
+    }
+
+    /// Export a page of the announcements recorded in audit mode as JSON Lines, returning how many were written
+    pub fn export_audit<W: std::io::Write>(&self, writer: W, since: Option<u64>, from: u64, limit: usize) -> Result<usize> {
+        let audit = self.audit.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Audit mode not enabled"))?;
+        
+        audit.export(writer, since, from, limit)
+    }
+
+    /// Get orders for a pair
+    pub async fn get_orders(&self, base_asset: &Asset, quote_asset: &Asset) -> Result<Vec<Order>> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        orderbook.get_orders(base_asset, quote_asset).await
+    }
+
+    /// Get all orders
+    pub async fn get_all_orders(&self) -> Result<Vec<Order>> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        orderbook.get_all_orders().await
+    }
+
+    /// Get orders with the estimated latency to their makers, for a pair or for all pairs
+    ///
+    /// Latency is known for makers dialed before, e.g. through
+    /// [`DarkSwap::prewarm_order_makers`].
+    pub async fn get_order_listings(&self, pair: Option<(&Asset, &Asset)>) -> Result<Vec<OrderListing>> {
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        let orders = match pair {
+            Some((base_asset, quote_asset)) => self.get_orders(base_asset, quote_asset).await?,
+            None => self.get_all_orders().await?,
+        };
+        
+        let network = network.read().await;
+        let mut listings = Vec::with_capacity(orders.len());
+        for order in orders {
+            let estimated_latency = network.peer_latency(&order.maker).await;
+            listings.push(OrderListing { order, estimated_latency });
+        }
+        
+        Ok(listings)
+    }
+
+    /// Plan a market order, preferring makers with a low estimated latency at equal prices
+    ///
+    /// Makers measured above `max_latency` milliseconds are skipped. Nothing is
+    /// filled until the trades with the planned makers settle.
+    pub async fn plan_market_order(
+        &self,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        side: OrderSide,
+        amount: rust_decimal::Decimal,
+        limit_price: Option<rust_decimal::Decimal>,
+        max_latency: Option<u64>,
+    ) -> Result<MatchResult> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        let network = network.read().await;
+        let mut latencies = HashMap::new();
+        for order in orderbook.get_orders(base_asset, quote_asset).await? {
+            if latencies.contains_key(&order.maker) {
+                continue;
+            }
+            if let Some(latency) = network.peer_latency(&order.maker).await {
+                latencies.insert(order.maker, latency);
+            }
+        }
+        
+        Ok(orderbook.match_order_by_latency(base_asset, quote_asset, side, amount, limit_price, &latencies, max_latency)?)
+    }
+
+    /// List the pairs with live orders on the network, with order counts and depth
+    pub async fn list_markets(&self) -> Result<Vec<MarketSummary>> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        Ok(orderbook.list_markets())
+    }
+
+    /// Get the open orders of this node and the federated remote daemons, tagged by origin
+    ///
+    /// Without federation this returns the local open orders only.
+    pub async fn get_federated_orders(&self, pair: Option<(&Asset, &Asset)>) -> Result<Vec<FederatedOrder>> {
+        let local = match pair {
+            Some((base_asset, quote_asset)) => self.get_orders(base_asset, quote_asset).await?,
+            None => self.get_all_orders().await?,
+        };
+        
+        #[cfg(feature = "federation")]
+        if let Some(federation) = &self.federation {
+            return Ok(federation.combined(local, pair).await);
+        }
+        
+        Ok(federation::merge_orders(local, Vec::new(), pair, self.clock.now()))
+    }
+
+    /// Get the polling state of the federated remote daemons
+    pub async fn get_federation_status(&self) -> Vec<RemoteStatus> {
+        #[cfg(feature = "federation")]
+        if let Some(federation) = &self.federation {
+            return federation.statuses().await;
+        }
+        
+        Vec::new()
+    }
+
+    /// Get best bid and ask for a pair
+    pub async fn get_best_bid_ask(
+        &self,
+        base_asset: &Asset,
         quote_asset: &Asset,
     ) -> Result<(Option<rust_decimal::Decimal>, Option<rust_decimal::Decimal>)> {
         let orderbook = self.orderbook.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
         
-        orderbook.get_best_bid_ask(base_asset, quote_asset).await
+        orderbook.get_best_bid_ask(base_asset, quote_asset).await
+    }
+
+    /// Submit an order that was signed by its maker on another machine
+    pub async fn submit_signed_order(&self, signed_order: SignedOrder) -> Result<Order> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        orderbook.submit_signed_order(signed_order).await
+    }
+
+    /// Get a signed snapshot of the open orders, for publishing to relays
+    pub async fn get_orderbook_snapshot(&self) -> Result<OrderbookSnapshot> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        orderbook.snapshot().await
+    }
+
+    /// Load a relay-served orderbook snapshot
+    ///
+    /// The snapshot is rejected if it is older than the configured maximum age,
+    /// and orders with an invalid maker signature are skipped.
+    pub async fn load_orderbook_snapshot(&self, snapshot: &OrderbookSnapshot) -> Result<usize> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        orderbook.load_snapshot(snapshot, self.config.orderbook.snapshot_max_age).await
+    }
+
+    /// Pre-dial the makers of orders the user is viewing
+    ///
+    /// Taking one of the orders then goes out over a connection that is already
+    /// up. Dialing happens in the background.
+    pub async fn prewarm_order_makers(&self, order_ids: &[OrderId]) -> Result<()> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        // An order that left the book meanwhile is skipped, not an error
+        let mut makers = Vec::new();
+        for order_id in order_ids {
+            let order = match orderbook.get_order(order_id).await {
+                Ok(order) => order,
+                Err(e) => {
+                    debug!("Not pre-dialing the maker of order {}: {}", order_id, e);
+                    continue;
+                }
+            };
+            match order.maker.parse::<libp2p::PeerId>() {
+                Ok(peer_id) if !makers.iter().any(|(maker, _)| *maker == peer_id) => makers.push((peer_id, order.hints.clone())),
+                Ok(_) => {}
+                Err(e) => warn!("Invalid maker peer ID {}: {}", order.maker, e),
+            }
+        }
+        
+        // Dial without holding the network
+        let dialer = network.read().await.dialer()?;
+        tokio::spawn(async move {
+            let count = makers.len();
+            let warmed = dialer.prewarm(makers).await;
+            debug!("Pre-dialed {} of {} makers", warmed, count);
+        });
+        
+        Ok(())
+    }
+
+    /// Take an order
+    pub async fn take_order(
+        &self,
+        order_id: &OrderId,
+        amount: rust_decimal::Decimal,
+    ) -> Result<Trade> {
+        self.take_order_with_payout(order_id, amount, None).await
+    }
+
+    /// Take an order, having the proceeds paid to `payout_address` instead of the
+    /// wallet's default address
+    pub async fn take_order_with_payout(
+        &self,
+        order_id: &OrderId,
+        amount: rust_decimal::Decimal,
+        payout_address: Option<String>,
+    ) -> Result<Trade> {
+        // Get order
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let order = orderbook.get_order(order_id).await?;
+        self.match_hooks.take(&order, amount)?;
+        
+        // Create trade
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        let local_peer_id = network.read().await.local_peer_id().to_string();
+        trade_manager.create_trade(order_id, local_peer_id, amount, payout_address).await
+    }
+
+    /// Take the fills of a planned market order
+    ///
+    /// Fills of orders that settle together, such as several orders of the same
+    /// maker, are batched into one trade and one transaction. Returns the trades
+    /// created, one per batch.
+    pub async fn take_fills(&self, fills: &[Fill], payout_address: Option<String>) -> Result<Vec<Trade>> {
+        self.get_route_executor()?.take_fills(fills, payout_address).await
+    }
+
+    /// Quote converting `amount_in` of `from` into `to`
+    ///
+    /// Assets without a market of their own are routed in two hops through
+    /// bitcoin; the route giving the most of `to` is returned.
+    pub async fn quote_route(&self, from: &Asset, to: &Asset, amount_in: rust_decimal::Decimal) -> Result<Route> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        Ok(routing::find_route(&orderbook.view(), orderbook.match_hooks(), from, to, amount_in, &[Asset::Bitcoin])?)
+    }
+
+    /// Execute a quoted route hop by hop
+    ///
+    /// Each hop waits for its trades to settle, up to the trade timeout, before
+    /// the next hop is requoted for what was actually received. If a later hop
+    /// fails, the intermediate asset is converted back to the source asset when
+    /// `unwind` is set and left in the wallet otherwise; the outcome says which.
+    pub async fn execute_route(&self, route: &Route, unwind: bool) -> Result<RouteExecution> {
+        self.get_route_executor()?.execute(route, unwind).await
+    }
+
+    /// Get the executor of routes and fills
+    ///
+    /// It holds only shared handles, so a caller behind a lock on this node can
+    /// release the lock while a route waits for its trades to settle.
+    pub fn get_route_executor(&self) -> Result<RouteExecutor> {
+        let orderbook = self.orderbook.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        Ok(RouteExecutor::new(
+            orderbook.clone(),
+            trade_manager.clone(),
+            network.clone(),
+            self.clock.clone(),
+            self.config.trade.trade_timeout,
+        ))
+    }
+
+    /// Join a private trading group by its shared key
+    ///
+    /// Orders in the group are encrypted to the key and kept out of the public
+    /// orderbook. Returns the group ID, which names the group in the other calls.
+    pub async fn join_group(&self, key: &str) -> Result<GroupId> {
+        let groups = self.groups.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        let group = PrivateGroup::from_key(GroupKey::from_hex(key)?);
+        
+        groups.join(group).await
+    }
+
+    /// Leave a private trading group
+    pub async fn leave_group(&self, group_id: &GroupId) -> Result<()> {
+        let groups = self.groups.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        groups.leave(group_id).await
+    }
+
+    /// Get the private trading groups this node is a member of
+    pub async fn get_groups(&self) -> Result<Vec<PrivateGroup>> {
+        let groups = self.groups.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        Ok(groups.groups().await)
+    }
+
+    /// Create an order visible only to the members of a private group
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_group_order(
+        &self,
+        group_id: &GroupId,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: rust_decimal::Decimal,
+        price: rust_decimal::Decimal,
+        expiry: Option<u64>,
+        payout_address: Option<String>,
+    ) -> Result<Order> {
+        let groups = self.groups.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        self.check_fee_reserve(&base_asset, &quote_asset, side, amount, price).await?;
+        let order = groups.create_order(group_id, base_asset, quote_asset, side, amount, price, expiry).await?;
+        
+        if let Err(e) = trade_manager.set_payout_address(&order.id, payout_address).await {
+            // Without its payout address the order must not be taken
+            let _ = groups.cancel_order(group_id, &order.id).await;
+            return Err(e);
+        }
+        if let Err(e) = self.cosign_private_order(&order).await {
+            let _ = groups.cancel_order(group_id, &order.id).await;
+            return Err(e);
+        }
+        
+        // Members take the order with the standard trade protocol
+        let group = groups.group(group_id).await?;
+        trade_manager.add_private_order(order.clone(), group.takers()).await;
+        
+        Ok(order)
+    }
+
+    /// Cancel an own order in a private group
+    pub async fn cancel_group_order(&self, group_id: &GroupId, order_id: &OrderId) -> Result<()> {
+        let groups = self.groups.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        groups.cancel_order(group_id, order_id).await?;
+        trade_manager.remove_private_order(order_id).await;
+        
+        Ok(())
     }
 
-    /// Take an order
-    pub async fn take_order(
+    /// Get the open orders of a private group
+    pub async fn get_group_orders(&self, group_id: &GroupId) -> Result<Vec<Order>> {
+        let groups = self.groups.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        
+        groups.orders(group_id).await
+    }
+
+    /// Take an order of a private group
+    pub async fn take_group_order(
         &self,
+        group_id: &GroupId,
         order_id: &OrderId,
         amount: rust_decimal::Decimal,
+        payout_address: Option<String>,
     ) -> Result<Trade> {
-        // Get order
-        let orderbook = self.orderbook.as_ref()
+        let groups = self.groups.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Orderbook not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
         
-        let order = orderbook.get_order(order_id).await?;
+        let order = groups.order(group_id, order_id).await?;
+        self.match_hooks.take(&order, amount)?;
+        let takers = groups.group(group_id).await?.takers();
+        let local_peer_id = network.read().await.local_peer_id().to_string();
+        trade_manager.add_private_order(order, takers).await;
         
-        // Create trade
+        trade_manager.create_trade(order_id, local_peer_id, amount, payout_address).await
+    }
+
+    /// Create a dark order, gossiped with its pair and side only
+    ///
+    /// Its amount and price are disclosed with `reveal_dark_order` to the takers
+    /// who ask for them, and only those takers can take the order.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_dark_order(
+        &self,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: rust_decimal::Decimal,
+        price: rust_decimal::Decimal,
+        expiry: Option<u64>,
+        payout_address: Option<String>,
+    ) -> Result<Order> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        self.check_fee_reserve(&base_asset, &quote_asset, side, amount, price).await?;
+        let order = dark_pool.create_order(base_asset, quote_asset, side, amount, price, expiry).await?;
+        
+        if let Err(e) = trade_manager.set_payout_address(&order.id, payout_address).await {
+            // Without its payout address the order must not be taken
+            let _ = dark_pool.cancel_order(&order.id).await;
+            return Err(e);
+        }
+        if let Err(e) = self.cosign_private_order(&order).await {
+            let _ = dark_pool.cancel_order(&order.id).await;
+            return Err(e);
+        }
+        
+        Ok(order)
+    }
+
+    /// Cancel an own dark order
+    pub async fn cancel_dark_order(&self, order_id: &OrderId) -> Result<()> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        dark_pool.cancel_order(order_id).await?;
+        
+        // Takers the terms were revealed to can no longer take it
+        trade_manager.remove_private_order(order_id).await;
+        
+        Ok(())
+    }
+
+    /// Get the open dark orders, with their terms hidden
+    pub async fn get_dark_orders(&self) -> Result<Vec<DarkOrder>> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        
+        Ok(dark_pool.orders().await)
+    }
+
+    /// Ask the maker of a dark order to reveal its terms
+    pub async fn request_reveal(&self, order_id: &OrderId) -> Result<()> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        
+        dark_pool.request_reveal(order_id).await
+    }
+
+    /// Get the reveal requests for own dark orders awaiting a decision
+    pub async fn get_reveal_requests(&self) -> Result<Vec<RevealRequest>> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        
+        Ok(dark_pool.reveal_requests().await)
+    }
+
+    /// Disclose the terms of an own dark order to a taker, who may then take it
+    pub async fn reveal_dark_order(&self, order_id: &OrderId, taker: &str) -> Result<Order> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        let (order, takers) = dark_pool.reveal(order_id, taker).await?;
+        trade_manager.add_private_order(order.clone(), takers).await;
+        
+        Ok(order)
+    }
+
+    /// Decline to reveal an own dark order to a taker
+    pub async fn decline_reveal(&self, order_id: &OrderId, taker: &str) -> Result<()> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        
+        dark_pool.decline(order_id, taker).await
+    }
+
+    /// Get the dark orders whose terms were revealed to this node
+    pub async fn get_revealed_orders(&self) -> Result<Vec<Order>> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
+        
+        Ok(dark_pool.revealed_orders().await)
+    }
+
+    /// Take a dark order whose terms were revealed to this node
+    pub async fn take_dark_order(
+        &self,
+        order_id: &OrderId,
+        amount: rust_decimal::Decimal,
+        payout_address: Option<String>,
+    ) -> Result<Trade> {
+        let dark_pool = self.dark_pool.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Dark pool not initialized"))?;
         let trade_manager = self.trade_manager.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
         let network = self.network.as_ref()
             .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        let order = dark_pool.revealed_order(order_id).await?;
+        self.match_hooks.take(&order, amount)?;
         let local_peer_id = network.read().await.local_peer_id().to_string();
-        trade_manager.create_trade(order_id, local_peer_id, amount).await
+        trade_manager.add_private_order(order, vec![local_peer_id.clone()]).await;
+        
+        trade_manager.create_trade(order_id, local_peer_id, amount, payout_address).await
+    }
+
+    /// Request quotes for a block trade that would move the book
+    ///
+    /// The request is broadcast to all peers and the signed quotes makers send back
+    /// within `timeout` are returned, best price first. A quote is taken with
+    /// `accept_quote`.
+    pub async fn request_quotes(
+        &self,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: rust_decimal::Decimal,
+        timeout: std::time::Duration,
+    ) -> Result<Vec<SignedQuote>> {
+        let rfq = self.rfq.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("RFQ manager not initialized"))?;
+        
+        rfq.request_quotes(base_asset, quote_asset, side, amount, timeout).await
+    }
+
+    /// Get the open quote requests received from takers
+    pub async fn get_quote_requests(&self) -> Result<Vec<QuoteRequest>> {
+        let rfq = self.rfq.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("RFQ manager not initialized"))?;
+        
+        Ok(rfq.inbound_requests().await)
+    }
+
+    /// Answer a quote request with a private quote that holds for `ttl` seconds
+    pub async fn send_quote(
+        &self,
+        request_id: &str,
+        amount: rust_decimal::Decimal,
+        price: rust_decimal::Decimal,
+        ttl: u64,
+    ) -> Result<SignedQuote> {
+        let rfq = self.rfq.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("RFQ manager not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        let quote = rfq.send_quote(request_id, amount, price, ttl).await?;
+        
+        // Let the taker start the trade protocol against the quote, once co-signed
+        let order = quote.quote.to_order(self.clock.now());
+        self.cosign_private_order(&order).await?;
+        trade_manager.add_private_order(order, vec![quote.quote.taker.clone()]).await;
+        
+        Ok(quote)
+    }
+
+    /// Have an own order published outside the orderbook co-signed, if orders must be
+    async fn cosign_private_order(&self, order: &Order) -> Result<()> {
+        if let Some(cosigner) = &self.cosigner {
+            cosigner.cosign(order).await?;
+        }
+        
+        Ok(())
+    }
+
+    /// Accept a received quote and start the trade protocol with its maker
+    pub async fn accept_quote(&self, quote_id: &str, payout_address: Option<String>) -> Result<Trade> {
+        let rfq = self.rfq.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("RFQ manager not initialized"))?;
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        let quote = rfq.quote(quote_id).await?.quote;
+        let order = quote.to_order(self.clock.now());
+        self.match_hooks.take(&order, quote.amount)?;
+        let order_id = order.id.clone();
+        trade_manager.add_private_order(order, vec![quote.taker.clone()]).await;
+        
+        trade_manager.create_trade(&order_id, quote.taker, quote.amount, payout_address).await
     }
 
     /// Get a trade by ID
@@ -381,6 +2278,95 @@ impl DarkSwap {
         trade_manager.get_trade(trade_id).await
     }
 
+    /// Export a signed receipt for a completed trade
+    pub async fn export_receipt(
+        &self,
+        trade_id: &TradeId,
+        inclusion_proof: Option<spv::MerkleProof>,
+    ) -> Result<trade::receipt::TradeReceipt> {
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        trade_manager.export_receipt(trade_id, inclusion_proof).await
+    }
+
+    /// Get the stages a trade went through, with the time spent on each
+    pub async fn get_trade_timeline(&self, trade_id: &TradeId) -> Result<trade::timeline::TradeTimeline> {
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        trade_manager.get_trade_timeline(trade_id).await
+    }
+
+    /// Mark a completed trade confirmed, once a proof shows its transaction included in a block
+    pub async fn confirm_trade(&self, trade_id: &TradeId, inclusion_proof: &spv::MerkleProof) -> Result<()> {
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        let trade = trade_manager.get_trade(trade_id).await?;
+        let txid = trade.txid
+            .ok_or_else(|| anyhow::anyhow!("Trade {} has no transaction", trade_id))?;
+        if !self.verify_transaction_inclusion(&txid, inclusion_proof).await? {
+            return Err(anyhow::anyhow!("Transaction {} is not proven included in a block", txid));
+        }
+        
+        trade_manager.record_confirmed(trade_id).await
+    }
+
+    /// Add block headers for SPV verification
+    ///
+    /// Headers are given as (height, hex encoded header) pairs in ascending order.
+    pub async fn add_block_headers(&self, headers: &[(u32, String)]) -> Result<()> {
+        let spv = self.spv.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SPV verification not enabled"))?;
+        
+        let mut spv = spv.write().await;
+        for (height, header) in headers {
+            spv.chain_mut().add_header_hex(*height, header)?;
+        }
+        
+        Ok(())
+    }
+
+    /// Verify that a transaction is included in a block
+    ///
+    /// The proof is checked against the tracked block headers, so SPV verification
+    /// must be enabled; a merkle root carried by the proof alone proves nothing.
+    pub async fn verify_transaction_inclusion(&self, txid: &str, proof: &spv::MerkleProof) -> Result<bool> {
+        let spv = self.spv.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("SPV verification not enabled"))?;
+        
+        Ok(spv.read().await.verify_inclusion(txid, proof).is_ok())
+    }
+
+    /// Verify a trade receipt, checking its inclusion proof against the tracked block headers
+    pub async fn verify_receipt(&self, receipt: &trade::receipt::TradeReceipt) -> Result<()> {
+        match &self.spv {
+            Some(spv) => trade::receipt::verify_receipt(receipt, Some(spv.read().await.chain())),
+            None => trade::receipt::verify_receipt(receipt, None),
+        }
+    }
+
+    /// Ask for approval of inbound trades
+    ///
+    /// Returns the receiver of approval requests. Requests that are not answered
+    /// within `trade.approval_timeout` of being displayed, or not displayed within
+    /// `trade.trade_timeout`, fall back to the `trade.auto_accept` policy, as do
+    /// all trades while no handler is registered.
+    pub async fn enable_trade_approval(&self) -> Result<mpsc::Receiver<trade::approval::ApprovalRequest>> {
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        let policy = trade::approval::ApprovalPolicy {
+            auto_accept: self.config.trade.auto_accept,
+            timeout: std::time::Duration::from_secs(self.config.trade.approval_timeout),
+            display_timeout: std::time::Duration::from_secs(self.config.trade.trade_timeout),
+            network: self.config.bitcoin.network.into(),
+        };
+        
+        Ok(trade_manager.enable_approval(policy).await)
+    }
+
     /// Get all trades
     pub async fn get_trades(&self) -> Result<Vec<Trade>> {
         let trade_manager = self.trade_manager.as_ref()
@@ -389,28 +2375,327 @@ impl DarkSwap {
         Ok(trade_manager.get_trades().await)
     }
 
+    /// Get trades with their memos, filtered
+    pub async fn get_trade_history(&self, filter: &TradeHistoryFilter) -> Result<Vec<TradeHistoryEntry>> {
+        let trades = self.get_trades().await?;
+        
+        Ok(self.memos.read().await.history(trades, filter))
+    }
+
+    /// Get a page of trades matching a query
+    pub async fn query_trades(&self, query: &TradeQuery) -> Result<TradePage<Trade>> {
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        trade_manager.query_trades(query, |_| true).await
+    }
+
+    /// Get a page of trades with their memos matching a query and a memo filter
+    pub async fn query_trade_history(
+        &self,
+        query: &TradeQuery,
+        filter: &TradeHistoryFilter,
+    ) -> Result<TradePage<TradeHistoryEntry>> {
+        let trade_manager = self.trade_manager.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
+        
+        let memos = self.memos.read().await;
+        let page = trade_manager
+            .query_trades(query, |trade| filter.matches(&memos.entry(trade.clone())))
+            .await?;
+        
+        Ok(TradePage {
+            trades: page.trades.into_iter().map(|trade| memos.entry(trade)).collect(),
+            next_cursor: page.next_cursor,
+        })
+    }
+
+    /// Get the 24h statistics of a pair from the completed trades
+    pub async fn get_ticker(&self, base_asset: &Asset, quote_asset: &Asset) -> Result<Ticker> {
+        let trades = self.get_trades().await?;
+        
+        Ok(trade_ticker::ticker(&trades, base_asset, quote_asset, self.clock.now(), trade_ticker::TICKER_WINDOW))
+    }
+
+    /// Get the trades, volume and fees of each referral code from the trades
+    /// completed between `since` and `until` (unix seconds)
+    pub async fn referral_report(&self, since: Option<u64>, until: Option<u64>) -> Result<Vec<ReferralSummary>> {
+        let trades = self.get_trades().await?;
+        
+        let completed = trades.iter().filter(|trade| match trade.completed_at {
+            Some(completed_at) if trade.state == TradeState::Completed => {
+                since.map_or(true, |since| completed_at >= since) && until.map_or(true, |until| completed_at <= until)
+            }
+            _ => false,
+        });
+        
+        Ok(trade_fees::referral_report(completed))
+    }
+
+    /// Set the local memo of a trade; empty labels and note remove it
+    pub async fn set_trade_memo(&self, trade_id: &TradeId, labels: Vec<String>, note: Option<String>) -> Result<Memo> {
+        // Only trades this node knows about can be annotated
+        self.get_trade(trade_id).await?;
+        
+        let memo = Memo::new(labels, note, self.clock.now());
+        let mut memos = self.memos.write().await;
+        memos.set_trade_memo(trade_id, memo.clone());
+        memos.save()?;
+        
+        Ok(memo)
+    }
+
+    /// Add a message to the negotiation transcript of a trade, kept on this node only
+    pub async fn record_trade_message(&self, trade_id: &TradeId, sender: &str, text: &str) -> Result<TranscriptMessage> {
+        let transcripts = self.transcripts.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Negotiation transcripts are disabled"))?;
+        
+        // Only trades this node knows about have a transcript
+        self.get_trade(trade_id).await?;
+        
+        transcripts.append(trade_id, sender, text, self.clock.now())
+    }
+
+    /// Get the negotiation transcript of a trade
+    pub async fn get_trade_transcript(&self, trade_id: &TradeId) -> Result<TradeTranscript> {
+        let transcripts = self.transcripts.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Negotiation transcripts are disabled"))?;
+        
+        transcripts.get(trade_id)
+    }
+
+    /// Set the local memo of a transaction; empty labels and note remove it
+    pub async fn set_transaction_memo(&self, txid: &str, labels: Vec<String>, note: Option<String>) -> Result<Memo> {
+        let memo = Memo::new(labels, note, self.clock.now());
+        let mut memos = self.memos.write().await;
+        memos.set_transaction_memo(txid, memo.clone());
+        memos.save()?;
+        
+        Ok(memo)
+    }
+
     /// Cancel a trade
     pub async fn cancel_trade(&self, trade_id: &TradeId, reason: &str) -> Result<()> {
         let trade_manager = self.trade_manager.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Trade manager not initialized"))?;
         
-        trade_manager.cancel_trade(trade_id, reason).await
+        trade_manager.cancel_trade(trade_id, reason).await
+    }
+
+    /// Get wallet address
+    pub async fn get_address(&self) -> Result<String> {
+        let wallet = self.wallet.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
+        
+        wallet.get_address().await
+    }
+
+    /// Get a new receive address with an optional label
+    pub async fn get_new_address(&self, label: Option<String>) -> Result<AddressInfo> {
+        let wallet = self.wallet.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
+        
+        wallet.get_new_address(label).await
+    }
+
+    /// List the issued receive addresses
+    pub async fn list_addresses(&self) -> Result<Vec<AddressInfo>> {
+        let wallet = self.wallet.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
+        
+        wallet.list_addresses().await
+    }
+
+    /// Get the output descriptor of the wallet, with its checksum
+    ///
+    /// The public descriptor lets another wallet watch this one; with
+    /// `include_private` it can spend from it too.
+    pub async fn export_descriptor(&self, include_private: bool) -> Result<String> {
+        let wallet = self.wallet.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
+        
+        wallet.export_descriptor(include_private).await
+    }
+
+    /// Plan a dust consolidation without broadcasting it
+    ///
+    /// With `force` the fee rate ceiling is ignored.
+    pub async fn plan_consolidation(&self, force: bool) -> Result<ConsolidationPlan> {
+        let wallet = self.wallet.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
+        
+        let utxos = wallet.list_utxos().await?;
+        let config = self.consolidation_config(force);
+        let fee_rate = self.consolidation_fee_rate(wallet.as_ref()).await;
+        
+        Ok(ConsolidationPlan::new(&utxos, &config, fee_rate)?)
+    }
+
+    /// Consolidate wallet dust into a single output
+    ///
+    /// With `force` the fee rate ceiling is ignored.
+    pub async fn consolidate_dust(&self, force: bool) -> Result<ConsolidationReport> {
+        let wallet = self.wallet.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
+        
+        let config = self.consolidation_config(force);
+        let fee_rate = self.consolidation_fee_rate(wallet.as_ref()).await;
+        let report = consolidation::consolidate(wallet.as_ref(), &config, fee_rate).await?;
+        if let Some(deposits) = &self.deposits {
+            deposits.record_own_transaction(&report.txid, self.clock.now())?;
+        }
+        
+        Ok(report)
+    }
+
+    /// Get the fee rate to consolidate at: the wallet's estimate, or the configured
+    /// fee rate if the wallet cannot estimate one
+    async fn consolidation_fee_rate(&self, wallet: &(dyn WalletInterface + Send + Sync)) -> f32 {
+        match wallet.estimate_fee_rate(consolidation::CONSOLIDATION_TARGET_BLOCKS).await {
+            Ok(fee_rate) => fee_rate,
+            Err(e) => {
+                debug!("Consolidating at the configured fee rate without an estimate: {}", e);
+                self.config.bitcoin.fee_rate
+            }
+        }
+    }
+
+    /// Get the consolidation configuration, without fee ceiling if forced
+    fn consolidation_config(&self, force: bool) -> config::ConsolidationConfig {
+        let mut config = self.config.wallet.consolidation.clone();
+        if force {
+            config.max_fee_rate = f32::MAX;
+        }
+        config
+    }
+
+    /// Get the fee reserve state
+    ///
+    /// Emits `Event::LowFeeReserve` if the uncommitted balance has dropped below the
+    /// reserve since the last check.
+    pub async fn get_fee_reserve_status(&self) -> Result<FeeReserveStatus> {
+        let guard = self.fee_guard.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Fee reserve not configured"))?;
+        
+        let (committed, in_flight) = self.fee_commitments().await?;
+        guard.observe(committed, in_flight).await
+    }
+
+    /// Check that a new order leaves the fee reserve untouched
+    async fn check_fee_reserve(
+        &self,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        side: OrderSide,
+        amount: rust_decimal::Decimal,
+        price: rust_decimal::Decimal,
+    ) -> Result<()> {
+        let guard = match &self.fee_guard {
+            Some(guard) => guard,
+            None => return Ok(()),
+        };
+        
+        let (committed, in_flight) = self.fee_commitments().await?;
+        let committed = committed + btc_commitment(base_asset, quote_asset, side, amount, price)?;
+        
+        if let Err(e) = guard.check(committed, in_flight).await {
+            if let Err(e) = guard.observe(committed, in_flight).await {
+                warn!("Failed to check fee reserve: {}", e);
+            }
+            return Err(e);
+        }
+        
+        Ok(())
+    }
+
+    /// Get the bitcoin committed to own open orders and in-flight trades, and the
+    /// number of in-flight trades
+    async fn fee_commitments(&self) -> Result<(u64, usize)> {
+        let committed = match &self.fee_guard {
+            Some(guard) => guard.committed(&[]).await?,
+            None => 0,
+        };
+        
+        let in_flight = match &self.trade_manager {
+            Some(trade_manager) => trade_manager.in_flight_trades().await,
+            None => 0,
+        };
+        
+        Ok((committed, in_flight))
+    }
+
+    /// Get the node status an operator dashboard shows, in one call
+    ///
+    /// Balances are listed for bitcoin and the assets of the pairs shown.
+    pub async fn get_dashboard(&self) -> Result<dashboard::Dashboard> {
+        let now = self.clock.now();
+        let markets = match &self.orderbook {
+            Some(orderbook) => orderbook.list_markets(),
+            None => Vec::new(),
+        };
+        let trades = match &self.trade_manager {
+            Some(trade_manager) => trade_manager.get_trades().await,
+            None => Vec::new(),
+        };
+        let pairs = dashboard::pair_activity(&markets, &trades, now);
+        
+        let connected_peers = match &self.network {
+            Some(network) => network.read().await.connected_peers().await.len(),
+            None => 0,
+        };
+        
+        let mut balances = std::collections::BTreeMap::new();
+        if let Some(wallet) = &self.wallet {
+            balances.insert(Asset::Bitcoin.to_string(), wallet.get_balance().await?);
+            for pair in &pairs {
+                for asset in [&pair.base_asset, &pair.quote_asset] {
+                    if !balances.contains_key(&asset.to_string()) {
+                        balances.insert(asset.to_string(), wallet.get_asset_balance(asset).await?);
+                    }
+                }
+            }
+        }
+        
+        let fee_reserve = match &self.fee_guard {
+            Some(_) => Some(self.get_fee_reserve_status().await?),
+            None => None,
+        };
+        
+        Ok(dashboard::Dashboard {
+            uptime_secs: self.started_at.map_or(0, |started_at| now.saturating_sub(started_at)),
+            connected_peers,
+            in_flight_trades: trades.iter().filter(|trade| !matches!(
+                trade.state,
+                TradeState::Completed | TradeState::Failed | TradeState::Canceled | TradeState::Expired
+            )).count(),
+            pairs,
+            balances,
+            fee_reserve,
+        })
     }
 
-    /// Get wallet address
-    pub async fn get_address(&self) -> Result<String> {
+    /// Get wallet balance
+    pub async fn get_balance(&self) -> Result<u64> {
         let wallet = self.wallet.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
         
-        wallet.get_address().await
+        wallet.get_balance().await
     }
 
-    /// Get wallet balance
-    pub async fn get_balance(&self) -> Result<u64> {
+    /// Sync the wallet with its chain backend, returning the number of unspent outputs
+    pub async fn sync_wallet(&self) -> Result<usize> {
         let wallet = self.wallet.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Wallet not initialized"))?;
         
-        wallet.get_balance().await
+        Ok(wallet.list_utxos().await?.len())
+    }
+
+    /// Get the incoming payments to the wallet, newest first
+    pub fn get_deposits(&self) -> Result<Vec<Deposit>> {
+        let deposits = self.deposits.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Deposit detection not enabled"))?;
+        
+        deposits.deposits()
     }
 
     /// Get asset balance
@@ -421,28 +2706,54 @@ impl DarkSwap {
         wallet.get_asset_balance(asset).await
     }
 
-    /// Get runes
+    /// Get runes, with the operator's metadata overrides
     pub async fn get_runes(&self) -> Result<Vec<types::Rune>> {
         // TODO: Implement rune lookup
-        Ok(vec![])
+        let registered = Vec::new();
+        
+        Ok(asset_info::override_runes(registered, &self.config.assets))
     }
 
-    /// Get rune by ID
+    /// Get rune by ID, with the operator's metadata overrides
     pub async fn get_rune(&self, rune_id: u128) -> Result<Option<types::Rune>> {
         // TODO: Implement rune lookup
-        Ok(None)
+        let registered = None;
+        
+        Ok(asset_info::override_rune(rune_id, registered, &self.config.assets))
     }
 
-    /// Get alkanes
+    /// Get alkanes, with the operator's metadata overrides
     pub async fn get_alkanes(&self) -> Result<Vec<types::Alkane>> {
         // TODO: Implement alkane lookup
-        Ok(vec![])
+        let registered = Vec::new();
+        
+        Ok(asset_info::override_alkanes(registered, &self.config.assets))
     }
 
-    /// Get alkane by ID
+    /// Get alkane by ID, with the operator's metadata overrides
     pub async fn get_alkane(&self, alkane_id: &types::AlkaneId) -> Result<Option<types::Alkane>> {
         // TODO: Implement alkane lookup
-        Ok(None)
+        let registered = None;
+        
+        Ok(asset_info::override_alkane(alkane_id, registered, &self.config.assets))
+    }
+
+    /// Get the display metadata of an asset: the registry's, with the operator's overrides
+    pub async fn get_asset_info(&self, asset: &Asset) -> Result<AssetInfo> {
+        let overrides = &self.config.assets;
+        let info = match asset {
+            Asset::Bitcoin => return Ok(AssetInfo::bitcoin()),
+            Asset::Rune(id) => match self.get_rune(*id).await? {
+                Some(rune) => AssetInfo::from_rune(&rune),
+                None => AssetInfo::unregistered(asset),
+            },
+            Asset::Alkane(id) => match self.get_alkane(id).await? {
+                Some(alkane) => AssetInfo::from_alkane(&alkane),
+                None => AssetInfo::unregistered(asset),
+            },
+        };
+        
+        Ok(info.with_override(asset_info::find_override(overrides, asset)))
     }
 
     // Runes and Alkanes Orderbook Methods
@@ -549,120 +2860,81 @@ impl DarkSwap {
         self.get_best_bid_ask(&types::Asset::Alkane(alkane_id.clone()), &types::Asset::Bitcoin).await
     }
 
-    /// Create an equality predicate alkane
-    pub fn create_equality_predicate_alkane(
-        &self,
-        left_alkane_id: types::AlkaneId,
-        left_amount: u128,
-        right_alkane_id: types::AlkaneId,
-        right_amount: u128,
-    ) -> EqualityPredicateAlkane {
-        PredicateAlkaneFactory::create_equality_predicate(
-            left_alkane_id,
-            left_amount,
-            right_alkane_id,
-            right_amount,
-        )
+    /// Get the reputation of a peer, following identity rotations
+    pub async fn get_reputation(&self, peer_id: &str) -> Option<Reputation> {
+        self.reputation.read().await.get_reputation(peer_id)
     }
 
-    /// Create a composite predicate alkane with AND operator
-    pub fn create_composite_and_predicate_alkane(&self) -> CompositePredicateAlkane {
-        CompositePredicateAlkaneFactory::create_and()
+    /// Apply an identity rotation received from a peer
+    pub async fn apply_rotation(&self, statement: &RotationStatement) -> Result<()> {
+        self.reputation.write().await.apply_rotation(statement)
     }
 
-    /// Create a composite predicate alkane with OR operator
-    pub fn create_composite_or_predicate_alkane(&self) -> CompositePredicateAlkane {
-        CompositePredicateAlkaneFactory::create_or()
+    /// Publish an identity rotation statement
+    pub async fn publish_rotation(&self, statement: &RotationStatement) -> Result<()> {
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        // Verify the statement before announcing it
+        statement.verify()?;
+        
+        // Serialize statement
+        let message = darkswap_support::envelope::encode(statement)
+            .context("Failed to serialize rotation statement")?;
+        
+        // Publish statement to identity topic
+        network.write().await.publish(reputation::IDENTITY_TOPIC, message).await?;
+        
+        // Update our own mappings as well
+        self.apply_rotation(statement).await
     }
-    
-    /// Create a multi-signature predicate alkane
-    pub fn create_multi_signature_predicate_alkane(
-        &self,
-        alkane_id: types::AlkaneId,
-        amount: u128,
-        public_keys: Vec<bitcoin::PublicKey>,
-        required_signatures: usize,
-    ) -> MultiSignaturePredicateAlkane {
-        MultiSignaturePredicateAlkaneFactory::create(
-            alkane_id,
-            amount,
-            public_keys,
-            required_signatures,
-        )
+
+    /// Get path selection metrics for peer connections
+    pub async fn get_path_metrics(&self) -> Result<PathMetrics> {
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        Ok(network.read().await.path_metrics().await)
     }
-    
-    /// Validate a transaction against a predicate
-    pub fn validate_predicate(&self, predicate: &impl Predicate, tx: &bitcoin::Transaction) -> Result<bool> {
-        match predicate.validate(tx) {
-            Ok(result) => Ok(result),
-            Err(e) => Err(anyhow::anyhow!("Predicate validation error: {}", e))
-        }
+
+    /// Get the outbound message queue metrics, with the queueing delay of each message class
+    pub async fn get_queue_metrics(&self) -> Result<p2p::send_queue::QueueMetrics> {
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        Ok(network.read().await.queue_metrics().await)
     }
 
-    /// Create a time-locked predicate alkane that can only be executed before a specific timestamp
-    pub fn create_time_locked_before_predicate_alkane(
-        &self,
-        alkane_id: types::AlkaneId,
-        amount: u128,
-        timestamp: u64,
-    ) -> TimeLockedPredicateAlkane {
-        TimeLockedPredicateAlkaneFactory::create_before(
-            alkane_id,
-            amount,
-            timestamp,
-        )
+    /// Get the relays in use with their health history, in the order they are tried
+    pub async fn list_relays(&self) -> Result<Vec<p2p::relay_store::RelayHealth>> {
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        Ok(network.read().await.list_relays().await)
     }
 
-    /// Create a time-locked predicate alkane that can only be executed after a specific timestamp
-    pub fn create_time_locked_after_predicate_alkane(
-        &self,
-        alkane_id: types::AlkaneId,
-        amount: u128,
-        timestamp: u64,
-    ) -> TimeLockedPredicateAlkane {
-        TimeLockedPredicateAlkaneFactory::create_after(
-            alkane_id,
-            amount,
-            timestamp,
-        )
+    /// Get the peers known from earlier sessions and this one
+    pub async fn get_known_peers(&self) -> Result<Vec<p2p::peer_store::PeerRecord>> {
+        let network = self.network.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P network not initialized"))?;
+        
+        Ok(network.read().await.known_peers().await)
     }
 
-    /// Create a time-locked predicate alkane that can only be executed between two timestamps
-    pub fn create_time_locked_between_predicate_alkane(
-        &self,
-        alkane_id: types::AlkaneId,
-        amount: u128,
-        start_timestamp: u64,
-        end_timestamp: u64,
-    ) -> TimeLockedPredicateAlkane {
-        TimeLockedPredicateAlkaneFactory::create_between(
-            alkane_id,
-            amount,
-            start_timestamp,
-            end_timestamp,
-        )
+    /// Forget all known peers
+    pub async fn clear_peer_store(&self) -> Result<()> {
+        match &self.network {
+            Some(network) => network.read().await.clear_peer_store().await,
+            None => match &self.config.p2p.peer_store_path {
+                Some(path) => p2p::peer_store::PeerStore::remove_file(path),
+                None => Ok(()),
+            },
+        }
     }
 
     /// Subscribe to events
-    pub async fn subscribe_to_events(&self) -> mpsc::Receiver<Event> {
-        // Create a new channel
-        let (sender, receiver) = mpsc::channel(100);
-        
-        // Clone the event sender
-        let event_sender = self.event_channel.0.clone();
-        
-        // Spawn a task to forward events
-        tokio::spawn(async move {
-            // Create a new receiver by cloning the sender
-            let mut event_receiver = mpsc::channel::<Event>(100).1;
-            
-            // Forward events
-            while let Some(event) = event_receiver.recv().await {
-                let _ = sender.send(event).await;
-            }
-        });
-        
-        receiver
+    pub async fn subscribe_to_events(&self) -> EventReceiver {
+        self.event_bus.subscribe()
     }
     
     /// Get performance profiler
@@ -675,6 +2947,18 @@ impl DarkSwap {
         self.performance_optimizer.clone()
     }
     
+    /// Get the request-for-quote manager
+    ///
+    /// Useful to wait for quotes without holding on to the `DarkSwap` instance.
+    pub fn get_rfq_manager(&self) -> Option<Arc<RfqManager>> {
+        self.rfq.clone()
+    }
+    
+    /// Get the storage shared by the subsystems
+    pub fn get_storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
+    
     /// Enable performance profiling
     pub async fn enable_performance_profiling(&mut self) -> Result<()> {
         if let Some(profiler) = &self.performance_profiler {
@@ -767,12 +3051,151 @@ impl DarkSwap {
     }
 }
 
+/// Predicate alkanes, left out of builds with the `no-alkanes` feature
+#[cfg(not(feature = "no-alkanes"))]
+impl DarkSwap {
+    /// Create an equality predicate alkane
+    pub fn create_equality_predicate_alkane(
+        &self,
+        left_alkane_id: types::AlkaneId,
+        left_amount: u128,
+        right_alkane_id: types::AlkaneId,
+        right_amount: u128,
+    ) -> EqualityPredicateAlkane {
+        PredicateAlkaneFactory::create_equality_predicate(
+            left_alkane_id,
+            left_amount,
+            right_alkane_id,
+            right_amount,
+        )
+    }
+
+    /// Create a composite predicate alkane with AND operator
+    pub fn create_composite_and_predicate_alkane(&self) -> CompositePredicateAlkane {
+        CompositePredicateAlkaneFactory::create_and()
+    }
+
+    /// Create a composite predicate alkane with OR operator
+    pub fn create_composite_or_predicate_alkane(&self) -> CompositePredicateAlkane {
+        CompositePredicateAlkaneFactory::create_or()
+    }
+    
+    /// Create a multi-signature predicate alkane
+    pub fn create_multi_signature_predicate_alkane(
+        &self,
+        alkane_id: types::AlkaneId,
+        amount: u128,
+        public_keys: Vec<bitcoin::PublicKey>,
+        required_signatures: usize,
+    ) -> MultiSignaturePredicateAlkane {
+        MultiSignaturePredicateAlkaneFactory::create(
+            alkane_id,
+            amount,
+            public_keys,
+            required_signatures,
+        )
+    }
+    
+    /// Validate a transaction against a predicate
+    pub fn validate_predicate(&self, predicate: &impl Predicate, tx: &bitcoin::Transaction) -> Result<bool> {
+        match predicate.validate(tx) {
+            Ok(result) => Ok(result),
+            Err(e) => Err(anyhow::anyhow!("Predicate validation error: {}", e))
+        }
+    }
+
+    /// Create a time-locked predicate alkane that can only be executed before a specific timestamp
+    pub fn create_time_locked_before_predicate_alkane(
+        &self,
+        alkane_id: types::AlkaneId,
+        amount: u128,
+        timestamp: u64,
+    ) -> TimeLockedPredicateAlkane {
+        TimeLockedPredicateAlkaneFactory::create_before(
+            alkane_id,
+            amount,
+            timestamp,
+        )
+    }
+
+    /// Create a time-locked predicate alkane that can only be executed after a specific timestamp
+    pub fn create_time_locked_after_predicate_alkane(
+        &self,
+        alkane_id: types::AlkaneId,
+        amount: u128,
+        timestamp: u64,
+    ) -> TimeLockedPredicateAlkane {
+        TimeLockedPredicateAlkaneFactory::create_after(
+            alkane_id,
+            amount,
+            timestamp,
+        )
+    }
+
+    /// Create a time-locked predicate alkane that can only be executed between two timestamps
+    pub fn create_time_locked_between_predicate_alkane(
+        &self,
+        alkane_id: types::AlkaneId,
+        amount: u128,
+        start_timestamp: u64,
+        end_timestamp: u64,
+    ) -> TimeLockedPredicateAlkane {
+        TimeLockedPredicateAlkaneFactory::create_between(
+            alkane_id,
+            amount,
+            start_timestamp,
+            end_timestamp,
+        )
+    }
+}
+
 // Dummy implementations for the trade module traits
 // These are temporary implementations for compilation purposes
 // In a real implementation, these would be replaced with proper implementations
 
 /// Dummy wallet implementation
-struct DummyWallet {}
+///
+/// Its PSBTs have no inputs, only the outputs paying the counterparty's payout
+/// address and the fees.
+struct DummyWallet {
+    /// Bitcoin network of the payout and fee addresses
+    network: bitcoin::Network,
+    /// Public descriptor of the node wallet, attached to its inputs when signing
+    descriptor: Option<miniscript::Descriptor<miniscript::descriptor::DescriptorPublicKey>>,
+    /// Addresses of the descriptor looked at
+    gap_limit: u32,
+}
+
+impl DummyWallet {
+    /// Create an unsigned PSBT paying the payout and fee outputs
+    fn create_psbt(&self, payout: Option<&trade::settlement::PayoutOutput>, fees: &[trade::fees::FeeOutput]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        if let Some(payout) = payout {
+            let address = trade::settlement::parse_payout_address(&payout.address, self.network)?;
+            output.push(bitcoin::TxOut { value: payout.amount, script_pubkey: address.script_pubkey() });
+        }
+        for fee in fees {
+            let address = trade::fees::parse_fee_address(&fee.address, self.network)?;
+            output.push(bitcoin::TxOut { value: fee.amount, script_pubkey: address.script_pubkey() });
+        }
+        
+        let tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime::ZERO,
+            input: Vec::new(),
+            output,
+        };
+        let psbt = bitcoin::psbt::PartiallySignedTransaction::from_unsigned_tx(tx)
+            .context("Failed to create PSBT")?;
+        
+        Ok(bitcoin::consensus::encode::serialize(&psbt))
+    }
+    
+    /// Decode a PSBT
+    fn decode(psbt: &[u8]) -> Result<bitcoin::psbt::PartiallySignedTransaction> {
+        bitcoin::consensus::encode::deserialize(psbt).context("Invalid PSBT")
+    }
+}
 
 #[async_trait]
 impl trade::Wallet for DummyWallet {
@@ -784,20 +3207,46 @@ impl trade::Wallet for DummyWallet {
         _quote_asset: &Asset,
         _amount: u64,
         _price: u64,
+        payout: Option<&trade::settlement::PayoutOutput>,
+        fees: &[trade::fees::FeeOutput],
     ) -> Result<Vec<u8>> {
-        Ok(vec![])
+        self.create_psbt(payout, fees)
+    }
+    
+    async fn create_batch_trade_psbt(
+        &self,
+        _trade_id: &TradeId,
+        _base_asset: &Asset,
+        _quote_asset: &Asset,
+        _legs: &[trade::batch::TradeLeg],
+        payout: Option<&trade::settlement::PayoutOutput>,
+        fees: &[trade::fees::FeeOutput],
+    ) -> Result<Vec<u8>> {
+        self.create_psbt(payout, fees)
     }
     
     async fn verify_psbt(&self, _psbt: &[u8]) -> Result<bool> {
         Ok(true)
     }
     
-    async fn sign_psbt(&self, _psbt: &[u8]) -> Result<Vec<u8>> {
-        Ok(vec![])
+    /// Attach the wallet descriptor to the wallet's inputs; the dummy holds no key to sign with
+    async fn sign_psbt(&self, psbt: &[u8]) -> Result<Vec<u8>> {
+        let mut psbt = Self::decode(psbt)?;
+        if let Some(descriptor) = &self.descriptor {
+            wallet::finalize::attach_wallet_descriptor(&mut psbt, descriptor, self.gap_limit)?;
+        }
+        
+        Ok(bitcoin::consensus::encode::serialize(&psbt))
     }
     
-    async fn finalize_and_broadcast_psbt(&self, _psbt: &[u8]) -> Result<String> {
-        Ok("dummy_txid".to_string())
+    /// Finalize the PSBT, multisig and timelocked inputs from their descriptors
+    ///
+    /// The transaction is not broadcast; its ID is returned.
+    async fn finalize_and_broadcast_psbt(&self, psbt: &[u8]) -> Result<String> {
+        let mut psbt = Self::decode(psbt)?;
+        wallet::finalize::finalize_psbt(&mut psbt)?;
+        
+        Ok(psbt.extract_tx().txid().to_string())
     }
 }
 
@@ -0,0 +1,241 @@
+//! Announcement keys
+//!
+//! By default a maker signs its orders with its network identity key, which
+//! has to live on the trading node. A maker can instead announce its orders
+//! with a dedicated announcement key, held by the node or by an external
+//! signer such as the remote signing service. The network key then only signs
+//! a [`Delegation`] once, naming the announcement key, and every order carries
+//! the delegation with it.
+//!
+//! Peers pin the announcement key of a maker the first time they accept one of
+//! its delegated orders, and from then on refuse orders of that maker signed
+//! with any other key, including the network key itself, as well as unsigned
+//! orders and updates the maker's network connection vouches for. The pins
+//! are kept in storage so a restart does not open that window again. Whoever
+//! takes over the trading node can still trade the maker's open orders, but
+//! cannot quote new ones unless the signer agrees.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use darkswap_support::storage::StorageExt;
+
+use super::snapshot::{self, SignedOrder};
+use super::{Order, OrderId, Orderbook, OrderbookError};
+
+/// Storage namespace of the pinned announcement keys, by maker
+pub const ANNOUNCEMENT_KEY_NAMESPACE: &str = "announcement_keys";
+
+/// Binding of an announcement key to a maker, signed with the maker's network key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegation {
+    /// Maker network public key (protobuf encoded, hex)
+    pub public_key: String,
+    /// Announcement public key (protobuf encoded, hex)
+    pub announcement_key: String,
+    /// Network key signature over the maker and the announcement key (hex)
+    pub signature: String,
+}
+
+impl Delegation {
+    /// Delegate the announcements of the maker owning `network_key` to `announcement_key`
+    pub fn issue(network_key: &Keypair, announcement_key: &PublicKey) -> Result<Self, OrderbookError> {
+        let maker = PeerId::from(network_key.public()).to_string();
+        let announcement_key = hex::encode(announcement_key.to_protobuf_encoding());
+        let signature = network_key
+            .sign(&delegation_bytes(&maker, &announcement_key))
+            .map_err(|e| OrderbookError::Other(format!("Failed to sign delegation: {}", e)))?;
+
+        Ok(Self {
+            public_key: hex::encode(network_key.public().to_protobuf_encoding()),
+            announcement_key,
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Check that `maker` delegated its announcements to the announcement key
+    pub fn verify(&self, maker: &str) -> Result<(), OrderbookError> {
+        let invalid = |reason: &str| OrderbookError::InvalidOrder(format!("Invalid announcement delegation: {}", reason));
+
+        let public_key_bytes = hex::decode(&self.public_key).map_err(|_| invalid("public key encoding"))?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes).map_err(|_| invalid("public key"))?;
+        if PeerId::from_public_key(&public_key).to_string() != maker {
+            return Err(invalid("not issued by the maker"));
+        }
+
+        let signature = hex::decode(&self.signature).map_err(|_| invalid("signature encoding"))?;
+        if !public_key.verify(&delegation_bytes(maker, &self.announcement_key), &signature) {
+            return Err(invalid("signature does not match"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the bytes covered by a delegation signature
+fn delegation_bytes(maker: &str, announcement_key: &str) -> Vec<u8> {
+    format!("darkswap/announcement-key/v1:{}:{}", maker, announcement_key).into_bytes()
+}
+
+/// Holder of an announcement key
+#[async_trait]
+pub trait AnnouncementSigner: Send + Sync {
+    /// Get the announcement public key
+    fn public_key(&self) -> PublicKey;
+
+    /// Sign the announcement of an order, `message` being the bytes the signature covers
+    async fn sign(&self, order_id: &OrderId, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Announcement key held by the node
+pub struct LocalAnnouncementSigner {
+    /// Announcement key
+    keypair: Keypair,
+}
+
+impl LocalAnnouncementSigner {
+    /// Create a signer of `keypair`
+    pub fn new(keypair: Keypair) -> Self {
+        Self { keypair }
+    }
+}
+
+#[async_trait]
+impl AnnouncementSigner for LocalAnnouncementSigner {
+    fn public_key(&self) -> PublicKey {
+        self.keypair.public()
+    }
+
+    async fn sign(&self, _order_id: &OrderId, message: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.keypair.sign(message)?)
+    }
+}
+
+impl Orderbook {
+    /// Sign an own order with the announcement key if there is one, with `network_key` otherwise
+    pub(super) async fn sign_own_order(&self, order: Order, network_key: &Keypair) -> Result<SignedOrder> {
+        let Some((signer, delegation)) = &self.announcement else {
+            return Ok(SignedOrder::sign(order, network_key)?);
+        };
+
+        let message = snapshot::signing_bytes(&order)?;
+        let signature = signer.sign(&order.id, &message).await?;
+        let signed_order = SignedOrder::delegated(order, delegation.clone(), &signature);
+
+        // Catches a signer answering with another key than it announced
+        signed_order.verify()?;
+
+        Ok(signed_order)
+    }
+
+    /// Check that a maker who delegated signs with the announcement key pinned for it
+    ///
+    /// The first delegated order of a maker pins its announcement key.
+    pub(super) async fn check_announcement_key(&self, signed_order: &SignedOrder) -> Result<(), OrderbookError> {
+        let maker = &signed_order.order.maker;
+        let mut pinned = self.announcement_keys.write().await;
+
+        match (pinned.get(maker), &signed_order.delegation) {
+            (Some(key), _) if key != &signed_order.public_key => Err(OrderbookError::InvalidOrder(format!(
+                "Order of {} is not signed with its announcement key",
+                maker
+            ))),
+            (None, Some(delegation)) => {
+                if let Some(storage) = &self.announcement_storage {
+                    if let Err(e) = storage.put_json(ANNOUNCEMENT_KEY_NAMESPACE, maker, &delegation.announcement_key) {
+                        warn!("Failed to store the announcement key of {}: {}", maker, e);
+                    }
+                }
+                pinned.insert(maker.clone(), delegation.announcement_key.clone());
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuse an unsigned order or update of a maker who delegated its announcements
+    ///
+    /// Only the maker's network connection vouches for those, which the
+    /// announcement key is there to stop being enough.
+    pub(super) async fn check_undelegated(&self, maker: &str) -> Result<(), OrderbookError> {
+        if self.announcement_keys.read().await.contains_key(maker) {
+            return Err(OrderbookError::InvalidOrder(format!(
+                "Order of {} is not signed with its announcement key",
+                maker
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Get the announcement key pinned for a maker (protobuf encoded, hex)
+    pub async fn announcement_key(&self, maker: &str) -> Option<String> {
+        self.announcement_keys.read().await.get(maker).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::orderbook::{OrderMessage, OrderSide};
+    use crate::p2p::P2PNetwork;
+    use crate::types::Asset;
+    use crate::wallet::simple_wallet::SimpleWallet;
+    use darkswap_support::storage::{MemoryStorage, Storage};
+    use rust_decimal_macros::dec;
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, RwLock};
+
+    fn orderbook(storage: Arc<dyn Storage>) -> Orderbook {
+        let (event_sender, _) = mpsc::channel(100);
+        let network = Arc::new(RwLock::new(P2PNetwork::new(&Config::default(), event_sender.clone()).unwrap()));
+        let wallet = Arc::new(SimpleWallet::new(None, Config::default().bitcoin.network).unwrap());
+        Orderbook::new(network, wallet, event_sender).with_announcement_storage(storage).unwrap()
+    }
+
+    #[test]
+    fn test_delegation() {
+        let network_key = Keypair::generate_ed25519();
+        let announcement_key = Keypair::generate_ed25519();
+        let maker = PeerId::from(network_key.public()).to_string();
+
+        let delegation = Delegation::issue(&network_key, &announcement_key.public()).unwrap();
+        assert!(delegation.verify(&maker).is_ok());
+
+        // Only the maker's network key can delegate
+        let other = PeerId::from(Keypair::generate_ed25519().public()).to_string();
+        assert!(delegation.verify(&other).is_err());
+
+        // The signature covers the announcement key
+        let mut swapped = delegation.clone();
+        swapped.announcement_key = hex::encode(Keypair::generate_ed25519().public().to_protobuf_encoding());
+        assert!(swapped.verify(&maker).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_key_survives_restart() {
+        let network_key = Keypair::generate_ed25519();
+        let announcement_key = Keypair::generate_ed25519();
+        let maker = PeerId::from(network_key.public()).to_string();
+        let new_order = || Order::new(maker.clone(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(1), dec!(100), Some(3600));
+
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let order = new_order();
+        let delegation = Delegation::issue(&network_key, &announcement_key.public()).unwrap();
+        let signature = announcement_key.sign(&snapshot::signing_bytes(&order).unwrap()).unwrap();
+        let delegated = SignedOrder::delegated(order, delegation, &signature);
+        orderbook(storage.clone()).handle_order_message(OrderMessage::SignedOrder(delegated), "relay").await.unwrap();
+
+        // After a restart the maker's network key alone is still refused
+        let restarted = orderbook(storage);
+        assert_eq!(restarted.announcement_key(&maker).await, Some(hex::encode(announcement_key.public().to_protobuf_encoding())));
+        let signed = SignedOrder::sign(new_order(), &network_key).unwrap();
+        assert!(restarted.handle_order_message(OrderMessage::SignedOrder(signed), "relay").await.is_err());
+        assert!(restarted.handle_order_message(OrderMessage::NewOrder(new_order()), &maker).await.is_err());
+    }
+}
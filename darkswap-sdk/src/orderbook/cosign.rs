@@ -0,0 +1,491 @@
+//! Co-signed orders for multisig maker accounts
+//!
+//! A desk can back its maker identity with a 2-of-3 multisig wallet, one key on
+//! the node and one with a separate signer service run under other control. Each
+//! new order then produces a PSBT funding it, signed with the node's key, which
+//! the co-signer must sign as well before the order is published. Orders the
+//! co-signer rejects, or returns without enough signatures, are never announced,
+//! so no quote goes out without a second pair of eyes.
+//!
+//! Every signature the co-signer returns must be a valid signature of the
+//! transaction by one of the keys of the multisig account. The co-signed PSBTs
+//! are kept in [`COSIGN_NAMESPACE`], and trades are only opened on own orders
+//! that have one, whichever way the order was published.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use bitcoin::consensus::Decodable;
+use bitcoin::psbt::{Input, PartiallySignedTransaction as Psbt};
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::util::sighash::SighashCache;
+use bitcoin::{EcdsaSighashType, PublicKey, Script, Transaction};
+use darkswap_support::storage::{Storage, StorageExt};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+#[cfg(feature = "http")]
+use crate::config::{CosignerConfig, DnsConfig};
+use crate::orderbook::{Order, OrderId};
+use crate::wallet::WalletInterface;
+
+/// Storage namespace of the co-signed PSBTs of own orders, keyed by order ID
+pub const COSIGN_NAMESPACE: &str = "cosigned_orders";
+
+/// Co-signing error
+#[derive(Debug, Error)]
+pub enum CosignError {
+    /// The co-signer refused the order
+    #[error("Co-signer rejected the order: {0}")]
+    Rejected(String),
+    /// The co-signer returned a different transaction
+    #[error("Co-signer changed the order transaction")]
+    Tampered,
+    /// An input lacks signatures
+    #[error("Input {input} has {found} of {required} signatures")]
+    MissingSignatures {
+        /// Input index
+        input: usize,
+        /// Signatures found
+        found: usize,
+        /// Signatures required
+        required: usize,
+    },
+    /// A signature was made by a key outside the multisig account
+    #[error("Input {input} is signed by unknown key {key}")]
+    UnknownKey {
+        /// Input index
+        input: usize,
+        /// Signing key (hex)
+        key: String,
+    },
+    /// A signature does not sign the transaction
+    #[error("Input {input} has an invalid signature by {key}")]
+    InvalidSignature {
+        /// Input index
+        input: usize,
+        /// Signing key (hex)
+        key: String,
+    },
+    /// The PSBT could not be decoded
+    #[error("Invalid PSBT: {0}")]
+    InvalidPsbt(String),
+    /// The co-signer configuration is unusable
+    #[error("Invalid co-signer configuration: {0}")]
+    InvalidConfig(String),
+    /// An own order was never co-signed
+    #[error("Order {0} was not co-signed")]
+    NotCosigned(OrderId),
+}
+
+/// Request to co-sign an order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CosignRequest {
+    /// Order to be published
+    pub order: Order,
+    /// Order PSBT signed by the maker node (base64)
+    pub psbt: String,
+}
+
+/// Response of a co-signer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CosignResponse {
+    /// Order PSBT with the co-signer's signatures (base64); absent if rejected
+    pub psbt: Option<String>,
+    /// Reason for a rejection
+    pub reason: Option<String>,
+}
+
+/// Second signer of a multisig maker account
+#[async_trait]
+pub trait CoSigner: Send + Sync {
+    /// Co-sign the PSBT of an order, returning it with the added signatures
+    async fn cosign(&self, request: &CosignRequest) -> Result<String>;
+}
+
+/// Co-signer service reached over HTTP
+///
+/// The request is POSTed as JSON to the configured URL, which answers with a
+/// `CosignResponse`.
+#[cfg(feature = "http")]
+pub struct HttpCoSigner {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Service URL
+    url: String,
+    /// Bearer token, if the service needs one
+    auth_token: Option<String>,
+}
+
+#[cfg(feature = "http")]
+impl HttpCoSigner {
+    /// Create a co-signer client
+    pub fn new(config: &CosignerConfig, dns: &DnsConfig) -> Result<Self> {
+        let client = crate::dns::http_client_builder(dns)?
+            .timeout(Duration::from_secs(config.timeout.max(1)))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            auth_token: config.auth_token.clone(),
+        })
+    }
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl CoSigner for HttpCoSigner {
+    async fn cosign(&self, request: &CosignRequest) -> Result<String> {
+        let mut http_request = self.client.post(&self.url).json(request);
+        if let Some(token) = &self.auth_token {
+            http_request = http_request.bearer_auth(token);
+        }
+
+        let response: CosignResponse = http_request
+            .send()
+            .await
+            .context("Failed to reach co-signer")?
+            .error_for_status()
+            .context("Co-signer failed")?
+            .json()
+            .await
+            .context("Invalid co-signer response")?;
+
+        match response.psbt {
+            Some(psbt) => Ok(psbt),
+            None => Err(CosignError::Rejected(response.reason.unwrap_or_else(|| "no reason given".to_string())).into()),
+        }
+    }
+}
+
+/// Co-signing of own orders
+///
+/// Creates the PSBT of each own order, has it co-signed and keeps the result,
+/// in storage if given, so orders co-signed before a restart can still be traded.
+pub struct OrderCosigner {
+    /// Second signer
+    signer: Arc<dyn CoSigner>,
+    /// Wallet creating the order PSBTs
+    wallet: Arc<dyn WalletInterface>,
+    /// Signatures each input needs once co-signed
+    required_signatures: usize,
+    /// Keys of the multisig account, the only ones allowed to sign
+    keys: Vec<PublicKey>,
+    /// Co-signed PSBTs by order ID
+    psbts: RwLock<HashMap<OrderId, String>>,
+    /// Storage the PSBTs are persisted to
+    storage: Option<Arc<dyn Storage>>,
+}
+
+impl OrderCosigner {
+    /// Create a co-signer of own orders
+    pub fn new(
+        signer: Arc<dyn CoSigner>,
+        wallet: Arc<dyn WalletInterface>,
+        required_signatures: usize,
+        keys: Vec<PublicKey>,
+    ) -> Result<Self, CosignError> {
+        if keys.len() < required_signatures.max(1) {
+            return Err(CosignError::InvalidConfig(format!(
+                "{} signatures required but {} keys allowed",
+                required_signatures,
+                keys.len()
+            )));
+        }
+
+        Ok(Self {
+            signer,
+            wallet,
+            required_signatures,
+            keys,
+            psbts: RwLock::new(HashMap::new()),
+            storage: None,
+        })
+    }
+
+    /// Persist the co-signed PSBTs to `storage`, loading those already there
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Result<Self> {
+        let stored = storage.scan_json::<String>(COSIGN_NAMESPACE, "")
+            .context("Failed to load co-signed orders")?;
+        self.psbts = RwLock::new(stored.into_iter()
+            .map(|(order_id, psbt)| (OrderId(order_id), psbt))
+            .collect());
+        self.storage = Some(storage);
+        Ok(self)
+    }
+
+    /// Create the PSBT of an own order, have it co-signed and keep it
+    pub async fn cosign(&self, order: &Order) -> Result<String> {
+        let to_sats = |value: Decimal| {
+            (value * Decimal::from(100_000_000)).to_u64()
+                .ok_or_else(|| anyhow::anyhow!("Invalid order value: {}", value))
+        };
+
+        let psbt = self.wallet.create_order_psbt(
+            &order.id,
+            &order.base_asset,
+            &order.quote_asset,
+            to_sats(order.amount)?,
+            to_sats(order.price)?,
+        ).await?;
+
+        let request = CosignRequest {
+            order: order.clone(),
+            psbt: psbt.clone(),
+        };
+        let cosigned = self.signer.cosign(&request).await?;
+        verify_cosigned(&psbt, &cosigned, self.required_signatures, &self.keys)?;
+
+        if let Some(storage) = &self.storage {
+            storage.put_json(COSIGN_NAMESPACE, &order.id.0, &cosigned)
+                .context("Failed to store co-signed order")?;
+            storage.flush().context("Failed to flush co-signed orders")?;
+        }
+        self.psbts.write().await.insert(order.id.clone(), cosigned.clone());
+
+        Ok(cosigned)
+    }
+
+    /// Get the co-signed PSBT of an own order
+    pub async fn psbt(&self, order_id: &OrderId) -> Option<String> {
+        self.psbts.read().await.get(order_id).cloned()
+    }
+
+    /// Check that an own order was co-signed
+    pub async fn check(&self, order_id: &OrderId) -> Result<(), CosignError> {
+        match self.psbts.read().await.contains_key(order_id) {
+            true => Ok(()),
+            false => Err(CosignError::NotCosigned(order_id.clone())),
+        }
+    }
+
+    /// Forget the co-signed PSBT of an order that is gone
+    pub async fn forget(&self, order_id: &OrderId) -> Result<()> {
+        self.psbts.write().await.remove(order_id);
+        if let Some(storage) = &self.storage {
+            storage.delete(COSIGN_NAMESPACE, order_id.0.as_bytes())
+                .context("Failed to remove co-signed order")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the hex public keys of a multisig account
+pub fn parse_keys(keys: &[String]) -> Result<Vec<PublicKey>, CosignError> {
+    keys.iter()
+        .map(|key| PublicKey::from_str(key).map_err(|e| CosignError::InvalidConfig(format!("Invalid key {}: {}", key, e))))
+        .collect()
+}
+
+/// Check that the co-signer signed the maker's transaction, and that every input
+/// has at least `required` valid signatures by the allowed `keys`
+///
+/// Any signature by another key, or that does not sign the transaction, is
+/// rejected. Inputs must stay unfinalized, so their signatures can be checked.
+pub fn verify_cosigned(original: &str, cosigned: &str, required: usize, keys: &[PublicKey]) -> Result<(), CosignError> {
+    let original = decode_psbt(original)?;
+    let cosigned = decode_psbt(cosigned)?;
+
+    if original.unsigned_tx != cosigned.unsigned_tx {
+        return Err(CosignError::Tampered);
+    }
+
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(&cosigned.unsigned_tx);
+    for (index, input) in cosigned.inputs.iter().enumerate() {
+        if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+            return Err(CosignError::InvalidPsbt(format!("Input {} was finalized", index)));
+        }
+
+        for (key, signature) in &input.partial_sigs {
+            if !keys.contains(key) {
+                return Err(CosignError::UnknownKey { input: index, key: key.to_string() });
+            }
+
+            let sighash = input_sighash(&mut cache, &cosigned.unsigned_tx, index, input, key, signature.hash_ty)?;
+            let message = Message::from_slice(&sighash).map_err(|e| CosignError::InvalidPsbt(e.to_string()))?;
+            if secp.verify_ecdsa(&message, &signature.sig, &key.inner).is_err() {
+                return Err(CosignError::InvalidSignature { input: index, key: key.to_string() });
+            }
+        }
+
+        let found = input.partial_sigs.len();
+        if found < required {
+            return Err(CosignError::MissingSignatures { input: index, found, required });
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the hash an input's signature by `key` signs
+fn input_sighash(
+    cache: &mut SighashCache<&Transaction>,
+    tx: &Transaction,
+    index: usize,
+    input: &Input,
+    key: &PublicKey,
+    hash_ty: EcdsaSighashType,
+) -> Result<[u8; 32], CosignError> {
+    let sighash = match (&input.witness_utxo, &input.witness_script) {
+        // Segwit multisig
+        (Some(utxo), Some(script)) => cache.segwit_signature_hash(index, script, utxo.value, hash_ty),
+        // Single-key segwit
+        (Some(utxo), None) if utxo.script_pubkey.is_v0_p2wpkh() => {
+            let script_code = Script::new_p2pkh(&key.pubkey_hash());
+            cache.segwit_signature_hash(index, &script_code, utxo.value, hash_ty)
+        }
+        (Some(_), None) => {
+            return Err(CosignError::InvalidPsbt(format!("Input {} lacks its witness script", index)));
+        }
+        // Legacy
+        (None, _) => {
+            let script = match (&input.redeem_script, &input.non_witness_utxo) {
+                (Some(script), _) => script.clone(),
+                (None, Some(prevout)) => {
+                    let vout = tx.input[index].previous_output.vout as usize;
+                    prevout.output.get(vout)
+                        .map(|output| output.script_pubkey.clone())
+                        .ok_or_else(|| CosignError::InvalidPsbt(format!("Input {} spends a missing output", index)))?
+                }
+                (None, None) => {
+                    return Err(CosignError::InvalidPsbt(format!("Input {} lacks the output it spends", index)));
+                }
+            };
+            cache.legacy_signature_hash(index, &script, hash_ty.to_u32())
+        }
+    };
+
+    sighash
+        .map(|sighash| bitcoin::hashes::Hash::into_inner(sighash))
+        .map_err(|e| CosignError::InvalidPsbt(format!("Input {}: {}", index, e)))
+}
+
+/// Decode a base64 PSBT
+fn decode_psbt(psbt: &str) -> Result<Psbt, CosignError> {
+    let bytes = base64::decode(psbt.trim()).map_err(|e| CosignError::InvalidPsbt(e.to_string()))?;
+    Psbt::consensus_decode(&mut &bytes[..]).map_err(|e| CosignError::InvalidPsbt(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::opcodes::all::OP_CHECKMULTISIG;
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::consensus::Encodable;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::{EcdsaSig, OutPoint, PackedLockTime, Sequence, TxIn, TxOut, Witness};
+
+    /// Value of the escrowed output
+    const FUNDING: u64 = 10_000;
+
+    fn secret_key(signer: u8) -> SecretKey {
+        SecretKey::from_slice(&[signer; 32]).unwrap()
+    }
+
+    fn public_key(signer: u8) -> PublicKey {
+        PublicKey::new(secret_key(signer).public_key(&Secp256k1::new()))
+    }
+
+    /// Keys of a 2-of-3 account
+    fn keys() -> Vec<PublicKey> {
+        vec![public_key(1), public_key(2), public_key(3)]
+    }
+
+    fn multisig_script() -> Script {
+        let mut builder = Builder::new().push_int(2);
+        for key in keys() {
+            builder = builder.push_key(&key);
+        }
+        builder.push_int(3).push_opcode(OP_CHECKMULTISIG).into_script()
+    }
+
+    /// PSBT spending the account, signed by `signers`; `forged` signs a wrong hash
+    fn signed_psbt(signers: &[u8], value: u64, forged: bool) -> String {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut { value, script_pubkey: Script::new() }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let script = multisig_script();
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: FUNDING, script_pubkey: script.to_v0_p2wsh() });
+        psbt.inputs[0].witness_script = Some(script.clone());
+
+        let secp = Secp256k1::new();
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .segwit_signature_hash(0, &script, FUNDING, EcdsaSighashType::All)
+            .unwrap();
+        let message = match forged {
+            true => Message::from_slice(&[1; 32]).unwrap(),
+            false => Message::from_slice(&sighash[..]).unwrap(),
+        };
+        for signer in signers {
+            let signature = secp.sign_ecdsa(&message, &secret_key(*signer));
+            psbt.inputs[0].partial_sigs.insert(public_key(*signer), EcdsaSig::sighash_all(signature));
+        }
+
+        let mut bytes = Vec::new();
+        psbt.consensus_encode(&mut bytes).unwrap();
+        base64::encode(bytes)
+    }
+
+    fn psbt(signers: &[u8], value: u64) -> String {
+        signed_psbt(signers, value, false)
+    }
+
+    #[test]
+    fn test_cosigned_psbt_is_accepted() {
+        assert!(verify_cosigned(&psbt(&[1], 1_000), &psbt(&[1, 2], 1_000), 2, &keys()).is_ok());
+    }
+
+    #[test]
+    fn test_missing_cosignature_is_rejected() {
+        assert!(matches!(
+            verify_cosigned(&psbt(&[1], 1_000), &psbt(&[1], 1_000), 2, &keys()),
+            Err(CosignError::MissingSignatures { input: 0, found: 1, required: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_changed_transaction_is_rejected() {
+        assert!(matches!(
+            verify_cosigned(&psbt(&[1], 1_000), &psbt(&[1, 2], 2_000), 2, &keys()),
+            Err(CosignError::Tampered)
+        ));
+    }
+
+    #[test]
+    fn test_signature_by_unknown_key_is_rejected() {
+        assert!(matches!(
+            verify_cosigned(&psbt(&[1], 1_000), &psbt(&[1, 4], 1_000), 2, &keys()),
+            Err(CosignError::UnknownKey { input: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_forged_signature_is_rejected() {
+        assert!(matches!(
+            verify_cosigned(&psbt(&[1], 1_000), &signed_psbt(&[1, 2], 1_000, true), 2, &keys()),
+            Err(CosignError::InvalidSignature { input: 0, .. })
+        ));
+    }
+}
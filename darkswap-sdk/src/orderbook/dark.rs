@@ -0,0 +1,571 @@
+//! Dark pool orders with reveal-on-interest
+//!
+//! A dark order is gossiped with its pair and side only; its amount and price are
+//! replaced by a salted commitment. A taker interested in the order asks the maker
+//! to reveal it over a direct channel, and the maker decides whom to disclose the
+//! terms to. The taker checks the disclosed terms against the signed commitment, so
+//! a maker cannot show different terms to different takers, and then trades the
+//! order with the standard trade protocol.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::envelope::{self, Versioned};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use log::{debug, info};
+use rand::RngCore;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, RwLock};
+
+use super::{Order, OrderId, OrderSide, OrderStatus, OrderbookError};
+use crate::clock::{self, Clock};
+use crate::p2p::P2PNetwork as Network;
+use crate::types::{Asset, Event};
+
+/// Gossip topic dark orders are published on
+pub const DARK_TOPIC: &str = "darkswap/dark/orders/v1";
+
+/// Request-response protocol reveal requests and disclosures are sent over
+pub const REVEAL_PROTOCOL: &str = "/darkswap/dark/reveal/1.0.0";
+
+/// Hidden terms of a dark order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DarkTerms {
+    /// Amount
+    pub amount: Decimal,
+    /// Price
+    pub price: Decimal,
+    /// Random salt, so the terms cannot be guessed from the commitment (hex)
+    pub salt: String,
+}
+
+impl DarkTerms {
+    /// Get the commitment to the terms of an order
+    pub fn commitment(&self, order_id: &OrderId) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"darkswap/dark/");
+        hasher.update(order_id.0.as_bytes());
+        hasher.update(b"/");
+        hasher.update(self.amount.normalize().to_string().as_bytes());
+        hasher.update(b"/");
+        hasher.update(self.price.normalize().to_string().as_bytes());
+        hasher.update(b"/");
+        hasher.update(self.salt.as_bytes());
+
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Dark order as gossiped, with its amount and price hidden
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DarkOrder {
+    /// Order ID
+    pub id: OrderId,
+    /// Maker peer ID
+    pub maker: String,
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Order side
+    pub side: OrderSide,
+    /// Commitment to the amount and price (hex)
+    pub commitment: String,
+    /// Order status
+    pub status: OrderStatus,
+    /// Order timestamp
+    pub timestamp: u64,
+    /// Order expiry
+    pub expiry: u64,
+}
+
+impl DarkOrder {
+    /// Hide the terms of an order
+    pub fn conceal(order: &Order) -> (Self, DarkTerms) {
+        let mut salt = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut salt);
+
+        let terms = DarkTerms {
+            amount: order.amount,
+            price: order.price,
+            salt: hex::encode(salt),
+        };
+        let dark_order = Self {
+            id: order.id.clone(),
+            maker: order.maker.clone(),
+            base_asset: order.base_asset.clone(),
+            quote_asset: order.quote_asset.clone(),
+            side: order.side,
+            commitment: terms.commitment(&order.id),
+            status: order.status,
+            timestamp: order.timestamp,
+            expiry: order.expiry,
+        };
+
+        (dark_order, terms)
+    }
+
+    /// Get the full order from disclosed terms, checking them against the commitment
+    pub fn disclose(&self, terms: &DarkTerms) -> Result<Order, OrderbookError> {
+        if terms.commitment(&self.id) != self.commitment {
+            return Err(OrderbookError::InvalidOrder(format!("Disclosed terms do not match order {}", self.id)));
+        }
+
+        Ok(Order {
+            id: self.id.clone(),
+            maker: self.maker.clone(),
+            base_asset: self.base_asset.clone(),
+            quote_asset: self.quote_asset.clone(),
+            side: self.side,
+            amount: terms.amount,
+            price: terms.price,
+            status: self.status,
+            timestamp: self.timestamp,
+            expiry: self.expiry,
+            hints: Default::default(),
+            fees: None,
+            referral_code: None,
+        })
+    }
+
+    /// Check if the order is expired at `now` (unix seconds)
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        now >= self.expiry
+    }
+}
+
+/// Dark order signed by its maker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedDarkOrder {
+    /// Dark order
+    pub order: DarkOrder,
+    /// Maker public key (protobuf encoded, hex)
+    pub public_key: String,
+    /// Maker signature over the serialized dark order (hex)
+    pub signature: String,
+}
+
+impl SignedDarkOrder {
+    /// Sign a dark order with the maker's keypair
+    pub fn sign(order: DarkOrder, keypair: &Keypair) -> Result<Self, OrderbookError> {
+        if PeerId::from(keypair.public()).to_string() != order.maker {
+            return Err(OrderbookError::InvalidOrder("Signing key does not match order maker".to_string()));
+        }
+
+        let message = signing_bytes(&order)?;
+        let signature = keypair
+            .sign(&message)
+            .map_err(|e| OrderbookError::Other(format!("Failed to sign dark order: {}", e)))?;
+
+        Ok(Self {
+            order,
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Verify the maker signature
+    pub fn verify(&self) -> Result<(), OrderbookError> {
+        let public_key_bytes = hex::decode(&self.public_key)
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid public key encoding: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes)
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid public key: {}", e)))?;
+
+        // The key must belong to the maker
+        if PeerId::from_public_key(&public_key).to_string() != self.order.maker {
+            return Err(OrderbookError::InvalidOrder("Signing key does not match order maker".to_string()));
+        }
+
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid signature encoding: {}", e)))?;
+        let message = signing_bytes(&self.order)?;
+
+        if !public_key.verify(&message, &signature) {
+            return Err(OrderbookError::InvalidOrder("Invalid maker signature".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Request of a taker to see the terms of a dark order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevealRequest {
+    /// Order ID
+    pub order_id: OrderId,
+    /// Taker peer ID
+    pub taker: String,
+}
+
+/// Dark pool message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DarkMessage {
+    /// Dark order, gossiped on the dark topic
+    Order(SignedDarkOrder),
+    /// Reveal request, sent to the maker over the reveal protocol
+    RevealRequest(RevealRequest),
+    /// Disclosed terms, sent to the taker over the reveal protocol
+    Reveal {
+        /// Order ID
+        order_id: OrderId,
+        /// Terms of the order
+        terms: DarkTerms,
+    },
+}
+
+impl Versioned for DarkMessage {
+    const KIND: &'static str = "darkswap/dark/message";
+    const VERSION: u32 = 1;
+}
+
+/// Own dark order
+struct OwnDarkOrder {
+    /// Signed dark order, for re-publication
+    signed_order: SignedDarkOrder,
+    /// Hidden terms
+    terms: DarkTerms,
+    /// Takers the terms were disclosed to
+    revealed_to: HashSet<String>,
+}
+
+/// Dark pool
+pub struct DarkPool {
+    /// Network module
+    network: Arc<RwLock<Network>>,
+    /// Event sender
+    event_sender: mpsc::Sender<Event>,
+    /// Own dark orders
+    own: RwLock<HashMap<OrderId, OwnDarkOrder>>,
+    /// Dark orders of other makers
+    book: RwLock<HashMap<OrderId, DarkOrder>>,
+    /// Reveal requests awaiting the maker's decision
+    pending: RwLock<HashMap<OrderId, HashSet<String>>>,
+    /// Orders disclosed to this node
+    revealed: RwLock<HashMap<OrderId, Order>>,
+    /// Time source of order timestamps and expiry
+    clock: Arc<dyn Clock>,
+}
+
+impl DarkPool {
+    /// Create an empty dark pool
+    pub fn new(network: Arc<RwLock<Network>>, event_sender: mpsc::Sender<Event>) -> Self {
+        Self {
+            network,
+            event_sender,
+            own: RwLock::new(HashMap::new()),
+            book: RwLock::new(HashMap::new()),
+            pending: RwLock::new(HashMap::new()),
+            revealed: RwLock::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the wall clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Subscribe to dark orders
+    pub async fn init(&self) -> Result<()> {
+        self.network.write().await.subscribe(DARK_TOPIC).await
+    }
+
+    /// Create a dark order and publish it without its amount and price
+    pub async fn create_order(
+        &self,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: Decimal,
+        price: Decimal,
+        expiry: Option<u64>,
+    ) -> Result<Order> {
+        if amount <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Amount must be positive".to_string()).into());
+        }
+        if price <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Price must be positive".to_string()).into());
+        }
+
+        let (order, signed_order, terms) = {
+            let network = self.network.read().await;
+            let order = Order::new_at(network.local_peer_id().to_string(), base_asset, quote_asset, side, amount, price, expiry, self.clock.now());
+            let (dark_order, terms) = DarkOrder::conceal(&order);
+            (order, SignedDarkOrder::sign(dark_order, network.keypair())?, terms)
+        };
+
+        self.publish(&signed_order).await?;
+        self.own.write().await.insert(order.id.clone(), OwnDarkOrder {
+            signed_order,
+            terms,
+            revealed_to: HashSet::new(),
+        });
+        info!("Created dark order {}", order.id);
+
+        Ok(order)
+    }
+
+    /// Cancel an own dark order
+    pub async fn cancel_order(&self, order_id: &OrderId) -> Result<()> {
+        let own = self.own.write().await.remove(order_id)
+            .ok_or_else(|| OrderbookError::NotFound(order_id.clone()))?;
+        self.pending.write().await.remove(order_id);
+
+        // The canceled version is signed too, so peers can tell it comes from the maker
+        let mut order = own.signed_order.order;
+        order.status = OrderStatus::Canceled;
+        let signed_order = SignedDarkOrder::sign(order, self.network.read().await.keypair())?;
+        self.publish(&signed_order).await?;
+
+        let _ = self.event_sender
+            .send(Event::OrderCancelled(order_id.clone()))
+            .await;
+
+        Ok(())
+    }
+
+    /// Get the open dark orders, own ones included, with their terms hidden
+    pub async fn orders(&self) -> Vec<DarkOrder> {
+        let now = self.clock.now();
+        let mut orders: Vec<DarkOrder> = self.book.read().await.values()
+            .filter(|order| !order.is_expired_at(now))
+            .cloned()
+            .collect();
+        orders.extend(self.own.read().await.values().map(|own| own.signed_order.order.clone()));
+        orders.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        orders
+    }
+
+    /// Ask the maker of a dark order to reveal its terms
+    pub async fn request_reveal(&self, order_id: &OrderId) -> Result<()> {
+        let order = self.book.read().await.get(order_id).cloned()
+            .ok_or_else(|| OrderbookError::NotFound(order_id.clone()))?;
+        if order.is_expired_at(self.clock.now()) {
+            return Err(OrderbookError::InvalidOrder(format!("Dark order {} is expired", order_id)).into());
+        }
+
+        let maker: PeerId = order.maker.parse()
+            .map_err(|_| OrderbookError::InvalidOrder(format!("Invalid maker peer ID: {}", order.maker)))?;
+
+        let mut network = self.network.write().await;
+        let request = RevealRequest {
+            order_id: order_id.clone(),
+            taker: network.local_peer_id().to_string(),
+        };
+        let message_data = envelope::encode(&DarkMessage::RevealRequest(request))
+            .context("Failed to serialize reveal request")?;
+        network.send_request(&maker, REVEAL_PROTOCOL, message_data).await?;
+        info!("Requested reveal of dark order {}", order_id);
+
+        Ok(())
+    }
+
+    /// Get the reveal requests awaiting a decision
+    pub async fn reveal_requests(&self) -> Vec<RevealRequest> {
+        let mut requests: Vec<RevealRequest> = self.pending.read().await.iter()
+            .flat_map(|(order_id, takers)| takers.iter().map(move |taker| RevealRequest {
+                order_id: order_id.clone(),
+                taker: taker.clone(),
+            }))
+            .collect();
+        requests.sort_by(|a, b| (&a.order_id.0, &a.taker).cmp(&(&b.order_id.0, &b.taker)));
+        requests
+    }
+
+    /// Disclose the terms of an own dark order to a taker
+    ///
+    /// Returns the full order and every taker it has been disclosed to so far, who
+    /// are the ones allowed to take it.
+    pub async fn reveal(&self, order_id: &OrderId, taker: &str) -> Result<(Order, Vec<String>)> {
+        let peer_id: PeerId = taker.parse()
+            .map_err(|_| OrderbookError::InvalidOrder(format!("Invalid taker peer ID: {}", taker)))?;
+
+        let (order, terms) = {
+            let own = self.own.read().await;
+            let own = own.get(order_id)
+                .ok_or_else(|| OrderbookError::NotFound(order_id.clone()))?;
+            (own.signed_order.order.clone(), own.terms.clone())
+        };
+        if order.is_expired_at(self.clock.now()) {
+            return Err(OrderbookError::InvalidOrder(format!("Dark order {} is expired", order_id)).into());
+        }
+
+        let message_data = envelope::encode(&DarkMessage::Reveal { order_id: order_id.clone(), terms: terms.clone() })
+            .context("Failed to serialize disclosure")?;
+        self.network.write().await.send_request(&peer_id, REVEAL_PROTOCOL, message_data).await?;
+        info!("Revealed dark order {} to {}", order_id, taker);
+
+        if let Some(takers) = self.pending.write().await.get_mut(order_id) {
+            takers.remove(taker);
+        }
+
+        let mut own = self.own.write().await;
+        let own = own.get_mut(order_id)
+            .ok_or_else(|| OrderbookError::NotFound(order_id.clone()))?;
+        own.revealed_to.insert(taker.to_string());
+        let mut takers: Vec<String> = own.revealed_to.iter().cloned().collect();
+        takers.sort();
+
+        Ok((order.disclose(&terms)?, takers))
+    }
+
+    /// Decline a reveal request
+    pub async fn decline(&self, order_id: &OrderId, taker: &str) -> Result<()> {
+        let mut pending = self.pending.write().await;
+        let removed = pending.get_mut(order_id).map(|takers| takers.remove(taker)).unwrap_or(false);
+        if !removed {
+            return Err(OrderbookError::InvalidOrder(format!("No reveal request from {} for order {}", taker, order_id)).into());
+        }
+
+        Ok(())
+    }
+
+    /// Get a dark order whose terms were disclosed to this node
+    pub async fn revealed_order(&self, order_id: &OrderId) -> Result<Order> {
+        let order = self.revealed.read().await.get(order_id).cloned()
+            .ok_or_else(|| OrderbookError::InvalidOrder(format!("Dark order {} was not revealed", order_id)))?;
+        if order.is_expired_at(self.clock.now()) {
+            return Err(OrderbookError::InvalidOrder(format!("Dark order {} is expired", order_id)).into());
+        }
+
+        Ok(order)
+    }
+
+    /// Get the dark orders whose terms were disclosed to this node
+    pub async fn revealed_orders(&self) -> Vec<Order> {
+        let now = self.clock.now();
+        self.revealed.read().await.values()
+            .filter(|order| !order.is_expired_at(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Publish a signed dark order on the dark topic
+    async fn publish(&self, signed_order: &SignedDarkOrder) -> Result<()> {
+        let message_data = envelope::encode(&DarkMessage::Order(signed_order.clone()))
+            .context("Failed to serialize dark order")?;
+        self.network.write().await.publish(DARK_TOPIC, message_data).await
+    }
+
+    /// Handle a dark pool message from a peer
+    pub async fn handle_dark_message(&self, message: DarkMessage, peer_id: &str) -> Result<()> {
+        match message {
+            DarkMessage::Order(signed_order) => {
+                signed_order.verify()?;
+                let order = signed_order.order;
+                debug!("Dark order {} from {}", order.id, peer_id);
+
+                if order.status != OrderStatus::Open || order.is_expired_at(self.clock.now()) {
+                    self.book.write().await.remove(&order.id);
+                    self.revealed.write().await.remove(&order.id);
+                    let _ = self.event_sender
+                        .send(Event::OrderCancelled(order.id))
+                        .await;
+                    return Ok(());
+                }
+
+                self.book.write().await.insert(order.id.clone(), order);
+            }
+            DarkMessage::RevealRequest(request) => {
+                // Only the taker can ask for a reveal in its name
+                if request.taker != peer_id {
+                    return Err(OrderbookError::InvalidOrder("Reveal request taker does not match peer ID".to_string()).into());
+                }
+                if !self.own.read().await.contains_key(&request.order_id) {
+                    return Err(OrderbookError::NotFound(request.order_id).into());
+                }
+
+                let inserted = self.pending.write().await
+                    .entry(request.order_id.clone())
+                    .or_default()
+                    .insert(request.taker.clone());
+                if inserted {
+                    let _ = self.event_sender
+                        .send(Event::RevealRequested(request))
+                        .await;
+                }
+            }
+            DarkMessage::Reveal { order_id, terms } => {
+                let dark_order = self.book.read().await.get(&order_id).cloned()
+                    .ok_or_else(|| OrderbookError::NotFound(order_id.clone()))?;
+
+                // Only the maker can disclose its terms
+                if dark_order.maker != peer_id {
+                    return Err(OrderbookError::InvalidOrder("Disclosure does not come from the maker".to_string()).into());
+                }
+
+                let order = dark_order.disclose(&terms)?;
+                self.revealed.write().await.insert(order_id, order.clone());
+                debug!("Dark order {} revealed by {}", order.id, peer_id);
+
+                let _ = self.event_sender
+                    .send(Event::OrderRevealed(order))
+                    .await;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the bytes covered by the maker signature
+fn signing_bytes(order: &DarkOrder) -> Result<Vec<u8>, OrderbookError> {
+    serde_json::to_vec(order).map_err(|e| OrderbookError::Other(format!("Failed to serialize dark order: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn order(keypair: &Keypair) -> Order {
+        let maker = PeerId::from(keypair.public()).to_string();
+        Order::new(maker, Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(250), dec!(123.45), None)
+    }
+
+    #[test]
+    fn test_dark_order_hides_its_terms() {
+        let keypair = Keypair::generate_ed25519();
+        let (dark_order, _) = DarkOrder::conceal(&order(&keypair));
+        let signed = SignedDarkOrder::sign(dark_order, &keypair).unwrap();
+
+        let json = serde_json::to_value(&signed.order).unwrap();
+        assert!(json.get("amount").is_none());
+        assert!(json.get("price").is_none());
+        assert_eq!(json["side"], serde_json::json!("Sell"));
+    }
+
+    #[test]
+    fn test_disclosed_terms_must_match_the_commitment() {
+        let keypair = Keypair::generate_ed25519();
+        let order = order(&keypair);
+        let (dark_order, terms) = DarkOrder::conceal(&order);
+
+        let disclosed = dark_order.disclose(&terms).unwrap();
+        assert_eq!(disclosed.amount, order.amount);
+        assert_eq!(disclosed.price, order.price);
+
+        let better = DarkTerms { price: dec!(120), ..terms.clone() };
+        assert!(dark_order.disclose(&better).is_err());
+
+        let resalted = DarkTerms { salt: "00".to_string(), ..terms };
+        assert!(dark_order.disclose(&resalted).is_err());
+    }
+
+    #[test]
+    fn test_signed_dark_order_verifies() {
+        let keypair = Keypair::generate_ed25519();
+        let (dark_order, _) = DarkOrder::conceal(&order(&keypair));
+        let mut signed = SignedDarkOrder::sign(dark_order, &keypair).unwrap();
+
+        assert!(signed.verify().is_ok());
+
+        signed.order.side = OrderSide::Buy;
+        assert!(signed.verify().is_err());
+    }
+}
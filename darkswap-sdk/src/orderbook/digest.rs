@@ -0,0 +1,330 @@
+//! Order digests
+//!
+//! Gossiping every order again whenever a peer might have missed it costs the
+//! whole mesh bandwidth on every change of a busy book. Instead, each node
+//! periodically publishes a digest of the signed open orders it can serve: per
+//! pair, the ID and hash of each order and a root hash over them. A peer skips
+//! the pairs whose root matches its own book, and fetches the orders it does not
+//! know from the publisher over the fetch protocol, which answers with the
+//! orders on the response channel of the request. Fetched orders carry their
+//! maker signature and are checked like any relayed signed order.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use darkswap_support::envelope::{self, Versioned};
+use libp2p::PeerId;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::snapshot::signing_bytes;
+use super::{Order, OrderId, OrderMessage, OrderStatus, Orderbook, OrderbookError, SignedOrder};
+use crate::types::Asset;
+
+/// Gossip topic order digests are published on
+pub const DIGEST_TOPIC: &str = "darkswap/orders/digest/v1";
+
+/// Request-response protocol orders are fetched over
+pub const FETCH_PROTOCOL: &str = "/darkswap/orders/fetch/1.0.0";
+
+/// Hash of an order as listed in a digest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestEntry {
+    /// Order ID
+    pub order_id: OrderId,
+    /// SHA-256 of the signed order bytes (hex)
+    pub hash: String,
+}
+
+/// Orders of one pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairDigest {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// SHA-256 over the entries, in order (hex)
+    pub root: String,
+    /// Orders, sorted by ID
+    pub entries: Vec<DigestEntry>,
+}
+
+impl PairDigest {
+    /// Create the digest of a pair from its entries
+    pub fn new(base_asset: Asset, quote_asset: Asset, mut entries: Vec<DigestEntry>) -> Self {
+        entries.sort_by(|a, b| a.order_id.0.cmp(&b.order_id.0));
+
+        let mut hasher = Sha256::new();
+        for entry in &entries {
+            hasher.update(entry.order_id.0.as_bytes());
+            hasher.update(b"/");
+            hasher.update(entry.hash.as_bytes());
+            hasher.update(b"\n");
+        }
+
+        Self {
+            base_asset,
+            quote_asset,
+            root: hex::encode(hasher.finalize()),
+            entries,
+        }
+    }
+}
+
+/// Digest of the orders a node can serve
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderDigest {
+    /// Time the digest was generated (unix seconds)
+    pub timestamp: u64,
+    /// Pairs, sorted by base and quote asset
+    pub pairs: Vec<PairDigest>,
+}
+
+impl OrderDigest {
+    /// Generate the digest of `orders`
+    pub fn generate<'a>(orders: impl IntoIterator<Item = &'a Order>, timestamp: u64) -> Result<Self, OrderbookError> {
+        let mut pairs: HashMap<(Asset, Asset), Vec<DigestEntry>> = HashMap::new();
+        for order in orders {
+            pairs
+                .entry((order.base_asset.clone(), order.quote_asset.clone()))
+                .or_default()
+                .push(DigestEntry { order_id: order.id.clone(), hash: order_hash(order)? });
+        }
+
+        let mut pairs: Vec<PairDigest> = pairs
+            .into_iter()
+            .map(|((base_asset, quote_asset), entries)| PairDigest::new(base_asset, quote_asset, entries))
+            .collect();
+        pairs.sort_by_key(|pair| (pair.base_asset.to_string(), pair.quote_asset.to_string()));
+
+        Ok(Self { timestamp, pairs })
+    }
+
+    /// Get the digest of a pair
+    pub fn pair(&self, base_asset: &Asset, quote_asset: &Asset) -> Option<&PairDigest> {
+        self.pairs
+            .iter()
+            .find(|pair| &pair.base_asset == base_asset && &pair.quote_asset == quote_asset)
+    }
+
+    /// Get the number of orders listed
+    pub fn len(&self) -> usize {
+        self.pairs.iter().map(|pair| pair.entries.len()).sum()
+    }
+
+    /// Check if no order is listed
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Digest protocol message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DigestMessage {
+    /// Digest, gossiped on the digest topic
+    Digest(OrderDigest),
+    /// Request for orders listed in a digest, sent to its publisher
+    Fetch {
+        /// Orders requested
+        order_ids: Vec<OrderId>,
+    },
+    /// Requested orders the publisher still holds, the response to a fetch
+    Orders(Vec<SignedOrder>),
+}
+
+impl Versioned for DigestMessage {
+    const KIND: &'static str = "darkswap/orderbook/digest";
+    const VERSION: u32 = 1;
+}
+
+/// Get the hash of an order as listed in a digest
+pub fn order_hash(order: &Order) -> Result<String, OrderbookError> {
+    Ok(hex::encode(Sha256::digest(signing_bytes(order)?)))
+}
+
+/// Get the orders of `remote` to fetch, given the local digest and every order ID in the book
+///
+/// Pairs whose root matches the local digest are skipped. Orders the book already
+/// holds in any state are not fetched again, so a canceled order a peer still
+/// lists does not come back.
+pub fn missing_orders(local: &OrderDigest, known: &HashSet<OrderId>, remote: &OrderDigest) -> Vec<OrderId> {
+    remote
+        .pairs
+        .iter()
+        .filter(|pair| local.pair(&pair.base_asset, &pair.quote_asset).map_or(true, |own| own.root != pair.root))
+        .flat_map(|pair| &pair.entries)
+        .filter(|entry| !known.contains(&entry.order_id))
+        .map(|entry| entry.order_id.clone())
+        .collect()
+}
+
+impl Orderbook {
+    /// Get the digest of the signed open orders this node can serve
+    pub async fn digest(&self) -> Result<OrderDigest> {
+        let now = self.clock.now();
+        let orders = self.orders.read().await;
+        let signed_orders = self.signed_orders.read().await;
+
+        let servable = signed_orders
+            .values()
+            .filter(|signed_order| {
+                orders
+                    .get(&signed_order.order.id)
+                    .map_or(false, |order| order.status == OrderStatus::Open && !order.is_expired_at(now))
+            })
+            .map(|signed_order| &signed_order.order);
+
+        Ok(OrderDigest::generate(servable, now)?)
+    }
+
+    /// Publish the digest on the digest topic, returning the number of orders listed
+    pub async fn publish_digest(&self) -> Result<usize> {
+        let digest = self.digest().await?;
+        let listed = digest.len();
+
+        let message_data = envelope::encode(&DigestMessage::Digest(digest)).context("Failed to serialize order digest")?;
+        self.network.write().await.publish(DIGEST_TOPIC, message_data).await?;
+
+        Ok(listed)
+    }
+
+    /// Handle a digest protocol message from a peer
+    ///
+    /// Returns the response to a fetch, to be sent back on the request's
+    /// response channel.
+    pub async fn handle_digest_message(&self, message: DigestMessage, peer_id: &str) -> Result<Option<DigestMessage>> {
+        match message {
+            DigestMessage::Digest(digest) => {
+                let mut order_ids = {
+                    let now = self.clock.now();
+                    let orders = self.orders.read().await;
+                    let local = OrderDigest::generate(
+                        orders.values().filter(|order| order.status == OrderStatus::Open && !order.is_expired_at(now)),
+                        now,
+                    )?;
+                    let known: HashSet<OrderId> = orders.keys().cloned().collect();
+
+                    missing_orders(&local, &known, &digest)
+                };
+                if order_ids.is_empty() {
+                    return Ok(None);
+                }
+
+                // The rest is fetched after the publisher's next digest
+                order_ids.truncate(self.digest.max_fetch.max(1));
+                debug!("Fetching {} orders missing from the digest of {}", order_ids.len(), peer_id);
+
+                let peer = peer_id.parse::<PeerId>().context("Invalid peer ID")?;
+                let message_data = envelope::encode(&DigestMessage::Fetch { order_ids }).context("Failed to serialize order fetch")?;
+                self.network.write().await.send_request(&peer, FETCH_PROTOCOL, message_data).await?;
+            }
+            DigestMessage::Fetch { order_ids } => {
+                if order_ids.len() > self.digest.max_fetch.max(1) {
+                    return Err(OrderbookError::InvalidOrder(format!("Fetch of {} orders exceeds the limit", order_ids.len())).into());
+                }
+
+                let served: Vec<SignedOrder> = {
+                    let now = self.clock.now();
+                    let orders = self.orders.read().await;
+                    let signed_orders = self.signed_orders.read().await;
+
+                    order_ids
+                        .iter()
+                        .filter(|order_id| {
+                            orders
+                                .get(order_id)
+                                .map_or(false, |order| order.status == OrderStatus::Open && !order.is_expired_at(now))
+                        })
+                        .filter_map(|order_id| signed_orders.get(order_id).cloned())
+                        .collect()
+                };
+
+                return Ok(Some(DigestMessage::Orders(served)));
+            }
+            DigestMessage::Orders(signed_orders) => {
+                for signed_order in signed_orders {
+                    let order_id = signed_order.order.id.clone();
+                    if let Err(e) = self.handle_order_message(OrderMessage::SignedOrder(signed_order), peer_id).await {
+                        warn!("Skipping fetched order {} from {}: {}", order_id, peer_id, e);
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Spawn a task publishing the orderbook digest every `interval`
+pub fn spawn_digest_publisher(orderbook: Arc<Orderbook>, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            if let Err(e) = orderbook.publish_digest().await {
+                warn!("Failed to publish the order digest: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+    use rust_decimal_macros::dec;
+
+    fn order(quote_asset: Asset, price: u64) -> Order {
+        Order::new_at(
+            "maker".to_string(),
+            Asset::Bitcoin,
+            quote_asset,
+            OrderSide::Sell,
+            dec!(1),
+            price.into(),
+            None,
+            1_700_000_000,
+        )
+    }
+
+    #[test]
+    fn test_missing_orders() {
+        let a = order(Asset::Rune(1), 100);
+        let b = order(Asset::Rune(1), 101);
+        let c = order(Asset::Rune(2), 100);
+        let remote = OrderDigest::generate([&a, &b, &c], 0).unwrap();
+        assert_eq!(remote.len(), 3);
+
+        // The same orders in another order give the same digest
+        assert_eq!(OrderDigest::generate([&c, &b, &a], 0).unwrap(), remote);
+
+        // Only the orders the book does not hold are fetched
+        let local = OrderDigest::generate([&a], 0).unwrap();
+        let known: HashSet<OrderId> = [a.id.clone()].into_iter().collect();
+        let mut missing = missing_orders(&local, &known, &remote);
+        missing.sort_by(|x, y| x.0.cmp(&y.0));
+        let mut expected = vec![b.id.clone(), c.id.clone()];
+        expected.sort_by(|x, y| x.0.cmp(&y.0));
+        assert_eq!(missing, expected);
+
+        // A canceled order the book still holds is not fetched again
+        let known: HashSet<OrderId> = [a.id.clone(), b.id.clone()].into_iter().collect();
+        assert_eq!(missing_orders(&local, &known, &remote), vec![c.id.clone()]);
+
+        // Nothing is fetched for a pair whose root matches
+        let local = OrderDigest::generate([&a, &b, &c], 0).unwrap();
+        assert!(missing_orders(&local, &HashSet::new(), &remote).is_empty());
+
+        // A changed order changes the root of its pair only
+        let mut changed = b.clone();
+        changed.amount = dec!(0.5);
+        let updated = OrderDigest::generate([&a, &changed, &c], 0).unwrap();
+        assert_ne!(updated.pair(&Asset::Bitcoin, &Asset::Rune(1)), remote.pair(&Asset::Bitcoin, &Asset::Rune(1)));
+        assert_eq!(updated.pair(&Asset::Bitcoin, &Asset::Rune(2)), remote.pair(&Asset::Bitcoin, &Asset::Rune(2)));
+    }
+}
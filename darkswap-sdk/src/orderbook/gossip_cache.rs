@@ -0,0 +1,314 @@
+//! Gossip cache for late joiners
+//!
+//! A peer that joins, or comes back after a disconnect, misses the orders
+//! gossiped while it was away. A full node with the cache enabled keeps the
+//! signed orders it saw in the last few minutes, per pair, and serves them
+//! over the backfill protocol, so the newcomer catches up on a pair with one
+//! request instead of a snapshot or waiting for the next digest. Only signed
+//! orders are kept, since they are the only messages a third peer may relay,
+//! and orders no longer open are left out of the answer. The cache is written
+//! through to storage, so a restarted node still serves what it saw before.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::envelope::{self, Versioned};
+use darkswap_support::storage::{Storage, StorageExt};
+use libp2p::PeerId;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{OrderMessage, OrderStatus, Orderbook, OrderbookError, SignedOrder};
+use crate::config::GossipCacheConfig;
+use crate::types::Asset;
+
+/// Request-response protocol late joiners backfill over
+pub const BACKFILL_PROTOCOL: &str = "/darkswap/orders/backfill/1.0.0";
+
+/// Storage namespace of the cache
+pub const CACHE_NAMESPACE: &str = "gossip_cache";
+
+/// Signed order as it was received
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedOrder {
+    /// Time the order was received (unix seconds)
+    pub received_at: u64,
+    /// Order
+    pub signed_order: SignedOrder,
+}
+
+impl CachedOrder {
+    /// Get the storage key; zero-padded times keep the records in arrival order
+    fn key(&self) -> String {
+        format!("{:020}/{}", self.received_at, self.signed_order.order.id)
+    }
+}
+
+/// Backfill protocol message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BackfillMessage {
+    /// Ask for the orders of a pair received at or after `since` (unix seconds)
+    Request {
+        /// Base asset
+        base_asset: Asset,
+        /// Quote asset
+        quote_asset: Asset,
+        /// Earliest arrival time
+        since: u64,
+    },
+    /// Cached orders, oldest first
+    Orders(Vec<SignedOrder>),
+}
+
+impl Versioned for BackfillMessage {
+    const KIND: &'static str = "darkswap/orderbook/backfill";
+    const VERSION: u32 = 1;
+}
+
+/// Recent order gossip by pair
+pub struct GossipCache {
+    /// Orders by pair, oldest first
+    pairs: HashMap<(Asset, Asset), VecDeque<CachedOrder>>,
+    /// Storage the cache is written through to
+    storage: Option<Arc<dyn Storage>>,
+    /// How long and how many orders are kept
+    config: GossipCacheConfig,
+}
+
+impl GossipCache {
+    /// Create an empty cache kept in memory only
+    pub fn new(config: GossipCacheConfig) -> Self {
+        Self {
+            pairs: HashMap::new(),
+            storage: None,
+            config,
+        }
+    }
+
+    /// Open the cache kept in `storage`, dropping what is older than the retention at `now`
+    pub fn open(storage: Arc<dyn Storage>, config: GossipCacheConfig, now: u64) -> Result<Self> {
+        let records = storage
+            .scan_json::<CachedOrder>(CACHE_NAMESPACE, "")
+            .context("Failed to read the gossip cache")?;
+
+        let mut cache = Self::new(config);
+        for (_, cached) in records {
+            let order = &cached.signed_order.order;
+            cache.pairs
+                .entry((order.base_asset.clone(), order.quote_asset.clone()))
+                .or_default()
+                .push_back(cached);
+        }
+        cache.storage = Some(storage);
+        cache.prune(now)?;
+
+        Ok(cache)
+    }
+
+    /// Get the time before which orders are dropped
+    fn cutoff(&self, now: u64) -> u64 {
+        now.saturating_sub(self.config.retention_minutes.saturating_mul(60))
+    }
+
+    /// Remove a record from storage
+    fn delete(&self, cached: &CachedOrder) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            storage
+                .delete(CACHE_NAMESPACE, cached.key().as_bytes())
+                .context("Failed to prune the gossip cache")?;
+        }
+
+        Ok(())
+    }
+
+    /// Keep a signed order received at `now`, dropping the oldest of its pair past the limit
+    pub fn record(&mut self, signed_order: &SignedOrder, now: u64) -> Result<()> {
+        let order = &signed_order.order;
+        let pair = (order.base_asset.clone(), order.quote_asset.clone());
+        if self.pairs.get(&pair).map_or(false, |cached| cached.iter().any(|c| c.signed_order.order.id == order.id)) {
+            return Ok(());
+        }
+
+        let cached = CachedOrder { received_at: now, signed_order: signed_order.clone() };
+        if let Some(storage) = &self.storage {
+            storage
+                .put_json(CACHE_NAMESPACE, &cached.key(), &cached)
+                .context("Failed to write to the gossip cache")?;
+        }
+
+        let mut dropped = Vec::new();
+        let entries = self.pairs.entry(pair).or_default();
+        entries.push_back(cached);
+        while entries.len() > self.config.max_per_pair.max(1) {
+            dropped.extend(entries.pop_front());
+        }
+        for cached in &dropped {
+            self.delete(cached)?;
+        }
+
+        self.prune(now)
+    }
+
+    /// Drop the orders received before the retention window ending at `now`
+    pub fn prune(&mut self, now: u64) -> Result<()> {
+        let cutoff = self.cutoff(now);
+
+        let mut dropped = Vec::new();
+        for entries in self.pairs.values_mut() {
+            while entries.front().map_or(false, |cached| cached.received_at < cutoff) {
+                dropped.extend(entries.pop_front());
+            }
+        }
+        self.pairs.retain(|_, entries| !entries.is_empty());
+        for cached in &dropped {
+            self.delete(cached)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the orders of a pair received at or after `since`, oldest first, at most `limit`
+    pub fn since(&self, base_asset: &Asset, quote_asset: &Asset, since: u64, limit: usize) -> Vec<&CachedOrder> {
+        self.pairs
+            .get(&(base_asset.clone(), quote_asset.clone()))
+            .map(|entries| entries.iter().filter(|cached| cached.received_at >= since).take(limit).collect())
+            .unwrap_or_default()
+    }
+
+    /// Get the number of cached orders
+    pub fn len(&self) -> usize {
+        self.pairs.values().map(VecDeque::len).sum()
+    }
+
+    /// Check if nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl Orderbook {
+    /// Ask `peer` for the orders of a pair it received at or after `since`
+    pub async fn request_backfill(&self, peer: &PeerId, base_asset: &Asset, quote_asset: &Asset, since: u64) -> Result<()> {
+        let message_data = envelope::encode(&BackfillMessage::Request {
+            base_asset: base_asset.clone(),
+            quote_asset: quote_asset.clone(),
+            since,
+        })
+        .context("Failed to serialize backfill request")?;
+
+        self.network.write().await.send_request(peer, BACKFILL_PROTOCOL, message_data).await
+    }
+
+    /// Handle a backfill protocol message from a peer
+    ///
+    /// Returns the answer to a request, to be sent back on the request's
+    /// response channel.
+    pub async fn handle_backfill_message(&self, message: BackfillMessage, peer_id: &str) -> Result<Option<BackfillMessage>> {
+        match message {
+            BackfillMessage::Request { base_asset, quote_asset, since } => {
+                let Some(cache) = &self.gossip_cache else {
+                    return Err(OrderbookError::Other("Gossip cache is disabled".to_string()).into());
+                };
+
+                let served: Vec<SignedOrder> = {
+                    let now = self.clock.now();
+                    let orders = self.orders.read().await;
+                    let mut cache = cache.write().await;
+                    cache.prune(now)?;
+
+                    cache
+                        .since(&base_asset, &quote_asset, since, usize::MAX)
+                        .into_iter()
+                        .filter(|cached| {
+                            orders
+                                .get(&cached.signed_order.order.id)
+                                .map_or(false, |order| order.status == OrderStatus::Open && !order.is_expired_at(now))
+                        })
+                        .take(self.gossip_cache_config.max_backfill.max(1))
+                        .map(|cached| cached.signed_order.clone())
+                        .collect()
+                };
+                debug!("Backfilling {} orders of {}/{} to {}", served.len(), base_asset, quote_asset, peer_id);
+
+                return Ok(Some(BackfillMessage::Orders(served)));
+            }
+            BackfillMessage::Orders(signed_orders) => {
+                if signed_orders.len() > self.gossip_cache_config.max_backfill.max(1) {
+                    return Err(OrderbookError::InvalidOrder(format!("Backfill of {} orders exceeds the limit", signed_orders.len())).into());
+                }
+
+                for signed_order in signed_orders {
+                    let order_id = signed_order.order.id.clone();
+                    if let Err(e) = self.handle_order_message(OrderMessage::SignedOrder(signed_order), peer_id).await {
+                        warn!("Skipping backfilled order {} from {}: {}", order_id, peer_id, e);
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Keep a signed order this node accepted in the gossip cache, if there is one
+    pub(super) async fn cache_gossip(&self, signed_order: &SignedOrder) {
+        let Some(cache) = &self.gossip_cache else { return };
+
+        if let Err(e) = cache.write().await.record(signed_order, self.clock.now()) {
+            warn!("Failed to cache order {}: {}", signed_order.order.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Order, OrderSide};
+    use darkswap_support::storage::MemoryStorage;
+    use rust_decimal_macros::dec;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn signed_order(quote_asset: Asset, price: u64) -> SignedOrder {
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        let order = Order::new_at(
+            PeerId::from(keypair.public()).to_string(),
+            Asset::Bitcoin,
+            quote_asset,
+            OrderSide::Sell,
+            dec!(1),
+            price.into(),
+            None,
+            NOW,
+        );
+        SignedOrder::sign(order, &keypair).unwrap()
+    }
+
+    fn config() -> GossipCacheConfig {
+        GossipCacheConfig { enabled: true, retention_minutes: 10, max_per_pair: 2, max_backfill: 100 }
+    }
+
+    #[test]
+    fn test_record_and_prune() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let mut cache = GossipCache::open(storage.clone(), config(), NOW).unwrap();
+
+        let first = signed_order(Asset::Rune(1), 100);
+        cache.record(&first, NOW).unwrap();
+        cache.record(&first, NOW + 1).unwrap();
+        cache.record(&signed_order(Asset::Rune(1), 101), NOW + 60).unwrap();
+        cache.record(&signed_order(Asset::Rune(1), 102), NOW + 120).unwrap();
+        cache.record(&signed_order(Asset::Rune(2), 100), NOW + 120).unwrap();
+
+        // Duplicates are ignored and the oldest order of a full pair goes
+        assert_eq!(cache.len(), 3);
+        let times: Vec<u64> = cache.since(&Asset::Bitcoin, &Asset::Rune(1), 0, 10).iter().map(|c| c.received_at).collect();
+        assert_eq!(times, vec![NOW + 60, NOW + 120]);
+        assert_eq!(cache.since(&Asset::Bitcoin, &Asset::Rune(1), NOW + 61, 10).len(), 1);
+
+        // A reopened cache serves the same orders, minus those past the retention
+        let reopened = GossipCache::open(storage, config(), NOW + 60 + 10 * 60 + 1).unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert!(reopened.since(&Asset::Bitcoin, &Asset::Rune(1), 0, 10).iter().all(|c| c.received_at == NOW + 120));
+    }
+}
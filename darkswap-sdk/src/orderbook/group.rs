@@ -0,0 +1,570 @@
+//! Private orderbooks for permissioned trading groups
+//!
+//! A group runs its own book on a topic of its own, next to the public one. Members
+//! share a group key, an allowlist of makers, or both: with a key, orders are
+//! encrypted to the group before they are published, so peers outside it see
+//! neither the orders nor which group they belong to; with an allowlist, orders of
+//! other makers are dropped. Group orders never enter the public orderbook.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use darkswap_support::envelope::{self, Versioned};
+use log::{debug, info};
+use rand::RngCore;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, RwLock};
+
+use super::view::OrderbookView;
+use super::{Order, OrderId, OrderSide, OrderStatus, OrderbookError, SignedOrder};
+use crate::clock::{self, Clock};
+use crate::config::GroupConfig;
+use crate::p2p::P2PNetwork as Network;
+use crate::types::{Asset, Event};
+
+/// Start of the topics group orders are published on
+pub const GROUP_TOPIC_PREFIX: &str = "darkswap/group/";
+
+/// Check if a topic carries the orders of a group
+pub fn is_group_topic(topic: &str) -> bool {
+    topic.starts_with(GROUP_TOPIC_PREFIX) && topic.ends_with("/orders/v1")
+}
+
+/// Group ID, derived from the group key or, without one, the group name
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GroupId(pub String);
+
+impl fmt::Display for GroupId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Shared group key
+#[derive(Clone, PartialEq, Eq)]
+pub struct GroupKey([u8; 32]);
+
+impl GroupKey {
+    /// Generate a random group key
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Parse a hex encoded group key
+    pub fn from_hex(key: &str) -> Result<Self, OrderbookError> {
+        let bytes = hex::decode(key.trim())
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid group key: {}", e)))?;
+        let key: [u8; 32] = bytes.try_into()
+            .map_err(|_| OrderbookError::InvalidOrder("Group key must be 32 bytes".to_string()))?;
+
+        Ok(Self(key))
+    }
+
+    /// Encode the key as hex, to share it with new members
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl fmt::Debug for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the key itself
+        write!(f, "GroupKey(..)")
+    }
+}
+
+/// Private trading group
+#[derive(Debug, Clone)]
+pub struct PrivateGroup {
+    /// Group ID
+    pub id: GroupId,
+    /// Group name
+    pub name: String,
+    /// Shared key orders are encrypted to
+    key: Option<GroupKey>,
+    /// Makers whose orders are accepted; any maker if empty
+    allowlist: HashSet<String>,
+    /// Peers allowed to take the group's orders; the allowlisted makers if empty
+    members: HashSet<String>,
+}
+
+impl PrivateGroup {
+    /// Create a group from a shared key and an optional allowlist
+    pub fn new(name: String, key: Option<GroupKey>, allowlist: Vec<String>) -> Result<Self, OrderbookError> {
+        if key.is_none() && allowlist.is_empty() {
+            return Err(OrderbookError::InvalidOrder(format!("Group {} needs a key or an allowlist", name)));
+        }
+
+        Ok(Self {
+            id: group_id(key.as_ref(), &name),
+            name,
+            key,
+            allowlist: allowlist.into_iter().collect(),
+            members: HashSet::new(),
+        })
+    }
+
+    /// Set the peers allowed to take the group's orders
+    pub fn with_members(mut self, members: Vec<String>) -> Self {
+        self.members = members.into_iter().collect();
+        self
+    }
+
+    /// Create a group joined by its key alone, named after its ID
+    pub fn from_key(key: GroupKey) -> Self {
+        let id = group_id(Some(&key), "");
+
+        Self {
+            name: id.0.clone(),
+            id,
+            key: Some(key),
+            allowlist: HashSet::new(),
+            members: HashSet::new(),
+        }
+    }
+
+    /// Create a group from its configuration
+    pub fn from_config(config: &GroupConfig) -> Result<Self, OrderbookError> {
+        let key = config.key.as_deref().map(GroupKey::from_hex).transpose()?;
+        Ok(Self::new(config.name.clone(), key, config.allowlist.clone())?.with_members(config.members.clone()))
+    }
+
+    /// Get the topic the group's orders are published on
+    pub fn topic(&self) -> String {
+        format!("{}{}/orders/v1", GROUP_TOPIC_PREFIX, self.id)
+    }
+
+    /// Check if the group encrypts its orders
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Check if a maker may post orders in the group
+    pub fn is_allowed(&self, maker: &str) -> bool {
+        self.allowlist.is_empty() || self.allowlist.contains(maker)
+    }
+
+    /// Get the makers allowed in the group; empty if any key holder is
+    pub fn allowlist(&self) -> Vec<String> {
+        let mut allowlist: Vec<String> = self.allowlist.iter().cloned().collect();
+        allowlist.sort();
+        allowlist
+    }
+
+    /// Get the peers allowed to take the group's orders
+    ///
+    /// These are the configured members, or else the allowlisted makers. Empty
+    /// for a group joined by its key alone, whose orders only key holders see.
+    pub fn takers(&self) -> Vec<String> {
+        if self.members.is_empty() {
+            return self.allowlist();
+        }
+
+        let mut members: Vec<String> = self.members.iter().cloned().collect();
+        members.sort();
+        members
+    }
+
+    /// Seal a signed order for publication in the group
+    pub fn seal(&self, order: &SignedOrder) -> Result<GroupMessage, OrderbookError> {
+        let plaintext = serde_json::to_vec(order)
+            .map_err(|e| OrderbookError::Other(format!("Failed to serialize order: {}", e)))?;
+
+        let payload = match &self.key {
+            Some(key) => {
+                let mut nonce = [0u8; 24];
+                rand::thread_rng().fill_bytes(&mut nonce);
+
+                let cipher = XChaCha20Poly1305::new((&key.0).into());
+                let ciphertext = cipher
+                    .encrypt(XNonce::from_slice(&nonce), Payload { msg: &plaintext, aad: self.id.0.as_bytes() })
+                    .map_err(|_| OrderbookError::Other("Failed to encrypt group order".to_string()))?;
+
+                GroupPayload::Sealed {
+                    nonce: hex::encode(nonce),
+                    ciphertext: hex::encode(ciphertext),
+                }
+            }
+            None => GroupPayload::Plain(order.clone()),
+        };
+
+        Ok(GroupMessage { group: self.id.clone(), payload })
+    }
+
+    /// Open a group message, checking the maker signature and the allowlist
+    pub fn open(&self, message: &GroupMessage) -> Result<SignedOrder, OrderbookError> {
+        if message.group != self.id {
+            return Err(OrderbookError::InvalidOrder(format!("Message for another group: {}", message.group)));
+        }
+
+        let order = match (&message.payload, &self.key) {
+            (GroupPayload::Sealed { nonce, ciphertext }, Some(key)) => {
+                let nonce = hex::decode(nonce)
+                    .ok()
+                    .filter(|nonce| nonce.len() == 24)
+                    .ok_or_else(|| OrderbookError::InvalidOrder("Invalid group message nonce".to_string()))?;
+                let ciphertext = hex::decode(ciphertext)
+                    .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid group message: {}", e)))?;
+
+                let cipher = XChaCha20Poly1305::new((&key.0).into());
+                let plaintext = cipher
+                    .decrypt(XNonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: self.id.0.as_bytes() })
+                    .map_err(|_| OrderbookError::InvalidOrder("Group message does not decrypt with the group key".to_string()))?;
+
+                serde_json::from_slice::<SignedOrder>(&plaintext)
+                    .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid group order: {}", e)))?
+            }
+            (GroupPayload::Plain(order), None) => order.clone(),
+            // An encrypted group must not accept plain orders, nor the other way round
+            _ => return Err(OrderbookError::InvalidOrder("Group message is not sealed as the group requires".to_string())),
+        };
+
+        order.verify()?;
+        if !self.is_allowed(&order.order.maker) {
+            return Err(OrderbookError::InvalidOrder(format!("Maker {} is not allowed in group {}", order.order.maker, self.name)));
+        }
+
+        Ok(order)
+    }
+}
+
+/// Derive the ID of a group from its key or, without one, its name
+fn group_id(key: Option<&GroupKey>, name: &str) -> GroupId {
+    let mut hasher = Sha256::new();
+    hasher.update(b"darkswap/group/");
+    match key {
+        Some(key) => hasher.update(key.0),
+        None => hasher.update(name.as_bytes()),
+    }
+
+    GroupId(hex::encode(&hasher.finalize()[..16]))
+}
+
+/// Order published in a group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupMessage {
+    /// Group ID
+    pub group: GroupId,
+    /// Order
+    pub payload: GroupPayload,
+}
+
+impl Versioned for GroupMessage {
+    const KIND: &'static str = "darkswap/group/message";
+    const VERSION: u32 = 1;
+}
+
+/// Payload of a group message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GroupPayload {
+    /// Signed order of a group without a key
+    Plain(SignedOrder),
+    /// Signed order encrypted to the group key
+    Sealed {
+        /// XChaCha20-Poly1305 nonce (hex)
+        nonce: String,
+        /// Encrypted signed order (hex)
+        ciphertext: String,
+    },
+}
+
+/// Group and its book
+struct GroupBook {
+    /// Group
+    group: PrivateGroup,
+    /// Open orders of the group
+    view: OrderbookView,
+    /// Own orders, signed for re-publication
+    own: HashMap<OrderId, SignedOrder>,
+}
+
+/// Private group orderbooks
+pub struct GroupManager {
+    /// Network module
+    network: Arc<RwLock<Network>>,
+    /// Event sender
+    event_sender: mpsc::Sender<Event>,
+    /// Joined groups by ID
+    groups: RwLock<HashMap<GroupId, GroupBook>>,
+    /// Time source of order timestamps and expiry
+    clock: Arc<dyn Clock>,
+}
+
+impl GroupManager {
+    /// Create a group manager without groups
+    pub fn new(network: Arc<RwLock<Network>>, event_sender: mpsc::Sender<Event>) -> Self {
+        Self {
+            network,
+            event_sender,
+            groups: RwLock::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the wall clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Join a group and subscribe to its topic
+    ///
+    /// Joining a group again replaces its allowlist and keeps its book.
+    pub async fn join(&self, group: PrivateGroup) -> Result<GroupId> {
+        let id = group.id.clone();
+        self.network.write().await.subscribe(&group.topic()).await?;
+
+        let mut groups = self.groups.write().await;
+        match groups.get_mut(&id) {
+            Some(book) => book.group = group,
+            None => {
+                info!("Joined private group {} ({})", group.name, id);
+                groups.insert(id.clone(), GroupBook {
+                    group,
+                    view: OrderbookView::new(),
+                    own: HashMap::new(),
+                });
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Leave a group, dropping its book
+    pub async fn leave(&self, id: &GroupId) -> Result<()> {
+        let book = self.groups.write().await.remove(id)
+            .ok_or_else(|| OrderbookError::InvalidOrder(format!("Not a member of group {}", id)))?;
+        self.network.write().await.unsubscribe(&book.group.topic()).await?;
+        info!("Left private group {} ({})", book.group.name, id);
+
+        Ok(())
+    }
+
+    /// Get the joined groups
+    pub async fn groups(&self) -> Vec<PrivateGroup> {
+        let mut groups: Vec<PrivateGroup> = self.groups.read().await.values().map(|book| book.group.clone()).collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        groups
+    }
+
+    /// Get a joined group
+    pub async fn group(&self, id: &GroupId) -> Result<PrivateGroup> {
+        self.groups.read().await.get(id)
+            .map(|book| book.group.clone())
+            .ok_or_else(|| OrderbookError::InvalidOrder(format!("Not a member of group {}", id)).into())
+    }
+
+    /// Get the open orders of a group
+    pub async fn orders(&self, id: &GroupId) -> Result<Vec<Order>> {
+        let groups = self.groups.read().await;
+        let book = groups.get(id)
+            .ok_or_else(|| OrderbookError::InvalidOrder(format!("Not a member of group {}", id)))?;
+
+        let now = self.clock.now();
+        Ok(book.view.load().orders().filter(|order| !order.is_expired_at(now)).cloned().collect())
+    }
+
+    /// Get an open order of a group
+    pub async fn order(&self, id: &GroupId, order_id: &OrderId) -> Result<Order> {
+        self.orders(id).await?
+            .into_iter()
+            .find(|order| &order.id == order_id)
+            .ok_or_else(|| OrderbookError::NotFound(order_id.clone()).into())
+    }
+
+    /// Create an order in a group and publish it to the members
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_order(
+        &self,
+        id: &GroupId,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: Decimal,
+        price: Decimal,
+        expiry: Option<u64>,
+    ) -> Result<Order> {
+        if amount <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Amount must be positive".to_string()).into());
+        }
+        if price <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Price must be positive".to_string()).into());
+        }
+
+        let signed_order = {
+            let network = self.network.read().await;
+            let order = Order::new_at(network.local_peer_id().to_string(), base_asset, quote_asset, side, amount, price, expiry, self.clock.now());
+            SignedOrder::sign(order, network.keypair())?
+        };
+
+        self.publish(id, signed_order.clone()).await?;
+
+        let _ = self.event_sender
+            .send(Event::OrderCreated(signed_order.order.clone()))
+            .await;
+
+        Ok(signed_order.order)
+    }
+
+    /// Cancel an own order in a group
+    pub async fn cancel_order(&self, id: &GroupId, order_id: &OrderId) -> Result<()> {
+        let mut order = {
+            let groups = self.groups.read().await;
+            let book = groups.get(id)
+                .ok_or_else(|| OrderbookError::InvalidOrder(format!("Not a member of group {}", id)))?;
+            book.own.get(order_id)
+                .map(|signed_order| signed_order.order.clone())
+                .ok_or_else(|| OrderbookError::NotFound(order_id.clone()))?
+        };
+        order.status = OrderStatus::Canceled;
+
+        // The canceled version is signed too, so members can tell it comes from the maker
+        let signed_order = SignedOrder::sign(order, self.network.read().await.keypair())?;
+        self.publish(id, signed_order).await?;
+
+        let _ = self.event_sender
+            .send(Event::OrderCancelled(order_id.clone()))
+            .await;
+
+        Ok(())
+    }
+
+    /// Apply an own signed order to a group book and publish it
+    async fn publish(&self, id: &GroupId, signed_order: SignedOrder) -> Result<()> {
+        let (topic, message) = {
+            let mut groups = self.groups.write().await;
+            let book = groups.get_mut(id)
+                .ok_or_else(|| OrderbookError::InvalidOrder(format!("Not a member of group {}", id)))?;
+            if !book.group.is_allowed(&signed_order.order.maker) {
+                return Err(OrderbookError::InvalidOrder(format!("Not allowed to post in group {}", book.group.name)).into());
+            }
+
+            let message = book.group.seal(&signed_order)?;
+            book.view.apply(&signed_order.order);
+            if signed_order.order.status == OrderStatus::Open {
+                book.own.insert(signed_order.order.id.clone(), signed_order);
+            } else {
+                book.own.remove(&signed_order.order.id);
+            }
+
+            (book.group.topic(), message)
+        };
+
+        let message_data = envelope::encode(&message).context("Failed to serialize group message")?;
+        self.network.write().await.publish(&topic, message_data).await?;
+
+        Ok(())
+    }
+
+    /// Handle a message published in a group
+    ///
+    /// Messages of groups this node is not a member of are ignored.
+    pub async fn handle_group_message(&self, message: GroupMessage, peer_id: &str) -> Result<()> {
+        let mut groups = self.groups.write().await;
+        let book = match groups.get_mut(&message.group) {
+            Some(book) => book,
+            None => return Ok(()),
+        };
+
+        let signed_order = book.group.open(&message)?;
+        let order = signed_order.order;
+        if order.is_expired_at(self.clock.now()) {
+            return Ok(());
+        }
+
+        debug!("Group {} order {} from {}", book.group.name, order.id, peer_id);
+        book.view.apply(&order);
+        drop(groups);
+
+        let event = match order.status {
+            OrderStatus::Open => Event::OrderCreated(order),
+            OrderStatus::Canceled => Event::OrderCancelled(order.id),
+            OrderStatus::Filled => Event::OrderFilled(order.id),
+            OrderStatus::Expired => Event::OrderExpired(order.id),
+        };
+        let _ = self.event_sender.send(event).await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+    use libp2p::PeerId;
+    use rust_decimal_macros::dec;
+
+    fn signed_order(keypair: &Keypair) -> SignedOrder {
+        let maker = PeerId::from(keypair.public()).to_string();
+        let order = Order::new(maker, Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(5), dec!(100), None);
+        SignedOrder::sign(order, keypair).unwrap()
+    }
+
+    #[test]
+    fn test_sealed_order_opens_with_the_group_key() {
+        let keypair = Keypair::generate_ed25519();
+        let key = GroupKey::generate();
+        let group = PrivateGroup::new("desk".to_string(), Some(key.clone()), Vec::new()).unwrap();
+        let order = signed_order(&keypair);
+
+        let message = group.seal(&order).unwrap();
+        let json = serde_json::to_string(&message).unwrap();
+        assert!(!json.contains(&order.order.id.0), "order leaks out of the sealed message");
+
+        let opened = group.open(&message).unwrap();
+        assert_eq!(opened.order.id, order.order.id);
+
+        // The same name with a different key is a different group
+        let other = PrivateGroup::new("desk".to_string(), Some(GroupKey::generate()), Vec::new()).unwrap();
+        assert_ne!(other.id, group.id);
+        let mut forwarded = message.clone();
+        forwarded.group = other.id.clone();
+        assert!(other.open(&forwarded).is_err());
+    }
+
+    #[test]
+    fn test_allowlist_gates_makers() {
+        let member = Keypair::generate_ed25519();
+        let outsider = Keypair::generate_ed25519();
+        let allowlist = vec![PeerId::from(member.public()).to_string()];
+        let group = PrivateGroup::new("otc".to_string(), None, allowlist).unwrap();
+
+        assert!(group.open(&group.seal(&signed_order(&member)).unwrap()).is_ok());
+        assert!(group.open(&group.seal(&signed_order(&outsider)).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_takers_are_the_members() {
+        let makers = vec!["maker".to_string()];
+        let group = PrivateGroup::new("otc".to_string(), None, makers.clone()).unwrap();
+        assert_eq!(group.takers(), makers);
+
+        let group = group.with_members(vec!["taker-b".to_string(), "taker-a".to_string()]);
+        assert_eq!(group.takers(), vec!["taker-a".to_string(), "taker-b".to_string()]);
+        assert!(PrivateGroup::from_key(GroupKey::generate()).takers().is_empty());
+
+        assert!(is_group_topic(&group.topic()));
+        assert!(!is_group_topic("darkswap/orders/v1"));
+    }
+
+    #[test]
+    fn test_group_needs_a_key_or_allowlist() {
+        assert!(PrivateGroup::new("open".to_string(), None, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_group_key_round_trips() {
+        let key = GroupKey::generate();
+        assert_eq!(GroupKey::from_hex(&key.to_hex()).unwrap(), key);
+        assert!(GroupKey::from_hex("abcd").is_err());
+    }
+}
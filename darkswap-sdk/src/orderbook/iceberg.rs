@@ -0,0 +1,487 @@
+//! Iceberg orders
+//!
+//! An iceberg order hides its total size: only a slice of it is posted to the
+//! orderbook and gossiped at a time. Fills of the visible slice are tracked from
+//! completed trades, and once a slice is used up the maker's node posts the next
+//! one, until the total size is filled, the iceberg expires or it is canceled.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::DocumentFile;
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use super::{own_orders, OrderId, OrderSide, Orderbook, OrderbookError};
+use crate::clock::Clock;
+use crate::events::EventReceiver;
+use crate::trade::TradeModule;
+use crate::types::{Asset, Event, TradeId};
+
+/// Iceberg order ID
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IcebergId(pub String);
+
+impl fmt::Display for IcebergId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Status of an iceberg order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IcebergStatus {
+    /// Slices are being posted
+    Active,
+    /// Total size filled
+    Filled,
+    /// Expired before the total size was filled
+    Expired,
+    /// Canceled by the maker
+    Canceled,
+}
+
+/// Iceberg order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IcebergOrder {
+    /// Iceberg ID
+    pub id: IcebergId,
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Side
+    pub side: OrderSide,
+    /// Limit price of every slice
+    pub price: Decimal,
+    /// Total size, never gossiped
+    pub total_amount: Decimal,
+    /// Size of the visible slice
+    pub slice_size: Decimal,
+    /// Amount filled across all slices
+    pub filled_amount: Decimal,
+    /// Order of the visible slice
+    pub current_slice: Option<OrderId>,
+    /// Trades already counted, so a repeated completion is not counted twice
+    pub trades: Vec<TradeId>,
+    /// Status
+    pub status: IcebergStatus,
+    /// Expiry (unix seconds)
+    pub expires_at: u64,
+    /// Creation time (unix seconds)
+    pub created_at: u64,
+}
+
+impl IcebergOrder {
+    /// Create an iceberg order
+    ///
+    /// `expiry` is in seconds from `now` (unix seconds) and defaults to 24 hours,
+    /// as for a plain order.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        total_amount: Decimal,
+        slice_size: Decimal,
+        price: Decimal,
+        expiry: Option<u64>,
+        now: u64,
+    ) -> Result<Self, OrderbookError> {
+        if total_amount <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Amount must be positive".to_string()));
+        }
+        if slice_size <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Slice size must be positive".to_string()));
+        }
+        if slice_size > total_amount {
+            return Err(OrderbookError::InvalidOrder("Slice size exceeds the total amount".to_string()));
+        }
+        if price <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Price must be positive".to_string()));
+        }
+
+        let created_at = now;
+
+        Ok(Self {
+            id: IcebergId(Uuid::new_v4().to_string()),
+            base_asset,
+            quote_asset,
+            side,
+            price,
+            total_amount,
+            slice_size,
+            filled_amount: Decimal::ZERO,
+            current_slice: None,
+            trades: Vec::new(),
+            status: IcebergStatus::Active,
+            expires_at: created_at + expiry.unwrap_or(86400),
+            created_at,
+        })
+    }
+
+    /// Get the amount not filled yet
+    pub fn remaining(&self) -> Decimal {
+        (self.total_amount - self.filled_amount).max(Decimal::ZERO)
+    }
+
+    /// Get the size of the next slice
+    pub fn next_slice_amount(&self) -> Decimal {
+        self.slice_size.min(self.remaining())
+    }
+
+    /// Record a fill of the visible slice
+    ///
+    /// Returns false if the trade was already counted.
+    fn record_fill(&mut self, trade_id: &TradeId, amount: Decimal) -> bool {
+        if self.trades.contains(trade_id) {
+            return false;
+        }
+
+        self.trades.push(trade_id.clone());
+        self.filled_amount = (self.filled_amount + amount).min(self.total_amount);
+        if self.remaining().is_zero() {
+            self.status = IcebergStatus::Filled;
+        }
+
+        true
+    }
+}
+
+/// Iceberg orders of this node
+#[derive(Debug, Default)]
+pub struct IcebergManager {
+    /// File the icebergs are persisted to; `None` keeps them in memory only
+    file: Option<DocumentFile>,
+    /// Icebergs by ID
+    icebergs: HashMap<IcebergId, IcebergOrder>,
+}
+
+impl IcebergManager {
+    /// Create an in-memory manager
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a manager persisted at `path`, loading it if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_file(DocumentFile::plain(path))
+    }
+
+    /// Open a manager persisted in `file`, sealed if storage is encrypted
+    pub fn open_file(file: DocumentFile) -> Result<Self> {
+        let icebergs: Vec<IcebergOrder> = file.load()
+            .with_context(|| format!("Failed to read iceberg store {}", file.path().display()))?
+            .unwrap_or_default();
+
+        Ok(Self {
+            file: Some(file),
+            icebergs: icebergs.into_iter().map(|iceberg| (iceberg.id.clone(), iceberg)).collect(),
+        })
+    }
+
+    /// Save the icebergs to disk, if they are persisted
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        file.save(&self.list())
+            .with_context(|| format!("Failed to write iceberg store {}", file.path().display()))?;
+
+        Ok(())
+    }
+
+    /// Add an iceberg
+    pub fn add(&mut self, iceberg: IcebergOrder) {
+        self.icebergs.insert(iceberg.id.clone(), iceberg);
+    }
+
+    /// Get an iceberg
+    pub fn get(&self, id: &IcebergId) -> Option<&IcebergOrder> {
+        self.icebergs.get(id)
+    }
+
+    /// List all icebergs, oldest first
+    pub fn list(&self) -> Vec<IcebergOrder> {
+        let mut icebergs: Vec<IcebergOrder> = self.icebergs.values().cloned().collect();
+        icebergs.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.0.cmp(&b.id.0)));
+        icebergs
+    }
+
+    /// Find the active iceberg whose visible slice is an order
+    pub fn find_by_slice(&self, order_id: &OrderId) -> Option<&IcebergOrder> {
+        self.icebergs.values()
+            .find(|iceberg| iceberg.status == IcebergStatus::Active && iceberg.current_slice.as_ref() == Some(order_id))
+    }
+
+    /// Mark an active iceberg canceled and return its visible slice
+    fn cancel(&mut self, id: &IcebergId) -> Result<IcebergOrder, OrderbookError> {
+        let iceberg = self.icebergs.get_mut(id)
+            .ok_or_else(|| OrderbookError::InvalidOrder(format!("Unknown iceberg order: {}", id)))?;
+
+        if iceberg.status != IcebergStatus::Active {
+            return Err(OrderbookError::InvalidOrder(format!("Iceberg order {} is no longer active", id)));
+        }
+
+        iceberg.status = IcebergStatus::Canceled;
+        Ok(iceberg.clone())
+    }
+}
+
+/// Post the next slice of an active iceberg
+///
+/// An iceberg that has expired is marked expired instead.
+async fn post_next_slice(manager: &RwLock<IcebergManager>, orderbook: &Orderbook, id: &IcebergId) -> Result<()> {
+    let iceberg = match manager.read().await.get(id) {
+        Some(iceberg) if iceberg.status == IcebergStatus::Active => iceberg.clone(),
+        _ => return Ok(()),
+    };
+
+    let now = orderbook.clock().now();
+    if now >= iceberg.expires_at {
+        info!("Iceberg order {} expired with {} unfilled", id, iceberg.remaining());
+        if let Some(iceberg) = manager.write().await.icebergs.get_mut(id) {
+            iceberg.status = IcebergStatus::Expired;
+            iceberg.current_slice = None;
+        }
+        return Ok(());
+    }
+
+    let order = orderbook.create_order(
+        iceberg.base_asset.clone(),
+        iceberg.quote_asset.clone(),
+        iceberg.side,
+        iceberg.next_slice_amount(),
+        iceberg.price,
+        Some(iceberg.expires_at - now),
+    ).await?;
+
+    let mut manager = manager.write().await;
+    match manager.icebergs.get_mut(id) {
+        Some(iceberg) if iceberg.status == IcebergStatus::Active => {
+            iceberg.current_slice = Some(order.id);
+        }
+        _ => {
+            // Canceled while the slice was posted
+            drop(manager);
+            orderbook.cancel_order(&order.id).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Post an iceberg order and its first slice
+pub async fn post(manager: &RwLock<IcebergManager>, orderbook: &Orderbook, iceberg: IcebergOrder) -> Result<IcebergOrder> {
+    let id = iceberg.id.clone();
+    manager.write().await.add(iceberg);
+
+    if let Err(e) = post_next_slice(manager, orderbook, &id).await {
+        manager.write().await.icebergs.remove(&id);
+        return Err(e);
+    }
+
+    let manager = manager.read().await;
+    manager.save()?;
+    manager.get(&id)
+        .cloned()
+        .ok_or_else(|| OrderbookError::InvalidOrder(format!("Unknown iceberg order: {}", id)).into())
+}
+
+/// Cancel an iceberg order and withdraw its visible slice
+pub async fn cancel(manager: &RwLock<IcebergManager>, orderbook: &Orderbook, id: &IcebergId) -> Result<IcebergOrder> {
+    let iceberg = manager.write().await.cancel(id)?;
+
+    if let Some(slice) = &iceberg.current_slice {
+        if let Err(e) = orderbook.cancel_order(slice).await {
+            warn!("Failed to cancel slice {} of iceberg order {}: {}", slice, id, e);
+        }
+    }
+
+    manager.read().await.save()?;
+    Ok(iceberg)
+}
+
+/// Account for a completed trade against the visible slice of an iceberg
+///
+/// The slice is reduced by the traded amount and, once it is used up, the next
+/// slice is posted. Returns false if the trade did not fill an iceberg slice.
+pub async fn handle_fill(
+    manager: &RwLock<IcebergManager>,
+    orderbook: &Orderbook,
+    order_id: &OrderId,
+    trade_id: &TradeId,
+    amount: Decimal,
+    order_store: Option<&DocumentFile>,
+) -> Result<bool> {
+    let id = {
+        let mut manager = manager.write().await;
+        let iceberg = match manager.icebergs.values_mut()
+            .find(|iceberg| iceberg.status == IcebergStatus::Active && iceberg.current_slice.as_ref() == Some(order_id))
+        {
+            Some(iceberg) => iceberg,
+            None => return Ok(false),
+        };
+
+        if !iceberg.record_fill(trade_id, amount) {
+            return Ok(false);
+        }
+        iceberg.id.clone()
+    };
+
+    let slice = orderbook.reduce_order(order_id, amount).await?;
+    if slice.amount.is_zero() {
+        if let Some(iceberg) = manager.write().await.icebergs.get_mut(&id) {
+            iceberg.current_slice = None;
+        }
+        post_next_slice(manager, orderbook, &id).await?;
+    }
+
+    manager.read().await.save()?;
+    if let Some(file) = order_store {
+        own_orders::save(file, &orderbook.own_signed_orders().await)?;
+    }
+
+    Ok(true)
+}
+
+/// Re-post the next slice of active icebergs whose visible slice is gone
+///
+/// Called on start, since the slices of a previous run may have expired or been
+/// withdrawn in the meantime.
+pub async fn resume(manager: &RwLock<IcebergManager>, orderbook: &Orderbook) -> Result<usize> {
+    let active: Vec<IcebergOrder> = manager.read().await.list()
+        .into_iter()
+        .filter(|iceberg| iceberg.status == IcebergStatus::Active)
+        .collect();
+
+    let mut posted = 0;
+    for iceberg in active {
+        let visible = match &iceberg.current_slice {
+            Some(slice) => orderbook.get_order(slice).await
+                .map(|order| order.status == super::OrderStatus::Open)
+                .unwrap_or(false),
+            None => false,
+        };
+        if !visible {
+            post_next_slice(manager, orderbook, &iceberg.id).await?;
+            posted += 1;
+        }
+    }
+
+    manager.read().await.save()?;
+    Ok(posted)
+}
+
+/// Track fills of iceberg slices from trade completion events
+pub fn spawn_iceberg_manager(
+    manager: Arc<RwLock<IcebergManager>>,
+    orderbook: Arc<Orderbook>,
+    trade_manager: Arc<TradeModule>,
+    mut events: EventReceiver,
+    order_store: Option<Arc<DocumentFile>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let trade_id = match event {
+                Event::TradeCompleted(trade_id) => trade_id,
+                _ => continue,
+            };
+
+            let trade = match trade_manager.get_trade(&trade_id).await {
+                Ok(trade) => trade,
+                Err(e) => {
+                    warn!("Failed to get completed trade {}: {}", trade_id, e);
+                    continue;
+                }
+            };
+
+            // A batched trade fills each of its legs' orders
+            let fills: Vec<(OrderId, Decimal)> = if trade.is_batch() {
+                trade.legs.iter().map(|leg| (leg.order_id.clone(), leg.amount)).collect()
+            } else {
+                vec![(trade.order_id.clone(), trade.amount)]
+            };
+
+            for (order_id, amount) in fills {
+                if let Err(e) = handle_fill(
+                    &manager,
+                    &orderbook,
+                    &order_id,
+                    &trade.id,
+                    amount,
+                    order_store.as_deref(),
+                ).await {
+                    warn!("Failed to update iceberg order {} after trade {}: {}", order_id, trade_id, e);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn iceberg() -> IcebergOrder {
+        IcebergOrder::new(Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(10), dec!(4), dec!(100), None, 1_700_000_000).unwrap()
+    }
+
+    #[test]
+    fn test_new_validates_sizes() {
+        let new = |total, slice| IcebergOrder::new(Asset::Bitcoin, Asset::Rune(1), OrderSide::Buy, total, slice, dec!(1), None, 1_700_000_000);
+
+        assert!(new(dec!(10), dec!(4)).is_ok());
+        assert!(new(dec!(10), dec!(0)).is_err());
+        assert!(new(dec!(10), dec!(11)).is_err());
+        assert!(new(dec!(0), dec!(0)).is_err());
+    }
+
+    #[test]
+    fn test_slices_shrink_to_the_remaining_amount() {
+        let mut iceberg = iceberg();
+        assert_eq!(iceberg.next_slice_amount(), dec!(4));
+
+        assert!(iceberg.record_fill(&TradeId("a".to_string()), dec!(4)));
+        assert!(iceberg.record_fill(&TradeId("b".to_string()), dec!(4)));
+        assert_eq!(iceberg.remaining(), dec!(2));
+        assert_eq!(iceberg.next_slice_amount(), dec!(2));
+        assert_eq!(iceberg.status, IcebergStatus::Active);
+
+        assert!(iceberg.record_fill(&TradeId("c".to_string()), dec!(2)));
+        assert_eq!(iceberg.status, IcebergStatus::Filled);
+    }
+
+    #[test]
+    fn test_repeated_trade_is_counted_once() {
+        let mut iceberg = iceberg();
+        let trade_id = TradeId("a".to_string());
+
+        assert!(iceberg.record_fill(&trade_id, dec!(1)));
+        assert!(!iceberg.record_fill(&trade_id, dec!(1)));
+        assert_eq!(iceberg.filled_amount, dec!(1));
+    }
+
+    #[test]
+    fn test_find_by_slice_and_cancel() {
+        let mut manager = IcebergManager::new();
+        let mut iceberg = iceberg();
+        iceberg.current_slice = Some(OrderId("slice".to_string()));
+        let id = iceberg.id.clone();
+        manager.add(iceberg);
+
+        assert!(manager.find_by_slice(&OrderId("slice".to_string())).is_some());
+        assert!(manager.cancel(&id).is_ok());
+        assert!(manager.find_by_slice(&OrderId("slice".to_string())).is_none());
+        assert!(manager.cancel(&id).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+//! Order interest filters for light peers
+//!
+//! A browser or mobile peer rarely cares about more than a few pairs or makers,
+//! yet following the order topic makes it receive and verify every order of
+//! the network. Instead, a light peer sends full peers a bloom filter of the
+//! pairs and makers it wants over the interest protocol. A full peer keeps the
+//! filter and forwards each signed order it accepts that matches to the light
+//! peer directly. The filter only ever lets extra orders through, never drops
+//! a wanted one, and it does not list the light peer's interests in the clear.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::envelope::{self, Versioned};
+use libp2p::PeerId;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{Order, OrderMessage, Orderbook, OrderbookError, SignedOrder};
+use crate::events::EventReceiver;
+use crate::types::{Asset, Event};
+
+/// Request-response protocol interest filters and matching orders travel over
+pub const INTEREST_PROTOCOL: &str = "/darkswap/orders/interest/1.0.0";
+
+/// Largest filter accepted (bytes)
+pub const MAX_FILTER_BYTES: usize = 36_000;
+
+/// Most hash functions a filter may use
+pub const MAX_FILTER_HASHES: u32 = 50;
+
+/// Most light peers a full peer keeps filters of
+pub const MAX_FILTERED_PEERS: usize = 256;
+
+/// Bloom filter of items
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    /// Bit field
+    bits: Vec<u8>,
+    /// Number of hash functions
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized for `items` items at a false positive rate of `fp_rate`
+    pub fn new(items: usize, fp_rate: f64) -> Self {
+        let items = items.max(1) as f64;
+        let fp_rate = fp_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        let bits = (-items * fp_rate.ln() / (ln2 * ln2)).ceil();
+        let bytes = ((bits / 8.0).ceil() as usize).clamp(1, MAX_FILTER_BYTES);
+        let hashes = ((bytes * 8) as f64 / items * ln2).round() as u32;
+
+        Self { bits: vec![0; bytes], hashes: hashes.clamp(1, MAX_FILTER_HASHES) }
+    }
+
+    /// Add an item
+    pub fn insert(&mut self, item: &[u8]) {
+        for index in self.indexes(item) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// Check if an item may have been added; false positives are possible
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.indexes(item).all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Check that the filter is within the limits full peers accept
+    pub fn validate(&self) -> Result<(), OrderbookError> {
+        if self.bits.is_empty() || self.bits.len() > MAX_FILTER_BYTES {
+            return Err(OrderbookError::Other(format!("Interest filter of {} bytes is out of bounds", self.bits.len())));
+        }
+        if self.hashes == 0 || self.hashes > MAX_FILTER_HASHES {
+            return Err(OrderbookError::Other(format!("Interest filter with {} hashes is out of bounds", self.hashes)));
+        }
+
+        Ok(())
+    }
+
+    /// Get the bit positions of an item, by double hashing its SHA-256
+    fn indexes(&self, item: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = Sha256::digest(item);
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&hash[..8]);
+        let first = u64::from_le_bytes(word);
+        word.copy_from_slice(&hash[8..16]);
+        let second = u64::from_le_bytes(word);
+
+        let bits = (self.bits.len() * 8) as u64;
+        (0..u64::from(self.hashes)).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bits) as usize)
+    }
+}
+
+/// Get the filter item of a pair
+pub fn pair_item(base_asset: &Asset, quote_asset: &Asset) -> Vec<u8> {
+    format!("pair:{}/{}", base_asset, quote_asset).into_bytes()
+}
+
+/// Get the filter item of a maker
+pub fn maker_item(maker: &str) -> Vec<u8> {
+    format!("maker:{}", maker).into_bytes()
+}
+
+/// Build the filter of a light peer's pairs and makers
+pub fn interest_filter(pairs: &[(Asset, Asset)], makers: &[String], fp_rate: f64) -> BloomFilter {
+    let mut filter = BloomFilter::new(pairs.len() + makers.len(), fp_rate);
+    for (base_asset, quote_asset) in pairs {
+        filter.insert(&pair_item(base_asset, quote_asset));
+    }
+    for maker in makers {
+        filter.insert(&maker_item(maker));
+    }
+
+    filter
+}
+
+/// Check if an order matches a filter by its pair or its maker
+pub fn matches(filter: &BloomFilter, order: &Order) -> bool {
+    filter.contains(&pair_item(&order.base_asset, &order.quote_asset)) || filter.contains(&maker_item(&order.maker))
+}
+
+/// Interest protocol message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum InterestMessage {
+    /// Filter of the orders the sender wants forwarded, replacing any previous one
+    SetFilter(BloomFilter),
+    /// Stop forwarding orders to the sender
+    ClearFilter,
+    /// Order matching the recipient's filter
+    Order(SignedOrder),
+}
+
+impl Versioned for InterestMessage {
+    const KIND: &'static str = "darkswap/orderbook/interest";
+    const VERSION: u32 = 1;
+}
+
+impl Orderbook {
+    /// Send a filter of the orders this peer wants to `peers`, returning the number reached
+    pub async fn announce_interest(&self, peers: &[PeerId], filter: &BloomFilter) -> Result<usize> {
+        filter.validate()?;
+        let message_data = envelope::encode(&InterestMessage::SetFilter(filter.clone()))
+            .context("Failed to serialize interest filter")?;
+
+        let mut reached = 0;
+        let mut network = self.network.write().await;
+        for peer in peers {
+            match network.send_request(peer, INTEREST_PROTOCOL, message_data.clone()).await {
+                Ok(()) => reached += 1,
+                Err(e) => warn!("Failed to send interest filter to {}: {}", peer, e),
+            }
+        }
+
+        Ok(reached)
+    }
+
+    /// Handle an interest protocol message from a peer
+    pub async fn handle_interest_message(&self, message: InterestMessage, peer_id: &str) -> Result<()> {
+        match message {
+            InterestMessage::SetFilter(filter) => {
+                filter.validate()?;
+
+                let mut filters = self.interest_filters.write().await;
+                if !filters.contains_key(peer_id) && filters.len() >= MAX_FILTERED_PEERS {
+                    return Err(OrderbookError::Other("Too many light peers".to_string()).into());
+                }
+                debug!("Forwarding matching orders to light peer {}", peer_id);
+                filters.insert(peer_id.to_string(), filter);
+            }
+            InterestMessage::ClearFilter => {
+                self.interest_filters.write().await.remove(peer_id);
+            }
+            InterestMessage::Order(signed_order) => {
+                self.handle_order_message(OrderMessage::SignedOrder(signed_order), peer_id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget the filter of a peer, e.g. once it disconnected
+    pub async fn forget_interest(&self, peer_id: &str) {
+        self.interest_filters.write().await.remove(peer_id);
+    }
+
+    /// Forward a signed order to the light peers whose filter it matches, except `from`
+    pub(super) async fn forward_to_interested(&self, signed_order: &SignedOrder, from: Option<&str>) {
+        let peers: Vec<String> = self.interest_filters.read().await
+            .iter()
+            .filter(|(peer_id, filter)| Some(peer_id.as_str()) != from && matches(filter, &signed_order.order))
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+        if peers.is_empty() {
+            return;
+        }
+
+        let message_data = match envelope::encode(&InterestMessage::Order(signed_order.clone())) {
+            Ok(message_data) => message_data,
+            Err(e) => {
+                warn!("Failed to serialize order {} for light peers: {}", signed_order.order.id, e);
+                return;
+            }
+        };
+
+        let mut network = self.network.write().await;
+        for peer_id in peers {
+            let sent = match peer_id.parse::<PeerId>() {
+                Ok(peer) => network.send_request(&peer, INTEREST_PROTOCOL, message_data.clone()).await,
+                Err(e) => Err(anyhow::anyhow!("Invalid peer ID: {}", e)),
+            };
+            if let Err(e) = sent {
+                warn!("Failed to forward order {} to light peer {}: {}", signed_order.order.id, peer_id, e);
+            }
+        }
+    }
+}
+
+/// Spawn a task forgetting the filters of light peers as they disconnect
+pub fn spawn_interest_cleanup(orderbook: Arc<Orderbook>, mut events: EventReceiver) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let Event::PeerDisconnected(peer_id) = event {
+                orderbook.forget_interest(&peer_id.0.to_string()).await;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+    use rust_decimal_macros::dec;
+
+    fn order(maker: &str, quote_asset: Asset) -> Order {
+        Order::new_at(
+            maker.to_string(),
+            Asset::Bitcoin,
+            quote_asset,
+            OrderSide::Sell,
+            dec!(1),
+            dec!(100),
+            None,
+            1_700_000_000,
+        )
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(100, 0.01);
+        assert!(filter.validate().is_ok());
+
+        for n in 0..100u32 {
+            filter.insert(&n.to_le_bytes());
+        }
+        assert!((0..100u32).all(|n| filter.contains(&n.to_le_bytes())));
+
+        // About 1% of other items match
+        let false_positives = (100..10_100u32).filter(|n| filter.contains(&n.to_le_bytes())).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        // Oversized filters are refused
+        let oversized = BloomFilter { bits: vec![0; MAX_FILTER_BYTES + 1], hashes: 1 };
+        assert!(oversized.validate().is_err());
+        let too_many_hashes = BloomFilter { bits: vec![0; 8], hashes: MAX_FILTER_HASHES + 1 };
+        assert!(too_many_hashes.validate().is_err());
+    }
+
+    #[test]
+    fn test_interest_filter_matches() {
+        let filter = interest_filter(&[(Asset::Bitcoin, Asset::Rune(1))], &["maker-b".to_string()], 0.0001);
+
+        assert!(matches(&filter, &order("maker-a", Asset::Rune(1))));
+        assert!(matches(&filter, &order("maker-b", Asset::Rune(2))));
+        assert!(!matches(&filter, &order("maker-a", Asset::Rune(2))));
+    }
+}
@@ -0,0 +1,429 @@
+//! Order matching
+//!
+//! A taker's order is matched against the resting orders of a pair book in
+//! price-time priority: best price first, and the earliest order first at equal
+//! prices. Matching only plans the fills; each fill is then settled as a trade
+//! with the order's maker.
+//!
+//! Embedders add their own rules through [`MatchHooks`]. Pre-match hooks run in
+//! the order they were registered, after the built-in ranking: each sees the
+//! taker order and the candidates the previous one left, and may drop or
+//! reorder them, e.g. to keep out makers of another jurisdiction. The first
+//! hook to refuse the match stops it, and its [`MatchRefused`] is returned
+//! without any later hook running. Post-match hooks then see the planned fills
+//! of every match that was not refused, in registration order; they observe
+//! and cannot fail the match.
+//!
+//! The hooks run wherever a taker meets resting orders: market orders, route
+//! quotes and their requoted hops, and orders taken directly, including
+//! private group orders, revealed dark orders and accepted quotes. A direct
+//! take is a match against its one order, refused if a hook drops it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::view::PairBook;
+use super::{Order, OrderId, OrderSide};
+use crate::types::Asset;
+
+/// Planned fill of a resting order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fill {
+    /// Resting order
+    pub order_id: OrderId,
+    /// Price of the resting order
+    pub price: Decimal,
+    /// Amount taken from the resting order
+    pub amount: Decimal,
+}
+
+/// Result of matching a taker order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchResult {
+    /// Fills, in the order they were matched
+    pub fills: Vec<Fill>,
+    /// Amount left unmatched
+    pub remaining: Decimal,
+}
+
+impl MatchResult {
+    /// Get the matched amount
+    pub fn filled(&self) -> Decimal {
+        self.fills.iter().map(|fill| fill.amount).sum()
+    }
+}
+
+/// Taker order being matched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchRequest {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Side of the taker
+    pub side: OrderSide,
+    /// Amount to match; for a route hop spending an amount of the quote asset,
+    /// the amount that buys at the best ask
+    pub amount: Decimal,
+    /// Worst price to take
+    pub limit_price: Option<Decimal>,
+}
+
+/// Refusal of a match by a pre-match hook
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Match refused: {0}")]
+pub struct MatchRefused(pub String);
+
+/// Hook run before a taker order is matched
+pub trait PreMatchHook: Send + Sync {
+    /// Check the taker order and adjust the resting orders it may match
+    ///
+    /// `candidates` holds the resting orders in priority order, borrowed from
+    /// the book. Dropping one keeps it from being matched; moving one changes
+    /// its priority.
+    fn before_match(&self, request: &MatchRequest, candidates: &mut Vec<&Order>) -> Result<(), MatchRefused>;
+}
+
+/// Hook run after a taker order was matched
+pub trait PostMatchHook: Send + Sync {
+    /// Observe the planned fills of a match
+    fn after_match(&self, request: &MatchRequest, result: &MatchResult);
+}
+
+/// Hooks run around matching, in registration order
+#[derive(Clone, Default)]
+pub struct MatchHooks {
+    /// Pre-match hooks
+    pre: Vec<Arc<dyn PreMatchHook>>,
+    /// Post-match hooks
+    post: Vec<Arc<dyn PostMatchHook>>,
+}
+
+impl MatchHooks {
+    /// Add a hook run before matching, after the hooks added so far
+    pub fn with_pre_match(mut self, hook: Arc<dyn PreMatchHook>) -> Self {
+        self.pre.push(hook);
+        self
+    }
+
+    /// Add a hook run after matching, after the hooks added so far
+    pub fn with_post_match(mut self, hook: Arc<dyn PostMatchHook>) -> Self {
+        self.post.push(hook);
+        self
+    }
+
+    /// Check if no hook is registered
+    pub fn is_empty(&self) -> bool {
+        self.pre.is_empty() && self.post.is_empty()
+    }
+
+    /// Match a taker order against ranked candidates, running the hooks around it
+    pub fn run(&self, request: &MatchRequest, mut candidates: Vec<&Order>) -> Result<MatchResult, MatchRefused> {
+        self.before(request, &mut candidates)?;
+
+        let result = fill(candidates.into_iter(), request.side, request.amount, request.limit_price);
+        self.after(request, &result);
+
+        Ok(result)
+    }
+
+    /// Run the pre-match hooks, for matching that plans its fills itself
+    pub fn before<'a>(&self, request: &MatchRequest, candidates: &mut Vec<&'a Order>) -> Result<(), MatchRefused> {
+        for hook in &self.pre {
+            hook.before_match(request, candidates)?;
+        }
+
+        Ok(())
+    }
+
+    /// Run the post-match hooks on the fills planned after [`MatchHooks::before`]
+    pub fn after(&self, request: &MatchRequest, result: &MatchResult) {
+        for hook in &self.post {
+            hook.after_match(request, result);
+        }
+    }
+
+    /// Run the hooks on a taker taking `amount` of `order` directly
+    ///
+    /// The take is refused if a pre-match hook drops the order.
+    pub fn take(&self, order: &Order, amount: Decimal) -> Result<(), MatchRefused> {
+        let request = MatchRequest {
+            base_asset: order.base_asset.clone(),
+            quote_asset: order.quote_asset.clone(),
+            side: order.side.opposite(),
+            amount,
+            limit_price: Some(order.price),
+        };
+        let mut candidates = vec![order];
+        self.before(&request, &mut candidates)?;
+        if !candidates.iter().any(|candidate| candidate.id == order.id) {
+            return Err(MatchRefused(format!("Order {} was kept out by a match hook", order.id)));
+        }
+
+        let taken = amount.min(order.amount);
+        self.after(&request, &MatchResult {
+            fills: vec![Fill { order_id: order.id.clone(), price: order.price, amount: taken }],
+            remaining: amount - taken,
+        });
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for MatchHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MatchHooks")
+            .field("pre", &self.pre.len())
+            .field("post", &self.post.len())
+            .finish()
+    }
+}
+
+/// Get the resting orders a taker on `side` matches, best price first
+pub fn resting(book: &PairBook, side: OrderSide) -> &[Order] {
+    match side {
+        OrderSide::Buy => book.asks(),
+        OrderSide::Sell => book.bids(),
+    }
+}
+
+/// Match a taker order against a pair book
+///
+/// A buy takes the asks at or below `limit_price`, a sell takes the bids at or
+/// above it; without a limit price any price is taken. A non-positive amount
+/// matches nothing.
+pub fn match_order(
+    book: &PairBook,
+    side: OrderSide,
+    amount: Decimal,
+    limit_price: Option<Decimal>,
+) -> MatchResult {
+    fill(resting(book, side).iter(), side, amount, limit_price)
+}
+
+/// Match a taker order, preferring makers with a low estimated latency
+///
+/// Price still comes first; among orders at the same price, makers with a lower
+/// latency in `latencies` (milliseconds by maker peer ID) are taken first, and
+/// makers never measured last. With `max_latency`, makers measured above it are
+/// skipped.
+pub fn match_order_by_latency(
+    book: &PairBook,
+    side: OrderSide,
+    amount: Decimal,
+    limit_price: Option<Decimal>,
+    latencies: &HashMap<String, u64>,
+    max_latency: Option<u64>,
+) -> MatchResult {
+    let ranked = rank_by_latency(book, side, latencies, max_latency);
+
+    fill(ranked.into_iter(), side, amount, limit_price)
+}
+
+/// Rank the resting orders a taker on `side` matches by price, then maker latency
+///
+/// See [`match_order_by_latency`].
+pub fn rank_by_latency<'a>(
+    book: &'a PairBook,
+    side: OrderSide,
+    latencies: &HashMap<String, u64>,
+    max_latency: Option<u64>,
+) -> Vec<&'a Order> {
+    let latency = |order: &Order| latencies.get(&order.maker).copied();
+
+    let mut ranked: Vec<&Order> = resting(book, side)
+        .iter()
+        .filter(|order| match (latency(order), max_latency) {
+            (Some(latency), Some(max_latency)) => latency <= max_latency,
+            _ => true,
+        })
+        .collect();
+
+    // The sort is stable, so equally fast makers keep their time priority
+    ranked.sort_by_key(|order| {
+        let price = match side {
+            OrderSide::Buy => order.price,
+            OrderSide::Sell => -order.price,
+        };
+        (price, latency(order).unwrap_or(u64::MAX))
+    });
+
+    ranked
+}
+
+/// Fill a taker order from resting orders in priority order
+fn fill<'a>(
+    resting: impl Iterator<Item = &'a Order>,
+    side: OrderSide,
+    amount: Decimal,
+    limit_price: Option<Decimal>,
+) -> MatchResult {
+    let acceptable: fn(Decimal, Decimal) -> bool = match side {
+        OrderSide::Buy => |price, limit| price <= limit,
+        OrderSide::Sell => |price, limit| price >= limit,
+    };
+
+    let mut remaining = amount.max(Decimal::ZERO);
+    let mut fills = Vec::new();
+
+    for order in resting {
+        if remaining.is_zero() {
+            break;
+        }
+
+        // Hooks may have moved a worse price ahead, so skip rather than stop
+        if limit_price.map_or(false, |limit| !acceptable(order.price, limit)) {
+            continue;
+        }
+
+        if order.amount <= Decimal::ZERO || order.is_expired() {
+            continue;
+        }
+
+        let taken = remaining.min(order.amount);
+        fills.push(Fill {
+            order_id: order.id.clone(),
+            price: order.price,
+            amount: taken,
+        });
+        remaining -= taken;
+    }
+
+    MatchResult { fills, remaining }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::view::BookView;
+    use crate::orderbook::Order;
+    use crate::types::Asset;
+    use rust_decimal_macros::dec;
+
+    fn order(side: OrderSide, amount: Decimal, price: Decimal) -> Order {
+        Order::new("maker".to_string(), Asset::Bitcoin, Asset::Rune(1), side, amount, price, None)
+    }
+
+    #[test]
+    fn test_match_respects_limit_and_partial_fill() {
+        let cheap = order(OrderSide::Sell, dec!(1), dec!(100));
+        let dear = order(OrderSide::Sell, dec!(2), dec!(101));
+        let out_of_range = order(OrderSide::Sell, dec!(5), dec!(110));
+        let view = BookView::default()
+            .with_order(&dear)
+            .with_order(&out_of_range)
+            .with_order(&cheap);
+        let book = view.pair(&Asset::Bitcoin, &Asset::Rune(1)).unwrap();
+
+        let result = match_order(book, OrderSide::Buy, dec!(2.5), Some(dec!(105)));
+        assert_eq!(result.fills.len(), 2);
+        assert_eq!(result.fills[0].order_id, cheap.id);
+        assert_eq!(result.fills[1].amount, dec!(1.5));
+        assert_eq!(result.remaining, dec!(0));
+
+        let result = match_order(book, OrderSide::Buy, dec!(10), Some(dec!(105)));
+        assert_eq!(result.filled(), dec!(3));
+        assert_eq!(result.remaining, dec!(7));
+
+        assert!(match_order(book, OrderSide::Sell, dec!(1), None).fills.is_empty());
+    }
+
+    #[test]
+    fn test_match_prefers_fast_makers_at_equal_price() {
+        let mut slow = order(OrderSide::Sell, dec!(1), dec!(100));
+        slow.maker = "slow".to_string();
+        let mut fast = order(OrderSide::Sell, dec!(1), dec!(100));
+        fast.maker = "fast".to_string();
+        fast.timestamp = slow.timestamp + 1;
+        let mut unmeasured = order(OrderSide::Sell, dec!(1), dec!(100));
+        unmeasured.maker = "unmeasured".to_string();
+        let mut cheaper = order(OrderSide::Sell, dec!(1), dec!(99));
+        cheaper.maker = "slow".to_string();
+        let view = BookView::default()
+            .with_order(&unmeasured)
+            .with_order(&slow)
+            .with_order(&fast)
+            .with_order(&cheaper);
+        let book = view.pair(&Asset::Bitcoin, &Asset::Rune(1)).unwrap();
+
+        let latencies: HashMap<String, u64> = [("slow".to_string(), 900), ("fast".to_string(), 40)].into_iter().collect();
+
+        // Price comes first, then latency
+        let result = match_order_by_latency(book, OrderSide::Buy, dec!(4), None, &latencies, None);
+        let order_ids: Vec<OrderId> = result.fills.into_iter().map(|fill| fill.order_id).collect();
+        assert_eq!(order_ids, vec![cheaper.id.clone(), fast.id.clone(), slow.id.clone(), unmeasured.id.clone()]);
+
+        // Makers measured above the maximum are skipped, unmeasured ones are not
+        let result = match_order_by_latency(book, OrderSide::Buy, dec!(4), None, &latencies, Some(500));
+        let order_ids: Vec<OrderId> = result.fills.into_iter().map(|fill| fill.order_id).collect();
+        assert_eq!(order_ids, vec![fast.id, unmeasured.id]);
+        assert_eq!(result.remaining, dec!(2));
+    }
+
+    /// Drops one maker's orders and counts the matches it saw
+    struct Jurisdiction(&'static str, std::sync::atomic::AtomicUsize);
+
+    impl PreMatchHook for Jurisdiction {
+        fn before_match(&self, request: &MatchRequest, candidates: &mut Vec<&Order>) -> Result<(), MatchRefused> {
+            self.1.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if request.amount > dec!(100) {
+                return Err(MatchRefused("amount above the jurisdiction limit".to_string()));
+            }
+            candidates.retain(|order| order.maker != self.0);
+            Ok(())
+        }
+    }
+
+    /// Records the filled amounts
+    struct Recorder(std::sync::Mutex<Vec<Decimal>>);
+
+    impl PostMatchHook for Recorder {
+        fn after_match(&self, _request: &MatchRequest, result: &MatchResult) {
+            self.0.lock().unwrap().push(result.filled());
+        }
+    }
+
+    #[test]
+    fn test_match_hooks() {
+        let mut blocked = order(OrderSide::Sell, dec!(1), dec!(99));
+        blocked.maker = "blocked".to_string();
+        let allowed = order(OrderSide::Sell, dec!(1), dec!(100));
+        let candidates = vec![&blocked, &allowed];
+
+        let first = Arc::new(Jurisdiction("blocked", Default::default()));
+        let second = Arc::new(Jurisdiction("nobody", Default::default()));
+        let recorder = Arc::new(Recorder(Default::default()));
+        let hooks = MatchHooks::default()
+            .with_pre_match(first.clone())
+            .with_pre_match(second.clone())
+            .with_post_match(recorder.clone());
+
+        let request = MatchRequest {
+            base_asset: Asset::Bitcoin,
+            quote_asset: Asset::Rune(1),
+            side: OrderSide::Buy,
+            amount: dec!(2),
+            limit_price: None,
+        };
+        let result = hooks.run(&request, candidates.clone()).unwrap();
+        assert_eq!(result.fills.len(), 1);
+        assert_eq!(result.fills[0].order_id, allowed.id);
+        assert_eq!(*recorder.0.lock().unwrap(), vec![dec!(1)]);
+
+        // The first refusal stops the match before later hooks run
+        let too_large = MatchRequest { amount: dec!(500), ..request };
+        assert!(hooks.run(&too_large, candidates).is_err());
+        assert_eq!(first.1.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(second.1.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(recorder.0.lock().unwrap().len(), 1);
+
+        // A direct take is refused if its order is dropped
+        assert!(hooks.take(&blocked, dec!(1)).is_err());
+        hooks.take(&allowed, dec!(2)).unwrap();
+        assert_eq!(*recorder.0.lock().unwrap(), vec![dec!(1), dec!(1)]);
+    }
+}
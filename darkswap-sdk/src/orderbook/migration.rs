@@ -0,0 +1,250 @@
+//! Moving open orders between nodes
+//!
+//! A maker moving to another machine exports its open orders from the old
+//! node as a bundle signed with its identity key, and imports the bundle on
+//! the new node, which runs under the same identity. The new node cancels
+//! every order of the bundle, so peers drop the announcements of the old node,
+//! and announces the same quotes again as new orders it signed itself, with
+//! what is left of their lifetime. Orders the new node already knows to be
+//! filled or canceled, and expired orders, are not carried over.
+
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::snapshot::SignedOrder;
+use super::{OrderId, OrderStatus, Orderbook, OrderbookError};
+
+/// Domain separator of order bundle signatures
+const BUNDLE_DOMAIN: &[u8] = b"darkswap-order-bundle-v1\n";
+
+/// Open orders of a maker, exported to move them to another node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBundle {
+    /// Maker peer ID
+    pub maker: String,
+    /// Time of export (unix seconds)
+    pub exported_at: u64,
+    /// Orders, with their maker signatures
+    pub orders: Vec<SignedOrder>,
+}
+
+/// Order bundle signed with the maker's identity key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedOrderBundle {
+    /// Bundle
+    pub bundle: OrderBundle,
+    /// Maker identity key (protobuf encoding, hex)
+    pub public_key: String,
+    /// Signature over the bundle (hex)
+    pub signature: String,
+}
+
+impl SignedOrderBundle {
+    /// Sign a bundle with the maker's identity key
+    pub fn sign(bundle: OrderBundle, keypair: &Keypair) -> Result<Self, OrderbookError> {
+        if PeerId::from(keypair.public()).to_string() != bundle.maker {
+            return Err(OrderbookError::InvalidOrder("Signing key does not match bundle maker".to_string()));
+        }
+
+        let signature = keypair
+            .sign(&signing_bytes(&bundle)?)
+            .map_err(|e| OrderbookError::Other(format!("Failed to sign order bundle: {}", e)))?;
+
+        Ok(Self {
+            bundle,
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Check that the maker signed the bundle and every order in it
+    pub fn verify(&self) -> Result<(), OrderbookError> {
+        let public_key_bytes = hex::decode(&self.public_key)
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid public key encoding: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes)
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid public key: {}", e)))?;
+        if PeerId::from_public_key(&public_key).to_string() != self.bundle.maker {
+            return Err(OrderbookError::InvalidOrder("Signing key does not match bundle maker".to_string()));
+        }
+
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid signature encoding: {}", e)))?;
+        if !public_key.verify(&signing_bytes(&self.bundle)?, &signature) {
+            return Err(OrderbookError::InvalidOrder("Invalid order bundle signature".to_string()));
+        }
+
+        for signed_order in &self.bundle.orders {
+            if signed_order.order.maker != self.bundle.maker {
+                return Err(OrderbookError::InvalidOrder(format!(
+                    "Order {} of the bundle has another maker",
+                    signed_order.order.id
+                )));
+            }
+            signed_order.verify()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the bytes covered by the bundle signature
+fn signing_bytes(bundle: &OrderBundle) -> Result<Vec<u8>, OrderbookError> {
+    let body = serde_json::to_vec(bundle)
+        .map_err(|e| OrderbookError::Other(format!("Failed to serialize order bundle: {}", e)))?;
+
+    let mut message = BUNDLE_DOMAIN.to_vec();
+    message.extend_from_slice(&body);
+    Ok(message)
+}
+
+/// Order of a bundle announced again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportedOrder {
+    /// ID the old node announced the order under, now canceled
+    pub previous_id: OrderId,
+    /// ID of the order announced by this node
+    pub order_id: OrderId,
+}
+
+/// Order of a bundle not carried over
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedOrder {
+    /// Order ID
+    pub order_id: OrderId,
+    /// Reason
+    pub reason: String,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderImport {
+    /// Orders announced again
+    pub imported: Vec<ImportedOrder>,
+    /// Orders not carried over
+    pub skipped: Vec<SkippedOrder>,
+}
+
+impl Orderbook {
+    /// Export the open orders of this node, signed with its identity key
+    pub async fn export_orders(&self) -> anyhow::Result<SignedOrderBundle> {
+        let orders = self.own_signed_orders().await;
+
+        let network = self.network.read().await;
+        let bundle = OrderBundle {
+            maker: network.local_peer_id().to_string(),
+            exported_at: self.clock.now(),
+            orders,
+        };
+
+        Ok(SignedOrderBundle::sign(bundle, network.keypair())?)
+    }
+
+    /// Import the orders another node of the same maker exported, replacing its announcements with this node's
+    pub async fn import_orders(&self, signed_bundle: &SignedOrderBundle) -> anyhow::Result<OrderImport> {
+        if self.is_read_only() {
+            return Err(OrderbookError::ReadOnly.into());
+        }
+
+        signed_bundle.verify()?;
+        let local_peer_id = self.network.read().await.local_peer_id().to_string();
+        if signed_bundle.bundle.maker != local_peer_id {
+            return Err(OrderbookError::InvalidOrder(format!(
+                "Orders of {} can only be imported by a node with the same identity",
+                signed_bundle.bundle.maker
+            )).into());
+        }
+
+        let now = self.clock.now();
+        let mut import = OrderImport::default();
+        for signed_order in &signed_bundle.bundle.orders {
+            let order = &signed_order.order;
+            let skip = |reason: &str| SkippedOrder { order_id: order.id.clone(), reason: reason.to_string() };
+
+            if order.is_expired_at(now) {
+                import.skipped.push(skip("expired"));
+                continue;
+            }
+
+            // Withdraw the old announcement, remembering it so its gossip is not taken again
+            let known_status = self.orders.read().await.get(&order.id).map(|known| known.status.clone());
+            match known_status {
+                Some(OrderStatus::Open) => self.cancel_order(&order.id).await?,
+                Some(_) => {
+                    import.skipped.push(skip("no longer open"));
+                    continue;
+                }
+                None => {
+                    let mut withdrawn = order.clone();
+                    withdrawn.status = OrderStatus::Canceled;
+                    self.orders.write().await.insert(withdrawn.id.clone(), withdrawn);
+                    self.broadcast_cancel_order(&order.id, &local_peer_id).await?;
+                }
+            }
+
+            let replacement = self.create_referred_order(
+                order.base_asset.clone(),
+                order.quote_asset.clone(),
+                order.side.clone(),
+                order.amount,
+                order.price,
+                Some(order.expiry.saturating_sub(now)),
+                order.referral_code.clone(),
+            ).await?;
+
+            import.imported.push(ImportedOrder {
+                previous_id: order.id.clone(),
+                order_id: replacement.id,
+            });
+        }
+
+        info!(
+            "Imported {} orders, skipped {}",
+            import.imported.len(),
+            import.skipped.len()
+        );
+
+        Ok(import)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Order, OrderSide};
+    use crate::types::Asset;
+    use rust_decimal::Decimal;
+
+    fn bundle(keypair: &Keypair) -> OrderBundle {
+        let maker = PeerId::from(keypair.public()).to_string();
+        let order = Order::new(maker.clone(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, Decimal::ONE, Decimal::ONE, None);
+
+        OrderBundle {
+            maker,
+            exported_at: 1_700_000_000,
+            orders: vec![SignedOrder::sign(order, keypair).unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_bundle_signature() {
+        let keypair = Keypair::generate_ed25519();
+        let signed = SignedOrderBundle::sign(bundle(&keypair), &keypair).unwrap();
+        signed.verify().unwrap();
+
+        // Only the maker can sign its bundle
+        let other = Keypair::generate_ed25519();
+        assert!(SignedOrderBundle::sign(bundle(&keypair), &other).is_err());
+
+        // Orders cannot be added to a signed bundle
+        let mut tampered = signed.clone();
+        tampered.bundle.orders.extend(bundle(&keypair).orders);
+        assert!(tampered.verify().is_err());
+
+        // Nor can another maker's orders be passed off as the signer's
+        let mut foreign = SignedOrderBundle::sign(bundle(&other), &other).unwrap();
+        foreign.bundle.maker = signed.bundle.maker.clone();
+        assert!(foreign.verify().is_err());
+    }
+}
@@ -3,24 +3,60 @@
 //! This module provides orderbook functionality for DarkSwap, including order creation,
 //! cancellation, and matching.
 
+// Order messages are handled on the swarm task, which must not panic
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+
+#[cfg(not(feature = "light-orderbook"))]
 mod runes_alkanes;
+pub mod announcement;
+pub mod cosign;
+pub mod dark;
+pub mod digest;
+pub mod gossip_cache;
+pub mod group;
+pub mod iceberg;
+pub mod interest;
+pub mod matching;
+pub mod migration;
+pub mod own_orders;
+pub mod retention;
+pub mod routing;
+pub mod scheduler;
+pub mod snapshot;
+pub mod view;
+pub mod watchlist;
 
 use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context as AnyhowContext, Result};
+use bitcoin::Network;
+use darkswap_support::envelope::{self, Versioned};
+use darkswap_support::storage::{Storage, StorageExt};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::sync::{mpsc, RwLock};
 use uuid::Uuid;
 
+use crate::audit::{AuditLog, Observation};
+use crate::clock::{self, Clock};
+use crate::config::{DigestConfig, GossipCacheConfig, RetentionConfig};
 use crate::p2p::P2PNetwork;
+use crate::trade::fees::{self, OrderFees, ReferralShare};
+use crate::trade::psbt::VerificationPool;
 use crate::types::{Asset, Event};
 use crate::wallet::WalletInterface;
 
+use self::cosign::OrderCosigner;
+use self::retention::{ArchivedOrder, BookStats, CompactionReport, ARCHIVE_NAMESPACE};
+use self::view::{BookView, OrderbookView};
+
+pub use snapshot::{OrderbookSnapshot, SignedOrder};
+
 /// Order ID
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct OrderId(pub String);
@@ -40,6 +76,16 @@ pub enum OrderSide {
     Sell,
 }
 
+impl OrderSide {
+    /// Get the side of the counterparty
+    pub fn opposite(self) -> Self {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
 /// Order status
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum OrderStatus {
@@ -53,6 +99,22 @@ pub enum OrderStatus {
     Expired,
 }
 
+/// Hints on how to reach an order's maker
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingHints {
+    /// Relay servers the maker is reachable through
+    pub relays: Vec<String>,
+    /// Region the maker is in
+    pub region: Option<String>,
+}
+
+impl RoutingHints {
+    /// Check if there are no hints
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty() && self.region.is_none()
+    }
+}
+
 /// Order
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
@@ -76,6 +138,18 @@ pub struct Order {
     pub timestamp: u64,
     /// Expiry timestamp
     pub expiry: u64,
+    /// Maker's relay and region hints
+    ///
+    /// Not covered by the maker signature, and left out when empty, so orders
+    /// keep the signed form they had before hints existed.
+    #[serde(default, skip_serializing_if = "RoutingHints::is_empty")]
+    pub hints: RoutingHints,
+    /// Operator fees charged on trades of the order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fees: Option<OrderFees>,
+    /// Referral code of the front-end the order came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referral_code: Option<String>,
 }
 
 impl Order {
@@ -89,11 +163,21 @@ impl Order {
         price: Decimal,
         expiry: Option<u64>,
     ) -> Self {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
+        Self::new_at(maker, base_asset, quote_asset, side, amount, price, expiry, clock::SystemClock.now())
+    }
+
+    /// Create a new order at `now` (unix seconds)
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_at(
+        maker: String,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: Decimal,
+        price: Decimal,
+        expiry: Option<u64>,
+        now: u64,
+    ) -> Self {
         let expiry_time = match expiry {
             Some(expiry) => now + expiry,
             None => now + 86400, // Default expiry: 24 hours
@@ -110,16 +194,19 @@ impl Order {
             status: OrderStatus::Open,
             timestamp: now,
             expiry: expiry_time,
+            hints: RoutingHints::default(),
+            fees: None,
+            referral_code: None,
         }
     }
 
     /// Check if the order is expired
     pub fn is_expired(&self) -> bool {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        
+        self.is_expired_at(clock::SystemClock.now())
+    }
+
+    /// Check if the order is expired at `now` (unix seconds)
+    pub fn is_expired_at(&self, now: u64) -> bool {
         self.expiry < now
     }
 
@@ -129,6 +216,16 @@ impl Order {
     }
 }
 
+/// Order with the estimated latency to its maker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderListing {
+    /// Order
+    #[serde(flatten)]
+    pub order: Order,
+    /// Last measured connect latency to the maker (milliseconds); none if the maker was never dialed
+    pub estimated_latency: Option<u64>,
+}
+
 /// Orderbook error
 #[derive(Debug, Error)]
 pub enum OrderbookError {
@@ -144,6 +241,12 @@ pub enum OrderbookError {
     /// Network error
     #[error("Network error: {0}")]
     NetworkError(String),
+    /// Snapshot is too old
+    #[error("Stale snapshot: {0} seconds old")]
+    StaleSnapshot(u64),
+    /// Node only observes the network
+    #[error("Orderbook is read-only")]
+    ReadOnly,
     /// Other error
     #[error("Orderbook error: {0}")]
     Other(String),
@@ -170,6 +273,13 @@ pub enum OrderMessage {
         /// New amount
         amount: Decimal,
     },
+    /// Order signed by its maker, which may be relayed by any peer
+    SignedOrder(SignedOrder),
+}
+
+impl Versioned for OrderMessage {
+    const KIND: &'static str = "darkswap/orderbook/order";
+    const VERSION: u32 = 1;
 }
 
 /// Orderbook
@@ -180,6 +290,10 @@ pub struct Orderbook {
     buy_orders: Arc<RwLock<BTreeMap<Decimal, Vec<OrderId>>>>,
     /// Sell orders by price (lowest first)
     sell_orders: Arc<RwLock<BTreeMap<Decimal, Vec<OrderId>>>>,
+    /// Maker signatures for orders that can be included in snapshots
+    signed_orders: Arc<RwLock<HashMap<OrderId, SignedOrder>>>,
+    /// Lock-free view of the open orders, republished on every change
+    view: Arc<OrderbookView>,
     /// P2P network
     network: Arc<RwLock<P2PNetwork>>,
     /// Wallet
@@ -188,6 +302,50 @@ pub struct Orderbook {
     event_sender: mpsc::Sender<Event>,
     /// Order topic
     order_topic: String,
+    /// Second signer of a multisig maker account
+    cosigner: Option<Arc<OrderCosigner>>,
+    /// Operator fees disclosed with own orders
+    fees: Option<OrderFees>,
+    /// Referrers sharing the operator fees, by referral code
+    referrers: HashMap<String, ReferralShare>,
+    /// Bitcoin network the fee addresses of orders must belong to
+    bitcoin_network: Network,
+    /// Time source of order expiry
+    clock: Arc<dyn Clock>,
+    /// How long terminal orders are kept in memory
+    retention: RetentionConfig,
+    /// Storage compacted orders are archived to
+    archive: Option<Arc<dyn Storage>>,
+    /// Time each terminal order closed (unix seconds), by order ID
+    closed_at: Arc<RwLock<HashMap<OrderId, u64>>>,
+    /// Compacted orders refused if gossiped again, with the time they expire
+    tombstones: RwLock<HashMap<OrderId, u64>>,
+    /// Orders compacted since start
+    compacted: AtomicU64,
+    /// Orders archived since start
+    archived: AtomicU64,
+    /// Time of the last compaction (unix seconds), zero before the first one
+    last_compaction: AtomicU64,
+    /// Log of observed order messages; a book with a log never creates orders
+    audit: Option<Arc<AuditLog>>,
+    /// Limits of the digest protocol
+    digest: DigestConfig,
+    /// Pool signed orders from peers are checked on
+    verification_pool: VerificationPool,
+    /// Interest filters of light peers, by peer ID
+    interest_filters: Arc<RwLock<HashMap<String, interest::BloomFilter>>>,
+    /// Hooks run around matching
+    match_hooks: matching::MatchHooks,
+    /// Recent signed orders served to late joiners
+    gossip_cache: Option<RwLock<gossip_cache::GossipCache>>,
+    /// Limits of the backfill protocol
+    gossip_cache_config: GossipCacheConfig,
+    /// Key own orders are announced with, with its delegation, instead of the network key
+    announcement: Option<(Arc<dyn announcement::AnnouncementSigner>, announcement::Delegation)>,
+    /// Announcement keys of makers that delegated, pinned when first seen, by maker
+    announcement_keys: Arc<RwLock<HashMap<String, String>>>,
+    /// Storage the pinned announcement keys are kept in
+    announcement_storage: Option<Arc<dyn Storage>>,
 }
 
 impl Orderbook {
@@ -201,10 +359,163 @@ impl Orderbook {
             orders: Arc::new(RwLock::new(HashMap::new())),
             buy_orders: Arc::new(RwLock::new(BTreeMap::new())),
             sell_orders: Arc::new(RwLock::new(BTreeMap::new())),
+            signed_orders: Arc::new(RwLock::new(HashMap::new())),
+            view: Arc::new(OrderbookView::new()),
             network,
             wallet,
             event_sender,
             order_topic: "darkswap/orders/v1".to_string(),
+            cosigner: None,
+            fees: None,
+            referrers: HashMap::new(),
+            bitcoin_network: Network::Bitcoin,
+            clock: clock::system(),
+            retention: RetentionConfig::default(),
+            archive: None,
+            closed_at: Arc::new(RwLock::new(HashMap::new())),
+            tombstones: RwLock::new(HashMap::new()),
+            compacted: AtomicU64::new(0),
+            archived: AtomicU64::new(0),
+            last_compaction: AtomicU64::new(0),
+            audit: None,
+            digest: DigestConfig::default(),
+            verification_pool: VerificationPool::default(),
+            interest_filters: Arc::new(RwLock::new(HashMap::new())),
+            match_hooks: matching::MatchHooks::default(),
+            gossip_cache: None,
+            gossip_cache_config: GossipCacheConfig::default(),
+            announcement: None,
+            announcement_keys: Arc::new(RwLock::new(HashMap::new())),
+            announcement_storage: None,
+        }
+    }
+    
+    /// Run `hooks` around every match, replacing any registered before
+    pub fn with_match_hooks(mut self, hooks: matching::MatchHooks) -> Self {
+        self.match_hooks = hooks;
+        self
+    }
+    
+    /// Add a hook run before matching, after the hooks registered so far
+    pub fn with_pre_match_hook(mut self, hook: Arc<dyn matching::PreMatchHook>) -> Self {
+        self.match_hooks = self.match_hooks.with_pre_match(hook);
+        self
+    }
+    
+    /// Add a hook run after matching, after the hooks registered so far
+    pub fn with_post_match_hook(mut self, hook: Arc<dyn matching::PostMatchHook>) -> Self {
+        self.match_hooks = self.match_hooks.with_post_match(hook);
+        self
+    }
+    
+    /// Get the hooks run around matching, for takes and routes planned outside the orderbook
+    pub fn match_hooks(&self) -> &matching::MatchHooks {
+        &self.match_hooks
+    }
+
+    /// Check the signatures of orders from peers on `pool`, e.g. one shared with the trade manager
+    pub fn with_verification_pool(mut self, pool: VerificationPool) -> Self {
+        self.verification_pool = pool;
+        self
+    }
+
+    /// Require own orders to be co-signed before they are published
+    ///
+    /// Each new order's PSBT is sent to the co-signer, and the order is only
+    /// published once the returned PSBT carries enough valid signatures.
+    pub fn with_cosigner(mut self, cosigner: Arc<OrderCosigner>) -> Self {
+        self.cosigner = Some(cosigner);
+        self
+    }
+
+    /// Announce own orders with the key of `signer`, which `delegation` binds to the network identity
+    pub fn with_announcement_signer(
+        mut self,
+        signer: Arc<dyn announcement::AnnouncementSigner>,
+        delegation: announcement::Delegation,
+    ) -> Self {
+        self.announcement = Some((signer, delegation));
+        self
+    }
+
+    /// Keep the pinned announcement keys of makers in `storage`, loading the stored ones
+    pub fn with_announcement_storage(mut self, storage: Arc<dyn Storage>) -> Result<Self> {
+        let stored = storage.scan_json::<String>(announcement::ANNOUNCEMENT_KEY_NAMESPACE, "")
+            .context("Failed to load announcement keys")?;
+        self.announcement_keys = Arc::new(RwLock::new(stored.into_iter().collect()));
+        self.announcement_storage = Some(storage);
+        Ok(self)
+    }
+
+    /// Charge operator fees on the trades of own orders, disclosing them with each order
+    pub fn with_fees(mut self, fees: OrderFees) -> Self {
+        self.fees = Some(fees);
+        self
+    }
+
+    /// Register the referrers whose codes own orders may carry
+    pub fn with_referrers(mut self, referrers: Vec<ReferralShare>) -> Self {
+        self.referrers = referrers.into_iter().map(|referrer| (referrer.code.clone(), referrer)).collect();
+        self
+    }
+
+    /// Check the fee addresses of orders against `network` instead of mainnet
+    pub fn with_bitcoin_network(mut self, network: Network) -> Self {
+        self.bitcoin_network = network;
+        self
+    }
+
+    /// Read the time from `clock` instead of the wall clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Get the time source of order timestamps and expiry
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Keep terminal orders in memory as `retention` allows, archiving compacted ones to `archive`
+    ///
+    /// Compacted orders are dropped if `archive` is `None`.
+    pub fn with_retention(mut self, retention: RetentionConfig, archive: Option<Arc<dyn Storage>>) -> Self {
+        self.retention = retention;
+        self.archive = archive;
+        self
+    }
+
+    /// Limit the orders fetched from and served to a peer per digest request as `digest` sets
+    pub fn with_digest(mut self, digest: DigestConfig) -> Self {
+        self.digest = digest;
+        self
+    }
+
+    /// Serve the signed orders kept in `cache` to late joiners, within the limits `config` sets
+    ///
+    /// Without a cache, backfill requests are refused but backfilled orders are still accepted.
+    pub fn with_gossip_cache(mut self, config: GossipCacheConfig, cache: Option<gossip_cache::GossipCache>) -> Self {
+        self.gossip_cache_config = config;
+        self.gossip_cache = cache.map(RwLock::new);
+        self
+    }
+
+    /// Record every order message received to `audit`, and refuse to create orders
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Check if the book only observes the network
+    pub fn is_read_only(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Get the co-signed PSBT of an own order
+    pub async fn cosigned_psbt(&self, order_id: &OrderId) -> Option<String> {
+        match &self.cosigner {
+            Some(cosigner) => cosigner.psbt(order_id).await,
+            None => None,
         }
     }
 
@@ -213,6 +524,7 @@ impl Orderbook {
         // Subscribe to order topic
         let mut network = self.network.write().await;
         network.subscribe(&self.order_topic).await?;
+        network.subscribe(digest::DIGEST_TOPIC).await?;
         
         // Start order expiry checker
         self.start_expiry_checker().await?;
@@ -225,7 +537,10 @@ impl Orderbook {
         let orders = self.orders.clone();
         let buy_orders = self.buy_orders.clone();
         let sell_orders = self.sell_orders.clone();
+        let closed_at = self.closed_at.clone();
+        let view = self.view.clone();
         let event_sender = self.event_sender.clone();
+        let clock = self.clock.clone();
         
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(60));
@@ -233,60 +548,26 @@ impl Orderbook {
             loop {
                 interval.tick().await;
                 
-                // Check for expired orders
-                let mut orders_write = orders.write().await;
-                let mut buy_orders_write = buy_orders.write().await;
-                let mut sell_orders_write = sell_orders.write().await;
-                
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                
-                let mut expired_orders = Vec::new();
-                
-                for (order_id, order) in orders_write.iter_mut() {
-                    if order.expiry < now && order.status == OrderStatus::Open {
-                        // Update order status
-                        order.status = OrderStatus::Expired;
-                        expired_orders.push(order_id.clone());
-                        
-                        // Send event
-                        let _ = event_sender
-                            .send(Event::OrderExpired(order_id.clone()))
-                            .await;
-                    }
-                }
-                
-                // Remove expired orders from price maps
-                for order_id in &expired_orders {
-                    if let Some(order) = orders_write.get(order_id) {
-                        match order.side {
-                            OrderSide::Buy => {
-                                if let Some(orders_at_price) = buy_orders_write.get_mut(&order.price) {
-                                    orders_at_price.retain(|id| id != order_id);
-                                    if orders_at_price.is_empty() {
-                                        buy_orders_write.remove(&order.price);
-                                    }
-                                }
-                            }
-                            OrderSide::Sell => {
-                                if let Some(orders_at_price) = sell_orders_write.get_mut(&order.price) {
-                                    orders_at_price.retain(|id| id != order_id);
-                                    if orders_at_price.is_empty() {
-                                        sell_orders_write.remove(&order.price);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+                expire_orders(&orders, &buy_orders, &sell_orders, &closed_at, &view, &event_sender, clock.now()).await;
             }
         });
         
         Ok(())
     }
 
+    /// Expire the open orders past their expiry, as the expiry checker does every minute
+    pub async fn expire_orders(&self) -> Vec<OrderId> {
+        expire_orders(
+            &self.orders,
+            &self.buy_orders,
+            &self.sell_orders,
+            &self.closed_at,
+            &self.view,
+            &self.event_sender,
+            self.clock.now(),
+        ).await
+    }
+
     /// Create a new order
     pub async fn create_order(
         &self,
@@ -297,6 +578,32 @@ impl Orderbook {
         price: Decimal,
         expiry: Option<u64>,
     ) -> Result<Order> {
+        self.create_referred_order(base_asset, quote_asset, side, amount, price, expiry, None).await
+    }
+    
+    /// Create a new order tagged with a referral code
+    ///
+    /// A code registered with `with_referrers` has its referrer share the fees of
+    /// the order's trades; any other well-formed code only tags the order.
+    pub async fn create_referred_order(
+        &self,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: Decimal,
+        price: Decimal,
+        expiry: Option<u64>,
+        referral_code: Option<String>,
+    ) -> Result<Order> {
+        if self.is_read_only() {
+            return Err(OrderbookError::ReadOnly.into());
+        }
+        
+        if let Some(code) = &referral_code {
+            fees::validate_referral_code(code)
+                .map_err(|e| OrderbookError::InvalidOrder(e.to_string()))?;
+        }
+        
         // Check if amount and price are valid
         if amount <= Decimal::ZERO {
             return Err(OrderbookError::InvalidOrder("Amount must be positive".to_string()).into());
@@ -307,11 +614,10 @@ impl Orderbook {
         }
         
         // Get local peer ID
-        let network = self.network.read().await;
-        let local_peer_id = network.local_peer_id().to_string();
+        let local_peer_id = self.network.read().await.local_peer_id().to_string();
         
         // Create order
-        let order = Order::new(
+        let mut order = Order::new_at(
             local_peer_id,
             base_asset,
             quote_asset,
@@ -319,7 +625,25 @@ impl Orderbook {
             amount,
             price,
             expiry,
+            self.clock.now(),
         );
+        order.hints = self.network.read().await.routing_hints();
+        order.fees = self.fees.clone().map(|mut fees| {
+            fees.referral = referral_code.as_ref().and_then(|code| self.referrers.get(code)).cloned();
+            fees
+        });
+        order.referral_code = referral_code;
+        
+        // Have the order co-signed before anything is published
+        if let Some(cosigner) = &self.cosigner {
+            cosigner.cosign(&order).await?;
+        }
+        
+        // Sign order so it can be served in snapshots
+        let network = self.network.read().await;
+        let signed_order = self.sign_own_order(order.clone(), network.keypair()).await?;
+        drop(network);
+        self.signed_orders.write().await.insert(order.id.clone(), signed_order.clone());
         
         // Store order
         let mut orders = self.orders.write().await;
@@ -343,6 +667,10 @@ impl Orderbook {
             }
         }
         
+        // Publish to readers
+        self.view.apply(&order);
+        drop(orders);
+        
         // Send event
         let _ = self.event_sender
             .send(Event::OrderCreated(order.clone()))
@@ -350,6 +678,8 @@ impl Orderbook {
         
         // Broadcast order
         self.broadcast_order(&order).await?;
+        self.forward_to_interested(&signed_order, None).await;
+        self.cache_gossip(&signed_order).await;
         
         Ok(order)
     }
@@ -377,6 +707,8 @@ impl Orderbook {
         
         // Update order status
         order.status = OrderStatus::Canceled;
+        self.view.apply(order);
+        self.closed_at.write().await.insert(order_id.clone(), self.clock.now());
         
         // Remove from price map
         match order.side {
@@ -411,85 +743,302 @@ impl Orderbook {
         Ok(())
     }
 
+    /// Reduce an own order by a filled amount
+    ///
+    /// The remaining amount is re-signed and announced to peers. An order that is
+    /// filled completely is marked filled and withdrawn from the book.
+    pub async fn reduce_order(&self, order_id: &OrderId, filled: Decimal) -> Result<Order> {
+        if filled <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Filled amount must be positive".to_string()).into());
+        }
+
+        // Get order
+        let mut orders = self.orders.write().await;
+        let order = orders.get_mut(order_id)
+            .ok_or_else(|| OrderbookError::NotFound(order_id.clone()))?;
+
+        if order.status != OrderStatus::Open {
+            return Err(OrderbookError::InvalidOrder(format!("Order is not open: {:?}", order.status)).into());
+        }
+
+        // Only the maker tracks fills of its orders
+        let network = self.network.read().await;
+        let local_peer_id = network.local_peer_id().to_string();
+        if order.maker != local_peer_id {
+            return Err(OrderbookError::InvalidOrder("Only the maker can reduce an order".to_string()).into());
+        }
+
+        order.amount = (order.amount - filled).max(Decimal::ZERO);
+        if order.amount.is_zero() {
+            order.status = OrderStatus::Filled;
+            self.closed_at.write().await.insert(order_id.clone(), self.clock.now());
+        }
+        self.view.apply(order);
+        let order = order.clone();
+
+        if order.status == OrderStatus::Filled {
+            self.signed_orders.write().await.remove(order_id);
+        } else {
+            let signed_order = self.sign_own_order(order.clone(), network.keypair()).await?;
+            self.signed_orders.write().await.insert(order_id.clone(), signed_order);
+        }
+        drop(network);
+
+        if order.status == OrderStatus::Filled {
+            // Remove from price map
+            let mut price_map = match order.side {
+                OrderSide::Buy => self.buy_orders.write().await,
+                OrderSide::Sell => self.sell_orders.write().await,
+            };
+            if let Some(orders_at_price) = price_map.get_mut(&order.price) {
+                orders_at_price.retain(|id| id != order_id);
+                if orders_at_price.is_empty() {
+                    price_map.remove(&order.price);
+                }
+            }
+            drop(price_map);
+            drop(orders);
+
+            let _ = self.event_sender
+                .send(Event::OrderFilled(order_id.clone()))
+                .await;
+
+            // Peers drop the order the same way as a canceled one
+            self.broadcast_cancel_order(order_id, &local_peer_id).await?;
+        } else {
+            drop(orders);
+
+            let _ = self.event_sender
+                .send(Event::OrderUpdated(order.clone()))
+                .await;
+
+            self.broadcast_update_order(&order).await?;
+        }
+
+        Ok(order)
+    }
+
     /// Get an order by ID
     pub async fn get_order(&self, order_id: &OrderId) -> Result<Order> {
-        let orders = self.orders.read().await;
-        orders.get(order_id)
-            .cloned()
+        if let Some(order) = self.orders.read().await.get(order_id) {
+            return Ok(order.clone());
+        }
+        
+        // Compacted orders are looked up in the archive
+        self.archived_order(order_id)?
             .ok_or_else(|| OrderbookError::NotFound(order_id.clone()).into())
     }
 
+    /// Get an order from the archive
+    pub fn archived_order(&self, order_id: &OrderId) -> Result<Option<Order>> {
+        match &self.archive {
+            Some(archive) => archive.get_json::<ArchivedOrder>(ARCHIVE_NAMESPACE, &order_id.0)
+                .map(|archived| archived.map(|archived| archived.order))
+                .context("Failed to read the order archive"),
+            None => Ok(None),
+        }
+    }
+
+    /// Take the terminal orders beyond the retention policy out of memory
+    ///
+    /// Each order is archived before it is removed, so an archive failure
+    /// leaves the orders not yet archived in the book. Removed orders leave a
+    /// tombstone until they expire, and the archive is pruned to its cap.
+    pub async fn compact(&self) -> Result<CompactionReport> {
+        let now = self.clock.now();
+        let mut report = CompactionReport::default();
+        
+        let mut orders = self.orders.write().await;
+        let mut closed_at = self.closed_at.write().await;
+        
+        // Orders loaded closed, or closed by a path that did not stamp them, close now
+        for order in orders.values().filter(|order| retention::is_terminal(order)) {
+            closed_at.entry(order.id.clone()).or_insert(now);
+        }
+        let selected = retention::select_for_compaction(&orders, &closed_at, &self.retention, now);
+        
+        let mut removed = Vec::with_capacity(selected.len());
+        let mut tombstones = Vec::with_capacity(selected.len());
+        let mut result = Ok(());
+        for order_id in selected {
+            let order = match orders.get(&order_id) {
+                Some(order) => order,
+                None => continue,
+            };
+            let closed = closed_at.get(&order_id).copied().unwrap_or(now);
+            if let Some(archive) = &self.archive {
+                let archived = ArchivedOrder { order: order.clone(), closed_at: closed };
+                if let Err(e) = archive.put_json(ARCHIVE_NAMESPACE, &order_id.0, &archived) {
+                    result = Err(anyhow::Error::new(e).context(format!("Failed to archive order {}", order_id)));
+                    break;
+                }
+                report.archived += 1;
+            }
+            tombstones.push((order_id.clone(), order.expiry));
+            orders.remove(&order_id);
+            closed_at.remove(&order_id);
+            removed.push(order_id);
+        }
+        drop(closed_at);
+        drop(orders);
+        
+        retention::add_tombstones(&mut *self.tombstones.write().await, tombstones, now);
+        
+        let mut signed_orders = self.signed_orders.write().await;
+        for order_id in &removed {
+            signed_orders.remove(order_id);
+        }
+        drop(signed_orders);
+        if let Some(cosigner) = &self.cosigner {
+            for order_id in &removed {
+                if let Err(e) = cosigner.forget(order_id).await {
+                    log::warn!("Failed to forget co-signed order {}: {}", order_id, e);
+                }
+            }
+        }
+        
+        report.removed = removed.len();
+        self.compacted.fetch_add(report.removed as u64, Ordering::Relaxed);
+        self.archived.fetch_add(report.archived as u64, Ordering::Relaxed);
+        self.last_compaction.store(now, Ordering::Relaxed);
+        
+        if let Some(archive) = &self.archive {
+            if report.archived > 0 && self.retention.max_archived > 0 {
+                let archived: Vec<(String, ArchivedOrder)> = archive.scan_json(ARCHIVE_NAMESPACE, "")
+                    .context("Failed to read the order archive")?;
+                for key in retention::select_for_pruning(&archived, self.retention.max_archived) {
+                    archive.delete(ARCHIVE_NAMESPACE, key.as_bytes())
+                        .context("Failed to prune the order archive")?;
+                    report.pruned += 1;
+                }
+            }
+            if report.archived > 0 {
+                archive.flush().context("Failed to flush the order archive")?;
+            }
+        }
+        
+        result.map(|_| report)
+    }
+
+    /// Get the size of the book
+    pub async fn stats(&self) -> BookStats {
+        let orders = self.orders.read().await;
+        let terminal = orders.values().filter(|order| retention::is_terminal(order)).count();
+        let last_compaction = self.last_compaction.load(Ordering::Relaxed);
+        
+        BookStats {
+            open: orders.len() - terminal,
+            terminal,
+            signed: self.signed_orders.read().await.len(),
+            compacted: self.compacted.load(Ordering::Relaxed),
+            archived: self.archived.load(Ordering::Relaxed),
+            last_compaction: (last_compaction > 0).then_some(last_compaction),
+        }
+    }
+
     /// Get orders for a pair
     pub async fn get_orders(&self, base_asset: &Asset, quote_asset: &Asset) -> Result<Vec<Order>> {
-        let orders = self.orders.read().await;
+        let view = self.view.load();
         
-        let filtered_orders = orders.values()
-            .filter(|order| {
-                order.base_asset == *base_asset && 
-                order.quote_asset == *quote_asset &&
-                order.status == OrderStatus::Open
-            })
-            .cloned()
-            .collect();
+        let orders = view.pair(base_asset, quote_asset)
+            .map(|book| book.orders().cloned().collect())
+            .unwrap_or_default();
         
-        Ok(filtered_orders)
+        Ok(orders)
     }
 
     /// Get all orders
     pub async fn get_all_orders(&self) -> Result<Vec<Order>> {
-        let orders = self.orders.read().await;
-        
-        let filtered_orders = orders.values()
-            .filter(|order| order.status == OrderStatus::Open)
-            .cloned()
-            .collect();
+        let view = self.view.load();
         
-        Ok(filtered_orders)
+        Ok(view.orders().cloned().collect())
     }
 
     /// Get best bid and ask for a pair
     pub async fn get_best_bid_ask(&self, base_asset: &Asset, quote_asset: &Asset) -> Result<(Option<Decimal>, Option<Decimal>)> {
-        let orders = self.orders.read().await;
-        let buy_orders = self.buy_orders.read().await;
-        let sell_orders = self.sell_orders.read().await;
-        
-        // Find best bid (highest buy price)
-        let mut best_bid = None;
-        for (price, order_ids) in buy_orders.iter().rev() {
-            for order_id in order_ids {
-                if let Some(order) = orders.get(order_id) {
-                    if order.base_asset == *base_asset && 
-                       order.quote_asset == *quote_asset &&
-                       order.status == OrderStatus::Open {
-                        best_bid = Some(*price);
-                        break;
-                    }
-                }
-            }
-            if best_bid.is_some() {
-                break;
-            }
-        }
+        let view = self.view.load();
         
-        // Find best ask (lowest sell price)
-        let mut best_ask = None;
-        for (price, order_ids) in sell_orders.iter() {
-            for order_id in order_ids {
-                if let Some(order) = orders.get(order_id) {
-                    if order.base_asset == *base_asset && 
-                       order.quote_asset == *quote_asset &&
-                       order.status == OrderStatus::Open {
-                        best_ask = Some(*price);
-                        break;
-                    }
-                }
-            }
-            if best_ask.is_some() {
-                break;
-            }
+        Ok(view.pair(base_asset, quote_asset)
+            .map(|book| (book.best_bid(), book.best_ask()))
+            .unwrap_or((None, None)))
+    }
+
+    /// List the pairs with live orders, local and received over gossip, most orders first
+    pub fn list_markets(&self) -> Vec<view::MarketSummary> {
+        self.view.load().markets()
+    }
+
+    /// Match a taker order against the open orders of a pair
+    ///
+    /// See [`matching::match_order`]; nothing is filled until the trades settle.
+    /// Fails if a pre-match hook refuses the match.
+    pub fn match_order(
+        &self,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        side: OrderSide,
+        amount: Decimal,
+        limit_price: Option<Decimal>,
+    ) -> Result<matching::MatchResult, matching::MatchRefused> {
+        let view = self.view.load();
+        let candidates = match view.pair(base_asset, quote_asset) {
+            Some(book) => matching::resting(book, side).iter().collect(),
+            None => Vec::new(),
+        };
+
+        self.match_hooks.run(&Self::match_request(base_asset, quote_asset, side, amount, limit_price), candidates)
+    }
+
+    /// Match a taker order, preferring makers with a low estimated latency
+    ///
+    /// See [`matching::match_order_by_latency`]. Fails if a pre-match hook refuses the match.
+    #[allow(clippy::too_many_arguments)]
+    pub fn match_order_by_latency(
+        &self,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        side: OrderSide,
+        amount: Decimal,
+        limit_price: Option<Decimal>,
+        latencies: &HashMap<String, u64>,
+        max_latency: Option<u64>,
+    ) -> Result<matching::MatchResult, matching::MatchRefused> {
+        let view = self.view.load();
+        let candidates = match view.pair(base_asset, quote_asset) {
+            Some(book) => matching::rank_by_latency(book, side, latencies, max_latency),
+            None => Vec::new(),
+        };
+
+        self.match_hooks.run(&Self::match_request(base_asset, quote_asset, side, amount, limit_price), candidates)
+    }
+
+    /// Describe a taker order to the match hooks
+    fn match_request(
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        side: OrderSide,
+        amount: Decimal,
+        limit_price: Option<Decimal>,
+    ) -> matching::MatchRequest {
+        matching::MatchRequest {
+            base_asset: base_asset.clone(),
+            quote_asset: quote_asset.clone(),
+            side,
+            amount,
+            limit_price,
         }
-        
-        Ok((best_bid, best_ask))
+    }
+
+    /// Get the topic orders are gossiped on
+    pub fn order_topic(&self) -> &str {
+        &self.order_topic
+    }
+
+    /// Get a consistent view of the open orders without locking
+    ///
+    /// The view is immutable; later changes to the book publish a new view.
+    pub fn view(&self) -> Arc<BookView> {
+        self.view.load()
     }
 
     /// Handle order message
@@ -498,12 +1047,17 @@ impl Orderbook {
         message: OrderMessage,
         peer_id: &str,
     ) -> Result<()> {
+        if let Some(audit) = &self.audit {
+            audit.record(peer_id, Observation::Order(message.clone()))?;
+        }
+        
         match message {
             OrderMessage::NewOrder(order) => {
                 // Check if order is valid
                 if order.maker != peer_id {
                     return Err(OrderbookError::InvalidOrder("Order maker does not match peer ID".to_string()).into());
                 }
+                self.check_undelegated(&order.maker).await?;
                 
                 if order.amount <= Decimal::ZERO {
                     return Err(OrderbookError::InvalidOrder("Amount must be positive".to_string()).into());
@@ -513,39 +1067,18 @@ impl Orderbook {
                     return Err(OrderbookError::InvalidOrder("Price must be positive".to_string()).into());
                 }
                 
+                self.validate_fees(&order)?;
+                
                 // Check if order is expired
-                if order.is_expired() {
+                if order.is_expired_at(self.clock.now()) {
                     return Ok(());
                 }
                 
-                // Store order
-                let mut orders = self.orders.write().await;
-                
-                // Check if order already exists
-                if orders.contains_key(&order.id) {
+                // Store order, unless it is known or was compacted
+                if !self.insert_order(order.clone()).await {
                     return Ok(());
                 }
                 
-                orders.insert(order.id.clone(), order.clone());
-                
-                // Add to price map
-                match order.side {
-                    OrderSide::Buy => {
-                        let mut buy_orders = self.buy_orders.write().await;
-                        buy_orders
-                            .entry(order.price)
-                            .or_insert_with(Vec::new)
-                            .push(order.id.clone());
-                    }
-                    OrderSide::Sell => {
-                        let mut sell_orders = self.sell_orders.write().await;
-                        sell_orders
-                            .entry(order.price)
-                            .or_insert_with(Vec::new)
-                            .push(order.id.clone());
-                    }
-                }
-                
                 // Send event
                 let _ = self.event_sender
                     .send(Event::OrderCreated(order))
@@ -571,6 +1104,8 @@ impl Orderbook {
                 
                 // Update order status
                 order.status = OrderStatus::Canceled;
+                self.view.apply(order);
+                self.closed_at.write().await.insert(order_id.clone(), self.clock.now());
                 
                 // Remove from price map
                 match order.side {
@@ -604,6 +1139,7 @@ impl Orderbook {
                 if maker != peer_id {
                     return Err(OrderbookError::InvalidOrder("Order maker does not match peer ID".to_string()).into());
                 }
+                self.check_undelegated(&maker).await?;
                 
                 // Check if amount is valid
                 if amount <= Decimal::ZERO {
@@ -629,24 +1165,233 @@ impl Orderbook {
                 
                 // Update amount
                 order.amount = amount;
+                self.view.apply(order);
                 
                 // Send event
                 let _ = self.event_sender
                     .send(Event::OrderUpdated(order.clone()))
                     .await;
             }
+            OrderMessage::SignedOrder(signed_order) => {
+                // The maker signature replaces the sender check, so any peer may relay it
+                let checked = signed_order.clone();
+                self.verification_pool.run(move || checked.verify()).await??;
+                
+                // Expired orders are dropped silently
+                if signed_order.order.is_expired_at(self.clock.now()) {
+                    return Ok(());
+                }
+                
+                self.validate_signed_order(&signed_order.order)?;
+                self.check_announcement_key(&signed_order).await?;
+                
+                let order = signed_order.order.clone();
+                if !self.insert_order(order.clone()).await {
+                    return Ok(());
+                }
+                self.signed_orders.write().await.insert(order.id.clone(), signed_order.clone());
+                
+                // Send event
+                let _ = self.event_sender
+                    .send(Event::OrderCreated(order))
+                    .await;
+                
+                self.forward_to_interested(&signed_order, Some(peer_id)).await;
+                self.cache_gossip(&signed_order).await;
+            }
         }
         
         Ok(())
     }
 
+    /// Submit an order that was signed by its maker, e.g. on an offline machine
+    pub async fn submit_signed_order(&self, signed_order: SignedOrder) -> Result<Order> {
+        if self.is_read_only() {
+            return Err(OrderbookError::ReadOnly.into());
+        }
+        
+        // Check signature and order
+        signed_order.verify()?;
+        self.validate_signed_order(&signed_order.order)?;
+        self.check_announcement_key(&signed_order).await?;
+        
+        let order = signed_order.order.clone();
+        
+        // Store order
+        if !self.insert_order(order.clone()).await {
+            return Err(OrderbookError::InvalidOrder(format!("Order already exists: {}", order.id)).into());
+        }
+        self.signed_orders.write().await.insert(order.id.clone(), signed_order.clone());
+        
+        // Send event
+        let _ = self.event_sender
+            .send(Event::OrderCreated(order.clone()))
+            .await;
+        
+        // Broadcast signed order
+        let message = OrderMessage::SignedOrder(signed_order.clone());
+        let message_data = envelope::encode(&message)
+            .context("Failed to serialize signed order message")?;
+        
+        let mut network = self.network.write().await;
+        network.publish(&self.order_topic, message_data).await?;
+        drop(network);
+        self.forward_to_interested(&signed_order, None).await;
+        self.cache_gossip(&signed_order).await;
+        
+        Ok(order)
+    }
+
+    /// Check that a signed order can be added to the book
+    fn validate_signed_order(&self, order: &Order) -> Result<()> {
+        if order.amount <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Amount must be positive".to_string()).into());
+        }
+        
+        if order.price <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Price must be positive".to_string()).into());
+        }
+        
+        if order.status != OrderStatus::Open {
+            return Err(OrderbookError::InvalidOrder(format!("Order is not open: {:?}", order.status)).into());
+        }
+        
+        if order.is_expired_at(self.clock.now()) {
+            return Err(OrderbookError::InvalidOrder("Order is expired".to_string()).into());
+        }
+        
+        self.validate_fees(order)
+    }
+
+    /// Check the fees an order discloses: rates within the limit, addresses of our network
+    fn validate_fees(&self, order: &Order) -> Result<()> {
+        if let Some(fees) = &order.fees {
+            fees.validate(self.bitcoin_network)
+                .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid fees: {}", e)))?;
+        }
+        
+        Ok(())
+    }
+
+    /// Create a signed snapshot of the open orders
+    ///
+    /// Only orders whose maker signature is known are included.
+    pub async fn snapshot(&self) -> Result<OrderbookSnapshot> {
+        let now = self.clock.now();
+        let orders = self.orders.read().await;
+        let signed_orders = self.signed_orders.read().await;
+        
+        let snapshot_orders = signed_orders.values()
+            .filter(|signed_order| {
+                orders.get(&signed_order.order.id)
+                    .map(|order| order.status == OrderStatus::Open && !order.is_expired_at(now))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect();
+        
+        Ok(OrderbookSnapshot::new(snapshot_orders, now))
+    }
+
+    /// Get the open orders made by this node, with their signatures
+    pub async fn own_signed_orders(&self) -> Vec<SignedOrder> {
+        let local_peer_id = self.network.read().await.local_peer_id().to_string();
+        let now = self.clock.now();
+        let orders = self.orders.read().await;
+        let signed_orders = self.signed_orders.read().await;
+        
+        signed_orders.values()
+            .filter(|signed_order| signed_order.order.maker == local_peer_id)
+            .filter(|signed_order| {
+                orders.get(&signed_order.order.id)
+                    .map(|order| order.status == OrderStatus::Open && !order.is_expired_at(now))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Load orders from a relay-served snapshot
+    ///
+    /// Returns the number of orders added to the book.
+    pub async fn load_snapshot(&self, snapshot: &OrderbookSnapshot, max_age: u64) -> Result<usize> {
+        // Validate freshness and maker signatures
+        let valid_orders = snapshot.validate_on(&self.verification_pool, max_age, self.clock.now()).await?;
+        
+        let mut loaded = 0;
+        
+        for order in valid_orders {
+            let Some(signed_order) = snapshot.orders.iter().find(|signed_order| signed_order.order.id == order.id) else {
+                continue;
+            };
+            if let Err(e) = self.validate_fees(&order) {
+                log::warn!("Skipping snapshot order {}: {}", order.id, e);
+                continue;
+            }
+            if let Err(e) = self.check_announcement_key(signed_order).await {
+                log::warn!("Skipping snapshot order {}: {}", order.id, e);
+                continue;
+            }
+            
+            // Keep the signature so the order can be passed on in our own snapshots
+            self.signed_orders.write().await
+                .entry(order.id.clone())
+                .or_insert_with(|| signed_order.clone());
+            
+            if self.insert_order(order.clone()).await {
+                loaded += 1;
+                
+                // Send event
+                let _ = self.event_sender
+                    .send(Event::OrderCreated(order))
+                    .await;
+            }
+        }
+        
+        Ok(loaded)
+    }
+
+    /// Insert an order into the book, returning false if it is already known
+    ///
+    /// Compacted orders are known by their tombstones.
+    async fn insert_order(&self, order: Order) -> bool {
+        let mut orders = self.orders.write().await;
+        
+        if orders.contains_key(&order.id) || self.tombstones.read().await.contains_key(&order.id) {
+            return false;
+        }
+        
+        // Add to price map
+        match order.side {
+            OrderSide::Buy => {
+                let mut buy_orders = self.buy_orders.write().await;
+                buy_orders
+                    .entry(order.price)
+                    .or_insert_with(Vec::new)
+                    .push(order.id.clone());
+            }
+            OrderSide::Sell => {
+                let mut sell_orders = self.sell_orders.write().await;
+                sell_orders
+                    .entry(order.price)
+                    .or_insert_with(Vec::new)
+                    .push(order.id.clone());
+            }
+        }
+        
+        self.view.apply(&order);
+        orders.insert(order.id.clone(), order);
+        
+        true
+    }
+
     /// Broadcast an order
     async fn broadcast_order(&self, order: &Order) -> Result<()> {
         // Create order message
         let message = OrderMessage::NewOrder(order.clone());
         
         // Serialize message
-        let message_data = serde_json::to_vec(&message)
+        let message_data = envelope::encode(&message)
             .context("Failed to serialize order message")?;
         
         // Publish message to order topic
@@ -656,6 +1401,26 @@ impl Orderbook {
         Ok(())
     }
 
+    /// Broadcast the new amount of an order
+    async fn broadcast_update_order(&self, order: &Order) -> Result<()> {
+        // Create update message
+        let message = OrderMessage::UpdateOrder {
+            order_id: order.id.clone(),
+            maker: order.maker.clone(),
+            amount: order.amount,
+        };
+
+        // Serialize message
+        let message_data = envelope::encode(&message)
+            .context("Failed to serialize update order message")?;
+
+        // Publish message to order topic
+        let mut network = self.network.write().await;
+        network.publish(&self.order_topic, message_data).await?;
+
+        Ok(())
+    }
+
     /// Broadcast cancel order
     async fn broadcast_cancel_order(&self, order_id: &OrderId, maker: &str) -> Result<()> {
         // Create cancel message
@@ -665,7 +1430,7 @@ impl Orderbook {
         };
         
         // Serialize message
-        let message_data = serde_json::to_vec(&message)
+        let message_data = envelope::encode(&message)
             .context("Failed to serialize cancel order message")?;
         
         // Publish message to order topic
@@ -674,4 +1439,178 @@ impl Orderbook {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Mark the open orders expired at `now` and take them out of the price maps
+async fn expire_orders(
+    orders: &RwLock<HashMap<OrderId, Order>>,
+    buy_orders: &RwLock<BTreeMap<Decimal, Vec<OrderId>>>,
+    sell_orders: &RwLock<BTreeMap<Decimal, Vec<OrderId>>>,
+    closed_at: &RwLock<HashMap<OrderId, u64>>,
+    view: &OrderbookView,
+    event_sender: &mpsc::Sender<Event>,
+    now: u64,
+) -> Vec<OrderId> {
+    let mut orders_write = orders.write().await;
+    let mut buy_orders_write = buy_orders.write().await;
+    let mut sell_orders_write = sell_orders.write().await;
+    
+    let mut expired_orders = Vec::new();
+    
+    for (order_id, order) in orders_write.iter_mut() {
+        if order.is_expired_at(now) && order.status == OrderStatus::Open {
+            // Update order status
+            order.status = OrderStatus::Expired;
+            closed_at.write().await.insert(order_id.clone(), now);
+            expired_orders.push(order_id.clone());
+            view.apply(order);
+            
+            // Send event
+            let _ = event_sender
+                .send(Event::OrderExpired(order_id.clone()))
+                .await;
+        }
+    }
+    
+    // Remove expired orders from price maps
+    for order_id in &expired_orders {
+        if let Some(order) = orders_write.get(order_id) {
+            let price_map = match order.side {
+                OrderSide::Buy => &mut buy_orders_write,
+                OrderSide::Sell => &mut sell_orders_write,
+            };
+            if let Some(orders_at_price) = price_map.get_mut(&order.price) {
+                orders_at_price.retain(|id| id != order_id);
+                if orders_at_price.is_empty() {
+                    price_map.remove(&order.price);
+                }
+            }
+        }
+    }
+    
+    expired_orders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::config::Config;
+    use crate::trade::fees::MAX_FEE_BPS;
+    use crate::wallet::simple_wallet::SimpleWallet;
+    use libp2p::identity::Keypair;
+    use libp2p::PeerId;
+    use rust_decimal_macros::dec;
+
+    fn orderbook() -> Orderbook {
+        let (event_sender, _) = mpsc::channel(100);
+        let network = Arc::new(RwLock::new(P2PNetwork::new(&Config::default(), event_sender.clone()).unwrap()));
+        let wallet = Arc::new(SimpleWallet::new(None, Config::default().bitcoin.network).unwrap());
+        Orderbook::new(network, wallet, event_sender).with_bitcoin_network(Network::Regtest)
+    }
+
+    fn order_with_fees(maker: &str, taker_fee_bps: u32) -> Order {
+        let mut order = Order::new(maker.to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(1), dec!(100), Some(3600));
+        order.fees = Some(OrderFees {
+            address: "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080".to_string(),
+            maker_fee_bps: 10,
+            taker_fee_bps,
+            referral: None,
+        });
+        order
+    }
+
+    #[tokio::test]
+    async fn test_submit_offline_signed_order() {
+        let orderbook = orderbook();
+        let offline_key = Keypair::generate_ed25519();
+        let maker = PeerId::from(offline_key.public()).to_string();
+        let order = Order::new(maker, Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(1), dec!(100), Some(3600));
+
+        // A payload changed after signing is refused
+        let mut tampered = SignedOrder::sign(order.clone(), &offline_key).unwrap();
+        tampered.order.price = dec!(1);
+        assert!(orderbook.submit_signed_order(tampered).await.is_err());
+        assert!(orderbook.get_order(&order.id).await.is_err());
+
+        let submitted = orderbook.submit_signed_order(SignedOrder::sign(order.clone(), &offline_key).unwrap()).await.unwrap();
+        assert_eq!(submitted.id, order.id);
+        assert_eq!(orderbook.get_order(&order.id).await.unwrap().price, dec!(100));
+        assert_eq!(orderbook.snapshot().await.unwrap().orders.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_compacted_order_is_not_revived_by_new_order() {
+        let retention = RetentionConfig { max_records: 0, archive: false, ..Default::default() };
+        let orderbook = orderbook().with_retention(retention, None);
+        let order = Order::new("maker".to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, dec!(1), dec!(100), Some(3600));
+
+        orderbook.handle_order_message(OrderMessage::NewOrder(order.clone()), "maker").await.unwrap();
+        let cancel = OrderMessage::CancelOrder { order_id: order.id.clone(), maker: "maker".to_string() };
+        orderbook.handle_order_message(cancel, "maker").await.unwrap();
+        assert_eq!(orderbook.compact().await.unwrap().removed, 1);
+
+        // A replayed announcement of the compacted order is ignored
+        orderbook.handle_order_message(OrderMessage::NewOrder(order.clone()), "maker").await.unwrap();
+        assert!(orderbook.get_order(&order.id).await.is_err());
+        assert!(orderbook.get_all_orders().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_inbound_orders_above_the_fee_limit_are_dropped() {
+        let orderbook = orderbook();
+
+        let order = order_with_fees("maker", MAX_FEE_BPS + 1);
+        assert!(orderbook.handle_order_message(OrderMessage::NewOrder(order.clone()), "maker").await.is_err());
+        assert!(orderbook.get_order(&order.id).await.is_err());
+
+        let keypair = Keypair::generate_ed25519();
+        let maker = PeerId::from(keypair.public()).to_string();
+        let signed = SignedOrder::sign(order_with_fees(&maker, MAX_FEE_BPS + 1), &keypair).unwrap();
+        assert!(orderbook.handle_order_message(OrderMessage::SignedOrder(signed.clone()), "relay").await.is_err());
+        assert!(orderbook.submit_signed_order(signed.clone()).await.is_err());
+        let snapshot = OrderbookSnapshot::new(vec![signed], clock::system().now());
+        assert_eq!(orderbook.load_snapshot(&snapshot, 60).await.unwrap(), 0);
+
+        // Fees within the limit, paid to an address of our network, are accepted
+        let order = order_with_fees("maker", MAX_FEE_BPS);
+        orderbook.handle_order_message(OrderMessage::NewOrder(order.clone()), "maker").await.unwrap();
+        assert!(orderbook.get_order(&order.id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_expire_orders_with_mock_clock() {
+        let clock = MockClock::new(1_700_000_000);
+        let order = Order::new_at(
+            "maker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            OrderSide::Buy,
+            dec!(1),
+            dec!(100),
+            Some(3600),
+            clock.now(),
+        );
+        let order_id = order.id.clone();
+
+        let orders = RwLock::new(HashMap::from([(order_id.clone(), order.clone())]));
+        let buy_orders = RwLock::new(BTreeMap::from([(order.price, vec![order_id.clone()])]));
+        let sell_orders = RwLock::new(BTreeMap::new());
+        let closed_at = RwLock::new(HashMap::new());
+        let view = OrderbookView::new();
+        view.apply(&order);
+        let (event_sender, mut event_receiver) = mpsc::channel(8);
+
+        // Not expired until the expiry has passed
+        clock.advance(3600);
+        assert!(expire_orders(&orders, &buy_orders, &sell_orders, &closed_at, &view, &event_sender, clock.now()).await.is_empty());
+
+        clock.advance(1);
+        let expired = expire_orders(&orders, &buy_orders, &sell_orders, &closed_at, &view, &event_sender, clock.now()).await;
+        assert_eq!(expired, vec![order_id.clone()]);
+        assert_eq!(orders.read().await[&order_id].status, OrderStatus::Expired);
+        assert_eq!(closed_at.read().await.get(&order_id), Some(&clock.now()));
+        assert!(buy_orders.read().await.is_empty());
+        assert!(matches!(event_receiver.recv().await, Some(Event::OrderExpired(id)) if id == order_id));
+    }
+}
@@ -0,0 +1,77 @@
+//! Persisted own orders
+//!
+//! The open orders made by this node are kept on disk with their maker signatures,
+//! so a restarted node, or a standby taking over the same identity, can re-announce
+//! them without the user placing them again.
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::DocumentFile;
+
+use super::SignedOrder;
+
+/// Load persisted own orders, dropping the expired ones
+///
+/// A missing file holds no orders.
+pub fn load(file: &DocumentFile) -> Result<Vec<SignedOrder>> {
+    let orders: Vec<SignedOrder> = file.load()
+        .with_context(|| format!("Failed to read order store {}", file.path().display()))?
+        .unwrap_or_default();
+
+    Ok(orders.into_iter().filter(|signed_order| !signed_order.order.is_expired()).collect())
+}
+
+/// Persist own orders, replacing the previous file atomically
+pub fn save(file: &DocumentFile, orders: &[SignedOrder]) -> Result<()> {
+    file.save(&orders)
+        .with_context(|| format!("Failed to write order store {}", file.path().display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Order, OrderSide};
+    use crate::types::Asset;
+    use darkswap_support::storage::MasterKey;
+    use libp2p::identity::Keypair;
+    use libp2p::PeerId;
+    use rust_decimal::Decimal;
+
+    fn signed_order(keypair: &Keypair, expiry: u64) -> SignedOrder {
+        let mut order = Order::new(
+            PeerId::from(keypair.public()).to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            OrderSide::Sell,
+            Decimal::ONE,
+            Decimal::ONE,
+            Some(expiry),
+        );
+        if expiry == 0 {
+            order.expiry = 1;
+        }
+        SignedOrder::sign(order, keypair).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip_drops_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = DocumentFile::new(dir.path().join("orders.json"), Some(MasterKey::generate()));
+        let keypair = Keypair::generate_ed25519();
+
+        assert!(load(&file).unwrap().is_empty());
+
+        let open = signed_order(&keypair, 3600);
+        let expired = signed_order(&keypair, 0);
+        save(&file, &[open.clone(), expired]).unwrap();
+
+        let loaded = load(&file).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].order.id, open.order.id);
+        loaded[0].verify().unwrap();
+
+        // The orders are sealed with the storage key
+        assert!(!std::fs::read(file.path()).unwrap().windows(5).any(|window| window == b"maker"));
+    }
+}
@@ -0,0 +1,247 @@
+//! Retention of terminal orders
+//!
+//! Filled, canceled and expired orders stay in the book so their status can
+//! still be looked up, but they are never matched again. Compaction takes them
+//! out of memory once the retention period has passed since they closed, or
+//! oldest first once more of them are kept than the policy allows, and
+//! archives them to the `orders_archive` storage namespace where
+//! `Orderbook::get_order` still finds them. The archive is capped too: beyond
+//! `max_archived` the orders that closed first are dropped from it.
+//!
+//! A compacted order leaves a tombstone behind, so a copy still gossiped by a
+//! peer is not taken in again as a new order. The tombstone lasts until the
+//! order expires, after which any copy is refused as expired anyway.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RetentionConfig;
+
+use super::{Order, OrderId, OrderStatus, Orderbook};
+
+/// Storage namespace of archived orders
+pub const ARCHIVE_NAMESPACE: &str = "orders_archive";
+
+/// Tombstones kept at most; those of orders expiring first are dropped beyond this
+pub const MAX_TOMBSTONES: usize = 100_000;
+
+/// Order in the archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedOrder {
+    /// The order as it was compacted
+    pub order: Order,
+    /// Time the order closed (unix seconds)
+    pub closed_at: u64,
+}
+
+/// Result of a compaction
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactionReport {
+    /// Orders taken out of memory
+    pub removed: usize,
+    /// Orders written to the archive
+    pub archived: usize,
+    /// Orders dropped from the archive to keep it within its cap
+    pub pruned: usize,
+}
+
+/// Size of the book
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookStats {
+    /// Open orders
+    pub open: usize,
+    /// Filled, canceled and expired orders still in memory
+    pub terminal: usize,
+    /// Orders with a maker signature
+    pub signed: usize,
+    /// Orders compacted since start
+    pub compacted: u64,
+    /// Orders archived since start
+    pub archived: u64,
+    /// Time of the last compaction (unix seconds)
+    pub last_compaction: Option<u64>,
+}
+
+/// Check if an order will not change anymore
+pub fn is_terminal(order: &Order) -> bool {
+    order.status != OrderStatus::Open
+}
+
+/// Select the terminal orders to compact at `now` (unix seconds), earliest closed first
+///
+/// `closed_at` holds the time each terminal order closed; an order missing
+/// from it counts as closed at `now`.
+pub fn select_for_compaction(
+    orders: &HashMap<OrderId, Order>,
+    closed_at: &HashMap<OrderId, u64>,
+    retention: &RetentionConfig,
+    now: u64,
+) -> Vec<OrderId> {
+    let mut terminal: Vec<(u64, &Order)> = orders
+        .values()
+        .filter(|order| is_terminal(order))
+        .map(|order| (closed_at.get(&order.id).copied().unwrap_or(now), order))
+        .collect();
+    terminal.sort_by(|(a_closed, a), (b_closed, b)| a_closed.cmp(b_closed).then_with(|| a.id.0.cmp(&b.id.0)));
+
+    let max_age = retention.max_age_days.saturating_mul(86400);
+    let surplus = terminal.len().saturating_sub(retention.max_records);
+
+    terminal
+        .iter()
+        .enumerate()
+        .filter(|(index, (closed, _))| *index < surplus || closed.saturating_add(max_age) <= now)
+        .map(|(_, (_, order))| order.id.clone())
+        .collect()
+}
+
+/// Select the archive keys to drop so at most `max_archived` orders stay, earliest closed first
+///
+/// A cap of zero keeps every archived order.
+pub fn select_for_pruning(archived: &[(String, ArchivedOrder)], max_archived: usize) -> Vec<String> {
+    if max_archived == 0 || archived.len() <= max_archived {
+        return Vec::new();
+    }
+
+    let mut entries: Vec<&(String, ArchivedOrder)> = archived.iter().collect();
+    entries.sort_by(|(a_key, a), (b_key, b)| a.closed_at.cmp(&b.closed_at).then_with(|| a_key.cmp(b_key)));
+
+    entries
+        .into_iter()
+        .take(archived.len() - max_archived)
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Add the tombstones of compacted orders, with the time each order expires
+///
+/// Tombstones of orders expired at `now` are dropped, and beyond `MAX_TOMBSTONES`
+/// those expiring first.
+pub fn add_tombstones(tombstones: &mut HashMap<OrderId, u64>, compacted: impl IntoIterator<Item = (OrderId, u64)>, now: u64) {
+    tombstones.retain(|_, expires_at| *expires_at >= now);
+    tombstones.extend(compacted);
+
+    if tombstones.len() > MAX_TOMBSTONES {
+        let mut by_expiry: Vec<(u64, OrderId)> = tombstones.iter().map(|(id, expires_at)| (*expires_at, id.clone())).collect();
+        by_expiry.sort_by(|(a_expiry, a), (b_expiry, b)| a_expiry.cmp(b_expiry).then_with(|| a.0.cmp(&b.0)));
+        for (_, order_id) in by_expiry.into_iter().take(tombstones.len() - MAX_TOMBSTONES) {
+            tombstones.remove(&order_id);
+        }
+    }
+}
+
+/// Spawn a task compacting the book every `interval`
+pub fn spawn_compaction(orderbook: Arc<Orderbook>, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            match orderbook.compact().await {
+                Ok(report) if report.removed > 0 => {
+                    debug!(
+                        "Compacted {} terminal orders, {} archived, {} dropped from the archive",
+                        report.removed, report.archived, report.pruned
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to compact the orderbook: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+    use crate::types::Asset;
+    use rust_decimal_macros::dec;
+
+    fn order(status: OrderStatus, timestamp: u64) -> Order {
+        let mut order = Order::new_at(
+            "maker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            OrderSide::Sell,
+            dec!(1),
+            dec!(100),
+            None,
+            timestamp,
+        );
+        order.status = status;
+        order
+    }
+
+    #[test]
+    fn test_select_for_compaction() {
+        let day = 86400;
+        let now = 1_700_000_000;
+        // Every order was created a month ago; they closed at different times
+        let closed = [
+            (OrderStatus::Filled, now - 10 * day),
+            (OrderStatus::Canceled, now - 3 * day),
+            (OrderStatus::Expired, now - 2 * day),
+            (OrderStatus::Filled, now - day),
+        ];
+        let mut orders = HashMap::new();
+        let mut closed_at = HashMap::new();
+        for (status, time) in closed {
+            let order = order(status, now - 30 * day);
+            closed_at.insert(order.id.clone(), time);
+            orders.insert(order.id.clone(), order);
+        }
+        let open = order(OrderStatus::Open, now - 30 * day);
+        orders.insert(open.id.clone(), open);
+        let closed_ago = |age: u64| closed_at.iter().find(|(_, time)| **time == now - age * day).map(|(id, _)| id.clone());
+
+        // Only the order closed before the retention period goes
+        let retention = RetentionConfig { max_age_days: 7, max_records: 10, ..RetentionConfig::default() };
+        assert_eq!(select_for_compaction(&orders, &closed_at, &retention, now), vec![closed_ago(10).unwrap()]);
+
+        // The orders closed first beyond the record limit go too, open orders never do
+        let retention = RetentionConfig { max_age_days: 7, max_records: 2, ..RetentionConfig::default() };
+        assert_eq!(
+            select_for_compaction(&orders, &closed_at, &retention, now),
+            vec![closed_ago(10).unwrap(), closed_ago(3).unwrap()]
+        );
+
+        // An order without a closing time has just closed
+        let retention = RetentionConfig { max_age_days: 7, max_records: 10, ..RetentionConfig::default() };
+        assert!(select_for_compaction(&orders, &HashMap::new(), &retention, now).is_empty());
+    }
+
+    #[test]
+    fn test_select_for_pruning() {
+        let archived: Vec<(String, ArchivedOrder)> = [30, 10, 20]
+            .into_iter()
+            .map(|closed_at| {
+                let order = order(OrderStatus::Filled, 0);
+                (order.id.0.clone(), ArchivedOrder { order, closed_at })
+            })
+            .collect();
+
+        assert!(select_for_pruning(&archived, 0).is_empty());
+        assert!(select_for_pruning(&archived, 3).is_empty());
+        assert_eq!(select_for_pruning(&archived, 1), vec![archived[1].0.clone(), archived[2].0.clone()]);
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let now = 1_700_000_000;
+        let mut tombstones = HashMap::new();
+        let lapsed = OrderId("lapsed".to_string());
+        add_tombstones(&mut tombstones, [(lapsed.clone(), now + 10)], now);
+
+        // Tombstones of orders that expired are dropped
+        let kept = OrderId("kept".to_string());
+        add_tombstones(&mut tombstones, [(kept.clone(), now + 3600)], now + 11);
+        assert!(!tombstones.contains_key(&lapsed));
+        assert!(tombstones.contains_key(&kept));
+    }
+}
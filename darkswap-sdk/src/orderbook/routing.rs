@@ -0,0 +1,650 @@
+//! Cross-asset routing
+//!
+//! Runes mostly trade against bitcoin, so two runes rarely have a market of
+//! their own. A route converts one asset into another through their pair book
+//! if there is one, or in two hops through an intermediate asset, bitcoin by
+//! default: RuneA→BTC, then BTC→RuneB. Quoting walks the books without taking
+//! anything; the combined price and its slippage against the top of each book
+//! tell the user what the route costs before any leg is executed.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+use super::matching::{self, Fill, MatchHooks, MatchRefused, MatchRequest, MatchResult};
+use super::view::BookView;
+use super::{Order, OrderSide, Orderbook};
+use crate::clock::Clock;
+use crate::p2p::P2PNetwork;
+use crate::trade::{batch, Trade, TradeModule, TradeState};
+use crate::types::{Asset, TradeId};
+
+/// Decimal places of asset amounts
+const AMOUNT_SCALE: u32 = 8;
+
+/// Routing error
+#[derive(Debug, Error)]
+pub enum RoutingError {
+    /// Source and destination are the same asset
+    #[error("Cannot route {0} to itself")]
+    SameAsset(Asset),
+    /// No book, direct or through an intermediate asset, connects the assets
+    #[error("No route from {0} to {1}")]
+    NoRoute(Asset, Asset),
+    /// The books connecting the assets are too thin for the amount
+    #[error("Not enough liquidity to convert {amount} {from} to {to}")]
+    InsufficientLiquidity {
+        /// Source asset
+        from: Asset,
+        /// Destination asset
+        to: Asset,
+        /// Amount to convert
+        amount: Decimal,
+    },
+    /// A match hook refused a hop
+    #[error(transparent)]
+    Refused(#[from] MatchRefused),
+}
+
+/// Conversion through one pair book
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hop {
+    /// Asset given
+    pub from: Asset,
+    /// Asset received
+    pub to: Asset,
+    /// Base asset of the book
+    pub base_asset: Asset,
+    /// Quote asset of the book
+    pub quote_asset: Asset,
+    /// Side of the taker order in the book
+    pub side: OrderSide,
+    /// Amount of `from` given
+    pub amount_in: Decimal,
+    /// Amount of `to` received
+    pub amount_out: Decimal,
+    /// Resting orders taken
+    pub fills: Vec<Fill>,
+    /// Price at the top of the book (`to` per `from`)
+    pub best_price: Decimal,
+}
+
+impl Hop {
+    /// Get the average price of the hop (`to` per `from`)
+    pub fn price(&self) -> Decimal {
+        if self.amount_in.is_zero() {
+            return Decimal::ZERO;
+        }
+
+        self.amount_out / self.amount_in
+    }
+
+    /// Get the amounts of `from` given and `to` received by a settled trade of the hop
+    pub fn settled(&self, trade: &Trade) -> (Decimal, Decimal) {
+        let (amount, notional) = if trade.is_batch() {
+            (batch::total_amount(&trade.legs), batch::notional(&trade.legs))
+        } else {
+            (trade.amount, trade.amount * trade.price)
+        };
+
+        match self.side {
+            OrderSide::Sell => (amount, notional),
+            OrderSide::Buy => (notional, amount),
+        }
+    }
+}
+
+/// Quoted route between two assets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    /// Asset given
+    pub from: Asset,
+    /// Asset received
+    pub to: Asset,
+    /// Amount of `from` given
+    pub amount_in: Decimal,
+    /// Amount of `to` received
+    pub amount_out: Decimal,
+    /// Hops, in the order they are executed
+    pub hops: Vec<Hop>,
+    /// Combined price (`to` per `from`)
+    pub price: Decimal,
+    /// Combined price at the top of the books (`to` per `from`)
+    pub best_price: Decimal,
+    /// Shortfall of `price` against `best_price` (basis points)
+    pub slippage_bps: Decimal,
+}
+
+impl Route {
+    /// Get the intermediate asset of a two-hop route
+    pub fn via(&self) -> Option<&Asset> {
+        match self.hops.as_slice() {
+            [first, _] => Some(&first.to),
+            _ => None,
+        }
+    }
+
+    /// Build a route from consecutive hops
+    fn from_hops(hops: Vec<Hop>) -> Option<Self> {
+        let (first, last) = (hops.first()?, hops.last()?);
+        let best_price = hops.iter().map(|hop| hop.best_price).product::<Decimal>();
+        let price = if first.amount_in.is_zero() {
+            Decimal::ZERO
+        } else {
+            last.amount_out / first.amount_in
+        };
+        let slippage_bps = if best_price.is_zero() {
+            Decimal::ZERO
+        } else {
+            ((best_price - price) / best_price * Decimal::from(10_000)).max(Decimal::ZERO)
+        };
+
+        Some(Self {
+            from: first.from.clone(),
+            to: last.to.clone(),
+            amount_in: first.amount_in,
+            amount_out: last.amount_out,
+            price,
+            best_price,
+            slippage_bps,
+            hops,
+        })
+    }
+}
+
+/// Executed hop
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HopExecution {
+    /// Hop as quoted when it was executed
+    pub hop: Hop,
+    /// Trades taking the hop's fills
+    pub trades: Vec<TradeId>,
+    /// Amount of `from` given by the trades that settled
+    pub spent: Decimal,
+    /// Amount of `to` received from the trades that settled
+    pub received: Decimal,
+    /// Whether every trade of the hop settled
+    pub settled: bool,
+    /// Why the hop did not settle
+    pub error: Option<String>,
+}
+
+/// Outcome of executing a route
+///
+/// Settled trades cannot be undone, so a route failing after its first hop
+/// leaves the intermediate asset in the wallet unless it is unwound, i.e.
+/// converted back into the source asset at the price of the day.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RouteOutcome {
+    /// Every hop settled
+    Completed,
+    /// The first hop did not settle; only its settled trades changed hands
+    Failed {
+        /// Why the hop failed
+        reason: String,
+    },
+    /// A later hop failed, leaving an intermediate asset in the wallet
+    Stranded {
+        /// Hop that failed
+        hop: usize,
+        /// Asset left over
+        asset: Asset,
+        /// Amount left over
+        amount: Decimal,
+        /// Why the hop failed
+        reason: String,
+    },
+    /// A later hop failed and the intermediate asset was converted back
+    Unwound {
+        /// Hop that failed
+        hop: usize,
+        /// Amount of the source asset recovered
+        returned: Decimal,
+        /// Why the hop failed
+        reason: String,
+    },
+}
+
+/// Result of executing a route
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteExecution {
+    /// Route as quoted
+    pub route: Route,
+    /// Hops executed, including an unwinding hop
+    pub hops: Vec<HopExecution>,
+    /// Amount of the destination asset received
+    pub amount_out: Decimal,
+    /// How the execution ended
+    pub outcome: RouteOutcome,
+}
+
+/// Quote converting `amount_in` of `from` into `to` through their pair book
+///
+/// With `from` as the base asset the hop sells it into the bids; with `to` as
+/// the base asset it buys `to` from the asks, spending at most `amount_in`.
+/// Either way the resting orders go through `hooks` first.
+pub fn quote_hop(
+    view: &BookView,
+    hooks: &MatchHooks,
+    from: &Asset,
+    to: &Asset,
+    amount_in: Decimal,
+) -> Result<Hop, RoutingError> {
+    let insufficient = || RoutingError::InsufficientLiquidity {
+        from: from.clone(),
+        to: to.clone(),
+        amount: amount_in,
+    };
+
+    if let Some(book) = view.pair(from, to) {
+        let request = MatchRequest {
+            base_asset: from.clone(),
+            quote_asset: to.clone(),
+            side: OrderSide::Sell,
+            amount: amount_in,
+            limit_price: None,
+        };
+        let result = hooks.run(&request, matching::resting(book, OrderSide::Sell).iter().collect())?;
+        let best_price = book.best_bid().ok_or_else(insufficient)?;
+        if !result.remaining.is_zero() || result.fills.is_empty() {
+            return Err(insufficient());
+        }
+
+        let amount_out = result.fills.iter().map(|fill| fill.amount * fill.price).sum();
+        return Ok(Hop {
+            from: from.clone(),
+            to: to.clone(),
+            base_asset: from.clone(),
+            quote_asset: to.clone(),
+            side: OrderSide::Sell,
+            amount_in,
+            amount_out,
+            fills: result.fills,
+            best_price,
+        });
+    }
+
+    if let Some(book) = view.pair(to, from) {
+        let best_ask = book.best_ask().filter(|price| !price.is_zero()).ok_or_else(insufficient)?;
+        let request = MatchRequest {
+            base_asset: to.clone(),
+            quote_asset: from.clone(),
+            side: OrderSide::Buy,
+            amount: amount_in / best_ask,
+            limit_price: None,
+        };
+        let mut candidates = matching::resting(book, OrderSide::Buy).iter().collect();
+        hooks.before(&request, &mut candidates)?;
+        let (fills, spent) = spend(candidates, amount_in);
+        let amount_out = fills.iter().map(|fill| fill.amount).sum();
+        let result = MatchResult { remaining: (request.amount - amount_out).max(Decimal::ZERO), fills };
+        hooks.after(&request, &result);
+        if spent < amount_in || result.fills.is_empty() {
+            return Err(insufficient());
+        }
+
+        return Ok(Hop {
+            from: from.clone(),
+            to: to.clone(),
+            base_asset: to.clone(),
+            quote_asset: from.clone(),
+            side: OrderSide::Buy,
+            amount_in,
+            amount_out,
+            fills: result.fills,
+            best_price: Decimal::ONE / best_ask,
+        });
+    }
+
+    Err(RoutingError::NoRoute(from.clone(), to.clone()))
+}
+
+/// Take asks in priority order until `budget` of the quote asset is spent
+///
+/// Returns the fills and the amount spent. Taken amounts are rounded down to
+/// the amount precision, so the spend can fall short of the budget by dust.
+fn spend(asks: Vec<&Order>, budget: Decimal) -> (Vec<Fill>, Decimal) {
+    let mut fills = Vec::new();
+    let mut spent = Decimal::ZERO;
+
+    for order in asks {
+        let left = budget - spent;
+        if left <= Decimal::ZERO {
+            break;
+        }
+        if order.amount <= Decimal::ZERO || order.price <= Decimal::ZERO || order.is_expired() {
+            continue;
+        }
+
+        let affordable = (left / order.price).round_dp_with_strategy(AMOUNT_SCALE, RoundingStrategy::ToZero);
+        let taken = order.amount.min(affordable);
+        if taken.is_zero() {
+            // Dust left over; nothing more can be bought
+            spent = budget;
+            break;
+        }
+
+        fills.push(Fill {
+            order_id: order.id.clone(),
+            price: order.price,
+            amount: taken,
+        });
+        spent += taken * order.price;
+    }
+
+    (fills, spent)
+}
+
+/// Find the route giving the most of `to` for `amount_in` of `from`
+///
+/// The direct book is tried along with a two-hop route through each of the
+/// `intermediates`; every hop goes through `hooks`.
+pub fn find_route(
+    view: &BookView,
+    hooks: &MatchHooks,
+    from: &Asset,
+    to: &Asset,
+    amount_in: Decimal,
+    intermediates: &[Asset],
+) -> Result<Route, RoutingError> {
+    if from == to {
+        return Err(RoutingError::SameAsset(from.clone()));
+    }
+
+    let mut candidates = vec![quote_hop(view, hooks, from, to, amount_in).map(|hop| vec![hop])];
+    for via in intermediates.iter().filter(|via| *via != from && *via != to) {
+        candidates.push(quote_hop(view, hooks, from, via, amount_in).and_then(|first| {
+            let second = quote_hop(view, hooks, via, to, first.amount_out)?;
+            Ok(vec![first, second])
+        }));
+    }
+
+    let mut best: Option<Route> = None;
+    let mut error = RoutingError::NoRoute(from.clone(), to.clone());
+    for candidate in candidates {
+        match candidate {
+            Ok(hops) => {
+                if let Some(route) = Route::from_hops(hops) {
+                    if best.as_ref().map_or(true, |best| route.amount_out > best.amount_out) {
+                        best = Some(route);
+                    }
+                }
+            }
+            // A thin book or a refusal says more about why routing failed than a missing book
+            Err(e @ (RoutingError::InsufficientLiquidity { .. } | RoutingError::Refused(_))) => error = e,
+            Err(_) => {}
+        }
+    }
+
+    best.ok_or(error)
+}
+
+/// Takes fills and executes routes on shared handles of a node
+///
+/// Executing a route waits for its trades to settle, so it holds no lock on
+/// the node for that long.
+#[derive(Clone)]
+pub struct RouteExecutor {
+    /// Orderbook the fills are taken from
+    orderbook: Arc<Orderbook>,
+    /// Trade manager the trades are made with
+    trade_manager: Arc<TradeModule>,
+    /// P2P network
+    network: Arc<RwLock<P2PNetwork>>,
+    /// Time source of the hop deadlines
+    clock: Arc<dyn Clock>,
+    /// Time a hop waits for its trades to settle (seconds)
+    trade_timeout: u64,
+}
+
+impl RouteExecutor {
+    /// Create an executor
+    pub fn new(
+        orderbook: Arc<Orderbook>,
+        trade_manager: Arc<TradeModule>,
+        network: Arc<RwLock<P2PNetwork>>,
+        clock: Arc<dyn Clock>,
+        trade_timeout: u64,
+    ) -> Self {
+        Self {
+            orderbook,
+            trade_manager,
+            network,
+            clock,
+            trade_timeout,
+        }
+    }
+
+    /// Take fills, one trade per maker with its orders batched
+    pub async fn take_fills(&self, fills: &[Fill], payout_address: Option<String>) -> anyhow::Result<Vec<Trade>> {
+        let mut orders = HashMap::new();
+        for fill in fills {
+            let order = self.orderbook.get_order(&fill.order_id).await?;
+            orders.insert(order.id.clone(), order);
+        }
+
+        let local_peer_id = self.network.read().await.local_peer_id().to_string();
+        let mut trades = Vec::new();
+        for legs in batch::group_fills(fills, &orders) {
+            trades.push(self.trade_manager.create_batch_trade(legs, local_peer_id.clone(), payout_address.clone()).await?);
+        }
+
+        Ok(trades)
+    }
+
+    /// Execute a quoted route hop by hop
+    ///
+    /// Each hop waits for its trades to settle, up to the trade timeout, before
+    /// the next hop is requoted for what was actually received. If a later hop
+    /// fails, the intermediate asset is converted back to the source asset when
+    /// `unwind` is set and left in the wallet otherwise; the outcome says which.
+    pub async fn execute(&self, route: &Route, unwind: bool) -> anyhow::Result<RouteExecution> {
+        let mut hops = Vec::new();
+        let mut asset = route.from.clone();
+        let mut amount = route.amount_in;
+
+        for (index, quoted) in route.hops.iter().enumerate() {
+            // Later hops are requoted, since the books and the amount received may have moved
+            let hop = if index == 0 {
+                Ok(quoted.clone())
+            } else {
+                quote_hop(&self.orderbook.view(), self.orderbook.match_hooks(), &asset, &quoted.to, amount)
+            };
+
+            let execution = match hop {
+                Ok(hop) => self.execute_hop(hop).await?,
+                Err(e) => HopExecution {
+                    hop: quoted.clone(),
+                    trades: Vec::new(),
+                    spent: Decimal::ZERO,
+                    received: Decimal::ZERO,
+                    settled: false,
+                    error: Some(e.to_string()),
+                },
+            };
+
+            if !execution.settled {
+                let reason = execution.error.clone().unwrap_or_default();
+                let left = amount - execution.spent;
+                hops.push(execution);
+
+                let outcome = if index == 0 {
+                    RouteOutcome::Failed { reason }
+                } else {
+                    self.unwind(route, &mut hops, index, asset, left, reason, unwind).await?
+                };
+
+                return Ok(RouteExecution { route: route.clone(), hops, amount_out: Decimal::ZERO, outcome });
+            }
+
+            asset = execution.hop.to.clone();
+            amount = execution.received;
+            hops.push(execution);
+        }
+
+        Ok(RouteExecution { route: route.clone(), hops, amount_out: amount, outcome: RouteOutcome::Completed })
+    }
+
+    /// Convert what a failed route left of an intermediate asset back to the source asset
+    #[allow(clippy::too_many_arguments)]
+    async fn unwind(
+        &self,
+        route: &Route,
+        hops: &mut Vec<HopExecution>,
+        failed: usize,
+        asset: Asset,
+        amount: Decimal,
+        reason: String,
+        unwind: bool,
+    ) -> anyhow::Result<RouteOutcome> {
+        let stranded = |reason: String| RouteOutcome::Stranded { hop: failed, asset: asset.clone(), amount, reason };
+        if !unwind || amount <= Decimal::ZERO {
+            return Ok(stranded(reason));
+        }
+
+        let hop = match quote_hop(&self.orderbook.view(), self.orderbook.match_hooks(), &asset, &route.from, amount) {
+            Ok(hop) => hop,
+            Err(e) => return Ok(stranded(format!("{}; unwinding failed: {}", reason, e))),
+        };
+
+        let execution = self.execute_hop(hop).await?;
+        let outcome = if execution.settled {
+            RouteOutcome::Unwound { hop: failed, returned: execution.received, reason }
+        } else {
+            let error = execution.error.clone().unwrap_or_default();
+            RouteOutcome::Stranded {
+                hop: failed,
+                asset: asset.clone(),
+                amount: amount - execution.spent,
+                reason: format!("{}; unwinding failed: {}", reason, error),
+            }
+        };
+        hops.push(execution);
+
+        Ok(outcome)
+    }
+
+    /// Take the fills of a hop and wait for its trades to settle
+    ///
+    /// Trades still in flight at the trade timeout are canceled.
+    async fn execute_hop(&self, hop: Hop) -> anyhow::Result<HopExecution> {
+        let mut execution = HopExecution {
+            trades: Vec::new(),
+            spent: Decimal::ZERO,
+            received: Decimal::ZERO,
+            settled: false,
+            error: None,
+            hop,
+        };
+
+        let trades = match self.take_fills(&execution.hop.fills, None).await {
+            Ok(trades) => trades,
+            Err(e) => {
+                execution.error = Some(e.to_string());
+                return Ok(execution);
+            }
+        };
+        execution.trades = trades.iter().map(|trade| trade.id.clone()).collect();
+
+        // Wait for every trade to finish
+        let deadline = self.clock.now() + self.trade_timeout.max(1);
+        let finished = |state: TradeState| matches!(
+            state,
+            TradeState::Completed | TradeState::Failed | TradeState::Canceled | TradeState::Expired
+        );
+        let mut trades = Vec::with_capacity(execution.trades.len());
+        loop {
+            trades.clear();
+            for trade_id in &execution.trades {
+                trades.push(self.trade_manager.get_trade(trade_id).await?);
+            }
+            if trades.iter().all(|trade| finished(trade.state)) || self.clock.now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+
+        for trade in &trades {
+            if trade.state == TradeState::Completed {
+                let (spent, received) = execution.hop.settled(trade);
+                execution.spent += spent;
+                execution.received += received;
+            } else if !finished(trade.state) {
+                if let Err(e) = self.trade_manager.cancel_trade(&trade.id, "Route hop timed out").await {
+                    warn!("Failed to cancel trade {}: {}", trade.id, e);
+                }
+            }
+        }
+
+        execution.settled = !trades.is_empty() && trades.iter().all(|trade| trade.state == TradeState::Completed);
+        if !execution.settled {
+            let unsettled = trades.iter().filter(|trade| trade.state != TradeState::Completed).count();
+            execution.error = Some(format!("{} of {} trades did not settle", unsettled, trades.len()));
+        }
+
+        Ok(execution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn order(base_asset: Asset, side: OrderSide, amount: Decimal, price: Decimal) -> Order {
+        Order::new("maker".to_string(), base_asset, Asset::Bitcoin, side, amount, price, None)
+    }
+
+    #[test]
+    fn test_two_hop_route_through_bitcoin() {
+        let orders = vec![
+            // Bids for rune 1, priced in bitcoin
+            order(Asset::Rune(1), OrderSide::Buy, dec!(100), dec!(0.002)),
+            order(Asset::Rune(1), OrderSide::Buy, dec!(100), dec!(0.001)),
+            // Asks of rune 2, priced in bitcoin
+            order(Asset::Rune(2), OrderSide::Sell, dec!(10), dec!(0.01)),
+            order(Asset::Rune(2), OrderSide::Sell, dec!(100), dec!(0.02)),
+        ];
+        let view = BookView::from_orders(&orders);
+
+        let route = find_route(&view, &MatchHooks::default(), &Asset::Rune(1), &Asset::Rune(2), dec!(150), &[Asset::Bitcoin]).unwrap();
+        assert_eq!(route.via(), Some(&Asset::Bitcoin));
+
+        // 150 RUNE:1 sell for 0.2 + 0.05 = 0.25 BTC
+        assert_eq!(route.hops[0].amount_out, dec!(0.25));
+        // 0.25 BTC buy 10 RUNE:2 at 0.01 and 7.5 at 0.02
+        assert_eq!(route.amount_out, dec!(17.5));
+
+        // The top of the books would give 0.002 / 0.01 = 0.2 RUNE:2 per RUNE:1
+        assert_eq!(route.best_price, dec!(0.2));
+        assert!(route.slippage_bps > Decimal::ZERO);
+
+        assert!(matches!(
+            find_route(&view, &MatchHooks::default(), &Asset::Rune(1), &Asset::Rune(2), dec!(1000), &[Asset::Bitcoin]),
+            Err(RoutingError::InsufficientLiquidity { .. })
+        ));
+        assert!(matches!(
+            find_route(&view, &MatchHooks::default(), &Asset::Rune(1), &Asset::Rune(3), dec!(1), &[Asset::Bitcoin]),
+            Err(RoutingError::NoRoute(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_direct_route() {
+        let orders = vec![order(Asset::Rune(1), OrderSide::Sell, dec!(100), dec!(0.001))];
+        let view = BookView::from_orders(&orders);
+
+        let route = find_route(&view, &MatchHooks::default(), &Asset::Bitcoin, &Asset::Rune(1), dec!(0.05), &[Asset::Bitcoin]).unwrap();
+        assert_eq!(route.via(), None);
+        assert_eq!(route.hops[0].side, OrderSide::Buy);
+        assert_eq!(route.amount_out, dec!(50));
+        assert_eq!(route.slippage_bps, Decimal::ZERO);
+    }
+}
@@ -0,0 +1,469 @@
+//! Scheduled and conditional orders
+//!
+//! A scheduled order is kept locally and only posted to the orderbook, and thus
+//! broadcast to peers, once its conditions are met: its activation time has passed
+//! and, for a stop-style order, the reference price of its pair has reached the
+//! trigger price. A schedule whose expiry window closes before it fires is dropped.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::DocumentFile;
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use super::{own_orders, OrderId, OrderSide, Orderbook, OrderbookError};
+use crate::clock::Clock;
+use crate::types::Asset;
+
+/// Scheduled order ID
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ScheduleId(pub String);
+
+impl fmt::Display for ScheduleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Price a trigger is compared against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReferencePrice {
+    /// Best bid of the pair
+    BestBid,
+    /// Best ask of the pair
+    BestAsk,
+    /// Midpoint of the best bid and ask
+    Mid,
+}
+
+/// Direction in which the reference price has to cross the trigger price
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerDirection {
+    /// Fire once the reference price is at or above the trigger price
+    AtOrAbove,
+    /// Fire once the reference price is at or below the trigger price
+    AtOrBelow,
+}
+
+/// Stop-style price trigger
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceTrigger {
+    /// Reference price
+    pub reference: ReferencePrice,
+    /// Direction
+    pub direction: TriggerDirection,
+    /// Trigger price
+    pub price: Decimal,
+}
+
+impl PriceTrigger {
+    /// Check the trigger against the best prices of the pair
+    ///
+    /// A trigger never fires while its reference price is unknown.
+    pub fn is_met(&self, best_bid: Option<Decimal>, best_ask: Option<Decimal>) -> bool {
+        let reference = match self.reference {
+            ReferencePrice::BestBid => best_bid,
+            ReferencePrice::BestAsk => best_ask,
+            ReferencePrice::Mid => best_bid.zip(best_ask).map(|(bid, ask)| (bid + ask) / Decimal::TWO),
+        };
+
+        match (reference, self.direction) {
+            (Some(price), TriggerDirection::AtOrAbove) => price >= self.price,
+            (Some(price), TriggerDirection::AtOrBelow) => price <= self.price,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Order to post when a schedule fires
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderTemplate {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Side
+    pub side: OrderSide,
+    /// Amount
+    pub amount: Decimal,
+    /// Limit price
+    pub price: Decimal,
+    /// Expiry of the posted order, in seconds after posting
+    pub expiry: Option<u64>,
+}
+
+/// Status of a scheduled order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScheduleStatus {
+    /// Waiting for its conditions
+    Pending,
+    /// Posted to the orderbook
+    Posted(OrderId),
+    /// Expiry window closed before the conditions were met
+    Expired,
+    /// Canceled before it fired
+    Canceled,
+}
+
+/// Outcome of checking a pending schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleDecision {
+    /// Conditions not met yet
+    Wait,
+    /// Post the order now
+    Fire,
+    /// Drop the schedule
+    Expire,
+}
+
+/// Scheduled order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledOrder {
+    /// Schedule ID
+    pub id: ScheduleId,
+    /// Order to post
+    pub order: OrderTemplate,
+    /// Earliest time the order is posted (unix seconds)
+    pub activate_at: Option<u64>,
+    /// Price condition
+    pub trigger: Option<PriceTrigger>,
+    /// End of the window in which the schedule can fire (unix seconds)
+    pub expires_at: Option<u64>,
+    /// Status
+    pub status: ScheduleStatus,
+    /// Creation time (unix seconds)
+    pub created_at: u64,
+}
+
+impl ScheduledOrder {
+    /// Create a pending schedule at `now` (unix seconds)
+    pub fn new(
+        order: OrderTemplate,
+        activate_at: Option<u64>,
+        trigger: Option<PriceTrigger>,
+        expires_at: Option<u64>,
+        now: u64,
+    ) -> Result<Self, OrderbookError> {
+        if order.amount <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Amount must be positive".to_string()));
+        }
+        if order.price <= Decimal::ZERO {
+            return Err(OrderbookError::InvalidOrder("Price must be positive".to_string()));
+        }
+        if let Some(trigger) = &trigger {
+            if trigger.price <= Decimal::ZERO {
+                return Err(OrderbookError::InvalidOrder("Trigger price must be positive".to_string()));
+            }
+        }
+
+        let created_at = now;
+        if let Some(expires_at) = expires_at {
+            if expires_at <= activate_at.unwrap_or(created_at) {
+                return Err(OrderbookError::InvalidOrder("Schedule expires before it activates".to_string()));
+            }
+        }
+
+        Ok(Self {
+            id: ScheduleId(Uuid::new_v4().to_string()),
+            order,
+            activate_at,
+            trigger,
+            expires_at,
+            status: ScheduleStatus::Pending,
+            created_at,
+        })
+    }
+
+    /// Check whether a pending schedule fires at `now` with the given best prices
+    pub fn evaluate(&self, now: u64, best_bid: Option<Decimal>, best_ask: Option<Decimal>) -> ScheduleDecision {
+        if self.status != ScheduleStatus::Pending {
+            return ScheduleDecision::Wait;
+        }
+
+        if self.expires_at.map_or(false, |expires_at| now >= expires_at) {
+            return ScheduleDecision::Expire;
+        }
+
+        if self.activate_at.map_or(false, |activate_at| now < activate_at) {
+            return ScheduleDecision::Wait;
+        }
+
+        match &self.trigger {
+            Some(trigger) if !trigger.is_met(best_bid, best_ask) => ScheduleDecision::Wait,
+            _ => ScheduleDecision::Fire,
+        }
+    }
+}
+
+/// Scheduled orders of this node
+#[derive(Debug, Default)]
+pub struct OrderScheduler {
+    /// File the schedules are persisted to; `None` keeps them in memory only
+    file: Option<DocumentFile>,
+    /// Schedules by ID
+    orders: HashMap<ScheduleId, ScheduledOrder>,
+}
+
+impl OrderScheduler {
+    /// Create an in-memory scheduler
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a scheduler persisted at `path`, loading it if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_file(DocumentFile::plain(path))
+    }
+
+    /// Open a scheduler persisted in `file`, sealed if storage is encrypted
+    pub fn open_file(file: DocumentFile) -> Result<Self> {
+        let orders: Vec<ScheduledOrder> = file.load()
+            .with_context(|| format!("Failed to read schedule store {}", file.path().display()))?
+            .unwrap_or_default();
+
+        Ok(Self {
+            file: Some(file),
+            orders: orders.into_iter().map(|order| (order.id.clone(), order)).collect(),
+        })
+    }
+
+    /// Save the schedules to disk, if they are persisted
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        file.save(&self.list())
+            .with_context(|| format!("Failed to write schedule store {}", file.path().display()))?;
+
+        Ok(())
+    }
+
+    /// Add a schedule
+    pub fn add(&mut self, order: ScheduledOrder) {
+        self.orders.insert(order.id.clone(), order);
+    }
+
+    /// Cancel a pending schedule
+    pub fn cancel(&mut self, id: &ScheduleId) -> Result<ScheduledOrder, OrderbookError> {
+        let order = self.orders.get_mut(id)
+            .ok_or_else(|| OrderbookError::InvalidOrder(format!("Unknown schedule: {}", id)))?;
+
+        if order.status != ScheduleStatus::Pending {
+            return Err(OrderbookError::InvalidOrder(format!("Schedule {} is no longer pending", id)));
+        }
+
+        order.status = ScheduleStatus::Canceled;
+        Ok(order.clone())
+    }
+
+    /// Get a schedule
+    pub fn get(&self, id: &ScheduleId) -> Option<&ScheduledOrder> {
+        self.orders.get(id)
+    }
+
+    /// List all schedules, oldest first
+    pub fn list(&self) -> Vec<ScheduledOrder> {
+        let mut orders: Vec<ScheduledOrder> = self.orders.values().cloned().collect();
+        orders.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.0.cmp(&b.id.0)));
+        orders
+    }
+
+    /// Get the pending schedules
+    fn pending(&self) -> Vec<ScheduledOrder> {
+        self.list().into_iter().filter(|order| order.status == ScheduleStatus::Pending).collect()
+    }
+
+    /// Set the status of a schedule
+    fn set_status(&mut self, id: &ScheduleId, status: ScheduleStatus) {
+        if let Some(order) = self.orders.get_mut(id) {
+            order.status = status;
+        }
+    }
+}
+
+/// Check the pending schedules and post the orders whose conditions are met
+///
+/// Returns the number of orders posted. Posted orders are added to the own-order
+/// store, if there is one.
+pub async fn run_once(
+    scheduler: &RwLock<OrderScheduler>,
+    orderbook: &Orderbook,
+    order_store: Option<&DocumentFile>,
+) -> Result<usize> {
+    let pending = scheduler.read().await.pending();
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let view = orderbook.view();
+    let now = orderbook.clock().now();
+    let mut posted = 0;
+    let mut changed = false;
+
+    for schedule in pending {
+        let (best_bid, best_ask) = view
+            .pair(&schedule.order.base_asset, &schedule.order.quote_asset)
+            .map(|book| (book.best_bid(), book.best_ask()))
+            .unwrap_or((None, None));
+
+        match schedule.evaluate(now, best_bid, best_ask) {
+            ScheduleDecision::Wait => continue,
+            ScheduleDecision::Expire => {
+                info!("Scheduled order {} expired before it fired", schedule.id);
+                scheduler.write().await.set_status(&schedule.id, ScheduleStatus::Expired);
+            }
+            ScheduleDecision::Fire => {
+                let template = &schedule.order;
+
+                // Hold the write lock while posting, so a cancel cannot slip in
+                // between the status check and the order going out
+                let mut scheduler = scheduler.write().await;
+                if scheduler.get(&schedule.id).map(|order| &order.status) != Some(&ScheduleStatus::Pending) {
+                    continue;
+                }
+
+                match orderbook.create_order(
+                    template.base_asset.clone(),
+                    template.quote_asset.clone(),
+                    template.side,
+                    template.amount,
+                    template.price,
+                    template.expiry,
+                ).await {
+                    Ok(order) => {
+                        info!("Scheduled order {} posted as {}", schedule.id, order.id);
+                        scheduler.set_status(&schedule.id, ScheduleStatus::Posted(order.id));
+                        posted += 1;
+                    }
+                    Err(e) if is_permanent(&e) => {
+                        // The order would be rejected again, so do not retry it forever
+                        warn!("Failed to post scheduled order {}: {}", schedule.id, e);
+                        scheduler.set_status(&schedule.id, ScheduleStatus::Canceled);
+                    }
+                    Err(e) => {
+                        warn!("Failed to post scheduled order {}, retrying: {}", schedule.id, e);
+                        continue;
+                    }
+                }
+            }
+        }
+
+        changed = true;
+    }
+
+    if changed {
+        scheduler.read().await.save()?;
+    }
+
+    if posted > 0 {
+        if let Some(file) = order_store {
+            own_orders::save(file, &orderbook.own_signed_orders().await)?;
+        }
+    }
+
+    Ok(posted)
+}
+
+/// Whether posting an order failed for a reason a retry will not fix
+fn is_permanent(error: &OrderbookError) -> bool {
+    matches!(error, OrderbookError::InvalidOrder(_) | OrderbookError::ReadOnly)
+}
+
+/// Check the pending schedules on an interval
+pub fn spawn_scheduler(
+    scheduler: Arc<RwLock<OrderScheduler>>,
+    orderbook: Arc<Orderbook>,
+    order_store: Option<Arc<DocumentFile>>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = run_once(&scheduler, &orderbook, order_store.as_deref()).await {
+                warn!("Order scheduler failed: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn template() -> OrderTemplate {
+        OrderTemplate {
+            base_asset: Asset::Bitcoin,
+            quote_asset: Asset::Rune(1),
+            side: OrderSide::Sell,
+            amount: dec!(1),
+            price: dec!(95),
+            expiry: None,
+        }
+    }
+
+    fn stop(direction: TriggerDirection, price: Decimal) -> Option<PriceTrigger> {
+        Some(PriceTrigger { reference: ReferencePrice::Mid, direction, price })
+    }
+
+    #[test]
+    fn test_activation_time_and_expiry_window() {
+        let start = NOW + 100;
+        let order = ScheduledOrder::new(template(), Some(start), None, Some(start + 60), NOW).unwrap();
+
+        assert_eq!(order.evaluate(start - 1, None, None), ScheduleDecision::Wait);
+        assert_eq!(order.evaluate(start, None, None), ScheduleDecision::Fire);
+        assert_eq!(order.evaluate(start + 60, None, None), ScheduleDecision::Expire);
+
+        assert!(ScheduledOrder::new(template(), Some(start), None, Some(start), NOW).is_err());
+    }
+
+    #[test]
+    fn test_stop_trigger() {
+        let order = ScheduledOrder::new(template(), None, stop(TriggerDirection::AtOrBelow, dec!(100)), None, NOW).unwrap();
+        let now = NOW;
+
+        // Unknown and higher reference prices do not fire
+        assert_eq!(order.evaluate(now, Some(dec!(99)), None), ScheduleDecision::Wait);
+        assert_eq!(order.evaluate(now, Some(dec!(100)), Some(dec!(102))), ScheduleDecision::Wait);
+        assert_eq!(order.evaluate(now, Some(dec!(99)), Some(dec!(101))), ScheduleDecision::Fire);
+
+        let trigger = PriceTrigger { reference: ReferencePrice::BestAsk, direction: TriggerDirection::AtOrAbove, price: dec!(110) };
+        assert!(!trigger.is_met(Some(dec!(120)), Some(dec!(109))));
+        assert!(trigger.is_met(None, Some(dec!(110))));
+    }
+
+    #[test]
+    fn test_store_roundtrip_and_cancel() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schedules.json");
+
+        let order = ScheduledOrder::new(template(), None, stop(TriggerDirection::AtOrAbove, dec!(1)), None, NOW).unwrap();
+        let mut scheduler = OrderScheduler::open(&path).unwrap();
+        scheduler.add(order.clone());
+        scheduler.save().unwrap();
+
+        let mut scheduler = OrderScheduler::open(&path).unwrap();
+        assert_eq!(scheduler.pending().len(), 1);
+
+        scheduler.cancel(&order.id).unwrap();
+        assert!(scheduler.cancel(&order.id).is_err());
+        assert!(scheduler.pending().is_empty());
+        assert_eq!(scheduler.get(&order.id).unwrap().evaluate(NOW, Some(dec!(5)), Some(dec!(5))), ScheduleDecision::Wait);
+    }
+}
@@ -0,0 +1,424 @@
+//! Orderbook snapshot module for DarkSwap
+//!
+//! This module provides signed orderbook snapshots. Relay servers can cache these
+//! snapshots and serve them to new peers, so that a book can be rendered before the
+//! P2P mesh is ready. Every order in a snapshot carries the maker's signature, which
+//! is checked on the client before the order is accepted.
+
+use std::collections::HashMap;
+
+use darkswap_support::crypto::{BulkVerifier, Scheme};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::announcement::Delegation;
+use super::{Order, OrderStatus, OrderbookError};
+use crate::trade::psbt::VerificationPool;
+
+/// Orders verified in one run on a blocking thread
+pub const VERIFY_CHUNK: usize = 256;
+
+/// Signed order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedOrder {
+    /// Order
+    pub order: Order,
+    /// Maker public key (protobuf encoded, hex)
+    pub public_key: String,
+    /// Maker signature over the serialized order (hex)
+    pub signature: String,
+    /// Delegation to the announcement key that signed the order, if not the maker's network key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegation: Option<Delegation>,
+}
+
+impl SignedOrder {
+    /// Sign an order with the maker's keypair
+    pub fn sign(order: Order, keypair: &Keypair) -> Result<Self, OrderbookError> {
+        // Only the maker can sign an order
+        if PeerId::from(keypair.public()).to_string() != order.maker {
+            return Err(OrderbookError::InvalidOrder("Signing key does not match order maker".to_string()));
+        }
+
+        let message = signing_bytes(&order)?;
+        let signature = keypair
+            .sign(&message)
+            .map_err(|e| OrderbookError::Other(format!("Failed to sign order: {}", e)))?;
+
+        Ok(Self {
+            order,
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+            delegation: None,
+        })
+    }
+
+    /// Wrap an order signed with the announcement key `delegation` names
+    pub fn delegated(order: Order, delegation: Delegation, signature: &[u8]) -> Self {
+        Self {
+            order,
+            public_key: delegation.announcement_key.clone(),
+            signature: hex::encode(signature),
+            delegation: Some(delegation),
+        }
+    }
+
+    /// Verify the maker signature
+    pub fn verify(&self) -> Result<(), OrderbookError> {
+        let public_key = decode_public_key(&self.public_key)?;
+        let (signature, message) = self.signed_message(&public_key.1)?;
+
+        verify_signature(&public_key.0, &message, &signature)
+    }
+
+    /// Verify the maker signatures of many orders, with the same outcome as [`SignedOrder::verify`] on each
+    ///
+    /// Each maker key is decoded, and its peer ID derived, once for all its
+    /// orders; the Ed25519 signatures are then checked on one [`BulkVerifier`].
+    pub fn verify_all(orders: &[SignedOrder]) -> Vec<Result<(), OrderbookError>> {
+        let mut keys: HashMap<&str, Result<(PublicKey, String), String>> = HashMap::new();
+        let mut verifier = BulkVerifier::new();
+        let mut pending = Vec::new();
+
+        let mut results: Vec<Result<(), OrderbookError>> = orders.iter()
+            .enumerate()
+            .map(|(index, signed_order)| {
+                let public_key = keys.entry(signed_order.public_key.as_str())
+                    .or_insert_with(|| decode_public_key(&signed_order.public_key).map_err(|e| e.to_string()))
+                    .as_ref()
+                    .map_err(|e| OrderbookError::InvalidOrder(e.clone()))?;
+                let (signature, message) = signed_order.signed_message(&public_key.1)?;
+
+                match &public_key.0 {
+                    PublicKey::Ed25519(key) => {
+                        pending.push((index, verifier.push(Scheme::Ed25519, &key.encode(), &message, &signature)));
+                        Ok(())
+                    }
+                    // Makers rarely use other key types; they are checked here, one by one
+                    key @ (PublicKey::Secp256k1(_) | PublicKey::Ecdsa(_)) => verify_signature(key, &message, &signature),
+                    #[cfg(not(target_arch = "wasm32"))]
+                    key @ PublicKey::Rsa(_) => verify_signature(key, &message, &signature),
+                }
+            })
+            .collect();
+
+        let valid = verifier.verify();
+        for (index, item) in pending {
+            if !valid[item] {
+                results[index] = Err(invalid_signature());
+            }
+        }
+
+        results
+    }
+
+    /// Verify the maker signatures of many orders on the blocking threads of `pool`
+    ///
+    /// The orders are checked in runs of [`VERIFY_CHUNK`], spread over the pool
+    /// without holding up the async runtime; the outcome is that of
+    /// [`SignedOrder::verify_all`].
+    pub async fn verify_all_on(
+        pool: &VerificationPool,
+        orders: &[SignedOrder],
+    ) -> anyhow::Result<Vec<Result<(), OrderbookError>>> {
+        let runs = orders.chunks(VERIFY_CHUNK).map(|chunk| {
+            let chunk = chunk.to_vec();
+            pool.run(move || SignedOrder::verify_all(&chunk))
+        });
+
+        let mut results = Vec::with_capacity(orders.len());
+        for run in futures::future::join_all(runs).await {
+            results.extend(run?);
+        }
+
+        Ok(results)
+    }
+
+    /// Check that the key with peer ID `signer` belongs to the maker, and decode the signature and the bytes it covers
+    ///
+    /// A delegated order is signed by the announcement key the maker's network key delegated to.
+    fn signed_message(&self, signer: &str) -> Result<(Vec<u8>, Vec<u8>), OrderbookError> {
+        match &self.delegation {
+            Some(delegation) => {
+                if delegation.announcement_key != self.public_key {
+                    return Err(OrderbookError::InvalidOrder("Signing key is not the delegated announcement key".to_string()));
+                }
+                delegation.verify(&self.order.maker)?;
+            }
+            None if signer != self.order.maker => {
+                return Err(OrderbookError::InvalidOrder("Signing key does not match order maker".to_string()));
+            }
+            None => {}
+        }
+
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid signature encoding: {}", e)))?;
+
+        Ok((signature, signing_bytes(&self.order)?))
+    }
+}
+
+/// Decode a maker public key, with the peer ID it belongs to
+fn decode_public_key(public_key: &str) -> Result<(PublicKey, String), OrderbookError> {
+    let public_key_bytes = hex::decode(public_key)
+        .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid public key encoding: {}", e)))?;
+    let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes)
+        .map_err(|e| OrderbookError::InvalidOrder(format!("Invalid public key: {}", e)))?;
+    let peer_id = PeerId::from_public_key(&public_key).to_string();
+
+    Ok((public_key, peer_id))
+}
+
+/// Check a signature with a maker key
+fn verify_signature(public_key: &PublicKey, message: &[u8], signature: &[u8]) -> Result<(), OrderbookError> {
+    if !public_key.verify(message, signature) {
+        return Err(invalid_signature());
+    }
+
+    Ok(())
+}
+
+/// Error of a signature that does not verify
+fn invalid_signature() -> OrderbookError {
+    OrderbookError::InvalidOrder("Invalid maker signature".to_string())
+}
+
+/// Orderbook snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderbookSnapshot {
+    /// Snapshot timestamp
+    pub timestamp: u64,
+    /// Signed orders
+    pub orders: Vec<SignedOrder>,
+}
+
+impl OrderbookSnapshot {
+    /// Create a snapshot of the given orders, taken at `now` (unix seconds)
+    pub fn new(orders: Vec<SignedOrder>, now: u64) -> Self {
+        Self {
+            timestamp: now,
+            orders,
+        }
+    }
+
+    /// Get the age of the snapshot at `now` in seconds
+    pub fn age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.timestamp)
+    }
+
+    /// Check if the snapshot is younger than `max_age` seconds at `now`
+    pub fn is_fresh(&self, max_age: u64, now: u64) -> bool {
+        // Snapshots from the future are treated as stale
+        self.timestamp <= now && self.age(now) <= max_age
+    }
+
+    /// Validate the snapshot at `now` and return the orders that can be shown
+    ///
+    /// Orders with a bad signature, orders that are no longer open and expired
+    /// orders are skipped. A stale snapshot is rejected as a whole.
+    pub fn validate(&self, max_age: u64, now: u64) -> Result<Vec<Order>, OrderbookError> {
+        self.check_fresh(max_age, now)?;
+
+        Ok(self.accepted(SignedOrder::verify_all(&self.orders), now))
+    }
+
+    /// Validate the snapshot at `now` like [`OrderbookSnapshot::validate`], checking the signatures on `pool`
+    pub async fn validate_on(&self, pool: &VerificationPool, max_age: u64, now: u64) -> anyhow::Result<Vec<Order>> {
+        self.check_fresh(max_age, now)?;
+        let verified = SignedOrder::verify_all_on(pool, &self.orders).await?;
+
+        Ok(self.accepted(verified, now))
+    }
+
+    /// Reject the snapshot if it is stale at `now`
+    fn check_fresh(&self, max_age: u64, now: u64) -> Result<(), OrderbookError> {
+        if !self.is_fresh(max_age, now) {
+            return Err(OrderbookError::StaleSnapshot(self.age(now)));
+        }
+
+        Ok(())
+    }
+
+    /// Get the open, unexpired orders whose signatures verified
+    fn accepted(&self, verified: Vec<Result<(), OrderbookError>>, now: u64) -> Vec<Order> {
+        let mut orders = Vec::new();
+
+        for (signed_order, verified) in self.orders.iter().zip(verified) {
+            if let Err(e) = verified {
+                warn!("Skipping snapshot order {}: {}", signed_order.order.id, e);
+                continue;
+            }
+
+            if signed_order.order.status != OrderStatus::Open || signed_order.order.is_expired_at(now) {
+                continue;
+            }
+
+            orders.push(signed_order.order.clone());
+        }
+
+        orders
+    }
+
+    /// Serialize the snapshot to JSON
+    pub fn to_json(&self) -> Result<String, OrderbookError> {
+        serde_json::to_string(self)
+            .map_err(|e| OrderbookError::Other(format!("Failed to serialize snapshot: {}", e)))
+    }
+
+    /// Deserialize a snapshot from JSON
+    pub fn from_json(json: &str) -> Result<Self, OrderbookError> {
+        serde_json::from_str(json)
+            .map_err(|e| OrderbookError::Other(format!("Failed to parse snapshot: {}", e)))
+    }
+}
+
+/// Get the bytes covered by the maker signature
+///
+/// Routing hints are left out: they only help reach the maker, whose peer ID
+/// the connection authenticates anyway, and nodes that predate them drop the
+/// field and would otherwise see a different body than the one signed.
+pub(super) fn signing_bytes(order: &Order) -> Result<Vec<u8>, OrderbookError> {
+    let result = if order.hints.is_empty() {
+        serde_json::to_vec(order)
+    } else {
+        serde_json::to_vec(&Order { hints: Default::default(), ..order.clone() })
+    };
+
+    result.map_err(|e| OrderbookError::Other(format!("Failed to serialize order: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+    use crate::types::Asset;
+    use rust_decimal_macros::dec;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn signed_order(keypair: &Keypair) -> SignedOrder {
+        let maker = PeerId::from(keypair.public()).to_string();
+        let order = Order::new_at(
+            maker,
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            OrderSide::Sell,
+            dec!(1),
+            dec!(100),
+            None,
+            NOW,
+        );
+
+        SignedOrder::sign(order, keypair).unwrap()
+    }
+
+    #[test]
+    fn test_signed_order_verifies() {
+        let keypair = Keypair::generate_ed25519();
+        let signed = signed_order(&keypair);
+
+        assert!(signed.verify().is_ok());
+    }
+
+    #[test]
+    fn test_tampered_order_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let mut signed = signed_order(&keypair);
+        signed.order.price = dec!(1);
+
+        assert!(signed.verify().is_err());
+    }
+
+    #[test]
+    fn test_hints_are_not_signed() {
+        let keypair = Keypair::generate_ed25519();
+        let mut signed = signed_order(&keypair);
+        signed.order.hints.relays.push("/dns4/relay.example.com/tcp/443/wss".to_string());
+        assert!(signed.verify().is_ok());
+
+        // A node that predates hints sees the order without them
+        let json = serde_json::to_value(&signed).unwrap();
+        let mut stripped: SignedOrder = serde_json::from_value(json).unwrap();
+        stripped.order.hints = Default::default();
+        assert!(stripped.verify().is_ok());
+
+        // Signing an order with hints signs it without them
+        let mut order = stripped.order.clone();
+        order.hints.region = Some("eu-west".to_string());
+        assert_eq!(SignedOrder::sign(order, &keypair).unwrap().signature, stripped.signature);
+    }
+
+    #[test]
+    fn test_foreign_key_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        let mut signed = signed_order(&keypair);
+        signed.public_key = hex::encode(other.public().to_protobuf_encoding());
+
+        assert!(signed.verify().is_err());
+    }
+
+    #[test]
+    fn test_delegated_order() {
+        let network_key = Keypair::generate_ed25519();
+        let announcement_key = Keypair::generate_ed25519();
+        let order = signed_order(&network_key).order;
+        let delegation = Delegation::issue(&network_key, &announcement_key.public()).unwrap();
+
+        let signature = announcement_key.sign(&signing_bytes(&order).unwrap()).unwrap();
+        let signed = SignedOrder::delegated(order.clone(), delegation.clone(), &signature);
+        assert!(signed.verify().is_ok());
+        assert!(SignedOrder::verify_all(&[signed.clone()])[0].is_ok());
+
+        // A key the maker did not delegate to is refused
+        let other = Keypair::generate_ed25519();
+        let mut forged = SignedOrder::delegated(order.clone(), delegation, &other.sign(&signing_bytes(&order).unwrap()).unwrap());
+        forged.public_key = hex::encode(other.public().to_protobuf_encoding());
+        assert!(forged.verify().is_err());
+
+        // So is a delegation issued by another network key
+        let foreign = Delegation::issue(&other, &announcement_key.public()).unwrap();
+        assert!(SignedOrder::delegated(order, foreign, &signature).verify().is_err());
+    }
+
+    #[test]
+    fn test_verify_all_matches_verify() {
+        let makers: Vec<Keypair> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+        let mut orders: Vec<SignedOrder> = (0..200).map(|n| signed_order(&makers[n % makers.len()])).collect();
+        orders[7].order.price = dec!(1);
+        orders[130].public_key = hex::encode(makers[0].public().to_protobuf_encoding());
+        orders[131].signature = "zz".to_string();
+
+        let results = SignedOrder::verify_all(&orders);
+        for (signed_order, result) in orders.iter().zip(results) {
+            assert_eq!(result.is_ok(), signed_order.verify().is_ok());
+        }
+        assert_eq!(SignedOrder::verify_all(&orders).iter().filter(|result| result.is_err()).count(), 3);
+    }
+
+    #[test]
+    fn test_stale_snapshot_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let snapshot = OrderbookSnapshot::new(vec![signed_order(&keypair)], NOW);
+        assert_eq!(snapshot.validate(300, NOW + 60).unwrap().len(), 1);
+
+        assert!(snapshot.validate(300, NOW + 600).is_err());
+        assert!(snapshot.validate(300, NOW - 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_on_pool() {
+        let keypair = Keypair::generate_ed25519();
+        let mut orders: Vec<SignedOrder> = (0..VERIFY_CHUNK + 10).map(|_| signed_order(&keypair)).collect();
+        orders[VERIFY_CHUNK + 3].order.price = dec!(1);
+        let snapshot = OrderbookSnapshot::new(orders, NOW);
+
+        let ids = |orders: Vec<Order>| orders.into_iter().map(|order| order.id).collect::<Vec<_>>();
+        let pool = VerificationPool::new(2);
+        let on_pool = ids(snapshot.validate_on(&pool, 300, NOW).await.unwrap());
+        assert_eq!(on_pool.len(), VERIFY_CHUNK + 9);
+        assert_eq!(on_pool, ids(snapshot.validate(300, NOW).unwrap()));
+    }
+}
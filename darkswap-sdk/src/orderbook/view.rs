@@ -0,0 +1,352 @@
+//! Read-optimized orderbook views
+//!
+//! Readers (REST handlers, matching, gossip validation) get an immutable view of
+//! the open orders, grouped into per-pair books, without taking any lock. Writers
+//! build a new view with the changed pair replaced and publish it atomically;
+//! books of other pairs are shared between the old and the new view.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{Order, OrderId, OrderSide, OrderStatus};
+use crate::types::Asset;
+
+/// Open orders of one trading pair
+#[derive(Debug, Clone, Default)]
+pub struct PairBook {
+    /// Buy orders, best (highest) price first
+    bids: Vec<Order>,
+    /// Sell orders, best (lowest) price first
+    asks: Vec<Order>,
+}
+
+impl PairBook {
+    /// Get the buy orders, best price first
+    pub fn bids(&self) -> &[Order] {
+        &self.bids
+    }
+
+    /// Get the sell orders, best price first
+    pub fn asks(&self) -> &[Order] {
+        &self.asks
+    }
+
+    /// Get the best bid price
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.first().map(|order| order.price)
+    }
+
+    /// Get the best ask price
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.first().map(|order| order.price)
+    }
+
+    /// Iterate over all orders of the pair
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.bids.iter().chain(self.asks.iter())
+    }
+
+    /// Get the number of orders
+    pub fn len(&self) -> usize {
+        self.bids.len() + self.asks.len()
+    }
+
+    /// Check if the book is empty
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Remove an order by ID, returning true if it was present
+    fn remove(&mut self, order_id: &OrderId) -> bool {
+        let len = self.len();
+        self.bids.retain(|order| &order.id != order_id);
+        self.asks.retain(|order| &order.id != order_id);
+        self.len() != len
+    }
+
+    /// Insert an order at its price-time position
+    fn insert(&mut self, order: Order) {
+        match order.side {
+            OrderSide::Buy => {
+                let index = self.bids
+                    .partition_point(|o| (o.price, std::cmp::Reverse(o.timestamp)) >= (order.price, std::cmp::Reverse(order.timestamp)));
+                self.bids.insert(index, order);
+            }
+            OrderSide::Sell => {
+                let index = self.asks
+                    .partition_point(|o| (o.price, o.timestamp) <= (order.price, order.timestamp));
+                self.asks.insert(index, order);
+            }
+        }
+    }
+}
+
+/// Summary of a pair with live orders
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketSummary {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Number of open buy orders
+    pub bid_count: usize,
+    /// Number of open sell orders
+    pub ask_count: usize,
+    /// Total base amount of the open buy orders
+    pub bid_depth: Decimal,
+    /// Total base amount of the open sell orders
+    pub ask_depth: Decimal,
+    /// Best bid price
+    pub best_bid: Option<Decimal>,
+    /// Best ask price
+    pub best_ask: Option<Decimal>,
+}
+
+impl MarketSummary {
+    /// Summarize the live orders of a pair book
+    fn of(base_asset: &Asset, quote_asset: &Asset, book: &PairBook) -> Self {
+        let live = |orders: &[Order]| -> Vec<&Order> {
+            orders.iter().filter(|order| !order.is_expired()).collect()
+        };
+        let bids = live(book.bids());
+        let asks = live(book.asks());
+
+        Self {
+            base_asset: base_asset.clone(),
+            quote_asset: quote_asset.clone(),
+            bid_count: bids.len(),
+            ask_count: asks.len(),
+            bid_depth: bids.iter().map(|order| order.amount).sum(),
+            ask_depth: asks.iter().map(|order| order.amount).sum(),
+            best_bid: bids.first().map(|order| order.price),
+            best_ask: asks.first().map(|order| order.price),
+        }
+    }
+
+    /// Get the number of open orders
+    pub fn order_count(&self) -> usize {
+        self.bid_count + self.ask_count
+    }
+}
+
+/// Immutable view of the open orders
+#[derive(Debug, Clone, Default)]
+pub struct BookView {
+    /// Books by (base asset, quote asset)
+    pairs: HashMap<(Asset, Asset), Arc<PairBook>>,
+    /// Number of writes since the view was created
+    version: u64,
+}
+
+impl BookView {
+    /// Create a view from a stream of orders
+    ///
+    /// Builds the same books as applying the orders one by one with
+    /// [`BookView::with_order`], but sorts each book once instead of copying it on
+    /// every order.
+    pub fn from_orders<'a>(orders: impl IntoIterator<Item = &'a Order>) -> Self {
+        // Later versions of an order replace earlier ones and arrive last
+        let mut latest: Vec<Option<&Order>> = Vec::new();
+        let mut positions: HashMap<&OrderId, usize> = HashMap::new();
+        for order in orders {
+            if let Some(position) = positions.insert(&order.id, latest.len()) {
+                latest[position] = None;
+            }
+            latest.push(Some(order));
+        }
+
+        let mut books: HashMap<(Asset, Asset), PairBook> = HashMap::new();
+        for order in latest.into_iter().flatten() {
+            if order.status != OrderStatus::Open || order.is_expired() {
+                continue;
+            }
+
+            let book = books.entry((order.base_asset.clone(), order.quote_asset.clone())).or_default();
+            match order.side {
+                OrderSide::Buy => book.bids.push(order.clone()),
+                OrderSide::Sell => book.asks.push(order.clone()),
+            }
+        }
+
+        // Stable sorts keep arrival order at equal price and time, as `insert` does
+        for book in books.values_mut() {
+            book.bids.sort_by_key(|o| (std::cmp::Reverse(o.price), o.timestamp));
+            book.asks.sort_by_key(|o| (o.price, o.timestamp));
+        }
+
+        Self {
+            pairs: books.into_iter().map(|(pair, book)| (pair, Arc::new(book))).collect(),
+            version: 1,
+        }
+    }
+
+    /// Get the book of a pair
+    pub fn pair(&self, base_asset: &Asset, quote_asset: &Asset) -> Option<&PairBook> {
+        self.pairs
+            .get(&(base_asset.clone(), quote_asset.clone()))
+            .map(|book| book.as_ref())
+    }
+
+    /// Iterate over all pairs and their books
+    pub fn pairs(&self) -> impl Iterator<Item = (&(Asset, Asset), &PairBook)> {
+        self.pairs.iter().map(|(pair, book)| (pair, book.as_ref()))
+    }
+
+    /// Summarize the pairs with live orders, most orders first
+    ///
+    /// Pairs with the same number of orders are ordered by name, so the list is stable.
+    pub fn markets(&self) -> Vec<MarketSummary> {
+        let mut markets: Vec<MarketSummary> = self.pairs
+            .iter()
+            .map(|((base_asset, quote_asset), book)| MarketSummary::of(base_asset, quote_asset, book))
+            .filter(|market| market.order_count() > 0)
+            .collect();
+
+        markets.sort_by(|a, b| {
+            b.order_count().cmp(&a.order_count())
+                .then_with(|| a.base_asset.to_string().cmp(&b.base_asset.to_string()))
+                .then_with(|| a.quote_asset.to_string().cmp(&b.quote_asset.to_string()))
+        });
+        markets
+    }
+
+    /// Iterate over all open orders
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.pairs.values().flat_map(|book| book.orders())
+    }
+
+    /// Get the view version
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Create a new view with the given order applied
+    ///
+    /// The order replaces any earlier version with the same ID; orders that are no
+    /// longer open are removed.
+    pub fn with_order(&self, order: &Order) -> Self {
+        let key = (order.base_asset.clone(), order.quote_asset.clone());
+        let mut book = self.pairs.get(&key).map(|book| book.as_ref().clone()).unwrap_or_default();
+
+        book.remove(&order.id);
+        if order.status == OrderStatus::Open && !order.is_expired() {
+            book.insert(order.clone());
+        }
+
+        let mut pairs = self.pairs.clone();
+        if book.is_empty() {
+            pairs.remove(&key);
+        } else {
+            pairs.insert(key, Arc::new(book));
+        }
+
+        Self {
+            pairs,
+            version: self.version + 1,
+        }
+    }
+}
+
+/// Atomically replaceable book view
+#[derive(Debug, Default)]
+pub struct OrderbookView {
+    /// Current view
+    current: ArcSwap<BookView>,
+}
+
+impl OrderbookView {
+    /// Create an empty orderbook view
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the current view without locking
+    pub fn load(&self) -> Arc<BookView> {
+        self.current.load_full()
+    }
+
+    /// Publish a new view with the given order applied
+    pub fn apply(&self, order: &Order) {
+        self.current.rcu(|view| view.with_order(order));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn order(side: OrderSide, price: Decimal) -> Order {
+        Order::new(
+            "maker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            side,
+            dec!(1),
+            price,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_best_prices() {
+        let view = OrderbookView::new();
+        view.apply(&order(OrderSide::Buy, dec!(99)));
+        view.apply(&order(OrderSide::Buy, dec!(100)));
+        view.apply(&order(OrderSide::Sell, dec!(102)));
+        view.apply(&order(OrderSide::Sell, dec!(101)));
+
+        let snapshot = view.load();
+        let book = snapshot.pair(&Asset::Bitcoin, &Asset::Rune(1)).unwrap();
+        assert_eq!(book.best_bid(), Some(dec!(100)));
+        assert_eq!(book.best_ask(), Some(dec!(101)));
+        assert_eq!(snapshot.version(), 4);
+    }
+
+    #[test]
+    fn test_readers_keep_their_view() {
+        let view = OrderbookView::new();
+        let mut open = order(OrderSide::Sell, dec!(101));
+        view.apply(&open);
+
+        let before = view.load();
+
+        open.status = OrderStatus::Canceled;
+        view.apply(&open);
+
+        assert_eq!(before.orders().count(), 1);
+        assert_eq!(view.load().orders().count(), 0);
+    }
+
+    #[test]
+    fn test_markets() {
+        let other_pair = Order::new(
+            "maker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(2),
+            OrderSide::Sell,
+            dec!(3),
+            dec!(50),
+            None,
+        );
+        let view = BookView::default()
+            .with_order(&order(OrderSide::Buy, dec!(99)))
+            .with_order(&order(OrderSide::Buy, dec!(100)))
+            .with_order(&order(OrderSide::Sell, dec!(101)))
+            .with_order(&other_pair);
+
+        let markets = view.markets();
+        assert_eq!(markets.len(), 2);
+        assert_eq!(markets[0].quote_asset, Asset::Rune(1));
+        assert_eq!((markets[0].bid_count, markets[0].ask_count), (2, 1));
+        assert_eq!(markets[0].bid_depth, dec!(2));
+        assert_eq!(markets[0].best_bid, Some(dec!(100)));
+        assert_eq!(markets[1].quote_asset, Asset::Rune(2));
+        assert_eq!(markets[1].ask_depth, dec!(3));
+        assert_eq!(markets[1].best_bid, None);
+    }
+}
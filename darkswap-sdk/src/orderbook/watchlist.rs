@@ -0,0 +1,303 @@
+//! Watchlist of makers and pairs
+//!
+//! Users follow makers and pairs. When a followed maker posts an order, or the
+//! price of a followed pair crosses one of its thresholds, the watchlist emits a
+//! targeted event. The price of a pair is the midpoint of the best bid and ask,
+//! or the one side that has orders. Subscriptions are kept in a local file so
+//! they survive restarts.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::DocumentFile;
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, RwLock};
+
+use super::Orderbook;
+use crate::events::EventReceiver;
+use crate::types::{Asset, Event};
+
+/// Followed pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedPair {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Prices whose crossing is reported
+    pub thresholds: Vec<Decimal>,
+}
+
+/// Direction of a price crossing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrossDirection {
+    /// The price rose through the threshold
+    Up,
+    /// The price fell through the threshold
+    Down,
+}
+
+/// Price of a followed pair crossing a threshold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceCrossing {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Threshold crossed
+    pub threshold: Decimal,
+    /// Direction of the crossing
+    pub direction: CrossDirection,
+    /// Price before the crossing
+    pub previous_price: Decimal,
+    /// Price after the crossing
+    pub price: Decimal,
+}
+
+/// Persisted subscriptions
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Subscriptions {
+    /// Followed maker peer IDs
+    makers: BTreeSet<String>,
+    /// Followed pairs
+    pairs: Vec<WatchedPair>,
+}
+
+/// Watchlist
+#[derive(Debug, Default)]
+pub struct Watchlist {
+    /// File the watchlist is persisted to; `None` keeps it in memory only
+    file: Option<DocumentFile>,
+    /// Subscriptions
+    subscriptions: Subscriptions,
+    /// Last seen price by pair
+    last_prices: HashMap<(Asset, Asset), Decimal>,
+}
+
+impl Watchlist {
+    /// Create an in-memory watchlist
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a watchlist persisted at `path`, loading it if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_file(DocumentFile::plain(path))
+    }
+
+    /// Open a watchlist persisted in `file`, sealed if storage is encrypted
+    pub fn open_file(file: DocumentFile) -> Result<Self> {
+        let subscriptions: Subscriptions = file.load()
+            .with_context(|| format!("Failed to read watchlist {}", file.path().display()))?
+            .unwrap_or_default();
+
+        Ok(Self {
+            file: Some(file),
+            subscriptions,
+            last_prices: HashMap::new(),
+        })
+    }
+
+    /// Save the watchlist to disk, if it is persisted
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        file.save(&self.subscriptions)
+            .with_context(|| format!("Failed to write watchlist {}", file.path().display()))?;
+
+        Ok(())
+    }
+
+    /// Follow a maker, returning false if it was followed already
+    pub fn watch_maker(&mut self, peer_id: &str) -> bool {
+        self.subscriptions.makers.insert(peer_id.to_string())
+    }
+
+    /// Stop following a maker, returning false if it was not followed
+    pub fn unwatch_maker(&mut self, peer_id: &str) -> bool {
+        self.subscriptions.makers.remove(peer_id)
+    }
+
+    /// Check if a maker is followed
+    pub fn is_watched_maker(&self, peer_id: &str) -> bool {
+        self.subscriptions.makers.contains(peer_id)
+    }
+
+    /// Get the followed makers
+    pub fn makers(&self) -> Vec<String> {
+        self.subscriptions.makers.iter().cloned().collect()
+    }
+
+    /// Follow a pair, replacing its thresholds if it was followed already
+    pub fn watch_pair(&mut self, base_asset: Asset, quote_asset: Asset, mut thresholds: Vec<Decimal>) {
+        thresholds.sort();
+        thresholds.dedup();
+
+        let pairs = &mut self.subscriptions.pairs;
+        match pairs.iter_mut().find(|pair| pair.base_asset == base_asset && pair.quote_asset == quote_asset) {
+            Some(pair) => pair.thresholds = thresholds,
+            None => pairs.push(WatchedPair { base_asset, quote_asset, thresholds }),
+        }
+    }
+
+    /// Stop following a pair, returning false if it was not followed
+    pub fn unwatch_pair(&mut self, base_asset: &Asset, quote_asset: &Asset) -> bool {
+        let len = self.subscriptions.pairs.len();
+        self.subscriptions.pairs.retain(|pair| &pair.base_asset != base_asset || &pair.quote_asset != quote_asset);
+        self.last_prices.remove(&(base_asset.clone(), quote_asset.clone()));
+        self.subscriptions.pairs.len() != len
+    }
+
+    /// Get the followed pairs
+    pub fn pairs(&self) -> &[WatchedPair] {
+        &self.subscriptions.pairs
+    }
+
+    /// Record the current price of a pair, returning the thresholds it crossed
+    ///
+    /// The first price seen for a pair crosses nothing.
+    pub fn check_price(&mut self, base_asset: &Asset, quote_asset: &Asset, price: Decimal) -> Vec<PriceCrossing> {
+        let pair = match self.subscriptions.pairs.iter().find(|pair| &pair.base_asset == base_asset && &pair.quote_asset == quote_asset) {
+            Some(pair) => pair,
+            None => return Vec::new(),
+        };
+
+        let previous_price = match self.last_prices.insert((base_asset.clone(), quote_asset.clone()), price) {
+            Some(previous_price) => previous_price,
+            None => return Vec::new(),
+        };
+
+        pair.thresholds
+            .iter()
+            .filter_map(|threshold| {
+                let direction = if previous_price < *threshold && price >= *threshold {
+                    CrossDirection::Up
+                } else if previous_price > *threshold && price <= *threshold {
+                    CrossDirection::Down
+                } else {
+                    return None;
+                };
+
+                Some(PriceCrossing {
+                    base_asset: base_asset.clone(),
+                    quote_asset: quote_asset.clone(),
+                    threshold: *threshold,
+                    direction,
+                    previous_price,
+                    price,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Get the price of a pair from its best bid and ask
+pub fn pair_price(best_bid: Option<Decimal>, best_ask: Option<Decimal>) -> Option<Decimal> {
+    match (best_bid, best_ask) {
+        (Some(bid), Some(ask)) => Some((bid + ask) / Decimal::TWO),
+        (bid, ask) => bid.or(ask),
+    }
+}
+
+/// Spawn a task emitting watchlist events as orders arrive and change
+pub fn spawn_watchlist(
+    watchlist: Arc<RwLock<Watchlist>>,
+    orderbook: Arc<Orderbook>,
+    mut events: EventReceiver,
+    event_sender: mpsc::Sender<Event>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            match &event {
+                Event::OrderCreated(order) => {
+                    if watchlist.read().await.is_watched_maker(&order.maker) {
+                        let _ = event_sender.send(Event::NewOrderFromWatchedMaker(order.clone())).await;
+                    }
+                }
+                Event::OrderUpdated(_) | Event::OrderCancelled(_) | Event::OrderExpired(_) | Event::OrderFilled(_) => {}
+                _ => continue,
+            }
+
+            // Any order change may move the price of a followed pair
+            let pairs = watchlist.read().await.pairs().to_vec();
+            for pair in pairs {
+                let (best_bid, best_ask) = match orderbook.get_best_bid_ask(&pair.base_asset, &pair.quote_asset).await {
+                    Ok(best) => best,
+                    Err(e) => {
+                        warn!("Failed to get the price of {}/{}: {}", pair.base_asset, pair.quote_asset, e);
+                        continue;
+                    }
+                };
+                let price = match pair_price(best_bid, best_ask) {
+                    Some(price) => price,
+                    None => continue,
+                };
+
+                let crossings = watchlist.write().await.check_price(&pair.base_asset, &pair.quote_asset, price);
+                for crossing in crossings {
+                    let _ = event_sender.send(Event::PriceCrossedThreshold(crossing)).await;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_price_crossings() {
+        let mut watchlist = Watchlist::new();
+        watchlist.watch_pair(Asset::Bitcoin, Asset::Rune(1), vec![dec!(110), dec!(100), dec!(100)]);
+
+        assert!(watchlist.check_price(&Asset::Bitcoin, &Asset::Rune(1), dec!(95)).is_empty());
+        assert!(watchlist.check_price(&Asset::Bitcoin, &Asset::Rune(2), dec!(500)).is_empty());
+
+        let crossings = watchlist.check_price(&Asset::Bitcoin, &Asset::Rune(1), dec!(112));
+        assert_eq!(crossings.len(), 2);
+        assert!(crossings.iter().all(|crossing| crossing.direction == CrossDirection::Up));
+
+        // Staying on the same side crosses nothing
+        assert!(watchlist.check_price(&Asset::Bitcoin, &Asset::Rune(1), dec!(111)).is_empty());
+
+        let crossings = watchlist.check_price(&Asset::Bitcoin, &Asset::Rune(1), dec!(105));
+        assert_eq!(crossings.len(), 1);
+        assert_eq!(crossings[0].threshold, dec!(110));
+        assert_eq!(crossings[0].direction, CrossDirection::Down);
+        assert_eq!(crossings[0].previous_price, dec!(111));
+    }
+
+    #[test]
+    fn test_watchlist_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("watchlist.json");
+
+        let mut watchlist = Watchlist::open(&path).unwrap();
+        assert!(watchlist.watch_maker("maker"));
+        assert!(!watchlist.watch_maker("maker"));
+        watchlist.watch_pair(Asset::Bitcoin, Asset::Rune(1), vec![dec!(100)]);
+        watchlist.save().unwrap();
+
+        let mut reopened = Watchlist::open(&path).unwrap();
+        assert!(reopened.is_watched_maker("maker"));
+        assert_eq!(reopened.pairs().len(), 1);
+        assert!(reopened.unwatch_pair(&Asset::Bitcoin, &Asset::Rune(1)));
+        assert!(reopened.pairs().is_empty());
+    }
+
+    #[test]
+    fn test_pair_price() {
+        assert_eq!(pair_price(Some(dec!(99)), Some(dec!(101))), Some(dec!(100)));
+        assert_eq!(pair_price(None, Some(dec!(101))), Some(dec!(101)));
+        assert_eq!(pair_price(None, None), None);
+    }
+}
@@ -0,0 +1,325 @@
+//! Warm connection pool for DarkSwap P2P network
+//!
+//! Taking an order starts with connecting to its maker, and racing the paths to
+//! a peer costs a round of dials. The pool keeps connections open to the peers
+//! we trade with most and to the makers of orders the user is looking at, so a
+//! take can go out over a connection that is already up. Connections idle past
+//! the TTL are recycled: the frequent counterparties are dialed afresh, the rest
+//! are let go. A connection leaving the pool has its relay circuit closed.
+//!
+//! Only completed trades count towards keeping a counterparty warm, so a peer
+//! cannot claim a slot by opening trades it never finishes, and at most
+//! [`MAX_TRACKED_COUNTERPARTIES`] counters are kept.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use tokio::sync::RwLock;
+
+use super::path_selection::PathCandidate;
+use super::P2PNetwork;
+
+/// Counterparties whose trades are counted; the least traded with are forgotten first
+pub const MAX_TRACKED_COUNTERPARTIES: usize = 1024;
+
+/// Connection to a peer on a selected path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathConnection {
+    /// WebRTC connection
+    WebRtc,
+    /// Relay circuit
+    Relay {
+        /// Relay server ID
+        server_id: String,
+        /// Relay ID
+        relay_id: String,
+    },
+}
+
+/// Why a connection is kept warm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarmReason {
+    /// We trade with the peer frequently
+    Counterparty,
+    /// The user is viewing an order of the peer
+    Viewing,
+}
+
+/// Warm connection
+#[derive(Debug, Clone)]
+pub struct PooledConnection {
+    /// Path the connection was made over
+    pub path: PathCandidate,
+    /// Connection, closed when it leaves the pool
+    pub connection: PathConnection,
+    /// Why the connection is kept
+    pub reason: WarmReason,
+    /// Time the connection was made
+    pub connected_at: Instant,
+    /// Time the connection was last used
+    pub last_used: Instant,
+}
+
+/// Warm connection pool
+#[derive(Debug)]
+pub struct ConnectionPool {
+    /// Connections by peer ID
+    connections: HashMap<String, PooledConnection>,
+    /// Trades by counterparty peer ID
+    trade_counts: HashMap<String, u32>,
+    /// Maximum number of warm connections
+    max_size: usize,
+    /// Idle time after which a connection is recycled
+    idle_ttl: Duration,
+    /// Trades after which a counterparty is kept warm
+    frequent_after: u32,
+}
+
+impl ConnectionPool {
+    /// Create a pool
+    pub fn new(max_size: usize, idle_ttl: Duration, frequent_after: u32) -> Self {
+        Self {
+            connections: HashMap::new(),
+            trade_counts: HashMap::new(),
+            max_size,
+            idle_ttl,
+            frequent_after: frequent_after.max(1),
+        }
+    }
+
+    /// Check if the pool is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.max_size > 0
+    }
+
+    /// Get the warm connection to a peer, marking it used
+    pub fn checkout(&mut self, peer_id: &str, now: Instant) -> Option<PathCandidate> {
+        let connection = self.connections.get_mut(peer_id)?;
+        if now.duration_since(connection.last_used) >= self.idle_ttl {
+            return None;
+        }
+
+        connection.last_used = now;
+        Some(connection.path.clone())
+    }
+
+    /// Check if a peer has a warm connection
+    pub fn contains(&self, peer_id: &str) -> bool {
+        self.connections.contains_key(peer_id)
+    }
+
+    /// Add a connection, evicting the least recently used one if the pool is full
+    ///
+    /// Connections kept for viewing are evicted before counterparty connections.
+    /// Returns the connections that left the pool, which the caller closes: the
+    /// evicted one and one the peer had before, if any.
+    pub fn insert(
+        &mut self,
+        peer_id: String,
+        path: PathCandidate,
+        connection: PathConnection,
+        reason: WarmReason,
+        now: Instant,
+    ) -> Vec<(String, PooledConnection)> {
+        let mut released = Vec::new();
+        if !self.is_enabled() {
+            return released;
+        }
+
+        // A counterparty connection stays one when the user views its orders
+        let reason = match self.connections.get(&peer_id) {
+            Some(existing) if existing.reason == WarmReason::Counterparty => WarmReason::Counterparty,
+            _ => reason,
+        };
+
+        if !self.connections.contains_key(&peer_id) && self.connections.len() >= self.max_size {
+            let evicted = self.connections
+                .iter()
+                .min_by_key(|(_, connection)| (connection.reason == WarmReason::Counterparty, connection.last_used))
+                .map(|(peer_id, _)| peer_id.clone());
+
+            if let Some(evicted) = evicted {
+                if let Some(connection) = self.connections.remove(&evicted) {
+                    released.push((evicted, connection));
+                }
+            }
+        }
+
+        let previous = self.connections.insert(peer_id.clone(), PooledConnection {
+            path,
+            connection: connection.clone(),
+            reason,
+            connected_at: now,
+            last_used: now,
+        });
+        if let Some(previous) = previous.filter(|previous| previous.connection != connection) {
+            released.push((peer_id, previous));
+        }
+
+        released
+    }
+
+    /// Drop a connection
+    pub fn remove(&mut self, peer_id: &str) -> Option<PooledConnection> {
+        self.connections.remove(peer_id)
+    }
+
+    /// Record a completed trade with a counterparty
+    ///
+    /// When the counters are full, the counterparty traded with least is
+    /// forgotten to make room for a new one.
+    pub fn record_trade(&mut self, peer_id: &str) {
+        if !self.trade_counts.contains_key(peer_id) && self.trade_counts.len() >= MAX_TRACKED_COUNTERPARTIES {
+            let least = self.trade_counts
+                .iter()
+                .min_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+                .map(|(peer_id, _)| peer_id.clone());
+            if let Some(least) = least {
+                self.trade_counts.remove(&least);
+            }
+        }
+
+        *self.trade_counts.entry(peer_id.to_string()).or_insert(0) += 1;
+    }
+
+    /// Get the frequent counterparties, most traded with first
+    ///
+    /// At most as many as fit in the pool are returned.
+    pub fn frequent_counterparties(&self) -> Vec<String> {
+        let mut counterparties: Vec<(&String, &u32)> = self.trade_counts
+            .iter()
+            .filter(|(_, count)| **count >= self.frequent_after)
+            .collect();
+        counterparties.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+        counterparties.into_iter()
+            .take(self.max_size)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect()
+    }
+
+    /// Drop the connections idle past the TTL, returning them to be closed
+    pub fn recycle(&mut self, now: Instant) -> Vec<(String, PooledConnection)> {
+        let idle_ttl = self.idle_ttl;
+        let expired: Vec<String> = self.connections
+            .iter()
+            .filter(|(_, connection)| now.duration_since(connection.last_used) >= idle_ttl)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|peer_id| self.connections.remove(&peer_id).map(|connection| (peer_id, connection)))
+            .collect()
+    }
+
+    /// Get the frequent counterparties without a warm connection
+    pub fn to_warm(&self) -> Vec<String> {
+        self.frequent_counterparties()
+            .into_iter()
+            .filter(|peer_id| !self.connections.contains_key(peer_id))
+            .collect()
+    }
+
+    /// Get the warm connections
+    pub fn connections(&self) -> &HashMap<String, PooledConnection> {
+        &self.connections
+    }
+}
+
+/// Spawn a task that recycles idle pool connections and keeps the frequent
+/// counterparties warm
+///
+/// The network is only borrowed to take a dialer; the dials happen after it is released.
+pub fn spawn_connection_pool(network: Arc<RwLock<P2PNetwork>>, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let dialer = network.read().await.dialer();
+            match dialer {
+                Ok(dialer) => dialer.maintain_pool().await,
+                Err(e) => warn!("Failed to maintain connection pool: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewing_connections_are_evicted_first() {
+        let now = Instant::now();
+        let mut pool = ConnectionPool::new(2, Duration::from_secs(60), 2);
+
+        pool.insert("counterparty".to_string(), PathCandidate::direct(), PathConnection::WebRtc, WarmReason::Counterparty, now);
+        pool.insert("viewed".to_string(), PathCandidate::direct(), PathConnection::WebRtc, WarmReason::Viewing, now + Duration::from_secs(1));
+
+        let released = pool.insert("new".to_string(), PathCandidate::direct(), PathConnection::WebRtc, WarmReason::Viewing, now + Duration::from_secs(2));
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].0, "viewed");
+        assert!(pool.contains("counterparty"));
+        assert!(pool.contains("new"));
+    }
+
+    #[test]
+    fn test_idle_connections_are_recycled() {
+        let now = Instant::now();
+        let mut pool = ConnectionPool::new(4, Duration::from_secs(60), 2);
+
+        pool.insert("a".to_string(), PathCandidate::direct(), PathConnection::WebRtc, WarmReason::Viewing, now);
+        pool.insert("b".to_string(), PathCandidate::direct(), PathConnection::WebRtc, WarmReason::Viewing, now);
+        assert!(pool.checkout("b", now + Duration::from_secs(30)).is_some());
+
+        let later = now + Duration::from_secs(70);
+        assert!(pool.checkout("a", later).is_none());
+        let recycled = pool.recycle(later);
+        assert_eq!(recycled.len(), 1);
+        assert_eq!(recycled[0].0, "a");
+        assert!(pool.contains("b"));
+    }
+
+    #[test]
+    fn test_frequent_counterparties() {
+        let mut pool = ConnectionPool::new(2, Duration::from_secs(60), 2);
+
+        for peer_id in ["a", "b", "b", "c", "c", "c", "d", "d"] {
+            pool.record_trade(peer_id);
+        }
+        assert_eq!(pool.frequent_counterparties(), vec!["c".to_string(), "b".to_string()]);
+
+        pool.insert("c".to_string(), PathCandidate::direct(), PathConnection::WebRtc, WarmReason::Counterparty, Instant::now());
+        assert_eq!(pool.to_warm(), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn test_counters_are_bounded() {
+        let mut pool = ConnectionPool::new(2, Duration::from_secs(60), 2);
+
+        pool.record_trade("regular");
+        pool.record_trade("regular");
+        for i in 0..MAX_TRACKED_COUNTERPARTIES * 2 {
+            pool.record_trade(&format!("peer-{}", i));
+        }
+
+        assert_eq!(pool.trade_counts.len(), MAX_TRACKED_COUNTERPARTIES);
+        assert_eq!(pool.frequent_counterparties(), vec!["regular".to_string()]);
+    }
+
+    #[test]
+    fn test_replaced_connection_is_released() {
+        let now = Instant::now();
+        let mut pool = ConnectionPool::new(2, Duration::from_secs(60), 2);
+        let relay = PathConnection::Relay { server_id: "relay".to_string(), relay_id: "circuit".to_string() };
+
+        pool.insert("a".to_string(), PathCandidate::relayed("relay".to_string()), relay.clone(), WarmReason::Viewing, now);
+        let released = pool.insert("a".to_string(), PathCandidate::direct(), PathConnection::WebRtc, WarmReason::Viewing, now);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].1.connection, relay);
+    }
+}
@@ -0,0 +1,173 @@
+//! Fault injection for integration tests
+//!
+//! Real networks lose, delay, reorder and repeat messages. A [`FaultInjector`]
+//! does the same to the messages passing through it, with the rates of a
+//! [`FaultConfig`] and a seed, so a failing run can be replayed exactly. The
+//! network runs its outbound messages through one when `p2p.faults` is set, and
+//! tests wiring nodes together by hand can put one on each link.
+//!
+//! Only built with the `fault-injection` feature; never enable it in a release.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::config::FaultConfig;
+
+/// Messages each kind of fault was applied to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaultStats {
+    /// Messages passed in
+    pub messages: u64,
+    /// Messages dropped
+    pub dropped: u64,
+    /// Messages delayed
+    pub delayed: u64,
+    /// Messages held back behind the next one on their stream
+    pub reordered: u64,
+    /// Messages delivered twice
+    pub duplicated: u64,
+}
+
+/// Applies the faults of a configuration to a message stream
+pub struct FaultInjector {
+    /// Fault rates
+    config: FaultConfig,
+    /// Random source
+    rng: StdRng,
+    /// Message held back on each stream, delivered after the next one
+    held: HashMap<String, Vec<u8>>,
+    /// Fault counts
+    stats: FaultStats,
+}
+
+impl FaultInjector {
+    /// Create an injector; without a seed in `config` it is seeded at random
+    pub fn new(config: FaultConfig) -> Self {
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Self {
+            config,
+            rng,
+            held: HashMap::new(),
+            stats: FaultStats::default(),
+        }
+    }
+
+    /// Roll for a fault happening at `rate`
+    fn roll(&mut self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.gen_bool(rate.min(1.0))
+    }
+
+    /// Pass a message on `stream` through the faults, returning the messages to deliver now, in order
+    ///
+    /// Messages are only reordered within a stream, e.g. a topic or a peer and
+    /// protocol, and a held message is released by the next one on its stream
+    /// or by [`FaultInjector::flush`].
+    pub fn inject(&mut self, stream: &str, data: Vec<u8>) -> Vec<Vec<u8>> {
+        self.stats.messages += 1;
+
+        if self.roll(self.config.drop_rate) {
+            self.stats.dropped += 1;
+            return Vec::new();
+        }
+
+        let mut deliveries = Vec::new();
+        if self.roll(self.config.duplicate_rate) {
+            self.stats.duplicated += 1;
+            deliveries.push(data.clone());
+        }
+        deliveries.push(data);
+
+        match self.held.remove(stream) {
+            Some(held) => deliveries.push(held),
+            None if self.roll(self.config.reorder_rate) => {
+                self.stats.reordered += 1;
+                let data = deliveries.pop().unwrap_or_default();
+                self.held.insert(stream.to_string(), data);
+            }
+            None => {}
+        }
+
+        deliveries
+    }
+
+    /// Get the delay to wait before delivering the next messages
+    pub fn delay(&mut self) -> Duration {
+        if !self.roll(self.config.delay_rate) {
+            return Duration::ZERO;
+        }
+        self.stats.delayed += 1;
+
+        let jitter = self.rng.gen_range(0..=self.config.delay_jitter_ms);
+        Duration::from_millis(self.config.delay_ms.saturating_add(jitter))
+    }
+
+    /// Release the messages held back on every stream
+    pub fn flush(&mut self) -> Vec<(String, Vec<u8>)> {
+        let mut held: Vec<(String, Vec<u8>)> = self.held.drain().collect();
+        held.sort_by(|a, b| a.0.cmp(&b.0));
+        held
+    }
+
+    /// Get the fault counts so far
+    pub fn stats(&self) -> FaultStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seed: u64) -> FaultConfig {
+        FaultConfig {
+            drop_rate: 0.2,
+            duplicate_rate: 0.2,
+            reorder_rate: 0.2,
+            delay_rate: 0.5,
+            delay_ms: 10,
+            delay_jitter_ms: 5,
+            seed: Some(seed),
+        }
+    }
+
+    fn run(injector: &mut FaultInjector) -> Vec<Vec<u8>> {
+        let mut delivered = Vec::new();
+        for n in 0..200u8 {
+            delivered.extend(injector.inject("topic", vec![n]));
+        }
+        delivered.extend(injector.flush().into_iter().map(|(_, data)| data));
+        delivered
+    }
+
+    #[test]
+    fn test_faults_are_seeded() {
+        let mut first = FaultInjector::new(config(7));
+        let mut second = FaultInjector::new(config(7));
+        assert_eq!(run(&mut first), run(&mut second));
+
+        let stats = first.stats();
+        assert_eq!(stats.messages, 200);
+        assert!(stats.dropped > 0 && stats.duplicated > 0 && stats.reordered > 0);
+
+        // Every message arrives once, plus its duplicates, unless dropped
+        let delivered = run(&mut FaultInjector::new(config(7)));
+        assert_eq!(delivered.len() as u64, 200 - stats.dropped + stats.duplicated);
+    }
+
+    #[test]
+    fn test_no_faults_by_default() {
+        let mut injector = FaultInjector::new(FaultConfig { seed: Some(1), ..FaultConfig::default() });
+        let delivered = run(&mut injector);
+
+        assert_eq!(delivered, (0..200u8).map(|n| vec![n]).collect::<Vec<_>>());
+        assert_eq!(injector.delay(), Duration::ZERO);
+    }
+}
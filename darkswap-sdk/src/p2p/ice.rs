@@ -0,0 +1,184 @@
+//! ICE configuration
+//!
+//! WebRTC connections gather candidates from the STUN and TURN servers of the
+//! node's configuration. Deployments can point the SDK at their own servers, run
+//! STUN-only by listing no TURN servers, or force every connection through TURN
+//! with the `relay` transport policy, so no peer ever learns their address.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::P2PConfig;
+
+/// Candidates a connection may use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IceTransportPolicy {
+    /// Host, server reflexive and relay candidates
+    #[default]
+    All,
+    /// TURN relay candidates only
+    Relay,
+}
+
+impl fmt::Display for IceTransportPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IceTransportPolicy::All => write!(f, "all"),
+            IceTransportPolicy::Relay => write!(f, "relay"),
+        }
+    }
+}
+
+impl FromStr for IceTransportPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(IceTransportPolicy::All),
+            "relay" => Ok(IceTransportPolicy::Relay),
+            _ => bail!("Invalid ICE transport policy: {}", s),
+        }
+    }
+}
+
+/// TURN server with its credentials
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TurnServer {
+    /// URLs, e.g. `turn:turn.example.com:3478?transport=udp`
+    pub urls: Vec<String>,
+    /// Username
+    pub username: String,
+    /// Credential
+    pub credential: String,
+}
+
+/// ICE server, as handed to a WebRTC implementation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IceServer {
+    /// URLs
+    pub urls: Vec<String>,
+    /// Username; TURN servers only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Credential; TURN servers only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+/// ICE servers and transport policy of WebRTC connections
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IceConfiguration {
+    /// ICE servers
+    pub servers: Vec<IceServer>,
+    /// Transport policy
+    pub transport_policy: IceTransportPolicy,
+}
+
+/// Get the scheme of an ICE server URL
+fn scheme(url: &str) -> &str {
+    url.split_once(':').map_or("", |(scheme, _)| scheme)
+}
+
+impl IceConfiguration {
+    /// Build the configuration of the P2P settings, checking that it can connect at all
+    pub fn from_config(config: &P2PConfig) -> Result<Self> {
+        let mut servers = Vec::new();
+
+        for url in &config.ice_servers {
+            match scheme(url) {
+                "stun" | "stuns" => {}
+                "turn" | "turns" => bail!("TURN server {} needs credentials; list it in turn_servers", url),
+                _ => bail!("Invalid STUN server URL: {}", url),
+            }
+            servers.push(IceServer { urls: vec![url.clone()], username: None, credential: None });
+        }
+
+        for turn_server in &config.turn_servers {
+            if turn_server.urls.is_empty() {
+                bail!("TURN server without URLs");
+            }
+            if let Some(url) = turn_server.urls.iter().find(|url| !matches!(scheme(url), "turn" | "turns")) {
+                bail!("Invalid TURN server URL: {}", url);
+            }
+            servers.push(IceServer {
+                urls: turn_server.urls.clone(),
+                username: Some(turn_server.username.clone()),
+                credential: Some(turn_server.credential.clone()),
+            });
+        }
+
+        let ice = Self { servers, transport_policy: config.ice_transport_policy };
+        if ice.transport_policy == IceTransportPolicy::Relay && !ice.has_turn() {
+            bail!("The relay ICE transport policy needs a TURN server");
+        }
+
+        Ok(ice)
+    }
+
+    /// Check if a TURN server is configured
+    pub fn has_turn(&self) -> bool {
+        self.servers.iter().any(|server| server.credential.is_some())
+    }
+
+    /// Check if connections use STUN servers only, so they are either direct or hole punched
+    pub fn is_stun_only(&self) -> bool {
+        !self.servers.is_empty() && !self.has_turn()
+    }
+
+    /// Check if connections may use host candidates, i.e. connect without any ICE server
+    pub fn allows_direct(&self) -> bool {
+        self.transport_policy == IceTransportPolicy::All
+    }
+
+    /// Get the URLs of all servers
+    pub fn urls(&self) -> Vec<String> {
+        self.servers.iter().flat_map(|server| server.urls.iter().cloned()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn_server() -> TurnServer {
+        TurnServer {
+            urls: vec!["turn:turn.example.com:3478".to_string()],
+            username: "darkswap".to_string(),
+            credential: "secret".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ice_configuration() {
+        let mut config = P2PConfig::default();
+        let ice = IceConfiguration::from_config(&config).unwrap();
+        assert!(ice.is_stun_only());
+        assert!(ice.allows_direct());
+
+        config.turn_servers = vec![turn_server()];
+        config.ice_transport_policy = IceTransportPolicy::Relay;
+        let ice = IceConfiguration::from_config(&config).unwrap();
+        assert!(!ice.is_stun_only() && !ice.allows_direct());
+        assert_eq!(ice.servers.last().unwrap().username.as_deref(), Some("darkswap"));
+
+        // Relay only cannot connect without a TURN server
+        config.turn_servers.clear();
+        assert!(IceConfiguration::from_config(&config).is_err());
+
+        // TURN URLs need credentials
+        config.ice_transport_policy = IceTransportPolicy::All;
+        config.ice_servers.push("turn:turn.example.com:3478".to_string());
+        assert!(IceConfiguration::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_transport_policy_parse() {
+        assert_eq!("relay".parse::<IceTransportPolicy>().unwrap(), IceTransportPolicy::Relay);
+        assert_eq!("ALL".parse::<IceTransportPolicy>().unwrap(), IceTransportPolicy::All);
+        assert!("host".parse::<IceTransportPolicy>().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//! Inbound messages
+//!
+//! The transport hands every gossip message and request it receives to the
+//! network's inbound channel, tagged with the topic or request-response protocol
+//! it arrived on. The node takes the receiving end on start and routes each
+//! message to the module that handles it (see `crate::dispatch`). A response
+//! to one of the node's own requests arrives the same way, on the protocol of
+//! the request and without a response channel.
+
+use libp2p::PeerId;
+use tokio::sync::{mpsc, oneshot};
+
+/// Messages the inbound channel holds before the transport waits for the node
+pub const INBOUND_CAPACITY: usize = 1024;
+
+/// Where an inbound message arrived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InboundSource {
+    /// Gossip topic
+    Topic(String),
+    /// Request-response protocol
+    Protocol(String),
+}
+
+/// Message received from a peer
+#[derive(Debug)]
+pub struct InboundMessage {
+    /// Peer the message came from
+    pub peer_id: PeerId,
+    /// Topic or protocol the message arrived on
+    pub source: InboundSource,
+    /// Encoded message
+    pub data: Vec<u8>,
+    /// Response to a request; dropping it closes the stream without one
+    pub response: Option<oneshot::Sender<Vec<u8>>>,
+}
+
+impl InboundMessage {
+    /// Create a gossip message received on `topic`
+    pub fn topic(peer_id: PeerId, topic: &str, data: Vec<u8>) -> Self {
+        Self {
+            peer_id,
+            source: InboundSource::Topic(topic.to_string()),
+            data,
+            response: None,
+        }
+    }
+
+    /// Create a request received over `protocol`, with the channel its response goes to
+    pub fn request(peer_id: PeerId, protocol: &str, data: Vec<u8>) -> (Self, oneshot::Receiver<Vec<u8>>) {
+        let (response, receiver) = oneshot::channel();
+        let message = Self {
+            peer_id,
+            source: InboundSource::Protocol(protocol.to_string()),
+            data,
+            response: Some(response),
+        };
+
+        (message, receiver)
+    }
+
+    /// Answer the request, if the message is one
+    pub fn respond(&mut self, data: Vec<u8>) {
+        if let Some(response) = self.response.take() {
+            let _ = response.send(data);
+        }
+    }
+}
+
+/// Create the inbound channel
+pub fn channel() -> (mpsc::Sender<InboundMessage>, mpsc::Receiver<InboundMessage>) {
+    mpsc::channel(INBOUND_CAPACITY)
+}
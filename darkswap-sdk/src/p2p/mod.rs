@@ -3,24 +3,51 @@
 //! This module provides P2P networking functionality for DarkSwap, including WebRTC transport,
 //! circuit relay, and peer discovery.
 
+// Network failures are reported to the caller, never panicked on
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+
 use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context as AnyhowContext, Result};
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
 use libp2p::core::multiaddr::{Multiaddr, Protocol};
 use libp2p::core::PeerId;
 use log::{debug, error, info, warn};
-use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::sync::{mpsc, Mutex, Notify, RwLock};
+use tokio::task::JoinHandle;
 
-use crate::config::Config;
-use crate::types::Event;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, DnsConfig};
+use crate::orderbook::RoutingHints;
+use crate::types::{Event, SerializablePeerId};
 
 pub mod circuit_relay;
+pub mod connection_pool;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
+pub mod ice;
+pub mod inbound;
+pub mod path_selection;
+pub mod peer_store;
 pub mod relay_manager;
+pub mod relay_store;
+pub mod rfq;
+pub mod send_queue;
 pub mod webrtc_transport;
 use circuit_relay::CircuitRelay;
+use connection_pool::{ConnectionPool, PathConnection, PooledConnection, WarmReason};
+use inbound::InboundMessage;
+use path_selection::{PathAttempt, PathCandidate, PathDialer, PathMetrics, Teardown};
+use peer_store::{PeerRecord, PeerStore};
 use relay_manager::{RelayManager, RelayManagerConfig, RelayServer, RelayServerStatus};
-use webrtc_transport::{DarkSwapWebRtcTransport, WebRtcSignalingClient};
+use relay_store::{RelayHealth, RelayOutcomes, RelayStore};
+use send_queue::{OutboundMessage, OutboundTarget, QueueMetrics, SendQueue};
+use ice::IceConfiguration;
 use webrtc_transport::{DarkSwapWebRtcTransport, WebRtcSignalingClient};
 
 /// P2P network event
@@ -40,6 +67,8 @@ pub enum P2PEvent {
 
 /// P2P network
 pub struct P2PNetwork {
+    /// Local keypair
+    local_key: libp2p::identity::Keypair,
     /// Local peer ID
     local_peer_id: PeerId,
     /// WebRTC transport
@@ -49,31 +78,107 @@ pub struct P2PNetwork {
     /// Circuit relay
     circuit_relay: Option<Arc<CircuitRelay>>,
     /// Relay manager
-    relay_manager: Option<RelayManager>,
+    relay_manager: Option<Arc<RelayManager>>,
     /// Connected peers
     connected_peers: Arc<Mutex<HashMap<PeerId, Multiaddr>>>,
     /// Event sender
     event_sender: mpsc::Sender<Event>,
+    /// Messages received from peers, handed to the node
+    inbound: mpsc::Sender<InboundMessage>,
+    /// Receiving end of the inbound messages, until the node takes it
+    inbound_receiver: Option<mpsc::Receiver<InboundMessage>>,
     /// Listen addresses
     listen_addresses: Vec<Multiaddr>,
     /// Bootstrap peers
     bootstrap_peers: Vec<Multiaddr>,
-    /// Relay servers
+    /// Relay servers, healthiest first
     relay_servers: Vec<Multiaddr>,
+    /// Configured relay servers
+    configured_relays: Vec<Multiaddr>,
+    /// Relay health history
+    relay_store: Arc<Mutex<RelayStore>>,
+    /// Interval between relay health checks
+    relay_check_interval: Duration,
+    /// Timeout of a relay health check
+    relay_check_timeout: Duration,
+    /// Task checking relay health
+    relay_checker: Option<JoinHandle<()>>,
+    /// Time source of the relay health records
+    clock: Arc<dyn Clock>,
     /// Topics
     topics: HashMap<String, String>,
+    /// ICE servers and transport policy
+    ice: IceConfiguration,
+    /// Path dialer
+    path_dialer: Arc<PathDialer>,
+    /// Dials in flight by peer, joined by concurrent connects to the same peer
+    dials: Arc<Mutex<HashMap<PeerId, SharedDial>>>,
+    /// Known peers
+    peer_store: Arc<Mutex<PeerStore>>,
+    /// Interval between saves of the peer store
+    peer_store_save_interval: Duration,
+    /// Task saving the peer store
+    peer_store_saver: Option<JoinHandle<()>>,
+    /// Warm connections to counterparties and viewed makers
+    connection_pool: Arc<Mutex<ConnectionPool>>,
+    /// Region advertised in own orders
+    region: Option<String>,
+    /// DNS resolver and IPv6 settings
+    dns: DnsConfig,
+    /// Outbound messages, by priority class
+    send_queue: Arc<Mutex<SendQueue>>,
+    /// Wakes the sender when a message is queued
+    send_ready: Arc<Notify>,
+    /// Task sending queued messages
+    sender: Option<JoinHandle<()>>,
+    /// Faults injected into outbound messages
+    #[cfg(feature = "fault-injection")]
+    faults: Option<faults::FaultInjector>,
 }
 
+/// Dial shared by concurrent connects to the same peer
+type SharedDial = Shared<BoxFuture<'static, Result<(PathCandidate, PathConnection), String>>>;
+
 impl P2PNetwork {
     /// Create a new P2P network
     pub fn new(config: &Config, event_sender: mpsc::Sender<Event>) -> Result<Self> {
-        // Generate a random peer ID
-        let local_key = libp2p::identity::Keypair::generate_ed25519();
+        // Use the configured identity key, or generate a random peer ID
+        let local_key = match &config.p2p.identity_key {
+            Some(key) => crate::reputation::decode_identity_key(key)?,
+            None => libp2p::identity::Keypair::generate_ed25519(),
+        };
         let local_peer_id = PeerId::from(local_key.public());
 
         info!("Local peer ID: {}", local_peer_id);
 
+        // Load known peers
+        let peer_store = match &config.p2p.peer_store_path {
+            Some(path) => PeerStore::open_file(config.store_file(path)?, config.p2p.peer_address_ttl)?,
+            None => PeerStore::new(config.p2p.peer_address_ttl),
+        };
+
+        // Load relay health, trying the relays that worked before first
+        let mut relay_store = match &config.p2p.relay_store_path {
+            Some(path) => RelayStore::open_file(config.store_file(path)?)?,
+            None => RelayStore::new(),
+        };
+        let configured_relays = dialable(&config.dns, &config.p2p.relay_servers);
+        if !config.p2p.remember_relays {
+            let forgotten = relay_store.forget_unconfigured(&configured_relays);
+            if forgotten > 0 {
+                debug!("Forgot {} relays no longer configured", forgotten);
+            }
+        }
+        let relay_servers: Vec<Multiaddr> = relay_store
+            .ranked(&configured_relays, config.p2p.remember_relays)
+            .into_iter()
+            .filter_map(|addr| crate::dns::dialable_address(&config.dns, &addr))
+            .collect();
+
+        let (inbound, inbound_receiver) = inbound::channel();
+
         Ok(Self {
+            local_key,
             local_peer_id,
             webrtc_transport: None,
             webrtc_signaling: None,
@@ -81,24 +186,96 @@ impl P2PNetwork {
             relay_manager: None,
             connected_peers: Arc::new(Mutex::new(HashMap::new())),
             event_sender,
+            inbound,
+            inbound_receiver: Some(inbound_receiver),
             listen_addresses: config.p2p.listen_addresses.clone(),
-            bootstrap_peers: config.p2p.bootstrap_peers.clone(),
-            relay_servers: config.p2p.relay_servers.clone(),
+            bootstrap_peers: dialable(&config.dns, &config.p2p.bootstrap_peers),
+            relay_servers,
+            configured_relays,
+            relay_store: Arc::new(Mutex::new(relay_store)),
+            relay_check_interval: Duration::from_secs(config.p2p.relay_check_interval),
+            relay_check_timeout: Duration::from_secs(config.p2p.dial_timeout),
+            relay_checker: None,
+            clock: Arc::new(SystemClock),
             topics: HashMap::new(),
+            ice: IceConfiguration::from_config(&config.p2p)?,
+            path_dialer: Arc::new(PathDialer::new(
+                Duration::from_millis(config.p2p.dial_stagger_ms),
+                Duration::from_secs(config.p2p.dial_timeout),
+            )),
+            dials: Arc::new(Mutex::new(HashMap::new())),
+            peer_store: Arc::new(Mutex::new(peer_store)),
+            peer_store_save_interval: Duration::from_secs(config.p2p.peer_store_save_interval),
+            peer_store_saver: None,
+            connection_pool: Arc::new(Mutex::new(ConnectionPool::new(
+                config.p2p.pool_size,
+                Duration::from_secs(config.p2p.pool_idle_ttl),
+                config.p2p.pool_frequent_after,
+            ))),
+            region: config.p2p.region.clone(),
+            dns: config.dns.clone(),
+            send_queue: Arc::new(Mutex::new(SendQueue::new(&config.p2p.send_queue))),
+            send_ready: Arc::new(Notify::new()),
+            sender: None,
+            #[cfg(feature = "fault-injection")]
+            faults: config.p2p.faults.clone().map(faults::FaultInjector::new),
         })
     }
 
+    /// Read the time of relay health records from `clock` instead of the wall clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     /// Start the P2P network
     pub async fn start(&mut self) -> Result<()> {
+        // Hostnames go through the configured resolver, unless the defaults apply
+        #[cfg(not(target_arch = "wasm32"))]
+        let resolver = if self.dns.is_default() {
+            None
+        } else {
+            Some(Arc::new(crate::dns::resolver(&self.dns)?))
+        };
+
+        // Reconnect to known peers alongside the bootstrap peers
+        {
+            let mut peer_store = self.peer_store.lock().await;
+            for addr in &self.bootstrap_peers {
+                if let Some(peer_id) = Self::extract_peer_id(addr) {
+                    peer_store.add_address(&peer_id.to_string(), addr.clone());
+                }
+            }
+
+            let known_addresses = peer_store.dial_addresses();
+            info!("Loaded {} known peers from the peer store", peer_store.len());
+            for addr in known_addresses {
+                if let Some(addr) = crate::dns::dialable_address(&self.dns, &addr) {
+                    if !self.bootstrap_peers.contains(&addr) {
+                        self.bootstrap_peers.push(addr);
+                    }
+                }
+            }
+        }
+
+        // Dial the peers at the addresses of the configured resolver
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(resolver) = &resolver {
+            let mut resolved = Vec::with_capacity(self.bootstrap_peers.len());
+            for addr in &self.bootstrap_peers {
+                match crate::dns::resolve_multiaddr(resolver, addr).await {
+                    Ok(addr) => resolved.push(addr),
+                    Err(e) => warn!("Skipping peer address {}: {}", addr, e),
+                }
+            }
+            self.bootstrap_peers = resolved;
+        }
+
         // Create WebRTC transport
-        let ice_servers = vec![
-            "stun:stun.l.google.com:19302".to_string(),
-            "stun:stun1.l.google.com:19302".to_string(),
-        ];
         let signaling_server_url = Some("wss://signaling.darkswap.io".to_string());
         
         let webrtc_transport = DarkSwapWebRtcTransport::new(
-            ice_servers,
+            self.ice.clone(),
             signaling_server_url.clone(),
         ).await?;
         
@@ -131,26 +308,57 @@ impl P2PNetwork {
         
         // Create relay manager
         let mut relay_servers = Vec::new();
+        let mut relay_addresses = HashMap::new();
+        
+        // IPv6 relays are tried first if preferred
+        let mut relay_addrs = self.relay_servers.clone();
+        if self.dns.prefer_ipv6 {
+            relay_addrs.sort_by_key(|addr| !addr.iter().any(|proto| matches!(proto, Protocol::Ip6(_) | Protocol::Dns6(_))));
+        }
         
         // Convert relay server multiaddrs to relay server configs
-        for addr in &self.relay_servers {
+        for addr in &relay_addrs {
             if let Some(peer_id) = Self::extract_peer_id(addr) {
                 // Extract the host and port from the multiaddr
                 let mut host = String::new();
+                let mut domain = None;
                 let mut port = 9002; // Default signaling port
                 
                 for proto in addr.iter() {
                     match proto {
                         Protocol::Ip4(ip) => host = ip.to_string(),
-                        Protocol::Ip6(ip) => host = ip.to_string(),
-                        Protocol::Dns(domain) => host = domain.to_string(),
-                        Protocol::Dns4(domain) => host = domain.to_string(),
-                        Protocol::Dns6(domain) => host = domain.to_string(),
+                        Protocol::Ip6(ip) => host = format!("[{}]", ip),
+                        Protocol::Dns(name) => domain = Some((name.to_string(), None)),
+                        Protocol::Dns4(name) => domain = Some((name.to_string(), Some(false))),
+                        Protocol::Dns6(name) => domain = Some((name.to_string(), Some(true))),
                         Protocol::Tcp(p) => port = p,
                         _ => {}
                     }
                 }
                 
+                if let Some((name, family)) = domain {
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        host = match &resolver {
+                            Some(resolver) => match crate::dns::resolve_host(resolver, &name, family).await {
+                                Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+                                Ok(ip) => ip.to_string(),
+                                Err(e) => {
+                                    warn!("Skipping relay server {}: {}", addr, e);
+                                    continue;
+                                }
+                            },
+                            None => name,
+                        };
+                    }
+                    // The browser resolves the name
+                    #[cfg(target_arch = "wasm32")]
+                    {
+                        let _ = family;
+                        host = name;
+                    }
+                }
+                
                 // Create the relay server URL
                 let url = format!("ws://{}:{}/signaling", host, port);
                 
@@ -163,6 +371,7 @@ impl P2PNetwork {
                     latency_ms: None,
                 };
                 
+                relay_addresses.insert(server.id.clone(), addr.clone());
                 relay_servers.push(server);
             }
         }
@@ -177,22 +386,54 @@ impl P2PNetwork {
         };
         
         // Create the relay manager
-        let mut relay_manager = RelayManager::new(
+        let outcomes = RelayOutcomes::new(self.relay_store.clone(), relay_addresses, self.clock.clone());
+        let relay_manager = Arc::new(RelayManager::new(
             relay_config,
             webrtc_transport.clone(),
             circuit_relay.clone(),
             self.local_peer_id,
-        );
+        ).with_outcomes(Arc::new(outcomes)));
         
         // Start the relay manager
+        let runner = relay_manager.clone();
         tokio::spawn(async move {
-            if let Err(e) = relay_manager.start().await {
+            if let Err(e) = runner.start().await {
                 error!("Failed to start relay manager: {:?}", e);
             }
         });
         
         self.relay_manager = Some(relay_manager);
 
+        // Check relay health in the background
+        #[cfg(not(target_arch = "wasm32"))]
+        if !self.relay_check_interval.is_zero() && !self.relay_servers.is_empty() {
+            let checker = relay_store::spawn_relay_health_checker(
+                self.relay_store.clone(),
+                self.relay_servers.clone(),
+                self.relay_check_interval,
+                self.relay_check_timeout,
+                resolver.clone(),
+                self.clock.clone(),
+            );
+            if let Some(previous) = self.relay_checker.replace(checker) {
+                previous.abort();
+            }
+        }
+
+        // Save known peers periodically, so a crash loses little of what was learned
+        if !self.peer_store_save_interval.is_zero() {
+            let saver = spawn_peer_store_saver(self.peer_store.clone(), self.peer_store_save_interval);
+            if let Some(previous) = self.peer_store_saver.replace(saver) {
+                previous.abort();
+            }
+        }
+
+        // Send queued messages
+        let sender = tokio::spawn(Self::run_sender(self.send_queue.clone(), self.send_ready.clone()));
+        if let Some(previous) = self.sender.replace(sender) {
+            previous.abort();
+        }
+
         // Process events
         self.process_events().await?;
 
@@ -206,6 +447,27 @@ impl P2PNetwork {
             signaling.disconnect().await?;
         }
 
+        // Persist known peers
+        if let Some(saver) = self.peer_store_saver.take() {
+            saver.abort();
+        }
+        if let Err(e) = self.save_peer_store().await {
+            warn!("Failed to save peer store: {}", e);
+        }
+
+        // Persist relay health
+        if let Some(checker) = self.relay_checker.take() {
+            checker.abort();
+        }
+        if let Err(e) = self.relay_store.lock().await.save() {
+            warn!("Failed to save relay store: {}", e);
+        }
+
+        // Stop sending; messages still queued are kept for the next start
+        if let Some(sender) = self.sender.take() {
+            sender.abort();
+        }
+
         // Clear state
         self.webrtc_transport = None;
         self.webrtc_signaling = None;
@@ -222,10 +484,12 @@ impl P2PNetwork {
         // Clone event sender
         let event_sender = self.event_sender.clone();
         let connected_peers = self.connected_peers.clone();
+        let inbound = self.inbound.clone();
 
         // Spawn event processing task
         tokio::spawn(async move {
-            // In a real implementation, we would process events from the swarm
+            // In a real implementation, we would process events from the swarm,
+            // handing gossip messages and requests to `inbound`
             // For now, just log a message
             info!("P2P event processing started");
         });
@@ -270,6 +534,103 @@ impl P2PNetwork {
         }
     }
     
+    /// Connect to a peer over the fastest available path
+    ///
+    /// This dials while borrowing the network; callers holding a lock on it should
+    /// take a [`PeerDialer`] and release the lock first.
+    pub async fn connect_to_peer(&self, peer_id: PeerId) -> Result<PathCandidate> {
+        self.dialer()?.connect(peer_id).await
+    }
+
+    /// Get a dialer sharing the network's transports, usable after releasing the network
+    pub fn dialer(&self) -> Result<PeerDialer> {
+        let webrtc_transport = self.webrtc_transport.clone()
+            .ok_or_else(|| anyhow::anyhow!("WebRTC transport not initialized"))?;
+
+        Ok(PeerDialer {
+            local_peer_id: self.local_peer_id,
+            dns: self.dns.clone(),
+            webrtc_transport,
+            relay_manager: self.relay_manager.clone(),
+            ice: self.ice.clone(),
+            path_dialer: self.path_dialer.clone(),
+            relay_servers: self.relay_servers.clone(),
+            connected_peers: self.connected_peers.clone(),
+            peer_store: self.peer_store.clone(),
+            connection_pool: self.connection_pool.clone(),
+            dials: self.dials.clone(),
+            event_sender: self.event_sender.clone(),
+        })
+    }
+
+    /// Record a completed trade with a counterparty, so frequent ones are kept warm
+    pub async fn record_counterparty(&self, peer_id: &str) {
+        self.connection_pool.lock().await.record_trade(peer_id);
+    }
+
+    /// Get the warm connections by peer ID
+    pub async fn warm_connections(&self) -> HashMap<String, PooledConnection> {
+        self.connection_pool.lock().await.connections().clone()
+    }
+
+    /// Get the hints on how to reach this node, advertised in own orders
+    pub fn routing_hints(&self) -> RoutingHints {
+        RoutingHints {
+            relays: self.relay_servers.iter().map(|addr| addr.to_string()).collect(),
+            region: self.region.clone(),
+        }
+    }
+
+    /// Get the last measured connect latency to a peer in milliseconds
+    ///
+    /// Latency is measured whenever the peer is dialed, including pre-dials of the
+    /// makers of viewed orders.
+    pub async fn peer_latency(&self, peer_id: &str) -> Option<u64> {
+        self.peer_store.lock().await.get(peer_id).and_then(|record| record.latency_ms)
+    }
+
+    /// Get the path selection metrics
+    pub async fn path_metrics(&self) -> PathMetrics {
+        self.path_dialer.metrics().await
+    }
+
+    /// Record an address of a peer in the peer store
+    pub async fn add_peer_address(&self, peer_id: &PeerId, addr: Multiaddr) {
+        self.peer_store.lock().await.add_address(&peer_id.to_string(), addr);
+    }
+
+    /// Record the protocols supported by a peer in the peer store
+    pub async fn set_peer_protocols(&self, peer_id: &PeerId, protocols: Vec<String>) {
+        self.peer_store.lock().await.set_protocols(&peer_id.to_string(), protocols);
+    }
+
+    /// Record the reputation score of a known peer
+    pub async fn set_peer_reputation(&self, peer_id: &str, score: f64) {
+        self.peer_store.lock().await.set_reputation(peer_id, score);
+    }
+
+    /// Get the known peers
+    pub async fn known_peers(&self) -> Vec<PeerRecord> {
+        self.peer_store.lock().await.peers()
+    }
+
+    /// Save the peer store to disk
+    pub async fn save_peer_store(&self) -> Result<()> {
+        let mut peer_store = self.peer_store.lock().await;
+        peer_store.expire();
+        peer_store.save()
+    }
+
+    /// Get the relays in use with their health, in the order they are tried
+    pub async fn list_relays(&self) -> Vec<RelayHealth> {
+        self.relay_store.lock().await.health(&self.relay_servers, &self.configured_relays)
+    }
+
+    /// Forget all known peers, including the persisted copy
+    pub async fn clear_peer_store(&self) -> Result<()> {
+        self.peer_store.lock().await.clear()
+    }
+    
     /// Send data to a peer via relay
     pub async fn send_via_relay(&mut self, peer_id: PeerId, relay_id: &str, data: Vec<u8>) -> Result<()> {
         // Check if we have a relay manager
@@ -302,31 +663,456 @@ impl P2PNetwork {
 
     /// Publish a message to a topic
     pub async fn publish(&mut self, topic_name: &str, data: Vec<u8>) -> Result<()> {
-        // In a real implementation, we would publish a message to a gossipsub topic
-        // For now, just log a message
-        debug!("Published message to topic: {}", topic_name);
+        for data in self.inject_faults(&format!("topic:{}", topic_name), data).await {
+            self.enqueue(OutboundTarget::Topic(topic_name.to_string()), data).await?;
+        }
+        
+        Ok(())
+    }
+
+    /// Send a request to a single peer over a request-response protocol
+    pub async fn send_request(&mut self, peer_id: &PeerId, protocol: &str, data: Vec<u8>) -> Result<()> {
+        for data in self.inject_faults(&format!("peer:{}:{}", peer_id, protocol), data).await {
+            let target = OutboundTarget::Peer { peer_id: *peer_id, protocol: protocol.to_string() };
+            self.enqueue(target, data).await?;
+        }
+        
+        Ok(())
+    }
+
+    /// Queue a message for the sender
+    async fn enqueue(&self, target: OutboundTarget, data: Vec<u8>) -> Result<()> {
+        self.send_queue.lock().await.push(OutboundMessage::new(target, data, Instant::now()))?;
+        self.send_ready.notify_one();
         
         Ok(())
     }
 
+    /// Send queued messages, highest class first, until stopped
+    async fn run_sender(send_queue: Arc<Mutex<SendQueue>>, send_ready: Arc<Notify>) {
+        loop {
+            let next = send_queue.lock().await.pop(Instant::now());
+            let Some(message) = next else {
+                send_ready.notified().await;
+                continue;
+            };
+            let waited = message.queued_at.elapsed();
+            
+            match &message.target {
+                OutboundTarget::Topic(topic_name) => {
+                    // In a real implementation, we would publish a message to a gossipsub topic
+                    // For now, just log a message
+                    debug!(
+                        "Published {} byte {} message to topic {} after {:?} queued",
+                        message.data.len(), message.class, topic_name, waited
+                    );
+                }
+                OutboundTarget::Peer { peer_id, protocol } => {
+                    // In a real implementation, we would open a request-response stream to the peer
+                    // For now, just log a message
+                    debug!(
+                        "Sent {} byte {} request to peer {} after {:?} queued",
+                        message.data.len(), protocol, peer_id, waited
+                    );
+                }
+            }
+        }
+    }
+
+    /// Get the send queue metrics, with the queueing delay of each message class
+    pub async fn queue_metrics(&self) -> QueueMetrics {
+        self.send_queue.lock().await.metrics()
+    }
+
+    /// Run an outbound message through the configured faults, returning the messages to send
+    #[cfg(feature = "fault-injection")]
+    async fn inject_faults(&mut self, stream: &str, data: Vec<u8>) -> Vec<Vec<u8>> {
+        let Some(faults) = &mut self.faults else { return vec![data] };
+        
+        let delay = faults.delay();
+        let deliveries = faults.inject(stream, data);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        
+        deliveries
+    }
+
+    /// Run an outbound message through the configured faults; there are none in this build
+    #[cfg(not(feature = "fault-injection"))]
+    async fn inject_faults(&mut self, _stream: &str, data: Vec<u8>) -> Vec<Vec<u8>> {
+        vec![data]
+    }
+
+    /// Get the counts of the faults injected so far, if faults are configured
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_stats(&self) -> Option<faults::FaultStats> {
+        self.faults.as_ref().map(faults::FaultInjector::stats)
+    }
+
+    /// Take the receiving end of the messages received from peers
+    ///
+    /// There is one receiver; it is handed out once.
+    pub fn take_inbound(&mut self) -> Option<mpsc::Receiver<InboundMessage>> {
+        self.inbound_receiver.take()
+    }
+
+    /// Get a sender the transport hands received messages to
+    pub fn inbound_sender(&self) -> mpsc::Sender<InboundMessage> {
+        self.inbound.clone()
+    }
+
     /// Get connected peers
     pub async fn connected_peers(&self) -> HashMap<PeerId, Multiaddr> {
         self.connected_peers.lock().await.clone()
     }
 
+    /// Check if the network is listening: started, with its transport up and the sender running
+    pub fn is_listening(&self) -> bool {
+        self.webrtc_transport.is_some() && self.sender.as_ref().map_or(false, |sender| !sender.is_finished())
+    }
+
     /// Get local peer ID
     pub fn local_peer_id(&self) -> PeerId {
         self.local_peer_id
     }
 
+    /// Get the local keypair
+    pub fn keypair(&self) -> &libp2p::identity::Keypair {
+        &self.local_key
+    }
+
     /// Extract peer ID from multiaddr
     fn extract_peer_id(addr: &Multiaddr) -> Option<PeerId> {
         addr.iter().find_map(|proto| {
             if let Protocol::P2p(hash) = proto {
-                Some(PeerId::from_multihash(hash).expect("Valid multihash"))
+                PeerId::from_multihash(hash).ok()
             } else {
                 None
             }
         })
     }
-}
\ No newline at end of file
+}
+
+/// Dialer of peers, holding shared handles to the network's transports
+///
+/// Dialing takes up to the dial timeout, so it is done through a dialer taken
+/// from the network rather than while holding a lock on the network.
+#[derive(Clone)]
+pub struct PeerDialer {
+    /// Local peer ID
+    local_peer_id: PeerId,
+    /// DNS resolver and IPv6 settings, to filter advertised relays
+    dns: DnsConfig,
+    /// WebRTC transport
+    webrtc_transport: Arc<DarkSwapWebRtcTransport>,
+    /// Relay manager
+    relay_manager: Option<Arc<RelayManager>>,
+    /// ICE servers and transport policy
+    ice: IceConfiguration,
+    /// Path dialer
+    path_dialer: Arc<PathDialer>,
+    /// Relay servers, to derive the circuit addresses of relayed peers
+    relay_servers: Vec<Multiaddr>,
+    /// Connected peers
+    connected_peers: Arc<Mutex<HashMap<PeerId, Multiaddr>>>,
+    /// Known peers
+    peer_store: Arc<Mutex<PeerStore>>,
+    /// Warm connections
+    connection_pool: Arc<Mutex<ConnectionPool>>,
+    /// Dials in flight by peer
+    dials: Arc<Mutex<HashMap<PeerId, SharedDial>>>,
+    /// Event sender
+    event_sender: mpsc::Sender<Event>,
+}
+
+impl PeerDialer {
+    /// Connect to a peer over the fastest available path
+    ///
+    /// The direct, hole-punched and relayed paths are dialed in parallel. The first
+    /// one to connect is kept and relay circuits opened on the other paths are closed,
+    /// including those that connect after the race. Concurrent connects to the same
+    /// peer share one dial.
+    pub async fn connect(&self, peer_id: PeerId) -> Result<PathCandidate> {
+        self.connect_with_hints(peer_id, &RoutingHints::default()).await
+    }
+
+    /// Connect to a peer, trying the relays it advertised before the others
+    ///
+    /// The hints usually come from one of the peer's orders.
+    pub async fn connect_with_hints(&self, peer_id: PeerId, hints: &RoutingHints) -> Result<PathCandidate> {
+        // Use a warm connection if there is one and it is still up
+        if let Some(path) = self.checkout(peer_id).await {
+            debug!("Using warm {} connection to peer {}", path, peer_id);
+            return Ok(path);
+        }
+
+        Ok(self.shared_dial(peer_id, hints).await?.0)
+    }
+
+    /// Get the warm connection to a peer, dropping it from the pool if it went down
+    async fn checkout(&self, peer_id: PeerId) -> Option<PathCandidate> {
+        let peer = peer_id.to_string();
+        let path = self.connection_pool.lock().await.checkout(&peer, Instant::now())?;
+        if self.connected_peers.lock().await.contains_key(&peer_id) {
+            return Some(path);
+        }
+
+        debug!("Warm {} connection to peer {} went down", path, peer_id);
+        if let Some(connection) = self.connection_pool.lock().await.remove(&peer) {
+            self.release(&peer, connection).await;
+        }
+        self.disconnected(peer_id).await;
+        None
+    }
+
+    /// Dial a peer, joining the dial in flight if there is one
+    async fn shared_dial(&self, peer_id: PeerId, hints: &RoutingHints) -> Result<(PathCandidate, PathConnection)> {
+        // Join the dial in flight, or start one that removes itself once done
+        let dial = {
+            let mut dials = self.dials.lock().await;
+            match dials.get(&peer_id) {
+                Some(dial) => {
+                    debug!("Joining dial in flight to peer {}", peer_id);
+                    dial.clone()
+                }
+                None => {
+                    let dialer = self.clone();
+                    let hints = hints.clone();
+                    let dial = async move {
+                        let result = dialer.dial(peer_id, &hints).await.map_err(|e| format!("{:#}", e));
+                        dialer.dials.lock().await.remove(&peer_id);
+                        result
+                    }.boxed().shared();
+                    dials.insert(peer_id, dial.clone());
+                    dial
+                }
+            }
+        };
+
+        dial.await.map_err(anyhow::Error::msg)
+    }
+
+    /// Race the paths to a peer
+    async fn dial(&self, peer_id: PeerId, hints: &RoutingHints) -> Result<(PathCandidate, PathConnection)> {
+        // Collect the candidate paths, cheapest first; the relay policy rules out host candidates
+        let mut attempts: Vec<PathAttempt<'static, PathConnection>> = Vec::new();
+
+        if self.ice.allows_direct() {
+            let webrtc_transport = self.webrtc_transport.clone();
+            attempts.push((PathCandidate::direct(), async move {
+                webrtc_transport.connect_with_ice(peer_id, &IceConfiguration::default()).await?;
+                Ok(PathConnection::WebRtc)
+            }.boxed()));
+        }
+
+        if !self.ice.servers.is_empty() {
+            let webrtc_transport = self.webrtc_transport.clone();
+            let ice = self.ice.clone();
+            attempts.push((PathCandidate::hole_punched(), async move {
+                webrtc_transport.connect_with_ice(peer_id, &ice).await?;
+                Ok(PathConnection::WebRtc)
+            }.boxed()));
+        }
+
+        if let Some(relay_manager) = &self.relay_manager {
+            for server_id in self.hinted_first(peer_id, hints, relay_manager.get_open_servers().await).await {
+                let relay_manager = relay_manager.clone();
+                attempts.push((PathCandidate::relayed(server_id.clone()), async move {
+                    let relay_id = relay_manager.connect_to_peer_via(&server_id, &peer_id).await?;
+                    Ok(PathConnection::Relay { server_id, relay_id })
+                }.boxed()));
+            }
+        }
+
+        // Race the paths, closing the relay circuits of the losers
+        let relay_manager = self.relay_manager.clone();
+        let teardown: Teardown<PathConnection> = Arc::new(move |path, connection| {
+            let relay_manager = relay_manager.clone();
+            async move {
+                if let (Some(relay_manager), PathConnection::Relay { server_id, relay_id }) = (relay_manager, connection) {
+                    if let Err(e) = relay_manager.close_relay_via(&server_id, &relay_id).await {
+                        warn!("Failed to close losing {} path: {}", path, e);
+                    }
+                }
+            }.boxed()
+        });
+        let selection = self.path_dialer.race_with_teardown(attempts, teardown).await
+            .with_context(|| format!("Failed to connect to peer {}", peer_id))?;
+
+        info!("Connected to peer {} over {} path in {:?}", peer_id, selection.path, selection.latency);
+
+        // Remember the address the peer was reached at, so it is dialed again after a restart
+        let address = self.connection_address(peer_id, &selection.connection).await;
+        {
+            let mut peer_store = self.peer_store.lock().await;
+            peer_store.record_connection(
+                &peer_id.to_string(),
+                selection.path.kind,
+                selection.latency.as_millis() as u64,
+            );
+            if let Some(address) = &address {
+                peer_store.add_address(&peer_id.to_string(), address.clone());
+            }
+        }
+        if let Some(address) = address {
+            self.connected_peers.lock().await.insert(peer_id, address);
+        }
+        let _ = self.event_sender.send(Event::PeerConnected(SerializablePeerId(peer_id))).await;
+
+        Ok((selection.path, selection.connection))
+    }
+
+    /// Order the open relay servers with those a peer advertised first
+    ///
+    /// The circuit addresses through the advertised relays are remembered in the
+    /// peer store, so the peer can be dialed through them after a restart.
+    async fn hinted_first(&self, peer_id: PeerId, hints: &RoutingHints, open_servers: Vec<String>) -> Vec<String> {
+        let hinted: Vec<(String, Multiaddr)> = hints.relays
+            .iter()
+            .filter_map(|relay| relay.parse::<Multiaddr>().ok())
+            .filter_map(|addr| crate::dns::dialable_address(&self.dns, &addr))
+            .filter_map(|addr| P2PNetwork::extract_peer_id(&addr).map(|relay_id| (relay_id.to_string(), addr)))
+            .collect();
+
+        if !hinted.is_empty() {
+            let mut peer_store = self.peer_store.lock().await;
+            for (_, addr) in &hinted {
+                let circuit = addr.clone().with(Protocol::P2pCircuit).with(Protocol::P2p(peer_id.into()));
+                peer_store.add_address(&peer_id.to_string(), circuit);
+            }
+        }
+
+        let (mut first, rest): (Vec<String>, Vec<String>) = open_servers
+            .into_iter()
+            .partition(|server_id| hinted.iter().any(|(relay_id, _)| relay_id == server_id));
+        first.extend(rest);
+        first
+    }
+
+    /// Connect to a peer and keep the connection warm in the pool
+    pub async fn warm(&self, peer_id: PeerId, hints: &RoutingHints, reason: WarmReason) -> Result<()> {
+        if peer_id == self.local_peer_id || self.checkout(peer_id).await.is_some() {
+            return Ok(());
+        }
+
+        let (path, connection) = self.shared_dial(peer_id, hints).await?;
+        let released = self.connection_pool.lock().await.insert(peer_id.to_string(), path, connection, reason, Instant::now());
+        for (peer, connection) in released {
+            debug!("Released warm connection to peer {}", peer);
+            self.release(&peer, connection).await;
+        }
+
+        Ok(())
+    }
+
+    /// Pre-dial peers the user is likely to trade with, such as the makers of viewed orders
+    ///
+    /// The peers are dialed concurrently; one that cannot be reached does not
+    /// keep the others from being warmed. Returns the number warmed.
+    pub async fn prewarm(&self, peers: Vec<(PeerId, RoutingHints)>) -> usize {
+        if !self.connection_pool.lock().await.is_enabled() {
+            return 0;
+        }
+
+        let results = futures::future::join_all(
+            peers.iter().map(|(peer_id, hints)| self.warm(*peer_id, hints, WarmReason::Viewing)),
+        ).await;
+        let mut warmed = 0;
+        for ((peer_id, _), result) in peers.iter().zip(results) {
+            match result {
+                Ok(()) => warmed += 1,
+                Err(e) => debug!("Failed to pre-dial peer {}: {}", peer_id, e),
+            }
+        }
+
+        warmed
+    }
+
+    /// Recycle idle pool connections and dial the frequent counterparties without one
+    pub async fn maintain_pool(&self) {
+        let (recycled, to_warm) = {
+            let mut pool = self.connection_pool.lock().await;
+            (pool.recycle(Instant::now()), pool.to_warm())
+        };
+
+        for (peer, connection) in recycled {
+            debug!("Recycled idle connection to peer {}", peer);
+            self.release(&peer, connection).await;
+        }
+
+        for peer in to_warm {
+            let peer_id: PeerId = match peer.parse() {
+                Ok(peer_id) => peer_id,
+                Err(e) => {
+                    warn!("Invalid counterparty peer ID {}: {}", peer, e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.warm(peer_id, &RoutingHints::default(), WarmReason::Counterparty).await {
+                debug!("Failed to warm connection to counterparty {}: {}", peer, e);
+            }
+        }
+    }
+
+    /// Close a connection that left the pool
+    ///
+    /// A relay circuit is closed; a WebRTC connection is left to the transport,
+    /// which drops it once it goes idle.
+    async fn release(&self, peer: &str, connection: PooledConnection) {
+        if let (Some(relay_manager), PathConnection::Relay { server_id, relay_id }) = (&self.relay_manager, &connection.connection) {
+            if let Err(e) = relay_manager.close_relay_via(server_id, relay_id).await {
+                warn!("Failed to close pooled relay circuit to peer {}: {}", peer, e);
+            }
+            if let Ok(peer_id) = peer.parse::<PeerId>() {
+                if self.connected_peers.lock().await.remove(&peer_id).is_some() {
+                    self.disconnected(peer_id).await;
+                }
+            }
+        }
+    }
+
+    /// Report that the connection to a peer is gone
+    async fn disconnected(&self, peer_id: PeerId) {
+        let _ = self.event_sender.send(Event::PeerDisconnected(SerializablePeerId(peer_id))).await;
+    }
+
+    /// Get the address of a connection to a peer
+    ///
+    /// A WebRTC connection has the address the transport reports; a relay circuit
+    /// has the address of the relay followed by the circuit to the peer.
+    async fn connection_address(&self, peer_id: PeerId, connection: &PathConnection) -> Option<Multiaddr> {
+        match connection {
+            PathConnection::WebRtc => self.webrtc_transport.connected_peers().await.get(&peer_id).cloned(),
+            PathConnection::Relay { server_id, .. } => self.relay_servers.iter()
+                .find(|addr| P2PNetwork::extract_peer_id(addr).map(|id| id.to_string()).as_deref() == Some(server_id.as_str()))
+                .map(|addr| addr.clone().with(Protocol::P2pCircuit).with(Protocol::P2p(peer_id.into()))),
+        }
+    }
+}
+
+/// Save the peer store at an interval, dropping stale addresses first
+fn spawn_peer_store_saver(peer_store: Arc<Mutex<PeerStore>>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            let mut peer_store = peer_store.lock().await;
+            peer_store.expire();
+            if let Err(e) = peer_store.save() {
+                warn!("Failed to save peer store: {}", e);
+            }
+        }
+    })
+}
+
+/// Keep the addresses that may be dialed with the IPv6 settings
+fn dialable(dns: &DnsConfig, addrs: &[Multiaddr]) -> Vec<Multiaddr> {
+    addrs.iter()
+        .filter_map(|addr| crate::dns::dialable_address(dns, addr))
+        .collect()
+}
@@ -0,0 +1,396 @@
+//! Path selection for DarkSwap P2P network
+//!
+//! When connecting to a maker, the direct, hole-punched and relayed paths are dialed
+//! in parallel with a short stagger between them (happy-eyeballs style). The first path
+//! to connect is kept, and attempts still in flight are dropped, or with a teardown
+//! left to finish in the background and torn down. Wins, failures and connect
+//! latencies are recorded per path kind so the stagger can be tuned.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+/// Kind of network path to a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PathKind {
+    /// Direct connection without NAT traversal
+    Direct,
+    /// Direct connection established through STUN hole punching
+    HolePunched,
+    /// Connection relayed through a relay server
+    Relayed,
+}
+
+impl fmt::Display for PathKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathKind::Direct => write!(f, "direct"),
+            PathKind::HolePunched => write!(f, "hole-punched"),
+            PathKind::Relayed => write!(f, "relayed"),
+        }
+    }
+}
+
+/// Candidate path to a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathCandidate {
+    /// Path kind
+    pub kind: PathKind,
+    /// Relay server ID, for relayed paths
+    pub relay: Option<String>,
+}
+
+impl PathCandidate {
+    /// Create a direct path candidate
+    pub fn direct() -> Self {
+        Self {
+            kind: PathKind::Direct,
+            relay: None,
+        }
+    }
+
+    /// Create a hole-punched path candidate
+    pub fn hole_punched() -> Self {
+        Self {
+            kind: PathKind::HolePunched,
+            relay: None,
+        }
+    }
+
+    /// Create a relayed path candidate
+    pub fn relayed(relay: String) -> Self {
+        Self {
+            kind: PathKind::Relayed,
+            relay: Some(relay),
+        }
+    }
+}
+
+impl fmt::Display for PathCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.relay {
+            Some(relay) => write!(f, "{} via {}", self.kind, relay),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// Path selection statistics for one path kind
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathStats {
+    /// Number of dial attempts started
+    pub attempts: u64,
+    /// Number of races won
+    pub wins: u64,
+    /// Number of failed or timed out attempts
+    pub failures: u64,
+    /// Number of attempts dropped because another path won
+    pub cancelled: u64,
+    /// Total connect latency of won races in milliseconds
+    pub total_win_latency_ms: u64,
+}
+
+impl PathStats {
+    /// Get the average connect latency of won races in milliseconds
+    pub fn average_win_latency_ms(&self) -> Option<u64> {
+        if self.wins == 0 {
+            return None;
+        }
+
+        Some(self.total_win_latency_ms / self.wins)
+    }
+}
+
+/// Path selection metrics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PathMetrics {
+    /// Statistics by path kind
+    pub paths: HashMap<PathKind, PathStats>,
+    /// Number of races where every path failed
+    pub failed_races: u64,
+}
+
+impl PathMetrics {
+    /// Get the statistics for a path kind
+    pub fn get(&self, kind: PathKind) -> PathStats {
+        self.paths.get(&kind).cloned().unwrap_or_default()
+    }
+
+    /// Get the statistics for a path kind mutably
+    fn stats_mut(&mut self, kind: PathKind) -> &mut PathStats {
+        self.paths.entry(kind).or_default()
+    }
+}
+
+/// Result of a path race
+#[derive(Debug)]
+pub struct PathSelection<T> {
+    /// Winning path
+    pub path: PathCandidate,
+    /// Connection established on the winning path
+    pub connection: T,
+    /// Time from the start of the race to the connection
+    pub latency: Duration,
+    /// Losing paths that had already connected when the race was decided
+    ///
+    /// The caller is responsible for tearing these down, unless the race was run
+    /// with a teardown.
+    pub losers: Vec<(PathCandidate, T)>,
+}
+
+/// Dial attempt on one path
+pub type PathAttempt<'a, T> = (PathCandidate, BoxFuture<'a, Result<T>>);
+
+/// Tears down a connection on a path that lost the race
+pub type Teardown<T> = Arc<dyn Fn(PathCandidate, T) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Attempts still in flight when a race is decided
+type Pending<'a, T> = FuturesUnordered<BoxFuture<'a, (usize, PathCandidate, Result<T>)>>;
+
+/// Parallel path dialer
+pub struct PathDialer {
+    /// Delay between starting consecutive attempts
+    stagger: Duration,
+    /// Timeout of each attempt
+    timeout: Duration,
+    /// Metrics
+    metrics: Arc<RwLock<PathMetrics>>,
+}
+
+impl PathDialer {
+    /// Create a new path dialer
+    pub fn new(stagger: Duration, timeout: Duration) -> Self {
+        Self {
+            stagger,
+            timeout,
+            metrics: Arc::new(RwLock::new(PathMetrics::default())),
+        }
+    }
+
+    /// Get a copy of the path selection metrics
+    pub async fn metrics(&self) -> PathMetrics {
+        self.metrics.read().await.clone()
+    }
+
+    /// Race the given attempts and keep the first one to connect
+    ///
+    /// Attempts are started in order, each `stagger` after the previous one, so the
+    /// cheapest paths should come first. An attempt that fails early does not hold
+    /// back the next one beyond its slot.
+    pub async fn race<'a, T: Send + 'a>(&self, attempts: Vec<PathAttempt<'a, T>>) -> Result<PathSelection<T>> {
+        let (selection, pending) = self.race_pending(attempts).await?;
+        drop(pending);
+
+        Ok(selection)
+    }
+
+    /// Race the given attempts, tearing down every losing path that connects
+    ///
+    /// Losers that connected with the winner are torn down right away; attempts
+    /// still in flight are left to finish in the background and torn down if they
+    /// connect, so no relay circuit opened for the race is left behind.
+    pub async fn race_with_teardown<T: Send + 'static>(
+        &self,
+        attempts: Vec<PathAttempt<'static, T>>,
+        teardown: Teardown<T>,
+    ) -> Result<PathSelection<T>> {
+        let (mut selection, mut pending) = self.race_pending(attempts).await?;
+
+        let losers = std::mem::take(&mut selection.losers);
+        tokio::spawn(async move {
+            for (path, connection) in losers {
+                teardown(path, connection).await;
+            }
+            while let Some((_, path, result)) = pending.next().await {
+                if let Ok(connection) = result {
+                    debug!("Tearing down {} path that connected after the race", path);
+                    teardown(path, connection).await;
+                }
+            }
+        });
+
+        Ok(selection)
+    }
+
+    /// Race the given attempts, returning the selection and the attempts still in flight
+    async fn race_pending<'a, T: Send + 'a>(
+        &self,
+        attempts: Vec<PathAttempt<'a, T>>,
+    ) -> Result<(PathSelection<T>, Pending<'a, T>)> {
+        if attempts.is_empty() {
+            return Err(anyhow::anyhow!("No paths to dial"));
+        }
+
+        let started = Instant::now();
+        let candidates: Vec<PathCandidate> = attempts.iter().map(|(path, _)| path.clone()).collect();
+
+        // Start the attempts with a growing delay
+        let mut pending: Pending<'a, T> = FuturesUnordered::new();
+        for (index, (path, attempt)) in attempts.into_iter().enumerate() {
+            let delay = self.stagger * index as u32;
+            let timeout = self.timeout;
+
+            pending.push(async move {
+                tokio::time::sleep(delay).await;
+                let result = match tokio::time::timeout(timeout, attempt).await {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow::anyhow!("Timed out dialing {}", path)),
+                };
+                (index, path, result)
+            }.boxed());
+        }
+
+        let mut finished = vec![false; candidates.len()];
+        let mut winner = None;
+
+        // Wait for the first successful attempt
+        while let Some((index, path, result)) = pending.next().await {
+            finished[index] = true;
+
+            match result {
+                Ok(connection) => {
+                    winner = Some((index, connection));
+                    break;
+                }
+                Err(e) => {
+                    debug!("Path {} failed: {}", path, e);
+                    let mut metrics = self.metrics.write().await;
+                    let stats = metrics.stats_mut(path.kind);
+                    stats.attempts += 1;
+                    stats.failures += 1;
+                }
+            }
+        }
+
+        let (winner_index, connection) = match winner {
+            Some(winner) => winner,
+            None => {
+                self.metrics.write().await.failed_races += 1;
+                return Err(anyhow::anyhow!("All {} paths failed", candidates.len()));
+            }
+        };
+        let latency = started.elapsed();
+
+        // Collect losers that connected at the same time
+        let mut losers = Vec::new();
+        while let Some(Some((index, path, result))) = pending.next().now_or_never() {
+            finished[index] = true;
+            if let Ok(connection) = result {
+                losers.push((path, connection));
+            }
+        }
+
+        // Record metrics
+        let mut metrics = self.metrics.write().await;
+        {
+            let stats = metrics.stats_mut(candidates[winner_index].kind);
+            stats.attempts += 1;
+            stats.wins += 1;
+            stats.total_win_latency_ms += latency.as_millis() as u64;
+        }
+        for (path, _) in &losers {
+            let stats = metrics.stats_mut(path.kind);
+            stats.attempts += 1;
+            stats.cancelled += 1;
+        }
+        for (index, path) in candidates.iter().enumerate() {
+            // Attempts that never started are not counted
+            if !finished[index] && started.elapsed() >= self.stagger * index as u32 {
+                let stats = metrics.stats_mut(path.kind);
+                stats.attempts += 1;
+                stats.cancelled += 1;
+            }
+        }
+
+        let selection = PathSelection {
+            path: candidates[winner_index].clone(),
+            connection,
+            latency,
+            losers,
+        };
+
+        Ok((selection, pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempt<'a>(path: PathCandidate, delay_ms: u64, ok: bool) -> PathAttempt<'a, String> {
+        let label = path.to_string();
+        (path, async move {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            if ok {
+                Ok(label)
+            } else {
+                Err(anyhow::anyhow!("unreachable"))
+            }
+        }.boxed())
+    }
+
+    #[tokio::test]
+    async fn test_fastest_path_wins() {
+        let dialer = PathDialer::new(Duration::from_millis(10), Duration::from_secs(1));
+
+        let selection = dialer.race(vec![
+            attempt(PathCandidate::direct(), 500, true),
+            attempt(PathCandidate::hole_punched(), 0, false),
+            attempt(PathCandidate::relayed("relay-1".to_string()), 5, true),
+        ]).await.unwrap();
+
+        assert_eq!(selection.path.kind, PathKind::Relayed);
+        assert_eq!(selection.connection, "relayed via relay-1");
+
+        let metrics = dialer.metrics().await;
+        assert_eq!(metrics.get(PathKind::Relayed).wins, 1);
+        assert_eq!(metrics.get(PathKind::HolePunched).failures, 1);
+        assert_eq!(metrics.get(PathKind::Direct).cancelled, 1);
+    }
+
+    #[tokio::test]
+    async fn test_all_paths_failing() {
+        let dialer = PathDialer::new(Duration::from_millis(1), Duration::from_millis(50));
+
+        let result = dialer.race(vec![
+            attempt(PathCandidate::direct(), 0, false),
+            attempt(PathCandidate::relayed("relay-1".to_string()), 200, true),
+        ]).await;
+
+        assert!(result.is_err());
+
+        let metrics = dialer.metrics().await;
+        assert_eq!(metrics.failed_races, 1);
+        assert_eq!(metrics.get(PathKind::Relayed).failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_late_losers_are_torn_down() {
+        let dialer = PathDialer::new(Duration::from_millis(1), Duration::from_secs(1));
+        let (torn_down, mut torn_down_rx) = tokio::sync::mpsc::unbounded_channel();
+        let teardown: Teardown<String> = Arc::new(move |_, connection| {
+            let torn_down = torn_down.clone();
+            async move {
+                let _ = torn_down.send(connection);
+            }.boxed()
+        });
+
+        let selection = dialer.race_with_teardown(vec![
+            attempt(PathCandidate::direct(), 0, true),
+            attempt(PathCandidate::relayed("relay-1".to_string()), 50, true),
+        ], teardown).await.unwrap();
+        assert_eq!(selection.path.kind, PathKind::Direct);
+
+        // The relay connected after the race was decided
+        assert_eq!(torn_down_rx.recv().await.unwrap(), "relayed via relay-1");
+    }
+}
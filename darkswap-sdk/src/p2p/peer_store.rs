@@ -0,0 +1,294 @@
+//! Peer store for DarkSwap P2P network
+//!
+//! Known peers are remembered across restarts together with their addresses,
+//! supported protocols, last measured latency, reputation score and the path that
+//! last connected. On startup the stored addresses are dialed alongside the
+//! bootstrap peers, so a node rejoins the network without waiting for discovery.
+//! Addresses that have not been seen within the configured TTL are dropped.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context as AnyhowContext, Result};
+use darkswap_support::storage::DocumentFile;
+use libp2p::core::multiaddr::Multiaddr;
+use serde::{Deserialize, Serialize};
+
+use super::path_selection::PathKind;
+
+/// Address of a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerAddress {
+    /// Address
+    pub address: Multiaddr,
+    /// Last time the address was seen (unix seconds)
+    pub last_seen: u64,
+}
+
+/// Stored information about a peer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerRecord {
+    /// Peer ID
+    pub peer_id: String,
+    /// Known addresses
+    pub addresses: Vec<PeerAddress>,
+    /// Supported protocols
+    pub protocols: Vec<String>,
+    /// Last measured connect latency (milliseconds)
+    pub latency_ms: Option<u64>,
+    /// Reputation score between 0.0 and 1.0
+    pub reputation: Option<f64>,
+    /// Path of the last successful connection
+    pub last_path: Option<PathKind>,
+    /// Last time the peer was seen (unix seconds)
+    pub last_seen: u64,
+}
+
+impl PeerRecord {
+    /// Create an empty record for a peer
+    fn new(peer_id: String) -> Self {
+        Self {
+            peer_id,
+            last_seen: now(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Peer store
+#[derive(Debug)]
+pub struct PeerStore {
+    /// File the store is persisted to; `None` keeps it in memory only
+    file: Option<DocumentFile>,
+    /// Time an address is kept after it was last seen (seconds)
+    address_ttl: u64,
+    /// Peers by peer ID
+    peers: HashMap<String, PeerRecord>,
+}
+
+impl PeerStore {
+    /// Create an in-memory peer store
+    pub fn new(address_ttl: u64) -> Self {
+        Self {
+            file: None,
+            address_ttl,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Open a peer store persisted at `path`, loading it if the file exists
+    ///
+    /// Stale addresses are expired on load.
+    pub fn open<P: AsRef<Path>>(path: P, address_ttl: u64) -> Result<Self> {
+        Self::open_file(DocumentFile::plain(path), address_ttl)
+    }
+
+    /// Open a peer store persisted in `file`, sealed if storage is encrypted
+    pub fn open_file(file: DocumentFile, address_ttl: u64) -> Result<Self> {
+        let records: Vec<PeerRecord> = file.load()
+            .with_context(|| format!("Failed to read peer store {}", file.path().display()))?
+            .unwrap_or_default();
+        let peers = records.into_iter().map(|record| (record.peer_id.clone(), record)).collect();
+
+        let mut store = Self {
+            file: Some(file),
+            address_ttl,
+            peers,
+        };
+        store.expire();
+
+        Ok(store)
+    }
+
+    /// Delete a persisted peer store
+    pub fn remove_file<P: AsRef<Path>>(path: P) -> Result<()> {
+        let path = path.as_ref();
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove peer store {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Save the store to disk, if it is persisted
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let records: Vec<&PeerRecord> = self.peers.values().collect();
+        file.save(&records)
+            .with_context(|| format!("Failed to write peer store {}", file.path().display()))?;
+
+        Ok(())
+    }
+
+    /// Remove all peers, including the persisted copy
+    pub fn clear(&mut self) -> Result<()> {
+        self.peers.clear();
+
+        match &self.file {
+            Some(file) => file.remove()
+                .with_context(|| format!("Failed to remove peer store {}", file.path().display())),
+            None => Ok(()),
+        }
+    }
+
+    /// Record an address of a peer
+    pub fn add_address(&mut self, peer_id: &str, address: Multiaddr) {
+        let now = now();
+        let record = self.record_mut(peer_id);
+        record.last_seen = now;
+
+        match record.addresses.iter_mut().find(|known| known.address == address) {
+            Some(known) => known.last_seen = now,
+            None => record.addresses.push(PeerAddress { address, last_seen: now }),
+        }
+    }
+
+    /// Record the protocols supported by a peer
+    pub fn set_protocols(&mut self, peer_id: &str, protocols: Vec<String>) {
+        self.record_mut(peer_id).protocols = protocols;
+    }
+
+    /// Record a successful connection to a peer
+    pub fn record_connection(&mut self, peer_id: &str, path: PathKind, latency_ms: u64) {
+        let now = now();
+        let record = self.record_mut(peer_id);
+        record.last_path = Some(path);
+        record.latency_ms = Some(latency_ms);
+        record.last_seen = now;
+    }
+
+    /// Record the reputation score of a peer, if it is known
+    pub fn set_reputation(&mut self, peer_id: &str, score: f64) {
+        if let Some(record) = self.peers.get_mut(peer_id) {
+            record.reputation = Some(score);
+        }
+    }
+
+    /// Get a peer
+    pub fn get(&self, peer_id: &str) -> Option<&PeerRecord> {
+        self.peers.get(peer_id)
+    }
+
+    /// Get all peers
+    pub fn peers(&self) -> Vec<PeerRecord> {
+        self.peers.values().cloned().collect()
+    }
+
+    /// Get the addresses to dial on startup, best peers first
+    pub fn dial_addresses(&self) -> Vec<Multiaddr> {
+        let mut peers: Vec<&PeerRecord> = self.peers.values().collect();
+        peers.sort_by(|a, b| {
+            let score_a = a.reputation.unwrap_or(0.5);
+            let score_b = b.reputation.unwrap_or(0.5);
+            score_b
+                .partial_cmp(&score_a)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.latency_ms.unwrap_or(u64::MAX).cmp(&b.latency_ms.unwrap_or(u64::MAX)))
+        });
+
+        peers
+            .into_iter()
+            .flat_map(|record| record.addresses.iter().map(|known| known.address.clone()))
+            .collect()
+    }
+
+    /// Get the number of peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Check if the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Drop addresses older than the TTL, and peers left without addresses
+    pub fn expire(&mut self) {
+        let cutoff = now().saturating_sub(self.address_ttl);
+
+        for record in self.peers.values_mut() {
+            record.addresses.retain(|known| known.last_seen >= cutoff);
+        }
+        self.peers.retain(|_, record| !record.addresses.is_empty() || record.last_seen >= cutoff);
+    }
+
+    /// Get or create the record of a peer
+    fn record_mut(&mut self, peer_id: &str) -> &mut PeerRecord {
+        self.peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| PeerRecord::new(peer_id.to_string()))
+    }
+}
+
+/// Get the current unix time in seconds
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: &str = "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN";
+
+    #[test]
+    fn test_persist_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("peers.json");
+
+        let mut store = PeerStore::open(&path, 3600).unwrap();
+        store.add_address(PEER, "/ip4/127.0.0.1/tcp/9000".parse().unwrap());
+        store.record_connection(PEER, PathKind::HolePunched, 42);
+        store.set_reputation(PEER, 0.9);
+        store.save().unwrap();
+
+        let store = PeerStore::open(&path, 3600).unwrap();
+        let record = store.get(PEER).unwrap();
+        assert_eq!(record.addresses.len(), 1);
+        assert_eq!(record.latency_ms, Some(42));
+        assert_eq!(record.last_path, Some(PathKind::HolePunched));
+        assert_eq!(record.reputation, Some(0.9));
+    }
+
+    #[test]
+    fn test_stale_addresses_expire() {
+        let mut store = PeerStore::new(3600);
+        store.add_address(PEER, "/ip4/127.0.0.1/tcp/9000".parse().unwrap());
+        store.add_address(PEER, "/ip4/127.0.0.1/tcp/9001".parse().unwrap());
+
+        let record = store.peers.get_mut(PEER).unwrap();
+        record.addresses[0].last_seen -= 7200;
+        record.last_seen -= 7200;
+
+        store.expire();
+        assert_eq!(store.get(PEER).unwrap().addresses.len(), 1);
+
+        store.peers.get_mut(PEER).unwrap().addresses[0].last_seen -= 7200;
+        store.expire();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_clear_removes_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("peers.json");
+
+        let mut store = PeerStore::open(&path, 3600).unwrap();
+        store.add_address(PEER, "/ip4/127.0.0.1/tcp/9000".parse().unwrap());
+        store.save().unwrap();
+        assert!(path.exists());
+
+        store.clear().unwrap();
+        assert!(store.is_empty());
+        assert!(!path.exists());
+    }
+}
@@ -7,6 +7,7 @@ use crate::{
     error::Error,
     p2p::{
         circuit_relay::CircuitRelay,
+        relay_store::RelayOutcomes,
         webrtc_transport::WebRtcTransport,
         PeerId,
     },
@@ -227,6 +228,8 @@ impl Default for RelayManagerConfig {
 pub struct RelayManager {
     /// Configuration
     config: RelayManagerConfig,
+    /// Relay servers, with their status
+    servers: RwLock<Vec<RelayServer>>,
     /// Relay connections
     connections: Arc<RwLock<HashMap<String, RelayConnection>>>,
     /// WebRTC transport
@@ -237,8 +240,12 @@ pub struct RelayManager {
     peer_id: PeerId,
     /// Event sender
     event_sender: mpsc::Sender<RelayEvent>,
-    /// Event receiver
-    event_receiver: mpsc::Receiver<RelayEvent>,
+    /// Event receiver, taken by the event loop
+    event_receiver: tokio::sync::Mutex<mpsc::Receiver<RelayEvent>>,
+    /// Time each server connection was opened, by server ID, to measure its latency
+    dialed_at: RwLock<HashMap<String, Instant>>,
+    /// Where connection outcomes are recorded, if anywhere
+    outcomes: Option<Arc<RelayOutcomes>>,
 }
 
 /// Relay event
@@ -315,18 +322,30 @@ impl RelayManager {
         let (tx, rx) = mpsc::channel(100);
         
         Self {
+            servers: RwLock::new(config.servers.clone()),
             config,
             connections: Arc::new(RwLock::new(HashMap::new())),
             webrtc_transport,
             circuit_relay,
             peer_id,
             event_sender: tx,
-            event_receiver: rx,
+            event_receiver: tokio::sync::Mutex::new(rx),
+            dialed_at: RwLock::new(HashMap::new()),
+            outcomes: None,
         }
     }
     
+    /// Record the outcome of every server connection in `outcomes`
+    pub fn with_outcomes(mut self, outcomes: Arc<RelayOutcomes>) -> Self {
+        self.outcomes = Some(outcomes);
+        self
+    }
+    
     /// Start the relay manager
-    pub async fn start(&mut self) -> Result<()> {
+    ///
+    /// Runs the event loop until the manager is dropped, so it is spawned on a
+    /// shared manager.
+    pub async fn start(&self) -> Result<()> {
         // Connect to all relay servers
         for server in &self.config.servers {
             self.connect_to_server(server.clone()).await?;
@@ -339,15 +358,20 @@ impl RelayManager {
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(ping_interval));
             
+            let json = match serde_json::to_string(&RelayMessage::Ping) {
+                Ok(json) => json,
+                Err(e) => {
+                    warn!("Failed to serialize ping: {}", e);
+                    return;
+                }
+            };
+            
             loop {
                 interval.tick().await;
                 
                 // Send ping to all connected servers
                 let mut connections = connections.write().await;
                 for (_, connection) in connections.iter_mut() {
-                    // Send ping message
-                    let ping_msg = RelayMessage::Ping;
-                    let json = serde_json::to_string(&ping_msg).unwrap();
                     connection.ws.send_with_str(&json).unwrap_or_else(|e| {
                         warn!("Failed to send ping: {:?}", e);
                     });
@@ -356,26 +380,27 @@ impl RelayManager {
         });
         
         // Process events
-        while let Some(event) = self.event_receiver.next().await {
+        let mut event_receiver = self.event_receiver.lock().await;
+        while let Some(event) = event_receiver.next().await {
             match event {
                 RelayEvent::ServerConnected { server_id } => {
                     info!("Connected to relay server: {}", server_id);
                     
                     // Update server status
-                    if let Some(server) = self.get_server_mut(&server_id) {
-                        server.status = RelayServerStatus::Connected;
+                    self.set_status(&server_id, RelayServerStatus::Connected).await;
+                    let dialed_at = self.dialed_at.write().await.remove(&server_id);
+                    if let (Some(outcomes), Some(dialed_at)) = (&self.outcomes, dialed_at) {
+                        outcomes.connected(&server_id, dialed_at.elapsed().as_millis() as u64).await;
                     }
                 }
                 RelayEvent::ServerDisconnected { server_id } => {
                     info!("Disconnected from relay server: {}", server_id);
                     
                     // Update server status
-                    if let Some(server) = self.get_server_mut(&server_id) {
-                        server.status = RelayServerStatus::Disconnected;
-                    }
+                    self.set_status(&server_id, RelayServerStatus::Disconnected).await;
                     
                     // Try to reconnect
-                    if let Some(server) = self.get_server(&server_id) {
+                    if let Some(server) = self.get_server(&server_id).await {
                         let server_clone = server.clone();
                         tokio::spawn(async move {
                             tokio::time::sleep(Duration::from_secs(5)).await;
@@ -387,8 +412,10 @@ impl RelayManager {
                     warn!("Relay server failed: {}: {}", server_id, error);
                     
                     // Update server status
-                    if let Some(server) = self.get_server_mut(&server_id) {
-                        server.status = RelayServerStatus::Failed;
+                    self.set_status(&server_id, RelayServerStatus::Failed).await;
+                    self.dialed_at.write().await.remove(&server_id);
+                    if let Some(outcomes) = &self.outcomes {
+                        outcomes.failed(&server_id, &error).await;
                     }
                 }
                 RelayEvent::PeerConnected { peer_id, server_id } => {
@@ -450,7 +477,17 @@ impl RelayManager {
     /// Connect to a relay server
     pub async fn connect_to_server(&self, server: RelayServer) -> Result<()> {
         // Create a WebSocket connection
-        let ws = WebSocket::new(&server.url)?;
+        self.dialed_at.write().await.insert(server.id.clone(), Instant::now());
+        let ws = match WebSocket::new(&server.url) {
+            Ok(ws) => ws,
+            Err(e) => {
+                self.dialed_at.write().await.remove(&server.id);
+                if let Some(outcomes) = &self.outcomes {
+                    outcomes.failed(&server.id, &format!("{:?}", e)).await;
+                }
+                return Err(e.into());
+            }
+        };
         
         // Set up event handlers
         let server_id = server.id.clone();
@@ -468,7 +505,13 @@ impl RelayManager {
             let register_msg = RelayMessage::Register {
                 peer_id: peer_id.to_string(),
             };
-            let json = serde_json::to_string(&register_msg).unwrap();
+            let json = match serde_json::to_string(&register_msg) {
+                Ok(json) => json,
+                Err(e) => {
+                    warn!("Failed to serialize register message: {}", e);
+                    return;
+                }
+            };
             
             // Get the WebSocket
             let connections_clone = connections.clone();
@@ -504,8 +547,7 @@ impl RelayManager {
             let event_sender = event_sender.clone();
             
             // Parse the message
-            if let Ok(text) = e.data().dyn_into::<js_sys::JsString>() {
-                let text = text.as_string().unwrap();
+            if let Some(text) = e.data().dyn_into::<js_sys::JsString>().ok().and_then(|text| text.as_string()) {
                 
                 // Parse the message
                 match serde_json::from_str::<RelayMessage>(&text) {
@@ -575,12 +617,12 @@ impl RelayManager {
                             }
                             RelayMessage::RelayData { from, relay_id, data } => {
                                 // Decode the data
-                                match base64::decode(&data) {
-                                    Ok(data) => {
+                                match (base64::decode(&data), from.parse()) {
+                                    (Ok(data), Ok(src)) => {
                                         // Send data received event
                                         let event = RelayEvent::DataReceived {
                                             circuit_id: relay_id,
-                                            src: from.parse().unwrap(),
+                                            src,
                                             data,
                                         };
                                         
@@ -590,9 +632,12 @@ impl RelayManager {
                                             });
                                         });
                                     }
-                                    Err(e) => {
+                                    (Err(e), _) => {
                                         warn!("Failed to decode data: {:?}", e);
                                     }
+                                    (_, Err(e)) => {
+                                        warn!("Invalid relay data sender {}: {:?}", from, e);
+                                    }
                                 }
                             }
                             RelayMessage::Error { message } => {
@@ -672,21 +717,32 @@ impl RelayManager {
     }
     
     /// Get a server by ID
-    fn get_server(&self, server_id: &str) -> Option<&RelayServer> {
-        self.config.servers.iter().find(|s| s.id == server_id)
+    async fn get_server(&self, server_id: &str) -> Option<RelayServer> {
+        self.servers.read().await.iter().find(|s| s.id == server_id).cloned()
     }
     
-    /// Get a mutable server by ID
-    fn get_server_mut(&mut self, server_id: &str) -> Option<&mut RelayServer> {
-        self.config.servers.iter_mut().find(|s| s.id == server_id)
+    /// Set the status of a server
+    async fn set_status(&self, server_id: &str, status: RelayServerStatus) {
+        if let Some(server) = self.servers.write().await.iter_mut().find(|s| s.id == server_id) {
+            server.status = status;
+        }
     }
     
     /// Connect to a peer via relay
     pub async fn connect_to_peer(&self, peer_id: &PeerId) -> Result<String> {
         // Find a connected relay server
+        let server_id = {
+            let connections = self.connections.read().await;
+            connections.keys().next().cloned().ok_or_else(|| Error::NoRelayServers)?
+        };
+        
+        self.connect_to_peer_via(&server_id, peer_id).await
+    }
+    
+    /// Connect to a peer via a specific relay server
+    pub async fn connect_to_peer_via(&self, server_id: &str, peer_id: &PeerId) -> Result<String> {
         let connections = self.connections.read().await;
-        let server_id = connections.keys().next().ok_or_else(|| Error::NoRelayServers)?;
-        let connection = connections.get(server_id).unwrap();
+        let connection = connections.get(server_id).ok_or_else(|| Error::NoRelayServers)?;
         
         // Send relay request
         let relay_request = RelayMessage::RelayRequest {
@@ -706,8 +762,7 @@ impl RelayManager {
     pub async fn send_data(&self, peer_id: &PeerId, relay_id: &str, data: &[u8]) -> Result<()> {
         // Find a connected relay server
         let connections = self.connections.read().await;
-        let server_id = connections.keys().next().ok_or_else(|| Error::NoRelayServers)?;
-        let connection = connections.get(server_id).unwrap();
+        let connection = connections.values().next().ok_or_else(|| Error::NoRelayServers)?;
         
         // Encode the data
         let data_base64 = base64::encode(data);
@@ -728,9 +783,18 @@ impl RelayManager {
     /// Close a relay connection
     pub async fn close_relay(&self, relay_id: &str) -> Result<()> {
         // Find a connected relay server
+        let server_id = {
+            let connections = self.connections.read().await;
+            connections.keys().next().cloned().ok_or_else(|| Error::NoRelayServers)?
+        };
+        
+        self.close_relay_via(&server_id, relay_id).await
+    }
+    
+    /// Close a relay connection on a specific relay server
+    pub async fn close_relay_via(&self, server_id: &str, relay_id: &str) -> Result<()> {
         let connections = self.connections.read().await;
-        let server_id = connections.keys().next().ok_or_else(|| Error::NoRelayServers)?;
-        let connection = connections.get(server_id).unwrap();
+        let connection = connections.get(server_id).ok_or_else(|| Error::NoRelayServers)?;
         
         // Send close relay
         let close_relay = RelayMessage::CloseRelay {
@@ -771,9 +835,14 @@ impl RelayManager {
         circuits
     }
     
+    /// Get the IDs of relay servers with an open connection
+    pub async fn get_open_servers(&self) -> Vec<String> {
+        self.connections.read().await.keys().cloned().collect()
+    }
+    
     /// Get the list of connected relay servers
-    pub fn get_connected_servers(&self) -> Vec<String> {
-        self.config.servers
+    pub async fn get_connected_servers(&self) -> Vec<String> {
+        self.servers.read().await
             .iter()
             .filter(|s| s.status == RelayServerStatus::Connected)
             .map(|s| s.id.clone())
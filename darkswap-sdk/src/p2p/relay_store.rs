@@ -0,0 +1,509 @@
+//! Relay store for DarkSwap P2P network
+//!
+//! Every relay the node uses with a TCP address is probed every
+//! `p2p.relay_check_interval` seconds, and every connection the relay manager
+//! opens or fails to open is recorded as well, so relays reached over other
+//! transports such as QUIC get a history too. The outcome is kept per relay:
+//! how many checks it passed, its connect latency and when it last answered
+//! or failed. The store is persisted like the peer store, so on restart the
+//! relays that worked before are tried first. With `p2p.remember_relays`,
+//! relays that worked but have since left the configuration are still tried
+//! after them; otherwise their records are dropped.
+//!
+//! Latency is smoothed over the checks; uptime is the share of checks passed
+//! within the recent window, so a relay recovers from an old outage.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as AnyhowContext, Result};
+use darkswap_support::storage::DocumentFile;
+use libp2p::core::multiaddr::Multiaddr;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::clock::Clock;
+#[cfg(not(target_arch = "wasm32"))]
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// Checks uptime is computed over
+const UPTIME_WINDOW: usize = 50;
+/// Uptime below which a relay is unhealthy
+const HEALTHY_UPTIME: f64 = 0.8;
+/// Weight of a new latency sample in the smoothed latency
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Stored health history of a relay
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RelayRecord {
+    /// Relay address
+    pub address: String,
+    /// Checks passed, out of the checks made
+    pub successes: u64,
+    /// Checks made
+    pub checks: u64,
+    /// Outcomes of the most recent checks, oldest first
+    #[serde(default)]
+    pub recent: Vec<bool>,
+    /// Smoothed connect latency (milliseconds)
+    pub latency_ms: Option<u64>,
+    /// Last time the relay answered (unix seconds)
+    pub last_success: Option<u64>,
+    /// Last time the relay failed a check (unix seconds)
+    pub last_failure: Option<u64>,
+    /// Error of the last failed check
+    pub last_error: Option<String>,
+}
+
+impl RelayRecord {
+    /// Create an empty record for a relay
+    fn new(address: String) -> Self {
+        Self {
+            address,
+            ..Default::default()
+        }
+    }
+
+    /// Get the share of recent checks the relay passed, if it was checked
+    pub fn uptime(&self) -> Option<f64> {
+        if self.recent.is_empty() {
+            return None;
+        }
+
+        let passed = self.recent.iter().filter(|passed| **passed).count();
+        Some(passed as f64 / self.recent.len() as f64)
+    }
+
+    /// Check if the relay passed its last check and most recent ones
+    pub fn is_healthy(&self) -> bool {
+        self.recent.last() == Some(&true) && self.uptime().map_or(false, |uptime| uptime >= HEALTHY_UPTIME)
+    }
+
+    /// Check if the relay ever answered
+    pub fn has_worked(&self) -> bool {
+        self.successes > 0
+    }
+
+    /// Record the outcome of a check
+    fn push_outcome(&mut self, passed: bool) {
+        self.checks += 1;
+        self.recent.push(passed);
+        if self.recent.len() > UPTIME_WINDOW {
+            self.recent.remove(0);
+        }
+    }
+}
+
+/// Relay with its health, as listed to users
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayHealth {
+    /// Relay address
+    pub address: String,
+    /// Whether the relay is in the configuration, rather than only known from earlier sessions
+    pub configured: bool,
+    /// Whether the relay passed its last check and most recent ones
+    pub healthy: bool,
+    /// Share of recent checks passed
+    pub uptime: Option<f64>,
+    /// Smoothed connect latency (milliseconds)
+    pub latency_ms: Option<u64>,
+    /// Checks passed
+    pub successes: u64,
+    /// Checks made
+    pub checks: u64,
+    /// Last time the relay answered (unix seconds)
+    pub last_success: Option<u64>,
+    /// Last time the relay failed a check (unix seconds)
+    pub last_failure: Option<u64>,
+    /// Error of the last failed check
+    pub last_error: Option<String>,
+}
+
+impl RelayHealth {
+    /// Get the health of a relay from its record
+    pub fn of(record: &RelayRecord, configured: bool) -> Self {
+        Self {
+            address: record.address.clone(),
+            configured,
+            healthy: record.is_healthy(),
+            uptime: record.uptime(),
+            latency_ms: record.latency_ms,
+            successes: record.successes,
+            checks: record.checks,
+            last_success: record.last_success,
+            last_failure: record.last_failure,
+            last_error: record.last_error.clone(),
+        }
+    }
+}
+
+/// Relay store
+#[derive(Debug)]
+pub struct RelayStore {
+    /// File the store is persisted to; `None` keeps it in memory only
+    file: Option<DocumentFile>,
+    /// Relays by address
+    relays: HashMap<String, RelayRecord>,
+}
+
+impl RelayStore {
+    /// Create an in-memory relay store
+    pub fn new() -> Self {
+        Self {
+            file: None,
+            relays: HashMap::new(),
+        }
+    }
+
+    /// Open a relay store persisted at `path`, loading it if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_file(DocumentFile::plain(path))
+    }
+
+    /// Open a relay store persisted in `file`, sealed if storage is encrypted
+    pub fn open_file(file: DocumentFile) -> Result<Self> {
+        let records: Vec<RelayRecord> = file.load()
+            .with_context(|| format!("Failed to read relay store {}", file.path().display()))?
+            .unwrap_or_default();
+        let relays = records.into_iter().map(|record| (record.address.clone(), record)).collect();
+
+        Ok(Self {
+            file: Some(file),
+            relays,
+        })
+    }
+
+    /// Save the store to disk, if it is persisted
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        let mut records: Vec<&RelayRecord> = self.relays.values().collect();
+        records.sort_by(|a, b| a.address.cmp(&b.address));
+        file.save(&records)
+            .with_context(|| format!("Failed to write relay store {}", file.path().display()))?;
+
+        Ok(())
+    }
+
+    /// Record a passed check at `now`
+    pub fn record_success(&mut self, address: &Multiaddr, latency_ms: u64, now: u64) {
+        let record = self.record_mut(address);
+        record.push_outcome(true);
+        record.successes += 1;
+        record.last_success = Some(now);
+        record.latency_ms = Some(match record.latency_ms {
+            Some(previous) => {
+                (previous as f64 * (1.0 - LATENCY_SMOOTHING) + latency_ms as f64 * LATENCY_SMOOTHING).round() as u64
+            }
+            None => latency_ms,
+        });
+    }
+
+    /// Record a failed check at `now`
+    pub fn record_failure(&mut self, address: &Multiaddr, error: &str, now: u64) {
+        let record = self.record_mut(address);
+        record.push_outcome(false);
+        record.last_failure = Some(now);
+        record.last_error = Some(error.to_string());
+    }
+
+    /// Get a relay
+    pub fn get(&self, address: &Multiaddr) -> Option<&RelayRecord> {
+        self.relays.get(&address.to_string())
+    }
+
+    /// Get the relays to use, healthiest first: the configured ones and, if `remember` is set, those that worked before
+    ///
+    /// Relays never checked rank after healthy ones and before unhealthy ones,
+    /// each group by latency; ties keep the configured order.
+    pub fn ranked(&self, configured: &[Multiaddr], remember: bool) -> Vec<Multiaddr> {
+        let mut relays: Vec<Multiaddr> = configured.to_vec();
+        if remember {
+            let mut remembered: Vec<Multiaddr> = self.relays
+                .values()
+                .filter(|record| record.has_worked())
+                .filter_map(|record| record.address.parse::<Multiaddr>().ok())
+                .filter(|address| !configured.contains(address))
+                .collect();
+            remembered.sort_by_key(|address| address.to_string());
+            relays.extend(remembered);
+        }
+
+        relays.sort_by(|a, b| {
+            let (rank_a, latency_a) = self.rank(a);
+            let (rank_b, latency_b) = self.rank(b);
+            rank_a.cmp(&rank_b).then(latency_a.cmp(&latency_b))
+        });
+
+        relays
+    }
+
+    /// Get the health of the relays in use, in the order they are tried
+    pub fn health(&self, relays: &[Multiaddr], configured: &[Multiaddr]) -> Vec<RelayHealth> {
+        relays
+            .iter()
+            .map(|address| {
+                let record = self.get(address).cloned().unwrap_or_else(|| RelayRecord::new(address.to_string()));
+                RelayHealth::of(&record, configured.contains(address))
+            })
+            .collect()
+    }
+
+    /// Drop the records of relays no longer configured, returning how many were dropped
+    pub fn forget_unconfigured(&mut self, configured: &[Multiaddr]) -> usize {
+        let configured: Vec<String> = configured.iter().map(|address| address.to_string()).collect();
+        let before = self.relays.len();
+        self.relays.retain(|address, _| configured.contains(address));
+
+        before - self.relays.len()
+    }
+
+    /// Get the number of relays
+    pub fn len(&self) -> usize {
+        self.relays.len()
+    }
+
+    /// Check if the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty()
+    }
+
+    /// Get the rank of a relay, lowest first, and its latency
+    fn rank(&self, address: &Multiaddr) -> (u8, u64) {
+        match self.get(address) {
+            Some(record) if record.is_healthy() => (0, record.latency_ms.unwrap_or(u64::MAX)),
+            Some(record) if record.checks > 0 => (2, record.latency_ms.unwrap_or(u64::MAX)),
+            _ => (1, u64::MAX),
+        }
+    }
+
+    /// Get or create the record of a relay
+    fn record_mut(&mut self, address: &Multiaddr) -> &mut RelayRecord {
+        let address = address.to_string();
+        self.relays
+            .entry(address.clone())
+            .or_insert_with(|| RelayRecord::new(address))
+    }
+}
+
+impl Default for RelayStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Relay manager connection outcomes, recorded in the relay store
+pub struct RelayOutcomes {
+    /// Store the outcomes are recorded in
+    store: Arc<Mutex<RelayStore>>,
+    /// Relay addresses by relay server ID
+    addresses: HashMap<String, Multiaddr>,
+    /// Time source of the records
+    clock: Arc<dyn Clock>,
+}
+
+impl RelayOutcomes {
+    /// Record the outcomes of the relays in `addresses`, keyed by server ID, in `store`
+    pub fn new(store: Arc<Mutex<RelayStore>>, addresses: HashMap<String, Multiaddr>, clock: Arc<dyn Clock>) -> Self {
+        Self { store, addresses, clock }
+    }
+
+    /// Record that the relay manager connected to a server in `latency_ms`
+    pub async fn connected(&self, server_id: &str, latency_ms: u64) {
+        let Some(address) = self.addresses.get(server_id) else { return };
+
+        let mut store = self.store.lock().await;
+        store.record_success(address, latency_ms, self.clock.now());
+        if let Err(e) = store.save() {
+            debug!("Failed to save relay store: {}", e);
+        }
+    }
+
+    /// Record that the relay manager failed to connect to a server, or lost it
+    pub async fn failed(&self, server_id: &str, error: &str) {
+        let Some(address) = self.addresses.get(server_id) else { return };
+
+        let mut store = self.store.lock().await;
+        store.record_failure(address, error, self.clock.now());
+        if let Err(e) = store.save() {
+            debug!("Failed to save relay store: {}", e);
+        }
+    }
+}
+
+/// Open a TCP connection to a relay, returning the time it took (milliseconds)
+///
+/// Names in the address are resolved through `resolver` if one is configured.
+/// Returns `None` for an address without a TCP port, such as a QUIC relay,
+/// whose health comes from the relay manager's connections instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn probe_relay(
+    address: &Multiaddr,
+    timeout: Duration,
+    resolver: Option<&TokioAsyncResolver>,
+) -> Result<Option<u64>> {
+    use libp2p::core::multiaddr::Protocol;
+
+    if !address.iter().any(|proto| matches!(proto, Protocol::Tcp(_))) {
+        return Ok(None);
+    }
+
+    let resolved;
+    let address = match resolver {
+        Some(resolver) => {
+            resolved = crate::dns::resolve_multiaddr(resolver, address).await?;
+            &resolved
+        }
+        None => address,
+    };
+
+    let mut host = None;
+    let mut port = None;
+    for proto in address.iter() {
+        match proto {
+            Protocol::Ip4(ip) => host = Some(ip.to_string()),
+            Protocol::Ip6(ip) => host = Some(ip.to_string()),
+            Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => host = Some(name.to_string()),
+            Protocol::Tcp(p) => port = Some(p),
+            _ => {}
+        }
+    }
+    let (host, port) = match (host, port) {
+        (Some(host), Some(port)) => (host, port),
+        _ => anyhow::bail!("No host in relay address {}", address),
+    };
+
+    let started = std::time::Instant::now();
+    tokio::time::timeout(timeout, tokio::net::TcpStream::connect((host.as_str(), port)))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out after {} seconds", timeout.as_secs()))?
+        .context("Connection failed")?;
+
+    Ok(Some(started.elapsed().as_millis() as u64))
+}
+
+/// Spawn a task probing `relays` every `interval` and recording the outcomes in the store
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn_relay_health_checker(
+    store: Arc<Mutex<RelayStore>>,
+    relays: Vec<Multiaddr>,
+    interval: Duration,
+    timeout: Duration,
+    resolver: Option<Arc<TokioAsyncResolver>>,
+    clock: Arc<dyn Clock>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            for address in &relays {
+                let outcome = probe_relay(address, timeout, resolver.as_deref()).await;
+                let now = clock.now();
+
+                let mut store = store.lock().await;
+                match outcome {
+                    Ok(Some(latency_ms)) => store.record_success(address, latency_ms, now),
+                    Ok(None) => {}
+                    Err(e) => {
+                        debug!("Relay {} failed its health check: {}", address, e);
+                        store.record_failure(address, &e.to_string(), now);
+                    }
+                }
+            }
+
+            if let Err(e) = store.lock().await.save() {
+                debug!("Failed to save relay store: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn relay(port: u16) -> Multiaddr {
+        format!("/ip4/127.0.0.1/tcp/{}", port).parse().unwrap()
+    }
+
+    #[test]
+    fn test_persist_and_rank() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("relays.json");
+
+        let mut store = RelayStore::open(&path).unwrap();
+        store.record_success(&relay(9001), 100, NOW);
+        store.record_success(&relay(9001), 200, NOW + 60);
+        store.record_failure(&relay(9002), "Connection refused", NOW);
+        store.record_success(&relay(9003), 50, NOW);
+        store.record_failure(&relay(9003), "Timed out after 5 seconds", NOW + 60);
+        store.record_success(&relay(9004), 20, NOW);
+        store.save().unwrap();
+
+        let store = RelayStore::open(&path).unwrap();
+        let record = store.get(&relay(9001)).unwrap();
+        assert_eq!((record.checks, record.latency_ms, record.uptime()), (2, Some(130), Some(1.0)));
+        assert!(record.is_healthy());
+        assert!(!store.get(&relay(9003)).unwrap().is_healthy());
+
+        // Healthy relays first, then relays never checked, then failing ones, each by latency;
+        // relays that worked before are kept after they leave the configuration
+        let configured = vec![relay(9002), relay(9003), relay(9005), relay(9001)];
+        assert_eq!(store.ranked(&configured, true), vec![relay(9004), relay(9001), relay(9005), relay(9003), relay(9002)]);
+
+        let health = store.health(&store.ranked(&configured, true), &configured);
+        assert!(!health[0].configured && health[0].healthy);
+        assert_eq!(health[4].last_error.as_deref(), Some("Connection refused"));
+
+        // Unless remembering is on, relays that left the configuration are not used and can be forgotten
+        assert_eq!(store.ranked(&configured, false), vec![relay(9001), relay(9005), relay(9003), relay(9002)]);
+        let mut store = store;
+        assert_eq!(store.forget_unconfigured(&configured), 1);
+        assert!(store.get(&relay(9004)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_relay_manager_outcomes() {
+        let store = Arc::new(Mutex::new(RelayStore::new()));
+        let quic: Multiaddr = "/ip4/127.0.0.1/udp/9001/quic".parse().unwrap();
+        let addresses = HashMap::from([("relay-1".to_string(), quic.clone())]);
+        let outcomes = RelayOutcomes::new(store.clone(), addresses, Arc::new(crate::clock::MockClock::new(NOW)));
+
+        // QUIC relays are not probed, but their connections count
+        assert_eq!(probe_relay(&quic, Duration::from_secs(1), None).await.unwrap(), None);
+        outcomes.connected("relay-1", 40).await;
+        outcomes.failed("relay-1", "Connection closed").await;
+        outcomes.failed("unknown", "Connection closed").await;
+
+        let store = store.lock().await;
+        let record = store.get(&quic).unwrap();
+        assert_eq!((record.successes, record.checks, record.last_success), (1, 2, Some(NOW)));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_uptime_window() {
+        let mut store = RelayStore::new();
+        for i in 0..UPTIME_WINDOW as u64 {
+            store.record_failure(&relay(9001), "Connection refused", NOW + i);
+        }
+        for i in 0..UPTIME_WINDOW as u64 {
+            store.record_success(&relay(9001), 10, NOW + 100 + i);
+        }
+
+        // Old failures age out of the uptime, not of the totals
+        let record = store.get(&relay(9001)).unwrap();
+        assert_eq!(record.uptime(), Some(1.0));
+        assert_eq!((record.successes, record.checks), (UPTIME_WINDOW as u64, 2 * UPTIME_WINDOW as u64));
+    }
+}
@@ -0,0 +1,306 @@
+//! Request-for-quote messages
+//!
+//! Sizes that would move the book are traded over RFQ instead: the taker gossips a
+//! quote request for a pair and size on the RFQ topic, and makers answer with quotes
+//! sent to the taker alone over the quote request-response protocol. Quotes are
+//! signed by the maker, so a taker can hold a maker to the price it quoted.
+
+use darkswap_support::envelope::Versioned;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::orderbook::{Order, OrderId, OrderSide, OrderStatus};
+use crate::types::Asset;
+
+/// Gossip topic quote requests are broadcast on
+pub const RFQ_TOPIC: &str = "darkswap/rfq/v1";
+
+/// Request-response protocol quotes are sent to the taker over
+pub const QUOTE_PROTOCOL: &str = "/darkswap/rfq/quote/1.0.0";
+
+/// RFQ error
+#[derive(Debug, Error)]
+pub enum RfqError {
+    /// Invalid quote request
+    #[error("Invalid quote request: {0}")]
+    InvalidRequest(String),
+    /// Invalid quote
+    #[error("Invalid quote: {0}")]
+    InvalidQuote(String),
+    /// Unknown quote request
+    #[error("Unknown quote request: {0}")]
+    UnknownRequest(String),
+    /// Unknown quote
+    #[error("Unknown quote: {0}")]
+    UnknownQuote(String),
+    /// Quote request or quote expired
+    #[error("Expired: {0}")]
+    Expired(String),
+}
+
+/// Quote request, broadcast by a taker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteRequest {
+    /// Request ID
+    pub id: String,
+    /// Taker peer ID
+    pub taker: String,
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Side of the taker
+    pub side: OrderSide,
+    /// Amount of the base asset
+    pub amount: Decimal,
+    /// Time after which no more quotes are accepted (unix seconds)
+    pub expires_at: u64,
+}
+
+impl QuoteRequest {
+    /// Create a quote request that is open for `ttl` seconds from `now` (unix seconds)
+    pub fn new(
+        taker: String,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: Decimal,
+        ttl: u64,
+        now: u64,
+    ) -> Result<Self, RfqError> {
+        let request = Self {
+            id: Uuid::new_v4().to_string(),
+            taker,
+            base_asset,
+            quote_asset,
+            side,
+            amount,
+            expires_at: now + ttl,
+        };
+        request.validate(now)?;
+
+        Ok(request)
+    }
+
+    /// Check the request at `now` before it is quoted
+    pub fn validate(&self, now: u64) -> Result<(), RfqError> {
+        if self.amount <= Decimal::ZERO {
+            return Err(RfqError::InvalidRequest("Amount must be positive".to_string()));
+        }
+        if self.base_asset == self.quote_asset {
+            return Err(RfqError::InvalidRequest("Base and quote asset are the same".to_string()));
+        }
+        if self.is_expired_at(now) {
+            return Err(RfqError::Expired(format!("quote request {}", self.id)));
+        }
+
+        Ok(())
+    }
+
+    /// Check if the request no longer accepts quotes at `now`
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+}
+
+/// Quote of a maker for a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quote {
+    /// Quote ID, which is also the ID of the order the trade is made against
+    pub id: String,
+    /// Request ID
+    pub request_id: String,
+    /// Maker peer ID
+    pub maker: String,
+    /// Taker peer ID; nobody else can take the quote
+    pub taker: String,
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Side of the taker
+    pub side: OrderSide,
+    /// Amount of the base asset, at most the requested amount
+    pub amount: Decimal,
+    /// Price
+    pub price: Decimal,
+    /// Time until which the maker honors the price (unix seconds)
+    pub expires_at: u64,
+}
+
+impl Quote {
+    /// Create a quote for a request that holds for `ttl` seconds from `now` (unix seconds)
+    pub fn new(request: &QuoteRequest, maker: String, amount: Decimal, price: Decimal, ttl: u64, now: u64) -> Result<Self, RfqError> {
+        if amount <= Decimal::ZERO || amount > request.amount {
+            return Err(RfqError::InvalidQuote(format!("Amount {} does not fit request for {}", amount, request.amount)));
+        }
+        if price <= Decimal::ZERO {
+            return Err(RfqError::InvalidQuote("Price must be positive".to_string()));
+        }
+
+        Ok(Self {
+            id: Uuid::new_v4().to_string(),
+            request_id: request.id.clone(),
+            maker,
+            taker: request.taker.clone(),
+            base_asset: request.base_asset.clone(),
+            quote_asset: request.quote_asset.clone(),
+            side: request.side,
+            amount,
+            price,
+            expires_at: now + ttl,
+        })
+    }
+
+    /// Check if the quote is no longer honored at `now`
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Get the private order the trade protocol runs against, created at `now`
+    ///
+    /// The order is on the maker's side, opposite to the taker, and is never
+    /// added to the orderbook or gossiped.
+    pub fn to_order(&self, now: u64) -> Order {
+        Order {
+            id: OrderId(self.id.clone()),
+            maker: self.maker.clone(),
+            base_asset: self.base_asset.clone(),
+            quote_asset: self.quote_asset.clone(),
+            side: self.side.opposite(),
+            amount: self.amount,
+            price: self.price,
+            status: OrderStatus::Open,
+            timestamp: now,
+            expiry: self.expires_at,
+            hints: Default::default(),
+            fees: None,
+            referral_code: None,
+        }
+    }
+}
+
+/// Quote signed by its maker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedQuote {
+    /// Quote
+    pub quote: Quote,
+    /// Maker public key (protobuf encoded, hex)
+    pub public_key: String,
+    /// Maker signature over the serialized quote (hex)
+    pub signature: String,
+}
+
+impl SignedQuote {
+    /// Sign a quote with the maker's keypair
+    pub fn sign(quote: Quote, keypair: &Keypair) -> Result<Self, RfqError> {
+        if PeerId::from(keypair.public()).to_string() != quote.maker {
+            return Err(RfqError::InvalidQuote("Signing key does not match quote maker".to_string()));
+        }
+
+        let message = signing_bytes(&quote)?;
+        let signature = keypair
+            .sign(&message)
+            .map_err(|e| RfqError::InvalidQuote(format!("Failed to sign quote: {}", e)))?;
+
+        Ok(Self {
+            quote,
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Verify the maker signature
+    pub fn verify(&self) -> Result<(), RfqError> {
+        let public_key_bytes = hex::decode(&self.public_key)
+            .map_err(|e| RfqError::InvalidQuote(format!("Invalid public key encoding: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes)
+            .map_err(|e| RfqError::InvalidQuote(format!("Invalid public key: {}", e)))?;
+
+        // The key must belong to the maker
+        if PeerId::from_public_key(&public_key).to_string() != self.quote.maker {
+            return Err(RfqError::InvalidQuote("Signing key does not match quote maker".to_string()));
+        }
+
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| RfqError::InvalidQuote(format!("Invalid signature encoding: {}", e)))?;
+        let message = signing_bytes(&self.quote)?;
+
+        if !public_key.verify(&message, &signature) {
+            return Err(RfqError::InvalidQuote("Invalid maker signature".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// RFQ message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RfqMessage {
+    /// Quote request, gossiped on the RFQ topic
+    Request(QuoteRequest),
+    /// Quote, sent to the taker over the quote protocol
+    Quote(SignedQuote),
+}
+
+impl Versioned for RfqMessage {
+    const KIND: &'static str = "darkswap/rfq/message";
+    const VERSION: u32 = 1;
+}
+
+/// Get the bytes covered by the maker signature
+fn signing_bytes(quote: &Quote) -> Result<Vec<u8>, RfqError> {
+    serde_json::to_vec(quote).map_err(|e| RfqError::InvalidQuote(format!("Failed to serialize quote: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn request() -> QuoteRequest {
+        QuoteRequest::new("taker".to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Buy, dec!(50), 30, NOW).unwrap()
+    }
+
+    #[test]
+    fn test_request_is_validated() {
+        assert!(QuoteRequest::new("taker".to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Buy, dec!(0), 30, NOW).is_err());
+        assert!(QuoteRequest::new("taker".to_string(), Asset::Bitcoin, Asset::Bitcoin, OrderSide::Buy, dec!(1), 30, NOW).is_err());
+        assert!(QuoteRequest::new("taker".to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Buy, dec!(1), 0, NOW).is_err());
+    }
+
+    #[test]
+    fn test_signed_quote_verifies() {
+        let keypair = Keypair::generate_ed25519();
+        let maker = PeerId::from(keypair.public()).to_string();
+        let quote = Quote::new(&request(), maker, dec!(50), dec!(100), 30, NOW).unwrap();
+        let mut signed = SignedQuote::sign(quote, &keypair).unwrap();
+
+        assert!(signed.verify().is_ok());
+
+        signed.quote.price = dec!(1);
+        assert!(signed.verify().is_err());
+    }
+
+    #[test]
+    fn test_quote_cannot_exceed_request() {
+        assert!(Quote::new(&request(), "maker".to_string(), dec!(51), dec!(100), 30, NOW).is_err());
+    }
+
+    #[test]
+    fn test_quote_order_is_on_the_maker_side() {
+        let quote = Quote::new(&request(), "maker".to_string(), dec!(20), dec!(100), 30, NOW).unwrap();
+        let order = quote.to_order(NOW);
+
+        assert_eq!(order.id.0, quote.id);
+        assert_eq!(order.side, OrderSide::Sell);
+        assert_eq!(order.amount, dec!(20));
+        assert_eq!(order.expiry, quote.expires_at);
+    }
+}
@@ -0,0 +1,288 @@
+//! Outbound message prioritization
+//!
+//! Every message the node sends goes through a [`SendQueue`] with a queue per
+//! [`MessageClass`]. The sender always takes the oldest message of the highest
+//! class waiting, so trade protocol messages overtake order gossip however much
+//! of it is backed up, and request-response traffic such as backfills comes in
+//! between. When a queue is full, the oldest gossip is dropped, as peers gossip
+//! it again anyway; trade messages and requests are refused instead, so their
+//! caller learns they were not sent.
+//!
+//! The time each message waited is recorded per class, to tell whether gossip
+//! is holding back trades.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use libp2p::core::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::config::SendQueueConfig;
+
+/// Prefix of the topics carrying trade protocol messages
+pub const TRADE_TOPIC_PREFIX: &str = "darkswap/trade";
+
+/// Prefix of the request-response protocols carrying trade protocol messages
+pub const TRADE_PROTOCOL_PREFIX: &str = "/darkswap/trade/";
+
+/// Priority class of an outbound message, highest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageClass {
+    /// Trade protocol messages
+    Trade,
+    /// Other request-response messages
+    Request,
+    /// Gossip
+    Gossip,
+}
+
+impl MessageClass {
+    /// All classes, highest first
+    pub const ALL: [MessageClass; 3] = [MessageClass::Trade, MessageClass::Request, MessageClass::Gossip];
+
+    /// Get the class of a message to a target
+    pub fn of(target: &OutboundTarget) -> Self {
+        match target {
+            OutboundTarget::Topic(topic) if topic.starts_with(TRADE_TOPIC_PREFIX) => MessageClass::Trade,
+            OutboundTarget::Topic(_) => MessageClass::Gossip,
+            OutboundTarget::Peer { protocol, .. } if protocol.starts_with(TRADE_PROTOCOL_PREFIX) => MessageClass::Trade,
+            OutboundTarget::Peer { .. } => MessageClass::Request,
+        }
+    }
+
+    /// Get the index of the queue of the class
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for MessageClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageClass::Trade => write!(f, "trade"),
+            MessageClass::Request => write!(f, "request"),
+            MessageClass::Gossip => write!(f, "gossip"),
+        }
+    }
+}
+
+/// Destination of an outbound message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutboundTarget {
+    /// Gossipsub topic
+    Topic(String),
+    /// Single peer, over a request-response protocol
+    Peer {
+        /// Peer ID
+        peer_id: PeerId,
+        /// Protocol
+        protocol: String,
+    },
+}
+
+/// Message waiting to be sent
+#[derive(Debug, Clone)]
+pub struct OutboundMessage {
+    /// Destination
+    pub target: OutboundTarget,
+    /// Payload
+    pub data: Vec<u8>,
+    /// Priority class
+    pub class: MessageClass,
+    /// Time the message was queued
+    pub queued_at: Instant,
+}
+
+impl OutboundMessage {
+    /// Create a message to a target, classed by its target
+    pub fn new(target: OutboundTarget, data: Vec<u8>, queued_at: Instant) -> Self {
+        let class = MessageClass::of(&target);
+
+        Self { target, data, class, queued_at }
+    }
+}
+
+/// Queue statistics of a message class
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassStats {
+    /// Messages queued
+    pub queued: u64,
+    /// Messages taken off the queue to be sent
+    pub sent: u64,
+    /// Messages dropped or refused because the queue was full
+    pub dropped: u64,
+    /// Messages waiting now
+    pub depth: usize,
+    /// Total time sent messages waited in the queue (microseconds)
+    pub total_queue_delay_us: u64,
+    /// Longest time a sent message waited in the queue (microseconds)
+    pub max_queue_delay_us: u64,
+}
+
+impl ClassStats {
+    /// Get the average time sent messages waited in the queue (microseconds)
+    pub fn average_queue_delay_us(&self) -> Option<u64> {
+        if self.sent == 0 {
+            return None;
+        }
+
+        Some(self.total_queue_delay_us / self.sent)
+    }
+}
+
+/// Send queue metrics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueMetrics {
+    /// Statistics by message class
+    pub classes: HashMap<MessageClass, ClassStats>,
+}
+
+impl QueueMetrics {
+    /// Get the statistics of a message class
+    pub fn get(&self, class: MessageClass) -> ClassStats {
+        self.classes.get(&class).cloned().unwrap_or_default()
+    }
+
+    /// Get the statistics of a message class mutably
+    fn stats_mut(&mut self, class: MessageClass) -> &mut ClassStats {
+        self.classes.entry(class).or_default()
+    }
+}
+
+/// Outbound messages, queued by class
+pub struct SendQueue {
+    /// Queue of each class, by class index
+    queues: [VecDeque<OutboundMessage>; 3],
+    /// Messages each queue holds at most
+    capacity: usize,
+    /// Metrics
+    metrics: QueueMetrics,
+}
+
+impl SendQueue {
+    /// Create an empty queue
+    pub fn new(config: &SendQueueConfig) -> Self {
+        Self {
+            queues: Default::default(),
+            capacity: config.capacity.max(1),
+            metrics: QueueMetrics::default(),
+        }
+    }
+
+    /// Queue a message
+    ///
+    /// Fails if the queue of a trade message or request is full; a full gossip
+    /// queue drops its oldest message instead.
+    pub fn push(&mut self, message: OutboundMessage) -> Result<()> {
+        let class = message.class;
+        let queue = &mut self.queues[class.index()];
+
+        if queue.len() >= self.capacity {
+            self.metrics.stats_mut(class).dropped += 1;
+            if class != MessageClass::Gossip {
+                bail!("Send queue for {} messages is full", class);
+            }
+            queue.pop_front();
+        }
+        queue.push_back(message);
+
+        let depth = queue.len();
+        let stats = self.metrics.stats_mut(class);
+        stats.queued += 1;
+        stats.depth = depth;
+
+        Ok(())
+    }
+
+    /// Take the next message to send, the oldest of the highest class waiting, recording its delay at `now`
+    pub fn pop(&mut self, now: Instant) -> Option<OutboundMessage> {
+        let class = MessageClass::ALL.into_iter().find(|class| !self.queues[class.index()].is_empty())?;
+        let queue = &mut self.queues[class.index()];
+        let message = queue.pop_front()?;
+
+        let depth = queue.len();
+        let delay = now.saturating_duration_since(message.queued_at).as_micros() as u64;
+        let stats = self.metrics.stats_mut(class);
+        stats.sent += 1;
+        stats.depth = depth;
+        stats.total_queue_delay_us = stats.total_queue_delay_us.saturating_add(delay);
+        stats.max_queue_delay_us = stats.max_queue_delay_us.max(delay);
+
+        Some(message)
+    }
+
+    /// Get the number of messages waiting
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Check if no message is waiting
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+
+    /// Get a copy of the metrics
+    pub fn metrics(&self) -> QueueMetrics {
+        self.metrics.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+    use std::time::Duration;
+
+    fn topic(name: &str, queued_at: Instant) -> OutboundMessage {
+        OutboundMessage::new(OutboundTarget::Topic(name.to_string()), vec![], queued_at)
+    }
+
+    fn request(protocol: &str, queued_at: Instant) -> OutboundMessage {
+        let peer_id = PeerId::from(Keypair::generate_ed25519().public());
+        OutboundMessage::new(OutboundTarget::Peer { peer_id, protocol: protocol.to_string() }, vec![], queued_at)
+    }
+
+    #[test]
+    fn test_trade_messages_overtake_gossip() {
+        let start = Instant::now();
+        let mut queue = SendQueue::new(&SendQueueConfig { capacity: 100 });
+
+        for _ in 0..50 {
+            queue.push(topic("darkswap/orders", start)).unwrap();
+        }
+        queue.push(request("/darkswap/orders/backfill/1.0.0", start)).unwrap();
+        queue.push(topic("darkswap/trade", start + Duration::from_millis(5))).unwrap();
+
+        let now = start + Duration::from_millis(10);
+        let classes: Vec<MessageClass> = (0..3).filter_map(|_| queue.pop(now)).map(|message| message.class).collect();
+        assert_eq!(classes, vec![MessageClass::Trade, MessageClass::Request, MessageClass::Gossip]);
+
+        let metrics = queue.metrics();
+        assert_eq!(metrics.get(MessageClass::Trade).max_queue_delay_us, 5_000);
+        assert_eq!(metrics.get(MessageClass::Gossip).depth, 49);
+    }
+
+    #[test]
+    fn test_full_queue() {
+        let start = Instant::now();
+        let mut queue = SendQueue::new(&SendQueueConfig { capacity: 2 });
+
+        // Gossip makes room by dropping its oldest message
+        for _ in 0..3 {
+            queue.push(topic("darkswap/orders", start)).unwrap();
+        }
+        assert_eq!(queue.len(), 2);
+
+        // Trade messages are refused
+        queue.push(request("/darkswap/trade/1.0.0", start)).unwrap();
+        queue.push(request("/darkswap/trade/1.0.0", start)).unwrap();
+        assert!(queue.push(request("/darkswap/trade/1.0.0", start)).is_err());
+
+        let metrics = queue.metrics();
+        assert_eq!(metrics.get(MessageClass::Gossip).dropped, 1);
+        assert_eq!(metrics.get(MessageClass::Trade).dropped, 1);
+    }
+}
@@ -15,10 +15,12 @@ use libp2p::core::{Multiaddr, PeerId};
 use log::{debug, error, info, warn};
 use tokio::sync::Mutex;
 
+use super::ice::IceConfiguration;
+
 /// WebRTC transport wrapper
 pub struct DarkSwapWebRtcTransport {
-    /// ICE servers
-    ice_servers: Vec<String>,
+    /// ICE servers and transport policy
+    ice: IceConfiguration,
     /// Signaling server URL
     signaling_server_url: Option<String>,
     /// Connected peers
@@ -28,19 +30,19 @@ pub struct DarkSwapWebRtcTransport {
 impl DarkSwapWebRtcTransport {
     /// Create a new WebRTC transport
     pub async fn new(
-        ice_servers: Vec<String>,
+        ice: IceConfiguration,
         signaling_server_url: Option<String>,
     ) -> Result<Self> {
         Ok(Self {
-            ice_servers,
+            ice,
             signaling_server_url,
             connected_peers: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
-    /// Get the ICE servers
-    pub fn ice_servers(&self) -> &[String] {
-        &self.ice_servers
+    /// Get the ICE servers and transport policy
+    pub fn ice(&self) -> &IceConfiguration {
+        &self.ice
     }
 
     /// Get the signaling server URL
@@ -55,6 +57,15 @@ impl DarkSwapWebRtcTransport {
 
     /// Connect to a peer via signaling server
     pub async fn connect_via_signaling(&self, peer_id: PeerId) -> Result<()> {
+        self.connect_with_ice(peer_id, &self.ice).await
+    }
+
+    /// Connect to a peer via signaling server, gathering candidates from the given ICE servers
+    ///
+    /// With no ICE servers only host candidates are gathered, so the connection is
+    /// direct; with STUN servers the connection can be hole punched through NATs,
+    /// and with TURN servers it can fall back to a relayed candidate.
+    pub async fn connect_with_ice(&self, peer_id: PeerId, ice: &IceConfiguration) -> Result<()> {
         // Check if we have a signaling server URL
         let signaling_url = self.signaling_server_url.as_ref().ok_or_else(|| {
             anyhow::anyhow!("No signaling server URL configured")
@@ -62,7 +73,13 @@ impl DarkSwapWebRtcTransport {
 
         // TODO: Implement signaling server connection
         // For now, just log a message
-        info!("Connecting to peer {} via signaling server {}", peer_id, signaling_url);
+        info!(
+            "Connecting to peer {} via signaling server {} ({} ICE servers, {} policy)",
+            peer_id,
+            signaling_url,
+            ice.servers.len(),
+            ice.transport_policy
+        );
 
         Ok(())
     }
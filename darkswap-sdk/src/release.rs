@@ -0,0 +1,178 @@
+//! Release manifests
+//!
+//! Each published SDK build comes with a manifest of the SHA-256 hashes of its
+//! artifacts (the wasm module, the JS glue), signed with a release key. A web
+//! wallet fetches the wasm module it is about to run, hashes it and checks the
+//! hash against a manifest signed by a key it trusts, proving it runs the
+//! published build. Release builds are reproducible (see `build.sh --release`),
+//! so anyone can rebuild from the tagged source and compare the hashes too.
+
+use std::collections::BTreeMap;
+
+use libp2p::identity::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Domain separator of manifest signatures
+const MANIFEST_DOMAIN: &[u8] = b"darkswap-release-manifest-v1\n";
+
+/// Release verification error
+#[derive(Debug, Error)]
+pub enum ReleaseError {
+    /// The manifest is malformed
+    #[error("Invalid release manifest: {0}")]
+    Invalid(String),
+    /// The manifest is signed by a key that is not trusted
+    #[error("Release manifest is signed by an untrusted key")]
+    UntrustedKey,
+    /// The manifest signature is invalid
+    #[error("Invalid release manifest signature")]
+    BadSignature,
+    /// The manifest does not list the artifact
+    #[error("Artifact not in release manifest: {0}")]
+    UnknownArtifact(String),
+    /// The artifact differs from the published one
+    #[error("Artifact {name} has hash {actual}, the release has {expected}")]
+    HashMismatch {
+        /// Artifact name
+        name: String,
+        /// Published hash
+        expected: String,
+        /// Hash of the artifact checked
+        actual: String,
+    },
+}
+
+/// Signed list of the artifacts of a release
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    /// Release version
+    pub version: String,
+    /// SHA-256 hashes of the artifacts by file name (hex)
+    pub artifacts: BTreeMap<String, String>,
+    /// Release key (protobuf encoding, hex)
+    pub public_key: String,
+    /// Signature over the version and artifacts (hex)
+    pub signature: String,
+}
+
+impl ReleaseManifest {
+    /// Sign a manifest of artifact hashes
+    pub fn sign(version: &str, artifacts: BTreeMap<String, String>, keypair: &Keypair) -> Result<Self, ReleaseError> {
+        let message = signing_bytes(version, &artifacts)?;
+        let signature = keypair
+            .sign(&message)
+            .map_err(|e| ReleaseError::Invalid(format!("Failed to sign manifest: {}", e)))?;
+
+        Ok(Self {
+            version: version.to_string(),
+            artifacts,
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Parse a manifest from JSON
+    pub fn from_json(json: &str) -> Result<Self, ReleaseError> {
+        serde_json::from_str(json).map_err(|e| ReleaseError::Invalid(e.to_string()))
+    }
+
+    /// Verify that the manifest is signed by one of the trusted release keys (hex)
+    pub fn verify(&self, trusted_keys: &[String]) -> Result<(), ReleaseError> {
+        if !trusted_keys.iter().any(|key| key.eq_ignore_ascii_case(&self.public_key)) {
+            return Err(ReleaseError::UntrustedKey);
+        }
+
+        let public_key_bytes = hex::decode(&self.public_key)
+            .map_err(|e| ReleaseError::Invalid(format!("Invalid public key encoding: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes)
+            .map_err(|e| ReleaseError::Invalid(format!("Invalid public key: {}", e)))?;
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| ReleaseError::Invalid(format!("Invalid signature encoding: {}", e)))?;
+
+        if !public_key.verify(&signing_bytes(&self.version, &self.artifacts)?, &signature) {
+            return Err(ReleaseError::BadSignature);
+        }
+
+        Ok(())
+    }
+
+    /// Verify the manifest and check that `bytes` are the published artifact `name`
+    pub fn verify_artifact(&self, name: &str, bytes: &[u8], trusted_keys: &[String]) -> Result<(), ReleaseError> {
+        self.verify(trusted_keys)?;
+
+        let expected = self.artifacts.get(name)
+            .ok_or_else(|| ReleaseError::UnknownArtifact(name.to_string()))?;
+        let actual = artifact_hash(bytes);
+
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(ReleaseError::HashMismatch {
+                name: name.to_string(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// SHA-256 hash of an artifact (hex)
+pub fn artifact_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Get the bytes covered by the manifest signature
+fn signing_bytes(version: &str, artifacts: &BTreeMap<String, String>) -> Result<Vec<u8>, ReleaseError> {
+    let body = serde_json::to_vec(&(version, artifacts))
+        .map_err(|e| ReleaseError::Invalid(e.to_string()))?;
+
+    let mut message = MANIFEST_DOMAIN.to_vec();
+    message.extend_from_slice(&body);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn manifest(keypair: &Keypair) -> ReleaseManifest {
+        let mut artifacts = BTreeMap::new();
+        artifacts.insert("darkswap_sdk_bg.wasm".to_string(), artifact_hash(WASM));
+
+        ReleaseManifest::sign("0.1.0", artifacts, keypair).unwrap()
+    }
+
+    #[test]
+    fn test_published_artifact_verifies() {
+        let keypair = Keypair::generate_ed25519();
+        let manifest = ReleaseManifest::from_json(&serde_json::to_string(&manifest(&keypair)).unwrap()).unwrap();
+        let trusted = vec![manifest.public_key.clone()];
+
+        assert!(manifest.verify_artifact("darkswap_sdk_bg.wasm", WASM, &trusted).is_ok());
+        assert!(matches!(
+            manifest.verify_artifact("darkswap_sdk_bg.wasm", b"\0asm\x01\0\0\x01", &trusted),
+            Err(ReleaseError::HashMismatch { .. })
+        ));
+        assert!(matches!(
+            manifest.verify_artifact("darkswap_sdk.js", WASM, &trusted),
+            Err(ReleaseError::UnknownArtifact(_))
+        ));
+    }
+
+    #[test]
+    fn test_untrusted_or_altered_manifest_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let mut manifest = manifest(&keypair);
+
+        let other = hex::encode(Keypair::generate_ed25519().public().to_protobuf_encoding());
+        assert!(matches!(manifest.verify(&[other]), Err(ReleaseError::UntrustedKey)));
+
+        let trusted = vec![manifest.public_key.clone()];
+        manifest.artifacts.insert("darkswap_sdk_bg.wasm".to_string(), artifact_hash(b"other"));
+        assert!(matches!(manifest.verify(&trusted), Err(ReleaseError::BadSignature)));
+    }
+}
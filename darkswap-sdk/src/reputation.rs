@@ -0,0 +1,306 @@
+//! Reputation module for DarkSwap
+//!
+//! This module tracks maker reputation and lets makers rotate their node keypair
+//! without losing it. A rotation statement is signed by both the old and the new key,
+//! so peers can carry reputation over from the old peer ID to the new one.
+
+use std::collections::HashMap;
+
+use anyhow::{Context as AnyhowContext, Result};
+use darkswap_support::envelope::Versioned;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// Identity rotation topic
+pub const IDENTITY_TOPIC: &str = "darkswap/identity/v1";
+
+/// Maximum length of a rotation chain that is followed when resolving a peer ID
+const MAX_ROTATION_DEPTH: usize = 32;
+
+/// Reputation of a peer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Reputation {
+    /// Peer ID
+    pub peer_id: String,
+    /// Number of completed trades
+    pub completed_trades: u64,
+    /// Number of failed trades
+    pub failed_trades: u64,
+    /// First seen timestamp
+    pub first_seen: u64,
+}
+
+impl Reputation {
+    /// Create a new reputation for a peer
+    pub fn new(peer_id: String) -> Self {
+        Self {
+            peer_id,
+            completed_trades: 0,
+            failed_trades: 0,
+            first_seen: now(),
+        }
+    }
+
+    /// Get the reputation score between 0.0 and 1.0
+    pub fn score(&self) -> f64 {
+        let total = self.completed_trades + self.failed_trades;
+        if total == 0 {
+            return 0.5;
+        }
+
+        self.completed_trades as f64 / total as f64
+    }
+}
+
+/// Identity rotation statement
+///
+/// The old key signs the link to the new key, and the new key signs it back so a
+/// rotation cannot point at a key the maker does not control.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationStatement {
+    /// Old peer ID
+    pub old_peer_id: String,
+    /// New peer ID
+    pub new_peer_id: String,
+    /// Old public key (protobuf encoded, hex)
+    pub old_public_key: String,
+    /// New public key (protobuf encoded, hex)
+    pub new_public_key: String,
+    /// Timestamp
+    pub timestamp: u64,
+    /// Signature by the old key (hex)
+    pub old_signature: String,
+    /// Signature by the new key (hex)
+    pub new_signature: String,
+}
+
+impl Versioned for RotationStatement {
+    const KIND: &'static str = "darkswap/identity/rotation";
+    const VERSION: u32 = 1;
+}
+
+impl RotationStatement {
+    /// Create a rotation statement from the old keypair to the new keypair
+    pub fn new(old_key: &Keypair, new_key: &Keypair) -> Result<Self> {
+        let mut statement = Self {
+            old_peer_id: PeerId::from(old_key.public()).to_string(),
+            new_peer_id: PeerId::from(new_key.public()).to_string(),
+            old_public_key: hex::encode(old_key.public().to_protobuf_encoding()),
+            new_public_key: hex::encode(new_key.public().to_protobuf_encoding()),
+            timestamp: now(),
+            old_signature: String::new(),
+            new_signature: String::new(),
+        };
+
+        // Sign the statement with both keys
+        let message = statement.signing_bytes();
+        statement.old_signature = hex::encode(old_key.sign(&message).context("Failed to sign with old key")?);
+        statement.new_signature = hex::encode(new_key.sign(&message).context("Failed to sign with new key")?);
+
+        Ok(statement)
+    }
+
+    /// Verify both signatures and that the keys match the peer IDs
+    pub fn verify(&self) -> Result<()> {
+        if self.old_peer_id == self.new_peer_id {
+            anyhow::bail!("Rotation must change the peer ID");
+        }
+
+        let message = self.signing_bytes();
+        verify_signature(&self.old_public_key, &self.old_peer_id, &message, &self.old_signature)
+            .context("Invalid old key signature")?;
+        verify_signature(&self.new_public_key, &self.new_peer_id, &message, &self.new_signature)
+            .context("Invalid new key signature")?;
+
+        Ok(())
+    }
+
+    /// Get the bytes covered by the signatures
+    fn signing_bytes(&self) -> Vec<u8> {
+        format!(
+            "darkswap-rotation:{}:{}:{}:{}:{}",
+            self.old_peer_id, self.new_peer_id, self.old_public_key, self.new_public_key, self.timestamp
+        )
+        .into_bytes()
+    }
+}
+
+/// Reputation manager
+#[derive(Debug, Default)]
+pub struct ReputationManager {
+    /// Reputation by peer ID
+    reputations: HashMap<String, Reputation>,
+    /// Rotations from old peer ID to new peer ID
+    rotations: HashMap<String, String>,
+}
+
+impl ReputationManager {
+    /// Create a new reputation manager
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve a peer ID to its current identity by following rotations
+    pub fn resolve(&self, peer_id: &str) -> String {
+        let mut current = peer_id.to_string();
+
+        for _ in 0..MAX_ROTATION_DEPTH {
+            match self.rotations.get(&current) {
+                Some(next) => current = next.clone(),
+                None => break,
+            }
+        }
+
+        current
+    }
+
+    /// Get the reputation of a peer
+    pub fn get_reputation(&self, peer_id: &str) -> Option<Reputation> {
+        self.reputations.get(&self.resolve(peer_id)).cloned()
+    }
+
+    /// Record a completed trade
+    pub fn record_trade_completed(&mut self, peer_id: &str) {
+        let peer_id = self.resolve(peer_id);
+        self.reputations
+            .entry(peer_id.clone())
+            .or_insert_with(|| Reputation::new(peer_id))
+            .completed_trades += 1;
+    }
+
+    /// Record a failed trade
+    pub fn record_trade_failed(&mut self, peer_id: &str) {
+        let peer_id = self.resolve(peer_id);
+        self.reputations
+            .entry(peer_id.clone())
+            .or_insert_with(|| Reputation::new(peer_id))
+            .failed_trades += 1;
+    }
+
+    /// Apply an identity rotation
+    ///
+    /// The reputation of the old peer ID is moved to the new peer ID.
+    pub fn apply_rotation(&mut self, statement: &RotationStatement) -> Result<()> {
+        statement.verify()?;
+
+        // An identity can only be rotated once
+        if let Some(existing) = self.rotations.get(&statement.old_peer_id) {
+            if existing == &statement.new_peer_id {
+                return Ok(());
+            }
+            anyhow::bail!("Peer {} has already rotated to {}", statement.old_peer_id, existing);
+        }
+
+        // Refuse rotations that would create a cycle
+        if self.resolve(&statement.new_peer_id) == statement.old_peer_id {
+            anyhow::bail!("Rotation would create a cycle");
+        }
+
+        self.rotations.insert(statement.old_peer_id.clone(), statement.new_peer_id.clone());
+
+        // Carry reputation over to the new identity
+        if let Some(old) = self.reputations.remove(&statement.old_peer_id) {
+            let new = self.reputations
+                .entry(statement.new_peer_id.clone())
+                .or_insert_with(|| Reputation::new(statement.new_peer_id.clone()));
+            new.completed_trades += old.completed_trades;
+            new.failed_trades += old.failed_trades;
+            new.first_seen = new.first_seen.min(old.first_seen);
+        }
+
+        Ok(())
+    }
+}
+
+/// Generate a new identity key (protobuf encoded, hex)
+pub fn generate_identity_key() -> Result<String> {
+    encode_identity_key(&Keypair::generate_ed25519())
+}
+
+/// Encode an identity keypair (protobuf encoded, hex)
+pub fn encode_identity_key(keypair: &Keypair) -> Result<String> {
+    let bytes = keypair.to_protobuf_encoding().context("Failed to encode identity key")?;
+    Ok(hex::encode(bytes))
+}
+
+/// Decode an identity keypair (protobuf encoded, hex)
+pub fn decode_identity_key(key: &str) -> Result<Keypair> {
+    let bytes = hex::decode(key).context("Invalid identity key encoding")?;
+    Keypair::from_protobuf_encoding(&bytes).context("Invalid identity key")
+}
+
+/// Get the peer ID of an identity key (protobuf encoded, hex)
+pub fn identity_peer_id(key: &str) -> Result<String> {
+    let keypair = decode_identity_key(key)?;
+    Ok(PeerId::from(keypair.public()).to_string())
+}
+
+/// Verify a signature against a hex encoded public key and its peer ID
+fn verify_signature(public_key: &str, peer_id: &str, message: &[u8], signature: &str) -> Result<()> {
+    let public_key_bytes = hex::decode(public_key).context("Invalid public key encoding")?;
+    let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes).context("Invalid public key")?;
+
+    if PeerId::from_public_key(&public_key).to_string() != peer_id {
+        anyhow::bail!("Public key does not match peer ID");
+    }
+
+    let signature = hex::decode(signature).context("Invalid signature encoding")?;
+    if !public_key.verify(message, &signature) {
+        anyhow::bail!("Signature verification failed");
+    }
+
+    Ok(())
+}
+
+/// Get the current time in seconds
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_carries_reputation() {
+        let old_key = Keypair::generate_ed25519();
+        let new_key = Keypair::generate_ed25519();
+        let old_peer_id = PeerId::from(old_key.public()).to_string();
+        let new_peer_id = PeerId::from(new_key.public()).to_string();
+
+        let mut manager = ReputationManager::new();
+        manager.record_trade_completed(&old_peer_id);
+        manager.record_trade_completed(&old_peer_id);
+
+        let statement = RotationStatement::new(&old_key, &new_key).unwrap();
+        manager.apply_rotation(&statement).unwrap();
+
+        assert_eq!(manager.resolve(&old_peer_id), new_peer_id);
+        assert_eq!(manager.get_reputation(&new_peer_id).unwrap().completed_trades, 2);
+    }
+
+    #[test]
+    fn test_forged_rotation_is_rejected() {
+        let old_key = Keypair::generate_ed25519();
+        let new_key = Keypair::generate_ed25519();
+        let attacker_key = Keypair::generate_ed25519();
+
+        let mut statement = RotationStatement::new(&old_key, &new_key).unwrap();
+        statement.new_peer_id = PeerId::from(attacker_key.public()).to_string();
+        statement.new_public_key = hex::encode(attacker_key.public().to_protobuf_encoding());
+
+        assert!(statement.verify().is_err());
+    }
+
+    #[test]
+    fn test_identity_key_roundtrip() {
+        let key = generate_identity_key().unwrap();
+        let keypair = decode_identity_key(&key).unwrap();
+
+        assert_eq!(encode_identity_key(&keypair).unwrap(), key);
+    }
+}
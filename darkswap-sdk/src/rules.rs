@@ -0,0 +1,274 @@
+//! Market rules
+//!
+//! A daemon operator publishes the trading rules of their market (allowed
+//! pairs, minimum and maximum order sizes, fees) as a document signed with the
+//! node identity key. Clients fetch it from `GET /market/rules` and verify it
+//! against the operator keys they trust, so front-ends render the rules the
+//! operator signed instead of hardcoding them. Rules carry a sequence number so
+//! a client can refuse to go back to an older document.
+
+use std::path::Path;
+
+use anyhow::Context;
+use libp2p::identity::{Keypair, PublicKey};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::types::Asset;
+
+/// Domain separator of market rules signatures
+const RULES_DOMAIN: &[u8] = b"darkswap-market-rules-v1\n";
+
+/// Market rules error
+#[derive(Debug, Error)]
+pub enum RulesError {
+    /// The document is malformed
+    #[error("Invalid market rules: {0}")]
+    Invalid(String),
+    /// The document is signed by a key that is not trusted
+    #[error("Market rules are signed by an untrusted key")]
+    UntrustedKey,
+    /// The document signature is invalid
+    #[error("Invalid market rules signature")]
+    BadSignature,
+    /// The pair is not traded on this market
+    #[error("Pair {0}/{1} is not allowed on this market")]
+    PairNotAllowed(Asset, Asset),
+    /// The order amount is outside the allowed range
+    #[error("Order amount {amount} is outside the allowed range for {base_asset}/{quote_asset}")]
+    AmountOutOfRange {
+        /// Base asset
+        base_asset: Asset,
+        /// Quote asset
+        quote_asset: Asset,
+        /// Order amount
+        amount: Decimal,
+    },
+}
+
+/// Rules of one pair
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PairRules {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Minimum order amount, overriding the market minimum
+    pub min_amount: Option<Decimal>,
+    /// Maximum order amount
+    pub max_amount: Option<Decimal>,
+    /// Maker fee (basis points), overriding the market fee
+    pub maker_fee_bps: Option<u32>,
+    /// Taker fee (basis points), overriding the market fee
+    pub taker_fee_bps: Option<u32>,
+}
+
+/// Trading rules of a market
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarketRules {
+    /// Sequence number, increased with every published revision
+    pub sequence: u64,
+    /// Time of issue (unix seconds)
+    pub issued_at: u64,
+    /// Time after which the rules must not be relied on (unix seconds)
+    pub expires_at: Option<u64>,
+    /// Minimum order amount of every pair
+    pub min_amount: Option<Decimal>,
+    /// Maker fee (basis points)
+    pub maker_fee_bps: u32,
+    /// Taker fee (basis points)
+    pub taker_fee_bps: u32,
+    /// Pairs traded on the market; empty allows every pair
+    pub pairs: Vec<PairRules>,
+    /// Free-form notes for users
+    pub notes: Option<String>,
+}
+
+impl MarketRules {
+    /// Get the rules of a pair
+    pub fn pair(&self, base_asset: &Asset, quote_asset: &Asset) -> Option<&PairRules> {
+        self.pairs.iter().find(|pair| &pair.base_asset == base_asset && &pair.quote_asset == quote_asset)
+    }
+
+    /// Check if a pair is traded on the market
+    pub fn allows_pair(&self, base_asset: &Asset, quote_asset: &Asset) -> bool {
+        self.pairs.is_empty() || self.pair(base_asset, quote_asset).is_some()
+    }
+
+    /// Get the maker and taker fees of a pair (basis points)
+    pub fn fees_bps(&self, base_asset: &Asset, quote_asset: &Asset) -> (u32, u32) {
+        let pair = self.pair(base_asset, quote_asset);
+        (
+            pair.and_then(|pair| pair.maker_fee_bps).unwrap_or(self.maker_fee_bps),
+            pair.and_then(|pair| pair.taker_fee_bps).unwrap_or(self.taker_fee_bps),
+        )
+    }
+
+    /// Check that an order follows the rules
+    pub fn check_order(&self, base_asset: &Asset, quote_asset: &Asset, amount: Decimal) -> Result<(), RulesError> {
+        if !self.allows_pair(base_asset, quote_asset) {
+            return Err(RulesError::PairNotAllowed(base_asset.clone(), quote_asset.clone()));
+        }
+
+        let pair = self.pair(base_asset, quote_asset);
+        let min_amount = pair.and_then(|pair| pair.min_amount).or(self.min_amount);
+        let max_amount = pair.and_then(|pair| pair.max_amount);
+
+        if min_amount.map_or(false, |min| amount < min) || max_amount.map_or(false, |max| amount > max) {
+            return Err(RulesError::AmountOutOfRange {
+                base_asset: base_asset.clone(),
+                quote_asset: quote_asset.clone(),
+                amount,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Check if the rules have expired at `now` (unix seconds)
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |expires_at| now >= expires_at)
+    }
+}
+
+/// Market rules signed by the operator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedMarketRules {
+    /// Rules
+    pub rules: MarketRules,
+    /// Operator key (protobuf encoding, hex)
+    pub public_key: String,
+    /// Signature over the rules (hex)
+    pub signature: String,
+}
+
+impl SignedMarketRules {
+    /// Sign market rules
+    pub fn sign(rules: MarketRules, keypair: &Keypair) -> Result<Self, RulesError> {
+        let message = signing_bytes(&rules)?;
+        let signature = keypair
+            .sign(&message)
+            .map_err(|e| RulesError::Invalid(format!("Failed to sign market rules: {}", e)))?;
+
+        Ok(Self {
+            rules,
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Parse signed rules from JSON
+    pub fn from_json(json: &str) -> Result<Self, RulesError> {
+        serde_json::from_str(json).map_err(|e| RulesError::Invalid(e.to_string()))
+    }
+
+    /// Load signed rules from a file, checking their signature
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read market rules {}", path.display()))?;
+        let signed = Self::from_json(&json)?;
+        signed.verify_signature()?;
+
+        Ok(signed)
+    }
+
+    /// Check that the signature matches the key the rules carry
+    ///
+    /// This only shows the document is intact; clients use `verify` to also
+    /// check who signed it.
+    pub fn verify_signature(&self) -> Result<(), RulesError> {
+        let public_key_bytes = hex::decode(&self.public_key)
+            .map_err(|e| RulesError::Invalid(format!("Invalid public key encoding: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes)
+            .map_err(|e| RulesError::Invalid(format!("Invalid public key: {}", e)))?;
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| RulesError::Invalid(format!("Invalid signature encoding: {}", e)))?;
+
+        if !public_key.verify(&signing_bytes(&self.rules)?, &signature) {
+            return Err(RulesError::BadSignature);
+        }
+
+        Ok(())
+    }
+
+    /// Verify that the rules are signed by one of the trusted operator keys (hex)
+    pub fn verify(&self, trusted_keys: &[String]) -> Result<(), RulesError> {
+        if !trusted_keys.iter().any(|key| key.eq_ignore_ascii_case(&self.public_key)) {
+            return Err(RulesError::UntrustedKey);
+        }
+
+        self.verify_signature()
+    }
+}
+
+/// Get the bytes covered by the rules signature
+fn signing_bytes(rules: &MarketRules) -> Result<Vec<u8>, RulesError> {
+    let body = serde_json::to_vec(rules).map_err(|e| RulesError::Invalid(e.to_string()))?;
+
+    let mut message = RULES_DOMAIN.to_vec();
+    message.extend_from_slice(&body);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn rules() -> MarketRules {
+        MarketRules {
+            sequence: 1,
+            issued_at: 1_700_000_000,
+            expires_at: Some(1_800_000_000),
+            min_amount: Some(dec!(0.001)),
+            maker_fee_bps: 10,
+            taker_fee_bps: 20,
+            pairs: vec![PairRules {
+                base_asset: Asset::Bitcoin,
+                quote_asset: Asset::Rune(1),
+                min_amount: Some(dec!(0.01)),
+                max_amount: Some(dec!(5)),
+                maker_fee_bps: Some(0),
+                taker_fee_bps: None,
+            }],
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn test_signed_rules_verify() {
+        let keypair = Keypair::generate_ed25519();
+        let signed = SignedMarketRules::sign(rules(), &keypair).unwrap();
+        let signed = SignedMarketRules::from_json(&serde_json::to_string(&signed).unwrap()).unwrap();
+
+        assert!(signed.verify(&[signed.public_key.clone()]).is_ok());
+
+        let other = hex::encode(Keypair::generate_ed25519().public().to_protobuf_encoding());
+        assert!(matches!(signed.verify(&[other]), Err(RulesError::UntrustedKey)));
+
+        let mut altered = signed.clone();
+        altered.rules.taker_fee_bps = 0;
+        assert!(matches!(altered.verify(&[altered.public_key.clone()]), Err(RulesError::BadSignature)));
+    }
+
+    #[test]
+    fn test_check_order() {
+        let rules = rules();
+
+        assert!(rules.check_order(&Asset::Bitcoin, &Asset::Rune(1), dec!(1)).is_ok());
+        assert!(matches!(
+            rules.check_order(&Asset::Bitcoin, &Asset::Rune(1), dec!(0.005)),
+            Err(RulesError::AmountOutOfRange { .. })
+        ));
+        assert!(matches!(
+            rules.check_order(&Asset::Bitcoin, &Asset::Rune(2), dec!(1)),
+            Err(RulesError::PairNotAllowed(_, _))
+        ));
+
+        assert_eq!(rules.fees_bps(&Asset::Bitcoin, &Asset::Rune(1)), (0, 20));
+        assert!(!rules.is_expired(1_700_000_000));
+        assert!(rules.is_expired(1_800_000_000));
+    }
+}
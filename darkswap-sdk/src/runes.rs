@@ -9,6 +9,8 @@ use bitcoin::{
 use crate::error::{Error, Result};
 use crate::runestone::{Edict, Etching, Runestone, Terms};
 use crate::bitcoin_utils::BitcoinWallet;
+use crate::types::Asset;
+use crate::wallet::coin_selection;
 use std::collections::HashMap;
 
 /// Rune structure
@@ -162,8 +164,10 @@ impl Rune {
             0
         };
 
-        let divisor = 10u128.pow(self.decimals as u32);
-        Ok(integer_part * divisor + fractional_part)
+        10u128.checked_pow(self.decimals as u32)
+            .and_then(|divisor| integer_part.checked_mul(divisor))
+            .and_then(|amount| amount.checked_add(fractional_part))
+            .ok_or_else(|| Error::InvalidAmount("Amount too large".to_string()))
     }
 }
 
@@ -236,11 +240,13 @@ impl RuneProtocol {
             output: Vec::new(),
         };
         
-        // Add inputs from the wallet
-        let utxos = wallet.get_utxos()?;
-        for (outpoint, _) in &utxos {
+        // Add plain bitcoin inputs from the wallet; the etched runes go to the change output
+        let input_fee = (68.0 * fee_rate).ceil() as u64;
+        let target = 546 + (150.0 * fee_rate).ceil() as u64;
+        let selection = coin_selection::select_bitcoin(&wallet.get_tagged_utxos()?, target, input_fee)?;
+        for utxo in &selection.selected {
             tx.input.push(TxIn {
-                previous_output: *outpoint,
+                previous_output: utxo.outpoint,
                 script_sig: bitcoin::blockdata::script::Builder::new().into_script(),
                 sequence: bitcoin::Sequence::MAX,
                 witness: Witness::new(),
@@ -279,10 +285,7 @@ impl RuneProtocol {
         });
         
         // Calculate the total input value
-        let mut total_input_value = 0;
-        for (_, txout) in &utxos {
-            total_input_value += txout.value;
-        }
+        let total_input_value = selection.value;
         
         // Calculate the transaction size
         let tx_size = tx.size();
@@ -292,7 +295,10 @@ impl RuneProtocol {
         
         // Calculate the change amount
         let output_value = 546; // Dust limit for the recipient output
-        let change_amount = total_input_value - output_value - fee;
+        let change_amount = total_input_value
+            .checked_sub(output_value)
+            .and_then(|value| value.checked_sub(fee))
+            .ok_or(Error::InsufficientBalance)?;
         
         // Set the change output value
         tx.output[0].value = change_amount;
@@ -329,11 +335,19 @@ impl RuneProtocol {
             output: Vec::new(),
         };
         
-        // Add inputs from the wallet
-        let utxos = wallet.get_utxos()?;
-        for (outpoint, _) in &utxos {
+        // Add inputs holding the rune, topped up with plain bitcoin
+        let input_fee = (68.0 * fee_rate).ceil() as u64;
+        let target = 546 + (200.0 * fee_rate).ceil() as u64;
+        let selection = coin_selection::select_asset(
+            &wallet.get_tagged_utxos()?,
+            &Asset::Rune(rune_id),
+            amount,
+            target,
+            input_fee,
+        )?;
+        for utxo in &selection.selected {
             tx.input.push(TxIn {
-                previous_output: *outpoint,
+                previous_output: utxo.outpoint,
                 script_sig: bitcoin::blockdata::script::Builder::new().into_script(),
                 sequence: bitcoin::Sequence::MAX,
                 witness: Witness::new(),
@@ -353,11 +367,11 @@ impl RuneProtocol {
             output: 0, // First output is the recipient
         };
         
-        // Create the runestone
+        // Create the runestone; runes beyond the edict return to the change output
         let runestone = Runestone {
             edicts: vec![edict],
             etching: None,
-            default_output: None,
+            default_output: Some(2),
             burn: false,
         };
         
@@ -374,10 +388,7 @@ impl RuneProtocol {
         });
         
         // Calculate the total input value
-        let mut total_input_value = 0;
-        for (_, txout) in &utxos {
-            total_input_value += txout.value;
-        }
+        let total_input_value = selection.value;
         
         // Calculate the transaction size
         let tx_size = tx.size();
@@ -387,7 +398,10 @@ impl RuneProtocol {
         
         // Calculate the change amount
         let output_value = 546; // Dust limit for the recipient output
-        let change_amount = total_input_value - output_value - fee;
+        let change_amount = total_input_value
+            .checked_sub(output_value)
+            .and_then(|value| value.checked_sub(fee))
+            .ok_or(Error::InsufficientBalance)?;
         
         // Set the change output value
         tx.output[2].value = change_amount;
@@ -429,7 +443,10 @@ impl RuneProtocol {
                         let address = address;
                         // Update the balance
                         let balance = self.get_balance(&address, edict.id);
-                        let new_balance = balance + edict.amount;
+                        let new_balance = match balance.checked_add(edict.amount) {
+                            Some(new_balance) => new_balance,
+                            None => continue,
+                        };
                         
                         // Update the balances map
                         let address_str = format!("{:?}", address);
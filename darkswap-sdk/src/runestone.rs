@@ -8,6 +8,12 @@ use bitcoin::{
 use crate::error::{Error, Result};
 use std::collections::HashMap;
 
+/// Maximum number of decimals of a rune
+pub const MAX_DECIMALS: u8 = 38;
+
+/// Size of a serialized edict
+const EDICT_SIZE: usize = 16 + 16 + 4;
+
 /// Runestone structure
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Runestone {
@@ -323,6 +329,11 @@ fn parse_runestone_data(data: &[u8]) -> Option<Runestone> {
             }
             let decimals = data[index];
             index += 1;
+            
+            // More decimals than fit in a u128 amount cannot be displayed or parsed
+            if decimals > MAX_DECIMALS {
+                return None;
+            }
             Some(decimals)
         } else {
             None
@@ -444,6 +455,10 @@ fn parse_runestone_data(data: &[u8]) -> Option<Runestone> {
     let edict_count = u32::from_le_bytes(bytes) as usize;
     index += 4;
     
+    // The count is untrusted, so never reserve more than the remaining data can hold
+    if edict_count > (data.len() - index) / EDICT_SIZE {
+        return None;
+    }
     let mut edicts = Vec::with_capacity(edict_count);
     for _ in 0..edict_count {
         // Parse the id
@@ -587,4 +602,30 @@ mod tests {
         // Check that the parsed Runestone matches the original
         assert_eq!(parsed, runestone);
     }
+    
+    #[test]
+    fn test_runestone_rejects_malformed_data() {
+        let runestone = Runestone {
+            edicts: vec![],
+            etching: Some(Etching {
+                rune: 1,
+                symbol: None,
+                decimals: Some(MAX_DECIMALS + 1),
+                spacers: 0,
+                amount: 1,
+                terms: None,
+            }),
+            default_output: None,
+            burn: false,
+        };
+        let mut data = Vec::new();
+        runestone.serialize_to(&mut data);
+        assert!(parse_runestone_data(&data).is_none());
+        
+        // An edict count far beyond the data must not be allocated for
+        let mut data = vec![0x00, 0x00, 0x00];
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&[0u8; EDICT_SIZE]);
+        assert!(parse_runestone_data(&data).is_none());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,456 @@
+//! SPV module for DarkSwap
+//!
+//! This module provides compact block header tracking and merkle proof verification,
+//! so transaction inclusion can be checked without trusting the Electrum/Esplora server.
+
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+use anyhow::{Context as AnyhowContext, Result};
+use bitcoin::consensus::deserialize;
+use bitcoin::consensus::params::Params;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::util::uint::Uint256;
+use bitcoin::{BlockHash, BlockHeader, Network, TxMerkleNode};
+use serde::{Deserialize, Serialize};
+
+/// Merkle proof of transaction inclusion in a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Block hash
+    pub block_hash: String,
+    /// Block height
+    pub block_height: u32,
+    /// Position of the transaction in the block
+    pub position: u32,
+    /// Sibling hashes from the leaf to the root
+    pub path: Vec<String>,
+}
+
+impl MerkleProof {
+    /// Compute the merkle root the proof links a transaction ID to
+    pub fn compute_root(&self, txid: &str) -> Result<TxMerkleNode> {
+        let mut current = sha256d::Hash::from_str(txid).context("Invalid transaction ID")?;
+        let mut position = self.position;
+
+        for sibling in &self.path {
+            let sibling = sha256d::Hash::from_str(sibling).context("Invalid merkle path")?;
+
+            let mut data = Vec::with_capacity(64);
+            if position & 1 == 0 {
+                data.extend_from_slice(&current.into_inner());
+                data.extend_from_slice(&sibling.into_inner());
+            } else {
+                data.extend_from_slice(&sibling.into_inner());
+                data.extend_from_slice(&current.into_inner());
+            }
+
+            current = sha256d::Hash::hash(&data);
+            position >>= 1;
+        }
+
+        Ok(TxMerkleNode::from_hash(current))
+    }
+
+    /// Check that the proof links a transaction ID to the block of the chain at the proof's height
+    ///
+    /// The merkle root is taken from the tracked header, never from the proof, so
+    /// a proof for a block the chain does not hold is rejected.
+    pub fn verify(&self, txid: &str, chain: &HeaderChain) -> Result<bool> {
+        let (block_hash, header) = chain.header_at(self.block_height)
+            .ok_or_else(|| anyhow::anyhow!("No block header at height {}", self.block_height))?;
+
+        if !self.block_hash.is_empty() {
+            let claimed = BlockHash::from_str(&self.block_hash).context("Invalid block hash")?;
+            if claimed != block_hash {
+                anyhow::bail!("Block {} is not in the chain at height {}", self.block_hash, self.block_height);
+            }
+        }
+
+        Ok(self.compute_root(txid)? == header.merkle_root)
+    }
+}
+
+/// Header kept by the chain, with the work of the chain it ends
+#[derive(Debug, Clone)]
+struct ChainEntry {
+    /// Block height
+    height: u32,
+    /// Header
+    header: BlockHeader,
+    /// Work of the chain from the checkpoint up to and including this header
+    chain_work: Uint256,
+}
+
+/// Chain of block headers
+///
+/// Only the 80-byte headers are kept. The first header is taken as a
+/// checkpoint; every later header must name a known header as its parent, carry
+/// the target the consensus rules of the network expect after that parent, and
+/// meet it. Headers of competing branches are kept, and the branch with the most
+/// work is the chain heights and confirmations are read from.
+#[derive(Debug)]
+pub struct HeaderChain {
+    /// Consensus parameters of the network
+    params: Params,
+    /// Headers by hash, on every branch
+    headers: HashMap<BlockHash, ChainEntry>,
+    /// Block hashes of the most-work chain by height
+    heights: BTreeMap<u32, BlockHash>,
+    /// Tip of the most-work chain
+    tip: Option<BlockHash>,
+}
+
+impl HeaderChain {
+    /// Create an empty header chain for a network
+    pub fn new(network: Network) -> Self {
+        Self {
+            params: Params::new(network),
+            headers: HashMap::new(),
+            heights: BTreeMap::new(),
+            tip: None,
+        }
+    }
+
+    /// Add a block header at the given height
+    ///
+    /// Returns the block hash. A header extending a branch other than the tip's
+    /// only becomes part of the chain once its branch has more work.
+    pub fn add_header(&mut self, height: u32, header: BlockHeader) -> Result<BlockHash> {
+        // The target may not be easier than the network allows
+        let target = header.target();
+        if target > self.params.pow_limit {
+            anyhow::bail!("Header target is above the proof of work limit");
+        }
+
+        // Check proof of work
+        let block_hash = header.validate_pow(&target)
+            .map_err(|e| anyhow::anyhow!("Invalid proof of work: {}", e))?;
+
+        if self.headers.contains_key(&block_hash) {
+            return Ok(block_hash);
+        }
+
+        // Check that the header links to a known parent with the expected target
+        let chain_work = if self.headers.is_empty() {
+            header.work()
+        } else {
+            let parent = self.headers.get(&header.prev_blockhash)
+                .ok_or_else(|| anyhow::anyhow!("Header does not connect to the chain: {}", block_hash))?;
+            if parent.height + 1 != height {
+                anyhow::bail!("Header height does not follow its parent");
+            }
+
+            self.check_target(parent, &header)?;
+            parent.chain_work + header.work()
+        };
+
+        self.headers.insert(block_hash, ChainEntry { height, header, chain_work });
+
+        // Switch to the branch of the header if it now has the most work
+        let most_work = match self.tip.and_then(|tip| self.headers.get(&tip)) {
+            Some(tip) => chain_work > tip.chain_work,
+            None => true,
+        };
+        if most_work {
+            self.set_tip(block_hash);
+        }
+
+        Ok(block_hash)
+    }
+
+    /// Check that a header carries the target expected after its parent
+    fn check_target(&self, parent: &ChainEntry, header: &BlockHeader) -> Result<()> {
+        let height = u64::from(parent.height) + 1;
+        let interval = self.params.pow_target_timespan / self.params.pow_target_spacing;
+        let pow_limit_bits = BlockHeader::compact_target_from_u256(&self.params.pow_limit);
+
+        if height % interval != 0 {
+            if self.params.allow_min_difficulty_blocks {
+                // A block found long after its parent may use the minimum difficulty
+                let spacing = self.params.pow_target_spacing as u32;
+                if header.time > parent.header.time.saturating_add(spacing * 2) {
+                    return expect_bits(header, pow_limit_bits);
+                }
+
+                // Otherwise the difficulty is that of the last block not using the exception
+                let mut entry = parent;
+                while u64::from(entry.height) % interval != 0 && entry.header.bits == pow_limit_bits {
+                    match self.headers.get(&entry.header.prev_blockhash) {
+                        Some(previous) => entry = previous,
+                        None => break,
+                    }
+                }
+                return expect_bits(header, entry.header.bits);
+            }
+
+            return expect_bits(header, parent.header.bits);
+        }
+
+        if self.params.no_pow_retargeting {
+            return expect_bits(header, parent.header.bits);
+        }
+
+        // Retarget from the time the last period took, if its first header is known
+        let mut first = parent;
+        for _ in 1..interval {
+            match self.headers.get(&first.header.prev_blockhash) {
+                Some(previous) => first = previous,
+                None => return self.check_target_bounds(parent, header),
+            }
+        }
+
+        let timespan = self.params.pow_target_timespan;
+        let actual = u64::from(parent.header.time.saturating_sub(first.header.time))
+            .clamp(timespan / 4, timespan * 4);
+        let divisor = Uint256::from_u64(timespan)
+            .ok_or_else(|| anyhow::anyhow!("Invalid target timespan"))?;
+        let mut expected = parent.header.target().mul_u32(actual as u32) / divisor;
+        if expected > self.params.pow_limit {
+            expected = self.params.pow_limit;
+        }
+
+        expect_bits(header, BlockHeader::compact_target_from_u256(&expected))
+    }
+
+    /// Check that a retarget stays within a factor of four of the parent's target
+    ///
+    /// Used when the chain starts after the first header of the period, so the
+    /// time the period took is unknown.
+    fn check_target_bounds(&self, parent: &ChainEntry, header: &BlockHeader) -> Result<()> {
+        let parent_target = parent.header.target();
+        let target = header.target();
+        if target > parent_target.mul_u32(4) || target.mul_u32(4) < parent_target {
+            anyhow::bail!("Header target changes by more than a retarget allows");
+        }
+
+        Ok(())
+    }
+
+    /// Make a header the tip, rewriting the heights of the branch it ends
+    fn set_tip(&mut self, tip: BlockHash) {
+        let Some(tip_height) = self.headers.get(&tip).map(|entry| entry.height) else {
+            return;
+        };
+
+        // Heights above a shorter tip with more work no longer belong to the chain
+        self.heights.split_off(&(tip_height + 1));
+
+        let mut block_hash = tip;
+        while let Some(entry) = self.headers.get(&block_hash) {
+            if self.heights.insert(entry.height, block_hash) == Some(block_hash) {
+                break;
+            }
+            block_hash = entry.header.prev_blockhash;
+        }
+
+        self.tip = Some(tip);
+    }
+
+    /// Add a hex encoded block header at the given height
+    pub fn add_header_hex(&mut self, height: u32, header_hex: &str) -> Result<BlockHash> {
+        let bytes = hex::decode(header_hex).context("Invalid header encoding")?;
+        let header: BlockHeader = deserialize(&bytes).context("Invalid block header")?;
+
+        self.add_header(height, header)
+    }
+
+    /// Get a header and its height by block hash, on any branch
+    pub fn get_header(&self, block_hash: &BlockHash) -> Option<(u32, &BlockHeader)> {
+        self.headers.get(block_hash).map(|entry| (entry.height, &entry.header))
+    }
+
+    /// Get the hash and header of the block of the most-work chain at a height
+    pub fn header_at(&self, height: u32) -> Option<(BlockHash, &BlockHeader)> {
+        let block_hash = self.heights.get(&height)?;
+        self.headers.get(block_hash).map(|entry| (*block_hash, &entry.header))
+    }
+
+    /// Get the hash of the chain tip
+    pub fn tip(&self) -> Option<BlockHash> {
+        self.tip
+    }
+
+    /// Get the height of the chain tip
+    pub fn tip_height(&self) -> Option<u32> {
+        self.heights.keys().next_back().copied()
+    }
+
+    /// Get the number of tracked headers
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Check if no headers are tracked
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+}
+
+/// Check that a header carries the expected compact target
+fn expect_bits(header: &BlockHeader, bits: u32) -> Result<()> {
+    if header.bits != bits {
+        anyhow::bail!("Header target {:#010x} does not match the expected {:#010x}", header.bits, bits);
+    }
+
+    Ok(())
+}
+
+/// SPV verifier
+#[derive(Debug)]
+pub struct SpvVerifier {
+    /// Header chain
+    chain: HeaderChain,
+    /// Minimum confirmations
+    min_confirmations: u32,
+}
+
+impl SpvVerifier {
+    /// Create a new SPV verifier for a network
+    pub fn new(network: Network, min_confirmations: u32) -> Self {
+        Self {
+            chain: HeaderChain::new(network),
+            min_confirmations,
+        }
+    }
+
+    /// Get the header chain
+    pub fn chain(&self) -> &HeaderChain {
+        &self.chain
+    }
+
+    /// Get the header chain mutably
+    pub fn chain_mut(&mut self) -> &mut HeaderChain {
+        &mut self.chain
+    }
+
+    /// Verify that a transaction is included in a tracked block
+    ///
+    /// Returns the number of confirmations.
+    pub fn verify_inclusion(&self, txid: &str, proof: &MerkleProof) -> Result<u32> {
+        // Check the merkle path against the tracked header
+        if !proof.verify(txid, &self.chain)? {
+            anyhow::bail!("Merkle proof does not match transaction {}", txid);
+        }
+        let height = proof.block_height;
+
+        // Check confirmations
+        let tip_height = self.chain.tip_height().unwrap_or(height);
+        let confirmations = tip_height.saturating_sub(height) + 1;
+
+        if confirmations < self.min_confirmations {
+            anyhow::bail!(
+                "Transaction {} has {} confirmations, {} required",
+                txid,
+                confirmations,
+                self.min_confirmations
+            );
+        }
+
+        Ok(confirmations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Combine two displayed hashes the way a block merkle tree does
+    fn parent(left: &str, right: &str) -> String {
+        let left = sha256d::Hash::from_str(left).unwrap();
+        let right = sha256d::Hash::from_str(right).unwrap();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&left.into_inner());
+        data.extend_from_slice(&right.into_inner());
+
+        sha256d::Hash::hash(&data).to_string()
+    }
+
+    /// Mine a header with the regtest target on `prev_blockhash`
+    fn mine(prev_blockhash: BlockHash, merkle_root: TxMerkleNode) -> BlockHeader {
+        mine_bits(prev_blockhash, merkle_root, 0x207fffff)
+    }
+
+    /// Mine a header with a given target on `prev_blockhash`
+    fn mine_bits(prev_blockhash: BlockHash, merkle_root: TxMerkleNode, bits: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 1,
+            prev_blockhash,
+            merkle_root,
+            time: 1_700_000_000,
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+
+        header
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        let txids = [
+            sha256d::Hash::hash(b"tx0").to_string(),
+            sha256d::Hash::hash(b"tx1").to_string(),
+            sha256d::Hash::hash(b"tx2").to_string(),
+            sha256d::Hash::hash(b"tx3").to_string(),
+        ];
+        let left = parent(&txids[0], &txids[1]);
+        let right = parent(&txids[2], &txids[3]);
+        let root = parent(&left, &right);
+
+        let mut chain = HeaderChain::new(Network::Regtest);
+        let block_hash = chain
+            .add_header(100, mine(BlockHash::all_zeros(), TxMerkleNode::from_str(&root).unwrap()))
+            .unwrap();
+
+        let mut proof = MerkleProof {
+            block_hash: block_hash.to_string(),
+            block_height: 100,
+            position: 2,
+            path: vec![txids[3].clone(), left],
+        };
+
+        assert!(proof.verify(&txids[2], &chain).unwrap());
+        assert!(!proof.verify(&txids[1], &chain).unwrap());
+
+        // The root comes from the chain, so a proof for a block it does not hold is rejected
+        proof.block_height = 101;
+        assert!(proof.verify(&txids[2], &chain).is_err());
+    }
+
+    /// Mine a regtest header on `prev_blockhash`, told apart from its siblings by `label`
+    fn block(chain: &mut HeaderChain, height: u32, prev_blockhash: BlockHash, label: &str) -> Result<BlockHash> {
+        let merkle_root = TxMerkleNode::from_hash(sha256d::Hash::hash(label.as_bytes()));
+        chain.add_header(height, mine(prev_blockhash, merkle_root))
+    }
+
+    #[test]
+    fn test_most_work_chain() {
+        let mut chain = HeaderChain::new(Network::Regtest);
+        let a1 = block(&mut chain, 100, BlockHash::all_zeros(), "a1").unwrap();
+        let a2 = block(&mut chain, 101, a1, "a2").unwrap();
+        let a3 = block(&mut chain, 102, a2, "a3").unwrap();
+
+        // A shorter branch is kept aside
+        let b2 = block(&mut chain, 101, a1, "b2").unwrap();
+        assert_eq!((chain.tip(), chain.header_at(101).unwrap().0), (Some(a3), a2));
+
+        // And becomes the chain once it has more work
+        let b3 = block(&mut chain, 102, b2, "b3").unwrap();
+        assert_eq!(chain.tip(), Some(a3));
+        let b4 = block(&mut chain, 103, b3, "b4").unwrap();
+        assert_eq!((chain.tip(), chain.tip_height()), (Some(b4), Some(103)));
+        assert_eq!((chain.header_at(101).unwrap().0, chain.header_at(102).unwrap().0), (b2, b3));
+
+        // Headers must link to a known parent at the height below
+        assert!(block(&mut chain, 104, BlockHash::all_zeros(), "orphan").is_err());
+        assert!(block(&mut chain, 105, b4, "gap").is_err());
+
+        // And carry the target the network expects, even an easy one
+        let merkle_root = TxMerkleNode::from_hash(sha256d::Hash::hash(b"c5"));
+        assert!(chain.add_header(104, mine_bits(b4, merkle_root, 0x207ffffe)).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+//! Trade approval
+//!
+//! A maker can ask to approve inbound trades before its PSBT is sent to the taker.
+//! Each proposal is handed to the approval handler together with a summary of the
+//! maker PSBT. The answer timeout runs from the moment the handler displays the
+//! proposal, so proposals queued behind another one get the full time. If the
+//! handler does not answer in time, never displays the proposal, or is gone, the
+//! configured auto-accept policy decides.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bitcoin::consensus::Decodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{Address, Network};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+use super::fees::OrderFees;
+use super::Trade;
+
+/// Output of a PSBT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PsbtOutput {
+    /// Address, if the script has one
+    pub address: Option<String>,
+    /// Script pubkey (hex)
+    pub script_pubkey: String,
+    /// Value (satoshis)
+    pub value: u64,
+    /// Whether the output pays the operator or referrer fee address of the order
+    #[serde(default)]
+    pub operator_fee: bool,
+}
+
+/// Summary of a PSBT for display
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PsbtSummary {
+    /// Number of inputs
+    pub inputs: usize,
+    /// Total input value, if every input carries its UTXO (satoshis)
+    pub input_value: Option<u64>,
+    /// Outputs
+    pub outputs: Vec<PsbtOutput>,
+    /// Total output value (satoshis)
+    pub output_value: u64,
+    /// Fee, if the input value is known (satoshis)
+    pub fee: Option<u64>,
+    /// Total paid to the operator and referrer fee addresses (satoshis)
+    #[serde(default)]
+    pub operator_fee: u64,
+}
+
+impl PsbtSummary {
+    /// Summarize a consensus-encoded PSBT
+    pub fn from_bytes(psbt: &[u8], network: Network) -> Result<Self> {
+        let psbt = Psbt::consensus_decode(&mut &psbt[..]).context("Failed to decode PSBT")?;
+
+        // Input values are only known when the PSBT carries the spent outputs
+        let input_value = psbt.inputs.iter().zip(psbt.unsigned_tx.input.iter())
+            .map(|(input, txin)| match (&input.witness_utxo, &input.non_witness_utxo) {
+                (Some(utxo), _) => Some(utxo.value),
+                (None, Some(tx)) => tx.output.get(txin.previous_output.vout as usize).map(|out| out.value),
+                (None, None) => None,
+            })
+            .sum::<Option<u64>>();
+
+        let outputs: Vec<PsbtOutput> = psbt.unsigned_tx.output.iter()
+            .map(|output| PsbtOutput {
+                address: Address::from_script(&output.script_pubkey, network).ok().map(|address| address.to_string()),
+                script_pubkey: hex::encode(output.script_pubkey.as_bytes()),
+                value: output.value,
+                operator_fee: false,
+            })
+            .collect();
+        let output_value = outputs.iter().map(|output| output.value).sum();
+
+        Ok(Self {
+            inputs: psbt.unsigned_tx.input.len(),
+            input_value,
+            fee: input_value.and_then(|value| value.checked_sub(output_value)),
+            outputs,
+            output_value,
+            operator_fee: 0,
+        })
+    }
+}
+
+/// Summarize a consensus-encoded trade PSBT, marking the outputs paying the
+/// operator and referrer fees disclosed with its order
+pub fn describe_psbt(psbt: &[u8], network: Network, fees: Option<&OrderFees>) -> Result<PsbtSummary> {
+    let mut summary = PsbtSummary::from_bytes(psbt, network)?;
+
+    if let Some(fees) = fees {
+        let script_pubkeys: Vec<String> = fees.parse_addresses(network)?
+            .iter()
+            .map(|address| hex::encode(address.script_pubkey().as_bytes()))
+            .collect();
+        for output in summary.outputs.iter_mut().filter(|output| script_pubkeys.contains(&output.script_pubkey)) {
+            output.operator_fee = true;
+            summary.operator_fee += output.value;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Inbound trade waiting for approval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeProposal {
+    /// Proposed trade
+    pub trade: Trade,
+    /// Summary of the maker PSBT, if it could be decoded
+    pub psbt_summary: Option<PsbtSummary>,
+}
+
+/// Request to approve a trade
+#[derive(Debug)]
+pub struct ApprovalRequest {
+    /// Proposal
+    pub proposal: TradeProposal,
+    /// Time the handler has to answer once it displayed the proposal, before the
+    /// auto-accept policy applies
+    pub timeout: Duration,
+    /// Starts the answer timeout
+    displayed: Option<oneshot::Sender<()>>,
+    /// Answer channel; send `true` to accept
+    respond: oneshot::Sender<bool>,
+}
+
+impl ApprovalRequest {
+    /// Start the answer timeout; call when the proposal is shown to whoever answers it
+    pub fn displayed(&mut self) {
+        if let Some(displayed) = self.displayed.take() {
+            let _ = displayed.send(());
+        }
+    }
+
+    /// Accept the trade
+    pub fn accept(self) {
+        let _ = self.respond.send(true);
+    }
+
+    /// Reject the trade
+    pub fn reject(self) {
+        let _ = self.respond.send(false);
+    }
+}
+
+/// Approval policy
+#[derive(Debug, Clone)]
+pub struct ApprovalPolicy {
+    /// Accept trades nobody answered for
+    pub auto_accept: bool,
+    /// Time to wait for an answer once the proposal is displayed
+    pub timeout: Duration,
+    /// Time to wait for the proposal to be displayed
+    pub display_timeout: Duration,
+    /// Bitcoin network, used to show output addresses
+    pub network: Network,
+}
+
+/// Approval handler registered with the trade module
+pub(crate) struct Approver {
+    /// Policy
+    policy: ApprovalPolicy,
+    /// Approval requests
+    requests: mpsc::Sender<ApprovalRequest>,
+}
+
+impl Approver {
+    /// Create a new approver and the receiver of its requests
+    pub(crate) fn new(policy: ApprovalPolicy) -> (Self, mpsc::Receiver<ApprovalRequest>) {
+        let (requests, receiver) = mpsc::channel(16);
+        (Self { policy, requests }, receiver)
+    }
+
+    /// Ask whether a trade may go ahead
+    pub(crate) async fn approve(&self, trade: &Trade, maker_psbt: &[u8]) -> bool {
+        let psbt_summary = match describe_psbt(maker_psbt, self.policy.network, trade.fees.as_ref()) {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                warn!("Failed to summarize PSBT of trade {}: {}", trade.id, e);
+                None
+            }
+        };
+
+        let (displayed, shown) = oneshot::channel();
+        let (respond, mut response) = oneshot::channel();
+        let request = ApprovalRequest {
+            proposal: TradeProposal {
+                trade: trade.clone(),
+                psbt_summary,
+            },
+            timeout: self.policy.timeout,
+            displayed: Some(displayed),
+            respond,
+        };
+
+        if self.requests.send(request).await.is_err() {
+            return self.fallback(trade, "Approval handler is gone");
+        }
+
+        // Wait for the handler to display the proposal, unless it answers right away
+        let wait_display = tokio::time::timeout(self.policy.display_timeout, async {
+            tokio::select! {
+                biased;
+                answer = &mut response => Some(answer),
+                _ = shown => None,
+            }
+        });
+        match wait_display.await {
+            Ok(Some(Ok(approved))) => return approved,
+            Ok(Some(Err(_))) => return self.fallback(trade, "No answer"),
+            Ok(None) => {}
+            Err(_) => return self.fallback(trade, "Proposal was never displayed"),
+        }
+
+        match tokio::time::timeout(self.policy.timeout, response).await {
+            Ok(Ok(approved)) => approved,
+            Ok(Err(_)) | Err(_) => self.fallback(trade, "No answer"),
+        }
+    }
+
+    /// Apply the auto-accept policy to a trade nobody answered for
+    fn fallback(&self, trade: &Trade, reason: &str) -> bool {
+        info!(
+            "{} for trade {}, auto-accept policy {}",
+            reason,
+            trade.id,
+            if self.policy.auto_accept { "accepts it" } else { "rejects it" }
+        );
+        self.policy.auto_accept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use crate::types::Asset;
+    use rust_decimal::Decimal;
+
+    fn trade() -> Trade {
+        Trade::new(
+            OrderId("order".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            Decimal::ONE,
+            Decimal::ONE,
+            None,
+        )
+    }
+
+    fn new_approver(auto_accept: bool, timeout_ms: u64, display_timeout_ms: u64) -> (Approver, mpsc::Receiver<ApprovalRequest>) {
+        Approver::new(ApprovalPolicy {
+            auto_accept,
+            timeout: Duration::from_millis(timeout_ms),
+            display_timeout: Duration::from_millis(display_timeout_ms),
+            network: Network::Regtest,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_timeout_runs_from_display() {
+        let (approver, mut requests) = new_approver(false, 100, 5000);
+
+        let handler = tokio::spawn(async move {
+            let mut request = requests.recv().await.unwrap();
+
+            // Queued for longer than the answer timeout before it is shown
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            request.displayed();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            request.accept();
+        });
+
+        assert!(approver.approve(&trade(), &[]).await);
+        handler.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_undisplayed_proposal_falls_back() {
+        let (approver, mut requests) = new_approver(false, 5000, 100);
+
+        // The handler holds the request without ever showing it
+        let handler = tokio::spawn(async move {
+            let request = requests.recv().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            drop(request);
+        });
+
+        assert!(!approver.approve(&trade(), &[]).await);
+        handler.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_handler_falls_back() {
+        let (approver, requests) = new_approver(false, 100, 100);
+        drop(requests);
+        assert!(!approver.approve(&trade(), &[]).await);
+
+        let (approver, requests) = new_approver(true, 100, 100);
+        drop(requests);
+        assert!(approver.approve(&trade(), &[]).await);
+    }
+}
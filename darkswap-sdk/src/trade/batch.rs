@@ -0,0 +1,177 @@
+//! Batched settlement
+//!
+//! A market order often crosses several orders of the same maker. Instead of
+//! one swap per order, the taker proposes a single trade covering all of them
+//! with an `InitializeBatch` message. The maker answers with one PSBT paying an
+//! output per leg, and the rest of the protocol runs as for a single order, so
+//! the fills share one transaction and its fee. A maker that cannot build
+//! batched PSBTs cancels the trade; its orders can then be taken one by one.
+
+use std::collections::{HashMap, HashSet};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::matching::Fill;
+use crate::orderbook::{Order, OrderId};
+
+use super::TradeError;
+
+/// Order settled by a batched trade
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeLeg {
+    /// Order taken
+    pub order_id: OrderId,
+    /// Amount taken from the order
+    pub amount: Decimal,
+    /// Price of the order
+    pub price: Decimal,
+}
+
+impl From<&Fill> for TradeLeg {
+    fn from(fill: &Fill) -> Self {
+        Self {
+            order_id: fill.order_id.clone(),
+            amount: fill.amount,
+            price: fill.price,
+        }
+    }
+}
+
+/// Get the total amount of a batch
+pub fn total_amount(legs: &[TradeLeg]) -> Decimal {
+    legs.iter().map(|leg| leg.amount).sum()
+}
+
+/// Get the value of a batch in the quote asset
+pub fn notional(legs: &[TradeLeg]) -> Decimal {
+    legs.iter().map(|leg| leg.amount * leg.price).sum()
+}
+
+/// Get the amount-weighted price of a batch
+pub fn average_price(legs: &[TradeLeg]) -> Decimal {
+    let total = total_amount(legs);
+    if total.is_zero() {
+        return Decimal::ZERO;
+    }
+
+    notional(legs) / total
+}
+
+/// Check if two orders can settle in the same transaction
+///
+/// They must have the same maker, pair and side, and disclose the same fees
+/// and referral code, since the trade carries one of each.
+pub fn can_batch(a: &Order, b: &Order) -> bool {
+    a.maker == b.maker
+        && a.base_asset == b.base_asset
+        && a.quote_asset == b.quote_asset
+        && a.side == b.side
+        && a.fees == b.fees
+        && a.referral_code == b.referral_code
+}
+
+/// Check the legs of a batch against the orders they take, in the same order
+pub fn check_legs(legs: &[TradeLeg], orders: &[Order]) -> Result<(), TradeError> {
+    let first = match orders.first() {
+        Some(first) if legs.len() == orders.len() => first,
+        _ => return Err(TradeError::InvalidBatch("Legs do not match the orders".to_string())),
+    };
+
+    let mut seen = HashSet::new();
+    for (leg, order) in legs.iter().zip(orders) {
+        if leg.order_id != order.id || !seen.insert(&leg.order_id) {
+            return Err(TradeError::InvalidBatch(format!("Order {} is taken twice", leg.order_id)));
+        }
+        if !can_batch(first, order) {
+            return Err(TradeError::InvalidBatch(format!("Order {} cannot settle with order {}", order.id, first.id)));
+        }
+        if leg.amount <= Decimal::ZERO || leg.amount > order.amount {
+            return Err(TradeError::InvalidBatch(format!("Invalid amount {} for order {}", leg.amount, order.id)));
+        }
+        if leg.price != order.price {
+            return Err(TradeError::InvalidBatch(format!("Price {} does not match order {}", leg.price, order.id)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Group the fills of a market order into batches, in the order they were matched
+///
+/// Fills of orders that can settle together end up in the same batch; fills of
+/// unknown orders are left out.
+pub fn group_fills(fills: &[Fill], orders: &HashMap<OrderId, Order>) -> Vec<Vec<TradeLeg>> {
+    let mut batches: Vec<(&Order, Vec<TradeLeg>)> = Vec::new();
+
+    for fill in fills {
+        let order = match orders.get(&fill.order_id) {
+            Some(order) => order,
+            None => continue,
+        };
+
+        match batches.iter_mut().find(|(first, _)| can_batch(first, order)) {
+            Some((_, legs)) => legs.push(fill.into()),
+            None => batches.push((order, vec![fill.into()])),
+        }
+    }
+
+    batches.into_iter().map(|(_, legs)| legs).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+    use crate::types::Asset;
+    use rust_decimal_macros::dec;
+
+    fn order(maker: &str, price: Decimal) -> Order {
+        Order::new(maker.to_string(), Asset::Rune(1), Asset::Bitcoin, OrderSide::Sell, dec!(10), price, None)
+    }
+
+    fn fill(order: &Order, amount: Decimal) -> Fill {
+        Fill { order_id: order.id.clone(), price: order.price, amount }
+    }
+
+    #[test]
+    fn test_group_fills() {
+        let a1 = order("maker-a", dec!(1));
+        let b1 = order("maker-b", dec!(1));
+        let a2 = order("maker-a", dec!(2));
+        let fills = vec![fill(&a1, dec!(10)), fill(&b1, dec!(10)), fill(&a2, dec!(5))];
+        let orders: HashMap<OrderId, Order> = [a1.clone(), b1.clone(), a2.clone()]
+            .into_iter()
+            .map(|order| (order.id.clone(), order))
+            .collect();
+
+        let batches = group_fills(&fills, &orders);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0], vec![TradeLeg::from(&fills[0]), TradeLeg::from(&fills[2])]);
+        assert_eq!(batches[1], vec![TradeLeg::from(&fills[1])]);
+
+        assert_eq!(total_amount(&batches[0]), dec!(15));
+        assert_eq!(notional(&batches[0]), dec!(20));
+        assert_eq!(average_price(&batches[0]) * dec!(3), dec!(4));
+    }
+
+    #[test]
+    fn test_check_legs() {
+        let a1 = order("maker-a", dec!(1));
+        let a2 = order("maker-a", dec!(2));
+        let b1 = order("maker-b", dec!(1));
+
+        let legs = vec![TradeLeg::from(&fill(&a1, dec!(10))), TradeLeg::from(&fill(&a2, dec!(5)))];
+        assert!(check_legs(&legs, &[a1.clone(), a2.clone()]).is_ok());
+
+        // Orders of another maker cannot join the batch
+        let legs = vec![TradeLeg::from(&fill(&a1, dec!(10))), TradeLeg::from(&fill(&b1, dec!(5)))];
+        assert!(check_legs(&legs, &[a1.clone(), b1]).is_err());
+
+        // Neither can a leg larger than its order, nor the same order twice
+        let legs = vec![TradeLeg::from(&fill(&a1, dec!(11)))];
+        assert!(check_legs(&legs, &[a1.clone()]).is_err());
+        let legs = vec![TradeLeg::from(&fill(&a1, dec!(1))), TradeLeg::from(&fill(&a1, dec!(1)))];
+        assert!(check_legs(&legs, &[a1.clone(), a1]).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+//! Operator fees
+//!
+//! An operator can charge a maker and a taker fee, in basis points, on the trades
+//! of the orders their node makes. The rates and the fee address are disclosed
+//! with each order, and each side's trade PSBT pays that side's fee to the
+//! address in an extra output. Fees are charged on the bitcoin leg of a trade, so
+//! pairs without one carry none. Both sides check every counterparty PSBT and
+//! refuse one paying the fee address more than the advertised rates allow.
+//!
+//! Orders can carry a referral code naming the community front-end they came
+//! from. When the operator registered the code, the order discloses the
+//! referrer's address and share, and each fee is split between an operator and
+//! a referrer output.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bitcoin::consensus::Decodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{Address, Network};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{batch, Trade, TradeError};
+use crate::types::Asset;
+
+/// Highest fee rate an operator may charge a side (basis points)
+pub const MAX_FEE_BPS: u32 = 1_000;
+
+/// Basis points in a whole
+const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Longest referral code
+pub const MAX_REFERRAL_CODE_LEN: usize = 32;
+
+/// Referrer of orders and its share of the operator fees
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferralShare {
+    /// Referral code
+    pub code: String,
+    /// Address the referrer's share is paid to
+    pub address: String,
+    /// Referrer's share of each fee (basis points of the fee)
+    pub share_bps: u32,
+}
+
+impl ReferralShare {
+    /// Check the code and share, and that the address belongs to the network
+    pub fn validate(&self, network: Network) -> Result<(), TradeError> {
+        validate_referral_code(&self.code)?;
+        parse_fee_address(&self.address, network)?;
+
+        if u64::from(self.share_bps) > BPS_DENOMINATOR {
+            return Err(TradeError::InvalidFee(format!("Referral share of {} bps is above the whole fee", self.share_bps)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Check that a referral code is 1 to `MAX_REFERRAL_CODE_LEN` letters, digits, `-` or `_`
+pub fn validate_referral_code(code: &str) -> Result<(), TradeError> {
+    let valid = !code.is_empty()
+        && code.len() <= MAX_REFERRAL_CODE_LEN
+        && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !valid {
+        return Err(TradeError::InvalidFee(format!("Invalid referral code: {:?}", code)));
+    }
+
+    Ok(())
+}
+
+/// Parse a fee address, checking that it belongs to the network
+pub fn parse_fee_address(address: &str, network: Network) -> Result<Address, TradeError> {
+    let parsed = Address::from_str(address)
+        .map_err(|e| TradeError::InvalidFee(format!("Invalid fee address {}: {}", address, e)))?;
+
+    if !parsed.is_valid_for_network(network) {
+        return Err(TradeError::InvalidFee(format!("{} is not a {} address", address, network)));
+    }
+
+    Ok(parsed)
+}
+
+/// Fee rates and address disclosed with an order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderFees {
+    /// Address the fees are paid to
+    pub address: String,
+    /// Fee the maker pays (basis points)
+    pub maker_fee_bps: u32,
+    /// Fee the taker pays (basis points)
+    pub taker_fee_bps: u32,
+    /// Referrer sharing the fees
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub referral: Option<ReferralShare>,
+}
+
+impl OrderFees {
+    /// Check the rates and that the address belongs to the network
+    pub fn validate(&self, network: Network) -> Result<(), TradeError> {
+        self.parse_address(network)?;
+        if let Some(referral) = &self.referral {
+            referral.validate(network)?;
+        }
+
+        self.check_rates()
+    }
+
+    /// Check that neither side's rate is above `MAX_FEE_BPS` and the referrer's share is within the fee
+    pub fn check_rates(&self) -> Result<(), TradeError> {
+        for rate in [self.maker_fee_bps, self.taker_fee_bps] {
+            if rate > MAX_FEE_BPS {
+                return Err(TradeError::InvalidFee(format!("{} bps is above the {} bps limit", rate, MAX_FEE_BPS)));
+            }
+        }
+
+        if let Some(referral) = &self.referral {
+            if u64::from(referral.share_bps) > BPS_DENOMINATOR {
+                return Err(TradeError::InvalidFee(format!("Referral share of {} bps is above the whole fee", referral.share_bps)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse the fee address, checking that it belongs to the network
+    pub fn parse_address(&self, network: Network) -> Result<Address, TradeError> {
+        parse_fee_address(&self.address, network)
+    }
+
+    /// Parse the fee address and the referrer's, if any
+    pub fn parse_addresses(&self, network: Network) -> Result<Vec<Address>, TradeError> {
+        let mut addresses = vec![self.parse_address(network)?];
+        if let Some(referral) = &self.referral {
+            addresses.push(parse_fee_address(&referral.address, network)?);
+        }
+
+        Ok(addresses)
+    }
+
+    /// Get the fee one side pays on a trade value (satoshis)
+    pub fn fee(&self, value: u64, is_maker: bool) -> Result<u64, TradeError> {
+        let rate = if is_maker { self.maker_fee_bps } else { self.taker_fee_bps };
+        apply_rate(value, rate)
+    }
+
+    /// Get the most a trade of `value` may pay the fee address, both sides together (satoshis)
+    pub fn max_total(&self, value: u64) -> Result<u64, TradeError> {
+        self.fee(value, true)?.checked_add(self.fee(value, false)?)
+            .ok_or_else(|| TradeError::InvalidFee(format!("Fees on {} sats overflow", value)))
+    }
+
+    /// Split a fee into the operator's and the referrer's parts (satoshis)
+    pub fn split(&self, fee: u64) -> Result<(u64, u64), TradeError> {
+        let referrer = match &self.referral {
+            Some(referral) => apply_rate(fee, referral.share_bps)?,
+            None => 0,
+        };
+        let operator = fee.checked_sub(referrer)
+            .ok_or_else(|| TradeError::InvalidFee(format!("Referral share of {} sats is above the fee", referrer)))?;
+
+        Ok((operator, referrer))
+    }
+}
+
+/// Fee output a trade PSBT must carry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeOutput {
+    /// Fee address
+    pub address: String,
+    /// Fee (satoshis)
+    pub amount: u64,
+}
+
+/// Apply a rate in basis points, rounding down
+fn apply_rate(value: u64, rate: u32) -> Result<u64, TradeError> {
+    u64::try_from(u128::from(value) * u128::from(rate) / u128::from(BPS_DENOMINATOR))
+        .map_err(|_| TradeError::InvalidFee(format!("{} bps of {} sats overflows", rate, value)))
+}
+
+/// Get the value of the bitcoin leg of a trade (satoshis); zero without one
+pub fn trade_value(trade: &Trade) -> Result<u64, TradeError> {
+    let btc = match (&trade.base_asset, &trade.quote_asset) {
+        (Asset::Bitcoin, _) => trade.amount,
+        (_, Asset::Bitcoin) if trade.is_batch() => batch::notional(&trade.legs),
+        (_, Asset::Bitcoin) => trade.amount * trade.price,
+        _ => return Ok(0),
+    };
+
+    (btc * Decimal::from(100_000_000)).floor().to_u64()
+        .ok_or_else(|| TradeError::InvalidFee(format!("Invalid bitcoin amount: {}", btc)))
+}
+
+/// Get the fee outputs one side's PSBT must carry: the operator's and the referrer's
+pub fn fee_outputs(trade: &Trade, is_maker: bool) -> Result<Vec<FeeOutput>, TradeError> {
+    let fees = match &trade.fees {
+        Some(fees) => fees,
+        None => return Ok(Vec::new()),
+    };
+
+    // The rates came from the counterparty's order; never pay above the limit
+    fees.check_rates()?;
+
+    let (operator, referrer) = fees.split(fees.fee(trade_value(trade)?, is_maker)?)?;
+    let mut outputs = Vec::new();
+    if operator > 0 {
+        outputs.push(FeeOutput { address: fees.address.clone(), amount: operator });
+    }
+    if let Some(referral) = &fees.referral {
+        if referrer > 0 {
+            outputs.push(FeeOutput { address: referral.address.clone(), amount: referrer });
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// Get the total a consensus-encoded PSBT pays a set of addresses (satoshis)
+pub fn psbt_paid_to(psbt: &[u8], addresses: &[Address]) -> Result<u64, TradeError> {
+    let psbt = Psbt::consensus_decode(&mut &psbt[..])
+        .map_err(|e| TradeError::PsbtError(format!("Failed to decode PSBT: {}", e)))?;
+    let script_pubkeys: Vec<_> = addresses.iter().map(Address::script_pubkey).collect();
+
+    Ok(psbt.unsigned_tx.output.iter()
+        .filter(|output| script_pubkeys.contains(&output.script_pubkey))
+        .map(|output| output.value)
+        .sum())
+}
+
+/// Check that a trade PSBT pays the operator and referrer no more than the
+/// advertised rates allow
+pub fn check_psbt_fee(psbt: &[u8], trade: &Trade, network: Network) -> Result<(), TradeError> {
+    let fees = match &trade.fees {
+        Some(fees) => fees,
+        None => return Ok(()),
+    };
+
+    let paid = psbt_paid_to(psbt, &fees.parse_addresses(network)?)?;
+    let allowed = fees.max_total(trade_value(trade)?)?;
+
+    if paid > allowed {
+        return Err(TradeError::FeeExceeded { paid, allowed });
+    }
+
+    Ok(())
+}
+
+/// Trades and fees of a referral code
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferralSummary {
+    /// Referral code
+    pub code: String,
+    /// Number of trades
+    pub trades: usize,
+    /// Bitcoin traded (satoshis)
+    pub volume: u64,
+    /// Fees paid to the operator (satoshis)
+    pub operator_fees: u64,
+    /// Fees paid to the referrer (satoshis)
+    pub referrer_fees: u64,
+}
+
+/// Summarize the trades of each referral code, ordered by code
+pub fn referral_report<'a, I>(trades: I) -> Vec<ReferralSummary>
+where
+    I: IntoIterator<Item = &'a Trade>,
+{
+    let mut summaries: BTreeMap<&str, ReferralSummary> = BTreeMap::new();
+
+    for trade in trades {
+        let code = match &trade.referral_code {
+            Some(code) => code,
+            None => continue,
+        };
+        let value = trade_value(trade).unwrap_or(0);
+
+        let summary = summaries.entry(code).or_insert_with(|| ReferralSummary {
+            code: code.clone(),
+            ..Default::default()
+        });
+        summary.trades += 1;
+        summary.volume += value;
+
+        if let Some(fees) = &trade.fees {
+            for is_maker in [true, false] {
+                let (operator, referrer) = fees.fee(value, is_maker)
+                    .and_then(|fee| fees.split(fee))
+                    .unwrap_or((0, 0));
+                summary.operator_fees += operator;
+                summary.referrer_fees += referrer;
+            }
+        }
+    }
+
+    summaries.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use bitcoin::consensus::encode::serialize;
+    use bitcoin::{PackedLockTime, Transaction, TxOut};
+    use rust_decimal_macros::dec;
+
+    const FEE_ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    fn trade(fees: Option<OrderFees>) -> Trade {
+        let mut trade = Trade::new(
+            OrderId("order".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            dec!(0.5),
+            dec!(100),
+            None,
+            0,
+        );
+        trade.fees = fees;
+        trade
+    }
+
+    fn fees() -> OrderFees {
+        OrderFees {
+            address: FEE_ADDRESS.to_string(),
+            maker_fee_bps: 10,
+            taker_fee_bps: 25,
+            referral: None,
+        }
+    }
+
+    fn psbt_paying(value: u64) -> Vec<u8> {
+        let address = Address::from_str(FEE_ADDRESS).unwrap();
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut { value, script_pubkey: address.script_pubkey() }],
+        };
+        serialize(&Psbt::from_unsigned_tx(tx).unwrap())
+    }
+
+    #[test]
+    fn test_fee_outputs() {
+        let trade = trade(Some(fees()));
+        assert_eq!(trade_value(&trade).unwrap(), 50_000_000);
+        assert_eq!(fee_outputs(&trade, true).unwrap()[0].amount, 50_000);
+        assert_eq!(fee_outputs(&trade, false).unwrap()[0].amount, 125_000);
+
+        assert!(fee_outputs(&self::trade(None), true).unwrap().is_empty());
+
+        let mut fees = fees();
+        assert!(fees.validate(Network::Regtest).is_ok());
+        assert!(fees.validate(Network::Bitcoin).is_err());
+        fees.taker_fee_bps = MAX_FEE_BPS + 1;
+        assert!(fees.validate(Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn test_taker_never_pays_above_the_limit() {
+        // Rates a counterparty advertised without checking them
+        let mut fees = fees();
+        fees.taker_fee_bps = MAX_FEE_BPS + 1;
+        let trade = trade(Some(fees.clone()));
+        assert!(matches!(fee_outputs(&trade, false), Err(TradeError::InvalidFee(_))));
+
+        // At the limit the taker pays exactly the capped fee
+        fees.taker_fee_bps = MAX_FEE_BPS;
+        let trade = self::trade(Some(fees.clone()));
+        let paid: u64 = fee_outputs(&trade, false).unwrap().iter().map(|output| output.amount).sum();
+        assert_eq!(paid, trade_value(&trade).unwrap() * u64::from(MAX_FEE_BPS) / BPS_DENOMINATOR);
+
+        // A referrer share above the whole fee is refused, not underflowed
+        fees.referral = Some(ReferralShare {
+            code: "front-end_1".to_string(),
+            address: FEE_ADDRESS.to_string(),
+            share_bps: 10_001,
+        });
+        assert!(fee_outputs(&self::trade(Some(fees)), false).is_err());
+
+        assert!(matches!(apply_rate(u64::MAX, u32::MAX), Err(TradeError::InvalidFee(_))));
+    }
+
+    #[test]
+    fn test_check_psbt_fee() {
+        let trade = trade(Some(fees()));
+
+        assert!(check_psbt_fee(&psbt_paying(175_000), &trade, Network::Regtest).is_ok());
+        assert!(matches!(
+            check_psbt_fee(&psbt_paying(175_001), &trade, Network::Regtest),
+            Err(TradeError::FeeExceeded { paid: 175_001, allowed: 175_000 })
+        ));
+
+        // Without advertised fees there is nothing to check
+        assert!(check_psbt_fee(b"not a psbt", &self::trade(None), Network::Regtest).is_ok());
+    }
+
+    #[test]
+    fn test_referral_split_and_report() {
+        let mut fees = fees();
+        fees.referral = Some(ReferralShare {
+            code: "front-end_1".to_string(),
+            address: "bcrt1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qzf4jry".to_string(),
+            share_bps: 2_000,
+        });
+        assert!(fees.validate(Network::Regtest).is_ok());
+
+        let mut trade = trade(Some(fees));
+        trade.referral_code = Some("front-end_1".to_string());
+
+        let outputs = fee_outputs(&trade, false).unwrap();
+        assert_eq!(outputs.iter().map(|output| output.amount).collect::<Vec<_>>(), vec![100_000, 25_000]);
+
+        let report = referral_report(vec![&trade, &trade, &self::trade(None)]);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].trades, 2);
+        assert_eq!(report[0].volume, 100_000_000);
+        assert_eq!(report[0].operator_fees, 280_000);
+        assert_eq!(report[0].referrer_fees, 70_000);
+
+        assert!(validate_referral_code("ok-code_9").is_ok());
+        assert!(validate_referral_code("").is_err());
+        assert!(validate_referral_code("no spaces").is_err());
+    }
+}
@@ -0,0 +1,365 @@
+//! Trade concurrency limits
+//!
+//! Each trade in flight holds wallet UTXOs until it settles or is canceled, and
+//! a burst of proposals can commit more of a small wallet than it holds. The
+//! limits bound the trades in flight, in total and with any one counterparty.
+//! Own trades past a limit fail with `TradeError::LimitReached`. Inbound
+//! proposals past a limit are canceled with a reason starting with
+//! [`LIMIT_REACHED`], or, under [`LimitPolicy::Queue`], held until a trade
+//! finishes.
+//!
+//! A trade is admitted by taking a [`Reservation`], which counts against the
+//! limits until the trade is created, so concurrent proposals cannot all pass
+//! the check before any of them is stored.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::batch::TradeLeg;
+use super::{Trade, TradeError, TradeState};
+use crate::types::TradeId;
+
+/// Code starting the cancel reason of a proposal refused for a limit
+pub const LIMIT_REACHED: &str = "trade_limit_reached";
+
+/// Handling of inbound proposals past a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitPolicy {
+    /// Cancel the proposal
+    Reject,
+    /// Hold the proposal until a trade finishes, up to `max_queued` proposals
+    Queue,
+}
+
+/// Trade concurrency limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TradeLimits {
+    /// Trades in flight at once (0 for no limit)
+    pub max_in_flight: usize,
+    /// Trades in flight with one counterparty (0 for no limit)
+    pub max_per_peer: usize,
+    /// Handling of inbound proposals past a limit
+    pub policy: LimitPolicy,
+    /// Proposals held at once under `LimitPolicy::Queue`
+    pub max_queued: usize,
+    /// Proposals held at once from one taker (0 for no limit)
+    pub max_queued_per_peer: usize,
+    /// Time a proposal is held before it is canceled (seconds)
+    pub queue_timeout: u64,
+}
+
+impl Default for TradeLimits {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 0,
+            max_per_peer: 0,
+            policy: LimitPolicy::Reject,
+            max_queued: 16,
+            max_queued_per_peer: 2,
+            queue_timeout: 60,
+        }
+    }
+}
+
+/// Check if a trade still holds wallet funds
+fn in_flight(trade: &Trade) -> bool {
+    !matches!(
+        trade.state,
+        TradeState::Completed | TradeState::Failed | TradeState::Canceled | TradeState::Expired
+    )
+}
+
+impl TradeLimits {
+    /// Check if any limit is set
+    pub fn is_limited(&self) -> bool {
+        self.max_in_flight > 0 || self.max_per_peer > 0
+    }
+
+    /// Check that one more trade with `peer_id` stays within the limits
+    ///
+    /// `reserved` holds the counterparties of the trades admitted but not created yet.
+    pub fn check(
+        &self,
+        trades: &HashMap<TradeId, Trade>,
+        reserved: &HashMap<TradeId, String>,
+        peer_id: &str,
+    ) -> Result<(), TradeError> {
+        if !self.is_limited() {
+            return Ok(());
+        }
+
+        let in_flight: Vec<&Trade> = trades.values().filter(|trade| in_flight(trade)).collect();
+        let pending: Vec<&String> = reserved
+            .iter()
+            .filter(|(trade_id, _)| !trades.contains_key(*trade_id))
+            .map(|(_, peer)| peer)
+            .collect();
+        if self.max_in_flight > 0 && in_flight.len() + pending.len() >= self.max_in_flight {
+            return Err(TradeError::LimitReached { scope: "concurrent", limit: self.max_in_flight });
+        }
+
+        if self.max_per_peer > 0 {
+            let with_peer = in_flight
+                .iter()
+                .filter(|trade| trade.maker_peer_id == peer_id || trade.taker_peer_id == peer_id)
+                .count()
+                + pending.iter().filter(|peer| peer.as_str() == peer_id).count();
+            if with_peer >= self.max_per_peer {
+                return Err(TradeError::LimitReached { scope: "per-peer", limit: self.max_per_peer });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Trades admitted within the limits but not created yet
+#[derive(Debug, Clone, Default)]
+pub struct Reservations {
+    /// Counterparty of each admitted trade
+    slots: Arc<Mutex<HashMap<TradeId, String>>>,
+}
+
+impl Reservations {
+    /// Check the limits and admit a trade with `peer_id` in one step
+    ///
+    /// The trade counts against the limits until the reservation is dropped,
+    /// which must happen after the trade is stored with the other trades.
+    pub fn reserve(
+        &self,
+        limits: &TradeLimits,
+        trades: &HashMap<TradeId, Trade>,
+        trade_id: &TradeId,
+        peer_id: &str,
+    ) -> Result<Reservation, TradeError> {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        limits.check(trades, &slots, peer_id)?;
+        slots.insert(trade_id.clone(), peer_id.to_string());
+
+        Ok(Reservation {
+            slots: self.slots.clone(),
+            trade_id: trade_id.clone(),
+        })
+    }
+
+    /// Check that one more trade with `peer_id` stays within the limits, without admitting it
+    pub fn check(&self, limits: &TradeLimits, trades: &HashMap<TradeId, Trade>, peer_id: &str) -> Result<(), TradeError> {
+        limits.check(trades, &self.slots.lock().unwrap_or_else(|e| e.into_inner()), peer_id)
+    }
+}
+
+/// Slot within the limits held for a trade until it is created
+#[derive(Debug)]
+pub struct Reservation {
+    /// Reserved slots
+    slots: Arc<Mutex<HashMap<TradeId, String>>>,
+    /// Trade the slot is held for
+    trade_id: TradeId,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.trade_id);
+    }
+}
+
+/// Inbound proposal held for a free slot
+#[derive(Debug, Clone)]
+pub struct QueuedProposal {
+    /// Trade ID chosen by the taker
+    pub trade_id: TradeId,
+    /// Orders taken
+    pub legs: Vec<TradeLeg>,
+    /// Taker payout address
+    pub payout_address: Option<String>,
+    /// Proof that the taker controls its payout address
+    pub payout_proof: Option<String>,
+    /// Taker
+    pub peer_id: String,
+    /// Time the proposal was queued (unix seconds)
+    pub queued_at: u64,
+}
+
+/// Inbound proposals held for a free slot, oldest first
+#[derive(Debug, Default)]
+pub struct ProposalQueue {
+    /// Proposals
+    proposals: VecDeque<QueuedProposal>,
+}
+
+impl ProposalQueue {
+    /// Queue a proposal, returning false if `capacity` proposals, or `per_peer`
+    /// from its taker (0 for no limit), are already held
+    ///
+    /// A proposal already held is not queued twice.
+    pub fn push(&mut self, proposal: QueuedProposal, capacity: usize, per_peer: usize) -> bool {
+        if self.proposals.iter().any(|queued| queued.trade_id == proposal.trade_id) {
+            return true;
+        }
+        if self.proposals.len() >= capacity {
+            return false;
+        }
+        if per_peer > 0 && self.proposals.iter().filter(|queued| queued.peer_id == proposal.peer_id).count() >= per_peer {
+            return false;
+        }
+
+        self.proposals.push_back(proposal);
+        true
+    }
+
+    /// Take the proposals held for `timeout` seconds at `now`
+    pub fn take_expired(&mut self, now: u64, timeout: u64) -> Vec<QueuedProposal> {
+        let (expired, held): (Vec<_>, VecDeque<_>) = self
+            .proposals
+            .drain(..)
+            .partition(|proposal| proposal.queued_at.saturating_add(timeout) <= now);
+        self.proposals = held;
+
+        expired
+    }
+
+    /// Take the oldest proposal that fits the limits
+    ///
+    /// A proposal from a peer at its own limit does not hold up the others.
+    pub fn pop_ready(
+        &mut self,
+        limits: &TradeLimits,
+        trades: &HashMap<TradeId, Trade>,
+        reservations: &Reservations,
+    ) -> Option<QueuedProposal> {
+        let position = self
+            .proposals
+            .iter()
+            .position(|proposal| reservations.check(limits, trades, &proposal.peer_id).is_ok())?;
+
+        self.proposals.remove(position)
+    }
+
+    /// Get the number of proposals held
+    pub fn len(&self) -> usize {
+        self.proposals.len()
+    }
+
+    /// Check if no proposal is held
+    pub fn is_empty(&self) -> bool {
+        self.proposals.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use crate::types::Asset;
+    use rust_decimal::Decimal;
+
+    fn trade(maker: &str, taker: &str, state: TradeState) -> Trade {
+        let mut trade = Trade::new(
+            OrderId("order".to_string()),
+            maker.to_string(),
+            taker.to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            Decimal::ONE,
+            Decimal::ONE,
+            None,
+            0,
+        );
+        trade.state = state;
+        trade
+    }
+
+    fn proposal(trade_id: &str, peer_id: &str, queued_at: u64) -> QueuedProposal {
+        QueuedProposal {
+            trade_id: TradeId(trade_id.to_string()),
+            legs: Vec::new(),
+            payout_address: None,
+            payout_proof: None,
+            peer_id: peer_id.to_string(),
+            queued_at,
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = TradeLimits { max_in_flight: 3, max_per_peer: 1, ..TradeLimits::default() };
+        let mut trades = HashMap::new();
+        for trade in [
+            trade("me", "alice", TradeState::MakerPsbtSent),
+            trade("me", "bob", TradeState::Completed),
+        ] {
+            trades.insert(trade.id.clone(), trade);
+        }
+
+        let reserved = HashMap::new();
+        assert!(matches!(limits.check(&trades, &reserved, "alice"), Err(TradeError::LimitReached { scope: "per-peer", limit: 1 })));
+        // Finished trades do not count
+        assert!(limits.check(&trades, &reserved, "bob").is_ok());
+
+        let busy = trade("me", "carol", TradeState::Created);
+        trades.insert(busy.id.clone(), busy);
+        let busy = trade("dave", "me", TradeState::Created);
+        trades.insert(busy.id.clone(), busy);
+        assert!(matches!(limits.check(&trades, &reserved, "bob"), Err(TradeError::LimitReached { scope: "concurrent", limit: 3 })));
+
+        assert!(TradeLimits::default().check(&trades, &reserved, "alice").is_ok());
+    }
+
+    #[test]
+    fn test_reservations() {
+        let limits = TradeLimits { max_in_flight: 2, max_per_peer: 1, ..TradeLimits::default() };
+        let mut trades = HashMap::new();
+        let reservations = Reservations::default();
+
+        // A reserved slot counts before the trade exists
+        let alice = reservations.reserve(&limits, &trades, &TradeId("a".to_string()), "alice").unwrap();
+        assert!(reservations.reserve(&limits, &trades, &TradeId("b".to_string()), "alice").is_err());
+        let bob = reservations.reserve(&limits, &trades, &TradeId("c".to_string()), "bob").unwrap();
+        assert!(matches!(
+            reservations.check(&limits, &trades, "carol"),
+            Err(TradeError::LimitReached { scope: "concurrent", limit: 2 })
+        ));
+
+        // Once created, the trade is only counted once
+        let mut created = trade("me", "alice", TradeState::Created);
+        created.id = TradeId("a".to_string());
+        trades.insert(created.id.clone(), created);
+        drop(alice);
+        assert!(reservations.check(&limits, &trades, "carol").is_err());
+
+        // A dropped reservation frees its slot
+        drop(bob);
+        assert!(reservations.check(&limits, &trades, "carol").is_ok());
+    }
+
+    #[test]
+    fn test_queue() {
+        let limits = TradeLimits { max_per_peer: 1, ..TradeLimits::default() };
+        let mut trades = HashMap::new();
+        let busy = trade("me", "alice", TradeState::MakerPsbtSent);
+        trades.insert(busy.id.clone(), busy);
+
+        let reservations = Reservations::default();
+
+        let mut queue = ProposalQueue::default();
+        assert!(queue.push(proposal("a", "alice", 100), 2, 1));
+        assert!(queue.push(proposal("a", "alice", 100), 2, 1));
+        // One proposal per taker is held
+        assert!(!queue.push(proposal("d", "alice", 105), 2, 1));
+        assert!(queue.push(proposal("b", "bob", 110), 2, 1));
+        assert!(!queue.push(proposal("c", "carol", 120), 2, 1));
+        assert_eq!(queue.len(), 2);
+
+        // Bob is served while Alice is at her limit
+        assert_eq!(queue.pop_ready(&limits, &trades, &reservations).unwrap().trade_id.0, "b");
+        assert!(queue.pop_ready(&limits, &trades, &reservations).is_none());
+
+        assert!(queue.take_expired(159, 60).is_empty());
+        assert_eq!(queue.take_expired(160, 60).len(), 1);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,275 @@
+//! Trade and transaction memos
+//!
+//! Labels and notes attached to trades and transactions for bookkeeping. Memos are
+//! kept in a local file and are never part of any message sent to peers.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::DocumentFile;
+use serde::{Deserialize, Serialize};
+
+use super::{Trade, TradeState};
+use crate::types::{Asset, TradeId};
+
+/// Memo attached to a trade or transaction
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Memo {
+    /// Labels
+    pub labels: Vec<String>,
+    /// Free-form note
+    pub note: Option<String>,
+    /// Last update (unix seconds)
+    pub updated_at: u64,
+}
+
+impl Memo {
+    /// Create a memo, updated at `now` (unix seconds)
+    pub fn new(labels: Vec<String>, note: Option<String>, now: u64) -> Self {
+        Self {
+            labels,
+            note,
+            updated_at: now,
+        }
+    }
+
+    /// Check if the memo has neither labels nor a note
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.note.as_deref().map_or(true, str::is_empty)
+    }
+
+    /// Check if the memo has a label, ignoring case
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|own| own.eq_ignore_ascii_case(label))
+    }
+
+    /// Check if a lowercase search term occurs in the labels or note
+    fn matches(&self, term: &str) -> bool {
+        self.labels.iter().any(|label| label.to_lowercase().contains(term))
+            || self.note.as_deref().map_or(false, |note| note.to_lowercase().contains(term))
+    }
+}
+
+/// Persisted memos
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MemoFile {
+    /// Memos by trade
+    trades: HashMap<TradeId, Memo>,
+    /// Memos by transaction ID
+    transactions: HashMap<String, Memo>,
+}
+
+/// Memo store
+#[derive(Debug, Default)]
+pub struct MemoStore {
+    /// File the memos are persisted to; `None` keeps them in memory only
+    file: Option<DocumentFile>,
+    /// Memos
+    memos: MemoFile,
+}
+
+impl MemoStore {
+    /// Create an in-memory memo store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a memo store persisted at `path`, loading it if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_file(DocumentFile::plain(path))
+    }
+
+    /// Open a memo store persisted in `file`, sealed if storage is encrypted
+    pub fn open_file(file: DocumentFile) -> Result<Self> {
+        let memos: MemoFile = file.load()
+            .with_context(|| format!("Failed to read memo store {}", file.path().display()))?
+            .unwrap_or_default();
+
+        Ok(Self {
+            file: Some(file),
+            memos,
+        })
+    }
+
+    /// Save the store to disk, if it is persisted
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        file.save(&self.memos)
+            .with_context(|| format!("Failed to write memo store {}", file.path().display()))?;
+
+        Ok(())
+    }
+
+    /// Set the memo of a trade; an empty memo removes it
+    pub fn set_trade_memo(&mut self, trade_id: &TradeId, memo: Memo) {
+        if memo.is_empty() {
+            self.memos.trades.remove(trade_id);
+        } else {
+            self.memos.trades.insert(trade_id.clone(), memo);
+        }
+    }
+
+    /// Set the memo of a transaction; an empty memo removes it
+    pub fn set_transaction_memo(&mut self, txid: &str, memo: Memo) {
+        if memo.is_empty() {
+            self.memos.transactions.remove(txid);
+        } else {
+            self.memos.transactions.insert(txid.to_string(), memo);
+        }
+    }
+
+    /// Get the memo of a trade
+    pub fn trade_memo(&self, trade_id: &TradeId) -> Option<&Memo> {
+        self.memos.trades.get(trade_id)
+    }
+
+    /// Get the memo of a transaction
+    pub fn transaction_memo(&self, txid: &str) -> Option<&Memo> {
+        self.memos.transactions.get(txid)
+    }
+
+    /// Attach memos to trades and keep the entries matching `filter`
+    pub fn history(&self, trades: Vec<Trade>, filter: &TradeHistoryFilter) -> Vec<TradeHistoryEntry> {
+        trades
+            .into_iter()
+            .map(|trade| self.entry(trade))
+            .filter(|entry| filter.matches(entry))
+            .collect()
+    }
+
+    /// Attach the memos of a trade and its transaction to it
+    pub fn entry(&self, trade: Trade) -> TradeHistoryEntry {
+        TradeHistoryEntry {
+            memo: self.trade_memo(&trade.id).cloned(),
+            transaction_memo: trade.txid.as_deref().and_then(|txid| self.transaction_memo(txid)).cloned(),
+            trade,
+        }
+    }
+}
+
+/// Trade history filter
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeHistoryFilter {
+    /// Base asset
+    pub base_asset: Option<Asset>,
+    /// Quote asset
+    pub quote_asset: Option<Asset>,
+    /// Trade state
+    pub state: Option<TradeState>,
+    /// Label on the trade or its transaction
+    pub label: Option<String>,
+    /// Text in the memos, trade ID or transaction ID
+    pub query: Option<String>,
+}
+
+impl TradeHistoryFilter {
+    /// Check if an entry matches the filter
+    pub fn matches(&self, entry: &TradeHistoryEntry) -> bool {
+        let trade = &entry.trade;
+        let memos = || entry.memo.iter().chain(entry.transaction_memo.iter());
+
+        if self.base_asset.as_ref().map_or(false, |asset| *asset != trade.base_asset)
+            || self.quote_asset.as_ref().map_or(false, |asset| *asset != trade.quote_asset)
+            || self.state.map_or(false, |state| state != trade.state)
+        {
+            return false;
+        }
+
+        if let Some(label) = &self.label {
+            if !memos().any(|memo| memo.has_label(label)) {
+                return false;
+            }
+        }
+
+        if let Some(query) = &self.query {
+            let term = query.to_lowercase();
+            let in_ids = trade.id.0.to_lowercase().contains(&term)
+                || trade.txid.as_deref().map_or(false, |txid| txid.to_lowercase().contains(&term));
+            if !in_ids && !memos().any(|memo| memo.matches(&term)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Trade with its memos
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeHistoryEntry {
+    /// Trade
+    pub trade: Trade,
+    /// Memo of the trade
+    pub memo: Option<Memo>,
+    /// Memo of the settlement transaction
+    pub transaction_memo: Option<Memo>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use rust_decimal::Decimal;
+
+    fn trade(quote_asset: Asset) -> Trade {
+        Trade::new(
+            OrderId("order".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            quote_asset,
+            Decimal::ONE,
+            Decimal::ONE,
+            None,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_history_filter() {
+        let mut store = MemoStore::new();
+        let mut settled = trade(Asset::Rune(1));
+        settled.txid = Some("abcd".to_string());
+        let other = trade(Asset::Rune(2));
+
+        store.set_trade_memo(&settled.id, Memo::new(vec!["Taxes".to_string()], Some("Q3 rebalance".to_string()), 0));
+        store.set_transaction_memo("abcd", Memo::new(vec!["cold-storage".to_string()], None, 0));
+
+        let trades = vec![settled.clone(), other.clone()];
+        let by_label = TradeHistoryFilter { label: Some("taxes".to_string()), ..Default::default() };
+        let history = store.history(trades.clone(), &by_label);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].trade.id, settled.id);
+        assert!(history[0].transaction_memo.is_some());
+
+        let by_query = TradeHistoryFilter { query: Some("REBALANCE".to_string()), ..Default::default() };
+        assert_eq!(store.history(trades.clone(), &by_query).len(), 1);
+
+        let by_pair = TradeHistoryFilter { quote_asset: Some(Asset::Rune(2)), ..Default::default() };
+        let history = store.history(trades, &by_pair);
+        assert_eq!(history.len(), 1);
+        assert!(history[0].memo.is_none());
+    }
+
+    #[test]
+    fn test_persist_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memos.json");
+        let trade_id = TradeId("trade-1".to_string());
+
+        let mut store = MemoStore::open(&path).unwrap();
+        store.set_trade_memo(&trade_id, Memo::new(Vec::new(), Some("paid invoice 17".to_string()), 0));
+        store.save().unwrap();
+
+        let mut store = MemoStore::open(&path).unwrap();
+        assert_eq!(store.trade_memo(&trade_id).unwrap().note.as_deref(), Some("paid invoice 17"));
+
+        store.set_trade_memo(&trade_id, Memo::new(Vec::new(), None, 0));
+        assert!(store.trade_memo(&trade_id).is_none());
+    }
+}
@@ -1,16 +1,63 @@
+// A panic in the trade protocol would abort every trade in flight
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+
+pub mod approval;
+pub mod batch;
+pub mod fees;
+pub mod limits;
+pub mod memo;
+pub mod outbox;
+pub mod private;
+pub mod protocol;
+pub mod psbt;
+pub mod query;
+pub mod receipt;
+pub mod refund;
+pub mod replay;
+pub mod rfq;
+pub mod settlement;
+pub mod ticker;
+pub mod timeline;
+pub mod transcript;
+pub mod wal;
+
 use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use log::info;
+use bitcoin::Network as BitcoinNetwork;
+use darkswap_support::envelope;
+use darkswap_support::storage::{Storage, StorageExt};
+use log::{info, warn};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc, RwLock};
+use crate::analytics::{self, SessionAnalytics};
+use crate::audit::{AuditLog, Observation};
+use crate::clock::{self, Clock};
 use crate::p2p::P2PNetwork as Network;
-use crate::orderbook::{Order, OrderId, OrderSide, OrderStatus};
+use crate::orderbook::{Order, OrderId, OrderSide, OrderStatus, RoutingHints};
+use crate::orderbook::cosign::OrderCosigner;
+use crate::spv::MerkleProof;
 use crate::types::{Asset, Event, TradeId};
+use crate::wallet::reserve::{btc_commitment, FeeReserveGuard};
+
+use approval::{ApprovalPolicy, ApprovalRequest, Approver};
+use batch::TradeLeg;
+use fees::{FeeOutput, OrderFees};
+use limits::{LimitPolicy, ProposalQueue, QueuedProposal, Reservations, TradeLimits, LIMIT_REACHED};
+use outbox::{Outbox, OutboxRetry, TRADE_PROTOCOL};
+use private::PrivateOrders;
+use protocol::{ReplayError, ReplayGuard, SignedTradeMessage};
+use psbt::VerificationPool;
+use query::{TradeIndex, TradePage, TradeQuery};
+use wal::{TradeWal, WalMessage, WalRecovery};
+use receipt::{ReceiptBody, ReceiptSignature, TradeReceipt};
+use replay::{TraceRecorder, TradeTrace};
+use settlement::{PayoutOutput, Settlement, PAYOUT_NAMESPACE, PAYOUT_PROOF_REQUIRED};
+use timeline::{TimelineEntry, TradeStage, TradeTimeline};
 
 /// Trade module
 pub struct TradeModule {
@@ -20,6 +67,12 @@ pub struct TradeModule {
     /// Trades
     trades: Arc<RwLock<HashMap<TradeId, Trade>>>,
     
+    /// Trade index, updated with every trade added
+    index: Arc<RwLock<TradeIndex>>,
+    
+    /// Receipt signatures by trade
+    receipt_signatures: Arc<RwLock<HashMap<TradeId, Vec<ReceiptSignature>>>>,
+    
     /// Trade topic
     trade_topic: String,
     
@@ -34,6 +87,75 @@ pub struct TradeModule {
     
     /// Alkanes executor
     alkanes_executor: Arc<dyn AlkanesExecutor>,
+    
+    /// Approval handler for inbound trades
+    approver: RwLock<Option<Approver>>,
+    
+    /// Accept inbound trades when no approval handler is registered
+    auto_accept: bool,
+    
+    /// Recorder of the messages exchanged, for replay tests
+    recorder: RwLock<Option<TraceRecorder>>,
+    
+    /// Bitcoin network payout addresses must belong to
+    bitcoin_network: BitcoinNetwork,
+    
+    /// Payout addresses of own orders
+    payout_addresses: RwLock<HashMap<OrderId, String>>,
+    
+    /// Storage the payout addresses are kept in, if they outlive the process
+    payout_storage: Option<Arc<dyn Storage>>,
+    
+    /// Storage the trades are kept in, if they outlive the process
+    trade_storage: Option<Arc<dyn Storage>>,
+    
+    /// BIP-322 proofs of own payout addresses, by address
+    payout_proofs: RwLock<HashMap<String, String>>,
+    
+    /// Refuse to pay counterparty payout addresses without a proof of ownership
+    require_payout_proof: bool,
+    
+    /// Orders outside the public orderbook, from RFQ quotes and private groups
+    private_orders: RwLock<PrivateOrders>,
+    
+    /// Nonces of the signed messages accepted recently
+    replay_guard: RwLock<ReplayGuard>,
+    
+    /// Fee reserve inbound trades must leave untouched
+    fee_guard: Option<Arc<FeeReserveGuard>>,
+    
+    /// Co-signer own orders must have been approved by before they are traded
+    cosigner: Option<Arc<OrderCosigner>>,
+    
+    /// Time source of trade timestamps, trade timeouts and the replay window
+    clock: Arc<dyn Clock>,
+    
+    /// Time a trade has to be signed after it was created (seconds); 0 never expires trades
+    trade_timeout: u64,
+    
+    /// Log of observed trade messages; a module with a log never takes part in trades
+    audit: Option<Arc<AuditLog>>,
+    
+    /// Bounds on the trades in flight
+    limits: TradeLimits,
+    
+    /// Trades admitted within the limits but not stored yet
+    reservations: Reservations,
+    
+    /// Inbound proposals waiting for a trade to finish
+    queue: RwLock<ProposalQueue>,
+    
+    /// Session analytics, if they are recorded
+    analytics: Option<Arc<SessionAnalytics>>,
+    
+    /// Pool counterparty PSBT signatures are checked on
+    verification_pool: VerificationPool,
+    
+    /// Write-ahead log of trade messages, if they are logged
+    wal: Option<Arc<TradeWal>>,
+    
+    /// Trade messages waiting for an acknowledgment, if they are retried
+    outbox: Option<Arc<Outbox>>,
 }
 
 /// Trade state
@@ -67,6 +189,29 @@ pub enum TradeState {
     Expired,
 }
 
+/// Outcome of a PSBT signature check made without the trades locked
+struct VerifiedPsbt {
+    /// State of the trade when the check started
+    state: TradeState,
+    
+    /// Outcome of the check
+    result: Result<()>,
+}
+
+impl VerifiedPsbt {
+    /// Refuse the message if the trade moved on while its PSBT was checked
+    fn check_unchanged(&self, trade: &Trade) -> Result<(), TradeError> {
+        if trade.state != self.state {
+            return Err(TradeError::InvalidState(format!(
+                "Trade {} changed from {:?} to {:?} while its PSBT was verified",
+                trade.id, self.state, trade.state
+            )));
+        }
+        
+        Ok(())
+    }
+}
+
 /// Trade
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
@@ -111,10 +256,41 @@ pub struct Trade {
     
     /// Predicate ID
     pub predicate_id: Option<String>,
+    
+    /// Settlement instructions
+    #[serde(default)]
+    pub settlement: Settlement,
+    
+    /// Operator fees disclosed with the order
+    #[serde(default)]
+    pub fees: Option<OrderFees>,
+    
+    /// Referral code of the order
+    #[serde(default)]
+    pub referral_code: Option<String>,
+    
+    /// Completion time (unix seconds)
+    #[serde(default)]
+    pub completed_at: Option<u64>,
+    
+    /// Creation time (unix seconds)
+    #[serde(default)]
+    pub created_at: u64,
+    
+    /// Orders settled together by a batched trade; empty for a trade on one order
+    ///
+    /// `order_id` is then the first leg's order, `amount` the total and `price`
+    /// the amount-weighted price of the legs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub legs: Vec<TradeLeg>,
+    
+    /// Times the trade reached each stage, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timeline: Vec<TimelineEntry>,
 }
 
 impl Trade {
-    /// Create a new trade
+    /// Create a new trade, created at `created_at` (unix seconds)
     pub fn new(
         order_id: OrderId,
         maker_peer_id: String,
@@ -124,6 +300,7 @@ impl Trade {
         amount: Decimal,
         price: Decimal,
         predicate_id: Option<String>,
+        created_at: u64,
     ) -> Self {
         Self {
             id: TradeId(format!("trade-{}", uuid::Uuid::new_v4())),
@@ -140,13 +317,43 @@ impl Trade {
             final_psbt: None,
             txid: None,
             predicate_id,
+            settlement: Settlement::default(),
+            fees: None,
+            referral_code: None,
+            completed_at: None,
+            created_at,
+            legs: Vec::new(),
+            timeline: Vec::new(),
         }
     }
     
-    /// Update trade state
-    pub fn update_state(&mut self, state: TradeState) {
+    /// Check if the trade settles several orders in one transaction
+    pub fn is_batch(&self) -> bool {
+        !self.legs.is_empty()
+    }
+    
+    /// Update trade state at `now` (unix seconds)
+    pub fn update_state_at(&mut self, state: TradeState, now: u64) {
+        self.update_state_at_ms(state, now.saturating_mul(1000));
+    }
+    
+    /// Update trade state at `now_ms` (unix milliseconds)
+    pub fn update_state_at_ms(&mut self, state: TradeState, now_ms: u64) {
+        if state == TradeState::Completed && self.completed_at.is_none() {
+            self.completed_at = Some(now_ms / 1000);
+        }
+        if let Some(stage) = TradeStage::of_state(state) {
+            self.record_stage(stage, now_ms);
+        }
         self.state = state;
     }
+    
+    /// Record that the trade reached a stage at `at_ms` (unix milliseconds), unless it already had
+    pub fn record_stage(&mut self, stage: TradeStage, at_ms: u64) {
+        if !self.timeline.iter().any(|entry| entry.stage == stage) {
+            self.timeline.push(TimelineEntry { stage, at_ms });
+        }
+    }
 }
 
 /// Trade message
@@ -162,6 +369,31 @@ pub enum TradeMessage {
         
         /// Amount
         amount: Decimal,
+        
+        /// Taker payout address
+        #[serde(default)]
+        payout_address: Option<String>,
+        
+        /// BIP-322 proof that the taker controls its payout address
+        #[serde(default)]
+        payout_proof: Option<String>,
+    },
+    
+    /// Initialize a trade settling several orders of the maker in one transaction
+    InitializeBatch {
+        /// Trade ID
+        trade_id: TradeId,
+        
+        /// Orders taken, with the amount taken from each
+        legs: Vec<TradeLeg>,
+        
+        /// Taker payout address
+        #[serde(default)]
+        payout_address: Option<String>,
+        
+        /// BIP-322 proof that the taker controls its payout address
+        #[serde(default)]
+        payout_proof: Option<String>,
     },
     
     /// Send PSBT
@@ -171,6 +403,14 @@ pub enum TradeMessage {
         
         /// PSBT
         psbt: Vec<u8>,
+        
+        /// Payout address of the sender; only the maker's is used
+        #[serde(default)]
+        payout_address: Option<String>,
+        
+        /// BIP-322 proof that the sender controls its payout address
+        #[serde(default)]
+        payout_proof: Option<String>,
     },
     
     /// Sign PSBT
@@ -199,6 +439,54 @@ pub enum TradeMessage {
         /// Reason
         reason: String,
     },
+    
+    /// Accept the receipt of a completed trade
+    AcceptReceipt {
+        /// Trade ID
+        trade_id: TradeId,
+        
+        /// Signature over the receipt body
+        signature: ReceiptSignature,
+    },
+    
+    /// Acknowledge the delivery of a message
+    Ack {
+        /// Trade ID
+        trade_id: TradeId,
+        
+        /// Nonce of the envelope delivered
+        nonce: u64,
+    },
+}
+
+impl TradeMessage {
+    /// Get the trade the message belongs to
+    pub fn trade_id(&self) -> &TradeId {
+        match self {
+            TradeMessage::Initialize { trade_id, .. }
+            | TradeMessage::InitializeBatch { trade_id, .. }
+            | TradeMessage::SendPsbt { trade_id, .. }
+            | TradeMessage::SignPsbt { trade_id, .. }
+            | TradeMessage::Broadcast { trade_id, .. }
+            | TradeMessage::Cancel { trade_id, .. }
+            | TradeMessage::AcceptReceipt { trade_id, .. }
+            | TradeMessage::Ack { trade_id, .. } => trade_id,
+        }
+    }
+    
+    /// Get the message kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TradeMessage::Initialize { .. } => "Initialize",
+            TradeMessage::InitializeBatch { .. } => "InitializeBatch",
+            TradeMessage::SendPsbt { .. } => "SendPsbt",
+            TradeMessage::SignPsbt { .. } => "SignPsbt",
+            TradeMessage::Broadcast { .. } => "Broadcast",
+            TradeMessage::Cancel { .. } => "Cancel",
+            TradeMessage::AcceptReceipt { .. } => "AcceptReceipt",
+            TradeMessage::Ack { .. } => "Ack",
+        }
+    }
 }
 
 /// Trade error
@@ -215,12 +503,54 @@ pub enum TradeError {
     /// PSBT error
     #[error("PSBT error: {0}")]
     PsbtError(String),
+    
+    /// Invalid payout address
+    #[error("Invalid payout address: {0}")]
+    InvalidPayoutAddress(String),
+    
+    /// Payout address ownership is required but not proven
+    #[error("Payout address not proven: {0}")]
+    PayoutProof(String),
+    
+    /// Invalid operator fee
+    #[error("Invalid operator fee: {0}")]
+    InvalidFee(String),
+    
+    /// Invalid batched trade
+    #[error("Invalid batch: {0}")]
+    InvalidBatch(String),
+    
+    /// Node only observes the network
+    #[error("Trade module is read-only")]
+    ReadOnly,
+    
+    /// Another trade would exceed a concurrency limit
+    #[error("Trade limit reached: at most {limit} {scope} trades in flight")]
+    LimitReached {
+        /// Limit exceeded, `concurrent` or `per-peer`
+        scope: &'static str,
+        /// Trades allowed in flight
+        limit: usize,
+    },
+    
+    /// A PSBT pays the operator more than the advertised fees
+    #[error("PSBT pays {paid} sat in operator fees, the advertised rates allow {allowed} sat")]
+    FeeExceeded {
+        /// Paid to the fee address (satoshis)
+        paid: u64,
+        /// Allowed by the advertised rates (satoshis)
+        allowed: u64,
+    },
 }
 
 /// Wallet trait
 #[async_trait]
 pub trait Wallet: Send + Sync {
     /// Create trade PSBT
+    ///
+    /// `payout` is the output paying the counterparty's proceeds to the address it
+    /// asked for; without one the counterparty's default address is used. `fees`
+    /// are the operator and referrer fee outputs the PSBT must add and fund.
     async fn create_trade_psbt(
         &self,
         trade_id: &TradeId,
@@ -229,8 +559,26 @@ pub trait Wallet: Send + Sync {
         quote_asset: &Asset,
         amount: u64,
         price: u64,
+        payout: Option<&PayoutOutput>,
+        fees: &[FeeOutput],
     ) -> Result<Vec<u8>>;
     
+    /// Create a PSBT settling several orders of a batched trade
+    ///
+    /// The PSBT pays one output per leg. Wallets that cannot build batched PSBTs
+    /// keep this default, and the maker cancels batched trades.
+    async fn create_batch_trade_psbt(
+        &self,
+        _trade_id: &TradeId,
+        _base_asset: &Asset,
+        _quote_asset: &Asset,
+        _legs: &[TradeLeg],
+        _payout: Option<&PayoutOutput>,
+        _fees: &[FeeOutput],
+    ) -> Result<Vec<u8>> {
+        Err(TradeError::InvalidBatch("Batched trades are not supported by this wallet".to_string()).into())
+    }
+    
     /// Verify PSBT
     async fn verify_psbt(&self, psbt: &[u8]) -> Result<bool>;
     
@@ -244,7 +592,9 @@ pub trait Wallet: Send + Sync {
 /// Runes executor trait
 #[async_trait]
 pub trait RunesExecutor: Send + Sync {
-    /// Create rune trade PSBT
+    /// Create rune trade PSBT, paying the counterparty per the trade's settlement
+    /// instructions and the side's fees per `fees::fee_outputs`, with one output
+    /// per leg of a batched trade
     async fn create_rune_trade_psbt(&self, trade: &Trade, is_maker: bool) -> Result<Vec<u8>>;
     
     /// Verify rune trade PSBT
@@ -260,7 +610,9 @@ pub trait RunesExecutor: Send + Sync {
 /// Alkanes executor trait
 #[async_trait]
 pub trait AlkanesExecutor: Send + Sync {
-    /// Create alkane trade PSBT
+    /// Create alkane trade PSBT, paying the counterparty per the trade's settlement
+    /// instructions and the side's fees per `fees::fee_outputs`, with one output
+    /// per leg of a batched trade
     async fn create_alkane_trade_psbt(&self, trade: &Trade, is_maker: bool) -> Result<Vec<u8>>;
     
     /// Verify alkane trade PSBT
@@ -285,11 +637,312 @@ impl TradeModule {
         Self {
             network,
             trades: Arc::new(RwLock::new(HashMap::new())),
+            index: Arc::new(RwLock::new(TradeIndex::new())),
+            receipt_signatures: Arc::new(RwLock::new(HashMap::new())),
             trade_topic: "darkswap/trade".to_string(),
             event_sender,
             wallet,
             runes_executor,
             alkanes_executor,
+            approver: RwLock::new(None),
+            auto_accept: true,
+            recorder: RwLock::new(None),
+            bitcoin_network: BitcoinNetwork::Testnet,
+            payout_addresses: RwLock::new(HashMap::new()),
+            payout_storage: None,
+            trade_storage: None,
+            payout_proofs: RwLock::new(HashMap::new()),
+            require_payout_proof: false,
+            private_orders: RwLock::new(PrivateOrders::default()),
+            replay_guard: RwLock::new(ReplayGuard::default()),
+            fee_guard: None,
+            cosigner: None,
+            clock: clock::system(),
+            trade_timeout: 0,
+            audit: None,
+            limits: TradeLimits::default(),
+            reservations: Reservations::default(),
+            queue: RwLock::new(ProposalQueue::default()),
+            analytics: None,
+            verification_pool: VerificationPool::default(),
+            wal: None,
+            outbox: None,
+        }
+    }
+    
+    /// Set the Bitcoin network payout addresses are checked against (default: testnet)
+    pub fn with_bitcoin_network(mut self, network: BitcoinNetwork) -> Self {
+        self.bitcoin_network = network;
+        self
+    }
+    
+    /// Get the topic trade messages are published on
+    pub fn trade_topic(&self) -> &str {
+        &self.trade_topic
+    }
+    
+    /// Set how old a signed trade message may be when it arrives (seconds)
+    pub fn with_message_window(mut self, window: u64) -> Self {
+        self.replay_guard = RwLock::new(ReplayGuard::new(window));
+        self
+    }
+    
+    /// Set whether inbound trades are accepted while no approval handler is registered (default: true)
+    pub fn with_auto_accept(mut self, auto_accept: bool) -> Self {
+        self.auto_accept = auto_accept;
+        self
+    }
+    
+    /// Refuse inbound trades that would leave less than the fee reserve
+    pub fn with_fee_reserve(mut self, guard: Arc<FeeReserveGuard>) -> Self {
+        self.fee_guard = Some(guard);
+        self
+    }
+    
+    /// Refuse inbound trades on own orders that were not co-signed
+    pub fn with_cosigner(mut self, cosigner: Arc<OrderCosigner>) -> Self {
+        self.cosigner = Some(cosigner);
+        self
+    }
+    
+    /// Read the time from `clock` instead of the wall clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    
+    /// Expire trades not signed within `timeout` seconds of their creation (default: never)
+    pub fn with_trade_timeout(mut self, timeout: u64) -> Self {
+        self.trade_timeout = timeout;
+        self
+    }
+    
+    /// Record every signed trade message received to `audit`, without acting on any
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+    
+    /// Bound the trades in flight, in total and per counterparty
+    pub fn with_limits(mut self, limits: TradeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+    
+    /// Record the session analytics of trades to `analytics`
+    pub fn with_analytics(mut self, analytics: Arc<SessionAnalytics>) -> Self {
+        self.analytics = Some(analytics);
+        self
+    }
+    
+    /// Check counterparty PSBT signatures on `pool`, e.g. one shared with the orderbook
+    pub fn with_verification_pool(mut self, pool: VerificationPool) -> Self {
+        self.verification_pool = pool;
+        self
+    }
+    
+    /// Keep the payout addresses of own orders in `storage`, loading the stored ones
+    pub fn with_payout_storage(mut self, storage: Arc<dyn Storage>) -> Result<Self> {
+        let stored = storage.scan_json::<String>(PAYOUT_NAMESPACE, "")
+            .context("Failed to load payout addresses")?;
+        self.payout_addresses = RwLock::new(stored.into_iter()
+            .map(|(order_id, address)| (OrderId(order_id), address))
+            .collect());
+        self.payout_storage = Some(storage);
+        Ok(self)
+    }
+    
+    /// Keep the trades in `storage`, loading the stored ones and rebuilding their index
+    pub fn with_trade_storage(mut self, storage: Arc<dyn Storage>) -> Result<Self> {
+        let (trades, index) = query::load(&*storage)?;
+        self.trades = Arc::new(RwLock::new(trades));
+        self.index = Arc::new(RwLock::new(index));
+        self.trade_storage = Some(storage);
+        Ok(self)
+    }
+    
+    /// Log every trade message to `wal` before acting on it
+    pub fn with_wal(mut self, wal: Arc<TradeWal>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+    
+    /// Keep sending trade messages through `outbox` until the counterparty acknowledges them
+    pub fn with_outbox(mut self, outbox: Arc<Outbox>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+    
+    /// Record a trade message that failed to decode or verify to the session analytics
+    pub fn record_invalid_message(&self, peer_id: &str) {
+        self.record_failure(None, peer_id, "invalid_message");
+    }
+    
+    /// Record a failed trade handshake to the session analytics
+    fn record_failure(&self, trade_id: Option<&TradeId>, peer_id: &str, reason: &str) {
+        if let Some(analytics) = &self.analytics {
+            analytics.handshake_failed(trade_id, peer_id, reason);
+        }
+    }
+    
+    /// Refuse to pay a counterparty payout address that comes without a valid BIP-322 proof
+    pub fn with_payout_proof_required(mut self, required: bool) -> Self {
+        self.require_payout_proof = required;
+        self
+    }
+    
+    /// Keep a BIP-322 proof of an own payout address, sent along with the address
+    ///
+    /// The proof signs `settlement::payout_proof_message(address)` and is checked
+    /// before it is kept.
+    pub async fn add_payout_proof(&self, address: &str, proof: &str) -> Result<()> {
+        let parsed = settlement::parse_payout_address(address, self.bitcoin_network)?;
+        settlement::verify_payout_proof(&parsed, Some(proof))?;
+        
+        self.payout_proofs.write().await.insert(address.to_string(), proof.to_string());
+        Ok(())
+    }
+    
+    /// Get the proof of an own payout address, if one was added
+    async fn payout_proof(&self, address: Option<&str>) -> Option<String> {
+        self.payout_proofs.read().await.get(address?).cloned()
+    }
+    
+    /// Check a counterparty payout address against the proof policy
+    fn check_payout_proof(&self, address: Option<&str>, proof: Option<&str>) -> Result<(), TradeError> {
+        match address {
+            Some(address) if self.require_payout_proof => {
+                let address = settlement::parse_payout_address(address, self.bitcoin_network)?;
+                settlement::verify_payout_proof(&address, proof)
+            }
+            _ => Ok(()),
+        }
+    }
+    
+    /// Set or clear the payout address of an own order
+    ///
+    /// Trades on the order pay the maker's proceeds to this address instead of the
+    /// wallet's default. The address is stored before it takes effect.
+    pub async fn set_payout_address(&self, order_id: &OrderId, payout_address: Option<String>) -> Result<()> {
+        let mut payout_addresses = self.payout_addresses.write().await;
+        match payout_address {
+            Some(address) => {
+                settlement::parse_payout_address(&address, self.bitcoin_network)?;
+                if let Some(storage) = &self.payout_storage {
+                    storage.put_json(PAYOUT_NAMESPACE, &order_id.0, &address)?;
+                    storage.flush()?;
+                }
+                payout_addresses.insert(order_id.clone(), address);
+            }
+            None => {
+                if let Some(storage) = &self.payout_storage {
+                    storage.delete(PAYOUT_NAMESPACE, order_id.0.as_bytes())?;
+                    storage.flush()?;
+                }
+                payout_addresses.remove(order_id);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Register an order that is not in the public orderbook
+    ///
+    /// This lets the standard trade protocol run against the private order of an
+    /// RFQ quote or a private group. Only `takers` can take it; an empty list
+    /// leaves it open to any peer that knows the order. Trades cannot take more
+    /// than the order offers, and the order is dropped once filled or expired.
+    pub async fn add_private_order(&self, order: Order, takers: Vec<String>) {
+        self.private_orders.write().await.insert(order, takers);
+    }
+    
+    /// Remove a private order, so it can no longer be taken
+    ///
+    /// Trades already in flight on the order go ahead.
+    pub async fn remove_private_order(&self, order_id: &OrderId) {
+        self.private_orders.write().await.remove(order_id);
+    }
+    
+    /// Give back or keep what a trade took of private orders, and release its fee
+    /// reserve commitment, once it is over
+    async fn settle_private_orders(&self, trade_id: &TradeId) {
+        let state = match self.trades.read().await.get(trade_id) {
+            Some(trade) => trade.state,
+            None => return,
+        };
+        
+        if matches!(state, TradeState::Completed | TradeState::Failed | TradeState::Canceled | TradeState::Expired) {
+            self.private_orders.write().await.settle(trade_id, state == TradeState::Completed, self.clock.now());
+            if let Some(guard) = &self.fee_guard {
+                guard.release(trade_id).await;
+            }
+        }
+    }
+    
+    /// Get the output paying one side's proceeds to its payout address, if it has one
+    async fn payout_output(&self, trade: &Trade, is_maker: bool) -> Result<Option<PayoutOutput>> {
+        let address = match trade.settlement.payout_address(is_maker) {
+            Some(address) => address.to_string(),
+            None => return Ok(None),
+        };
+        
+        // The legs of a batch share the side of the first order
+        let order = self.get_order_by_id(&trade.order_id).await?;
+        let amount = settlement::payout_amount(trade, order.side, is_maker)?;
+        
+        Ok(Some(PayoutOutput { address, amount }))
+    }
+    
+    /// Check that a counterparty PSBT pays the local side's proceeds to its payout
+    /// address, if it has one
+    async fn pays_payout_address(&self, trade: &Trade, local_is_maker: bool, psbt: &[u8]) -> Result<bool> {
+        match self.payout_output(trade, local_is_maker).await? {
+            Some(payout) => {
+                let address = settlement::parse_payout_address(&payout.address, self.bitcoin_network)?;
+                Ok(settlement::psbt_pays_to(psbt, &address, payout.amount))
+            }
+            None => Ok(true),
+        }
+    }
+    
+    /// Ask for approval of inbound trades
+    ///
+    /// Returns the receiver of approval requests. Until this is called, trades are
+    /// accepted or rejected without asking, per `with_auto_accept`; replacing the
+    /// handler drops pending requests, which then fall back to the policy.
+    pub async fn enable_approval(&self, policy: ApprovalPolicy) -> mpsc::Receiver<ApprovalRequest> {
+        let (approver, requests) = Approver::new(policy);
+        *self.approver.write().await = Some(approver);
+        requests
+    }
+    
+    /// Record the messages received and sent from now on
+    ///
+    /// The trades known at this point are kept in the trace, so it can be replayed
+    /// against a fresh trade module.
+    pub async fn start_recording(&self) {
+        let trades = self.get_trades().await;
+        *self.recorder.write().await = Some(TraceRecorder::new(trades));
+    }
+    
+    /// Stop recording and return the trace
+    pub async fn take_trace(&self) -> Option<TradeTrace> {
+        self.recorder.write().await.take().map(TraceRecorder::finish)
+    }
+    
+    /// Add a trade as it was recorded, replacing any trade with the same ID
+    pub(crate) async fn restore_trade(&self, trade: Trade) {
+        self.store_trade(&trade).await;
+        self.trades.write().await.insert(trade.id.clone(), trade);
+    }
+    
+    /// Index a trade and keep its current state in storage
+    async fn store_trade(&self, trade: &Trade) {
+        self.index.write().await.insert(trade);
+        if let Some(storage) = &self.trade_storage {
+            if let Err(e) = query::store(&**storage, trade) {
+                warn!("Failed to store trade {}: {}", trade.id, e);
+            }
         }
     }
     
@@ -303,21 +956,32 @@ impl TradeModule {
     }
     
     /// Create a new trade
+    ///
+    /// The taker's proceeds are paid to `payout_address` if given, instead of the
+    /// wallet's default address.
     pub async fn create_trade(
         &self,
         order_id: &OrderId,
         taker_peer_id: String,
         amount: Decimal,
+        payout_address: Option<String>,
     ) -> Result<Trade> {
+        if self.audit.is_some() {
+            return Err(TradeError::ReadOnly.into());
+        }
+        
+        if let Some(address) = &payout_address {
+            settlement::parse_payout_address(address, self.bitcoin_network)?;
+        }
+        
         // Get the order
         let order = self.get_order_by_id(order_id).await?;
+        self.reservations.check(&self.limits, &*self.trades.read().await, &order.maker)?;
         
-        // Get local peer ID
-        let network = self.network.read().await;
-        let local_peer_id = network.local_peer_id().to_string();
+        self.connect_to_counterparty(&order.maker, &order.hints).await;
         
         // Create a new trade
-        let trade = Trade::new(
+        let mut trade = Trade::new(
             order_id.clone(),
             order.maker.clone(),
             taker_peer_id,
@@ -326,11 +990,27 @@ impl TradeModule {
             amount,
             order.price,
             None,
+            self.clock.now(),
         );
+        trade.record_stage(TradeStage::ProposalSent, self.clock.now_ms());
+        trade.settlement.taker_payout_address = payout_address.clone();
+        trade.fees = order.fees.clone();
+        trade.referral_code = order.referral_code.clone();
+        let leg = TradeLeg { order_id: order_id.clone(), amount, price: order.price };
+        let _slot = self.reservations.reserve(&self.limits, &*self.trades.read().await, &trade.id, &order.maker)?;
+        self.private_orders.write().await.take(&trade.id, &[leg], &trade.taker_peer_id, self.clock.now())?;
         
         // Store the trade
         let mut trades = self.trades.write().await;
         trades.insert(trade.id.clone(), trade.clone());
+        self.store_trade(&trade).await;
+        if let Some(guard) = &self.fee_guard {
+            let commitment = btc_commitment(&order.base_asset, &order.quote_asset, order.side.opposite(), amount, order.price)?;
+            guard.hold(&trade.id, vec![(order_id.clone(), commitment)]).await;
+        }
+        if let Some(analytics) = &self.analytics {
+            analytics.trade_started(&trade.id);
+        }
         
         // Send initialize message
         self.send_trade_message(
@@ -338,9 +1018,17 @@ impl TradeModule {
                 trade_id: trade.id.clone(),
                 order_id: order_id.clone(),
                 amount,
+                payout_proof: self.payout_proof(payout_address.as_deref()).await,
+                payout_address,
             },
             &order.maker,
         ).await?;
+        // The message is out, so a failed checkpoint must not fail the trade
+        if let Some(wal) = &self.wal {
+            if let Err(e) = wal.checkpoint(&trade) {
+                warn!("Failed to checkpoint trade {}: {}", trade.id, e);
+            }
+        }
         
         // Send event
         let _ = self.event_sender
@@ -350,88 +1038,402 @@ impl TradeModule {
         Ok(trade)
     }
     
+    /// Create a trade settling several orders of one maker in one transaction
+    ///
+    /// The orders must settle together (see `batch::can_batch`). A single leg
+    /// creates a regular trade on its order.
+    pub async fn create_batch_trade(
+        &self,
+        legs: Vec<TradeLeg>,
+        taker_peer_id: String,
+        payout_address: Option<String>,
+    ) -> Result<Trade> {
+        if self.audit.is_some() {
+            return Err(TradeError::ReadOnly.into());
+        }
+        
+        if let [leg] = legs.as_slice() {
+            return self.create_trade(&leg.order_id, taker_peer_id, leg.amount, payout_address).await;
+        }
+        
+        if let Some(address) = &payout_address {
+            settlement::parse_payout_address(address, self.bitcoin_network)?;
+        }
+        
+        // Get the orders
+        let mut orders = Vec::with_capacity(legs.len());
+        for leg in &legs {
+            orders.push(self.get_order_by_id(&leg.order_id).await?);
+        }
+        batch::check_legs(&legs, &orders)?;
+        let order = orders.first()
+            .ok_or_else(|| TradeError::InvalidBatch("No orders taken".to_string()))?;
+        self.reservations.check(&self.limits, &*self.trades.read().await, &order.maker)?;
+        
+        self.connect_to_counterparty(&order.maker, &order.hints).await;
+        
+        // Create a new trade over all the legs
+        let mut trade = Trade::new(
+            order.id.clone(),
+            order.maker.clone(),
+            taker_peer_id,
+            order.base_asset.clone(),
+            order.quote_asset.clone(),
+            batch::total_amount(&legs),
+            batch::average_price(&legs),
+            None,
+            self.clock.now(),
+        );
+        trade.record_stage(TradeStage::ProposalSent, self.clock.now_ms());
+        trade.settlement.taker_payout_address = payout_address.clone();
+        trade.fees = order.fees.clone();
+        trade.referral_code = order.referral_code.clone();
+        trade.legs = legs.clone();
+        let _slot = self.reservations.reserve(&self.limits, &*self.trades.read().await, &trade.id, &order.maker)?;
+        self.private_orders.write().await.take(&trade.id, &legs, &trade.taker_peer_id, self.clock.now())?;
+        
+        // Store the trade
+        self.trades.write().await.insert(trade.id.clone(), trade.clone());
+        self.store_trade(&trade).await;
+        if let Some(guard) = &self.fee_guard {
+            let mut commitments = Vec::with_capacity(legs.len());
+            for (leg, order) in legs.iter().zip(&orders) {
+                let amount = btc_commitment(&order.base_asset, &order.quote_asset, order.side.opposite(), leg.amount, order.price)?;
+                commitments.push((order.id.clone(), amount));
+            }
+            guard.hold(&trade.id, commitments).await;
+        }
+        if let Some(analytics) = &self.analytics {
+            analytics.trade_started(&trade.id);
+        }
+        
+        // Propose the batch to the maker
+        self.send_trade_message(
+            &TradeMessage::InitializeBatch {
+                trade_id: trade.id.clone(),
+                legs,
+                payout_proof: self.payout_proof(payout_address.as_deref()).await,
+                payout_address,
+            },
+            &order.maker,
+        ).await?;
+        // The message is out, so a failed checkpoint must not fail the trade
+        if let Some(wal) = &self.wal {
+            if let Err(e) = wal.checkpoint(&trade) {
+                warn!("Failed to checkpoint trade {}: {}", trade.id, e);
+            }
+        }
+        
+        // Send event
+        let _ = self.event_sender
+            .send(Event::TradeCreated(trade.id.clone()))
+            .await;
+        
+        Ok(trade)
+    }
+    
+    /// Connect to a counterparty over the fastest path, trying the relays its order advertises first
+    ///
+    /// The taker connects to the maker of the order it takes, the maker to the
+    /// taker of a proposal it accepts; both record the path taken. Gossip is
+    /// used as a fallback, so a failed connection is only logged.
+    async fn connect_to_counterparty(&self, peer_id: &str, hints: &RoutingHints) {
+        // Dial without holding the network, which would stall everything that publishes
+        let dialer = self.network.read().await.dialer();
+        let dialer = match dialer {
+            Ok(dialer) => dialer,
+            Err(e) => {
+                warn!("Failed to connect to counterparty {}: {}", peer_id, e);
+                return;
+            }
+        };
+        
+        match peer_id.parse() {
+            Ok(counterparty) => match dialer.connect_with_hints(counterparty, hints).await {
+                Ok(path) => {
+                    if let Some(analytics) = &self.analytics {
+                        analytics.connected(peer_id, path.kind);
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to connect to counterparty {}: {}", peer_id, e);
+                    self.record_failure(None, peer_id, "unreachable");
+                }
+            },
+            Err(e) => warn!("Invalid counterparty peer ID {}: {}", peer_id, e),
+        }
+    }
+    
+    /// Handle a signed trade message received from the network
+    ///
+    /// The envelope is checked for its signature, its age and replays before the
+    /// message is handled in the name of its sender. An audit node records the
+    /// envelope and stops there.
+    pub async fn handle_signed_message(&self, envelope: SignedTradeMessage) -> Result<()> {
+        let checked = match envelope.verify() {
+            Ok(()) => self.replay_guard.write().await.check_at(&envelope, self.clock.now()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = checked {
+            // A copy of a message that arrived before; its acknowledgment may have been lost
+            if matches!(e, ReplayError::Replayed { .. }) {
+                if self.audit.is_none() {
+                    self.acknowledge(&envelope).await;
+                }
+            } else {
+                // The sender is not authenticated, so the trade it names is kept
+                self.record_invalid_message(&envelope.sender);
+            }
+            return Err(e.into());
+        }
+        
+        if let Some(audit) = &self.audit {
+            let sender = envelope.sender.clone();
+            audit.record(&sender, Observation::Trade(envelope))?;
+            return Ok(());
+        }
+        
+        if let TradeMessage::Ack { nonce, .. } = &envelope.message {
+            if let Some(outbox) = &self.outbox {
+                outbox.acknowledge(&envelope.sender, *nonce)?;
+            }
+            return Ok(());
+        }
+        
+        // Only the party to a trade handles its messages, so only it acknowledges them
+        self.handle_trade_message(envelope.message.clone(), &envelope.sender).await?;
+        self.acknowledge(&envelope).await;
+        
+        Ok(())
+    }
+    
+    /// Acknowledge a message to its sender, which keeps sending it until then
+    ///
+    /// A lost acknowledgment only costs a retry, so failures are logged.
+    async fn acknowledge(&self, envelope: &SignedTradeMessage) {
+        if matches!(envelope.message, TradeMessage::Ack { .. }) {
+            return;
+        }
+        if let Err(e) = self.send_ack(envelope).await {
+            warn!("Failed to acknowledge {} of trade {}: {}", envelope.message.kind(), envelope.message.trade_id(), e);
+        }
+    }
+    
+    /// Send the acknowledgment of a message directly to its sender
+    async fn send_ack(&self, envelope: &SignedTradeMessage) -> Result<()> {
+        let peer_id: libp2p::PeerId = envelope.sender.parse()
+            .with_context(|| format!("Invalid sender peer ID {}", envelope.sender))?;
+        let ack = TradeMessage::Ack { trade_id: envelope.message.trade_id().clone(), nonce: envelope.nonce };
+        
+        let mut network = self.network.write().await;
+        let signed_ack = SignedTradeMessage::sign(ack, network.keypair(), self.clock.now())?;
+        let data = envelope::encode(&signed_ack).context("Failed to serialize acknowledgment")?;
+        
+        network.send_request(&peer_id, TRADE_PROTOCOL, data).await
+    }
+    
+    /// Send the trade messages not acknowledged yet again, dropping those past their TTL
+    ///
+    /// With `connected_peer`, every message waiting for that peer is sent at once;
+    /// otherwise those last sent a retry interval ago are. A message that cannot
+    /// be sent is counted as failed and left for the next round.
+    pub async fn retry_outbox(&self, connected_peer: Option<&str>) -> Result<OutboxRetry> {
+        let Some(outbox) = &self.outbox else { return Ok(OutboxRetry::default()) };
+        let now = self.clock.now();
+        let mut retry = OutboxRetry::default();
+        
+        // The trade itself times out on its own, so a message given up on is not a handshake failure
+        for entry in outbox.expire(now)? {
+            warn!("Giving up on {} of trade {} to {} after {} sends", entry.kind, entry.trade_id, entry.peer_id, entry.attempts);
+            let _ = self.event_sender.send(Event::TradeMessageUndelivered(entry.into())).await;
+            retry.expired += 1;
+        }
+        
+        for entry in outbox.due(connected_peer, now)? {
+            let peer_id = match entry.peer_id.parse::<libp2p::PeerId>() {
+                Ok(peer_id) => peer_id,
+                Err(e) => {
+                    warn!("Dropping message to invalid peer ID {} from the trade outbox: {}", entry.peer_id, e);
+                    if let Err(e) = outbox.acknowledge(&entry.peer_id, entry.nonce) {
+                        warn!("Failed to drop message from the trade outbox: {}", e);
+                    }
+                    retry.failed += 1;
+                    continue;
+                }
+            };
+            
+            let sent = self.network.write().await.send_request(&peer_id, TRADE_PROTOCOL, entry.data.clone()).await;
+            match sent.and_then(|()| outbox.record_attempt(&entry, now)) {
+                Ok(()) => retry.resent += 1,
+                Err(e) => {
+                    warn!("Failed to send {} of trade {} to {} again: {}", entry.kind, entry.trade_id, entry.peer_id, e);
+                    retry.failed += 1;
+                }
+            }
+        }
+        
+        Ok(retry)
+    }
+    
     /// Handle trade message
     pub async fn handle_trade_message(
         &self,
         message: TradeMessage,
         peer_id: &str,
     ) -> Result<()> {
-        match message {
-            TradeMessage::Initialize { trade_id, order_id, amount } => {
-                // Get the order
-                let order = self.get_order_by_id(&order_id).await?;
-                
-                // Create a new trade
-                let trade = Trade::new(
-                    order_id,
-                    peer_id.to_string(),
-                    self.network.read().await.local_peer_id().to_string(),
-                    order.base_asset.clone(),
-                    order.quote_asset.clone(),
-                    amount,
-                    order.price,
-                    None,
-                );
-                
-                // Store the trade
-                let mut trades = self.trades.write().await;
-                trades.insert(trade.id.clone(), trade.clone());
-                
-                // Send event
-                let _ = self.event_sender
-                    .send(Event::TradeStarted(trade.id.clone()))
-                    .await;
-                
-                // Create a PSBT based on the asset type
-                let psbt = match (&order.base_asset, &order.quote_asset) {
-                    (Asset::Rune(_), _) | (_, Asset::Rune(_)) => {
-                        // Create a rune trade PSBT
-                        self.runes_executor.create_rune_trade_psbt(&trade, true).await?
-                    }
-                    (Asset::Alkane(_), _) | (_, Asset::Alkane(_)) => {
-                        // Create an alkane trade PSBT
-                        self.alkanes_executor.create_alkane_trade_psbt(&trade, true).await?
-                    }
-                    _ => {
-                        // Create a regular trade PSBT
-                        self.wallet.create_trade_psbt(
-                            &trade.id,
-                            &trade.order_id,
-                            &trade.base_asset,
-                            &trade.quote_asset,
-                            amount_to_u64(trade.amount)?,
-                            price_to_u64(trade.price)?,
-                        ).await?
-                    }
+        let recorded = match self.recorder.read().await.as_ref() {
+            Some(_) => Some(message.clone()),
+            None => None,
+        };
+        
+        let kind = message.kind();
+        let trade_id = message.trade_id().clone();
+        
+        // Log the message, with the state it finds, before acting on it
+        let logged = match &self.wal {
+            Some(wal) => {
+                let trade = self.trades.read().await.get(&trade_id).cloned();
+                Some(wal.append(WalMessage::Inbound { peer_id: peer_id.to_string(), message: message.clone(), trade })?)
+            }
+            None => None,
+        };
+        
+        let result = self.process_trade_message(message, peer_id).await;
+        match logged {
+            Some(sequence) => self.resolve_logged(sequence, &trade_id).await,
+            None => self.checkpoint(&trade_id).await,
+        }
+        self.settle_private_orders(&trade_id).await;
+        
+        // A PSBT exchange that broke down is a failed handshake
+        if let Err(e) = &result {
+            if matches!(kind, "SendPsbt" | "SignPsbt") {
+                let reason = match e.downcast_ref::<TradeError>() {
+                    Some(TradeError::PsbtError(_) | TradeError::FeeExceeded { .. }) => "invalid_psbt",
+                    Some(TradeError::PayoutProof(_)) => PAYOUT_PROOF_REQUIRED,
+                    _ => "protocol_error",
                 };
+                self.record_failure(Some(&trade_id), peer_id, reason);
+            }
+        }
+        
+        // A trade the message ended is no longer timed
+        if let Some(analytics) = &self.analytics {
+            let state = self.trades.read().await.get(&trade_id).map(|trade| trade.state);
+            if matches!(state, Some(TradeState::Failed | TradeState::Canceled | TradeState::Expired)) {
+                analytics.trade_finished(&trade_id);
+            }
+        }
+        
+        if let Some(message) = recorded {
+            let state = self.trades.read().await.get(message.trade_id()).map(|trade| trade.state);
+            if let Some(recorder) = self.recorder.write().await.as_mut() {
+                recorder.inbound(peer_id, message, state, result.as_ref().err());
+            }
+        }
+        
+        // The message may have finished a trade a queued proposal waits for
+        self.process_queue().await;
+        
+        result
+    }
+    
+    /// Open the queued proposals that fit the limits, and cancel those queued for too long
+    pub async fn process_queue(&self) {
+        if self.queue.read().await.is_empty() {
+            return;
+        }
+        
+        let expired = self.queue.write().await.take_expired(self.clock.now(), self.limits.queue_timeout);
+        for proposal in expired {
+            info!("Queued trade {} from {} timed out", proposal.trade_id, proposal.peer_id);
+            self.record_failure(Some(&proposal.trade_id), &proposal.peer_id, LIMIT_REACHED);
+            let cancel = TradeMessage::Cancel {
+                trade_id: proposal.trade_id,
+                reason: format!("{}: no trade slot freed in time", LIMIT_REACHED),
+            };
+            if let Err(e) = self.send_trade_message(&cancel, &proposal.peer_id).await {
+                warn!("Failed to cancel queued trade: {}", e);
+            }
+        }
+        
+        loop {
+            let next = {
+                let trades = self.trades.read().await;
+                self.queue.write().await.pop_ready(&self.limits, &trades, &self.reservations)
+            };
+            let Some(proposal) = next else { break };
+            
+            info!("Opening queued trade {} from {}", proposal.trade_id, proposal.peer_id);
+            if let Err(e) = self.open_maker_trade(proposal.trade_id.clone(), proposal.legs, proposal.payout_address, proposal.payout_proof, &proposal.peer_id).await {
+                warn!("Failed to open queued trade {}: {}", proposal.trade_id, e);
+            }
+            self.settle_private_orders(&proposal.trade_id).await;
+        }
+    }
+    
+    /// Get the number of inbound proposals waiting for a trade slot
+    pub async fn queued_proposals(&self) -> usize {
+        self.queue.read().await.len()
+    }
+    
+    /// Check the signatures of a counterparty PSBT on the verification pool
+    ///
+    /// The trades are not locked during the check; the returned outcome holds
+    /// the state the trade was in, to be confirmed once they are locked again.
+    async fn verify_signatures(&self, trade_id: &TradeId, psbt: &[u8]) -> Result<VerifiedPsbt> {
+        let state = self.trades.read().await
+            .get(trade_id)
+            .map(|trade| trade.state)
+            .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
+        let result = self.verification_pool.verify_psbt(psbt.to_vec()).await;
+        
+        Ok(VerifiedPsbt { state, result })
+    }
+    
+    /// Apply a trade message to the trade state machine
+    async fn process_trade_message(
+        &self,
+        message: TradeMessage,
+        peer_id: &str,
+    ) -> Result<()> {
+        match message {
+            TradeMessage::Initialize { trade_id, order_id, amount, payout_address, payout_proof } => {
+                let price = self.get_order_by_id(&order_id).await?.price;
+                let leg = TradeLeg { order_id, amount, price };
                 
-                // Update trade state
-                let mut trades = self.trades.write().await;
-                let trade = trades.get_mut(&trade_id)
-                    .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
-                trade.maker_psbt = Some(psbt.clone());
-                trade.update_state(TradeState::MakerPsbtSent);
+                self.open_maker_trade(trade_id, vec![leg], payout_address, payout_proof, peer_id).await?;
+            }
+            TradeMessage::InitializeBatch { trade_id, legs, payout_address, payout_proof } => {
+                if legs.len() < 2 {
+                    return Err(TradeError::InvalidBatch("A batch takes at least two orders".to_string()).into());
+                }
                 
-                // Send PSBT
-                self.send_trade_message(
-                    &TradeMessage::SendPsbt {
-                        trade_id: trade.id.clone(),
-                        psbt,
-                    },
-                    peer_id,
-                ).await?;
+                self.open_maker_trade(trade_id, legs, payout_address, payout_proof, peer_id).await?;
             }
-            TradeMessage::SendPsbt { trade_id, psbt } => {
+            TradeMessage::SendPsbt { trade_id, psbt, payout_address, payout_proof } => {
+                // Signature checks run off the event loop, without holding the trades
+                let verified = self.verify_signatures(&trade_id, &psbt).await?;
+                
                 // Get trade
                 let mut trades = self.trades.write().await;
                 let trade = trades.get_mut(&trade_id)
                     .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
+                verified.check_unchanged(trade)?;
                 
                 // Check if peer is maker or taker
                 if peer_id == trade.maker_peer_id {
                     // Maker sent PSBT
+                    if let Some(address) = &payout_address {
+                        settlement::parse_payout_address(address, self.bitcoin_network)?;
+                    }
+                    if let Err(e) = self.check_payout_proof(payout_address.as_deref(), payout_proof.as_deref()) {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e.into());
+                    }
+                    trade.settlement.maker_payout_address = payout_address;
                     trade.maker_psbt = Some(psbt.clone());
-                    trade.update_state(TradeState::MakerPsbtSent);
+                    trade.update_state_at_ms(TradeState::MakerPsbtSent, self.clock.now_ms());
                     
                     // Verify PSBT based on the asset type
                     let is_valid = match (&trade.base_asset, &trade.quote_asset) {
@@ -450,48 +1452,48 @@ impl TradeModule {
                     };
 
                     if !is_valid {
-                        trade.update_state(TradeState::Failed);
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
                         return Err(TradeError::PsbtError("Invalid maker PSBT".to_string()).into());
                     }
                     
-                    // Create taker PSBT based on the asset type
-                    let taker_psbt = match (&trade.base_asset, &trade.quote_asset) {
-                        (Asset::Rune(_), _) | (_, Asset::Rune(_)) => {
-                            // Create a rune trade PSBT
-                            self.runes_executor.create_rune_trade_psbt(trade, false).await?
-                        }
-                        (Asset::Alkane(_), _) | (_, Asset::Alkane(_)) => {
-                            // Create an alkane trade PSBT
-                            self.alkanes_executor.create_alkane_trade_psbt(trade, false).await?
-                        }
-                        _ => {
-                            // Create a regular trade PSBT
-                            self.wallet.create_trade_psbt(
-                                &trade.id,
-                                &trade.order_id,
-                                &trade.base_asset,
-                                &trade.quote_asset,
-                                amount_to_u64(trade.amount)?,
-                                price_to_u64(trade.price)?,
-                            ).await?
-                        }
-                    };
+                    if let Err(e) = verified.result {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e);
+                    }
+                    
+                    if !self.pays_payout_address(trade, false, &psbt).await? {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(TradeError::PsbtError("Maker PSBT does not pay the taker payout address".to_string()).into());
+                    }
+                    
+                    if let Err(e) = fees::check_psbt_fee(&psbt, trade, self.bitcoin_network) {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e.into());
+                    }
+                    if let Some(analytics) = &self.analytics {
+                        analytics.trade_matched(&trade.id);
+                    }
+                    
+                    // Create taker PSBT
+                    let taker_psbt = self.create_psbt(trade, false).await?;
                     
                     trade.taker_psbt = Some(taker_psbt.clone());
-                    trade.update_state(TradeState::TakerPsbtSent);
+                    trade.update_state_at_ms(TradeState::TakerPsbtSent, self.clock.now_ms());
                     
                     // Send taker PSBT
                     self.send_trade_message(
                         &TradeMessage::SendPsbt {
                             trade_id: trade.id.clone(),
                             psbt: taker_psbt,
+                            payout_address: trade.settlement.taker_payout_address.clone(),
+                            payout_proof: self.payout_proof(trade.settlement.taker_payout_address.as_deref()).await,
                         },
                         &trade.maker_peer_id,
                     ).await?;
                 } else if peer_id == trade.taker_peer_id {
                     // Taker sent PSBT
                     trade.taker_psbt = Some(psbt.clone());
-                    trade.update_state(TradeState::TakerPsbtSent);
+                    trade.update_state_at_ms(TradeState::TakerPsbtSent, self.clock.now_ms());
                     
                     // Verify PSBT based on the asset type
                     let is_valid = match (&trade.base_asset, &trade.quote_asset) {
@@ -510,11 +1512,30 @@ impl TradeModule {
                     };
 
                     if !is_valid {
-                        trade.update_state(TradeState::Failed);
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
                         return Err(TradeError::PsbtError("Invalid taker PSBT".to_string()).into());
                     }
                     
+                    if let Err(e) = verified.result {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e);
+                    }
+                    
+                    if !self.pays_payout_address(trade, true, &psbt).await? {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(TradeError::PsbtError("Taker PSBT does not pay the maker payout address".to_string()).into());
+                    }
+                    
+                    if let Err(e) = fees::check_psbt_fee(&psbt, trade, self.bitcoin_network) {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e.into());
+                    }
+                    if let Some(analytics) = &self.analytics {
+                        analytics.trade_matched(&trade.id);
+                    }
+                    
                     // Sign taker PSBT based on the asset type
+                    let signing = self.log_signing(&trade.id, peer_id)?;
                     let signed_psbt = match (&trade.base_asset, &trade.quote_asset) {
                         (Asset::Rune(_), _) | (_, Asset::Rune(_)) => {
                             // Sign rune trade PSBT
@@ -538,22 +1559,27 @@ impl TradeModule {
                         },
                         &trade.taker_peer_id,
                     ).await?;
+                    self.resolve_sent(signing);
                     
-                    trade.update_state(TradeState::MakerSigned);
+                    trade.update_state_at_ms(TradeState::MakerSigned, self.clock.now_ms());
                 } else {
                     return Err(TradeError::InvalidState(format!("Unknown peer ID: {}", peer_id)).into());
                 }
             }
             TradeMessage::SignPsbt { trade_id, signed_psbt } => {
+                // Signature checks run off the event loop, without holding the trades
+                let verified = self.verify_signatures(&trade_id, &signed_psbt).await?;
+                
                 // Get trade
                 let mut trades = self.trades.write().await;
                 let trade = trades.get_mut(&trade_id)
                     .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
+                verified.check_unchanged(trade)?;
                 
                 // Check if peer is maker or taker
                 if peer_id == trade.maker_peer_id {
                     // Maker signed PSBT
-                    trade.update_state(TradeState::MakerSigned);
+                    trade.update_state_at_ms(TradeState::MakerSigned, self.clock.now_ms());
                     
                     // Verify signed PSBT based on the asset type
                     let is_valid = match (&trade.base_asset, &trade.quote_asset) {
@@ -572,11 +1598,22 @@ impl TradeModule {
                     };
 
                     if !is_valid {
-                        trade.update_state(TradeState::Failed);
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
                         return Err(TradeError::PsbtError("Invalid maker signed PSBT".to_string()).into());
                     }
                     
+                    if let Err(e) = verified.result {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e);
+                    }
+                    
+                    if let Err(e) = fees::check_psbt_fee(&signed_psbt, trade, self.bitcoin_network) {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e.into());
+                    }
+                    
                     // Sign PSBT based on the asset type
+                    let signing = self.log_signing(&trade.id, peer_id)?;
                     let final_psbt = match (&trade.base_asset, &trade.quote_asset) {
                         (Asset::Rune(_), _) | (_, Asset::Rune(_)) => {
                             // Sign rune trade PSBT
@@ -611,7 +1648,10 @@ impl TradeModule {
                     trade.txid = Some(txid.clone());
                     
                     // Update trade state
-                    trade.update_state(TradeState::Completed);
+                    trade.update_state_at_ms(TradeState::Completed, self.clock.now_ms());
+                    
+                    // A signed, completed trade counts towards keeping the counterparty warm
+                    self.network.read().await.record_counterparty(peer_id).await;
                     
                     // Send broadcast message
                     self.send_trade_message(
@@ -621,6 +1661,7 @@ impl TradeModule {
                         },
                         &trade.maker_peer_id,
                     ).await?;
+                    self.resolve_sent(signing);
                     
                     // Send event
                     let _ = self.event_sender
@@ -628,7 +1669,7 @@ impl TradeModule {
                         .await;
                 } else if peer_id == trade.taker_peer_id {
                     // Taker signed PSBT
-                    trade.update_state(TradeState::TakerSigned);
+                    trade.update_state_at_ms(TradeState::TakerSigned, self.clock.now_ms());
                     
                     // Verify signed PSBT based on the asset type
                     let is_valid = match (&trade.base_asset, &trade.quote_asset) {
@@ -647,11 +1688,22 @@ impl TradeModule {
                     };
 
                     if !is_valid {
-                        trade.update_state(TradeState::Failed);
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
                         return Err(TradeError::PsbtError("Invalid taker signed PSBT".to_string()).into());
                     }
                     
+                    if let Err(e) = verified.result {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e);
+                    }
+                    
+                    if let Err(e) = fees::check_psbt_fee(&signed_psbt, trade, self.bitcoin_network) {
+                        trade.update_state_at_ms(TradeState::Failed, self.clock.now_ms());
+                        return Err(e.into());
+                    }
+                    
                     // Sign PSBT based on the asset type
+                    let signing = self.log_signing(&trade.id, peer_id)?;
                     let final_psbt = match (&trade.base_asset, &trade.quote_asset) {
                         (Asset::Rune(_), _) | (_, Asset::Rune(_)) => {
                             // Sign rune trade PSBT
@@ -686,7 +1738,10 @@ impl TradeModule {
                     trade.txid = Some(txid.clone());
                     
                     // Update trade state
-                    trade.update_state(TradeState::Completed);
+                    trade.update_state_at_ms(TradeState::Completed, self.clock.now_ms());
+                    
+                    // A signed, completed trade counts towards keeping the counterparty warm
+                    self.network.read().await.record_counterparty(peer_id).await;
                     
                     // Send broadcast message
                     self.send_trade_message(
@@ -696,6 +1751,7 @@ impl TradeModule {
                         },
                         &trade.taker_peer_id,
                     ).await?;
+                    self.resolve_sent(signing);
                     
                     // Send event
                     let _ = self.event_sender
@@ -711,14 +1767,60 @@ impl TradeModule {
                 let trade = trades.get_mut(&trade_id)
                     .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
                 
+                // A repeated confirmation of the same transaction changes nothing
+                if trade.state == TradeState::Completed && trade.txid.as_deref() == Some(txid.as_str()) {
+                    return Ok(());
+                }
+                
+                // Only a trade we have signed can have been broadcast
+                if trade.state != TradeState::MakerSigned && trade.state != TradeState::TakerSigned {
+                    return Err(TradeError::InvalidState(format!("Broadcast for trade in state: {:?}", trade.state)).into());
+                }
+                
                 // Update trade
                 trade.txid = Some(txid);
-                trade.update_state(TradeState::Completed);
+                trade.update_state_at_ms(TradeState::Completed, self.clock.now_ms());
+                
+                // A signed, completed trade counts towards keeping the counterparty warm
+                self.network.read().await.record_counterparty(peer_id).await;
                 
                 // Send event
                 let _ = self.event_sender
                     .send(Event::TradeCompleted(trade.id.clone()))
                     .await;
+                
+                // Sign the receipt and send it to the counterparty
+                let trade = trade.clone();
+                drop(trades);
+                self.accept_receipt(&trade).await?;
+            }
+            TradeMessage::AcceptReceipt { trade_id, signature } => {
+                // Get trade
+                let trade = self.get_trade(&trade_id).await?;
+                
+                // The signature must come from the sender
+                if signature.peer_id != peer_id {
+                    return Err(TradeError::InvalidState(format!("Receipt signature from {} sent by {}", signature.peer_id, peer_id)).into());
+                }
+                
+                // Verify the signature against our view of the trade
+                let body = ReceiptBody::from_trade(&trade)?;
+                signature.verify(&body)?;
+                
+                // Store the signature
+                {
+                    let mut receipt_signatures = self.receipt_signatures.write().await;
+                    let signatures = receipt_signatures.entry(trade_id).or_insert_with(Vec::new);
+                    if !signatures.iter().any(|s| s.peer_id == signature.peer_id) {
+                        signatures.push(signature);
+                    }
+                }
+                
+                // Reply with our own signature if we have not sent it yet
+                self.accept_receipt(&trade).await?;
+            }
+            TradeMessage::Ack { .. } => {
+                // Acknowledgments are taken off the envelope before it gets here
             }
             TradeMessage::Cancel { trade_id, reason } => {
                 // Get trade
@@ -727,7 +1829,7 @@ impl TradeModule {
                     .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
                 
                 // Update trade
-                trade.update_state(TradeState::Canceled);
+                trade.update_state_at_ms(TradeState::Canceled, self.clock.now_ms());
                 
                 // Send event
                 let _ = self.event_sender
@@ -735,27 +1837,525 @@ impl TradeModule {
                     .await;
                 
                 info!("Trade {} canceled by {}: {}", trade_id.0, peer_id, reason);
+                self.record_failure(Some(&trade_id), peer_id, &analytics::reason_code(&reason, "canceled_by_peer"));
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Open a trade on own orders proposed by a taker and send the maker PSBT
+    ///
+    /// A single leg is a trade on one order; several legs settle the orders in
+    /// one batched transaction.
+    async fn open_maker_trade(
+        &self,
+        trade_id: TradeId,
+        legs: Vec<TradeLeg>,
+        payout_address: Option<String>,
+        payout_proof: Option<String>,
+        peer_id: &str,
+    ) -> Result<()> {
+        // A repeated initialize must not reset a trade in progress
+        if self.trades.read().await.contains_key(&trade_id) {
+            return Err(TradeError::InvalidState(format!("Trade already exists: {}", trade_id.0)).into());
+        }
+        
+        // The taker's payout address must belong to our network
+        if let Some(address) = &payout_address {
+            settlement::parse_payout_address(address, self.bitcoin_network)?;
+        }
+        
+        // and, if the policy says so, be proven to be the taker's
+        if let Err(e) = self.check_payout_proof(payout_address.as_deref(), payout_proof.as_deref()) {
+            info!("Trade {} refused: {}", trade_id, e);
+            self.record_failure(Some(&trade_id), peer_id, PAYOUT_PROOF_REQUIRED);
+            self.send_trade_message(
+                &TradeMessage::Cancel {
+                    trade_id,
+                    reason: format!("{}: {}", PAYOUT_PROOF_REQUIRED, e),
+                },
+                peer_id,
+            ).await?;
+            
+            return Ok(());
+        }
+        
+        // Get the orders; a batch may only take orders that settle together
+        let mut orders = Vec::with_capacity(legs.len());
+        for leg in &legs {
+            orders.push(self.get_order_by_id(&leg.order_id).await?);
+        }
+        batch::check_legs(&legs, &orders)?;
+        let order = orders.first()
+            .ok_or_else(|| TradeError::InvalidBatch("No orders taken".to_string()))?;
+        
+        // Own orders are only traded once co-signed, whichever way they were published
+        if let Some(cosigner) = &self.cosigner {
+            for order in &orders {
+                if let Err(e) = cosigner.check(&order.id).await {
+                    info!("Trade {} refused: {}", trade_id, e);
+                    self.record_failure(Some(&trade_id), peer_id, "not_cosigned");
+                    self.send_trade_message(
+                        &TradeMessage::Cancel {
+                            trade_id,
+                            reason: e.to_string(),
+                        },
+                        peer_id,
+                    ).await?;
+                    
+                    return Ok(());
+                }
+            }
+        }
+        
+        // Hold or refuse the proposal if another trade would over-commit the wallet;
+        // the slot is held until the trade is stored
+        let reserved = self.reservations.reserve(&self.limits, &*self.trades.read().await, &trade_id, peer_id);
+        let _slot = match reserved {
+            Ok(slot) => slot,
+            Err(e) => {
+                let proposal = QueuedProposal {
+                    trade_id: trade_id.clone(),
+                    legs: legs.clone(),
+                    payout_address: payout_address.clone(),
+                    payout_proof: payout_proof.clone(),
+                    peer_id: peer_id.to_string(),
+                    queued_at: self.clock.now(),
+                };
+                if self.limits.policy == LimitPolicy::Queue
+                    && self.queue.write().await.push(proposal, self.limits.max_queued, self.limits.max_queued_per_peer)
+                {
+                    info!("Trade {} queued: {}", trade_id, e);
+                    return Ok(());
+                }
+                
+                info!("Trade {} refused: {}", trade_id, e);
+                self.record_failure(Some(&trade_id), peer_id, LIMIT_REACHED);
+                self.send_trade_message(
+                    &TradeMessage::Cancel {
+                        trade_id,
+                        reason: format!("{}: {}", LIMIT_REACHED, e),
+                    },
+                    peer_id,
+                ).await?;
+                
+                return Ok(());
+            }
+        };
+        
+        // Keep the fee reserve of the trades already in flight
+        let mut commitments = Vec::with_capacity(legs.len());
+        for (leg, order) in legs.iter().zip(&orders) {
+            let amount = btc_commitment(&order.base_asset, &order.quote_asset, order.side, leg.amount, order.price)?;
+            commitments.push((order.id.clone(), amount));
+        }
+        if let Some(guard) = &self.fee_guard {
+            // The rest of the orders taken is only committed once it is taken
+            let taken: Vec<OrderId> = orders.iter().map(|order| order.id.clone()).collect();
+            let committed = guard.committed(&taken).await?
+                + commitments.iter().map(|(_, amount)| amount).sum::<u64>();
+            let in_flight = self.in_flight_trades().await + 1;
+            
+            if let Err(e) = guard.check(committed, in_flight).await {
+                info!("Trade {} refused: {}", trade_id, e);
+                self.record_failure(Some(&trade_id), peer_id, "fee_reserve");
+                
+                if let Err(e) = guard.observe(committed, in_flight).await {
+                    warn!("Failed to check fee reserve: {}", e);
+                }
+                
+                self.send_trade_message(
+                    &TradeMessage::Cancel {
+                        trade_id,
+                        reason: "Insufficient fee reserve".to_string(),
+                    },
+                    peer_id,
+                ).await?;
+                
+                return Ok(());
+            }
+        }
+        
+        // Create the trade under the taker's ID, with this node as the maker
+        let local_peer_id = self.network.read().await.local_peer_id().to_string();
+        
+        let mut trade = Trade::new(
+            order.id.clone(),
+            local_peer_id,
+            peer_id.to_string(),
+            order.base_asset.clone(),
+            order.quote_asset.clone(),
+            batch::total_amount(&legs),
+            batch::average_price(&legs),
+            None,
+            self.clock.now(),
+        );
+        trade.id = trade_id.clone();
+        trade.record_stage(TradeStage::ProposalReceived, self.clock.now_ms());
+        trade.settlement = Settlement {
+            maker_payout_address: self.payout_addresses.read().await.get(&trade.order_id).cloned(),
+            taker_payout_address: payout_address,
+        };
+        trade.fees = order.fees.clone();
+        trade.referral_code = order.referral_code.clone();
+        
+        // A private order can only be taken by the peers it was made for, up to its amount
+        self.private_orders.write().await.take(&trade.id, &legs, peer_id, self.clock.now())?;
+        if legs.len() > 1 {
+            trade.legs = legs;
+        }
+        
+        // Store the trade
+        self.trades.write().await.insert(trade.id.clone(), trade.clone());
+        self.store_trade(&trade).await;
+        if let Some(guard) = &self.fee_guard {
+            guard.hold(&trade.id, commitments).await;
+        }
+        if let Some(analytics) = &self.analytics {
+            analytics.trade_started(&trade.id);
+        }
+        
+        // Send event
+        let _ = self.event_sender
+            .send(Event::TradeStarted(trade.id.clone()))
+            .await;
+        
+        // Answer the taker over the fastest path
+        self.connect_to_counterparty(peer_id, &RoutingHints::default()).await;
+        
+        // A batch this node cannot settle is canceled, so the taker can take the orders one by one
+        let psbt = match self.create_psbt(&trade, true).await {
+            Ok(psbt) => psbt,
+            Err(e) if trade.is_batch() => {
+                info!("Batched trade {} canceled: {}", trade.id, e);
+                self.record_failure(Some(&trade.id), peer_id, "batch_unsupported");
+                
+                if let Some(trade) = self.trades.write().await.get_mut(&trade.id) {
+                    trade.update_state_at_ms(TradeState::Canceled, self.clock.now_ms());
+                }
+                
+                self.send_trade_message(
+                    &TradeMessage::Cancel {
+                        trade_id: trade.id.clone(),
+                        reason: format!("Batch not supported: {}", e),
+                    },
+                    peer_id,
+                ).await?;
+                
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        
+        let _ = self.event_sender
+            .send(Event::TradeProposalReceived(trade.id.clone()))
+            .await;
+        
+        // Ask for approval before the PSBT leaves the node; with nobody to ask, the policy decides
+        let approved = match self.approver.read().await.as_ref() {
+            Some(approver) => approver.approve(&trade, &psbt).await,
+            None => self.auto_accept,
+        };
+        
+        if !approved {
+            info!("Trade {} rejected", trade.id);
+            self.record_failure(Some(&trade.id), peer_id, "rejected");
+            
+            if let Some(trade) = self.trades.write().await.get_mut(&trade.id) {
+                trade.update_state_at_ms(TradeState::Canceled, self.clock.now_ms());
             }
+            
+            self.send_trade_message(
+                &TradeMessage::Cancel {
+                    trade_id: trade.id.clone(),
+                    reason: "Rejected by maker".to_string(),
+                },
+                peer_id,
+            ).await?;
+            
+            let _ = self.event_sender
+                .send(Event::TradeCancelled(trade.id.clone()))
+                .await;
+            
+            return Ok(());
         }
         
+        // Update trade state
+        let mut trades = self.trades.write().await;
+        let trade = trades.get_mut(&trade_id)
+            .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
+        trade.maker_psbt = Some(psbt.clone());
+        trade.update_state_at_ms(TradeState::MakerPsbtSent, self.clock.now_ms());
+        
+        // Send PSBT
+        self.send_trade_message(
+            &TradeMessage::SendPsbt {
+                trade_id: trade.id.clone(),
+                psbt,
+                payout_address: trade.settlement.maker_payout_address.clone(),
+                payout_proof: self.payout_proof(trade.settlement.maker_payout_address.as_deref()).await,
+            },
+            peer_id,
+        ).await?;
+        
         Ok(())
     }
 
+    /// Create one side's PSBT based on the asset type
+    async fn create_psbt(&self, trade: &Trade, is_maker: bool) -> Result<Vec<u8>> {
+        let payout = self.payout_output(trade, !is_maker).await?;
+        
+        match (&trade.base_asset, &trade.quote_asset) {
+            (Asset::Rune(_), _) | (_, Asset::Rune(_)) => {
+                // Create a rune trade PSBT
+                self.runes_executor.create_rune_trade_psbt(trade, is_maker).await
+            }
+            (Asset::Alkane(_), _) | (_, Asset::Alkane(_)) => {
+                // Create an alkane trade PSBT
+                self.alkanes_executor.create_alkane_trade_psbt(trade, is_maker).await
+            }
+            _ if trade.is_batch() => {
+                // Create a batched trade PSBT, paying the counterparty's payout address
+                self.wallet.create_batch_trade_psbt(
+                    &trade.id,
+                    &trade.base_asset,
+                    &trade.quote_asset,
+                    &trade.legs,
+                    payout.as_ref(),
+                    &fees::fee_outputs(trade, is_maker)?,
+                ).await
+            }
+            _ => {
+                // Create a regular trade PSBT, paying the counterparty's payout address
+                self.wallet.create_trade_psbt(
+                    &trade.id,
+                    &trade.order_id,
+                    &trade.base_asset,
+                    &trade.quote_asset,
+                    amount_to_u64(trade.amount)?,
+                    price_to_u64(trade.price)?,
+                    payout.as_ref(),
+                    &fees::fee_outputs(trade, is_maker)?,
+                ).await
+            }
+        }
+    }
+
+    /// Sign the receipt of a completed trade and send it to the counterparty
+    async fn accept_receipt(&self, trade: &Trade) -> Result<()> {
+        let body = ReceiptBody::from_trade(trade)?;
+        
+        // Sign with the local identity
+        let network = self.network.read().await;
+        let local_peer_id = network.local_peer_id().to_string();
+        let signature = body.sign(network.keypair())?;
+        drop(network);
+        
+        // Store our own signature, once
+        {
+            let mut receipt_signatures = self.receipt_signatures.write().await;
+            let signatures = receipt_signatures.entry(trade.id.clone()).or_insert_with(Vec::new);
+            if signatures.iter().any(|s| s.peer_id == local_peer_id) {
+                return Ok(());
+            }
+            signatures.push(signature.clone());
+        }
+        
+        // Determine recipient
+        let recipient = if local_peer_id == trade.maker_peer_id {
+            &trade.taker_peer_id
+        } else {
+            &trade.maker_peer_id
+        };
+        
+        self.send_trade_message(
+            &TradeMessage::AcceptReceipt {
+                trade_id: trade.id.clone(),
+                signature,
+            },
+            recipient,
+        ).await
+    }
+
+    /// Export the receipt of a completed trade
+    ///
+    /// The inclusion proof of the settlement transaction can be attached once the
+    /// transaction has confirmed.
+    pub async fn export_receipt(
+        &self,
+        trade_id: &TradeId,
+        inclusion_proof: Option<MerkleProof>,
+    ) -> Result<TradeReceipt> {
+        let trade = self.get_trade(trade_id).await?;
+        
+        if trade.state != TradeState::Completed {
+            return Err(TradeError::InvalidState(format!("Trade is not completed: {:?}", trade.state)).into());
+        }
+        
+        // Make sure our own signature is included
+        self.accept_receipt(&trade).await?;
+        
+        let signatures = self.receipt_signatures.read().await
+            .get(trade_id)
+            .cloned()
+            .unwrap_or_default();
+        
+        let order = self.get_order_by_id(&trade.order_id).await.ok();
+        
+        Ok(TradeReceipt {
+            body: ReceiptBody::from_trade(&trade)?,
+            order,
+            signatures,
+            inclusion_proof,
+        })
+    }
+
     /// Send trade message
     async fn send_trade_message(
         &self,
         message: &TradeMessage,
         peer_id: &str,
     ) -> Result<()> {
-        // Serialize message
-        let message_data = serde_json::to_vec(message)
+        if let Some(recorder) = self.recorder.write().await.as_mut() {
+            recorder.outbound(peer_id, message.clone());
+        }
+        
+        // Sign and serialize message
+        let mut network = self.network.write().await;
+        let signed_message = SignedTradeMessage::sign(message.clone(), network.keypair(), self.clock.now())?;
+        let message_data = envelope::encode(&signed_message)
             .context("Failed to serialize trade message")?;
         
+        // Log the signed envelope, so a restart publishes it again instead of signing anew
+        let logged = match &self.wal {
+            Some(wal) => Some(wal.append(WalMessage::Outbound {
+                peer_id: peer_id.to_string(),
+                trade_id: message.trade_id().clone(),
+                kind: message.kind().to_string(),
+                data: message_data.clone(),
+            })?),
+            None => None,
+        };
+        
+        // Keep the message until the counterparty acknowledges it
+        if let Some(outbox) = &self.outbox {
+            let kind = message.kind();
+            outbox.push(peer_id, message.trade_id(), kind, signed_message.nonce, message_data.clone(), self.clock.now())?;
+        }
+        
         // Publish message to trade topic
-        let mut network = self.network.write().await;
-        network.publish(&self.trade_topic, message_data).await?;
+        // A message that was not published stays pending, to be sent on recovery
+        let published = network.publish(&self.trade_topic, message_data).await;
+        if published.is_ok() {
+            self.resolve_sent(logged);
+        }
         
-        Ok(())
+        published
+    }
+    
+    /// Log that a trade PSBT is about to be signed
+    ///
+    /// The entry supersedes the inbound message that led to the signature, so a
+    /// restart does not apply that message, and sign, a second time.
+    fn log_signing(&self, trade_id: &TradeId, peer_id: &str) -> Result<Option<u64>> {
+        match &self.wal {
+            Some(wal) => Ok(Some(wal.append(WalMessage::Signing {
+                peer_id: peer_id.to_string(),
+                trade_id: trade_id.clone(),
+            })?)),
+            None => Ok(None),
+        }
+    }
+    
+    /// Mark a logged entry as handled once its message went out
+    ///
+    /// The message cannot be taken back, so a failure only leaves the entry to
+    /// be resolved again on recovery.
+    fn resolve_sent(&self, logged: Option<u64>) {
+        let (Some(wal), Some(sequence)) = (&self.wal, logged) else { return };
+        if let Err(e) = wal.resolve(sequence) {
+            warn!("Failed to resolve trade log entry {}: {}", sequence, e);
+        }
+    }
+    
+    /// Keep the current state of a trade in the write-ahead log and the trade store
+    async fn checkpoint(&self, trade_id: &TradeId) {
+        let Some(trade) = self.trades.read().await.get(trade_id).cloned() else { return };
+        if let Some(wal) = &self.wal {
+            if let Err(e) = wal.checkpoint(&trade) {
+                warn!("Failed to checkpoint trade {}: {}", trade_id, e);
+            }
+        }
+        self.store_trade(&trade).await;
+    }
+    
+    /// Mark a logged inbound message as handled, keeping the state it left its trade in
+    async fn resolve_logged(&self, sequence: u64, trade_id: &TradeId) {
+        let Some(wal) = &self.wal else { return };
+        self.checkpoint(trade_id).await;
+        if let Err(e) = wal.resolve(sequence) {
+            warn!("Failed to resolve trade log entry {}: {}", sequence, e);
+        }
+    }
+    
+    /// Resolve the trade messages a crash left half-handled
+    ///
+    /// Trades are restored from their checkpoints first. Pending outbound
+    /// messages are then published again as they were signed, and pending
+    /// inbound ones applied again to the state they found, unless a later entry
+    /// of their trade shows they took effect. A signature whose message was
+    /// never logged is reported rather than made again.
+    pub async fn recover(&self) -> Result<WalRecovery> {
+        let Some(wal) = &self.wal else { return Ok(WalRecovery::default()) };
+        let mut recovery = WalRecovery::default();
+        
+        for trade in wal.checkpoints()? {
+            self.restore_trade(trade).await;
+            recovery.restored += 1;
+        }
+        
+        let entries = wal.entries()?;
+        for entry in entries.iter().filter(|entry| !entry.resolved) {
+            match &entry.message {
+                WalMessage::Outbound { peer_id, kind, data, .. } => {
+                    info!("Resending {} to {} from the trade log", kind, peer_id);
+                    match self.network.write().await.publish(&self.trade_topic, data.clone()).await {
+                        Ok(()) => {
+                            recovery.resent += 1;
+                            wal.resolve(entry.sequence)?;
+                        }
+                        Err(e) => warn!("Failed to resend logged trade message {}: {}", entry.sequence, e),
+                    }
+                }
+                WalMessage::Signing { .. } if wal::is_superseded(&entries, entry) => {
+                    wal.resolve(entry.sequence)?;
+                }
+                WalMessage::Signing { peer_id, trade_id } => {
+                    warn!("Trade {} was signed for {} but the signed message was never logged", trade_id, peer_id);
+                    recovery.unsent += 1;
+                    wal.resolve(entry.sequence)?;
+                }
+                WalMessage::Inbound { .. } if wal::is_superseded(&entries, entry) => {
+                    recovery.superseded += 1;
+                    wal.resolve(entry.sequence)?;
+                }
+                WalMessage::Inbound { peer_id, message, trade } => {
+                    if let Some(trade) = trade {
+                        self.restore_trade(trade.clone()).await;
+                    }
+                    info!("Applying logged {} of trade {} again", message.kind(), message.trade_id());
+                    if let Err(e) = self.process_trade_message(message.clone(), peer_id).await {
+                        warn!("Failed to apply logged trade message {}: {}", entry.sequence, e);
+                    }
+                    recovery.reapplied += 1;
+                    self.resolve_logged(entry.sequence, message.trade_id()).await;
+                }
+            }
+        }
+        wal.compact()?;
+        
+        Ok(recovery)
     }
 
     /// Get trade by ID
@@ -766,11 +2366,102 @@ impl TradeModule {
             .ok_or_else(|| TradeError::NotFound(trade_id.clone()).into())
     }
 
+    /// Get the stages a trade went through, with the time spent on each
+    pub async fn get_trade_timeline(&self, trade_id: &TradeId) -> Result<TradeTimeline> {
+        let trade = self.get_trade(trade_id).await?;
+        
+        Ok(TradeTimeline::of(&trade))
+    }
+    
+    /// Record that the transaction of a completed trade was confirmed in a block
+    pub async fn record_confirmed(&self, trade_id: &TradeId) -> Result<()> {
+        {
+            let mut trades = self.trades.write().await;
+            let trade = trades.get_mut(trade_id)
+                .ok_or_else(|| TradeError::NotFound(trade_id.clone()))?;
+            
+            if trade.state != TradeState::Completed {
+                return Err(TradeError::InvalidState(format!("Trade is not completed: {:?}", trade.state)).into());
+            }
+            // Both the deposit watcher and a proof may confirm a trade
+            if trade.timeline.iter().any(|entry| entry.stage == TradeStage::Confirmed) {
+                return Ok(());
+            }
+            trade.record_stage(TradeStage::Confirmed, self.clock.now_ms());
+        }
+        self.checkpoint(trade_id).await;
+        if let Some(analytics) = &self.analytics {
+            analytics.trade_confirmed(trade_id);
+        }
+        
+        Ok(())
+    }
+    
     /// Get all trades
     pub async fn get_trades(&self) -> Vec<Trade> {
         let trades = self.trades.read().await;
         trades.values().cloned().collect()
     }
+    
+    /// Get a page of trades matching a query and a further filter
+    pub async fn query_trades<F>(&self, query: &TradeQuery, keep: F) -> Result<TradePage<Trade>>
+    where
+        F: FnMut(&Trade) -> bool,
+    {
+        let trades = self.trades.read().await;
+        let index = self.index.read().await;
+        query::query_trades(&index, &trades, query, keep)
+    }
+    
+    /// Get the number of trades that have not finished yet
+    pub async fn in_flight_trades(&self) -> usize {
+        self.trades.read().await.values()
+            .filter(|trade| !matches!(
+                trade.state,
+                TradeState::Completed | TradeState::Failed | TradeState::Canceled | TradeState::Expired
+            ))
+            .count()
+    }
+
+    /// Expire the trades not signed within the trade timeout of their creation
+    ///
+    /// Trades a side has signed are left to finish, their transaction may be
+    /// out already. Returns the expired trades.
+    pub async fn expire_trades(&self) -> Vec<TradeId> {
+        if self.trade_timeout == 0 {
+            return Vec::new();
+        }
+        
+        let now = self.clock.now();
+        let expired: Vec<TradeId> = {
+            let mut trades = self.trades.write().await;
+            trades.values_mut()
+                .filter(|trade| matches!(
+                    trade.state,
+                    TradeState::Created | TradeState::MakerPsbtSent | TradeState::TakerPsbtSent
+                ))
+                .filter(|trade| trade.created_at.saturating_add(self.trade_timeout) <= now)
+                .map(|trade| {
+                    trade.update_state_at(TradeState::Expired, now);
+                    trade.id.clone()
+                })
+                .collect()
+        };
+        
+        for trade_id in &expired {
+            info!("Trade {} timed out", trade_id);
+            if let Some(analytics) = &self.analytics {
+                analytics.trade_finished(trade_id);
+            }
+            let _ = self.event_sender
+                .send(Event::TradeFailed(trade_id.clone()))
+                .await;
+            self.checkpoint(trade_id).await;
+            self.settle_private_orders(trade_id).await;
+        }
+        
+        expired
+    }
 
     /// Cancel trade
     pub async fn cancel_trade(&self, trade_id: &TradeId, reason: &str) -> Result<()> {
@@ -786,7 +2477,10 @@ impl TradeModule {
         }
         
         // Update trade
-        trade.update_state(TradeState::Canceled);
+        trade.update_state_at_ms(TradeState::Canceled, self.clock.now_ms());
+        if let Some(analytics) = &self.analytics {
+            analytics.trade_finished(trade_id);
+        }
         
         // Send cancel message
         let network = self.network.read().await;
@@ -812,12 +2506,22 @@ impl TradeModule {
         let _ = self.event_sender
             .send(Event::TradeFailed(trade.id.clone()))
             .await;
+        drop(trades);
+        self.checkpoint(trade_id).await;
+        self.settle_private_orders(trade_id).await;
+        
+        // The canceled trade frees a slot for a queued proposal
+        self.process_queue().await;
         
         Ok(())
     }
 
     /// Get order by ID
     async fn get_order_by_id(&self, order_id: &OrderId) -> Result<Order> {
+        if let Some(order) = self.private_orders.read().await.get(order_id) {
+            return Ok(order.clone());
+        }
+        
         // In a real implementation, we would get the order from the orderbook
         // For now, just create a dummy order
         let order = Order {
@@ -831,6 +2535,9 @@ impl TradeModule {
             status: OrderStatus::Open,
             timestamp: 0,
             expiry: 0,
+            hints: Default::default(),
+            fees: None,
+            referral_code: None,
         };
 
         Ok(order)
@@ -856,3 +2563,23 @@ fn price_to_u64(price: Decimal) -> Result<u64> {
     satoshis.to_u64()
         .ok_or_else(|| anyhow::anyhow!("Failed to convert price to u64"))
 }
+
+/// Spawn a task expiring timed out trades and sweeping the proposal queue every `interval`
+///
+/// The sweep opens queued proposals the freed slots fit, and cancels those held
+/// past the queue timeout even when no trade message arrives.
+pub fn spawn_trade_expiry(trade_manager: Arc<TradeModule>, interval: std::time::Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        
+        loop {
+            interval.tick().await;
+            
+            let expired = trade_manager.expire_trades().await;
+            if !expired.is_empty() {
+                info!("Expired {} timed out trades", expired.len());
+            }
+            trade_manager.process_queue().await;
+        }
+    })
+}
@@ -0,0 +1,263 @@
+//! Outbound trade message queue
+//!
+//! A trade message published while its counterparty is offline for a moment
+//! is lost, and the trade stalls until it times out. Every signed trade message
+//! is therefore kept in a per-peer outbox until the counterparty acknowledges
+//! it with an [`TradeMessage::Ack`](super::TradeMessage::Ack) naming its
+//! nonce. Messages not acknowledged are sent again directly to the peer, every
+//! `retry_interval` and as soon as the peer connects again, until their TTL
+//! runs out.
+//!
+//! A retry is the same signed envelope, so the receiver's replay guard drops
+//! copies of a message that already arrived; it acknowledges them again all the
+//! same, in case the first acknowledgment was lost. Receivers reject envelopes
+//! older than their message window, so a TTL beyond it buys nothing.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::{Storage, StorageExt};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::TradeModule;
+use crate::config::OutboxConfig;
+use crate::events::EventReceiver;
+use crate::types::{Event, TradeId};
+
+/// Storage namespace of the outbox
+pub const OUTBOX_NAMESPACE: &str = "trade_outbox";
+
+/// Request-response protocol directed trade messages are sent over
+pub const TRADE_PROTOCOL: &str = "/darkswap/trade/1.0.0";
+
+/// Trade message waiting for an acknowledgment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Recipient
+    pub peer_id: String,
+    /// Trade the message belongs to
+    pub trade_id: TradeId,
+    /// Message kind, for inspection
+    pub kind: String,
+    /// Nonce of the signed envelope, echoed in the acknowledgment
+    pub nonce: u64,
+    /// Encoded signed envelope
+    pub data: Vec<u8>,
+    /// Time the message was first sent (unix seconds)
+    pub queued_at: u64,
+    /// Time after which the message is given up on (unix seconds)
+    pub expires_at: u64,
+    /// Times the message was sent
+    pub attempts: u32,
+    /// Time the message was last sent (unix seconds)
+    pub last_attempt_at: u64,
+}
+
+/// Trade message given up on before its counterparty acknowledged it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UndeliveredMessage {
+    /// Recipient
+    pub peer_id: String,
+    /// Trade the message belongs to
+    pub trade_id: TradeId,
+    /// Message kind
+    pub kind: String,
+    /// Times the message was sent
+    pub attempts: u32,
+}
+
+impl From<OutboxEntry> for UndeliveredMessage {
+    fn from(entry: OutboxEntry) -> Self {
+        Self {
+            peer_id: entry.peer_id,
+            trade_id: entry.trade_id,
+            kind: entry.kind,
+            attempts: entry.attempts,
+        }
+    }
+}
+
+/// Get the key of a message
+fn entry_key(peer_id: &str, nonce: u64) -> String {
+    format!("{}/{:020}", peer_id, nonce)
+}
+
+/// Per-peer queue of the trade messages not acknowledged yet
+pub struct Outbox {
+    /// Storage the messages are kept in
+    storage: Arc<dyn Storage>,
+    /// TTL and retry interval
+    config: OutboxConfig,
+}
+
+impl Outbox {
+    /// Open the outbox kept in `storage`
+    pub fn open(storage: Arc<dyn Storage>, config: OutboxConfig) -> Self {
+        Self { storage, config }
+    }
+
+    /// Get the configuration
+    pub fn config(&self) -> &OutboxConfig {
+        &self.config
+    }
+
+    /// Keep a message sent at `now` until it is acknowledged or expires
+    pub fn push(&self, peer_id: &str, trade_id: &TradeId, kind: &str, nonce: u64, data: Vec<u8>, now: u64) -> Result<()> {
+        let entry = OutboxEntry {
+            peer_id: peer_id.to_string(),
+            trade_id: trade_id.clone(),
+            kind: kind.to_string(),
+            nonce,
+            data,
+            queued_at: now,
+            expires_at: now.saturating_add(self.config.ttl),
+            attempts: 1,
+            last_attempt_at: now,
+        };
+        self.write(&entry)?;
+        self.storage.flush().context("Failed to flush the trade outbox")
+    }
+
+    /// Write an entry to storage
+    fn write(&self, entry: &OutboxEntry) -> Result<()> {
+        self.storage
+            .put_json(OUTBOX_NAMESPACE, &entry_key(&entry.peer_id, entry.nonce), entry)
+            .context("Failed to write to the trade outbox")
+    }
+
+    /// Drop a message its recipient acknowledged, returning whether it was waiting
+    pub fn acknowledge(&self, peer_id: &str, nonce: u64) -> Result<bool> {
+        let removed = self.storage
+            .delete(OUTBOX_NAMESPACE, entry_key(peer_id, nonce).as_bytes())
+            .context("Failed to delete from the trade outbox")?;
+        if removed {
+            self.storage.flush()?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Get the messages waiting for a peer, or for every peer
+    pub fn pending(&self, peer_id: Option<&str>) -> Result<Vec<OutboxEntry>> {
+        let prefix = peer_id.map(|peer_id| format!("{}/", peer_id)).unwrap_or_default();
+        let entries = self.storage
+            .scan_json::<OutboxEntry>(OUTBOX_NAMESPACE, &prefix)
+            .context("Failed to read the trade outbox")?;
+
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    /// Drop the messages expired at `now`, returning them
+    pub fn expire(&self, now: u64) -> Result<Vec<OutboxEntry>> {
+        let expired: Vec<OutboxEntry> = self.pending(None)?
+            .into_iter()
+            .filter(|entry| entry.expires_at <= now)
+            .collect();
+        for entry in &expired {
+            self.storage.delete(OUTBOX_NAMESPACE, entry_key(&entry.peer_id, entry.nonce).as_bytes())?;
+        }
+        if !expired.is_empty() {
+            self.storage.flush()?;
+        }
+
+        Ok(expired)
+    }
+
+    /// Get the live messages to send again at `now`: all of a peer that just connected, or those last sent a retry interval ago
+    pub fn due(&self, connected_peer: Option<&str>, now: u64) -> Result<Vec<OutboxEntry>> {
+        let entries = self.pending(connected_peer)?
+            .into_iter()
+            .filter(|entry| entry.expires_at > now)
+            .filter(|entry| connected_peer.is_some() || entry.last_attempt_at.saturating_add(self.config.retry_interval) <= now)
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Record that a message was sent again at `now`
+    pub fn record_attempt(&self, entry: &OutboxEntry, now: u64) -> Result<()> {
+        let mut entry = entry.clone();
+        entry.attempts += 1;
+        entry.last_attempt_at = now;
+
+        self.write(&entry)
+    }
+}
+
+/// Outcome of a retry round
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxRetry {
+    /// Messages sent again
+    pub resent: usize,
+    /// Messages given up on
+    pub expired: usize,
+    /// Messages that could not be sent again, left for the next round
+    pub failed: usize,
+}
+
+/// Spawn a task sending unacknowledged trade messages again every `interval`, and to peers as they connect
+pub fn spawn_outbox_retrier(
+    trade_manager: Arc<TradeModule>,
+    mut events: EventReceiver,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            let connected_peer = tokio::select! {
+                _ = interval.tick() => None,
+                event = events.recv() => match event {
+                    Some(Event::PeerConnected(peer_id)) => Some(peer_id.0.to_string()),
+                    Some(_) => continue,
+                    None => break,
+                },
+            };
+
+            match trade_manager.retry_outbox(connected_peer.as_deref()).await {
+                Ok(retry) if retry != OutboxRetry::default() => debug!("Trade outbox retry: {:?}", retry),
+                Ok(_) => {}
+                Err(e) => warn!("Trade outbox retry failed: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkswap_support::storage::MemoryStorage;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_outbox() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let outbox = Outbox::open(storage.clone(), OutboxConfig { enabled: true, ttl: 120, retry_interval: 10 });
+        let trade_id = TradeId("trade-1".to_string());
+
+        outbox.push("peer-a", &trade_id, "SendPsbt", 1, vec![1], NOW).unwrap();
+        outbox.push("peer-a", &trade_id, "SignPsbt", 2, vec![2], NOW + 5).unwrap();
+        outbox.push("peer-b", &trade_id, "Cancel", 3, vec![3], NOW).unwrap();
+
+        // Retries wait for the interval, unless the peer just connected
+        let due = outbox.due(None, NOW + 10).unwrap();
+        assert_eq!(due.iter().map(|entry| entry.nonce).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(outbox.due(Some("peer-a"), NOW + 6).unwrap().len(), 2);
+
+        outbox.record_attempt(&due[0], NOW + 10).unwrap();
+        assert!(outbox.acknowledge("peer-a", 2).unwrap());
+        assert!(!outbox.acknowledge("peer-a", 2).unwrap());
+
+        // A reopened outbox still holds what is not acknowledged
+        let outbox = Outbox::open(storage, OutboxConfig { enabled: true, ttl: 120, retry_interval: 10 });
+        let pending = outbox.pending(Some("peer-a")).unwrap();
+        assert_eq!((pending.len(), pending[0].attempts), (1, 2));
+
+        let expired = outbox.expire(NOW + 120).unwrap();
+        assert_eq!(expired.len(), 2);
+        assert!(outbox.pending(None).unwrap().is_empty());
+    }
+}
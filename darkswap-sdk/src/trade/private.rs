@@ -0,0 +1,209 @@
+//! Orders outside the public orderbook
+//!
+//! The private orders of RFQ quotes and private groups are traded with the
+//! standard trade protocol but never enter the orderbook. The store keeps each
+//! one with the peers allowed to take it and the amount its trades take, so an
+//! order cannot be taken for more than it offers. An order is dropped once its
+//! trades filled it, or once it expired with no trade in flight.
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use super::batch::TradeLeg;
+use super::TradeError;
+use crate::orderbook::{Order, OrderId};
+use crate::types::TradeId;
+
+/// Order outside the public orderbook
+#[derive(Debug, Clone)]
+struct PrivateOrder {
+    /// Order
+    order: Order,
+    /// Peers allowed to take the order; anyone that knows it if empty
+    takers: Vec<String>,
+    /// Amount taken by trades in flight and completed trades
+    taken: Decimal,
+    /// Trades in flight on the order, with the amount each takes
+    trades: HashMap<TradeId, Decimal>,
+}
+
+impl PrivateOrder {
+    /// Check if the order is filled or expired, with no trade in flight
+    fn is_done(&self, now: u64) -> bool {
+        self.trades.is_empty() && (self.taken >= self.order.amount || self.order.is_expired_at(now))
+    }
+}
+
+/// Orders outside the public orderbook, by ID
+#[derive(Debug, Default)]
+pub struct PrivateOrders {
+    /// Orders
+    orders: HashMap<OrderId, PrivateOrder>,
+}
+
+impl PrivateOrders {
+    /// Add an order that `takers` can take, or anyone if empty
+    ///
+    /// Adding an order again keeps what its trades already took.
+    pub fn insert(&mut self, order: Order, takers: Vec<String>) {
+        match self.orders.get_mut(&order.id) {
+            Some(existing) => {
+                existing.order = order;
+                existing.takers = takers;
+            }
+            None => {
+                self.orders.insert(order.id.clone(), PrivateOrder {
+                    order,
+                    takers,
+                    taken: Decimal::ZERO,
+                    trades: HashMap::new(),
+                });
+            }
+        }
+    }
+
+    /// Get an order
+    pub fn get(&self, order_id: &OrderId) -> Option<&Order> {
+        self.orders.get(order_id).map(|entry| &entry.order)
+    }
+
+    /// Remove an order
+    pub fn remove(&mut self, order_id: &OrderId) -> Option<Order> {
+        self.orders.remove(order_id).map(|entry| entry.order)
+    }
+
+    /// Get the amount of an order not taken yet
+    pub fn remaining(&self, order_id: &OrderId) -> Option<Decimal> {
+        self.orders.get(order_id).map(|entry| entry.order.amount - entry.taken)
+    }
+
+    /// Take the private orders among the legs of a trade with `taker`
+    ///
+    /// Legs on public orders are left alone. Nothing is taken unless every
+    /// private leg is open to the taker, not expired and within what is left of
+    /// its order.
+    pub fn take(&mut self, trade_id: &TradeId, legs: &[TradeLeg], taker: &str, now: u64) -> Result<(), TradeError> {
+        let checked = self.check(legs, taker, now);
+        if checked.is_ok() {
+            for leg in legs {
+                if let Some(entry) = self.orders.get_mut(&leg.order_id) {
+                    entry.taken += leg.amount;
+                    *entry.trades.entry(trade_id.clone()).or_default() += leg.amount;
+                }
+            }
+        }
+
+        self.orders.retain(|_, entry| !entry.is_done(now));
+        checked
+    }
+
+    /// Check that the private legs of a trade can be taken by `taker`
+    fn check(&self, legs: &[TradeLeg], taker: &str, now: u64) -> Result<(), TradeError> {
+        for leg in legs {
+            let Some(entry) = self.orders.get(&leg.order_id) else { continue };
+
+            if !entry.takers.is_empty() && !entry.takers.iter().any(|allowed| allowed == taker) {
+                return Err(TradeError::InvalidState(format!("Order {} is not open to peer {}", leg.order_id, taker)));
+            }
+            if entry.order.is_expired_at(now) {
+                return Err(TradeError::InvalidState(format!("Order {} has expired", leg.order_id)));
+            }
+            let remaining = entry.order.amount - entry.taken;
+            if leg.amount <= Decimal::ZERO || leg.amount > remaining {
+                return Err(TradeError::InvalidState(format!(
+                    "Amount {} exceeds the {} left of order {}",
+                    leg.amount, remaining, leg.order_id
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Settle the orders a trade took, once it reached a terminal state
+    ///
+    /// A completed trade keeps what it took; any other gives it back. Orders left
+    /// filled or expired are dropped.
+    pub fn settle(&mut self, trade_id: &TradeId, completed: bool, now: u64) {
+        for entry in self.orders.values_mut() {
+            if let Some(amount) = entry.trades.remove(trade_id) {
+                if !completed {
+                    entry.taken -= amount;
+                }
+            }
+        }
+
+        self.orders.retain(|_, entry| !entry.is_done(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+    use crate::types::Asset;
+    use rust_decimal_macros::dec;
+
+    fn order(amount: Decimal, expiry: u64) -> Order {
+        let mut order = Order::new("maker".to_string(), Asset::Bitcoin, Asset::Rune(1), OrderSide::Sell, amount, dec!(100), None);
+        order.expiry = expiry;
+        order
+    }
+
+    fn leg(order: &Order, amount: Decimal) -> TradeLeg {
+        TradeLeg { order_id: order.id.clone(), amount, price: order.price }
+    }
+
+    fn trade_id(id: &str) -> TradeId {
+        TradeId(id.to_string())
+    }
+
+    #[test]
+    fn test_quote_is_taken_once() {
+        let quoted = order(dec!(10), 1_000);
+        let mut orders = PrivateOrders::default();
+        orders.insert(quoted.clone(), vec!["taker".to_string()]);
+
+        // Only the taker it was quoted to, and only for the quoted amount
+        assert!(orders.take(&trade_id("t0"), &[leg(&quoted, dec!(10))], "other", 100).is_err());
+        assert!(orders.take(&trade_id("t0"), &[leg(&quoted, dec!(11))], "taker", 100).is_err());
+
+        orders.take(&trade_id("t1"), &[leg(&quoted, dec!(10))], "taker", 100).unwrap();
+        assert!(orders.take(&trade_id("t2"), &[leg(&quoted, dec!(1))], "taker", 100).is_err());
+
+        // The order stays known until its trade settles, then is dropped
+        assert!(orders.get(&quoted.id).is_some());
+        orders.settle(&trade_id("t1"), true, 100);
+        assert!(orders.get(&quoted.id).is_none());
+    }
+
+    #[test]
+    fn test_failed_trade_gives_back_its_amount() {
+        let group_order = order(dec!(10), 1_000);
+        let mut orders = PrivateOrders::default();
+        orders.insert(group_order.clone(), Vec::new());
+
+        orders.take(&trade_id("t1"), &[leg(&group_order, dec!(4))], "a", 100).unwrap();
+        orders.take(&trade_id("t2"), &[leg(&group_order, dec!(6))], "b", 100).unwrap();
+        assert_eq!(orders.remaining(&group_order.id), Some(dec!(0)));
+
+        orders.settle(&trade_id("t2"), false, 100);
+        assert_eq!(orders.remaining(&group_order.id), Some(dec!(6)));
+
+        orders.settle(&trade_id("t1"), true, 100);
+        orders.take(&trade_id("t3"), &[leg(&group_order, dec!(6))], "c", 100).unwrap();
+        orders.settle(&trade_id("t3"), true, 100);
+        assert!(orders.get(&group_order.id).is_none());
+    }
+
+    #[test]
+    fn test_expired_order_is_dropped() {
+        let quoted = order(dec!(10), 1_000);
+        let mut orders = PrivateOrders::default();
+        orders.insert(quoted.clone(), Vec::new());
+
+        assert!(orders.take(&trade_id("t1"), &[leg(&quoted, dec!(1))], "taker", 1_001).is_err());
+        assert!(orders.get(&quoted.id).is_none());
+    }
+}
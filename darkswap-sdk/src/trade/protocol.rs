@@ -0,0 +1,253 @@
+//! Replay protection for trade protocol messages
+//!
+//! Trade messages travel over gossip and relays, so a malicious relay could keep a
+//! message and deliver it again later. Every message is therefore sent in an
+//! envelope carrying a random nonce and the time it was sent, and the sender signs
+//! the nonce, the trade ID and the message together. An envelope is accepted once,
+//! and only within an acceptance window around its timestamp. Older envelopes are
+//! rejected on their timestamp alone, so nonces are only remembered for as long as
+//! the window lasts.
+
+use std::collections::HashMap;
+
+use darkswap_support::envelope::{EnvelopeError, Versioned};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::TradeMessage;
+use crate::types::TradeId;
+
+/// Default acceptance window of trade messages (seconds)
+pub const DEFAULT_MESSAGE_WINDOW: u64 = 120;
+
+/// Replay protection error
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    /// The envelope was accepted before
+    #[error("Replayed message: nonce {nonce} for trade {trade_id} was already accepted")]
+    Replayed {
+        /// Trade ID
+        trade_id: TradeId,
+        /// Nonce
+        nonce: u64,
+    },
+    /// The envelope was sent too long ago, or claims to be sent in the future
+    #[error("Message outside the acceptance window: sent at {timestamp}, now {now}")]
+    OutsideWindow {
+        /// Time the envelope claims to be sent at (unix seconds)
+        timestamp: u64,
+        /// Time of the check (unix seconds)
+        now: u64,
+    },
+    /// Invalid signature
+    #[error("Invalid message signature: {0}")]
+    InvalidSignature(String),
+}
+
+/// Trade message in a signed envelope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedTradeMessage {
+    /// Sender peer ID
+    pub sender: String,
+    /// Random nonce, unique within the trade session
+    pub nonce: u64,
+    /// Time the message was sent (unix seconds)
+    pub timestamp: u64,
+    /// Message
+    pub message: TradeMessage,
+    /// Sender public key (protobuf encoded, hex)
+    pub public_key: String,
+    /// Sender signature over the nonce, trade ID, timestamp and message (hex)
+    pub signature: String,
+}
+
+impl SignedTradeMessage {
+    /// Sign a message with a fresh nonce, sent at `now` (unix seconds)
+    pub fn sign(message: TradeMessage, keypair: &Keypair, now: u64) -> Result<Self, ReplayError> {
+        let nonce = rand::thread_rng().next_u64();
+        Self::sign_at(message, keypair, nonce, now)
+    }
+
+    /// Sign a message with a given nonce and timestamp
+    fn sign_at(message: TradeMessage, keypair: &Keypair, nonce: u64, timestamp: u64) -> Result<Self, ReplayError> {
+        let bytes = signing_bytes(nonce, timestamp, &message)?;
+        let signature = keypair
+            .sign(&bytes)
+            .map_err(|e| ReplayError::InvalidSignature(format!("Failed to sign message: {}", e)))?;
+
+        Ok(Self {
+            sender: PeerId::from(keypair.public()).to_string(),
+            nonce,
+            timestamp,
+            message,
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Verify the sender signature
+    pub fn verify(&self) -> Result<(), ReplayError> {
+        let public_key_bytes = hex::decode(&self.public_key)
+            .map_err(|e| ReplayError::InvalidSignature(format!("Invalid public key encoding: {}", e)))?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes)
+            .map_err(|e| ReplayError::InvalidSignature(format!("Invalid public key: {}", e)))?;
+
+        // The key must belong to the sender
+        if PeerId::from_public_key(&public_key).to_string() != self.sender {
+            return Err(ReplayError::InvalidSignature("Signing key does not match sender".to_string()));
+        }
+
+        let signature = hex::decode(&self.signature)
+            .map_err(|e| ReplayError::InvalidSignature(format!("Invalid signature encoding: {}", e)))?;
+        let bytes = signing_bytes(self.nonce, self.timestamp, &self.message)?;
+
+        if !public_key.verify(&bytes, &signature) {
+            return Err(ReplayError::InvalidSignature("Signature does not match message".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+impl Versioned for SignedTradeMessage {
+    const KIND: &'static str = "darkswap/trade/message";
+    const VERSION: u32 = 2;
+
+    fn upgrade(from: u32, _payload: serde_json::Value) -> Result<serde_json::Value, EnvelopeError> {
+        // Version 1 was the bare trade message, which carried no nonce or signature
+        Err(EnvelopeError::Obsolete {
+            kind: Self::KIND,
+            version: from,
+            reason: "unsigned trade messages can be replayed and are no longer accepted".to_string(),
+        })
+    }
+}
+
+/// Nonces accepted within the acceptance window
+#[derive(Debug)]
+pub struct ReplayGuard {
+    /// Acceptance window (seconds)
+    window: u64,
+    /// Accepted nonces by trade and sender, with their timestamps
+    seen: HashMap<(TradeId, String, u64), u64>,
+}
+
+impl ReplayGuard {
+    /// Create a guard accepting messages up to `window` seconds old
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Accept a verified envelope once, if it is within the window at `now`
+    pub fn check_at(&mut self, envelope: &SignedTradeMessage, now: u64) -> Result<(), ReplayError> {
+        // Timestamps come from the sender, so the sums must not overflow
+        if envelope.timestamp.saturating_add(self.window) < now || envelope.timestamp > now.saturating_add(self.window) {
+            return Err(ReplayError::OutsideWindow { timestamp: envelope.timestamp, now });
+        }
+
+        // Nonces older than the window are rejected on their timestamp already
+        let window = self.window;
+        self.seen.retain(|_, timestamp| timestamp.saturating_add(window) >= now);
+
+        let trade_id = envelope.message.trade_id().clone();
+        let key = (trade_id.clone(), envelope.sender.clone(), envelope.nonce);
+        if self.seen.insert(key, envelope.timestamp).is_some() {
+            return Err(ReplayError::Replayed { trade_id, nonce: envelope.nonce });
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        Self::new(DEFAULT_MESSAGE_WINDOW)
+    }
+}
+
+/// Get the bytes covered by the sender signature
+fn signing_bytes(nonce: u64, timestamp: u64, message: &TradeMessage) -> Result<Vec<u8>, ReplayError> {
+    serde_json::to_vec(&(nonce, message.trade_id(), timestamp, message))
+        .map_err(|e| ReplayError::InvalidSignature(format!("Failed to serialize message: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(trade_id: &str) -> TradeMessage {
+        TradeMessage::Broadcast {
+            trade_id: TradeId(trade_id.to_string()),
+            txid: "00".repeat(32),
+        }
+    }
+
+    #[test]
+    fn test_signature_covers_nonce_and_message() {
+        let keypair = Keypair::generate_ed25519();
+        let envelope = SignedTradeMessage::sign(message("trade"), &keypair, 1_000).unwrap();
+        assert!(envelope.verify().is_ok());
+
+        let mut renonced = envelope.clone();
+        renonced.nonce = renonced.nonce.wrapping_add(1);
+        assert!(renonced.verify().is_err());
+
+        let mut moved = envelope;
+        moved.message = message("other");
+        assert!(moved.verify().is_err());
+    }
+
+    #[test]
+    fn test_replayed_envelope_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let envelope = SignedTradeMessage::sign_at(message("trade"), &keypair, 7, 1_000).unwrap();
+        let mut guard = ReplayGuard::new(60);
+
+        assert!(guard.check_at(&envelope, 1_010).is_ok());
+        assert!(matches!(guard.check_at(&envelope, 1_020), Err(ReplayError::Replayed { nonce: 7, .. })));
+
+        // A fresh nonce for the same trade is accepted
+        let next = SignedTradeMessage::sign_at(message("trade"), &keypair, 8, 1_020).unwrap();
+        assert!(guard.check_at(&next, 1_020).is_ok());
+    }
+
+    #[test]
+    fn test_envelope_outside_window_is_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let envelope = SignedTradeMessage::sign_at(message("trade"), &keypair, 7, 1_000).unwrap();
+        let mut guard = ReplayGuard::new(60);
+
+        assert!(matches!(guard.check_at(&envelope, 1_061), Err(ReplayError::OutsideWindow { .. })));
+        assert!(matches!(guard.check_at(&envelope, 939), Err(ReplayError::OutsideWindow { .. })));
+    }
+
+    #[test]
+    fn test_envelope_at_the_end_of_time_does_not_overflow() {
+        let keypair = Keypair::generate_ed25519();
+        let envelope = SignedTradeMessage::sign_at(message("trade"), &keypair, 7, u64::MAX).unwrap();
+        let mut guard = ReplayGuard::new(60);
+
+        assert!(matches!(guard.check_at(&envelope, 1_000), Err(ReplayError::OutsideWindow { .. })));
+        assert!(guard.check_at(&envelope, u64::MAX).is_ok());
+        assert!(matches!(guard.check_at(&envelope, u64::MAX), Err(ReplayError::Replayed { nonce: 7, .. })));
+    }
+
+    #[test]
+    fn test_forgotten_nonce_stays_rejected() {
+        let keypair = Keypair::generate_ed25519();
+        let envelope = SignedTradeMessage::sign_at(message("trade"), &keypair, 7, 1_000).unwrap();
+        let mut guard = ReplayGuard::new(60);
+        assert!(guard.check_at(&envelope, 1_000).is_ok());
+
+        // Checking a later message forgets the nonce, but the window still holds
+        let later = SignedTradeMessage::sign_at(message("trade"), &keypair, 8, 1_100).unwrap();
+        assert!(guard.check_at(&later, 1_100).is_ok());
+        assert!(guard.check_at(&envelope, 1_100).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! PSBT signature verification
+//!
+//! Checking the signatures a counterparty put in its PSBT takes a signature
+//! hash and a curve operation per input, enough to stall the event loop when a
+//! burst of trades arrives. The [`VerificationPool`] runs these checks, and
+//! other CPU-bound proofs such as signed orders, on Tokio's blocking threads,
+//! with at most a configured number at once so a burst cannot take over every
+//! core.
+
+use std::sync::Arc;
+
+use bitcoin::consensus::Decodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::secp256k1::{Message, Secp256k1, VerifyOnly, XOnlyPublicKey};
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::{Script, Transaction, TxOut};
+use tokio::sync::Semaphore;
+
+use super::TradeError;
+
+/// Get the output an input spends, from the PSBT's UTXO fields
+fn spent_output(psbt: &Psbt, index: usize) -> Option<TxOut> {
+    let input = &psbt.inputs[index];
+    if let Some(output) = &input.witness_utxo {
+        return Some(output.clone());
+    }
+
+    let vout = psbt.unsigned_tx.input[index].previous_output.vout as usize;
+    input.non_witness_utxo.as_ref()?.output.get(vout).cloned()
+}
+
+/// Check the signatures present on an input
+fn verify_input(
+    secp: &Secp256k1<VerifyOnly>,
+    psbt: &Psbt,
+    index: usize,
+    spent: &[Option<TxOut>],
+    cache: &mut SighashCache<&Transaction>,
+) -> Result<(), TradeError> {
+    let input = &psbt.inputs[index];
+    if input.partial_sigs.is_empty() && input.tap_key_sig.is_none() {
+        return Ok(());
+    }
+
+    let output = spent[index].as_ref()
+        .ok_or_else(|| TradeError::PsbtError(format!("Input {} is signed but lacks the output it spends", index)))?;
+    let invalid = || TradeError::PsbtError(format!("Invalid signature on input {}", index));
+
+    for (public_key, signature) in &input.partial_sigs {
+        // Segwit inputs commit to the script code, legacy ones to the spent script
+        let script = input.redeem_script.as_ref().unwrap_or(&output.script_pubkey);
+        let sighash = if let Some(witness_script) = &input.witness_script {
+            cache.segwit_signature_hash(index, witness_script, output.value, signature.hash_ty)
+        } else if script.is_v0_p2wpkh() {
+            let script_code = Script::new_p2pkh(&public_key.pubkey_hash());
+            cache.segwit_signature_hash(index, &script_code, output.value, signature.hash_ty)
+        } else {
+            cache.legacy_signature_hash(index, script, signature.hash_ty.to_u32())
+        }
+        .map_err(|e| TradeError::PsbtError(format!("Failed to compute signature hash of input {}: {}", index, e)))?;
+
+        let message = Message::from_slice(&sighash[..]).map_err(|_| invalid())?;
+        secp.verify_ecdsa(&message, &signature.sig, &public_key.inner).map_err(|_| invalid())?;
+    }
+
+    if let Some(signature) = &input.tap_key_sig {
+        if !output.script_pubkey.is_v1_p2tr() {
+            return Err(invalid());
+        }
+        // Key-path signatures commit to every spent output
+        let prevouts = spent.iter().cloned().collect::<Option<Vec<_>>>()
+            .ok_or_else(|| TradeError::PsbtError(format!("Input {} is signed but the PSBT lacks spent outputs", index)))?;
+        let sighash = cache
+            .taproot_key_spend_signature_hash(index, &Prevouts::All(&prevouts), signature.hash_ty)
+            .map_err(|e| TradeError::PsbtError(format!("Failed to compute signature hash of input {}: {}", index, e)))?;
+
+        let output_key = XOnlyPublicKey::from_slice(&output.script_pubkey.as_bytes()[2..]).map_err(|_| invalid())?;
+        let message = Message::from_slice(&sighash[..]).map_err(|_| invalid())?;
+        secp.verify_schnorr(&signature.sig, &message, &output_key).map_err(|_| invalid())?;
+    }
+
+    Ok(())
+}
+
+/// Check every signature present in a consensus-encoded PSBT
+///
+/// Unsigned inputs pass; whether the PSBT is fully signed is up to the caller.
+pub fn verify_signatures(psbt: &[u8]) -> Result<(), TradeError> {
+    let psbt = Psbt::consensus_decode(&mut &psbt[..])
+        .map_err(|e| TradeError::PsbtError(format!("Failed to decode PSBT: {}", e)))?;
+
+    verify_psbt_signatures(&psbt)
+}
+
+/// Check every signature present in a PSBT
+pub fn verify_psbt_signatures(psbt: &Psbt) -> Result<(), TradeError> {
+    let secp = Secp256k1::verification_only();
+    let spent: Vec<Option<TxOut>> = (0..psbt.inputs.len()).map(|index| spent_output(psbt, index)).collect();
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+
+    for index in 0..psbt.inputs.len() {
+        verify_input(&secp, psbt, index, &spent, &mut cache)?;
+    }
+
+    Ok(())
+}
+
+/// Bounded pool of blocking threads for signature checks
+#[derive(Debug, Clone)]
+pub struct VerificationPool {
+    /// Checks allowed at once
+    permits: Arc<Semaphore>,
+}
+
+impl Default for VerificationPool {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl VerificationPool {
+    /// Create a pool running up to `concurrency` checks at once; 0 uses one per core
+    pub fn new(concurrency: usize) -> Self {
+        let concurrency = match concurrency {
+            0 => std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+            concurrency => concurrency,
+        };
+
+        Self { permits: Arc::new(Semaphore::new(concurrency)) }
+    }
+
+    /// Run a CPU-bound check on a blocking thread, waiting for a free slot first
+    pub async fn run<T, F>(&self, check: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let _permit = self.permits.acquire().await?;
+        Ok(tokio::task::spawn_blocking(check).await?)
+    }
+
+    /// Check every signature present in a trade PSBT
+    ///
+    /// Payloads that do not decode as PSBTs are refused.
+    pub async fn verify_psbt(&self, psbt: Vec<u8>) -> anyhow::Result<()> {
+        Ok(self.run(move || verify_signatures(&psbt)).await??)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::Encodable;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::{EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, Sequence, TxIn, Witness};
+
+    /// PSBT spending a P2WPKH output, signed by `signer`
+    fn signed_psbt(signer: &SecretKey) -> Psbt {
+        let secp = Secp256k1::new();
+        let public_key = PublicKey::new(SecretKey::from_slice(&[1u8; 32]).unwrap().public_key(&secp));
+        let spent = TxOut {
+            value: 100_000,
+            script_pubkey: Script::new_v0_p2wpkh(&public_key.wpubkey_hash().unwrap()),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut { value: 90_000, script_pubkey: Script::new() }],
+        };
+
+        let script_code = Script::new_p2pkh(&public_key.pubkey_hash());
+        let sighash = SighashCache::new(&tx)
+            .segwit_signature_hash(0, &script_code, spent.value, EcdsaSighashType::All)
+            .unwrap();
+        let signature = EcdsaSig::sighash_all(secp.sign_ecdsa(&Message::from_slice(&sighash[..]).unwrap(), signer));
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(spent);
+        psbt.inputs[0].partial_sigs.insert(public_key, signature);
+        psbt
+    }
+
+    fn encode(psbt: &Psbt) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        psbt.consensus_encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_verify_signatures() {
+        let valid = signed_psbt(&SecretKey::from_slice(&[1u8; 32]).unwrap());
+        assert!(verify_signatures(&encode(&valid)).is_ok());
+
+        // Signed by another key than the one it claims
+        let forged = signed_psbt(&SecretKey::from_slice(&[2u8; 32]).unwrap());
+        assert!(matches!(verify_signatures(&encode(&forged)), Err(TradeError::PsbtError(_))));
+
+        // A signed input must carry the output it spends
+        let mut missing = valid.clone();
+        missing.inputs[0].witness_utxo = None;
+        assert!(verify_signatures(&encode(&missing)).is_err());
+
+        // Unsigned inputs pass
+        let mut unsigned = valid;
+        unsigned.inputs[0].partial_sigs.clear();
+        assert!(verify_signatures(&encode(&unsigned)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_verification_pool() {
+        let pool = VerificationPool::new(2);
+        let valid = encode(&signed_psbt(&SecretKey::from_slice(&[1u8; 32]).unwrap()));
+        let forged = encode(&signed_psbt(&SecretKey::from_slice(&[2u8; 32]).unwrap()));
+
+        let results = futures::future::join_all((0..8).map(|i| {
+            let psbt = if i % 2 == 0 { valid.clone() } else { forged.clone() };
+            pool.verify_psbt(psbt)
+        }))
+        .await;
+
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result.is_ok(), i % 2 == 0);
+        }
+
+        // Anything but a PSBT is refused
+        assert!(pool.verify_psbt(b"not a psbt".to_vec()).await.is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Trade queries
+//!
+//! Trades are indexed by creation time, overall and per pair and per
+//! counterparty, so filtered listings walk only the trades that can match.
+//! Listings are sorted by creation time and paginated with an opaque cursor
+//! naming the last trade of the previous page; trades created while paging
+//! neither repeat nor shift later pages.
+//!
+//! Trades are kept in storage under [`TRADE_NAMESPACE`], and the index is
+//! rebuilt from them when the node starts.
+
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+use anyhow::{anyhow, Context, Result};
+use darkswap_support::storage::{Storage, StorageExt};
+use serde::{Deserialize, Serialize};
+
+use super::{Trade, TradeState};
+use crate::types::{Asset, TradeId};
+
+/// Storage namespace of the trades
+pub const TRADE_NAMESPACE: &str = "trades";
+
+/// Default page size
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Maximum page size
+pub const MAX_PAGE_SIZE: usize = 500;
+
+/// Sort order of a trade listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeSort {
+    /// Most recently created first
+    NewestFirst,
+    /// Least recently created first
+    OldestFirst,
+}
+
+impl Default for TradeSort {
+    fn default() -> Self {
+        Self::NewestFirst
+    }
+}
+
+/// Trade query
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeQuery {
+    /// Trade state
+    pub state: Option<TradeState>,
+    /// Base asset
+    pub base_asset: Option<Asset>,
+    /// Quote asset
+    pub quote_asset: Option<Asset>,
+    /// Maker or taker peer ID
+    pub counterparty: Option<String>,
+    /// Referral code
+    pub referral_code: Option<String>,
+    /// Created at or after (unix seconds)
+    pub since: Option<u64>,
+    /// Created at or before (unix seconds)
+    pub until: Option<u64>,
+    /// Sort order
+    pub sort: TradeSort,
+    /// Page size, `DEFAULT_PAGE_SIZE` if unset and at most `MAX_PAGE_SIZE`
+    pub limit: Option<usize>,
+    /// Cursor returned with the previous page
+    pub cursor: Option<String>,
+}
+
+impl TradeQuery {
+    /// Check if a trade matches the filters of the query
+    pub fn matches(&self, trade: &Trade) -> bool {
+        self.state.map_or(true, |state| state == trade.state)
+            && self.base_asset.as_ref().map_or(true, |asset| *asset == trade.base_asset)
+            && self.quote_asset.as_ref().map_or(true, |asset| *asset == trade.quote_asset)
+            && self.counterparty.as_ref().map_or(true, |peer_id| {
+                *peer_id == trade.maker_peer_id || *peer_id == trade.taker_peer_id
+            })
+            && self.referral_code.as_ref().map_or(true, |code| trade.referral_code.as_ref() == Some(code))
+            && self.since.map_or(true, |since| trade.created_at >= since)
+            && self.until.map_or(true, |until| trade.created_at <= until)
+    }
+
+    /// Get the page size
+    pub fn page_size(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// Page of a trade listing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradePage<T> {
+    /// Trades on the page
+    pub trades: Vec<T>,
+    /// Cursor of the next page; none on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// Index key: creation time, then trade ID
+type IndexKey = (u64, TradeId);
+
+/// Encode the cursor pointing after a trade
+pub fn encode_cursor(trade: &Trade) -> String {
+    format!("{}:{}", trade.created_at, trade.id.0)
+}
+
+/// Decode a cursor
+fn decode_cursor(cursor: &str) -> Result<IndexKey> {
+    let (created_at, trade_id) = cursor.split_once(':')
+        .ok_or_else(|| anyhow!("Invalid cursor: {}", cursor))?;
+    let created_at = created_at.parse()
+        .map_err(|_| anyhow!("Invalid cursor: {}", cursor))?;
+
+    Ok((created_at, TradeId(trade_id.to_string())))
+}
+
+/// Trade index
+///
+/// Keys only use fields that never change after a trade is created, so the
+/// index is maintained on insert alone.
+#[derive(Debug, Default)]
+pub struct TradeIndex {
+    /// All trades
+    by_time: BTreeSet<IndexKey>,
+    /// Trades by pair
+    by_pair: HashMap<(Asset, Asset), BTreeSet<IndexKey>>,
+    /// Trades by maker and taker peer ID
+    by_peer: HashMap<String, BTreeSet<IndexKey>>,
+}
+
+impl TradeIndex {
+    /// Create an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index a trade; indexing a trade again is a no-op
+    pub fn insert(&mut self, trade: &Trade) {
+        let key = (trade.created_at, trade.id.clone());
+
+        self.by_pair
+            .entry((trade.base_asset.clone(), trade.quote_asset.clone()))
+            .or_default()
+            .insert(key.clone());
+        for peer_id in [&trade.maker_peer_id, &trade.taker_peer_id] {
+            self.by_peer.entry(peer_id.clone()).or_default().insert(key.clone());
+        }
+        self.by_time.insert(key);
+    }
+
+    /// Get the number of indexed trades
+    pub fn len(&self) -> usize {
+        self.by_time.len()
+    }
+
+    /// Check if the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.by_time.is_empty()
+    }
+
+    /// Get the IDs of the trades that may match a query, in its sort order
+    ///
+    /// The narrowest index is walked and bounded by the time range and cursor;
+    /// the remaining filters are left to `TradeQuery::matches`.
+    pub fn scan<'a>(&'a self, query: &TradeQuery) -> Result<Box<dyn Iterator<Item = &'a TradeId> + 'a>> {
+        let after = query.cursor.as_deref().map(decode_cursor).transpose()?;
+
+        let set = if let Some(peer_id) = &query.counterparty {
+            self.by_peer.get(peer_id)
+        } else if let (Some(base_asset), Some(quote_asset)) = (&query.base_asset, &query.quote_asset) {
+            self.by_pair.get(&(base_asset.clone(), quote_asset.clone()))
+        } else {
+            Some(&self.by_time)
+        };
+        let set = match set {
+            Some(set) => set,
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+
+        let since = query.since.unwrap_or(0);
+        let until = query.until.unwrap_or(u64::MAX);
+
+        // Only one end of each range is bound so that a stale cursor outside the
+        // time range cannot make an inverted range
+        let iter: Box<dyn Iterator<Item = &'a IndexKey> + 'a> = match query.sort {
+            TradeSort::OldestFirst => {
+                let start = match after {
+                    Some(after) if after.0 >= since => Bound::Excluded(after),
+                    _ => Bound::Included((since, TradeId(String::new()))),
+                };
+                Box::new(set.range((start, Bound::Unbounded)).take_while(move |key| key.0 <= until))
+            }
+            TradeSort::NewestFirst => {
+                let end = match after {
+                    Some(after) if after.0 <= until => Bound::Excluded(after),
+                    _ => match until.checked_add(1) {
+                        Some(end) => Bound::Excluded((end, TradeId(String::new()))),
+                        None => Bound::Unbounded,
+                    },
+                };
+                Box::new(set.range((Bound::Unbounded, end)).rev().take_while(move |key| key.0 >= since))
+            }
+        };
+
+        Ok(Box::new(iter.map(|(_, trade_id)| trade_id)))
+    }
+}
+
+/// Load the stored trades, with their index
+pub fn load(storage: &dyn Storage) -> Result<(HashMap<TradeId, Trade>, TradeIndex)> {
+    let stored = storage.scan_json::<Trade>(TRADE_NAMESPACE, "")
+        .context("Failed to load trades")?;
+
+    let mut index = TradeIndex::new();
+    let trades = stored
+        .into_iter()
+        .map(|(_, trade)| {
+            index.insert(&trade);
+            (trade.id.clone(), trade)
+        })
+        .collect();
+
+    Ok((trades, index))
+}
+
+/// Store a trade, replacing its earlier state
+pub fn store(storage: &dyn Storage, trade: &Trade) -> Result<()> {
+    storage.put_json(TRADE_NAMESPACE, &trade.id.0, trade)?;
+    storage.flush()?;
+    Ok(())
+}
+
+/// Get a page of trades matching a query and a further filter
+pub fn query_trades<F>(
+    index: &TradeIndex,
+    trades: &HashMap<TradeId, Trade>,
+    query: &TradeQuery,
+    mut keep: F,
+) -> Result<TradePage<Trade>>
+where
+    F: FnMut(&Trade) -> bool,
+{
+    let page_size = query.page_size();
+    let mut page = Vec::new();
+    let mut next_cursor = None;
+
+    for trade_id in index.scan(query)? {
+        let trade = match trades.get(trade_id) {
+            Some(trade) => trade,
+            None => continue,
+        };
+        if !query.matches(trade) || !keep(trade) {
+            continue;
+        }
+
+        // Another match past a full page means there is a next page
+        if page.len() == page_size {
+            next_cursor = page.last().map(encode_cursor);
+            break;
+        }
+        page.push(trade.clone());
+    }
+
+    Ok(TradePage { trades: page, next_cursor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use rust_decimal_macros::dec;
+
+    fn trade(id: &str, created_at: u64, maker: &str, quote_asset: Asset) -> Trade {
+        let mut trade = Trade::new(
+            OrderId("order".to_string()),
+            maker.to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            quote_asset,
+            dec!(1),
+            dec!(100),
+            None,
+        );
+        trade.id = TradeId(id.to_string());
+        trade.created_at = created_at;
+        trade
+    }
+
+    fn store(trades: Vec<Trade>) -> (TradeIndex, HashMap<TradeId, Trade>) {
+        let mut index = TradeIndex::new();
+        for trade in &trades {
+            index.insert(trade);
+        }
+        (index, trades.into_iter().map(|trade| (trade.id.clone(), trade)).collect())
+    }
+
+    fn ids(page: &TradePage<Trade>) -> Vec<&str> {
+        page.trades.iter().map(|trade| trade.id.0.as_str()).collect()
+    }
+
+    #[test]
+    fn test_cursor_pagination() {
+        let (index, trades) = store(vec![
+            trade("a", 10, "maker", Asset::Rune(1)),
+            trade("b", 20, "maker", Asset::Rune(1)),
+            trade("c", 20, "maker", Asset::Rune(1)),
+            trade("d", 30, "maker", Asset::Rune(1)),
+            trade("e", 40, "maker", Asset::Rune(1)),
+        ]);
+
+        let mut query = TradeQuery { limit: Some(2), ..Default::default() };
+        let page = query_trades(&index, &trades, &query, |_| true).unwrap();
+        assert_eq!(ids(&page), vec!["e", "d"]);
+
+        query.cursor = page.next_cursor;
+        let page = query_trades(&index, &trades, &query, |_| true).unwrap();
+        assert_eq!(ids(&page), vec!["c", "b"]);
+
+        query.cursor = page.next_cursor;
+        let page = query_trades(&index, &trades, &query, |_| true).unwrap();
+        assert_eq!(ids(&page), vec!["a"]);
+        assert!(page.next_cursor.is_none());
+
+        let query = TradeQuery { sort: TradeSort::OldestFirst, limit: Some(3), ..Default::default() };
+        let page = query_trades(&index, &trades, &query, |_| true).unwrap();
+        assert_eq!(ids(&page), vec!["a", "b", "c"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("20:c"));
+    }
+
+    #[test]
+    fn test_filters() {
+        let (index, trades) = store(vec![
+            trade("a", 10, "alice", Asset::Rune(1)),
+            trade("b", 20, "bob", Asset::Rune(1)),
+            trade("c", 30, "alice", Asset::Rune(2)),
+            trade("d", 40, "alice", Asset::Rune(1)),
+        ]);
+
+        let query = TradeQuery { counterparty: Some("alice".to_string()), ..Default::default() };
+        assert_eq!(ids(&query_trades(&index, &trades, &query, |_| true).unwrap()), vec!["d", "c", "a"]);
+
+        let query = TradeQuery {
+            base_asset: Some(Asset::Bitcoin),
+            quote_asset: Some(Asset::Rune(1)),
+            since: Some(15),
+            until: Some(40),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query_trades(&index, &trades, &query, |_| true).unwrap()), vec!["d", "b"]);
+
+        let query = TradeQuery { counterparty: Some("carol".to_string()), ..Default::default() };
+        assert!(query_trades(&index, &trades, &query, |_| true).unwrap().trades.is_empty());
+
+        let query = TradeQuery { state: Some(TradeState::Completed), ..Default::default() };
+        assert!(query_trades(&index, &trades, &query, |_| true).unwrap().trades.is_empty());
+
+        let query = TradeQuery { cursor: Some("garbage".to_string()), ..Default::default() };
+        assert!(query_trades(&index, &trades, &query, |_| true).is_err());
+    }
+
+    #[test]
+    fn test_index_is_rebuilt_from_storage() {
+        let storage = darkswap_support::storage::MemoryStorage::new();
+        for trade in [trade("a", 10, "maker", Asset::Rune(1)), trade("b", 20, "other", Asset::Rune(2))] {
+            super::store(&storage, &trade).unwrap();
+        }
+
+        let (trades, index) = load(&storage).unwrap();
+        assert_eq!(index.len(), 2);
+
+        let query = TradeQuery { counterparty: Some("other".to_string()), ..Default::default() };
+        let page = query_trades(&index, &trades, &query, |_| true).unwrap();
+        assert_eq!(ids(&page), vec!["b"]);
+    }
+}
@@ -0,0 +1,212 @@
+//! Trade receipts for DarkSwap
+//!
+//! A receipt bundles the terms of a completed trade, the settlement txid and an
+//! optional block inclusion proof, signed by both parties. Either party can later
+//! present it to prove that the trade took place.
+
+use anyhow::{Context as AnyhowContext, Result};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::{Order, OrderId};
+use crate::spv::{HeaderChain, MerkleProof};
+use crate::types::{Asset, TradeId};
+
+use super::Trade;
+
+/// Terms of a trade covered by the receipt signatures
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiptBody {
+    /// Trade ID
+    pub trade_id: TradeId,
+    /// Order ID
+    pub order_id: OrderId,
+    /// Maker peer ID
+    pub maker_peer_id: String,
+    /// Taker peer ID
+    pub taker_peer_id: String,
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Amount
+    pub amount: Decimal,
+    /// Price
+    pub price: Decimal,
+    /// Settlement transaction ID
+    pub txid: String,
+}
+
+impl ReceiptBody {
+    /// Create a receipt body from a completed trade
+    pub fn from_trade(trade: &Trade) -> Result<Self> {
+        let txid = trade.txid.clone()
+            .ok_or_else(|| anyhow::anyhow!("Trade {} has no settlement transaction", trade.id.0))?;
+
+        Ok(Self {
+            trade_id: trade.id.clone(),
+            order_id: trade.order_id.clone(),
+            maker_peer_id: trade.maker_peer_id.clone(),
+            taker_peer_id: trade.taker_peer_id.clone(),
+            base_asset: trade.base_asset.clone(),
+            quote_asset: trade.quote_asset.clone(),
+            amount: trade.amount,
+            price: trade.price,
+            txid,
+        })
+    }
+
+    /// Sign the receipt body
+    pub fn sign(&self, keypair: &Keypair) -> Result<ReceiptSignature> {
+        let message = self.signing_bytes()?;
+        let signature = keypair.sign(&message).context("Failed to sign receipt")?;
+
+        Ok(ReceiptSignature {
+            peer_id: PeerId::from(keypair.public()).to_string(),
+            public_key: hex::encode(keypair.public().to_protobuf_encoding()),
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Get the bytes covered by the signatures
+    fn signing_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).context("Failed to serialize receipt")
+    }
+}
+
+/// Signature of one trade party over a receipt body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiptSignature {
+    /// Signer peer ID
+    pub peer_id: String,
+    /// Signer public key (protobuf encoded, hex)
+    pub public_key: String,
+    /// Signature (hex)
+    pub signature: String,
+}
+
+impl ReceiptSignature {
+    /// Verify the signature over a receipt body
+    pub fn verify(&self, body: &ReceiptBody) -> Result<()> {
+        let public_key_bytes = hex::decode(&self.public_key).context("Invalid public key encoding")?;
+        let public_key = PublicKey::from_protobuf_encoding(&public_key_bytes).context("Invalid public key")?;
+
+        if PeerId::from_public_key(&public_key).to_string() != self.peer_id {
+            anyhow::bail!("Public key does not match peer ID {}", self.peer_id);
+        }
+
+        let signature = hex::decode(&self.signature).context("Invalid signature encoding")?;
+        if !public_key.verify(&body.signing_bytes()?, &signature) {
+            anyhow::bail!("Invalid receipt signature from {}", self.peer_id);
+        }
+
+        Ok(())
+    }
+}
+
+/// Trade receipt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeReceipt {
+    /// Signed trade terms
+    pub body: ReceiptBody,
+    /// Order the trade filled
+    pub order: Option<Order>,
+    /// Signatures of the maker and taker
+    pub signatures: Vec<ReceiptSignature>,
+    /// Block inclusion proof of the settlement transaction
+    pub inclusion_proof: Option<MerkleProof>,
+}
+
+/// Verify a trade receipt
+///
+/// Checks that both the maker and the taker signed the trade terms, that the bundled
+/// order matches, and that the inclusion proof (if any) links the txid to a block of
+/// `chain`. A receipt carrying a proof is rejected without a header chain to check it against.
+pub fn verify_receipt(receipt: &TradeReceipt, chain: Option<&HeaderChain>) -> Result<()> {
+    let body = &receipt.body;
+
+    // Check the bundled order
+    if let Some(order) = &receipt.order {
+        if order.id != body.order_id {
+            anyhow::bail!("Receipt order {} does not match trade order {}", order.id, body.order_id);
+        }
+    }
+
+    // Check signatures
+    let mut maker_signed = false;
+    let mut taker_signed = false;
+
+    for signature in &receipt.signatures {
+        signature.verify(body)?;
+
+        if signature.peer_id == body.maker_peer_id {
+            maker_signed = true;
+        } else if signature.peer_id == body.taker_peer_id {
+            taker_signed = true;
+        } else {
+            anyhow::bail!("Receipt signed by {} who is not a trade party", signature.peer_id);
+        }
+    }
+
+    if !maker_signed {
+        anyhow::bail!("Receipt is missing the maker signature");
+    }
+
+    if !taker_signed {
+        anyhow::bail!("Receipt is missing the taker signature");
+    }
+
+    // Check the inclusion proof
+    if let Some(proof) = &receipt.inclusion_proof {
+        let chain = chain
+            .ok_or_else(|| anyhow::anyhow!("No block headers to check the inclusion proof against"))?;
+        if !proof.verify(&body.txid, chain)? {
+            anyhow::bail!("Inclusion proof does not match transaction {}", body.txid);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn body(maker: &Keypair, taker: &Keypair) -> ReceiptBody {
+        ReceiptBody {
+            trade_id: TradeId("trade-1".to_string()),
+            order_id: OrderId("order-1".to_string()),
+            maker_peer_id: PeerId::from(maker.public()).to_string(),
+            taker_peer_id: PeerId::from(taker.public()).to_string(),
+            base_asset: Asset::Bitcoin,
+            quote_asset: Asset::Rune(1),
+            amount: dec!(1),
+            price: dec!(100),
+            txid: "00".repeat(32),
+        }
+    }
+
+    #[test]
+    fn test_receipt_requires_both_parties() {
+        let maker = Keypair::generate_ed25519();
+        let taker = Keypair::generate_ed25519();
+        let body = body(&maker, &taker);
+
+        let mut receipt = TradeReceipt {
+            body: body.clone(),
+            order: None,
+            signatures: vec![body.sign(&maker).unwrap()],
+            inclusion_proof: None,
+        };
+        assert!(verify_receipt(&receipt, None).is_err());
+
+        receipt.signatures.push(body.sign(&taker).unwrap());
+        assert!(verify_receipt(&receipt, None).is_ok());
+
+        receipt.body.amount = dec!(2);
+        assert!(verify_receipt(&receipt, None).is_err());
+    }
+}
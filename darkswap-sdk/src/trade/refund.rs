@@ -0,0 +1,427 @@
+//! Sweeping of matured refund paths
+//!
+//! An escrowed trade locks funds in an output the counterparties can only spend
+//! together, with a presigned refund transaction as the way back if the trade
+//! stalls. The refund is timelocked with its `nLockTime` and is only valid once
+//! that height or time has been reached. The sweeper keeps the refund transactions
+//! of this node's escrows and broadcasts each one as soon as it matures, unless the
+//! escrow has been spent by then, so funds are not stranded by a forgotten refund.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::DocumentFile;
+use async_trait::async_trait;
+use bitcoin::consensus::deserialize;
+use bitcoin::{OutPoint, Transaction, Txid};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, RwLock};
+
+use super::TradeError;
+use crate::clock::Clock;
+use crate::types::{Event, TradeId};
+use crate::wallet::deposits::DepositWatcher;
+
+/// `nLockTime` values below this are block heights, the others unix times
+const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// How far the median time past of the chain lags behind the clock (seconds)
+///
+/// Time-locked refunds are only broadcast once their lock time is this far in the
+/// past, so nodes do not reject them as premature.
+const MEDIAN_TIME_LAG: u64 = 3600;
+
+/// Chain access the sweeper needs
+#[async_trait]
+pub trait RefundChain: Send + Sync {
+    /// Get the height of the chain tip
+    async fn tip_height(&self) -> Result<u32>;
+
+    /// Check if an output has been spent
+    async fn is_spent(&self, outpoint: &OutPoint) -> Result<bool>;
+
+    /// Broadcast a transaction
+    async fn broadcast(&self, tx: &Transaction) -> Result<Txid>;
+}
+
+/// Refund path status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RefundStatus {
+    /// Waiting for the lock time
+    Pending,
+    /// Refund broadcast
+    Broadcast,
+    /// Escrow spent otherwise, usually because the trade settled
+    Spent,
+}
+
+/// Presigned refund of an escrow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefundPath {
+    /// Trade the escrow belongs to
+    pub trade_id: TradeId,
+    /// Escrow outputs the refund spends
+    pub escrow_outpoints: Vec<String>,
+    /// Signed refund transaction (hex)
+    pub refund_tx: String,
+    /// Lock time of the refund: a block height, or a unix time from 500000000
+    pub lock_time: u32,
+    /// Status
+    pub status: RefundStatus,
+    /// ID of the broadcast refund transaction
+    pub txid: Option<String>,
+    /// Error of the last broadcast attempt
+    pub last_error: Option<String>,
+}
+
+impl RefundPath {
+    /// Create a refund path from a signed, timelocked refund transaction
+    pub fn new(trade_id: TradeId, refund_tx: &str) -> Result<Self, TradeError> {
+        let tx = decode_tx(refund_tx)?;
+
+        if tx.lock_time.0 == 0 {
+            return Err(TradeError::InvalidState("Refund transaction has no lock time".to_string()));
+        }
+        // The lock time is only enforced if an input is not final
+        if tx.input.iter().all(|input| input.sequence.0 == u32::MAX) {
+            return Err(TradeError::InvalidState("Refund transaction lock time is disabled by final sequences".to_string()));
+        }
+
+        Ok(Self {
+            trade_id,
+            escrow_outpoints: tx.input.iter().map(|input| input.previous_output.to_string()).collect(),
+            refund_tx: refund_tx.trim().to_string(),
+            lock_time: tx.lock_time.0,
+            status: RefundStatus::Pending,
+            txid: None,
+            last_error: None,
+        })
+    }
+
+    /// Check if the refund can be mined in the next block
+    pub fn is_mature(&self, tip_height: u32, now: u64) -> bool {
+        if self.lock_time < LOCK_TIME_THRESHOLD {
+            self.lock_time <= tip_height
+        } else {
+            u64::from(self.lock_time) + MEDIAN_TIME_LAG <= now
+        }
+    }
+}
+
+/// Refund paths of this node's escrows
+#[derive(Debug, Default)]
+pub struct RefundSweeper {
+    /// File the refund paths are persisted to; `None` keeps them in memory only
+    file: Option<DocumentFile>,
+    /// Refund paths by trade
+    refunds: HashMap<TradeId, RefundPath>,
+}
+
+impl RefundSweeper {
+    /// Create an in-memory sweeper
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a sweeper persisted at `path`, loading it if the file exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_file(DocumentFile::plain(path))
+    }
+
+    /// Open a sweeper persisted in `file`, sealed if storage is encrypted
+    pub fn open_file(file: DocumentFile) -> Result<Self> {
+        let refunds: Vec<RefundPath> = file.load()
+            .with_context(|| format!("Failed to read refund store {}", file.path().display()))?
+            .unwrap_or_default();
+
+        Ok(Self {
+            file: Some(file),
+            refunds: refunds.into_iter().map(|refund| (refund.trade_id.clone(), refund)).collect(),
+        })
+    }
+
+    /// Save the refund paths to disk, if they are persisted
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+
+        file.save(&self.list())
+            .with_context(|| format!("Failed to write refund store {}", file.path().display()))?;
+
+        Ok(())
+    }
+
+    /// Add the refund path of a trade, replacing a pending one
+    pub fn add(&mut self, refund: RefundPath) -> Result<(), TradeError> {
+        if let Some(existing) = self.refunds.get(&refund.trade_id) {
+            if existing.status != RefundStatus::Pending {
+                return Err(TradeError::InvalidState(format!("Refund of trade {} is already {:?}", refund.trade_id, existing.status)));
+            }
+        }
+
+        self.refunds.insert(refund.trade_id.clone(), refund);
+        Ok(())
+    }
+
+    /// Get the refund path of a trade
+    pub fn get(&self, trade_id: &TradeId) -> Option<&RefundPath> {
+        self.refunds.get(trade_id)
+    }
+
+    /// List the refund paths, lowest lock time first
+    pub fn list(&self) -> Vec<RefundPath> {
+        let mut refunds: Vec<RefundPath> = self.refunds.values().cloned().collect();
+        refunds.sort_by(|a, b| a.lock_time.cmp(&b.lock_time).then_with(|| a.trade_id.0.cmp(&b.trade_id.0)));
+        refunds
+    }
+}
+
+/// Broadcast the refunds that have matured, returning how many were broadcast
+///
+/// A refund that cannot be checked or saved is logged and tried again on the
+/// next sweep, without holding up the others. Broadcast refunds are recorded with
+/// `deposits`, so their outputs are not reported as incoming payments.
+pub async fn sweep(
+    sweeper: &RwLock<RefundSweeper>,
+    chain: &dyn RefundChain,
+    deposits: Option<&DepositWatcher>,
+    event_sender: &mpsc::Sender<Event>,
+    clock: &dyn Clock,
+) -> Result<usize> {
+    let pending: Vec<RefundPath> = sweeper.read().await.refunds.values()
+        .filter(|refund| refund.status == RefundStatus::Pending)
+        .cloned()
+        .collect();
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let tip_height = chain.tip_height().await?;
+    let now = clock.now();
+    let mut broadcast = 0;
+
+    for refund in pending {
+        let trade_id = refund.trade_id.clone();
+        let update = match sweep_refund(sweeper, chain, refund, tip_height, now).await {
+            Ok(Some(update)) => update,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Failed to sweep refund of trade {}: {:#}", trade_id, e);
+                continue;
+            }
+        };
+
+        if update.status == RefundStatus::Broadcast {
+            broadcast += 1;
+            if let (Some(deposits), Some(txid)) = (deposits, &update.txid) {
+                if let Err(e) = deposits.record_own_transaction(txid, now) {
+                    warn!("Failed to record refund {}: {}", txid, e);
+                }
+            }
+            let _ = event_sender.send(Event::RefundBroadcast(update)).await;
+        }
+    }
+
+    Ok(broadcast)
+}
+
+/// Broadcast a refund if it matured, returning its update if its status changed
+/// or its broadcast failed
+async fn sweep_refund(
+    sweeper: &RwLock<RefundSweeper>,
+    chain: &dyn RefundChain,
+    refund: RefundPath,
+    tip_height: u32,
+    now: u64,
+) -> Result<Option<RefundPath>> {
+    // Once any escrow output is gone the refund can no longer be mined
+    let mut spent = false;
+    for outpoint in &refund.escrow_outpoints {
+        let outpoint: OutPoint = outpoint.parse().context("Invalid escrow outpoint")?;
+        if chain.is_spent(&outpoint).await? {
+            spent = true;
+            break;
+        }
+    }
+
+    let mut update = refund.clone();
+    if spent {
+        update.status = RefundStatus::Spent;
+    } else if refund.is_mature(tip_height, now) {
+        let tx = decode_tx(&refund.refund_tx)?;
+        match chain.broadcast(&tx).await {
+            Ok(txid) => {
+                info!("Broadcast refund {} of trade {}", txid, refund.trade_id);
+                update.status = RefundStatus::Broadcast;
+                update.txid = Some(txid.to_string());
+                update.last_error = None;
+            }
+            Err(e) => {
+                // Retried on the next sweep
+                warn!("Failed to broadcast refund of trade {}: {}", refund.trade_id, e);
+                update.last_error = Some(e.to_string());
+            }
+        }
+    } else {
+        return Ok(None);
+    }
+
+    let mut sweeper = sweeper.write().await;
+    sweeper.refunds.insert(update.trade_id.clone(), update.clone());
+    sweeper.save()?;
+
+    Ok(Some(update))
+}
+
+/// Spawn a task sweeping matured refunds every `interval`
+pub fn spawn_refund_sweeper(
+    sweeper: Arc<RwLock<RefundSweeper>>,
+    chain: Arc<dyn RefundChain>,
+    deposits: Option<Arc<DepositWatcher>>,
+    event_sender: mpsc::Sender<Event>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = sweep(&sweeper, chain.as_ref(), deposits.as_deref(), &event_sender, clock.as_ref()).await {
+                warn!("Refund sweeper failed: {}", e);
+            }
+        }
+    })
+}
+
+/// Decode a hex encoded transaction
+fn decode_tx(tx_hex: &str) -> Result<Transaction, TradeError> {
+    let bytes = hex::decode(tx_hex.trim())
+        .map_err(|e| TradeError::InvalidState(format!("Invalid refund transaction encoding: {}", e)))?;
+    deserialize(&bytes).map_err(|e| TradeError::InvalidState(format!("Invalid refund transaction: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::serialize;
+    use bitcoin::{PackedLockTime, Script, Sequence, TxIn, TxOut, Witness};
+    use crate::clock::MockClock;
+    use std::sync::Mutex;
+
+    fn refund_tx(lock_time: u32, sequence: u32) -> String {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(lock_time),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: Sequence(sequence),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut { value: 10_000, script_pubkey: Script::new() }],
+        };
+        hex::encode(serialize(&tx))
+    }
+
+    /// Chain at a fixed height that records broadcasts
+    struct TestChain {
+        height: u32,
+        spent: bool,
+        broadcast: Mutex<Vec<Txid>>,
+    }
+
+    #[async_trait]
+    impl RefundChain for TestChain {
+        async fn tip_height(&self) -> Result<u32> {
+            Ok(self.height)
+        }
+
+        async fn is_spent(&self, _outpoint: &OutPoint) -> Result<bool> {
+            Ok(self.spent)
+        }
+
+        async fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
+            let txid = tx.txid();
+            self.broadcast.lock().unwrap().push(txid);
+            Ok(txid)
+        }
+    }
+
+    #[test]
+    fn test_refund_needs_an_enforced_lock_time() {
+        let trade_id = TradeId("trade".to_string());
+
+        assert!(RefundPath::new(trade_id.clone(), &refund_tx(0, 0xfffffffe)).is_err());
+        assert!(RefundPath::new(trade_id.clone(), &refund_tx(800_000, u32::MAX)).is_err());
+        assert!(RefundPath::new(trade_id, &refund_tx(800_000, 0xfffffffe)).is_ok());
+    }
+
+    #[test]
+    fn test_maturity() {
+        let by_height = RefundPath::new(TradeId("a".to_string()), &refund_tx(800_000, 0xfffffffe)).unwrap();
+        assert!(!by_height.is_mature(799_999, 0));
+        assert!(by_height.is_mature(800_000, 0));
+
+        let by_time = RefundPath::new(TradeId("b".to_string()), &refund_tx(1_700_000_000, 0xfffffffe)).unwrap();
+        assert!(!by_time.is_mature(u32::MAX, 1_700_000_000));
+        assert!(by_time.is_mature(0, 1_700_000_000 + MEDIAN_TIME_LAG));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_broadcasts_matured_refunds_once() {
+        let sweeper = RwLock::new(RefundSweeper::new());
+        sweeper.write().await
+            .add(RefundPath::new(TradeId("due".to_string()), &refund_tx(100, 0xfffffffe)).unwrap())
+            .unwrap();
+        sweeper.write().await
+            .add(RefundPath::new(TradeId("later".to_string()), &refund_tx(200, 0xfffffffe)).unwrap())
+            .unwrap();
+        let chain = TestChain { height: 150, spent: false, broadcast: Mutex::new(Vec::new()) };
+        let (event_sender, mut events) = mpsc::channel(8);
+
+        assert_eq!(sweep(&sweeper, &chain, None, &event_sender, &MockClock::new(1_700_000_000)).await.unwrap(), 1);
+        assert_eq!(sweep(&sweeper, &chain, None, &event_sender, &MockClock::new(1_700_000_000)).await.unwrap(), 0);
+
+        assert_eq!(chain.broadcast.lock().unwrap().len(), 1);
+        assert!(matches!(events.try_recv(), Ok(Event::RefundBroadcast(refund)) if refund.trade_id.0 == "due"));
+        assert_eq!(sweeper.read().await.get(&TradeId("later".to_string())).unwrap().status, RefundStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_failed_refund_does_not_hold_up_the_others() {
+        let sweeper = RwLock::new(RefundSweeper::new());
+        let mut broken = RefundPath::new(TradeId("broken".to_string()), &refund_tx(100, 0xfffffffe)).unwrap();
+        broken.escrow_outpoints = vec!["not an outpoint".to_string()];
+        sweeper.write().await.add(broken).unwrap();
+        sweeper.write().await
+            .add(RefundPath::new(TradeId("due".to_string()), &refund_tx(100, 0xfffffffe)).unwrap())
+            .unwrap();
+        let chain = TestChain { height: 150, spent: false, broadcast: Mutex::new(Vec::new()) };
+        let (event_sender, _events) = mpsc::channel(8);
+
+        assert_eq!(sweep(&sweeper, &chain, None, &event_sender, &MockClock::new(1_700_000_000)).await.unwrap(), 1);
+        assert_eq!(sweeper.read().await.get(&TradeId("broken".to_string())).unwrap().status, RefundStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn test_spent_escrow_is_not_refunded() {
+        let sweeper = RwLock::new(RefundSweeper::new());
+        sweeper.write().await
+            .add(RefundPath::new(TradeId("settled".to_string()), &refund_tx(100, 0xfffffffe)).unwrap())
+            .unwrap();
+        let chain = TestChain { height: 150, spent: true, broadcast: Mutex::new(Vec::new()) };
+        let (event_sender, _events) = mpsc::channel(8);
+
+        assert_eq!(sweep(&sweeper, &chain, None, &event_sender, &MockClock::new(1_700_000_000)).await.unwrap(), 0);
+        assert!(chain.broadcast.lock().unwrap().is_empty());
+        assert_eq!(sweeper.read().await.get(&TradeId("settled".to_string())).unwrap().status, RefundStatus::Spent);
+    }
+}
@@ -0,0 +1,384 @@
+//! Deterministic replay of trade protocol traces
+//!
+//! A trade module can record the messages it receives and sends, together with
+//! the trade state after each received message. A recorded trace is replayed by
+//! feeding its received messages, in order, to a fresh trade module backed by a
+//! deterministic wallet, and comparing the states, failures and sent messages
+//! with the recording. Traces from real sessions thus become regression tests
+//! for protocol edge cases such as duplicate or out-of-order messages.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, Txid, Witness};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, RwLock};
+
+use super::batch::TradeLeg;
+use super::fees::FeeOutput;
+use super::settlement::PayoutOutput;
+use super::{AlkanesExecutor, RunesExecutor, Trade, TradeMessage, TradeModule, TradeState, Wallet};
+use crate::config::Config;
+use crate::orderbook::OrderId;
+use crate::p2p::P2PNetwork;
+use crate::types::{Asset, Event, TradeId};
+
+/// Direction of a recorded message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TraceDirection {
+    /// Received from a peer
+    Inbound,
+    /// Sent to a peer
+    Outbound,
+}
+
+/// Recorded trade message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Direction
+    pub direction: TraceDirection,
+    /// Sender of an inbound message, recipient of an outbound one
+    pub peer_id: String,
+    /// Message
+    pub message: TradeMessage,
+    /// State of the message's trade after an inbound message was handled
+    pub state_after: Option<TradeState>,
+    /// Error returned while handling an inbound message
+    pub error: Option<String>,
+}
+
+/// Recorded trade session
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeTrace {
+    /// Trades known when the recording started
+    pub trades: Vec<Trade>,
+    /// Messages in the order they were received or sent
+    pub entries: Vec<TraceEntry>,
+}
+
+impl TradeTrace {
+    /// Load a trace from a JSON file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read trade trace {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse trade trace {}", path.display()))
+    }
+
+    /// Save the trace to a JSON file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self).context("Failed to serialize trade trace")?;
+        fs::write(path, contents).context("Failed to write trade trace")
+    }
+
+    /// Get the entries in one direction
+    pub fn entries(&self, direction: TraceDirection) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter().filter(move |entry| entry.direction == direction)
+    }
+}
+
+/// Recorder of a trade session
+#[derive(Debug)]
+pub(crate) struct TraceRecorder {
+    /// Trace recorded so far
+    trace: TradeTrace,
+}
+
+impl TraceRecorder {
+    /// Start a recording with the currently known trades
+    pub(crate) fn new(trades: Vec<Trade>) -> Self {
+        Self {
+            trace: TradeTrace {
+                trades,
+                entries: Vec::new(),
+            },
+        }
+    }
+
+    /// Record a handled inbound message
+    pub(crate) fn inbound(
+        &mut self,
+        peer_id: &str,
+        message: TradeMessage,
+        state_after: Option<TradeState>,
+        error: Option<&anyhow::Error>,
+    ) {
+        self.trace.entries.push(TraceEntry {
+            direction: TraceDirection::Inbound,
+            peer_id: peer_id.to_string(),
+            message,
+            state_after,
+            error: error.map(|e| e.to_string()),
+        });
+    }
+
+    /// Record a sent message
+    pub(crate) fn outbound(&mut self, peer_id: &str, message: TradeMessage) {
+        self.trace.entries.push(TraceEntry {
+            direction: TraceDirection::Outbound,
+            peer_id: peer_id.to_string(),
+            message,
+            state_after: None,
+            error: None,
+        });
+    }
+
+    /// Finish the recording
+    pub(crate) fn finish(self) -> TradeTrace {
+        self.trace
+    }
+}
+
+/// Difference between a recording and its replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayMismatch {
+    /// Index of the entry among the entries of its direction
+    pub index: usize,
+    /// Direction of the entry
+    pub direction: TraceDirection,
+    /// Recorded behaviour
+    pub expected: String,
+    /// Replayed behaviour
+    pub actual: String,
+}
+
+/// Result of a replay
+#[derive(Debug)]
+pub struct ReplayReport {
+    /// Trace recorded during the replay
+    pub trace: TradeTrace,
+    /// Events emitted during the replay
+    pub events: Vec<Event>,
+    /// Differences from the original recording
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayReport {
+    /// Check that the replay behaved as recorded
+    pub fn is_clean(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Describe how an inbound entry was handled
+fn describe_inbound(entry: &TraceEntry) -> String {
+    format!(
+        "{} from {} -> {:?}{}",
+        entry.message.kind(),
+        entry.peer_id,
+        entry.state_after,
+        if entry.error.is_some() { " (rejected)" } else { "" },
+    )
+}
+
+/// Describe an outbound entry
+fn describe_outbound(entry: &TraceEntry) -> String {
+    format!("{} for {} to {}", entry.message.kind(), entry.message.trade_id().0, entry.peer_id)
+}
+
+/// Compare the entries of one direction
+///
+/// Messages are compared by kind, trade and peer; payloads such as PSBTs and
+/// signatures depend on the wallet and identity and are not compared.
+fn compare(
+    expected: &TradeTrace,
+    actual: &TradeTrace,
+    direction: TraceDirection,
+    describe: fn(&TraceEntry) -> String,
+) -> Vec<ReplayMismatch> {
+    let expected: Vec<String> = expected.entries(direction).map(describe).collect();
+    let actual: Vec<String> = actual.entries(direction).map(describe).collect();
+
+    (0..expected.len().max(actual.len()))
+        .filter_map(|index| {
+            let expected = expected.get(index).cloned().unwrap_or_else(|| "nothing".to_string());
+            let actual = actual.get(index).cloned().unwrap_or_else(|| "nothing".to_string());
+            (expected != actual).then(|| ReplayMismatch { index, direction, expected, actual })
+        })
+        .collect()
+}
+
+/// Replays traces against a trade module with a deterministic wallet
+pub struct Replayer {
+    /// Trade module under test
+    module: TradeModule,
+    /// Events emitted by the trade module
+    events: mpsc::Receiver<Event>,
+}
+
+impl Replayer {
+    /// Create a replayer; the P2P identity is taken from the configuration
+    pub fn new(config: &Config) -> Result<Self> {
+        let (event_sender, events) = mpsc::channel(1024);
+        let network = P2PNetwork::new(config, event_sender.clone())?;
+        let wallet = Arc::new(ReplayWallet);
+
+        let module = TradeModule::new(
+            Arc::new(RwLock::new(network)),
+            event_sender,
+            wallet.clone(),
+            wallet.clone(),
+            wallet,
+        );
+
+        Ok(Self { module, events })
+    }
+
+    /// Get the trade module under test
+    pub fn module(&self) -> &TradeModule {
+        &self.module
+    }
+
+    /// Replay the inbound messages of a trace and compare the outcome with the recording
+    pub async fn replay(&mut self, trace: &TradeTrace) -> Result<ReplayReport> {
+        for trade in &trace.trades {
+            self.module.restore_trade(trade.clone()).await;
+        }
+
+        self.module.start_recording().await;
+
+        let mut events = Vec::new();
+        for entry in trace.entries(TraceDirection::Inbound) {
+            // Failures are part of the recording, so they do not stop the replay
+            let _ = self.module.handle_trade_message(entry.message.clone(), &entry.peer_id).await;
+
+            while let Ok(event) = self.events.try_recv() {
+                events.push(event);
+            }
+        }
+
+        let replayed = self.module.take_trace().await.unwrap_or_default();
+
+        let mut mismatches = compare(trace, &replayed, TraceDirection::Inbound, describe_inbound);
+        mismatches.extend(compare(trace, &replayed, TraceDirection::Outbound, describe_outbound));
+
+        Ok(ReplayReport {
+            trace: replayed,
+            events,
+            mismatches,
+        })
+    }
+}
+
+/// Deterministic wallet for replays
+///
+/// PSBTs spend a single unsigned input derived from the trade and side, and are
+/// valid if they decode; signing returns the PSBT unchanged and the txid of a
+/// broadcast is the double SHA-256 of the PSBT.
+pub struct ReplayWallet;
+
+impl ReplayWallet {
+    /// Create a PSBT for a trade side
+    fn psbt(trade_id: &TradeId, is_maker: bool) -> Vec<u8> {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(trade_id.0.as_bytes()), is_maker as u32),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: Vec::new(),
+        };
+
+        let psbt = Psbt::from_unsigned_tx(tx).expect("Replay transactions are unsigned");
+        serialize(&psbt)
+    }
+
+    /// Check a PSBT
+    fn verify(psbt: &[u8]) -> bool {
+        deserialize::<Psbt>(psbt).is_ok()
+    }
+
+    /// Get the txid of a PSBT
+    fn txid(psbt: &[u8]) -> String {
+        sha256d::Hash::hash(psbt).to_string()
+    }
+}
+
+#[async_trait]
+impl Wallet for ReplayWallet {
+    async fn create_trade_psbt(
+        &self,
+        trade_id: &TradeId,
+        _order_id: &OrderId,
+        _base_asset: &Asset,
+        _quote_asset: &Asset,
+        _amount: u64,
+        _price: u64,
+        _payout: Option<&PayoutOutput>,
+        _fees: &[FeeOutput],
+    ) -> Result<Vec<u8>> {
+        Ok(Self::psbt(trade_id, true))
+    }
+
+    async fn create_batch_trade_psbt(
+        &self,
+        trade_id: &TradeId,
+        _base_asset: &Asset,
+        _quote_asset: &Asset,
+        _legs: &[TradeLeg],
+        _payout: Option<&PayoutOutput>,
+        _fees: &[FeeOutput],
+    ) -> Result<Vec<u8>> {
+        Ok(Self::psbt(trade_id, true))
+    }
+
+    async fn verify_psbt(&self, psbt: &[u8]) -> Result<bool> {
+        Ok(Self::verify(psbt))
+    }
+
+    async fn sign_psbt(&self, psbt: &[u8]) -> Result<Vec<u8>> {
+        Ok(psbt.to_vec())
+    }
+
+    async fn finalize_and_broadcast_psbt(&self, psbt: &[u8]) -> Result<String> {
+        Ok(Self::txid(psbt))
+    }
+}
+
+#[async_trait]
+impl RunesExecutor for ReplayWallet {
+    async fn create_rune_trade_psbt(&self, trade: &Trade, is_maker: bool) -> Result<Vec<u8>> {
+        Ok(Self::psbt(&trade.id, is_maker))
+    }
+
+    async fn verify_rune_trade_psbt(&self, psbt: &[u8], _trade: &Trade) -> Result<bool> {
+        Ok(Self::verify(psbt))
+    }
+
+    async fn sign_rune_trade_psbt(&self, psbt: &[u8]) -> Result<Vec<u8>> {
+        Ok(psbt.to_vec())
+    }
+
+    async fn finalize_and_broadcast_rune_trade_psbt(&self, psbt: &[u8]) -> Result<String> {
+        Ok(Self::txid(psbt))
+    }
+}
+
+#[async_trait]
+impl AlkanesExecutor for ReplayWallet {
+    async fn create_alkane_trade_psbt(&self, trade: &Trade, is_maker: bool) -> Result<Vec<u8>> {
+        Ok(Self::psbt(&trade.id, is_maker))
+    }
+
+    async fn verify_alkane_trade_psbt(&self, psbt: &[u8], _trade: &Trade) -> Result<bool> {
+        Ok(Self::verify(psbt))
+    }
+
+    async fn sign_alkane_trade_psbt(&self, psbt: &[u8]) -> Result<Vec<u8>> {
+        Ok(psbt.to_vec())
+    }
+
+    async fn finalize_and_broadcast_alkane_trade_psbt(&self, psbt: &[u8]) -> Result<String> {
+        Ok(Self::txid(psbt))
+    }
+}
@@ -0,0 +1,222 @@
+//! Request-for-quote flow
+//!
+//! The taker side broadcasts a quote request and collects the signed quotes makers
+//! send back; the maker side keeps the requests it has seen so it can answer them.
+//! An accepted quote is traded with the standard trade protocol, against the
+//! private order the quote describes.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use darkswap_support::envelope;
+use log::{debug, info};
+use rust_decimal::Decimal;
+use tokio::sync::{mpsc, RwLock};
+
+use crate::clock::{self, Clock};
+use crate::orderbook::OrderSide;
+use crate::p2p::rfq::{Quote, QuoteRequest, RfqError, RfqMessage, SignedQuote, QUOTE_PROTOCOL, RFQ_TOPIC};
+use crate::p2p::P2PNetwork as Network;
+use crate::types::{Asset, Event};
+
+/// Request-for-quote manager
+pub struct RfqManager {
+    /// Network module
+    network: Arc<RwLock<Network>>,
+    /// Event sender
+    event_sender: mpsc::Sender<Event>,
+    /// Own quote requests with the quotes received for them
+    requests: RwLock<HashMap<String, (QuoteRequest, Vec<SignedQuote>)>>,
+    /// Quote requests received from takers
+    inbound: RwLock<HashMap<String, QuoteRequest>>,
+    /// Time source of request and quote expiry
+    clock: Arc<dyn Clock>,
+}
+
+impl RfqManager {
+    /// Create a new RFQ manager
+    pub fn new(network: Arc<RwLock<Network>>, event_sender: mpsc::Sender<Event>) -> Self {
+        Self {
+            network,
+            event_sender,
+            requests: RwLock::new(HashMap::new()),
+            inbound: RwLock::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Read the time from `clock` instead of the wall clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Subscribe to quote requests
+    pub async fn init(&self) -> Result<()> {
+        self.network.write().await.subscribe(RFQ_TOPIC).await
+    }
+
+    /// Broadcast a quote request and collect quotes until `timeout` has passed
+    ///
+    /// Quotes are returned best price first, larger quotes first among equal prices.
+    pub async fn request_quotes(
+        &self,
+        base_asset: Asset,
+        quote_asset: Asset,
+        side: OrderSide,
+        amount: Decimal,
+        timeout: Duration,
+    ) -> Result<Vec<SignedQuote>> {
+        let taker = self.network.read().await.local_peer_id().to_string();
+        let request = QuoteRequest::new(taker, base_asset, quote_asset, side, amount, timeout.as_secs().max(1), self.clock.now())?;
+        let request_id = request.id.clone();
+
+        self.requests.write().await.insert(request_id.clone(), (request.clone(), Vec::new()));
+
+        let message_data = envelope::encode(&RfqMessage::Request(request))
+            .context("Failed to serialize quote request")?;
+        self.network.write().await.publish(RFQ_TOPIC, message_data).await?;
+        info!("Requested quotes for {} {:?}", amount, side);
+
+        tokio::time::sleep(timeout).await;
+
+        Ok(self.quotes(&request_id).await)
+    }
+
+    /// Get the quotes received for an own request, best price first
+    pub async fn quotes(&self, request_id: &str) -> Vec<SignedQuote> {
+        let requests = self.requests.read().await;
+        let (request, quotes) = match requests.get(request_id) {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+
+        let now = self.clock.now();
+        let mut quotes: Vec<SignedQuote> = quotes.iter().filter(|quote| !quote.quote.is_expired_at(now)).cloned().collect();
+        quotes.sort_by(|a, b| {
+            let by_price = match request.side {
+                OrderSide::Buy => a.quote.price.cmp(&b.quote.price),
+                OrderSide::Sell => b.quote.price.cmp(&a.quote.price),
+            };
+            by_price.then_with(|| b.quote.amount.cmp(&a.quote.amount))
+        });
+        quotes
+    }
+
+    /// Get a received quote that can still be accepted
+    pub async fn quote(&self, quote_id: &str) -> Result<SignedQuote, RfqError> {
+        let requests = self.requests.read().await;
+        let quote = requests.values()
+            .flat_map(|(_, quotes)| quotes.iter())
+            .find(|quote| quote.quote.id == quote_id)
+            .ok_or_else(|| RfqError::UnknownQuote(quote_id.to_string()))?;
+
+        if quote.quote.is_expired_at(self.clock.now()) {
+            return Err(RfqError::Expired(format!("quote {}", quote_id)));
+        }
+
+        Ok(quote.clone())
+    }
+
+    /// Get the open quote requests received from takers
+    pub async fn inbound_requests(&self) -> Vec<QuoteRequest> {
+        let now = self.clock.now();
+        let mut requests: Vec<QuoteRequest> = self.inbound.read().await.values()
+            .filter(|request| !request.is_expired_at(now))
+            .cloned()
+            .collect();
+        requests.sort_by(|a, b| a.expires_at.cmp(&b.expires_at));
+        requests
+    }
+
+    /// Answer a quote request with a signed quote, sent to the taker alone
+    pub async fn send_quote(&self, request_id: &str, amount: Decimal, price: Decimal, ttl: u64) -> Result<SignedQuote> {
+        let request = self.inbound.read().await.get(request_id).cloned()
+            .ok_or_else(|| RfqError::UnknownRequest(request_id.to_string()))?;
+        let now = self.clock.now();
+        if request.is_expired_at(now) {
+            return Err(RfqError::Expired(format!("quote request {}", request_id)).into());
+        }
+
+        let taker: libp2p::PeerId = request.taker.parse()
+            .map_err(|_| RfqError::InvalidRequest(format!("Invalid taker peer ID: {}", request.taker)))?;
+
+        let mut network = self.network.write().await;
+        let maker = network.local_peer_id().to_string();
+        let quote = SignedQuote::sign(Quote::new(&request, maker, amount, price, ttl, now)?, network.keypair())?;
+
+        let message_data = envelope::encode(&RfqMessage::Quote(quote.clone()))
+            .context("Failed to serialize quote")?;
+        network.send_request(&taker, QUOTE_PROTOCOL, message_data).await?;
+        info!("Quoted {} at {} for request {}", amount, price, request_id);
+
+        Ok(quote)
+    }
+
+    /// Handle an RFQ message from a peer
+    pub async fn handle_rfq_message(&self, message: RfqMessage, peer_id: &str) -> Result<()> {
+        match message {
+            RfqMessage::Request(request) => {
+                // Only the taker can ask for quotes in its name
+                if request.taker != peer_id {
+                    return Err(RfqError::InvalidRequest("Request taker does not match peer ID".to_string()).into());
+                }
+                let now = self.clock.now();
+                request.validate(now)?;
+
+                // Drop the requests that can no longer be quoted
+                let mut inbound = self.inbound.write().await;
+                inbound.retain(|_, request| !request.is_expired_at(now));
+                if inbound.insert(request.id.clone(), request.clone()).is_some() {
+                    return Ok(());
+                }
+                drop(inbound);
+
+                let _ = self.event_sender
+                    .send(Event::QuoteRequestReceived(request))
+                    .await;
+            }
+            RfqMessage::Quote(quote) => {
+                // Only the maker can send its quotes
+                if quote.quote.maker != peer_id {
+                    return Err(RfqError::InvalidQuote("Quote maker does not match peer ID".to_string()).into());
+                }
+                quote.verify()?;
+                if quote.quote.is_expired_at(self.clock.now()) {
+                    return Err(RfqError::Expired(format!("quote {}", quote.quote.id)).into());
+                }
+
+                let mut requests = self.requests.write().await;
+                let (request, quotes) = requests.get_mut(&quote.quote.request_id)
+                    .ok_or_else(|| RfqError::UnknownRequest(quote.quote.request_id.clone()))?;
+
+                // The quote must be for what was asked
+                let terms = &quote.quote;
+                if terms.taker != request.taker
+                    || terms.base_asset != request.base_asset
+                    || terms.quote_asset != request.quote_asset
+                    || terms.side != request.side
+                    || terms.amount <= Decimal::ZERO
+                    || terms.amount > request.amount
+                    || terms.price <= Decimal::ZERO
+                {
+                    return Err(RfqError::InvalidQuote(format!("Quote {} does not match request {}", terms.id, request.id)).into());
+                }
+
+                // A maker's newer quote replaces its older one
+                quotes.retain(|existing| existing.quote.maker != terms.maker);
+                quotes.push(quote.clone());
+                drop(requests);
+                debug!("Received quote {} from {}", quote.quote.id, peer_id);
+
+                let _ = self.event_sender
+                    .send(Event::QuoteReceived(quote))
+                    .await;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Settlement instructions
+//!
+//! Makers and takers can have the proceeds of a trade paid to an address other than
+//! their wallet's default, e.g. straight to cold storage. The taker's payout address
+//! travels in the `Initialize` message and the maker's in its `SendPsbt` message.
+//! PSBT construction pays each side's proceeds to its payout address, and a side
+//! that asked for one refuses to sign a counterparty PSBT that does not pay it its
+//! proceeds in full: the bitcoin it receives, or a dust output carrying the rune
+//! or alkane. Payout addresses of own orders are kept in [`PAYOUT_NAMESPACE`].
+//!
+//! A payout address can travel with a BIP-322 proof that its owner controls it,
+//! signing [`payout_proof_message`]. A node requiring proofs refuses to pay a
+//! counterparty address without a valid one, so an address swapped on the
+//! counterparty's machine, e.g. by clipboard malware, is not silently paid.
+
+use std::str::FromStr;
+
+use bitcoin::consensus::Decodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{Address, Network};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{batch, Trade, TradeError};
+use crate::orderbook::OrderSide;
+use crate::types::Asset;
+use crate::wallet::bip322;
+
+/// Storage namespace of the payout addresses of own orders, keyed by order ID
+pub const PAYOUT_NAMESPACE: &str = "payout_addresses";
+
+/// Value of the output carrying a rune or alkane payout (satoshis)
+pub const ASSET_PAYOUT_VALUE: u64 = 546;
+
+/// Settlement instructions of a trade
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settlement {
+    /// Address the maker's proceeds are paid to, instead of the maker wallet's
+    pub maker_payout_address: Option<String>,
+    /// Address the taker's proceeds are paid to, instead of the taker wallet's
+    pub taker_payout_address: Option<String>,
+}
+
+impl Settlement {
+    /// Get the payout address of one side
+    pub fn payout_address(&self, is_maker: bool) -> Option<&str> {
+        if is_maker {
+            self.maker_payout_address.as_deref()
+        } else {
+            self.taker_payout_address.as_deref()
+        }
+    }
+}
+
+/// Output a trade PSBT must carry to pay one side's proceeds to its payout address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayoutOutput {
+    /// Payout address
+    pub address: String,
+    /// Least value of the output (satoshis)
+    pub amount: u64,
+}
+
+/// Get the least value the output paying one side's proceeds must have (satoshis)
+///
+/// The maker of a sell order receives the quote asset and the taker the base
+/// asset, and the other way round for a buy order. Bitcoin proceeds are paid in
+/// full; a rune or alkane travels on a dust output.
+pub fn payout_amount(trade: &Trade, maker_side: OrderSide, is_maker: bool) -> Result<u64, TradeError> {
+    let (amount, notional) = if trade.is_batch() {
+        (batch::total_amount(&trade.legs), batch::notional(&trade.legs))
+    } else {
+        (trade.amount, trade.amount * trade.price)
+    };
+
+    let receives_base = (maker_side == OrderSide::Buy) == is_maker;
+    let (asset, value) = if receives_base {
+        (&trade.base_asset, amount)
+    } else {
+        (&trade.quote_asset, notional)
+    };
+
+    match asset {
+        Asset::Bitcoin => (value * Decimal::from(100_000_000)).floor().to_u64()
+            .ok_or_else(|| TradeError::PsbtError(format!("Invalid bitcoin amount: {}", value))),
+        _ => Ok(ASSET_PAYOUT_VALUE),
+    }
+}
+
+/// Parse a payout address, checking that it belongs to the network
+pub fn parse_payout_address(address: &str, network: Network) -> Result<Address, TradeError> {
+    let parsed = Address::from_str(address)
+        .map_err(|e| TradeError::InvalidPayoutAddress(format!("{}: {}", address, e)))?;
+
+    if !parsed.is_valid_for_network(network) {
+        return Err(TradeError::InvalidPayoutAddress(format!("{} is not a {} address", address, network)));
+    }
+
+    Ok(parsed)
+}
+
+/// Code starting the cancel reason of a proposal refused for an unproven payout address
+pub const PAYOUT_PROOF_REQUIRED: &str = "payout_proof_required";
+
+/// Get the message a payout address proof signs
+pub fn payout_proof_message(address: &str) -> String {
+    format!("DarkSwap payout address: {}", address)
+}
+
+/// Check a BIP-322 proof that the owner of a payout address controls it
+pub fn verify_payout_proof(address: &Address, proof: Option<&str>) -> Result<(), TradeError> {
+    let proof = proof
+        .ok_or_else(|| TradeError::PayoutProof(format!("No ownership proof for {}", address)))?;
+    let message = payout_proof_message(&address.to_string());
+
+    match bip322::verify(address, message.as_bytes(), proof) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(TradeError::PayoutProof(format!("Proof does not match {}", address))),
+        Err(e) => Err(TradeError::PayoutProof(format!("Invalid proof for {}: {}", address, e))),
+    }
+}
+
+/// Check that a consensus-encoded PSBT pays an address at least `amount` satoshis
+pub fn psbt_pays_to(psbt: &[u8], address: &Address, amount: u64) -> bool {
+    let script_pubkey = address.script_pubkey();
+
+    match Psbt::consensus_decode(&mut &psbt[..]) {
+        Ok(psbt) => {
+            let paid: u64 = psbt.unsigned_tx.output.iter()
+                .filter(|output| output.script_pubkey == script_pubkey)
+                .map(|output| output.value)
+                .sum();
+            paid > 0 && paid >= amount
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::encode::serialize;
+    use bitcoin::{PackedLockTime, Transaction, TxOut};
+
+    const REGTEST_ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    #[test]
+    fn test_parse_payout_address_checks_network() {
+        assert!(parse_payout_address(REGTEST_ADDRESS, Network::Regtest).is_ok());
+        assert!(parse_payout_address(REGTEST_ADDRESS, Network::Bitcoin).is_err());
+        assert!(parse_payout_address("not an address", Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn test_psbt_pays_to() {
+        let payout = parse_payout_address(REGTEST_ADDRESS, Network::Regtest).unwrap();
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut { value: 10_000, script_pubkey: payout.script_pubkey() }],
+        };
+        let psbt = serialize(&Psbt::from_unsigned_tx(tx).unwrap());
+
+        assert!(psbt_pays_to(&psbt, &payout, 10_000));
+
+        // Paying the address less than the proceeds is not paying it
+        assert!(!psbt_pays_to(&psbt, &payout, 10_001));
+
+        let other = parse_payout_address("bcrt1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qzf4jry", Network::Regtest).unwrap();
+        assert!(!psbt_pays_to(&psbt, &other, 546));
+        assert!(!psbt_pays_to(b"not a psbt", &payout, 546));
+    }
+
+    #[test]
+    fn test_payout_amount() {
+        let trade = Trade::new(
+            crate::orderbook::OrderId("order".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Rune(1),
+            Asset::Bitcoin,
+            Decimal::new(2, 0),
+            Decimal::new(1, 2),
+            None,
+        );
+
+        // Selling runes for bitcoin: the maker gets 0.02 BTC, the taker the runes
+        assert_eq!(payout_amount(&trade, OrderSide::Sell, true).unwrap(), 2_000_000);
+        assert_eq!(payout_amount(&trade, OrderSide::Sell, false).unwrap(), ASSET_PAYOUT_VALUE);
+        assert_eq!(payout_amount(&trade, OrderSide::Buy, false).unwrap(), 2_000_000);
+    }
+
+    #[test]
+    fn test_verify_payout_proof() {
+        use bitcoin::secp256k1::Secp256k1;
+        use bitcoin::{PrivateKey, PublicKey};
+
+        let private_key = PrivateKey::from_slice(&[9u8; 32], Network::Regtest).unwrap();
+        let public_key = PublicKey::from_private_key(&Secp256k1::new(), &private_key);
+        let address = Address::p2wpkh(&public_key, Network::Regtest).unwrap();
+        let message = payout_proof_message(&address.to_string());
+        let proof = bip322::sign_p2wpkh(&private_key, message.as_bytes()).unwrap();
+
+        assert!(verify_payout_proof(&address, Some(&proof)).is_ok());
+        assert!(matches!(verify_payout_proof(&address, None), Err(TradeError::PayoutProof(_))));
+
+        // A proof for one address does not vouch for another
+        let swapped = parse_payout_address(REGTEST_ADDRESS, Network::Regtest).unwrap();
+        assert!(matches!(verify_payout_proof(&swapped, Some(&proof)), Err(TradeError::PayoutProof(_))));
+    }
+}
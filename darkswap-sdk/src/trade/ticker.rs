@@ -0,0 +1,144 @@
+//! Trading pair statistics
+//!
+//! A ticker summarizes the completed trades of a pair over a rolling window,
+//! 24 hours by default: the last price, the high and low, and the traded
+//! volume. Only trades with a completion time count.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{Trade, TradeState};
+use crate::types::Asset;
+
+/// Default ticker window (seconds)
+pub const TICKER_WINDOW: u64 = 24 * 60 * 60;
+
+/// Statistics of a trading pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ticker {
+    /// Base asset
+    pub base_asset: Asset,
+    /// Quote asset
+    pub quote_asset: Asset,
+    /// Price of the last completed trade, also if it is older than the window
+    pub last_price: Option<Decimal>,
+    /// Price of the first trade in the window
+    pub open_price: Option<Decimal>,
+    /// Highest price in the window
+    pub high: Option<Decimal>,
+    /// Lowest price in the window
+    pub low: Option<Decimal>,
+    /// Traded base amount in the window
+    pub volume: Decimal,
+    /// Traded quote amount in the window
+    pub quote_volume: Decimal,
+    /// Number of trades in the window
+    pub trade_count: usize,
+    /// Start of the window (unix seconds)
+    pub window_start: u64,
+}
+
+impl Ticker {
+    /// Get the price change over the window
+    pub fn price_change(&self) -> Option<Decimal> {
+        Some(self.last_price? - self.open_price?)
+    }
+}
+
+/// Compute the ticker of a pair from trades, over the `window` seconds before `now`
+pub fn ticker(trades: &[Trade], base_asset: &Asset, quote_asset: &Asset, now: u64, window: u64) -> Ticker {
+    let window_start = now.saturating_sub(window);
+
+    let mut completed: Vec<(u64, &Trade)> = trades
+        .iter()
+        .filter(|trade| trade.state == TradeState::Completed)
+        .filter(|trade| &trade.base_asset == base_asset && &trade.quote_asset == quote_asset)
+        .filter_map(|trade| trade.completed_at.map(|completed_at| (completed_at, trade)))
+        .filter(|(completed_at, _)| *completed_at <= now)
+        .collect();
+    completed.sort_by_key(|(completed_at, _)| *completed_at);
+
+    let last_price = completed.last().map(|(_, trade)| trade.price);
+    let in_window: Vec<&Trade> = completed
+        .into_iter()
+        .filter(|(completed_at, _)| *completed_at >= window_start)
+        .map(|(_, trade)| trade)
+        .collect();
+
+    Ticker {
+        base_asset: base_asset.clone(),
+        quote_asset: quote_asset.clone(),
+        last_price,
+        open_price: in_window.first().map(|trade| trade.price),
+        high: in_window.iter().map(|trade| trade.price).max(),
+        low: in_window.iter().map(|trade| trade.price).min(),
+        volume: in_window.iter().map(|trade| trade.amount).sum(),
+        quote_volume: in_window.iter().map(|trade| trade.amount * trade.price).sum(),
+        trade_count: in_window.len(),
+        window_start,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use rust_decimal_macros::dec;
+
+    fn trade(amount: Decimal, price: Decimal, completed_at: Option<u64>) -> Trade {
+        let mut trade = Trade::new(
+            OrderId("order".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            amount,
+            price,
+            None,
+            0,
+        );
+        if completed_at.is_some() {
+            trade.state = TradeState::Completed;
+        }
+        trade.completed_at = completed_at;
+        trade
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let now = 1_000_000;
+        let trades = vec![
+            trade(dec!(5), dec!(90), Some(now - TICKER_WINDOW - 1)),
+            trade(dec!(1), dec!(100), Some(now - 3600)),
+            trade(dec!(2), dec!(120), Some(now - 60)),
+            trade(dec!(1), dec!(110), Some(now - 10)),
+            trade(dec!(9), dec!(500), None),
+        ];
+
+        let ticker = ticker(&trades, &Asset::Bitcoin, &Asset::Rune(1), now, TICKER_WINDOW);
+        assert_eq!(ticker.last_price, Some(dec!(110)));
+        assert_eq!(ticker.open_price, Some(dec!(100)));
+        assert_eq!(ticker.high, Some(dec!(120)));
+        assert_eq!(ticker.low, Some(dec!(100)));
+        assert_eq!(ticker.volume, dec!(4));
+        assert_eq!(ticker.quote_volume, dec!(450));
+        assert_eq!(ticker.trade_count, 3);
+        assert_eq!(ticker.price_change(), Some(dec!(10)));
+    }
+
+    #[test]
+    fn test_quiet_pair_keeps_last_price() {
+        let now = 1_000_000;
+        let trades = vec![trade(dec!(1), dec!(100), Some(now - 2 * TICKER_WINDOW))];
+
+        let ticker = ticker(&trades, &Asset::Bitcoin, &Asset::Rune(1), now, TICKER_WINDOW);
+        assert_eq!(ticker.last_price, Some(dec!(100)));
+        assert_eq!(ticker.high, None);
+        assert_eq!(ticker.volume, Decimal::ZERO);
+        assert_eq!(ticker.trade_count, 0);
+        assert_eq!(ticker.price_change(), None);
+
+        let other = super::ticker(&trades, &Asset::Bitcoin, &Asset::Rune(2), now, TICKER_WINDOW);
+        assert_eq!(other.last_price, None);
+    }
+}
@@ -0,0 +1,209 @@
+//! Trade latency timeline
+//!
+//! Each trade keeps the wall clock time, in milliseconds, at which it first
+//! reached each stage of the protocol: the proposal going out or coming in,
+//! the maker accepting with its PSBT, the PSBTs being exchanged, signing,
+//! broadcast and confirmation, once the deposit watcher sees the settlement
+//! confirmed in the wallet or a proof of inclusion is checked. The entries
+//! are part of the trade, so they survive restarts with it.
+//!
+//! A [`TradeTimeline`] turns the entries into the time spent on each step, to
+//! tell whether a slow trade waited on the network, on the counterparty or on
+//! the chain.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Trade, TradeState};
+use crate::types::TradeId;
+
+/// Stage of a trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeStage {
+    /// Taker sent the proposal
+    ProposalSent,
+    /// Maker received the proposal
+    ProposalReceived,
+    /// Maker accepted the proposal and sent its PSBT
+    Accepted,
+    /// Taker added its side of the PSBT
+    PsbtExchanged,
+    /// The PSBT was signed
+    Signed,
+    /// The transaction was broadcast
+    Broadcast,
+    /// The transaction was confirmed in a block
+    Confirmed,
+    /// Trade failed
+    Failed,
+    /// Trade canceled
+    Canceled,
+    /// Trade expired
+    Expired,
+}
+
+impl TradeStage {
+    /// Get the stage a trade entering a state reaches, if any
+    pub fn of_state(state: TradeState) -> Option<Self> {
+        match state {
+            TradeState::Created => None,
+            TradeState::MakerPsbtSent => Some(TradeStage::Accepted),
+            TradeState::TakerPsbtSent => Some(TradeStage::PsbtExchanged),
+            TradeState::MakerSigned | TradeState::TakerSigned => Some(TradeStage::Signed),
+            TradeState::Completed => Some(TradeStage::Broadcast),
+            TradeState::Failed => Some(TradeStage::Failed),
+            TradeState::Canceled => Some(TradeStage::Canceled),
+            TradeState::Expired => Some(TradeStage::Expired),
+        }
+    }
+}
+
+impl fmt::Display for TradeStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TradeStage::ProposalSent => "proposal sent",
+            TradeStage::ProposalReceived => "proposal received",
+            TradeStage::Accepted => "accepted",
+            TradeStage::PsbtExchanged => "PSBT exchanged",
+            TradeStage::Signed => "signed",
+            TradeStage::Broadcast => "broadcast",
+            TradeStage::Confirmed => "confirmed",
+            TradeStage::Failed => "failed",
+            TradeStage::Canceled => "canceled",
+            TradeStage::Expired => "expired",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Time a trade reached a stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineEntry {
+    /// Stage
+    pub stage: TradeStage,
+    /// Time the stage was reached (unix milliseconds)
+    pub at_ms: u64,
+}
+
+/// Stage of a timeline with the time it took to reach
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimelineStep {
+    /// Stage
+    pub stage: TradeStage,
+    /// Time the stage was reached (unix milliseconds)
+    pub at_ms: u64,
+    /// Time since the previous stage (milliseconds)
+    pub since_previous_ms: u64,
+    /// Time since the first stage (milliseconds)
+    pub since_start_ms: u64,
+}
+
+/// Stages a trade went through, with the time spent on each
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeTimeline {
+    /// Trade ID
+    pub trade_id: TradeId,
+    /// Current state
+    pub state: TradeState,
+    /// Stages reached, oldest first
+    pub steps: Vec<TimelineStep>,
+    /// Time from the first to the last stage (milliseconds)
+    pub total_ms: u64,
+    /// Stage that took longest to reach
+    pub slowest: Option<TradeStage>,
+}
+
+impl TradeTimeline {
+    /// Get the timeline of a trade
+    pub fn of(trade: &Trade) -> Self {
+        let mut entries = trade.timeline.clone();
+        entries.sort_by_key(|entry| entry.at_ms);
+
+        let start = entries.first().map_or(0, |entry| entry.at_ms);
+        let mut previous = start;
+        let steps: Vec<TimelineStep> = entries
+            .iter()
+            .map(|entry| {
+                let step = TimelineStep {
+                    stage: entry.stage,
+                    at_ms: entry.at_ms,
+                    since_previous_ms: entry.at_ms.saturating_sub(previous),
+                    since_start_ms: entry.at_ms.saturating_sub(start),
+                };
+                previous = entry.at_ms;
+                step
+            })
+            .collect();
+
+        let total_ms = steps.last().map_or(0, |step| step.since_start_ms);
+        let slowest = steps
+            .iter()
+            .skip(1)
+            .max_by_key(|step| step.since_previous_ms)
+            .map(|step| step.stage);
+
+        Self {
+            trade_id: trade.id.clone(),
+            state: trade.state,
+            steps,
+            total_ms,
+            slowest,
+        }
+    }
+
+    /// Get the time a stage was reached (unix milliseconds)
+    pub fn reached(&self, stage: TradeStage) -> Option<u64> {
+        self.steps.iter().find(|step| step.stage == stage).map(|step| step.at_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use crate::types::Asset;
+    use rust_decimal::Decimal;
+
+    const START_MS: u64 = 1_700_000_000_000;
+
+    #[test]
+    fn test_timeline() {
+        let mut trade = Trade::new(
+            OrderId("order-1".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            Decimal::ONE,
+            Decimal::ONE,
+            None,
+            START_MS / 1000,
+        );
+        trade.record_stage(TradeStage::ProposalSent, START_MS);
+        trade.update_state_at_ms(TradeState::TakerPsbtSent, START_MS + 250);
+        trade.update_state_at_ms(TradeState::MakerSigned, START_MS + 400);
+        trade.update_state_at_ms(TradeState::Completed, START_MS + 2_400);
+        trade.record_stage(TradeStage::Confirmed, START_MS + 600_000);
+
+        // Only the first time a stage is reached counts
+        trade.record_stage(TradeStage::ProposalSent, START_MS + 10);
+        trade.update_state_at_ms(TradeState::TakerSigned, START_MS + 500);
+
+        let timeline = TradeTimeline::of(&trade);
+        let stages: Vec<TradeStage> = timeline.steps.iter().map(|step| step.stage).collect();
+        assert_eq!(stages, vec![
+            TradeStage::ProposalSent,
+            TradeStage::PsbtExchanged,
+            TradeStage::Signed,
+            TradeStage::Broadcast,
+            TradeStage::Confirmed,
+        ]);
+        assert_eq!(timeline.steps[3].since_previous_ms, 2_000);
+        assert_eq!(timeline.total_ms, 600_000);
+        assert_eq!(timeline.slowest, Some(TradeStage::Confirmed));
+        assert_eq!(timeline.reached(TradeStage::Broadcast), Some(START_MS + 2_400));
+        assert_eq!(trade.completed_at, Some(1_700_000_002));
+    }
+}
@@ -0,0 +1,190 @@
+//! Negotiation transcripts
+//!
+//! The messages exchanged with a counterparty while negotiating a trade can be
+//! kept next to the trade, as a record of what was agreed should the trade be
+//! disputed later. Transcripts never leave the node: they are written to the
+//! node's storage only, which must be encrypted for them to be kept at all,
+//! and a transcript is dropped once its last message is older than the
+//! retention.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::{Storage, StorageExt};
+use serde::{Deserialize, Serialize};
+
+use crate::config::TranscriptConfig;
+use crate::types::TradeId;
+
+/// Storage namespace of the transcripts
+pub const TRANSCRIPT_NAMESPACE: &str = "trade_transcripts";
+
+/// Transcript message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptMessage {
+    /// Sequence number, increasing across all transcripts
+    pub sequence: u64,
+    /// Time the message was sent or received (unix seconds)
+    pub sent_at: u64,
+    /// Peer ID of the sender
+    pub sender: String,
+    /// Text
+    pub text: String,
+}
+
+/// Negotiation transcript of a trade
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeTranscript {
+    /// Trade ID
+    pub trade_id: TradeId,
+    /// Messages, oldest first
+    pub messages: Vec<TranscriptMessage>,
+}
+
+/// Get the key prefix of the messages of a trade
+fn trade_prefix(trade_id: &TradeId) -> String {
+    format!("{}/", trade_id)
+}
+
+/// Transcripts kept in storage
+pub struct TranscriptStore {
+    /// Storage
+    storage: Arc<dyn Storage>,
+    /// How long transcripts are kept
+    config: TranscriptConfig,
+    /// Sequence number of the next message
+    next_sequence: AtomicU64,
+    /// Time of the last pruning (unix seconds)
+    pruned_at: AtomicU64,
+}
+
+impl TranscriptStore {
+    /// Open the transcripts kept in `storage`, dropping those past the retention at `now`
+    pub fn open(storage: Arc<dyn Storage>, config: TranscriptConfig, now: u64) -> Result<Self> {
+        let next_sequence = storage
+            .scan_json::<TranscriptMessage>(TRANSCRIPT_NAMESPACE, "")
+            .context("Failed to read the trade transcripts")?
+            .iter()
+            .map(|(_, message)| message.sequence + 1)
+            .max()
+            .unwrap_or(0);
+
+        let store = Self {
+            storage,
+            config,
+            next_sequence: AtomicU64::new(next_sequence),
+            pruned_at: AtomicU64::new(now),
+        };
+        store.prune(now)?;
+
+        Ok(store)
+    }
+
+    /// Add a message to the transcript of a trade, pruning expired transcripts once a day
+    pub fn append(&self, trade_id: &TradeId, sender: &str, text: &str, sent_at: u64) -> Result<TranscriptMessage> {
+        let message = TranscriptMessage {
+            sequence: self.next_sequence.fetch_add(1, Ordering::SeqCst),
+            sent_at,
+            sender: sender.to_string(),
+            text: text.to_string(),
+        };
+
+        // Zero-padded sequence numbers keep the messages of a trade in order
+        let key = format!("{}{:020}", trade_prefix(trade_id), message.sequence);
+        self.storage
+            .put_json(TRANSCRIPT_NAMESPACE, &key, &message)
+            .context("Failed to write to the trade transcript")?;
+        self.storage.flush()?;
+
+        if sent_at >= self.pruned_at.load(Ordering::SeqCst).saturating_add(86400) {
+            self.pruned_at.store(sent_at, Ordering::SeqCst);
+            self.prune(sent_at)?;
+        }
+
+        Ok(message)
+    }
+
+    /// Get the transcript of a trade; it has no messages if none were kept
+    pub fn get(&self, trade_id: &TradeId) -> Result<TradeTranscript> {
+        let messages = self.storage
+            .scan_json::<TranscriptMessage>(TRANSCRIPT_NAMESPACE, &trade_prefix(trade_id))
+            .context("Failed to read the trade transcript")?
+            .into_iter()
+            .map(|(_, message)| message)
+            .collect();
+
+        Ok(TradeTranscript { trade_id: trade_id.clone(), messages })
+    }
+
+    /// Delete the transcript of a trade
+    pub fn delete(&self, trade_id: &TradeId) -> Result<()> {
+        for (key, _) in self.storage.scan_prefix(TRANSCRIPT_NAMESPACE, trade_prefix(trade_id).as_bytes())? {
+            self.storage.delete(TRANSCRIPT_NAMESPACE, &key).context("Failed to delete the trade transcript")?;
+        }
+        self.storage.flush()?;
+
+        Ok(())
+    }
+
+    /// Drop the transcripts whose last message is older than the retention at `now`, returning how many
+    ///
+    /// A retention of zero days keeps transcripts forever.
+    pub fn prune(&self, now: u64) -> Result<usize> {
+        if self.config.retention_days == 0 {
+            return Ok(0);
+        }
+        let cutoff = now.saturating_sub(self.config.retention_days.saturating_mul(86400));
+
+        let mut last_message: BTreeMap<String, u64> = BTreeMap::new();
+        for (key, message) in self.storage.scan_json::<TranscriptMessage>(TRANSCRIPT_NAMESPACE, "")? {
+            let trade_id = key.rsplit_once('/').map_or(key.as_str(), |(trade_id, _)| trade_id).to_string();
+            let last = last_message.entry(trade_id).or_default();
+            *last = (*last).max(message.sent_at);
+        }
+
+        let expired: Vec<String> = last_message
+            .into_iter()
+            .filter(|(_, last)| *last < cutoff)
+            .map(|(trade_id, _)| trade_id)
+            .collect();
+        for trade_id in &expired {
+            self.delete(&TradeId(trade_id.clone()))?;
+        }
+
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use darkswap_support::storage::MemoryStorage;
+
+    const NOW: u64 = 1_700_000_000;
+    const DAY: u64 = 86400;
+
+    #[test]
+    fn test_transcripts() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let config = TranscriptConfig { enabled: true, retention_days: 30 };
+        let store = TranscriptStore::open(storage.clone(), config.clone(), NOW).unwrap();
+
+        let old = TradeId("trade-1".to_string());
+        let recent = TradeId("trade-2".to_string());
+        store.append(&old, "maker", "0.5 BTC at 100?", NOW).unwrap();
+        store.append(&recent, "taker", "hello", NOW + 5 * DAY).unwrap();
+        store.append(&old, "taker", "deal", NOW + DAY).unwrap();
+
+        let transcript = store.get(&old).unwrap();
+        let texts: Vec<&str> = transcript.messages.iter().map(|message| message.text.as_str()).collect();
+        assert_eq!(texts, vec!["0.5 BTC at 100?", "deal"]);
+
+        // A reopened store carries on the sequence and drops what is past the retention
+        let reopened = TranscriptStore::open(storage, config, NOW + 31 * DAY + 1).unwrap();
+        assert!(reopened.get(&old).unwrap().messages.is_empty());
+        assert_eq!(reopened.append(&recent, "maker", "bye", NOW + 32 * DAY).unwrap().sequence, 3);
+        assert_eq!(reopened.get(&recent).unwrap().messages.len(), 2);
+    }
+}
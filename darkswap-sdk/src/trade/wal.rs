@@ -0,0 +1,381 @@
+//! Write-ahead log of trade protocol messages
+//!
+//! Every trade message is logged before it is acted on: an inbound message
+//! with the state of its trade before it was applied, an outbound one as the
+//! signed envelope that is about to be published. An entry is resolved once
+//! the message was handled or published. After a crash, pending outbound
+//! entries are published again byte for byte, so nothing is signed twice, and
+//! pending inbound entries are applied again to the state they found, unless a
+//! later entry of the same trade shows they already took effect. A PSBT is
+//! logged before the wallet signs it, so the message that asked for the
+//! signature is never applied twice. The log also keeps a checkpoint of each
+//! trade in flight, so trades are restored along with it.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use darkswap_support::storage::{Storage, StorageExt};
+use serde::{Deserialize, Serialize};
+
+use super::{Trade, TradeMessage, TradeState};
+use crate::clock::Clock;
+use crate::types::TradeId;
+
+/// Storage namespace of the log
+pub const WAL_NAMESPACE: &str = "trade_wal";
+
+/// Entries resolved between two compactions
+pub const COMPACT_EVERY: u64 = 256;
+
+/// Key prefix of message entries
+const MESSAGE_PREFIX: &str = "msg/";
+
+/// Key prefix of trade checkpoints
+const TRADE_PREFIX: &str = "trade/";
+
+/// Logged trade message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "direction", rename_all = "snake_case")]
+pub enum WalMessage {
+    /// Message received from a counterparty
+    Inbound {
+        /// Sender
+        peer_id: String,
+        /// Message
+        message: TradeMessage,
+        /// Trade the message was applied to, as it was before
+        trade: Option<Trade>,
+    },
+    /// Signed message sent to a counterparty
+    Outbound {
+        /// Recipient
+        peer_id: String,
+        /// Trade the message belongs to
+        trade_id: TradeId,
+        /// Message kind, for inspection
+        kind: String,
+        /// Encoded signed envelope, as published
+        data: Vec<u8>,
+    },
+    /// Trade PSBT about to be signed
+    Signing {
+        /// Counterparty the signature is for
+        peer_id: String,
+        /// Trade the PSBT belongs to
+        trade_id: TradeId,
+    },
+}
+
+impl WalMessage {
+    /// Get the trade the message belongs to
+    pub fn trade_id(&self) -> &TradeId {
+        match self {
+            WalMessage::Inbound { message, .. } => message.trade_id(),
+            WalMessage::Outbound { trade_id, .. } | WalMessage::Signing { trade_id, .. } => trade_id,
+        }
+    }
+}
+
+/// Log entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalEntry {
+    /// Position in the log, increasing with every entry
+    pub sequence: u64,
+    /// Time the entry was written (unix seconds)
+    pub recorded_at: u64,
+    /// Message
+    pub message: WalMessage,
+    /// Whether the message was handled or sent
+    pub resolved: bool,
+}
+
+/// Outcome of resolving the log after a restart
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalRecovery {
+    /// Trades restored from their checkpoints
+    pub restored: usize,
+    /// Outbound messages published again
+    pub resent: usize,
+    /// Inbound messages applied again
+    pub reapplied: usize,
+    /// Inbound messages a later entry showed to have taken effect
+    pub superseded: usize,
+    /// Signatures made without their message being logged
+    #[serde(default)]
+    pub unsent: usize,
+}
+
+/// Check if a trade will not change anymore
+fn is_finished(state: TradeState) -> bool {
+    matches!(state, TradeState::Completed | TradeState::Failed | TradeState::Canceled | TradeState::Expired)
+}
+
+/// Write-ahead log of the trade protocol
+pub struct TradeWal {
+    /// Storage the entries are kept in
+    storage: Arc<dyn Storage>,
+    /// Time source of the entry times
+    clock: Arc<dyn Clock>,
+    /// Sequence number of the next entry
+    next_sequence: AtomicU64,
+    /// Entries resolved since the last compaction
+    resolved: AtomicU64,
+}
+
+impl TradeWal {
+    /// Open the log, continuing after the entries already in storage
+    pub fn open(storage: Arc<dyn Storage>, clock: Arc<dyn Clock>) -> Result<Self> {
+        let next_sequence = storage
+            .scan_prefix(WAL_NAMESPACE, MESSAGE_PREFIX.as_bytes())
+            .context("Failed to read the trade log")?
+            .last()
+            .and_then(|(key, _)| std::str::from_utf8(&key[MESSAGE_PREFIX.len()..]).ok()?.parse::<u64>().ok())
+            .map_or(0, |sequence| sequence + 1);
+
+        Ok(Self {
+            storage,
+            clock,
+            next_sequence: AtomicU64::new(next_sequence),
+            resolved: AtomicU64::new(0),
+        })
+    }
+
+    /// Write an entry to storage; zero-padded keys keep the entries in sequence order
+    fn write(&self, entry: &WalEntry) -> Result<()> {
+        self.storage
+            .put_json(WAL_NAMESPACE, &format!("{}{:020}", MESSAGE_PREFIX, entry.sequence), entry)
+            .context("Failed to write trade log entry")
+    }
+
+    /// Log a message before it is acted on, returning its sequence number
+    ///
+    /// The entry is flushed to disk before this returns.
+    pub fn append(&self, message: WalMessage) -> Result<u64> {
+        let entry = WalEntry {
+            sequence: self.next_sequence.fetch_add(1, Ordering::SeqCst),
+            recorded_at: self.clock.now(),
+            message,
+            resolved: false,
+        };
+        self.write(&entry)?;
+        self.storage.flush().context("Failed to flush the trade log")?;
+
+        Ok(entry.sequence)
+    }
+
+    /// Mark an entry as handled, compacting the log every [`COMPACT_EVERY`] entries
+    pub fn resolve(&self, sequence: u64) -> Result<()> {
+        let key = format!("{}{:020}", MESSAGE_PREFIX, sequence);
+        let Some(mut entry) = self.storage.get_json::<WalEntry>(WAL_NAMESPACE, &key)
+            .context("Failed to read trade log entry")? else {
+            return Ok(());
+        };
+        entry.resolved = true;
+        self.write(&entry)?;
+
+        if self.resolved.fetch_add(1, Ordering::SeqCst) + 1 >= COMPACT_EVERY {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    /// Keep the current state of a trade, replacing its previous checkpoint
+    pub fn checkpoint(&self, trade: &Trade) -> Result<()> {
+        self.storage
+            .put_json(WAL_NAMESPACE, &format!("{}{}", TRADE_PREFIX, trade.id.0), trade)
+            .context("Failed to write trade checkpoint")
+    }
+
+    /// Get every entry, oldest first
+    pub fn entries(&self) -> Result<Vec<WalEntry>> {
+        let entries = self.storage
+            .scan_json::<WalEntry>(WAL_NAMESPACE, MESSAGE_PREFIX)
+            .context("Failed to read the trade log")?;
+
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    /// Get the checkpointed trades
+    pub fn checkpoints(&self) -> Result<Vec<Trade>> {
+        let trades = self.storage
+            .scan_json::<Trade>(WAL_NAMESPACE, TRADE_PREFIX)
+            .context("Failed to read the trade checkpoints")?;
+
+        Ok(trades.into_iter().map(|(_, trade)| trade).collect())
+    }
+
+    /// Drop what recovery no longer needs, returning the number of records removed
+    ///
+    /// Resolved entries older than the oldest pending one go, as do the
+    /// checkpoints of finished trades without pending entries.
+    pub fn compact(&self) -> Result<usize> {
+        self.resolved.store(0, Ordering::SeqCst);
+        let entries = self.entries()?;
+        let oldest_pending = entries.iter()
+            .find(|entry| !entry.resolved)
+            .map_or(u64::MAX, |entry| entry.sequence);
+
+        let mut removed = 0;
+        for entry in entries.iter().filter(|entry| entry.sequence < oldest_pending) {
+            let key = format!("{}{:020}", MESSAGE_PREFIX, entry.sequence);
+            if self.storage.delete(WAL_NAMESPACE, key.as_bytes()).context("Failed to compact the trade log")? {
+                removed += 1;
+            }
+        }
+
+        for trade in self.checkpoints()? {
+            let pending = entries.iter().any(|entry| !entry.resolved && entry.message.trade_id() == &trade.id);
+            if is_finished(trade.state) && !pending {
+                let key = format!("{}{}", TRADE_PREFIX, trade.id.0);
+                if self.storage.delete(WAL_NAMESPACE, key.as_bytes()).context("Failed to compact the trade log")? {
+                    removed += 1;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Check if a later entry of the same trade exists, i.e. the handling of `entry` went on past it
+pub fn is_superseded(entries: &[WalEntry], entry: &WalEntry) -> bool {
+    entries.iter().any(|later| later.sequence > entry.sequence && later.message.trade_id() == entry.message.trade_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::config::Config;
+    use crate::orderbook::OrderId;
+    use crate::p2p::P2PNetwork;
+    use crate::trade::replay::ReplayWallet;
+    use crate::trade::TradeModule;
+    use crate::types::Asset;
+    use darkswap_support::storage::MemoryStorage;
+    use rust_decimal_macros::dec;
+    use tokio::sync::{mpsc, RwLock};
+
+    fn wal(storage: Arc<dyn Storage>) -> TradeWal {
+        TradeWal::open(storage, Arc::new(MockClock::new(1_700_000_000))).unwrap()
+    }
+
+    fn trade() -> Trade {
+        Trade::new(
+            OrderId("order".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            dec!(1),
+            dec!(100),
+            None,
+            0,
+        )
+    }
+
+    fn cancel(trade_id: &TradeId) -> WalMessage {
+        WalMessage::Inbound {
+            peer_id: "maker".to_string(),
+            message: TradeMessage::Cancel { trade_id: trade_id.clone(), reason: "test".to_string() },
+            trade: None,
+        }
+    }
+
+    #[test]
+    fn test_append_and_resolve() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let log = wal(storage.clone());
+        let trade = trade();
+
+        let first = log.append(cancel(&trade.id)).unwrap();
+        let second = log.append(cancel(&trade.id)).unwrap();
+        log.resolve(first).unwrap();
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].resolved);
+        assert!(!entries[1].resolved);
+        assert!(is_superseded(&entries, &entries[0]));
+        assert!(!is_superseded(&entries, &entries[1]));
+
+        // Reopening continues the sequence
+        let reopened = wal(storage);
+        assert_eq!(reopened.append(cancel(&trade.id)).unwrap(), second + 1);
+    }
+
+    #[test]
+    fn test_compact() {
+        let log = wal(Arc::new(MemoryStorage::new()));
+        let mut finished = trade();
+        finished.update_state_at(TradeState::Completed, 1_700_000_000);
+        let in_flight = trade();
+        log.checkpoint(&finished).unwrap();
+        log.checkpoint(&in_flight).unwrap();
+
+        let first = log.append(cancel(&finished.id)).unwrap();
+        let pending = log.append(cancel(&in_flight.id)).unwrap();
+        let last = log.append(cancel(&finished.id)).unwrap();
+        log.resolve(first).unwrap();
+        log.resolve(last).unwrap();
+
+        // Only the first entry and the finished trade's checkpoint can go
+        assert_eq!(log.compact().unwrap(), 2);
+        let sequences: Vec<u64> = log.entries().unwrap().iter().map(|entry| entry.sequence).collect();
+        assert_eq!(sequences, vec![pending, last]);
+        let checkpoints = log.checkpoints().unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].id, in_flight.id);
+    }
+
+    #[tokio::test]
+    async fn test_recover() {
+        let log = Arc::new(wal(Arc::new(MemoryStorage::new())));
+        let canceled = trade();
+        let signed = trade();
+        let unsent = trade();
+        log.checkpoint(&canceled).unwrap();
+        log.checkpoint(&signed).unwrap();
+
+        // A cancel that was logged but never applied
+        log.append(cancel(&canceled.id)).unwrap();
+        // A PSBT that was signed, but whose message never reached the log
+        log.append(WalMessage::Inbound {
+            peer_id: "taker".to_string(),
+            message: TradeMessage::SendPsbt {
+                trade_id: signed.id.clone(),
+                psbt: Vec::new(),
+                payout_address: None,
+                payout_proof: None,
+            },
+            trade: Some(signed.clone()),
+        }).unwrap();
+        log.append(WalMessage::Signing { peer_id: "taker".to_string(), trade_id: signed.id.clone() }).unwrap();
+        // A signed message that was never published
+        log.append(WalMessage::Outbound {
+            peer_id: "maker".to_string(),
+            trade_id: unsent.id.clone(),
+            kind: "Cancel".to_string(),
+            data: vec![1, 2, 3],
+        }).unwrap();
+
+        let (event_sender, _events) = mpsc::channel(16);
+        let network = P2PNetwork::new(&Config::default(), event_sender.clone()).unwrap();
+        let wallet = Arc::new(ReplayWallet);
+        let module = TradeModule::new(Arc::new(RwLock::new(network)), event_sender, wallet.clone(), wallet.clone(), wallet)
+            .with_wal(log.clone());
+
+        let recovery = module.recover().await.unwrap();
+        assert_eq!(recovery, WalRecovery { restored: 2, resent: 1, reapplied: 1, superseded: 1, unsent: 1 });
+        assert_eq!(module.get_trade(&canceled.id).await.unwrap().state, TradeState::Canceled);
+        assert_eq!(module.get_trade(&signed.id).await.unwrap().state, signed.state);
+
+        // Everything was resolved, and the canceled trade is done with
+        assert!(log.entries().unwrap().is_empty());
+        let checkpoints = log.checkpoints().unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].id, signed.id);
+    }
+}
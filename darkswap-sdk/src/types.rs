@@ -23,7 +23,7 @@ impl fmt::Display for AlkaneId {
 }
 
 /// Trade ID
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct TradeId(pub String);
 
 impl fmt::Display for TradeId {
@@ -113,6 +113,8 @@ pub enum Event {
     OrderFilled(crate::orderbook::OrderId),
     /// Trade created
     TradeCreated(TradeId),
+    /// Inbound trade received from a taker, before it is approved
+    TradeProposalReceived(TradeId),
     /// Trade started
     TradeStarted(TradeId),
     /// Trade updated
@@ -125,6 +127,36 @@ pub enum Event {
     TradeExpired(TradeId),
     /// Trade failed
     TradeFailed(TradeId),
+    /// Taker asked to see the terms of an own dark order
+    RevealRequested(crate::orderbook::dark::RevealRequest),
+    /// Maker disclosed the terms of a dark order
+    OrderRevealed(crate::orderbook::Order),
+    /// Refund of a stalled escrow broadcast after its lock time
+    RefundBroadcast(crate::trade::refund::RefundPath),
+    /// Quote request received from a taker
+    QuoteRequestReceived(crate::p2p::rfq::QuoteRequest),
+    /// Quote received for an own quote request
+    QuoteReceived(crate::p2p::rfq::SignedQuote),
+    /// Uncommitted bitcoin dropped below the fee reserve
+    LowFeeReserve(crate::wallet::reserve::FeeReserveStatus),
+    /// A followed maker posted an order
+    NewOrderFromWatchedMaker(crate::orderbook::Order),
+    /// The price of a followed pair crossed one of its thresholds
+    PriceCrossedThreshold(crate::orderbook::watchlist::PriceCrossing),
+    /// Switched to another Bitcoin network; state of the previous one no longer applies
+    NetworkSwitched(crate::config::BitcoinNetwork),
+    /// Events were dropped because the subscriber lagged
+    LaggedEvents(u64),
+    /// The chain backend connection changed
+    ChainBackend(crate::chain::ChainBackendEvent),
+    /// A trade message was given up on before the counterparty acknowledged it
+    TradeMessageUndelivered(crate::trade::outbox::UndeliveredMessage),
+    /// The wallet balance of an asset changed
+    BalanceChanged(crate::wallet::balance_sync::BalanceChange),
+    /// An incoming payment to the wallet was seen
+    DepositDetected(crate::wallet::deposits::Deposit),
+    /// An incoming payment to the wallet reached the required confirmations
+    DepositConfirmed(crate::wallet::deposits::Deposit),
 }
 
 /// Rune
@@ -0,0 +1,241 @@
+//! Address book for DarkSwap wallets
+//!
+//! Keeps track of the receive addresses a wallet has handed out, with an optional
+//! label and whether the address has received funds. New addresses are refused once
+//! `gap_limit` consecutive addresses at the end of the book are unused, so a wallet
+//! restored from its seed still finds every funded address when it scans ahead.
+//!
+//! A book opened on storage keeps every change there, so the derivation index,
+//! labels and used flags survive restarts.
+
+use std::sync::Arc;
+
+use darkswap_support::storage::{Storage, StorageExt};
+use serde::{Deserialize, Serialize};
+
+use crate::wallet::WalletError;
+
+/// Storage namespace of the address book
+pub const ADDRESS_BOOK_NAMESPACE: &str = "address_book";
+
+/// Default number of unused addresses that may be issued in a row
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Issued receive address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressInfo {
+    /// Address
+    pub address: String,
+    /// Derivation index
+    pub index: u32,
+    /// Label
+    pub label: Option<String>,
+    /// Whether the address has received funds
+    pub used: bool,
+    /// Creation timestamp
+    pub created_at: u64,
+}
+
+/// Get the key of the address at a derivation index
+fn address_key(index: u32) -> String {
+    format!("address/{:010}", index)
+}
+
+/// Issued receive addresses
+#[derive(Clone)]
+pub struct AddressBook {
+    /// Addresses in derivation order
+    addresses: Vec<AddressInfo>,
+    /// Maximum number of unused addresses in a row
+    gap_limit: u32,
+    /// Storage the book is kept in; `None` keeps it in memory only
+    storage: Option<Arc<dyn Storage>>,
+}
+
+impl AddressBook {
+    /// Create an empty in-memory address book
+    pub fn new(gap_limit: u32) -> Self {
+        Self {
+            addresses: Vec::new(),
+            gap_limit: gap_limit.max(1),
+            storage: None,
+        }
+    }
+
+    /// Open the address book kept in `storage`
+    pub fn open(storage: Arc<dyn Storage>, gap_limit: u32) -> Result<Self, WalletError> {
+        let mut addresses: Vec<AddressInfo> = storage
+            .scan_json(ADDRESS_BOOK_NAMESPACE, "address/")
+            .map_err(|e| WalletError::Other(format!("Failed to load address book: {}", e)))?
+            .into_iter()
+            .map(|(_, info)| info)
+            .collect();
+        addresses.sort_by_key(|info| info.index);
+
+        // Indexes must be contiguous for the next one to be right
+        if let Some((position, info)) = addresses.iter().enumerate().find(|(position, info)| info.index != *position as u32) {
+            return Err(WalletError::Other(format!(
+                "Address book is missing the address at index {} (found {})",
+                position, info.index
+            )));
+        }
+
+        Ok(Self {
+            addresses,
+            gap_limit: gap_limit.max(1),
+            storage: Some(storage),
+        })
+    }
+
+    /// Save an address, if the book is kept in storage
+    fn save(&self, info: &AddressInfo) -> Result<(), WalletError> {
+        if let Some(storage) = &self.storage {
+            storage
+                .put_json(ADDRESS_BOOK_NAMESPACE, &address_key(info.index), info)
+                .and_then(|_| storage.flush())
+                .map_err(|e| WalletError::Other(format!("Failed to save address book: {}", e)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the gap limit
+    pub fn gap_limit(&self) -> u32 {
+        self.gap_limit
+    }
+
+    /// Get the derivation index of the next address
+    pub fn next_index(&self) -> u32 {
+        self.addresses.len() as u32
+    }
+
+    /// Get the number of unused addresses after the last used one
+    pub fn unused_gap(&self) -> u32 {
+        self.addresses.iter().rev().take_while(|info| !info.used).count() as u32
+    }
+
+    /// Check that another address can be issued without exceeding the gap limit
+    pub fn check_gap(&self) -> Result<(), WalletError> {
+        if self.unused_gap() >= self.gap_limit {
+            return Err(WalletError::GapLimitReached(self.gap_limit));
+        }
+
+        Ok(())
+    }
+
+    /// Record a newly derived address
+    pub fn issue(&mut self, address: String, label: Option<String>) -> Result<AddressInfo, WalletError> {
+        self.check_gap()?;
+
+        let info = AddressInfo {
+            address,
+            index: self.next_index(),
+            label,
+            used: false,
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        self.save(&info)?;
+        self.addresses.push(info.clone());
+
+        Ok(info)
+    }
+
+    /// Mark an address as having received funds, returning false if it is unknown
+    pub fn mark_used(&mut self, address: &str) -> Result<bool, WalletError> {
+        let info = match self.addresses.iter_mut().find(|info| info.address == address) {
+            Some(info) => info,
+            None => return Ok(false),
+        };
+        if info.used {
+            return Ok(true);
+        }
+
+        info.used = true;
+        let info = info.clone();
+        self.save(&info)?;
+
+        Ok(true)
+    }
+
+    /// Change the label of an address, returning false if it is unknown
+    pub fn set_label(&mut self, address: &str, label: Option<String>) -> Result<bool, WalletError> {
+        let info = match self.addresses.iter_mut().find(|info| info.address == address) {
+            Some(info) => info,
+            None => return Ok(false),
+        };
+
+        info.label = label;
+        let info = info.clone();
+        self.save(&info)?;
+
+        Ok(true)
+    }
+
+    /// Get an address by its string form
+    pub fn get(&self, address: &str) -> Option<&AddressInfo> {
+        self.addresses.iter().find(|info| info.address == address)
+    }
+
+    /// Get all issued addresses in derivation order
+    pub fn list(&self) -> Vec<AddressInfo> {
+        self.addresses.clone()
+    }
+}
+
+impl Default for AddressBook {
+    fn default() -> Self {
+        Self::new(DEFAULT_GAP_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_limit() {
+        let mut book = AddressBook::new(2);
+
+        book.issue("a".to_string(), Some("exchange".to_string())).unwrap();
+        book.issue("b".to_string(), None).unwrap();
+        assert!(matches!(
+            book.issue("c".to_string(), None),
+            Err(WalletError::GapLimitReached(2))
+        ));
+
+        // Funding an address opens the gap again
+        assert!(book.mark_used("b").unwrap());
+        let info = book.issue("c".to_string(), None).unwrap();
+        assert_eq!(info.index, 2);
+        assert_eq!(book.unused_gap(), 1);
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut book = AddressBook::default();
+        book.issue("a".to_string(), None).unwrap();
+
+        assert!(book.set_label("a", Some("savings".to_string())).unwrap());
+        assert!(!book.set_label("unknown", None).unwrap());
+        assert_eq!(book.get("a").unwrap().label.as_deref(), Some("savings"));
+    }
+
+    #[test]
+    fn test_reopen() {
+        let storage: Arc<dyn Storage> = Arc::new(darkswap_support::storage::MemoryStorage::new());
+
+        let mut book = AddressBook::open(storage.clone(), 2).unwrap();
+        book.issue("a".to_string(), Some("exchange".to_string())).unwrap();
+        book.issue("b".to_string(), None).unwrap();
+        assert!(book.mark_used("a").unwrap());
+
+        let mut book = AddressBook::open(storage, 2).unwrap();
+        assert_eq!(book.next_index(), 2);
+        assert!(book.get("a").unwrap().used);
+        assert_eq!(book.get("a").unwrap().label.as_deref(), Some("exchange"));
+        assert_eq!(book.issue("c".to_string(), None).unwrap().index, 2);
+    }
+}
@@ -0,0 +1,249 @@
+//! Balance change detection
+//!
+//! The wallet's balances are read every `wallet.balance_sync.interval` and
+//! compared with the previous reading. Each asset whose balance moved gives an
+//! `Event::BalanceChanged`, so a UI can update its balances as they change
+//! rather than polling `get_balance`.
+//!
+//! Changes are attributed to a trade when one settled since the previous
+//! reading and involves the asset; bitcoin changes are attributed to any
+//! settled trade, which paid its network fee in bitcoin. Other increases are
+//! deposits, and other decreases the wallet's own spending, such as dust
+//! consolidation, which leaves nothing but its fee.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::clock::Clock;
+use crate::trade::{Trade, TradeModule};
+use crate::types::{Asset, Event, TradeId};
+use crate::wallet::WalletInterface;
+
+/// What changed a balance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BalanceCause {
+    /// A trade settled
+    Trade,
+    /// Funds arrived from outside
+    Deposit,
+    /// The wallet spent on fees
+    Fee,
+}
+
+/// Change of the balance of an asset
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceChange {
+    /// Asset
+    pub asset: Asset,
+    /// Change (satoshis or asset units)
+    pub delta: i128,
+    /// New balance
+    pub balance: u64,
+    /// Cause
+    pub cause: BalanceCause,
+    /// Trade behind the change, if the cause is a trade
+    pub trade_id: Option<TradeId>,
+}
+
+/// Balances of the previous reading
+#[derive(Debug, Default)]
+pub struct BalanceTracker {
+    /// Balance of each asset
+    balances: HashMap<Asset, u64>,
+    /// Time of the previous reading (unix seconds)
+    synced_at: Option<u64>,
+}
+
+impl BalanceTracker {
+    /// Create a tracker without a reading
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the balances of the previous reading
+    pub fn balances(&self) -> &HashMap<Asset, u64> {
+        &self.balances
+    }
+
+    /// Compare balances read at `now` with the previous reading, attributing changes to the trades settled since
+    ///
+    /// The first reading only sets the baseline.
+    pub fn sync(&mut self, balances: HashMap<Asset, u64>, trades: &[Trade], now: u64) -> Vec<BalanceChange> {
+        let Some(since) = self.synced_at.replace(now) else {
+            self.balances = balances;
+            return Vec::new();
+        };
+
+        let settled: Vec<&Trade> = trades
+            .iter()
+            .filter(|trade| trade.completed_at.map_or(false, |completed_at| completed_at >= since))
+            .collect();
+
+        let mut assets: Vec<&Asset> = self.balances.keys().chain(balances.keys()).collect();
+        assets.sort_by_key(|asset| asset.to_string());
+        assets.dedup();
+
+        let mut changes = Vec::new();
+        for asset in assets {
+            let before = self.balances.get(asset).copied().unwrap_or(0);
+            let after = balances.get(asset).copied().unwrap_or(0);
+            let delta = i128::from(after) - i128::from(before);
+            if delta == 0 {
+                continue;
+            }
+
+            let trade = settled.iter().find(|trade| {
+                *asset == Asset::Bitcoin || trade.base_asset == *asset || trade.quote_asset == *asset
+            });
+            let cause = match (trade, delta > 0) {
+                (Some(_), _) => BalanceCause::Trade,
+                (None, true) => BalanceCause::Deposit,
+                (None, false) => BalanceCause::Fee,
+            };
+
+            changes.push(BalanceChange {
+                asset: asset.clone(),
+                delta,
+                balance: after,
+                cause,
+                trade_id: trade.map(|trade| trade.id.clone()),
+            });
+        }
+
+        self.balances = balances;
+        changes
+    }
+}
+
+/// Read the balances of bitcoin, the assets held and the assets of `trades`, besides those already tracked
+pub async fn read_balances(
+    wallet: &dyn WalletInterface,
+    tracked: &HashMap<Asset, u64>,
+    trades: &[Trade],
+) -> Result<HashMap<Asset, u64>> {
+    let mut assets: Vec<Asset> = vec![Asset::Bitcoin];
+    let held = wallet.list_utxos().await?
+        .into_iter()
+        .flat_map(|utxo| utxo.assets.into_iter().map(|(asset, _)| asset));
+    let traded = trades.iter().flat_map(|trade| [trade.base_asset.clone(), trade.quote_asset.clone()]);
+    for asset in tracked.keys().cloned().chain(held).chain(traded) {
+        if !assets.contains(&asset) {
+            assets.push(asset);
+        }
+    }
+
+    let mut balances = HashMap::new();
+    for asset in assets {
+        let balance = match asset {
+            Asset::Bitcoin => wallet.get_balance().await?,
+            _ => wallet.get_asset_balance(&asset).await?,
+        };
+        balances.insert(asset, balance);
+    }
+
+    Ok(balances)
+}
+
+/// Spawn a task reading the wallet's balances every `interval` and emitting an event for each change
+pub fn spawn_balance_sync(
+    wallet: Arc<dyn WalletInterface + Send + Sync>,
+    trade_manager: Option<Arc<TradeModule>>,
+    event_sender: mpsc::Sender<Event>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        let mut tracker = BalanceTracker::new();
+
+        loop {
+            interval.tick().await;
+
+            let trades = match &trade_manager {
+                Some(trade_manager) => trade_manager.get_trades().await,
+                None => Vec::new(),
+            };
+            let balances = match read_balances(wallet.as_ref(), tracker.balances(), &trades).await {
+                Ok(balances) => balances,
+                Err(e) => {
+                    warn!("Failed to read wallet balances: {}", e);
+                    continue;
+                }
+            };
+
+            for change in tracker.sync(balances, &trades, clock.now()) {
+                if event_sender.send(Event::BalanceChanged(change)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderId;
+    use crate::trade::TradeState;
+    use rust_decimal::Decimal;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn balances(entries: &[(Asset, u64)]) -> HashMap<Asset, u64> {
+        entries.iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_balance_changes() {
+        let mut tracker = BalanceTracker::new();
+        assert!(tracker.sync(balances(&[(Asset::Bitcoin, 100_000)]), &[], NOW).is_empty());
+
+        // Nothing settled: bitcoin in is a deposit, bitcoin out a fee
+        let changes = tracker.sync(balances(&[(Asset::Bitcoin, 150_000)]), &[], NOW + 30);
+        assert_eq!((changes[0].delta, changes[0].cause), (50_000, BalanceCause::Deposit));
+        let changes = tracker.sync(balances(&[(Asset::Bitcoin, 149_000)]), &[], NOW + 60);
+        assert_eq!((changes[0].delta, changes[0].cause), (-1_000, BalanceCause::Fee));
+
+        // A settled trade explains both of its assets
+        let mut trade = Trade::new(
+            OrderId("order-1".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Rune(1),
+            Asset::Bitcoin,
+            Decimal::ONE,
+            Decimal::ONE,
+            None,
+            NOW,
+        );
+        trade.update_state_at(TradeState::Completed, NOW + 70);
+        let changes = tracker.sync(
+            balances(&[(Asset::Bitcoin, 99_000), (Asset::Rune(1), 500), (Asset::Rune(2), 10)]),
+            &[trade.clone()],
+            NOW + 90,
+        );
+        assert_eq!(changes.len(), 3);
+        for change in &changes {
+            match change.asset {
+                Asset::Rune(2) => assert_eq!(change.cause, BalanceCause::Deposit),
+                _ => assert_eq!((change.cause, change.trade_id.as_ref()), (BalanceCause::Trade, Some(&trade.id))),
+            }
+        }
+
+        // Trades settled before the previous reading explain nothing
+        let changes = tracker.sync(
+            balances(&[(Asset::Bitcoin, 98_500), (Asset::Rune(1), 500), (Asset::Rune(2), 10)]),
+            &[trade],
+            NOW + 120,
+        );
+        assert_eq!((changes.len(), changes[0].delta, changes[0].cause), (1, -500, BalanceCause::Fee));
+        assert_eq!(changes[0].balance, 98_500);
+    }
+}
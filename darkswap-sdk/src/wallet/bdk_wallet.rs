@@ -15,7 +15,11 @@ use log::{debug, info, warn};
 
 use crate::config::BitcoinNetwork;
 use crate::orderbook::OrderId;
+use crate::trade::settlement::PayoutOutput;
 use crate::types::{Asset, TradeId};
+use crate::wallet::address_book::AddressInfo;
+use crate::wallet::finalize;
+use crate::wallet::utxo::Utxo;
 use crate::wallet::{WalletError, WalletInterface};
 
 /// BDK Wallet implementation
@@ -67,6 +71,18 @@ impl WalletInterface for BdkWallet {
         Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
     }
 
+    async fn get_new_address(&self, _label: Option<String>) -> Result<AddressInfo> {
+        Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
+    }
+
+    async fn list_addresses(&self) -> Result<Vec<AddressInfo>> {
+        Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
+    }
+
+    async fn list_utxos(&self) -> Result<Vec<Utxo>> {
+        Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
+    }
+
     async fn get_balance(&self) -> Result<u64> {
         Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
     }
@@ -94,15 +110,28 @@ impl WalletInterface for BdkWallet {
         _quote_asset: &Asset,
         _amount: u64,
         _price: u64,
+        _payout: Option<&PayoutOutput>,
     ) -> Result<String> {
         Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
     }
 
-    async fn sign_psbt(&self, _psbt_base64: &str) -> Result<String> {
+    /// Sign a PSBT
+    ///
+    /// Once enabled, the wallet attaches its descriptor to its inputs with
+    /// `finalize::attach_wallet_descriptor` before signing them.
+    async fn sign_psbt(&self, psbt_base64: &str) -> Result<String> {
+        decode_psbt(psbt_base64)?;
+
         Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
     }
 
-    async fn finalize_and_broadcast_psbt(&self, _psbt_base64: &str) -> Result<String> {
+    /// Finalize a PSBT, multisig and timelocked inputs from their descriptors, and broadcast it
+    async fn finalize_and_broadcast_psbt(&self, psbt_base64: &str) -> Result<String> {
+        let mut psbt = decode_psbt(psbt_base64)?;
+        finalize::finalize_psbt(&mut psbt)?;
+        let tx = psbt.extract_tx();
+        debug!("Finalized transaction {}", tx.txid());
+
         Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
     }
 
@@ -112,11 +141,17 @@ impl WalletInterface for BdkWallet {
 }
 
 // Helper functions for serializing and deserializing PSBTs
-// These are stubs since BDK wallet is disabled
-fn serialize_psbt(_psbt: &Psbt) -> Result<Vec<u8>> {
-    Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
+fn serialize_psbt(psbt: &Psbt) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    psbt.consensus_encode(&mut bytes).context("Failed to serialize PSBT")?;
+    Ok(bytes)
+}
+
+fn deserialize_psbt(bytes: &[u8]) -> Result<Psbt> {
+    Ok(Psbt::consensus_decode(&mut &bytes[..]).map_err(|e| WalletError::InvalidPsbt(e.to_string()))?)
 }
 
-fn deserialize_psbt(_bytes: &[u8]) -> Result<Psbt> {
-    Err(anyhow::anyhow!("BDK wallet is not enabled. Enable the bdk-wallet feature to use this functionality."))
+fn decode_psbt(psbt_base64: &str) -> Result<Psbt> {
+    let bytes = base64::decode(psbt_base64.trim()).map_err(|e| WalletError::InvalidPsbt(e.to_string()))?;
+    deserialize_psbt(&bytes)
 }
\ No newline at end of file
@@ -0,0 +1,191 @@
+//! BIP-322 message signatures
+//!
+//! A BIP-322 signature proves control of an address by spending a virtual
+//! output locked to it: `to_spend` commits to the message hash and pays the
+//! address, and `to_sign` spends it with the signature as its witness. Only
+//! the "simple" format is handled, the base64 encoded witness, for P2WPKH and
+//! key-path P2TR addresses, which is what Sparrow and other wallets produce.
+
+use anyhow::{Context, Result};
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::util::schnorr::SchnorrSig;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::{
+    Address, EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PrivateKey, PublicKey, Script, Sequence,
+    Transaction, TxIn, TxOut, Witness,
+};
+
+use crate::wallet::WalletError;
+
+/// Tag of the message hash
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// Get the tagged hash of a message
+fn message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(MESSAGE_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message);
+
+    sha256::Hash::from_engine(engine)
+}
+
+/// Build the virtual transaction paying `script_pubkey` and committing to the message
+fn to_spend(script_pubkey: &Script, message: &[u8]) -> Transaction {
+    let script_sig = Builder::new()
+        .push_int(0)
+        .push_slice(&message_hash(message)[..])
+        .into_script();
+
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut { value: 0, script_pubkey: script_pubkey.clone() }],
+    }
+}
+
+/// Build the virtual transaction spending `to_spend` with `witness`
+fn to_sign(to_spend: &Transaction, witness: Witness) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint { txid: to_spend.txid(), vout: 0 },
+            script_sig: Script::new(),
+            sequence: Sequence::ZERO,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script(),
+        }],
+    }
+}
+
+/// Check a simple BIP-322 signature of `message` by `address`
+///
+/// Returns `false` if the signature is well formed but does not verify, and an
+/// error if it cannot be decoded or the address type is not supported.
+pub fn verify(address: &Address, message: &[u8], signature: &str) -> Result<bool> {
+    let bytes = base64::decode(signature.trim()).context("Signature is not base64")?;
+    let witness: Witness = deserialize(&bytes).context("Signature is not an encoded witness")?;
+
+    let script_pubkey = address.script_pubkey();
+    let to_spend = to_spend(&script_pubkey, message);
+    let to_sign = to_sign(&to_spend, witness.clone());
+    let secp = Secp256k1::verification_only();
+
+    if script_pubkey.is_v0_p2wpkh() {
+        let (signature, public_key) = match witness.to_vec().as_slice() {
+            [signature, public_key] => (
+                EcdsaSig::from_slice(signature).context("Invalid ECDSA signature")?,
+                PublicKey::from_slice(public_key).context("Invalid public key")?,
+            ),
+            _ => return Ok(false),
+        };
+        if !public_key.compressed || Script::new_v0_p2wpkh(&public_key.wpubkey_hash().context("Invalid public key")?) != script_pubkey {
+            return Ok(false);
+        }
+
+        let script_code = Script::new_p2pkh(&public_key.pubkey_hash());
+        let sighash = SighashCache::new(&to_sign)
+            .segwit_signature_hash(0, &script_code, 0, signature.hash_ty)
+            .context("Failed to compute signature hash")?;
+        let message = Message::from_slice(&sighash[..]).context("Invalid signature hash")?;
+
+        Ok(secp.verify_ecdsa(&message, &signature.sig, &public_key.inner).is_ok())
+    } else if script_pubkey.is_v1_p2tr() {
+        let signature = match witness.to_vec().as_slice() {
+            [signature] => SchnorrSig::from_slice(signature).context("Invalid Schnorr signature")?,
+            _ => return Ok(false),
+        };
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).context("Invalid output key")?;
+
+        let prevouts = [to_spend.output[0].clone()];
+        let sighash = SighashCache::new(&to_sign)
+            .taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), signature.hash_ty)
+            .context("Failed to compute signature hash")?;
+        let message = Message::from_slice(&sighash[..]).context("Invalid signature hash")?;
+
+        Ok(secp.verify_schnorr(&signature.sig, &message, &output_key).is_ok())
+    } else {
+        Err(WalletError::InvalidAddress(format!("BIP-322 proofs of {} are not supported", address)).into())
+    }
+}
+
+/// Sign `message` with the P2WPKH address of `private_key`, in the simple format
+pub fn sign_p2wpkh(private_key: &PrivateKey, message: &[u8]) -> Result<String> {
+    let secp = Secp256k1::new();
+    let public_key = PublicKey::from_private_key(&secp, private_key);
+    let address = Address::p2wpkh(&public_key, private_key.network).context("Failed to create P2WPKH address")?;
+
+    let to_spend = to_spend(&address.script_pubkey(), message);
+    let to_sign = to_sign(&to_spend, Witness::new());
+    let script_code = Script::new_p2pkh(&public_key.pubkey_hash());
+    let sighash = SighashCache::new(&to_sign)
+        .segwit_signature_hash(0, &script_code, 0, EcdsaSighashType::All)
+        .context("Failed to compute signature hash")?;
+    let message = Message::from_slice(&sighash[..]).context("Invalid signature hash")?;
+
+    let signature = EcdsaSig::sighash_all(secp.sign_ecdsa(&message, &private_key.inner));
+    let witness = Witness::from_vec(vec![signature.to_vec(), public_key.to_bytes()]);
+
+    Ok(base64::encode(serialize(&witness)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_message_hash() {
+        // Test vectors from BIP-322
+        assert_eq!(
+            message_hash(b"").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn test_verify_vector() {
+        // Test vector from BIP-322
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+        let signature = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+
+        assert!(verify(&address, b"Hello World", signature).unwrap());
+        assert!(!verify(&address, b"Hello", signature).unwrap());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let private_key = PrivateKey::from_slice(&[3u8; 32], bitcoin::Network::Testnet).unwrap();
+        let public_key = PublicKey::from_private_key(&Secp256k1::new(), &private_key);
+        let address = Address::p2wpkh(&public_key, bitcoin::Network::Testnet).unwrap();
+
+        let signature = sign_p2wpkh(&private_key, b"payout").unwrap();
+        assert!(verify(&address, b"payout", &signature).unwrap());
+
+        // Another address does not verify with the same signature
+        let other_key = PrivateKey::from_slice(&[4u8; 32], bitcoin::Network::Testnet).unwrap();
+        let other = Address::p2wpkh(&PublicKey::from_private_key(&Secp256k1::new(), &other_key), bitcoin::Network::Testnet).unwrap();
+        assert!(!verify(&other, b"payout", &signature).unwrap());
+
+        assert!(verify(&address, b"payout", "not base64!").is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Asset-aware coin selection
+//!
+//! A UTXO holding runes or alkanes also holds bitcoin, and a plain bitcoin spend of
+//! it destroys the assets. Bitcoin-only selection therefore never picks such an
+//! output. Spending one has to be asked for explicitly with [`select_asset`], which
+//! reports every asset the selected inputs carry so the caller can route all of
+//! them, not only the one being sent.
+
+use thiserror::Error;
+
+use crate::types::Asset;
+use crate::wallet::utxo::Utxo;
+
+/// Coin selection error
+#[derive(Debug, Error)]
+pub enum CoinSelectionError {
+    /// Not enough plain bitcoin
+    #[error("Insufficient funds: need {needed} sats, {available} sats available")]
+    InsufficientFunds {
+        /// Satoshis needed, including fees
+        needed: u64,
+        /// Satoshis available for selection
+        available: u64,
+    },
+    /// Not enough of an asset
+    #[error("Insufficient {asset} balance: need {needed}, {available} available")]
+    InsufficientAsset {
+        /// Asset
+        asset: Asset,
+        /// Amount needed
+        needed: u128,
+        /// Amount available
+        available: u128,
+    },
+}
+
+/// Selected inputs
+#[derive(Debug, Clone, Default)]
+pub struct CoinSelection {
+    /// Selected UTXOs
+    pub selected: Vec<Utxo>,
+    /// Total value of the selected UTXOs (satoshis)
+    pub value: u64,
+    /// Fee for spending the selected inputs (satoshis)
+    pub input_fee: u64,
+}
+
+impl CoinSelection {
+    /// Add a UTXO to the selection
+    fn push(&mut self, utxo: &Utxo, input_fee: u64) {
+        self.value = self.value.saturating_add(utxo.value());
+        self.input_fee = self.input_fee.saturating_add(input_fee);
+        self.selected.push(utxo.clone());
+    }
+
+    /// Get the value left after the target and input fees (satoshis)
+    pub fn change(&self, target: u64) -> u64 {
+        self.value.saturating_sub(target.saturating_add(self.input_fee))
+    }
+
+    /// Get every rune and alkane balance held by the selected inputs
+    ///
+    /// Each of these has to be assigned to an output by the spending transaction.
+    pub fn carried_assets(&self) -> Vec<(Asset, u128)> {
+        let mut assets: Vec<(Asset, u128)> = Vec::new();
+        for (asset, amount) in self.selected.iter().flat_map(|utxo| utxo.assets.iter()) {
+            match assets.iter_mut().find(|(held, _)| held == asset) {
+                Some((_, total)) => *total = total.saturating_add(*amount),
+                None => assets.push((asset.clone(), *amount)),
+            }
+        }
+        assets
+    }
+}
+
+/// Select plain bitcoin UTXOs worth `target` plus `input_fee` per input
+///
+/// UTXOs holding runes or alkanes are never selected.
+pub fn select_bitcoin(utxos: &[Utxo], target: u64, input_fee: u64) -> Result<CoinSelection, CoinSelectionError> {
+    let mut selection = CoinSelection::default();
+    add_bitcoin(&mut selection, utxos, target, input_fee)?;
+    Ok(selection)
+}
+
+/// Select UTXOs holding `amount` of `asset`, topped up with plain bitcoin worth
+/// `target` plus `input_fee` per input
+///
+/// Outputs holding only the requested asset are preferred, so as few other assets
+/// as possible are pulled into the transaction.
+pub fn select_asset(
+    utxos: &[Utxo],
+    asset: &Asset,
+    amount: u128,
+    target: u64,
+    input_fee: u64,
+) -> Result<CoinSelection, CoinSelectionError> {
+    if *asset == Asset::Bitcoin {
+        // More satoshis than exist can never be covered
+        let amount = u64::try_from(amount).map_err(|_| CoinSelectionError::InsufficientFunds {
+            needed: u64::MAX,
+            available: utxos.iter()
+                .filter(|utxo| !utxo.carries_assets())
+                .fold(0u64, |total, utxo| total.saturating_add(utxo.value())),
+        })?;
+        return select_bitcoin(utxos, target.saturating_add(amount), input_fee);
+    }
+
+    let mut candidates: Vec<&Utxo> = utxos.iter().filter(|utxo| utxo.asset_amount(asset) > 0).collect();
+    candidates.sort_by_key(|utxo| (utxo.assets.len(), std::cmp::Reverse(utxo.asset_amount(asset))));
+
+    let mut selection = CoinSelection::default();
+    let mut selected_amount = 0u128;
+    for utxo in candidates {
+        if selected_amount >= amount {
+            break;
+        }
+        selected_amount = selected_amount.saturating_add(utxo.asset_amount(asset));
+        selection.push(utxo, input_fee);
+    }
+
+    if selected_amount < amount {
+        return Err(CoinSelectionError::InsufficientAsset {
+            asset: asset.clone(),
+            needed: amount,
+            available: selected_amount,
+        });
+    }
+
+    add_bitcoin(&mut selection, utxos, target, input_fee)?;
+    Ok(selection)
+}
+
+/// Add plain bitcoin UTXOs, largest first, until the selection covers `target`
+fn add_bitcoin(
+    selection: &mut CoinSelection,
+    utxos: &[Utxo],
+    target: u64,
+    input_fee: u64,
+) -> Result<(), CoinSelectionError> {
+    let mut candidates: Vec<&Utxo> = utxos.iter()
+        .filter(|utxo| !utxo.carries_assets())
+        .filter(|utxo| !selection.selected.iter().any(|selected| selected.outpoint == utxo.outpoint))
+        .collect();
+    candidates.sort_by_key(|utxo| std::cmp::Reverse(utxo.value()));
+
+    for utxo in candidates {
+        if selection.value >= target.saturating_add(selection.input_fee) {
+            break;
+        }
+        selection.push(utxo, input_fee);
+    }
+
+    let needed = target.saturating_add(selection.input_fee);
+    if selection.value < needed {
+        return Err(CoinSelectionError::InsufficientFunds {
+            needed,
+            available: selection.value,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, Script, TxOut, Txid};
+
+    fn utxo(vout: u32, value: u64) -> Utxo {
+        Utxo::new(
+            OutPoint::new(Txid::all_zeros(), vout),
+            TxOut { value, script_pubkey: Script::new() },
+            6,
+        )
+    }
+
+    #[test]
+    fn test_bitcoin_selection_skips_asset_outputs() {
+        let utxos = vec![
+            utxo(0, 1_000_000).with_asset(Asset::Rune(1), 500),
+            utxo(1, 20_000),
+            utxo(2, 30_000),
+        ];
+
+        let selection = select_bitcoin(&utxos, 40_000, 100).unwrap();
+        assert!(selection.selected.iter().all(|utxo| !utxo.carries_assets()));
+        assert_eq!(selection.value, 50_000);
+        assert_eq!(selection.change(40_000), 9_800);
+
+        // The rune output would cover it, but must not be used
+        assert!(matches!(
+            select_bitcoin(&utxos, 60_000, 100),
+            Err(CoinSelectionError::InsufficientFunds { available: 50_000, .. })
+        ));
+    }
+
+    #[test]
+    fn test_asset_selection_reports_carried_assets() {
+        let utxos = vec![
+            utxo(0, 546).with_asset(Asset::Rune(1), 300).with_asset(Asset::Rune(2), 7),
+            utxo(1, 546).with_asset(Asset::Rune(1), 200),
+            utxo(2, 10_000),
+        ];
+
+        // The output holding only the requested rune is enough
+        let selection = select_asset(&utxos, &Asset::Rune(1), 200, 1_000, 100).unwrap();
+        let spent: Vec<u32> = selection.selected.iter().map(|utxo| utxo.outpoint.vout).collect();
+        assert_eq!(spent, vec![1, 2]);
+        assert_eq!(selection.carried_assets(), vec![(Asset::Rune(1), 200)]);
+
+        // Needing both pulls in the other rune, which the caller must route
+        let selection = select_asset(&utxos, &Asset::Rune(1), 400, 1_000, 100).unwrap();
+        assert!(selection.carried_assets().contains(&(Asset::Rune(2), 7)));
+
+        assert!(matches!(
+            select_asset(&utxos, &Asset::Rune(1), 600, 1_000, 100),
+            Err(CoinSelectionError::InsufficientAsset { available: 500, .. })
+        ));
+    }
+
+    #[test]
+    fn test_oversized_targets_are_insufficient() {
+        let utxos = vec![utxo(0, 20_000), utxo(1, u64::MAX).with_asset(Asset::Rune(1), 1)];
+
+        // A bitcoin amount beyond u64 is not truncated into a small one
+        assert!(matches!(
+            select_asset(&utxos, &Asset::Bitcoin, u64::MAX as u128 + 1_000, 0, 100),
+            Err(CoinSelectionError::InsufficientFunds { needed: u64::MAX, available: 20_000 })
+        ));
+
+        // Target plus input fees saturates instead of wrapping around
+        assert!(matches!(
+            select_bitcoin(&utxos, u64::MAX - 50, 100),
+            Err(CoinSelectionError::InsufficientFunds { needed: u64::MAX, available: 20_000 })
+        ));
+        assert_eq!(CoinSelection { value: 20_000, input_fee: 100, ..Default::default() }.change(u64::MAX), 0);
+    }
+}
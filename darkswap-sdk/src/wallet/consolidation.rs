@@ -0,0 +1,308 @@
+//! Dust consolidation
+//!
+//! Rune and alkane trades leave the wallet with many outputs at or near the dust
+//! limit. The planner batches plain bitcoin dust into a single output back to the
+//! wallet, but only while the estimated fee rate is below the configured ceiling,
+//! so the sweep is done when block space is cheap. Outputs holding runes or alkanes are
+//! never selected, as spending them without an edict would burn or misdirect the
+//! balance, and outputs worth less than the fee to spend them are left alone.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bitcoin::consensus::Encodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{Address, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Witness};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::ConsolidationConfig;
+use crate::wallet::deposits::DepositWatcher;
+use crate::wallet::utxo::Utxo;
+use crate::wallet::WalletInterface;
+
+/// Fixed size of a transaction (vbytes)
+const TX_OVERHEAD_VBYTES: u64 = 11;
+/// Size of a P2WPKH input (vbytes)
+const P2WPKH_INPUT_VBYTES: u64 = 68;
+/// Size of a P2WPKH output (vbytes)
+const P2WPKH_OUTPUT_VBYTES: u64 = 31;
+/// Dust limit of a P2WPKH output (satoshis)
+const DUST_LIMIT: u64 = 546;
+/// Confirmation target of consolidations (blocks)
+pub const CONSOLIDATION_TARGET_BLOCKS: u16 = 144;
+
+/// Consolidation error
+#[derive(Debug, Error)]
+pub enum ConsolidationError {
+    /// Fee rate above the ceiling
+    #[error("Fee rate {current} sat/vB is above the consolidation ceiling of {max} sat/vB")]
+    FeeRateTooHigh {
+        /// Current fee rate
+        current: f32,
+        /// Configured ceiling
+        max: f32,
+    },
+    /// Not enough dust to be worth a transaction
+    #[error("Found {found} dust outputs, at least {required} are needed")]
+    NotEnoughDust {
+        /// Dust outputs found
+        found: usize,
+        /// Dust outputs required
+        required: usize,
+    },
+    /// Consolidated output would itself be dust
+    #[error("Consolidating {0} sats leaves nothing above the dust limit")]
+    Uneconomic(u64),
+}
+
+/// Planned consolidation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsolidationPlan {
+    /// Outputs to spend
+    pub inputs: Vec<Utxo>,
+    /// Total input value (satoshis)
+    pub input_value: u64,
+    /// Fee (satoshis)
+    pub fee: u64,
+    /// Value of the consolidated output (satoshis)
+    pub output_value: u64,
+    /// Fee rate (sat/vB)
+    pub fee_rate: f32,
+    /// Dust outputs left alone because they hold runes or alkanes
+    pub skipped_asset_outputs: usize,
+    /// Dust outputs left alone because spending them costs more than they hold
+    pub skipped_uneconomic_outputs: usize,
+}
+
+impl ConsolidationPlan {
+    /// Plan a consolidation of the dust among `utxos` at `fee_rate`
+    pub fn new(utxos: &[Utxo], config: &ConsolidationConfig, fee_rate: f32) -> Result<Self, ConsolidationError> {
+        if fee_rate > config.max_fee_rate {
+            return Err(ConsolidationError::FeeRateTooHigh {
+                current: fee_rate,
+                max: config.max_fee_rate,
+            });
+        }
+
+        let input_cost = fee(P2WPKH_INPUT_VBYTES, fee_rate);
+        let dust = utxos.iter().filter(|utxo| utxo.value() <= config.dust_threshold);
+
+        let mut skipped_asset_outputs = 0;
+        let mut skipped_uneconomic_outputs = 0;
+        let mut inputs = Vec::new();
+        for utxo in dust {
+            if utxo.carries_assets() {
+                skipped_asset_outputs += 1;
+            } else if utxo.value() <= input_cost {
+                skipped_uneconomic_outputs += 1;
+            } else {
+                inputs.push(utxo.clone());
+            }
+        }
+
+        // Sweep the smallest outputs first
+        inputs.sort_by_key(|utxo| utxo.value());
+        inputs.truncate(config.max_inputs.max(1));
+
+        let required = config.min_inputs.max(2);
+        if inputs.len() < required {
+            return Err(ConsolidationError::NotEnoughDust {
+                found: inputs.len(),
+                required,
+            });
+        }
+
+        let input_value: u64 = inputs.iter().map(|utxo| utxo.value()).sum();
+        let vbytes = TX_OVERHEAD_VBYTES + P2WPKH_INPUT_VBYTES * inputs.len() as u64 + P2WPKH_OUTPUT_VBYTES;
+        let fee = fee(vbytes, fee_rate);
+        let output_value = input_value.saturating_sub(fee);
+        if output_value < DUST_LIMIT {
+            return Err(ConsolidationError::Uneconomic(input_value));
+        }
+
+        Ok(Self {
+            inputs,
+            input_value,
+            fee,
+            output_value,
+            fee_rate,
+            skipped_asset_outputs,
+            skipped_uneconomic_outputs,
+        })
+    }
+
+    /// Build the unsigned consolidation PSBT paying to `destination`
+    pub fn to_psbt(&self, destination: &Address) -> Result<Psbt> {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: self.inputs.iter()
+                .map(|utxo| TxIn {
+                    previous_output: utxo.outpoint,
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: self.output_value,
+                script_pubkey: destination.script_pubkey(),
+            }],
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).context("Failed to create consolidation PSBT")?;
+        for (input, utxo) in psbt.inputs.iter_mut().zip(&self.inputs) {
+            input.witness_utxo = Some(utxo.txout.clone());
+        }
+
+        Ok(psbt)
+    }
+}
+
+/// Result of a broadcast consolidation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsolidationReport {
+    /// Executed plan
+    pub plan: ConsolidationPlan,
+    /// Transaction ID
+    pub txid: String,
+}
+
+/// Consolidate the dust of a wallet into one of its own addresses
+pub async fn consolidate(
+    wallet: &(dyn WalletInterface + Send + Sync),
+    config: &ConsolidationConfig,
+    fee_rate: f32,
+) -> Result<ConsolidationReport> {
+    let utxos = wallet.list_utxos().await?;
+    let plan = ConsolidationPlan::new(&utxos, config, fee_rate)?;
+
+    let destination = Address::from_str(&wallet.get_address().await?)
+        .context("Wallet returned an invalid address")?;
+    let psbt = plan.to_psbt(&destination)?;
+
+    let mut psbt_bytes = Vec::new();
+    psbt.consensus_encode(&mut psbt_bytes).context("Failed to serialize PSBT")?;
+
+    let signed = wallet.sign_psbt(&base64::encode(&psbt_bytes)).await?;
+    let txid = wallet.finalize_and_broadcast_psbt(&signed).await?;
+
+    info!(
+        "Consolidated {} dust outputs ({} sats) into {} sats, fee {} sats: {}",
+        plan.inputs.len(),
+        plan.input_value,
+        plan.output_value,
+        plan.fee,
+        txid
+    );
+
+    Ok(ConsolidationReport { plan, txid })
+}
+
+/// Run consolidations on the configured interval at the estimated fee rate,
+/// recording their transactions with the deposit watcher
+pub fn spawn_scheduler(
+    wallet: Arc<dyn WalletInterface + Send + Sync>,
+    config: ConsolidationConfig,
+    deposits: Option<Arc<DepositWatcher>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval.max(1)));
+
+        loop {
+            interval.tick().await;
+
+            // Consolidation can wait, so it goes at the rate of the slowest target
+            let fee_rate = match wallet.estimate_fee_rate(CONSOLIDATION_TARGET_BLOCKS).await {
+                Ok(fee_rate) => fee_rate,
+                Err(e) => {
+                    debug!("Skipping scheduled consolidation without a fee estimate: {}", e);
+                    continue;
+                }
+            };
+
+            match consolidate(wallet.as_ref(), &config, fee_rate).await {
+                Ok(report) => {
+                    if let Some(deposits) = &deposits {
+                        if let Err(e) = deposits.record_own_transaction(&report.txid, SystemClock.now()) {
+                            warn!("Failed to record consolidation {}: {}", report.txid, e);
+                        }
+                    }
+                }
+                Err(e) => match e.downcast_ref::<ConsolidationError>() {
+                    // Waiting for cheaper fees or more dust is the normal case
+                    Some(reason) => debug!("Skipping scheduled consolidation: {}", reason),
+                    None => warn!("Scheduled consolidation failed: {}", e),
+                },
+            }
+        }
+    })
+}
+
+/// Get the fee for `vbytes` at `fee_rate`
+fn fee(vbytes: u64, fee_rate: f32) -> u64 {
+    (vbytes as f64 * fee_rate as f64).ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Asset;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, Txid};
+
+    fn utxo(vout: u32, value: u64) -> Utxo {
+        Utxo::new(
+            OutPoint::new(Txid::all_zeros(), vout),
+            TxOut { value, script_pubkey: Script::new() },
+            6,
+        )
+    }
+
+    fn config() -> ConsolidationConfig {
+        ConsolidationConfig {
+            min_inputs: 3,
+            ..ConsolidationConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_asset_outputs_are_never_selected() {
+        let utxos = vec![
+            utxo(0, 546),
+            utxo(1, 546).with_asset(Asset::Rune(1), 1_000),
+            utxo(2, 600),
+            utxo(3, 700),
+            utxo(4, 100_000),
+        ];
+
+        let plan = ConsolidationPlan::new(&utxos, &config(), 1.0).unwrap();
+        let spent: Vec<u32> = plan.inputs.iter().map(|utxo| utxo.outpoint.vout).collect();
+        assert_eq!(spent, vec![0, 2, 3]);
+        assert_eq!(plan.skipped_asset_outputs, 1);
+        assert_eq!(plan.input_value, plan.output_value + plan.fee);
+    }
+
+    #[test]
+    fn test_waits_for_low_fees() {
+        let utxos: Vec<Utxo> = (0..5).map(|vout| utxo(vout, 546)).collect();
+
+        assert!(matches!(
+            ConsolidationPlan::new(&utxos, &config(), 50.0),
+            Err(ConsolidationError::FeeRateTooHigh { .. })
+        ));
+
+        // Outputs that cost more to spend than they hold are skipped
+        let mut config = config();
+        config.max_fee_rate = 10.0;
+        assert!(matches!(
+            ConsolidationPlan::new(&utxos, &config, 9.0),
+            Err(ConsolidationError::NotEnoughDust { found: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,404 @@
+//! External custody wallet
+//!
+//! Some operators keep both the keys and the books of their funds with a
+//! custody provider. A `custody` wallet asks the provider for balances and
+//! deposit addresses and has it sign every PSBT, while UTXOs, verification and
+//! broadcast stay with a watch-only chain wallet. Providers plug in through the
+//! [`CustodyProvider`] trait; [`HttpCustodyProvider`] is the reference
+//! implementation, speaking this protocol over HTTPS with a bearer token:
+//!
+//! - `GET /balance` returns `{"amount": <satoshis>}`
+//! - `GET /balance/<asset>` returns `{"amount": <units>}` for an asset such as `RUNE:2a`
+//! - `POST /addresses` with `{"label": ...}` returns a new deposit address
+//! - `POST /sign` takes a signing request and returns a signing response, as for
+//!   the remote signer; the echo and the transaction are checked the same way
+//!
+//! Requests that time out, fail in transit or get a server error are retried
+//! with exponential backoff. Signing requests keep their ID across retries, so a
+//! provider can recognize a request it already served. Address requests carry
+//! nothing to recognize them by and are sent once: a retry after a lost
+//! response would issue a second address.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::{CustodyConfig, DnsConfig};
+use crate::orderbook::OrderId;
+use crate::trade::settlement::PayoutOutput;
+use crate::types::{Asset, TradeId};
+use crate::wallet::address_book::AddressInfo;
+use crate::wallet::remote_signer::{check_response, SignPurpose, SignRequest, SignResponse};
+use crate::wallet::utxo::Utxo;
+use crate::wallet::{WalletError, WalletInterface};
+
+/// Longest delay between two attempts of a request
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Custody provider holding the keys and balances of a wallet
+#[async_trait]
+pub trait CustodyProvider: Send + Sync {
+    /// Get the bitcoin balance held for the node (satoshis)
+    async fn balance(&self) -> Result<u64>;
+
+    /// Get the balance of a rune or alkane held for the node
+    async fn asset_balance(&self, asset: &Asset) -> Result<u64>;
+
+    /// Get a new deposit address
+    async fn new_address(&self, label: Option<String>) -> Result<AddressInfo>;
+
+    /// Have a PSBT created or signed, as the request's purpose says
+    ///
+    /// The response is checked against the request by the wallet, so a provider
+    /// returns what it received without judging it.
+    async fn sign(&self, request: &SignRequest) -> Result<SignResponse>;
+}
+
+/// Balance document of the HTTP protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BalanceResponse {
+    /// Balance (satoshis, or units of the asset)
+    amount: u64,
+}
+
+/// Address request of the HTTP protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AddressRequest {
+    /// Label
+    label: Option<String>,
+}
+
+/// Custody provider reached over HTTPS
+pub struct HttpCustodyProvider {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Provider base URL
+    url: String,
+    /// Bearer token
+    auth_token: Option<String>,
+    /// Retries after the first attempt
+    max_retries: u32,
+    /// Delay before the first retry
+    retry_backoff: Duration,
+}
+
+impl HttpCustodyProvider {
+    /// Create a provider client
+    pub fn new(config: &CustodyConfig, dns: &DnsConfig) -> Result<Self> {
+        if !config.url.starts_with("https://") && !config.allow_insecure {
+            return Err(anyhow::anyhow!("Custody provider URL must use HTTPS: {}", config.url));
+        }
+
+        let client = crate::dns::http_client_builder(dns)?
+            .timeout(Duration::from_secs(config.timeout.max(1)))
+            .build()
+            .context("Failed to create custody provider HTTP client")?;
+
+        Ok(Self {
+            client,
+            url: config.url.trim_end_matches('/').to_string(),
+            auth_token: config.auth_token.clone(),
+            max_retries: config.max_retries,
+            retry_backoff: Duration::from_millis(config.retry_backoff),
+        })
+    }
+
+    /// Send a request built by `build`, retrying transient failures
+    async fn call<T, F>(&self, build: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    {
+        self.call_with_retries(build, self.max_retries).await
+    }
+
+    /// Send a request built by `build`, retrying transient failures up to `max_retries` times
+    async fn call_with_retries<T, F>(&self, build: F, max_retries: u32) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let request = build(&self.client);
+            let request = match &self.auth_token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            };
+
+            let error = match request.send().await {
+                Ok(response) if is_transient(response.status()) => {
+                    anyhow::anyhow!("Custody provider answered {}", response.status())
+                }
+                Ok(response) => {
+                    return response
+                        .error_for_status()
+                        .context("Custody provider refused the request")?
+                        .json()
+                        .await
+                        .context("Invalid custody provider response");
+                }
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    anyhow::Error::new(e).context("Failed to reach custody provider")
+                }
+                Err(e) => return Err(anyhow::Error::new(e).context("Custody provider request failed")),
+            };
+
+            if attempt >= max_retries {
+                return Err(error.context(format!("Gave up after {} attempts", attempt + 1)));
+            }
+
+            let delay = backoff(self.retry_backoff, attempt);
+            log::debug!("Retrying custody provider request in {:?}: {:#}", delay, error);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl CustodyProvider for HttpCustodyProvider {
+    async fn balance(&self) -> Result<u64> {
+        let response: BalanceResponse = self.call(|client| client.get(format!("{}/balance", self.url))).await?;
+        Ok(response.amount)
+    }
+
+    async fn asset_balance(&self, asset: &Asset) -> Result<u64> {
+        let response: BalanceResponse = self
+            .call(|client| client.get(format!("{}/balance/{}", self.url, asset)))
+            .await?;
+        Ok(response.amount)
+    }
+
+    async fn new_address(&self, label: Option<String>) -> Result<AddressInfo> {
+        // Not retried: the provider cannot tell a retry from a request for another address
+        let request = AddressRequest { label };
+        self.call_with_retries(|client| client.post(format!("{}/addresses", self.url)).json(&request), 0).await
+    }
+
+    async fn sign(&self, request: &SignRequest) -> Result<SignResponse> {
+        self.call(|client| client.post(format!("{}/sign", self.url)).json(request)).await
+    }
+}
+
+/// Check if a response status is worth retrying the request for
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Get the delay before retry number `attempt` (from zero)
+pub fn backoff(base: Duration, attempt: u32) -> Duration {
+    base.checked_mul(2u32.saturating_pow(attempt))
+        .map_or(MAX_BACKOFF, |delay| delay.min(MAX_BACKOFF))
+}
+
+/// Wallet whose keys and balances are held by a custody provider
+pub struct CustodyWallet {
+    /// Custody provider
+    provider: Arc<dyn CustodyProvider>,
+    /// Policy the provider is expected to apply
+    policy: Option<String>,
+    /// Watch-only wallet for UTXOs, verification and broadcast
+    chain: Arc<dyn WalletInterface>,
+}
+
+impl CustodyWallet {
+    /// Create a custody wallet
+    pub fn new(provider: Arc<dyn CustodyProvider>, policy: Option<String>, chain: Arc<dyn WalletInterface>) -> Self {
+        Self { provider, policy, chain }
+    }
+
+    /// Create a custody wallet on the reference HTTP provider
+    pub fn from_config(config: &CustodyConfig, dns: &DnsConfig, chain: Arc<dyn WalletInterface>) -> Result<Self> {
+        let provider = HttpCustodyProvider::new(config, dns)?;
+        Ok(Self::new(Arc::new(provider), config.policy.clone(), chain))
+    }
+
+    /// Have the provider sign, checking its policy echo
+    async fn sign(&self, purpose: SignPurpose) -> Result<String> {
+        let request = SignRequest {
+            request_id: Uuid::new_v4().to_string(),
+            policy: self.policy.clone(),
+            purpose,
+        };
+
+        let response = self.provider.sign(&request).await?;
+
+        Ok(check_response(&request, response)?)
+    }
+}
+
+#[async_trait]
+impl WalletInterface for CustodyWallet {
+    /// Get a deposit address from the provider
+    async fn get_address(&self) -> Result<String> {
+        Ok(self.provider.new_address(None).await?.address)
+    }
+
+    /// Get a labeled deposit address from the provider
+    async fn get_new_address(&self, label: Option<String>) -> Result<AddressInfo> {
+        self.provider.new_address(label).await
+    }
+
+    /// List the addresses watched by the chain wallet
+    async fn list_addresses(&self) -> Result<Vec<AddressInfo>> {
+        self.chain.list_addresses().await
+    }
+
+    /// List unspent outputs
+    async fn list_utxos(&self) -> Result<Vec<Utxo>> {
+        self.chain.list_utxos().await
+    }
+
+    /// Get the balance held by the provider
+    async fn get_balance(&self) -> Result<u64> {
+        self.provider.balance().await
+    }
+
+    /// Get an asset balance held by the provider
+    async fn get_asset_balance(&self, asset: &Asset) -> Result<u64> {
+        match asset {
+            Asset::Bitcoin => self.provider.balance().await,
+            _ => self.provider.asset_balance(asset).await,
+        }
+    }
+
+    /// Have the provider create and sign a PSBT for an order
+    async fn create_order_psbt(
+        &self,
+        order_id: &OrderId,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        amount: u64,
+        price: u64,
+    ) -> Result<String> {
+        self.sign(SignPurpose::Order {
+            order_id: order_id.clone(),
+            base_asset: base_asset.clone(),
+            quote_asset: quote_asset.clone(),
+            amount,
+            price,
+        })
+        .await
+    }
+
+    /// Have the provider create and sign a PSBT for a trade
+    async fn create_trade_psbt(
+        &self,
+        trade_id: &TradeId,
+        order_id: &OrderId,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        amount: u64,
+        price: u64,
+        payout: Option<&PayoutOutput>,
+    ) -> Result<String> {
+        self.sign(SignPurpose::Trade {
+            trade_id: trade_id.clone(),
+            order_id: order_id.clone(),
+            base_asset: base_asset.clone(),
+            quote_asset: quote_asset.clone(),
+            amount,
+            price,
+            payout: payout.cloned(),
+        })
+        .await
+    }
+
+    /// Have the provider sign a PSBT
+    async fn sign_psbt(&self, psbt_base64: &str) -> Result<String> {
+        if base64::decode(psbt_base64.trim()).is_err() {
+            return Err(WalletError::InvalidPsbt("Failed to decode PSBT".to_string()).into());
+        }
+
+        self.sign(SignPurpose::Psbt { psbt: psbt_base64.to_string() }).await
+    }
+
+    /// Finalize and broadcast a PSBT
+    async fn finalize_and_broadcast_psbt(&self, psbt_base64: &str) -> Result<String> {
+        self.chain.finalize_and_broadcast_psbt(psbt_base64).await
+    }
+
+    /// Verify a PSBT
+    async fn verify_psbt(&self, psbt_base64: &str) -> Result<bool> {
+        self.chain.verify_psbt(psbt_base64).await
+    }
+
+    /// Estimate the fee rate with the chain wallet
+    async fn estimate_fee_rate(&self, target_blocks: u16) -> Result<f32> {
+        self.chain.estimate_fee_rate(target_blocks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BitcoinNetwork;
+    use crate::wallet::simple_wallet::SimpleWallet;
+
+    /// Provider holding fixed balances, approving or tampering with every request
+    struct MockProvider {
+        /// Echo a different policy than asked
+        tamper: bool,
+    }
+
+    #[async_trait]
+    impl CustodyProvider for MockProvider {
+        async fn balance(&self) -> Result<u64> {
+            Ok(42_000)
+        }
+
+        async fn asset_balance(&self, _asset: &Asset) -> Result<u64> {
+            Ok(7)
+        }
+
+        async fn new_address(&self, label: Option<String>) -> Result<AddressInfo> {
+            Ok(AddressInfo { address: "tb1qdeposit".to_string(), index: 0, label, used: false, created_at: 0 })
+        }
+
+        async fn sign(&self, request: &SignRequest) -> Result<SignResponse> {
+            let mut echo = request.clone();
+            if self.tamper {
+                echo.policy = None;
+            }
+            Ok(SignResponse { psbt: Some("signed".to_string()), echo: Some(echo), reason: None })
+        }
+    }
+
+    fn wallet(tamper: bool) -> CustodyWallet {
+        let chain = Arc::new(SimpleWallet::new(None, BitcoinNetwork::Testnet).unwrap());
+        CustodyWallet::new(Arc::new(MockProvider { tamper }), Some("desk".to_string()), chain)
+    }
+
+    #[tokio::test]
+    async fn test_custody_wallet() {
+        let wallet = wallet(false);
+        assert_eq!(wallet.get_balance().await.unwrap(), 42_000);
+        assert_eq!(wallet.get_asset_balance(&Asset::Bitcoin).await.unwrap(), 42_000);
+        assert_eq!(wallet.get_asset_balance(&Asset::Rune(1)).await.unwrap(), 7);
+        assert_eq!(wallet.get_address().await.unwrap(), "tb1qdeposit");
+
+        let order_id = OrderId("order".to_string());
+        let psbt = wallet.create_order_psbt(&order_id, &Asset::Bitcoin, &Asset::Rune(1), 1000, 10).await;
+        assert_eq!(psbt.unwrap(), "signed");
+
+        // A provider that approved something else is refused
+        let wallet = self::wallet(true);
+        assert!(wallet.create_order_psbt(&order_id, &Asset::Bitcoin, &Asset::Rune(1), 1000, 10).await.is_err());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_millis(500);
+        assert_eq!(backoff(base, 0), Duration::from_millis(500));
+        assert_eq!(backoff(base, 2), Duration::from_secs(2));
+        assert_eq!(backoff(base, 10), MAX_BACKOFF);
+        assert_eq!(backoff(base, 40), MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,367 @@
+//! Deposit detection
+//!
+//! The wallet's unspent outputs are scanned every `wallet.deposits.interval`.
+//! An output that was not there at the previous scan and was not created by
+//! one of the node's own transactions, a trade settlement or a consolidation,
+//! is a deposit: it gives an `Event::DepositDetected` when first seen and an
+//! `Event::DepositConfirmed` once it has `wallet.deposits.confirmations`.
+//!
+//! The outputs seen and the deposits are kept in storage, so deposits that
+//! arrived while the node was down are reported when it starts again. The very
+//! first scan only records the outputs the wallet already holds.
+//!
+//! Every scan also marks the receive addresses the outputs pay as used, deposits
+//! and trade payouts alike, so they no longer count against the gap limit, and
+//! records completed trades whose settlement outputs have the confirmations as
+//! confirmed on their timeline.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bitcoin::{Address, Network};
+use darkswap_support::storage::{Storage, StorageExt};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::clock::Clock;
+use crate::config::DepositConfig;
+use crate::trade::timeline::TradeStage;
+use crate::trade::{Trade, TradeModule, TradeState};
+use crate::types::{Asset, Event};
+use crate::wallet::utxo::Utxo;
+use crate::wallet::WalletInterface;
+
+/// Storage namespace of the deposit watcher
+pub const DEPOSITS_NAMESPACE: &str = "deposits";
+
+/// Key of the outputs seen at the previous scan
+const KNOWN_KEY: &str = "known";
+
+/// Incoming payment to the wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    /// Outpoint (`txid:vout`)
+    pub outpoint: String,
+    /// Transaction ID
+    pub txid: String,
+    /// Output index
+    pub vout: u32,
+    /// Address paid, if the output script has one
+    pub address: Option<String>,
+    /// Bitcoin received (satoshis)
+    pub value: u64,
+    /// Runes and alkanes received, with amounts
+    pub assets: Vec<(Asset, u128)>,
+    /// Confirmations at the last scan
+    pub confirmations: u32,
+    /// Time the deposit was first seen (unix seconds)
+    pub detected_at: u64,
+    /// Time the deposit reached the required confirmations (unix seconds)
+    pub confirmed_at: Option<u64>,
+}
+
+impl Deposit {
+    /// Check if the deposit has the required confirmations
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed_at.is_some()
+    }
+}
+
+/// Deposits found by a scan
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepositScan {
+    /// Deposits seen for the first time
+    pub detected: Vec<Deposit>,
+    /// Deposits that reached the required confirmations
+    pub confirmed: Vec<Deposit>,
+}
+
+/// Get the key of a deposit
+fn deposit_key(outpoint: &str) -> String {
+    format!("deposit/{}", outpoint)
+}
+
+/// Get the key of an own transaction
+fn own_key(txid: &str) -> String {
+    format!("own/{}", txid)
+}
+
+/// Watcher of incoming payments to the wallet
+pub struct DepositWatcher {
+    /// Storage the outputs seen and the deposits are kept in
+    storage: Arc<dyn Storage>,
+    /// Network of the wallet's addresses
+    network: Network,
+    /// Confirmations a deposit needs
+    confirmations: u32,
+}
+
+impl DepositWatcher {
+    /// Open the watcher kept in `storage`
+    pub fn open(storage: Arc<dyn Storage>, network: Network, config: &DepositConfig) -> Self {
+        Self {
+            storage,
+            network,
+            confirmations: config.confirmations.max(1),
+        }
+    }
+
+    /// Record a transaction the node broadcast itself, so its outputs are not taken for deposits
+    pub fn record_own_transaction(&self, txid: &str, now: u64) -> Result<()> {
+        self.storage
+            .put_json(DEPOSITS_NAMESPACE, &own_key(txid), &now)
+            .context("Failed to record own transaction")?;
+        self.storage.flush().context("Failed to flush deposits")
+    }
+
+    /// Compare the wallet's outputs at `now` with the previous scan
+    ///
+    /// Outputs of the transactions of `trades` are not deposits.
+    pub fn scan(&self, utxos: &[Utxo], trades: &[Trade], now: u64) -> Result<DepositScan> {
+        let current: Vec<String> = utxos.iter().map(|utxo| utxo.outpoint.to_string()).collect();
+        let Some(known) = self.storage.get_json::<Vec<String>>(DEPOSITS_NAMESPACE, KNOWN_KEY)? else {
+            debug!("Recording {} wallet outputs held before deposit detection", current.len());
+            self.storage.put_json(DEPOSITS_NAMESPACE, KNOWN_KEY, &current)?;
+            self.storage.flush()?;
+            return Ok(DepositScan::default());
+        };
+        let known: HashSet<String> = known.into_iter().collect();
+
+        let mut scan = DepositScan::default();
+
+        // Deposits still waiting for confirmations
+        for mut deposit in self.deposits()?.into_iter().filter(|deposit| !deposit.is_confirmed()) {
+            match utxos.iter().find(|utxo| utxo.outpoint.to_string() == deposit.outpoint) {
+                Some(utxo) => {
+                    deposit.confirmations = utxo.confirmations;
+                    if utxo.confirmations >= self.confirmations {
+                        deposit.confirmed_at = Some(now);
+                        scan.confirmed.push(deposit.clone());
+                    }
+                    self.storage.put_json(DEPOSITS_NAMESPACE, &deposit_key(&deposit.outpoint), &deposit)?;
+                }
+                None => {
+                    // Replaced or double spent before it confirmed
+                    warn!("Unconfirmed deposit {} disappeared from the wallet", deposit.outpoint);
+                    self.storage.delete(DEPOSITS_NAMESPACE, deposit_key(&deposit.outpoint).as_bytes())?;
+                }
+            }
+        }
+
+        // Outputs new since the previous scan
+        for utxo in utxos {
+            let outpoint = utxo.outpoint.to_string();
+            let txid = utxo.outpoint.txid.to_string();
+            if known.contains(&outpoint) || self.is_own_transaction(&txid, trades)? {
+                continue;
+            }
+
+            let confirmed = utxo.confirmations >= self.confirmations;
+            let deposit = Deposit {
+                outpoint: outpoint.clone(),
+                txid,
+                vout: utxo.outpoint.vout,
+                address: Address::from_script(&utxo.txout.script_pubkey, self.network)
+                    .ok()
+                    .map(|address| address.to_string()),
+                value: utxo.value(),
+                assets: utxo.assets.clone(),
+                confirmations: utxo.confirmations,
+                detected_at: now,
+                confirmed_at: confirmed.then_some(now),
+            };
+            self.storage.put_json(DEPOSITS_NAMESPACE, &deposit_key(&outpoint), &deposit)?;
+
+            scan.detected.push(deposit.clone());
+            if confirmed {
+                scan.confirmed.push(deposit);
+            }
+        }
+
+        self.storage.put_json(DEPOSITS_NAMESPACE, KNOWN_KEY, &current)?;
+        self.storage.flush().context("Failed to flush deposits")?;
+
+        Ok(scan)
+    }
+
+    /// Check if a transaction is a trade settlement or was broadcast by the node
+    fn is_own_transaction(&self, txid: &str, trades: &[Trade]) -> Result<bool> {
+        if trades.iter().any(|trade| trade.txid.as_deref() == Some(txid)) {
+            return Ok(true);
+        }
+
+        Ok(self.storage.get_json::<u64>(DEPOSITS_NAMESPACE, &own_key(txid))?.is_some())
+    }
+
+    /// Get the completed trades not yet recorded as confirmed whose settlement pays the wallet an output with the confirmations
+    pub fn confirmed_settlements<'a>(&self, utxos: &[Utxo], trades: &'a [Trade]) -> Vec<&'a Trade> {
+        trades
+            .iter()
+            .filter(|trade| trade.state == TradeState::Completed)
+            .filter(|trade| !trade.timeline.iter().any(|entry| entry.stage == TradeStage::Confirmed))
+            .filter(|trade| {
+                utxos.iter().any(|utxo| {
+                    trade.txid.as_deref() == Some(utxo.outpoint.txid.to_string().as_str())
+                        && utxo.confirmations >= self.confirmations
+                })
+            })
+            .collect()
+    }
+
+    /// Get the deposits, newest first
+    pub fn deposits(&self) -> Result<Vec<Deposit>> {
+        let mut deposits: Vec<Deposit> = self.storage
+            .scan_json::<Deposit>(DEPOSITS_NAMESPACE, "deposit/")
+            .context("Failed to read deposits")?
+            .into_iter()
+            .map(|(_, deposit)| deposit)
+            .collect();
+        deposits.sort_by(|a, b| b.detected_at.cmp(&a.detected_at).then_with(|| a.outpoint.cmp(&b.outpoint)));
+
+        Ok(deposits)
+    }
+}
+
+/// Spawn a task scanning the wallet for deposits every `interval` and emitting their events
+pub fn spawn_deposit_watcher(
+    watcher: Arc<DepositWatcher>,
+    wallet: Arc<dyn WalletInterface + Send + Sync>,
+    trade_manager: Option<Arc<TradeModule>>,
+    event_sender: mpsc::Sender<Event>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let utxos = match wallet.list_utxos().await {
+                Ok(utxos) => utxos,
+                Err(e) => {
+                    warn!("Failed to list wallet outputs: {}", e);
+                    continue;
+                }
+            };
+            let trades = match &trade_manager {
+                Some(trade_manager) => trade_manager.get_trades().await,
+                None => Vec::new(),
+            };
+
+            for utxo in &utxos {
+                let Ok(address) = Address::from_script(&utxo.txout.script_pubkey, watcher.network) else {
+                    continue;
+                };
+                if let Err(e) = wallet.mark_address_used(&address.to_string()).await {
+                    warn!("Failed to mark address {} as used: {}", address, e);
+                }
+            }
+
+            if let Some(trade_manager) = &trade_manager {
+                for trade in watcher.confirmed_settlements(&utxos, &trades) {
+                    if let Err(e) = trade_manager.record_confirmed(&trade.id).await {
+                        warn!("Failed to record trade {} confirmed: {}", trade.id, e);
+                    }
+                }
+            }
+
+            let scan = match watcher.scan(&utxos, &trades, clock.now()) {
+                Ok(scan) => scan,
+                Err(e) => {
+                    warn!("Deposit scan failed: {}", e);
+                    continue;
+                }
+            };
+
+            let events = scan.detected.into_iter().map(Event::DepositDetected)
+                .chain(scan.confirmed.into_iter().map(Event::DepositConfirmed));
+            for event in events {
+                if event_sender.send(event).await.is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, Script, TxOut, Txid};
+    use crate::orderbook::OrderId;
+    use darkswap_support::storage::MemoryStorage;
+    use rust_decimal_macros::dec;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn utxo(tx: u8, value: u64, confirmations: u32) -> Utxo {
+        Utxo::new(
+            OutPoint::new(Txid::from_inner([tx; 32]), 0),
+            TxOut { value, script_pubkey: Script::new() },
+            confirmations,
+        )
+    }
+
+    #[test]
+    fn test_deposits() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let watcher = DepositWatcher::open(storage.clone(), Network::Testnet, &DepositConfig::default());
+
+        // Outputs held before the first scan are not deposits
+        assert_eq!(watcher.scan(&[utxo(1, 50_000, 6)], &[], NOW).unwrap(), DepositScan::default());
+
+        // A new output is detected, then confirmed
+        let mut utxos = vec![utxo(1, 50_000, 6), utxo(2, 20_000, 0)];
+        let scan = watcher.scan(&utxos, &[], NOW + 30).unwrap();
+        assert_eq!((scan.detected.len(), scan.confirmed.len()), (1, 0));
+        assert_eq!(scan.detected[0].value, 20_000);
+
+        utxos[1] = utxo(2, 20_000, 1);
+        let scan = watcher.scan(&utxos, &[], NOW + 60).unwrap();
+        assert_eq!((scan.detected.len(), scan.confirmed.len()), (0, 1));
+        assert_eq!(scan.confirmed[0].confirmed_at, Some(NOW + 60));
+
+        // Outputs of own transactions are not deposits
+        watcher.record_own_transaction(&Txid::from_inner([3; 32]).to_string(), NOW).unwrap();
+        utxos.push(utxo(3, 10_000, 0));
+        assert_eq!(watcher.scan(&utxos, &[], NOW + 90).unwrap(), DepositScan::default());
+
+        // A reopened watcher knows what it has seen
+        let watcher = DepositWatcher::open(storage, Network::Testnet, &DepositConfig::default());
+        utxos.push(utxo(4, 5_000, 3));
+        let scan = watcher.scan(&utxos, &[], NOW + 120).unwrap();
+        assert_eq!((scan.detected.len(), scan.confirmed.len()), (1, 1));
+        assert_eq!(watcher.deposits().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_confirmed_settlements() {
+        let watcher = DepositWatcher::open(Arc::new(MemoryStorage::new()), Network::Testnet, &DepositConfig::default());
+        let mut trade = Trade::new(
+            OrderId("order-1".to_string()),
+            "maker".to_string(),
+            "taker".to_string(),
+            Asset::Bitcoin,
+            Asset::Rune(1),
+            dec!(1),
+            dec!(100),
+            None,
+            NOW,
+        );
+        trade.txid = Some(Txid::from_inner([5; 32]).to_string());
+        trade.update_state_at(TradeState::Completed, NOW);
+
+        // The settlement output needs the confirmations
+        assert!(watcher.confirmed_settlements(&[utxo(5, 10_000, 0)], &[trade.clone()]).is_empty());
+        assert_eq!(watcher.confirmed_settlements(&[utxo(5, 10_000, 1)], &[trade.clone()]).len(), 1);
+
+        // A trade recorded as confirmed is left alone
+        trade.record_stage(TradeStage::Confirmed, NOW * 1000);
+        assert!(watcher.confirmed_settlements(&[utxo(5, 10_000, 1)], &[trade]).is_empty());
+    }
+}
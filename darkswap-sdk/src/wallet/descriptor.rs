@@ -0,0 +1,100 @@
+//! Output descriptors
+//!
+//! The receive addresses of a simple wallet are the P2WPKH outputs of the
+//! unhardened children of one extended key, i.e. the descriptor
+//! `wpkh([fingerprint]xprv/*)`. Exporting it, with its checksum, lets Sparrow
+//! or Bitcoin Core watch or spend the same wallet. Importing a descriptor of
+//! that shape, e.g. one exported from Sparrow, makes it the wallet's key.
+
+use anyhow::Result;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::bip32::ExtendedPrivKey;
+use bitcoin::Network;
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorSecretKey, Wildcard};
+use miniscript::Descriptor;
+
+use crate::wallet::WalletError;
+
+/// Get the descriptor of the addresses derived from `receive_key`, with its checksum
+///
+/// The public descriptor is enough to watch the wallet; the private one also spends from it.
+pub fn export(receive_key: &ExtendedPrivKey, include_private: bool) -> Result<String> {
+    let secp = Secp256k1::new();
+    let fingerprint = receive_key.fingerprint(&secp);
+    let source = format!("wpkh([{}]{}/*)", fingerprint, receive_key);
+
+    let (descriptor, key_map) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, &source)
+        .map_err(|e| WalletError::InvalidDescriptor(e.to_string()))?;
+
+    Ok(if include_private {
+        descriptor.to_string_with_secret(&key_map)
+    } else {
+        descriptor.to_string()
+    })
+}
+
+/// Get the key the receive addresses of a private `wpkh(xprv/.../*)` descriptor derive from
+///
+/// The descriptor must carry its checksum, so a mistyped descriptor is refused
+/// instead of silently creating another wallet.
+pub fn import(descriptor: &str, network: Network) -> Result<ExtendedPrivKey> {
+    let descriptor = descriptor.trim();
+    if !descriptor.contains('#') {
+        return Err(WalletError::InvalidDescriptor("Checksum missing".to_string()).into());
+    }
+
+    let secp = Secp256k1::new();
+    let (parsed, key_map) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)
+        .map_err(|e| WalletError::InvalidDescriptor(e.to_string()))?;
+
+    let public_key = match &parsed {
+        Descriptor::Wpkh(wpkh) => wpkh.as_inner(),
+        _ => return Err(WalletError::InvalidDescriptor("Only wpkh() descriptors can be imported".to_string()).into()),
+    };
+    let secret_key = key_map.get(public_key)
+        .ok_or_else(|| WalletError::InvalidDescriptor("Descriptor holds no private key".to_string()))?;
+
+    let xkey = match secret_key {
+        DescriptorSecretKey::XPrv(xkey) => xkey,
+        _ => return Err(WalletError::InvalidDescriptor("Descriptor key is not an extended private key".to_string()).into()),
+    };
+    if xkey.wildcard != Wildcard::Unhardened {
+        return Err(WalletError::InvalidDescriptor("Descriptor must end in an unhardened /*".to_string()).into());
+    }
+
+    // Extended keys only tell mainnet from the test networks
+    let mainnet = network == Network::Bitcoin;
+    if (xkey.xkey.network == Network::Bitcoin) != mainnet {
+        return Err(WalletError::InvalidDescriptor(format!("Descriptor key is not for {}", network)).into());
+    }
+
+    let receive_key = xkey.xkey.derive_priv(&secp, &xkey.derivation_path)
+        .map_err(|e| WalletError::InvalidDescriptor(e.to_string()))?;
+
+    Ok(ExtendedPrivKey { network, ..receive_key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let key = ExtendedPrivKey::new_master(Network::Testnet, &[7u8; 32]).unwrap();
+
+        let public = export(&key, false).unwrap();
+        assert!(public.starts_with("wpkh(["));
+        assert!(public.contains("tpub"));
+        assert!(public.contains('#'));
+
+        // Only the private descriptor imports, and to the same key
+        assert!(import(&public, Network::Testnet).is_err());
+        let private = export(&key, true).unwrap();
+        assert_eq!(import(&private, Network::Testnet).unwrap(), key);
+
+        // A descriptor without its checksum, or for another network, is refused
+        let unchecked = private.split('#').next().unwrap();
+        assert!(import(unchecked, Network::Testnet).is_err());
+        assert!(import(&private, Network::Bitcoin).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Miniscript PSBT finalization
+//!
+//! A key-spend input is finalized from its one signature, but an input of a
+//! descriptor wallet, e.g. a 2-of-3 multisig or a timelocked recovery path,
+//! needs its script to assemble the witness. The descriptor of such an input
+//! travels in the PSBT as a proprietary field (prefix `darkswap`, subtype 0,
+//! the descriptor string as value), so the party finalizing the trade does not
+//! need the other wallet's descriptor. Finalization satisfies the descriptor
+//! with the partial signatures and the transaction's lock time and sequences.
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use bitcoin::psbt::raw::ProprietaryKey;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::secp256k1::Secp256k1;
+use miniscript::descriptor::{DefiniteDescriptorKey, DescriptorPublicKey};
+use miniscript::psbt::PsbtExt;
+use miniscript::Descriptor;
+
+use crate::wallet::WalletError;
+
+/// Prefix of DarkSwap proprietary PSBT fields
+pub const PROPRIETARY_PREFIX: &[u8] = b"darkswap";
+
+/// Subtype of the input descriptor field
+pub const DESCRIPTOR_SUBTYPE: u8 = 0x00;
+
+/// Key of the input descriptor field
+fn descriptor_key() -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: PROPRIETARY_PREFIX.to_vec(),
+        subtype: DESCRIPTOR_SUBTYPE,
+        key: Vec::new(),
+    }
+}
+
+/// Attach the descriptor of an input, filling in its scripts and key origins
+///
+/// The input must carry the output it spends, which has to match the descriptor.
+pub fn set_input_descriptor(psbt: &mut Psbt, index: usize, descriptor: &Descriptor<DefiniteDescriptorKey>) -> Result<()> {
+    if index >= psbt.inputs.len() {
+        return Err(WalletError::InvalidPsbt(format!("No input {}", index)).into());
+    }
+
+    psbt.update_input_with_descriptor(index, descriptor)
+        .map_err(|e| WalletError::InvalidPsbt(format!("Descriptor does not match input {}: {}", index, e)))?;
+    psbt.inputs[index]
+        .proprietary
+        .insert(descriptor_key(), descriptor.to_string().into_bytes());
+
+    Ok(())
+}
+
+/// Attach the descriptor of every input spending one of the first `gap_limit` addresses of a wallet
+///
+/// Called by a wallet on the PSBT it signs, so the party finalizing the trade
+/// can satisfy the wallet's inputs. Inputs of other wallets are left as they
+/// are. Returns the number of inputs the descriptor was attached to.
+pub fn attach_wallet_descriptor(psbt: &mut Psbt, descriptor: &Descriptor<DescriptorPublicKey>, gap_limit: u32) -> Result<usize> {
+    let derived: Vec<_> = (0..gap_limit.max(1))
+        .map(|index| descriptor.at_derivation_index(index))
+        .collect();
+
+    let mut attached = 0;
+    for index in 0..psbt.inputs.len() {
+        let spent = match &psbt.inputs[index].witness_utxo {
+            Some(txout) => txout.script_pubkey.clone(),
+            None => continue,
+        };
+        if let Some(descriptor) = derived.iter().find(|descriptor| descriptor.script_pubkey() == spent) {
+            set_input_descriptor(psbt, index, descriptor)?;
+            attached += 1;
+        }
+    }
+
+    Ok(attached)
+}
+
+/// Get the descriptor attached to an input
+pub fn input_descriptor(psbt: &Psbt, index: usize) -> Result<Option<Descriptor<DefiniteDescriptorKey>>> {
+    let input = psbt.inputs.get(index)
+        .ok_or_else(|| WalletError::InvalidPsbt(format!("No input {}", index)))?;
+
+    match input.proprietary.get(&descriptor_key()) {
+        Some(value) => {
+            let descriptor = std::str::from_utf8(value).context("Input descriptor is not UTF-8")?;
+            let descriptor = Descriptor::<DefiniteDescriptorKey>::from_str(descriptor)
+                .map_err(|e| WalletError::InvalidPsbt(format!("Invalid descriptor on input {}: {}", index, e)))?;
+            Ok(Some(descriptor))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Finalize every input not finalized yet
+///
+/// Inputs with an attached descriptor get their scripts from it first, so a
+/// PSBT assembled by the counterparty finalizes without them.
+pub fn finalize_psbt(psbt: &mut Psbt) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+
+    for index in 0..psbt.inputs.len() {
+        let input = &psbt.inputs[index];
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+
+        if input.witness_script.is_none() && input.redeem_script.is_none() {
+            if let Some(descriptor) = input_descriptor(psbt, index)? {
+                psbt.update_input_with_descriptor(index, &descriptor)
+                    .map_err(|e| WalletError::InvalidPsbt(format!("Descriptor does not match input {}: {}", index, e)))?;
+            }
+        }
+
+        psbt.finalize_inp_mut(&secp, index)
+            .map_err(|e| WalletError::InvalidPsbt(format!("Failed to finalize input {}: {}", index, e)))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{Message, SecretKey};
+    use bitcoin::util::sighash::SighashCache;
+    use bitcoin::{EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, Script, Sequence, Transaction, TxIn, TxOut, Witness};
+    use bitcoin::util::bip32::ExtendedPrivKey;
+    use bitcoin::Network;
+    use crate::wallet::descriptor;
+
+    #[test]
+    fn test_attach_wallet_descriptor() {
+        let key = ExtendedPrivKey::new_master(Network::Testnet, &[7u8; 32]).unwrap();
+        let wallet = Descriptor::<DescriptorPublicKey>::from_str(&descriptor::export(&key, false).unwrap()).unwrap();
+        let own = wallet.at_derivation_index(2);
+
+        let input = TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![input.clone(), input],
+            output: vec![TxOut { value: 90_000, script_pubkey: Script::new() }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut { value: 50_000, script_pubkey: Script::new_op_return(&[]) });
+        psbt.inputs[1].witness_utxo = Some(TxOut { value: 50_000, script_pubkey: own.script_pubkey() });
+
+        // Only the wallet's own input gets its descriptor, and only within the gap limit
+        assert_eq!(attach_wallet_descriptor(&mut psbt.clone(), &wallet, 2).unwrap(), 0);
+        assert_eq!(attach_wallet_descriptor(&mut psbt, &wallet, 20).unwrap(), 1);
+        assert!(input_descriptor(&psbt, 0).unwrap().is_none());
+        assert_eq!(input_descriptor(&psbt, 1).unwrap().unwrap().to_string(), own.to_string());
+    }
+
+    #[test]
+    fn test_finalize_multisig() {
+        let secp = Secp256k1::new();
+        let secret_keys: Vec<SecretKey> = (1..=3u8).map(|i| SecretKey::from_slice(&[i; 32]).unwrap()).collect();
+        let public_keys: Vec<PublicKey> = secret_keys
+            .iter()
+            .map(|secret_key| PublicKey::new(secret_key.public_key(&secp)))
+            .collect();
+
+        let descriptor = Descriptor::<DefiniteDescriptorKey>::from_str(&format!(
+            "wsh(multi(2,{},{},{}))",
+            public_keys[0], public_keys[1], public_keys[2]
+        ))
+        .unwrap();
+        let spent = TxOut { value: 100_000, script_pubkey: descriptor.script_pubkey() };
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut { value: 90_000, script_pubkey: Script::new() }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(spent.clone());
+
+        // Without the script the input cannot be finalized
+        assert!(finalize_psbt(&mut psbt.clone()).is_err());
+
+        // The counterparty sees only the descriptor field, not the scripts
+        set_input_descriptor(&mut psbt, 0, &descriptor).unwrap();
+        psbt.inputs[0].witness_script = None;
+        psbt.inputs[0].bip32_derivation.clear();
+        assert_eq!(input_descriptor(&psbt, 0).unwrap().unwrap().to_string(), descriptor.to_string());
+
+        // Two of the three keys sign
+        let script_code = descriptor.explicit_script().unwrap();
+        let sighash = SighashCache::new(&tx)
+            .segwit_signature_hash(0, &script_code, spent.value, EcdsaSighashType::All)
+            .unwrap();
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        for i in [0, 2] {
+            let signature = EcdsaSig::sighash_all(secp.sign_ecdsa(&message, &secret_keys[i]));
+            psbt.inputs[0].partial_sigs.insert(public_keys[i], signature);
+        }
+
+        finalize_psbt(&mut psbt).unwrap();
+
+        // Dummy element, two signatures and the witness script
+        let witness = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 4);
+        assert_eq!(witness.last().unwrap(), script_code.as_bytes());
+    }
+}
@@ -9,10 +9,31 @@ use async_trait::async_trait;
 use thiserror::Error;
 
 use crate::orderbook::OrderId;
+use crate::trade::settlement::PayoutOutput;
 use crate::types::{Asset, TradeId};
 
+pub mod address_book;
+pub mod balance_sync;
+pub mod bip322;
+#[cfg(not(feature = "no-bdk"))]
 pub mod bdk_wallet;
+pub mod coin_selection;
+pub mod consolidation;
+#[cfg(feature = "http")]
+pub mod custody;
+pub mod deposits;
+pub mod descriptor;
+pub mod finalize;
+#[cfg(feature = "http")]
+pub mod remote_signer;
+pub mod reserve;
 pub mod simple_wallet;
+pub mod utxo;
+#[cfg(feature = "http")]
+pub mod watch_only;
+
+use address_book::AddressInfo;
+use utxo::Utxo;
 
 /// Wallet error
 #[derive(Debug, Error)]
@@ -32,9 +53,23 @@ pub enum WalletError {
     /// Invalid PSBT
     #[error("Invalid PSBT: {0}")]
     InvalidPsbt(String),
+    /// Invalid or unsupported output descriptor
+    #[error("Invalid descriptor: {0}")]
+    InvalidDescriptor(String),
     /// Unsupported asset
     #[error("Unsupported asset: {0}")]
     UnsupportedAsset(String),
+    /// Too many unused addresses in a row
+    #[error("Gap limit of {0} unused addresses reached")]
+    GapLimitReached(u32),
+    /// Committing the funds would eat into the fee reserve
+    #[error("Fee reserve would be breached: {spare} sats would be left, {required} sats are reserved for fees")]
+    FeeReserve {
+        /// Uncommitted balance that would be left (satoshis)
+        spare: u64,
+        /// Reserve (satoshis)
+        required: u64,
+    },
     /// Other error
     #[error("Wallet error: {0}")]
     Other(String),
@@ -46,6 +81,21 @@ pub trait WalletInterface: Send + Sync {
     /// Get wallet address
     async fn get_address(&self) -> Result<String>;
 
+    /// Derive a new receive address with an optional label
+    async fn get_new_address(&self, label: Option<String>) -> Result<AddressInfo>;
+
+    /// List the issued receive addresses
+    async fn list_addresses(&self) -> Result<Vec<AddressInfo>>;
+
+    /// Mark a receive address as having received funds, so it no longer counts
+    /// against the gap limit; returns false if the address was not issued by this wallet
+    async fn mark_address_used(&self, _address: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// List unspent outputs
+    async fn list_utxos(&self) -> Result<Vec<Utxo>>;
+
     /// Get wallet balance
     async fn get_balance(&self) -> Result<u64>;
 
@@ -63,6 +113,9 @@ pub trait WalletInterface: Send + Sync {
     ) -> Result<String>;
 
     /// Create and sign a PSBT for a trade
+    ///
+    /// `payout` is the output paying the counterparty's proceeds to the address it
+    /// asked for, which the PSBT must carry.
     async fn create_trade_psbt(
         &self,
         trade_id: &TradeId,
@@ -71,6 +124,7 @@ pub trait WalletInterface: Send + Sync {
         quote_asset: &Asset,
         amount: u64,
         price: u64,
+        payout: Option<&PayoutOutput>,
     ) -> Result<String>;
 
     /// Sign a PSBT
@@ -81,4 +135,14 @@ pub trait WalletInterface: Send + Sync {
 
     /// Verify a PSBT
     async fn verify_psbt(&self, psbt_base64: &str) -> Result<bool>;
+
+    /// Estimate the fee rate for confirmation within `target_blocks` (sat/vB)
+    async fn estimate_fee_rate(&self, _target_blocks: u16) -> Result<f32> {
+        Err(WalletError::Other("This wallet has no fee estimator".to_string()).into())
+    }
+
+    /// Get the output descriptor of the wallet's receive addresses, with its checksum
+    async fn export_descriptor(&self, _include_private: bool) -> Result<String> {
+        Err(WalletError::Other("This wallet has no output descriptor".to_string()).into())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,601 @@
+//! Remote signer wallet
+//!
+//! Institutional deployments keep their private keys in a separate signing
+//! service rather than on the trading node. This backend forwards every operation
+//! that needs a key (receive addresses, order and trade PSBTs, signing) to that
+//! service over authenticated HTTPS, and delegates the read-only ones (balances,
+//! UTXOs, verification, broadcast) to a watch-only chain wallet.
+//!
+//! Each signing request names the policy the node expects the signer to enforce.
+//! The signer echoes the request it approved, and a response whose echo differs
+//! from what was asked, or whose PSBT spends a different transaction, is refused.
+//!
+//! The same service can hold the key own orders are announced with, apart from
+//! the network identity, through [`RemoteAnnouncementSigner`]: `GET
+//! /announcement/key` returns `{"public_key": <hex>}`, and `POST
+//! /announcement/sign` takes a signing request and answers with the signature.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bitcoin::consensus::Decodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use libp2p::identity::PublicKey;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::config::{DnsConfig, RemoteSignerConfig};
+use crate::orderbook::announcement::AnnouncementSigner;
+use crate::orderbook::OrderId;
+use crate::trade::settlement::PayoutOutput;
+use crate::types::{Asset, TradeId};
+use crate::wallet::address_book::AddressInfo;
+use crate::wallet::utxo::Utxo;
+use crate::wallet::{WalletError, WalletInterface};
+
+/// Remote signer error
+#[derive(Debug, Error)]
+pub enum RemoteSignerError {
+    /// The signer refused the request
+    #[error("Remote signer refused the request: {0}")]
+    Refused(String),
+    /// The signer approved something other than what was asked
+    #[error("Remote signer policy echo does not match the request: {0}")]
+    EchoMismatch(String),
+    /// The signer returned a PSBT for a different transaction
+    #[error("Remote signer changed the transaction")]
+    Tampered,
+}
+
+/// What the signer is asked to sign
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SignPurpose {
+    /// Create and sign the PSBT of an order
+    Order {
+        /// Order ID
+        order_id: OrderId,
+        /// Base asset
+        base_asset: Asset,
+        /// Quote asset
+        quote_asset: Asset,
+        /// Amount (satoshis)
+        amount: u64,
+        /// Price (satoshis)
+        price: u64,
+    },
+    /// Create and sign the PSBT of a trade
+    Trade {
+        /// Trade ID
+        trade_id: TradeId,
+        /// Order ID
+        order_id: OrderId,
+        /// Base asset
+        base_asset: Asset,
+        /// Quote asset
+        quote_asset: Asset,
+        /// Amount (satoshis)
+        amount: u64,
+        /// Price (satoshis)
+        price: u64,
+        /// Output paying the counterparty's proceeds, which the PSBT must carry
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        payout: Option<PayoutOutput>,
+    },
+    /// Sign an existing PSBT
+    Psbt {
+        /// PSBT (base64)
+        psbt: String,
+    },
+    /// Sign the announcement of an own order with the announcement key
+    Announcement {
+        /// Order ID
+        order_id: OrderId,
+        /// Bytes to sign, the JSON of the order (hex)
+        message: String,
+    },
+}
+
+/// Signing request sent to the signer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignRequest {
+    /// Request ID
+    pub request_id: String,
+    /// Policy the signer is expected to apply
+    pub policy: Option<String>,
+    /// What to sign
+    pub purpose: SignPurpose,
+}
+
+/// Signer response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignResponse {
+    /// Signed PSBT (base64); absent if refused
+    pub psbt: Option<String>,
+    /// The request as approved by the signer's policy
+    pub echo: Option<SignRequest>,
+    /// Reason for a refusal
+    pub reason: Option<String>,
+}
+
+/// Signer response to an announcement signing request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnouncementResponse {
+    /// Signature (hex); absent if refused
+    pub signature: Option<String>,
+    /// The request as approved by the signer's policy
+    pub echo: Option<SignRequest>,
+    /// Reason for a refusal
+    pub reason: Option<String>,
+}
+
+/// Announcement public key of the signer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnnouncementKey {
+    /// Public key (protobuf encoded, hex)
+    public_key: String,
+}
+
+/// New address request sent to the signer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NewAddressRequest {
+    /// Label
+    label: Option<String>,
+}
+
+/// Authenticated HTTP client of the signing service
+struct SignerClient {
+    /// HTTP client
+    client: reqwest::Client,
+    /// Signer base URL
+    url: String,
+    /// Bearer token
+    auth_token: Option<String>,
+    /// Policy the signer is expected to apply
+    policy: Option<String>,
+}
+
+impl SignerClient {
+    /// Create a client of the configured signer
+    fn new(config: &RemoteSignerConfig, dns: &DnsConfig) -> Result<Self> {
+        if !config.url.starts_with("https://") && !config.allow_insecure {
+            return Err(anyhow::anyhow!("Remote signer URL must use HTTPS: {}", config.url));
+        }
+
+        let mut builder = crate::dns::http_client_builder(dns)?
+            .timeout(Duration::from_secs(config.timeout.max(1)));
+
+        if let Some(path) = &config.ca_cert {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Failed to read remote signer CA certificate {}", path))?;
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(reqwest::Certificate::from_pem(&pem).context("Invalid remote signer CA certificate")?);
+        }
+
+        if let Some(path) = &config.client_identity {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Failed to read remote signer client identity {}", path))?;
+            builder = builder.identity(reqwest::Identity::from_pem(&pem).context("Invalid remote signer client identity")?);
+        }
+
+        let client = builder.build().context("Failed to create remote signer HTTP client")?;
+
+        Ok(Self {
+            client,
+            url: config.url.trim_end_matches('/').to_string(),
+            auth_token: config.auth_token.clone(),
+            policy: config.policy.clone(),
+        })
+    }
+
+    /// Send a request to the signer
+    async fn call<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T> {
+        let request = match &self.auth_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+
+        request
+            .send()
+            .await
+            .context("Failed to reach remote signer")?
+            .error_for_status()
+            .context("Remote signer failed")?
+            .json()
+            .await
+            .context("Invalid remote signer response")
+    }
+
+    /// Build a signing request under the configured policy
+    fn request(&self, purpose: SignPurpose) -> SignRequest {
+        SignRequest {
+            request_id: Uuid::new_v4().to_string(),
+            policy: self.policy.clone(),
+            purpose,
+        }
+    }
+}
+
+/// Wallet whose keys are held by a remote signing service
+pub struct RemoteSignerWallet {
+    /// Signing service
+    signer: SignerClient,
+    /// Watch-only wallet for balances, UTXOs and broadcast
+    chain: Arc<dyn WalletInterface>,
+}
+
+impl RemoteSignerWallet {
+    /// Create a remote signer wallet
+    pub fn new(config: &RemoteSignerConfig, dns: &DnsConfig, chain: Arc<dyn WalletInterface>) -> Result<Self> {
+        Ok(Self {
+            signer: SignerClient::new(config, dns)?,
+            chain,
+        })
+    }
+
+    /// Send a request to the signer
+    async fn call<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T> {
+        self.signer.call(request).await
+    }
+
+    /// Have the signer sign, checking its policy echo
+    async fn sign(&self, purpose: SignPurpose) -> Result<String> {
+        let request = self.signer.request(purpose);
+
+        let response: SignResponse = self
+            .call(self.signer.client.post(format!("{}/sign", self.signer.url)).json(&request))
+            .await?;
+
+        Ok(check_response(&request, response)?)
+    }
+}
+
+/// Announcement key held by the remote signing service
+pub struct RemoteAnnouncementSigner {
+    /// Signing service
+    signer: SignerClient,
+    /// Announcement public key
+    public_key: PublicKey,
+}
+
+impl RemoteAnnouncementSigner {
+    /// Connect to the configured signer and fetch its announcement key
+    pub async fn connect(config: &RemoteSignerConfig, dns: &DnsConfig) -> Result<Self> {
+        let signer = SignerClient::new(config, dns)?;
+        let key: AnnouncementKey = signer
+            .call(signer.client.get(format!("{}/announcement/key", signer.url)))
+            .await?;
+        let public_key = PublicKey::from_protobuf_encoding(&hex::decode(&key.public_key).context("Invalid announcement key encoding")?)
+            .context("Invalid announcement key")?;
+
+        Ok(Self { signer, public_key })
+    }
+}
+
+#[async_trait]
+impl AnnouncementSigner for RemoteAnnouncementSigner {
+    fn public_key(&self) -> PublicKey {
+        self.public_key.clone()
+    }
+
+    async fn sign(&self, order_id: &OrderId, message: &[u8]) -> Result<Vec<u8>> {
+        let request = self.signer.request(SignPurpose::Announcement {
+            order_id: order_id.clone(),
+            message: hex::encode(message),
+        });
+
+        let response: AnnouncementResponse = self.signer
+            .call(self.signer.client.post(format!("{}/announcement/sign", self.signer.url)).json(&request))
+            .await?;
+        check_echo(&request, response.echo)?;
+
+        let signature = response.signature
+            .ok_or_else(|| RemoteSignerError::Refused(response.reason.unwrap_or_else(|| "no reason given".to_string())))?;
+        hex::decode(signature).context("Invalid announcement signature encoding")
+    }
+}
+
+/// Check a signer response against its request, returning the signed PSBT
+pub fn check_response(request: &SignRequest, response: SignResponse) -> Result<String, RemoteSignerError> {
+    let psbt = response.psbt
+        .ok_or_else(|| RemoteSignerError::Refused(response.reason.unwrap_or_else(|| "no reason given".to_string())))?;
+
+    check_echo(request, response.echo)?;
+
+    // Signing an existing PSBT must only add signatures
+    if let SignPurpose::Psbt { psbt: original } = &request.purpose {
+        if !only_signatures_added(&decode_psbt(original)?, &decode_psbt(&psbt)?) {
+            return Err(RemoteSignerError::Tampered);
+        }
+    }
+
+    Ok(psbt)
+}
+
+/// Check that the signer approved exactly the request sent
+fn check_echo(request: &SignRequest, echo: Option<SignRequest>) -> Result<(), RemoteSignerError> {
+    match echo {
+        None => return Err(RemoteSignerError::EchoMismatch("no echo".to_string())),
+        Some(echo) if echo.request_id != request.request_id => {
+            return Err(RemoteSignerError::EchoMismatch(format!("request {}", echo.request_id)));
+        }
+        Some(echo) if echo.policy != request.policy => {
+            return Err(RemoteSignerError::EchoMismatch(format!("policy {:?}", echo.policy)));
+        }
+        Some(echo) if echo.purpose != request.purpose => {
+            return Err(RemoteSignerError::EchoMismatch("purpose".to_string()));
+        }
+        Some(_) => {}
+    }
+
+    Ok(())
+}
+
+/// Decode a base64 PSBT
+fn decode_psbt(psbt: &str) -> Result<Psbt, RemoteSignerError> {
+    let bytes = base64::decode(psbt.trim()).map_err(|_| RemoteSignerError::Tampered)?;
+    Psbt::consensus_decode(&mut &bytes[..]).map_err(|_| RemoteSignerError::Tampered)
+}
+
+/// Check that a signed PSBT differs from the original only by signatures
+///
+/// The transaction, the outputs and, for every input, the output it spends,
+/// its scripts and its sighash type must be unchanged, so a signer cannot
+/// swap the prevout amounts a fee is computed from or the scripts it signs.
+fn only_signatures_added(original: &Psbt, signed: &Psbt) -> bool {
+    original.unsigned_tx == signed.unsigned_tx
+        && original.outputs == signed.outputs
+        && original.inputs.len() == signed.inputs.len()
+        && original.inputs.iter().zip(&signed.inputs).all(|(before, after)| {
+            before.witness_utxo == after.witness_utxo
+                && before.non_witness_utxo == after.non_witness_utxo
+                && before.sighash_type == after.sighash_type
+                && before.redeem_script == after.redeem_script
+                && before.witness_script == after.witness_script
+                && before.tap_internal_key == after.tap_internal_key
+                && before.tap_merkle_root == after.tap_merkle_root
+        })
+}
+
+#[async_trait]
+impl WalletInterface for RemoteSignerWallet {
+    /// Get wallet address
+    async fn get_address(&self) -> Result<String> {
+        let info: AddressInfo = self.call(self.signer.client.get(format!("{}/address", self.signer.url))).await?;
+        Ok(info.address)
+    }
+
+    /// Have the signer derive a new receive address
+    async fn get_new_address(&self, label: Option<String>) -> Result<AddressInfo> {
+        self.call(self.signer.client.post(format!("{}/addresses", self.signer.url)).json(&NewAddressRequest { label })).await
+    }
+
+    /// List the receive addresses issued by the signer
+    async fn list_addresses(&self) -> Result<Vec<AddressInfo>> {
+        self.call(self.signer.client.get(format!("{}/addresses", self.signer.url))).await
+    }
+
+    /// List unspent outputs
+    async fn list_utxos(&self) -> Result<Vec<Utxo>> {
+        self.chain.list_utxos().await
+    }
+
+    /// Get wallet balance
+    async fn get_balance(&self) -> Result<u64> {
+        self.chain.get_balance().await
+    }
+
+    /// Get asset balance
+    async fn get_asset_balance(&self, asset: &Asset) -> Result<u64> {
+        self.chain.get_asset_balance(asset).await
+    }
+
+    /// Have the signer create and sign a PSBT for an order
+    async fn create_order_psbt(
+        &self,
+        order_id: &OrderId,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        amount: u64,
+        price: u64,
+    ) -> Result<String> {
+        self.sign(SignPurpose::Order {
+            order_id: order_id.clone(),
+            base_asset: base_asset.clone(),
+            quote_asset: quote_asset.clone(),
+            amount,
+            price,
+        })
+        .await
+    }
+
+    /// Have the signer create and sign a PSBT for a trade
+    async fn create_trade_psbt(
+        &self,
+        trade_id: &TradeId,
+        order_id: &OrderId,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        amount: u64,
+        price: u64,
+        payout: Option<&PayoutOutput>,
+    ) -> Result<String> {
+        self.sign(SignPurpose::Trade {
+            trade_id: trade_id.clone(),
+            order_id: order_id.clone(),
+            base_asset: base_asset.clone(),
+            quote_asset: quote_asset.clone(),
+            amount,
+            price,
+            payout: payout.cloned(),
+        })
+        .await
+    }
+
+    /// Have the signer sign a PSBT
+    async fn sign_psbt(&self, psbt_base64: &str) -> Result<String> {
+        if decode_psbt(psbt_base64).is_err() {
+            return Err(WalletError::InvalidPsbt("Failed to decode PSBT".to_string()).into());
+        }
+
+        self.sign(SignPurpose::Psbt { psbt: psbt_base64.to_string() }).await
+    }
+
+    /// Finalize and broadcast a PSBT
+    async fn finalize_and_broadcast_psbt(&self, psbt_base64: &str) -> Result<String> {
+        self.chain.finalize_and_broadcast_psbt(psbt_base64).await
+    }
+
+    /// Verify a PSBT
+    async fn verify_psbt(&self, psbt_base64: &str) -> Result<bool> {
+        self.chain.verify_psbt(psbt_base64).await
+    }
+
+    /// Estimate the fee rate with the chain wallet
+    async fn estimate_fee_rate(&self, target_blocks: u16) -> Result<f32> {
+        self.chain.estimate_fee_rate(target_blocks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> SignRequest {
+        SignRequest {
+            request_id: "request".to_string(),
+            policy: Some("desk-limits-v1".to_string()),
+            purpose: SignPurpose::Order {
+                order_id: OrderId("order".to_string()),
+                base_asset: Asset::Bitcoin,
+                quote_asset: Asset::Rune(1),
+                amount: 100_000,
+                price: 2_000,
+            },
+        }
+    }
+
+    #[test]
+    fn test_matching_echo_is_accepted() {
+        let response = SignResponse {
+            psbt: Some("signed".to_string()),
+            echo: Some(request()),
+            reason: None,
+        };
+
+        assert_eq!(check_response(&request(), response).unwrap(), "signed");
+    }
+
+    #[test]
+    fn test_mismatched_echo_is_rejected() {
+        let mut echo = request();
+        echo.purpose = SignPurpose::Order {
+            order_id: OrderId("order".to_string()),
+            base_asset: Asset::Bitcoin,
+            quote_asset: Asset::Rune(1),
+            amount: 200_000,
+            price: 2_000,
+        };
+        let response = SignResponse {
+            psbt: Some("signed".to_string()),
+            echo: Some(echo),
+            reason: None,
+        };
+        assert!(matches!(check_response(&request(), response), Err(RemoteSignerError::EchoMismatch(_))));
+
+        let mut echo = request();
+        echo.policy = None;
+        let response = SignResponse {
+            psbt: Some("signed".to_string()),
+            echo: Some(echo),
+            reason: None,
+        };
+        assert!(matches!(check_response(&request(), response), Err(RemoteSignerError::EchoMismatch(_))));
+    }
+
+    #[test]
+    fn test_announcement_echo() {
+        let request = SignRequest {
+            request_id: "request".to_string(),
+            policy: Some("quotes-v1".to_string()),
+            purpose: SignPurpose::Announcement {
+                order_id: OrderId("order".to_string()),
+                message: "7b7d".to_string(),
+            },
+        };
+        assert!(check_echo(&request, Some(request.clone())).is_ok());
+
+        // The signer must not sign another message than the one asked
+        let mut echo = request.clone();
+        echo.purpose = SignPurpose::Announcement {
+            order_id: OrderId("order".to_string()),
+            message: "7b2261223a317d".to_string(),
+        };
+        assert!(matches!(check_echo(&request, Some(echo)), Err(RemoteSignerError::EchoMismatch(_))));
+    }
+
+    #[test]
+    fn test_refusal() {
+        let response = SignResponse {
+            psbt: None,
+            echo: None,
+            reason: Some("over desk limit".to_string()),
+        };
+
+        assert!(matches!(
+            check_response(&request(), response),
+            Err(RemoteSignerError::Refused(reason)) if reason == "over desk limit"
+        ));
+    }
+
+    #[test]
+    fn test_signed_psbt_must_keep_inputs_and_outputs() {
+        use bitcoin::consensus::serialize;
+        use bitcoin::secp256k1::{Secp256k1, SecretKey};
+        use bitcoin::{EcdsaSig, OutPoint, PackedLockTime, PublicKey, Script, Transaction, TxIn, TxOut};
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn { previous_output: OutPoint::default(), ..Default::default() }],
+            output: vec![TxOut { value: 90_000, script_pubkey: Script::new() }],
+        };
+        let mut original = Psbt::from_unsigned_tx(tx).unwrap();
+        original.inputs[0].witness_utxo = Some(TxOut { value: 100_000, script_pubkey: Script::new() });
+        let encode = |psbt: &Psbt| base64::encode(serialize(psbt));
+
+        let request = SignRequest {
+            request_id: "request".to_string(),
+            policy: None,
+            purpose: SignPurpose::Psbt { psbt: encode(&original) },
+        };
+        let response = |psbt: &Psbt| SignResponse {
+            psbt: Some(encode(psbt)),
+            echo: Some(request.clone()),
+            reason: None,
+        };
+
+        // Adding a signature is what the signer is asked for
+        let secp = Secp256k1::new();
+        let key = PublicKey::new(SecretKey::from_slice(&[1; 32]).unwrap().public_key(&secp));
+        let mut signed = original.clone();
+        signed.inputs[0].partial_sigs.insert(key, EcdsaSig::from_slice(&[
+            0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01,
+        ]).unwrap());
+        assert!(check_response(&request, response(&signed)).is_ok());
+
+        // Changing the amount an input claims to spend is not
+        let mut tampered = signed.clone();
+        tampered.inputs[0].witness_utxo = Some(TxOut { value: 1_000_000, script_pubkey: Script::new() });
+        assert!(matches!(check_response(&request, response(&tampered)), Err(RemoteSignerError::Tampered)));
+
+        // Nor is adding a script to an output
+        let mut tampered = signed;
+        tampered.outputs[0].witness_script = Some(Script::new());
+        assert!(matches!(check_response(&request, response(&tampered)), Err(RemoteSignerError::Tampered)));
+    }
+}
@@ -0,0 +1,224 @@
+//! Fee reserve guardrails
+//!
+//! Trades pay their network fees from the wallet's plain bitcoin. If orders and
+//! accepted trades commit all of it, trades already in flight cannot be settled or
+//! refunded. The guard keeps a configured reserve back: a fixed minimum plus an
+//! allowance per in-flight trade. New orders and inbound trades that would eat into
+//! it are refused, and `Event::LowFeeReserve` is emitted when the spare balance
+//! first drops below the reserve.
+//!
+//! What is committed is the bitcoin of own open orders and of in-flight trades.
+//! An order's part taken by its in-flight trades is counted with the trades, so
+//! it is not counted twice.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::warn;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::config::FeeReserveConfig;
+use crate::orderbook::{OrderId, OrderSide, Orderbook};
+use crate::types::{Asset, Event, TradeId};
+use crate::wallet::{WalletError, WalletInterface};
+
+/// Bitcoin an order or trade commits from the wallet (satoshis)
+///
+/// Selling bitcoin commits the amount, buying an asset with bitcoin commits the
+/// amount times the price. Other pairs commit no bitcoin.
+pub fn btc_commitment(base_asset: &Asset, quote_asset: &Asset, side: OrderSide, amount: Decimal, price: Decimal) -> Result<u64> {
+    let btc = match (base_asset, quote_asset, side) {
+        (Asset::Bitcoin, _, OrderSide::Sell) => amount,
+        (_, Asset::Bitcoin, OrderSide::Buy) => amount * price,
+        _ => return Ok(0),
+    };
+
+    (btc * Decimal::from(100_000_000)).ceil().to_u64()
+        .ok_or_else(|| anyhow::anyhow!("Invalid bitcoin amount: {}", btc))
+}
+
+/// Fee reserve state of the wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeReserveStatus {
+    /// Wallet balance (satoshis)
+    pub balance: u64,
+    /// Bitcoin committed to orders and trades (satoshis)
+    pub committed: u64,
+    /// Reserve to keep back for fees (satoshis)
+    pub required: u64,
+}
+
+impl FeeReserveStatus {
+    /// Balance left after the commitments (satoshis)
+    pub fn spare(&self) -> u64 {
+        self.balance.saturating_sub(self.committed)
+    }
+
+    /// Check if the spare balance covers the reserve
+    pub fn is_covered(&self) -> bool {
+        self.spare() >= self.required
+    }
+}
+
+/// Fee reserve guard
+pub struct FeeReserveGuard {
+    /// Configuration
+    config: FeeReserveConfig,
+    /// Wallet
+    wallet: Arc<dyn WalletInterface>,
+    /// Event sender
+    event_sender: mpsc::Sender<Event>,
+    /// Whether the reserve was last seen uncovered
+    low: Mutex<bool>,
+    /// Orderbook holding the own open orders
+    orderbook: Option<Arc<Orderbook>>,
+    /// Bitcoin committed by in-flight trades, per order they trade on (satoshis)
+    trades: Mutex<HashMap<TradeId, Vec<(OrderId, u64)>>>,
+}
+
+impl FeeReserveGuard {
+    /// Create a guard
+    pub fn new(config: FeeReserveConfig, wallet: Arc<dyn WalletInterface>, event_sender: mpsc::Sender<Event>) -> Self {
+        Self {
+            config,
+            wallet,
+            event_sender,
+            low: Mutex::new(false),
+            orderbook: None,
+            trades: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count the bitcoin of the own open orders in `orderbook` as committed
+    pub fn with_orderbook(mut self, orderbook: Arc<Orderbook>) -> Self {
+        self.orderbook = Some(orderbook);
+        self
+    }
+
+    /// Record the bitcoin an in-flight trade commits, per order it trades on
+    pub async fn hold(&self, trade_id: &TradeId, commitments: Vec<(OrderId, u64)>) {
+        self.trades.lock().await.insert(trade_id.clone(), commitments);
+    }
+
+    /// Forget the commitment of a trade that is over
+    pub async fn release(&self, trade_id: &TradeId) {
+        self.trades.lock().await.remove(trade_id);
+    }
+
+    /// Get the bitcoin committed to own open orders and in-flight trades (satoshis)
+    ///
+    /// The orders in `exclude` are left out, though their in-flight trades are not.
+    pub async fn committed(&self, exclude: &[OrderId]) -> Result<u64> {
+        let mut held_by_order: HashMap<OrderId, u64> = HashMap::new();
+        let mut committed: u64 = 0;
+        for (order_id, amount) in self.trades.lock().await.values().flatten() {
+            *held_by_order.entry(order_id.clone()).or_default() += amount;
+            committed = committed.saturating_add(*amount);
+        }
+
+        if let Some(orderbook) = &self.orderbook {
+            for signed_order in orderbook.own_signed_orders().await {
+                let order = &signed_order.order;
+                if exclude.contains(&order.id) {
+                    continue;
+                }
+
+                let amount = btc_commitment(&order.base_asset, &order.quote_asset, order.side, order.amount, order.price)?;
+                let held = held_by_order.get(&order.id).copied().unwrap_or(0);
+                committed = committed.saturating_add(amount.saturating_sub(held));
+            }
+        }
+
+        Ok(committed)
+    }
+
+    /// Reserve needed for a number of in-flight trades (satoshis)
+    pub fn required(&self, in_flight: usize) -> u64 {
+        self.config.minimum.saturating_add(self.config.per_trade.saturating_mul(in_flight as u64))
+    }
+
+    /// Check that committing `committed` satoshis leaves the reserve for `in_flight` trades
+    ///
+    /// `committed` includes the existing commitments (see `committed`) and the new
+    /// one, and `in_flight` the trade being accepted, if any.
+    pub async fn check(&self, committed: u64, in_flight: usize) -> Result<FeeReserveStatus> {
+        let status = FeeReserveStatus {
+            balance: self.wallet.get_balance().await?,
+            committed,
+            required: self.required(in_flight),
+        };
+
+        if !status.is_covered() {
+            return Err(WalletError::FeeReserve {
+                spare: status.spare(),
+                required: status.required,
+            }
+            .into());
+        }
+
+        Ok(status)
+    }
+
+    /// Record the current reserve state, emitting `LowFeeReserve` when it first drops below the reserve
+    pub async fn observe(&self, committed: u64, in_flight: usize) -> Result<FeeReserveStatus> {
+        let status = FeeReserveStatus {
+            balance: self.wallet.get_balance().await?,
+            committed,
+            required: self.required(in_flight),
+        };
+
+        let mut low = self.low.lock().await;
+        let was_low = *low;
+        *low = !status.is_covered();
+        if *low && !was_low {
+            warn!("Fee reserve low: {} sats spare, {} sats required", status.spare(), status.required);
+            let _ = self.event_sender.send(Event::LowFeeReserve(status.clone())).await;
+        }
+
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_btc_commitment() {
+        let rune = Asset::Rune(1);
+
+        assert_eq!(btc_commitment(&Asset::Bitcoin, &rune, OrderSide::Sell, dec!(0.5), dec!(1000)).unwrap(), 50_000_000);
+        assert_eq!(btc_commitment(&Asset::Bitcoin, &rune, OrderSide::Buy, dec!(0.5), dec!(1000)).unwrap(), 0);
+        assert_eq!(btc_commitment(&rune, &Asset::Bitcoin, OrderSide::Buy, dec!(100), dec!(0.0001)).unwrap(), 1_000_000);
+        assert_eq!(btc_commitment(&rune, &Asset::Bitcoin, OrderSide::Sell, dec!(100), dec!(0.0001)).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_trades_are_committed() {
+        let wallet = Arc::new(crate::wallet::simple_wallet::SimpleWallet::new(None, crate::config::BitcoinNetwork::Testnet).unwrap());
+        let guard = FeeReserveGuard::new(FeeReserveConfig::default(), wallet, mpsc::channel(1).0);
+        let order_id = OrderId("order".to_string());
+
+        guard.hold(&TradeId("a".to_string()), vec![(order_id.clone(), 30_000)]).await;
+        guard.hold(&TradeId("b".to_string()), vec![(order_id.clone(), 20_000)]).await;
+        assert_eq!(guard.committed(&[]).await.unwrap(), 50_000);
+
+        guard.release(&TradeId("a".to_string())).await;
+        assert_eq!(guard.committed(&[order_id]).await.unwrap(), 20_000);
+    }
+
+    #[test]
+    fn test_reserve_coverage() {
+        let status = FeeReserveStatus { balance: 100_000, committed: 80_000, required: 20_000 };
+        assert!(status.is_covered());
+
+        let status = FeeReserveStatus { committed: 80_001, ..status };
+        assert_eq!(status.spare(), 19_999);
+        assert!(!status.is_covered());
+    }
+}
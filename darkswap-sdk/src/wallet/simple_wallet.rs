@@ -3,12 +3,15 @@
 //! This module provides a simple in-memory wallet implementation for testing and development.
 
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use darkswap_support::storage::Storage;
 use bitcoin::consensus::{Encodable, Decodable};
 use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
 use bitcoin::{Address, Network, PrivateKey, PublicKey, Script, Transaction, TxOut, PackedLockTime};
 use log::{debug, info, warn};
 use rand::rngs::OsRng;
@@ -16,7 +19,12 @@ use tokio::sync::Mutex;
 
 use crate::config::BitcoinNetwork;
 use crate::orderbook::OrderId;
+use crate::trade::settlement::{self, PayoutOutput};
 use crate::types::{Asset, TradeId};
+use crate::wallet::address_book::{AddressBook, AddressInfo};
+use crate::wallet::descriptor;
+use crate::wallet::finalize;
+use crate::wallet::utxo::Utxo;
 use crate::wallet::{WalletError, WalletInterface};
 
 /// Simple wallet implementation
@@ -29,6 +37,14 @@ pub struct SimpleWallet {
     network: Network,
     /// Asset balances
     balances: Arc<Mutex<HashMap<String, u64>>>,
+    /// Key receive addresses are derived from
+    receive_key: ExtendedPrivKey,
+    /// Path from the receive key to the parent of the receive addresses
+    receive_path: DerivationPath,
+    /// Issued receive addresses
+    address_book: Arc<Mutex<AddressBook>>,
+    /// Unspent outputs
+    utxos: Arc<Mutex<Vec<Utxo>>>,
 }
 
 impl SimpleWallet {
@@ -61,14 +77,95 @@ impl SimpleWallet {
         balances.insert("RUNE:0x123".to_string(), 1000); // 1000 RUNE:0x123
         balances.insert("ALKANE:0x456".to_string(), 500); // 500 ALKANE:0x456
 
+        // Derive receive addresses from the wallet key
+        let receive_key = ExtendedPrivKey::new_master(bitcoin_network, &private_key.to_bytes())
+            .context("Failed to create receive key")?;
+
         Ok(Self {
             private_key,
             public_key,
             network: bitcoin_network,
             balances: Arc::new(Mutex::new(balances)),
+            receive_key,
+            receive_path: DerivationPath::master(),
+            address_book: Arc::new(Mutex::new(AddressBook::default())),
+            utxos: Arc::new(Mutex::new(Vec::new())),
         })
     }
 
+    /// Create a simple wallet from a private `wpkh(xprv/.../*)` descriptor with its checksum
+    ///
+    /// The wallet issues the addresses the descriptor describes, so Sparrow or
+    /// Bitcoin Core can manage the same wallet.
+    pub fn from_descriptor(descriptor: &str, network: BitcoinNetwork) -> Result<Self> {
+        let bitcoin_network = match network {
+            BitcoinNetwork::Mainnet => Network::Bitcoin,
+            BitcoinNetwork::Testnet => Network::Testnet,
+            BitcoinNetwork::Regtest => Network::Regtest,
+            BitcoinNetwork::Signet => Network::Signet,
+        };
+        let receive_key = descriptor::import(descriptor, bitcoin_network)?;
+
+        let mut wallet = Self::new(Some(&receive_key.to_priv().to_wif()), network)?;
+        wallet.receive_key = receive_key;
+        Ok(wallet)
+    }
+
+    /// Set the number of unused receive addresses that may be issued in a row
+    pub fn with_gap_limit(mut self, gap_limit: u32) -> Self {
+        self.address_book = Arc::new(Mutex::new(AddressBook::new(gap_limit)));
+        self
+    }
+
+    /// Derive receive addresses under `path` (e.g. `m/84'/1'/0'/0`) instead of directly
+    /// under the wallet key
+    pub fn with_derivation_path(mut self, path: &str) -> Result<Self> {
+        self.receive_path = DerivationPath::from_str(path)
+            .with_context(|| format!("Invalid derivation path {}", path))?;
+        Ok(self)
+    }
+
+    /// Keep the issued receive addresses in `storage`, loading those issued before
+    ///
+    /// Call after `with_gap_limit` and `with_derivation_path`. Fails if the stored
+    /// addresses were not derived from this wallet's key and path.
+    pub async fn with_address_store(self, storage: Arc<dyn Storage>) -> Result<Self> {
+        let gap_limit = self.address_book.lock().await.gap_limit();
+        let address_book = AddressBook::open(storage, gap_limit)?;
+
+        if let Some(first) = address_book.list().first() {
+            if first.address != self.derive_address(first.index)?.to_string() {
+                return Err(anyhow::anyhow!(
+                    "Stored address book belongs to another wallet key or derivation path"
+                ));
+            }
+        }
+
+        *self.address_book.lock().await = address_book;
+        Ok(self)
+    }
+
+    /// Add an unspent output, marking the receive address it pays as used
+    pub async fn add_utxo(&self, utxo: Utxo) -> Result<()> {
+        if let Ok(address) = Address::from_script(&utxo.txout.script_pubkey, self.network) {
+            self.address_book.lock().await.mark_used(&address.to_string())?;
+        }
+        self.utxos.lock().await.push(utxo);
+        Ok(())
+    }
+
+    /// Derive the receive address at an index
+    fn derive_address(&self, index: u32) -> Result<Address> {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let child = ChildNumber::from_normal_idx(index).context("Invalid address index")?;
+        let key = self.receive_key
+            .derive_priv(&secp, &self.receive_path.child(child))
+            .context("Failed to derive receive key")?;
+        let public_key = PublicKey::from_private_key(&secp, &key.to_priv());
+
+        Address::p2wpkh(&public_key, self.network).context("Failed to create P2WPKH address")
+    }
+
     /// Get asset key for balances map
     fn get_asset_key(asset: &Asset) -> String {
         match asset {
@@ -79,18 +176,29 @@ impl SimpleWallet {
     }
 
     /// Create a dummy PSBT
-    fn create_dummy_psbt(&self) -> Result<String> {
+    fn create_dummy_psbt(&self, payout: Option<&PayoutOutput>) -> Result<String> {
+        let mut output = vec![
+            TxOut {
+                value: 50_000_000, // 0.5 BTC
+                script_pubkey: Script::new(),
+            },
+        ];
+
+        // Pay the counterparty's proceeds to the address it asked for
+        if let Some(payout) = payout {
+            let address = settlement::parse_payout_address(&payout.address, self.network)?;
+            output.push(TxOut {
+                value: payout.amount,
+                script_pubkey: address.script_pubkey(),
+            });
+        }
+
         // Create a dummy transaction
         let tx = Transaction {
             version: 2,
             lock_time: PackedLockTime::ZERO,
             input: vec![],
-            output: vec![
-                TxOut {
-                    value: 50_000_000, // 0.5 BTC
-                    script_pubkey: Script::new(),
-                },
-            ],
+            output,
         };
 
         // Create a PSBT from the transaction
@@ -116,6 +224,31 @@ impl WalletInterface for SimpleWallet {
         Ok(address.to_string())
     }
 
+    /// Derive a new receive address with an optional label
+    async fn get_new_address(&self, label: Option<String>) -> Result<AddressInfo> {
+        let mut address_book = self.address_book.lock().await;
+        address_book.check_gap()?;
+        
+        let address = self.derive_address(address_book.next_index())?;
+        
+        Ok(address_book.issue(address.to_string(), label)?)
+    }
+
+    /// List the issued receive addresses
+    async fn list_addresses(&self) -> Result<Vec<AddressInfo>> {
+        Ok(self.address_book.lock().await.list())
+    }
+
+    /// Mark a receive address as having received funds
+    async fn mark_address_used(&self, address: &str) -> Result<bool> {
+        Ok(self.address_book.lock().await.mark_used(address)?)
+    }
+
+    /// List unspent outputs
+    async fn list_utxos(&self) -> Result<Vec<Utxo>> {
+        Ok(self.utxos.lock().await.clone())
+    }
+
     /// Get wallet balance
     async fn get_balance(&self) -> Result<u64> {
         let balances = self.balances.lock().await;
@@ -148,7 +281,7 @@ impl WalletInterface for SimpleWallet {
         }
         
         // Create a dummy PSBT
-        self.create_dummy_psbt()
+        self.create_dummy_psbt(None)
     }
 
     /// Create and sign a PSBT for a trade
@@ -160,6 +293,7 @@ impl WalletInterface for SimpleWallet {
         quote_asset: &Asset,
         amount: u64,
         price: u64,
+        payout: Option<&PayoutOutput>,
     ) -> Result<String> {
         // Check if we have enough balance
         let balances = self.balances.lock().await;
@@ -174,7 +308,7 @@ impl WalletInterface for SimpleWallet {
         }
         
         // Create a dummy PSBT
-        self.create_dummy_psbt()
+        self.create_dummy_psbt(payout)
     }
 
     /// Sign a PSBT
@@ -185,10 +319,19 @@ impl WalletInterface for SimpleWallet {
     }
 
     /// Finalize and broadcast a PSBT
-    async fn finalize_and_broadcast_psbt(&self, _psbt_base64: &str) -> Result<String> {
-        // In a real implementation, we would finalize and broadcast the PSBT
-        // For now, just return a dummy txid
-        Ok("0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef".to_string())
+    async fn finalize_and_broadcast_psbt(&self, psbt_base64: &str) -> Result<String> {
+        let bytes = base64::decode(psbt_base64.trim())
+            .map_err(|e| WalletError::InvalidPsbt(e.to_string()))?;
+        let mut psbt = Psbt::consensus_decode(&mut &bytes[..])
+            .map_err(|e| WalletError::InvalidPsbt(e.to_string()))?;
+
+        // Multisig and timelocked inputs are finalized from their descriptors
+        finalize::finalize_psbt(&mut psbt)?;
+        let tx = psbt.extract_tx();
+
+        // In a real implementation, we would broadcast the transaction
+        debug!("Finalized transaction {}", tx.txid());
+        Ok(tx.txid().to_string())
     }
 
     /// Verify a PSBT
@@ -197,4 +340,9 @@ impl WalletInterface for SimpleWallet {
         // For now, just return true
         Ok(true)
     }
+
+    /// Get the output descriptor of the receive addresses
+    async fn export_descriptor(&self, include_private: bool) -> Result<String> {
+        descriptor::export(&self.receive_key, include_private)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,62 @@
+//! Wallet UTXOs
+//!
+//! Unspent outputs as the wallet sees them, including the runes and alkanes they
+//! carry. An output holding a rune or alkane balance must only be spent by a
+//! transaction that moves that balance on purpose.
+
+use bitcoin::{OutPoint, TxOut};
+use serde::{Deserialize, Serialize};
+
+use crate::types::Asset;
+
+/// Unspent output owned by the wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Utxo {
+    /// Outpoint
+    pub outpoint: OutPoint,
+    /// Output
+    pub txout: TxOut,
+    /// Runes and alkanes held by the output, with amounts
+    pub assets: Vec<(Asset, u128)>,
+    /// Number of confirmations
+    pub confirmations: u32,
+}
+
+impl Utxo {
+    /// Create a plain bitcoin UTXO
+    pub fn new(outpoint: OutPoint, txout: TxOut, confirmations: u32) -> Self {
+        Self {
+            outpoint,
+            txout,
+            assets: Vec::new(),
+            confirmations,
+        }
+    }
+
+    /// Add a rune or alkane balance held by the output
+    pub fn with_asset(mut self, asset: Asset, amount: u128) -> Self {
+        self.assets.push((asset, amount));
+        self
+    }
+
+    /// Get the value in satoshis
+    pub fn value(&self) -> u64 {
+        self.txout.value
+    }
+
+    /// Check if the output holds any rune or alkane balance
+    pub fn carries_assets(&self) -> bool {
+        self.assets.iter().any(|(asset, amount)| *asset != Asset::Bitcoin && *amount > 0)
+    }
+
+    /// Get the amount of an asset held by the output
+    pub fn asset_amount(&self, asset: &Asset) -> u128 {
+        match asset {
+            Asset::Bitcoin => self.txout.value as u128,
+            _ => self.assets.iter()
+                .filter(|(held, _)| held == asset)
+                .map(|(_, amount)| *amount)
+                .sum(),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Watch-only chain wallet
+//!
+//! A remote signer or custody wallet keeps its keys elsewhere, but the node
+//! still needs the wallet's outputs, balance and a way to broadcast. This wallet
+//! watches the addresses of a public `wpkh(xpub/.../*)` descriptor, as exported
+//! by the signer or by Sparrow, up to the gap limit, and reads their outputs
+//! from an Esplora server, reconnecting and retrying through a
+//! [`ResilientChain`]. It holds no key: it cannot create or sign PSBTs.
+
+use std::str::FromStr;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bitcoin::consensus::Decodable;
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{Address, Network, OutPoint, TxOut};
+use miniscript::descriptor::DescriptorPublicKey;
+use miniscript::Descriptor;
+
+use crate::chain::{ChainBackendError, ResilientChain};
+use crate::esplora::EsploraChain;
+use crate::orderbook::OrderId;
+use crate::trade::refund::RefundChain;
+use crate::trade::settlement::PayoutOutput;
+use crate::types::{Asset, TradeId};
+use crate::wallet::address_book::AddressInfo;
+use crate::wallet::utxo::Utxo;
+use crate::wallet::{finalize, WalletError, WalletInterface};
+
+/// Fee rate used when the server has no estimate for the target (sat/vB)
+const FALLBACK_FEE_RATE: f32 = 1.0;
+
+/// Wallet watching the addresses of a public descriptor
+pub struct WatchOnlyWallet {
+    /// Watched descriptor
+    descriptor: Descriptor<DescriptorPublicKey>,
+    /// Watched addresses, by derivation index
+    addresses: Vec<Address>,
+    /// Chain backend
+    chain: ResilientChain<EsploraChain>,
+}
+
+impl WatchOnlyWallet {
+    /// Create a wallet watching the first `gap_limit` addresses of `descriptor`
+    ///
+    /// The descriptor must be a public `wpkh()` one ending in an unhardened `/*`;
+    /// a private descriptor is refused, the keys stay with the signer.
+    pub fn new(descriptor: &str, gap_limit: u32, network: Network, chain: ResilientChain<EsploraChain>) -> Result<Self> {
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(descriptor.trim())
+            .map_err(|e| WalletError::InvalidDescriptor(e.to_string()))?;
+        if !matches!(descriptor, Descriptor::Wpkh(_)) {
+            return Err(WalletError::InvalidDescriptor("Only wpkh() descriptors can be watched".to_string()).into());
+        }
+        if !descriptor.has_wildcard() {
+            return Err(WalletError::InvalidDescriptor("Descriptor must end in an unhardened /*".to_string()).into());
+        }
+
+        let addresses = (0..gap_limit.max(1))
+            .map(|index| {
+                descriptor.at_derivation_index(index)
+                    .address(network)
+                    .map_err(|e| WalletError::InvalidDescriptor(e.to_string()).into())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            descriptor,
+            addresses,
+            chain,
+        })
+    }
+
+    /// Refuse an operation that needs a key
+    fn no_keys<T>(&self) -> Result<T> {
+        Err(WalletError::Other("Watch-only wallet holds no keys".to_string()).into())
+    }
+
+    /// Decode a base64 PSBT
+    fn decode(psbt_base64: &str) -> Result<Psbt> {
+        let bytes = base64::decode(psbt_base64.trim())
+            .map_err(|e| WalletError::InvalidPsbt(e.to_string()))?;
+        Ok(Psbt::consensus_decode(&mut &bytes[..])
+            .map_err(|e| WalletError::InvalidPsbt(e.to_string()))?)
+    }
+
+    /// Get the output an outpoint refers to, from the chain
+    async fn output(&self, outpoint: &OutPoint) -> Result<Option<TxOut>> {
+        let txid = outpoint.txid;
+        let tx = match self.chain.call(move |chain| Box::pin(async move { chain.transaction(&txid).await })).await {
+            Ok(tx) => tx,
+            Err(e) if matches!(e.downcast_ref::<ChainBackendError>(), Some(ChainBackendError::Rejected(_))) => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(tx.output.get(outpoint.vout as usize).cloned())
+    }
+}
+
+#[async_trait]
+impl WalletInterface for WatchOnlyWallet {
+    /// Get the first watched address
+    async fn get_address(&self) -> Result<String> {
+        Ok(self.addresses[0].to_string())
+    }
+
+    /// Addresses are issued by the wallet holding the keys
+    async fn get_new_address(&self, _label: Option<String>) -> Result<AddressInfo> {
+        self.no_keys()
+    }
+
+    /// List the watched addresses
+    async fn list_addresses(&self) -> Result<Vec<AddressInfo>> {
+        Ok(self.addresses
+            .iter()
+            .enumerate()
+            .map(|(index, address)| AddressInfo {
+                address: address.to_string(),
+                index: index as u32,
+                label: None,
+                used: false,
+                created_at: 0,
+            })
+            .collect())
+    }
+
+    /// List the unspent outputs of the watched addresses
+    async fn list_utxos(&self) -> Result<Vec<Utxo>> {
+        let tip = self.chain.tip_height().await?;
+        let mut utxos = Vec::new();
+
+        for address in &self.addresses {
+            let outputs = self.chain
+                .call(|chain| {
+                    let address = address.clone();
+                    Box::pin(async move { chain.address_utxos(&address).await })
+                })
+                .await?;
+            for output in outputs {
+                let confirmations = match output.status.block_height {
+                    Some(height) if output.status.confirmed => tip.saturating_sub(height) + 1,
+                    _ => 0,
+                };
+                let txout = TxOut {
+                    value: output.value,
+                    script_pubkey: address.script_pubkey(),
+                };
+                utxos.push(Utxo::new(OutPoint::new(output.txid, output.vout), txout, confirmations));
+            }
+        }
+
+        Ok(utxos)
+    }
+
+    /// Get the bitcoin balance of the watched addresses
+    async fn get_balance(&self) -> Result<u64> {
+        Ok(self.list_utxos().await?.iter().map(|utxo| utxo.txout.value).sum())
+    }
+
+    /// Get asset balance; Esplora does not index runes or alkanes
+    async fn get_asset_balance(&self, asset: &Asset) -> Result<u64> {
+        match asset {
+            Asset::Bitcoin => self.get_balance().await,
+            _ => Err(WalletError::UnsupportedAsset(format!("{:?} is not indexed by the chain backend", asset)).into()),
+        }
+    }
+
+    async fn create_order_psbt(
+        &self,
+        _order_id: &OrderId,
+        _base_asset: &Asset,
+        _quote_asset: &Asset,
+        _amount: u64,
+        _price: u64,
+    ) -> Result<String> {
+        self.no_keys()
+    }
+
+    async fn create_trade_psbt(
+        &self,
+        _trade_id: &TradeId,
+        _order_id: &OrderId,
+        _base_asset: &Asset,
+        _quote_asset: &Asset,
+        _amount: u64,
+        _price: u64,
+        _payout: Option<&PayoutOutput>,
+    ) -> Result<String> {
+        self.no_keys()
+    }
+
+    async fn sign_psbt(&self, _psbt_base64: &str) -> Result<String> {
+        self.no_keys()
+    }
+
+    /// Finalize a signed PSBT and broadcast its transaction
+    async fn finalize_and_broadcast_psbt(&self, psbt_base64: &str) -> Result<String> {
+        let mut psbt = Self::decode(psbt_base64)?;
+
+        // Multisig and timelocked inputs are finalized from their descriptors
+        finalize::finalize_psbt(&mut psbt)?;
+        let txid = self.chain.broadcast(&psbt.extract_tx()).await?;
+
+        Ok(txid.to_string())
+    }
+
+    /// Verify that every input of a PSBT spends an unspent output it describes truthfully
+    async fn verify_psbt(&self, psbt_base64: &str) -> Result<bool> {
+        let psbt = Self::decode(psbt_base64)?;
+
+        for (txin, input) in psbt.unsigned_tx.input.iter().zip(&psbt.inputs) {
+            let outpoint = txin.previous_output;
+            let claimed = match (&input.witness_utxo, &input.non_witness_utxo) {
+                (Some(txout), _) => txout.clone(),
+                (None, Some(tx)) if tx.txid() == outpoint.txid => match tx.output.get(outpoint.vout as usize) {
+                    Some(txout) => txout.clone(),
+                    None => return Ok(false),
+                },
+                _ => return Ok(false),
+            };
+
+            if self.output(&outpoint).await? != Some(claimed) || self.chain.is_spent(&outpoint).await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Estimate the fee rate from the server's estimates
+    async fn estimate_fee_rate(&self, target_blocks: u16) -> Result<f32> {
+        let estimates = self.chain.call(|chain| Box::pin(async move { chain.fee_estimates().await })).await?;
+
+        // The closest target no later than the one asked for
+        Ok(estimates
+            .iter()
+            .filter(|(target, _)| **target <= target_blocks.max(1))
+            .max_by_key(|(target, _)| **target)
+            .map(|(_, rate)| *rate)
+            .unwrap_or(FALLBACK_FEE_RATE))
+    }
+
+    /// Get the watched descriptor; it never holds private keys
+    async fn export_descriptor(&self, include_private: bool) -> Result<String> {
+        if include_private {
+            return self.no_keys();
+        }
+
+        Ok(self.descriptor.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChainBackendConfig, DnsConfig};
+    use crate::esplora::EsploraConnector;
+    use crate::wallet::descriptor;
+    use bitcoin::util::bip32::ExtendedPrivKey;
+    use std::sync::Arc;
+
+    fn chain() -> ResilientChain<EsploraChain> {
+        let connector = Arc::new(EsploraConnector::new("http://127.0.0.1:3002", &DnsConfig::default()));
+        ResilientChain::new(connector, ChainBackendConfig::default())
+    }
+
+    #[tokio::test]
+    async fn test_watches_the_descriptor_addresses() {
+        let key = ExtendedPrivKey::new_master(Network::Testnet, &[7u8; 32]).unwrap();
+        let public = descriptor::export(&key, false).unwrap();
+        let wallet = WatchOnlyWallet::new(&public, 3, Network::Testnet, chain()).unwrap();
+
+        let addresses = wallet.list_addresses().await.unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert!(addresses[0].address.starts_with("tb1q"));
+        assert_eq!(wallet.export_descriptor(false).await.unwrap(), public);
+
+        // Nothing that needs a key is possible
+        assert!(wallet.sign_psbt("").await.is_err());
+        assert!(wallet.export_descriptor(true).await.is_err());
+
+        // A private descriptor is refused
+        let private = descriptor::export(&key, true).unwrap();
+        assert!(WatchOnlyWallet::new(&private, 3, Network::Testnet, chain()).is_err());
+    }
+}
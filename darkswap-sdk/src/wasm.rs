@@ -4,7 +4,7 @@
 //! in web applications.
 
 #[cfg(feature = "wasm")]
-mod wasm_impl {
+pub(crate) mod wasm_impl {
     use std::str::FromStr;
     use std::sync::Arc;
 
@@ -18,7 +18,11 @@ mod wasm_impl {
     use web_sys::{console, window};
 
     use crate::config::{BitcoinNetwork, Config};
-    use crate::orderbook::{Order, OrderId, OrderSide, OrderStatus};
+    use crate::p2p::ice::TurnServer;
+    use crate::orderbook::{Order, OrderId, OrderSide, OrderStatus, OrderbookSnapshot};
+    use crate::orderbook::view::MarketSummary;
+    use crate::release::ReleaseManifest;
+    use crate::rules::SignedMarketRules;
     use crate::trade::{Trade, TradeId};
     use crate::types::{Asset, AlkaneId, Event, SerializablePeerId};
     use crate::DarkSwap;
@@ -81,10 +85,20 @@ mod wasm_impl {
         pub derivation_path: Option<String>,
         /// Enable WebRTC
         pub enable_webrtc: bool,
-        /// WebRTC ICE servers
+        /// WebRTC STUN server URLs
         pub ice_servers: Vec<String>,
+        /// WebRTC TURN server URLs, sharing the TURN credentials
+        pub turn_servers: Vec<String>,
+        /// TURN username
+        pub turn_username: Option<String>,
+        /// TURN credential
+        pub turn_credential: Option<String>,
+        /// ICE transport policy, `all` or `relay`; `all` if unset
+        pub ice_transport_policy: Option<String>,
         /// Signaling server URL
         pub signaling_server_url: Option<String>,
+        /// Asset metadata overrides (JSON object keyed by asset ID, as the `assets` configuration section)
+        pub asset_overrides: Option<String>,
     }
 
     /// DarkSwap SDK for JavaScript
@@ -148,7 +162,7 @@ mod wasm_impl {
     }
 
     /// Convert JsConfig to Config
-    fn js_config_to_config(js_config: &JsConfig) -> Config {
+    fn js_config_to_config(js_config: &JsConfig) -> Result<Config> {
         let mut config = Config::default();
         
         // Set Bitcoin network
@@ -163,9 +177,25 @@ mod wasm_impl {
         // Set P2P configuration
         config.p2p.enable_webrtc = js_config.enable_webrtc;
         config.p2p.ice_servers = js_config.ice_servers.clone();
+        if !js_config.turn_servers.is_empty() {
+            config.p2p.turn_servers = vec![TurnServer {
+                urls: js_config.turn_servers.clone(),
+                username: js_config.turn_username.clone().context("TURN servers need a username")?,
+                credential: js_config.turn_credential.clone().context("TURN servers need a credential")?,
+            }];
+        }
+        if let Some(policy) = &js_config.ice_transport_policy {
+            config.p2p.ice_transport_policy = policy.parse()?;
+        }
         config.p2p.signaling_server_url = js_config.signaling_server_url.clone();
         
-        config
+        // Set asset metadata overrides
+        if let Some(asset_overrides) = &js_config.asset_overrides {
+            config.assets = serde_json::from_str(asset_overrides).context("Invalid asset overrides")?;
+            crate::asset_info::validate_overrides(&config.assets)?;
+        }
+        
+        Ok(config)
     }
 
     /// Convert Order to JsValue
@@ -186,6 +216,23 @@ mod wasm_impl {
         Ok(obj.into())
     }
 
+    /// Convert MarketSummary to JsValue
+    fn market_to_js_value(market: &MarketSummary) -> Result<JsValue> {
+        let obj = Object::new();
+        let price = |price: Option<Decimal>| price.map_or(JsValue::NULL, |price| JsValue::from_str(&price.to_string()));
+        
+        Reflect::set(&obj, &JsValue::from_str("baseAsset"), &JsValue::from_str(&market.base_asset.to_string()))?;
+        Reflect::set(&obj, &JsValue::from_str("quoteAsset"), &JsValue::from_str(&market.quote_asset.to_string()))?;
+        Reflect::set(&obj, &JsValue::from_str("bidCount"), &JsValue::from_f64(market.bid_count as f64))?;
+        Reflect::set(&obj, &JsValue::from_str("askCount"), &JsValue::from_f64(market.ask_count as f64))?;
+        Reflect::set(&obj, &JsValue::from_str("bidDepth"), &JsValue::from_str(&market.bid_depth.to_string()))?;
+        Reflect::set(&obj, &JsValue::from_str("askDepth"), &JsValue::from_str(&market.ask_depth.to_string()))?;
+        Reflect::set(&obj, &JsValue::from_str("bestBid"), &price(market.best_bid))?;
+        Reflect::set(&obj, &JsValue::from_str("bestAsk"), &price(market.best_ask))?;
+        
+        Ok(obj.into())
+    }
+
     /// Convert Trade to JsValue
     fn trade_to_js_value(trade: &Trade) -> Result<JsValue> {
         let obj = Object::new();
@@ -244,6 +291,10 @@ mod wasm_impl {
                 Reflect::set(&obj, &JsValue::from_str("type"), &JsValue::from_str("orderExpired"))?;
                 Reflect::set(&obj, &JsValue::from_str("orderId"), &JsValue::from_str(&order_id.0))?;
             }
+            Event::TradeProposalReceived(trade_id) => {
+                Reflect::set(&obj, &JsValue::from_str("type"), &JsValue::from_str("tradeProposalReceived"))?;
+                Reflect::set(&obj, &JsValue::from_str("tradeId"), &JsValue::from_str(&trade_id.0))?;
+            }
             Event::TradeStarted(trade) => {
                 Reflect::set(&obj, &JsValue::from_str("type"), &JsValue::from_str("tradeStarted"))?;
                 Reflect::set(&obj, &JsValue::from_str("trade"), &trade_to_js_value(trade)?)?;
@@ -264,6 +315,10 @@ mod wasm_impl {
                 Reflect::set(&obj, &JsValue::from_str("type"), &JsValue::from_str("peerDisconnected"))?;
                 Reflect::set(&obj, &JsValue::from_str("peerId"), &JsValue::from_str(&peer_id.to_string()))?;
             }
+            Event::LaggedEvents(count) => {
+                Reflect::set(&obj, &JsValue::from_str("type"), &JsValue::from_str("laggedEvents"))?;
+                Reflect::set(&obj, &JsValue::from_str("count"), &JsValue::from_f64(*count as f64))?;
+            }
             Event::Error(error) => {
                 Reflect::set(&obj, &JsValue::from_str("type"), &JsValue::from_str("error"))?;
                 Reflect::set(&obj, &JsValue::from_str("error"), &JsValue::from_str(error))?;
@@ -282,7 +337,8 @@ mod wasm_impl {
             console_error_panic_hook::set_once();
             
             // Convert JsConfig to Config
-            let config = js_config_to_config(&js_config);
+            let config = js_config_to_config(&js_config)
+                .map_err(|e| JsValue::from_str(&format!("Invalid configuration: {}", e)))?;
             
             // Create DarkSwap instance
             let darkswap = match DarkSwap::new(config) {
@@ -326,6 +382,21 @@ mod wasm_impl {
             })
         }
 
+        /// Switch to another Bitcoin network, e.g. for a testnet toggle
+        #[wasm_bindgen]
+        pub fn switch_network(&self, network: JsBitcoinNetwork) -> Promise {
+            let darkswap = self.darkswap.clone();
+            
+            future_to_promise(async move {
+                let mut darkswap = darkswap.lock().await;
+                
+                match darkswap.switch_network(js_bitcoin_network_to_bitcoin_network(network)).await {
+                    Ok(_) => Ok(JsValue::from_bool(true)),
+                    Err(e) => Err(JsValue::from_str(&format!("Failed to switch network: {}", e))),
+                }
+            })
+        }
+
         /// Set event callback
         #[wasm_bindgen]
         pub fn set_event_callback(&mut self, callback: Function) -> Promise {
@@ -410,6 +481,29 @@ mod wasm_impl {
             })
         }
 
+        /// Get the name, symbol, decimals and icon URL of an asset, as JSON
+        #[wasm_bindgen]
+        pub fn get_asset_info(&self, asset_type: JsAssetType, id: String) -> Promise {
+            let darkswap = self.darkswap.clone();
+            
+            future_to_promise(async move {
+                let darkswap = darkswap.lock().await;
+                
+                // Convert JsAssetType to Asset
+                let asset = match js_asset_type_to_asset(asset_type, &id) {
+                    Ok(asset) => asset,
+                    Err(e) => return Err(JsValue::from_str(&format!("Invalid asset: {}", e))),
+                };
+                
+                match darkswap.get_asset_info(&asset).await {
+                    Ok(info) => serde_json::to_string(&info)
+                        .map(|json| JsValue::from_str(&json))
+                        .map_err(|e| JsValue::from_str(&e.to_string())),
+                    Err(e) => Err(JsValue::from_str(&format!("Failed to get asset info: {}", e))),
+                }
+            })
+        }
+
         /// Create an order
         #[wasm_bindgen]
         pub fn create_order(
@@ -554,6 +648,36 @@ mod wasm_impl {
             })
         }
 
+        /// List the pairs with live orders on the network
+        #[wasm_bindgen]
+        pub fn list_markets(&self) -> Promise {
+            let darkswap = self.darkswap.clone();
+            
+            future_to_promise(async move {
+                let darkswap = darkswap.lock().await;
+                
+                match darkswap.list_markets().await {
+                    Ok(markets) => {
+                        let js_markets = Array::new();
+                        
+                        for market in &markets {
+                            match market_to_js_value(market) {
+                                Ok(js_market) => {
+                                    js_markets.push(&js_market);
+                                }
+                                Err(e) => {
+                                    return Err(JsValue::from_str(&format!("Failed to convert market to JS value: {}", e)));
+                                }
+                            }
+                        }
+                        
+                        Ok(js_markets.into())
+                    }
+                    Err(e) => Err(JsValue::from_str(&format!("Failed to list markets: {}", e))),
+                }
+            })
+        }
+
         /// Take an order
         #[wasm_bindgen]
         pub fn take_order(&self, order_id: String, amount: String) -> Promise {
@@ -582,5 +706,104 @@ mod wasm_impl {
                 }
             })
         }
+
+        /// Load a signed orderbook snapshot served by a relay
+        #[wasm_bindgen]
+        pub fn load_orderbook_snapshot(&self, snapshot_json: String) -> Promise {
+            let darkswap = self.darkswap.clone();
+            
+            future_to_promise(async move {
+                let darkswap = darkswap.lock().await;
+                
+                // Parse snapshot
+                let snapshot = match OrderbookSnapshot::from_json(&snapshot_json) {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => return Err(JsValue::from_str(&format!("Invalid snapshot: {}", e))),
+                };
+                
+                match darkswap.load_orderbook_snapshot(&snapshot).await {
+                    Ok(loaded) => Ok(JsValue::from_f64(loaded as f64)),
+                    Err(e) => Err(JsValue::from_str(&format!("Failed to load snapshot: {}", e))),
+                }
+            })
+        }
+
+        /// Ask full peers to forward only the orders of some pairs and makers
+        ///
+        /// `pairs_json` is a JSON array of `[base, quote]` assets, as they appear
+        /// in orders. Resolves to the number of peers the filter was sent to.
+        #[wasm_bindgen]
+        pub fn set_order_interest(&self, peers: Array, pairs_json: String, makers: Array) -> Promise {
+            let darkswap = self.darkswap.clone();
+            
+            future_to_promise(async move {
+                let darkswap = darkswap.lock().await;
+                
+                // Parse peers, pairs and makers
+                let mut peer_ids = Vec::new();
+                for peer in peers.iter() {
+                    let peer = peer.as_string().ok_or_else(|| JsValue::from_str("Peer IDs must be strings"))?;
+                    match libp2p::PeerId::from_str(&peer) {
+                        Ok(peer_id) => peer_ids.push(peer_id),
+                        Err(e) => return Err(JsValue::from_str(&format!("Invalid peer ID {}: {}", peer, e))),
+                    }
+                }
+                let pairs: Vec<(Asset, Asset)> = match serde_json::from_str(&pairs_json) {
+                    Ok(pairs) => pairs,
+                    Err(e) => return Err(JsValue::from_str(&format!("Invalid pairs: {}", e))),
+                };
+                let makers = makers.iter()
+                    .map(|maker| maker.as_string().ok_or_else(|| JsValue::from_str("Makers must be strings")))
+                    .collect::<Result<Vec<_>, _>>()?;
+                
+                match darkswap.set_order_interest(&peer_ids, &pairs, &makers).await {
+                    Ok(reached) => Ok(JsValue::from_f64(reached as f64)),
+                    Err(e) => Err(JsValue::from_str(&format!("Failed to set order interest: {}", e))),
+                }
+            })
+        }
+    }
+
+    /// Check that a fetched artifact (e.g. `darkswap_sdk_bg.wasm`) is the one
+    /// published in a release manifest signed by a trusted key
+    ///
+    /// Returns the release version. Call it before instantiating the module.
+    #[wasm_bindgen(js_name = verifyRelease)]
+    pub fn verify_release(
+        artifact_name: String,
+        artifact: &[u8],
+        manifest_json: String,
+        trusted_keys: Array,
+    ) -> Result<String, JsValue> {
+        let manifest = ReleaseManifest::from_json(&manifest_json)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        
+        let trusted_keys: Vec<String> = trusted_keys.iter()
+            .filter_map(|key| key.as_string())
+            .collect();
+        
+        manifest.verify_artifact(&artifact_name, artifact, &trusted_keys)
+            .map_err(|e| JsValue::from_str(&format!("Release verification failed: {}", e)))?;
+        
+        Ok(manifest.version)
+    }
+
+    /// Verify market rules fetched from a daemon's `/market/rules` against
+    /// trusted operator keys
+    ///
+    /// Returns the rules as JSON; front-ends should check `expires_at` too.
+    #[wasm_bindgen(js_name = verifyMarketRules)]
+    pub fn verify_market_rules(rules_json: String, trusted_keys: Array) -> Result<String, JsValue> {
+        let signed_rules = SignedMarketRules::from_json(&rules_json)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        
+        let trusted_keys: Vec<String> = trusted_keys.iter()
+            .filter_map(|key| key.as_string())
+            .collect();
+        
+        signed_rules.verify(&trusted_keys)
+            .map_err(|e| JsValue::from_str(&format!("Market rules verification failed: {}", e)))?;
+        
+        serde_json::to_string(&signed_rules.rules).map_err(|e| JsValue::from_str(&e.to_string()))
     }
 }
\ No newline at end of file
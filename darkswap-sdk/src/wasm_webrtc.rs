@@ -4,7 +4,9 @@
 
 #![cfg(all(feature = "wasm", feature = "webrtc"))]
 
+use crate::config::P2PConfig;
 use crate::error::{Error, Result};
+use crate::p2p::ice::{IceConfiguration, IceTransportPolicy};
 use crate::types::PeerId;
 use crate::webrtc_signaling::{SessionDescription, SessionDescriptionType, IceCandidate};
 use crate::webrtc_data_channel::{WebRtcDataChannel, ChannelState};
@@ -23,6 +25,44 @@ fn result_to_js_value<T: Into<JsValue>>(result: Result<T>) -> Result<JsValue, Js
     result.map(|value| value.into()).map_err(error_to_js_value)
 }
 
+/// Build the RTCPeerConnection configuration of an ICE configuration, given as JSON
+///
+/// Without one, candidates are gathered from the default STUN servers of the SDK.
+fn rtc_configuration(ice_config: Option<String>) -> Result<web_sys::RtcConfiguration, JsValue> {
+    let ice = match ice_config {
+        Some(json) => serde_json::from_str::<IceConfiguration>(&json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid ICE configuration: {}", e)))?,
+        None => IceConfiguration::from_config(&P2PConfig::default())
+            .map_err(|e| JsValue::from_str(&e.to_string()))?,
+    };
+
+    let ice_servers = js_sys::Array::new();
+    for server in &ice.servers {
+        let ice_server = web_sys::RtcIceServer::new();
+        let urls = js_sys::Array::new();
+        for url in &server.urls {
+            urls.push(&JsValue::from_str(url));
+        }
+        ice_server.urls(&urls);
+        if let Some(username) = &server.username {
+            ice_server.username(username);
+        }
+        if let Some(credential) = &server.credential {
+            ice_server.credential(credential);
+        }
+        ice_servers.push(&ice_server);
+    }
+
+    let rtc_config = web_sys::RtcConfiguration::new();
+    rtc_config.ice_servers(&ice_servers);
+    rtc_config.ice_transport_policy(match ice.transport_policy {
+        IceTransportPolicy::All => web_sys::RtcIceTransportPolicy::All,
+        IceTransportPolicy::Relay => web_sys::RtcIceTransportPolicy::Relay,
+    });
+
+    Ok(rtc_config)
+}
+
 /// Session description type for JavaScript
 #[wasm_bindgen]
 pub enum JsSessionDescriptionType {
@@ -212,17 +252,11 @@ pub struct WebRtcConnection {
 
 #[wasm_bindgen]
 impl WebRtcConnection {
-    /// Create a new WebRTC connection
+    /// Create a new WebRTC connection, with an optional ICE configuration as JSON
     #[wasm_bindgen(constructor)]
-    pub fn new(local_peer_id: String, remote_peer_id: String) -> Result<WebRtcConnection, JsValue> {
+    pub fn new(local_peer_id: String, remote_peer_id: String, ice_config: Option<String>) -> Result<WebRtcConnection, JsValue> {
         // Create RTCPeerConnection
-        let rtc_config = web_sys::RtcConfiguration::new();
-        let ice_server = web_sys::RtcIceServer::new();
-        ice_server.urls(&JsValue::from_str("stun:stun.l.google.com:19302"));
-        let ice_servers = js_sys::Array::new();
-        ice_servers.push(&ice_server);
-        rtc_config.ice_servers(&ice_servers);
-        
+        let rtc_config = rtc_configuration(ice_config)?;
         let peer_connection = RtcPeerConnection::new_with_configuration(&rtc_config)?;
         
         Ok(WebRtcConnection {
@@ -239,17 +273,11 @@ impl WebRtcConnection {
         })
     }
     
-    /// Create a new WebRTC connection with relay
+    /// Create a new WebRTC connection with relay, with an optional ICE configuration as JSON
     #[wasm_bindgen]
-    pub fn new_with_relay(local_peer_id: String, remote_peer_id: String, relay_peer_id: String) -> Result<WebRtcConnection, JsValue> {
+    pub fn new_with_relay(local_peer_id: String, remote_peer_id: String, relay_peer_id: String, ice_config: Option<String>) -> Result<WebRtcConnection, JsValue> {
         // Create RTCPeerConnection
-        let rtc_config = web_sys::RtcConfiguration::new();
-        let ice_server = web_sys::RtcIceServer::new();
-        ice_server.urls(&JsValue::from_str("stun:stun.l.google.com:19302"));
-        let ice_servers = js_sys::Array::new();
-        ice_servers.push(&ice_server);
-        rtc_config.ice_servers(&ice_servers);
-        
+        let rtc_config = rtc_configuration(ice_config)?;
         let peer_connection = RtcPeerConnection::new_with_configuration(&rtc_config)?;
         
         Ok(WebRtcConnection {
@@ -0,0 +1,809 @@
+//! Web Worker support for the WebAssembly bindings
+//!
+//! Running the swarm and event loop on the browser main thread blocks rendering
+//! whenever the SDK is busy. [`JsDarkSwapWorker`] exposes the same methods as
+//! `JsDarkSwap`, but forwards every call over `postMessage` to a DarkSwap instance
+//! living in a dedicated or shared worker, and resolves the returned promises with
+//! the worker's replies. Events are forwarded to the callback registered with
+//! `set_event_callback`.
+//!
+//! The worker script imports the package and calls [`worker_main`] or
+//! [`shared_worker_main`]; `js/darkswap-worker.js` does this for both kinds. A shared
+//! worker runs one DarkSwap instance for every tab of the origin: the first tab's
+//! configuration is used, and events are sent to all connected tabs.
+//!
+//! Messages are plain objects, so they survive the structured clone:
+//!
+//! - request: `{ id, method, args }`
+//! - response: `{ kind: "response", id, ok, value | error }`
+//! - event: `{ kind: "event", event }`
+//! - ready: `{ kind: "ready" }`, sent once the worker is listening
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use js_sys::{Array, Function, Object, Promise, Reflect};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    console, DedicatedWorkerGlobalScope, MessageEvent, MessagePort, SharedWorker, SharedWorkerGlobalScope, Worker,
+    WorkerOptions, WorkerType,
+};
+
+use crate::wasm::wasm_impl::{JsAssetType, JsBitcoinNetwork, JsConfig, JsDarkSwap, JsOrderSide};
+
+/// Message sent by the worker once it handles requests
+const KIND_READY: &str = "ready";
+/// Reply to a request
+const KIND_RESPONSE: &str = "response";
+/// Forwarded DarkSwap event
+const KIND_EVENT: &str = "event";
+
+/// Configuration sent to the worker
+///
+/// `JsConfig` is a wasm-bindgen class and cannot be cloned into another thread.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WorkerConfig {
+    /// Bitcoin network, as the `JsBitcoinNetwork` discriminant
+    network: u8,
+    /// Wallet type
+    wallet_type: String,
+    /// Private key
+    private_key: Option<String>,
+    /// Mnemonic
+    mnemonic: Option<String>,
+    /// Derivation path
+    derivation_path: Option<String>,
+    /// Enable WebRTC
+    enable_webrtc: bool,
+    /// WebRTC STUN server URLs
+    ice_servers: Vec<String>,
+    /// WebRTC TURN server URLs
+    #[serde(default)]
+    turn_servers: Vec<String>,
+    /// TURN username
+    #[serde(default)]
+    turn_username: Option<String>,
+    /// TURN credential
+    #[serde(default)]
+    turn_credential: Option<String>,
+    /// ICE transport policy
+    #[serde(default)]
+    ice_transport_policy: Option<String>,
+    /// Signaling server URL
+    signaling_server_url: Option<String>,
+    /// Asset metadata overrides (JSON)
+    #[serde(default)]
+    asset_overrides: Option<String>,
+}
+
+impl WorkerConfig {
+    /// Capture a JavaScript configuration
+    fn from_js_config(js_config: &JsConfig) -> Self {
+        Self {
+            network: js_config.network as u8,
+            wallet_type: js_config.wallet_type.clone(),
+            private_key: js_config.private_key.clone(),
+            mnemonic: js_config.mnemonic.clone(),
+            derivation_path: js_config.derivation_path.clone(),
+            enable_webrtc: js_config.enable_webrtc,
+            ice_servers: js_config.ice_servers.clone(),
+            turn_servers: js_config.turn_servers.clone(),
+            turn_username: js_config.turn_username.clone(),
+            turn_credential: js_config.turn_credential.clone(),
+            ice_transport_policy: js_config.ice_transport_policy.clone(),
+            signaling_server_url: js_config.signaling_server_url.clone(),
+            asset_overrides: js_config.asset_overrides.clone(),
+        }
+    }
+
+    /// Rebuild the JavaScript configuration inside the worker
+    fn into_js_config(self) -> Result<JsConfig, JsValue> {
+        let network = match self.network {
+            0 => JsBitcoinNetwork::Mainnet,
+            1 => JsBitcoinNetwork::Testnet,
+            2 => JsBitcoinNetwork::Regtest,
+            3 => JsBitcoinNetwork::Signet,
+            other => return Err(JsValue::from_str(&format!("Invalid network: {}", other))),
+        };
+
+        Ok(JsConfig {
+            network,
+            wallet_type: self.wallet_type,
+            private_key: self.private_key,
+            mnemonic: self.mnemonic,
+            derivation_path: self.derivation_path,
+            enable_webrtc: self.enable_webrtc,
+            ice_servers: self.ice_servers,
+            turn_servers: self.turn_servers,
+            turn_username: self.turn_username,
+            turn_credential: self.turn_credential,
+            ice_transport_policy: self.ice_transport_policy,
+            signaling_server_url: self.signaling_server_url,
+            asset_overrides: self.asset_overrides,
+        })
+    }
+}
+
+/// One side of a message channel
+#[derive(Clone)]
+enum Endpoint {
+    /// Dedicated worker, seen from the page
+    Worker(Worker),
+    /// Page, seen from a dedicated worker
+    Scope(DedicatedWorkerGlobalScope),
+    /// Port of a shared worker, seen from either side
+    Port(MessagePort),
+}
+
+impl Endpoint {
+    /// Post a message to the other side
+    fn post(&self, message: &JsValue) -> Result<(), JsValue> {
+        match self {
+            Endpoint::Worker(worker) => worker.post_message(message),
+            Endpoint::Scope(scope) => scope.post_message(message),
+            Endpoint::Port(port) => port.post_message(message),
+        }
+    }
+
+    /// Set the message handler, which also starts a port
+    fn set_onmessage(&self, handler: Option<&Function>) {
+        match self {
+            Endpoint::Worker(worker) => worker.set_onmessage(handler),
+            Endpoint::Scope(scope) => scope.set_onmessage(handler),
+            Endpoint::Port(port) => port.set_onmessage(handler),
+        }
+    }
+}
+
+/// Create a message object of a kind
+fn message(kind: &str) -> Result<Object, JsValue> {
+    let obj = Object::new();
+    Reflect::set(&obj, &JsValue::from_str("kind"), &JsValue::from_str(kind))?;
+    Ok(obj)
+}
+
+/// Get a string property of a message
+fn string_field(obj: &JsValue, key: &str) -> Option<String> {
+    Reflect::get(obj, &JsValue::from_str(key)).ok().and_then(|value| value.as_string())
+}
+
+/// Get a string argument
+fn string_arg(args: &Array, index: u32) -> Result<String, JsValue> {
+    args.get(index)
+        .as_string()
+        .ok_or_else(|| JsValue::from_str(&format!("Argument {} must be a string", index)))
+}
+
+/// Get an asset type argument
+fn asset_type_arg(args: &Array, index: u32) -> Result<JsAssetType, JsValue> {
+    match args.get(index).as_f64().map(|value| value as u8) {
+        Some(0) => Ok(JsAssetType::Bitcoin),
+        Some(1) => Ok(JsAssetType::Rune),
+        Some(2) => Ok(JsAssetType::Alkane),
+        _ => Err(JsValue::from_str(&format!("Argument {} must be an asset type", index))),
+    }
+}
+
+/// Get an order side argument
+fn order_side_arg(args: &Array, index: u32) -> Result<JsOrderSide, JsValue> {
+    match args.get(index).as_f64().map(|value| value as u8) {
+        Some(0) => Ok(JsOrderSide::Buy),
+        Some(1) => Ok(JsOrderSide::Sell),
+        _ => Err(JsValue::from_str(&format!("Argument {} must be an order side", index))),
+    }
+}
+
+/// DarkSwap instance served by a worker
+struct WorkerHost {
+    /// DarkSwap instance, created by the first `init` request
+    darkswap: RefCell<Option<JsDarkSwap>>,
+    /// Connected pages
+    endpoints: RefCell<Vec<Endpoint>>,
+    /// Whether events are being forwarded
+    subscribed: Cell<bool>,
+}
+
+impl WorkerHost {
+    /// Create a host without a DarkSwap instance
+    fn new() -> Rc<Self> {
+        Rc::new(Self {
+            darkswap: RefCell::new(None),
+            endpoints: RefCell::new(Vec::new()),
+            subscribed: Cell::new(false),
+        })
+    }
+
+    /// Serve requests arriving on an endpoint
+    fn attach(self: Rc<Self>, endpoint: Endpoint) -> Result<(), JsValue> {
+        let host = self.clone();
+        let reply_to = endpoint.clone();
+        let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
+            let host = host.clone();
+            let reply_to = reply_to.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                host.handle(reply_to, event.data()).await;
+            });
+        }) as Box<dyn FnMut(MessageEvent)>);
+
+        endpoint.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        // The handler lives as long as the worker
+        onmessage.forget();
+
+        endpoint.post(&message(KIND_READY)?.into())?;
+        self.endpoints.borrow_mut().push(endpoint);
+
+        Ok(())
+    }
+
+    /// Handle a request and post the response
+    async fn handle(self: Rc<Self>, reply_to: Endpoint, request: JsValue) {
+        let id = Reflect::get(&request, &JsValue::from_str("id")).unwrap_or(JsValue::NULL);
+        let method = string_field(&request, "method").unwrap_or_default();
+        let args = Reflect::get(&request, &JsValue::from_str("args"))
+            .ok()
+            .and_then(|args| args.dyn_into::<Array>().ok())
+            .unwrap_or_else(Array::new);
+
+        let result = self.dispatch(&method, &args).await;
+
+        let response = match Self::response(&id, result) {
+            Ok(response) => response,
+            Err(e) => {
+                console::error_1(&e);
+                return;
+            }
+        };
+
+        if let Err(e) = reply_to.post(&response) {
+            console::error_1(&e);
+        }
+    }
+
+    /// Build a response message
+    fn response(id: &JsValue, result: Result<JsValue, JsValue>) -> Result<JsValue, JsValue> {
+        let response = message(KIND_RESPONSE)?;
+        Reflect::set(&response, &JsValue::from_str("id"), id)?;
+        Reflect::set(&response, &JsValue::from_str("ok"), &JsValue::from_bool(result.is_ok()))?;
+
+        match result {
+            Ok(value) => Reflect::set(&response, &JsValue::from_str("value"), &value)?,
+            Err(error) => {
+                // Errors are not always cloneable, their message is
+                let error = error.as_string().unwrap_or_else(|| format!("{:?}", error));
+                Reflect::set(&response, &JsValue::from_str("error"), &JsValue::from_str(&error))?
+            }
+        };
+
+        Ok(response.into())
+    }
+
+    /// Run a request against the DarkSwap instance
+    async fn dispatch(self: Rc<Self>, method: &str, args: &Array) -> Result<JsValue, JsValue> {
+        match method {
+            "init" => return self.init(&string_arg(args, 0)?),
+            "subscribe" => return self.subscribe().await,
+            _ => {}
+        }
+
+        let promise = {
+            let darkswap = self.darkswap.borrow();
+            let darkswap = darkswap.as_ref().ok_or_else(|| JsValue::from_str("DarkSwap is not initialized"))?;
+
+            match method {
+                "start" => darkswap.start(),
+                "stop" => darkswap.stop(),
+                "get_address" => darkswap.get_address(),
+                "get_balance" => darkswap.get_balance(),
+                "get_asset_balance" => darkswap.get_asset_balance(asset_type_arg(args, 0)?, string_arg(args, 1)?),
+                "get_asset_info" => darkswap.get_asset_info(asset_type_arg(args, 0)?, string_arg(args, 1)?),
+                "create_order" => darkswap.create_order(
+                    asset_type_arg(args, 0)?,
+                    string_arg(args, 1)?,
+                    asset_type_arg(args, 2)?,
+                    string_arg(args, 3)?,
+                    order_side_arg(args, 4)?,
+                    string_arg(args, 5)?,
+                    string_arg(args, 6)?,
+                    args.get(7).as_f64().map(|expiry| expiry as u64),
+                ),
+                "cancel_order" => darkswap.cancel_order(string_arg(args, 0)?),
+                "get_order" => darkswap.get_order(string_arg(args, 0)?),
+                "get_orders" => darkswap.get_orders(
+                    asset_type_arg(args, 0)?,
+                    string_arg(args, 1)?,
+                    asset_type_arg(args, 2)?,
+                    string_arg(args, 3)?,
+                ),
+                "take_order" => darkswap.take_order(string_arg(args, 0)?, string_arg(args, 1)?),
+                "load_orderbook_snapshot" => darkswap.load_orderbook_snapshot(string_arg(args, 0)?),
+                _ => return Err(JsValue::from_str(&format!("Unknown method: {}", method))),
+            }
+        };
+
+        JsFuture::from(promise).await
+    }
+
+    /// Create the DarkSwap instance, unless another page already did
+    fn init(&self, config_json: &str) -> Result<JsValue, JsValue> {
+        if self.darkswap.borrow().is_some() {
+            return Ok(JsValue::from_bool(true));
+        }
+
+        let config: WorkerConfig = serde_json::from_str(config_json)
+            .map_err(|e| JsValue::from_str(&format!("Invalid worker configuration: {}", e)))?;
+        let darkswap = JsDarkSwap::new(config.into_js_config()?)?;
+        *self.darkswap.borrow_mut() = Some(darkswap);
+
+        Ok(JsValue::from_bool(true))
+    }
+
+    /// Forward events to every connected page
+    async fn subscribe(self: Rc<Self>) -> Result<JsValue, JsValue> {
+        if self.subscribed.get() {
+            return Ok(JsValue::from_bool(true));
+        }
+
+        let host = self.clone();
+        let forward = Closure::wrap(Box::new(move |event: JsValue| {
+            let message = match message(KIND_EVENT) {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+            if Reflect::set(&message, &JsValue::from_str("event"), &event).is_err() {
+                return;
+            }
+
+            for endpoint in host.endpoints.borrow().iter() {
+                // Pages that went away are dropped by the browser
+                let _ = endpoint.post(&message);
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+        let callback: Function = forward.into_js_value().unchecked_into();
+
+        let promise = {
+            let mut darkswap = self.darkswap.borrow_mut();
+            let darkswap = darkswap.as_mut().ok_or_else(|| JsValue::from_str("DarkSwap is not initialized"))?;
+            darkswap.set_event_callback(callback)
+        };
+
+        let result = JsFuture::from(promise).await?;
+        self.subscribed.set(true);
+
+        Ok(result)
+    }
+}
+
+/// Serve DarkSwap from a dedicated worker
+///
+/// Call this from the worker script after the package is initialized.
+#[wasm_bindgen]
+pub fn worker_main() -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+
+    let scope: DedicatedWorkerGlobalScope = js_sys::global().dyn_into()?;
+    WorkerHost::new().attach(Endpoint::Scope(scope))
+}
+
+/// Serve DarkSwap from a shared worker
+///
+/// Call this from the worker script after the package is initialized. Connections
+/// made while the package was loading must be replayed through `onconnect`.
+#[wasm_bindgen]
+pub fn shared_worker_main() -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+
+    let scope: SharedWorkerGlobalScope = js_sys::global().dyn_into()?;
+    let host = WorkerHost::new();
+    let onconnect = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let port = match event.ports().get(0).dyn_into::<MessagePort>() {
+            Ok(port) => port,
+            Err(_) => return,
+        };
+
+        if let Err(e) = host.clone().attach(Endpoint::Port(port)) {
+            console::error_1(&e);
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+
+    scope.set_onconnect(Some(onconnect.as_ref().unchecked_ref()));
+    // The handler lives as long as the worker
+    onconnect.forget();
+
+    Ok(())
+}
+
+/// Page side of the worker channel
+struct WorkerClient {
+    /// Channel to the worker
+    endpoint: Endpoint,
+    /// ID of the next request
+    next_id: Cell<u32>,
+    /// Resolve and reject functions of the requests awaiting a response
+    pending: RefCell<HashMap<u32, (Function, Function)>>,
+    /// Requests held back until the worker is ready; `None` once it is
+    queued: RefCell<Option<Vec<JsValue>>>,
+    /// Event callback
+    event_callback: RefCell<Option<Function>>,
+}
+
+impl WorkerClient {
+    /// Send a request, or queue it if the worker is not ready yet
+    fn send(&self, request: JsValue) -> Result<(), JsValue> {
+        if let Some(queued) = self.queued.borrow_mut().as_mut() {
+            queued.push(request);
+            return Ok(());
+        }
+
+        self.endpoint.post(&request)
+    }
+
+    /// Handle a message from the worker
+    fn receive(&self, data: JsValue) {
+        match string_field(&data, "kind").as_deref() {
+            Some(KIND_READY) => {
+                let queued = self.queued.borrow_mut().take().unwrap_or_default();
+                for request in queued {
+                    if let Err(e) = self.endpoint.post(&request) {
+                        console::error_1(&e);
+                    }
+                }
+            }
+            Some(KIND_RESPONSE) => {
+                let id = match Reflect::get(&data, &JsValue::from_str("id")).ok().and_then(|id| id.as_f64()) {
+                    Some(id) => id as u32,
+                    None => return,
+                };
+                let (resolve, reject) = match self.pending.borrow_mut().remove(&id) {
+                    Some(handlers) => handlers,
+                    None => return,
+                };
+
+                let ok = Reflect::get(&data, &JsValue::from_str("ok")).ok().and_then(|ok| ok.as_bool()).unwrap_or(false);
+                let _ = if ok {
+                    let value = Reflect::get(&data, &JsValue::from_str("value")).unwrap_or(JsValue::UNDEFINED);
+                    resolve.call1(&JsValue::NULL, &value)
+                } else {
+                    let error = Reflect::get(&data, &JsValue::from_str("error")).unwrap_or(JsValue::UNDEFINED);
+                    reject.call1(&JsValue::NULL, &error)
+                };
+            }
+            Some(KIND_EVENT) => {
+                if let Some(callback) = self.event_callback.borrow().as_ref() {
+                    let event = Reflect::get(&data, &JsValue::from_str("event")).unwrap_or(JsValue::UNDEFINED);
+                    let _ = callback.call1(&JsValue::NULL, &event);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reject every request awaiting a response
+    fn reject_pending(&self, reason: &str) {
+        for (_, (_, reject)) in self.pending.borrow_mut().drain() {
+            let _ = reject.call1(&JsValue::NULL, &JsValue::from_str(reason));
+        }
+    }
+}
+
+/// DarkSwap SDK for JavaScript, running in a Web Worker
+///
+/// Has the same methods as `JsDarkSwap`.
+#[wasm_bindgen]
+pub struct JsDarkSwapWorker {
+    /// Channel state
+    client: Rc<WorkerClient>,
+    /// Message handler, kept alive with the instance
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+}
+
+#[wasm_bindgen]
+impl JsDarkSwapWorker {
+    /// Start a worker running `script_url` and create a DarkSwap instance in it
+    ///
+    /// With `shared`, the worker is a SharedWorker and the instance is shared with
+    /// the other tabs using the same script.
+    #[wasm_bindgen(constructor)]
+    pub fn new(js_config: JsConfig, script_url: String, shared: bool) -> Result<JsDarkSwapWorker, JsValue> {
+        console_error_panic_hook::set_once();
+
+        let mut options = WorkerOptions::new();
+        options.type_(WorkerType::Module);
+
+        let endpoint = if shared {
+            let worker = SharedWorker::new_with_worker_options(&script_url, &options)?;
+            Endpoint::Port(worker.port())
+        } else {
+            Endpoint::Worker(Worker::new_with_options(&script_url, &options)?)
+        };
+
+        let client = Rc::new(WorkerClient {
+            endpoint: endpoint.clone(),
+            next_id: Cell::new(0),
+            pending: RefCell::new(HashMap::new()),
+            queued: RefCell::new(Some(Vec::new())),
+            event_callback: RefCell::new(None),
+        });
+
+        let receiver = client.clone();
+        let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
+            receiver.receive(event.data());
+        }) as Box<dyn FnMut(MessageEvent)>);
+        endpoint.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+
+        let darkswap = JsDarkSwapWorker {
+            client,
+            _onmessage: onmessage,
+        };
+
+        // Requests are handled in order, so later calls see the instance
+        let config_json = serde_json::to_string(&WorkerConfig::from_js_config(&js_config))
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize configuration: {}", e)))?;
+        let on_init_error = Closure::once_into_js(|error: JsValue| {
+            console::error_2(&JsValue::from_str("Failed to create DarkSwap in worker:"), &error);
+        });
+        let _ = darkswap
+            .call("init", Array::of1(&JsValue::from_str(&config_json)))
+            .catch(on_init_error.unchecked_ref());
+
+        Ok(darkswap)
+    }
+
+    /// Send a request to the worker
+    fn call(&self, method: &str, args: Array) -> Promise {
+        let client = self.client.clone();
+
+        Promise::new(&mut |resolve, reject| {
+            let id = client.next_id.get();
+            client.next_id.set(id.wrapping_add(1));
+
+            let request = Object::new();
+            let built = Reflect::set(&request, &JsValue::from_str("id"), &JsValue::from_f64(id as f64))
+                .and_then(|_| Reflect::set(&request, &JsValue::from_str("method"), &JsValue::from_str(method)))
+                .and_then(|_| Reflect::set(&request, &JsValue::from_str("args"), &args));
+            if let Err(e) = built {
+                let _ = reject.call1(&JsValue::NULL, &e);
+                return;
+            }
+
+            client.pending.borrow_mut().insert(id, (resolve, reject.clone()));
+            if let Err(e) = client.send(request.into()) {
+                client.pending.borrow_mut().remove(&id);
+                let _ = reject.call1(&JsValue::NULL, &e);
+            }
+        })
+    }
+
+    /// Start DarkSwap
+    #[wasm_bindgen]
+    pub fn start(&self) -> Promise {
+        self.call("start", Array::new())
+    }
+
+    /// Stop DarkSwap
+    ///
+    /// In a shared worker this stops the instance for every tab.
+    #[wasm_bindgen]
+    pub fn stop(&self) -> Promise {
+        self.call("stop", Array::new())
+    }
+
+    /// Set event callback
+    #[wasm_bindgen]
+    pub fn set_event_callback(&mut self, callback: Function) -> Promise {
+        *self.client.event_callback.borrow_mut() = Some(callback);
+        self.call("subscribe", Array::new())
+    }
+
+    /// Get wallet address
+    #[wasm_bindgen]
+    pub fn get_address(&self) -> Promise {
+        self.call("get_address", Array::new())
+    }
+
+    /// Get wallet balance
+    #[wasm_bindgen]
+    pub fn get_balance(&self) -> Promise {
+        self.call("get_balance", Array::new())
+    }
+
+    /// Get asset balance
+    #[wasm_bindgen]
+    pub fn get_asset_balance(&self, asset_type: JsAssetType, id: String) -> Promise {
+        self.call(
+            "get_asset_balance",
+            Array::of2(&JsValue::from_f64(asset_type as u8 as f64), &JsValue::from_str(&id)),
+        )
+    }
+
+    /// Get the name, symbol, decimals and icon URL of an asset, as JSON
+    #[wasm_bindgen]
+    pub fn get_asset_info(&self, asset_type: JsAssetType, id: String) -> Promise {
+        self.call(
+            "get_asset_info",
+            Array::of2(&JsValue::from_f64(asset_type as u8 as f64), &JsValue::from_str(&id)),
+        )
+    }
+
+    /// Create an order
+    #[wasm_bindgen]
+    pub fn create_order(
+        &self,
+        base_asset_type: JsAssetType,
+        base_asset_id: String,
+        quote_asset_type: JsAssetType,
+        quote_asset_id: String,
+        side: JsOrderSide,
+        amount: String,
+        price: String,
+        expiry: Option<u64>,
+    ) -> Promise {
+        let args = Array::new();
+        args.push(&JsValue::from_f64(base_asset_type as u8 as f64));
+        args.push(&JsValue::from_str(&base_asset_id));
+        args.push(&JsValue::from_f64(quote_asset_type as u8 as f64));
+        args.push(&JsValue::from_str(&quote_asset_id));
+        args.push(&JsValue::from_f64(side as u8 as f64));
+        args.push(&JsValue::from_str(&amount));
+        args.push(&JsValue::from_str(&price));
+        args.push(&expiry.map_or(JsValue::UNDEFINED, |expiry| JsValue::from_f64(expiry as f64)));
+
+        self.call("create_order", args)
+    }
+
+    /// Cancel an order
+    #[wasm_bindgen]
+    pub fn cancel_order(&self, order_id: String) -> Promise {
+        self.call("cancel_order", Array::of1(&JsValue::from_str(&order_id)))
+    }
+
+    /// Get an order by ID
+    #[wasm_bindgen]
+    pub fn get_order(&self, order_id: String) -> Promise {
+        self.call("get_order", Array::of1(&JsValue::from_str(&order_id)))
+    }
+
+    /// Get orders for a pair
+    #[wasm_bindgen]
+    pub fn get_orders(
+        &self,
+        base_asset_type: JsAssetType,
+        base_asset_id: String,
+        quote_asset_type: JsAssetType,
+        quote_asset_id: String,
+    ) -> Promise {
+        self.call(
+            "get_orders",
+            Array::of4(
+                &JsValue::from_f64(base_asset_type as u8 as f64),
+                &JsValue::from_str(&base_asset_id),
+                &JsValue::from_f64(quote_asset_type as u8 as f64),
+                &JsValue::from_str(&quote_asset_id),
+            ),
+        )
+    }
+
+    /// Take an order
+    #[wasm_bindgen]
+    pub fn take_order(&self, order_id: String, amount: String) -> Promise {
+        self.call(
+            "take_order",
+            Array::of2(&JsValue::from_str(&order_id), &JsValue::from_str(&amount)),
+        )
+    }
+
+    /// Load a signed orderbook snapshot served by a relay
+    #[wasm_bindgen]
+    pub fn load_orderbook_snapshot(&self, snapshot_json: String) -> Promise {
+        self.call("load_orderbook_snapshot", Array::of1(&JsValue::from_str(&snapshot_json)))
+    }
+
+    /// Stop the worker, rejecting pending requests
+    ///
+    /// A shared worker keeps running for the other tabs; only this tab's port is closed.
+    #[wasm_bindgen]
+    pub fn terminate(&self) {
+        match &self.client.endpoint {
+            Endpoint::Worker(worker) => worker.terminate(),
+            Endpoint::Port(port) => port.close(),
+            Endpoint::Scope(_) => {}
+        }
+
+        self.client.reject_pending("Worker terminated");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+    use web_sys::MessageChannel;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn client(ready: bool) -> WorkerClient {
+        let channel = MessageChannel::new().unwrap();
+        WorkerClient {
+            endpoint: Endpoint::Port(channel.port1()),
+            next_id: Cell::new(0),
+            pending: RefCell::new(HashMap::new()),
+            queued: RefCell::new(if ready { None } else { Some(Vec::new()) }),
+            event_callback: RefCell::new(None),
+        }
+    }
+
+    /// Register a request awaiting a response, returning what the response settles
+    fn pending(client: &WorkerClient, id: u32) -> JsFuture {
+        let promise = Promise::new(&mut |resolve, reject| {
+            client.pending.borrow_mut().insert(id, (resolve, reject));
+        });
+        JsFuture::from(promise)
+    }
+
+    fn response(id: u32, result: Result<JsValue, JsValue>) -> JsValue {
+        WorkerHost::response(&JsValue::from_f64(id as f64), result).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_responses_settle_their_own_requests() {
+        let client = client(true);
+        let first = pending(&client, 0);
+        let second = pending(&client, 1);
+
+        // Responses may arrive in any order
+        client.receive(response(1, Ok(JsValue::from_str("bcrt1qaddress"))));
+        client.receive(response(0, Err(JsValue::from_str("Wallet is locked"))));
+        // A response nobody waits for is ignored
+        client.receive(response(7, Ok(JsValue::TRUE)));
+
+        assert_eq!(second.await.unwrap().as_string().as_deref(), Some("bcrt1qaddress"));
+        assert_eq!(first.await.unwrap_err().as_string().as_deref(), Some("Wallet is locked"));
+        assert!(client.pending.borrow().is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_errors_are_sent_as_strings() {
+        let response = response(3, Err(JsValue::from_str("Order not found")));
+        assert_eq!(string_field(&response, "kind").as_deref(), Some(KIND_RESPONSE));
+        assert_eq!(Reflect::get(&response, &JsValue::from_str("id")).unwrap().as_f64(), Some(3.0));
+        assert_eq!(Reflect::get(&response, &JsValue::from_str("ok")).unwrap().as_bool(), Some(false));
+        assert_eq!(string_field(&response, "error").as_deref(), Some("Order not found"));
+
+        // Error objects do not survive the structured clone everywhere; their message does
+        let response = self::response(4, Err(js_sys::Error::new("Swarm stopped").into()));
+        assert!(string_field(&response, "error").unwrap().contains("Swarm stopped"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_requests_wait_for_ready() {
+        let client = client(false);
+        client.send(JsValue::from_str("request")).unwrap();
+        assert_eq!(client.queued.borrow().as_ref().map(Vec::len), Some(1));
+
+        client.receive(message(KIND_READY).unwrap().into());
+        assert!(client.queued.borrow().is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_terminate_rejects_pending_requests() {
+        let client = client(true);
+        let request = pending(&client, 0);
+
+        client.reject_pending("Worker terminated");
+        assert_eq!(request.await.unwrap_err().as_string().as_deref(), Some("Worker terminated"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_host_rejects_calls_before_init() {
+        let host = WorkerHost::new();
+
+        let error = host.clone().dispatch("get_balance", &Array::new()).await.unwrap_err();
+        assert_eq!(error.as_string().as_deref(), Some("DarkSwap is not initialized"));
+
+        let error = host.dispatch("init", &Array::new()).await.unwrap_err();
+        assert_eq!(error.as_string().as_deref(), Some("Argument 0 must be a string"));
+    }
+}
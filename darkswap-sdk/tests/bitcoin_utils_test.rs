@@ -43,4 +43,32 @@ fn test_keypair_methods() {
     // Verify the private key corresponds to the public key
     let derived_public_key = bitcoin::PublicKey::from_private_key(&secp, &private_key);
     assert_eq!(derived_public_key, public_key);
-}
\ No newline at end of file
+}
+#[test]
+fn test_bitcoin_transfer_never_spends_rune_outputs() {
+    use bitcoin::hashes::Hash;
+    use darkswap_sdk::bitcoin_utils::{generate_test_address, BitcoinWallet, PsbtUtils, SimpleWallet};
+    use darkswap_sdk::types::Asset;
+
+    // The wallet starts with one plain 1 BTC output
+    let mut wallet = SimpleWallet::new(Network::Regtest).unwrap();
+    let from_address = wallet.get_address(0).unwrap();
+    let to_address = generate_test_address(Network::Regtest, 2).unwrap();
+
+    // Add a large output that also holds runes
+    let rune_outpoint = bitcoin::OutPoint::new(bitcoin::Txid::all_zeros(), 7);
+    wallet.add_asset_utxo(
+        rune_outpoint,
+        bitcoin::TxOut {
+            value: 500_000_000,
+            script_pubkey: from_address.script_pubkey(),
+        },
+        vec![(Asset::Rune(1), 1_000)],
+    );
+
+    let psbt = PsbtUtils::create_bitcoin_transfer_psbt(&wallet, &from_address, &to_address, 10_000, 1.0).unwrap();
+    assert!(psbt.unsigned_tx.input.iter().all(|input| input.previous_output != rune_outpoint));
+
+    // More than the plain balance fails rather than dipping into the rune output
+    assert!(PsbtUtils::create_bitcoin_transfer_psbt(&wallet, &from_address, &to_address, 200_000_000, 1.0).is_err());
+}
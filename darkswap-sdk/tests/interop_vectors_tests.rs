@@ -0,0 +1,189 @@
+//! Wire compatibility with the published test vectors
+//!
+//! The vectors in `darkswap-support/vectors` are what alternative clients check
+//! their encoders and decoders against, so this implementation must decode
+//! every one of them to the documented values, accept their signatures and
+//! encode the same values back to equivalent bytes.
+
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::psbt::PartiallySignedTransaction as Psbt;
+use bitcoin::{PackedLockTime, Script, Transaction, TxOut};
+use darkswap_sdk::{
+    orderbook::OrderId,
+    runestone::{Edict, Etching, Runestone, Terms},
+    trade::{protocol::SignedTradeMessage, psbt::verify_signatures, receipt::ReceiptBody, TradeMessage},
+    types::{Asset, TradeId},
+};
+use darkswap_support::envelope;
+use darkswap_support::vectors::{VectorFile, PSBT_EXCHANGE, RUNESTONES, TRADE_MESSAGES};
+use rust_decimal_macros::dec;
+use serde_json::Value;
+
+fn u128_field(value: &Value, field: &str) -> u128 {
+    value[field].as_str().unwrap_or_else(|| panic!("{} is not a string", field)).parse().unwrap()
+}
+
+fn optional<T>(value: &Value, parse: impl Fn(&Value) -> T) -> Option<T> {
+    if value.is_null() {
+        None
+    } else {
+        Some(parse(value))
+    }
+}
+
+/// Number of trade message kinds
+const MESSAGE_KINDS: usize = 8;
+
+/// Position of a message kind, so that a new kind fails to compile here until
+/// it is counted and given a vector
+fn kind_index(message: &TradeMessage) -> usize {
+    match message {
+        TradeMessage::Initialize { .. } => 0,
+        TradeMessage::InitializeBatch { .. } => 1,
+        TradeMessage::SendPsbt { .. } => 2,
+        TradeMessage::SignPsbt { .. } => 3,
+        TradeMessage::Broadcast { .. } => 4,
+        TradeMessage::Cancel { .. } => 5,
+        TradeMessage::AcceptReceipt { .. } => 6,
+        TradeMessage::Ack { .. } => 7,
+    }
+}
+
+/// Build the runestone a vector describes
+fn runestone(decoded: &Value) -> Runestone {
+    let etching = optional(&decoded["etching"], |etching| Etching {
+        rune: u128_field(etching, "rune"),
+        symbol: etching["symbol"].as_str().map(str::to_string),
+        decimals: etching["decimals"].as_u64().map(|decimals| decimals as u8),
+        spacers: etching["spacers"].as_u64().unwrap() as u32,
+        amount: u128_field(etching, "amount"),
+        terms: optional(&etching["terms"], |terms| Terms {
+            cap: optional(&terms["cap"], |_| u128_field(terms, "cap")),
+            height: terms["height"].as_u64().map(|height| height as u32),
+            amount: optional(&terms["amount"], |_| u128_field(terms, "amount")),
+        }),
+    });
+
+    Runestone {
+        edicts: decoded["edicts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edict| Edict {
+                id: u128_field(edict, "id"),
+                amount: u128_field(edict, "amount"),
+                output: edict["output"].as_u64().unwrap() as u32,
+            })
+            .collect(),
+        etching,
+        default_output: decoded["default_output"].as_u64().map(|output| output as u32),
+        burn: decoded["burn"].as_bool().unwrap(),
+    }
+}
+
+#[test]
+fn test_trade_message_vectors() {
+    let file = VectorFile::parse(TRADE_MESSAGES).unwrap();
+
+    for vector in &file.vectors {
+        let wire = vector.wire_bytes().unwrap();
+        let message: SignedTradeMessage = envelope::decode(&wire).unwrap_or_else(|e| panic!("{}: {}", vector.name, e));
+
+        message.verify().unwrap_or_else(|e| panic!("{}: {}", vector.name, e));
+        assert_eq!(serde_json::to_value(&message).unwrap(), vector.decoded, "{}", vector.name);
+
+        // Key order within the JSON is not part of the format
+        let encoded: Value = serde_json::from_slice(&envelope::encode(&message).unwrap()).unwrap();
+        assert_eq!(encoded, serde_json::from_slice::<Value>(&wire).unwrap(), "{}", vector.name);
+    }
+
+    // Every message kind is covered
+    let mut covered = [false; MESSAGE_KINDS];
+    for vector in &file.vectors {
+        let message: SignedTradeMessage = serde_json::from_value(vector.decoded.clone()).unwrap();
+        covered[kind_index(&message.message)] = true;
+    }
+    for (index, covered) in covered.iter().enumerate() {
+        assert!(covered, "no vector for message kind {}", index);
+    }
+}
+
+#[test]
+fn test_trade_message_vectors_carry_the_psbt_exchange() {
+    let messages = VectorFile::parse(TRADE_MESSAGES).unwrap();
+    let psbts = VectorFile::parse(PSBT_EXCHANGE).unwrap();
+    let decode = |name: &str| -> TradeMessage {
+        let message: SignedTradeMessage = envelope::decode(&messages.get(name).unwrap().wire_bytes().unwrap()).unwrap();
+        message.message
+    };
+
+    match decode("send_psbt") {
+        TradeMessage::SendPsbt { psbt, .. } => assert_eq!(psbt, psbts.get("unsigned").unwrap().wire_bytes().unwrap()),
+        other => panic!("unexpected message: {:?}", other),
+    }
+    match decode("sign_psbt") {
+        TradeMessage::SignPsbt { signed_psbt, .. } => {
+            assert_eq!(signed_psbt, psbts.get("signed").unwrap().wire_bytes().unwrap())
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+
+    // The receipt countersigned by the maker covers the settled trade
+    let accept = envelope::decode::<SignedTradeMessage>(&messages.get("accept_receipt").unwrap().wire_bytes().unwrap()).unwrap();
+    let TradeMessage::AcceptReceipt { signature, .. } = &accept.message else {
+        panic!("unexpected message: {:?}", accept.message);
+    };
+    let taker = messages.get("initialize").unwrap().decoded["sender"].as_str().unwrap().to_string();
+    let txid = psbts.get("signed").unwrap().decoded["txid"].as_str().unwrap().to_string();
+    let body = ReceiptBody {
+        trade_id: TradeId("trade-1".to_string()),
+        order_id: OrderId("order-1".to_string()),
+        maker_peer_id: accept.sender.clone(),
+        taker_peer_id: taker,
+        base_asset: Asset::Bitcoin,
+        quote_asset: Asset::Rune(1),
+        amount: dec!(0.5),
+        price: dec!(100),
+        txid,
+    };
+    signature.verify(&body).unwrap();
+}
+
+#[test]
+fn test_psbt_exchange_vectors() {
+    let file = VectorFile::parse(PSBT_EXCHANGE).unwrap();
+
+    for vector in &file.vectors {
+        let wire = vector.wire_bytes().unwrap();
+        let psbt: Psbt = deserialize(&wire).unwrap_or_else(|e| panic!("{}: {}", vector.name, e));
+
+        assert_eq!(serialize(&psbt), wire, "{}", vector.name);
+        assert_eq!(psbt.unsigned_tx.txid().to_string(), vector.decoded["txid"].as_str().unwrap(), "{}", vector.name);
+        assert_eq!(psbt.inputs.len() as u64, vector.decoded["inputs"].as_u64().unwrap(), "{}", vector.name);
+        assert_eq!(psbt.outputs.len() as u64, vector.decoded["outputs"].as_u64().unwrap(), "{}", vector.name);
+
+        let signatures: usize = psbt.inputs.iter().map(|input| input.partial_sigs.len()).sum();
+        assert_eq!(signatures as u64, vector.decoded["partial_signatures"].as_u64().unwrap(), "{}", vector.name);
+        verify_signatures(&wire).unwrap_or_else(|e| panic!("{}: {}", vector.name, e));
+    }
+}
+
+#[test]
+fn test_runestone_vectors() {
+    let file = VectorFile::parse(RUNESTONES).unwrap();
+
+    for vector in &file.vectors {
+        let expected = runestone(&vector.decoded);
+        let script = Script::from(vector.wire_bytes().unwrap());
+
+        assert_eq!(expected.to_script(), script, "{}", vector.name);
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut { value: 0, script_pubkey: script }],
+        };
+        assert_eq!(Runestone::parse(&tx), Some(expected), "{}", vector.name);
+    }
+}
@@ -0,0 +1,282 @@
+//! Property tests for order matching
+//!
+//! Random streams of placed and canceled orders are applied to a book view and to a
+//! naive reference model, then random taker orders are matched against both. The
+//! model keeps open orders in arrival order and sorts them on every match, so it is
+//! slow but obviously right.
+
+use darkswap_sdk::orderbook::{
+    matching::{match_order, Fill, MatchResult},
+    view::{BookView, PairBook},
+    Order, OrderId, OrderSide, OrderStatus,
+};
+use darkswap_sdk::types::Asset;
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+/// Book change
+#[derive(Debug, Clone)]
+enum Op {
+    /// Place a new order
+    Place { side: OrderSide, price: Decimal, amount: Decimal, timestamp: u64 },
+    /// Cancel a previously placed order, possibly again
+    Cancel(usize),
+}
+
+/// Taker order
+#[derive(Debug, Clone)]
+struct Taker {
+    side: OrderSide,
+    amount: Decimal,
+    limit_price: Option<Decimal>,
+}
+
+fn side() -> impl Strategy<Value = OrderSide> {
+    prop_oneof![Just(OrderSide::Buy), Just(OrderSide::Sell)]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (side(), 1i64..=40, 1i64..=10_000, 0u64..4).prop_map(|(side, price, amount, timestamp)| Op::Place {
+            side,
+            // Few distinct prices and timestamps, so ties are common
+            price: Decimal::new(price, 1),
+            amount: Decimal::new(amount, 2),
+            timestamp,
+        }),
+        1 => any::<prop::sample::Index>().prop_map(|index| Op::Cancel(index.index(usize::MAX))),
+    ]
+}
+
+fn taker() -> impl Strategy<Value = Taker> {
+    (side(), -1_000i64..=50_000, prop::option::of(1i64..=40)).prop_map(|(side, amount, limit)| Taker {
+        side,
+        amount: Decimal::new(amount, 2),
+        limit_price: limit.map(|limit| Decimal::new(limit, 1)),
+    })
+}
+
+fn order(index: usize, side: OrderSide, price: Decimal, amount: Decimal, timestamp: u64) -> Order {
+    Order {
+        id: OrderId(format!("order-{}", index)),
+        maker: "maker".to_string(),
+        base_asset: Asset::Bitcoin,
+        quote_asset: Asset::Rune(1),
+        side,
+        amount,
+        price,
+        status: OrderStatus::Open,
+        timestamp,
+        expiry: u64::MAX,
+    }
+}
+
+/// Reference model of a pair book
+#[derive(Default)]
+struct Model {
+    /// Open orders in arrival order
+    open: Vec<Order>,
+}
+
+impl Model {
+    fn apply(&mut self, order: &Order) {
+        self.open.retain(|o| o.id != order.id);
+        if order.status == OrderStatus::Open {
+            self.open.push(order.clone());
+        }
+    }
+
+    fn match_order(&self, taker: &Taker) -> MatchResult {
+        let mut resting: Vec<&Order> = self.open.iter().filter(|o| o.side != taker.side).collect();
+        // Stable sorts keep arrival order among equal prices and timestamps
+        match taker.side {
+            OrderSide::Buy => resting.sort_by_key(|o| (o.price, o.timestamp)),
+            OrderSide::Sell => resting.sort_by_key(|o| (Reverse(o.price), o.timestamp)),
+        }
+
+        let mut remaining = taker.amount.max(Decimal::ZERO);
+        let mut fills = Vec::new();
+        for o in resting {
+            let acceptable = match (taker.side, taker.limit_price) {
+                (_, None) => true,
+                (OrderSide::Buy, Some(limit)) => o.price <= limit,
+                (OrderSide::Sell, Some(limit)) => o.price >= limit,
+            };
+            if remaining.is_zero() || !acceptable {
+                break;
+            }
+            let amount = remaining.min(o.amount);
+            fills.push(Fill { order_id: o.id.clone(), price: o.price, amount });
+            remaining -= amount;
+        }
+
+        MatchResult { fills, remaining }
+    }
+}
+
+/// Turn a stream of changes into the order versions to apply
+fn versions(ops: &[Op]) -> Vec<Order> {
+    let mut placed: Vec<Order> = Vec::new();
+    let mut versions = Vec::new();
+
+    for op in ops {
+        let order = match op {
+            Op::Place { side, price, amount, timestamp } => {
+                let order = order(placed.len(), *side, *price, *amount, *timestamp);
+                placed.push(order.clone());
+                order
+            }
+            Op::Cancel(index) if !placed.is_empty() => {
+                let order = &mut placed[index % placed.len()];
+                order.status = OrderStatus::Canceled;
+                order.clone()
+            }
+            Op::Cancel(_) => continue,
+        };
+        versions.push(order);
+    }
+
+    versions
+}
+
+/// Apply a stream of changes to a view and a model
+fn build(ops: &[Op]) -> (BookView, Model) {
+    let mut view = BookView::default();
+    let mut model = Model::default();
+
+    for order in versions(ops) {
+        view = view.with_order(&order);
+        model.apply(&order);
+    }
+
+    (view, model)
+}
+
+fn pair_book(view: &BookView) -> PairBook {
+    view.pair(&Asset::Bitcoin, &Asset::Rune(1)).cloned().unwrap_or_default()
+}
+
+fn ids(orders: &[Order]) -> Vec<OrderId> {
+    orders.iter().map(|o| o.id.clone()).collect()
+}
+
+proptest! {
+    #[test]
+    fn fills_are_positive_and_bounded(ops in prop::collection::vec(op(), 0..60), taker in taker()) {
+        let (view, _) = build(&ops);
+        let book = pair_book(&view);
+        let result = match_order(&book, taker.side, taker.amount, taker.limit_price);
+
+        let mut seen = HashSet::new();
+        for fill in &result.fills {
+            prop_assert!(fill.amount > Decimal::ZERO);
+            prop_assert!(seen.insert(fill.order_id.clone()), "order filled twice");
+
+            let resting = book.orders().find(|o| o.id == fill.order_id).expect("fill of unknown order");
+            prop_assert_ne!(resting.side, taker.side);
+            prop_assert!(fill.amount <= resting.amount);
+            prop_assert_eq!(fill.price, resting.price);
+        }
+    }
+
+    #[test]
+    fn amounts_are_conserved(ops in prop::collection::vec(op(), 0..60), taker in taker()) {
+        let (view, _) = build(&ops);
+        let result = match_order(&pair_book(&view), taker.side, taker.amount, taker.limit_price);
+
+        prop_assert!(result.remaining >= Decimal::ZERO);
+        prop_assert_eq!(result.filled() + result.remaining, taker.amount.max(Decimal::ZERO));
+
+        // Only the last fill may leave part of a resting order
+        let book = pair_book(&view);
+        for fill in result.fills.iter().rev().skip(1) {
+            let resting = book.orders().find(|o| o.id == fill.order_id).unwrap();
+            prop_assert_eq!(fill.amount, resting.amount);
+        }
+    }
+
+    #[test]
+    fn matches_reference_model(ops in prop::collection::vec(op(), 0..60), takers in prop::collection::vec(taker(), 1..5)) {
+        let (view, model) = build(&ops);
+        let book = pair_book(&view);
+
+        for taker in takers {
+            let expected = model.match_order(&taker);
+            let actual = match_order(&book, taker.side, taker.amount, taker.limit_price);
+            prop_assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn respects_price_time_priority(ops in prop::collection::vec(op(), 0..60), taker in taker()) {
+        let (view, _) = build(&ops);
+        let book = pair_book(&view);
+        let result = match_order(&book, taker.side, taker.amount, taker.limit_price);
+
+        let filled: Vec<&Order> = result.fills.iter()
+            .map(|fill| book.orders().find(|o| o.id == fill.order_id).unwrap())
+            .collect();
+
+        for pair in filled.windows(2) {
+            let (earlier, later) = (pair[0], pair[1]);
+            match taker.side {
+                OrderSide::Buy => prop_assert!((earlier.price, earlier.timestamp) <= (later.price, later.timestamp)),
+                OrderSide::Sell => prop_assert!((Reverse(earlier.price), earlier.timestamp) <= (Reverse(later.price), later.timestamp)),
+            }
+        }
+
+        // No unfilled order was better than a filled one
+        if let Some(worst) = filled.last() {
+            let unfilled = book.orders()
+                .filter(|o| o.side != taker.side)
+                .filter(|o| !result.fills.iter().any(|fill| fill.order_id == o.id));
+            for o in unfilled {
+                match taker.side {
+                    OrderSide::Buy => prop_assert!(o.price >= worst.price),
+                    OrderSide::Sell => prop_assert!(o.price <= worst.price),
+                }
+            }
+        }
+
+        if let Some(limit) = taker.limit_price {
+            for o in &filled {
+                match taker.side {
+                    OrderSide::Buy => prop_assert!(o.price <= limit),
+                    OrderSide::Sell => prop_assert!(o.price >= limit),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cancels_are_idempotent(ops in prop::collection::vec(op(), 1..60), index in any::<prop::sample::Index>()) {
+        let (view, _) = build(&ops);
+        let book = pair_book(&view);
+        let orders: Vec<Order> = book.orders().cloned().collect();
+        prop_assume!(!orders.is_empty());
+
+        let mut canceled = orders[index.index(orders.len())].clone();
+        canceled.status = OrderStatus::Canceled;
+
+        let once = view.with_order(&canceled);
+        let twice = once.with_order(&canceled);
+        let (once, twice) = (pair_book(&once), pair_book(&twice));
+
+        prop_assert_eq!(once.len(), orders.len() - 1);
+        prop_assert_eq!(ids(once.bids()), ids(twice.bids()));
+        prop_assert_eq!(ids(once.asks()), ids(twice.asks()));
+        prop_assert!(once.orders().all(|o| o.id != canceled.id));
+    }
+
+    #[test]
+    fn bulk_load_matches_incremental(ops in prop::collection::vec(op(), 0..60)) {
+        let (view, _) = build(&ops);
+        let bulk = BookView::from_orders(&versions(&ops));
+        let (book, bulk) = (pair_book(&view), pair_book(&bulk));
+
+        prop_assert_eq!(ids(book.bids()), ids(bulk.bids()));
+        prop_assert_eq!(ids(book.asks()), ids(bulk.asks()));
+    }
+}
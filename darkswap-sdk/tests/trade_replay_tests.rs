@@ -0,0 +1,85 @@
+//! Replay of recorded trade protocol traces
+//!
+//! Every trace in `tests/trade_traces` is replayed against a fresh trade module and
+//! must produce the recorded states, rejections and outgoing messages. To add a
+//! regression test, record a session with `TradeModule::start_recording` and
+//! `take_trace`, save it with `TradeTrace::save` and drop the file in that directory.
+
+use anyhow::Result;
+use darkswap_sdk::{
+    config::Config,
+    trade::{
+        replay::{Replayer, TraceDirection, TradeTrace},
+        TradeMessage, TradeState,
+    },
+    types::{Event, TradeId},
+};
+use std::path::PathBuf;
+
+fn traces_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("trade_traces")
+}
+
+#[tokio::test]
+async fn test_replay_recorded_traces() -> Result<()> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(traces_dir())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|path| path.extension().map_or(false, |extension| extension == "json"));
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let trace = TradeTrace::load(&path)?;
+        let mut replayer = Replayer::new(&Config::default())?;
+        let report = replayer.replay(&trace).await?;
+
+        assert!(report.is_clean(), "{}: {:#?}", path.display(), report.mismatches);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_is_deterministic() -> Result<()> {
+    let trace = TradeTrace::load(traces_dir().join("maker_happy_path.json"))?;
+
+    let first = Replayer::new(&Config::default())?.replay(&trace).await?;
+    let second = Replayer::new(&Config::default())?.replay(&first.trace).await?;
+
+    assert!(second.is_clean(), "{:#?}", second.mismatches);
+    assert!(second.events.iter().any(|event| matches!(event, Event::TradeCompleted(_))));
+
+    // The settlement PSBTs are the same in both runs
+    let psbts = |trace: &TradeTrace| -> Vec<Vec<u8>> {
+        trace.entries(TraceDirection::Outbound)
+            .filter_map(|entry| match &entry.message {
+                TradeMessage::SendPsbt { psbt, .. } => Some(psbt.clone()),
+                TradeMessage::SignPsbt { signed_psbt, .. } => Some(signed_psbt.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(psbts(&first.trace), psbts(&second.trace));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replay_reports_divergence() -> Result<()> {
+    let mut trace = TradeTrace::load(traces_dir().join("malformed_psbt.json"))?;
+
+    // Pretend the malformed PSBT had been accepted when recording
+    let last = trace.entries.last_mut().unwrap();
+    last.state_after = Some(TradeState::MakerSigned);
+    last.error = None;
+
+    let report = Replayer::new(&Config::default())?.replay(&trace).await?;
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].direction, TraceDirection::Inbound);
+
+    let trade = report.trace.entries.last().unwrap().message.trade_id().clone();
+    assert_eq!(trade, TradeId("trade-replay-1".to_string()));
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 //! Tests for the wallet module
 
 use anyhow::Result;
+use darkswap_support::storage::{MemoryStorage, Storage};
 use darkswap_sdk::{
     config::BitcoinNetwork,
     types::Asset,
@@ -8,6 +9,7 @@ use darkswap_sdk::{
 };
 use rust_decimal::Decimal;
 use std::str::FromStr;
+use std::sync::Arc;
 
 #[tokio::test]
 async fn test_simple_wallet_creation() -> Result<()> {
@@ -137,4 +139,67 @@ async fn test_simple_wallet_psbt_broadcast() -> Result<()> {
     assert!(txid.chars().all(|c| c.is_ascii_hexdigit()));
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn test_simple_wallet_new_addresses() -> Result<()> {
+    // Create a simple wallet with a small gap limit
+    let wallet = SimpleWallet::new(None, BitcoinNetwork::Testnet)?.with_gap_limit(2);
+    
+    // Issue labeled addresses
+    let first = wallet.get_new_address(Some("exchange".to_string())).await?;
+    let second = wallet.get_new_address(None).await?;
+    assert_ne!(first.address, second.address);
+    assert_eq!(second.index, 1);
+    assert!(second.address.starts_with("tb1"));
+    
+    // The gap limit stops further unused addresses
+    assert!(wallet.get_new_address(None).await.is_err());
+    
+    // Using an address allows another one
+    assert!(wallet.mark_address_used(&second.address).await?);
+    wallet.get_new_address(None).await?;
+    
+    let addresses = wallet.list_addresses().await?;
+    assert_eq!(addresses.len(), 3);
+    assert_eq!(addresses[0].label.as_deref(), Some("exchange"));
+    assert!(addresses[1].used);
+    
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_simple_wallet_address_store() -> Result<()> {
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let wif = "cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy";
+    let path = "m/84'/1'/0'/0";
+    
+    // Issue an address under a derivation path
+    let wallet = SimpleWallet::new(Some(wif), BitcoinNetwork::Testnet)?
+        .with_derivation_path(path)?
+        .with_address_store(storage.clone())
+        .await?;
+    let first = wallet.get_new_address(Some("exchange".to_string())).await?;
+    
+    // The derivation path changes the addresses
+    let unpathed = SimpleWallet::new(Some(wif), BitcoinNetwork::Testnet)?;
+    assert_ne!(unpathed.get_new_address(None).await?.address, first.address);
+    
+    // Issued addresses survive a restart
+    let wallet = SimpleWallet::new(Some(wif), BitcoinNetwork::Testnet)?
+        .with_derivation_path(path)?
+        .with_address_store(storage.clone())
+        .await?;
+    let addresses = wallet.list_addresses().await?;
+    assert_eq!(addresses.len(), 1);
+    assert_eq!(addresses[0].label.as_deref(), Some("exchange"));
+    assert_eq!(wallet.get_new_address(None).await?.index, 1);
+    
+    // A wallet with another path refuses the stored book
+    let result = SimpleWallet::new(Some(wif), BitcoinNetwork::Testnet)?
+        .with_address_store(storage)
+        .await;
+    assert!(result.is_err());
+    
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Compatibility of the P2P payloads with earlier releases
+//!
+//! The files in `tests/wire_fixtures` are payloads as earlier releases sent them.
+//! They must keep decoding, or keep being rejected for the same reason, as the
+//! schemas evolve. Add a fixture whenever a payload's version changes.
+
+use darkswap_sdk::{
+    orderbook::{OrderMessage, OrderSide},
+    p2p::rfq::RfqMessage,
+    trade::{protocol::SignedTradeMessage, TradeMessage},
+    types::Asset,
+};
+use darkswap_support::envelope::{self, EnvelopeError, Versioned};
+use rust_decimal_macros::dec;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("wire_fixtures").join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+#[test]
+fn test_order_message_without_envelope() {
+    let message: OrderMessage = envelope::decode(&fixture("order_message_legacy.json")).unwrap();
+
+    match message {
+        OrderMessage::NewOrder(order) => {
+            assert_eq!(order.id.0, "order-1");
+            assert_eq!(order.quote_asset, Asset::Rune(1));
+            assert_eq!(order.side, OrderSide::Sell);
+            assert_eq!(order.amount, dec!(0.5));
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+}
+
+#[test]
+fn test_order_message_v1() {
+    let message: OrderMessage = envelope::decode(&fixture("order_message_v1.json")).unwrap();
+
+    assert!(matches!(message, OrderMessage::CancelOrder { order_id, .. } if order_id.0 == "order-1"));
+}
+
+#[test]
+fn test_newer_order_message_with_unknown_fields() {
+    let message: OrderMessage = envelope::decode(&fixture("order_message_v2_unknown_fields.json")).unwrap();
+
+    match message {
+        OrderMessage::UpdateOrder { amount, .. } => assert_eq!(amount, dec!(0.25)),
+        other => panic!("unexpected message: {:?}", other),
+    }
+}
+
+#[test]
+fn test_signed_trade_message_v2() {
+    let message: SignedTradeMessage = envelope::decode(&fixture("trade_message_v2.json")).unwrap();
+
+    assert_eq!(message.nonce, 42);
+    // The signature covers the message as the current schema serializes it
+    message.verify().unwrap();
+    match message.message {
+        // Sent before payout addresses were carried in the protocol
+        TradeMessage::Initialize { payout_address, amount, .. } => {
+            assert_eq!(payout_address, None);
+            assert_eq!(amount, dec!(0.5));
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+}
+
+#[test]
+fn test_trade_message_v1_is_rejected() {
+    let result = envelope::decode::<SignedTradeMessage>(&fixture("trade_message_v1.json"));
+
+    assert!(matches!(result, Err(EnvelopeError::Obsolete { version: 1, .. })), "{:?}", result);
+}
+
+#[test]
+fn test_unsigned_trade_message_is_rejected() {
+    let result = envelope::decode::<SignedTradeMessage>(&fixture("trade_message_legacy.json"));
+
+    assert!(matches!(result, Err(EnvelopeError::Obsolete { version: 1, .. })), "{:?}", result);
+}
+
+#[test]
+fn test_rfq_message_v1() {
+    let message: RfqMessage = envelope::decode(&fixture("rfq_message_v1.json")).unwrap();
+
+    match message {
+        RfqMessage::Request(request) => {
+            assert_eq!(request.side, OrderSide::Buy);
+            assert_eq!(request.amount, dec!(50));
+        }
+        other => panic!("unexpected message: {:?}", other),
+    }
+}
+
+#[test]
+fn test_fixtures_match_current_versions() {
+    // A version bump must come with a fixture of the previous version
+    assert_eq!(OrderMessage::VERSION, 1);
+    assert_eq!(SignedTradeMessage::VERSION, 2);
+    assert_eq!(RfqMessage::VERSION, 1);
+}
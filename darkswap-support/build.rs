@@ -1,4 +1,10 @@
 fn main() {
+    #[cfg(feature = "proto")]
+    compile_protos();
+}
+
+#[cfg(feature = "proto")]
+fn compile_protos() {
     let mut config = prost_build::Config::new();
     config.type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]");
     
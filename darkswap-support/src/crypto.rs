@@ -0,0 +1,183 @@
+//! Bulk signature verification
+//!
+//! Syncing an orderbook snapshot means checking thousands of maker signatures.
+//! A [`BulkVerifier`] collects them and checks them one after the other,
+//! sharing one secp256k1 verification context across the run. Callers spread runs over
+//! blocking threads; the verifier itself works on the calling thread.
+//!
+//! Every signature is checked on its own, so a run gives exactly the verdicts
+//! single checks would. No scheme gets a batch equation: batched Ed25519
+//! checks use the cofactored equation and accept crafted signatures that
+//! single checks reject, and libsecp256k1 has no batch verification of ECDSA
+//! or Schnorr signatures.
+
+use ed25519_dalek::Verifier;
+use secp256k1::{ecdsa, schnorr, Message, Secp256k1, VerifyOnly, XOnlyPublicKey};
+use sha2::{Digest, Sha256};
+
+/// Signature scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scheme {
+    /// Ed25519 over the message; 32-byte keys, 64-byte signatures
+    Ed25519,
+    /// ECDSA on secp256k1 over the SHA-256 of the message; compressed or
+    /// uncompressed keys, DER signatures
+    EcdsaSecp256k1,
+    /// BIP-340 Schnorr on secp256k1 over the SHA-256 of the message; x-only
+    /// keys, 64-byte signatures
+    SchnorrSecp256k1,
+}
+
+/// Signature waiting to be checked
+#[derive(Debug, Clone)]
+struct Item {
+    /// Scheme
+    scheme: Scheme,
+    /// Encoded public key
+    public_key: Vec<u8>,
+    /// Signed message
+    message: Vec<u8>,
+    /// Encoded signature
+    signature: Vec<u8>,
+}
+
+/// Collects signatures and checks each of them on its own
+#[derive(Debug, Clone, Default)]
+pub struct BulkVerifier {
+    /// Signatures, in the order they were added
+    items: Vec<Item>,
+}
+
+impl BulkVerifier {
+    /// Create an empty verifier
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a signature, returning its index in the results
+    pub fn push(&mut self, scheme: Scheme, public_key: &[u8], message: &[u8], signature: &[u8]) -> usize {
+        self.items.push(Item {
+            scheme,
+            public_key: public_key.to_vec(),
+            message: message.to_vec(),
+            signature: signature.to_vec(),
+        });
+        self.items.len() - 1
+    }
+
+    /// Get the number of signatures added
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Check if no signature was added
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Check every signature, returning whether each is valid, in the order they were added
+    ///
+    /// Keys and signatures that do not decode are invalid.
+    pub fn verify(&self) -> Vec<bool> {
+        let secp = Secp256k1::verification_only();
+
+        self.items
+            .iter()
+            .map(|item| match item.scheme {
+                Scheme::Ed25519 => verify_ed25519(item),
+                Scheme::EcdsaSecp256k1 => verify_ecdsa(&secp, item),
+                Scheme::SchnorrSecp256k1 => verify_schnorr(&secp, item),
+            })
+            .collect()
+    }
+}
+
+/// Check an Ed25519 signature
+fn verify_ed25519(item: &Item) -> bool {
+    let Ok(public_key) = ed25519_dalek::PublicKey::from_bytes(&item.public_key) else { return false };
+    let Ok(signature) = ed25519_dalek::Signature::try_from(item.signature.as_slice()) else { return false };
+
+    public_key.verify(&item.message, &signature).is_ok()
+}
+
+/// Get the message secp256k1 signatures commit to
+fn secp256k1_message(item: &Item) -> Option<Message> {
+    Message::from_slice(&Sha256::digest(&item.message)).ok()
+}
+
+/// Check an ECDSA signature
+fn verify_ecdsa(secp: &Secp256k1<VerifyOnly>, item: &Item) -> bool {
+    let Ok(public_key) = secp256k1::PublicKey::from_slice(&item.public_key) else { return false };
+    let Ok(signature) = ecdsa::Signature::from_der(&item.signature) else { return false };
+    let Some(message) = secp256k1_message(item) else { return false };
+
+    secp.verify_ecdsa(&message, &signature, &public_key).is_ok()
+}
+
+/// Check a Schnorr signature
+fn verify_schnorr(secp: &Secp256k1<VerifyOnly>, item: &Item) -> bool {
+    let Ok(public_key) = XOnlyPublicKey::from_slice(&item.public_key) else { return false };
+    let Ok(signature) = schnorr::Signature::from_slice(&item.signature) else { return false };
+    let Some(message) = secp256k1_message(item) else { return false };
+
+    secp.verify_schnorr(&signature, &message, &public_key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Signer;
+    use secp256k1::{KeyPair, SecretKey};
+
+    fn ed25519_keypair(seed: u8) -> ed25519_dalek::Keypair {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        ed25519_dalek::Keypair { secret, public }
+    }
+
+    #[test]
+    fn test_ed25519_flags_bad_signatures() {
+        let mut verifier = BulkVerifier::new();
+        let mut expected = Vec::new();
+
+        for n in 0..200 {
+            let keypair = ed25519_keypair((n % 7) as u8 + 1);
+            let message = format!("order {}", n).into_bytes();
+            let mut signature = keypair.sign(&message).to_bytes().to_vec();
+            let good = n % 50 != 3;
+            if !good {
+                signature[0] ^= 1;
+            }
+            verifier.push(Scheme::Ed25519, keypair.public.as_bytes(), &message, &signature);
+            expected.push(good);
+        }
+
+        // Undecodable keys are invalid
+        verifier.push(Scheme::Ed25519, &[0u8; 5], b"order", &[0u8; 64]);
+        expected.push(false);
+
+        assert_eq!(verifier.verify(), expected);
+    }
+
+    #[test]
+    fn test_secp256k1_signatures() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let message = b"order";
+        let digest = Message::from_slice(&Sha256::digest(message)).unwrap();
+
+        let public_key = secp256k1::PublicKey::from_secret_key(&secp, &secret_key);
+        let ecdsa = secp.sign_ecdsa(&digest, &secret_key).serialize_der();
+        let keypair = KeyPair::from_secret_key(&secp, &secret_key);
+        let schnorr = secp.sign_schnorr_no_aux_rand(&digest, &keypair);
+        let x_only = keypair.x_only_public_key().0.serialize();
+
+        let mut verifier = BulkVerifier::new();
+        verifier.push(Scheme::EcdsaSecp256k1, &public_key.serialize(), message, &ecdsa);
+        verifier.push(Scheme::EcdsaSecp256k1, &public_key.serialize(), b"other", &ecdsa);
+        verifier.push(Scheme::SchnorrSecp256k1, &x_only, message, schnorr.as_ref());
+        verifier.push(Scheme::SchnorrSecp256k1, &x_only, b"other", schnorr.as_ref());
+
+        assert_eq!(verifier.verify(), vec![true, false, true, false]);
+    }
+}
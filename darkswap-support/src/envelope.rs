@@ -0,0 +1,247 @@
+//! Versioned envelopes for P2P payloads
+//!
+//! Every gossip and request-response payload is sent in an envelope naming its
+//! kind and the version of its schema. Readers handle three cases:
+//!
+//! - Same version: the payload is decoded as is.
+//! - Older version: the payload is passed through the type's upgrade shims, one
+//!   version at a time, until it has the current schema.
+//! - Newer version: the payload is decoded if the writer says readers of our
+//!   version can still understand it (`min_version`). Fields we do not know are
+//!   ignored, so a writer may add optional fields without breaking older readers.
+//!
+//! Payloads sent before envelopes were introduced carry no envelope at all; they
+//! are read as the type's legacy version.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+/// Envelope error
+#[derive(Debug, Error)]
+pub enum EnvelopeError {
+    /// The envelope holds another kind of payload
+    #[error("Expected a {expected} payload, got {actual}")]
+    WrongKind {
+        /// Kind expected by the reader
+        expected: &'static str,
+        /// Kind in the envelope
+        actual: String,
+    },
+    /// The payload needs a newer reader
+    #[error("{kind} version {version} needs a reader of version {min_version} or later, this one is {supported}")]
+    TooNew {
+        /// Payload kind
+        kind: &'static str,
+        /// Payload version
+        version: u32,
+        /// Oldest reader version that understands the payload
+        min_version: u32,
+        /// Version of this reader
+        supported: u32,
+    },
+    /// The payload has a version that can no longer be read
+    #[error("{kind} version {version} is no longer supported: {reason}")]
+    Obsolete {
+        /// Payload kind
+        kind: &'static str,
+        /// Payload version
+        version: u32,
+        /// Why the version cannot be upgraded
+        reason: String,
+    },
+    /// The payload or envelope could not be (de)serialized
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Payload with a versioned schema
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Payload kind, unique across the protocol
+    const KIND: &'static str;
+
+    /// Current schema version
+    const VERSION: u32;
+
+    /// Oldest reader version that can read payloads written now
+    ///
+    /// Raise it to `VERSION` when a change cannot be read by older readers, for
+    /// example when a required field is added or a field changes meaning.
+    const MIN_VERSION: u32 = 1;
+
+    /// Version of payloads sent without an envelope
+    const LEGACY_VERSION: u32 = 1;
+
+    /// Upgrade a payload of version `from` to version `from + 1`
+    ///
+    /// Types whose schema changed override this for every older version they
+    /// still accept.
+    fn upgrade(from: u32, payload: Value) -> Result<Value, EnvelopeError> {
+        let _ = payload;
+        Err(EnvelopeError::Obsolete {
+            kind: Self::KIND,
+            version: from,
+            reason: "no upgrade shim".to_string(),
+        })
+    }
+}
+
+/// Versioned envelope
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Payload kind
+    pub kind: String,
+    /// Schema version of the payload
+    pub version: u32,
+    /// Oldest reader version that can read the payload
+    pub min_version: u32,
+    /// Payload
+    pub payload: Value,
+}
+
+impl Envelope {
+    /// Wrap a payload in an envelope of its current version
+    pub fn wrap<T: Versioned>(payload: &T) -> Result<Self, EnvelopeError> {
+        Ok(Self {
+            kind: T::KIND.to_string(),
+            version: T::VERSION,
+            min_version: T::MIN_VERSION,
+            payload: serde_json::to_value(payload)?,
+        })
+    }
+
+    /// Open the envelope, upgrading older payloads to the current schema
+    pub fn open<T: Versioned>(self) -> Result<T, EnvelopeError> {
+        if self.kind != T::KIND {
+            return Err(EnvelopeError::WrongKind { expected: T::KIND, actual: self.kind });
+        }
+
+        if self.version > T::VERSION && self.min_version > T::VERSION {
+            return Err(EnvelopeError::TooNew {
+                kind: T::KIND,
+                version: self.version,
+                min_version: self.min_version,
+                supported: T::VERSION,
+            });
+        }
+
+        let mut payload = self.payload;
+        for version in self.version..T::VERSION {
+            payload = T::upgrade(version, payload)?;
+        }
+
+        Ok(serde_json::from_value(payload)?)
+    }
+}
+
+/// Encode a payload in an envelope of its current version
+pub fn encode<T: Versioned>(payload: &T) -> Result<Vec<u8>, EnvelopeError> {
+    Ok(serde_json::to_vec(&Envelope::wrap(payload)?)?)
+}
+
+/// Decode a payload, with or without an envelope
+pub fn decode<T: Versioned>(data: &[u8]) -> Result<T, EnvelopeError> {
+    let value: Value = serde_json::from_slice(data)?;
+    if is_envelope(&value) {
+        return serde_json::from_value::<Envelope>(value)?.open();
+    }
+
+    // Sent by a release without envelopes
+    Envelope {
+        kind: T::KIND.to_string(),
+        version: T::LEGACY_VERSION,
+        min_version: 1,
+        payload: value,
+    }
+    .open()
+}
+
+/// Check if a value is an envelope rather than a bare payload
+fn is_envelope(value: &Value) -> bool {
+    match value.as_object() {
+        Some(object) => {
+            object.get("kind").map_or(false, Value::is_string)
+                && object.get("version").map_or(false, Value::is_u64)
+                && object.contains_key("payload")
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Version 3 of a test payload: `size` was called `amount` in version 1
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ping {
+        id: String,
+        size: u64,
+        #[serde(default)]
+        note: Option<String>,
+    }
+
+    impl Versioned for Ping {
+        const KIND: &'static str = "test/ping";
+        const VERSION: u32 = 3;
+
+        fn upgrade(from: u32, mut payload: Value) -> Result<Value, EnvelopeError> {
+            match from {
+                1 => {
+                    if let Some(object) = payload.as_object_mut() {
+                        if let Some(amount) = object.remove("amount") {
+                            object.insert("size".to_string(), amount);
+                        }
+                    }
+                    Ok(payload)
+                }
+                // Version 3 only added the optional note
+                2 => Ok(payload),
+                _ => Err(EnvelopeError::Obsolete { kind: Self::KIND, version: from, reason: "unknown".to_string() }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let ping = Ping { id: "a".to_string(), size: 5, note: Some("hi".to_string()) };
+        let data = encode(&ping).unwrap();
+
+        let envelope: Envelope = serde_json::from_slice(&data).unwrap();
+        assert_eq!(envelope.kind, "test/ping");
+        assert_eq!(envelope.version, 3);
+        assert_eq!(decode::<Ping>(&data).unwrap(), ping);
+    }
+
+    #[test]
+    fn test_legacy_payload_is_upgraded() {
+        let data = serde_json::to_vec(&json!({ "id": "a", "amount": 5 })).unwrap();
+
+        assert_eq!(decode::<Ping>(&data).unwrap(), Ping { id: "a".to_string(), size: 5, note: None });
+    }
+
+    #[test]
+    fn test_newer_payload_with_unknown_fields_is_read() {
+        let data = serde_json::to_vec(&json!({
+            "kind": "test/ping",
+            "version": 4,
+            "min_version": 3,
+            "payload": { "id": "a", "size": 5, "priority": "high" },
+            "trace": "ignored"
+        }))
+        .unwrap();
+
+        assert_eq!(decode::<Ping>(&data).unwrap(), Ping { id: "a".to_string(), size: 5, note: None });
+    }
+
+    #[test]
+    fn test_incompatible_payload_is_rejected() {
+        let too_new = Envelope { kind: "test/ping".to_string(), version: 5, min_version: 5, payload: json!({}) };
+        assert!(matches!(too_new.open::<Ping>(), Err(EnvelopeError::TooNew { .. })));
+
+        let other = Envelope { kind: "test/pong".to_string(), version: 3, min_version: 1, payload: json!({}) };
+        assert!(matches!(other.open::<Ping>(), Err(EnvelopeError::WrongKind { .. })));
+    }
+}
@@ -1,3 +1,11 @@
+pub mod crypto;
+pub mod envelope;
+pub mod secrets;
+pub mod storage;
+pub mod systemd;
+pub mod vectors;
+
+#[cfg(feature = "proto")]
 pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/darkswap.p2p.rs"));
     include!(concat!(env!("OUT_DIR"), "/darkswap.orderbook.rs"));
@@ -0,0 +1,220 @@
+//! Secrets kept in the OS keyring
+//!
+//! Configuration values holding secrets, such as wallet mnemonics, JWT signing
+//! keys and relay tokens, may name a keyring entry instead of holding the
+//! secret: `"keyring:wallet-mnemonic"` is replaced by the password of the entry
+//! `wallet-mnemonic` of the `darkswap` service when the configuration is used.
+//! Any other value is taken literally.
+//!
+//! The OS keyring (Keychain, Credential Manager, Secret Service) is behind the
+//! `keyring` feature; without it, references fail to resolve.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use thiserror::Error;
+
+/// Keyring service the entries are stored under
+pub const KEYRING_SERVICE: &str = "darkswap";
+
+/// Prefix of configuration values naming a keyring entry
+pub const KEYRING_PREFIX: &str = "keyring:";
+
+/// Secret error
+#[derive(Debug, Error)]
+pub enum SecretError {
+    /// The entry does not exist
+    #[error("Secret not found: {0}")]
+    NotFound(String),
+    /// The keyring failed
+    #[error("Keyring error: {0}")]
+    Keyring(String),
+    /// No keyring is available in this build
+    #[error("OS keyring support is not compiled in")]
+    Unsupported,
+}
+
+/// Named secret store
+pub trait SecretStore: Send + Sync {
+    /// Get a secret
+    fn get(&self, name: &str) -> Result<String, SecretError>;
+
+    /// Set a secret, replacing any previous value
+    fn set(&self, name: &str, value: &str) -> Result<(), SecretError>;
+
+    /// Remove a secret, returning false if it did not exist
+    fn delete(&self, name: &str) -> Result<bool, SecretError>;
+}
+
+/// Secrets in the OS keyring
+#[cfg(feature = "keyring")]
+#[derive(Debug, Clone)]
+pub struct KeyringStore {
+    /// Service the entries are stored under
+    service: String,
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringStore {
+    /// Create a store for a keyring service
+    pub fn new(service: &str) -> Self {
+        Self { service: service.to_string() }
+    }
+
+    /// Get the keyring entry of a secret
+    fn entry(&self, name: &str) -> Result<keyring::Entry, SecretError> {
+        keyring::Entry::new(&self.service, name).map_err(|e| SecretError::Keyring(e.to_string()))
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl SecretStore for KeyringStore {
+    fn get(&self, name: &str) -> Result<String, SecretError> {
+        match self.entry(name)?.get_password() {
+            Ok(value) => Ok(value),
+            Err(keyring::Error::NoEntry) => Err(SecretError::NotFound(name.to_string())),
+            Err(e) => Err(SecretError::Keyring(e.to_string())),
+        }
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<(), SecretError> {
+        self.entry(name)?.set_password(value).map_err(|e| SecretError::Keyring(e.to_string()))
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, SecretError> {
+        match self.entry(name)?.delete_password() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) => Err(SecretError::Keyring(e.to_string())),
+        }
+    }
+}
+
+/// Secrets in memory, for tests and embedders with their own secret handling
+#[derive(Debug, Default)]
+pub struct MemorySecretStore {
+    /// Secrets by name
+    secrets: RwLock<HashMap<String, String>>,
+}
+
+impl MemorySecretStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SecretStore for MemorySecretStore {
+    fn get(&self, name: &str) -> Result<String, SecretError> {
+        self.secrets.read()
+            .map_err(|_| SecretError::Keyring("Secret store lock poisoned".to_string()))?
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SecretError::NotFound(name.to_string()))
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<(), SecretError> {
+        self.secrets.write()
+            .map_err(|_| SecretError::Keyring("Secret store lock poisoned".to_string()))?
+            .insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, name: &str) -> Result<bool, SecretError> {
+        Ok(self.secrets.write()
+            .map_err(|_| SecretError::Keyring("Secret store lock poisoned".to_string()))?
+            .remove(name)
+            .is_some())
+    }
+}
+
+/// Open the OS keyring
+pub fn default_store() -> Result<Arc<dyn SecretStore>, SecretError> {
+    #[cfg(feature = "keyring")]
+    {
+        Ok(Arc::new(KeyringStore::new(KEYRING_SERVICE)))
+    }
+    #[cfg(not(feature = "keyring"))]
+    {
+        Err(SecretError::Unsupported)
+    }
+}
+
+/// Get the keyring entry a configuration value names, if it names one
+pub fn keyring_entry(value: &str) -> Option<&str> {
+    value.strip_prefix(KEYRING_PREFIX).map(str::trim)
+}
+
+/// Resolve configuration values naming keyring entries
+///
+/// The keyring is only opened once a value names an entry, so configurations
+/// without references work where no keyring is available.
+pub struct SecretResolver {
+    /// Store, opened on first use
+    store: Option<Arc<dyn SecretStore>>,
+}
+
+impl SecretResolver {
+    /// Create a resolver on the OS keyring
+    pub fn new() -> Self {
+        Self { store: None }
+    }
+
+    /// Create a resolver on a given store
+    pub fn with_store(store: Arc<dyn SecretStore>) -> Self {
+        Self { store: Some(store) }
+    }
+
+    /// Resolve a value, returning it as is unless it names a keyring entry
+    pub fn resolve(&mut self, value: &str) -> Result<String, SecretError> {
+        let name = match keyring_entry(value) {
+            Some(name) => name,
+            None => return Ok(value.to_string()),
+        };
+
+        if self.store.is_none() {
+            self.store = Some(default_store()?);
+        }
+        match &self.store {
+            Some(store) => store.get(name),
+            None => Err(SecretError::Unsupported),
+        }
+    }
+
+    /// Resolve an optional value in place
+    pub fn resolve_in_place(&mut self, value: &mut Option<String>) -> Result<(), SecretError> {
+        if let Some(value) = value {
+            *value = self.resolve(value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for SecretResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_references() {
+        let store = Arc::new(MemorySecretStore::new());
+        store.set("wallet-mnemonic", "abandon abandon about").unwrap();
+        let mut resolver = SecretResolver::with_store(store.clone());
+
+        assert_eq!(resolver.resolve("keyring:wallet-mnemonic").unwrap(), "abandon abandon about");
+        assert_eq!(resolver.resolve("plain value").unwrap(), "plain value");
+        assert!(matches!(resolver.resolve("keyring:missing"), Err(SecretError::NotFound(_))));
+
+        let mut token = Some("keyring: wallet-mnemonic".to_string());
+        resolver.resolve_in_place(&mut token).unwrap();
+        assert_eq!(token.as_deref(), Some("abandon abandon about"));
+
+        assert!(store.delete("wallet-mnemonic").unwrap());
+        assert!(!store.delete("wallet-mnemonic").unwrap());
+    }
+}
@@ -0,0 +1,439 @@
+//! Encryption at rest for any storage backend
+//!
+//! `EncryptedStorage` wraps a backend and encrypts every value with
+//! XChaCha20-Poly1305 before it reaches the disk. Values are bound to their
+//! namespace and key, so a record cannot be moved to another key unnoticed.
+//! Keys stay in the clear to keep prefix scans ordered: subsystems put
+//! identifiers in keys and everything else in values.
+//!
+//! Values are encrypted with random data keys, which are stored in the backend
+//! wrapped by a master key. The master key is derived from a node passphrase
+//! with Argon2id, or supplied directly, for example from the OS keyring. Two
+//! rotations are supported:
+//!
+//! - Master key rotation rewraps the data keys; no record is rewritten.
+//! - Data key rotation re-encrypts every record under a new data key. Records
+//!   name the data key they were written with and old data keys are kept until
+//!   the last record is rewritten, so an interrupted rotation loses nothing and
+//!   is completed by rotating again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use super::{Storage, StorageError, StorageExt};
+
+/// Namespace holding the wrapped data keys, hidden from users of the storage
+pub const KEYRING_NAMESPACE: &str = "__encryption";
+
+/// Key of the keyring document
+const KEYRING_KEY: &str = "keyring";
+
+/// Format version of encrypted values
+const VALUE_VERSION: u8 = 1;
+
+/// Nonce length of XChaCha20-Poly1305
+const NONCE_LEN: usize = 24;
+
+/// Length of the value header: version, data key ID and nonce
+const HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+
+/// Master key of an encrypted storage
+#[derive(Clone)]
+pub enum MasterKey {
+    /// Node passphrase, stretched with Argon2id
+    Passphrase(String),
+    /// 32 random bytes, for example kept in the OS keyring
+    Raw([u8; 32]),
+}
+
+impl MasterKey {
+    /// Generate a random raw master key
+    pub fn generate() -> Self {
+        Self::Raw(random_key())
+    }
+
+    /// Parse a hex encoded raw master key
+    pub fn from_hex(key: &str) -> Result<Self, StorageError> {
+        let bytes = hex::decode(key.trim())
+            .map_err(|e| StorageError::Encryption(format!("Invalid master key: {}", e)))?;
+        let key: [u8; 32] = bytes.try_into()
+            .map_err(|_| StorageError::Encryption("Master key must be 32 bytes".to_string()))?;
+
+        Ok(Self::Raw(key))
+    }
+
+    /// Derive the key wrapping the data keys
+    pub(crate) fn derive(&self, salt: &[u8]) -> Result<[u8; 32], StorageError> {
+        match self {
+            Self::Passphrase(passphrase) => {
+                let mut key = [0u8; 32];
+                Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(|e| StorageError::Encryption(format!("Failed to derive storage key: {}", e)))?;
+                Ok(key)
+            }
+            Self::Raw(key) => Ok(*key),
+        }
+    }
+}
+
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Passphrase(_) => write!(f, "MasterKey::Passphrase(..)"),
+            Self::Raw(_) => write!(f, "MasterKey::Raw(..)"),
+        }
+    }
+}
+
+/// Data key wrapped by the master key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WrappedKey {
+    /// Nonce (hex)
+    nonce: String,
+    /// Encrypted data key (hex)
+    ciphertext: String,
+}
+
+/// Keyring document stored in the backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyringDocument {
+    /// Salt of the passphrase derivation (hex)
+    salt: String,
+    /// Data key new records are written with
+    current: u32,
+    /// Wrapped data keys by ID
+    data_keys: BTreeMap<u32, WrappedKey>,
+}
+
+/// Unwrapped data keys
+struct Keyring {
+    /// Data key new records are written with
+    current: u32,
+    /// Data keys by ID
+    keys: HashMap<u32, [u8; 32]>,
+}
+
+/// Storage encrypting values at rest
+pub struct EncryptedStorage {
+    /// Backend
+    inner: Arc<dyn Storage>,
+    /// Data keys
+    keyring: RwLock<Keyring>,
+}
+
+impl EncryptedStorage {
+    /// Open encrypted storage on a backend
+    ///
+    /// A backend without a keyring is set up for encryption, as long as it holds
+    /// no records yet. Fails if the master key does not unwrap the data keys.
+    pub fn open(inner: Arc<dyn Storage>, master_key: &MasterKey) -> Result<Self, StorageError> {
+        let keyring = match inner.get_json::<KeyringDocument>(KEYRING_NAMESPACE, KEYRING_KEY)? {
+            Some(document) => unwrap_keyring(&document, master_key)?,
+            None => {
+                if inner.namespaces()?.iter().any(|namespace| namespace != KEYRING_NAMESPACE) {
+                    return Err(StorageError::Encryption(
+                        "Storage already holds unencrypted records; encryption must be enabled on empty storage".to_string(),
+                    ));
+                }
+
+                let keyring = Keyring {
+                    current: 1,
+                    keys: HashMap::from([(1, random_key())]),
+                };
+                save_keyring(inner.as_ref(), &keyring, master_key)?;
+                keyring
+            }
+        };
+
+        Ok(Self { inner, keyring: RwLock::new(keyring) })
+    }
+
+    /// Rewrap the data keys with a new master key
+    pub fn rotate_master_key(&self, new_master_key: &MasterKey) -> Result<(), StorageError> {
+        let keyring = self.keyring.read().map_err(poisoned)?;
+        save_keyring(self.inner.as_ref(), &keyring, new_master_key)
+    }
+
+    /// Re-encrypt every record under a new data key
+    ///
+    /// The master key is needed to save the new data key.
+    pub fn rotate_data_key(&self, master_key: &MasterKey) -> Result<(), StorageError> {
+        // Check the master key before anything is written with it
+        let document = self.inner.get_json::<KeyringDocument>(KEYRING_NAMESPACE, KEYRING_KEY)?
+            .ok_or_else(|| StorageError::Encryption("Storage keyring is missing".to_string()))?;
+        unwrap_keyring(&document, master_key)?;
+
+        // Add the new key first: records are readable at every step
+        let current = {
+            let mut keyring = self.keyring.write().map_err(poisoned)?;
+            let current = keyring.keys.keys().max().copied().unwrap_or(0) + 1;
+            keyring.keys.insert(current, random_key());
+            keyring.current = current;
+            save_keyring(self.inner.as_ref(), &keyring, master_key)?;
+            current
+        };
+
+        for namespace in self.namespaces()? {
+            for (key, value) in self.inner.scan_prefix(&namespace, &[])? {
+                if data_key_id(&value)? != current {
+                    let plaintext = self.decrypt(&namespace, &key, &value)?;
+                    self.put(&namespace, &key, &plaintext)?;
+                }
+            }
+        }
+        self.inner.flush()?;
+
+        // Every record is under the new key: drop the old ones
+        let mut keyring = self.keyring.write().map_err(poisoned)?;
+        keyring.keys.retain(|id, _| *id == current);
+        save_keyring(self.inner.as_ref(), &keyring, master_key)
+    }
+
+    /// Encrypt a value for a namespace and key
+    fn encrypt(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let keyring = self.keyring.read().map_err(poisoned)?;
+        let data_key = keyring.keys.get(&keyring.current)
+            .ok_or_else(|| StorageError::Encryption("Current data key is missing".to_string()))?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = XChaCha20Poly1305::new(data_key.into());
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: value, aad: &associated_data(namespace, key) })
+            .map_err(|_| StorageError::Encryption("Failed to encrypt record".to_string()))?;
+
+        let mut sealed = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        sealed.push(VALUE_VERSION);
+        sealed.extend_from_slice(&keyring.current.to_be_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt a value read from a namespace and key
+    fn decrypt(&self, namespace: &str, key: &[u8], sealed: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let id = data_key_id(sealed)?;
+        let keyring = self.keyring.read().map_err(poisoned)?;
+        let data_key = keyring.keys.get(&id)
+            .ok_or_else(|| StorageError::Encryption(format!("Record is encrypted with unknown data key {}", id)))?;
+
+        let cipher = XChaCha20Poly1305::new(data_key.into());
+        cipher
+            .decrypt(
+                XNonce::from_slice(&sealed[5..HEADER_LEN]),
+                Payload { msg: &sealed[HEADER_LEN..], aad: &associated_data(namespace, key) },
+            )
+            .map_err(|_| StorageError::Encryption("Record does not decrypt; it was tampered with or moved".to_string()))
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        check_namespace(namespace)?;
+        match self.inner.get(namespace, key)? {
+            Some(sealed) => Ok(Some(self.decrypt(namespace, key, &sealed)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        check_namespace(namespace)?;
+        let sealed = self.encrypt(namespace, key, value)?;
+        self.inner.put(namespace, key, &sealed)
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, StorageError> {
+        check_namespace(namespace)?;
+        self.inner.delete(namespace, key)
+    }
+
+    fn scan_prefix(&self, namespace: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        check_namespace(namespace)?;
+        self.inner.scan_prefix(namespace, prefix)?
+            .into_iter()
+            .map(|(key, sealed)| {
+                let value = self.decrypt(namespace, &key, &sealed)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self.inner.namespaces()?
+            .into_iter()
+            .filter(|namespace| namespace != KEYRING_NAMESPACE)
+            .collect())
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.inner.flush()
+    }
+}
+
+/// Reject access to the keyring namespace
+fn check_namespace(namespace: &str) -> Result<(), StorageError> {
+    if namespace == KEYRING_NAMESPACE {
+        return Err(StorageError::Encryption(format!("Namespace {} is reserved", KEYRING_NAMESPACE)));
+    }
+    Ok(())
+}
+
+/// Bind a value to where it is stored
+fn associated_data(namespace: &str, key: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(namespace.len() + 1 + key.len());
+    aad.extend_from_slice(namespace.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(key);
+    aad
+}
+
+/// Get the ID of the data key a value was encrypted with
+fn data_key_id(sealed: &[u8]) -> Result<u32, StorageError> {
+    if sealed.len() < HEADER_LEN || sealed[0] != VALUE_VERSION {
+        return Err(StorageError::Encryption("Record is not encrypted or has an unknown format".to_string()));
+    }
+    Ok(u32::from_be_bytes([sealed[1], sealed[2], sealed[3], sealed[4]]))
+}
+
+/// Generate a random 32-byte key
+fn random_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
+
+/// Unwrap the data keys of a keyring document
+fn unwrap_keyring(document: &KeyringDocument, master_key: &MasterKey) -> Result<Keyring, StorageError> {
+    let salt = hex::decode(&document.salt)
+        .map_err(|e| StorageError::Encryption(format!("Invalid keyring salt: {}", e)))?;
+    let cipher = XChaCha20Poly1305::new((&master_key.derive(&salt)?).into());
+
+    let keys = document.data_keys
+        .iter()
+        .map(|(id, wrapped)| {
+            let nonce = hex::decode(&wrapped.nonce)
+                .ok()
+                .filter(|nonce| nonce.len() == NONCE_LEN)
+                .ok_or_else(|| StorageError::Encryption("Invalid keyring nonce".to_string()))?;
+            let ciphertext = hex::decode(&wrapped.ciphertext)
+                .map_err(|e| StorageError::Encryption(format!("Invalid keyring: {}", e)))?;
+
+            let key = cipher
+                .decrypt(XNonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &id.to_be_bytes() })
+                .map_err(|_| StorageError::Encryption("Wrong storage passphrase or key".to_string()))?;
+            let key: [u8; 32] = key.try_into()
+                .map_err(|_| StorageError::Encryption("Invalid data key length".to_string()))?;
+
+            Ok((*id, key))
+        })
+        .collect::<Result<HashMap<_, _>, StorageError>>()?;
+
+    if !keys.contains_key(&document.current) {
+        return Err(StorageError::Encryption("Current data key is missing from the keyring".to_string()));
+    }
+
+    Ok(Keyring { current: document.current, keys })
+}
+
+/// Wrap the data keys with a master key and store them, under a fresh salt
+fn save_keyring(inner: &dyn Storage, keyring: &Keyring, master_key: &MasterKey) -> Result<(), StorageError> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let cipher = XChaCha20Poly1305::new((&master_key.derive(&salt)?).into());
+
+    let data_keys = keyring.keys
+        .iter()
+        .map(|(id, key)| {
+            let mut nonce = [0u8; NONCE_LEN];
+            rand::thread_rng().fill_bytes(&mut nonce);
+            let ciphertext = cipher
+                .encrypt(XNonce::from_slice(&nonce), Payload { msg: key, aad: &id.to_be_bytes() })
+                .map_err(|_| StorageError::Encryption("Failed to wrap data key".to_string()))?;
+
+            Ok((*id, WrappedKey { nonce: hex::encode(nonce), ciphertext: hex::encode(ciphertext) }))
+        })
+        .collect::<Result<BTreeMap<_, _>, StorageError>>()?;
+
+    let document = KeyringDocument {
+        salt: hex::encode(salt),
+        current: keyring.current,
+        data_keys,
+    };
+    inner.put_json(KEYRING_NAMESPACE, KEYRING_KEY, &document)?;
+    inner.flush()
+}
+
+/// Error for a lock poisoned by a panicking writer
+fn poisoned<T>(_: T) -> StorageError {
+    StorageError::Backend("Storage keyring lock poisoned".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn passphrase(passphrase: &str) -> MasterKey {
+        MasterKey::Passphrase(passphrase.to_string())
+    }
+
+    #[test]
+    fn test_encrypted_storage() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let storage = EncryptedStorage::open(inner.clone(), &passphrase("correct horse")).unwrap();
+        crate::storage::conformance::check(&storage);
+
+        storage.put("trades", b"trade/1", b"secret trade").unwrap();
+        let sealed = inner.get("trades", b"trade/1").unwrap().unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"secret"));
+
+        // A record moved to another key does not decrypt
+        inner.put("trades", b"trade/2", &sealed).unwrap();
+        assert!(storage.get("trades", b"trade/2").is_err());
+
+        assert!(storage.get(KEYRING_NAMESPACE, KEYRING_KEY.as_bytes()).is_err());
+        assert!(!storage.namespaces().unwrap().contains(&KEYRING_NAMESPACE.to_string()));
+
+        assert!(EncryptedStorage::open(inner.clone(), &passphrase("wrong")).is_err());
+        let reopened = EncryptedStorage::open(inner, &passphrase("correct horse")).unwrap();
+        assert_eq!(reopened.get("trades", b"trade/1").unwrap(), Some(b"secret trade".to_vec()));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let old_key = passphrase("old");
+        let new_key = MasterKey::generate();
+
+        let storage = EncryptedStorage::open(inner.clone(), &old_key).unwrap();
+        storage.put("orders", b"order/1", b"one").unwrap();
+
+        storage.rotate_master_key(&new_key).unwrap();
+        assert!(EncryptedStorage::open(inner.clone(), &old_key).is_err());
+
+        assert!(storage.rotate_data_key(&old_key).is_err());
+        let old_sealed = inner.get("orders", b"order/1").unwrap().unwrap();
+        storage.rotate_data_key(&new_key).unwrap();
+        let new_sealed = inner.get("orders", b"order/1").unwrap().unwrap();
+        assert_eq!(data_key_id(&old_sealed).unwrap(), 1);
+        assert_eq!(data_key_id(&new_sealed).unwrap(), 2);
+
+        let reopened = EncryptedStorage::open(inner, &new_key).unwrap();
+        assert_eq!(reopened.get("orders", b"order/1").unwrap(), Some(b"one".to_vec()));
+    }
+
+    #[test]
+    fn test_refuses_unencrypted_records() {
+        let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        inner.put("orders", b"order/1", b"plain").unwrap();
+        assert!(EncryptedStorage::open(inner, &passphrase("passphrase")).is_err());
+    }
+}
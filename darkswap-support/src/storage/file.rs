@@ -0,0 +1,288 @@
+//! Documents kept in files of their own
+//!
+//! State a standby node takes over through a shared directory lives in files
+//! rather than in a `Storage`. `DocumentFile` reads and writes one such file
+//! as a whole JSON document, replacing it atomically. Given a master key it
+//! seals the file with XChaCha20-Poly1305, so the files are protected like the
+//! records of an `EncryptedStorage` opened with the same key.
+//!
+//! A sealed file carries the salt its key was derived with, so another node
+//! with the same passphrase opens it. The file name is bound to the contents:
+//! a sealed file renamed to another store does not open.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{MasterKey, StorageError};
+
+/// Marker and format version starting a sealed file
+const MAGIC: &[u8; 6] = b"DSENC\x01";
+
+/// Length of the salt the file key is derived with
+const SALT_LEN: usize = 16;
+
+/// Nonce length of XChaCha20-Poly1305
+const NONCE_LEN: usize = 24;
+
+/// Length of the header of a sealed file: marker, salt and nonce
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+/// JSON document kept in a file
+pub struct DocumentFile {
+    /// Path of the file
+    path: PathBuf,
+    /// Master key sealing the file; none keeps it in the clear
+    master_key: Option<MasterKey>,
+    /// Salt and key derived from the master key, once derived
+    derived: Mutex<Option<([u8; SALT_LEN], [u8; 32])>>,
+}
+
+impl DocumentFile {
+    /// Create a document kept in the clear at `path`
+    pub fn plain<P: AsRef<Path>>(path: P) -> Self {
+        Self::new(path, None)
+    }
+
+    /// Create a document at `path`, sealed with `master_key` if there is one
+    pub fn new<P: AsRef<Path>>(path: P, master_key: Option<MasterKey>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            master_key,
+            derived: Mutex::new(None),
+        }
+    }
+
+    /// Get the path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file is sealed
+    pub fn is_sealed(&self) -> bool {
+        self.master_key.is_some()
+    }
+
+    /// Read the document; a missing file holds none
+    ///
+    /// A sealed file is refused without a master key, and a file in the clear
+    /// with one: it was not written by this node's stores.
+    pub fn load<T: DeserializeOwned>(&self) -> Result<Option<T>, StorageError> {
+        match self.load_bytes()? {
+            Some(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Write the document, replacing the file atomically
+    pub fn save<T: Serialize>(&self, document: &T) -> Result<(), StorageError> {
+        self.save_bytes(&serde_json::to_vec_pretty(document)?)
+    }
+
+    /// Delete the file, if it exists
+    pub fn remove(&self) -> Result<(), StorageError> {
+        if self.path.exists() {
+            fs::remove_file(&self.path).map_err(|e| self.io_error("remove", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Seal the file with a new master key
+    ///
+    /// The file is read with the current key and rewritten under the new one,
+    /// which is returned as the document to use from now on. A missing file is
+    /// left missing.
+    pub fn reseal(&self, new_master_key: &MasterKey) -> Result<Self, StorageError> {
+        let resealed = Self::new(&self.path, Some(new_master_key.clone()));
+        if let Some(contents) = self.load_bytes()? {
+            resealed.save_bytes(&contents)?;
+        }
+
+        Ok(resealed)
+    }
+
+    /// Read the plaintext of the file
+    fn load_bytes(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read(&self.path).map_err(|e| self.io_error("read", e))?;
+        let sealed = contents.starts_with(MAGIC);
+
+        match &self.master_key {
+            None if sealed => Err(StorageError::Encryption(format!(
+                "{} is encrypted but storage encryption is not configured",
+                self.path.display()
+            ))),
+            None => Ok(Some(contents)),
+            Some(_) if !sealed => Err(StorageError::Encryption(format!(
+                "{} is not encrypted; encrypted stores must start empty",
+                self.path.display()
+            ))),
+            Some(_) => self.open(&contents).map(Some),
+        }
+    }
+
+    /// Write plaintext to the file, sealing it if there is a master key
+    fn save_bytes(&self, contents: &[u8]) -> Result<(), StorageError> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).map_err(|e| self.io_error("create the directory of", e))?;
+            }
+        }
+
+        let contents = match self.master_key {
+            Some(_) => self.seal(contents)?,
+            None => contents.to_vec(),
+        };
+
+        // Write to a temporary file first so a crash never leaves a truncated file
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, contents).map_err(|e| self.io_error("write", e))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| self.io_error("replace", e))
+    }
+
+    /// Encrypt plaintext, under the salt of the last file read or written
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, StorageError> {
+        let salt = match *self.derived.lock().map_err(poisoned)? {
+            Some((salt, _)) => salt,
+            None => {
+                let mut salt = [0u8; SALT_LEN];
+                rand::thread_rng().fill_bytes(&mut salt);
+                salt
+            }
+        };
+        let cipher = XChaCha20Poly1305::new((&self.key(&salt)?).into());
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: &self.associated_data() })
+            .map_err(|_| StorageError::Encryption(format!("Failed to encrypt {}", self.path.display())))?;
+
+        let mut sealed = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&salt);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt the contents of a sealed file
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, StorageError> {
+        if sealed.len() < HEADER_LEN {
+            return Err(StorageError::Encryption(format!("{} is truncated", self.path.display())));
+        }
+        let salt: [u8; SALT_LEN] = sealed[MAGIC.len()..MAGIC.len() + SALT_LEN]
+            .try_into()
+            .map_err(|_| StorageError::Encryption("Invalid file salt".to_string()))?;
+        let cipher = XChaCha20Poly1305::new((&self.key(&salt)?).into());
+
+        cipher
+            .decrypt(
+                XNonce::from_slice(&sealed[MAGIC.len() + SALT_LEN..HEADER_LEN]),
+                Payload { msg: &sealed[HEADER_LEN..], aad: &self.associated_data() },
+            )
+            .map_err(|_| StorageError::Encryption(format!(
+                "{} does not decrypt; wrong storage passphrase, or it was tampered with or renamed",
+                self.path.display()
+            )))
+    }
+
+    /// Get the file key for a salt, deriving it only when the salt changes
+    fn key(&self, salt: &[u8; SALT_LEN]) -> Result<[u8; 32], StorageError> {
+        let master_key = self.master_key.as_ref()
+            .ok_or_else(|| StorageError::Encryption("File is not sealed".to_string()))?;
+
+        let mut derived = self.derived.lock().map_err(poisoned)?;
+        match *derived {
+            Some((cached_salt, key)) if cached_salt == *salt => Ok(key),
+            _ => {
+                let key = master_key.derive(salt)?;
+                *derived = Some((*salt, key));
+                Ok(key)
+            }
+        }
+    }
+
+    /// Bind the contents to the name of the file
+    fn associated_data(&self) -> Vec<u8> {
+        self.path.file_name()
+            .map(|name| name.to_string_lossy().as_bytes().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Describe a failed file operation
+    fn io_error(&self, operation: &str, error: std::io::Error) -> StorageError {
+        StorageError::Backend(format!("Failed to {} {}: {}", operation, self.path.display(), error))
+    }
+}
+
+impl std::fmt::Debug for DocumentFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DocumentFile")
+            .field("path", &self.path)
+            .field("sealed", &self.is_sealed())
+            .finish()
+    }
+}
+
+/// Error for a lock poisoned by a panicking writer
+fn poisoned<T>(_: T) -> StorageError {
+    StorageError::Backend("File key lock poisoned".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sealed_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("peers.json");
+        let key = MasterKey::Passphrase("correct horse".to_string());
+
+        let file = DocumentFile::new(&path, Some(key.clone()));
+        assert_eq!(file.load::<Vec<String>>().unwrap(), None);
+        file.save(&vec!["secret peer".to_string()]).unwrap();
+        assert!(!fs::read(&path).unwrap().windows(6).any(|window| window == b"secret"));
+
+        // Another node with the same passphrase opens it
+        let reopened = DocumentFile::new(&path, Some(key.clone()));
+        assert_eq!(reopened.load::<Vec<String>>().unwrap(), Some(vec!["secret peer".to_string()]));
+
+        // Without the key, with another one or renamed, it does not open
+        assert!(DocumentFile::plain(&path).load::<Vec<String>>().is_err());
+        assert!(DocumentFile::new(&path, Some(MasterKey::generate())).load::<Vec<String>>().is_err());
+        let renamed = dir.path().join("orders.json");
+        fs::copy(&path, &renamed).unwrap();
+        assert!(DocumentFile::new(&renamed, Some(key.clone())).load::<Vec<String>>().is_err());
+
+        // A file in the clear is refused once encryption is on
+        let plain = dir.path().join("memos.json");
+        DocumentFile::plain(&plain).save(&vec!["memo".to_string()]).unwrap();
+        assert!(DocumentFile::new(&plain, Some(key)).load::<Vec<String>>().is_err());
+    }
+
+    #[test]
+    fn test_reseal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("orders.json");
+        let old_key = MasterKey::Passphrase("old".to_string());
+        let new_key = MasterKey::generate();
+
+        DocumentFile::new(&path, Some(old_key.clone())).save(&42u32).unwrap();
+        let resealed = DocumentFile::new(&path, Some(old_key.clone())).reseal(&new_key).unwrap();
+        assert_eq!(resealed.load::<u32>().unwrap(), Some(42));
+
+        assert!(DocumentFile::new(&path, Some(old_key)).load::<u32>().is_err());
+        assert_eq!(DocumentFile::new(&path, Some(new_key)).load::<u32>().unwrap(), Some(42));
+    }
+}
@@ -0,0 +1,66 @@
+//! In-memory storage backend
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use super::{Storage, StorageError};
+
+/// Storage that keeps everything in memory
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    /// Records by namespace
+    namespaces: RwLock<HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl MemoryStorage {
+    /// Create an empty storage
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Error for a lock poisoned by a panicking writer
+fn poisoned<T>(_: T) -> StorageError {
+    StorageError::Backend("Memory storage lock poisoned".to_string())
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        let namespaces = self.namespaces.read().map_err(poisoned)?;
+        Ok(namespaces.get(namespace).and_then(|records| records.get(key)).cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        let mut namespaces = self.namespaces.write().map_err(poisoned)?;
+        namespaces.entry(namespace.to_string()).or_default().insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, StorageError> {
+        let mut namespaces = self.namespaces.write().map_err(poisoned)?;
+        Ok(namespaces.get_mut(namespace).and_then(|records| records.remove(key)).is_some())
+    }
+
+    fn scan_prefix(&self, namespace: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        let namespaces = self.namespaces.read().map_err(poisoned)?;
+        let records = match namespaces.get(namespace) {
+            Some(records) => records,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(records
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>, StorageError> {
+        let namespaces = self.namespaces.read().map_err(poisoned)?;
+        Ok(namespaces
+            .iter()
+            .filter(|(_, records)| !records.is_empty())
+            .map(|(namespace, _)| namespace.clone())
+            .collect())
+    }
+}
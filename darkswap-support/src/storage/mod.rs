@@ -0,0 +1,404 @@
+//! Key-value storage for DarkSwap subsystems
+//!
+//! Trades and their write-ahead log, the trade outbox, wallet addresses and
+//! deposits, the order archive, the gossip cache and the audit log are
+//! persisted through one `Storage` trait. Records live in namespaces, one per
+//! subsystem, and are ordered by key so a subsystem can list its records by
+//! prefix. Documents are stored as JSON through `StorageExt`.
+//!
+//! State a standby node takes over through a shared directory, such as the own
+//! orders and known peers, is not kept here but in files of its own, each at
+//! the path configured for it, see `file`.
+//!
+//! Three backends ship with the crate, selected with `StorageConfig`:
+//!
+//! - `memory`: nothing is persisted; the default, and what tests use.
+//! - `sled`: an embedded sled database, behind the `sled` feature.
+//! - `sqlite`: a single SQLite file, behind the `sqlite` feature.
+//!
+//! Embedders with their own database implement `Storage` and hand it to the
+//! SDK in place of a configured backend.
+//!
+//! Any backend can be encrypted at rest, see `encrypted`; the files are sealed
+//! with the same master key.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::secrets::SecretStore;
+
+pub mod encrypted;
+pub mod file;
+pub mod memory;
+#[cfg(feature = "sled")]
+pub mod sled;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+pub use encrypted::{EncryptedStorage, MasterKey};
+pub use file::DocumentFile;
+pub use memory::MemoryStorage;
+
+/// Storage error
+#[derive(Debug, Error)]
+pub enum StorageError {
+    /// The backend failed
+    #[error("Storage backend error: {0}")]
+    Backend(String),
+    /// The backend is not compiled in or is misconfigured
+    #[error("Unsupported storage configuration: {0}")]
+    Unsupported(String),
+    /// A record or key could not be encrypted or decrypted
+    #[error("Storage encryption error: {0}")]
+    Encryption(String),
+    /// A document could not be (de)serialized
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Key-value store with namespaces
+pub trait Storage: Send + Sync {
+    /// Get the value of a key
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Set the value of a key
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError>;
+
+    /// Remove a key, returning false if it was not set
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, StorageError>;
+
+    /// Get the records whose key starts with `prefix`, ordered by key
+    fn scan_prefix(&self, namespace: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError>;
+
+    /// Get the namespaces holding records
+    fn namespaces(&self) -> Result<Vec<String>, StorageError>;
+
+    /// Make the writes so far durable
+    fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// JSON documents on top of a `Storage`
+pub trait StorageExt: Storage {
+    /// Get a document
+    fn get_json<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> Result<Option<T>, StorageError> {
+        match self.get(namespace, key.as_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Set a document
+    fn put_json<T: Serialize>(&self, namespace: &str, key: &str, document: &T) -> Result<(), StorageError> {
+        self.put(namespace, key.as_bytes(), &serde_json::to_vec(document)?)
+    }
+
+    /// Get the documents whose key starts with `prefix`, ordered by key
+    fn scan_json<T: DeserializeOwned>(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, T)>, StorageError> {
+        self.scan_prefix(namespace, prefix.as_bytes())?
+            .into_iter()
+            .map(|(key, value)| {
+                let key = String::from_utf8(key)
+                    .map_err(|_| StorageError::Backend(format!("Non UTF-8 key in {}", namespace)))?;
+                Ok((key, serde_json::from_slice(&value)?))
+            })
+            .collect()
+    }
+}
+
+impl<S: Storage + ?Sized> StorageExt for S {}
+
+/// Storage backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// In memory, nothing is persisted
+    Memory,
+    /// Embedded sled database
+    Sled,
+    /// SQLite file
+    Sqlite,
+}
+
+/// Default environment variable holding the storage passphrase
+pub const DEFAULT_PASSPHRASE_ENV: &str = "DARKSWAP_STORAGE_PASSPHRASE";
+
+/// Encryption at rest configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Environment variable holding the node passphrase
+    pub passphrase_env: String,
+    /// OS keyring entry holding the node passphrase, used instead of the environment
+    pub keyring_entry: Option<String>,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            passphrase_env: DEFAULT_PASSPHRASE_ENV.to_string(),
+            keyring_entry: None,
+        }
+    }
+}
+
+impl EncryptionConfig {
+    /// Get the master key from the OS keyring or the environment
+    pub fn master_key(&self) -> Result<MasterKey, StorageError> {
+        if self.keyring_entry.is_some() {
+            let store = crate::secrets::default_store()
+                .map_err(|e| StorageError::Encryption(format!("Failed to read the storage passphrase: {}", e)))?;
+            return self.keyring_master_key(store.as_ref());
+        }
+
+        match std::env::var(&self.passphrase_env) {
+            Ok(passphrase) if !passphrase.is_empty() => Ok(MasterKey::Passphrase(passphrase)),
+            _ => Err(StorageError::Encryption(format!(
+                "Storage is encrypted but {} is not set",
+                self.passphrase_env
+            ))),
+        }
+    }
+
+    /// Get the master key from the keyring entry, in `secrets`
+    ///
+    /// Fails while a rotation is unfinished: the entry may no longer match
+    /// everything the storage holds.
+    pub fn keyring_master_key(&self, secrets: &dyn SecretStore) -> Result<MasterKey, StorageError> {
+        let entry = self.keyring_entry.as_deref()
+            .ok_or_else(|| StorageError::Encryption("No keyring entry is configured".to_string()))?;
+
+        if secrets.get(&pending_entry(entry)).is_ok() {
+            return Err(StorageError::Encryption(
+                "A storage key rotation was interrupted; run darkswap-cli rotate-storage-key to complete it".to_string(),
+            ));
+        }
+
+        let passphrase = secrets.get(entry)
+            .map_err(|e| StorageError::Encryption(format!("Failed to read the storage passphrase: {}", e)))?;
+        Ok(MasterKey::Passphrase(passphrase))
+    }
+}
+
+/// Get the keyring entry holding the new passphrase while a rotation is unfinished
+pub fn pending_entry(entry: &str) -> String {
+    format!("{}.pending", entry)
+}
+
+/// Storage configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Backend
+    pub backend: StorageBackend,
+    /// Database path, required by the sled and SQLite backends
+    pub path: Option<String>,
+    /// Encryption at rest; none stores records in the clear
+    pub encryption: Option<EncryptionConfig>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Memory,
+            path: None,
+            encryption: None,
+        }
+    }
+}
+
+/// Open the configured storage, encrypted if configured
+pub fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>, StorageError> {
+    let storage = open_backend(config)?;
+    match &config.encryption {
+        Some(encryption) => Ok(Arc::new(EncryptedStorage::open(storage, &encryption.master_key()?)?)),
+        None => Ok(storage),
+    }
+}
+
+/// Open the configured storage backend without encryption
+pub fn open_backend(config: &StorageConfig) -> Result<Arc<dyn Storage>, StorageError> {
+    match config.backend {
+        StorageBackend::Memory => Ok(Arc::new(MemoryStorage::new())),
+        StorageBackend::Sled => {
+            #[cfg(feature = "sled")]
+            {
+                Ok(Arc::new(sled::SledStorage::open(required_path(config)?)?))
+            }
+            #[cfg(not(feature = "sled"))]
+            {
+                Err(not_compiled_in(config.backend))
+            }
+        }
+        StorageBackend::Sqlite => {
+            #[cfg(feature = "sqlite")]
+            {
+                Ok(Arc::new(sqlite::SqliteStorage::open(required_path(config)?)?))
+            }
+            #[cfg(not(feature = "sqlite"))]
+            {
+                Err(not_compiled_in(config.backend))
+            }
+        }
+    }
+}
+
+/// Move encrypted storage and the files sealed with it to a new master key
+///
+/// Every step can be repeated: records and files already under `new_key` are
+/// left alone, so a rotation that was interrupted is completed by running it
+/// again with the same keys. With `data_key`, the records are then re-encrypted
+/// under a new data key.
+pub fn rotate_master_key(
+    backend: Arc<dyn Storage>,
+    current_key: &MasterKey,
+    new_key: &MasterKey,
+    files: &[PathBuf],
+    data_key: bool,
+) -> Result<(), StorageError> {
+    // Check that everything opens before anything is rewritten
+    let storage = match EncryptedStorage::open(backend.clone(), current_key) {
+        Ok(storage) => Some(storage),
+        Err(_) => {
+            EncryptedStorage::open(backend.clone(), new_key)?;
+            None
+        }
+    };
+    let mut stale = Vec::new();
+    for path in files {
+        let file = DocumentFile::new(path, Some(current_key.clone()));
+        if file.load::<serde_json::Value>().is_ok() {
+            stale.push(file);
+        } else {
+            DocumentFile::new(path, Some(new_key.clone())).load::<serde_json::Value>()?;
+        }
+    }
+
+    // Files first: the storage keyring is the step that makes the new key current
+    for file in stale {
+        file.reseal(new_key)?;
+    }
+    if let Some(storage) = &storage {
+        storage.rotate_master_key(new_key)?;
+    }
+
+    if data_key {
+        EncryptedStorage::open(backend, new_key)?.rotate_data_key(new_key)?;
+    }
+
+    Ok(())
+}
+
+/// Get the path of a file-backed storage
+#[cfg(any(feature = "sled", feature = "sqlite"))]
+fn required_path(config: &StorageConfig) -> Result<&str, StorageError> {
+    config.path.as_deref().ok_or_else(|| {
+        StorageError::Unsupported(format!("The {:?} storage backend needs a path", config.backend))
+    })
+}
+
+/// Error for a backend left out of the build
+#[cfg(not(all(feature = "sled", feature = "sqlite")))]
+fn not_compiled_in(backend: StorageBackend) -> StorageError {
+    StorageError::Unsupported(format!("The {:?} storage backend is not compiled in", backend))
+}
+
+/// Tests shared by the backends
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+
+    /// Check that a backend behaves like the others
+    pub fn check(storage: &dyn Storage) {
+        assert_eq!(storage.get("orders", b"a").unwrap(), None);
+
+        storage.put("orders", b"order/2", b"two").unwrap();
+        storage.put("orders", b"order/1", b"one").unwrap();
+        storage.put("orders", b"other", b"x").unwrap();
+        storage.put("trades", b"order/3", b"three").unwrap();
+        assert_eq!(storage.get("orders", b"order/1").unwrap(), Some(b"one".to_vec()));
+
+        // Namespaces are separate and scans are ordered by key
+        let keys: Vec<Vec<u8>> = storage.scan_prefix("orders", b"order/").unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![b"order/1".to_vec(), b"order/2".to_vec()]);
+
+        storage.put("orders", b"order/1", b"uno").unwrap();
+        assert_eq!(storage.get("orders", b"order/1").unwrap(), Some(b"uno".to_vec()));
+
+        assert!(storage.delete("orders", b"order/1").unwrap());
+        assert!(!storage.delete("orders", b"order/1").unwrap());
+        assert_eq!(storage.get("orders", b"order/1").unwrap(), None);
+
+        let mut namespaces = storage.namespaces().unwrap();
+        namespaces.sort();
+        assert_eq!(namespaces, vec!["orders".to_string(), "trades".to_string()]);
+
+        storage.put_json("peers", "peer/a", &vec![1, 2, 3]).unwrap();
+        assert_eq!(storage.get_json::<Vec<u32>>("peers", "peer/a").unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(storage.scan_json::<Vec<u32>>("peers", "peer/").unwrap().len(), 1);
+
+        storage.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_backends() {
+        let storage = open(&StorageConfig::default()).unwrap();
+        conformance::check(storage.as_ref());
+
+        let config = StorageConfig { backend: StorageBackend::Sqlite, path: None, encryption: None };
+        assert!(open(&config).is_err());
+    }
+
+    #[test]
+    fn test_rotate_master_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let current_key = MasterKey::Passphrase("old".to_string());
+        let new_key = MasterKey::Passphrase("new".to_string());
+
+        let storage = EncryptedStorage::open(backend.clone(), &current_key).unwrap();
+        storage.put_json("trades", "trade/1", &"one").unwrap();
+        let files = vec![dir.path().join("orders.json"), dir.path().join("peers.json")];
+        DocumentFile::new(&files[0], Some(current_key.clone())).save(&"orders").unwrap();
+
+        rotate_master_key(backend.clone(), &current_key, &new_key, &files, true).unwrap();
+
+        // Storage and files reopen with the new key only
+        assert!(EncryptedStorage::open(backend.clone(), &current_key).is_err());
+        let reopened = EncryptedStorage::open(backend.clone(), &new_key).unwrap();
+        assert_eq!(reopened.get_json::<String>("trades", "trade/1").unwrap().as_deref(), Some("one"));
+        let orders = DocumentFile::new(&files[0], Some(new_key.clone()));
+        assert_eq!(orders.load::<String>().unwrap().as_deref(), Some("orders"));
+        assert!(DocumentFile::new(&files[0], Some(current_key.clone())).load::<String>().is_err());
+
+        // Running it again, as after an interruption, changes nothing
+        rotate_master_key(backend.clone(), &current_key, &new_key, &files, false).unwrap();
+        let reopened = EncryptedStorage::open(backend, &new_key).unwrap();
+        assert_eq!(reopened.get_json::<String>("trades", "trade/1").unwrap().as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn test_interrupted_rotation_is_refused() {
+        let secrets = crate::secrets::MemorySecretStore::new();
+        let config = EncryptionConfig {
+            keyring_entry: Some("storage".to_string()),
+            ..EncryptionConfig::default()
+        };
+        secrets.set("storage", "old").unwrap();
+        assert!(config.keyring_master_key(&secrets).is_ok());
+
+        secrets.set(&pending_entry("storage"), "new").unwrap();
+        assert!(config.keyring_master_key(&secrets).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! sled storage backend
+//!
+//! Each namespace is a sled tree.
+
+use std::path::Path;
+
+use super::{Storage, StorageError};
+
+/// Storage in an embedded sled database
+#[derive(Debug, Clone)]
+pub struct SledStorage {
+    /// Database
+    db: ::sled::Db,
+}
+
+impl From<::sled::Error> for StorageError {
+    fn from(e: ::sled::Error) -> Self {
+        StorageError::Backend(e.to_string())
+    }
+}
+
+impl SledStorage {
+    /// Open the database at `path`, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Ok(Self { db: ::sled::open(path)? })
+    }
+}
+
+impl Storage for SledStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.db.open_tree(namespace)?.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.db.open_tree(namespace)?.insert(key, value)?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, StorageError> {
+        Ok(self.db.open_tree(namespace)?.remove(key)?.is_some())
+    }
+
+    fn scan_prefix(&self, namespace: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        self.db.open_tree(namespace)?
+            .scan_prefix(prefix)
+            .map(|record| {
+                let (key, value) = record?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>, StorageError> {
+        let mut namespaces = Vec::new();
+        for name in self.db.tree_names() {
+            // Skip sled's own default tree
+            if name == self.db.name() {
+                continue;
+            }
+            if !self.db.open_tree(&name)?.is_empty() {
+                namespaces.push(String::from_utf8_lossy(&name).into_owned());
+            }
+        }
+        Ok(namespaces)
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sled_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("darkswap.sled");
+
+        let storage = SledStorage::open(&path).unwrap();
+        super::super::conformance::check(&storage);
+        storage.put("orders", b"kept", b"yes").unwrap();
+        storage.flush().unwrap();
+        drop(storage);
+
+        let reopened = SledStorage::open(&path).unwrap();
+        assert_eq!(reopened.get("orders", b"kept").unwrap(), Some(b"yes".to_vec()));
+    }
+}
@@ -0,0 +1,131 @@
+//! SQLite storage backend
+//!
+//! All namespaces share one table keyed by namespace and key.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{Storage, StorageError};
+
+/// Storage in a SQLite file
+#[derive(Debug)]
+pub struct SqliteStorage {
+    /// Connection; SQLite connections are not shared between threads
+    connection: Mutex<Connection>,
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Backend(e.to_string())
+    }
+}
+
+impl SqliteStorage {
+    /// Open the database at `path`, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Open a database that lives in memory only
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// Create the table if needed
+    fn init(connection: Connection) -> Result<Self, StorageError> {
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS records (
+                 namespace TEXT NOT NULL,
+                 key BLOB NOT NULL,
+                 value BLOB NOT NULL,
+                 PRIMARY KEY (namespace, key)
+             ) WITHOUT ROWID;",
+        )?;
+
+        Ok(Self { connection: Mutex::new(connection) })
+    }
+
+    /// Lock the connection
+    fn connection(&self) -> Result<std::sync::MutexGuard<'_, Connection>, StorageError> {
+        self.connection.lock().map_err(|_| StorageError::Backend("SQLite connection lock poisoned".to_string()))
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.connection()?
+            .query_row(
+                "SELECT value FROM records WHERE namespace = ?1 AND key = ?2",
+                params![namespace, key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: &[u8]) -> Result<(), StorageError> {
+        self.connection()?.execute(
+            "INSERT INTO records (namespace, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (namespace, key) DO UPDATE SET value = excluded.value",
+            params![namespace, key, value],
+        )?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<bool, StorageError> {
+        let deleted = self.connection()?.execute(
+            "DELETE FROM records WHERE namespace = ?1 AND key = ?2",
+            params![namespace, key],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn scan_prefix(&self, namespace: &str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+        let connection = self.connection()?;
+        // Blobs compare bytewise, so the keys with the prefix follow it in order
+        let mut statement = connection.prepare(
+            "SELECT key, value FROM records WHERE namespace = ?1 AND key >= ?2 ORDER BY key",
+        )?;
+        let rows = statement.query_map(params![namespace, prefix], |row| {
+            Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+
+        let mut records = Vec::new();
+        for row in rows {
+            let (key, value) = row?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            records.push((key, value));
+        }
+
+        Ok(records)
+    }
+
+    fn namespaces(&self) -> Result<Vec<String>, StorageError> {
+        let connection = self.connection()?;
+        let mut statement = connection.prepare("SELECT DISTINCT namespace FROM records")?;
+        let namespaces = statement.query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(namespaces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_storage() {
+        super::super::conformance::check(&SqliteStorage::open_in_memory().unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("darkswap.db");
+        SqliteStorage::open(&path).unwrap().put("orders", b"kept", b"yes").unwrap();
+
+        let reopened = SqliteStorage::open(&path).unwrap();
+        assert_eq!(reopened.get("orders", b"kept").unwrap(), Some(b"yes".to_vec()));
+    }
+}
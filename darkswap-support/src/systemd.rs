@@ -0,0 +1,131 @@
+//! systemd service notifications
+//!
+//! Under a `Type=notify` unit systemd passes a datagram socket in
+//! `NOTIFY_SOCKET` and only considers the service started once it receives
+//! `READY=1`. With `WatchdogSec=` it also sets `WATCHDOG_USEC` and restarts the
+//! service when no `WATCHDOG=1` arrives within that time. Outside of systemd
+//! the variables are unset and the functions here do nothing.
+
+use std::io;
+use std::time::Duration;
+
+/// Send a state to the service manager, e.g. `READY=1`
+///
+/// Returns `false` if the process was not started by a service manager.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match std::env::var("NOTIFY_SOCKET") {
+        Ok(socket_path) if !socket_path.is_empty() => {
+            send(&socket_path, state)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Tell the service manager that startup is complete
+pub fn ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+/// Tell the service manager that shutdown has begun
+pub fn stopping() -> io::Result<bool> {
+    notify("STOPPING=1")
+}
+
+/// Show a one-line status in `systemctl status`
+pub fn status(text: &str) -> io::Result<bool> {
+    notify(&format!("STATUS={}", text.replace('\n', " ")))
+}
+
+/// Tell the watchdog that the service is alive
+pub fn watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+/// Get the watchdog timeout, if the service manager expects pings from this process
+///
+/// Pings should be sent at least twice per timeout.
+pub fn watchdog_timeout() -> Option<Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// Parse the watchdog variables; a `WATCHDOG_PID` of another process means the pings are not ours to send
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.trim().parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+
+    match usec?.trim().parse::<u64>().ok()? {
+        0 => None,
+        usec => Some(Duration::from_micros(usec)),
+    }
+}
+
+/// Send a datagram to the notification socket, a path or an abstract name starting with `@`
+#[cfg(unix)]
+fn send(socket_path: &str, state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+
+    if let Some(name) = socket_path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Abstract notification sockets need Linux"));
+        }
+    }
+
+    socket.send_to(state.as_bytes(), socket_path)?;
+    Ok(())
+}
+
+/// Service manager notifications are only sent on unix
+#[cfg(not(unix))]
+fn send(_socket_path: &str, _state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(parse_watchdog(Some("30000000"), None, 42), Some(Duration::from_secs(30)));
+        assert_eq!(parse_watchdog(Some("30000000"), Some("42"), 42), Some(Duration::from_secs(30)));
+        assert_eq!(parse_watchdog(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog(None, Some("42"), 42), None);
+        assert_eq!(parse_watchdog(Some("soon"), None, 42), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        send(path.to_str().unwrap(), "READY=1").unwrap();
+
+        let mut buffer = [0u8; 64];
+        let received = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..received], b"READY=1");
+    }
+}
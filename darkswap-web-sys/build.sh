@@ -14,7 +14,8 @@ fi
 # Parse command line arguments
 BUILD_TYPE="debug"
 TARGET_DIR="pkg"
-FEATURES=""
+FEATURES="--features wasm-light"
+REACT_HOOKS=false
 
 while [[ $# -gt 0 ]]; do
     case $1 in
@@ -30,13 +31,18 @@ while [[ $# -gt 0 ]]; do
             FEATURES="--features $2"
             shift 2
             ;;
+        --react-hooks)
+            REACT_HOOKS=true
+            shift
+            ;;
         --help)
             echo "Usage: $0 [OPTIONS]"
             echo ""
             echo "Options:"
             echo "  --release         Build in release mode"
             echo "  --target-dir DIR  Output directory for the WebAssembly module [default: pkg]"
-            echo "  --features LIST   Comma-separated list of features to enable"
+            echo "  --features LIST   Comma-separated list of SDK features to enable [default: wasm-light]"
+            echo "  --react-hooks     Also generate the React hooks module in DIR/react"
             echo "  --help            Show this help message"
             exit 0
             ;;
@@ -67,10 +73,18 @@ fi
 # Navigate back to the web-sys directory
 cd ../darkswap-web-sys
 
+echo "WebAssembly module size: $(wc -c < "$TARGET_DIR/darkswap_sdk_bg.wasm") bytes"
+
 # Copy the WebAssembly module to the src/wasm directory
 mkdir -p src/wasm
 cp -r "$TARGET_DIR"/* src/wasm/
 
+# Generate the React hooks from the binding types
+if [ "$REACT_HOOKS" = true ]; then
+    echo "Generating React hooks..."
+    cargo run --features react-hooks --bin generate-react-hooks -- "$TARGET_DIR/react/index.ts"
+fi
+
 # Create the TypeScript wrapper for the WebAssembly module
 echo "Creating TypeScript wrapper for the WebAssembly module..."
 cat > src/wasm/darkswap_sdk.ts << EOL
@@ -0,0 +1,21 @@
+//! Write the generated React hooks module
+//!
+//! Usage: generate-react-hooks [OUTPUT]; the module is written to `react/index.ts`
+//! when no output path is given.
+
+use std::path::PathBuf;
+
+fn main() -> std::io::Result<()> {
+    let output = std::env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("react").join("index.ts"));
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&output, darkswap_web_sys::react::generate_hooks())?;
+
+    println!("React hooks written to {}", output.display());
+    Ok(())
+}
@@ -16,6 +16,9 @@ pub mod orderbook;
 pub mod trade;
 pub mod webrtc;
 pub mod utils;
+pub mod notifications;
+#[cfg(feature = "react-hooks")]
+pub mod react;
 
 // Initialize panic hook and logger
 #[wasm_bindgen(start)]
@@ -0,0 +1,230 @@
+//! WebAssembly bindings for trade notifications
+//!
+//! This module turns trade events into payloads for the browser Notification API.
+//! The bindings never show notifications themselves: the page registers a callback
+//! and decides, after asking for permission, how to display them.
+
+use wasm_bindgen::prelude::*;
+use js_sys::{Function, Reflect};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+
+use crate::to_js_value;
+
+/// Trade events that can raise a notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NotificationEvent {
+    /// A taker wants to trade against one of our orders
+    TradeProposalReceived,
+    /// A trade completed
+    TradeCompleted,
+    /// A trade failed
+    TradeFailed,
+}
+
+impl NotificationEvent {
+    /// All notification events
+    pub const ALL: [NotificationEvent; 3] = [
+        NotificationEvent::TradeProposalReceived,
+        NotificationEvent::TradeCompleted,
+        NotificationEvent::TradeFailed,
+    ];
+
+    /// Parse an event type
+    pub fn from_event_type(event_type: &str) -> Option<Self> {
+        match event_type {
+            "tradeProposalReceived" => Some(NotificationEvent::TradeProposalReceived),
+            "tradeCompleted" => Some(NotificationEvent::TradeCompleted),
+            "tradeFailed" => Some(NotificationEvent::TradeFailed),
+            _ => None,
+        }
+    }
+
+    /// Get the event type
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            NotificationEvent::TradeProposalReceived => "tradeProposalReceived",
+            NotificationEvent::TradeCompleted => "tradeCompleted",
+            NotificationEvent::TradeFailed => "tradeFailed",
+        }
+    }
+
+    /// Get the notification title
+    fn title(&self) -> &'static str {
+        match self {
+            NotificationEvent::TradeProposalReceived => "Trade proposal received",
+            NotificationEvent::TradeCompleted => "Trade completed",
+            NotificationEvent::TradeFailed => "Trade failed",
+        }
+    }
+
+    /// Get the notification body
+    fn body(&self, trade_id: &str) -> String {
+        match self {
+            NotificationEvent::TradeProposalReceived => format!("Trade {} is waiting for your approval", trade_id),
+            NotificationEvent::TradeCompleted => format!("Trade {} has settled", trade_id),
+            NotificationEvent::TradeFailed => format!("Trade {} did not go through", trade_id),
+        }
+    }
+}
+
+/// Notification for a trade event, shaped for `new Notification(title, options)`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeNotification {
+    /// Notification title
+    pub title: String,
+    /// Notification options
+    pub options: TradeNotificationOptions,
+}
+
+/// Options of a trade notification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeNotificationOptions {
+    /// Notification text
+    pub body: String,
+    /// Tag, shared by all notifications of a trade so newer ones replace older ones
+    pub tag: String,
+    /// Keep the notification until the user acts on it
+    pub require_interaction: bool,
+    /// Event the notification was raised for
+    pub data: TradeNotificationData,
+}
+
+/// Event details attached to a trade notification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeNotificationData {
+    /// Event type
+    pub event_type: String,
+    /// Trade ID
+    pub trade_id: String,
+}
+
+impl TradeNotification {
+    /// Create the notification for a trade event
+    pub fn new(event: NotificationEvent, trade_id: &str) -> Self {
+        TradeNotification {
+            title: event.title().to_string(),
+            options: TradeNotificationOptions {
+                body: event.body(trade_id),
+                tag: format!("darkswap-trade-{}", trade_id),
+                // Proposals time out, so they should not disappear on their own
+                require_interaction: event == NotificationEvent::TradeProposalReceived,
+                data: TradeNotificationData {
+                    event_type: event.event_type().to_string(),
+                    trade_id: trade_id.to_string(),
+                },
+            },
+        }
+    }
+}
+
+/// Get the trade ID of an event object, which carries either `tradeId` or a `trade` object
+fn event_trade_id(event: &JsValue) -> Option<String> {
+    let get = |target: &JsValue, key: &str| Reflect::get(target, &JsValue::from_str(key)).ok();
+
+    if let Some(trade_id) = get(event, "tradeId").and_then(|value| value.as_string()) {
+        return Some(trade_id);
+    }
+
+    let trade = get(event, "trade").filter(|value| value.is_object())?;
+    get(&trade, "id").and_then(|value| value.as_string())
+}
+
+/// Trade notifications for JavaScript
+#[wasm_bindgen]
+pub struct Notifications {
+    /// Callback receiving the notifications
+    callback: Option<Function>,
+    /// Events that raise a notification
+    enabled: HashSet<NotificationEvent>,
+}
+
+#[wasm_bindgen]
+impl Notifications {
+    /// Create a notifications facility with every event enabled and no callback
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Notifications {
+        Notifications {
+            callback: None,
+            enabled: NotificationEvent::ALL.iter().copied().collect(),
+        }
+    }
+
+    /// Set the callback receiving `{ title, options }` notification payloads
+    #[wasm_bindgen]
+    pub fn set_callback(&mut self, callback: Function) {
+        self.callback = Some(callback);
+    }
+
+    /// Remove the callback
+    #[wasm_bindgen]
+    pub fn clear_callback(&mut self) {
+        self.callback = None;
+    }
+
+    /// Enable or disable notifications for an event type
+    #[wasm_bindgen]
+    pub fn set_enabled(&mut self, event_type: &str, enabled: bool) -> Result<(), JsValue> {
+        let event = NotificationEvent::from_event_type(event_type)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown notification event: {}", event_type)))?;
+
+        if enabled {
+            self.enabled.insert(event);
+        } else {
+            self.enabled.remove(&event);
+        }
+
+        Ok(())
+    }
+
+    /// Check if notifications are enabled for an event type
+    #[wasm_bindgen]
+    pub fn is_enabled(&self, event_type: &str) -> bool {
+        NotificationEvent::from_event_type(event_type)
+            .map_or(false, |event| self.enabled.contains(&event))
+    }
+
+    /// Get the event types that can raise a notification
+    #[wasm_bindgen]
+    pub fn event_types() -> Vec<JsValue> {
+        NotificationEvent::ALL.iter()
+            .map(|event| JsValue::from_str(event.event_type()))
+            .collect()
+    }
+
+    /// Pass an event to the callback as a notification, if it is a trade event that is enabled
+    ///
+    /// Returns true if the callback was called.
+    #[wasm_bindgen]
+    pub fn handle_event(&self, event: JsValue) -> Result<bool, JsValue> {
+        let callback = match &self.callback {
+            Some(callback) => callback,
+            None => return Ok(false),
+        };
+
+        let event_type = Reflect::get(&event, &JsValue::from_str("type"))?
+            .as_string()
+            .unwrap_or_default();
+        let notification_event = match NotificationEvent::from_event_type(&event_type) {
+            Some(notification_event) if self.enabled.contains(&notification_event) => notification_event,
+            _ => return Ok(false),
+        };
+
+        let trade_id = event_trade_id(&event)
+            .ok_or_else(|| JsValue::from_str(&format!("Event {} has no trade ID", event_type)))?;
+
+        let notification = TradeNotification::new(notification_event, &trade_id);
+        callback.call1(&JsValue::NULL, &to_js_value(&notification)?)?;
+
+        Ok(true)
+    }
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -46,6 +46,7 @@ fn get_orderbook() -> Result<Arc<Mutex<SdkOrderbook>>, JsValue> {
 
 /// Order information
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "react-hooks", derive(ts_rs::TS))]
 pub struct Order {
     pub id: String,
     pub base_asset: String,
@@ -53,7 +54,9 @@ pub struct Order {
     pub side: String,
     pub amount: String,
     pub price: String,
+    #[cfg_attr(feature = "react-hooks", ts(type = "number"))]
     pub timestamp: u64,
+    #[cfg_attr(feature = "react-hooks", ts(type = "number"))]
     pub expiry: u64,
     pub status: String,
     pub maker: String,
@@ -0,0 +1,136 @@
+//! React hooks generator
+//!
+//! This module emits a TypeScript module with the binding types, stores for orders,
+//! trades and the connection status, and React hooks reading them. The types are
+//! generated from the Rust structs the bindings serialize, so the hooks cannot drift
+//! from what the WebAssembly module returns.
+
+use serde::{Serialize, Deserialize};
+use ts_rs::TS;
+
+use crate::orderbook::Order;
+use crate::trade::TradeExecution;
+use crate::webrtc::WebRtcConnectionInfo;
+
+/// Connection status kept by the connection store
+#[derive(Serialize, Deserialize, TS)]
+pub struct ConnectionStatus {
+    /// Whether any peer connection is established
+    pub connected: bool,
+    /// Peer connections
+    pub connections: Vec<WebRtcConnectionInfo>,
+    /// Last error reported while refreshing the stores
+    pub error: Option<String>,
+}
+
+/// Header of the generated module
+const HEADER: &str = "\
+// Generated by `cargo run --features react-hooks --bin generate-react-hooks`.
+// Do not edit: change the Rust types in darkswap-web-sys and regenerate.
+
+import { useSyncExternalStore } from 'react';
+";
+
+/// Stores, hooks and the refresh loop
+const STORES: &str = r#"
+/** Minimal external store for useSyncExternalStore */
+export interface Store<T> {
+  get(): T;
+  set(value: T): void;
+  subscribe(listener: () => void): () => void;
+}
+
+/** Create a store holding `initial` */
+export function createStore<T>(initial: T): Store<T> {
+  let value = initial;
+  const listeners = new Set<() => void>();
+
+  return {
+    get: () => value,
+    set: (next: T) => {
+      value = next;
+      listeners.forEach((listener) => listener());
+    },
+    subscribe: (listener: () => void) => {
+      listeners.add(listener);
+      return () => {
+        listeners.delete(listener);
+      };
+    },
+  };
+}
+
+export const ordersStore = createStore<Array<Order>>([]);
+export const tradesStore = createStore<Array<TradeExecution>>([]);
+export const connectionStore = createStore<ConnectionStatus>({ connected: false, connections: [], error: null });
+
+/** Open orders */
+export function useOrders(): Array<Order> {
+  return useSyncExternalStore(ordersStore.subscribe, ordersStore.get);
+}
+
+/** Trades */
+export function useTrades(): Array<TradeExecution> {
+  return useSyncExternalStore(tradesStore.subscribe, tradesStore.get);
+}
+
+/** Peer connection status */
+export function useConnectionStatus(): ConnectionStatus {
+  return useSyncExternalStore(connectionStore.subscribe, connectionStore.get);
+}
+
+/** Instances of the WebAssembly classes the stores are filled from */
+export interface DarkSwapBindings {
+  orderbook: { get_orders(): Promise<Array<Order>> };
+  trade: { get_trades(): Promise<Array<TradeExecution>> };
+  webrtc: { get_connections(): Promise<Array<WebRtcConnectionInfo>> };
+}
+
+/** Refresh all stores once */
+export async function refreshStores(bindings: DarkSwapBindings): Promise<void> {
+  try {
+    const [orders, trades, connections] = await Promise.all([
+      bindings.orderbook.get_orders(),
+      bindings.trade.get_trades(),
+      bindings.webrtc.get_connections(),
+    ]);
+    ordersStore.set(orders);
+    tradesStore.set(trades);
+    connectionStore.set({
+      connected: connections.some((connection) => connection.state === 'connected'),
+      connections,
+      error: null,
+    });
+  } catch (error) {
+    connectionStore.set({ ...connectionStore.get(), error: String(error) });
+  }
+}
+
+/** Refresh the stores every `intervalMs`; returns a function stopping the refresh */
+export function startRefreshing(bindings: DarkSwapBindings, intervalMs = 2000): () => void {
+  void refreshStores(bindings);
+  const timer = setInterval(() => void refreshStores(bindings), intervalMs);
+  return () => clearInterval(timer);
+}
+"#;
+
+/// Generate the TypeScript module
+pub fn generate_hooks() -> String {
+    let declarations = [
+        Order::decl(),
+        TradeExecution::decl(),
+        WebRtcConnectionInfo::decl(),
+        ConnectionStatus::decl(),
+    ];
+
+    let mut output = String::from(HEADER);
+    for declaration in declarations {
+        output.push('\n');
+        output.push_str("export ");
+        output.push_str(&declaration);
+        output.push('\n');
+    }
+    output.push_str(STORES);
+
+    output
+}
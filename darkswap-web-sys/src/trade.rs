@@ -48,12 +48,15 @@ fn get_trade() -> Result<Arc<Mutex<SdkTrade>>, JsValue> {
 
 /// Trade execution information
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "react-hooks", derive(ts_rs::TS))]
 pub struct TradeExecution {
     pub id: String,
     pub maker_order: Order,
     pub taker_order: Order,
     pub status: String,
+    #[cfg_attr(feature = "react-hooks", ts(type = "number"))]
     pub timestamp: u64,
+    #[cfg_attr(feature = "react-hooks", ts(type = "number | null"))]
     pub completed_at: Option<u64>,
 }
 
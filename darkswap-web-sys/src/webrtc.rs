@@ -48,6 +48,7 @@ fn get_webrtc() -> Result<Arc<Mutex<WebRtcManager>>, JsValue> {
 
 /// WebRTC connection information
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "react-hooks", derive(ts_rs::TS))]
 pub struct WebRtcConnectionInfo {
     pub peer_id: String,
     pub connection_id: String,
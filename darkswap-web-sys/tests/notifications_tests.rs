@@ -0,0 +1,59 @@
+use wasm_bindgen_test::*;
+use wasm_bindgen::prelude::*;
+use js_sys::{Array, Function, Object, Reflect};
+use darkswap_web_sys::notifications::Notifications;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+fn trade_event(event_type: &str, trade_id: &str) -> JsValue {
+    let event = Object::new();
+    Reflect::set(&event, &JsValue::from_str("type"), &JsValue::from_str(event_type)).unwrap();
+    Reflect::set(&event, &JsValue::from_str("tradeId"), &JsValue::from_str(trade_id)).unwrap();
+    event.into()
+}
+
+#[wasm_bindgen_test]
+fn test_notification_payload() {
+    let received = Array::new();
+    let sink = received.clone();
+    let callback = Closure::wrap(Box::new(move |notification: JsValue| {
+        sink.push(&notification);
+    }) as Box<dyn FnMut(JsValue)>);
+
+    let mut notifications = Notifications::new();
+    notifications.set_callback(callback.as_ref().unchecked_ref::<Function>().clone());
+
+    assert!(notifications.handle_event(trade_event("tradeProposalReceived", "trade-1")).unwrap());
+    assert_eq!(received.length(), 1);
+
+    let notification = received.get(0);
+    let title = Reflect::get(&notification, &JsValue::from_str("title")).unwrap();
+    assert_eq!(title.as_string().unwrap(), "Trade proposal received");
+
+    let options = Reflect::get(&notification, &JsValue::from_str("options")).unwrap();
+    let tag = Reflect::get(&options, &JsValue::from_str("tag")).unwrap();
+    assert_eq!(tag.as_string().unwrap(), "darkswap-trade-trade-1");
+    let require_interaction = Reflect::get(&options, &JsValue::from_str("requireInteraction")).unwrap();
+    assert_eq!(require_interaction.as_bool(), Some(true));
+}
+
+#[wasm_bindgen_test]
+fn test_disabled_events_are_ignored() {
+    let received = Array::new();
+    let sink = received.clone();
+    let callback = Closure::wrap(Box::new(move |notification: JsValue| {
+        sink.push(&notification);
+    }) as Box<dyn FnMut(JsValue)>);
+
+    let mut notifications = Notifications::new();
+    notifications.set_callback(callback.as_ref().unchecked_ref::<Function>().clone());
+    notifications.set_enabled("tradeCompleted", false).unwrap();
+
+    assert!(!notifications.is_enabled("tradeCompleted"));
+    assert!(!notifications.handle_event(trade_event("tradeCompleted", "trade-1")).unwrap());
+    assert!(!notifications.handle_event(trade_event("orderCreated", "trade-1")).unwrap());
+    assert!(notifications.handle_event(trade_event("tradeFailed", "trade-1")).unwrap());
+    assert_eq!(received.length(), 1);
+
+    assert!(notifications.set_enabled("walletUpdated", true).is_err());
+}
@@ -0,0 +1,20 @@
+#![cfg(all(feature = "react-hooks", not(target_arch = "wasm32")))]
+
+use darkswap_web_sys::react::generate_hooks;
+
+#[test]
+fn test_generated_module_exports_types_and_hooks() {
+    let module = generate_hooks();
+
+    for export in ["Order", "TradeExecution", "WebRtcConnectionInfo", "ConnectionStatus"] {
+        assert!(module.contains(&format!("export interface {}", export)) || module.contains(&format!("export type {}", export)),
+            "missing type {}", export);
+    }
+
+    for hook in ["useOrders", "useTrades", "useConnectionStatus"] {
+        assert!(module.contains(&format!("export function {}", hook)), "missing hook {}", hook);
+    }
+
+    // Timestamps are plain numbers in serialized values, not bigints
+    assert!(!module.contains("bigint"));
+}
@@ -0,0 +1,35 @@
+//! Size tracking for the WebAssembly module
+//!
+//! Runs natively against the module built by `./build.sh --release`.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::path::PathBuf;
+
+/// Largest accepted size of the release module (bytes)
+const WASM_SIZE_BUDGET: u64 = 3 * 1024 * 1024;
+
+#[test]
+fn test_wasm_size_within_budget() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("pkg").join("darkswap_sdk_bg.wasm");
+    let size = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => {
+            eprintln!("Skipping size check: {} not found, run ./build.sh --release first", path.display());
+            return;
+        }
+    };
+
+    let budget = std::env::var("DARKSWAP_WASM_SIZE_BUDGET")
+        .ok()
+        .and_then(|budget| budget.parse().ok())
+        .unwrap_or(WASM_SIZE_BUDGET);
+
+    println!("{}: {} bytes ({} byte budget)", path.display(), size, budget);
+    assert!(
+        size <= budget,
+        "WebAssembly module is {} bytes, over the {} byte budget",
+        size,
+        budget
+    );
+}